@@ -0,0 +1,152 @@
+//! Embedding the gateway core in another application
+//!
+//! Builds a gateway from programmatic configuration, swaps the TDMoE
+//! transport for an in-process one and records CDRs to an in-memory sink.
+//!
+//! ```bash
+//! cargo run --example embedded_gateway
+//! ```
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+
+use redfire_gateway::interfaces::tdmoe::{TdmoeEvent, TdmoeFrame};
+use redfire_gateway::services::cdr::{CallDetailRecord, CdrAggregateStats};
+use redfire_gateway::{
+    CdrStorage, GatewayConfig, GatewayEvent, RedFireGateway, Result, TdmTransport,
+};
+
+/// TDM transport that loops frames straight back as received frames
+struct LoopbackTransport {
+    event_tx: mpsc::UnboundedSender<TdmoeEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<TdmoeEvent>>,
+}
+
+impl LoopbackTransport {
+    fn new() -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self { event_tx, event_rx: Some(event_rx) }
+    }
+}
+
+#[async_trait]
+impl TdmTransport for LoopbackTransport {
+    fn name(&self) -> &str {
+        "loopback"
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let _ = self.event_tx.send(TdmoeEvent::ChannelStateChanged { channel: 1, active: true });
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TdmoeEvent>> {
+        self.event_rx.take()
+    }
+
+    async fn send_frame(&self, frame: TdmoeFrame, _dest: Option<SocketAddr>) -> Result<()> {
+        let source = SocketAddr::from(([127, 0, 0, 1], 0));
+        let _ = self.event_tx.send(TdmoeEvent::FrameReceived { frame, source });
+        Ok(())
+    }
+
+    fn active_channel_count(&self) -> u32 {
+        1
+    }
+}
+
+/// CDR sink keeping records in memory
+#[derive(Default)]
+struct MemoryCdrStorage {
+    records: Mutex<Vec<CallDetailRecord>>,
+}
+
+#[async_trait]
+impl CdrStorage for MemoryCdrStorage {
+    async fn store_cdr(&self, cdr: &CallDetailRecord) -> Result<()> {
+        println!("CDR {}: {} -> {} ({}s)", cdr.id, cdr.caller, cdr.callee, cdr.duration_seconds);
+        self.records.lock().unwrap().push(cdr.clone());
+        Ok(())
+    }
+
+    async fn get_cdr(&self, cdr_id: &str) -> Result<Option<CallDetailRecord>> {
+        Ok(self.records.lock().unwrap().iter().find(|cdr| cdr.id == cdr_id).cloned())
+    }
+
+    async fn query_cdrs(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        _filters: HashMap<String, String>,
+    ) -> Result<Vec<CallDetailRecord>> {
+        Ok(self.records.lock().unwrap().iter()
+            .filter(|cdr| cdr.start_time >= start_time && cdr.start_time < end_time)
+            .cloned()
+            .collect())
+    }
+
+    async fn aggregate_stats(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<CdrAggregateStats> {
+        let records = self.query_cdrs(start_time, end_time, HashMap::new()).await?;
+        let total_duration_seconds: u64 = records.iter().map(|cdr| cdr.duration_seconds).sum();
+        Ok(CdrAggregateStats {
+            total_calls: records.len() as u64,
+            total_duration_seconds,
+            total_revenue: 0.0,
+            average_call_duration: total_duration_seconds as f64 / records.len().max(1) as f64,
+            calls_by_category: HashMap::new(),
+            revenue_by_category: HashMap::new(),
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter("info")
+        .init();
+
+    // Configuration is plain data; start from the defaults and adjust
+    let mut config = GatewayConfig::default_config();
+    config.general.node_id = "embedded-1".to_string();
+    config.sip.listen_port = 5070;
+
+    let mut gateway = RedFireGateway::builder()
+        .config(config)
+        .tdm_transport(LoopbackTransport::new())
+        .cdr_storage(Arc::new(MemoryCdrStorage::default()))
+        .build()?;
+
+    let mut events = gateway.take_event_receiver().expect("event receiver taken once");
+    gateway.start().await?;
+
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            match event {
+                GatewayEvent::InterfaceUp { interface } => println!("interface up: {}", interface),
+                GatewayEvent::CallStarted { call_id } => println!("call started: {}", call_id),
+                GatewayEvent::CallEnded { call_id } => println!("call ended: {}", call_id),
+                // GatewayEvent is non_exhaustive
+                other => println!("event: {:?}", other),
+            }
+        }
+    });
+
+    println!("Embedded gateway running; press Ctrl+C to stop");
+    tokio::signal::ctrl_c().await?;
+
+    gateway.stop().await?;
+    Ok(())
+}
@@ -16,7 +16,34 @@ use tracing::{error, info, warn};
 use redfire_gateway::config::{RouteType, RoutingRule, NumberTranslation};
 use redfire_gateway::services::{
     B2buaCall, B2buaCallState, MediaRelaySession, CallDetailRecord,
-    ClusterNode, TranscodingSession, CodecType,
+    ClusterNode, TranscodingSession, CodecType, HeapStats, ActiveGap, RerouteCounter, TakeoverRecord,
+    CodecNegotiationCounter, ShadowRoutingSummary, EarlyMediaCounter, MediaSecurityCounter,
+};
+use redfire_gateway::services::call_gapping::CALL_GAPS_PATH;
+use redfire_gateway::services::codec_negotiation::{TranscodingHeadroom, CODEC_NEGOTIATION_PATH, TRANSCODING_HEADROOM_PATH};
+use redfire_gateway::services::transcoding_latency::{TranscodingLatencyReport, TRANSCODING_LATENCY_PATH};
+use redfire_gateway::services::call_trace::{call_trace_path, CallTrace, TraceSelector, CALL_TRACE_PATH};
+use redfire_gateway::services::reroute::REROUTE_COUNTERS_PATH;
+use redfire_gateway::services::early_media::EARLY_MEDIA_PATH;
+use redfire_gateway::services::media_security::MEDIA_SECURITY_PATH;
+use redfire_gateway::services::shadow_routing::SHADOW_ROUTING_PATH;
+use redfire_gateway::services::support_tunnel::{
+    TunnelAuditRecord, TunnelRequest, TunnelSession, SUPPORT_TUNNEL_AUDIT_PATH, SUPPORT_TUNNEL_PATH,
+};
+use redfire_gateway::services::takeover::takeover_path;
+use redfire_gateway::services::upgrade::{UpgradeRequest, UPGRADE_PATH};
+use redfire_gateway::services::profiling::{CPU_PROFILE_PATH, HEAP_STATS_PATH};
+use redfire_gateway::services::api_schema::API_SCHEMA_PATH;
+use redfire_gateway::services::canary::{CanaryMetrics, CANARY_PATH};
+use redfire_gateway::services::test_numbers::{TestCallRecord, TEST_CALLS_PATH};
+use redfire_gateway::services::paging::{PageRecord, PAGING_PATH};
+use redfire_gateway::services::trunk_registration::{TrunkRegistrationStatus, TRUNK_REGISTRATIONS_PATH};
+use redfire_gateway::services::prompts::{parse_prompt, prompt_path, PromptInfo, PromptPackSummary, PROMPTS_PATH};
+use redfire_gateway::services::campaigns::{
+    campaign_path, Campaign, CampaignRequest, CampaignSummary, CallOutcome, CAMPAIGNS_PATH,
+};
+use redfire_gateway::services::audio_tap::{
+    audio_tap_path, TapAuditRecord, TapRequest, TapSession, TapSink, TapTarget, AUDIO_TAPS_PATH, AUDIO_TAP_AUDIT_PATH,
 };
 
 #[derive(Parser)]
@@ -31,6 +58,10 @@ struct Cli {
     #[arg(long, default_value = "http://localhost:8080")]
     endpoint: String,
 
+    /// Management API secret, sent as a bearer token
+    #[arg(long, env = "REDFIRE_API_SECRET")]
+    api_secret: Option<String>,
+
     /// Output format
     #[arg(long, value_enum, default_value = "table")]
     format: OutputFormat,
@@ -90,6 +121,236 @@ enum Commands {
         #[command(subcommand)]
         action: ConfigAction,
     },
+    /// Capture runtime profiles
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Inspect and manage call gaps towards congested destinations
+    Gaps {
+        #[command(subcommand)]
+        action: GapAction,
+    },
+    /// Show leg B failures per trunk and status and how many were re-routed
+    Reroutes,
+    /// Show calls per trunk whose early media was blocked or cut off by route policy
+    EarlyMedia,
+    /// Show encrypted, plain and SRTP-downgraded call legs per trunk
+    MediaSecurity,
+    /// Show where the candidate routing table would route live calls differently
+    Shadow,
+    /// Trace-level logging for selected numbers, trunks or calls only
+    Trace {
+        #[command(subcommand)]
+        action: TraceAction,
+    },
+    /// Open or close the outbound remote support tunnel
+    Support {
+        #[command(subcommand)]
+        action: SupportAction,
+    },
+    /// Restart into the configured binary, keeping established calls up
+    Upgrade {
+        /// Hex SHA-256 of the installed binary, which the gateway checks
+        /// before executing it
+        #[arg(long)]
+        sha256: String,
+    },
+    /// Print the OpenAPI description of the management API
+    Schema,
+    /// Show canary call results per destination
+    Canary,
+    /// Show active and recent calls to the echo, milliwatt and silence numbers
+    TestCalls,
+    /// Show active and recent pages to the overhead paging groups
+    Pages,
+    /// Show outbound trunk registrations and their retry timers
+    Registrations,
+    /// Manage announcement prompt packs
+    Prompts {
+        #[command(subcommand)]
+        action: PromptAction,
+    },
+    /// Run outbound notification campaigns
+    Campaigns {
+        #[command(subcommand)]
+        action: CampaignAction,
+    },
+    /// Tap raw TDM channel audio
+    Taps {
+        /// Operator token configured for audio taps
+        #[arg(long, env = "REDFIRE_TAP_TOKEN", hide_env_values = true)]
+        token: String,
+        #[command(subcommand)]
+        action: TapAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum PromptAction {
+    /// List packs and the standard prompts each lacks
+    List,
+    /// Upload a WAV prompt to a pack
+    Upload {
+        /// Language of the pack, e.g. en or pt-br
+        language: String,
+        /// Announcement the prompt plays, e.g. all-circuits-busy
+        event: String,
+        /// 8 kHz mono G.711 or 16-bit PCM WAV file
+        file: String,
+    },
+    /// Remove a prompt from a pack
+    Remove {
+        language: String,
+        event: String,
+    },
+    /// Check a WAV file locally without uploading it
+    Validate {
+        file: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum CampaignAction {
+    /// List campaigns with their outcome counts
+    List,
+    /// Start or schedule a campaign
+    Start {
+        name: String,
+        /// Campaign prompt to play, e.g. site-evacuation
+        prompt: String,
+        /// File of numbers to call, one per line
+        numbers: String,
+        /// Trunk to call over; repeat for several (all trunks when omitted)
+        #[arg(long)]
+        trunk: Vec<String>,
+        /// Calls per second (gateway default when omitted)
+        #[arg(long)]
+        cps: Option<f64>,
+        /// Start time in RFC 3339, e.g. 2026-06-01T09:00:00Z (now when omitted)
+        #[arg(long)]
+        start_at: Option<chrono::DateTime<chrono::Utc>>,
+    },
+    /// Show the outcome of every number of a campaign
+    Show {
+        id: String,
+    },
+    /// Cancel a campaign; calls already up finish
+    Cancel {
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TapAction {
+    /// List running taps
+    List,
+    /// Tap a span channel, or a call with --call
+    Start {
+        /// Warrant, ticket or reason recorded in the audit trail
+        #[arg(long)]
+        reason: String,
+        #[arg(long, requires = "channel", conflicts_with = "call")]
+        span: Option<u32>,
+        #[arg(long, requires = "span")]
+        channel: Option<u8>,
+        /// TDM call to tap, both channels of a tandem call
+        #[arg(long)]
+        call: Option<String>,
+        /// Stream RTP to this multicast group instead of writing files
+        #[arg(long)]
+        multicast: Option<std::net::SocketAddr>,
+        /// Tap lifetime in minutes (gateway default when omitted)
+        #[arg(long)]
+        minutes: Option<u64>,
+    },
+    /// Stop a tap
+    Stop {
+        id: String,
+    },
+    /// Show the tap audit trail
+    Audit,
+}
+
+#[derive(Subcommand)]
+enum SupportAction {
+    /// Open the tunnel to the support concentrator
+    Open {
+        /// Operator recorded in the audit trail
+        #[arg(long)]
+        operator: String,
+        /// Ticket or reason for access
+        #[arg(long)]
+        reason: String,
+        /// Tunnel lifetime in minutes (gateway default when omitted)
+        #[arg(long)]
+        minutes: Option<u64>,
+    },
+    /// Close the open tunnel
+    Close {
+        #[arg(long)]
+        operator: String,
+    },
+    /// Show the open tunnel
+    Status,
+    /// Show the tunnel audit trail
+    Audit,
+}
+
+#[derive(Subcommand)]
+enum TraceAction {
+    /// List what is being traced
+    List,
+    /// Trace calls from or to a number, on a trunk, or one live call
+    Add {
+        /// number, trunk, call-id or correlation-id
+        kind: String,
+        value: String,
+    },
+    /// Stop tracing a number, trunk or call
+    Remove {
+        /// number, trunk, call-id or correlation-id
+        kind: String,
+        value: String,
+    },
+    /// Show the collected trace of a call, by Call-ID or correlation ID
+    Show {
+        call_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum GapAction {
+    /// List gaps in force
+    List,
+    /// Gap a destination prefix by hand
+    Apply {
+        /// Called-number prefix
+        prefix: String,
+    },
+    /// Lift a gap before it expires
+    Remove {
+        /// Called-number prefix
+        prefix: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// Capture a CPU profile
+    Cpu {
+        /// Sampling duration in seconds (gateway default when omitted)
+        #[arg(long)]
+        seconds: Option<u64>,
+        /// Output format: protobuf or flamegraph
+        #[arg(long, default_value = "protobuf")]
+        format: String,
+        /// Output file
+        #[arg(long, default_value = "cpu.pb")]
+        output: String,
+    },
+    /// Show heap and allocation statistics
+    Heap,
 }
 
 #[derive(Subcommand)]
@@ -119,6 +380,23 @@ enum CallAction {
         #[arg(long, default_value = "Administrative")]
         reason: String,
     },
+    /// Move one leg of a live call to a new target, keeping the other leg up
+    Takeover {
+        /// Call ID to take over
+        call_id: String,
+        /// Leg to move (a or b)
+        #[arg(long, default_value = "b")]
+        leg: String,
+        /// New target host or SIP URI
+        #[arg(long)]
+        target: String,
+        /// Operator recorded in the audit trail
+        #[arg(long, env = "USER")]
+        operator: String,
+        /// Reason recorded in the audit trail
+        #[arg(long)]
+        reason: Option<String>,
+    },
     /// Monitor calls in real-time
     Monitor {
         /// Update interval in seconds
@@ -225,6 +503,12 @@ enum TranscodingAction {
     },
     /// Show transcoding performance
     Performance,
+    /// Show answered calls per trunk that were relayed or transcoded
+    Savings,
+    /// Show free transcoding capacity and admission state
+    Headroom,
+    /// Show per-frame transcoding latency by codec and backend
+    Latency,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum)]
@@ -302,7 +586,6 @@ enum BillingAction {
 enum ExportFormat {
     Csv,
     Json,
-    Excel,
 }
 
 #[derive(Subcommand)]
@@ -352,11 +635,17 @@ struct ApiClient {
 }
 
 impl ApiClient {
-    fn new(endpoint: String) -> Self {
-        Self {
-            endpoint,
-            client: reqwest::Client::new(),
+    fn new(endpoint: String, secret: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(secret) = secret {
+            let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", secret))?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
         }
+        Ok(Self {
+            endpoint,
+            client: reqwest::Client::builder().default_headers(headers).build()?,
+        })
     }
 
     async fn get_active_calls(&self) -> Result<Vec<B2buaCall>, Box<dyn std::error::Error>> {
@@ -386,6 +675,32 @@ impl ApiClient {
         }
     }
 
+    async fn take_over_call(
+        &self,
+        call_id: &str,
+        leg: &str,
+        target: &str,
+        operator: &str,
+        reason: Option<&str>,
+    ) -> Result<TakeoverRecord, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, takeover_path(call_id));
+        let payload = serde_json::json!({
+            "call_id": call_id,
+            "leg": leg,
+            "target": target,
+            "operator": operator,
+            "reason": reason,
+        });
+        let response = timeout(Duration::from_secs(10),
+            self.client.post(&url).json(&payload).send()).await??;
+
+        if response.status().is_success() {
+            Ok(response.json().await?)
+        } else {
+            Err(format!("Takeover request failed: {}", response.status()).into())
+        }
+    }
+
     async fn get_media_sessions(&self) -> Result<Vec<MediaRelaySession>, Box<dyn std::error::Error>> {
         let url = format!("{}/api/v1/media/sessions", self.endpoint);
         let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
@@ -416,6 +731,307 @@ impl ApiClient {
         let cdrs = response.json().await?;
         Ok(cdrs)
     }
+
+    async fn get_cpu_profile(&self, seconds: Option<u64>, format: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut url = format!("{}{}?format={}", self.endpoint, CPU_PROFILE_PATH, format);
+        if let Some(seconds) = seconds {
+            url.push_str(&format!("&seconds={}", seconds));
+        }
+        // The gateway holds the request open for the whole sampling period
+        let wait = Duration::from_secs(seconds.unwrap_or(120) + 30);
+        let response = timeout(wait, self.client.get(&url).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Profile request failed: {}", response.status()).into());
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn get_call_gaps(&self) -> Result<Vec<ActiveGap>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, CALL_GAPS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let gaps = response.json().await?;
+        Ok(gaps)
+    }
+
+    async fn set_call_gap(&self, prefix: &str, apply: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("{}{}/{}", self.endpoint, CALL_GAPS_PATH, prefix);
+        let request = if apply { self.client.post(&url) } else { self.client.delete(&url) };
+        let response = timeout(Duration::from_secs(10), request.send()).await??;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Call gap request failed: {}", response.status()).into())
+        }
+    }
+
+    async fn get_trace_selectors(&self) -> Result<Vec<TraceSelector>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, CALL_TRACE_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let selectors = response.json().await?;
+        Ok(selectors)
+    }
+
+    async fn set_trace_selector(&self, selector: &TraceSelector, add: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, CALL_TRACE_PATH);
+        let request = if add { self.client.post(&url) } else { self.client.delete(&url) };
+        let response = timeout(Duration::from_secs(10), request.json(selector).send()).await??;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Trace request failed: {}", response.status()).into())
+        }
+    }
+
+    async fn get_call_trace(&self, call_id: &str) -> Result<CallTrace, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, call_trace_path(call_id));
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("No trace for call {}: {}", call_id, response.status()).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn get_codec_negotiation_counters(&self) -> Result<Vec<CodecNegotiationCounter>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, CODEC_NEGOTIATION_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let counters = response.json().await?;
+        Ok(counters)
+    }
+
+    async fn get_transcoding_headroom(&self) -> Result<TranscodingHeadroom, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, TRANSCODING_HEADROOM_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let headroom = response.json().await?;
+        Ok(headroom)
+    }
+
+    async fn get_transcoding_latency(&self) -> Result<Vec<TranscodingLatencyReport>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, TRANSCODING_LATENCY_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let reports = response.json().await?;
+        Ok(reports)
+    }
+
+    async fn get_reroute_counters(&self) -> Result<Vec<RerouteCounter>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, REROUTE_COUNTERS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let counters = response.json().await?;
+        Ok(counters)
+    }
+
+    async fn get_early_media_counters(&self) -> Result<Vec<EarlyMediaCounter>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, EARLY_MEDIA_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let counters = response.json().await?;
+        Ok(counters)
+    }
+
+    async fn get_media_security_counters(&self) -> Result<Vec<MediaSecurityCounter>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, MEDIA_SECURITY_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let counters = response.json().await?;
+        Ok(counters)
+    }
+
+    async fn get_shadow_routing(&self) -> Result<ShadowRoutingSummary, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, SHADOW_ROUTING_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let summary = response.json().await?;
+        Ok(summary)
+    }
+
+    async fn open_support_tunnel(&self, request: &TunnelRequest) -> Result<TunnelSession, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, SUPPORT_TUNNEL_PATH);
+        // Opening waits for the tunnel process or interface to come up
+        let response = timeout(Duration::from_secs(30), self.client.post(&url).json(request).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Support tunnel request failed: {}", response.status()).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn close_support_tunnel(&self, operator: &str) -> Result<TunnelSession, Box<dyn std::error::Error>> {
+        let url = format!("{}{}?operator={}", self.endpoint, SUPPORT_TUNNEL_PATH, operator);
+        let response = timeout(Duration::from_secs(30), self.client.delete(&url).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Support tunnel request failed: {}", response.status()).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn get_support_tunnel(&self) -> Result<Option<TunnelSession>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, SUPPORT_TUNNEL_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let session = response.json().await?;
+        Ok(session)
+    }
+
+    async fn get_support_tunnel_audit(&self) -> Result<Vec<TunnelAuditRecord>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, SUPPORT_TUNNEL_AUDIT_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let records = response.json().await?;
+        Ok(records)
+    }
+
+    async fn start_upgrade(&self, request: &UpgradeRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, UPGRADE_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.post(&url).json(request).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Upgrade request failed: {}", response.status()).into());
+        }
+        Ok(())
+    }
+
+    async fn get_api_schema(&self) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, API_SCHEMA_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let schema = response.json().await?;
+        Ok(schema)
+    }
+
+    async fn get_canary_metrics(&self) -> Result<Vec<CanaryMetrics>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, CANARY_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let metrics = response.json().await?;
+        Ok(metrics)
+    }
+
+    async fn get_test_calls(&self) -> Result<Vec<TestCallRecord>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, TEST_CALLS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let calls = response.json().await?;
+        Ok(calls)
+    }
+
+    async fn get_pages(&self) -> Result<Vec<PageRecord>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, PAGING_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let pages = response.json().await?;
+        Ok(pages)
+    }
+
+    async fn get_trunk_registrations(&self) -> Result<Vec<TrunkRegistrationStatus>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, TRUNK_REGISTRATIONS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let registrations = response.json().await?;
+        Ok(registrations)
+    }
+
+    async fn get_prompt_packs(&self) -> Result<Vec<PromptPackSummary>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, PROMPTS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let packs = response.json().await?;
+        Ok(packs)
+    }
+
+    async fn upload_prompt(&self, language: &str, event: &str, audio: Vec<u8>) -> Result<PromptInfo, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, prompt_path(language, event));
+        // Recordings run to a few megabytes
+        let request = self.client.put(&url).header("Content-Type", "audio/wav").body(audio);
+        let response = timeout(Duration::from_secs(60), request.send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Prompt upload failed: {}", response.status()).into());
+        }
+        let info = response.json().await?;
+        Ok(info)
+    }
+
+    async fn remove_prompt(&self, language: &str, event: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, prompt_path(language, event));
+        let response = timeout(Duration::from_secs(10), self.client.delete(&url).send()).await??;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Prompt removal failed: {}", response.status()).into())
+        }
+    }
+
+    async fn get_campaigns(&self) -> Result<Vec<CampaignSummary>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, CAMPAIGNS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let campaigns = response.json().await?;
+        Ok(campaigns)
+    }
+
+    async fn start_campaign(&self, request: &CampaignRequest) -> Result<Campaign, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, CAMPAIGNS_PATH);
+        let response = timeout(Duration::from_secs(30), self.client.post(&url).json(request).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Campaign request failed: {}", response.status()).into());
+        }
+        let campaign = response.json().await?;
+        Ok(campaign)
+    }
+
+    async fn get_campaign(&self, id: &str) -> Result<Campaign, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, campaign_path(id));
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Campaign lookup failed: {}", response.status()).into());
+        }
+        let campaign = response.json().await?;
+        Ok(campaign)
+    }
+
+    async fn cancel_campaign(&self, id: &str) -> Result<CampaignSummary, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, campaign_path(id));
+        let response = timeout(Duration::from_secs(10), self.client.delete(&url).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Campaign cancellation failed: {}", response.status()).into());
+        }
+        let summary = response.json().await?;
+        Ok(summary)
+    }
+
+    async fn get_audio_taps(&self, token: &str) -> Result<Vec<TapSession>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, AUDIO_TAPS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).bearer_auth(token).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Audio tap request failed: {}", response.status()).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn start_audio_tap(
+        &self,
+        token: &str,
+        request: &TapRequest,
+    ) -> Result<TapSession, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, AUDIO_TAPS_PATH);
+        let request = self.client.post(&url).bearer_auth(token).json(request);
+        let response = timeout(Duration::from_secs(10), request.send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Audio tap request failed: {}", response.status()).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn stop_audio_tap(&self, token: &str, id: &str) -> Result<TapSession, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, audio_tap_path(id));
+        let response = timeout(Duration::from_secs(10), self.client.delete(&url).bearer_auth(token).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Audio tap request failed: {}", response.status()).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn get_audio_tap_audit(&self, token: &str) -> Result<Vec<TapAuditRecord>, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, AUDIO_TAP_AUDIT_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).bearer_auth(token).send()).await??;
+        if !response.status().is_success() {
+            return Err(format!("Audio tap request failed: {}", response.status()).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn get_heap_stats(&self) -> Result<HeapStats, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.endpoint, HEAP_STATS_PATH);
+        let response = timeout(Duration::from_secs(10), self.client.get(&url).send()).await??;
+        let stats = response.json().await?;
+        Ok(stats)
+    }
 }
 
 /// Output formatter for different display formats
@@ -544,7 +1160,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tracing_subscriber::fmt::init();
     }
 
-    let api_client = ApiClient::new(cli.endpoint);
+    let api_client = ApiClient::new(cli.endpoint, cli.api_secret.as_deref())?;
     let formatter = OutputFormatter::new(cli.format);
 
     match cli.command {
@@ -556,6 +1172,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Commands::Billing { action } => handle_billing_command(action, &api_client).await?,
         Commands::Stats { action } => handle_stats_command(action, &api_client).await?,
         Commands::Config { action } => handle_config_command(action, &api_client).await?,
+        Commands::Profile { action } => handle_profile_command(action, &api_client).await?,
+        Commands::Gaps { action } => handle_gaps_command(action, &api_client).await?,
+        Commands::Reroutes => handle_reroutes_command(&api_client).await?,
+        Commands::EarlyMedia => handle_early_media_command(&api_client).await?,
+        Commands::MediaSecurity => handle_media_security_command(&api_client).await?,
+        Commands::Shadow => handle_shadow_command(&api_client).await?,
+        Commands::Trace { action } => handle_trace_command(action, &api_client).await?,
+        Commands::Support { action } => handle_support_command(action, &api_client).await?,
+        Commands::Upgrade { sha256 } => handle_upgrade_command(sha256, &api_client).await?,
+        Commands::Schema => handle_schema_command(&api_client).await?,
+        Commands::Canary => handle_canary_command(&api_client).await?,
+        Commands::TestCalls => handle_test_calls_command(&api_client).await?,
+        Commands::Pages => handle_pages_command(&api_client).await?,
+        Commands::Registrations => handle_registrations_command(&api_client).await?,
+        Commands::Prompts { action } => handle_prompts_command(action, &api_client).await?,
+        Commands::Campaigns { action } => handle_campaigns_command(action, &api_client).await?,
+        Commands::Taps { token, action } => handle_taps_command(&token, action, &api_client).await?,
     }
 
     Ok(())
@@ -591,6 +1224,11 @@ async fn handle_call_command(
             api_client.terminate_call(&call_id, &reason).await?;
             println!("Call {} terminated successfully", call_id);
         }
+        CallAction::Takeover { call_id, leg, target, operator, reason } => {
+            let record = api_client.take_over_call(&call_id, &leg, &target, &operator, reason.as_deref()).await?;
+            println!("Takeover {} of call {} leg {} to {} started",
+                     record.id, call_id, leg, target);
+        }
         CallAction::Monitor { interval, filter: _ } => {
             loop {
                 let calls = api_client.get_active_calls().await?;
@@ -674,6 +1312,47 @@ async fn handle_transcoding_command(
         TranscodingAction::Performance => {
             println!("Transcoding performance metrics not implemented yet");
         }
+        TranscodingAction::Savings => {
+            let counters = api_client.get_codec_negotiation_counters().await?;
+            if counters.is_empty() {
+                println!("No answered calls recorded");
+                return Ok(());
+            }
+            println!("{:<32} {:>9} {:>11} {:>8}", "Trunk", "Relayed", "Transcoded", "Relayed%");
+            for counter in counters {
+                let total = counter.relayed + counter.transcoded;
+                println!("{:<32} {:>9} {:>11} {:>7.1}%",
+                         counter.trunk,
+                         counter.relayed,
+                         counter.transcoded,
+                         counter.relayed as f64 * 100.0 / total.max(1) as f64);
+            }
+        }
+        TranscodingAction::Headroom => {
+            let headroom = api_client.get_transcoding_headroom().await?;
+            println!("Transcoded calls: {} of {}", headroom.active, headroom.capacity);
+            println!("Headroom: {} calls ({:.1}%)", headroom.available, headroom.headroom_percent);
+            println!("Admission: {:?}", headroom.congestion);
+            println!("Rejected for capacity: {}", headroom.rejected);
+        }
+        TranscodingAction::Latency => {
+            let reports = api_client.get_transcoding_latency().await?;
+            if reports.is_empty() {
+                println!("No transcoded frames measured");
+                return Ok(());
+            }
+            println!("{:<8} {:<8} {:>10} {:>9} {:>9} {:>9} {:>8}", "Codec", "Backend", "Frames", "p50(us)", "p99(us)", "Budget", "Avoided");
+            for report in reports {
+                println!("{:<8} {:<8} {:>10} {:>9} {:>9} {:>9} {:>8}",
+                         report.codec,
+                         format!("{:?}", report.backend),
+                         report.frames,
+                         report.p50_us,
+                         report.p99_us,
+                         report.budget_us,
+                         if report.avoided { "yes" } else { "no" });
+            }
+        }
     }
     Ok(())
 }
@@ -725,8 +1404,28 @@ async fn handle_billing_command(
         BillingAction::Rates { prefix: _ } => {
             println!("Billing rates not implemented yet");
         }
-        BillingAction::Export { start: _, end: _, output, format: _ } => {
-            println!("Exporting CDRs to: {}", output);
+        BillingAction::Export { start: _, end: _, output, format } => {
+            let cdrs = api_client.get_cdrs(u32::MAX, None).await?;
+
+            // Custom fields become extra columns so billing can join on them
+            let custom_field_names: Vec<String> = cdrs
+                .iter()
+                .flat_map(|cdr| cdr.custom_fields.keys().cloned())
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .collect();
+
+            let content = match format {
+                ExportFormat::Json => serde_json::to_string_pretty(&cdrs)?,
+                ExportFormat::Csv => {
+                    let mut lines = vec![CallDetailRecord::csv_header(&custom_field_names)];
+                    lines.extend(cdrs.iter().map(|cdr| cdr.to_csv_row(&custom_field_names)));
+                    lines.join("\n") + "\n"
+                }
+            };
+
+            std::fs::write(&output, content)?;
+            println!("Exported {} CDRs to: {}", cdrs.len(), output);
         }
     }
     Ok(())
@@ -753,6 +1452,515 @@ async fn handle_stats_command(
     Ok(())
 }
 
+async fn handle_profile_command(
+    action: ProfileAction,
+    api_client: &ApiClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        ProfileAction::Cpu { seconds, format, output } => {
+            println!("Capturing CPU profile...");
+            let profile = api_client.get_cpu_profile(seconds, &format).await?;
+            std::fs::write(&output, &profile)?;
+            println!("Wrote {} byte {} profile to: {}", profile.len(), format, output);
+        }
+        ProfileAction::Heap => {
+            let stats = api_client.get_heap_stats().await?;
+            println!("Heap statistics (bytes):");
+            println!("  Allocated: {}", stats.allocated);
+            println!("  Active:    {}", stats.active);
+            println!("  Resident:  {}", stats.resident);
+            println!("  Mapped:    {}", stats.mapped);
+            println!("  Retained:  {}", stats.retained);
+            println!("  Metadata:  {}", stats.metadata);
+        }
+    }
+    Ok(())
+}
+
+fn trace_selector(kind: &str, value: String) -> Result<TraceSelector, Box<dyn std::error::Error>> {
+    match kind {
+        "number" => Ok(TraceSelector::Number(value)),
+        "trunk" => Ok(TraceSelector::Trunk(value)),
+        "call-id" => Ok(TraceSelector::CallId(value)),
+        "correlation-id" => Ok(TraceSelector::CorrelationId(value)),
+        _ => Err(format!("Unknown trace kind {} (number, trunk, call-id or correlation-id)", kind).into()),
+    }
+}
+
+async fn handle_trace_command(
+    action: TraceAction,
+    api_client: &ApiClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        TraceAction::List => {
+            let selectors = api_client.get_trace_selectors().await?;
+            if selectors.is_empty() {
+                println!("No calls are being traced");
+            }
+            for selector in selectors {
+                match selector {
+                    TraceSelector::Number(number) => println!("number   {}", number),
+                    TraceSelector::Trunk(trunk) => println!("trunk    {}", trunk),
+                    TraceSelector::CallId(call_id) => println!("call-id  {}", call_id),
+                    TraceSelector::CorrelationId(id) => println!("correlation-id  {}", id),
+                }
+            }
+        }
+        TraceAction::Add { kind, value } => {
+            api_client.set_trace_selector(&trace_selector(&kind, value.clone())?, true).await?;
+            println!("Tracing {} {}", kind, value);
+        }
+        TraceAction::Remove { kind, value } => {
+            api_client.set_trace_selector(&trace_selector(&kind, value.clone())?, false).await?;
+            println!("Stopped tracing {} {}", kind, value);
+        }
+        TraceAction::Show { call_id } => {
+            let trace = api_client.get_call_trace(&call_id).await?;
+            println!("Trace of call {} ({:?}), started {}",
+                     trace.call_id, trace.selector, trace.started_at.format("%Y-%m-%d %H:%M:%S"));
+            if !trace.correlation_id.is_empty() {
+                println!("Correlation ID {}", trace.correlation_id);
+            }
+            for record in &trace.records {
+                println!("{} {:<11} {}",
+                         record.timestamp.format("%H:%M:%S%.3f"),
+                         format!("{:?}", record.subsystem),
+                         record.message);
+            }
+            if trace.dropped > 0 {
+                println!("... {} further records dropped", trace.dropped);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn handle_gaps_command(
+    action: GapAction,
+    api_client: &ApiClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        GapAction::List => {
+            let gaps = api_client.get_call_gaps().await?;
+            if gaps.is_empty() {
+                println!("No call gaps in force");
+                return Ok(());
+            }
+            println!("{:<16} {:>10} {:<10} {:<20} {:>9} {:>9}",
+                     "Prefix", "Interval", "Cause", "Expires", "Admitted", "Gapped");
+            for gap in gaps {
+                let cause = gap.triggering_cause.map_or("manual".to_string(), |cause| cause.to_string());
+                println!("{:<16} {:>8}ms {:<10} {:<20} {:>9} {:>9}",
+                         gap.prefix,
+                         gap.gap_interval_ms,
+                         cause,
+                         gap.expires_at.format("%Y-%m-%d %H:%M:%S"),
+                         gap.calls_admitted,
+                         gap.calls_gapped);
+            }
+        }
+        GapAction::Apply { prefix } => {
+            api_client.set_call_gap(&prefix, true).await?;
+            println!("Call gap applied to {}", prefix);
+        }
+        GapAction::Remove { prefix } => {
+            api_client.set_call_gap(&prefix, false).await?;
+            println!("Call gap on {} lifted", prefix);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_reroutes_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let counters = api_client.get_reroute_counters().await?;
+    if counters.is_empty() {
+        println!("No leg B failures recorded");
+        return Ok(());
+    }
+    println!("{:<32} {:>6} {:>9} {:>9}", "Trunk", "Status", "Rerouted", "Released");
+    for counter in counters {
+        println!("{:<32} {:>6} {:>9} {:>9}",
+                 counter.trunk,
+                 counter.status,
+                 counter.rerouted,
+                 counter.released);
+    }
+    Ok(())
+}
+
+async fn handle_early_media_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let counters = api_client.get_early_media_counters().await?;
+    if counters.is_empty() {
+        println!("No calls affected by early media policy");
+        return Ok(());
+    }
+    println!("{:<32} {:>8} {:>8}", "Trunk", "Blocked", "Cut off");
+    for counter in counters {
+        println!("{:<32} {:>8} {:>8}",
+                 counter.trunk,
+                 counter.blocked,
+                 counter.cut_off);
+    }
+    Ok(())
+}
+
+async fn handle_media_security_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let counters = api_client.get_media_security_counters().await?;
+    if counters.is_empty() {
+        println!("No call legs negotiated yet");
+        return Ok(());
+    }
+    println!("{:<32} {:>10} {:>10} {:>10} {:>10}", "Trunk", "Encrypted", "Plaintext", "Downgrades", "Rejected");
+    for counter in counters {
+        println!("{:<32} {:>10} {:>10} {:>10} {:>10}",
+                 counter.trunk,
+                 counter.encrypted,
+                 counter.plaintext,
+                 counter.downgrades,
+                 counter.rejected);
+    }
+    Ok(())
+}
+
+async fn handle_shadow_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let summary = api_client.get_shadow_routing().await?;
+    println!("Calls evaluated: {}", summary.evaluated);
+    println!("Routed differently: {}", summary.differed);
+    if summary.changes.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!("{:<32} {:<32} {:>8}", "Active target", "Candidate target", "Calls");
+    for change in summary.changes {
+        println!("{:<32} {:<32} {:>8}",
+                 change.active_target.as_deref().unwrap_or("-"),
+                 change.candidate_target.as_deref().unwrap_or("-"),
+                 change.calls);
+    }
+
+    println!();
+    println!("Recent differences:");
+    for difference in summary.recent {
+        println!("  {} {} {} -> {} ({})",
+                 difference.at.format("%Y-%m-%d %H:%M:%S"),
+                 difference.callee,
+                 difference.active.destination_uri,
+                 difference.candidate.destination_uri,
+                 difference.fields.join(", "));
+    }
+    Ok(())
+}
+
+async fn handle_support_command(
+    action: SupportAction,
+    api_client: &ApiClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        SupportAction::Open { operator, reason, minutes } => {
+            let request = TunnelRequest {
+                operator,
+                reason,
+                duration_secs: minutes.map(|minutes| minutes * 60),
+            };
+            let session = api_client.open_support_tunnel(&request).await?;
+            println!("Support tunnel {} open to {} until {}",
+                     session.id,
+                     session.concentrator,
+                     session.expires_at.format("%Y-%m-%d %H:%M:%S UTC"));
+        }
+        SupportAction::Close { operator } => {
+            let session = api_client.close_support_tunnel(&operator).await?;
+            println!("Support tunnel {} closed", session.id);
+        }
+        SupportAction::Status => match api_client.get_support_tunnel().await? {
+            Some(session) => {
+                println!("Tunnel:       {}", session.id);
+                println!("Method:       {:?}", session.method);
+                println!("Concentrator: {}", session.concentrator);
+                println!("Operator:     {}", session.operator);
+                println!("Reason:       {}", session.reason);
+                println!("Opened:       {}", session.opened_at.format("%Y-%m-%d %H:%M:%S UTC"));
+                println!("Expires:      {}", session.expires_at.format("%Y-%m-%d %H:%M:%S UTC"));
+            }
+            None => println!("No support tunnel open"),
+        },
+        SupportAction::Audit => {
+            for record in api_client.get_support_tunnel_audit().await? {
+                println!("{} {:<8} {:<12} {}",
+                         record.at.format("%Y-%m-%d %H:%M:%S"),
+                         format!("{:?}", record.action),
+                         record.operator.as_deref().unwrap_or("-"),
+                         record.detail);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn handle_upgrade_command(sha256: String, api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    api_client.start_upgrade(&UpgradeRequest { sha256 }).await?;
+    println!("Upgrade started; established calls are restored once the gateway is back");
+    Ok(())
+}
+
+async fn handle_schema_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = api_client.get_api_schema().await?;
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+async fn handle_canary_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let metrics = api_client.get_canary_metrics().await?;
+    if metrics.is_empty() {
+        println!("No canary calls placed");
+        return Ok(());
+    }
+    println!("{:<20} {:>6} {:>8} {:>6} {:>9} {:>5}  {}", "Destination", "Calls", "Failures", "Streak", "PDD (ms)", "MOS", "Last");
+    for entry in metrics {
+        println!("{:<20} {:>6} {:>8} {:>6} {:>9} {:>5}  {}",
+                 entry.destination,
+                 entry.calls,
+                 entry.failures,
+                 entry.failure_streak,
+                 entry.average_pdd_ms.map(|pdd| format!("{:.0}", pdd)).unwrap_or_else(|| "-".to_string()),
+                 entry.average_mos.map(|mos| format!("{:.2}", mos)).unwrap_or_else(|| "-".to_string()),
+                 entry.last.map(|last| format!("{:?}", last.outcome)).unwrap_or_default());
+    }
+    Ok(())
+}
+
+async fn handle_test_calls_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let calls = api_client.get_test_calls().await?;
+    if calls.is_empty() {
+        println!("No test calls");
+        return Ok(());
+    }
+    println!("{:<12} {:<10} {:<16} {:<20} {:>8} {:>8} {:>9}  {}",
+             "Number", "Kind", "Caller", "Started", "Received", "Sent", "RTT (ms)", "State");
+    for call in calls {
+        println!("{:<12} {:<10} {:<16} {:<20} {:>8} {:>8} {:>9}  {}",
+                 call.number,
+                 format!("{:?}", call.kind),
+                 call.caller,
+                 call.started_at.format("%Y-%m-%d %H:%M:%S"),
+                 call.packets_received,
+                 call.packets_sent,
+                 call.round_trip_ms.map_or_else(|| "-".to_string(), |rtt| rtt.to_string()),
+                 if call.ended_at.is_some() { "ended" } else { "active" });
+    }
+    Ok(())
+}
+
+async fn handle_pages_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let pages = api_client.get_pages().await?;
+    if pages.is_empty() {
+        println!("No pages");
+        return Ok(());
+    }
+    println!("{:<12} {:<16} {:<20} {:<14} {:<20} {:>9}  {}",
+             "Number", "Caller", "Started", "Zone", "Group", "Packets", "State");
+    for page in pages {
+        println!("{:<12} {:<16} {:<20} {:<14} {:<20} {:>9}  {}",
+                 page.number,
+                 page.caller,
+                 page.started_at.format("%Y-%m-%d %H:%M:%S"),
+                 page.zone.as_deref().unwrap_or("-"),
+                 page.group.as_deref().unwrap_or("-"),
+                 page.packets_forwarded,
+                 if page.ended_at.is_some() { "ended" } else { "active" });
+    }
+    Ok(())
+}
+
+async fn handle_registrations_command(api_client: &ApiClient) -> Result<(), Box<dyn std::error::Error>> {
+    let registrations = api_client.get_trunk_registrations().await?;
+    if registrations.is_empty() {
+        println!("No registering trunks");
+        return Ok(());
+    }
+    println!("{:<16} {:<22} {:<11} {:>8} {:<20} {:>10}  {}",
+             "Trunk", "Registrar", "State", "Failures", "Next attempt", "Interval", "Last error");
+    for registration in registrations {
+        println!("{:<16} {:<22} {:<11} {:>8} {:<20} {:>9}s  {}",
+                 registration.trunk,
+                 registration.registrar,
+                 format!("{:?}", registration.state),
+                 registration.consecutive_failures,
+                 registration.next_attempt_at.format("%Y-%m-%d %H:%M:%S"),
+                 registration.next_interval_secs,
+                 registration.last_error.as_deref().unwrap_or("-"));
+    }
+    Ok(())
+}
+
+async fn handle_prompts_command(
+    action: PromptAction,
+    api_client: &ApiClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        PromptAction::List => {
+            let packs = api_client.get_prompt_packs().await?;
+            if packs.is_empty() {
+                println!("No prompt packs installed");
+                return Ok(());
+            }
+            for pack in packs {
+                println!("{} ({} prompts)", pack.language, pack.prompts.len());
+                for prompt in &pack.prompts {
+                    println!("  {:<32} {:<9} {:>8}ms", prompt.event, format!("{:?}", prompt.format), prompt.duration_ms);
+                }
+                if !pack.missing.is_empty() {
+                    println!("  Missing: {}", pack.missing.join(", "));
+                }
+            }
+        }
+        PromptAction::Upload { language, event, file } => {
+            let audio = std::fs::read(&file)?;
+            // Catch unusable recordings before they leave this host
+            parse_prompt(&event, &audio)?;
+            let info = api_client.upload_prompt(&language, &event, audio).await?;
+            println!("Uploaded {} prompt {} ({:?}, {}ms)", language, info.event, info.format, info.duration_ms);
+        }
+        PromptAction::Remove { language, event } => {
+            api_client.remove_prompt(&language, &event).await?;
+            println!("Removed {} prompt {}", language, event);
+        }
+        PromptAction::Validate { file } => {
+            let audio = std::fs::read(&file)?;
+            let info = parse_prompt(&file, &audio)?;
+            println!("{} is usable ({:?}, {}ms)", file, info.format, info.duration_ms);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_campaigns_command(
+    action: CampaignAction,
+    api_client: &ApiClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        CampaignAction::List => {
+            let campaigns = api_client.get_campaigns().await?;
+            if campaigns.is_empty() {
+                println!("No campaigns");
+                return Ok(());
+            }
+            println!("{:<36} {:<20} {:<10} {:>7} {:>7} {:>8} {:>5} {:>9} {:>8} {:>6}",
+                     "ID", "Name", "Status", "Numbers", "Pending", "Answered", "Busy", "No answer", "Rejected", "Failed");
+            for campaign in campaigns {
+                println!("{:<36} {:<20} {:<10} {:>7} {:>7} {:>8} {:>5} {:>9} {:>8} {:>6}",
+                         campaign.id,
+                         campaign.name,
+                         format!("{:?}", campaign.status),
+                         campaign.destinations,
+                         campaign.pending,
+                         campaign.answered,
+                         campaign.busy,
+                         campaign.no_answer,
+                         campaign.rejected,
+                         campaign.failed);
+            }
+        }
+        CampaignAction::Start { name, prompt, numbers, trunk, cps, start_at } => {
+            let destinations = std::fs::read_to_string(&numbers)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect();
+            let request = CampaignRequest { name, destinations, prompt, trunks: trunk, cps, start_at };
+            let campaign = api_client.start_campaign(&request).await?;
+            println!("Campaign {} {:?}: {} numbers over {} at {} calls per second",
+                     campaign.id, campaign.status, campaign.calls.len(), campaign.trunks.join(", "), campaign.cps);
+        }
+        CampaignAction::Show { id } => {
+            let campaign = api_client.get_campaign(&id).await?;
+            println!("{} ({:?}), prompt {}", campaign.name, campaign.status, campaign.prompt);
+            println!("{:<20} {:<10} {:>8} {:<16}  {}", "Number", "Outcome", "Attempts", "Trunk", "Detail");
+            for call in campaign.calls {
+                let (outcome, detail) = match call.outcome {
+                    CallOutcome::Answered { played_ms } => ("answered", format!("played {}ms", played_ms)),
+                    CallOutcome::Rejected { status_code, reason } => ("rejected", format!("{} {}", status_code, reason)),
+                    CallOutcome::Failed { reason } => ("failed", reason),
+                    CallOutcome::Pending => ("pending", String::new()),
+                    CallOutcome::Calling => ("calling", String::new()),
+                    CallOutcome::Busy => ("busy", String::new()),
+                    CallOutcome::NoAnswer => ("no answer", String::new()),
+                    CallOutcome::Cancelled => ("cancelled", String::new()),
+                };
+                println!("{:<20} {:<10} {:>8} {:<16}  {}",
+                         call.destination, outcome, call.attempts, call.trunk.as_deref().unwrap_or("-"), detail);
+            }
+        }
+        CampaignAction::Cancel { id } => {
+            let summary = api_client.cancel_campaign(&id).await?;
+            println!("Cancelled campaign {}: {} answered, {} numbers not called", summary.name, summary.answered, summary.cancelled);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_taps_command(
+    token: &str,
+    action: TapAction,
+    api_client: &ApiClient,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        TapAction::List => {
+            let taps = api_client.get_audio_taps(token).await?;
+            if taps.is_empty() {
+                println!("No audio taps running");
+                return Ok(());
+            }
+            println!("{:<36} {:<12} {:<16} {:>10} {:<20}  {}",
+                     "ID", "Operator", "Channels", "Octets", "Expires", "Reason");
+            for tap in taps {
+                let channels: Vec<String> = tap.channels.iter().map(ToString::to_string).collect();
+                println!("{:<36} {:<12} {:<16} {:>10} {:<20}  {}",
+                         tap.id,
+                         tap.operator,
+                         channels.join(","),
+                         tap.octets,
+                         tap.expires_at.format("%Y-%m-%d %H:%M:%S"),
+                         tap.reason);
+            }
+        }
+        TapAction::Start { reason, span, channel, call, multicast, minutes } => {
+            let target = match (span, channel, call) {
+                (Some(span_id), Some(channel_id), None) => TapTarget::Channel { span_id, channel_id },
+                (None, None, Some(call_id)) => TapTarget::Call { call_id },
+                _ => return Err("Give --span and --channel, or --call".into()),
+            };
+            let sink = match multicast {
+                Some(group) => TapSink::Multicast { group },
+                None => TapSink::File,
+            };
+            let request = TapRequest { reason, target, sink, duration_secs: minutes.map(|minutes| minutes * 60) };
+            let tap = api_client.start_audio_tap(token, &request).await?;
+            println!("Audio tap {} running until {}", tap.id, tap.expires_at.format("%Y-%m-%d %H:%M:%S UTC"));
+            for file in &tap.files {
+                println!("  {}", file);
+            }
+        }
+        TapAction::Stop { id } => {
+            let tap = api_client.stop_audio_tap(token, &id).await?;
+            println!("Audio tap {} stopped after {} octets", tap.id, tap.octets);
+        }
+        TapAction::Audit => {
+            for record in api_client.get_audio_tap_audit(token).await? {
+                println!("{} {:<9} {:<12} {:<36} {}",
+                         record.at.format("%Y-%m-%d %H:%M:%S"),
+                         format!("{:?}", record.action),
+                         record.operator.as_deref().unwrap_or("-"),
+                         record.tap_id.as_deref().unwrap_or("-"),
+                         record.detail);
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn handle_config_command(
     action: ConfigAction,
     _api_client: &ApiClient,
@@ -775,4 +1983,4 @@ async fn handle_config_command(
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,5 +1,6 @@
 //! Redfire Gateway Advanced Diagnostics CLI Tool
 
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::net::SocketAddr;
 use std::time::Duration;
@@ -7,8 +8,24 @@ use std::time::Duration;
 use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
 use colored::*;
+use tokio::net::UdpSocket;
 use tokio::time::sleep;
 
+use redfire_gateway::config::Layer1Type;
+use redfire_gateway::core::control::ControlStatus;
+use redfire_gateway::services::channel_history::{ChannelCall, CHANNEL_HISTORY_PATH};
+use redfire_gateway::services::gateway_diff::{self, DiffReport, DiffSection, GatewaySnapshot};
+use redfire_gateway::services::management_api::{
+    ActiveCallReport, AlarmReport, ServiceReport, SpanReport, ACTIVE_CALLS_PATH, ALARMS_PATH, CONFIG_PATH,
+    SERVICES_PATH, SPANS_PATH, STATUS_PATH,
+};
+use redfire_gateway::services::release_causes::{ReleaseCauseReport, RELEASE_CAUSES_PATH};
+use redfire_gateway::services::trunk_registration::{
+    TrunkRegistrationState, TrunkRegistrationStatus, TRUNK_REGISTRATIONS_PATH,
+};
+use redfire_gateway::testing::q931_conformance::Verdict;
+use redfire_gateway::testing::{ConformanceConfig, ConformanceSuite, ConformanceTarget};
+
 
 #[derive(Parser)]
 #[command(name = "redfire-diag")]
@@ -26,6 +43,10 @@ struct DiagCli {
     #[arg(short, long, default_value = "8080")]
     port: u16,
 
+    /// Management API secret, sent as a bearer token (also to a compared peer)
+    #[arg(long, env = "REDFIRE_API_SECRET")]
+    api_secret: Option<String>,
+
     /// Enable verbose output
     #[arg(short, long)]
     verbose: bool,
@@ -66,6 +87,9 @@ enum DiagCommands {
     
     /// Performance analysis and bottleneck detection
     Performance {
+        #[command(subcommand)]
+        command: Option<PerformanceCommands>,
+
         /// Analysis duration in seconds
         #[arg(short, long, default_value = "60")]
         duration: u64,
@@ -100,6 +124,47 @@ enum DiagCommands {
         #[arg(short, long)]
         output: Option<String>,
     },
+
+    /// Diff configuration and key state against another gateway
+    Compare {
+        /// Gateway to compare against
+        #[arg(long)]
+        peer: String,
+
+        /// Management port of the peer; the same as this gateway's when omitted
+        #[arg(long)]
+        peer_port: Option<u16>,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
+
+        /// Also write the JSON report, both snapshots included, to this file
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PerformanceCommands {
+    /// Release causes, with the top failures and the destinations driving them
+    Causes {
+        /// Hours back from now; the gateway's retention when omitted
+        #[arg(long)]
+        hours: Option<u32>,
+
+        /// Only releases on this trunk
+        #[arg(short, long)]
+        trunk: Option<String>,
+
+        /// Only releases on this routing rule
+        #[arg(long)]
+        route: Option<String>,
+
+        /// Failure causes to rank
+        #[arg(long)]
+        top: Option<usize>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -246,6 +311,17 @@ enum ChannelCommands {
         #[arg(short, long)]
         detailed: bool,
     },
+    
+    /// Last calls on a span's channels
+    History {
+        /// Span to show
+        #[arg(short, long)]
+        span: u32,
+        
+        /// Channel to show; every channel of the span when omitted
+        #[arg(short, long)]
+        channel: Option<u8>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -325,8 +401,37 @@ enum TestCommands {
     
     /// Protocol conformance testing
     Conformance {
-        /// Protocol to test
+        /// Protocol to test (q931)
         protocol: String,
+
+        /// Span named on the report card
+        #[arg(long, default_value = "emulator")]
+        span: String,
+
+        /// TDMoE address of the span's network side; the built-in PSTN
+        /// simulator when not given
+        #[arg(long)]
+        peer: Option<SocketAddr>,
+
+        /// Local address for TDMoE frames to the peer
+        #[arg(long, default_value = "0.0.0.0:2427")]
+        bind: SocketAddr,
+
+        /// Span type, e1 or t1
+        #[arg(long, default_value = "e1")]
+        span_type: String,
+
+        /// Number the network side answers
+        #[arg(long, default_value = "4155550100")]
+        called: String,
+
+        /// Number the network side refuses as unallocated
+        #[arg(long, default_value = "4155550000")]
+        unallocated: String,
+
+        /// Print the report card as JSON
+        #[arg(long)]
+        json: bool,
     },
 }
 
@@ -432,7 +537,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         DiagCommands::Capture { ref command } => {
             run_capture_diagnostics(&cli, command).await?;
         },
-        DiagCommands::Performance { duration, report } => {
+        DiagCommands::Performance { command: Some(PerformanceCommands::Causes { hours, ref trunk, ref route, top }), .. } => {
+            println!("{}", "📉 Release Causes".bold().blue());
+            display_release_causes(&cli, hours, trunk.as_deref(), route.as_deref(), top).await?;
+        },
+        DiagCommands::Performance { command: None, duration, report } => {
             run_performance_analysis(&cli, duration, report).await?;
         },
         DiagCommands::Alarms { ref command } => {
@@ -447,6 +556,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         DiagCommands::Report { ref format, ref output } => {
             generate_system_report(&cli, format, output.as_deref()).await?;
         },
+        DiagCommands::Compare { ref peer, peer_port, ref format, ref output } => {
+            compare_gateways(&cli, peer, peer_port.unwrap_or(cli.port), format, output.as_deref()).await?;
+        },
     }
 
     Ok(())
@@ -473,13 +585,13 @@ async fn run_system_diagnostics(cli: &DiagCli, interval: u64) -> Result<(), Box<
         display_system_metrics().await;
         
         // Gateway status
-        display_gateway_status().await;
+        display_gateway_status(cli).await;
         
         // Active alarms
-        display_active_alarms().await;
+        display_active_alarms(cli).await;
         
         // Channel utilization
-        display_channel_utilization().await;
+        display_channel_utilization(cli).await;
         
         // Protocol statistics  
         display_protocol_stats().await;
@@ -555,7 +667,7 @@ async fn run_tdm_diagnostics(cli: &DiagCli, command: &TdmCommands) -> Result<(),
         },
         TdmCommands::LineStatus { span } => {
             println!("{}", "📈 Line Status and Alarms".bold().blue());
-            display_line_status(span.clone()).await?;
+            display_line_status(cli, *span).await?;
         },
         TdmCommands::Stack { detailed } => {
             println!("{}", "🏗️ Protocol Stack Analysis".bold().blue());
@@ -580,11 +692,11 @@ async fn run_channel_diagnostics(cli: &DiagCli, command: &ChannelCommands) -> Re
             }
             println!("Press Ctrl+C to exit\n");
 
-            monitor_channel_status(span.clone(), channel.clone(), *interval).await?;
+            monitor_channel_status(cli, *span, *channel, *interval).await?;
         },
         ChannelCommands::Calls { detailed, export } => {
             println!("{}", "📞 Active Call Analysis".bold().blue());
-            analyze_active_calls(*detailed, *export).await?;
+            analyze_active_calls(cli, *detailed, *export).await?;
         },
         ChannelCommands::Utilization { period } => {
             println!("{}", "📈 Channel Utilization Statistics".bold().blue());
@@ -594,6 +706,10 @@ async fn run_channel_diagnostics(cli: &DiagCli, command: &ChannelCommands) -> Re
             println!("{}", "🎵 Channel Quality Metrics".bold().blue());
             display_channel_quality(*detailed).await?;
         },
+        ChannelCommands::History { span, channel } => {
+            println!("{}", "🕘 Channel Call History".bold().blue());
+            display_channel_history(cli, *span, *channel).await?;
+        },
     }
     
     Ok(())
@@ -624,10 +740,15 @@ async fn run_performance_analysis(cli: &DiagCli, duration: u64, report: bool) ->
     println!("Duration: {} seconds", duration);
     println!("Generating report: {}\n", if report { "Yes" } else { "No" });
 
-    // Simulate performance analysis
-    let total_steps = duration;
-    
+    // One status sample a second for the whole duration
+    let total_steps = duration.max(1);
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut samples = Vec::new();
+
     for step in 0..total_steps {
+        ticker.tick().await;
+        samples.push(fetch::<ControlStatus>(cli, STATUS_PATH).await?);
+
         let progress = ((step + 1) * 100) / total_steps;
         print!("\rAnalysis Progress: [");
         
@@ -641,27 +762,25 @@ async fn run_performance_analysis(cli: &DiagCli, duration: u64, report: bool) ->
         }
         print!("] {}%", progress);
         io::stdout().flush()?;
-        
-        sleep(Duration::from_millis(100)).await;
     }
     
     println!("\n\n{}", "Performance Analysis Complete".bold().green());
     
     // Display results
-    display_performance_results(report).await?;
+    display_performance_results(&samples, report);
     
     Ok(())
 }
 
-async fn run_alarm_diagnostics(_cli: &DiagCli, command: &AlarmCommands) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_alarm_diagnostics(cli: &DiagCli, command: &AlarmCommands) -> Result<(), Box<dyn std::error::Error>> {
     match command {
         AlarmCommands::Monitor { severity } => {
             println!("{}", "🚨 Real-time Alarm Monitor".bold().blue());
-            monitor_alarms(severity.clone()).await?;
+            monitor_alarms(cli, severity.as_deref()).await?;
         },
         AlarmCommands::History { hours } => {
             println!("{}", "📜 Alarm History Analysis".bold().blue());
-            analyze_alarm_history(*hours).await?;
+            analyze_alarm_history(cli, *hours).await?;
         },
         AlarmCommands::Correlate { patterns } => {
             println!("{}", "🔗 Alarm Correlation Analysis".bold().blue());
@@ -672,19 +791,30 @@ async fn run_alarm_diagnostics(_cli: &DiagCli, command: &AlarmCommands) -> Resul
     Ok(())
 }
 
-async fn run_test_diagnostics(_cli: &DiagCli, command: &TestCommands) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_test_diagnostics(cli: &DiagCli, command: &TestCommands) -> Result<(), Box<dyn std::error::Error>> {
     match command {
         TestCommands::Connectivity { external } => {
             println!("{}", "🔗 Comprehensive Connectivity Test".bold().blue());
-            test_connectivity(*external).await?;
+            test_connectivity(cli, *external).await?;
         },
         TestCommands::Stress { duration, calls } => {
             println!("{}", "💪 System Stress Test".bold().blue());
             run_stress_test(*duration, *calls).await?;
         },
-        TestCommands::Conformance { protocol } => {
+        TestCommands::Conformance { protocol, span, peer, bind, span_type, called, unallocated, json } => {
             println!("{}", "✅ Protocol Conformance Test".bold().blue());
-            test_protocol_conformance(&protocol).await?;
+            let span_type = match span_type.to_ascii_lowercase().as_str() {
+                "e1" => Layer1Type::E1,
+                "t1" => Layer1Type::T1,
+                other => return Err(format!("Unknown span type {}", other).into()),
+            };
+            let config = ConformanceConfig {
+                span_type,
+                called: called.clone(),
+                unallocated: unallocated.clone(),
+                ..ConformanceConfig::default()
+            };
+            test_protocol_conformance(protocol, span, config, *peer, *bind, *json).await?;
         },
     }
     
@@ -710,9 +840,9 @@ async fn run_interactive_mode(cli: &DiagCli) -> Result<(), Box<dyn std::error::E
         match input {
             "quit" | "exit" => break,
             "help" => show_interactive_help(),
-            "status" => display_quick_status().await,
-            "alarms" => display_quick_alarms().await,
-            "channels" => display_quick_channels().await,
+            "status" => display_quick_status(cli).await,
+            "alarms" => display_quick_alarms(cli).await,
+            "channels" => display_quick_channels(cli).await,
             "sip" => display_quick_sip().await,
             _ => {
                 if input.starts_with("debug ") {
@@ -772,28 +902,186 @@ async fn display_system_metrics() {
     println!();
 }
 
-async fn display_gateway_status() {
+/// GET `path` from the gateway's management API
+async fn fetch<T: serde::de::DeserializeOwned>(cli: &DiagCli, path: &str) -> Result<T, Box<dyn std::error::Error>> {
+    fetch_from(&cli.host, cli.port, cli.api_secret.as_deref(), path).await
+}
+
+/// GET `path` from the management API of the gateway at `host`, with the
+/// API secret when there is one
+async fn fetch_from<T: serde::de::DeserializeOwned>(
+    host: &str,
+    port: u16,
+    secret: Option<&str>,
+    path: &str,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let mut request = reqwest::Client::new().get(format!("http://{}:{}{}", host, port, path));
+    if let Some(secret) = secret {
+        request = request.bearer_auth(secret);
+    }
+    let response = tokio::time::timeout(Duration::from_secs(10), request.send()).await??;
+    Ok(response.error_for_status()?.json().await?)
+}
+
+/// Configuration and key state of the gateway at `host`. Registrations and
+/// configuration are left out where the gateway does not serve them
+async fn take_snapshot(
+    host: &str,
+    port: u16,
+    secret: Option<&str>,
+) -> Result<GatewaySnapshot, Box<dyn std::error::Error>> {
+    let status = fetch_from(host, port, secret, STATUS_PATH).await?;
+    let spans = fetch_from(host, port, secret, SPANS_PATH).await?;
+    Ok(GatewaySnapshot {
+        endpoint: format!("{}:{}", host, port),
+        taken_at: Utc::now(),
+        status,
+        spans,
+        trunk_registrations: fetch_from(host, port, secret, TRUNK_REGISTRATIONS_PATH).await.ok(),
+        config: fetch_from(host, port, secret, CONFIG_PATH).await.ok(),
+    })
+}
+
+async fn compare_gateways(
+    cli: &DiagCli,
+    peer: &str,
+    peer_port: u16,
+    format: &str,
+    output: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let secret = cli.api_secret.as_deref();
+    let (left, right) =
+        tokio::try_join!(take_snapshot(&cli.host, cli.port, secret), take_snapshot(peer, peer_port, secret))?;
+    let report = gateway_diff::compare(left, right);
+    if let Some(file) = output {
+        std::fs::write(file, serde_json::to_string_pretty(&report)?)?;
+    }
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&report.differences)?),
+        _ => display_diff_report(&report),
+    }
+    if let Some(file) = output {
+        println!("Report written to {}", file);
+    }
+    Ok(())
+}
+
+fn display_diff_value(value: Option<&serde_json::Value>) -> String {
+    value.map_or_else(|| "(absent)".to_string(), |value| value.to_string())
+}
+
+fn display_diff_report(report: &DiffReport) {
+    println!("{}", "🔍 Gateway Comparison".bold().blue());
+    for (side, snapshot) in [("A", &report.left), ("B", &report.right)] {
+        println!(
+            "  {}: {} ({}, version {})",
+            side,
+            snapshot.endpoint.cyan(),
+            snapshot.status.node_id,
+            snapshot.status.version
+        );
+        if snapshot.config.is_none() {
+            println!("     {}", "configuration not served; config and routing not compared".yellow());
+        }
+        if snapshot.trunk_registrations.is_none() {
+            println!("     {}", "trunk registrations not served; trunks not compared".yellow());
+        }
+    }
+    println!();
+    if report.is_empty() {
+        println!("{}", "No differences found".green());
+        return;
+    }
+    for section in DiffSection::ALL {
+        let differences: Vec<_> = report.section(section).collect();
+        if differences.is_empty() {
+            continue;
+        }
+        println!("{} ({})", section.to_string().bold(), differences.len());
+        for difference in differences {
+            let path = if difference.path.is_empty() { "(all)" } else { difference.path.as_str() };
+            match (&difference.left, &difference.right) {
+                (Some(left), None) => println!("  {} {} = {}", "-".red(), path, display_diff_value(Some(left))),
+                (None, Some(right)) => println!("  {} {} = {}", "+".green(), path, display_diff_value(Some(right))),
+                (left, right) => println!(
+                    "  {} {}: {} → {}",
+                    "~".yellow(),
+                    path,
+                    display_diff_value(left.as_ref()).red(),
+                    display_diff_value(right.as_ref()).green()
+                ),
+            }
+        }
+        println!();
+    }
+    println!("{} differences; - only on A, + only on B", report.differences.len());
+}
+
+fn format_uptime(secs: u64) -> String {
+    format!("{}d {}h {}m", secs / 86400, secs % 86400 / 3600, secs % 3600 / 60)
+}
+
+fn count_severity(alarms: &[AlarmReport], severity: &str) -> usize {
+    alarms.iter().filter(|alarm| alarm.severity == severity).count()
+}
+
+async fn display_gateway_status(cli: &DiagCli) {
     println!("{}", "Gateway Status:".bold());
-    println!("  Version:      {}", redfire_gateway::VERSION.green());
-    println!("  Uptime:       {}", "2d 5h 32m".green());
-    println!("  Spans:        {} {} / {} {}", "2".green(), "UP".green(), "1".red(), "DOWN".red());
-    println!("  Active Calls: {}", "7".yellow());
-    println!("  SIP Sessions: {}", "12".cyan());
+    match fetch::<ControlStatus>(cli, STATUS_PATH).await {
+        Ok(status) => {
+            let up = status.spans.iter().filter(|span| span.up).count();
+            let down = status.spans.len() - up;
+            println!("  Version:      {}", status.version.green());
+            println!("  Uptime:       {}", format_uptime(status.uptime_secs).green());
+            println!("  Spans:        {} {} / {} {}", up.to_string().green(), "UP".green(), down.to_string().red(), "DOWN".red());
+            println!("  Active Calls: {}", status.active_calls.to_string().yellow());
+            println!("  SIP Sessions: {}", status.sip_sessions.to_string().cyan());
+        }
+        Err(e) => println!("  {}: {}", "Unavailable".red(), e),
+    }
     println!();
 }
 
-async fn display_active_alarms() {
+async fn display_active_alarms(cli: &DiagCli) {
     println!("{}", "Active Alarms:".bold());
-    println!("  🔴 {} Critical", "0".green());
-    println!("  🟡 {} Major", "1".yellow());
-    println!("  🔵 {} Minor", "3".blue());
+    match fetch::<Vec<AlarmReport>>(cli, ALARMS_PATH).await {
+        Ok(alarms) => {
+            println!("  🔴 {} Critical", count_severity(&alarms, "critical").to_string().red());
+            println!("  🟡 {} Major", count_severity(&alarms, "major").to_string().yellow());
+            println!("  🔵 {} Minor", count_severity(&alarms, "minor").to_string().blue());
+        }
+        Err(e) => println!("  {}: {}", "Unavailable".red(), e),
+    }
     println!();
 }
 
-async fn display_channel_utilization() {
+/// Busy and total B-channels of a span
+fn span_usage(span: &SpanReport) -> (usize, usize) {
+    let busy = span.channels.iter().filter(|channel| channel.state == "in_use").count();
+    (busy, span.channels.len())
+}
+
+async fn display_channel_utilization(cli: &DiagCli) {
     println!("{}", "Channel Utilization:".bold());
-    println!("  Span 1 (E1):  [████████░░░░░░░░░░░░] 30% (9/30)");
-    println!("  Span 2 (T1):  [████░░░░░░░░░░░░░░░░] 17% (4/24)");
+    match fetch::<Vec<SpanReport>>(cli, SPANS_PATH).await {
+        Ok(spans) => {
+            for span in spans {
+                let (busy, total) = span_usage(&span);
+                let percent = if total > 0 { busy * 100 / total } else { 0 };
+                let filled = percent / 5;
+                println!("  Span {} ({}):  [{}{}] {}% ({}/{})",
+                    span.span_id,
+                    span.trunk_type.to_uppercase(),
+                    "█".repeat(filled),
+                    "░".repeat(20 - filled),
+                    percent,
+                    busy,
+                    total
+                );
+            }
+        }
+        Err(e) => println!("  {}: {}", "Unavailable".red(), e),
+    }
     println!();
 }
 
@@ -935,7 +1223,12 @@ fn display_d_channel_message(msg: &DChannelMessageDebug, hex: bool) {
     println!();
 }
 
-async fn monitor_channel_status(span: Option<u32>, channel: Option<u8>, interval_secs: u64) -> Result<(), Box<dyn std::error::Error>> {
+async fn monitor_channel_status(
+    cli: &DiagCli,
+    span: Option<u32>,
+    channel: Option<u8>,
+    interval_secs: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
     
     loop {
@@ -948,65 +1241,53 @@ async fn monitor_channel_status(span: Option<u32>, channel: Option<u8>, interval
         println!("{} - {}", "B-Channel Status".bold().green(), now.format("%H:%M:%S UTC"));
         println!("{}", "─".repeat(80));
         
-        // Generate sample channel status
-        let channels = generate_sample_channel_status(span, channel);
+        let spans: Vec<SpanReport> = fetch(cli, SPANS_PATH).await?;
+        let calls: Vec<ActiveCallReport> = fetch(cli, ACTIVE_CALLS_PATH).await?;
+        let channels = channel_status(&spans, &calls, span, channel);
         
         display_channel_status_table(&channels);
     }
 }
 
-fn generate_sample_channel_status(span_filter: Option<u32>, channel_filter: Option<u8>) -> Vec<BChannelStatus> {
+/// B-channel states with the calls occupying them
+fn channel_status(
+    spans: &[SpanReport],
+    calls: &[ActiveCallReport],
+    span_filter: Option<u32>,
+    channel_filter: Option<u8>,
+) -> Vec<BChannelStatus> {
     let mut channels = Vec::new();
     
-    let spans = if let Some(s) = span_filter { vec![s] } else { vec![1, 2] };
-    
-    for &span_id in &spans {
-        let max_channels = if span_id == 1 { 30 } else { 24 }; // E1 vs T1
-        let channel_range = if let Some(c) = channel_filter {
-            vec![c]
-        } else {
-            (1..=max_channels).collect()
-        };
-        
-        for &channel_id in &channel_range {
-            // Skip D-channel (16 for E1)
-            if span_id == 1 && channel_id == 16 {
-                continue;
-            }
-            
-            let status = match (span_id + channel_id as u32) % 5 {
-                0 => ChannelState::Connected,
-                1 => ChannelState::Inbound,
-                2 => ChannelState::Outbound,
+    for span in spans.iter().filter(|span| span_filter.map_or(true, |s| s == span.span_id)) {
+        for channel in span.channels.iter().filter(|channel| channel_filter.map_or(true, |c| c == channel.channel_id)) {
+            let slot = format!("{}/{}", span.span_id, channel.channel_id);
+            let call = calls.iter().find(|call| call.ingress == slot || call.egress == slot);
+            let state = match (channel.state.as_str(), call) {
+                (_, Some(call)) if call.state == "connected" => ChannelState::Connected,
+                (_, Some(call)) if call.ingress == slot => ChannelState::Inbound,
+                (_, Some(_)) => ChannelState::Outbound,
+                ("in_use", None) => ChannelState::Connected,
+                ("blocked", None) => ChannelState::Maintenance,
+                ("out_of_service", None) => ChannelState::OutOfService,
                 _ => ChannelState::Idle,
             };
-            
-            let (call_id, caller, callee, start_time) = if matches!(status, ChannelState::Connected | ChannelState::Inbound | ChannelState::Outbound) {
-                (
-                    Some(format!("call-{}-{}", span_id, channel_id)),
-                    Some("1234567890".to_string()),
-                    Some("0987654321".to_string()),
-                    Some(Utc::now() - chrono::Duration::minutes((channel_id as i64) * 2)),
-                )
-            } else {
-                (None, None, None, None)
-            };
+            let duration = call.map(|call| Duration::from_secs(call.duration_secs));
             
             channels.push(BChannelStatus {
-                span_id,
-                channel_id,
-                state: status,
-                call_id: call_id.clone(),
-                caller,
-                callee,
-                start_time,
-                duration: start_time.map(|st| (Utc::now() - st).to_std().unwrap_or(Duration::from_secs(0))),
-                codec: if call_id.is_some() { Some("G.711A".to_string()) } else { None },
+                span_id: span.span_id,
+                channel_id: channel.channel_id,
+                state,
+                call_id: call.map(|call| call.id.clone()),
+                caller: call.and_then(|call| call.calling_number.clone()),
+                callee: call.map(|call| call.called_number.clone()),
+                start_time: duration.and_then(|duration| chrono::Duration::from_std(duration).ok()).map(|elapsed| Utc::now() - elapsed),
+                duration,
+                codec: None,
                 quality_metrics: QualityMetrics {
-                    packet_loss: 0.1,
-                    jitter: 2.5,
-                    latency: 15.0,
-                    mos_score: Some(4.2),
+                    packet_loss: 0.0,
+                    jitter: 0.0,
+                    latency: 0.0,
+                    mos_score: None,
                 },
             });
         }
@@ -1069,6 +1350,129 @@ fn display_channel_status_table(channels: &[BChannelStatus]) {
         total, active, idle, utilization);
 }
 
+async fn display_release_causes(
+    cli: &DiagCli,
+    hours: Option<u32>,
+    trunk: Option<&str>,
+    route: Option<&str>,
+    top: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut params: Vec<(&str, String)> = Vec::new();
+    if let Some(hours) = hours {
+        params.push(("hours", hours.to_string()));
+    }
+    if let Some(trunk) = trunk {
+        params.push(("trunk", trunk.to_string()));
+    }
+    if let Some(route) = route {
+        params.push(("route", route.to_string()));
+    }
+    if let Some(top) = top {
+        params.push(("top", top.to_string()));
+    }
+    let url = reqwest::Url::parse_with_params(&format!("http://{}:{}{}", cli.host, cli.port, RELEASE_CAUSES_PATH), &params)?;
+    let report: ReleaseCauseReport = fetch(cli, &format!("{}?{}", url.path(), url.query().unwrap_or(""))).await?;
+
+    println!("Period: {} to {}", report.from.format("%Y-%m-%d %H:%M"), report.to.format("%Y-%m-%d %H:%M"));
+    let failure_rate = if report.releases > 0 { report.failures as f64 * 100.0 / report.releases as f64 } else { 0.0 };
+    println!("Releases: {}, failures: {} ({:.1}%)\n", report.releases, report.failures, failure_rate);
+    if report.top_failures.is_empty() {
+        println!("No failures recorded");
+        return Ok(());
+    }
+
+    println!("{:<4} {:<6} {:<42} {:<8} {:<7} {:<20} {:<30}",
+        "#".bold(),
+        "Cause".bold(),
+        "Description".bold(),
+        "Calls".bold(),
+        "Share".bold(),
+        "Top trunk".bold(),
+        "Top destinations".bold()
+    );
+    println!("{}", "─".repeat(120));
+
+    for (rank, failure) in report.top_failures.iter().enumerate() {
+        let trunk_str = failure
+            .trunks
+            .first()
+            .map(|trunk| format!("{} ({})", if trunk.name.is_empty() { "-" } else { &trunk.name }, trunk.calls))
+            .unwrap_or_else(|| "-".to_string());
+        let destinations: Vec<String> = failure
+            .destinations
+            .iter()
+            .take(3)
+            .map(|destination| format!("{}… ({})", destination.name, destination.calls))
+            .collect();
+        let share_str = format!("{:.1}%", failure.share);
+
+        println!("{:<4} {:<6} {:<42} {:<8} {:<7} {:<20} {:<30}",
+            rank + 1,
+            failure.cause.to_string().red(),
+            failure.description,
+            failure.calls,
+            if failure.share >= 50.0 { share_str.red().to_string() } else { share_str },
+            trunk_str,
+            destinations.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+async fn display_channel_history(cli: &DiagCli, span: u32, channel: Option<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut path = format!("{}?span={}", CHANNEL_HISTORY_PATH, span);
+    if let Some(channel) = channel {
+        path.push_str(&format!("&channel={}", channel));
+    }
+    let calls: Vec<ChannelCall> = fetch(cli, &path).await?;
+    if calls.is_empty() {
+        println!("No calls recorded");
+        return Ok(());
+    }
+    
+    println!("{:<4} {:<20} {:<15} {:<15} {:<9} {:<9} {:<6} {:<5}",
+        "Ch".bold(),
+        "Started".bold(),
+        "Caller".bold(),
+        "Callee".bold(),
+        "Answered".bold(),
+        "Duration".bold(),
+        "Cause".bold(),
+        "MOS".bold()
+    );
+    println!("{}", "─".repeat(90));
+    
+    for call in calls {
+        let duration_str = match call.ended_at {
+            Some(ended) => {
+                let secs = (ended - call.started_at).num_seconds().max(0);
+                format!("{}:{:02}", secs / 60, secs % 60)
+            }
+            None => "active".green().to_string(),
+        };
+        let cause_str = match call.release_cause {
+            // Normal clearing is expected; anything else is worth a look
+            Some(16) => "16".to_string(),
+            Some(cause) => cause.to_string().red().to_string(),
+            None => "-".to_string(),
+        };
+        
+        println!("{:<4} {:<20} {:<15} {:<15} {:<9} {:<9} {:<6} {:<5}",
+            call.channel_id,
+            call.started_at.format("%Y-%m-%d %H:%M:%S"),
+            call.calling_number.as_deref().unwrap_or("-"),
+            call.called_number.as_deref().unwrap_or("-"),
+            if call.answered_at.is_some() { "yes" } else { "no" },
+            duration_str,
+            cause_str,
+            call.mos.map_or_else(|| "-".to_string(), |mos| format!("{:.1}", mos))
+        );
+    }
+    
+    Ok(())
+}
+
 // Placeholder implementations for other diagnostic functions
 
 async fn analyze_call_flow(call_id: &str, _export: bool) -> Result<(), Box<dyn std::error::Error>> {
@@ -1123,15 +1527,18 @@ async fn analyze_lapd_frames(_stats: bool) -> Result<(), Box<dyn std::error::Err
     Ok(())
 }
 
-async fn display_line_status(span: Option<u32>) -> Result<(), Box<dyn std::error::Error>> {
-    let spans = if let Some(s) = span { vec![s] } else { vec![1, 2] };
+async fn display_line_status(cli: &DiagCli, span: Option<u32>) -> Result<(), Box<dyn std::error::Error>> {
+    let spans: Vec<SpanReport> = fetch(cli, SPANS_PATH).await?;
     
-    for &span_id in &spans {
-        println!("Span {} Status:", span_id);
-        println!("  Line State: {}", "UP".green());
-        println!("  Framing: CRC4, Coding: HDB3");
-        println!("  Alarms: {}", "None".green());
-        println!("  Signal Level: -12.5 dBm");
+    for span in spans.iter().filter(|s| span.map_or(true, |id| id == s.span_id)) {
+        println!("Span {} ({}) Status:", span.span_id, span.name);
+        println!("  Line State: {}", if span.up { "UP".green() } else { "DOWN".red() });
+        println!("  Type: {}", span.trunk_type.to_uppercase());
+        if span.alarms.is_empty() {
+            println!("  Alarms: {}", "None".green());
+        } else {
+            println!("  Alarms: {}", span.alarms.join(", ").red());
+        }
         println!();
     }
     Ok(())
@@ -1145,11 +1552,32 @@ async fn analyze_protocol_stack(_detailed: bool) -> Result<(), Box<dyn std::erro
     Ok(())
 }
 
-async fn analyze_active_calls(_detailed: bool, _export: bool) -> Result<(), Box<dyn std::error::Error>> {
+async fn analyze_active_calls(cli: &DiagCli, detailed: bool, _export: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let calls: Vec<ActiveCallReport> = fetch(cli, ACTIVE_CALLS_PATH).await?;
+    let format_duration = |secs: u64| format!("{}m {}s", secs / 60, secs % 60);
+    
     println!("Active Calls Analysis:");
-    println!("  Total active: 7 calls");
-    println!("  Average duration: 3m 45s");
-    println!("  Longest call: 15m 23s");
+    println!("  Total active: {} calls", calls.len());
+    if let Some(longest) = calls.iter().map(|call| call.duration_secs).max() {
+        let average = calls.iter().map(|call| call.duration_secs).sum::<u64>() / calls.len() as u64;
+        println!("  Average duration: {}", format_duration(average));
+        println!("  Longest call: {}", format_duration(longest));
+    }
+    if detailed {
+        println!();
+        for call in &calls {
+            println!("  {} {} -> {} via {} ({} to {}) {} {}",
+                call.id,
+                call.calling_number.as_deref().unwrap_or("-"),
+                call.called_number,
+                call.route,
+                call.ingress,
+                call.egress,
+                call.state,
+                format_duration(call.duration_secs)
+            );
+        }
+    }
     Ok(())
 }
 
@@ -1188,54 +1616,211 @@ async fn analyze_packet_capture(_file: &str, _analysis: &str) -> Result<(), Box<
     Ok(())
 }
 
-async fn display_performance_results(_report: bool) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Performance Analysis Results:");
-    println!("  CPU bottlenecks: {}", "None detected".green());
-    println!("  Memory usage: {}", "Stable".green());
-    println!("  Network I/O: {}", "Normal".green());
-    println!("  Call processing: {} ms average", "45".green());
-    Ok(())
+/// Minimum, average and peak of the status samples taken during the analysis
+fn display_performance_results(samples: &[ControlStatus], report: bool) {
+    println!("Performance Analysis Results ({} samples):", samples.len());
+    let gauges: [(&str, fn(&ControlStatus) -> u32); 4] = [
+        ("Active calls", |status| status.active_calls),
+        ("Busy channels", |status| status.active_channels),
+        ("SIP sessions", |status| status.sip_sessions),
+        ("RTP sessions", |status| status.rtp_sessions),
+    ];
+    for (name, gauge) in gauges {
+        let values: Vec<u32> = samples.iter().map(gauge).collect();
+        let (Some(min), Some(peak)) = (values.iter().min(), values.iter().max()) else { continue };
+        let average = values.iter().map(|&value| u64::from(value)).sum::<u64>() / values.len() as u64;
+        println!("  {:<14} min {:>5}  avg {:>5}  peak {:>5}", name, min, average, peak.to_string().yellow());
+    }
+    let down: Vec<&str> = samples
+        .last()
+        .map(|status| status.spans.iter().filter(|span| !span.up).map(|span| span.name.as_str()).collect())
+        .unwrap_or_default();
+    if down.is_empty() {
+        println!("  Spans down:    {}", "None".green());
+    } else {
+        println!("  Spans down:    {}", down.join(", ").red());
+    }
+
+    if report {
+        println!("\n  {:>4}  {:>6}  {:>8}  {:>4}  {:>4}", "Sec", "Calls", "Channels", "SIP", "RTP");
+        for (second, status) in samples.iter().enumerate() {
+            println!(
+                "  {:>4}  {:>6}  {:>8}  {:>4}  {:>4}",
+                second + 1,
+                status.active_calls,
+                status.active_channels,
+                status.sip_sessions,
+                status.rtp_sessions
+            );
+        }
+    }
 }
 
-async fn monitor_alarms(_severity: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Monitoring alarms in real-time...");
-    println!("(No active alarms)");
-    Ok(())
+fn display_alarm(alarm: &AlarmReport) {
+    let severity = match alarm.severity.as_str() {
+        "critical" => alarm.severity.to_uppercase().red().bold(),
+        "major" => alarm.severity.to_uppercase().red(),
+        "minor" => alarm.severity.to_uppercase().yellow(),
+        _ => alarm.severity.to_uppercase().normal(),
+    };
+    println!(
+        "  [{}] {} {} {}: {}",
+        alarm.raised_time.format("%H:%M:%S"),
+        severity,
+        alarm.alarm_type,
+        alarm.source.cyan(),
+        alarm.description
+    );
+}
+
+/// Poll the gateway's alarms, printing each one as it is raised and clears
+async fn monitor_alarms(cli: &DiagCli, severity: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut ticker = tokio::time::interval(Duration::from_secs(2));
+    let mut seen: HashMap<String, AlarmReport> = HashMap::new();
+    println!("Monitoring alarms (Ctrl+C to stop)...");
+
+    loop {
+        ticker.tick().await;
+        let alarms: Vec<AlarmReport> = fetch(cli, ALARMS_PATH).await?;
+        let alarms: HashMap<String, AlarmReport> = alarms
+            .into_iter()
+            .filter(|alarm| match severity {
+                Some(severity) => alarm.severity.eq_ignore_ascii_case(severity),
+                None => true,
+            })
+            .map(|alarm| (alarm.id.clone(), alarm))
+            .collect();
+
+        for alarm in alarms.values().filter(|alarm| !seen.contains_key(&alarm.id)) {
+            display_alarm(alarm);
+        }
+        for alarm in seen.values().filter(|alarm| !alarms.contains_key(&alarm.id)) {
+            let now = Utc::now().format("%H:%M:%S");
+            println!("  [{}] {} {} {}", now, "CLEARED".green(), alarm.alarm_type, alarm.source.cyan());
+        }
+        seen = alarms;
+    }
 }
 
-async fn analyze_alarm_history(_hours: u64) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Alarm History Analysis:");
-    println!("  Total alarms: 23");
-    println!("  Critical: 0, Major: 3, Minor: 20");
-    println!("  Most common: Interface flap (Span 2)");
+/// Summary of the active alarms raised in the last `hours`; the gateway does
+/// not keep cleared alarms, so they are not counted
+async fn analyze_alarm_history(cli: &DiagCli, hours: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let since = Utc::now() - chrono::Duration::hours(i64::try_from(hours)?);
+    let alarms: Vec<AlarmReport> = fetch(cli, ALARMS_PATH).await?;
+    let alarms: Vec<AlarmReport> = alarms.into_iter().filter(|alarm| alarm.raised_time >= since).collect();
+
+    println!("Active alarms raised in the last {} hours (cleared alarms are not kept):", hours);
+    println!("  Total alarms: {}", alarms.len());
+    println!(
+        "  Critical: {}, Major: {}, Minor: {}",
+        count_severity(&alarms, "critical"),
+        count_severity(&alarms, "major"),
+        count_severity(&alarms, "minor")
+    );
+    let mut by_source: HashMap<&str, u32> = HashMap::new();
+    for alarm in &alarms {
+        *by_source.entry(alarm.source.as_str()).or_default() += alarm.event_count.max(1);
+    }
+    let busiest = by_source.into_iter().max_by_key(|&(source, events)| (events, std::cmp::Reverse(source)));
+    if let Some((source, events)) = busiest {
+        println!("  Most events: {} ({})", source, events);
+    }
     Ok(())
 }
 
 async fn correlate_alarms(_patterns: bool) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Alarm Correlation Analysis:");
-    println!("  Pattern detected: Network congestion → Call failures");
-    Ok(())
+    Err("Alarm correlation is not supported: the management API keeps no alarm history to correlate".into())
 }
 
-async fn test_connectivity(_external: bool) -> Result<(), Box<dyn std::error::Error>> {
+fn display_check(name: &str, passed: bool, detail: &str) {
+    let verdict = if passed { "PASS".green() } else { "FAIL".red() };
+    println!("  {:<24} {} {}", name, verdict, detail);
+}
+
+/// Check the management API answers, every enabled service runs and, with
+/// `external`, that every registering trunk is registered
+async fn test_connectivity(cli: &DiagCli, external: bool) -> Result<(), Box<dyn std::error::Error>> {
     println!("Testing connectivity...");
-    println!("  Internal services: {}", "PASS".green());
-    println!("  SIP registrar: {}", "PASS".green());
-    println!("  External routing: {}", "PASS".green());
+    let mut failures = 0;
+
+    let status = fetch::<ControlStatus>(cli, STATUS_PATH).await;
+    let detail = match &status {
+        Ok(status) => format!("{} ({})", status.node_id, status.version),
+        Err(e) => e.to_string(),
+    };
+    display_check("Management API", status.is_ok(), &detail);
+    if status.is_err() {
+        return Err("Gateway management API is unreachable".into());
+    }
+
+    let services: Vec<ServiceReport> = fetch(cli, SERVICES_PATH).await?;
+    for service in services.iter().filter(|service| service.state != "disabled") {
+        let running = service.state == "running";
+        failures += usize::from(!running);
+        display_check(&service.name, running, &service.state);
+    }
+
+    if external {
+        match fetch::<Vec<TrunkRegistrationStatus>>(cli, TRUNK_REGISTRATIONS_PATH).await {
+            Ok(trunks) => {
+                for trunk in &trunks {
+                    let registered = trunk.state == TrunkRegistrationState::Registered;
+                    failures += usize::from(!registered);
+                    let detail = trunk.last_error.clone().unwrap_or_else(|| trunk.registrar.clone());
+                    display_check(&format!("Trunk {}", trunk.trunk), registered, &detail);
+                }
+            }
+            Err(e) => println!("  {:<24} {} {}", "Trunk registrations", "SKIP".yellow(), e),
+        }
+    }
+
+    if failures > 0 {
+        return Err(format!("{} connectivity checks failed", failures).into());
+    }
     Ok(())
 }
 
 async fn run_stress_test(_duration: u64, _calls: u32) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Running stress test...");
-    println!("Simulating high call volume...");
-    println!("{}: System stable under load", "PASS".green());
-    Ok(())
-}
+    Err("Stress testing is not supported from redfire-diag; drive load with a SIP traffic generator".into())
+}
+
+async fn test_protocol_conformance(
+    protocol: &str,
+    span: &str,
+    config: ConformanceConfig,
+    peer: Option<SocketAddr>,
+    bind: SocketAddr,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !protocol.eq_ignore_ascii_case("q931") {
+        return Err(format!("No conformance suite for {}; available: q931", protocol).into());
+    }
+    let target = match peer {
+        Some(peer) => ConformanceTarget::span(UdpSocket::bind(bind).await?, peer, &config.span_type),
+        None => ConformanceTarget::emulator(&config),
+    };
+    println!("Running Q.931 conformance cases against {}...", target.describe());
+    let report = ConformanceSuite::new(config, target).run(span).await;
 
-async fn test_protocol_conformance(_protocol: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Testing protocol conformance...");
-    println!("  Standards compliance: {}", "PASS".green());
-    println!("  Interoperability: {}", "PASS".green());
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for line in report.to_string().lines() {
+            let line = if line.contains(" PASS ") || line.ends_with("CONFORMANT") {
+                line.green()
+            } else if line.contains(" FAIL ") || line.contains("NOT CONFORMANT") {
+                line.red()
+            } else if line.contains(" SKIP ") {
+                line.yellow()
+            } else {
+                line.normal()
+            };
+            println!("{}", line);
+        }
+    }
+    if !report.conformant() {
+        return Err(format!("{} Q.931 conformance cases failed", report.count(Verdict::Fail)).into());
+    }
     Ok(())
 }
 
@@ -1250,22 +1835,43 @@ fn show_interactive_help() {
     println!("  quit/exit  - Exit interactive mode");
 }
 
-async fn display_quick_status() {
+async fn display_quick_status(cli: &DiagCli) {
     println!("Quick Status:");
-    println!("  System: {}", "OK".green());
-    println!("  Calls: 7 active");
-    println!("  Alarms: 1 minor");
+    match (fetch::<ControlStatus>(cli, STATUS_PATH).await, fetch::<Vec<AlarmReport>>(cli, ALARMS_PATH).await) {
+        (Ok(status), Ok(alarms)) => {
+            println!("  System: {}", if status.running { "OK".green() } else { "STOPPED".red() });
+            println!("  Calls: {} active", status.active_calls);
+            println!("  Alarms: {} active", alarms.len());
+        }
+        (Err(e), _) | (_, Err(e)) => println!("  System: {} ({})", "UNREACHABLE".red(), e),
+    }
 }
 
-async fn display_quick_alarms() {
+async fn display_quick_alarms(cli: &DiagCli) {
     println!("Active Alarms:");
-    println!("  Interface flap on Span 2 (Minor)");
+    match fetch::<Vec<AlarmReport>>(cli, ALARMS_PATH).await {
+        Ok(alarms) if alarms.is_empty() => println!("  None"),
+        Ok(alarms) => {
+            for alarm in alarms {
+                println!("  {} on {} ({})", alarm.description, alarm.source, alarm.severity);
+            }
+        }
+        Err(e) => println!("  {}: {}", "Unavailable".red(), e),
+    }
 }
 
-async fn display_quick_channels() {
+async fn display_quick_channels(cli: &DiagCli) {
     println!("Channel Summary:");
-    println!("  Span 1: 9/30 active (30%)");
-    println!("  Span 2: 4/24 active (17%)");
+    match fetch::<Vec<SpanReport>>(cli, SPANS_PATH).await {
+        Ok(spans) => {
+            for span in spans {
+                let (busy, total) = span_usage(&span);
+                let percent = if total > 0 { busy * 100 / total } else { 0 };
+                println!("  Span {}: {}/{} active ({}%)", span.span_id, busy, total, percent);
+            }
+        }
+        Err(e) => println!("  {}: {}", "Unavailable".red(), e),
+    }
 }
 
 async fn display_quick_sip() {
@@ -44,6 +44,19 @@ struct Cli {
     #[arg(long, default_value = "ffmpeg")]
     ffmpeg_path: String,
 
+    /// Objective speech quality algorithm used to score captures
+    #[arg(long, value_enum, default_value = "pesq")]
+    quality_algorithm: QualityAlgorithm,
+
+    /// PESQ/POLQA executable path
+    #[arg(long, default_value = "pesq")]
+    quality_tool_path: String,
+
+    /// Quality tool arguments; `{reference}`, `{degraded}` and `{rate}` are
+    /// substituted (defaults to the algorithm's usual command line)
+    #[arg(long)]
+    quality_tool_args: Option<String>,
+
     /// Verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -145,9 +158,55 @@ enum Commands {
         /// Reference file for comparison
         #[arg(long)]
         reference: Option<PathBuf>,
+        /// Sample rate of the files in Hz (8000 narrowband, 16000 wideband)
+        #[arg(long, default_value = "8000")]
+        sample_rate: u32,
+        /// Lowest MOS-LQO counted as a pass
+        #[arg(long)]
+        min_mos: Option<f64>,
     },
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum QualityAlgorithm {
+    /// ITU-T P.862
+    Pesq,
+    /// ITU-T P.863
+    Polqa,
+}
+
+impl QualityAlgorithm {
+    fn default_args(&self) -> &'static str {
+        match self {
+            Self::Pesq => "+{rate} {reference} {degraded}",
+            Self::Polqa => "-Ref {reference} -Test {degraded}",
+        }
+    }
+}
+
+/// External PESQ/POLQA binary comparing a capture with its reference
+#[derive(Debug, Clone)]
+struct QualityTool {
+    algorithm: QualityAlgorithm,
+    path: String,
+    args: Option<String>,
+}
+
+impl QualityTool {
+    fn command_args(&self, reference: &Path, degraded: &Path, sample_rate: u32) -> Vec<String> {
+        self.args
+            .as_deref()
+            .unwrap_or(self.algorithm.default_args())
+            .split_whitespace()
+            .map(|arg| {
+                arg.replace("{reference}", &reference.to_string_lossy())
+                    .replace("{degraded}", &degraded.to_string_lossy())
+                    .replace("{rate}", &sample_rate.to_string())
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, ValueEnum, Serialize, Deserialize)]
 enum TestCodec {
     G711u,
@@ -221,6 +280,7 @@ struct TestRunner {
     output_dir: PathBuf,
     sipp_path: String,
     ffmpeg_path: String,
+    quality_tool: QualityTool,
     results: Vec<TestResult>,
 }
 
@@ -231,6 +291,7 @@ impl TestRunner {
         output_dir: PathBuf,
         sipp_path: String,
         ffmpeg_path: String,
+        quality_tool: QualityTool,
     ) -> Self {
         Self {
             gateway,
@@ -238,6 +299,7 @@ impl TestRunner {
             output_dir,
             sipp_path,
             ffmpeg_path,
+            quality_tool,
             results: Vec::new(),
         }
     }
@@ -981,6 +1043,59 @@ impl TestRunner {
         Ok(output_path)
     }
 
+    /// Score a far-end capture against the transmitted reference with the
+    /// external PESQ/POLQA tool and record the result
+    async fn run_quality_analysis(
+        &mut self,
+        reference: &Path,
+        capture: &Path,
+        sample_rate: u32,
+        min_mos: Option<f64>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        info!("Scoring {:?} against {:?} with {:?}", capture, reference, self.quality_tool.algorithm);
+
+        let start_time = Instant::now();
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+
+        let output = AsyncCommand::new(&self.quality_tool.path)
+            .args(self.quality_tool.command_args(reference, capture, sample_rate))
+            .output()
+            .await
+            .map_err(|e| format!("Failed to run {}: {}", self.quality_tool.path, e))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        fs::write(self.output_dir.join("logs/quality_tool.log"), stdout.as_bytes()).await?;
+        if !output.status.success() {
+            // Some PESQ builds exit non-zero even after printing a score
+            warnings.push(format!(
+                "{} exited with {}: {}",
+                self.quality_tool.path,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let metrics = parse_quality_output(&stdout);
+        match (metrics.get("mos_lqo"), min_mos) {
+            (None, _) => errors.push(format!("No MOS score found in {} output", self.quality_tool.path)),
+            (Some(mos), Some(min)) if *mos < min => errors.push(format!("MOS-LQO {:.2} below {:.2}", mos, min)),
+            _ => {}
+        }
+
+        let result = TestResult {
+            test_name: format!("media_quality_{:?}", self.quality_tool.algorithm).to_lowercase(),
+            success: errors.is_empty(),
+            duration: start_time.elapsed(),
+            metrics,
+            errors,
+            warnings,
+        };
+
+        self.results.push(result);
+        Ok(())
+    }
+
     async fn parse_sipp_output(&self, output: &[u8]) -> Result<HashMap<String, f64>, Box<dyn std::error::Error>> {
         let output_str = String::from_utf8_lossy(output);
         let mut metrics = HashMap::new();
@@ -1080,6 +1195,32 @@ impl TestRunner {
     }
 }
 
+/// Extract scores from PESQ/POLQA output. The MOS-LQO is the last number on
+/// the result line, e.g. `P.862 Prediction (Raw MOS, MOS-LQO):  = 2.987  3.012`
+/// or `POLQA MOS-LQO: 4.21`; P.862 also reports the raw MOS before it.
+fn parse_quality_output(output: &str) -> HashMap<String, f64> {
+    let mut metrics = HashMap::new();
+    let result_line = output
+        .lines()
+        .rev()
+        .find(|line| line.contains("MOS-LQO") || line.contains("PESQ_MOS") || line.contains("MOS ="));
+    if let Some(line) = result_line {
+        let scores_text = line.rsplit_once('=').or_else(|| line.rsplit_once(':')).map(|(_, v)| v).unwrap_or(line);
+        let scores: Vec<f64> = scores_text
+            .split_whitespace()
+            .filter_map(|value| value.trim_matches(|c: char| c == ',' || c == ';').parse().ok())
+            .filter(|score| (0.0..=5.0).contains(score))
+            .collect();
+        if let Some(mos) = scores.last() {
+            metrics.insert("mos_lqo".to_string(), *mos);
+        }
+        if scores.len() > 1 {
+            metrics.insert("raw_mos".to_string(), scores[0]);
+        }
+    }
+    metrics
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
@@ -1101,6 +1242,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         cli.output_dir,
         cli.sipp_path,
         cli.ffmpeg_path,
+        QualityTool {
+            algorithm: cli.quality_algorithm,
+            path: cli.quality_tool_path,
+            args: cli.quality_tool_args,
+        },
     );
 
     test_runner.setup().await?;
@@ -1143,12 +1289,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Generated media file: {:?}", output_path);
             return Ok(());
         }
-        Commands::AnalyzeMedia { input, reference } => {
+        Commands::AnalyzeMedia { input, reference, sample_rate, min_mos } => {
             info!("Analyzing media file: {:?}", input);
-            if let Some(ref_file) = reference {
-                info!("Reference file: {:?}", ref_file);
-            }
-            // Implementation would analyze audio quality metrics
+            let Some(ref_file) = reference else {
+                return Err("Quality scoring needs the transmitted --reference file".into());
+            };
+            test_runner.run_quality_analysis(&ref_file, &input, sample_rate, min_mos).await?;
         }
     }
 
@@ -1176,6 +1322,7 @@ mod tests {
             PathBuf::from("/tmp/test"),
             "sipp".to_string(),
             "ffmpeg".to_string(),
+            QualityTool { algorithm: QualityAlgorithm::Pesq, path: "pesq".to_string(), args: None },
         );
 
         let scenario = runner.create_uac_scenario().await.unwrap();
@@ -1183,4 +1330,26 @@ mod tests {
         assert!(scenario.contains("BYE"));
         assert!(scenario.contains("application/sdp"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_quality_output_parsing() {
+        let pesq = "Reading reference file ref.wav...\n\
+            P.862 Prediction (Raw MOS, MOS-LQO):  = 2.987\t3.012\n";
+        let metrics = parse_quality_output(pesq);
+        assert_eq!(metrics.get("mos_lqo"), Some(&3.012));
+        assert_eq!(metrics.get("raw_mos"), Some(&2.987));
+
+        let polqa = "POLQA v3\nPOLQA MOS-LQO: 4.21\n";
+        let metrics = parse_quality_output(polqa);
+        assert_eq!(metrics.get("mos_lqo"), Some(&4.21));
+        assert!(metrics.get("raw_mos").is_none());
+
+        assert!(parse_quality_output("Error: sample rate mismatch\n").is_empty());
+
+        let tool = QualityTool { algorithm: QualityAlgorithm::Pesq, path: "pesq".to_string(), args: None };
+        assert_eq!(
+            tool.command_args(Path::new("ref.wav"), Path::new("cap.wav"), 16000),
+            vec!["+16000", "ref.wav", "cap.wav"]
+        );
+    }
+}
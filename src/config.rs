@@ -1,8 +1,19 @@
 //! Configuration management for the Redfire Gateway
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 
+use crate::interfaces::regulatory::RegulatoryPacks;
+use crate::protocols::sip_tls;
+use crate::services::automation;
+use crate::services::craft;
+use crate::services::precedence;
+use crate::services::prompts;
+use crate::services::reject_announcements::RejectReason;
+use crate::services::session_timer::{self, Refresher};
 use crate::{Error, Result};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +36,38 @@ pub struct GatewayConfig {
     pub snmp: SnmpConfig,
     pub testing: TestingConfig,
     pub b2bua: B2buaConfig,
+    #[serde(default)]
+    pub tandem: TandemConfig,
+    #[serde(default)]
+    pub certificates: CertificateConfig,
+    #[serde(default)]
+    pub profiling: ProfilingConfig,
+    #[serde(default)]
+    pub craft: CraftConsoleConfig,
+    #[serde(default)]
+    pub self_test: SelfTestConfig,
+    #[serde(default)]
+    pub support_tunnel: SupportTunnelConfig,
+    #[serde(default)]
+    pub canary: CanaryConfig,
+    #[serde(default)]
+    pub channel_history: ChannelHistoryConfig,
+    #[serde(default)]
+    pub time_sync: TimeSyncConfig,
+    #[serde(default)]
+    pub management_api: ManagementApiConfig,
+    #[serde(default)]
+    pub capacity: CapacityConfig,
+    #[serde(default)]
+    pub alarm_relays: AlarmRelayConfig,
+    #[serde(default)]
+    pub automation: AutomationConfig,
+    #[serde(default)]
+    pub campaigns: CampaignConfig,
+    #[serde(default)]
+    pub audio_tap: AudioTapConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +78,13 @@ pub struct GeneralConfig {
     pub contact: String,
     pub max_calls: u32,
     pub call_timeout: u32,
+    /// Unix domain socket the `stop` and `status` commands talk to
+    #[serde(default = "default_control_socket")]
+    pub control_socket: PathBuf,
+}
+
+fn default_control_socket() -> PathBuf {
+    PathBuf::from("/run/redfire/gateway.sock")
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +123,208 @@ pub struct SipConfig {
     pub max_sessions: u32,
     pub session_timeout: u32,
     pub register_interval: u32,
+    /// Requests larger than this many bytes are sent over TCP when the
+    /// transport is UDP (RFC 3261 §18.1.1)
+    #[serde(default = "default_udp_size_threshold")]
+    pub udp_size_threshold: usize,
+    /// Send over UDP anyway when the TCP connection for a large request fails
+    #[serde(default = "default_tcp_fallback")]
+    pub tcp_fallback: bool,
+    /// Peers (IP or host) whose responses always go back to the source
+    /// address and port of their request, for PBXes behind NAT that do not
+    /// ask for rport
+    #[serde(default)]
+    pub force_rport: Vec<String>,
+    /// Largest received message, header and SDP body accepted
+    #[serde(default)]
+    pub size_limits: SipSizeLimits,
+    /// TLS listener for sips: trunks
+    #[serde(default)]
+    pub tls: SipTlsConfig,
+    /// SIP over WebSocket for browser and softphone clients
+    #[serde(default)]
+    pub websocket: SipWebSocketConfig,
+    /// Registrar for phones and softphones registering with the gateway
+    #[serde(default)]
+    pub registrar: SipRegistrarConfig,
+    /// Service network the TLS and WebSocket listeners are opened in
+    #[serde(default)]
+    pub network: NetworkPlacement,
+}
+
+fn default_udp_size_threshold() -> usize {
+    1300
+}
+
+fn default_tcp_fallback() -> bool {
+    true
+}
+
+/// What happens to a received message over a size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizeAction {
+    /// Answer requests with 413 Request Entity Too Large; responses are dropped
+    #[default]
+    Reject,
+    /// Discard without answering
+    Drop,
+}
+
+/// Size limits on received SIP messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SipSizeLimits {
+    /// Whole message, start line to end of body
+    pub max_message_bytes: usize,
+    /// One header line, folded continuation lines included
+    pub max_header_bytes: usize,
+    /// SDP body, or the SDP part of a multipart body
+    pub max_sdp_bytes: usize,
+    pub action: OversizeAction,
+}
+
+impl Default for SipSizeLimits {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 65535,
+            max_header_bytes: 8192,
+            max_sdp_bytes: 16384,
+            action: OversizeAction::Reject,
+        }
+    }
+}
+
+/// TLS listener for trunks that send sips: traffic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SipTlsConfig {
+    pub enabled: bool,
+    pub listen_port: u16,
+    /// Certificate chain and key presented when no SNI name matches
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Certificates presented to clients asking for particular names
+    pub sni: Vec<SipTlsSniCertificate>,
+    /// IANA names of the cipher suites offered, most preferred first; the
+    /// TLS library defaults when empty
+    pub cipher_suites: Vec<String>,
+}
+
+impl Default for SipTlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_port: 5061,
+            cert_path: PathBuf::from("/etc/redfire/tls/sip.crt"),
+            key_path: PathBuf::from("/etc/redfire/tls/sip.key"),
+            sni: Vec::new(),
+            cipher_suites: Vec::new(),
+        }
+    }
+}
+
+/// SIP over WebSocket listeners (RFC 7118)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SipWebSocketConfig {
+    pub enabled: bool,
+    /// Port for plain ws:// connections; none when unset
+    pub listen_port: Option<u16>,
+    /// Port for wss:// connections, served with the certificates of the
+    /// TLS listener; none when unset
+    pub secure_port: Option<u16>,
+    /// Origin headers browsers may connect with; any origin when empty
+    pub allowed_origins: Vec<String>,
+    /// How often each connection is pinged
+    pub ping_interval_secs: u64,
+    /// Connections silent for this long are closed
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for SipWebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_port: None,
+            secure_port: Some(8443),
+            allowed_origins: Vec::new(),
+            ping_interval_secs: 30,
+            idle_timeout_secs: 90,
+        }
+    }
+}
+
+/// Registrar accepting REGISTER with digest authentication (RFC 3261 §10, RFC 2617)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SipRegistrarConfig {
+    pub enabled: bool,
+    /// Digest realm; the SIP domain when empty
+    pub realm: String,
+    /// Shorter registrations are refused with 423 Interval Too Brief
+    pub min_expires_secs: u32,
+    /// Longer registrations are granted this long
+    pub max_expires_secs: u32,
+    /// Granted when the REGISTER asks for no expiry
+    pub default_expires_secs: u32,
+    /// Contacts kept per address of record; the oldest is replaced beyond this
+    pub max_contacts: usize,
+    /// Older nonces are challenged again as stale
+    pub nonce_lifetime_secs: u64,
+    pub credential_source: CredentialSource,
+    /// Users when credentials come from the configuration
+    pub users: Vec<RegistrarUser>,
+    /// Endpoint receiving `{"username", "realm"}` as a JSON POST and
+    /// answering `{"ha1"}` or `{"password"}`, or 404 for an unknown user
+    pub credentials_url: Option<String>,
+    pub credentials_timeout_ms: u64,
+}
+
+impl Default for SipRegistrarConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            realm: String::new(),
+            min_expires_secs: 60,
+            max_expires_secs: 3600,
+            default_expires_secs: 3600,
+            max_contacts: 5,
+            nonce_lifetime_secs: 300,
+            credential_source: CredentialSource::Config,
+            users: Vec::new(),
+            credentials_url: None,
+            credentials_timeout_ms: 500,
+        }
+    }
+}
+
+/// Where the registrar looks up digest credentials
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialSource {
+    Config,
+    Http,
+}
+
+/// User allowed to register, with a password or its precomputed digest
+/// hash, `MD5(username:realm:password)` in hex
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrarUser {
+    pub username: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub ha1: Option<String>,
+}
+
+/// Certificate for one SNI name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SipTlsSniCertificate {
+    /// Host name, or `*.` followed by a domain to match one more label
+    pub server_name: String,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,6 +332,74 @@ pub struct RtpConfig {
     pub port_range: PortRange,
     pub jitter_buffer_size: u32,
     pub packet_timeout: u32,
+    #[serde(default)]
+    pub batching: RtpBatchingConfig,
+    /// Service network for media sockets; a media interface's own device
+    /// takes precedence
+    #[serde(default)]
+    pub network: NetworkPlacement,
+    #[serde(default)]
+    pub rtcp: RtcpConfig,
+}
+
+/// Network a service's sockets are opened in
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkPlacement {
+    /// Interface or VRF device bound with SO_BINDTODEVICE
+    pub device: Option<String>,
+    /// Network namespace, by name under /var/run/netns or as a path
+    pub netns: Option<String>,
+}
+
+/// Syscall batching and UDP segmentation offload for RTP sockets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RtpBatchingConfig {
+    /// Use recvmmsg/sendmmsg where the platform supports them
+    pub enabled: bool,
+    /// Datagrams read per receive syscall
+    pub recv_batch_size: usize,
+    /// Datagrams written per send syscall
+    pub send_batch_size: usize,
+    /// Coalesce same-destination packets into one UDP_SEGMENT send
+    pub gso: bool,
+    /// Accept kernel-coalesced receives (UDP_GRO); each receive slot grows to 64 KiB
+    pub gro: bool,
+    /// Receive buffer per datagram when GRO is off
+    pub max_datagram_size: usize,
+}
+
+impl Default for RtpBatchingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            recv_batch_size: 16,
+            send_batch_size: 16,
+            gso: true,
+            gro: false,
+            max_datagram_size: 2048,
+        }
+    }
+}
+
+/// RTCP sender and receiver reports for plain RTP sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RtcpConfig {
+    /// Bind each session's RTCP port (RTP port + 1) and send reports
+    pub enabled: bool,
+    /// Seconds between reports
+    pub interval_secs: u64,
+}
+
+impl Default for RtcpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 5,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -106,6 +426,187 @@ pub struct FreeTdmConfig {
     pub enabled: bool,
     pub config_file: String,
     pub spans: Vec<FreeTdmSpan>,
+    /// Device directory used by spans on the `dahdi` backend
+    #[serde(default = "default_dahdi_device_dir")]
+    pub dahdi_device_dir: String,
+    #[serde(default)]
+    pub recovery: SpanRecoveryConfig,
+    #[serde(default)]
+    pub hot_swap: HotSwapConfig,
+    #[serde(default)]
+    pub regulatory: RegulatoryConfig,
+}
+
+fn default_dahdi_device_dir() -> String {
+    "/dev/dahdi".to_string()
+}
+
+/// Runtime discovery of inserted and removed TDM cards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotSwapConfig {
+    pub enabled: bool,
+    /// Interval between hardware rescans of backends that support discovery
+    pub rescan_interval_secs: u64,
+    /// Configuration given to newly found spans, chosen by line type
+    pub templates: Vec<SpanTemplate>,
+}
+
+impl Default for HotSwapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rescan_interval_secs: 30,
+            templates: vec![
+                SpanTemplate { trunk_type: Layer1Type::E1, ..Default::default() },
+                SpanTemplate { trunk_type: Layer1Type::T1, ..Default::default() },
+            ],
+        }
+    }
+}
+
+/// Country packs selectable per span
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegulatoryConfig {
+    /// Country of spans that do not name a pack; built-in or from `packs`
+    pub default_pack: Option<String>,
+    /// Packs added to the built-in ones, replacing a built-in of the same country
+    pub packs: Vec<RegulatoryPack>,
+}
+
+/// Tones, ring cadence, disconnect supervision and CLIP of one country's
+/// network, so a span is set up for a market by naming its country
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegulatoryPack {
+    /// ISO 3166-1 alpha-2 code the pack is selected by, e.g. `gb`
+    pub country: String,
+    pub ring_cadence: Cadence,
+    pub dial_tone: ToneSpec,
+    pub ringback_tone: ToneSpec,
+    pub busy_tone: ToneSpec,
+    pub congestion_tone: ToneSpec,
+    pub disconnect: DisconnectSupervision,
+    pub clip: ClipFormat,
+}
+
+/// Alternating on and off periods in milliseconds, starting with on;
+/// empty for a continuous signal
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Cadence(pub Vec<u32>);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToneSpec {
+    /// One frequency, or two played together
+    pub frequencies: Vec<u16>,
+    pub level_dbm0: i8,
+    #[serde(default)]
+    pub cadence: Cadence,
+}
+
+/// How the far end clearing is recognised on an analog line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisconnectSupervision {
+    pub method: DisconnectMethod,
+    /// Shortest signal taken as a disconnect; for `tone`, the time the
+    /// busy or congestion tone must be heard
+    pub min_duration_ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectMethod {
+    /// Loop current removed for an open switch interval
+    LoopCurrentDenial,
+    PolarityReversal,
+    /// Busy or congestion tone from the exchange
+    Tone,
+}
+
+/// Calling line identity presentation to analog terminals
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClipFormat {
+    pub modulation: ClipModulation,
+    pub timing: ClipTiming,
+    /// Signal that wakes the terminal before a pre-ring CLIP
+    #[serde(default)]
+    pub alert: ClipAlert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipModulation {
+    Bell202Fsk,
+    V23Fsk,
+    Dtmf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipTiming {
+    BeforeFirstRing,
+    AfterFirstRing,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipAlert {
+    #[default]
+    None,
+    LineReversal,
+    /// Line reversal followed by a dual-tone alerting signal
+    LineReversalDualTone,
+    RingPulse,
+}
+
+/// Default configuration for a span discovered at run time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpanTemplate {
+    pub trunk_type: Layer1Type,
+    pub signaling: SignalingType,
+    /// Used when discovery does not say which driver owns the span
+    pub backend: String,
+}
+
+impl Default for SpanTemplate {
+    fn default() -> Self {
+        Self {
+            trunk_type: Layer1Type::E1,
+            signaling: SignalingType::Pri,
+            backend: default_tdm_backend(),
+        }
+    }
+}
+
+/// Automatic restarts of spans that drop or never bring up their D-channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpanRecoveryConfig {
+    pub enabled: bool,
+    /// Delay before the first restart; doubled for every further attempt
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    /// Restarts before the span is latched out of service until cleared by hand
+    pub max_attempts: u32,
+    /// Time a span may be up without its D-channel before it is restarted
+    pub d_channel_timeout_secs: u64,
+    /// Time a span must stay up with its D-channel before the attempt count resets
+    pub stable_secs: u64,
+}
+
+impl Default for SpanRecoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_backoff_secs: 5,
+            max_backoff_secs: 300,
+            max_attempts: 5,
+            d_channel_timeout_secs: 30,
+            stable_secs: 120,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -175,6 +676,11 @@ pub struct SnmpConfig {
     pub port: u16,
     pub bind_address: String,
     pub version: SnmpVersion,
+    #[serde(default)]
+    pub v3: SnmpV3Config,
+    /// OAM network the agent listens and sends traps on
+    #[serde(default)]
+    pub network: NetworkPlacement,
 }
 
 impl Default for SnmpConfig {
@@ -185,6 +691,8 @@ impl Default for SnmpConfig {
             port: 161,
             bind_address: "0.0.0.0".to_string(),
             version: SnmpVersion::V2c,
+            v3: SnmpV3Config::default(),
+            network: NetworkPlacement::default(),
         }
     }
 }
@@ -268,7 +776,7 @@ pub enum SigtranVariant {
     Etsi,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Layer1Type {
     #[serde(rename = "e1")]
     E1,
@@ -326,6 +834,105 @@ pub enum SnmpVersion {
     V3,
 }
 
+/// SNMPv3 User-based Security Model and View-based Access Control settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnmpV3Config {
+    /// File holding the persisted engine ID and boot counter
+    pub engine_state_file: String,
+    /// Fixed engine ID (hex); generated and persisted when absent
+    pub engine_id: Option<String>,
+    pub users: Vec<SnmpUsmUser>,
+    pub views: Vec<SnmpView>,
+    /// USM user that signs notifications sent as traps
+    pub trap_user: Option<String>,
+    pub inform_targets: Vec<SnmpInformTarget>,
+}
+
+impl Default for SnmpV3Config {
+    fn default() -> Self {
+        Self {
+            engine_state_file: "/var/lib/redfire-gateway/snmp-engine".to_string(),
+            engine_id: None,
+            users: vec![],
+            views: vec![],
+            trap_user: None,
+            inform_targets: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnmpUsmUser {
+    pub name: String,
+    pub auth_protocol: SnmpAuthProtocol,
+    pub auth_password: Option<String>,
+    pub priv_protocol: SnmpPrivProtocol,
+    pub priv_password: Option<String>,
+    /// VACM view the user may read
+    pub read_view: String,
+    /// Lowest security level the user may use
+    #[serde(default = "default_snmp_security_level")]
+    pub min_security_level: SnmpSecurityLevel,
+}
+
+fn default_snmp_security_level() -> SnmpSecurityLevel {
+    SnmpSecurityLevel::AuthPriv
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnmpAuthProtocol {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "sha-224")]
+    HmacSha224,
+    #[serde(rename = "sha-256")]
+    HmacSha256,
+    #[serde(rename = "sha-384")]
+    HmacSha384,
+    #[serde(rename = "sha-512")]
+    HmacSha512,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnmpPrivProtocol {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "aes-128")]
+    Aes128,
+    #[serde(rename = "aes-192")]
+    Aes192,
+    #[serde(rename = "aes-256")]
+    Aes256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SnmpSecurityLevel {
+    #[serde(rename = "noAuthNoPriv")]
+    NoAuthNoPriv,
+    #[serde(rename = "authNoPriv")]
+    AuthNoPriv,
+    #[serde(rename = "authPriv")]
+    AuthPriv,
+}
+
+/// VACM MIB view made of included and excluded subtrees
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnmpView {
+    pub name: String,
+    pub included: Vec<String>,
+    #[serde(default)]
+    pub excluded: Vec<String>,
+}
+
+/// Destination for acknowledged notifications
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnmpInformTarget {
+    pub address: String,
+    pub user: String,
+    pub timeout_ms: u64,
+    pub retries: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortRange {
     pub min: u16,
@@ -345,6 +952,20 @@ pub struct FreeTdmSpan {
     pub trunk_type: Layer1Type,
     pub d_channel: u8,
     pub channels: Vec<FreeTdmChannel>,
+    /// Driver serving the span: `freetdm`, `dahdi`, or the name of a
+    /// backend registered through the gateway builder
+    #[serde(default = "default_tdm_backend")]
+    pub backend: String,
+    #[serde(default)]
+    pub description: PortDescription,
+    /// Country pack for the span's tones, ringing, disconnect supervision
+    /// and CLIP; the configured default pack when unset
+    #[serde(default)]
+    pub regulatory_pack: Option<String>,
+}
+
+fn default_tdm_backend() -> String {
+    "freetdm".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -353,6 +974,19 @@ pub struct FreeTdmChannel {
     pub channel_type: ChannelType,
     pub enabled: bool,
     pub signaling: SignalingType,
+    #[serde(default)]
+    pub description: PortDescription,
+}
+
+/// Operator-assigned metadata for a span or channel, shown wherever the
+/// port is reported
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PortDescription {
+    pub name: Option<String>,
+    pub location: Option<String>,
+    pub customer: Option<String>,
+    pub circuit_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -598,9 +1232,122 @@ pub struct B2buaConfig {
     pub gpu_memory_limit_mb: Option<u64>,
     pub routing_table: Vec<RoutingRule>,
     pub clustering: ClusteringConfig,
+    #[serde(default)]
+    pub cps_shaping: CpsShapingConfig,
+    #[serde(default)]
+    pub cdr_custom_fields: Vec<CdrCustomField>,
+    #[serde(default)]
+    pub media_policies: Vec<TrunkMediaPolicy>,
+    /// Handling of video and additional audio streams in offers
+    #[serde(default)]
+    pub media_streams: MediaStreamsConfig,
+    #[serde(default)]
+    pub trunk_failure: TrunkFailureConfig,
+    /// Local addresses available for relayed media
+    #[serde(default)]
+    pub media_interfaces: Vec<MediaInterface>,
+    /// Which media interface each trunk or tenant is pinned to
+    #[serde(default)]
+    pub media_interface_rules: Vec<MediaInterfaceRule>,
+    /// Interface used for legs no rule matches
+    #[serde(default)]
+    pub default_media_interface: Option<String>,
+    /// External routing decision hook consulted before the static routing table
+    #[serde(default)]
+    pub routing_hook: RoutingHookConfig,
+    /// Registration proxying to a hosted PBX with local fallback
+    #[serde(default)]
+    pub survivability: SurvivabilityConfig,
+    /// Number portability / caller name dip performed before routing
+    #[serde(default)]
+    pub number_lookup: NumberLookupConfig,
+    /// Per-trunk voice quality baselines and deviation alerting
+    #[serde(default)]
+    pub quality_alerting: QualityAlertingConfig,
+    /// Automatic call gapping towards congested destinations
+    #[serde(default)]
+    pub call_gapping: CallGappingConfig,
+    /// Which leg B failure responses move the call to the next target
+    #[serde(default)]
+    pub reroute: RerouteConfig,
+    /// Manual moves of live call legs by the NOC
+    #[serde(default)]
+    pub takeover: TakeoverConfig,
+    /// Interoperability workarounds assigned to trunks by profile
+    #[serde(default)]
+    pub quirks: QuirksConfig,
+    /// Trace-level logging for selected calls only
+    #[serde(default)]
+    pub call_trace: CallTraceConfig,
+    /// Groups that routing rules can target to fork inbound calls
+    #[serde(default)]
+    pub ring_groups: Vec<RingGroup>,
+    /// Codec ordering of leg B offers and transcoding avoidance
+    #[serde(default)]
+    pub codec_negotiation: CodecNegotiationConfig,
+    /// Serviceable ranges and live capacity pushed to upstream proxies
+    #[serde(default)]
+    pub route_advertisement: RouteAdvertisementConfig,
+    /// Candidate routing table evaluated alongside the active one
+    #[serde(default)]
+    pub shadow_routing: ShadowRoutingConfig,
+    /// Carrying established calls across an in-place upgrade
+    #[serde(default)]
+    pub upgrade_assist: UpgradeAssistConfig,
+    /// Announcement audio per language and how callers are matched to it
+    #[serde(default)]
+    pub prompts: PromptsConfig,
+    /// Echo, milliwatt and silence numbers answered by the gateway itself
+    #[serde(default)]
+    pub test_numbers: TestNumbersConfig,
+    /// Outbound registrations of trunks with their carriers' registrars
+    #[serde(default)]
+    pub trunk_registration: TrunkRegistrationConfig,
+    /// Inbound numbers that page over multicast RTP
+    #[serde(default)]
+    pub paging: PagingConfig,
+    /// Media silence after which an answered call is released, by call type
+    #[serde(default)]
+    pub media_inactivity: MediaInactivityConfig,
+    /// Release causes counted per trunk, route and hour
+    #[serde(default)]
+    pub release_causes: ReleaseCauseConfig,
+    /// Browser legs bridged through the media relay with ICE and DTLS-SRTP
+    #[serde(default)]
+    pub webrtc: WebRtcConfig,
+    /// RTCP extended reports on IP legs and vq-rtcpxr publication
+    #[serde(default)]
+    pub rtcp_xr: RtcpXrConfig,
+    /// Resource-Priority and MLPP precedence, with preemption at capacity
+    #[serde(default)]
+    pub precedence: PrecedenceConfig,
+    /// Session-Expires negotiation and refreshes on both legs (RFC 4028)
+    #[serde(default)]
+    pub session_timers: SessionTimerConfig,
+    /// Announcements played to refused callers before release
+    #[serde(default)]
+    pub reject_announcements: RejectAnnouncementConfig,
+    /// 100rel/PRACK on both legs
+    #[serde(default)]
+    pub reliable_provisionals: ReliableProvisionalConfig,
+    /// Blind and attended transfer on REFER
+    #[serde(default)]
+    pub transfer: TransferConfig,
+    /// Hold and resume by re-INVITE or Q.931 HOLD/RETRIEVE
+    #[serde(default)]
+    pub hold: CallHoldConfig,
+    /// Per-frame transcoding latency budgets by codec and backend
+    #[serde(default)]
+    pub transcoding_latency: TranscodingLatencyConfig,
+    /// Fax tone detection and T.38 relay on calls broken out to TDM
+    #[serde(default)]
+    pub fax: FaxRelayConfig,
+    /// Correlation ID shared by both legs of a call and passed on to other systems
+    #[serde(default)]
+    pub correlation: CorrelationConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum TranscodingBackend {
     #[serde(rename = "cpu")]
     Cpu,
@@ -629,6 +1376,33 @@ pub struct RoutingRule {
     pub priority: u8,
     pub translation: Option<NumberTranslation>,
     pub codec_preference: Vec<String>,
+    /// Seconds leg B may ring unanswered before the next target is tried
+    #[serde(default)]
+    pub no_answer_timeout_secs: Option<u64>,
+    /// Targets tried in order when the previous one does not answer or
+    /// fails with a re-routable status
+    #[serde(default)]
+    pub alternate_targets: Vec<String>,
+    /// Last resort once alternates are exhausted, e.g. a voicemail server
+    #[serde(default)]
+    pub no_answer_divert: Option<String>,
+    /// Failure handling for this route, keyed by status code or class ("5xx")
+    #[serde(default)]
+    pub failover_actions: BTreeMap<String, RerouteAction>,
+    /// Limits on early media before leg B answers
+    #[serde(default)]
+    pub early_media: Option<EarlyMediaPolicy>,
+}
+
+/// Early media a route allows before answer; some fraudulent far ends play
+/// chargeable content in 183 Session Progress and never answer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EarlyMediaPolicy {
+    /// Seconds from the first 183 with SDP until the unanswered call is released
+    pub max_duration_secs: Option<u64>,
+    /// Pass 183 to leg A as plain ringing so no backward early media reaches the caller
+    pub block_backward: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -651,85 +1425,2746 @@ pub struct NumberTranslation {
     pub suffix_add: Option<String>,
 }
 
+impl NumberTranslation {
+    /// Apply prefix/suffix manipulation to a dialed number
+    pub fn apply(&self, number: &str) -> String {
+        let mut result = number.to_string();
+
+        if let Some(prefix_strip) = &self.prefix_strip {
+            if result.starts_with(prefix_strip.as_str()) {
+                result = result[prefix_strip.len()..].to_string();
+            }
+        }
+        if let Some(suffix_strip) = &self.suffix_strip {
+            if result.ends_with(suffix_strip.as_str()) {
+                result.truncate(result.len() - suffix_strip.len());
+            }
+        }
+        if let Some(prefix_add) = &self.prefix_add {
+            result = format!("{}{}", prefix_add, result);
+        }
+        if let Some(suffix_add) = &self.suffix_add {
+            result.push_str(suffix_add);
+        }
+
+        result
+    }
+}
+
+/// Custom CDR field populated from signaling
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ClusteringConfig {
-    pub enabled: bool,
-    pub cluster_id: String,
-    pub node_id: String,
-    pub anycast_addresses: Vec<String>,
-    pub sync_port: u16,
-    pub heartbeat_interval: u32,
-    pub transaction_sync_enabled: bool,
-    pub shared_state_backend: SharedStateBackend,
-    pub consensus_algorithm: ConsensusAlgorithm,
+pub struct CdrCustomField {
+    /// Field name as it appears in CDR exports
+    pub name: String,
+    pub source: CdrFieldSource,
+    /// Values longer than this are truncated
+    pub max_length: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum SharedStateBackend {
-    #[serde(rename = "redis")]
-    Redis { addresses: Vec<String>, password: Option<String> },
-    #[serde(rename = "etcd")]
-    Etcd { endpoints: Vec<String> },
-    #[serde(rename = "consul")]
-    Consul { endpoints: Vec<String> },
-    #[serde(rename = "raft")]
-    Raft { peers: Vec<String> },
+pub enum CdrFieldSource {
+    /// SIP header from the inbound INVITE (case-insensitive)
+    #[serde(rename = "sip_header")]
+    SipHeader { header: String },
+    /// Q.931 user-user information element: from the SETUP of tandem calls,
+    /// or as interworked into the INVITE's User-to-User header
+    #[serde(rename = "q931_uui")]
+    Q931Uui,
 }
 
+/// Per-trunk media policy applied to SDP offers and answers
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ConsensusAlgorithm {
-    #[serde(rename = "raft")]
-    Raft,
-    #[serde(rename = "pbft")]
-    Pbft,
-    #[serde(rename = "hashgraph")]
-    Hashgraph,
+pub struct TrunkMediaPolicy {
+    /// Trunk (route target) the policy applies to
+    pub trunk: String,
+    /// Codec names permitted on the trunk (empty allows any codec)
+    #[serde(default)]
+    pub allowed_codecs: Vec<String>,
+    /// Maximum negotiated audio bandwidth per stream in kbps
+    pub max_bandwidth_kbps: Option<u32>,
+    /// Packetization time forced onto every audio stream
+    pub ptime_ms: Option<u32>,
+    /// SIP response code used when an offer violates the policy
+    #[serde(default = "default_policy_reject_code")]
+    pub reject_code: u16,
+    /// Codec order, most preferred first, for offers sent to the trunk
+    #[serde(default)]
+    pub outbound_codec_priority: Vec<String>,
+    /// Codec order, most preferred first, for calls arriving from the trunk
+    /// when the egress trunk has no outbound order of its own
+    #[serde(default)]
+    pub inbound_codec_priority: Vec<String>,
+    /// Handling of video and additional audio streams on this trunk, in
+    /// place of the B2BUA-wide setting
+    #[serde(default)]
+    pub media_streams: Option<MediaStreamsConfig>,
+    /// Offer SRTP with an SDES key to the trunk when the relay terminates
+    /// media, whether or not the caller used it
+    #[serde(default)]
+    pub srtp: bool,
+    /// Treat plain RTP on a leg with this trunk as a downgrade; implies `srtp`
+    #[serde(default)]
+    pub require_srtp: bool,
+    /// What a downgrade does to the call
+    #[serde(default)]
+    pub srtp_downgrade: SrtpDowngradeAction,
+    /// The trunk's endpoints are browsers: offer them ICE and DTLS-SRTP
+    #[serde(default)]
+    pub webrtc: bool,
 }
 
-impl GatewayConfig {
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let contents = std::fs::read_to_string(path)?;
-        let config: GatewayConfig = toml::from_str(&contents)
-            .map_err(|e| Error::parse(format!("Invalid TOML: {}", e)))?;
-        Ok(config)
-    }
+/// Handling of a leg that negotiates plain RTP where SRTP is required
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SrtpDowngradeAction {
+    /// Refuse the call with the policy's reject code
+    Reject,
+    /// Let the call go ahead and raise a security alarm on the trunk
+    #[default]
+    Alarm,
+}
 
-    pub fn load_from_env() -> Result<Self> {
-        let mut settings = config::Config::builder();
-        
-        // Load from environment variables with REDFIRE_ prefix
-        settings = settings.add_source(
-            config::Environment::with_prefix("REDFIRE")
-                .separator("_")
-        );
-        
-        let config = settings.build()?;
-        let gateway_config = config.try_deserialize()?;
-        Ok(gateway_config)
-    }
+/// What happens to an offered stream the gateway does not relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamHandling {
+    /// Decline the stream with port 0; the call goes ahead on the primary audio
+    #[default]
+    Reject,
+    /// Leave the stream to flow directly between the endpoints
+    PassThrough,
+}
 
-    pub fn validate(&self) -> Result<()> {
-        // Validate port ranges
-        if self.rtp.port_range.min >= self.rtp.port_range.max {
-            return Err(Error::parse("Invalid RTP port range"));
-        }
+/// Streams offered beyond the primary audio stream, which is the only one
+/// relayed. T.38 image streams are left to fax handling.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaStreamsConfig {
+    pub video: StreamHandling,
+    /// Audio streams after the first active one
+    pub extra_audio: StreamHandling,
+}
 
-        // Validate time slots
-        for slot in &self.e1.time_slots {
-            if *slot == 0 || *slot > 31 {
-                return Err(Error::parse("Invalid E1 time slot"));
-            }
-        }
+fn default_policy_reject_code() -> u16 {
+    488
+}
 
-        for slot in &self.t1.time_slots {
-            if *slot == 0 || *slot > 24 {
-                return Err(Error::parse("Invalid T1 time slot"));
-            }
-        }
+/// Local address (and optionally device) media can be relayed through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaInterface {
+    pub name: String,
+    /// Address RTP sockets are bound to
+    pub bind_address: String,
+    /// Address written into SDP `c=` lines when it differs from the bind address (e.g. behind NAT)
+    pub advertised_address: Option<String>,
+    /// Network device to bind to, e.g. a carrier VLAN interface
+    pub device: Option<String>,
+}
 
-        // Validate codec configuration
+/// Pins the media leg facing a trunk or tenant to a media interface
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaInterfaceRule {
+    /// Route target the egress leg is sent to
+    pub trunk: Option<String>,
+    /// Caller domain of the ingress leg
+    pub tenant: Option<String>,
+    pub interface: String,
+}
+
+/// Delegation of routing decisions to an HTTP endpoint or WASM module
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingHookConfig {
+    pub enabled: bool,
+    pub kind: RoutingHookKind,
+    /// Endpoint receiving the call context as a JSON POST
+    pub url: Option<String>,
+    /// WASM module exporting `memory`, `alloc` and `route`
+    pub module: Option<String>,
+    pub timeout_ms: u64,
+    /// Fuel available to a single WASM invocation
+    pub wasm_fuel: u64,
+    /// Reject the call rather than fall back to static rules when the hook fails
+    pub fail_closed: bool,
+}
+
+impl Default for RoutingHookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: RoutingHookKind::Http,
+            url: None,
+            module: None,
+            timeout_ms: 250,
+            wasm_fuel: 10_000_000,
+            fail_closed: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RoutingHookKind {
+    #[serde(rename = "http")]
+    Http,
+    #[serde(rename = "wasm")]
+    Wasm,
+}
+
+/// External number lookup (LNP or CNAM dip) consulted before routing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NumberLookupConfig {
+    pub enabled: bool,
+    pub kind: NumberLookupKind,
+    /// HTTP endpoint; `{number}` is replaced with the dialed number
+    pub url: Option<String>,
+    /// Redirect server (SIP) or DNS resolver (ENUM) as host:port
+    pub server: Option<String>,
+    pub enum_domain: String,
+    pub timeout_ms: u64,
+    pub cache_ttl_secs: u64,
+    pub cache_max_entries: usize,
+    /// Also dip the calling number for its caller name
+    pub caller_name: bool,
+}
+
+impl Default for NumberLookupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: NumberLookupKind::Http,
+            url: None,
+            server: None,
+            enum_domain: "e164.arpa".to_string(),
+            timeout_ms: 200,
+            cache_ttl_secs: 3600,
+            cache_max_entries: 100_000,
+            caller_name: false,
+        }
+    }
+}
+
+/// Alerting when a trunk's MOS, loss or jitter departs from its learned baseline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualityAlertingConfig {
+    pub enabled: bool,
+    /// Weight of each new sample in the learned baseline
+    pub baseline_weight: f64,
+    /// Samples an hour-of-day slot needs before it is alerted on
+    pub min_baseline_samples: u32,
+    /// Recent samples averaged into the current value
+    pub window_secs: u64,
+    /// How long a deviation must persist before alerting
+    pub sustain_secs: u64,
+    pub mos_drop: f64,
+    pub loss_increase_pct: f64,
+    pub jitter_increase_ms: f64,
+    /// Offset applied to UTC when bucketing samples by hour of day
+    pub utc_offset_hours: i32,
+}
+
+impl Default for QualityAlertingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            baseline_weight: 0.02,
+            min_baseline_samples: 50,
+            window_secs: 60,
+            sustain_secs: 300,
+            mos_drop: 0.5,
+            loss_increase_pct: 2.0,
+            jitter_increase_ms: 20.0,
+            utc_offset_hours: 0,
+        }
+    }
+}
+
+/// Q.541-style call gapping applied when a destination keeps returning congestion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallGappingConfig {
+    pub enabled: bool,
+    /// Q.850 release causes counted as downstream congestion
+    pub congestion_causes: Vec<u16>,
+    /// Congestion releases within `trigger_window_secs` that start a gap
+    pub trigger_count: u32,
+    pub trigger_window_secs: u64,
+    /// Minimum spacing between calls admitted to a gapped destination
+    pub gap_interval_ms: u64,
+    /// How long a gap stays in force after it was last triggered
+    pub gap_duration_secs: u64,
+    /// Leading digits of the called number that identify a destination
+    pub prefix_digits: usize,
+    /// SIP status returned for gapped calls
+    pub reject_status: u16,
+}
+
+impl Default for CallGappingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            congestion_causes: vec![34, 41, 42, 44],
+            trigger_count: 10,
+            trigger_window_secs: 10,
+            gap_interval_ms: 1000,
+            gap_duration_secs: 60,
+            prefix_digits: 6,
+            reject_status: 503,
+        }
+    }
+}
+
+/// What to do with a call whose leg B failed with a given status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RerouteAction {
+    /// Try the route's next alternate target
+    Reroute,
+    /// Pass the failure back to the caller
+    Release,
+}
+
+/// Failover semantics for leg B failure responses
+///
+/// Actions are keyed by an exact status ("486") or a class ("5xx"). Route
+/// actions win over trunk actions, which win over the defaults; within each
+/// table an exact status wins over its class.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RerouteConfig {
+    pub enabled: bool,
+    pub default_actions: BTreeMap<String, RerouteAction>,
+    /// Per-trunk overrides, keyed by the target gateway of the route
+    pub trunk_actions: BTreeMap<String, BTreeMap<String, RerouteAction>>,
+    /// Upper bound on leg B attempts per call, including the first
+    pub max_attempts: usize,
+}
+
+impl Default for RerouteConfig {
+    fn default() -> Self {
+        let default_actions = [
+            ("403", RerouteAction::Release),
+            ("408", RerouteAction::Reroute),
+            ("486", RerouteAction::Release),
+            ("4xx", RerouteAction::Release),
+            ("500", RerouteAction::Reroute),
+            ("503", RerouteAction::Reroute),
+            ("5xx", RerouteAction::Reroute),
+            ("600", RerouteAction::Release),
+            ("6xx", RerouteAction::Release),
+        ]
+        .into_iter()
+        .map(|(key, action)| (key.to_string(), action))
+        .collect();
+
+        Self {
+            enabled: false,
+            default_actions,
+            trunk_actions: BTreeMap::new(),
+            max_attempts: 3,
+        }
+    }
+}
+
+/// Manual call takeover by operators
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TakeoverConfig {
+    pub enabled: bool,
+    /// How long the new leg may take to answer before the takeover is abandoned
+    pub answer_timeout_secs: u64,
+    /// JSON-lines audit trail of every takeover request and its outcome
+    pub audit_log: Option<String>,
+}
+
+impl Default for TakeoverConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            answer_timeout_secs: 30,
+            audit_log: None,
+        }
+    }
+}
+
+/// Call transfer requested by either party with REFER
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransferConfig {
+    pub enabled: bool,
+    /// Transfers straight to a new target
+    pub allow_blind: bool,
+    /// Transfers replacing a consultation call (Refer-To with Replaces)
+    pub allow_attended: bool,
+    /// How long the target may take to answer before the transfer fails
+    pub answer_timeout_secs: u64,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allow_blind: true,
+            allow_attended: true,
+            answer_timeout_secs: 60,
+        }
+    }
+}
+
+/// Call hold by either party, with optional music on hold
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallHoldConfig {
+    pub enabled: bool,
+    /// WAV file (PCMU, PCMA or 16-bit linear, 8 kHz mono) looped to the
+    /// held party. Without one, holds are passed on to the other leg
+    pub music_on_hold: Option<PathBuf>,
+}
+
+/// Per-call debug tracing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallTraceConfig {
+    /// Calling or called numbers traced from startup
+    pub numbers: Vec<String>,
+    /// Trunks (route targets) whose calls are traced from startup
+    pub trunks: Vec<String>,
+    /// Records kept per call; later ones are counted but dropped
+    pub max_records_per_call: usize,
+    /// Traces of finished calls kept for retrieval
+    pub max_completed: usize,
+}
+
+impl Default for CallTraceConfig {
+    fn default() -> Self {
+        Self {
+            numbers: Vec::new(),
+            trunks: Vec::new(),
+            max_records_per_call: 2000,
+            max_completed: 100,
+        }
+    }
+}
+
+/// How a ring group offers a call to its members
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RingStrategy {
+    /// Ring every member at once; the first to answer wins
+    Simultaneous,
+    /// Hunt through members in order, each for its own ring time
+    Sequential,
+}
+
+/// Member of a ring group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RingGroupMember {
+    pub target: String,
+    /// Seconds this member rings before it is cancelled; the group's ring
+    /// time when unset
+    #[serde(default)]
+    pub ring_timeout_secs: Option<u64>,
+}
+
+/// Set of SIP targets an inbound call is forked to. A routing rule uses a
+/// group by naming it as its target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RingGroup {
+    pub name: String,
+    pub strategy: RingStrategy,
+    pub members: Vec<RingGroupMember>,
+    #[serde(default = "default_ring_timeout_secs")]
+    pub ring_timeout_secs: u64,
+}
+
+fn default_ring_timeout_secs() -> u64 {
+    20
+}
+
+/// How leg B offers are ordered against the trunks' codec priorities
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CodecNegotiationConfig {
+    /// Keep codecs leg A offered ahead of any that would need transcoding,
+    /// so the call is relayed whenever both legs share a codec
+    pub avoid_transcoding: bool,
+    pub admission: TranscodingAdmissionConfig,
+}
+
+impl Default for CodecNegotiationConfig {
+    fn default() -> Self {
+        Self {
+            avoid_transcoding: true,
+            admission: TranscodingAdmissionConfig::default(),
+        }
+    }
+}
+
+/// Admission of transcoded calls as DSP capacity runs out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscodingAdmissionConfig {
+    pub enabled: bool,
+    /// Concurrent transcoded calls the CPU and GPU backends can carry
+    pub capacity: u32,
+    /// Below this share of free capacity shared codecs are always offered
+    /// ahead of transcoded fallbacks
+    pub prefer_relay_below_percent: u8,
+    /// Below this share of free capacity no fallbacks are offered and calls
+    /// that cannot be relayed are rejected
+    pub reject_below_percent: u8,
+    /// SIP status for rejected calls, sent with Q.850 cause 58
+    pub reject_code: u16,
+}
+
+impl Default for TranscodingAdmissionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 0,
+            prefer_relay_below_percent: 20,
+            reject_below_percent: 5,
+            reject_code: 488,
+        }
+    }
+}
+
+/// Per-frame transcoding latency budget, enforced by codec and backend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscodingLatencyConfig {
+    pub enabled: bool,
+    /// 99th percentile time to transcode one frame, in microseconds
+    pub budget_us: u64,
+    /// Budgets overriding `budget_us` by codec name
+    pub codec_budgets_us: BTreeMap<String, u64>,
+    /// Most recent frames per codec and backend the percentile is taken over
+    pub window_frames: usize,
+    /// Backends new sessions shift to, in order, while the preferred one is
+    /// over budget; once every one is, only relayed calls are negotiated
+    pub fallback_backends: Vec<TranscodingBackend>,
+    /// How long a backend is avoided for a codec after it was last over budget
+    pub hold_down_secs: u64,
+}
+
+impl Default for TranscodingLatencyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            budget_us: 2000,
+            codec_budgets_us: BTreeMap::new(),
+            window_frames: 500,
+            fallback_backends: Vec::new(),
+            hold_down_secs: 60,
+        }
+    }
+}
+
+/// Fax on calls broken out to TDM: when fax tones are heard the SIP leg is
+/// re-INVITEd to T.38, and the fax is passed through as G.711 if it refuses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaxRelayConfig {
+    pub enabled: bool,
+    /// Offer T.38 when fax is detected; otherwise fax stays G.711 passthrough
+    pub t38: bool,
+    /// Also switch on the calling machine's CNG. A caller sending CNG may
+    /// still be a person, so by default only CED and V.21 flags switch
+    pub switch_on_cng: bool,
+    /// Earlier IFP packets repeated in each UDPTL datagram
+    pub udptl_redundancy: usize,
+    /// Largest UDPTL datagram sent or accepted (T38FaxMaxDatagram)
+    pub max_datagram: usize,
+    /// Highest fax rate offered (T38MaxBitRate)
+    pub max_bit_rate: u32,
+}
+
+impl Default for FaxRelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            t38: true,
+            switch_on_cng: false,
+            udptl_redundancy: 3,
+            max_datagram: 400,
+            max_bit_rate: 14400,
+        }
+    }
+}
+
+/// How a call's correlation ID is learned from and passed on to the
+/// systems either side of the gateway; every call has one regardless
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorrelationConfig {
+    /// SIP header carrying the ID on leg B INVITEs and leg A's 200 OK
+    pub header: String,
+    /// Send the ID in `header` and in Q.931 user-user information
+    pub propagate: bool,
+    /// Keep an ID leg A arrives with instead of assigning a new one
+    pub accept_inbound: bool,
+    /// Carry the ID as IA5 user-user information in the SETUP of calls
+    /// broken out to TDM, and read it from inbound UUI
+    pub q931_user_user: bool,
+}
+
+impl Default for CorrelationConfig {
+    fn default() -> Self {
+        Self {
+            header: "X-Correlation-ID".to_string(),
+            propagate: true,
+            accept_inbound: true,
+            q931_user_user: true,
+        }
+    }
+}
+
+/// Dry run of a candidate routing table: every call is also routed through
+/// it and differences are logged and counted, without affecting the call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowRoutingConfig {
+    pub enabled: bool,
+    pub routing_table: Vec<RoutingRule>,
+    /// Most recent differing decisions kept for the diff report
+    pub max_recent: usize,
+}
+
+impl Default for ShadowRoutingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            routing_table: Vec::new(),
+            max_recent: 100,
+        }
+    }
+}
+
+/// How route advertisements reach the upstream proxies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdvertisementMethod {
+    /// OPTIONS request carrying the advertisement in custom headers
+    SipOptions,
+    /// JSON POST to a REST callback
+    Rest,
+}
+
+/// Periodic advertisement of the number ranges this gateway serves and
+/// its free call capacity, so upstream load balancers can steer traffic
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteAdvertisementConfig {
+    pub enabled: bool,
+    pub method: AdvertisementMethod,
+    /// Proxies as host:port for OPTIONS, or callback URLs for REST
+    pub targets: Vec<String>,
+    /// Number prefixes advertised as serviceable, e.g. `+1212555`
+    pub number_ranges: Vec<String>,
+    pub interval_secs: u64,
+    /// How long to wait for each target to acknowledge
+    pub timeout_ms: u64,
+}
+
+impl Default for RouteAdvertisementConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            method: AdvertisementMethod::SipOptions,
+            targets: Vec::new(),
+            number_ranges: Vec::new(),
+            interval_secs: 30,
+            timeout_ms: 2000,
+        }
+    }
+}
+
+/// Named set of workarounds for a carrier's SIP implementation
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuirkProfile {
+    pub name: String,
+    /// Peer mishandles reliable provisionals; never offer or require 100rel
+    pub no_prack: bool,
+    /// Peer only routes Request-URIs carrying `user=phone`
+    pub user_phone: bool,
+    /// Peer mishandles re-INVITEs; mid-dialog changes use UPDATE instead
+    pub broken_reinvite: bool,
+    /// Peer's 183 early media is unusable; relay it as 180 without SDP
+    pub suppress_183: bool,
+    /// Requests larger than this many bytes are sent over TCP
+    pub max_udp_size: Option<usize>,
+}
+
+/// Quirk profiles and their assignment to trunks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuirksConfig {
+    /// Profiles added to, or replacing, the built-in ones of the same name
+    pub profiles: Vec<QuirkProfile>,
+    /// Trunk (route target) to profile name
+    pub trunks: BTreeMap<String, String>,
+}
+
+/// Whether `key` names a failure status (400-699) or class ("4xx" to "6xx")
+pub(crate) fn is_status_key(key: &str) -> bool {
+    match key.as_bytes() {
+        [b'4'..=b'6', b'x', b'x'] => true,
+        _ => key.parse::<u16>().is_ok_and(|status| (400..700).contains(&status)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NumberLookupKind {
+    #[serde(rename = "http")]
+    Http,
+    /// INVITE answered with a 302 whose Contact carries `rn=`
+    #[serde(rename = "sip_redirect")]
+    SipRedirect,
+    /// NAPTR query under `enum_domain`
+    #[serde(rename = "enum")]
+    Enum,
+}
+
+/// Branch survivability: REGISTERs are proxied to a hosted PBX while it is
+/// reachable and answered locally while it is not
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurvivabilityConfig {
+    pub enabled: bool,
+    /// Hosted PBX registrar as host:port
+    pub upstream_registrar: String,
+    /// Interval between OPTIONS probes of the registrar
+    pub probe_interval_secs: u64,
+    /// How long to wait for the registrar to answer a REGISTER or probe
+    pub upstream_timeout_ms: u64,
+    /// Consecutive failures before entering survivability mode
+    pub failures_before_outage: u32,
+    /// Consecutive successful probes before returning to normal mode
+    pub successes_before_recovery: u32,
+    /// Expiry granted to phones registered locally; kept short so phones
+    /// move back to the registrar soon after it recovers
+    pub local_expires_secs: u32,
+    /// Trunks towards the hosted PBX; losing any of them also enters survivability mode
+    pub upstream_trunks: Vec<String>,
+    /// Fallback dial plan applied while surviving, first match wins
+    pub dial_plan: Vec<SurvivabilityDialRule>,
+    /// Announcement played to calls no fallback rule allows
+    pub announcement: String,
+    /// Final response sent once the announcement has played
+    pub announcement_release_status: u16,
+}
+
+impl Default for SurvivabilityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            upstream_registrar: String::new(),
+            probe_interval_secs: 10,
+            upstream_timeout_ms: 2000,
+            failures_before_outage: 3,
+            successes_before_recovery: 2,
+            local_expires_secs: 300,
+            upstream_trunks: vec![],
+            dial_plan: vec![],
+            announcement: "survivability-unavailable".to_string(),
+            announcement_release_status: 503,
+        }
+    }
+}
+
+/// Number allowed to break out to local TDM spans during a WAN outage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurvivabilityDialRule {
+    pub id: String,
+    pub category: BreakoutCategory,
+    /// Regular expression matched against the called number
+    pub pattern: String,
+    /// Spans tried in order
+    pub egress_spans: Vec<u32>,
+    pub translation: Option<NumberTranslation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BreakoutCategory {
+    #[serde(rename = "emergency")]
+    Emergency,
+    #[serde(rename = "local")]
+    Local,
+}
+
+/// Handling of established calls when their trunk or span fails
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrunkFailureConfig {
+    pub action: TrunkFailureAction,
+    /// How long a preserved call may wait for its trunk to recover
+    pub preserve_secs: u64,
+    /// Interval between re-establishment attempts while preserved
+    pub retry_interval_secs: u64,
+    pub overrides: Vec<TrunkFailureOverride>,
+}
+
+impl Default for TrunkFailureConfig {
+    fn default() -> Self {
+        Self {
+            action: TrunkFailureAction::Release,
+            preserve_secs: 30,
+            retry_interval_secs: 5,
+            overrides: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TrunkFailureAction {
+    /// Release affected calls immediately
+    #[serde(rename = "release")]
+    Release,
+    /// Keep media flowing and try to re-establish signaling
+    #[serde(rename = "preserve_media")]
+    PreserveMedia,
+}
+
+/// Failure handling for a single trunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrunkFailureOverride {
+    /// Trunk (route target) name, or `span-<id>` for a TDM span
+    pub trunk: String,
+    pub action: TrunkFailureAction,
+    pub preserve_secs: Option<u64>,
+}
+
+/// Call-setup rate shaping configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpsShapingConfig {
+    pub enabled: bool,
+    /// Maximum time an INVITE may wait for a token before being rejected
+    pub max_queue_delay_ms: u64,
+    /// Maximum number of INVITEs queued per bucket
+    pub max_queue_depth: usize,
+    /// SIP response code used when a queued call cannot be admitted
+    pub reject_code: u16,
+    /// Rate applied to trunks without an explicit bucket (None = unshaped)
+    pub default_trunk_cps: Option<f64>,
+    pub buckets: Vec<CpsBucketConfig>,
+}
+
+impl Default for CpsShapingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_queue_delay_ms: 2000,
+            max_queue_depth: 100,
+            reject_code: 503,
+            default_trunk_cps: None,
+            buckets: vec![],
+        }
+    }
+}
+
+/// Token bucket definition for a trunk or tenant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpsBucketConfig {
+    pub scope: ShapingScope,
+    pub id: String,
+    /// Sustained calls per second
+    pub rate_cps: f64,
+    /// Number of calls that may be admitted back-to-back
+    pub burst: u32,
+    /// Source networks (CIDR) whose INVITEs count against a tenant bucket;
+    /// the first tenant listing the INVITE's source address applies
+    #[serde(default)]
+    pub sources: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ShapingScope {
+    #[serde(rename = "trunk")]
+    Trunk,
+    #[serde(rename = "tenant")]
+    Tenant,
+}
+
+/// TDM-to-TDM tandem switching configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TandemConfig {
+    pub enabled: bool,
+    pub channel_hunt: ChannelHunt,
+    pub routes: Vec<TandemRoute>,
+    #[serde(default)]
+    pub span_failure: TrunkFailureConfig,
+    /// Directory tandem call CDRs are written to as JSON lines, unless the
+    /// embedding application injects its own CDR sink
+    #[serde(default)]
+    pub cdr_directory: Option<PathBuf>,
+}
+
+impl Default for TandemConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            channel_hunt: ChannelHunt::Ascending,
+            routes: vec![],
+            span_failure: TrunkFailureConfig::default(),
+            cdr_directory: None,
+        }
+    }
+}
+
+/// Tandem route from one or more ingress spans to a list of egress spans
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TandemRoute {
+    pub id: String,
+    /// Ingress spans this route applies to (empty matches any span)
+    pub ingress_spans: Vec<u32>,
+    /// Regular expression matched against the called number
+    pub pattern: String,
+    /// Egress spans tried in order
+    pub egress_spans: Vec<u32>,
+    pub translation: Option<NumberTranslation>,
+    pub priority: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ChannelHunt {
+    #[serde(rename = "ascending")]
+    Ascending,
+    #[serde(rename = "descending")]
+    Descending,
+}
+
+/// TLS certificate lifecycle configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateConfig {
+    pub enabled: bool,
+    /// How often certificate files are checked for changes and expiry
+    pub check_interval_secs: u64,
+    /// Days before expiry at which alarms start
+    pub expiry_warning_days: u32,
+    pub certificates: Vec<ManagedCertificate>,
+    pub acme: Option<AcmeConfig>,
+}
+
+impl Default for CertificateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            check_interval_secs: 3600,
+            expiry_warning_days: 30,
+            certificates: vec![],
+            acme: None,
+        }
+    }
+}
+
+/// On-demand runtime profiling exposed through the management API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfilingConfig {
+    pub enabled: bool,
+    /// CPU profile length used when a request does not give one
+    pub default_cpu_seconds: u64,
+    /// Longest CPU profile a request may ask for
+    pub max_cpu_seconds: u64,
+    /// Sampling frequency for CPU profiles
+    pub frequency_hz: i32,
+    /// Largest encoded profile returned to a client
+    pub max_profile_bytes: usize,
+}
+
+impl Default for ProfilingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_cpu_seconds: 30,
+            max_cpu_seconds: 120,
+            frequency_hz: 99,
+            max_profile_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Serial craft console for units whose network configuration is unusable
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CraftConsoleConfig {
+    pub enabled: bool,
+    /// Serial device the shell runs on
+    pub device: String,
+    pub baud_rate: u32,
+    /// Craft password asked for before any command; `None` leaves the port open
+    pub password: Option<String>,
+    /// Idle time after which the session is locked again
+    pub idle_timeout_secs: u64,
+    /// Interface carrying the management address
+    pub management_interface: String,
+    /// Management address in CIDR form, applied at startup and set from the console
+    pub management_address: Option<String>,
+    pub management_gateway: Option<String>,
+}
+
+impl Default for CraftConsoleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: "/dev/ttyS0".to_string(),
+            baud_rate: 115200,
+            password: None,
+            idle_timeout_secs: 600,
+            management_interface: "eth0".to_string(),
+            management_address: None,
+            management_gateway: None,
+        }
+    }
+}
+
+/// How the support tunnel reaches the concentrator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelMethod {
+    /// SSH session forwarding a concentrator port back to the management API
+    Ssh,
+    /// WireGuard interface whose only peer is the concentrator
+    WireGuard,
+}
+
+/// Outbound tunnel giving remote engineers the management API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SupportTunnelConfig {
+    pub enabled: bool,
+    pub method: TunnelMethod,
+    /// Support concentrator host, with `:port` for SSH when not 22
+    pub concentrator: String,
+    pub ssh_user: String,
+    pub ssh_identity_path: String,
+    /// Port opened on the concentrator for the forwarded API
+    pub remote_port: u16,
+    /// Local management API forwarded through the SSH tunnel, which is only
+    /// allowed once `management_api.secret` is set
+    pub management_api: String,
+    /// wg-quick configuration naming the concentrator as its peer
+    pub wireguard_config_path: String,
+    pub ssh_path: String,
+    pub wg_quick_path: String,
+    /// Tunnel lifetime when the request does not give one
+    pub default_duration_secs: u64,
+    /// Longest lifetime a request may ask for
+    pub max_duration_secs: u64,
+    /// Append-only JSON lines record of tunnel sessions
+    pub audit_log_path: String,
+}
+
+impl Default for SupportTunnelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            method: TunnelMethod::Ssh,
+            concentrator: String::new(),
+            ssh_user: "support".to_string(),
+            ssh_identity_path: "/etc/redfire/support_tunnel_key".to_string(),
+            remote_port: 0,
+            management_api: "127.0.0.1:8080".to_string(),
+            wireguard_config_path: "/etc/redfire/wg-support.conf".to_string(),
+            ssh_path: "ssh".to_string(),
+            wg_quick_path: "wg-quick".to_string(),
+            default_duration_secs: 3600,
+            max_duration_secs: 8 * 3600,
+            audit_log_path: "/var/log/redfire/support-tunnel.log".to_string(),
+        }
+    }
+}
+
+/// Recent calls kept per B-channel for troubleshooting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelHistoryConfig {
+    pub enabled: bool,
+    /// Finished calls kept per channel; older ones are dropped
+    pub calls_per_channel: usize,
+}
+
+impl Default for ChannelHistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            calls_per_channel: 10,
+        }
+    }
+}
+
+/// How the gateway's clock health is shown to SIP peers and billing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeSyncConfig {
+    /// Send a Date header on requests and responses while the clock is
+    /// synchronized; phones that set their clock from it never see a
+    /// drifting one
+    pub sip_date_header: bool,
+    /// Send a Timestamp header on outgoing requests and echo received ones
+    /// with the time held (RFC 3261 §8.2.6.1), for round-trip estimation
+    pub sip_timestamp: bool,
+    /// Holdover after which the clock no longer counts as synchronized.
+    /// CDRs started or ended past it are flagged for billing review
+    pub max_holdover_secs: u64,
+}
+
+impl Default for TimeSyncConfig {
+    fn default() -> Self {
+        Self {
+            sip_date_header: true,
+            sip_timestamp: true,
+            max_holdover_secs: 3600,
+        }
+    }
+}
+
+/// HTTP management API queried by redfire-diag and integrators
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ManagementApiConfig {
+    pub enabled: bool,
+    /// Loopback by default; any other address needs a `secret`
+    pub listen: SocketAddr,
+    /// Required as a bearer token on every request when set. Audio taps
+    /// are the exception: they take their operators' own tokens
+    pub secret: Option<String>,
+    /// Most CDRs returned by one query
+    pub max_cdrs: usize,
+    /// Serve the provisioning routes that export and import routes and
+    /// trunks as CSV, rewriting the configuration file
+    pub provisioning: bool,
+    pub status_page: StatusPageConfig,
+    /// OAM network the API listens on
+    pub network: NetworkPlacement,
+}
+
+impl Default for ManagementApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 8080)),
+            secret: None,
+            max_cdrs: 1000,
+            provisioning: false,
+            status_page: StatusPageConfig::default(),
+            network: NetworkPlacement::default(),
+        }
+    }
+}
+
+/// Public status page for load balancer health checks and NOC wallboards,
+/// served on its own listener; it shows counts and states only
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatusPageConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+    /// Required as a bearer token or `secret` query parameter when set;
+    /// the page is open to anyone who can reach it otherwise
+    pub secret: Option<String>,
+    /// Reload interval of the HTML page
+    pub refresh_secs: u32,
+    /// Network the page listens on, where the load balancers reach it
+    pub network: NetworkPlacement,
+}
+
+impl Default for StatusPageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8081)),
+            secret: None,
+            refresh_secs: 30,
+            network: NetworkPlacement::default(),
+        }
+    }
+}
+
+/// Busy channel sampling behind the capacity planning reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CapacityConfig {
+    pub enabled: bool,
+    pub sample_interval_secs: u64,
+    /// Days of hourly history kept; a monthly report needs 30
+    pub retention_days: u32,
+    /// Blocking probability the required channel count is sized for
+    pub grade_of_service: f64,
+}
+
+impl Default for CapacityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_interval_secs: 60,
+            retention_days: 35,
+            grade_of_service: 0.01,
+        }
+    }
+}
+
+/// Bay alarm panel outputs driven from the active alarms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlarmRelayConfig {
+    pub enabled: bool,
+    /// How often the outputs are brought in line with the active alarms
+    pub poll_interval_ms: u64,
+    /// GPIO class directory used by sysfs outputs
+    pub sysfs_root: PathBuf,
+    pub outputs: Vec<AlarmRelayOutput>,
+}
+
+impl Default for AlarmRelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval_ms: 1000,
+            sysfs_root: PathBuf::from("/sys/class/gpio"),
+            outputs: Vec::new(),
+        }
+    }
+}
+
+/// Alarm severity an output signals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelaySeverity {
+    Critical,
+    Major,
+    Minor,
+}
+
+/// How an output line is driven
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GpioDriver {
+    /// `/sys/class/gpio`, exported on first use
+    #[default]
+    Sysfs,
+    /// GPIO character device, held open while the gateway runs
+    Gpiod,
+}
+
+/// One relay or GPIO line energised while alarms of its severity are active
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmRelayOutput {
+    pub severity: RelaySeverity,
+    #[serde(default)]
+    pub driver: GpioDriver,
+    /// Character device of a gpiod line
+    #[serde(default)]
+    pub chip: Option<PathBuf>,
+    /// Global GPIO number for sysfs, line offset on the chip for gpiod
+    pub line: u32,
+    /// Drive the line low to signal the alarm, for normally-closed contacts
+    #[serde(default)]
+    pub active_low: bool,
+    /// Keep signalling alarms that have been acknowledged
+    #[serde(default = "default_include_acknowledged")]
+    pub include_acknowledged: bool,
+}
+
+fn default_include_acknowledged() -> bool {
+    true
+}
+
+/// Maintenance rules run by the gateway itself, codifying NOC runbooks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutomationConfig {
+    pub enabled: bool,
+    /// Log and audit what the rules would do without doing it
+    pub dry_run: bool,
+    pub evaluation_interval_secs: u64,
+    /// Webhook URLs by the name rules refer to them with
+    pub webhooks: BTreeMap<String, String>,
+    pub webhook_timeout_ms: u64,
+    /// JSON lines file every action, taken or dry-run, is appended to; none when empty
+    pub audit_log_path: String,
+    pub rules: Vec<AutomationRuleConfig>,
+}
+
+impl Default for AutomationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dry_run: true,
+            evaluation_interval_secs: 10,
+            webhooks: BTreeMap::new(),
+            webhook_timeout_ms: 5000,
+            audit_log_path: String::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// One named rule, such as
+/// `if trunk carrier-a down for 5m then busy-out span 2, webhook noc`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutomationRuleConfig {
+    pub name: String,
+    pub rule: String,
+}
+
+/// Bulk outbound notification calls, each answered call hearing a prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CampaignConfig {
+    pub enabled: bool,
+    /// Trunks campaigns may call out over
+    pub trunks: Vec<CampaignTrunk>,
+    /// Directory holding the `<prompt>.wav` files campaigns play
+    pub prompt_directory: PathBuf,
+    /// Call attempts per second of a campaign that does not set its own
+    pub default_cps: f64,
+    /// Most call attempts per second a campaign may ask for
+    pub max_cps: f64,
+    /// Calls up at once across all campaigns
+    pub max_concurrent_calls: usize,
+    pub max_destinations: usize,
+    /// Longest a call may ring before it is cancelled as unanswered
+    pub ring_timeout_secs: u64,
+    /// Attempts per destination, the first included, when busy, unanswered or failed
+    pub max_attempts: u32,
+    pub retry_delay_secs: u64,
+    /// Finished campaigns kept for the management API
+    pub retained_campaigns: usize,
+}
+
+impl Default for CampaignConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            trunks: Vec::new(),
+            prompt_directory: PathBuf::from("/var/lib/redfire/campaign-prompts"),
+            default_cps: 5.0,
+            max_cps: 50.0,
+            max_concurrent_calls: 100,
+            max_destinations: 100_000,
+            ring_timeout_secs: 30,
+            max_attempts: 3,
+            retry_delay_secs: 300,
+            retained_campaigns: 50,
+        }
+    }
+}
+
+/// SIP trunk campaign calls are sent to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CampaignTrunk {
+    pub name: String,
+    /// Carrier proxy as host:port
+    pub proxy: String,
+    /// Calling number presented
+    pub caller_id: String,
+}
+
+/// Raw TDM channel audio taps, open only to the configured operators
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioTapConfig {
+    pub enabled: bool,
+    /// Operators allowed near tapped audio; anyone else is refused
+    pub operators: Vec<TapOperator>,
+    /// Directory tap files are written under, one subdirectory per tap
+    pub directory: PathBuf,
+    /// Multicast groups taps may be streamed to; none allowed when empty
+    pub multicast_groups: Vec<SocketAddr>,
+    /// Tap lifetime when the request does not give one
+    pub default_duration_secs: u64,
+    /// Longest lifetime a request may ask for
+    pub max_duration_secs: u64,
+    /// Taps running at once
+    pub max_taps: usize,
+    /// Append-only JSON lines record of every tap and refusal
+    pub audit_log_path: String,
+}
+
+impl Default for AudioTapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            operators: Vec::new(),
+            directory: PathBuf::from("/var/lib/redfire/taps"),
+            multicast_groups: Vec::new(),
+            default_duration_secs: 600,
+            max_duration_secs: 4 * 3600,
+            max_taps: 4,
+            audit_log_path: "/var/log/redfire/audio-tap.log".to_string(),
+        }
+    }
+}
+
+/// Operator presenting `token` as a bearer token to the tap endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TapOperator {
+    pub name: String,
+    pub token: String,
+    pub role: TapRole,
+    /// Spans the operator may tap; every span when empty
+    #[serde(default)]
+    pub spans: Vec<u32>,
+}
+
+/// What an operator may do with taps, each role including the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TapRole {
+    /// List taps and read the audit trail
+    Auditor,
+    /// Start taps and stop their own
+    Tapper,
+    /// Stop anyone's tap
+    Supervisor,
+}
+
+/// Periodic signed status summary POSTed to a central fleet controller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    pub enabled: bool,
+    /// Controller URL the summaries are POSTed to
+    pub controller_url: String,
+    /// Shared secret the controller checks each summary's HMAC-SHA256 against
+    pub secret: String,
+    pub interval_secs: u64,
+    pub timeout_ms: u64,
+    /// Further attempts at a summary the controller did not take
+    pub max_retries: u32,
+    /// Wait before the first retry, doubling up to `retry_max_ms`
+    pub retry_initial_ms: u64,
+    pub retry_max_ms: u64,
+    /// Licence the unit was provisioned with, reported as-is
+    pub license_id: Option<String>,
+    pub license_expires: Option<chrono::NaiveDate>,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            controller_url: String::new(),
+            secret: String::new(),
+            interval_secs: 60,
+            timeout_ms: 5000,
+            max_retries: 3,
+            retry_initial_ms: 2000,
+            retry_max_ms: 30_000,
+            license_id: None,
+            license_expires: None,
+        }
+    }
+}
+
+/// Synthetic test calls placed through the gateway's own trunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CanaryConfig {
+    pub enabled: bool,
+    /// Upstream registrar the canary registers with, as host:port
+    pub registrar: String,
+    /// Proxy the test calls are sent to; the registrar when empty
+    pub proxy: String,
+    /// User the canary registers and calls as
+    pub user: String,
+    /// Numbers that route out over a trunk and loop the audio back
+    pub destinations: Vec<String>,
+    pub interval_secs: u64,
+    /// How long looped audio is sent once a test call is answered
+    pub call_duration_secs: u64,
+    /// Longest wait for a final response to REGISTER or INVITE
+    pub setup_timeout_secs: u64,
+    pub register_expires_secs: u32,
+    /// Consecutive failures to one destination before an alarm is raised
+    pub failure_streak_alarm: u32,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            registrar: String::new(),
+            proxy: String::new(),
+            user: "canary".to_string(),
+            destinations: Vec::new(),
+            interval_secs: 300,
+            call_duration_secs: 5,
+            setup_timeout_secs: 10,
+            register_expires_secs: 3600,
+            failure_streak_alarm: 3,
+        }
+    }
+}
+
+/// Carrying established calls across an in-place software upgrade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpgradeAssistConfig {
+    pub enabled: bool,
+    /// Binary to restart into; the running binary when unset, for packages
+    /// that replace it in place
+    pub binary: Option<String>,
+    /// Where the outgoing process leaves its calls for the new one
+    pub snapshot_path: String,
+    /// Snapshots older than this at startup are discarded
+    pub max_snapshot_age_secs: u64,
+}
+
+impl Default for UpgradeAssistConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            binary: None,
+            snapshot_path: "/var/lib/redfire/call-snapshot.json".to_string(),
+            max_snapshot_age_secs: 30,
+        }
+    }
+}
+
+/// Announcement prompt packs: one directory of `<event>.wav` files per
+/// language, selected per trunk or tenant or by the caller's preference
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromptsConfig {
+    pub enabled: bool,
+    /// Holds a subdirectory per language, e.g. `en/all-circuits-busy.wav`
+    pub directory: String,
+    /// Language played when no rule or caller preference picks a pack
+    pub default_language: String,
+    /// Play the caller's Accept-Language choice when a pack has the prompt
+    pub honor_caller_preference: bool,
+    /// Language per trunk or tenant; the first matching rule wins
+    pub selection: Vec<PromptSelectionRule>,
+}
+
+impl Default for PromptsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "/var/lib/redfire/prompts".to_string(),
+            default_language: "en".to_string(),
+            honor_caller_preference: true,
+            selection: Vec::new(),
+        }
+    }
+}
+
+/// Prompt language for calls on a trunk or from a tenant domain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptSelectionRule {
+    #[serde(default)]
+    pub trunk: Option<String>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    pub language: String,
+}
+
+/// Whether `tag` is a language tag a prompt pack can be named by, e.g. `en`
+/// or `pt-br`: alphanumeric subtags of up to 8 characters joined by `-`,
+/// starting with a primary language of letters only
+pub(crate) fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary_ok = subtags
+        .next()
+        .is_some_and(|primary| (1..=8).contains(&primary.len()) && primary.bytes().all(|b| b.is_ascii_alphabetic()));
+    primary_ok && subtags.all(|subtag| (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// One trunk registering with its carrier
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrunkRegistrationEntry {
+    /// Trunk name, as used for route targets
+    pub trunk: String,
+    /// Carrier registrar as host:port
+    pub registrar: String,
+    pub user: String,
+    /// Contact registered for the trunk, e.g. `<sip:trunk1@203.0.113.5:5060>`
+    pub contact: String,
+    #[serde(default = "default_trunk_register_expires")]
+    pub expires_secs: u32,
+}
+
+fn default_trunk_register_expires() -> u32 {
+    3600
+}
+
+/// Retry timing for failed trunk registrations. Each consecutive failure
+/// multiplies the interval up to the cap, and every interval is jittered so
+/// trunks do not retry in step once the registrar comes back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegistrationBackoffConfig {
+    pub initial_retry_secs: u64,
+    pub max_retry_secs: u64,
+    pub multiplier: f64,
+    /// Each interval is moved randomly by up to this share of itself
+    pub jitter_percent: u8,
+    /// First registrations after startup are spread over this window
+    pub startup_spread_secs: u64,
+}
+
+impl Default for RegistrationBackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_retry_secs: 30,
+            max_retry_secs: 1800,
+            multiplier: 2.0,
+            jitter_percent: 25,
+            startup_spread_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrunkRegistrationConfig {
+    pub enabled: bool,
+    pub trunks: Vec<TrunkRegistrationEntry>,
+    /// How long to wait for a registrar's final response
+    pub timeout_ms: u64,
+    pub backoff: RegistrationBackoffConfig,
+}
+
+impl Default for TrunkRegistrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            trunks: Vec::new(),
+            timeout_ms: 5000,
+            backoff: RegistrationBackoffConfig::default(),
+        }
+    }
+}
+
+/// What a test number plays to the caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TestNumberKind {
+    /// Loops the caller's audio back and announces the round-trip delay
+    Echo,
+    /// 1 kHz tone at 0 dBm0
+    Milliwatt,
+    Silence,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestNumber {
+    pub number: String,
+    pub kind: TestNumberKind,
+}
+
+/// Numbers terminated inside the gateway, for verifying a trunk's media
+/// path without involving the far-end carrier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TestNumbersConfig {
+    pub enabled: bool,
+    pub numbers: Vec<TestNumber>,
+    /// Test calls still up after this long are ended
+    pub max_duration_secs: u64,
+    /// Echo calls announce the round-trip delay once this far in
+    pub latency_announcement_secs: u64,
+    /// Ended test calls kept for the management API
+    pub max_recent: usize,
+}
+
+impl Default for TestNumbersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            numbers: Vec::new(),
+            max_duration_secs: 600,
+            latency_announcement_secs: 5,
+            max_recent: 50,
+        }
+    }
+}
+
+/// Codec pages are multicast in; callers must offer it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PagingCodec {
+    Pcmu,
+    Pcma,
+}
+
+/// Paging zone the caller picks with a DTMF digit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PagingZone {
+    /// 0-9, * or #
+    pub digit: char,
+    pub name: String,
+    /// Multicast group the zone's speakers listen on, as address:port
+    pub group: String,
+}
+
+/// Calls to the paging numbers are answered by the gateway and their audio
+/// multicast to overhead paging speakers on the LAN
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PagingConfig {
+    pub enabled: bool,
+    pub numbers: Vec<String>,
+    /// Multicast group paged when the caller picks no zone, as address:port
+    pub group: String,
+    pub codec: PagingCodec,
+    /// Multicast TTL; 1 keeps pages on the local segment
+    pub ttl: u32,
+    /// Local address pages are sent from; the routing table decides when unset
+    pub interface: Option<Ipv4Addr>,
+    /// Zones the caller can pick by DTMF at the start of a page
+    pub zones: Vec<PagingZone>,
+    /// How long the caller has to press a zone digit before `group` is paged
+    pub zone_select_secs: u64,
+    /// Pages still up after this long are ended
+    pub max_duration_secs: u64,
+    /// Ended pages kept for the management API
+    pub max_recent: usize,
+}
+
+impl Default for PagingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            numbers: Vec::new(),
+            group: "239.192.0.1:5004".to_string(),
+            codec: PagingCodec::Pcmu,
+            ttl: 1,
+            interface: None,
+            zones: Vec::new(),
+            zone_select_secs: 5,
+            max_duration_secs: 300,
+            max_recent: 50,
+        }
+    }
+}
+
+/// How long an answered call's media may stay silent before the call is
+/// released. Fax and modem passthrough pause for longer than voice, during
+/// T.30 phase changes and retrains
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaInactivityConfig {
+    pub enabled: bool,
+    pub voice_secs: u64,
+    pub fax_secs: u64,
+    pub modem_secs: u64,
+}
+
+impl Default for MediaInactivityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            voice_secs: 60,
+            fax_secs: 300,
+            modem_secs: 900,
+        }
+    }
+}
+
+/// Hourly release cause statistics kept for failure reporting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReleaseCauseConfig {
+    pub enabled: bool,
+    /// Hours of statistics kept
+    pub retention_hours: u32,
+    /// Leading digits of the called number that identify a destination
+    pub destination_digits: usize,
+    /// Failure causes ranked in a report unless the request asks otherwise
+    pub top: usize,
+}
+
+impl Default for ReleaseCauseConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_hours: 24,
+            destination_digits: 6,
+            top: 10,
+        }
+    }
+}
+
+/// WebRTC legs: the relay answers as an ICE-lite agent and passive DTLS
+/// endpoint, with RTP and RTCP multiplexed on one port
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebRtcConfig {
+    pub enabled: bool,
+    /// STUN server (host:port) queried for a server-reflexive candidate
+    /// next to the host candidate
+    pub stun_server: Option<String>,
+    /// How long candidate gathering waits for the STUN server
+    pub stun_timeout_ms: u64,
+}
+
+impl Default for WebRtcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stun_server: None,
+            stun_timeout_ms: 1000,
+        }
+    }
+}
+
+/// RTCP extended reports (RFC 3611) sent on plain RTP legs, and call
+/// quality published to a collector as SIP PUBLISH vq-rtcpxr (RFC 6035)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RtcpXrConfig {
+    pub enabled: bool,
+    /// Interval between extended reports on each leg
+    pub report_interval_secs: u64,
+    /// Send Loss RLE blocks alongside the VoIP metrics
+    pub loss_rle: bool,
+    /// Packetisation interval used to turn burst and gap lengths into time
+    pub packet_ms: u32,
+    /// Collector receiving PUBLISH requests, as host:port
+    pub collector: Option<String>,
+    /// Request URI of the PUBLISH; `sip:collector@<host>` when unset
+    pub collector_uri: Option<String>,
+    /// Publish interval reports while calls are up, not only at call end
+    pub interval_reports: bool,
+    pub publish_timeout_ms: u64,
+}
+
+impl Default for RtcpXrConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            report_interval_secs: 5,
+            loss_rle: false,
+            packet_ms: 20,
+            collector: None,
+            collector_uri: None,
+            interval_reports: false,
+            publish_timeout_ms: 2000,
+        }
+    }
+}
+
+/// Priority calls marked with the SIP Resource-Priority header (RFC 4412),
+/// ranked on the MLPP precedence levels shared with ISDN
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrecedenceConfig {
+    pub enabled: bool,
+    /// Namespaces accepted from callers: `dsn`, `drsn`, `q735`, `ets`, `wps`
+    /// or one listed in `custom_namespaces`
+    pub namespaces: Vec<String>,
+    /// Priority values of operator-defined namespaces, highest first; the
+    /// last value is routine and each earlier one a level above it
+    pub custom_namespaces: BTreeMap<String, Vec<String>>,
+    /// Release the lowest call of lower precedence when a priority call
+    /// finds the gateway at capacity
+    pub preemption: bool,
+}
+
+impl Default for PrecedenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            namespaces: precedence::BUILTIN_NAMESPACES.iter().map(|namespace| namespace.to_string()).collect(),
+            custom_namespaces: BTreeMap::new(),
+            preemption: true,
+        }
+    }
+}
+
+/// SIP session timers: calls whose far end disappears are released once a
+/// refresh fails or the session interval lapses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionTimerConfig {
+    pub enabled: bool,
+    /// Interval requested on leg B and the most granted on leg A
+    pub session_expires_secs: u32,
+    /// Shortest interval accepted; shorter requests are refused with 422
+    pub min_se_secs: u32,
+    /// Refresher of leg A when the caller supports timers but leaves it open
+    pub leg_a_refresher: Refresher,
+    /// Refresh with UPDATE instead of re-INVITE; trunks whose quirk profile
+    /// marks re-INVITE broken always get UPDATE
+    pub use_update: bool,
+}
+
+impl Default for SessionTimerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            session_expires_secs: 1800,
+            min_se_secs: session_timer::MIN_SE_FLOOR_SECS,
+            leg_a_refresher: Refresher::Uac,
+            use_update: false,
+        }
+    }
+}
+
+/// Cause-specific announcements for refused calls, keyed by reason:
+/// `blocked`, `call-limit`, `invalid-destination` or `congestion`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RejectAnnouncementConfig {
+    pub enabled: bool,
+    pub causes: BTreeMap<String, RejectAnnouncementRule>,
+    /// Per ingress trunk (the caller's host) overrides of `causes`
+    pub trunks: BTreeMap<String, BTreeMap<String, RejectAnnouncementRule>>,
+}
+
+impl Default for RejectAnnouncementConfig {
+    fn default() -> Self {
+        let rule = |announcement: &str| RejectAnnouncementRule { announcement: announcement.to_string(), answer: false };
+        Self {
+            enabled: false,
+            causes: BTreeMap::from([
+                (RejectReason::CallLimit.key().to_string(), rule(prompts::ALL_CIRCUITS_BUSY)),
+                (RejectReason::InvalidDestination.key().to_string(), rule(prompts::INVALID_NUMBER)),
+                (RejectReason::Congestion.key().to_string(), rule(prompts::ALL_CIRCUITS_BUSY)),
+            ]),
+            trunks: BTreeMap::new(),
+        }
+    }
+}
+
+/// How a refused call hears its announcement
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RejectAnnouncementRule {
+    /// Prompt to play; empty to send only the SIP status
+    pub announcement: String,
+    /// Answer before playing, for providers that drop early media
+    pub answer: bool,
+}
+
+/// Reliable provisional responses (RFC 3262) and their mapping to Q.931
+/// ALERTING/PROGRESS. Leg A gets reliable 18x whenever its INVITE supports
+/// 100rel; callers requiring it are refused with 420 while this is off
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReliableProvisionalConfig {
+    pub enabled: bool,
+    /// Require rather than offer 100rel on leg B, for carriers whose PRI
+    /// interworking only passes reliable provisionals
+    pub require_on_leg_b: bool,
+}
+
+/// Whether `group` is an IPv4 multicast address:port
+fn is_multicast_group(group: &str) -> bool {
+    group
+        .parse::<SocketAddr>()
+        .is_ok_and(|address| address.is_ipv4() && address.ip().is_multicast() && address.port() != 0)
+}
+
+/// Power-on self-test run before the gateway goes in service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelfTestConfig {
+    pub enabled: bool,
+    /// Briefly loop each span back; only where the far end tolerates it
+    pub span_loopback: bool,
+    pub loopback_duration_ms: u64,
+    /// Lowest acceptable firmware per TDM backend name, e.g. `DAHDI = "3.1.0"`
+    pub min_firmware: BTreeMap<String, String>,
+    /// NTP servers that must answer an SNTP query
+    pub ntp_servers: Vec<String>,
+    /// GPS receiver that must produce NMEA sentences
+    pub gps_device: Option<String>,
+    pub timing_timeout_ms: u64,
+    /// Checks whose failure keeps the gateway out of service
+    pub critical_checks: Vec<SelfTestCheck>,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            span_loopback: false,
+            loopback_duration_ms: 2000,
+            min_firmware: BTreeMap::new(),
+            ntp_servers: Vec::new(),
+            gps_device: None,
+            timing_timeout_ms: 2000,
+            critical_checks: vec![SelfTestCheck::TdmHardware, SelfTestCheck::Certificates],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTestCheck {
+    TdmHardware,
+    SpanLoopback,
+    Timing,
+    Certificates,
+}
+
+/// Certificate/key pair served by one of the gateway's TLS endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagedCertificate {
+    pub id: String,
+    pub usage: CertificateUsage,
+    pub cert_path: String,
+    pub key_path: String,
+    /// Names requested when the certificate is renewed through ACME
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Renew automatically through the configured ACME client
+    #[serde(default)]
+    pub auto_renew: bool,
+}
+
+/// Endpoint a managed certificate is served on. The management API is
+/// plain HTTP and has none; front it with a TLS-terminating proxy that
+/// manages its own certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CertificateUsage {
+    #[serde(rename = "sip_tls")]
+    SipTls,
+}
+
+/// ACME renewal settings (Let's Encrypt or an internal CA)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeConfig {
+    pub directory_url: String,
+    pub contact_email: String,
+    /// Days before expiry at which renewal is attempted
+    pub renew_before_days: u32,
+    /// ACME client invocation; `{domains}`, `{cert_path}`, `{key_path}`,
+    /// `{directory_url}` and `{email}` are substituted before execution
+    pub client_command: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusteringConfig {
+    pub enabled: bool,
+    pub cluster_id: String,
+    pub node_id: String,
+    pub anycast_addresses: Vec<String>,
+    pub sync_port: u16,
+    pub heartbeat_interval: u32,
+    pub transaction_sync_enabled: bool,
+    pub shared_state_backend: SharedStateBackend,
+    pub consensus_algorithm: ConsensusAlgorithm,
+    #[serde(default)]
+    pub discovery: ClusterDiscoveryConfig,
+}
+
+/// Automatic discovery of cluster peers via mDNS or unicast DNS-SD
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterDiscoveryConfig {
+    pub enabled: bool,
+    /// Advertise and browse on the local link with multicast DNS
+    pub mdns: bool,
+    /// Browse `<service_type>.<dns_domain>` records on a unicast DNS server
+    pub dns_domain: Option<String>,
+    /// DNS server for domain browsing; the system resolver's first nameserver when unset
+    pub dns_server: Option<String>,
+    pub service_type: String,
+    /// Address advertised to peers; the primary outbound address when unset
+    pub advertise_address: Option<String>,
+    pub announce_interval_secs: u64,
+    /// Key shared by all cluster members, used to authenticate discovered peers
+    pub shared_secret: String,
+    /// Maximum age of a signed advertisement
+    pub max_clock_skew_secs: u64,
+    pub auth_timeout_ms: u64,
+}
+
+impl Default for ClusterDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mdns: true,
+            dns_domain: None,
+            dns_server: None,
+            service_type: "_redfire-cluster._udp".to_string(),
+            advertise_address: None,
+            announce_interval_secs: 30,
+            shared_secret: String::new(),
+            max_clock_skew_secs: 120,
+            auth_timeout_ms: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SharedStateBackend {
+    #[serde(rename = "redis")]
+    Redis { addresses: Vec<String>, password: Option<String> },
+    #[serde(rename = "etcd")]
+    Etcd { endpoints: Vec<String> },
+    #[serde(rename = "consul")]
+    Consul { endpoints: Vec<String> },
+    #[serde(rename = "raft")]
+    Raft { peers: Vec<String> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ConsensusAlgorithm {
+    #[serde(rename = "raft")]
+    Raft,
+    #[serde(rename = "pbft")]
+    Pbft,
+    #[serde(rename = "hashgraph")]
+    Hashgraph,
+}
+
+/// Top-level key listing the files a configuration file is layered on
+const INCLUDE_KEY: &str = "include";
+
+/// Merge `overlay` into `base`: tables key by key, any other value replaced
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Load `path` over its includes; `chain` holds the files including it
+fn load_layered(path: &Path, chain: &mut Vec<PathBuf>) -> Result<toml::Table> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| Error::invalid_config(format!("Cannot read {}: {}", path.display(), e)))?;
+    if chain.contains(&canonical) {
+        return Err(Error::invalid_config(format!("{} includes itself", path.display())));
+    }
+    let contents = std::fs::read_to_string(path)?;
+    let mut table: toml::Table = toml::from_str(&contents)
+        .map_err(|e| Error::parse(format!("Invalid TOML in {}: {}", path.display(), e)))?;
+
+    let not_a_list = || Error::invalid_config(format!("include in {} must be a list of file names", path.display()));
+    let includes = match table.remove(INCLUDE_KEY) {
+        None => Vec::new(),
+        Some(toml::Value::Array(items)) => items
+            .into_iter()
+            .map(|item| match item {
+                toml::Value::String(include) => Ok(include),
+                _ => Err(not_a_list()),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Some(_) => return Err(not_a_list()),
+    };
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = toml::Table::new();
+    chain.push(canonical);
+    for include in includes {
+        merge_tables(&mut merged, load_layered(&dir.join(include), chain)?);
+    }
+    chain.pop();
+    merge_tables(&mut merged, table);
+    Ok(merged)
+}
+
+impl GatewayConfig {
+    /// Load a configuration file layered over the files it includes
+    ///
+    /// A file may name other files in a top-level `include` list, relative
+    /// to its own directory. They are merged in the order listed, and the
+    /// file itself is merged over them, so a site file including the fleet
+    /// base only states what differs. Tables merge key by key; any other
+    /// value, arrays and arrays of tables included, replaces the one beneath
+    /// it. Included files may include others, but not themselves.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        toml::Value::Table(Self::load_merged(path)?)
+            .try_into()
+            .map_err(|e| Error::parse(format!("Invalid TOML: {}", e)))
+    }
+
+    /// The document a configuration file and its includes merge into
+    pub fn load_merged<P: AsRef<Path>>(path: P) -> Result<toml::Table> {
+        load_layered(path.as_ref(), &mut Vec::new())
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = toml::to_string_pretty(self)
+            .map_err(|e| Error::internal(format!("Failed to serialize config: {}", e)))?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn load_from_env() -> Result<Self> {
+        let mut settings = config::Config::builder();
+        
+        // Load from environment variables with REDFIRE_ prefix
+        settings = settings.add_source(
+            config::Environment::with_prefix("REDFIRE")
+                .separator("_")
+        );
+        
+        let config = settings.build()?;
+        let gateway_config = config.try_deserialize()?;
+        Ok(gateway_config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        // Validate port ranges
+        if self.rtp.port_range.min >= self.rtp.port_range.max {
+            return Err(Error::invalid_config("Invalid RTP port range"));
+        }
+        let limits = &self.sip.size_limits;
+        if limits.max_message_bytes == 0 || limits.max_header_bytes == 0 || limits.max_sdp_bytes == 0 {
+            return Err(Error::invalid_config("SIP size limits must be at least 1 byte"));
+        }
+        if limits.max_header_bytes > limits.max_message_bytes || limits.max_sdp_bytes > limits.max_message_bytes {
+            return Err(Error::invalid_config("SIP header and SDP limits cannot exceed the message limit"));
+        }
+        if self.sip.tls.enabled {
+            if self.sip.tls.sni.iter().any(|sni| sni.server_name.is_empty()) {
+                return Err(Error::invalid_config("SIP TLS SNI server names must not be empty"));
+            }
+            sip_tls::cipher_suites(&self.sip.tls.cipher_suites)?;
+        }
+        let websocket = &self.sip.websocket;
+        if websocket.enabled {
+            let ports = [websocket.listen_port, websocket.secure_port];
+            if ports.iter().all(Option::is_none) {
+                return Err(Error::invalid_config("SIP WebSocket needs a listen port or a secure port"));
+            }
+            let mut taken = vec![self.sip.listen_port];
+            if self.sip.tls.enabled {
+                taken.push(self.sip.tls.listen_port);
+            }
+            for port in ports.into_iter().flatten() {
+                if port == 0 || taken.contains(&port) {
+                    return Err(Error::invalid_config(format!("SIP WebSocket port {} is zero or already in use", port)));
+                }
+                taken.push(port);
+            }
+            if websocket.ping_interval_secs == 0 || websocket.idle_timeout_secs <= websocket.ping_interval_secs {
+                return Err(Error::invalid_config(
+                    "SIP WebSocket needs a non-zero ping interval shorter than the idle timeout",
+                ));
+            }
+        }
+        let registrar = &self.sip.registrar;
+        if registrar.enabled {
+            if registrar.min_expires_secs == 0
+                || registrar.max_expires_secs < registrar.min_expires_secs
+                || !(registrar.min_expires_secs..=registrar.max_expires_secs).contains(&registrar.default_expires_secs)
+            {
+                return Err(Error::invalid_config(
+                    "SIP registrar needs a non-zero minimum expiry, a maximum at or above it and a default between them",
+                ));
+            }
+            if registrar.max_contacts == 0 || registrar.nonce_lifetime_secs == 0 {
+                return Err(Error::invalid_config("SIP registrar needs at least one contact and a non-zero nonce lifetime"));
+            }
+            match registrar.credential_source {
+                CredentialSource::Config => {
+                    for (index, user) in registrar.users.iter().enumerate() {
+                        let hashed = user.ha1.as_ref().is_some_and(|ha1| ha1.len() == 32 && ha1.bytes().all(|b| b.is_ascii_hexdigit()));
+                        if user.username.is_empty() || (user.password.is_none() && !hashed) {
+                            return Err(Error::invalid_config(format!(
+                                "Registrar user {} needs a username and a password or 32-digit hex ha1",
+                                index
+                            )));
+                        }
+                        if registrar.users[..index].iter().any(|other| other.username == user.username) {
+                            return Err(Error::invalid_config(format!("Registrar user {} is listed twice", user.username)));
+                        }
+                    }
+                }
+                CredentialSource::Http => {
+                    if registrar.credentials_url.as_ref().filter(|url| !url.is_empty()).is_none() || registrar.credentials_timeout_ms == 0 {
+                        return Err(Error::invalid_config("HTTP registrar credentials need a URL and a non-zero timeout"));
+                    }
+                }
+            }
+        }
+        if self.rtp.batching.recv_batch_size == 0 || self.rtp.batching.send_batch_size == 0 {
+            return Err(Error::invalid_config("RTP batch sizes must be at least 1"));
+        }
+        if self.rtp.rtcp.enabled && self.rtp.rtcp.interval_secs == 0 {
+            return Err(Error::invalid_config("RTCP report interval must be at least 1 second"));
+        }
+        let placements = [
+            ("RTP", &self.rtp.network),
+            ("SNMP", &self.snmp.network),
+            ("SIP", &self.sip.network),
+            ("Management API", &self.management_api.network),
+            ("Status page", &self.management_api.status_page.network),
+        ];
+        for (service, placement) in placements {
+            if placement.device.as_deref() == Some("") || placement.netns.as_deref() == Some("") {
+                return Err(Error::invalid_config(format!("{} network device and namespace must not be empty", service)));
+            }
+        }
+        let packs = RegulatoryPacks::new(&self.freetdm.regulatory)?;
+        for span in &self.freetdm.spans {
+            packs.for_span(span)?;
+        }
+        if self.freetdm.hot_swap.enabled && self.freetdm.hot_swap.rescan_interval_secs == 0 {
+            return Err(Error::invalid_config("TDM hot-swap needs a non-zero rescan interval"));
+        }
+        if self.channel_history.enabled && self.channel_history.calls_per_channel == 0 {
+            return Err(Error::invalid_config("Channel history needs at least one call per channel"));
+        }
+        if self.management_api.enabled {
+            if self.management_api.max_cdrs == 0 {
+                return Err(Error::invalid_config("Management API must return at least one CDR per query"));
+            }
+            if self.management_api.secret.as_deref() == Some("") {
+                return Err(Error::invalid_config("Management API secret must not be empty"));
+            }
+        }
+        let status_page = &self.management_api.status_page;
+        if status_page.enabled {
+            if status_page.refresh_secs == 0 {
+                return Err(Error::invalid_config("Status page refresh interval must be at least one second"));
+            }
+            if status_page.secret.as_deref() == Some("") {
+                return Err(Error::invalid_config("Status page secret must not be empty; omit it to leave the page open"));
+            }
+            if self.management_api.enabled && status_page.listen == self.management_api.listen {
+                return Err(Error::invalid_config("Status page needs a listener of its own, not the management API's"));
+            }
+        }
+        if self.alarm_relays.enabled {
+            if self.alarm_relays.poll_interval_ms == 0 {
+                return Err(Error::invalid_config("Alarm relay poll interval must be non-zero"));
+            }
+            for output in &self.alarm_relays.outputs {
+                if output.driver == GpioDriver::Gpiod && output.chip.is_none() {
+                    return Err(Error::invalid_config(format!("Alarm relay line {} uses gpiod but names no chip", output.line)));
+                }
+            }
+        }
+        if self.automation.enabled {
+            if self.automation.evaluation_interval_secs == 0 {
+                return Err(Error::invalid_config("Automation evaluation interval must be non-zero"));
+            }
+            for rule in &self.automation.rules {
+                if self.automation.rules.iter().filter(|other| other.name == rule.name).count() > 1 {
+                    return Err(Error::invalid_config(format!("Automation rule {} is defined twice", rule.name)));
+                }
+                let parsed = automation::AutomationRule::parse(&rule.name, &rule.rule)?;
+                for webhook in parsed.webhooks() {
+                    if !self.automation.webhooks.contains_key(webhook) {
+                        return Err(Error::invalid_config(format!("Automation rule {} names unknown webhook {}", rule.name, webhook)));
+                    }
+                }
+            }
+        }
+        let campaigns = &self.campaigns;
+        if campaigns.enabled {
+            if campaigns.trunks.is_empty() {
+                return Err(Error::invalid_config("Campaigns need at least one trunk to call out over"));
+            }
+            for trunk in &campaigns.trunks {
+                if trunk.name.is_empty() || trunk.proxy.is_empty() || trunk.caller_id.is_empty() {
+                    return Err(Error::invalid_config("Campaign trunks need a name, proxy and caller ID"));
+                }
+                if campaigns.trunks.iter().filter(|other| other.name == trunk.name).count() > 1 {
+                    return Err(Error::invalid_config(format!("Campaign trunk {} is defined twice", trunk.name)));
+                }
+            }
+            if !(campaigns.default_cps > 0.0 && campaigns.default_cps <= campaigns.max_cps) {
+                return Err(Error::invalid_config("Campaign default CPS must be positive and at most the maximum CPS"));
+            }
+            if campaigns.max_concurrent_calls == 0 || campaigns.max_destinations == 0 || campaigns.max_attempts == 0 {
+                return Err(Error::invalid_config("Campaigns need at least one concurrent call, destination and attempt"));
+            }
+            if campaigns.ring_timeout_secs == 0 {
+                return Err(Error::invalid_config("Campaign ring timeout must be non-zero"));
+            }
+        }
+        let heartbeat = &self.heartbeat;
+        if heartbeat.enabled {
+            if !(heartbeat.controller_url.starts_with("https://") || heartbeat.controller_url.starts_with("http://")) {
+                return Err(Error::invalid_config("Heartbeat controller URL must be http:// or https://"));
+            }
+            if heartbeat.secret.len() < 16 {
+                return Err(Error::invalid_config("Heartbeat secret must be at least 16 characters"));
+            }
+            if heartbeat.interval_secs == 0 || heartbeat.timeout_ms == 0 {
+                return Err(Error::invalid_config("Heartbeat interval and timeout must be non-zero"));
+            }
+            if heartbeat.retry_initial_ms == 0 || heartbeat.retry_initial_ms > heartbeat.retry_max_ms {
+                return Err(Error::invalid_config("Heartbeat retry delay must be non-zero and at most the maximum"));
+            }
+        }
+        let audio_tap = &self.audio_tap;
+        if audio_tap.enabled {
+            if audio_tap.operators.is_empty() {
+                return Err(Error::invalid_config("Audio taps need at least one operator"));
+            }
+            for operator in &audio_tap.operators {
+                if operator.name.is_empty() || operator.token.len() < 16 {
+                    return Err(Error::invalid_config("Tap operators need a name and a token of at least 16 characters"));
+                }
+                if audio_tap.operators.iter().filter(|other| other.token == operator.token).count() > 1 {
+                    return Err(Error::invalid_config(format!("Tap operator {} shares its token", operator.name)));
+                }
+            }
+            if let Some(group) = audio_tap.multicast_groups.iter().find(|group| !group.ip().is_multicast()) {
+                return Err(Error::invalid_config(format!("Audio tap group {} is not a multicast address", group)));
+            }
+            if audio_tap.default_duration_secs == 0 || audio_tap.default_duration_secs > audio_tap.max_duration_secs {
+                return Err(Error::invalid_config("Audio tap default duration must be non-zero and at most the maximum"));
+            }
+            if audio_tap.max_taps == 0 {
+                return Err(Error::invalid_config("Audio taps need room for at least one tap"));
+            }
+        }
+        if self.capacity.enabled {
+            if self.capacity.sample_interval_secs == 0 || self.capacity.sample_interval_secs > 3600 {
+                return Err(Error::invalid_config("Capacity sample interval must be between 1 and 3600 seconds"));
+            }
+            if self.capacity.retention_days < 30 {
+                return Err(Error::invalid_config("Capacity history must cover at least 30 days for monthly reports"));
+            }
+            if !(self.capacity.grade_of_service > 0.0 && self.capacity.grade_of_service < 1.0) {
+                return Err(Error::invalid_config("Capacity grade of service must be a blocking probability between 0 and 1"));
+            }
+        }
+        if self.time_sync.max_holdover_secs == 0 {
+            return Err(Error::invalid_config("Maximum holdover for billing must be non-zero"));
+        }
+        let recovery = &self.freetdm.recovery;
+        if recovery.enabled
+            && (recovery.max_attempts == 0
+                || recovery.initial_backoff_secs == 0
+                || recovery.max_backoff_secs < recovery.initial_backoff_secs)
+        {
+            return Err(Error::invalid_config(
+                "Span recovery needs at least one attempt and a backoff ceiling above its initial delay",
+            ));
+        }
+        if self.profiling.enabled && (self.profiling.max_cpu_seconds == 0 || self.profiling.frequency_hz <= 0) {
+            return Err(Error::invalid_config("Profiling needs a non-zero CPU profile limit and sampling frequency"));
+        }
+        let hook = &self.b2bua.routing_hook;
+        if hook.enabled {
+            let target_set = match hook.kind {
+                RoutingHookKind::Http => hook.url.is_some(),
+                RoutingHookKind::Wasm => hook.module.is_some(),
+            };
+            if !target_set || hook.timeout_ms == 0 {
+                return Err(Error::invalid_config("Routing hook needs a url or module and a non-zero timeout"));
+            }
+        }
+        let survivability = &self.b2bua.survivability;
+        if survivability.enabled
+            && (survivability.upstream_registrar.is_empty()
+                || survivability.failures_before_outage == 0
+                || survivability.successes_before_recovery == 0)
+        {
+            return Err(Error::invalid_config("Survivability needs an upstream registrar and non-zero probe thresholds"));
+        }
+        if let Some(rule) = survivability.dial_plan.iter().find(|rule| rule.egress_spans.is_empty()) {
+            return Err(Error::invalid_config(format!("Survivability rule {} has no egress spans", rule.id)));
+        }
+        let lookup = &self.b2bua.number_lookup;
+        if lookup.enabled {
+            let target_set = match lookup.kind {
+                NumberLookupKind::Http => lookup.url.is_some(),
+                NumberLookupKind::SipRedirect | NumberLookupKind::Enum => lookup.server.is_some(),
+            };
+            if !target_set || lookup.timeout_ms == 0 {
+                return Err(Error::invalid_config("Number lookup needs a url or server and a non-zero timeout"));
+            }
+        }
+        let quality = &self.b2bua.quality_alerting;
+        if quality.enabled && !(quality.baseline_weight > 0.0 && quality.baseline_weight <= 1.0) {
+            return Err(Error::invalid_config("Quality baseline weight must be in (0, 1]"));
+        }
+        let xr = &self.b2bua.rtcp_xr;
+        if xr.enabled {
+            if xr.report_interval_secs == 0 || xr.packet_ms == 0 {
+                return Err(Error::invalid_config("RTCP XR needs a non-zero report interval and packet time"));
+            }
+            let collector_valid = xr.collector.as_deref().map_or(true, |collector| {
+                collector.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+            });
+            if !collector_valid || (xr.collector.is_some() && xr.publish_timeout_ms == 0) {
+                return Err(Error::invalid_config("RTCP XR collector must be host:port with a non-zero publish timeout"));
+            }
+        }
+        let priority = &self.b2bua.precedence;
+        if priority.enabled {
+            let known = |namespace: &String| {
+                precedence::BUILTIN_NAMESPACES.contains(&namespace.as_str())
+                    || priority.custom_namespaces.contains_key(namespace)
+            };
+            if let Some(namespace) = priority.namespaces.iter().find(|namespace| !known(namespace)) {
+                return Err(Error::invalid_config(format!("Unknown Resource-Priority namespace {}", namespace)));
+            }
+            for (namespace, values) in &priority.custom_namespaces {
+                let token = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+                if !token(namespace) || values.is_empty() || !values.iter().all(|value| token(value)) {
+                    return Err(Error::invalid_config(format!("Resource-Priority namespace {} needs a name and values without dots", namespace)));
+                }
+            }
+        }
+        let announcements = &self.b2bua.reject_announcements;
+        let reason_keys = announcements.causes.keys().chain(announcements.trunks.values().flat_map(|rules| rules.keys()));
+        for key in reason_keys {
+            if !RejectReason::ALL.iter().any(|reason| reason.key() == key) {
+                return Err(Error::invalid_config(format!("Unknown call rejection reason {}", key)));
+            }
+        }
+        let timers = &self.b2bua.session_timers;
+        if timers.enabled && (timers.min_se_secs < session_timer::MIN_SE_FLOOR_SECS || timers.session_expires_secs < timers.min_se_secs) {
+            return Err(Error::invalid_config("Session timers need a Min-SE of at least 90s and an interval no shorter"));
+        }
+        let gapping = &self.b2bua.call_gapping;
+        if gapping.enabled && (gapping.trigger_count == 0 || gapping.prefix_digits == 0 || gapping.gap_duration_secs == 0) {
+            return Err(Error::invalid_config("Call gapping needs a non-zero trigger count, prefix length and duration"));
+        }
+        // Candidate rules are held to the same checks before they can be activated
+        for rule in self.b2bua.routing_table.iter().chain(&self.b2bua.shadow_routing.routing_table) {
+            let diverts = rule.no_answer_divert.is_some();
+            if rule.no_answer_timeout_secs == Some(0) || (diverts && rule.no_answer_timeout_secs.is_none()) {
+                return Err(Error::invalid_config(format!("Routing rule {} needs a non-zero no-answer timeout", rule.id)));
+            }
+            if let Some(key) = rule.failover_actions.keys().find(|key| !is_status_key(key)) {
+                return Err(Error::invalid_config(format!("Routing rule {} has invalid failover status {}", rule.id, key)));
+            }
+            if rule.early_media.as_ref().is_some_and(|policy| policy.max_duration_secs == Some(0)) {
+                return Err(Error::invalid_config(format!("Routing rule {} needs a non-zero early media limit", rule.id)));
+            }
+        }
+        for bucket in &self.b2bua.cps_shaping.buckets {
+            if bucket.scope == ShapingScope::Tenant && bucket.sources.is_empty() {
+                return Err(Error::invalid_config(format!("CPS tenant bucket {} needs source networks", bucket.id)));
+            }
+            if let Some(source) = bucket.sources.iter().find(|source| craft::parse_cidr(source).is_none()) {
+                return Err(Error::invalid_config(format!("CPS bucket {} has invalid source {}", bucket.id, source)));
+            }
+        }
+        let reroute = &self.b2bua.reroute;
+        if reroute.enabled && reroute.max_attempts == 0 {
+            return Err(Error::invalid_config("Re-routing needs a non-zero attempt limit"));
+        }
+        let reroute_keys = reroute.default_actions.keys().chain(reroute.trunk_actions.values().flat_map(|actions| actions.keys()));
+        for key in reroute_keys {
+            if !is_status_key(key) {
+                return Err(Error::invalid_config(format!("Invalid re-route status {}", key)));
+            }
+        }
+        if self.b2bua.takeover.enabled && self.b2bua.takeover.answer_timeout_secs == 0 {
+            return Err(Error::invalid_config("Call takeover answer timeout must be non-zero"));
+        }
+        if self.b2bua.transfer.enabled && self.b2bua.transfer.answer_timeout_secs == 0 {
+            return Err(Error::invalid_config("Call transfer answer timeout must be non-zero"));
+        }
+        if self.b2bua.hold.music_on_hold.as_ref().is_some_and(|path| path.as_os_str().is_empty()) {
+            return Err(Error::invalid_config("Music on hold file path must not be empty"));
+        }
+        let latency = &self.b2bua.transcoding_latency;
+        if latency.enabled {
+            if latency.budget_us == 0 || latency.codec_budgets_us.values().any(|budget| *budget == 0) {
+                return Err(Error::invalid_config("Transcoding latency budgets must be non-zero"));
+            }
+            if latency.window_frames < 100 {
+                return Err(Error::invalid_config("Transcoding latency window needs at least 100 frames for a 99th percentile"));
+            }
+        }
+        let fax = &self.b2bua.fax;
+        if fax.enabled && fax.t38 {
+            if fax.max_datagram < 100 {
+                return Err(Error::invalid_config("T.38 maximum datagram must be at least 100 bytes"));
+            }
+            if ![2400, 4800, 7200, 9600, 12000, 14400].contains(&fax.max_bit_rate) {
+                return Err(Error::invalid_config(format!("Invalid T.38 maximum bit rate {}", fax.max_bit_rate)));
+            }
+        }
+        let correlation = &self.b2bua.correlation;
+        if correlation.header.is_empty() || !correlation.header.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+            return Err(Error::invalid_config(format!("Invalid correlation header name '{}'", correlation.header)));
+        }
+        for (index, group) in self.b2bua.ring_groups.iter().enumerate() {
+            if group.members.is_empty() {
+                return Err(Error::invalid_config(format!("Ring group {} has no members", group.name)));
+            }
+            let zero_timeout = group.ring_timeout_secs == 0 || group.members.iter().any(|m| m.ring_timeout_secs == Some(0));
+            if zero_timeout {
+                return Err(Error::invalid_config(format!("Ring group {} needs non-zero ring timeouts", group.name)));
+            }
+            if self.b2bua.ring_groups[..index].iter().any(|other| other.name == group.name) {
+                return Err(Error::invalid_config(format!("Duplicate ring group {}", group.name)));
+            }
+        }
+        let advertisement = &self.b2bua.route_advertisement;
+        if advertisement.enabled
+            && (advertisement.targets.is_empty() || advertisement.interval_secs == 0 || advertisement.timeout_ms == 0)
+        {
+            return Err(Error::invalid_config("Route advertisement needs targets, a non-zero interval and a timeout"));
+        }
+        let admission = &self.b2bua.codec_negotiation.admission;
+        if admission.enabled
+            && (admission.capacity == 0
+                || admission.prefer_relay_below_percent > 100
+                || admission.reject_below_percent > admission.prefer_relay_below_percent)
+        {
+            return Err(Error::invalid_config(
+                "Transcoding admission needs a non-zero capacity and a reject threshold at or below the relay threshold",
+            ));
+        }
+        if self.b2bua.call_trace.max_records_per_call == 0 {
+            return Err(Error::invalid_config("Call trace needs room for at least one record per call"));
+        }
+        if self.craft.enabled && (self.craft.device.is_empty() || self.craft.management_interface.is_empty()) {
+            return Err(Error::invalid_config("Craft console needs a serial device and management interface"));
+        }
+        let tunnel = &self.support_tunnel;
+        if tunnel.enabled {
+            if tunnel.default_duration_secs == 0 || tunnel.default_duration_secs > tunnel.max_duration_secs {
+                return Err(Error::invalid_config("Support tunnel default duration must be between 1 second and the maximum"));
+            }
+            let ready = match tunnel.method {
+                TunnelMethod::Ssh => !tunnel.concentrator.is_empty() && tunnel.remote_port != 0 && !tunnel.management_api.is_empty(),
+                TunnelMethod::WireGuard => !tunnel.wireguard_config_path.is_empty(),
+            };
+            if !ready {
+                return Err(Error::invalid_config(
+                    "Support tunnel needs a concentrator, remote port and management API for SSH, or a WireGuard configuration",
+                ));
+            }
+            if tunnel.method == TunnelMethod::Ssh && self.management_api.secret.is_none() {
+                return Err(Error::invalid_config(
+                    "SSH support tunnel forwards the management API and needs management_api.secret",
+                ));
+            }
+        }
+        let canary = &self.canary;
+        if canary.enabled {
+            if canary.registrar.is_empty() || canary.user.is_empty() || canary.destinations.is_empty() {
+                return Err(Error::invalid_config("Canary needs a registrar, a user and at least one destination"));
+            }
+            if canary.interval_secs == 0 || canary.call_duration_secs == 0 || canary.setup_timeout_secs == 0 {
+                return Err(Error::invalid_config("Canary interval, call duration and setup timeout must be non-zero"));
+            }
+            if canary.call_duration_secs + canary.setup_timeout_secs >= canary.interval_secs {
+                return Err(Error::invalid_config("Canary interval must be longer than a test call"));
+            }
+            if canary.failure_streak_alarm == 0 {
+                return Err(Error::invalid_config("Canary failure streak for alarms must be at least 1"));
+            }
+        }
+        if self.b2bua.upgrade_assist.enabled
+            && (self.b2bua.upgrade_assist.snapshot_path.is_empty() || self.b2bua.upgrade_assist.max_snapshot_age_secs == 0)
+        {
+            return Err(Error::invalid_config("Upgrade assist needs a snapshot path and a non-zero snapshot age"));
+        }
+        let registration = &self.b2bua.trunk_registration;
+        if registration.enabled {
+            let backoff = &registration.backoff;
+            if backoff.initial_retry_secs == 0
+                || backoff.max_retry_secs < backoff.initial_retry_secs
+                || backoff.multiplier < 1.0
+                || backoff.jitter_percent > 100
+                || registration.timeout_ms == 0
+            {
+                return Err(Error::invalid_config(
+                    "Trunk registration needs a non-zero timeout and retry, a cap at or above it, a multiplier of at least 1 and jitter up to 100%",
+                ));
+            }
+            for entry in &registration.trunks {
+                if entry.registrar.is_empty() || entry.user.is_empty() || entry.contact.is_empty() || entry.expires_secs == 0 {
+                    return Err(Error::invalid_config(format!(
+                        "Registration of trunk {} needs a registrar, user, contact and non-zero expiry",
+                        entry.trunk
+                    )));
+                }
+            }
+        }
+        let test_numbers = &self.b2bua.test_numbers;
+        if test_numbers.enabled {
+            if test_numbers.numbers.is_empty() || test_numbers.max_duration_secs == 0 {
+                return Err(Error::invalid_config("Test numbers need at least one number and a non-zero call limit"));
+            }
+            for (index, test_number) in test_numbers.numbers.iter().enumerate() {
+                if test_number.number.is_empty() || test_numbers.numbers[..index].iter().any(|other| other.number == test_number.number) {
+                    return Err(Error::invalid_config(format!("Test number {:?} is empty or listed twice", test_number.number)));
+                }
+            }
+        }
+        let paging = &self.b2bua.paging;
+        if paging.enabled {
+            if paging.numbers.is_empty() || paging.numbers.iter().any(String::is_empty) || paging.max_duration_secs == 0 {
+                return Err(Error::invalid_config("Paging needs at least one number and a non-zero page limit"));
+            }
+            if !is_multicast_group(&paging.group) {
+                return Err(Error::invalid_config(format!("Paging group {} is not an IPv4 multicast address:port", paging.group)));
+            }
+            for (index, zone) in paging.zones.iter().enumerate() {
+                if !(zone.digit.is_ascii_digit() || zone.digit == '*' || zone.digit == '#')
+                    || paging.zones[..index].iter().any(|other| other.digit == zone.digit)
+                {
+                    return Err(Error::invalid_config(format!("Paging zone {} needs its own digit, 0-9, * or #", zone.name)));
+                }
+                if !is_multicast_group(&zone.group) {
+                    return Err(Error::invalid_config(format!("Paging zone {} group {} is not an IPv4 multicast address:port", zone.name, zone.group)));
+                }
+            }
+        }
+        let inactivity = &self.b2bua.media_inactivity;
+        if inactivity.enabled && (inactivity.voice_secs == 0 || inactivity.fax_secs == 0 || inactivity.modem_secs == 0) {
+            return Err(Error::invalid_config("Media inactivity timers must be non-zero"));
+        }
+        let causes = &self.b2bua.release_causes;
+        if causes.enabled && (causes.retention_hours == 0 || causes.destination_digits == 0 || causes.top == 0) {
+            return Err(Error::invalid_config("Release cause retention, destination digits and top must be non-zero"));
+        }
+        let webrtc = &self.b2bua.webrtc;
+        if webrtc.enabled {
+            let valid_server = |server: &str| server.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if webrtc.stun_server.as_deref().is_some_and(|server| !valid_server(server)) {
+                return Err(Error::invalid_config("WebRTC STUN server must be host:port"));
+            }
+            if webrtc.stun_timeout_ms == 0 {
+                return Err(Error::invalid_config("WebRTC STUN timeout must be non-zero"));
+            }
+        }
+        let prompts = &self.b2bua.prompts;
+        if prompts.enabled {
+            if prompts.directory.is_empty() || !is_language_tag(&prompts.default_language) {
+                return Err(Error::invalid_config("Prompt packs need a directory and a valid default language"));
+            }
+            for rule in &prompts.selection {
+                if rule.trunk.is_none() && rule.tenant.is_none() {
+                    return Err(Error::invalid_config(format!("Prompt rule for {} needs a trunk or tenant", rule.language)));
+                }
+                if !is_language_tag(&rule.language) {
+                    return Err(Error::invalid_config(format!("Invalid prompt language {}", rule.language)));
+                }
+            }
+        }
+        if self.self_test.enabled && (self.self_test.loopback_duration_ms == 0 || self.self_test.timing_timeout_ms == 0) {
+            return Err(Error::invalid_config("Self-test loopback duration and timing timeout must be non-zero"));
+        }
+
+        // Validate time slots
+        for slot in &self.e1.time_slots {
+            if *slot == 0 || *slot > 31 {
+                return Err(Error::invalid_config("Invalid E1 time slot"));
+            }
+        }
+
+        for slot in &self.t1.time_slots {
+            if *slot == 0 || *slot > 24 {
+                return Err(Error::invalid_config("Invalid T1 time slot"));
+            }
+        }
+
+        // Validate codec configuration
         if self.trunk.codec.allowed_codecs.is_empty() {
-            return Err(Error::parse("No codecs configured"));
+            return Err(Error::invalid_config("No codecs configured"));
         }
 
         Ok(())
@@ -744,6 +4179,7 @@ impl GatewayConfig {
                 contact: "admin@redfire-gateway.local".to_string(),
                 max_calls: 1000,
                 call_timeout: 300,
+                control_socket: default_control_socket(),
             },
             tdmoe: TdmoeConfig {
                 interface: "eth0".to_string(),
@@ -774,11 +4210,22 @@ impl GatewayConfig {
                 max_sessions: 500,
                 session_timeout: 300,
                 register_interval: 3600,
+                udp_size_threshold: default_udp_size_threshold(),
+                tcp_fallback: default_tcp_fallback(),
+                force_rport: Vec::new(),
+                size_limits: SipSizeLimits::default(),
+                tls: SipTlsConfig::default(),
+                websocket: SipWebSocketConfig::default(),
+                registrar: SipRegistrarConfig::default(),
+                network: NetworkPlacement::default(),
             },
             rtp: RtpConfig {
                 port_range: PortRange { min: 10000, max: 20000 },
                 jitter_buffer_size: 50,
                 packet_timeout: 1000,
+                batching: RtpBatchingConfig::default(),
+                network: NetworkPlacement::default(),
+                rtcp: RtcpConfig::default(),
             },
             pri: PriConfig {
                 variant: PriVariant::Etsi,
@@ -799,6 +4246,10 @@ impl GatewayConfig {
                 enabled: false,
                 config_file: "/etc/freetdm.conf".to_string(),
                 spans: vec![],
+                dahdi_device_dir: default_dahdi_device_dir(),
+                recovery: SpanRecoveryConfig::default(),
+                hot_swap: HotSwapConfig::default(),
+                regulatory: RegulatoryConfig::default(),
             },
             trunk: TrunkConfig {
                 trunk_type: TrunkType::Voice,
@@ -915,6 +4366,8 @@ impl GatewayConfig {
                 port: 161,
                 bind_address: "0.0.0.0".to_string(),
                 version: SnmpVersion::V2c,
+                v3: SnmpV3Config::default(),
+                network: NetworkPlacement::default(),
             },
             testing: TestingConfig {
                 loopback: LoopbackConfig {
@@ -957,6 +4410,11 @@ impl GatewayConfig {
                         priority: 1,
                         translation: None,
                         codec_preference: vec!["g711u".to_string()],
+                        no_answer_timeout_secs: None,
+                        alternate_targets: Vec::new(),
+                        no_answer_divert: None,
+                        failover_actions: BTreeMap::new(),
+                        early_media: None,
                     },
                     RoutingRule {
                         id: "local".to_string(),
@@ -966,6 +4424,11 @@ impl GatewayConfig {
                         priority: 10,
                         translation: None,
                         codec_preference: vec!["g711a".to_string(), "g711u".to_string()],
+                        no_answer_timeout_secs: None,
+                        alternate_targets: Vec::new(),
+                        no_answer_divert: None,
+                        failover_actions: BTreeMap::new(),
+                        early_media: None,
                     },
                 ],
                 clustering: ClusteringConfig {
@@ -981,8 +4444,99 @@ impl GatewayConfig {
                         password: None,
                     },
                     consensus_algorithm: ConsensusAlgorithm::Raft,
+                    discovery: ClusterDiscoveryConfig::default(),
                 },
+                cps_shaping: CpsShapingConfig::default(),
+                cdr_custom_fields: vec![],
+                media_policies: vec![],
+                media_streams: MediaStreamsConfig::default(),
+                trunk_failure: TrunkFailureConfig::default(),
+                media_interfaces: vec![],
+                media_interface_rules: vec![],
+                default_media_interface: None,
+                routing_hook: RoutingHookConfig::default(),
+                survivability: SurvivabilityConfig::default(),
+                number_lookup: NumberLookupConfig::default(),
+                quality_alerting: QualityAlertingConfig::default(),
+                call_gapping: CallGappingConfig::default(),
+                reroute: RerouteConfig::default(),
+                takeover: TakeoverConfig::default(),
+                quirks: QuirksConfig::default(),
+                call_trace: CallTraceConfig::default(),
+                ring_groups: Vec::new(),
+                codec_negotiation: CodecNegotiationConfig::default(),
+                route_advertisement: RouteAdvertisementConfig::default(),
+                shadow_routing: ShadowRoutingConfig::default(),
+                upgrade_assist: UpgradeAssistConfig::default(),
+                prompts: PromptsConfig::default(),
+                test_numbers: TestNumbersConfig::default(),
+                trunk_registration: TrunkRegistrationConfig::default(),
+                paging: PagingConfig::default(),
+                media_inactivity: MediaInactivityConfig::default(),
+                release_causes: ReleaseCauseConfig::default(),
+                webrtc: WebRtcConfig::default(),
+                rtcp_xr: RtcpXrConfig::default(),
+                precedence: PrecedenceConfig::default(),
+                session_timers: SessionTimerConfig::default(),
+                reject_announcements: RejectAnnouncementConfig::default(),
+                reliable_provisionals: ReliableProvisionalConfig::default(),
+                transfer: TransferConfig::default(),
+                hold: CallHoldConfig::default(),
+                transcoding_latency: TranscodingLatencyConfig::default(),
+                fax: FaxRelayConfig::default(),
+                correlation: CorrelationConfig::default(),
             },
+            tandem: TandemConfig::default(),
+            certificates: CertificateConfig::default(),
+            profiling: ProfilingConfig::default(),
+            craft: CraftConsoleConfig::default(),
+            self_test: SelfTestConfig::default(),
+            support_tunnel: SupportTunnelConfig::default(),
+            canary: CanaryConfig::default(),
+            channel_history: ChannelHistoryConfig::default(),
+            time_sync: TimeSyncConfig::default(),
+            management_api: ManagementApiConfig::default(),
+            capacity: CapacityConfig::default(),
+            alarm_relays: AlarmRelayConfig::default(),
+            automation: AutomationConfig::default(),
+            campaigns: CampaignConfig::default(),
+            audio_tap: AudioTapConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_includes_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("common")).unwrap();
+        let base = toml::to_string(&GatewayConfig::default_config()).unwrap();
+        std::fs::write(dir.path().join("common/base.toml"), base).unwrap();
+        std::fs::write(
+            dir.path().join("common/region.toml"),
+            "include = [\"base.toml\"]\n[sip]\ndomain = \"east.example.com\"\nforce_rport = [\"10.0.0.1\"]\n",
+        )
+        .unwrap();
+        let site = dir.path().join("site.toml");
+        std::fs::write(
+            &site,
+            "include = [\"common/region.toml\"]\n[general]\nnode_id = \"gw-17\"\n[sip]\nforce_rport = []\n",
+        )
+        .unwrap();
+
+        let config = GatewayConfig::load_from_file(&site).unwrap();
+        assert_eq!(config.general.node_id, "gw-17");
+        assert_eq!(config.sip.domain, "east.example.com");
+        // Arrays replace rather than append
+        assert!(config.sip.force_rport.is_empty());
+        assert_eq!(config.sip.listen_port, 5060);
+        assert!(!GatewayConfig::load_merged(&site).unwrap().contains_key(INCLUDE_KEY));
+
+        std::fs::write(dir.path().join("common/base.toml"), "include = [\"region.toml\"]\n").unwrap();
+        assert!(GatewayConfig::load_from_file(&site).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Builder for embedding the gateway in another application
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::GatewayConfig;
+use crate::core::RedFireGateway;
+use crate::interfaces::{TdmBackend, TdmTransport};
+use crate::services::cdr::{BillingConfig, CdrStorage};
+use crate::Result;
+
+/// Assembles a [`RedFireGateway`] from programmatic configuration and
+/// optional injected components.
+///
+/// Anything not injected falls back to what the standalone binary uses: a
+/// TDMoE transport built from `[tdmoe]` and CDR files in `[tandem]
+/// cdr_directory`, if set.
+#[derive(Default)]
+pub struct GatewayBuilder {
+    config: Option<GatewayConfig>,
+    tdm_transport: Option<Box<dyn TdmTransport>>,
+    tdm_backends: HashMap<String, Box<dyn TdmBackend>>,
+    cdr_storage: Option<Arc<dyn CdrStorage>>,
+    billing_config: BillingConfig,
+}
+
+impl GatewayBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use an already-built configuration; defaults to `GatewayConfig::default_config()`
+    pub fn config(mut self, config: GatewayConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Load the configuration from a TOML file
+    pub fn config_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        self.config = Some(GatewayConfig::load_from_file(path)?);
+        Ok(self)
+    }
+
+    /// Replace the built-in TDMoE transport
+    pub fn tdm_transport<T: TdmTransport + 'static>(mut self, transport: T) -> Self {
+        self.tdm_transport = Some(Box::new(transport));
+        self
+    }
+
+    /// Drive the spans whose `backend` is `name` with the given driver
+    pub fn tdm_backend<B: TdmBackend + 'static>(mut self, name: impl Into<String>, backend: B) -> Self {
+        self.tdm_backends.insert(name.into(), Box::new(backend));
+        self
+    }
+
+    /// Record CDRs to the given sink
+    pub fn cdr_storage(mut self, storage: Arc<dyn CdrStorage>) -> Self {
+        self.cdr_storage = Some(storage);
+        self
+    }
+
+    /// Billing defaults applied to CDRs written to the injected sink
+    pub fn billing_config(mut self, billing_config: BillingConfig) -> Self {
+        self.billing_config = billing_config;
+        self
+    }
+
+    /// Validate the configuration and create the gateway; nothing is bound
+    /// or started until [`RedFireGateway::start`]
+    pub fn build(self) -> Result<RedFireGateway> {
+        let config = self.config.unwrap_or_else(GatewayConfig::default_config);
+        config.validate()?;
+
+        Ok(RedFireGateway::from_parts(
+            config,
+            self.tdm_transport,
+            self.tdm_backends,
+            self.cdr_storage.map(|storage| (storage, self.billing_config)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    use crate::interfaces::tdmoe::{TdmoeEvent, TdmoeFrame};
+
+    struct NullTransport;
+
+    #[async_trait]
+    impl TdmTransport for NullTransport {
+        fn name(&self) -> &str {
+            "null"
+        }
+
+        async fn start(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn stop(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TdmoeEvent>> {
+            None
+        }
+
+        async fn send_frame(&self, _frame: TdmoeFrame, _dest: Option<SocketAddr>) -> Result<()> {
+            Ok(())
+        }
+
+        fn active_channel_count(&self) -> u32 {
+            3
+        }
+    }
+
+    #[tokio::test]
+    async fn test_injected_transport_is_used() {
+        let gateway = RedFireGateway::builder()
+            .tdm_transport(NullTransport)
+            .build()
+            .unwrap();
+
+        let status = gateway.get_status().await;
+        assert_eq!(status.interfaces.tdmoe, "stopped");
+        assert_eq!(status.sessions.active_channels, 3);
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        let mut config = GatewayConfig::default_config();
+        config.rtp.port_range.min = config.rtp.port_range.max + 1;
+        assert!(RedFireGateway::builder().config(config).build().is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Control socket for the command line
+//!
+//! A running gateway listens on a Unix domain socket so that
+//! `redfire-gateway status` and `redfire-gateway stop` can reach it. Each
+//! connection carries one JSON request line and gets one JSON response line.
+//! Requests are handed to whoever owns the gateway, like craft console
+//! requests, and answered once carried out. The socket is only accessible
+//! to its owner and group, since anyone who can open it can stop the gateway.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::{Error, Result};
+
+/// How long either side waits for the other
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest request line read from a client
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Status,
+    /// Shut the gateway down gracefully
+    Stop,
+}
+
+/// One TDM span as seen by `status`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SpanSummary {
+    pub span_id: u32,
+    pub name: String,
+    pub up: bool,
+    pub channels: usize,
+    pub busy_channels: usize,
+    pub alarms: Vec<String>,
+}
+
+/// State of a running gateway
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ControlStatus {
+    pub node_id: String,
+    pub version: String,
+    pub running: bool,
+    pub uptime_secs: u64,
+    pub active_calls: u32,
+    pub active_channels: u32,
+    pub sip_sessions: u32,
+    pub rtp_sessions: u32,
+    pub spans: Vec<SpanSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Status(ControlStatus),
+    /// Shutdown has begun
+    Stopping,
+    Error { message: String },
+}
+
+/// A request received on the control socket, waiting for its response
+#[derive(Debug)]
+pub struct ControlCommand {
+    pub request: ControlRequest,
+    reply: oneshot::Sender<ControlResponse>,
+}
+
+impl ControlCommand {
+    pub fn respond(self, response: ControlResponse) {
+        // The client may have given up waiting
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Listener on the control socket; the socket file is removed on drop
+pub struct ControlServer {
+    path: PathBuf,
+    listener: UnixListener,
+    command_tx: mpsc::UnboundedSender<ControlCommand>,
+}
+
+impl ControlServer {
+    /// Listen on `path`, replacing a socket left behind by a gateway that
+    /// did not shut down cleanly
+    pub async fn bind(path: &Path) -> Result<(Self, mpsc::UnboundedReceiver<ControlCommand>)> {
+        if path.exists() {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(Error::invalid_state(format!("A gateway is already listening on {}", path.display())));
+            }
+            std::fs::remove_file(path)?;
+        }
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+        info!("Control socket listening on {}", path.display());
+
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        Ok((Self { path: path.to_path_buf(), listener, command_tx }, command_rx))
+    }
+
+    /// Accept clients until the task is aborted
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.listener.accept().await {
+                    Ok((stream, _)) => {
+                        let command_tx = self.command_tx.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve(stream, command_tx).await {
+                                debug!("Control client failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("Control socket accept failed: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn serve(stream: UnixStream, command_tx: mpsc::UnboundedSender<ControlCommand>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    tokio::time::timeout(CONTROL_TIMEOUT, BufReader::new(reader.take(MAX_REQUEST_BYTES)).read_line(&mut line))
+        .await
+        .map_err(|_| Error::timeout("No control request received"))??;
+
+    let response = match serde_json::from_str::<ControlRequest>(line.trim()) {
+        Ok(request) => {
+            let (reply, response) = oneshot::channel();
+            command_tx
+                .send(ControlCommand { request, reply })
+                .map_err(|_| Error::invalid_state("Gateway is no longer taking control requests"))?;
+            match tokio::time::timeout(CONTROL_TIMEOUT, response).await {
+                Ok(Ok(response)) => response,
+                Ok(Err(_)) => ControlResponse::Error { message: "Request was dropped".to_string() },
+                Err(_) => ControlResponse::Error { message: "Gateway did not answer in time".to_string() },
+            }
+        }
+        Err(e) => ControlResponse::Error { message: format!("Invalid control request: {}", e) },
+    };
+    write_line(&mut writer, &response).await
+}
+
+async fn write_line<W: AsyncWrite + Unpin, T: Serialize>(writer: &mut W, message: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Send `request` to the gateway listening on `path` and wait for its response
+pub async fn send_request(path: &Path, request: ControlRequest) -> Result<ControlResponse> {
+    let exchange = async {
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|e| Error::network(format!("No gateway listening on {}: {}", path.display(), e)))?;
+        let (reader, mut writer) = stream.into_split();
+        write_line(&mut writer, &request).await?;
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+        if line.is_empty() {
+            return Err(Error::network("Gateway closed the control connection"));
+        }
+        Ok::<ControlResponse, Error>(serde_json::from_str(line.trim())?)
+    };
+    tokio::time::timeout(CONTROL_TIMEOUT, exchange)
+        .await
+        .map_err(|_| Error::timeout(format!("Gateway on {} did not answer", path.display())))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_round_trip() {
+        let dir = std::env::temp_dir().join(format!("redfire-control-{:x}", rand::random::<u64>()));
+        let path = dir.join("gateway.sock");
+        let (server, mut command_rx) = ControlServer::bind(&path).await.unwrap();
+        assert!(ControlServer::bind(&path).await.is_err());
+        let task = server.spawn();
+
+        tokio::spawn(async move {
+            while let Some(command) = command_rx.recv().await {
+                let response = match command.request {
+                    ControlRequest::Status => ControlResponse::Status(ControlStatus {
+                        node_id: "gw-1".to_string(),
+                        version: "1.0".to_string(),
+                        running: true,
+                        uptime_secs: 42,
+                        active_calls: 3,
+                        active_channels: 3,
+                        sip_sessions: 3,
+                        rtp_sessions: 3,
+                        spans: Vec::new(),
+                    }),
+                    ControlRequest::Stop => ControlResponse::Stopping,
+                };
+                command.respond(response);
+            }
+        });
+
+        match send_request(&path, ControlRequest::Status).await.unwrap() {
+            ControlResponse::Status(status) => assert_eq!((status.uptime_secs, status.active_calls), (42, 3)),
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(send_request(&path, ControlRequest::Stop).await.unwrap(), ControlResponse::Stopping);
+        assert_eq!(serde_json::to_string(&ControlRequest::Stop).unwrap(), r#"{"command":"stop"}"#);
+
+        // A socket left behind by a dead gateway is replaced
+        task.abort();
+        let _ = task.await;
+        assert!(!path.exists());
+        std::fs::write(&path, b"").unwrap();
+        let (server, _command_rx) = ControlServer::bind(&path).await.unwrap();
+        drop(server);
+        assert!(send_request(&path, ControlRequest::Status).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
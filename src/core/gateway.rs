@@ -1,25 +1,58 @@
 //! Main gateway orchestrator for the Redfire Gateway
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
+use async_trait::async_trait;
 use tokio::sync::{mpsc, RwLock};
 use tokio::task::JoinHandle;
 use tracing::{error, info, warn};
 
-use crate::config::{GatewayConfig, PerformanceConfig, SnmpConfig};
-use crate::interfaces::{TdmoeInterface, FreeTdmInterface};
+use crate::config::{CraftConsoleConfig, GatewayConfig, PerformanceConfig};
+use crate::core::control::{ControlCommand, ControlServer, ControlStatus, SpanSummary};
+use crate::core::GatewayBuilder;
+use crate::interfaces::freetdm::ChannelState;
+use crate::interfaces::inventory::span_from_template;
+use crate::interfaces::{
+    HardwareInventory, InventoryChange, PendingChange, RecoveryAction, SpanRecovery, TdmoeInterface, TdmBackend,
+    TdmBackendSet, TdmTransport,
+};
 use crate::protocols::{SipHandler, RtpHandler};
+use crate::protocols::sip_time::SipTimeHeaders;
 use crate::services::{
     PerformanceMonitor, AlarmManager, TestingService, AutoDetectionService,
-    SnmpService, DebugService, InterfaceTestingService, TestAutomationService,
-    TimingService, TimingConfig,
+    DebugService, InterfaceTestingService, TestAutomationService,
+    TimingService, TimingConfig, TandemService, CertificateManager, ProfilingService,
+    CdrService, CraftConsole, CraftRequest, CraftSnapshot, SelfTest, SelfTestReport, PortDirectory,
+    SupportTunnel, CanaryMonitor, UdpCanaryAgent, ChannelHistory, MetricsRegistry,
+    CampaignScheduler, UdpCampaignDialer, AudioTap,
 };
+#[cfg(feature = "snmp")]
+use crate::config::SnmpConfig;
+#[cfg(feature = "snmp")]
+use crate::services::SnmpService;
 use crate::services::{
-    alarms::AlarmConfig, auto_detection::AutoDetectionConfig, debug::DebugConfig,
-    testing::TestingConfig,
+    alarms::{AlarmConfig, AlarmSeverity, AlarmSource, AlarmType}, auto_detection::AutoDetectionConfig,
+    cdr::{BillingConfig, CdrStorage, FileCdrStorage},
+    debug::DebugConfig, testing::TestingConfig,
+};
+use crate::services::craft;
+use crate::services::alarm_relays::AlarmRelays;
+use crate::services::automation::{AuditOutcome, AutomationEngine, ObservedSpan, ObservedState, RuleAction};
+use crate::services::capacity::{CapacityQuery, CapacityReport, CapacityStats};
+use crate::services::management_api::{
+    ActiveCallReport, AlarmReport, CdrQuery, CdrReport, ChannelReport, ClockSourceReport, ManagementSource,
+    RtpSessionReport, ServiceReport, SpanReport, TestSessionReport, TimingReport,
 };
-use crate::Result;
+use crate::services::metrics;
+use crate::services::tandem::{TandemCallState, TandemEvent, TdmChannel};
+use crate::services::trunk_failure::span_trunk_name;
+use crate::utils::TimeHealth;
+use crate::{Error, Result};
+
+/// Size at which a CDR file configured with `[tandem] cdr_directory` is rotated
+const CDR_FILE_ROTATION_MB: u64 = 100;
 
 /// Gateway status information
 #[derive(Debug, Clone)]
@@ -52,7 +85,10 @@ pub struct SessionStatus {
 }
 
 /// Gateway events
+///
+/// New variants may be added in minor releases; match with a wildcard arm.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum GatewayEvent {
     Started,
     Stopped,
@@ -60,6 +96,10 @@ pub enum GatewayEvent {
     InterfaceDown { interface: String },
     CallStarted { call_id: String },
     CallEnded { call_id: String },
+    /// Power-on self-test phase finished; `passed` is false when the gateway stays out of service
+    SelfTestCompleted { passed: bool, failures: usize },
+    /// A span came or went with its TDM card
+    InventoryChanged { change: InventoryChange },
     Error { message: String },
 }
 
@@ -68,8 +108,10 @@ pub struct RedFireGateway {
     config: GatewayConfig,
     
     // Interfaces
-    tdmoe_interface: Option<TdmoeInterface>,
-    freetdm_interface: Option<FreeTdmInterface>,
+    tdm_transport: Option<Box<dyn TdmTransport>>,
+    tdm_backends: Option<TdmBackendSet>,
+    /// Span drivers injected through the builder, consumed on start
+    custom_tdm_backends: HashMap<String, Box<dyn TdmBackend>>,
     
     // Protocol handlers
     sip_handler: Option<SipHandler>,
@@ -77,14 +119,57 @@ pub struct RedFireGateway {
     
     // Services
     performance_monitor: Option<PerformanceMonitor>,
-    alarm_manager: Option<AlarmManager>,
+    alarm_manager: Option<Arc<AlarmManager>>,
     testing_service: Option<TestingService>,
     auto_detection_service: Option<AutoDetectionService>,
+    #[cfg(feature = "snmp")]
     snmp_service: Option<SnmpService>,
     debug_service: Option<DebugService>,
     interface_testing_service: Option<InterfaceTestingService>,
     test_automation_service: Option<TestAutomationService>,
     timing_service: Option<TimingService>,
+    tandem_service: Option<Arc<TandemService>>,
+    /// Signaling the tandem service asks for, until taken by the embedder
+    tandem_rx: Option<mpsc::UnboundedReceiver<TandemEvent>>,
+    certificate_manager: Option<Arc<CertificateManager>>,
+    profiling_service: Option<Arc<ProfilingService>>,
+    cdr_storage: Option<(Arc<dyn CdrStorage>, BillingConfig)>,
+    cdr_service: Option<Arc<CdrService>>,
+    /// Serial craft console; kept across gateway restarts it initiates
+    craft_console: Option<Arc<CraftConsole>>,
+    craft_rx: Option<mpsc::UnboundedReceiver<CraftRequest>>,
+    craft_task: Option<JoinHandle<()>>,
+    /// Control socket for the command line; kept across restarts like the craft console
+    control_rx: Option<mpsc::UnboundedReceiver<ControlCommand>>,
+    control_task: Option<JoinHandle<()>>,
+    /// Remote support tunnel; kept across restarts so a remote engineer
+    /// restarting the gateway keeps access
+    support_tunnel: Option<Arc<SupportTunnel>>,
+    /// Synthetic test calls through the gateway's own trunks
+    canary: Option<Arc<CanaryMonitor>>,
+    /// Outbound notification campaigns; kept across restarts so campaigns
+    /// under way carry on
+    campaigns: Option<Arc<CampaignScheduler>>,
+    /// Raw channel audio taps
+    audio_tap: Option<Arc<AudioTap>>,
+    self_test_report: Option<SelfTestReport>,
+    /// Span and channel descriptions, editable through the provisioning API
+    port_directory: Arc<PortDirectory>,
+    /// Last calls on each B-channel, for troubleshooting one timeslot
+    channel_history: Option<Arc<ChannelHistory>>,
+    /// Busy channel samples behind the capacity planning reports
+    capacity: Option<Arc<CapacityStats>>,
+    last_capacity_sample: Option<std::time::Instant>,
+    /// Maintenance rules, built on start
+    automation: Option<Arc<AutomationEngine>>,
+    last_automation_run: Option<std::time::Instant>,
+    /// Prometheus metrics, refreshed on each scrape
+    metrics: Arc<MetricsRegistry>,
+    /// Holdover of the system clock, shared by timing, SIP and CDRs
+    time_health: Arc<TimeHealth>,
+    span_recovery: Arc<SpanRecovery>,
+    hardware_inventory: Arc<HardwareInventory>,
+    last_hardware_rescan: Option<std::time::Instant>,
     
     // Event handling
     event_tx: mpsc::UnboundedSender<GatewayEvent>,
@@ -100,29 +185,85 @@ pub struct RedFireGateway {
 
 impl RedFireGateway {
     pub fn new(config: GatewayConfig) -> Result<Self> {
+        Ok(Self::from_parts(config, None, HashMap::new(), None))
+    }
+
+    /// Builder for embedding with injected components
+    pub fn builder() -> GatewayBuilder {
+        GatewayBuilder::new()
+    }
+
+    pub(crate) fn from_parts(
+        config: GatewayConfig,
+        tdm_transport: Option<Box<dyn TdmTransport>>,
+        custom_tdm_backends: HashMap<String, Box<dyn TdmBackend>>,
+        cdr_storage: Option<(Arc<dyn CdrStorage>, BillingConfig)>,
+    ) -> Self {
         let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let cdr_storage = cdr_storage.or_else(|| {
+            let directory = config.tandem.cdr_directory.clone()?;
+            let storage: Arc<dyn CdrStorage> = Arc::new(FileCdrStorage::new(directory, CDR_FILE_ROTATION_MB));
+            Some((storage, BillingConfig::default()))
+        });
+        let port_directory = Arc::new(PortDirectory::new(&config.freetdm.spans));
+        let span_recovery = Arc::new(SpanRecovery::new(config.freetdm.recovery.clone()));
+        let channel_history = config
+            .channel_history
+            .enabled
+            .then(|| Arc::new(ChannelHistory::new(&config.channel_history)));
+        let capacity = config.capacity.enabled.then(|| Arc::new(CapacityStats::new(config.capacity.clone())));
+        let time_health = Arc::new(TimeHealth::new(Duration::from_secs(config.time_sync.max_holdover_secs)));
         
-        Ok(Self {
+        Self {
             config,
-            tdmoe_interface: None,
-            freetdm_interface: None,
+            tdm_transport,
+            tdm_backends: None,
+            custom_tdm_backends,
             sip_handler: None,
             rtp_handler: None,
             performance_monitor: None,
             alarm_manager: None,
             testing_service: None,
             auto_detection_service: None,
+            #[cfg(feature = "snmp")]
             snmp_service: None,
             debug_service: None,
             interface_testing_service: None,
             test_automation_service: None,
             timing_service: None,
+            tandem_service: None,
+            tandem_rx: None,
+            certificate_manager: None,
+            profiling_service: None,
+            cdr_storage,
+            cdr_service: None,
+            craft_console: None,
+            craft_rx: None,
+            craft_task: None,
+            control_rx: None,
+            control_task: None,
+            support_tunnel: None,
+            canary: None,
+            campaigns: None,
+            audio_tap: None,
+            self_test_report: None,
+            port_directory,
+            channel_history,
+            capacity,
+            last_capacity_sample: None,
+            automation: None,
+            last_automation_run: None,
+            metrics: Arc::new(MetricsRegistry::new()),
+            time_health,
+            span_recovery,
+            hardware_inventory: Arc::new(HardwareInventory::new()),
+            last_hardware_rescan: None,
             event_tx,
             event_rx: Some(event_rx),
             is_running: Arc::new(RwLock::new(false)),
             start_time: None,
             tasks: Vec::new(),
-        })
+        }
     }
 
     pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<GatewayEvent>> {
@@ -144,6 +285,14 @@ impl RedFireGateway {
         // Start all components
         self.start_components().await?;
         
+        // Loop spans back once the drivers are up
+        if self.config.self_test.enabled {
+            if let Err(e) = self.run_span_self_test().await {
+                self.stop().await?;
+                return Err(e);
+            }
+        }
+        
         // Setup event handling
         self.setup_event_handlers().await?;
         
@@ -162,24 +311,26 @@ impl RedFireGateway {
     async fn initialize_interfaces(&mut self) -> Result<()> {
         info!("Initializing interfaces");
         
-        // Initialize TDMoE interface
-        let tdmoe_config = crate::interfaces::tdmoe::TdmoeConfig {
-            interface: self.config.tdmoe.interface.clone(),
-            bind_port: 2427,
-            remote_addr: None,
-            channels: self.config.tdmoe.channels,
-            keepalive_interval: Duration::from_secs(30),
-            frame_timeout: Duration::from_secs(10),
-            max_retries: 3,
-        };
-        
-        let tdmoe_interface = TdmoeInterface::new(tdmoe_config).await?;
-        self.tdmoe_interface = Some(tdmoe_interface);
+        // Initialize TDMoE interface unless a transport was injected
+        if self.tdm_transport.is_none() {
+            let tdmoe_config = crate::interfaces::tdmoe::TdmoeConfig {
+                interface: self.config.tdmoe.interface.clone(),
+                bind_port: 2427,
+                remote_addr: None,
+                channels: self.config.tdmoe.channels,
+                keepalive_interval: Duration::from_secs(30),
+                frame_timeout: Duration::from_secs(10),
+                max_retries: 3,
+            };
+
+            let tdmoe_interface = TdmoeInterface::new(tdmoe_config).await?;
+            self.tdm_transport = Some(Box::new(tdmoe_interface));
+        }
         
-        // Initialize FreeTDM interface if enabled
+        // Initialize span drivers if TDM spans are enabled
         if self.config.freetdm.enabled {
-            let freetdm_interface = FreeTdmInterface::new(self.config.freetdm.clone())?;
-            self.freetdm_interface = Some(freetdm_interface);
+            let custom = std::mem::take(&mut self.custom_tdm_backends);
+            self.tdm_backends = Some(TdmBackendSet::new(&self.config.freetdm, custom)?);
         }
         
         info!("Interfaces initialized");
@@ -190,11 +341,17 @@ impl RedFireGateway {
         info!("Initializing protocol handlers");
         
         // Initialize SIP handler
-        let sip_handler = SipHandler::new(self.config.sip.clone()).await?;
+        let mut sip_handler = SipHandler::new(self.config.sip.clone()).await?;
+        sip_handler.set_time_headers(SipTimeHeaders::new(&self.config.time_sync, Arc::clone(&self.time_health)));
         self.sip_handler = Some(sip_handler);
         
         // Initialize RTP handler
-        let rtp_handler = RtpHandler::new(self.config.rtp.port_range.clone())?;
+        let mut rtp_handler = RtpHandler::with_batching(
+            self.config.rtp.port_range.clone(),
+            self.config.rtp.batching.clone(),
+        )?;
+        rtp_handler.set_network_placement(self.config.rtp.network.clone());
+        rtp_handler.set_rtcp(self.config.rtp.rtcp.clone());
         self.rtp_handler = Some(rtp_handler);
         
         info!("Protocol handlers initialized");
@@ -207,6 +364,7 @@ impl RedFireGateway {
         // Initialize Timing Service
         let timing_config = TimingConfig::default();
         let mut timing_service = TimingService::new(timing_config);
+        timing_service.set_time_health(Arc::clone(&self.time_health));
         timing_service.start().await?;
         self.timing_service = Some(timing_service);
         
@@ -218,7 +376,41 @@ impl RedFireGateway {
         // Initialize Alarm Manager
         let alarm_config = AlarmConfig::default();
         let alarm_manager = AlarmManager::new(alarm_config);
-        self.alarm_manager = Some(alarm_manager);
+        self.alarm_manager = Some(Arc::new(alarm_manager));
+        
+        // Open the control socket once, so `status` can reach a unit that
+        // fails its self-test
+        if self.control_task.is_none() {
+            match ControlServer::bind(&self.config.general.control_socket).await {
+                Ok((server, control_rx)) => {
+                    self.control_rx = Some(control_rx);
+                    self.control_task = Some(server.spawn());
+                }
+                Err(e) => warn!("Control socket {} unavailable: {}", self.config.general.control_socket.display(), e),
+            }
+        }
+
+        // Start the serial craft console once, ahead of the self-test so a
+        // unit that fails it can still be reached; it outlives restarts
+        if self.config.craft.enabled && self.craft_console.is_none() {
+            let mut craft_console = CraftConsole::new(self.config.craft.clone());
+            self.craft_rx = craft_console.take_event_receiver();
+            let craft_console = Arc::new(craft_console);
+            self.craft_task = Some(craft_console.spawn());
+            self.craft_console = Some(craft_console);
+
+            if let Some(ref address) = self.config.craft.management_address {
+                let craft = &self.config.craft;
+                if let Err(e) = craft::apply_management_address(&craft.management_interface, address, craft.management_gateway.as_deref()).await {
+                    warn!("Failed to apply management address {}: {}", address, e);
+                }
+            }
+        }
+        
+        // Power-on self-test before anything goes in service
+        if self.config.self_test.enabled {
+            self.run_self_test().await?;
+        }
         
         // Initialize Testing Service
         let testing_config = TestingConfig::default();
@@ -226,18 +418,28 @@ impl RedFireGateway {
         self.testing_service = Some(testing_service);
         
         // Initialize Auto Detection Service
-        let auto_detection_config = AutoDetectionConfig::default();
+        let auto_detection_config = AutoDetectionConfig {
+            configured_switch_type: self.config.pri.switch_type.clone(),
+            ..Default::default()
+        };
         let auto_detection_service = AutoDetectionService::new(auto_detection_config);
         self.auto_detection_service = Some(auto_detection_service);
         
         // Initialize SNMP Service
-        let snmp_config = SnmpConfig::default();
-        let snmp_service = SnmpService::new(snmp_config);
-        self.snmp_service = Some(snmp_service);
+        #[cfg(feature = "snmp")]
+        {
+            let snmp_config = SnmpConfig::default();
+            let snmp_service = SnmpService::new(snmp_config);
+            self.snmp_service = Some(snmp_service);
+        }
         
         // Initialize Debug Service
-        let debug_config = DebugConfig::default();
-        let debug_service = DebugService::new(debug_config);
+        let debug_config = DebugConfig {
+            correlation_header: self.config.b2bua.correlation.header.clone(),
+            ..DebugConfig::default()
+        };
+        let mut debug_service = DebugService::new(debug_config);
+        debug_service.set_port_directory(Arc::clone(&self.port_directory));
         self.debug_service = Some(debug_service);
         
         // Initialize Interface Testing Service
@@ -252,6 +454,84 @@ impl RedFireGateway {
             self.test_automation_service = Some(test_automation_service);
         }
         
+        // Initialize CDR recording to an injected sink
+        if let Some((storage, billing_config)) = self.cdr_storage.take() {
+            let mut cdr_service = CdrService::new(storage, billing_config);
+            cdr_service.set_node_id(&self.config.general.node_id);
+            cdr_service.set_time_health(Arc::clone(&self.time_health));
+            cdr_service.start().await?;
+            self.cdr_service = Some(Arc::new(cdr_service));
+        }
+        
+        // Initialize TDM tandem switching
+        if self.config.tandem.enabled {
+            let mut tandem_service = TandemService::new(
+                self.config.tandem.clone(),
+                &self.config.freetdm.spans,
+            )?;
+            if let Some(ref cdr_service) = self.cdr_service {
+                tandem_service.set_cdr_service(Arc::clone(cdr_service));
+            }
+            tandem_service.set_cdr_custom_fields(self.config.b2bua.cdr_custom_fields.clone());
+            tandem_service.set_port_directory(Arc::clone(&self.port_directory));
+            self.tandem_rx = tandem_service.take_event_receiver();
+            self.tandem_service = Some(Arc::new(tandem_service));
+        }
+        
+        // Initialize TLS certificate management
+        if self.config.certificates.enabled {
+            let mut certificate_manager = CertificateManager::new(self.config.certificates.clone());
+            if let Some(ref alarm_manager) = self.alarm_manager {
+                certificate_manager.set_alarm_manager(Arc::clone(alarm_manager));
+            }
+            certificate_manager.load_all().await?;
+            let certificate_manager = Arc::new(certificate_manager);
+            if let Some(ref mut sip) = self.sip_handler {
+                sip.set_certificate_manager(Arc::clone(&certificate_manager));
+            }
+            self.certificate_manager = Some(certificate_manager);
+        }
+        
+        // Initialize on-demand profiling for the management API
+        if self.config.profiling.enabled {
+            self.profiling_service = Some(Arc::new(ProfilingService::new(self.config.profiling.clone())));
+        }
+
+        // Support tunnel opened on request through the management API
+        if self.config.support_tunnel.enabled && self.support_tunnel.is_none() {
+            self.support_tunnel = Some(Arc::new(SupportTunnel::new(self.config.support_tunnel.clone())));
+        }
+
+        // Canary calls alarm on a run of failures
+        if self.config.canary.enabled {
+            let agent = Arc::new(UdpCanaryAgent::new(self.config.canary.clone()));
+            let mut canary = CanaryMonitor::new(self.config.canary.clone(), agent);
+            if let Some(ref alarm_manager) = self.alarm_manager {
+                canary.set_alarm_manager(Arc::clone(alarm_manager));
+            }
+            self.canary = Some(Arc::new(canary));
+        }
+
+        if self.config.campaigns.enabled && self.campaigns.is_none() {
+            let dialer = Arc::new(UdpCampaignDialer);
+            self.campaigns = Some(Arc::new(CampaignScheduler::new(self.config.campaigns.clone(), dialer)));
+        }
+
+        // Channel audio taps, fed by the span drivers
+        self.audio_tap = if self.config.audio_tap.enabled {
+            let mut audio_tap = AudioTap::new(self.config.audio_tap.clone(), &self.config.freetdm.spans);
+            if let Some(ref tandem) = self.tandem_service {
+                audio_tap.set_tandem_service(Arc::clone(tandem));
+            }
+            let audio_tap = Arc::new(audio_tap);
+            if let Some(ref mut backends) = self.tdm_backends {
+                backends.set_audio_sink(audio_tap.clone());
+            }
+            Some(audio_tap)
+        } else {
+            None
+        };
+        
         info!("Services initialized");
         Ok(())
     }
@@ -259,20 +539,20 @@ impl RedFireGateway {
     async fn start_components(&mut self) -> Result<()> {
         info!("Starting components");
         
-        // Start TDMoE interface
-        if let Some(ref mut tdmoe) = self.tdmoe_interface {
-            tdmoe.start().await?;
+        // Start TDM transport
+        if let Some(ref mut transport) = self.tdm_transport {
+            transport.start().await?;
             let _ = self.event_tx.send(GatewayEvent::InterfaceUp {
-                interface: "TDMoE".to_string(),
+                interface: transport.name().to_string(),
             });
         }
         
-        // Start FreeTDM interface
-        if let Some(ref mut freetdm) = self.freetdm_interface {
-            freetdm.start().await?;
-            if freetdm.is_running() {
+        // Start span drivers
+        if let Some(ref mut backends) = self.tdm_backends {
+            backends.start().await?;
+            for backend in backends.backends().filter(|backend| backend.is_running()) {
                 let _ = self.event_tx.send(GatewayEvent::InterfaceUp {
-                    interface: "FreeTDM".to_string(),
+                    interface: backend.name().to_string(),
                 });
             }
         }
@@ -296,6 +576,7 @@ impl RedFireGateway {
             auto_detection.start().await?;
         }
         
+        #[cfg(feature = "snmp")]
         if let Some(ref mut snmp) = self.snmp_service {
             snmp.start().await?;
         }
@@ -304,6 +585,34 @@ impl RedFireGateway {
             debug.start().await?;
         }
         
+        if let Some(ref certificates) = self.certificate_manager {
+            self.tasks.push(certificates.spawn_monitor());
+        }
+        
+        if let Some(ref tandem) = self.tandem_service {
+            self.tasks.push(tandem.spawn_failure_monitor());
+        }
+
+        if let Some(ref canary) = self.canary {
+            self.tasks.push(canary.spawn_monitor());
+        }
+
+        if self.config.alarm_relays.enabled {
+            if let Some(ref alarm_manager) = self.alarm_manager {
+                let relays = AlarmRelays::open(&self.config.alarm_relays)?;
+                self.tasks.push(relays.spawn(Arc::clone(alarm_manager)));
+            }
+        }
+
+        self.automation = if self.config.automation.enabled {
+            Some(Arc::new(AutomationEngine::new(self.config.automation.clone())?))
+        } else {
+            None
+        };
+        self.last_automation_run = None;
+        
+        self.refresh_craft_status().await;
+        
         info!("All components started");
         Ok(())
     }
@@ -311,9 +620,9 @@ impl RedFireGateway {
     async fn setup_event_handlers(&mut self) -> Result<()> {
         info!("Setting up event handlers");
         
-        // Handle TDMoE events
-        if let Some(ref mut tdmoe) = self.tdmoe_interface {
-            if let Some(mut event_rx) = tdmoe.take_event_receiver() {
+        // Handle TDM transport events
+        if let Some(ref mut transport) = self.tdm_transport {
+            if let Some(mut event_rx) = transport.take_event_receiver() {
                 let event_tx = self.event_tx.clone();
                 let task = tokio::spawn(async move {
                     while let Some(event) = event_rx.recv().await {
@@ -324,13 +633,27 @@ impl RedFireGateway {
             }
         }
         
-        // Handle FreeTDM events
-        if let Some(ref mut freetdm) = self.freetdm_interface {
-            if let Some(mut event_rx) = freetdm.take_event_receiver() {
+        // Handle span driver events
+        if let Some(ref mut backends) = self.tdm_backends {
+            for mut event_rx in backends.take_event_receivers() {
                 let event_tx = self.event_tx.clone();
+                let tandem = self.tandem_service.clone();
+                let ports = Arc::clone(&self.port_directory);
+                let recovery = Arc::clone(&self.span_recovery);
+                let inventory = Arc::clone(&self.hardware_inventory);
+                let history = self.channel_history.clone();
+                let audio_tap = self.audio_tap.clone();
                 let task = tokio::spawn(async move {
                     while let Some(event) = event_rx.recv().await {
-                        Self::handle_freetdm_event(event, &event_tx).await;
+                        recovery.on_event(&event, std::time::Instant::now());
+                        inventory.on_event(&event);
+                        if let Some(ref history) = history {
+                            history.on_event(&event);
+                        }
+                        if let Some(ref audio_tap) = audio_tap {
+                            audio_tap.on_event(&event);
+                        }
+                        Self::handle_freetdm_event(event, &event_tx, tandem.as_ref(), &ports).await;
                     }
                 });
                 self.tasks.push(task);
@@ -375,8 +698,7 @@ impl RedFireGateway {
         
         match event {
             TdmoeEvent::FrameReceived { frame, source: _ } => {
-                // Process TDMoE frame - in a real implementation, this would
-                // route the frame to the appropriate protocol handler
+                // No Q.931 runs on TDMoE D-channels yet; call control is on FreeTDM spans
                 tracing::trace!("Received TDMoE frame on channel {}", frame.channel);
             }
             TdmoeEvent::ChannelStateChanged { channel, active } => {
@@ -401,41 +723,98 @@ impl RedFireGateway {
     async fn handle_freetdm_event(
         event: crate::interfaces::freetdm::FreeTdmEvent,
         event_tx: &mpsc::UnboundedSender<GatewayEvent>,
+        tandem: Option<&Arc<TandemService>>,
+        ports: &PortDirectory,
     ) {
         use crate::interfaces::freetdm::FreeTdmEvent;
         
         match event {
-            FreeTdmEvent::IncomingCall { span_id, channel_id, calling_number, called_number } => {
+            FreeTdmEvent::IncomingCall { span_id, channel_id, calling_number, called_number, user_user } => {
                 info!("Incoming call on span {}, channel {}: {} -> {:?}", 
-                    span_id, channel_id, calling_number.unwrap_or_default(), called_number);
+                    span_id, channel_id, calling_number.clone().unwrap_or_default(), called_number);
+                
+                // Tandem-routed calls stay on the TDM side and never create SIP legs
+                if let (Some(tandem), Some(called)) = (tandem, called_number.as_deref()) {
+                    match tandem.handle_incoming_call(span_id, channel_id, calling_number, called).await {
+                        Ok(Some(call)) => {
+                            if let Err(e) = tandem.record_setup_fields(&call.id, user_user.as_deref()).await {
+                                warn!("Failed to record SETUP fields of tandem call {}: {}", call.id, e);
+                            }
+                            let _ = event_tx.send(GatewayEvent::CallStarted { call_id: call.id });
+                            return;
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!("Tandem routing failed on span {}, channel {} (cause {}): {}",
+                                span_id, channel_id, e.q850_cause(), e);
+                            tandem.reject_call(TdmChannel::new(span_id, channel_id), e.q850_cause());
+                            return;
+                        }
+                    }
+                }
                 
                 let call_id = format!("ftdm-{}-{}", span_id, channel_id);
                 let _ = event_tx.send(GatewayEvent::CallStarted { call_id });
             }
-            FreeTdmEvent::CallAnswered { span_id, channel_id } => {
+            FreeTdmEvent::CallAnswered { span_id, channel_id, .. } => {
                 info!("Call answered on span {}, channel {}", span_id, channel_id);
+                if let Some(tandem) = tandem {
+                    if let Err(e) = tandem.handle_answer(span_id, channel_id).await {
+                        error!("Tandem answer handling failed: {}", e);
+                    }
+                }
             }
             FreeTdmEvent::CallHangup { span_id, channel_id, cause } => {
                 info!("Call hangup on span {}, channel {} (cause: {})", span_id, channel_id, cause);
+                if let Some(tandem) = tandem {
+                    if let Err(e) = tandem.handle_hangup(span_id, channel_id, cause).await {
+                        error!("Tandem hangup handling failed: {}", e);
+                    }
+                }
                 
                 let call_id = format!("ftdm-{}-{}", span_id, channel_id);
                 let _ = event_tx.send(GatewayEvent::CallEnded { call_id });
             }
             FreeTdmEvent::Alarm { span_id, message, severity: _ } => {
-                warn!("FreeTDM alarm on span {}: {}", span_id, message);
+                let span = ports.span_label(span_id);
+                warn!("FreeTDM alarm on {}: {}", span, message);
                 let _ = event_tx.send(GatewayEvent::Error { 
-                    message: format!("FreeTDM span {}: {}", span_id, message) 
+                    message: format!("FreeTDM {}: {}", span, message) 
                 });
             }
             FreeTdmEvent::SpanUp { span_id } => {
-                info!("FreeTDM span {} is UP", span_id);
+                info!("FreeTDM {} is UP", ports.span_label(span_id));
+                if let Some(tandem) = tandem {
+                    let recovered = tandem.handle_span_up(span_id);
+                    if !recovered.is_empty() {
+                        info!("Recovered {} preserved tandem calls on span {}", recovered.len(), span_id);
+                    }
+                }
             }
             FreeTdmEvent::SpanDown { span_id } => {
-                warn!("FreeTDM span {} is DOWN", span_id);
+                let span = ports.span_label(span_id);
+                warn!("FreeTDM {} is DOWN", span);
+                if let Some(tandem) = tandem {
+                    if let Err(e) = tandem.handle_span_down(span_id).await {
+                        error!("Tandem span failure handling failed: {}", e);
+                    }
+                }
                 let _ = event_tx.send(GatewayEvent::InterfaceDown {
-                    interface: format!("FreeTDM-Span-{}", span_id),
+                    interface: format!("FreeTDM {}", span),
                 });
             }
+            FreeTdmEvent::DChannelUp { span_id } => {
+                info!("D-channel up on {}", ports.span_label(span_id));
+            }
+            FreeTdmEvent::DChannelDown { span_id } => {
+                warn!("D-channel down on {}", ports.span_label(span_id));
+            }
+            FreeTdmEvent::HardwareAdded { span_id, trunk_type, .. } => {
+                info!("TDM card with {:?} span {} inserted", trunk_type, span_id);
+            }
+            FreeTdmEvent::HardwareRemoved { span_id } => {
+                warn!("TDM card with {} removed", ports.span_label(span_id));
+            }
         }
     }
 
@@ -446,24 +825,42 @@ impl RedFireGateway {
         use crate::protocols::sip::SipEvent;
         
         match event {
-            SipEvent::IncomingCall { session_id, call_id, from, to, sdp: _ } => {
+            SipEvent::IncomingCall { session_id, call_id, from, to, .. } => {
                 info!("Incoming SIP call: {} ({} -> {})", call_id, from, to);
                 let _ = event_tx.send(GatewayEvent::CallStarted { call_id: session_id });
             }
-            SipEvent::CallRinging { session_id: _ } => {
+            SipEvent::CallRinging { .. } => {
                 // Call is ringing
             }
-            SipEvent::CallAnswered { session_id: _, sdp: _ } => {
+            SipEvent::SessionProgress { .. } => {
+                // Early media on an outgoing call
+            }
+            SipEvent::PrackReceived { session_id, .. } => {
+                tracing::debug!("SIP session {} acknowledged a reliable provisional", session_id);
+            }
+            SipEvent::ReferReceived { session_id, refer_to, .. } => {
+                info!("SIP session {} referred to {}", session_id, refer_to);
+            }
+            SipEvent::CallAnswered { .. } => {
                 info!("SIP call answered");
             }
             SipEvent::CallTerminated { session_id, reason } => {
                 info!("SIP call terminated: {}", reason);
                 let _ = event_tx.send(GatewayEvent::CallEnded { call_id: session_id });
             }
+            SipEvent::CallFailed { session_id: _, status_code, reason } => {
+                info!("SIP call failed: {} {}", status_code, reason);
+            }
+            SipEvent::SessionRefreshed { session_id, .. } => {
+                tracing::debug!("SIP session {} refreshed by peer", session_id);
+            }
+            SipEvent::RefreshResponse { session_id, status_code, .. } => {
+                tracing::debug!("SIP session {} refresh answered {}", session_id, status_code);
+            }
             SipEvent::DtmfReceived { session_id: _, digit, duration: _ } => {
                 tracing::debug!("DTMF received: {}", digit);
             }
-            SipEvent::RegistrationReceived { user, contact: _, expires: _ } => {
+            SipEvent::RegistrationReceived { user, .. } => {
                 info!("SIP registration received for user: {}", user);
             }
             SipEvent::Started { listen_address } => {
@@ -531,17 +928,21 @@ impl RedFireGateway {
             }
         }
         
-        if let Some(ref mut freetdm) = self.freetdm_interface {
-            if let Err(e) = freetdm.stop().await {
-                error!("Error stopping FreeTDM interface: {}", e);
+        if let Some(ref mut backends) = self.tdm_backends {
+            if let Err(e) = backends.stop().await {
+                error!("Error stopping TDM span drivers: {}", e);
             }
         }
         
-        if let Some(ref tdmoe) = self.tdmoe_interface {
-            if let Err(e) = tdmoe.stop().await {
-                error!("Error stopping TDMoE interface: {}", e);
+        if let Some(ref transport) = self.tdm_transport {
+            if let Err(e) = transport.stop().await {
+                error!("Error stopping {} transport: {}", transport.name(), e);
             }
         }
+
+        if let Some(ref audio_tap) = self.audio_tap {
+            audio_tap.stop_all("gateway stopped");
+        }
         
         let _ = self.event_tx.send(GatewayEvent::Stopped);
         info!("Redfire Gateway stopped");
@@ -559,9 +960,13 @@ impl RedFireGateway {
             .unwrap_or_default();
 
         let interfaces = InterfaceStatus {
-            tdmoe: if self.tdmoe_interface.is_some() { "running" } else { "disabled" }.to_string(),
-            freetdm: if let Some(ref freetdm) = self.freetdm_interface {
-                if freetdm.is_running() { "running" } else { "stopped" }.to_string()
+            tdmoe: match self.tdm_transport {
+                Some(_) if is_running => "running",
+                Some(_) => "stopped",
+                None => "disabled",
+            }.to_string(),
+            freetdm: if let Some(ref backends) = self.tdm_backends {
+                if backends.is_running() { "running" } else { "stopped" }.to_string()
             } else {
                 "disabled".to_string()
             },
@@ -573,7 +978,9 @@ impl RedFireGateway {
         };
 
         let sessions = SessionStatus {
-            active_calls: 0, // Would be calculated from actual sessions
+            active_calls: self.tandem_service.as_ref()
+                .map(|tandem| tandem.get_active_calls().len() as u32)
+                .unwrap_or(0),
             active_channels: self.get_active_channel_count().await,
             sip_sessions: self.sip_handler.as_ref()
                 .map(|h| h.get_active_session_count() as u32)
@@ -595,12 +1002,12 @@ impl RedFireGateway {
     async fn get_active_channel_count(&self) -> u32 {
         let mut count = 0;
         
-        if let Some(ref tdmoe) = self.tdmoe_interface {
-            count += tdmoe.get_active_channels().len() as u32;
+        if let Some(ref transport) = self.tdm_transport {
+            count += transport.active_channel_count();
         }
         
-        if let Some(ref freetdm) = self.freetdm_interface {
-            count += freetdm.get_active_channel_count();
+        if let Some(ref backends) = self.tdm_backends {
+            count += backends.active_channel_count();
         }
         
         count
@@ -610,6 +1017,560 @@ impl RedFireGateway {
         &self.config
     }
 
+    /// CDR service writing to the sink injected through the builder
+    pub fn get_cdr_service(&self) -> Option<Arc<CdrService>> {
+        self.cdr_service.clone()
+    }
+
+    /// Restart spans that are due for recovery and latch those out of
+    /// attempts; call periodically while the gateway runs
+    pub async fn poll_span_recovery(&self) {
+        for action in self.span_recovery.poll(std::time::Instant::now()) {
+            match action {
+                RecoveryAction::Restart { span_id, attempt } => {
+                    let span = self.port_directory.span_label(span_id);
+                    info!("Restarting {} (attempt {})", span, attempt);
+                    let Some(ref backends) = self.tdm_backends else {
+                        continue;
+                    };
+                    if let Err(e) = backends.restart_span(span_id).await {
+                        warn!("Restart of {} failed: {}", span, e);
+                    }
+                }
+                RecoveryAction::Latch { span_id, attempts } => {
+                    let span = self.port_directory.span_label(span_id);
+                    let _ = self.event_tx.send(GatewayEvent::Error {
+                        message: format!("FreeTDM {} out of service after {} restarts", span, attempts),
+                    });
+                    let Some(ref alarm_manager) = self.alarm_manager else {
+                        continue;
+                    };
+                    let result = alarm_manager.raise_alarm(
+                        AlarmSeverity::Critical,
+                        AlarmType::Equipment,
+                        AlarmSource {
+                            component: "span-recovery".to_string(),
+                            instance: span_id.to_string(),
+                            location: self.port_directory.span(span_id).and_then(|description| description.location),
+                        },
+                        format!("{} did not recover after {} restarts", span, attempts),
+                        None,
+                        Some("Span or D-channel repeatedly failed to come up".to_string()),
+                        Some("Check the line and far end, then clear the span to resume automatic recovery".to_string()),
+                    ).await;
+                    match result {
+                        Ok(alarm_id) => self.span_recovery.set_alarm(span_id, alarm_id),
+                        Err(e) => error!("Failed to raise span recovery alarm: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bring hot-swapped TDM hardware into line: rescan the backends when
+    /// due, give spans on new cards their template configuration and drop
+    /// spans whose card went away. Call periodically while the gateway runs.
+    pub async fn poll_hardware_changes(&mut self) -> Vec<InventoryChange> {
+        let hot_swap = self.config.freetdm.hot_swap.clone();
+        let Some(ref mut backends) = self.tdm_backends else {
+            return Vec::new();
+        };
+        if !hot_swap.enabled {
+            return Vec::new();
+        }
+
+        let now = std::time::Instant::now();
+        let rescan_due = self
+            .last_hardware_rescan
+            .map_or(true, |last| now.duration_since(last) >= Duration::from_secs(hot_swap.rescan_interval_secs));
+        if rescan_due {
+            self.last_hardware_rescan = Some(now);
+            for (owned, found) in backends.discover_spans() {
+                self.hardware_inventory.reconcile(&owned, &found);
+            }
+        }
+
+        let mut changes = Vec::new();
+        for pending in self.hardware_inventory.take_pending() {
+            let change = match pending {
+                PendingChange::Inserted(found) if !backends.has_span(found.span_id) => {
+                    let Some(span) = span_from_template(&found, &hot_swap.templates) else {
+                        warn!("No hot-swap template for {:?} span {}", found.trunk_type, found.span_id);
+                        continue;
+                    };
+                    if let Err(e) = backends.add_span(&span) {
+                        warn!("Cannot bring inserted span {} into service: {}", span.span_id, e);
+                        continue;
+                    }
+                    self.port_directory.add_span(&span);
+                    if let Some(ref tandem) = self.tandem_service {
+                        tandem.add_span(&span);
+                    }
+                    if let Some(ref audio_tap) = self.audio_tap {
+                        audio_tap.add_span(&span);
+                    }
+                    let change = InventoryChange::Added {
+                        span_id: span.span_id,
+                        trunk_type: span.trunk_type.clone(),
+                        backend: span.backend.clone(),
+                    };
+                    self.config.freetdm.spans.push(span);
+                    change
+                }
+                PendingChange::Removed(span_id) if backends.has_span(span_id) => {
+                    // Calls on the span get its failure handling before it goes
+                    if let Some(ref tandem) = self.tandem_service {
+                        if let Err(e) = tandem.handle_span_down(span_id).await {
+                            error!("Tandem span failure handling failed: {}", e);
+                        }
+                        tandem.remove_span(span_id);
+                    }
+                    if let Err(e) = backends.remove_span(span_id) {
+                        warn!("Cannot remove span {}: {}", span_id, e);
+                        continue;
+                    }
+                    self.span_recovery.forget(span_id);
+                    self.port_directory.remove_span(span_id);
+                    self.config.freetdm.spans.retain(|span| span.span_id != span_id);
+                    InventoryChange::Removed { span_id }
+                }
+                _ => continue,
+            };
+            info!("TDM inventory changed: {:?}", change);
+            let _ = self.event_tx.send(GatewayEvent::InventoryChanged { change: change.clone() });
+            changes.push(change);
+        }
+        changes
+    }
+
+    /// Manually clear a span latched by recovery, resuming automatic restarts
+    pub async fn clear_span_recovery(&self, span_id: u32, cleared_by: String) -> Result<()> {
+        let alarm_id = self.span_recovery.clear(span_id, std::time::Instant::now())?;
+        if let (Some(alarm_manager), Some(alarm_id)) = (&self.alarm_manager, alarm_id) {
+            alarm_manager.clear_alarm(&alarm_id, cleared_by).await?;
+        }
+        Ok(())
+    }
+
+    /// Span and channel descriptions backing the provisioning API's port view
+    pub fn get_port_directory(&self) -> Arc<PortDirectory> {
+        Arc::clone(&self.port_directory)
+    }
+
+    /// Profiling service backing the management API's pprof endpoints
+    pub fn get_profiling_service(&self) -> Option<Arc<ProfilingService>> {
+        self.profiling_service.clone()
+    }
+
+    /// Support tunnel backing the management API's tunnel endpoints
+    pub fn get_support_tunnel(&self) -> Option<Arc<SupportTunnel>> {
+        self.support_tunnel.clone()
+    }
+
+    /// Canary monitor backing the management API's canary endpoint
+    pub fn get_canary(&self) -> Option<Arc<CanaryMonitor>> {
+        self.canary.clone()
+    }
+
+    /// Campaign scheduler backing the management API's campaign endpoints
+    pub fn get_campaigns(&self) -> Option<Arc<CampaignScheduler>> {
+        self.campaigns.clone()
+    }
+
+    /// Audio taps backing the management API's tap endpoints
+    pub fn get_audio_tap(&self) -> Option<Arc<AudioTap>> {
+        self.audio_tap.clone()
+    }
+
+    /// Per-channel call history backing the management API's history endpoint
+    pub fn get_channel_history(&self) -> Option<Arc<ChannelHistory>> {
+        self.channel_history.clone()
+    }
+
+    /// Sample busy channels on every span for the capacity reports when the
+    /// sample interval has passed. Call periodically while the gateway runs.
+    pub fn poll_capacity(&mut self) {
+        let (Some(capacity), Some(backends)) = (&self.capacity, &self.tdm_backends) else {
+            return;
+        };
+        let now = std::time::Instant::now();
+        let interval = Duration::from_secs(self.config.capacity.sample_interval_secs);
+        if self.last_capacity_sample.is_some_and(|last| now.duration_since(last) < interval) {
+            return;
+        }
+        self.last_capacity_sample = Some(now);
+
+        let at = chrono::Utc::now();
+        for span in backends.span_statuses() {
+            let busy = span.channels.iter().filter(|channel| channel.state == ChannelState::InUse).count();
+            let trunk = if span.name.is_empty() { format!("span-{}", span.span_id) } else { span.name };
+            capacity.sample(&trunk, span.channels.len(), busy as u32, at);
+        }
+    }
+
+    /// Evaluate the maintenance rules when their interval has passed and
+    /// carry out the actions they call for. Call periodically while the
+    /// gateway runs.
+    pub async fn poll_automation(&mut self) {
+        let Some(automation) = self.automation.clone() else {
+            return;
+        };
+        let now = std::time::Instant::now();
+        if self.last_automation_run.is_some_and(|last| now.duration_since(last) < automation.evaluation_interval()) {
+            return;
+        }
+        self.last_automation_run = Some(now);
+
+        let state = ObservedState {
+            spans: self
+                .tdm_backends
+                .as_ref()
+                .map(|backends| backends.span_statuses())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|span| ObservedSpan {
+                    span_id: span.span_id,
+                    trunk: if span.name.is_empty() { span_trunk_name(span.span_id) } else { span.name },
+                    up: span.is_up,
+                })
+                .collect(),
+            alarms: match self.alarm_manager {
+                Some(ref alarm_manager) => alarm_manager.get_active_alarms().await.into_iter().map(|alarm| alarm.severity).collect(),
+                None => Vec::new(),
+            },
+        };
+        for firing in automation.evaluate(&state, now) {
+            let result = match firing.action {
+                RuleAction::BusyOut(span_id) => self.set_span_blocked(span_id, true),
+                RuleAction::Restore(span_id) => self.set_span_blocked(span_id, false),
+                RuleAction::RestartSpan(span_id) => match self.tdm_backends {
+                    Some(ref backends) => backends.restart_span(span_id).await,
+                    None => Err(Error::invalid_state("No TDM spans configured")),
+                },
+                RuleAction::Webhook(_) => {
+                    automation.send_webhook(firing.clone(), self.config.general.node_id.clone());
+                    continue;
+                }
+            };
+            let outcome = match result {
+                Ok(()) => AuditOutcome::Done,
+                Err(e) => AuditOutcome::Failed { error: e.to_string() },
+            };
+            automation.record(&firing, outcome);
+        }
+    }
+
+    /// Busy out a span, or return it to service
+    pub fn set_span_blocked(&mut self, span_id: u32, blocked: bool) -> Result<()> {
+        match self.tdm_backends {
+            Some(ref mut backends) => backends.set_span_blocked(span_id, blocked),
+            None => Err(Error::invalid_state("No TDM spans configured")),
+        }
+    }
+
+    /// Maintenance rules engine, for its audit trail
+    pub fn get_automation(&self) -> Option<Arc<AutomationEngine>> {
+        self.automation.clone()
+    }
+
+    /// Prometheus text exposition served on the scrape path, refreshed from
+    /// current status first
+    pub async fn render_metrics(&self) -> Result<String> {
+        let status = self.get_status().await;
+        self.metrics.set(metrics::UP, &[], if status.running { 1.0 } else { 0.0 });
+        self.metrics.set(metrics::UPTIME, &[], status.uptime.as_secs_f64());
+        self.metrics.set(metrics::ACTIVE_CHANNELS, &[], status.sessions.active_channels as f64);
+        self.metrics.set(metrics::SIP_SESSIONS, &[], status.sessions.sip_sessions as f64);
+        self.metrics.set(metrics::RTP_SESSIONS, &[], status.sessions.rtp_sessions as f64);
+
+        let current = match self.performance_monitor {
+            Some(ref performance) => performance.get_current_metrics().await,
+            None => None,
+        };
+        if let Some(current) = current {
+            self.metrics.set(metrics::CPU_USAGE, &[], current.cpu_usage as f64);
+            self.metrics.set(metrics::MEMORY_USAGE, &[], current.memory_usage);
+            self.metrics.set(metrics::LOAD_AVERAGE, &[], current.load_average);
+            self.metrics.set_total(metrics::NETWORK_ERRORS, &["in"], current.network_errors_in);
+            self.metrics.set_total(metrics::NETWORK_ERRORS, &["out"], current.network_errors_out);
+        }
+
+        if let Some(ref alarm_manager) = self.alarm_manager {
+            let alarms = alarm_manager.get_active_alarms().await;
+            for severity in [AlarmSeverity::Critical, AlarmSeverity::Major, AlarmSeverity::Minor, AlarmSeverity::Warning] {
+                let count = alarms.iter().filter(|alarm| alarm.severity == severity).count();
+                let label = format!("{:?}", severity).to_lowercase();
+                self.metrics.set(metrics::ACTIVE_ALARMS, &[&label], count as f64);
+            }
+        }
+
+        if let Some(ref canary) = self.canary {
+            for destination in canary.metrics() {
+                let labels = [destination.destination.as_str()];
+                self.metrics.set_total(metrics::CANARY_CALLS, &labels, destination.calls);
+                self.metrics.set_total(metrics::CANARY_FAILURES, &labels, destination.failures);
+                if let Some(pdd) = destination.average_pdd_ms {
+                    self.metrics.set(metrics::CANARY_PDD, &labels, pdd);
+                }
+                if let Some(mos) = destination.average_mos {
+                    self.metrics.set(metrics::CANARY_MOS, &labels, mos);
+                }
+            }
+        }
+
+        self.metrics.render()
+    }
+
+    /// Outcome of the last power-on self-test
+    pub fn get_self_test_report(&self) -> Option<&SelfTestReport> {
+        self.self_test_report.as_ref()
+    }
+
+    /// Self-test of TDM hardware, timing sources and certificates
+    async fn run_self_test(&mut self) -> Result<()> {
+        info!("Running power-on self-test");
+        self.self_test_report = None;
+        let self_test = SelfTest::new(self.config.self_test.clone());
+        let mut report = SelfTestReport::default();
+
+        if let Some(ref backends) = self.tdm_backends {
+            report.extend(self_test.check_hardware(backends));
+        }
+        report.extend(self_test.check_timing().await);
+        report.extend(self_test.check_certificates(&self.config.certificates, chrono::Utc::now()));
+        self.conclude_self_test(&self_test, report).await
+    }
+
+    async fn run_span_self_test(&mut self) -> Result<()> {
+        let self_test = SelfTest::new(self.config.self_test.clone());
+        let mut report = SelfTestReport::default();
+        if let Some(ref backends) = self.tdm_backends {
+            report.extend(self_test.check_spans(backends, &self.port_directory).await);
+        }
+        self.conclude_self_test(&self_test, report).await
+    }
+
+    /// Raise alarms for failed checks and refuse service on critical failures
+    async fn conclude_self_test(&mut self, self_test: &SelfTest, report: SelfTestReport) -> Result<()> {
+        if let Some(ref alarm_manager) = self.alarm_manager {
+            self_test.raise_alarms(&report, alarm_manager).await;
+        }
+        let blocking: Vec<String> = report
+            .blocking_failures(self_test.critical_checks())
+            .map(|failure| format!("{:?} {}: {}", failure.check, failure.subject, failure.detail))
+            .collect();
+        let _ = self.event_tx.send(GatewayEvent::SelfTestCompleted {
+            passed: blocking.is_empty(),
+            failures: report.failures().count(),
+        });
+        self.self_test_report.get_or_insert_with(SelfTestReport::default).extend(report.outcomes);
+
+        if blocking.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::hardware(format!("Self-test failed, not entering service: {}", blocking.join("; "))))
+        }
+    }
+
+    /// Requests received on the control socket; `status` is answered with
+    /// `control_status`, `stop` by stopping the gateway
+    pub fn take_control_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<ControlCommand>> {
+        self.control_rx.take()
+    }
+
+    /// Signaling requested by tandem switching, to be passed to
+    /// `handle_tandem_event` as it arrives
+    pub fn take_tandem_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TandemEvent>> {
+        self.tandem_rx.take()
+    }
+
+    /// Signal a tandem call on the spans it crosses
+    pub async fn handle_tandem_event(&self, event: TandemEvent) {
+        let (Some(tandem), Some(backends)) = (&self.tandem_service, &self.tdm_backends) else {
+            return;
+        };
+        match event {
+            TandemEvent::CallRouted { call_id, egress, translated_number, .. } => {
+                if let Err(e) = backends.place_call(egress.span_id, egress.channel_id, &translated_number).await {
+                    warn!("Tandem call {} could not be placed on {}: {}", call_id, egress, e);
+                    if let Err(e) = tandem.fail_call(&call_id, e.q850_cause()).await {
+                        error!("Failed to release tandem call {}: {}", call_id, e);
+                    }
+                }
+            }
+            TandemEvent::CrossConnected { call_id, ingress, .. } => {
+                if let Err(e) = backends.answer_call(ingress.span_id, ingress.channel_id).await {
+                    warn!("Tandem call {} could not be answered on {}: {}", call_id, ingress, e);
+                    if let Err(e) = tandem.fail_call(&call_id, e.q850_cause()).await {
+                        error!("Failed to release tandem call {}: {}", call_id, e);
+                    }
+                }
+            }
+            TandemEvent::CallReleased { call_id, cause, ingress, egress, hung_up } => {
+                for channel in [ingress, egress].into_iter().filter(|channel| Some(*channel) != hung_up) {
+                    if let Err(e) = backends.hangup_call(channel.span_id, channel.channel_id, cause).await {
+                        warn!("Tandem call {} could not be cleared on {}: {}", call_id, channel, e);
+                    }
+                }
+            }
+            TandemEvent::CallRejected { ingress, cause } => {
+                if let Err(e) = backends.hangup_call(ingress.span_id, ingress.channel_id, cause).await {
+                    warn!("Rejected tandem call could not be cleared on {}: {}", ingress, e);
+                }
+            }
+            TandemEvent::NoChannelAvailable { .. } | TandemEvent::SpanFailure { .. } | TandemEvent::Error { .. } => {}
+        }
+    }
+
+    /// Uptime, call counts and span states reported by the `status` command
+    pub async fn control_status(&self) -> ControlStatus {
+        let status = self.get_status().await;
+        let spans = self
+            .tdm_backends
+            .as_ref()
+            .map(|backends| backends.span_statuses())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|span| SpanSummary {
+                span_id: span.span_id,
+                name: span.name,
+                up: span.is_up,
+                channels: span.channels.len(),
+                busy_channels: span.channels.iter().filter(|channel| channel.state == ChannelState::InUse).count(),
+                alarms: span.alarms,
+            })
+            .collect();
+        ControlStatus {
+            node_id: self.config.general.node_id.clone(),
+            version: crate::VERSION.to_string(),
+            running: status.running,
+            uptime_secs: status.uptime.as_secs(),
+            active_calls: status.sessions.active_calls,
+            active_channels: status.sessions.active_channels,
+            sip_sessions: status.sessions.sip_sessions,
+            rtp_sessions: status.sessions.rtp_sessions,
+            spans,
+        }
+    }
+
+    /// Requests entered on the craft console, carried out with `handle_craft_request`
+    pub fn take_craft_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<CraftRequest>> {
+        self.craft_rx.take()
+    }
+
+    /// Publish current span, alarm and address status to the craft console
+    pub async fn refresh_craft_status(&self) {
+        let Some(ref craft_console) = self.craft_console else {
+            return;
+        };
+        let snapshot = CraftSnapshot {
+            node_id: self.config.general.node_id.clone(),
+            running: self.is_running().await,
+            uptime: self.start_time.map(|start| start.elapsed()).unwrap_or_default(),
+            spans: self
+                .tdm_backends
+                .as_ref()
+                .map(|backends| backends.span_statuses())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|mut span| {
+                    span.description = self.port_directory.span(span.span_id).unwrap_or_default();
+                    span
+                })
+                .collect(),
+            alarms: match self.alarm_manager {
+                Some(ref alarm_manager) => alarm_manager.get_active_alarms().await,
+                None => Vec::new(),
+            },
+            management_addresses: craft::interface_addresses(&self.config.craft.management_interface).await,
+        };
+        craft_console.publish(snapshot).await;
+    }
+
+    /// Carry out a craft console request; the new configuration is persisted to `config_path`
+    pub async fn handle_craft_request(&mut self, request: CraftRequest, config_path: Option<&std::path::Path>) -> Result<()> {
+        match request {
+            CraftRequest::SetManagementAddress { interface, address, gateway } => {
+                craft::apply_management_address(&interface, &address, gateway.as_deref()).await?;
+                self.config.craft.management_address = Some(address);
+                self.config.craft.management_gateway = gateway;
+            }
+            CraftRequest::RestartService { service } => {
+                self.restart_service(&service).await?;
+                return Ok(());
+            }
+            CraftRequest::FactoryReset => {
+                warn!("Restoring factory configuration");
+                let mut defaults = GatewayConfig::default_config();
+                // Keep the console reachable so the unit can be recommissioned
+                defaults.craft = CraftConsoleConfig {
+                    management_address: None,
+                    management_gateway: None,
+                    ..self.config.craft.clone()
+                };
+                self.config = defaults;
+                self.restart_service("gateway").await?;
+            }
+        }
+
+        if let Some(path) = config_path {
+            self.config.save_to_file(path)?;
+        }
+        self.refresh_craft_status().await;
+        Ok(())
+    }
+
+    /// Restart the whole gateway or one of its components
+    pub async fn restart_service(&mut self, service: &str) -> Result<()> {
+        info!("Restarting {}", service);
+        match service {
+            "gateway" => {
+                self.stop().await?;
+                self.start().await
+            }
+            "sip" => match self.sip_handler {
+                Some(ref mut sip) => {
+                    sip.stop().await?;
+                    sip.start().await
+                }
+                None => Err(Error::invalid_state("SIP handler is not running")),
+            },
+            "spans" => match self.tdm_backends {
+                Some(ref mut backends) => {
+                    backends.stop().await?;
+                    backends.start().await
+                }
+                None => Err(Error::invalid_state("No TDM spans configured")),
+            },
+            other => Err(Error::invalid_config(format!("Unknown service {}", other))),
+        }
+    }
+
+    /// Apply span settings the auto-detection service queued for automatic correction
+    pub async fn apply_detected_settings(&mut self) -> Result<usize> {
+        let Some(auto_detection) = &self.auto_detection_service else {
+            return Ok(0);
+        };
+        let pending = auto_detection.take_pending_settings();
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let mut new_config = self.config.clone();
+        let applied: Vec<_> = pending
+            .into_iter()
+            .filter(|suggestion| suggestion.apply_to(&mut new_config))
+            .collect();
+        self.reload_config(new_config).await?;
+
+        if let Some(auto_detection) = &self.auto_detection_service {
+            for suggestion in applied.iter().cloned() {
+                auto_detection.confirm_applied(suggestion);
+            }
+        }
+        Ok(applied.len())
+    }
+
     pub async fn reload_config(&mut self, new_config: GatewayConfig) -> Result<()> {
         info!("Reloading gateway configuration");
         
@@ -651,6 +1612,280 @@ impl Drop for RedFireGateway {
         for task in self.tasks.drain(..) {
             task.abort();
         }
+        if let Some(task) = self.control_task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Lowercase name of a unit variant, as the management API reports it
+fn variant_name<T: std::fmt::Debug>(value: &T) -> String {
+    let mut name = String::new();
+    for (i, c) in format!("{:?}", value).chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            name.push('_');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+#[async_trait]
+impl ManagementSource for tokio::sync::Mutex<RedFireGateway> {
+    async fn status(&self) -> ControlStatus {
+        self.lock().await.control_status().await
+    }
+
+    async fn spans(&self) -> Vec<SpanReport> {
+        let gateway = self.lock().await;
+        gateway
+            .tdm_backends
+            .as_ref()
+            .map(|backends| backends.span_statuses())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|span| SpanReport {
+                span_id: span.span_id,
+                name: span.name,
+                trunk_type: variant_name(&span.trunk_type),
+                up: span.is_up,
+                alarms: span.alarms,
+                channels: span
+                    .channels
+                    .into_iter()
+                    .map(|channel| ChannelReport {
+                        channel_id: channel.id,
+                        state: variant_name(&channel.state),
+                        enabled: channel.enabled,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    async fn active_calls(&self) -> Vec<ActiveCallReport> {
+        let gateway = self.lock().await;
+        let Some(ref tandem_service) = gateway.tandem_service else {
+            return Vec::new();
+        };
+        tandem_service
+            .get_active_calls()
+            .into_iter()
+            .filter(|call| call.state != TandemCallState::Released)
+            .map(|call| ActiveCallReport {
+                id: call.id,
+                route: call.route_id,
+                calling_number: call.calling_number,
+                called_number: call.called_number,
+                ingress: call.ingress.to_string(),
+                egress: call.egress.to_string(),
+                state: variant_name(&call.state),
+                duration_secs: call.created_at.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    async fn rtp_sessions(&self) -> Vec<RtpSessionReport> {
+        let gateway = self.lock().await;
+        let Some(ref rtp_handler) = gateway.rtp_handler else {
+            return Vec::new();
+        };
+        let mut sessions: Vec<RtpSessionReport> = rtp_handler
+            .get_all_sessions()
+            .into_iter()
+            .map(|session| RtpSessionReport {
+                local_port: session.local_port,
+                remote_addr: session.remote_addr.map(|addr| addr.to_string()),
+                payload_type: session.payload_type,
+                packets_sent: session.stats.packets_sent,
+                packets_received: session.stats.packets_received,
+                loss_pct: session.stats.loss_pct(),
+                jitter_ms: session.stats.jitter,
+                remote_loss_pct: session.stats.rtcp.remote_loss_pct(),
+                remote_jitter_ms: session.stats.rtcp.remote_jitter_ms(),
+                round_trip_ms: session.stats.rtcp.round_trip_ms,
+                rtcp_reports_sent: session.stats.rtcp.reports_sent,
+                rtcp_reports_received: session.stats.rtcp.reports_received,
+                id: session.id,
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.local_port);
+        sessions
+    }
+
+    async fn services(&self) -> Vec<ServiceReport> {
+        let gateway = self.lock().await;
+        let status = gateway.get_status().await;
+        let optional = |present: bool| if present { "running" } else { "disabled" }.to_string();
+        [
+            ("tdmoe", status.interfaces.tdmoe),
+            ("freetdm", status.interfaces.freetdm),
+            ("sip", status.protocols.sip),
+            ("rtp", status.protocols.rtp),
+            ("tandem", optional(gateway.tandem_service.is_some())),
+            ("cdr", optional(gateway.cdr_service.is_some())),
+            ("alarms", optional(gateway.alarm_manager.is_some())),
+            ("timing", optional(gateway.timing_service.is_some())),
+        ]
+        .into_iter()
+        .map(|(name, state)| ServiceReport { name: name.to_string(), state })
+        .collect()
+    }
+
+    async fn alarms(&self) -> Vec<AlarmReport> {
+        let gateway = self.lock().await;
+        let Some(ref alarm_manager) = gateway.alarm_manager else {
+            return Vec::new();
+        };
+        alarm_manager
+            .get_active_alarms()
+            .await
+            .into_iter()
+            .map(|alarm| AlarmReport {
+                id: alarm.id,
+                severity: variant_name(&alarm.severity),
+                alarm_type: variant_name(&alarm.alarm_type),
+                source: format!("{}/{}", alarm.source.component, alarm.source.instance),
+                description: alarm.description,
+                raised_time: alarm.raised_time,
+                acknowledged: alarm.acknowledged_time.is_some(),
+                event_count: alarm.event_count,
+            })
+            .collect()
+    }
+
+    async fn cdrs(&self, query: &CdrQuery, limit: usize) -> Result<Vec<CdrReport>> {
+        let cdr_service = match self.lock().await.cdr_service {
+            Some(ref cdr_service) => Arc::clone(cdr_service),
+            None => return Ok(Vec::new()),
+        };
+        let end_time = chrono::Utc::now();
+        let start_time = end_time - chrono::Duration::hours(i64::from(query.hours.unwrap_or(24)));
+        let mut filters = HashMap::new();
+        if let Some(ref caller) = query.caller {
+            filters.insert("caller".to_string(), caller.clone());
+        }
+        if let Some(ref callee) = query.callee {
+            filters.insert("callee".to_string(), callee.clone());
+        }
+
+        let mut cdrs: Vec<CdrReport> = cdr_service
+            .query_cdrs(start_time, end_time, filters)
+            .await?
+            .into_iter()
+            .filter(|cdr| query.caller.as_ref().map_or(true, |caller| &cdr.caller == caller))
+            .filter(|cdr| query.callee.as_ref().map_or(true, |callee| &cdr.callee == callee))
+            .filter(|cdr| query.correlation_id.is_none() || cdr.correlation_id == query.correlation_id)
+            .map(|cdr| CdrReport {
+                id: cdr.id,
+                call_id: cdr.call_id,
+                correlation_id: cdr.correlation_id,
+                caller: cdr.caller,
+                callee: cdr.callee,
+                start_time: cdr.start_time,
+                answer_time: cdr.answer_time,
+                end_time: cdr.end_time,
+                duration_secs: cdr.duration_seconds,
+                disconnect_reason: cdr.disconnect_reason.map(|reason| variant_name(&reason)),
+            })
+            .collect();
+        cdrs.sort_by(|a, b| b.start_time.cmp(&a.start_time));
+        cdrs.truncate(limit);
+        Ok(cdrs)
+    }
+
+    async fn timing(&self) -> Option<TimingReport> {
+        let gateway = self.lock().await;
+        let timing_service = gateway.timing_service.as_ref()?;
+        let mut sources: Vec<ClockSourceReport> = timing_service
+            .get_clock_sources()
+            .await
+            .into_values()
+            .map(|clock| ClockSourceReport {
+                source_id: clock.source_id,
+                source_type: clock.source_type.kind().to_string(),
+                stratum: variant_name(&clock.stratum_level),
+                active: clock.is_active,
+                holdover: clock.is_holdover,
+                last_sync: clock.last_sync,
+                phase_offset_ns: clock.phase_offset_ns,
+                frequency_offset_ppb: clock.frequency_offset_ppb,
+            })
+            .collect();
+        sources.sort_by(|a, b| a.source_id.cmp(&b.source_id));
+        Some(TimingReport {
+            running: timing_service.is_running(),
+            stratum: variant_name(&timing_service.get_stratum_level().await),
+            selected_source: timing_service.get_selected_clock().await,
+            sources,
+        })
+    }
+
+    async fn test_sessions(&self) -> Vec<TestSessionReport> {
+        let gateway = self.lock().await;
+        let Some(ref testing_service) = gateway.testing_service else {
+            return Vec::new();
+        };
+        let loopbacks = testing_service.get_active_loopback_tests().await.into_values().map(|test| TestSessionReport {
+            kind: "loopback".to_string(),
+            channel: test.channel,
+            status: variant_name(&test.status),
+            elapsed_secs: test.duration.as_secs(),
+            errors: test.packets_lost,
+            error_rate: if test.packets_sent > 0 { test.packets_lost as f64 / test.packets_sent as f64 } else { 0.0 },
+        });
+        let berts = testing_service.get_active_bert_tests().await.into_values().map(|test| TestSessionReport {
+            kind: "bert".to_string(),
+            channel: test.channel,
+            status: variant_name(&test.status),
+            elapsed_secs: test.duration.as_secs(),
+            errors: test.error_bits,
+            error_rate: test.bit_error_rate,
+        });
+        let mut sessions: Vec<TestSessionReport> = loopbacks.chain(berts).collect();
+        sessions.sort_by(|a, b| (a.channel, &a.kind).cmp(&(b.channel, &b.kind)));
+        sessions
+    }
+
+    async fn capacity(&self, query: &CapacityQuery) -> Option<CapacityReport> {
+        let gateway = self.lock().await;
+        Some(gateway.capacity.as_ref()?.report(query, chrono::Utc::now()))
+    }
+
+    async fn metrics(&self) -> Result<String> {
+        self.lock().await.render_metrics().await
+    }
+
+    async fn config(&self) -> Option<GatewayConfig> {
+        Some(self.lock().await.config.clone())
+    }
+
+    async fn support_tunnel(&self) -> Option<Arc<SupportTunnel>> {
+        self.lock().await.support_tunnel.clone()
+    }
+
+    async fn canary(&self) -> Option<Arc<CanaryMonitor>> {
+        self.lock().await.canary.clone()
+    }
+
+    async fn campaigns(&self) -> Option<Arc<CampaignScheduler>> {
+        self.lock().await.campaigns.clone()
+    }
+
+    async fn audio_tap(&self) -> Option<Arc<AudioTap>> {
+        self.lock().await.audio_tap.clone()
+    }
+
+    async fn port_directory(&self) -> Option<Arc<PortDirectory>> {
+        Some(Arc::clone(&self.lock().await.port_directory))
+    }
+
+    async fn channel_history(&self) -> Option<Arc<ChannelHistory>> {
+        self.lock().await.channel_history.clone()
+    }
+
+    async fn profiling(&self) -> Option<Arc<ProfilingService>> {
+        self.lock().await.profiling_service.clone()
     }
 }
 
@@ -675,4 +1910,27 @@ mod tests {
         assert!(!status.running);
         assert_eq!(status.uptime, Duration::ZERO);
     }
+
+    #[tokio::test]
+    async fn test_control_status() {
+        let gateway = RedFireGateway::new(GatewayConfig::default_config()).unwrap();
+
+        let status = gateway.control_status().await;
+        assert_eq!(status.node_id, "redfire-gateway-1");
+        assert_eq!(status.version, crate::VERSION);
+        assert!(!status.running);
+        assert_eq!((status.uptime_secs, status.active_calls), (0, 0));
+        assert!(status.spans.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_management_source() {
+        let gateway = tokio::sync::Mutex::new(RedFireGateway::new(GatewayConfig::default_config()).unwrap());
+
+        assert_eq!(ManagementSource::status(&gateway).await.node_id, "redfire-gateway-1");
+        assert!(gateway.active_calls().await.is_empty());
+        assert!(gateway.cdrs(&CdrQuery::default(), 10).await.unwrap().is_empty());
+        assert_eq!(variant_name(&ChannelState::OutOfService), "out_of_service");
+        assert_eq!(variant_name(&AlarmSeverity::Critical), "critical");
+    }
 }
\ No newline at end of file
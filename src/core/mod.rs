@@ -1,5 +1,10 @@
 //! Core gateway functionality
 
+pub mod builder;
+pub mod control;
 pub mod gateway;
+pub mod setup;
 
-pub use gateway::RedFireGateway;
\ No newline at end of file
+pub use builder::GatewayBuilder;
+pub use gateway::{GatewayEvent, GatewayStatus, RedFireGateway};
+pub use setup::{SetupAnswers, SetupWizard};
@@ -0,0 +1,333 @@
+//! Interactive first-time setup
+//!
+//! `redfire-gateway setup` asks an installer for the handful of settings a
+//! field turn-up needs: node ID, spans and their framing, the carrier SIP
+//! trunk, RTP ports and the timing source. Each answer is checked as it is
+//! given; everything else keeps its default. The result is validated as a
+//! whole and written as TOML with a comment in front of each section.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use crate::config::{
+    ChannelType, ClockSource, E1Framing, FreeTdmChannel, FreeTdmSpan, GatewayConfig, Layer1Type, RouteType,
+    RoutingRule, SignalingType, SipTransport, T1Framing,
+};
+use crate::{Error, Result};
+
+/// Comments written in front of the section headers the wizard fills in
+const SECTION_COMMENTS: &[(&str, &str)] = &[
+    ("[general]", "Node identity, used in CDR sequence numbers, SNMP and cluster messages"),
+    ("[e1]", "E1 line settings; clock_source is the timing source of the spans"),
+    ("[t1]", "T1 line settings; clock_source is the timing source of the spans"),
+    ("[sip]", "SIP signalling listener"),
+    ("[rtp]", "Media ports; the range must be open in any firewall in front of the gateway"),
+    ("[freetdm]", "TDM span drivers"),
+    ("[[freetdm.spans]]", "One span; channel 16 (E1) or 24 (T1) carries the D-channel for PRI"),
+    ("[b2bua]", "Call routing; rules are tried in order and the carrier trunk rule matches any number"),
+];
+
+/// Installer's answers, applied on top of the default configuration
+#[derive(Debug, Clone)]
+pub struct SetupAnswers {
+    pub node_id: String,
+    pub line_type: Layer1Type,
+    pub span_count: u32,
+    /// `crc4`/`no-crc4` for E1, `esf`/`d4` for T1
+    pub framing: String,
+    pub signaling: SignalingType,
+    pub clock_source: ClockSource,
+    pub sip_port: u16,
+    pub sip_transport: SipTransport,
+    pub sip_domain: String,
+    /// Carrier SIP trunk all calls from the spans are sent to, if any
+    pub carrier_trunk: Option<String>,
+    pub rtp_min: u16,
+    pub rtp_max: u16,
+}
+
+impl SetupAnswers {
+    /// Default configuration with the answers applied
+    pub fn to_config(&self) -> Result<GatewayConfig> {
+        let mut config = GatewayConfig::default_config();
+        config.general.node_id = self.node_id.clone();
+
+        match self.line_type {
+            Layer1Type::E1 => {
+                config.e1.framing = match self.framing.as_str() {
+                    "crc4" => E1Framing::Crc4,
+                    "no-crc4" => E1Framing::NoCrc4,
+                    other => return Err(Error::invalid_config(format!("Unknown E1 framing {}", other))),
+                };
+                config.e1.clock_source = self.clock_source.clone();
+            }
+            Layer1Type::T1 => {
+                config.t1.framing = match self.framing.as_str() {
+                    "esf" => T1Framing::Esf,
+                    "d4" => T1Framing::D4,
+                    other => return Err(Error::invalid_config(format!("Unknown T1 framing {}", other))),
+                };
+                config.t1.clock_source = self.clock_source.clone();
+            }
+        }
+
+        config.freetdm.enabled = true;
+        config.freetdm.spans = (1..=self.span_count).map(|span_id| self.span(span_id)).collect();
+        config.trunk.signaling = self.signaling.clone();
+
+        config.sip.listen_port = self.sip_port;
+        config.sip.transport = self.sip_transport.clone();
+        config.sip.domain = self.sip_domain.clone();
+        if let Some(trunk) = &self.carrier_trunk {
+            config.b2bua.routing_table.push(RoutingRule {
+                id: "carrier".to_string(),
+                pattern: String::new(),
+                route_type: RouteType::Trunk,
+                target: trunk.clone(),
+                priority: 100,
+                translation: None,
+                codec_preference: vec!["g711a".to_string(), "g711u".to_string()],
+                no_answer_timeout_secs: None,
+                alternate_targets: Vec::new(),
+                no_answer_divert: None,
+                failover_actions: BTreeMap::new(),
+                early_media: None,
+            });
+        }
+
+        config.rtp.port_range.min = self.rtp_min;
+        config.rtp.port_range.max = self.rtp_max;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn span(&self, span_id: u32) -> FreeTdmSpan {
+        let (channels, d_channel) = match self.line_type {
+            Layer1Type::E1 => (31, 16),
+            Layer1Type::T1 => (24, 24),
+        };
+        let pri = matches!(self.signaling, SignalingType::Pri);
+        FreeTdmSpan {
+            span_id,
+            name: format!("span{}", span_id),
+            trunk_type: self.line_type.clone(),
+            d_channel,
+            channels: (1..=channels)
+                .map(|id| FreeTdmChannel {
+                    id,
+                    channel_type: if pri && id == d_channel { ChannelType::DChannel } else { ChannelType::BChannel },
+                    enabled: true,
+                    signaling: self.signaling.clone(),
+                    description: Default::default(),
+                })
+                .collect(),
+            backend: "freetdm".to_string(),
+            description: Default::default(),
+            regulatory_pack: None,
+        }
+    }
+}
+
+/// Question-and-answer session over any line-based input and output
+pub struct SetupWizard<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> SetupWizard<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    pub fn run(&mut self) -> Result<SetupAnswers> {
+        writeln!(self.output, "Redfire Gateway setup. Press Enter to accept the value in brackets.")?;
+
+        let node_id = self.ask("Node ID", Some("redfire-gateway-1"), |answer| {
+            if answer.chars().any(char::is_whitespace) {
+                Err("must not contain spaces".to_string())
+            } else {
+                Ok(answer.to_string())
+            }
+        })?;
+
+        let line_type = self.ask("Span line type (e1, t1)", Some("e1"), |answer| match answer {
+            "e1" => Ok(Layer1Type::E1),
+            "t1" => Ok(Layer1Type::T1),
+            _ => Err("enter e1 or t1".to_string()),
+        })?;
+        let span_count = self.ask("Number of spans", Some("1"), |answer| match answer.parse::<u32>() {
+            Ok(count) if (1..=32).contains(&count) => Ok(count),
+            _ => Err("enter a number from 1 to 32".to_string()),
+        })?;
+        let framings: &[&str] = match line_type {
+            Layer1Type::E1 => &["crc4", "no-crc4"],
+            Layer1Type::T1 => &["esf", "d4"],
+        };
+        let framing = self.ask(&format!("Framing ({})", framings.join(", ")), Some(framings[0]), |answer| {
+            if framings.contains(&answer) {
+                Ok(answer.to_string())
+            } else {
+                Err(format!("enter one of {}", framings.join(", ")))
+            }
+        })?;
+        let signaling = self.ask("Signaling (pri, cas)", Some("pri"), |answer| match answer {
+            "pri" => Ok(SignalingType::Pri),
+            "cas" => Ok(SignalingType::Cas),
+            _ => Err("enter pri or cas".to_string()),
+        })?;
+        let clock_source = self.ask("Timing source (recovered, internal, external)", Some("recovered"), |answer| {
+            match answer {
+                "recovered" => Ok(ClockSource::Recovered),
+                "internal" => Ok(ClockSource::Internal),
+                "external" => Ok(ClockSource::External),
+                _ => Err("enter recovered, internal or external".to_string()),
+            }
+        })?;
+
+        let sip_port = self.ask("SIP listen port", Some("5060"), parse_port)?;
+        let sip_transport = self.ask("SIP transport (udp, tcp, tls)", Some("udp"), |answer| match answer {
+            "udp" => Ok(SipTransport::Udp),
+            "tcp" => Ok(SipTransport::Tcp),
+            "tls" => Ok(SipTransport::Tls),
+            _ => Err("enter udp, tcp or tls".to_string()),
+        })?;
+        let sip_domain = self.ask("SIP domain", Some("gateway.local"), |answer| Ok(answer.to_string()))?;
+        let carrier_trunk = self.ask("Carrier SIP trunk host[:port] (blank for none)", None, |answer| {
+            if answer.is_empty() {
+                Ok(None)
+            } else if answer.contains(char::is_whitespace) || answer.starts_with(':') {
+                Err("enter a host name or address, optionally with :port".to_string())
+            } else {
+                Ok(Some(answer.to_string()))
+            }
+        })?;
+
+        let rtp_min = self.ask("First RTP port", Some("10000"), |answer| match parse_port(answer)? {
+            port if port < 1024 || port % 2 != 0 => Err("enter an even port from 1024".to_string()),
+            port => Ok(port),
+        })?;
+        let rtp_max = self.ask("Last RTP port", Some("20000"), |answer| match parse_port(answer)? {
+            port if port <= rtp_min => Err(format!("enter a port above {}", rtp_min)),
+            port => Ok(port),
+        })?;
+
+        Ok(SetupAnswers {
+            node_id,
+            line_type,
+            span_count,
+            framing,
+            signaling,
+            clock_source,
+            sip_port,
+            sip_transport,
+            sip_domain,
+            carrier_trunk,
+            rtp_min,
+            rtp_max,
+        })
+    }
+
+    /// Yes/no question, for confirmations
+    pub fn confirm(&mut self, question: &str) -> Result<bool> {
+        self.ask(&format!("{} (yes, no)", question), Some("no"), |answer| match answer {
+            "yes" | "y" => Ok(true),
+            "no" | "n" => Ok(false),
+            _ => Err("enter yes or no".to_string()),
+        })
+    }
+
+    /// Ask until `parse` accepts the answer; an empty answer takes `default`
+    fn ask<T>(
+        &mut self,
+        question: &str,
+        default: Option<&str>,
+        parse: impl Fn(&str) -> std::result::Result<T, String>,
+    ) -> Result<T> {
+        loop {
+            match default {
+                Some(default) => write!(self.output, "{} [{}]: ", question, default)?,
+                None => write!(self.output, "{}: ", question)?,
+            }
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(Error::invalid_state("Setup aborted"));
+            }
+            let answer = match line.trim() {
+                "" => default.unwrap_or(""),
+                answer => answer,
+            };
+            match parse(answer) {
+                Ok(value) => return Ok(value),
+                Err(reason) => writeln!(self.output, "  Invalid answer: {}", reason)?,
+            }
+        }
+    }
+}
+
+fn parse_port(answer: &str) -> std::result::Result<u16, String> {
+    match answer.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err("enter a port from 1 to 65535".to_string()),
+    }
+}
+
+/// `config` as TOML, with a header and a comment in front of each section
+/// the wizard fills in
+pub fn render_commented_toml(config: &GatewayConfig) -> Result<String> {
+    let body = toml::to_string_pretty(config)
+        .map_err(|e| Error::internal(format!("Failed to serialize config: {}", e)))?;
+
+    let mut output = format!(
+        "# Redfire Gateway configuration, generated by `redfire-gateway setup` v{}\n\
+         # Sections not described here keep their defaults; see `redfire-gateway generate-config`.\n",
+        crate::VERSION
+    );
+    for line in body.lines() {
+        if let Some((_, comment)) = SECTION_COMMENTS.iter().find(|(header, _)| line == *header) {
+            output.push('\n');
+            output.push_str(&format!("# {}\n", comment));
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wizard_reprompts_and_builds_valid_config() {
+        let input = "\nt1\n40\n2\nd4\npri\ninternal\n\n\nsip.example.net\ncarrier.example.net:5080\n10001\n10000\n10100\n";
+        let mut output = Vec::new();
+        let answers = SetupWizard::new(input.as_bytes(), &mut output).run().unwrap();
+
+        let prompts = String::from_utf8(output).unwrap();
+        assert_eq!(prompts.matches("Invalid answer").count(), 2);
+        assert_eq!(answers.span_count, 2);
+        assert_eq!(answers.carrier_trunk.as_deref(), Some("carrier.example.net:5080"));
+
+        let config = answers.to_config().unwrap();
+        assert_eq!(config.freetdm.spans.len(), 2);
+        assert_eq!(config.freetdm.spans[1].channels.len(), 24);
+        assert!(matches!(config.t1.framing, T1Framing::D4));
+        assert!(matches!(config.t1.clock_source, ClockSource::Internal));
+        assert_eq!((config.rtp.port_range.min, config.rtp.port_range.max), (10000, 10100));
+        assert_eq!(config.b2bua.routing_table.last().unwrap().target, "carrier.example.net:5080");
+
+        let rendered = render_commented_toml(&config).unwrap();
+        assert!(rendered.contains("# One span;"));
+        let reloaded: GatewayConfig = toml::from_str(&rendered).unwrap();
+        assert_eq!(reloaded.general.node_id, "redfire-gateway-1");
+    }
+
+    #[test]
+    fn test_wizard_stops_at_end_of_input() {
+        let mut output = Vec::new();
+        assert!(SetupWizard::new("node-7\n".as_bytes(), &mut output).run().is_err());
+    }
+}
@@ -3,6 +3,70 @@
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Broad failure class used for retry decisions and cause mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// Network or socket failure reaching a peer
+    Transport,
+    /// Malformed or unexpected message
+    Protocol,
+    /// No route or unknown destination
+    Routing,
+    /// Out of ports, channels, call capacity or similar limits
+    ResourceExhausted,
+    /// Invalid or inconsistent configuration
+    Config,
+    /// TDM span, channel or interface failure
+    Hardware,
+    Timeout,
+    /// Operation not valid in the current state
+    State,
+    Unsupported,
+    Internal,
+}
+
+impl ErrorCategory {
+    /// Whether repeating the operation later may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCategory::Transport | ErrorCategory::Timeout | ErrorCategory::ResourceExhausted
+        )
+    }
+
+    /// SIP final response sent when a call fails with this category
+    pub fn sip_response(&self) -> (u16, &'static str) {
+        match self {
+            ErrorCategory::Transport => (503, "Service Unavailable"),
+            ErrorCategory::Protocol => (400, "Bad Request"),
+            ErrorCategory::Routing => (404, "Not Found"),
+            ErrorCategory::ResourceExhausted => (503, "Service Unavailable"),
+            ErrorCategory::Config => (500, "Server Internal Error"),
+            ErrorCategory::Hardware => (502, "Bad Gateway"),
+            ErrorCategory::Timeout => (504, "Server Time-out"),
+            ErrorCategory::State => (500, "Server Internal Error"),
+            ErrorCategory::Unsupported => (501, "Not Implemented"),
+            ErrorCategory::Internal => (500, "Server Internal Error"),
+        }
+    }
+
+    /// Q.850 cause used when a call fails with this category
+    pub fn q850_cause(&self) -> u16 {
+        match self {
+            ErrorCategory::Transport => 41,         // temporary failure
+            ErrorCategory::Protocol => 111,         // protocol error, unspecified
+            ErrorCategory::Routing => 3,            // no route to destination
+            ErrorCategory::ResourceExhausted => 34, // no circuit/channel available
+            ErrorCategory::Config => 63,            // service or option not available
+            ErrorCategory::Hardware => 38,          // network out of order
+            ErrorCategory::Timeout => 102,          // recovery on timer expiry
+            ErrorCategory::State => 101,            // message not compatible with call state
+            ErrorCategory::Unsupported => 79,       // service or option not implemented
+            ErrorCategory::Internal => 127,         // interworking, unspecified
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Configuration error: {0}")]
@@ -68,6 +132,18 @@ pub enum Error {
     #[error("Transcoding error: {0}")]
     Transcoding(String),
 
+    #[error("Routing error: {0}")]
+    Routing(String),
+
+    #[error("Resource exhausted: {0}")]
+    ResourceExhausted(String),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Hardware error: {0}")]
+    Hardware(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
@@ -81,14 +157,6 @@ impl Error {
         Self::Protocol(msg.into())
     }
 
-    pub fn sip<S: Into<String>>(msg: S) -> Self {
-        Self::Sip(msg.into())
-    }
-
-    pub fn tdm<S: Into<String>>(msg: S) -> Self {
-        Self::Tdm(msg.into())
-    }
-
     pub fn rtp<S: Into<String>>(msg: S) -> Self {
         Self::Rtp(msg.into())
     }
@@ -109,10 +177,6 @@ impl Error {
         Self::Parse(msg.into())
     }
 
-    pub fn b2bua<S: Into<String>>(msg: S) -> Self {
-        Self::B2bua(msg.into())
-    }
-
     pub fn clustering<S: Into<String>>(msg: S) -> Self {
         Self::Clustering(msg.into())
     }
@@ -124,4 +188,100 @@ impl Error {
     pub fn internal<S: Into<String>>(msg: S) -> Self {
         Self::Internal(msg.into())
     }
+
+    pub fn routing<S: Into<String>>(msg: S) -> Self {
+        Self::Routing(msg.into())
+    }
+
+    pub fn resource_exhausted<S: Into<String>>(msg: S) -> Self {
+        Self::ResourceExhausted(msg.into())
+    }
+
+    pub fn invalid_config<S: Into<String>>(msg: S) -> Self {
+        Self::InvalidConfig(msg.into())
+    }
+
+    pub fn hardware<S: Into<String>>(msg: S) -> Self {
+        Self::Hardware(msg.into())
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Network(_) => ErrorCategory::Transport,
+            Self::Io(e) => match e.kind() {
+                std::io::ErrorKind::TimedOut => ErrorCategory::Timeout,
+                std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => ErrorCategory::Protocol,
+                std::io::ErrorKind::Unsupported => ErrorCategory::Unsupported,
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => ErrorCategory::Config,
+                _ => ErrorCategory::Transport,
+            },
+            Self::Protocol(_)
+            | Self::Sip(_)
+            | Self::Rtp(_)
+            | Self::Snmp(_)
+            | Self::Parse(_)
+            | Self::Serialization(_) => ErrorCategory::Protocol,
+            Self::Routing(_) => ErrorCategory::Routing,
+            Self::ResourceExhausted(_) => ErrorCategory::ResourceExhausted,
+            Self::Config(_) | Self::InvalidConfig(_) => ErrorCategory::Config,
+            Self::Tdm(_) | Self::FreeTdm(_) | Self::Hardware(_) => ErrorCategory::Hardware,
+            Self::Timeout(_) => ErrorCategory::Timeout,
+            Self::InvalidState(_) | Self::B2bua(_) | Self::Clustering(_) => ErrorCategory::State,
+            Self::NotSupported(_) => ErrorCategory::Unsupported,
+            Self::Codec(_)
+            | Self::Transcoding(_)
+            | Self::Performance(_)
+            | Self::Alarm(_)
+            | Self::Test(_)
+            | Self::Internal(_) => ErrorCategory::Internal,
+        }
+    }
+
+    /// Whether repeating the failed operation may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // Local address problems do not clear up on retry
+            Self::Io(e) if matches!(e.kind(), std::io::ErrorKind::AddrNotAvailable | std::io::ErrorKind::AddrInUse) => false,
+            _ => self.category().is_retryable(),
+        }
+    }
+
+    /// SIP final response for a call failing with this error
+    pub fn sip_response(&self) -> (u16, &'static str) {
+        self.category().sip_response()
+    }
+
+    /// Q.850 cause for a call failing with this error
+    pub fn q850_cause(&self) -> u16 {
+        self.category().q850_cause()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_categories_and_retry() {
+        assert_eq!(Error::network("reset").category(), ErrorCategory::Transport);
+        assert!(Error::network("reset").is_retryable());
+        assert!(Error::timeout("no answer").is_retryable());
+        assert!(Error::resource_exhausted("no ports").is_retryable());
+        assert!(!Error::parse("bad SDP").is_retryable());
+        assert!(!Error::invalid_config("bad range").is_retryable());
+
+        let io = Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        assert_eq!(io.category(), ErrorCategory::Timeout);
+        let in_use = Error::from(std::io::Error::from(std::io::ErrorKind::AddrInUse));
+        assert!(!in_use.is_retryable());
+    }
+
+    #[test]
+    fn test_cause_mapping() {
+        assert_eq!(Error::routing("no match").sip_response(), (404, "Not Found"));
+        assert_eq!(Error::routing("no match").q850_cause(), 3);
+        assert_eq!(Error::resource_exhausted("no circuit").q850_cause(), 34);
+        assert_eq!(Error::resource_exhausted("no circuit").sip_response().0, 503);
+        assert_eq!(Error::hardware("span down").q850_cause(), 38);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,631 @@
+//! TDM driver abstraction
+//!
+//! Span and channel I/O goes through [`TdmBackend`] so hardware FreeTDM does
+//! not support can still be used. Each span names its backend in config:
+//! `freetdm` (default), `dahdi`, or a backend registered through
+//! [`GatewayBuilder::tdm_backend`](crate::core::GatewayBuilder::tdm_backend)
+//! such as a userspace xHFC or Zaptel-over-IP driver.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::config::{FreeTdmConfig, FreeTdmSpan, Layer1Type, RegulatoryPack};
+use crate::interfaces::freetdm::{ChannelInfo, ChannelState, FreeTdmEvent, FreeTdmInterface, SpanStatus};
+use crate::interfaces::inventory::DiscoveredSpan;
+use crate::interfaces::regulatory::RegulatoryPacks;
+use crate::utils::ClockStamp;
+use crate::{Error, Result};
+
+/// Which way bearer audio was travelling when a driver handed it over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioDirection {
+    /// Read from the line
+    Receive,
+    /// Written to the line
+    Transmit,
+}
+
+/// Receiver of raw 64 kbit/s channel audio as drivers read and write it,
+/// before any transcoding
+pub trait ChannelAudioSink: Send + Sync {
+    /// Whether audio of the channel is wanted; checked before each frame
+    /// is handed over so untapped channels cost a lookup
+    fn wants(&self, span_id: u32, channel_id: u8) -> bool;
+
+    /// G.711 octets of the channel as carried on the span
+    fn audio(&self, span_id: u32, channel_id: u8, direction: AudioDirection, octets: &[u8]);
+}
+
+/// Driver for one or more TDM spans
+#[async_trait]
+pub trait TdmBackend: Send + Sync {
+    /// Name reported in interface up/down events
+    fn name(&self) -> &str;
+
+    /// Take over a configured span; called before `start`, or while
+    /// running for a span found on a hot-inserted card
+    fn add_span(&mut self, span: &FreeTdmSpan) -> Result<()>;
+
+    /// Give up a span whose card was removed
+    fn remove_span(&mut self, span_id: u32) -> Result<()> {
+        Err(Error::not_supported(format!("{} cannot remove span {}", self.name(), span_id)))
+    }
+
+    /// Program the country's tones, ring cadence, disconnect supervision and
+    /// CLIP into `span_id`; called after `add_span`. Drivers with no line-side
+    /// settings ignore it
+    fn apply_regulatory_pack(&mut self, span_id: u32, pack: &RegulatoryPack) -> Result<()> {
+        let _ = (span_id, pack);
+        Ok(())
+    }
+
+    async fn start(&mut self) -> Result<()>;
+
+    async fn stop(&mut self) -> Result<()>;
+
+    fn is_running(&self) -> bool;
+
+    /// Span and call events; taken once by the gateway on start
+    fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<FreeTdmEvent>>;
+
+    async fn place_call(&self, span_id: u32, channel_id: u8, called_number: &str) -> Result<()>;
+
+    async fn answer_call(&self, span_id: u32, channel_id: u8) -> Result<()>;
+
+    async fn hangup_call(&self, span_id: u32, channel_id: u8, cause: u16) -> Result<()>;
+
+    fn span_statuses(&self) -> Vec<SpanStatus>;
+
+    fn active_channel_count(&self) -> u32;
+
+    /// Card model and firmware; an error means the card is missing
+    fn probe_hardware(&self) -> Result<HardwareInfo> {
+        Ok(HardwareInfo::default())
+    }
+
+    /// Loop `span_id` back locally for `duration`; `Ok(true)` when the
+    /// looped signal came back intact
+    async fn loopback_test(&self, span_id: u32, duration: Duration) -> Result<bool> {
+        let _ = duration;
+        Err(Error::not_supported(format!("{} cannot loop back span {}", self.name(), span_id)))
+    }
+
+    /// Take `span_id` down and bring it back up, re-running D-channel
+    /// establishment; the outcome arrives as span and D-channel events
+    async fn restart_span(&self, span_id: u32) -> Result<()> {
+        Err(Error::not_supported(format!("{} cannot restart span {}", self.name(), span_id)))
+    }
+
+    /// Busy out `span_id`, marking its idle channels blocked so no new calls
+    /// are placed on them, or return them to service; calls in progress are
+    /// left to finish
+    fn set_span_blocked(&mut self, span_id: u32, blocked: bool) -> Result<()> {
+        let _ = blocked;
+        Err(Error::not_supported(format!("{} cannot busy out span {}", self.name(), span_id)))
+    }
+
+    /// Spans present on the installed cards right now
+    fn discover_spans(&self) -> Result<Vec<DiscoveredSpan>> {
+        Err(Error::not_supported(format!("{} cannot rescan its hardware", self.name())))
+    }
+
+    /// Hand bearer audio of wanted channels to `sink`; drivers that never
+    /// see bearer audio ignore it
+    fn set_audio_sink(&mut self, sink: Arc<dyn ChannelAudioSink>) {
+        let _ = sink;
+    }
+}
+
+/// Hardware identity reported by a TDM driver
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HardwareInfo {
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+}
+
+/// Look up a channel that can take a new call
+pub(crate) fn idle_channel(spans: &HashMap<u32, SpanStatus>, span_id: u32, channel_id: u8) -> Result<&ChannelInfo> {
+    let span = spans.get(&span_id)
+        .ok_or_else(|| Error::hardware(format!("Span {} not found", span_id)))?;
+
+    let channel = span.channels.iter()
+        .find(|ch| ch.id == channel_id)
+        .ok_or_else(|| Error::hardware(format!("Channel {} not found on span {}", channel_id, span_id)))?;
+
+    if channel.state != ChannelState::Idle {
+        return Err(Error::resource_exhausted(format!("Channel {}/{} is not idle", span_id, channel_id)));
+    }
+    Ok(channel)
+}
+
+/// Block or unblock the idle channels of a span
+pub(crate) fn block_channels(spans: &mut HashMap<u32, SpanStatus>, span_id: u32, blocked: bool) -> Result<()> {
+    let span = spans.get_mut(&span_id)
+        .ok_or_else(|| Error::hardware(format!("Span {} not found", span_id)))?;
+    let (from, to) = if blocked {
+        (ChannelState::Idle, ChannelState::Blocked)
+    } else {
+        (ChannelState::Blocked, ChannelState::Idle)
+    };
+    for channel in span.channels.iter_mut().filter(|ch| ch.state == from) {
+        channel.state = to.clone();
+    }
+    Ok(())
+}
+
+/// Driver version exported by the dahdi kernel module
+const DAHDI_VERSION_PATH: &str = "/sys/module/dahdi/version";
+/// One `span-<n>` directory per span registered with DAHDI
+const DAHDI_SPANS_PATH: &str = "/sys/bus/dahdi_spans/devices";
+
+/// Span described by a DAHDI sysfs `span-<n>` directory
+fn read_dahdi_span(dir: &std::path::Path) -> Option<DiscoveredSpan> {
+    let span_id = dir.file_name()?.to_str()?.strip_prefix("span-")?.parse().ok()?;
+    let attribute = |name: &str| std::fs::read_to_string(dir.join(name)).ok().map(|value| value.trim().to_string());
+    let trunk_type = match attribute("spantype")?.as_str() {
+        "E1" => Layer1Type::E1,
+        "T1" | "J1" => Layer1Type::T1,
+        _ => return None,
+    };
+    Some(DiscoveredSpan {
+        span_id,
+        trunk_type,
+        channels: attribute("channels")?.parse().ok()?,
+        backend: Some("dahdi".to_string()),
+        name: attribute("name"),
+    })
+}
+
+/// DAHDI (formerly Zaptel) kernel driver backend
+pub struct DahdiBackend {
+    device_dir: PathBuf,
+    spans: HashMap<u32, SpanStatus>,
+    event_tx: mpsc::UnboundedSender<FreeTdmEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<FreeTdmEvent>>,
+    is_running: bool,
+}
+
+impl DahdiBackend {
+    pub fn new(device_dir: impl Into<PathBuf>) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            device_dir: device_dir.into(),
+            spans: HashMap::new(),
+            event_tx,
+            event_rx: Some(event_rx),
+            is_running: false,
+        }
+    }
+
+    /// Per-channel device node, `<dir>/chan/<span>/<channel>`
+    fn channel_path(&self, span_id: u32, channel_id: u8) -> PathBuf {
+        self.device_dir.join("chan").join(span_id.to_string()).join(channel_id.to_string())
+    }
+
+    fn ensure_running(&self) -> Result<()> {
+        if self.is_running {
+            Ok(())
+        } else {
+            Err(Error::invalid_state("DAHDI backend not running"))
+        }
+    }
+}
+
+#[async_trait]
+impl TdmBackend for DahdiBackend {
+    fn name(&self) -> &str {
+        "DAHDI"
+    }
+
+    fn add_span(&mut self, span: &FreeTdmSpan) -> Result<()> {
+        self.spans.insert(span.span_id, SpanStatus::from_config(span));
+        Ok(())
+    }
+
+    fn remove_span(&mut self, span_id: u32) -> Result<()> {
+        self.spans
+            .remove(&span_id)
+            .map(|_| ())
+            .ok_or_else(|| Error::hardware(format!("Span {} not found", span_id)))
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        self.probe_hardware()?;
+
+        // Span configuration itself is owned by dahdi_cfg; channel I/O
+        // opens the per-channel nodes on demand
+        info!("DAHDI backend started with {} spans", self.spans.len());
+        for span in self.spans.values_mut() {
+            span.is_up = true;
+            let _ = self.event_tx.send(FreeTdmEvent::SpanUp { span_id: span.span_id });
+        }
+
+        self.is_running = true;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.is_running = false;
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<FreeTdmEvent>> {
+        self.event_rx.take()
+    }
+
+    async fn place_call(&self, span_id: u32, channel_id: u8, called_number: &str) -> Result<()> {
+        self.ensure_running()?;
+        idle_channel(&self.spans, span_id, channel_id)?;
+
+        let path = self.channel_path(span_id, channel_id);
+        if !path.exists() {
+            return Err(Error::hardware(format!("DAHDI channel {} not found", path.display())));
+        }
+
+        info!("Placing call on DAHDI channel {} to {}", path.display(), called_number);
+        Ok(())
+    }
+
+    async fn answer_call(&self, span_id: u32, channel_id: u8) -> Result<()> {
+        self.ensure_running()?;
+        let _ = self.event_tx.send(FreeTdmEvent::CallAnswered { span_id, channel_id, received: ClockStamp::now() });
+        Ok(())
+    }
+
+    async fn hangup_call(&self, span_id: u32, channel_id: u8, cause: u16) -> Result<()> {
+        self.ensure_running()?;
+        let _ = self.event_tx.send(FreeTdmEvent::CallHangup { span_id, channel_id, cause });
+        Ok(())
+    }
+
+    fn span_statuses(&self) -> Vec<SpanStatus> {
+        self.spans.values().cloned().collect()
+    }
+
+    fn set_span_blocked(&mut self, span_id: u32, blocked: bool) -> Result<()> {
+        block_channels(&mut self.spans, span_id, blocked)
+    }
+
+    fn probe_hardware(&self) -> Result<HardwareInfo> {
+        let ctl = self.device_dir.join("ctl");
+        if !ctl.exists() {
+            return Err(Error::hardware(format!(
+                "DAHDI control device {} not found; is the dahdi module loaded?",
+                ctl.display()
+            )));
+        }
+        let firmware = std::fs::read_to_string(DAHDI_VERSION_PATH).ok().map(|version| version.trim().to_string());
+        Ok(HardwareInfo { model: None, firmware })
+    }
+
+    fn discover_spans(&self) -> Result<Vec<DiscoveredSpan>> {
+        let mut spans: Vec<DiscoveredSpan> = std::fs::read_dir(DAHDI_SPANS_PATH)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| read_dahdi_span(&entry.path()))
+            .collect();
+        spans.sort_by_key(|span| span.span_id);
+        Ok(spans)
+    }
+
+    fn active_channel_count(&self) -> u32 {
+        self.spans.values()
+            .flat_map(|span| &span.channels)
+            .filter(|ch| ch.state == ChannelState::InUse)
+            .count() as u32
+    }
+}
+
+/// The backends serving all configured spans, with calls dispatched by span
+pub struct TdmBackendSet {
+    backends: Vec<Box<dyn TdmBackend>>,
+    /// Configured backend name of each entry in `backends`
+    backend_names: Vec<String>,
+    span_owner: HashMap<u32, usize>,
+    regulatory: RegulatoryPacks,
+    /// Pack applied to each span, where one is selected
+    span_packs: HashMap<u32, RegulatoryPack>,
+}
+
+impl TdmBackendSet {
+    /// Assign every configured span to its backend. `custom` backends are
+    /// matched by name and take precedence over the built-in drivers.
+    pub fn new(config: &FreeTdmConfig, mut custom: HashMap<String, Box<dyn TdmBackend>>) -> Result<Self> {
+        let mut backends: Vec<Box<dyn TdmBackend>> = Vec::new();
+        let mut backend_names = Vec::new();
+        let mut by_name: HashMap<&str, usize> = HashMap::new();
+        let mut span_owner = HashMap::new();
+        let regulatory = RegulatoryPacks::new(&config.regulatory)?;
+        let mut span_packs = HashMap::new();
+
+        // Drivers named by hot-swap templates run even before they have a
+        // span, so cards inserted later have somewhere to go
+        let template_backends = config
+            .hot_swap
+            .templates
+            .iter()
+            .filter(|_| config.hot_swap.enabled)
+            .map(|template| (template.backend.as_str(), None));
+        let span_backends = config.spans.iter().map(|span| (span.backend.as_str(), Some(span.span_id)));
+
+        for (name, span_id) in span_backends.chain(template_backends) {
+            if by_name.contains_key(name) {
+                continue;
+            }
+            let backend: Box<dyn TdmBackend> = match (custom.remove(name), name) {
+                (Some(backend), _) => backend,
+                (None, "freetdm") => Box::new(FreeTdmInterface::new(FreeTdmConfig {
+                    spans: Vec::new(),
+                    ..config.clone()
+                })?),
+                (None, "dahdi") => Box::new(DahdiBackend::new(&config.dahdi_device_dir)),
+                (None, name) => {
+                    return Err(Error::invalid_config(match span_id {
+                        Some(span_id) => format!("Span {} uses unknown TDM backend {}", span_id, name),
+                        None => format!("Hot-swap template uses unknown TDM backend {}", name),
+                    }));
+                }
+            };
+            backends.push(backend);
+            backend_names.push(name.to_string());
+            by_name.insert(name, backends.len() - 1);
+        }
+
+        for span in &config.spans {
+            let slot = by_name[span.backend.as_str()];
+            backends[slot].add_span(span)?;
+            if span_owner.insert(span.span_id, slot).is_some() {
+                return Err(Error::invalid_config(format!("Span {} is configured twice", span.span_id)));
+            }
+            if let Some(pack) = regulatory.for_span(span)? {
+                backends[slot].apply_regulatory_pack(span.span_id, pack)?;
+                span_packs.insert(span.span_id, pack.clone());
+            }
+        }
+
+        Ok(Self { backends, backend_names, span_owner, regulatory, span_packs })
+    }
+
+    pub fn backends(&self) -> impl Iterator<Item = &dyn TdmBackend> {
+        self.backends.iter().map(|backend| backend.as_ref())
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        for backend in &mut self.backends {
+            backend.start().await?;
+        }
+        Ok(())
+    }
+
+    /// Stop every backend, reporting the first failure
+    pub async fn stop(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for backend in &mut self.backends {
+            if let Err(e) = backend.stop().await {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.backends.iter().any(|backend| backend.is_running())
+    }
+
+    pub fn take_event_receivers(&mut self) -> Vec<mpsc::UnboundedReceiver<FreeTdmEvent>> {
+        self.backends.iter_mut().filter_map(|backend| backend.take_event_receiver()).collect()
+    }
+
+    /// Backend driving `span_id`
+    pub fn backend_for_span(&self, span_id: u32) -> Result<&dyn TdmBackend> {
+        self.span_owner
+            .get(&span_id)
+            .map(|&slot| self.backends[slot].as_ref())
+            .ok_or_else(|| Error::hardware(format!("Span {} not found", span_id)))
+    }
+
+    pub async fn place_call(&self, span_id: u32, channel_id: u8, called_number: &str) -> Result<()> {
+        self.backend_for_span(span_id)?.place_call(span_id, channel_id, called_number).await
+    }
+
+    pub async fn answer_call(&self, span_id: u32, channel_id: u8) -> Result<()> {
+        self.backend_for_span(span_id)?.answer_call(span_id, channel_id).await
+    }
+
+    pub async fn hangup_call(&self, span_id: u32, channel_id: u8, cause: u16) -> Result<()> {
+        self.backend_for_span(span_id)?.hangup_call(span_id, channel_id, cause).await
+    }
+
+    pub async fn loopback_test(&self, span_id: u32, duration: Duration) -> Result<bool> {
+        self.backend_for_span(span_id)?.loopback_test(span_id, duration).await
+    }
+
+    pub async fn restart_span(&self, span_id: u32) -> Result<()> {
+        self.backend_for_span(span_id)?.restart_span(span_id).await
+    }
+
+    pub fn set_span_blocked(&mut self, span_id: u32, blocked: bool) -> Result<()> {
+        let slot = *self
+            .span_owner
+            .get(&span_id)
+            .ok_or_else(|| Error::hardware(format!("Span {} not found", span_id)))?;
+        self.backends[slot].set_span_blocked(span_id, blocked)
+    }
+
+    /// Hand bearer audio from every backend to `sink`
+    pub fn set_audio_sink(&mut self, sink: Arc<dyn ChannelAudioSink>) {
+        for backend in &mut self.backends {
+            backend.set_audio_sink(Arc::clone(&sink));
+        }
+    }
+
+    /// Country pack applied to `span_id`, for tones the gateway plays itself
+    pub fn regulatory_pack(&self, span_id: u32) -> Option<&RegulatoryPack> {
+        self.span_packs.get(&span_id)
+    }
+
+    pub fn has_span(&self, span_id: u32) -> bool {
+        self.span_owner.contains_key(&span_id)
+    }
+
+    /// Spans found by each backend that can rescan its hardware, with the
+    /// spans that backend currently drives
+    pub fn discover_spans(&self) -> Vec<(Vec<u32>, Vec<DiscoveredSpan>)> {
+        self.backends
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, backend)| {
+                let found = backend.discover_spans().ok()?;
+                let owned = self.span_owner.iter().filter(|(_, &owner)| owner == slot).map(|(&span_id, _)| span_id).collect();
+                Some((owned, found))
+            })
+            .collect()
+    }
+
+    /// Hand a hot-inserted span to the running backend it names
+    pub fn add_span(&mut self, span: &FreeTdmSpan) -> Result<()> {
+        if self.span_owner.contains_key(&span.span_id) {
+            return Err(Error::invalid_config(format!("Span {} is configured twice", span.span_id)));
+        }
+        let slot = self
+            .backend_names
+            .iter()
+            .position(|name| *name == span.backend)
+            .ok_or_else(|| Error::invalid_state(format!(
+                "No {} driver is running to take span {}",
+                span.backend, span.span_id
+            )))?;
+        let pack = self.regulatory.for_span(span)?.cloned();
+        self.backends[slot].add_span(span)?;
+        if let Some(pack) = pack {
+            self.backends[slot].apply_regulatory_pack(span.span_id, &pack)?;
+            self.span_packs.insert(span.span_id, pack);
+        }
+        self.span_owner.insert(span.span_id, slot);
+        Ok(())
+    }
+
+    /// Drop a span whose card was removed
+    pub fn remove_span(&mut self, span_id: u32) -> Result<()> {
+        let slot = *self
+            .span_owner
+            .get(&span_id)
+            .ok_or_else(|| Error::hardware(format!("Span {} not found", span_id)))?;
+        self.backends[slot].remove_span(span_id)?;
+        self.span_owner.remove(&span_id);
+        self.span_packs.remove(&span_id);
+        Ok(())
+    }
+
+    pub fn span_statuses(&self) -> Vec<SpanStatus> {
+        self.backends.iter().flat_map(|backend| backend.span_statuses()).collect()
+    }
+
+    pub fn active_channel_count(&self) -> u32 {
+        self.backends.iter().map(|backend| backend.active_channel_count()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelType, FreeTdmChannel, Layer1Type, SignalingType};
+
+    fn span(span_id: u32, backend: &str) -> FreeTdmSpan {
+        FreeTdmSpan {
+            span_id,
+            name: format!("span{}", span_id),
+            trunk_type: Layer1Type::T1,
+            d_channel: 24,
+            channels: (1..=23)
+                .map(|id| FreeTdmChannel {
+                    id,
+                    channel_type: ChannelType::BChannel,
+                    enabled: true,
+                    signaling: SignalingType::Pri,
+                    description: Default::default(),
+                })
+                .collect(),
+            backend: backend.to_string(),
+            description: Default::default(),
+            regulatory_pack: None,
+        }
+    }
+
+    fn config(spans: Vec<FreeTdmSpan>) -> FreeTdmConfig {
+        FreeTdmConfig {
+            enabled: true,
+            config_file: "/etc/freetdm.conf".to_string(),
+            spans,
+            dahdi_device_dir: "/nonexistent/dahdi".to_string(),
+            recovery: Default::default(),
+            hot_swap: Default::default(),
+            regulatory: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spans_dispatch_to_their_backend() {
+        let mut custom: HashMap<String, Box<dyn TdmBackend>> = HashMap::new();
+        custom.insert("xhfc".to_string(), Box::new(DahdiBackend::new("/nonexistent/xhfc")));
+
+        let mut set = TdmBackendSet::new(
+            &config(vec![span(1, "freetdm"), span(2, "dahdi"), span(3, "xhfc"), span(4, "dahdi")]),
+            custom,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = set.backends().map(|backend| backend.name()).collect();
+        assert_eq!(names, vec!["FreeTDM", "DAHDI", "DAHDI"]);
+        assert_eq!(set.backend_for_span(4).unwrap().span_statuses().len(), 2);
+        assert_eq!(set.span_statuses().len(), 4);
+        assert!(set.backend_for_span(9).is_err());
+
+        set.set_span_blocked(2, true).unwrap();
+        let blocked = |set: &TdmBackendSet, span_id| {
+            set.span_statuses()
+                .into_iter()
+                .find(|span| span.span_id == span_id)
+                .is_some_and(|span| span.channels.iter().all(|ch| ch.state == ChannelState::Blocked))
+        };
+        assert!(blocked(&set, 2) && !blocked(&set, 4));
+        set.set_span_blocked(2, false).unwrap();
+        assert!(!blocked(&set, 2));
+    }
+
+    #[test]
+    fn test_spans_get_their_regulatory_pack() {
+        let mut config = config(vec![span(1, "dahdi"), span(2, "dahdi")]);
+        config.regulatory.default_pack = Some("us".to_string());
+        config.spans[1].regulatory_pack = Some("gb".to_string());
+
+        let set = TdmBackendSet::new(&config, HashMap::new()).unwrap();
+        assert_eq!(set.regulatory_pack(1).unwrap().country, "us");
+        assert_eq!(set.regulatory_pack(2).unwrap().country, "gb");
+
+        config.spans[1].regulatory_pack = Some("zz".to_string());
+        assert!(TdmBackendSet::new(&config, HashMap::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_backend_and_missing_driver() {
+        let error = TdmBackendSet::new(&config(vec![span(1, "zoip")]), HashMap::new()).err().unwrap();
+        assert!(error.to_string().contains("zoip"));
+
+        let duplicate = TdmBackendSet::new(&config(vec![span(1, "dahdi"), span(1, "dahdi")]), HashMap::new());
+        assert!(duplicate.is_err());
+
+        let mut set = TdmBackendSet::new(&config(vec![span(1, "dahdi")]), HashMap::new()).unwrap();
+        assert!(set.start().await.is_err());
+        assert!(!set.is_running());
+    }
+}
@@ -3,10 +3,13 @@
 use std::collections::HashMap;
 use std::path::Path;
 
+use async_trait::async_trait;
 use tokio::sync::mpsc;
 use tracing::info;
 
-use crate::config::{FreeTdmConfig, ChannelType, SignalingType, Layer1Type};
+use crate::config::{FreeTdmConfig, FreeTdmSpan, ChannelType, SignalingType, Layer1Type, PortDescription};
+use crate::interfaces::backend::{block_channels, idle_channel, TdmBackend};
+use crate::utils::ClockStamp;
 use crate::{Error, Result};
 
 /// FreeTDM span status
@@ -18,6 +21,31 @@ pub struct SpanStatus {
     pub is_up: bool,
     pub channels: Vec<ChannelInfo>,
     pub alarms: Vec<String>,
+    pub description: PortDescription,
+}
+
+impl SpanStatus {
+    /// Initial (down, all channels idle) status of a configured span
+    pub fn from_config(span: &FreeTdmSpan) -> Self {
+        Self {
+            span_id: span.span_id,
+            name: span.name.clone(),
+            trunk_type: span.trunk_type.clone(),
+            is_up: false,
+            channels: span.channels
+                .iter()
+                .map(|ch| ChannelInfo {
+                    id: ch.id,
+                    channel_type: ch.channel_type.clone(),
+                    state: ChannelState::Idle,
+                    signaling: ch.signaling.clone(),
+                    enabled: ch.enabled,
+                })
+                .collect(),
+            alarms: Vec::new(),
+            description: span.description.clone(),
+        }
+    }
 }
 
 /// Channel information
@@ -47,10 +75,14 @@ pub enum FreeTdmEvent {
         channel_id: u8,
         calling_number: Option<String>,
         called_number: Option<String>,
+        /// User-user information element of the SETUP
+        user_user: Option<Vec<u8>>,
     },
     CallAnswered {
         span_id: u32,
         channel_id: u8,
+        /// When the CONNECT was decoded, the answer time of the call
+        received: ClockStamp,
     },
     CallHangup {
         span_id: u32,
@@ -68,6 +100,23 @@ pub enum FreeTdmEvent {
     SpanDown {
         span_id: u32,
     },
+    /// Layer 2 on the span's D-channel came up
+    DChannelUp {
+        span_id: u32,
+    },
+    DChannelDown {
+        span_id: u32,
+    },
+    /// A card providing `span_id` was inserted
+    HardwareAdded {
+        span_id: u32,
+        trunk_type: Layer1Type,
+        channels: u8,
+    },
+    /// The card providing `span_id` disappeared
+    HardwareRemoved {
+        span_id: u32,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -90,32 +139,11 @@ impl FreeTdmInterface {
     pub fn new(config: FreeTdmConfig) -> Result<Self> {
         let (event_tx, event_rx) = mpsc::unbounded_channel();
         
-        let mut spans = HashMap::new();
-        
         // Initialize spans from configuration
-        for span_config in &config.spans {
-            let channels: Vec<ChannelInfo> = span_config.channels
-                .iter()
-                .map(|ch| ChannelInfo {
-                    id: ch.id,
-                    channel_type: ch.channel_type.clone(),
-                    state: ChannelState::Idle,
-                    signaling: ch.signaling.clone(),
-                    enabled: ch.enabled,
-                })
-                .collect();
-
-            let span_status = SpanStatus {
-                span_id: span_config.span_id,
-                name: span_config.name.clone(),
-                trunk_type: span_config.trunk_type.clone(),
-                is_up: false,
-                channels,
-                alarms: Vec::new(),
-            };
-            
-            spans.insert(span_config.span_id, span_status);
-        }
+        let spans = config.spans
+            .iter()
+            .map(|span| (span.span_id, SpanStatus::from_config(span)))
+            .collect();
 
         Ok(Self {
             config,
@@ -140,7 +168,7 @@ impl FreeTdmInterface {
 
         // Validate configuration file exists
         if !Path::new(&self.config.config_file).exists() {
-            return Err(Error::parse(format!(
+            return Err(Error::invalid_config(format!(
                 "FreeTDM config file not found: {}",
                 self.config.config_file
             )));
@@ -199,6 +227,21 @@ impl FreeTdmInterface {
         }
     }
 
+    pub async fn restart_span(&self, span_id: u32) -> Result<()> {
+        if !self.is_running {
+            return Err(Error::invalid_state("FreeTDM interface not running"));
+        }
+        if !self.spans.contains_key(&span_id) {
+            return Err(Error::hardware(format!("Span {} not found", span_id)));
+        }
+
+        // In a real implementation, this would stop and restart the span
+        // through the FreeTDM library; its signaling module then reports
+        // the span and D-channel coming back
+        info!("Restarting span {}", span_id);
+        Ok(())
+    }
+
     pub fn get_span_status(&self, span_id: u32) -> Option<&SpanStatus> {
         self.spans.get(&span_id)
     }
@@ -221,17 +264,7 @@ impl FreeTdmInterface {
             return Err(Error::invalid_state("FreeTDM interface not running"));
         }
 
-        // Validate span and channel exist
-        let span = self.spans.get(&span_id)
-            .ok_or_else(|| Error::tdm(format!("Span {} not found", span_id)))?;
-
-        let channel = span.channels.iter()
-            .find(|ch| ch.id == channel_id)
-            .ok_or_else(|| Error::tdm(format!("Channel {} not found on span {}", channel_id, span_id)))?;
-
-        if channel.state != ChannelState::Idle {
-            return Err(Error::tdm(format!("Channel {}/{} is not idle", span_id, channel_id)));
-        }
+        idle_channel(&self.spans, span_id, channel_id)?;
 
         // In a real implementation, this would initiate an outbound call
         // through the FreeTDM library
@@ -251,6 +284,7 @@ impl FreeTdmInterface {
         let _ = self.event_tx.send(FreeTdmEvent::CallAnswered {
             span_id,
             channel_id,
+            received: ClockStamp::now(),
         });
 
         Ok(())
@@ -287,6 +321,69 @@ impl FreeTdmInterface {
     }
 }
 
+#[async_trait]
+impl TdmBackend for FreeTdmInterface {
+    fn name(&self) -> &str {
+        "FreeTDM"
+    }
+
+    fn add_span(&mut self, span: &FreeTdmSpan) -> Result<()> {
+        self.spans.insert(span.span_id, SpanStatus::from_config(span));
+        Ok(())
+    }
+
+    fn remove_span(&mut self, span_id: u32) -> Result<()> {
+        self.spans
+            .remove(&span_id)
+            .map(|_| ())
+            .ok_or_else(|| Error::hardware(format!("Span {} not found", span_id)))
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        FreeTdmInterface::start(self).await
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        FreeTdmInterface::stop(self).await
+    }
+
+    fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<FreeTdmEvent>> {
+        FreeTdmInterface::take_event_receiver(self)
+    }
+
+    async fn place_call(&self, span_id: u32, channel_id: u8, called_number: &str) -> Result<()> {
+        FreeTdmInterface::place_call(self, span_id, channel_id, called_number).await
+    }
+
+    async fn answer_call(&self, span_id: u32, channel_id: u8) -> Result<()> {
+        FreeTdmInterface::answer_call(self, span_id, channel_id).await
+    }
+
+    async fn hangup_call(&self, span_id: u32, channel_id: u8, cause: u16) -> Result<()> {
+        FreeTdmInterface::hangup_call(self, span_id, channel_id, cause).await
+    }
+
+    async fn restart_span(&self, span_id: u32) -> Result<()> {
+        FreeTdmInterface::restart_span(self, span_id).await
+    }
+
+    fn set_span_blocked(&mut self, span_id: u32, blocked: bool) -> Result<()> {
+        block_channels(&mut self.spans, span_id, blocked)
+    }
+
+    fn span_statuses(&self) -> Vec<SpanStatus> {
+        self.get_all_span_statuses()
+    }
+
+    fn active_channel_count(&self) -> u32 {
+        self.get_active_channel_count()
+    }
+}
+
 // Note: In a real implementation, you would create FFI bindings to the FreeTDM C library
 // This would involve:
 // 1. Creating a freetdm-sys crate with bindgen
@@ -305,6 +402,10 @@ mod tests {
             enabled: false,
             config_file: "/tmp/test.conf".to_string(),
             spans: vec![],
+            dahdi_device_dir: "/dev/dahdi".to_string(),
+            recovery: Default::default(),
+            hot_swap: Default::default(),
+            regulatory: Default::default(),
         };
         
         let interface = FreeTdmInterface::new(config);
@@ -317,6 +418,10 @@ mod tests {
             enabled: false,
             config_file: "/tmp/test.conf".to_string(),
             spans: vec![],
+            dahdi_device_dir: "/dev/dahdi".to_string(),
+            recovery: Default::default(),
+            hot_swap: Default::default(),
+            regulatory: Default::default(),
         };
         
         let mut interface = FreeTdmInterface::new(config).unwrap();
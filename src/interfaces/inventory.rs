@@ -0,0 +1,172 @@
+//! TDM hardware inventory for hot-swapped cards
+//!
+//! Cards can be inserted or pulled while the gateway runs. Backends report
+//! this as hardware events or, when they can rescan their hardware, through
+//! periodic discovery. Spans found on a new card get a configuration from
+//! the template for their line type; spans whose card disappeared are
+//! released and dropped. Each change is reported as an inventory event.
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ChannelType, FreeTdmChannel, FreeTdmSpan, Layer1Type, PortDescription, SpanTemplate};
+use crate::interfaces::freetdm::FreeTdmEvent;
+
+/// A span present on an installed card
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveredSpan {
+    pub span_id: u32,
+    pub trunk_type: Layer1Type,
+    /// Timeslots including the D-channel
+    pub channels: u8,
+    /// Driver that found the span; the template's backend otherwise
+    pub backend: Option<String>,
+    /// Name the driver gives the span, e.g. `WCT1/0/1`
+    pub name: Option<String>,
+}
+
+/// A span added to or removed from service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum InventoryChange {
+    Added { span_id: u32, trunk_type: Layer1Type, backend: String },
+    Removed { span_id: u32 },
+}
+
+/// Hardware change waiting to be applied by the gateway
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingChange {
+    Inserted(DiscoveredSpan),
+    Removed(u32),
+}
+
+/// Configuration for `found` from the first template matching its line type
+pub fn span_from_template(found: &DiscoveredSpan, templates: &[SpanTemplate]) -> Option<FreeTdmSpan> {
+    let template = templates.iter().find(|template| template.trunk_type == found.trunk_type)?;
+    let d_channel = match found.trunk_type {
+        Layer1Type::E1 => 16,
+        Layer1Type::T1 => found.channels,
+    };
+    let channels = (1..=found.channels)
+        .map(|id| FreeTdmChannel {
+            id,
+            channel_type: if id == d_channel { ChannelType::DChannel } else { ChannelType::BChannel },
+            enabled: true,
+            signaling: template.signaling.clone(),
+            description: PortDescription::default(),
+        })
+        .collect();
+
+    Some(FreeTdmSpan {
+        span_id: found.span_id,
+        name: found.name.clone().unwrap_or_else(|| format!("span{}", found.span_id)),
+        trunk_type: found.trunk_type.clone(),
+        d_channel,
+        channels,
+        backend: found.backend.clone().unwrap_or_else(|| template.backend.clone()),
+        description: PortDescription::default(),
+        regulatory_pack: None,
+    })
+}
+
+/// Collects hardware changes from events and rescans
+#[derive(Debug, Default)]
+pub struct HardwareInventory {
+    pending: Mutex<Vec<PendingChange>>,
+}
+
+impl HardwareInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_event(&self, event: &FreeTdmEvent) {
+        let change = match *event {
+            FreeTdmEvent::HardwareAdded { span_id, ref trunk_type, channels } => PendingChange::Inserted(DiscoveredSpan {
+                span_id,
+                trunk_type: trunk_type.clone(),
+                channels,
+                backend: None,
+                name: None,
+            }),
+            FreeTdmEvent::HardwareRemoved { span_id } => PendingChange::Removed(span_id),
+            _ => return,
+        };
+        self.push(change);
+    }
+
+    /// Compare one backend's `owned` spans with what its rescan `found`
+    pub fn reconcile(&self, owned: &[u32], found: &[DiscoveredSpan]) {
+        for span in found.iter().filter(|span| !owned.contains(&span.span_id)) {
+            self.push(PendingChange::Inserted(span.clone()));
+        }
+        for &span_id in owned.iter().filter(|&&span_id| !found.iter().any(|span| span.span_id == span_id)) {
+            self.push(PendingChange::Removed(span_id));
+        }
+    }
+
+    pub fn take_pending(&self) -> Vec<PendingChange> {
+        self.pending.lock().map(|mut pending| std::mem::take(&mut *pending)).unwrap_or_default()
+    }
+
+    fn push(&self, change: PendingChange) {
+        if let Ok(mut pending) = self.pending.lock() {
+            if !pending.contains(&change) {
+                pending.push(change);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{HotSwapConfig, SignalingType};
+
+    fn found(span_id: u32, trunk_type: Layer1Type, channels: u8) -> DiscoveredSpan {
+        DiscoveredSpan {
+            span_id,
+            trunk_type,
+            channels,
+            backend: Some("dahdi".to_string()),
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_templates_configure_discovered_spans() {
+        let templates = HotSwapConfig::default().templates;
+
+        let e1 = span_from_template(&found(5, Layer1Type::E1, 31), &templates).unwrap();
+        assert_eq!(e1.d_channel, 16);
+        assert_eq!(e1.channels.len(), 31);
+        assert!(matches!(e1.channels[15].channel_type, ChannelType::DChannel));
+        assert_eq!(e1.backend, "dahdi");
+        assert_eq!(e1.name, "span5");
+
+        let mut t1_found = found(6, Layer1Type::T1, 24);
+        t1_found.backend = None;
+        let t1 = span_from_template(&t1_found, &templates).unwrap();
+        assert_eq!(t1.d_channel, 24);
+        assert_eq!(t1.backend, "freetdm");
+        assert_eq!(t1.channels[0].signaling, SignalingType::Pri);
+
+        let e1_only = vec![templates[0].clone()];
+        assert!(span_from_template(&found(7, Layer1Type::T1, 24), &e1_only).is_none());
+    }
+
+    #[test]
+    fn test_rescans_and_events_queue_changes_once() {
+        let inventory = HardwareInventory::new();
+        inventory.reconcile(&[1, 2], &[found(1, Layer1Type::E1, 31), found(3, Layer1Type::E1, 31)]);
+        inventory.on_event(&FreeTdmEvent::HardwareRemoved { span_id: 2 });
+        inventory.on_event(&FreeTdmEvent::SpanUp { span_id: 1 });
+
+        assert_eq!(inventory.take_pending(), vec![
+            PendingChange::Inserted(found(3, Layer1Type::E1, 31)),
+            PendingChange::Removed(2),
+        ]);
+        assert!(inventory.take_pending().is_empty());
+    }
+}
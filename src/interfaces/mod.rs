@@ -2,6 +2,16 @@
 
 pub mod tdmoe;
 pub mod freetdm;
+pub mod transport;
+pub mod backend;
+pub mod recovery;
+pub mod inventory;
+pub mod regulatory;
 
 pub use tdmoe::TdmoeInterface;
-pub use freetdm::FreeTdmInterface;
\ No newline at end of file
+pub use freetdm::FreeTdmInterface;
+pub use transport::TdmTransport;
+pub use backend::{AudioDirection, ChannelAudioSink, DahdiBackend, HardwareInfo, TdmBackend, TdmBackendSet};
+pub use recovery::{RecoveryAction, SpanRecovery, SpanRecoveryEvent};
+pub use inventory::{DiscoveredSpan, HardwareInventory, InventoryChange, PendingChange};
+pub use regulatory::RegulatoryPacks;
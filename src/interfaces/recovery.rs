@@ -0,0 +1,324 @@
+//! Automatic span recovery
+//!
+//! A span that drops, or comes up without establishing its D-channel, is
+//! restarted by its backend after a delay that doubles with every attempt.
+//! The attempt count only resets once the span has stayed up with its
+//! D-channel for a while, so a flapping span backs off instead of being
+//! restarted in a tight loop. When the attempts run out the span is latched
+//! out of service and left alone until an operator clears it.
+
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::SpanRecoveryConfig;
+use crate::interfaces::freetdm::FreeTdmEvent;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanHealth {
+    /// Up; `d_channel_deadline` is set until the D-channel establishes
+    Up {
+        d_channel_deadline: Option<Instant>,
+        since: Instant,
+    },
+    Down {
+        next_restart: Instant,
+    },
+    /// Out of attempts; waits for a manual clear
+    Latched {
+        span_up: bool,
+    },
+}
+
+/// Work due after a poll
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryAction {
+    Restart { span_id: u32, attempt: u32 },
+    /// Raise the alarm that holds the span until it is cleared by hand
+    Latch { span_id: u32, attempts: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub enum SpanRecoveryEvent {
+    RestartScheduled { span_id: u32, delay: Duration },
+    Recovered { span_id: u32, attempts: u32 },
+    Latched { span_id: u32, attempts: u32 },
+    Cleared { span_id: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanRecoveryStatus {
+    pub span_id: u32,
+    pub health: SpanHealth,
+    pub attempts: u32,
+}
+
+#[derive(Debug)]
+struct SpanState {
+    health: SpanHealth,
+    attempts: u32,
+    /// Alarm raised when the span was latched
+    alarm_id: Option<String>,
+}
+
+/// Decides when spans are restarted, driven by span and D-channel events
+pub struct SpanRecovery {
+    config: SpanRecoveryConfig,
+    spans: DashMap<u32, SpanState>,
+    event_tx: mpsc::UnboundedSender<SpanRecoveryEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<SpanRecoveryEvent>>,
+}
+
+impl SpanRecovery {
+    pub fn new(config: SpanRecoveryConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            config,
+            spans: DashMap::new(),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<SpanRecoveryEvent>> {
+        self.event_rx.take()
+    }
+
+    /// Delay before restart attempt `attempt + 1`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let secs = self
+            .config
+            .initial_backoff_secs
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.config.max_backoff_secs);
+        Duration::from_secs(secs)
+    }
+
+    pub fn on_event(&self, event: &FreeTdmEvent, now: Instant) {
+        if !self.config.enabled {
+            return;
+        }
+        match *event {
+            FreeTdmEvent::SpanUp { span_id } => self.on_span_up(span_id, now),
+            FreeTdmEvent::SpanDown { span_id } => self.on_failure(span_id, now),
+            FreeTdmEvent::DChannelUp { span_id } => {
+                if let Some(mut state) = self.spans.get_mut(&span_id) {
+                    if let SpanHealth::Up { since, .. } = state.health {
+                        state.health = SpanHealth::Up { d_channel_deadline: None, since };
+                    }
+                }
+            }
+            FreeTdmEvent::DChannelDown { span_id } => {
+                let up = self.spans.get(&span_id).is_some_and(|state| matches!(state.health, SpanHealth::Up { .. }));
+                if up {
+                    self.on_failure(span_id, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Restarts and latches that are due at `now`
+    pub fn poll(&self, now: Instant) -> Vec<RecoveryAction> {
+        let d_channel_timeout = Duration::from_secs(self.config.d_channel_timeout_secs);
+        let stable = Duration::from_secs(self.config.stable_secs);
+        let mut actions = Vec::new();
+
+        for mut entry in self.spans.iter_mut() {
+            let span_id = *entry.key();
+            let state = entry.value_mut();
+            match state.health {
+                SpanHealth::Up { d_channel_deadline: Some(deadline), .. } if now >= deadline => {
+                    warn!("Span {} D-channel not established within {:?}", span_id, d_channel_timeout);
+                    state.health = SpanHealth::Down { next_restart: now };
+                }
+                SpanHealth::Up { d_channel_deadline: None, since } if state.attempts > 0 && now >= since + stable => {
+                    info!("Span {} stable after {} restarts", span_id, state.attempts);
+                    let _ = self.event_tx.send(SpanRecoveryEvent::Recovered { span_id, attempts: state.attempts });
+                    state.attempts = 0;
+                }
+                _ => {}
+            }
+
+            if let SpanHealth::Down { next_restart } = state.health {
+                if now < next_restart {
+                    continue;
+                }
+                if state.attempts >= self.config.max_attempts {
+                    warn!("Span {} latched out of service after {} restarts", span_id, state.attempts);
+                    state.health = SpanHealth::Latched { span_up: false };
+                    let _ = self.event_tx.send(SpanRecoveryEvent::Latched { span_id, attempts: state.attempts });
+                    actions.push(RecoveryAction::Latch { span_id, attempts: state.attempts });
+                    continue;
+                }
+                state.attempts += 1;
+                // Wait for the restart's outcome before trying again
+                state.health = SpanHealth::Down { next_restart: now + self.backoff(state.attempts) };
+                actions.push(RecoveryAction::Restart { span_id, attempt: state.attempts });
+            }
+        }
+        actions.sort_by_key(|action| match action {
+            RecoveryAction::Restart { span_id, .. } | RecoveryAction::Latch { span_id, .. } => *span_id,
+        });
+        actions
+    }
+
+    /// Remember the alarm raised for a latched span
+    pub fn set_alarm(&self, span_id: u32, alarm_id: String) {
+        if let Some(mut state) = self.spans.get_mut(&span_id) {
+            state.alarm_id = Some(alarm_id);
+        }
+    }
+
+    /// Manual clear of a latched span; returns the alarm to clear with it
+    pub fn clear(&self, span_id: u32, now: Instant) -> Result<Option<String>> {
+        let mut state = self
+            .spans
+            .get_mut(&span_id)
+            .ok_or_else(|| Error::invalid_state(format!("Span {} is not latched", span_id)))?;
+        let SpanHealth::Latched { span_up } = state.health else {
+            return Err(Error::invalid_state(format!("Span {} is not latched", span_id)));
+        };
+
+        state.attempts = 0;
+        state.health = if span_up {
+            SpanHealth::Up { d_channel_deadline: None, since: now }
+        } else {
+            SpanHealth::Down { next_restart: now }
+        };
+        info!("Span {} recovery cleared", span_id);
+        let _ = self.event_tx.send(SpanRecoveryEvent::Cleared { span_id });
+        Ok(state.alarm_id.take())
+    }
+
+    /// Stop tracking a span whose hardware was removed
+    pub fn forget(&self, span_id: u32) {
+        self.spans.remove(&span_id);
+    }
+
+    pub fn is_latched(&self, span_id: u32) -> bool {
+        self.spans.get(&span_id).is_some_and(|state| matches!(state.health, SpanHealth::Latched { .. }))
+    }
+
+    /// Recovery state of every span seen so far, ordered by span
+    pub fn statuses(&self) -> Vec<SpanRecoveryStatus> {
+        let mut statuses: Vec<SpanRecoveryStatus> = self
+            .spans
+            .iter()
+            .map(|entry| SpanRecoveryStatus {
+                span_id: *entry.key(),
+                health: entry.value().health,
+                attempts: entry.value().attempts,
+            })
+            .collect();
+        statuses.sort_by_key(|status| status.span_id);
+        statuses
+    }
+
+    fn on_span_up(&self, span_id: u32, now: Instant) {
+        let mut state = self.state(span_id, now);
+        state.health = match state.health {
+            SpanHealth::Latched { .. } => SpanHealth::Latched { span_up: true },
+            _ => SpanHealth::Up {
+                d_channel_deadline: Some(now + Duration::from_secs(self.config.d_channel_timeout_secs)),
+                since: now,
+            },
+        };
+    }
+
+    fn on_failure(&self, span_id: u32, now: Instant) {
+        let mut state = self.state(span_id, now);
+        match state.health {
+            SpanHealth::Latched { .. } => state.health = SpanHealth::Latched { span_up: false },
+            // A restart is already pending; its own backoff applies
+            SpanHealth::Down { .. } => {}
+            SpanHealth::Up { .. } => {
+                let delay = self.backoff(state.attempts);
+                state.health = SpanHealth::Down { next_restart: now + delay };
+                let _ = self.event_tx.send(SpanRecoveryEvent::RestartScheduled { span_id, delay });
+            }
+        }
+    }
+
+    fn state(&self, span_id: u32, now: Instant) -> dashmap::mapref::one::RefMut<'_, u32, SpanState> {
+        self.spans.entry(span_id).or_insert_with(|| SpanState {
+            health: SpanHealth::Up { d_channel_deadline: None, since: now },
+            attempts: 0,
+            alarm_id: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recovery() -> SpanRecovery {
+        SpanRecovery::new(SpanRecoveryConfig {
+            enabled: true,
+            initial_backoff_secs: 5,
+            max_backoff_secs: 20,
+            max_attempts: 3,
+            d_channel_timeout_secs: 30,
+            stable_secs: 120,
+        })
+    }
+
+    #[test]
+    fn test_flapping_span_backs_off_then_latches() {
+        let recovery = recovery();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(recovery.backoff(0), Duration::from_secs(5));
+        assert_eq!(recovery.backoff(1), Duration::from_secs(10));
+        assert_eq!(recovery.backoff(5), Duration::from_secs(20));
+
+        recovery.on_event(&FreeTdmEvent::SpanDown { span_id: 3 }, at(0));
+        assert!(recovery.poll(at(4)).is_empty());
+        assert_eq!(recovery.poll(at(5)), vec![RecoveryAction::Restart { span_id: 3, attempt: 1 }]);
+
+        // Comes back, then drops again before it is stable
+        recovery.on_event(&FreeTdmEvent::SpanUp { span_id: 3 }, at(6));
+        recovery.on_event(&FreeTdmEvent::DChannelUp { span_id: 3 }, at(7));
+        recovery.on_event(&FreeTdmEvent::SpanDown { span_id: 3 }, at(20));
+        assert!(recovery.poll(at(29)).is_empty());
+        assert_eq!(recovery.poll(at(30)), vec![RecoveryAction::Restart { span_id: 3, attempt: 2 }]);
+
+        // Restart does not bring it back
+        assert!(recovery.poll(at(49)).is_empty());
+        assert_eq!(recovery.poll(at(50)), vec![RecoveryAction::Restart { span_id: 3, attempt: 3 }]);
+        assert_eq!(recovery.poll(at(70)), vec![RecoveryAction::Latch { span_id: 3, attempts: 3 }]);
+        assert!(recovery.is_latched(3));
+
+        // Latched spans are left alone until cleared by hand
+        recovery.on_event(&FreeTdmEvent::SpanUp { span_id: 3 }, at(80));
+        recovery.on_event(&FreeTdmEvent::SpanDown { span_id: 3 }, at(81));
+        assert!(recovery.poll(at(500)).is_empty());
+
+        recovery.set_alarm(3, "span-recovery-3".to_string());
+        assert_eq!(recovery.clear(3, at(600)).unwrap(), Some("span-recovery-3".to_string()));
+        assert_eq!(recovery.poll(at(600)), vec![RecoveryAction::Restart { span_id: 3, attempt: 1 }]);
+        assert!(recovery.clear(3, at(601)).is_err());
+    }
+
+    #[test]
+    fn test_missing_d_channel_restarts_and_stable_span_resets() {
+        let recovery = recovery();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        recovery.on_event(&FreeTdmEvent::SpanUp { span_id: 1 }, at(0));
+        assert!(recovery.poll(at(29)).is_empty());
+        assert_eq!(recovery.poll(at(30)), vec![RecoveryAction::Restart { span_id: 1, attempt: 1 }]);
+
+        recovery.on_event(&FreeTdmEvent::SpanUp { span_id: 1 }, at(32));
+        recovery.on_event(&FreeTdmEvent::DChannelUp { span_id: 1 }, at(33));
+        assert!(recovery.poll(at(100)).is_empty());
+        assert_eq!(recovery.statuses()[0].attempts, 1);
+        assert!(recovery.poll(at(152)).is_empty());
+        assert_eq!(recovery.statuses()[0].attempts, 0);
+    }
+}
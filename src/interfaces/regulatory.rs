@@ -0,0 +1,241 @@
+//! Per-country regulatory packs
+//!
+//! A pack bundles the ring cadence, call progress tones, disconnect
+//! supervision and CLIP format of one country's network. Spans select a pack
+//! by country code, falling back to the configured default, so one build can
+//! be deployed in another market by changing a single setting. Packs given in
+//! config are added to the built-in ones and replace a built-in pack of the
+//! same country.
+
+use std::collections::HashMap;
+
+use crate::config::{
+    Cadence, ClipAlert, ClipFormat, ClipModulation, ClipTiming, DisconnectMethod, DisconnectSupervision, FreeTdmSpan,
+    RegulatoryConfig, RegulatoryPack, ToneSpec,
+};
+use crate::{Error, Result};
+
+impl Cadence {
+    pub fn is_continuous(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Length of one on/off cycle
+    pub fn cycle_ms(&self) -> u32 {
+        self.0.iter().sum()
+    }
+
+    /// Whether the signal is on `offset_ms` after it started
+    pub fn is_on_at(&self, offset_ms: u64) -> bool {
+        if self.is_continuous() {
+            return true;
+        }
+        let mut offset = offset_ms % u64::from(self.cycle_ms());
+        for (index, period) in self.0.iter().enumerate() {
+            if offset < u64::from(*period) {
+                return index % 2 == 0;
+            }
+            offset -= u64::from(*period);
+        }
+        false
+    }
+}
+
+fn tone(frequencies: &[u16], level_dbm0: i8, cadence: &[u32]) -> ToneSpec {
+    ToneSpec {
+        frequencies: frequencies.to_vec(),
+        level_dbm0,
+        cadence: Cadence(cadence.to_vec()),
+    }
+}
+
+fn clip(modulation: ClipModulation, timing: ClipTiming, alert: ClipAlert) -> ClipFormat {
+    ClipFormat { modulation, timing, alert }
+}
+
+fn disconnect(method: DisconnectMethod, min_duration_ms: u32) -> DisconnectSupervision {
+    DisconnectSupervision { method, min_duration_ms }
+}
+
+/// Packs shipped with the gateway
+pub fn builtin_packs() -> Vec<RegulatoryPack> {
+    vec![
+        RegulatoryPack {
+            country: "us".to_string(),
+            ring_cadence: Cadence(vec![2000, 4000]),
+            dial_tone: tone(&[350, 440], -13, &[]),
+            ringback_tone: tone(&[440, 480], -19, &[2000, 4000]),
+            busy_tone: tone(&[480, 620], -24, &[500, 500]),
+            congestion_tone: tone(&[480, 620], -24, &[250, 250]),
+            disconnect: disconnect(DisconnectMethod::LoopCurrentDenial, 600),
+            clip: clip(ClipModulation::Bell202Fsk, ClipTiming::AfterFirstRing, ClipAlert::None),
+        },
+        RegulatoryPack {
+            country: "gb".to_string(),
+            ring_cadence: Cadence(vec![400, 200, 400, 2000]),
+            dial_tone: tone(&[350, 440], -13, &[]),
+            ringback_tone: tone(&[400, 450], -19, &[400, 200, 400, 2000]),
+            busy_tone: tone(&[400], -19, &[375, 375]),
+            congestion_tone: tone(&[400], -19, &[400, 350, 225, 525]),
+            disconnect: disconnect(DisconnectMethod::LoopCurrentDenial, 350),
+            clip: clip(ClipModulation::V23Fsk, ClipTiming::BeforeFirstRing, ClipAlert::LineReversalDualTone),
+        },
+        RegulatoryPack {
+            country: "de".to_string(),
+            ring_cadence: Cadence(vec![1000, 4000]),
+            dial_tone: tone(&[425], -10, &[]),
+            ringback_tone: tone(&[425], -10, &[1000, 4000]),
+            busy_tone: tone(&[425], -10, &[480, 480]),
+            congestion_tone: tone(&[425], -10, &[240, 240]),
+            disconnect: disconnect(DisconnectMethod::Tone, 1920),
+            clip: clip(ClipModulation::V23Fsk, ClipTiming::AfterFirstRing, ClipAlert::None),
+        },
+        RegulatoryPack {
+            country: "fr".to_string(),
+            ring_cadence: Cadence(vec![1500, 3500]),
+            dial_tone: tone(&[440], -10, &[]),
+            ringback_tone: tone(&[440], -10, &[1500, 3500]),
+            busy_tone: tone(&[440], -10, &[500, 500]),
+            congestion_tone: tone(&[440], -10, &[250, 250]),
+            disconnect: disconnect(DisconnectMethod::Tone, 2000),
+            clip: clip(ClipModulation::V23Fsk, ClipTiming::BeforeFirstRing, ClipAlert::LineReversal),
+        },
+        RegulatoryPack {
+            country: "au".to_string(),
+            ring_cadence: Cadence(vec![400, 200, 400, 2000]),
+            dial_tone: tone(&[413, 438], -13, &[]),
+            ringback_tone: tone(&[413, 438], -19, &[400, 200, 400, 2000]),
+            busy_tone: tone(&[425], -13, &[375, 375]),
+            congestion_tone: tone(&[425], -19, &[375, 375]),
+            disconnect: disconnect(DisconnectMethod::LoopCurrentDenial, 250),
+            clip: clip(ClipModulation::V23Fsk, ClipTiming::BeforeFirstRing, ClipAlert::LineReversal),
+        },
+    ]
+}
+
+fn check_pack(pack: &RegulatoryPack) -> Result<()> {
+    let invalid = |what: &str| Err(Error::invalid_config(format!("Regulatory pack {} has {}", pack.country, what)));
+    if pack.country.is_empty() {
+        return Err(Error::invalid_config("Regulatory pack without a country code"));
+    }
+    if pack.ring_cadence.is_continuous() || pack.ring_cadence.0.contains(&0) {
+        return invalid("an empty or zero-length ring cadence period");
+    }
+    let tones = [&pack.dial_tone, &pack.ringback_tone, &pack.busy_tone, &pack.congestion_tone];
+    if tones.iter().any(|tone| {
+        !(1..=2).contains(&tone.frequencies.len()) || tone.frequencies.contains(&0) || tone.cadence.0.contains(&0)
+    }) {
+        return invalid("a tone without one or two frequencies, or with a zero-length cadence period");
+    }
+    if pack.disconnect.min_duration_ms == 0 {
+        return invalid("a zero disconnect supervision time");
+    }
+    Ok(())
+}
+
+/// Built-in and configured packs, by country
+#[derive(Debug, Clone)]
+pub struct RegulatoryPacks {
+    packs: HashMap<String, RegulatoryPack>,
+    default_pack: Option<String>,
+}
+
+impl RegulatoryPacks {
+    pub fn new(config: &RegulatoryConfig) -> Result<Self> {
+        let mut packs = HashMap::new();
+        for pack in builtin_packs() {
+            packs.insert(pack.country.clone(), pack);
+        }
+        for pack in &config.packs {
+            check_pack(pack)?;
+            packs.insert(pack.country.to_ascii_lowercase(), pack.clone());
+        }
+
+        let default_pack = config.default_pack.as_deref().map(str::to_ascii_lowercase);
+        if let Some(country) = &default_pack {
+            if !packs.contains_key(country) {
+                return Err(Error::invalid_config(format!("Unknown default regulatory pack {}", country)));
+            }
+        }
+        Ok(Self { packs, default_pack })
+    }
+
+    pub fn get(&self, country: &str) -> Option<&RegulatoryPack> {
+        self.packs.get(&country.to_ascii_lowercase())
+    }
+
+    /// Pack selected by `span`, or the default; `None` leaves the driver's
+    /// own settings in place
+    pub fn for_span(&self, span: &FreeTdmSpan) -> Result<Option<&RegulatoryPack>> {
+        match span.regulatory_pack.as_deref() {
+            Some(country) => self.get(country).map(Some).ok_or_else(|| {
+                Error::invalid_config(format!("Span {} uses unknown regulatory pack {}", span.span_id, country))
+            }),
+            None => Ok(self.default_pack.as_deref().and_then(|country| self.packs.get(country))),
+        }
+    }
+
+    /// Country codes of all available packs, sorted
+    pub fn countries(&self) -> Vec<String> {
+        let mut countries: Vec<String> = self.packs.keys().cloned().collect();
+        countries.sort();
+        countries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Layer1Type;
+
+    fn span(span_id: u32, pack: Option<&str>) -> FreeTdmSpan {
+        FreeTdmSpan {
+            span_id,
+            name: format!("span{}", span_id),
+            trunk_type: Layer1Type::E1,
+            d_channel: 16,
+            channels: Vec::new(),
+            backend: "freetdm".to_string(),
+            description: Default::default(),
+            regulatory_pack: pack.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_builtin_packs_are_valid() {
+        for pack in builtin_packs() {
+            check_pack(&pack).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_span_selection_and_overrides() {
+        let mut custom = builtin_packs().into_iter().find(|pack| pack.country == "de").unwrap();
+        custom.disconnect.method = DisconnectMethod::PolarityReversal;
+        let packs = RegulatoryPacks::new(&RegulatoryConfig {
+            default_pack: Some("GB".to_string()),
+            packs: vec![custom],
+        })
+        .unwrap();
+
+        assert_eq!(packs.for_span(&span(1, None)).unwrap().unwrap().country, "gb");
+        let german = packs.for_span(&span(2, Some("DE"))).unwrap().unwrap();
+        assert_eq!(german.disconnect.method, DisconnectMethod::PolarityReversal);
+        assert!(packs.for_span(&span(3, Some("zz"))).is_err());
+
+        let unset = RegulatoryPacks::new(&RegulatoryConfig::default()).unwrap();
+        assert!(unset.for_span(&span(1, None)).unwrap().is_none());
+        assert!(RegulatoryPacks::new(&RegulatoryConfig { default_pack: Some("zz".to_string()), packs: Vec::new() }).is_err());
+    }
+
+    #[test]
+    fn test_cadence_phase() {
+        let uk_ring = Cadence(vec![400, 200, 400, 2000]);
+        assert!(uk_ring.is_on_at(0));
+        assert!(!uk_ring.is_on_at(450));
+        assert!(uk_ring.is_on_at(700));
+        assert!(!uk_ring.is_on_at(1500));
+        assert!(uk_ring.is_on_at(3000 + 100));
+        assert!(Cadence::default().is_on_at(12_345));
+    }
+}
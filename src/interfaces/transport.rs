@@ -0,0 +1,60 @@
+//! Pluggable TDM transport
+//!
+//! The gateway talks to its TDM side through [`TdmTransport`]. The built-in
+//! implementation is [`TdmoeInterface`]; embedders can supply their own (for
+//! example a vendor card driver or an in-process test harness) through
+//! [`GatewayBuilder::tdm_transport`](crate::core::GatewayBuilder::tdm_transport).
+
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use crate::interfaces::tdmoe::{TdmoeEvent, TdmoeFrame};
+use crate::interfaces::TdmoeInterface;
+use crate::Result;
+
+/// TDM transport driven by the gateway
+#[async_trait]
+pub trait TdmTransport: Send + Sync {
+    /// Name reported in interface up/down events and status
+    fn name(&self) -> &str;
+
+    async fn start(&mut self) -> Result<()>;
+
+    async fn stop(&self) -> Result<()>;
+
+    /// Events from the transport; taken once by the gateway on start
+    fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TdmoeEvent>>;
+
+    async fn send_frame(&self, frame: TdmoeFrame, dest: Option<SocketAddr>) -> Result<()>;
+
+    fn active_channel_count(&self) -> u32;
+}
+
+#[async_trait]
+impl TdmTransport for TdmoeInterface {
+    fn name(&self) -> &str {
+        "TDMoE"
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        TdmoeInterface::start(self).await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        TdmoeInterface::stop(self).await
+    }
+
+    fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TdmoeEvent>> {
+        TdmoeInterface::take_event_receiver(self)
+    }
+
+    async fn send_frame(&self, frame: TdmoeFrame, dest: Option<SocketAddr>) -> Result<()> {
+        TdmoeInterface::send_frame(self, frame, dest).await
+    }
+
+    fn active_channel_count(&self) -> u32 {
+        self.get_active_channels().len() as u32
+    }
+}
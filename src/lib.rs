@@ -4,6 +4,13 @@
 //! with modern SIP/VoIP systems, supporting various protocols and standards.
 //!
 //! **Sponsored by [Carrier One Inc](https://carrierone.com) - Professional Telecommunications Solutions**
+//!
+//! # Embedding
+//!
+//! The gateway core can run inside another application. Build it with
+//! [`RedFireGateway::builder`], optionally injecting a custom [`TdmTransport`],
+//! [`TdmBackend`] span drivers or a [`CdrStorage`] sink, then consume [`GatewayEvent`]s from
+//! [`RedFireGateway::take_event_receiver`]. See `examples/embedded_gateway.rs`.
 
 pub mod config;
 pub mod core;
@@ -14,7 +21,11 @@ pub mod testing;
 pub mod error;
 pub mod utils;
 
-pub use error::{Error, Result};
+pub use config::GatewayConfig;
+pub use crate::core::{GatewayBuilder, GatewayEvent, GatewayStatus, RedFireGateway};
+pub use error::{Error, ErrorCategory, Result};
+pub use interfaces::{TdmBackend, TdmTransport};
+pub use services::CdrStorage;
 
 /// Gateway version information
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
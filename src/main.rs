@@ -1,19 +1,33 @@
 //! Redfire Gateway main application
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::{Parser, Subcommand};
 use tokio::signal;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use redfire_gateway::{
     config::GatewayConfig,
+    core::control::{self, ControlRequest, ControlResponse},
+    core::setup::{render_commented_toml, SetupWizard},
     core::RedFireGateway,
+    services::demo::{DemoGateway, DEMO_TICK},
+    services::management_api::{ManagementApi, ManagementSource},
+    services::metrics::{grafana_dashboard, MetricsRegistry},
+    services::route_provisioning::{self, ProvisioningTable},
+    services::heartbeat::Heartbeat,
+    services::status_page::StatusPage,
     utils::setup_logging,
     Result,
 };
 
+// jemalloc provides the allocation statistics served by the profiling endpoints
+#[cfg(feature = "profiling")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
 #[derive(Parser)]
 #[command(name = "redfire-gateway")]
 #[command(about = "TDMoE to SIP Gateway")]
@@ -33,6 +47,11 @@ struct Cli {
     /// Run as daemon
     #[arg(short, long)]
     daemon: bool,
+
+    /// Serve simulated spans, calls, alarms and clocks instead of running
+    /// against real interfaces
+    #[arg(long)]
+    demo: bool,
 }
 
 #[derive(Subcommand)]
@@ -44,19 +63,92 @@ enum Commands {
     /// Check gateway status
     Status,
     /// Validate configuration
-    ValidateConfig,
+    ValidateConfig {
+        /// Print the document the configuration file and its includes merge into
+        #[arg(long)]
+        show_merged: bool,
+    },
     /// Generate default configuration
     GenerateConfig {
         /// Output file path
         #[arg(short, long)]
         output: Option<PathBuf>,
     },
+    /// Create a configuration interactively for a first-time install
+    Setup {
+        /// Output file path
+        #[arg(short, long, default_value = "/etc/redfire/gateway.toml")]
+        output: PathBuf,
+    },
+    /// Inspect the metrics this build exports
+    Metrics {
+        #[command(subcommand)]
+        action: MetricsAction,
+    },
+    /// Export or import the routing table and registering trunks as spreadsheets
+    Routes {
+        #[command(subcommand)]
+        action: RoutesAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum MetricsAction {
+    /// Generate a Grafana dashboard charting every exported metric
+    Dashboard {
+        /// Output file path
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RoutesAction {
+    /// Write the routing table of the configuration file
+    Export {
+        /// Write CSV, one row per route
+        #[arg(long)]
+        csv: bool,
+        /// Export the registering trunks instead of the routes
+        #[arg(long)]
+        trunks: bool,
+        /// Output file path
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Replace the routing table of the configuration file from a sheet
+    Import {
+        /// Sheet to import
+        file: PathBuf,
+        /// Read the sheet as CSV with a header row
+        #[arg(long)]
+        csv: bool,
+        /// Import registering trunks instead of routes
+        #[arg(long)]
+        trunks: bool,
+        /// Show what would change without writing the configuration
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // Setup runs before there is a configuration to load
+    if let Some(Commands::Setup { output }) = &cli.command {
+        return run_setup(output).await;
+    }
+    // The dashboard describes the build, not a configuration
+    if let Some(Commands::Metrics { action: MetricsAction::Dashboard { output } }) = &cli.command {
+        return generate_dashboard(output.clone()).await;
+    }
+    // Route sheets edit the configuration file rather than run against it
+    if let Some(Commands::Routes { action }) = &cli.command {
+        return run_routes(cli.config.as_deref(), action);
+    }
+
     // Load configuration
     let config = load_configuration(&cli).await?;
     
@@ -68,21 +160,31 @@ async fn main() -> Result<()> {
 
     // Handle commands
     match &cli.command {
+        Some(Commands::Start) | None if cli.demo => {
+            run_demo(config).await
+        }
         Some(Commands::Start) | None => {
-            run_gateway(config, cli.daemon).await
+            run_gateway(config, cli.config.clone(), cli.daemon).await
         }
         Some(Commands::Stop) => {
-            stop_gateway().await
+            stop_gateway(&config).await
         }
         Some(Commands::Status) => {
-            show_status().await
+            show_status(&config).await
         }
-        Some(Commands::ValidateConfig) => {
-            validate_configuration(&config).await
+        Some(Commands::ValidateConfig { show_merged }) => {
+            validate_configuration(&config).await?;
+            if *show_merged {
+                show_merged_configuration(cli.config.as_deref())?;
+            }
+            Ok(())
         }
         Some(Commands::GenerateConfig { output }) => {
             generate_default_config(output.clone()).await
         }
+        Some(Commands::Setup { .. }) => unreachable!("setup is handled before configuration is loaded"),
+        Some(Commands::Metrics { .. }) => unreachable!("metrics commands are handled before configuration is loaded"),
+        Some(Commands::Routes { .. }) => unreachable!("route commands are handled before configuration is loaded"),
     }
 }
 
@@ -108,18 +210,34 @@ async fn load_configuration(cli: &Cli) -> Result<GatewayConfig> {
     Ok(config)
 }
 
-async fn run_gateway(config: GatewayConfig, daemon: bool) -> Result<()> {
+async fn run_gateway(config: GatewayConfig, config_path: Option<PathBuf>, daemon: bool) -> Result<()> {
     info!("Initializing Redfire Gateway");
 
     // Create and start gateway
+    let management_api = config.management_api.clone();
+    let heartbeat = config.heartbeat.clone();
+    let licensed_calls = config.general.max_calls;
+    let provisioning_path = config_path.clone();
     let mut gateway = RedFireGateway::new(config)?;
     
     // Take the event receiver before starting
     let mut event_rx = gateway.take_event_receiver()
-        .ok_or_else(|| redfire_gateway::Error::internal("Failed to get event receiver"))?;
+        .ok_or_else(|| redfire_gateway::Error::invalid_state("Failed to get event receiver"))?;
+
+    // Start the gateway; a unit failing its self-test stays reachable on the craft console
+    let craft_rx = match gateway.start().await {
+        Ok(()) => gateway.take_craft_receiver(),
+        Err(e) => match gateway.take_craft_receiver() {
+            Some(craft_rx) => {
+                error!("Gateway not in service: {}", e);
+                Some(craft_rx)
+            }
+            None => return Err(e),
+        },
+    };
 
-    // Start the gateway
-    gateway.start().await?;
+    let control_rx = gateway.take_control_receiver();
+    let tandem_rx = gateway.take_tandem_receiver();
 
     // Handle daemon mode
     if daemon {
@@ -131,6 +249,110 @@ async fn run_gateway(config: GatewayConfig, daemon: bool) -> Result<()> {
     let gateway = Arc::new(tokio::sync::Mutex::new(gateway));
     let gateway_shutdown = Arc::clone(&gateway);
 
+    // Carry out craft console requests and keep its status current
+    if let Some(mut craft_rx) = craft_rx {
+        let gateway_craft = Arc::clone(&gateway);
+        tokio::spawn(async move {
+            let mut refresh = tokio::time::interval(Duration::from_secs(5));
+            loop {
+                tokio::select! {
+                    request = craft_rx.recv() => {
+                        let Some(request) = request else { break };
+                        let mut gateway = gateway_craft.lock().await;
+                        if let Err(e) = gateway.handle_craft_request(request, config_path.as_deref()).await {
+                            error!("Craft console request failed: {}", e);
+                        }
+                    }
+                    _ = refresh.tick() => gateway_craft.lock().await.refresh_craft_status().await,
+                }
+            }
+        });
+    }
+
+    // Place, answer and clear tandem calls on their spans
+    if let Some(mut tandem_rx) = tandem_rx {
+        let gateway_tandem = Arc::clone(&gateway);
+        tokio::spawn(async move {
+            while let Some(event) = tandem_rx.recv().await {
+                gateway_tandem.lock().await.handle_tandem_event(event).await;
+            }
+        });
+    }
+
+    // Answer the stop and status commands
+    let stop_requested = Arc::new(tokio::sync::Notify::new());
+    if let Some(mut control_rx) = control_rx {
+        let gateway_control = Arc::clone(&gateway);
+        let stop_requested = Arc::clone(&stop_requested);
+        tokio::spawn(async move {
+            while let Some(command) = control_rx.recv().await {
+                match command.request {
+                    ControlRequest::Status => {
+                        let status = gateway_control.lock().await.control_status().await;
+                        command.respond(ControlResponse::Status(status));
+                    }
+                    ControlRequest::Stop => {
+                        command.respond(ControlResponse::Stopping);
+                        stop_requested.notify_one();
+                    }
+                }
+            }
+        });
+    }
+
+    // Serve gateway state to redfire-diag and integrators
+    if management_api.enabled {
+        let source: Arc<dyn ManagementSource> = gateway.clone();
+        match ManagementApi::bind(&management_api, source).await {
+            Ok(mut api) => {
+                match (provisioning_path, management_api.provisioning) {
+                    (Some(path), true) => api = api.with_provisioning(path),
+                    (None, true) => warn!("Route provisioning needs a configuration file (--config)"),
+                    _ => {}
+                }
+                api.spawn();
+            }
+            Err(e) => warn!("Management API not available: {}", e),
+        }
+    }
+
+    // Tell load balancers and wallboards whether the gateway is in service
+    if management_api.status_page.enabled {
+        let source: Arc<dyn ManagementSource> = gateway.clone();
+        match StatusPage::bind(&management_api.status_page, source).await {
+            Ok(page) => {
+                page.spawn();
+            }
+            Err(e) => warn!("Status page not available: {}", e),
+        }
+    }
+
+    // Report to the fleet controller so managed units need not be polled
+    if heartbeat.enabled {
+        let source: Arc<dyn ManagementSource> = gateway.clone();
+        match Heartbeat::new(heartbeat, licensed_calls, source) {
+            Ok(heartbeat) => {
+                Arc::new(heartbeat).spawn();
+            }
+            Err(e) => warn!("Fleet heartbeat not available: {}", e),
+        }
+    }
+
+    // Restart failed spans as their backoff expires, follow hot-swapped cards,
+    // sample channel usage for capacity planning and run maintenance rules
+    let gateway_recovery = Arc::clone(&gateway);
+    tokio::spawn(async move {
+        let mut poll = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            poll.tick().await;
+            let mut gateway = gateway_recovery.lock().await;
+            gateway.poll_span_recovery().await;
+            gateway.poll_hardware_changes().await;
+            gateway.poll_capacity();
+            gateway.poll_automation().await;
+        }
+    });
+
     // Handle events
     let event_task = tokio::spawn(async move {
         while let Some(event) = event_rx.recv().await {
@@ -140,17 +362,19 @@ async fn run_gateway(config: GatewayConfig, daemon: bool) -> Result<()> {
 
     // Handle shutdown signals
     let shutdown_task = tokio::spawn(async move {
-        match signal::ctrl_c().await {
-            Ok(()) => {
-                info!("Received Ctrl+C, shutting down gracefully");
-                let mut gateway = gateway_shutdown.lock().await;
-                if let Err(e) = gateway.stop().await {
-                    error!("Error during shutdown: {}", e);
+        tokio::select! {
+            result = signal::ctrl_c() => match result {
+                Ok(()) => info!("Received Ctrl+C, shutting down gracefully"),
+                Err(err) => {
+                    error!("Unable to listen for shutdown signal: {}", err);
+                    return;
                 }
-            }
-            Err(err) => {
-                error!("Unable to listen for shutdown signal: {}", err);
-            }
+            },
+            _ = stop_requested.notified() => info!("Stop requested on the control socket, shutting down gracefully"),
+        }
+        let mut gateway = gateway_shutdown.lock().await;
+        if let Err(e) = gateway.stop().await {
+            error!("Error during shutdown: {}", e);
         }
     });
 
@@ -174,6 +398,83 @@ async fn run_gateway(config: GatewayConfig, daemon: bool) -> Result<()> {
     Ok(())
 }
 
+/// Serve synthetic traffic on the management API and control socket, with
+/// no interfaces behind it
+async fn run_demo(config: GatewayConfig) -> Result<()> {
+    info!("Running in demo mode: spans, calls, alarms and clocks are simulated");
+    let demo = Arc::new(DemoGateway::new(&config.general.node_id));
+    let ticks = demo.spawn(DEMO_TICK);
+
+    // The API is what demo mode is for, so it is served even if disabled
+    let source: Arc<dyn ManagementSource> = demo.clone();
+    let api = ManagementApi::bind(&config.management_api, source).await?;
+    let api_task = api.spawn();
+    let status_page = &config.management_api.status_page;
+    let status_page_task = if status_page.enabled {
+        Some(StatusPage::bind(status_page, demo.clone()).await?.spawn())
+    } else {
+        None
+    };
+
+    #[cfg(feature = "snmp")]
+    let _snmp = if config.snmp.enabled {
+        let mut snmp = redfire_gateway::services::SnmpService::new(config.snmp.clone());
+        match snmp.start().await {
+            Ok(()) => Some(snmp),
+            Err(e) => {
+                warn!("SNMP agent not available: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let stop_requested = Arc::new(tokio::sync::Notify::new());
+    let control_task = match control::ControlServer::bind(&config.general.control_socket).await {
+        Ok((server, mut control_rx)) => {
+            let demo = Arc::clone(&demo);
+            let stop_requested = Arc::clone(&stop_requested);
+            tokio::spawn(async move {
+                while let Some(command) = control_rx.recv().await {
+                    match command.request {
+                        ControlRequest::Status => command.respond(ControlResponse::Status(demo.status().await)),
+                        ControlRequest::Stop => {
+                            command.respond(ControlResponse::Stopping);
+                            stop_requested.notify_one();
+                        }
+                    }
+                }
+            });
+            Some(server.spawn())
+        }
+        Err(e) => {
+            warn!("Control socket not available: {}", e);
+            None
+        }
+    };
+
+    tokio::select! {
+        result = signal::ctrl_c() => {
+            result?;
+            info!("Received Ctrl+C, leaving demo mode");
+        }
+        _ = stop_requested.notified() => info!("Stop requested on the control socket, leaving demo mode"),
+    }
+
+    ticks.abort();
+    api_task.abort();
+    if let Some(task) = status_page_task {
+        task.abort();
+    }
+    if let Some(task) = control_task {
+        task.abort();
+        // Dropping the server removes the socket file
+        let _ = task.await;
+    }
+    Ok(())
+}
+
 async fn handle_gateway_event(event: redfire_gateway::core::gateway::GatewayEvent) {
     use redfire_gateway::core::gateway::GatewayEvent;
 
@@ -199,20 +500,51 @@ async fn handle_gateway_event(event: redfire_gateway::core::gateway::GatewayEven
         GatewayEvent::Error { message } => {
             error!("✗ Gateway error: {}", message);
         }
+        other => {
+            info!("Gateway event: {:?}", other);
+        }
     }
 }
 
-async fn stop_gateway() -> Result<()> {
-    // In a real implementation, this would connect to a running instance
-    // and send a shutdown signal (e.g., via Unix socket or signal)
-    println!("Stop command not implemented (send SIGTERM to running process)");
-    Ok(())
+async fn stop_gateway(config: &GatewayConfig) -> Result<()> {
+    match control::send_request(&config.general.control_socket, ControlRequest::Stop).await? {
+        ControlResponse::Stopping => {
+            println!("✓ Gateway is shutting down");
+            Ok(())
+        }
+        ControlResponse::Error { message } => Err(redfire_gateway::Error::invalid_state(message)),
+        other => Err(redfire_gateway::Error::protocol(format!("Unexpected response to stop: {:?}", other))),
+    }
 }
 
-async fn show_status() -> Result<()> {
-    // In a real implementation, this would connect to a running instance
-    // and query its status
-    println!("Status command not implemented");
+async fn show_status(config: &GatewayConfig) -> Result<()> {
+    let status = match control::send_request(&config.general.control_socket, ControlRequest::Status).await? {
+        ControlResponse::Status(status) => status,
+        ControlResponse::Error { message } => return Err(redfire_gateway::Error::invalid_state(message)),
+        other => return Err(redfire_gateway::Error::protocol(format!("Unexpected response to status: {:?}", other))),
+    };
+
+    let uptime = status.uptime_secs;
+    println!("{} v{}: {}", status.node_id, status.version, if status.running { "running" } else { "not in service" });
+    println!("  Uptime: {}d {:02}h {:02}m {:02}s", uptime / 86400, uptime / 3600 % 24, uptime / 60 % 60, uptime % 60);
+    println!("  Active calls: {}", status.active_calls);
+    println!("  Active channels: {}", status.active_channels);
+    println!("  SIP sessions: {}", status.sip_sessions);
+    println!("  RTP sessions: {}", status.rtp_sessions);
+    if status.spans.is_empty() {
+        println!("  Spans: none");
+    }
+    for span in &status.spans {
+        println!(
+            "  Span {} ({}): {}, {}/{} channels busy{}",
+            span.span_id,
+            span.name,
+            if span.up { "up" } else { "down" },
+            span.busy_channels,
+            span.channels,
+            if span.alarms.is_empty() { String::new() } else { format!(", alarms: {}", span.alarms.join(", ")) }
+        );
+    }
     Ok(())
 }
 
@@ -233,6 +565,18 @@ async fn validate_configuration(config: &GatewayConfig) -> Result<()> {
     Ok(())
 }
 
+fn show_merged_configuration(config_path: Option<&Path>) -> Result<()> {
+    let config_path = config_path
+        .ok_or_else(|| redfire_gateway::Error::invalid_config("--show-merged needs a configuration file (--config)"))?;
+    let merged = GatewayConfig::load_merged(config_path)?;
+    let toml_content = toml::to_string_pretty(&merged)
+        .map_err(|e| redfire_gateway::Error::internal(format!("Failed to serialize config: {}", e)))?;
+    println!();
+    println!("# Merged from {} and its includes", config_path.display());
+    print!("{}", toml_content);
+    Ok(())
+}
+
 async fn generate_default_config(output_path: Option<PathBuf>) -> Result<()> {
     let config = GatewayConfig::default_config();
     let toml_content = toml::to_string_pretty(&config)
@@ -251,6 +595,98 @@ async fn generate_default_config(output_path: Option<PathBuf>) -> Result<()> {
     Ok(())
 }
 
+fn run_routes(config_path: Option<&Path>, action: &RoutesAction) -> Result<()> {
+    let config_path = config_path
+        .ok_or_else(|| redfire_gateway::Error::invalid_config("Route sheets need a configuration file (--config)"))?;
+    let (csv, trunks) = match action {
+        RoutesAction::Export { csv, trunks, .. } | RoutesAction::Import { csv, trunks, .. } => (*csv, *trunks),
+    };
+    if !csv {
+        return Err(redfire_gateway::Error::not_supported("Route sheets are CSV; pass --csv"));
+    }
+    let table = if trunks { ProvisioningTable::Trunks } else { ProvisioningTable::Routes };
+
+    match action {
+        RoutesAction::Export { output, .. } => {
+            let sheet = table.to_csv(&GatewayConfig::load_from_file(config_path)?);
+            match output {
+                Some(path) => {
+                    std::fs::write(path, sheet)?;
+                    println!("✓ {} written to: {}", table, path.display());
+                }
+                None => print!("{}", sheet),
+            }
+            Ok(())
+        }
+        RoutesAction::Import { file, dry_run, .. } => {
+            let sheet = std::fs::read_to_string(file)?;
+            let report = route_provisioning::import(config_path, table, &sheet, *dry_run)?;
+            if !report.is_valid() {
+                for problem in &report.problems {
+                    println!("✗ {}", problem);
+                }
+                return Err(redfire_gateway::Error::invalid_config(format!(
+                    "{} has {} problem(s); {} was not changed",
+                    file.display(),
+                    report.problems.len(),
+                    config_path.display()
+                )));
+            }
+            println!("{}", report.diff);
+            if report.applied {
+                println!("✓ {} imported into {}; restart the gateway to use them", table, config_path.display());
+            } else if *dry_run {
+                println!("Dry run: {} was not changed", config_path.display());
+            } else {
+                println!("✓ {} already match {}", table, config_path.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn generate_dashboard(output_path: Option<PathBuf>) -> Result<()> {
+    let dashboard = grafana_dashboard(&MetricsRegistry::new().descriptors());
+    let json = serde_json::to_string_pretty(&dashboard)
+        .map_err(|e| redfire_gateway::Error::internal(format!("Failed to serialize dashboard: {}", e)))?;
+
+    match output_path {
+        Some(path) => {
+            std::fs::write(&path, json)?;
+            println!("✓ Grafana dashboard written to: {}", path.display());
+        }
+        None => {
+            println!("{}", json);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_setup(output_path: &Path) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut wizard = SetupWizard::new(stdin.lock(), std::io::stdout());
+
+    let config = loop {
+        match wizard.run()?.to_config() {
+            Ok(config) => break config,
+            Err(e) => println!("✗ {}; please go through the questions again", e),
+        }
+    };
+    if output_path.exists() && !wizard.confirm(&format!("{} exists. Overwrite it?", output_path.display()))? {
+        println!("Configuration not written");
+        return Ok(());
+    }
+
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(output_path, render_commented_toml(&config)?)?;
+    println!("✓ Configuration written to: {}", output_path.display());
+    println!("  Start the gateway with: redfire-gateway --config {}", output_path.display());
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,225 @@
+//! ICE-lite agent for media relay sessions (RFC 8445 §2.5)
+//!
+//! The relay has a public address, so it gathers a host candidate and,
+//! when a STUN server is configured, a server-reflexive one, but never
+//! runs checks of its own. It answers the full agent's connectivity checks
+//! and sends media to the address the nominated check came from.
+
+use std::fmt;
+use std::net::SocketAddr;
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use crate::protocols::stun::{self, StunMessage};
+
+/// ICE type preferences (RFC 8445 §5.1.2.2)
+const HOST_PREFERENCE: u32 = 126;
+const SERVER_REFLEXIVE_PREFERENCE: u32 = 100;
+const LOCAL_PREFERENCE: u32 = 65535;
+
+/// Username fragment and password of one side of an ICE session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceCredentials {
+    pub ufrag: String,
+    pub pwd: String,
+}
+
+impl IceCredentials {
+    pub fn new(ufrag: impl Into<String>, pwd: impl Into<String>) -> Self {
+        Self { ufrag: ufrag.into(), pwd: pwd.into() }
+    }
+
+    pub fn generate() -> Self {
+        let random = |len| rand::thread_rng().sample_iter(&Alphanumeric).take(len).map(char::from).collect::<String>();
+        Self { ufrag: random(8), pwd: random(24) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateType {
+    Host,
+    ServerReflexive,
+}
+
+impl CandidateType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandidateType::Host => "host",
+            CandidateType::ServerReflexive => "srflx",
+        }
+    }
+}
+
+/// A local candidate for the RTP component, written as an `a=candidate` value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceCandidate {
+    pub foundation: String,
+    pub priority: u32,
+    pub address: SocketAddr,
+    pub kind: CandidateType,
+    /// Base a server-reflexive candidate was learned from
+    pub related: Option<SocketAddr>,
+}
+
+impl IceCandidate {
+    pub fn host(address: SocketAddr) -> Self {
+        Self {
+            foundation: "1".to_string(),
+            priority: Self::priority(HOST_PREFERENCE),
+            address,
+            kind: CandidateType::Host,
+            related: None,
+        }
+    }
+
+    pub fn server_reflexive(address: SocketAddr, base: SocketAddr) -> Self {
+        Self {
+            foundation: "2".to_string(),
+            priority: Self::priority(SERVER_REFLEXIVE_PREFERENCE),
+            address,
+            kind: CandidateType::ServerReflexive,
+            related: Some(base),
+        }
+    }
+
+    /// Candidate priority for component 1 (RFC 8445 §5.1.2.1)
+    fn priority(type_preference: u32) -> u32 {
+        (type_preference << 24) + (LOCAL_PREFERENCE << 8) + (256 - 1)
+    }
+}
+
+impl fmt::Display for IceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} 1 udp {} {} {} typ {}",
+            self.foundation,
+            self.priority,
+            self.address.ip(),
+            self.address.port(),
+            self.kind.as_str()
+        )?;
+        if let Some(related) = self.related {
+            write!(f, " raddr {} rport {}", related.ip(), related.port())?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a connectivity check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceCheck {
+    /// Binding response to send back to the check's source
+    pub response: Vec<u8>,
+    /// The full agent nominated the source as the pair to use
+    pub nominated: bool,
+}
+
+/// Lite agent answering the checks of one media session
+#[derive(Debug, Clone)]
+pub struct IceLite {
+    local: IceCredentials,
+    remote: IceCredentials,
+    selected: Option<SocketAddr>,
+}
+
+impl IceLite {
+    pub fn new(local: IceCredentials, remote: IceCredentials) -> Self {
+        Self { local, remote, selected: None }
+    }
+
+    pub fn local_credentials(&self) -> &IceCredentials {
+        &self.local
+    }
+
+    /// Address of the nominated pair, once there is one
+    pub fn selected(&self) -> Option<SocketAddr> {
+        self.selected
+    }
+
+    /// Answer a Binding request received from `source`. Requests not meant
+    /// for this session, or failing their integrity check, get no response.
+    pub fn handle_request(&mut self, data: &[u8], source: SocketAddr) -> Option<IceCheck> {
+        let request = StunMessage::parse(data).ok()?;
+        if request.message_type != stun::BINDING_REQUEST {
+            return None;
+        }
+        let expected = format!("{}:{}", self.local.ufrag, self.remote.ufrag);
+        if request.username() != Some(expected.as_str())
+            || !stun::verify_integrity(data, self.local.pwd.as_bytes())
+            || !stun::verify_fingerprint(data)
+        {
+            return None;
+        }
+
+        // A lite agent is always controlled; USE-CANDIDATE nominates the pair
+        let nominated = request.has_attribute(stun::ATTR_USE_CANDIDATE);
+        if nominated {
+            self.selected = Some(source);
+        }
+
+        let mut response = StunMessage::new(stun::BINDING_SUCCESS, request.transaction_id);
+        response.add_xor_mapped_address(source);
+        Some(IceCheck {
+            response: response.encode(Some(self.local.pwd.as_bytes()), true),
+            nominated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let host = IceCandidate::host("192.0.2.10:20000".parse().unwrap());
+        assert_eq!(host.to_string(), "1 1 udp 2130706431 192.0.2.10 20000 typ host");
+        let srflx = IceCandidate::server_reflexive("198.51.100.7:40000".parse().unwrap(), host.address);
+        assert_eq!(
+            srflx.to_string(),
+            "2 1 udp 1694498815 198.51.100.7 40000 typ srflx raddr 192.0.2.10 rport 20000"
+        );
+
+        let credentials = IceCredentials::generate();
+        assert_eq!((credentials.ufrag.len(), credentials.pwd.len()), (8, 24));
+    }
+
+    #[test]
+    fn test_connectivity_checks() {
+        let local = IceCredentials::new("relayufr", "relaypasswordrelaypasswo");
+        let remote = IceCredentials::new("brws", "browserpassword");
+        let mut agent = IceLite::new(local.clone(), remote.clone());
+        let source: SocketAddr = "203.0.113.5:51000".parse().unwrap();
+
+        let check = |use_candidate: bool, username: &str, key: &str| {
+            let mut request = StunMessage::binding_request();
+            request.add_attribute(stun::ATTR_USERNAME, username.as_bytes().to_vec());
+            request.add_attribute(stun::ATTR_ICE_CONTROLLING, vec![0; 8]);
+            if use_candidate {
+                request.add_attribute(stun::ATTR_USE_CANDIDATE, Vec::new());
+            }
+            (request.transaction_id, request.encode(Some(key.as_bytes()), true))
+        };
+
+        let (_, wrong_user) = check(false, "other:brws", &local.pwd);
+        assert!(agent.handle_request(&wrong_user, source).is_none());
+        let (_, wrong_key) = check(false, "relayufr:brws", &remote.pwd);
+        assert!(agent.handle_request(&wrong_key, source).is_none());
+
+        let (transaction_id, request) = check(false, "relayufr:brws", &local.pwd);
+        let result = agent.handle_request(&request, source).unwrap();
+        assert!(!result.nominated);
+        assert_eq!(agent.selected(), None);
+        assert!(stun::verify_integrity(&result.response, local.pwd.as_bytes()));
+        let response = StunMessage::parse(&result.response).unwrap();
+        assert_eq!(response.message_type, stun::BINDING_SUCCESS);
+        assert_eq!(response.transaction_id, transaction_id);
+        assert_eq!(response.xor_mapped_address(), Some(source));
+
+        let (_, request) = check(true, "relayufr:brws", &local.pwd);
+        assert!(agent.handle_request(&request, source).unwrap().nominated);
+        assert_eq!(agent.selected(), Some(source));
+    }
+}
@@ -0,0 +1,304 @@
+//! Multipart MIME bodies in SIP (RFC 5621)
+//!
+//! SIP-I and SIP-T carry the ISUP message a call was set up with next to the
+//! SDP offer in a multipart/mixed body (RFC 3204), and QSIG is tunnelled the
+//! same way. Parts other than SDP are kept as opaque bytes so the B2BUA can
+//! pass them through unchanged and hand them to the SS7 or QSIG side.
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::protocols::q931::Q931Message;
+use crate::{Error, Result};
+
+pub const CONTENT_TYPE_SDP: &str = "application/sdp";
+pub const CONTENT_TYPE_ISUP: &str = "application/isup";
+pub const CONTENT_TYPE_QSIG: &str = "application/qsig";
+pub const CONTENT_TYPE_MULTIPART: &str = "multipart/mixed";
+
+/// ISUP variant advertised on generated ISUP parts
+pub const ISUP_VERSION: &str = "itu-t92+";
+
+/// Disposition of encapsulated signalling; receivers that cannot use it may ignore it
+const SIGNAL_DISPOSITION: &str = "signal; handling=optional";
+
+/// One part of a SIP message body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BodyPart {
+    /// Full Content-Type value, parameters included
+    pub content_type: String,
+    /// Other part headers, e.g. Content-Disposition
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl BodyPart {
+    pub fn sdp(sdp: &str) -> Self {
+        Self {
+            content_type: CONTENT_TYPE_SDP.to_string(),
+            headers: Vec::new(),
+            body: sdp.as_bytes().to_vec(),
+        }
+    }
+
+    /// Encoded ISUP message for SIP-I/SIP-T
+    pub fn isup(message: &[u8]) -> Self {
+        Self {
+            content_type: format!("{}; version={}", CONTENT_TYPE_ISUP, ISUP_VERSION),
+            headers: vec![("Content-Disposition".to_string(), SIGNAL_DISPOSITION.to_string())],
+            body: message.to_vec(),
+        }
+    }
+
+    /// Tunnelled QSIG message
+    pub fn qsig(message: &Q931Message) -> Self {
+        Self {
+            content_type: CONTENT_TYPE_QSIG.to_string(),
+            headers: vec![("Content-Disposition".to_string(), SIGNAL_DISPOSITION.to_string())],
+            body: message.encode().to_vec(),
+        }
+    }
+
+    /// Media type without parameters, lower-cased
+    pub fn media_type(&self) -> String {
+        self.content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+    }
+
+    pub fn is(&self, media_type: &str) -> bool {
+        self.media_type() == media_type
+    }
+
+    /// The tunnelled QSIG message, if this is a QSIG part
+    pub fn qsig_message(&self) -> Option<Result<Q931Message>> {
+        self.is(CONTENT_TYPE_QSIG).then(|| Q931Message::decode(&self.body))
+    }
+}
+
+/// A multipart/mixed body
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartBody {
+    pub boundary: String,
+    pub parts: Vec<BodyPart>,
+}
+
+impl MultipartBody {
+    pub fn new(parts: Vec<BodyPart>) -> Self {
+        Self {
+            boundary: format!("redfire-{}", Uuid::new_v4().simple()),
+            parts,
+        }
+    }
+
+    /// Content-Type header value for the whole body
+    pub fn content_type(&self) -> String {
+        format!("{};boundary={}", CONTENT_TYPE_MULTIPART, self.boundary)
+    }
+
+    pub fn parse(content_type: &str, body: &[u8]) -> Result<Self> {
+        let boundary = boundary_param(content_type)
+            .ok_or_else(|| Error::parse("Multipart body without a boundary parameter"))?;
+        let delimiter = format!("--{}", boundary).into_bytes();
+
+        // Delimiters only count at the start of a line
+        let starts: Vec<usize> = find_all(body, &delimiter)
+            .into_iter()
+            .filter(|&pos| pos == 0 || body[pos - 1] == b'\n')
+            .collect();
+
+        let mut parts = Vec::new();
+        let mut closed = false;
+        for (index, &start) in starts.iter().enumerate() {
+            let after = &body[start + delimiter.len()..];
+            if after.starts_with(b"--") {
+                closed = true;
+                break;
+            }
+            let Some(end) = starts.get(index + 1) else {
+                break;
+            };
+            let line_end = after
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .ok_or_else(|| Error::parse("Truncated multipart delimiter line"))?;
+            let content_start = start + delimiter.len() + line_end + 1;
+            let content = strip_line_break(&body[content_start.min(*end)..*end]);
+            parts.push(parse_part(content)?);
+        }
+
+        if !closed {
+            return Err(Error::parse("Multipart body has no closing delimiter"));
+        }
+        Ok(Self { boundary, parts })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for part in &self.parts {
+            out.extend_from_slice(format!("--{}\r\n", self.boundary).as_bytes());
+            out.extend_from_slice(format!("Content-Type: {}\r\n", part.content_type).as_bytes());
+            for (name, value) in &part.headers {
+                out.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+            }
+            out.extend_from_slice(b"\r\n");
+            out.extend_from_slice(&part.body);
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        out
+    }
+
+    pub fn sdp(&self) -> Option<String> {
+        self.parts
+            .iter()
+            .find(|part| part.is(CONTENT_TYPE_SDP))
+            .map(|part| String::from_utf8_lossy(&part.body).into_owned())
+    }
+
+    /// Every part other than the SDP
+    pub fn encapsulated(&self) -> Vec<BodyPart> {
+        self.parts.iter().filter(|part| !part.is(CONTENT_TYPE_SDP)).cloned().collect()
+    }
+}
+
+/// Split a received body into its SDP and any encapsulated signalling
+pub fn split_body(content_type: Option<&str>, body: &[u8]) -> Result<(Option<String>, Vec<BodyPart>)> {
+    let Some(content_type) = content_type.filter(|_| !body.is_empty()) else {
+        return Ok((None, Vec::new()));
+    };
+    let part = BodyPart {
+        content_type: content_type.to_string(),
+        headers: Vec::new(),
+        body: body.to_vec(),
+    };
+    if part.is(CONTENT_TYPE_MULTIPART) {
+        let multipart = MultipartBody::parse(content_type, body)?;
+        Ok((multipart.sdp(), multipart.encapsulated()))
+    } else if part.is(CONTENT_TYPE_SDP) {
+        Ok((Some(String::from_utf8_lossy(body).into_owned()), Vec::new()))
+    } else {
+        Ok((None, vec![part]))
+    }
+}
+
+/// Content-Type and body for an outgoing request; multipart only when
+/// there is signalling to carry next to the SDP
+pub fn build_body(sdp: Option<&str>, encapsulated: &[BodyPart]) -> Option<(String, Vec<u8>)> {
+    match (sdp, encapsulated) {
+        (None, []) => None,
+        (Some(sdp), []) => Some((CONTENT_TYPE_SDP.to_string(), sdp.as_bytes().to_vec())),
+        (None, [only]) => Some((only.content_type.clone(), only.body.clone())),
+        (sdp, parts) => {
+            let mut all: Vec<BodyPart> = sdp.map(BodyPart::sdp).into_iter().collect();
+            all.extend_from_slice(parts);
+            let multipart = MultipartBody::new(all);
+            Some((multipart.content_type(), multipart.encode()))
+        }
+    }
+}
+
+fn boundary_param(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|boundary| !boundary.is_empty())
+    })
+}
+
+fn find_all(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return Vec::new();
+    }
+    (0..=haystack.len() - needle.len())
+        .filter(|&pos| &haystack[pos..pos + needle.len()] == needle)
+        .collect()
+}
+
+/// Drop the line break that belongs to the following delimiter
+fn strip_line_break(content: &[u8]) -> &[u8] {
+    content
+        .strip_suffix(b"\r\n")
+        .or_else(|| content.strip_suffix(b"\n"))
+        .unwrap_or(content)
+}
+
+fn parse_part(content: &[u8]) -> Result<BodyPart> {
+    let (header_block, body) = match find_all(content, b"\r\n\r\n").first() {
+        Some(&pos) => (&content[..pos], &content[pos + 4..]),
+        None => match find_all(content, b"\n\n").first() {
+            Some(&pos) => (&content[..pos], &content[pos + 2..]),
+            // No headers at all: the part defaults to text/plain
+            None if content.starts_with(b"\r\n") => (&content[..0], &content[2..]),
+            None => return Err(Error::parse("Multipart body part without header terminator")),
+        },
+    };
+
+    let header_text = std::str::from_utf8(header_block).map_err(|_| Error::parse("Non-text multipart part headers"))?;
+    let mut content_type = "text/plain".to_string();
+    let mut headers = Vec::new();
+    for line in header_text.lines().filter(|line| !line.trim().is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::parse(format!("Malformed multipart part header: {}", line)))?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("Content-Type") {
+            content_type = value.to_string();
+        } else {
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    Ok(BodyPart {
+        content_type,
+        headers,
+        body: body.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::q931::MessageType;
+
+    const SDP: &str = "v=0\r\no=- 1 1 IN IP4 192.0.2.1\r\ns=-\r\nc=IN IP4 192.0.2.1\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n";
+
+    #[test]
+    fn test_parse_sip_i_body() {
+        // IAM with octets that would break a text parser
+        let iam = [0x01, 0x00, 0x49, 0x00, 0x00, 0x03, 0x02, 0x00, 0x07, 0x0d, 0x0a];
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--unique-boundary-1\r\nContent-Type: application/sdp\r\n\r\n");
+        body.extend_from_slice(SDP.as_bytes());
+        body.extend_from_slice(b"\r\n--unique-boundary-1\r\nContent-Type: application/isup; version=itu-t92+\r\n");
+        body.extend_from_slice(b"Content-Disposition: signal; handling=optional\r\n\r\n");
+        body.extend_from_slice(&iam);
+        body.extend_from_slice(b"\r\n--unique-boundary-1--\r\n");
+
+        let (sdp, encapsulated) = split_body(Some("multipart/mixed; boundary=\"unique-boundary-1\""), &body).unwrap();
+        assert_eq!(sdp.as_deref(), Some(SDP));
+        assert_eq!(encapsulated.len(), 1);
+        assert!(encapsulated[0].is(CONTENT_TYPE_ISUP));
+        assert_eq!(encapsulated[0].body, iam);
+        assert_eq!(encapsulated[0].headers, vec![("Content-Disposition".to_string(), SIGNAL_DISPOSITION.to_string())]);
+
+        assert!(split_body(Some("multipart/mixed"), &body).is_err());
+        assert_eq!(split_body(Some(CONTENT_TYPE_SDP), SDP.as_bytes()).unwrap(), (Some(SDP.to_string()), vec![]));
+    }
+
+    #[test]
+    fn test_generated_qsig_body_round_trips() {
+        let setup = Q931Message::new(MessageType::Setup, 7, false).with_called_number("4000");
+        let (content_type, body) = build_body(Some(SDP), &[BodyPart::qsig(&setup)]).unwrap();
+        assert!(content_type.starts_with("multipart/mixed;boundary="));
+
+        let parsed = MultipartBody::parse(&content_type, &body).unwrap();
+        assert_eq!(parsed.sdp().as_deref(), Some(SDP));
+        let tunnelled = parsed.encapsulated()[0].qsig_message().unwrap().unwrap();
+        assert_eq!(tunnelled.encode(), setup.encode());
+
+        assert_eq!(build_body(Some(SDP), &[]), Some((CONTENT_TYPE_SDP.to_string(), SDP.as_bytes().to_vec())));
+        assert_eq!(build_body(None, &[]), None);
+    }
+}
@@ -1,14 +1,45 @@
 //! Protocol implementations for the Redfire Gateway
 
 pub mod sip;
+pub mod sip_transport;
+pub mod sip_limits;
+pub mod sip_message;
+pub mod sip_connection;
+pub mod sip_registrar;
+pub mod sip_tls;
+pub mod sip_ws;
+pub mod sip_time;
+pub mod sip_via;
 pub mod rtp;
+pub mod rtcp_xr;
+pub mod rtp_socket;
+pub mod srtp;
+pub mod stun;
+pub mod ice;
+pub mod webrtc;
 pub mod pri;
 pub mod sigtran;
 pub mod dtmf;
+#[cfg(feature = "tr069")]
 pub mod tr069;
+pub mod sdp;
+pub mod udptl;
+pub mod q931;
+pub mod mime;
 
 pub use sip::SipHandler;
+pub use sip_registrar::{Binding, CredentialStore, Registrar, Registration, RegistrationReply};
+pub use sip_tls::{SipTlsListener, StreamConnections, StreamMessage};
+#[cfg(feature = "websocket")]
+pub use sip_ws::SipWsListener;
+pub use sip_ws::{WsConnections, WsMessage};
 pub use rtp::RtpHandler;
+pub use rtcp_xr::{ExtendedReport, LossTracker, VoipMetrics};
+pub use rtp_socket::{BatchedUdpSocket, RtpSocketStats};
+pub use srtp::{CryptoAttribute, SrtpProfile, SrtpSession};
 pub use pri::PriEmulator;
 pub use sigtran::SigtranHandler;
-pub use tr069::Tr069Service;
\ No newline at end of file
+#[cfg(feature = "tr069")]
+pub use tr069::Tr069Service;
+pub use sdp::SessionDescription;
+pub use q931::Q931Message;
\ No newline at end of file
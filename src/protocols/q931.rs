@@ -0,0 +1,305 @@
+//! Q.931 call control message encoding (ITU-T Q.931 / ETSI EN 300 403)
+//!
+//! Covers the basic call messages, the Q.932 hold and retrieve messages and
+//! the information elements needed to place, answer, hold and clear calls on
+//! a PRI. Messages travel as the payload of TDMoE control frames on the
+//! D-channel timeslot.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{Error, Result};
+
+const PROTOCOL_DISCRIMINATOR: u8 = 0x08;
+
+/// Information element identifiers
+pub const IE_BEARER_CAPABILITY: u8 = 0x04;
+pub const IE_CAUSE: u8 = 0x08;
+pub const IE_CHANNEL_ID: u8 = 0x18;
+pub const IE_FACILITY: u8 = 0x1c;
+pub const IE_PROGRESS_INDICATOR: u8 = 0x1e;
+pub const IE_DISPLAY: u8 = 0x28;
+pub const IE_CALLING_NUMBER: u8 = 0x6c;
+pub const IE_CALLED_NUMBER: u8 = 0x70;
+pub const IE_REDIRECTING_NUMBER: u8 = 0x74;
+pub const IE_USER_USER: u8 = 0x7e;
+
+/// Q.850 causes used by the basic call
+pub const CAUSE_UNALLOCATED_NUMBER: u8 = 1;
+pub const CAUSE_NORMAL_CLEARING: u8 = 16;
+pub const CAUSE_USER_BUSY: u8 = 17;
+pub const CAUSE_CHANNEL_UNAVAILABLE: u8 = 44;
+pub const CAUSE_FACILITY_NOT_IMPLEMENTED: u8 = 69;
+pub const CAUSE_INVALID_CALL_REFERENCE: u8 = 81;
+pub const CAUSE_CHANNEL_DOES_NOT_EXIST: u8 = 82;
+pub const CAUSE_MANDATORY_IE_MISSING: u8 = 96;
+pub const CAUSE_INVALID_IE_CONTENTS: u8 = 100;
+pub const CAUSE_WRONG_CALL_STATE: u8 = 101;
+pub const CAUSE_RECOVERY_ON_TIMER_EXPIRY: u8 = 102;
+
+/// Reasons for redirection carried in the redirecting number
+pub const REDIRECTION_NO_REPLY: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Alerting = 0x01,
+    CallProceeding = 0x02,
+    Progress = 0x03,
+    Setup = 0x05,
+    Connect = 0x07,
+    ConnectAck = 0x0f,
+    Hold = 0x24,
+    HoldAcknowledge = 0x28,
+    HoldReject = 0x30,
+    Retrieve = 0x31,
+    RetrieveAcknowledge = 0x33,
+    RetrieveReject = 0x37,
+    Disconnect = 0x45,
+    Release = 0x4d,
+    ReleaseComplete = 0x5a,
+}
+
+impl MessageType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0x01 => Self::Alerting,
+            0x02 => Self::CallProceeding,
+            0x03 => Self::Progress,
+            0x05 => Self::Setup,
+            0x07 => Self::Connect,
+            0x0f => Self::ConnectAck,
+            0x24 => Self::Hold,
+            0x28 => Self::HoldAcknowledge,
+            0x30 => Self::HoldReject,
+            0x31 => Self::Retrieve,
+            0x33 => Self::RetrieveAcknowledge,
+            0x37 => Self::RetrieveReject,
+            0x45 => Self::Disconnect,
+            0x4d => Self::Release,
+            0x5a => Self::ReleaseComplete,
+            _ => return None,
+        })
+    }
+}
+
+/// A Q.931 message with a two-octet (PRI) call reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct Q931Message {
+    pub call_reference: u16,
+    /// Call reference flag: set on messages sent by the side that did not
+    /// allocate the call reference
+    pub from_destination: bool,
+    pub message_type: MessageType,
+    pub information_elements: Vec<(u8, Bytes)>,
+}
+
+impl Q931Message {
+    pub fn new(message_type: MessageType, call_reference: u16, from_destination: bool) -> Self {
+        Self {
+            call_reference: call_reference & 0x7fff,
+            from_destination,
+            message_type,
+            information_elements: Vec::new(),
+        }
+    }
+
+    pub fn with_ie(mut self, id: u8, contents: impl Into<Bytes>) -> Self {
+        self.information_elements.push((id, contents.into()));
+        self
+    }
+
+    /// Speech, 64 kbit/s circuit mode, G.711 µ-law
+    pub fn with_speech_bearer(self) -> Self {
+        self.with_ie(IE_BEARER_CAPABILITY, vec![0x80, 0x90, 0xa2])
+    }
+
+    /// Exclusive B-channel on the PRI carrying the D-channel
+    pub fn with_channel(self, channel: u8) -> Self {
+        self.with_ie(IE_CHANNEL_ID, vec![0xa9, 0x83, 0x80 | (channel & 0x7f)])
+    }
+
+    pub fn with_called_number(self, digits: &str) -> Self {
+        self.with_ie(IE_CALLED_NUMBER, number_contents(digits))
+    }
+
+    pub fn with_calling_number(self, digits: &str) -> Self {
+        self.with_ie(IE_CALLING_NUMBER, number_contents(digits))
+    }
+
+    /// Party the call was diverted from, presentation allowed (Q.952)
+    pub fn with_redirecting_number(self, digits: &str, reason: u8) -> Self {
+        let mut contents = vec![0x01, 0x00, 0x80 | (reason & 0x0f)];
+        contents.extend(digits.bytes().filter(u8::is_ascii_digit));
+        self.with_ie(IE_REDIRECTING_NUMBER, contents)
+    }
+
+    /// Cause generated by the public network serving the local user
+    pub fn with_cause(self, cause: u8) -> Self {
+        self.with_ie(IE_CAUSE, vec![0x82, 0x80 | (cause & 0x7f)])
+    }
+
+    pub fn with_progress(self, description: u8) -> Self {
+        self.with_ie(IE_PROGRESS_INDICATOR, vec![0x82, 0x80 | (description & 0x7f)])
+    }
+
+    /// User-user information: protocol discriminator, then the user data
+    pub fn with_user_user(self, contents: impl Into<Bytes>) -> Self {
+        self.with_ie(IE_USER_USER, contents)
+    }
+
+    pub fn ie(&self, id: u8) -> Option<&[u8]> {
+        self.information_elements
+            .iter()
+            .find(|(ie, _)| *ie == id)
+            .map(|(_, contents)| contents.as_ref())
+    }
+
+    pub fn called_number(&self) -> Option<String> {
+        self.ie(IE_CALLED_NUMBER).and_then(number_digits)
+    }
+
+    pub fn calling_number(&self) -> Option<String> {
+        self.ie(IE_CALLING_NUMBER).and_then(number_digits)
+    }
+
+    pub fn redirecting_number(&self) -> Option<String> {
+        self.ie(IE_REDIRECTING_NUMBER).and_then(number_digits)
+    }
+
+    pub fn user_user(&self) -> Option<&[u8]> {
+        self.ie(IE_USER_USER)
+    }
+
+    /// B-channel number from the channel identification element
+    pub fn channel(&self) -> Option<u8> {
+        self.ie(IE_CHANNEL_ID)
+            .filter(|contents| contents.len() >= 3)
+            .and_then(|contents| contents.last())
+            .map(|octet| octet & 0x7f)
+    }
+
+    pub fn cause(&self) -> Option<u8> {
+        self.ie(IE_CAUSE)?.get(1).map(|octet| octet & 0x7f)
+    }
+
+    /// Progress description from the progress indicator element
+    pub fn progress(&self) -> Option<u8> {
+        self.ie(IE_PROGRESS_INDICATOR)?.get(1).map(|octet| octet & 0x7f)
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(5 + self.information_elements.len() * 8);
+        buf.put_u8(PROTOCOL_DISCRIMINATOR);
+        buf.put_u8(2);
+        let flag = if self.from_destination { 0x8000 } else { 0 };
+        buf.put_u16(flag | self.call_reference);
+        buf.put_u8(self.message_type as u8);
+
+        // Elements go out in ascending identifier order as Q.931 requires
+        let mut elements: Vec<&(u8, Bytes)> = self.information_elements.iter().collect();
+        elements.sort_by_key(|(id, _)| *id);
+        for (id, contents) in elements {
+            buf.put_u8(*id);
+            buf.put_u8(contents.len() as u8);
+            buf.put(contents.clone());
+        }
+        buf.freeze()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < 5 || data[0] != PROTOCOL_DISCRIMINATOR {
+            return Err(Error::protocol("Not a Q.931 message"));
+        }
+        if data[1] != 2 {
+            return Err(Error::protocol(format!("Unsupported call reference length {}", data[1])));
+        }
+
+        let raw_reference = u16::from_be_bytes([data[2], data[3]]);
+        let message_type = MessageType::from_u8(data[4])
+            .ok_or_else(|| Error::protocol(format!("Unknown Q.931 message type 0x{:02x}", data[4])))?;
+
+        let mut information_elements = Vec::new();
+        let mut pos = 5;
+        while pos < data.len() {
+            let id = data[pos];
+            // Single-octet elements (shift, sending complete) carry no length
+            if id & 0x80 != 0 {
+                pos += 1;
+                continue;
+            }
+            let len = *data.get(pos + 1).ok_or_else(|| Error::protocol("Truncated Q.931 element"))? as usize;
+            let contents = data
+                .get(pos + 2..pos + 2 + len)
+                .ok_or_else(|| Error::protocol("Truncated Q.931 element"))?;
+            information_elements.push((id, Bytes::copy_from_slice(contents)));
+            pos += 2 + len;
+        }
+
+        Ok(Self {
+            call_reference: raw_reference & 0x7fff,
+            from_destination: raw_reference & 0x8000 != 0,
+            message_type,
+            information_elements,
+        })
+    }
+}
+
+/// Unknown type of number, ISDN/telephony numbering plan, IA5 digits
+fn number_contents(digits: &str) -> Vec<u8> {
+    let mut contents = vec![0x81];
+    contents.extend(digits.bytes().filter(u8::is_ascii_digit));
+    contents
+}
+
+fn number_digits(contents: &[u8]) -> Option<String> {
+    // Octet 3 may be extended by 3a; digits follow the octet with bit 8 set
+    let start = contents.iter().position(|octet| octet & 0x80 != 0)? + 1;
+    Some(String::from_utf8_lossy(&contents[start..]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_round_trip() {
+        let setup = Q931Message::new(MessageType::Setup, 0x1234, false)
+            .with_called_number("4155550100")
+            .with_speech_bearer()
+            .with_calling_number("2125550199")
+            .with_redirecting_number("4155550199", REDIRECTION_NO_REPLY)
+            .with_user_user(&b"\x04call-1"[..])
+            .with_channel(5);
+
+        let encoded = setup.encode();
+        assert_eq!(&encoded[..5], &[0x08, 0x02, 0x12, 0x34, 0x05]);
+        // Bearer capability (0x04) sorts first
+        assert_eq!(encoded[5], IE_BEARER_CAPABILITY);
+
+        let decoded = Q931Message::decode(&encoded).unwrap();
+        assert_eq!(decoded.message_type, MessageType::Setup);
+        assert_eq!(decoded.call_reference, 0x1234);
+        assert!(!decoded.from_destination);
+        assert_eq!(decoded.called_number().as_deref(), Some("4155550100"));
+        assert_eq!(decoded.calling_number().as_deref(), Some("2125550199"));
+        assert_eq!(decoded.redirecting_number().as_deref(), Some("4155550199"));
+        assert_eq!(decoded.channel(), Some(5));
+        assert_eq!(decoded.user_user(), Some(&b"\x04call-1"[..]));
+    }
+
+    #[test]
+    fn test_release_flag_cause_and_errors() {
+        let release = Q931Message::new(MessageType::ReleaseComplete, 7, true).with_cause(CAUSE_USER_BUSY);
+        let decoded = Q931Message::decode(&release.encode()).unwrap();
+        assert!(decoded.from_destination);
+        assert_eq!(decoded.call_reference, 7);
+        assert_eq!(decoded.cause(), Some(CAUSE_USER_BUSY));
+
+        // Calling number with octet 3a (presentation) before the digits
+        let calling = Q931Message::decode(&[0x08, 0x02, 0x00, 0x01, 0x05, 0xa1, 0x6c, 0x05, 0x01, 0x80, b'1', b'2', b'3']).unwrap();
+        assert_eq!(calling.calling_number().as_deref(), Some("123"));
+
+        assert!(Q931Message::decode(&[0x09, 0x02, 0x00, 0x01, 0x05]).is_err());
+        assert!(Q931Message::decode(&[0x08, 0x02, 0x00, 0x01, 0x7e]).is_err());
+        assert!(Q931Message::decode(&[0x08, 0x02, 0x00, 0x01, 0x05, 0x70, 0x09, 0x81]).is_err());
+    }
+}
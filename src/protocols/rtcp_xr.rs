@@ -0,0 +1,726 @@
+//! RTCP Extended Reports (RFC 3611)
+//!
+//! Loss RLE and VoIP Metrics report blocks, encoded for the far end and
+//! parsed from what it sends. `LossTracker` follows the arrival of each RTP
+//! sequence number and splits the stream into bursts and gaps as RFC 3611
+//! §4.7.2 defines them: a burst starts and ends with a loss and has fewer
+//! than Gmin packets received between any two of its losses; everything
+//! else, including isolated losses, belongs to a gap. Listening quality is
+//! estimated from those statistics with the E-model (ITU-T G.107).
+
+use std::collections::VecDeque;
+
+use crate::{Error, Result};
+
+/// RTCP packet type of an extended report
+pub const PACKET_TYPE: u8 = 207;
+pub const BLOCK_LOSS_RLE: u8 = 1;
+pub const BLOCK_VOIP_METRICS: u8 = 7;
+
+/// Received packets that end a burst, the RFC 3611 recommended value
+pub const DEFAULT_GMIN: u8 = 16;
+
+/// Signal, noise, RERL, R-factor and MOS fields that were not measured
+pub const UNAVAILABLE: u8 = 127;
+
+const HEADER_LEN: usize = 8;
+const VOIP_METRICS_LEN: usize = 32;
+/// Longest run a run-length chunk can describe
+const MAX_RUN: u32 = 0x3FFF;
+/// Runs remembered between Loss RLE reports
+const MAX_RUNS: usize = 128;
+/// Packets a Loss RLE block may cover; keeps its sequence range unambiguous
+const MAX_RLE_PACKETS: u32 = 0x8000;
+/// Forward sequence jumps beyond this are a restarted stream, not loss
+const MAX_DROPOUT: u16 = 3000;
+
+/// Arrival of a range of sequence numbers (BT=1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LossRle {
+    pub ssrc: u32,
+    pub begin_seq: u16,
+    /// One past the last sequence number covered
+    pub end_seq: u16,
+    /// One entry per packet from `begin_seq`, `true` when it arrived
+    pub received: Vec<bool>,
+}
+
+impl LossRle {
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        out.extend_from_slice(&self.begin_seq.to_be_bytes());
+        out.extend_from_slice(&self.end_seq.to_be_bytes());
+
+        let mut chunks: Vec<u16> = Vec::new();
+        for (received, length) in runs(&self.received) {
+            let mut remaining = length;
+            while remaining > 0 {
+                let run = remaining.min(MAX_RUN);
+                chunks.push(((received as u16) << 14) | run as u16);
+                remaining -= run;
+            }
+        }
+        // A null chunk pads the block to a whole number of words
+        if chunks.len() % 2 == 1 {
+            chunks.push(0);
+        }
+        for chunk in chunks {
+            out.extend_from_slice(&chunk.to_be_bytes());
+        }
+    }
+
+    fn parse_body(body: &[u8]) -> Result<Self> {
+        if body.len() < 8 {
+            return Err(Error::parse("Loss RLE block too short"));
+        }
+        let begin_seq = u16::from_be_bytes([body[4], body[5]]);
+        let end_seq = u16::from_be_bytes([body[6], body[7]]);
+        let covered = end_seq.wrapping_sub(begin_seq) as usize;
+
+        let mut received = Vec::with_capacity(covered);
+        for chunk in body[8..].chunks_exact(2) {
+            let chunk = u16::from_be_bytes([chunk[0], chunk[1]]);
+            if chunk == 0 {
+                continue;
+            }
+            if chunk & 0x8000 == 0 {
+                let length = (chunk & 0x3FFF) as usize;
+                received.extend(std::iter::repeat(chunk & 0x4000 != 0).take(length));
+            } else {
+                received.extend((0..15).rev().map(|bit| chunk & (1 << bit) != 0));
+            }
+        }
+        // Bit vectors may run past the end of the range
+        if received.len() < covered {
+            return Err(Error::parse("Loss RLE chunks do not cover the sequence range"));
+        }
+        received.truncate(covered);
+
+        Ok(Self { ssrc: u32::from_be_bytes([body[0], body[1], body[2], body[3]]), begin_seq, end_seq, received })
+    }
+
+    pub fn lost(&self) -> usize {
+        self.received.iter().filter(|received| !**received).count()
+    }
+}
+
+/// Consecutive equal entries as (value, length)
+fn runs(arrivals: &[bool]) -> Vec<(bool, u32)> {
+    let mut runs: Vec<(bool, u32)> = Vec::new();
+    for &received in arrivals {
+        match runs.last_mut() {
+            Some((kind, length)) if *kind == received => *length += 1,
+            _ => runs.push((received, 1)),
+        }
+    }
+    runs
+}
+
+/// Call quality of one stream (BT=7). Rates and densities are fractions in
+/// units of 1/256; MOS values are scaled by ten
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoipMetrics {
+    pub ssrc: u32,
+    pub loss_rate: u8,
+    pub discard_rate: u8,
+    pub burst_density: u8,
+    pub gap_density: u8,
+    pub burst_duration_ms: u16,
+    pub gap_duration_ms: u16,
+    pub round_trip_delay_ms: u16,
+    pub end_system_delay_ms: u16,
+    /// dBm0
+    pub signal_level: i8,
+    pub noise_level: i8,
+    /// Residual echo return loss, dB
+    pub rerl: u8,
+    pub gmin: u8,
+    pub r_factor: u8,
+    pub ext_r_factor: u8,
+    pub mos_lq: u8,
+    pub mos_cq: u8,
+    pub rx_config: u8,
+    pub jb_nominal_ms: u16,
+    pub jb_maximum_ms: u16,
+    pub jb_abs_max_ms: u16,
+}
+
+impl VoipMetrics {
+    /// Metrics of a received stream; `round_trip_ms` comes from RTCP reports
+    /// when the far end sends them
+    pub fn measure(
+        ssrc: u32,
+        stats: &LossStatistics,
+        gmin: u8,
+        packet_ms: u32,
+        round_trip_ms: Option<u32>,
+        impairment: CodecImpairment,
+    ) -> Self {
+        let duration = |packets: f64| (packets * packet_ms as f64).round().min(u16::MAX as f64) as u16;
+        let round_trip = round_trip_ms.unwrap_or(0).min(u16::MAX as u32) as u16;
+
+        let listening = r_factor(stats.loss_pct(), stats.burst_ratio(), 0.0, impairment);
+        let conversational = r_factor(stats.loss_pct(), stats.burst_ratio(), round_trip as f64 / 2.0, impairment);
+        let mos = |r: f64| (mos_from_r(r) * 10.0).round() as u8;
+
+        Self {
+            ssrc,
+            loss_rate: fraction(stats.lost, stats.received + stats.lost),
+            discard_rate: 0,
+            burst_density: fraction(stats.burst_lost, stats.burst_packets),
+            gap_density: fraction(stats.gap_lost, stats.gap_packets),
+            burst_duration_ms: duration(stats.mean_burst_packets()),
+            gap_duration_ms: duration(stats.mean_gap_packets()),
+            round_trip_delay_ms: round_trip,
+            end_system_delay_ms: 0,
+            signal_level: UNAVAILABLE as i8,
+            noise_level: UNAVAILABLE as i8,
+            rerl: UNAVAILABLE,
+            gmin,
+            r_factor: conversational.round() as u8,
+            ext_r_factor: UNAVAILABLE,
+            mos_lq: mos(listening),
+            mos_cq: mos(conversational),
+            rx_config: 0,
+            jb_nominal_ms: 0,
+            jb_maximum_ms: 0,
+            jb_abs_max_ms: 0,
+        }
+    }
+
+    pub fn loss_pct(&self) -> f64 {
+        self.loss_rate as f64 * 100.0 / 256.0
+    }
+
+    pub fn burst_density_pct(&self) -> f64 {
+        self.burst_density as f64 * 100.0 / 256.0
+    }
+
+    pub fn gap_density_pct(&self) -> f64 {
+        self.gap_density as f64 * 100.0 / 256.0
+    }
+
+    /// Listening quality MOS, if the sender estimated it
+    pub fn mos_lq(&self) -> Option<f64> {
+        (self.mos_lq != UNAVAILABLE && self.mos_lq >= 10).then_some(self.mos_lq as f64 / 10.0)
+    }
+
+    /// Conversational quality MOS, if the sender estimated it
+    pub fn mos_cq(&self) -> Option<f64> {
+        (self.mos_cq != UNAVAILABLE && self.mos_cq >= 10).then_some(self.mos_cq as f64 / 10.0)
+    }
+
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        out.extend_from_slice(&[self.loss_rate, self.discard_rate, self.burst_density, self.gap_density]);
+        out.extend_from_slice(&self.burst_duration_ms.to_be_bytes());
+        out.extend_from_slice(&self.gap_duration_ms.to_be_bytes());
+        out.extend_from_slice(&self.round_trip_delay_ms.to_be_bytes());
+        out.extend_from_slice(&self.end_system_delay_ms.to_be_bytes());
+        out.extend_from_slice(&[self.signal_level as u8, self.noise_level as u8, self.rerl, self.gmin]);
+        out.extend_from_slice(&[self.r_factor, self.ext_r_factor, self.mos_lq, self.mos_cq]);
+        out.extend_from_slice(&[self.rx_config, 0]);
+        out.extend_from_slice(&self.jb_nominal_ms.to_be_bytes());
+        out.extend_from_slice(&self.jb_maximum_ms.to_be_bytes());
+        out.extend_from_slice(&self.jb_abs_max_ms.to_be_bytes());
+    }
+
+    fn parse_body(body: &[u8]) -> Result<Self> {
+        if body.len() < VOIP_METRICS_LEN {
+            return Err(Error::parse("VoIP metrics block too short"));
+        }
+        let word = |at: usize| u16::from_be_bytes([body[at], body[at + 1]]);
+        Ok(Self {
+            ssrc: u32::from_be_bytes([body[0], body[1], body[2], body[3]]),
+            loss_rate: body[4],
+            discard_rate: body[5],
+            burst_density: body[6],
+            gap_density: body[7],
+            burst_duration_ms: word(8),
+            gap_duration_ms: word(10),
+            round_trip_delay_ms: word(12),
+            end_system_delay_ms: word(14),
+            signal_level: body[16] as i8,
+            noise_level: body[17] as i8,
+            rerl: body[18],
+            gmin: body[19],
+            r_factor: body[20],
+            ext_r_factor: body[21],
+            mos_lq: body[22],
+            mos_cq: body[23],
+            rx_config: body[24],
+            jb_nominal_ms: word(26),
+            jb_maximum_ms: word(28),
+            jb_abs_max_ms: word(30),
+        })
+    }
+}
+
+/// `part / whole` in units of 1/256, saturating at 255
+fn fraction(part: u64, whole: u64) -> u8 {
+    if whole == 0 {
+        0
+    } else {
+        (part * 256 / whole).min(255) as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportBlock {
+    LossRle(LossRle),
+    VoipMetrics(VoipMetrics),
+    /// Block types not interpreted here, kept as received
+    Other { block_type: u8, type_specific: u8, body: Vec<u8> },
+}
+
+/// One RTCP XR packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedReport {
+    pub ssrc: u32,
+    pub blocks: Vec<ReportBlock>,
+}
+
+impl ExtendedReport {
+    pub fn new(ssrc: u32) -> Self {
+        Self { ssrc, blocks: Vec::new() }
+    }
+
+    pub fn voip_metrics(&self) -> Option<&VoipMetrics> {
+        self.blocks.iter().find_map(|block| match block {
+            ReportBlock::VoipMetrics(metrics) => Some(metrics),
+            _ => None,
+        })
+    }
+
+    pub fn loss_rle(&self) -> Option<&LossRle> {
+        self.blocks.iter().find_map(|block| match block {
+            ReportBlock::LossRle(rle) => Some(rle),
+            _ => None,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0x80, PACKET_TYPE, 0, 0];
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        for block in &self.blocks {
+            let start = out.len();
+            let (block_type, type_specific) = match block {
+                ReportBlock::LossRle(_) => (BLOCK_LOSS_RLE, 0),
+                ReportBlock::VoipMetrics(_) => (BLOCK_VOIP_METRICS, 0),
+                ReportBlock::Other { block_type, type_specific, .. } => (*block_type, *type_specific),
+            };
+            out.extend_from_slice(&[block_type, type_specific, 0, 0]);
+            match block {
+                ReportBlock::LossRle(rle) => rle.encode_body(&mut out),
+                ReportBlock::VoipMetrics(metrics) => metrics.encode_body(&mut out),
+                ReportBlock::Other { body, .. } => {
+                    out.extend_from_slice(body);
+                    out.resize(out.len() + (4 - body.len() % 4) % 4, 0);
+                }
+            }
+            let words = ((out.len() - start) / 4 - 1) as u16;
+            out[start + 2..start + 4].copy_from_slice(&words.to_be_bytes());
+        }
+        let words = (out.len() / 4 - 1) as u16;
+        out[2..4].copy_from_slice(&words.to_be_bytes());
+        out
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN || data[0] >> 6 != 2 || data[1] != PACKET_TYPE {
+            return Err(Error::parse("Not an RTCP XR packet"));
+        }
+        let length = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+        if length > data.len() {
+            return Err(Error::parse("RTCP XR packet truncated"));
+        }
+
+        let mut report = Self::new(u32::from_be_bytes([data[4], data[5], data[6], data[7]]));
+        let mut rest = &data[HEADER_LEN..length];
+        while rest.len() >= 4 {
+            let block_len = (u16::from_be_bytes([rest[2], rest[3]]) as usize + 1) * 4;
+            if block_len > rest.len() {
+                return Err(Error::parse("RTCP XR report block truncated"));
+            }
+            let body = &rest[4..block_len];
+            report.blocks.push(match rest[0] {
+                BLOCK_LOSS_RLE => ReportBlock::LossRle(LossRle::parse_body(body)?),
+                BLOCK_VOIP_METRICS => ReportBlock::VoipMetrics(VoipMetrics::parse_body(body)?),
+                block_type => ReportBlock::Other { block_type, type_specific: rest[1], body: body.to_vec() },
+            });
+            rest = &rest[block_len..];
+        }
+        Ok(report)
+    }
+
+    /// The extended report in a compound RTCP packet, if it carries one
+    pub fn find(compound: &[u8]) -> Option<Self> {
+        let mut rest = compound;
+        while rest.len() >= 4 {
+            let length = (u16::from_be_bytes([rest[2], rest[3]]) as usize + 1) * 4;
+            if length > rest.len() {
+                return None;
+            }
+            if rest[1] == PACKET_TYPE {
+                return Self::parse(&rest[..length]).ok();
+            }
+            rest = &rest[length..];
+        }
+        None
+    }
+}
+
+/// Burst and gap statistics of a received stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LossStatistics {
+    pub received: u64,
+    pub lost: u64,
+    /// Runs of consecutive lost packets
+    pub loss_runs: u64,
+    pub bursts: u64,
+    pub burst_packets: u64,
+    pub burst_lost: u64,
+    pub gap_packets: u64,
+    pub gap_lost: u64,
+}
+
+impl LossStatistics {
+    pub fn loss_pct(&self) -> f64 {
+        percent(self.lost, self.received + self.lost)
+    }
+
+    pub fn burst_density_pct(&self) -> f64 {
+        percent(self.burst_lost, self.burst_packets)
+    }
+
+    pub fn gap_density_pct(&self) -> f64 {
+        percent(self.gap_lost, self.gap_packets)
+    }
+
+    /// Mean length of a burst, in packets
+    pub fn mean_burst_packets(&self) -> f64 {
+        if self.bursts == 0 {
+            0.0
+        } else {
+            self.burst_packets as f64 / self.bursts as f64
+        }
+    }
+
+    /// Mean length of a gap, in packets; bursts split the stream into one
+    /// more gap than there are bursts
+    pub fn mean_gap_packets(&self) -> f64 {
+        if self.gap_packets == 0 {
+            0.0
+        } else {
+            self.gap_packets as f64 / (self.bursts + 1) as f64
+        }
+    }
+
+    /// Mean run of consecutive losses over the run expected if losses were
+    /// random (ITU-T G.113 BurstR); 1 for random loss
+    pub fn burst_ratio(&self) -> f64 {
+        let total = self.received + self.lost;
+        if self.loss_runs == 0 || self.lost == total {
+            return 1.0;
+        }
+        let loss = self.lost as f64 / total as f64;
+        (self.lost as f64 / self.loss_runs as f64 * (1.0 - loss)).max(1.0)
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+/// Follows the sequence numbers of one received RTP stream
+#[derive(Debug, Clone)]
+pub struct LossTracker {
+    gmin: u32,
+    /// Next sequence number expected, once the first packet arrived
+    expected: Option<u16>,
+    stats: LossStatistics,
+    last_lost: bool,
+    /// Packets received since the last loss
+    since_loss: u32,
+    /// A loss after a gap, which starts a burst if another follows soon
+    pending_loss: bool,
+    /// Packets and losses of the burst in progress
+    burst: Option<(u64, u64)>,
+    /// Arrivals since the last Loss RLE report, as runs
+    runs: VecDeque<(bool, u32)>,
+    runs_packets: u32,
+    runs_begin: u16,
+}
+
+impl LossTracker {
+    pub fn new(gmin: u8) -> Self {
+        Self {
+            gmin: gmin.max(1) as u32,
+            expected: None,
+            stats: LossStatistics::default(),
+            last_lost: false,
+            since_loss: 0,
+            pending_loss: false,
+            burst: None,
+            runs: VecDeque::new(),
+            runs_packets: 0,
+            runs_begin: 0,
+        }
+    }
+
+    pub fn gmin(&self) -> u8 {
+        self.gmin as u8
+    }
+
+    /// Note the arrival of `seq`; sequence numbers skipped since the last
+    /// one are counted lost. Late and duplicate packets are ignored, so a
+    /// reordered packet stays counted as lost
+    pub fn record(&mut self, seq: u16) {
+        let Some(expected) = self.expected else {
+            self.expected = Some(seq.wrapping_add(1));
+            self.runs_begin = seq;
+            self.arrive(true);
+            return;
+        };
+        let ahead = seq.wrapping_sub(expected);
+        if ahead >= 0x8000 {
+            return;
+        }
+        if ahead > MAX_DROPOUT {
+            // Restarted sender; the skipped range is not loss
+            self.runs.clear();
+            self.runs_packets = 0;
+            self.runs_begin = seq;
+        } else {
+            for _ in 0..ahead {
+                self.arrive(false);
+            }
+        }
+        self.arrive(true);
+        self.expected = Some(seq.wrapping_add(1));
+    }
+
+    fn arrive(&mut self, received: bool) {
+        self.push_run(received);
+        if received {
+            self.stats.received += 1;
+            self.last_lost = false;
+            self.since_loss += 1;
+            if self.since_loss == self.gmin {
+                self.settle();
+                self.stats.gap_packets += self.gmin as u64;
+            } else if self.since_loss > self.gmin {
+                self.stats.gap_packets += 1;
+            }
+            return;
+        }
+
+        self.stats.lost += 1;
+        if !self.last_lost {
+            self.stats.loss_runs += 1;
+        }
+        self.last_lost = true;
+        let pending = self.pending_loss;
+        if self.since_loss < self.gmin && (pending || self.burst.is_some()) {
+            let (packets, lost) = self.burst.get_or_insert((0, 0));
+            if pending {
+                *packets += 1;
+                *lost += 1;
+            }
+            *packets += self.since_loss as u64 + 1;
+            *lost += 1;
+            self.pending_loss = false;
+        } else {
+            // Only packets at the start of the stream are still unaccounted
+            if self.since_loss < self.gmin {
+                self.stats.gap_packets += self.since_loss as u64;
+            }
+            self.pending_loss = true;
+        }
+        self.since_loss = 0;
+    }
+
+    /// Close the burst in progress, or give a lone loss to the gap
+    fn settle(&mut self) {
+        if let Some((packets, lost)) = self.burst.take() {
+            self.stats.bursts += 1;
+            self.stats.burst_packets += packets;
+            self.stats.burst_lost += lost;
+        } else if self.pending_loss {
+            self.stats.gap_packets += 1;
+            self.stats.gap_lost += 1;
+        }
+        self.pending_loss = false;
+    }
+
+    fn push_run(&mut self, received: bool) {
+        match self.runs.back_mut() {
+            Some((kind, length)) if *kind == received => *length += 1,
+            _ => self.runs.push_back((received, 1)),
+        }
+        self.runs_packets += 1;
+        while self.runs.len() > MAX_RUNS || self.runs_packets > MAX_RLE_PACKETS {
+            let Some((_, length)) = self.runs.pop_front() else {
+                break;
+            };
+            self.runs_packets -= length;
+            self.runs_begin = self.runs_begin.wrapping_add(length as u16);
+        }
+    }
+
+    /// Statistics so far, treating the stream as if it ended now
+    pub fn statistics(&self) -> LossStatistics {
+        let mut tracker = self.clone();
+        if tracker.since_loss < tracker.gmin {
+            tracker.settle();
+            tracker.stats.gap_packets += tracker.since_loss as u64;
+        }
+        tracker.stats
+    }
+
+    /// Arrivals since the previous call, for a Loss RLE block
+    pub fn take_loss_rle(&mut self, ssrc: u32) -> LossRle {
+        let received: Vec<bool> = self
+            .runs
+            .drain(..)
+            .flat_map(|(received, length)| std::iter::repeat(received).take(length as usize))
+            .collect();
+        let begin_seq = self.runs_begin;
+        self.runs_begin = begin_seq.wrapping_add(self.runs_packets as u16);
+        self.runs_packets = 0;
+        LossRle { ssrc, begin_seq, end_seq: self.runs_begin, received }
+    }
+}
+
+/// Codec parameters of the E-model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodecImpairment {
+    /// Equipment impairment factor without loss
+    pub ie: f64,
+    /// Packet-loss robustness with the codec's concealment
+    pub bpl: f64,
+}
+
+impl CodecImpairment {
+    /// ITU-T G.113 values for the static payload types; anything else is
+    /// rated like G.711
+    pub fn for_payload_type(payload_type: u8) -> Self {
+        match payload_type {
+            4 => Self { ie: 15.0, bpl: 16.1 },
+            18 => Self { ie: 11.0, bpl: 19.0 },
+            _ => Self { ie: 0.0, bpl: 25.1 },
+        }
+    }
+}
+
+/// Transmission rating R of the E-model (ITU-T G.107) with default
+/// terminal and room factors
+pub fn r_factor(loss_pct: f64, burst_ratio: f64, one_way_delay_ms: f64, codec: CodecImpairment) -> f64 {
+    let ie_eff = codec.ie + (95.0 - codec.ie) * loss_pct / (loss_pct / burst_ratio.max(1.0) + codec.bpl);
+    let delay = one_way_delay_ms.max(0.0);
+    let id = 0.024 * delay + if delay > 177.3 { 0.11 * (delay - 177.3) } else { 0.0 };
+    (93.2 - id - ie_eff).clamp(0.0, 100.0)
+}
+
+/// MOS for a transmission rating (ITU-T G.107 Annex B)
+pub fn mos_from_r(r: f64) -> f64 {
+    if r <= 0.0 {
+        1.0
+    } else if r >= 100.0 {
+        4.5
+    } else {
+        1.0 + 0.035 * r + 0.000007 * r * (r - 60.0) * (100.0 - r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_parse() {
+        let stats = LossStatistics {
+            received: 990,
+            lost: 10,
+            loss_runs: 4,
+            bursts: 1,
+            burst_packets: 12,
+            burst_lost: 7,
+            gap_packets: 988,
+            gap_lost: 3,
+        };
+        let g711 = CodecImpairment::for_payload_type(0);
+        let metrics = VoipMetrics::measure(0x1234_5678, &stats, DEFAULT_GMIN, 20, Some(400), g711);
+        assert_eq!(metrics.loss_rate, 2);
+        assert_eq!(metrics.burst_density, 149);
+        assert_eq!(metrics.burst_duration_ms, 240);
+        assert!(metrics.mos_lq().unwrap() > metrics.mos_cq().unwrap());
+
+        let mut received = vec![true, true, false, true, false, false];
+        received.extend([true; 10]);
+        let rle = LossRle { ssrc: 0x1234_5678, begin_seq: 65530, end_seq: 10, received };
+        let mut report = ExtendedReport::new(0xCAFE);
+        report.blocks.push(ReportBlock::LossRle(rle.clone()));
+        report.blocks.push(ReportBlock::VoipMetrics(metrics));
+        report.blocks.push(ReportBlock::Other { block_type: 4, type_specific: 0, body: vec![0; 8] });
+
+        // Behind a receiver report in a compound packet
+        let mut compound = vec![0x80, 201, 0, 1, 0, 0, 0xCA, 0xFE];
+        compound.extend(report.encode());
+        let parsed = ExtendedReport::find(&compound).unwrap();
+        assert_eq!(parsed, report);
+        assert_eq!(parsed.loss_rle().unwrap().lost(), 3);
+
+        // A bit-vector chunk may describe more packets than the range
+        let mut body = vec![0, 0, 0, 1, 0, 0, 0, 4];
+        body.extend_from_slice(&0b1101_1111_1111_1111u16.to_be_bytes());
+        body.extend_from_slice(&[0, 0]);
+        let rle = LossRle::parse_body(&body).unwrap();
+        assert_eq!(rle.received, vec![true, false, true, true]);
+        assert!(ExtendedReport::parse(&[0x80, 200, 0, 1, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_bursts_and_gaps() {
+        let mut tracker = LossTracker::new(DEFAULT_GMIN);
+        // 100 received, a lone loss, 50 received, a burst of three losses
+        // two packets apart, 100 received; sequence numbers wrap mid-way
+        let mut arrivals = vec![true; 100];
+        arrivals.push(false);
+        arrivals.extend([true; 50]);
+        arrivals.extend([false, true, false, true, false]);
+        arrivals.extend([true; 100]);
+        let mut seq = 65400u16;
+        for received in &arrivals {
+            if *received {
+                tracker.record(seq);
+            }
+            seq = seq.wrapping_add(1);
+        }
+        // A late duplicate changes nothing
+        tracker.record(65450);
+
+        let stats = tracker.statistics();
+        assert_eq!((stats.received, stats.lost, stats.loss_runs), (252, 4, 4));
+        assert_eq!((stats.bursts, stats.burst_packets, stats.burst_lost), (1, 5, 3));
+        assert_eq!((stats.gap_packets, stats.gap_lost), (251, 1));
+        assert_eq!(stats.burst_density_pct(), 60.0);
+
+        let rle = tracker.take_loss_rle(1);
+        assert_eq!((rle.begin_seq, rle.end_seq), (65400, 65400u16.wrapping_add(256)));
+        assert_eq!(rle.received, arrivals);
+        assert!(tracker.take_loss_rle(1).received.is_empty());
+
+        // A burst still open at the end counts as one
+        let mut tracker = LossTracker::new(DEFAULT_GMIN);
+        for seq in [1, 3, 5] {
+            tracker.record(seq);
+        }
+        let stats = tracker.statistics();
+        assert_eq!((stats.bursts, stats.burst_packets, stats.gap_packets), (1, 3, 2));
+    }
+}
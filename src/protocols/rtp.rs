@@ -1,17 +1,36 @@
 //! RTP (Real-time Transport Protocol) implementation
-
-use std::net::SocketAddr;
-use std::sync::Arc;
+//!
+//! Sessions with SRTP enabled are protected on send and unprotected on
+//! receive, so callers always see plain RTP. WebRTC sessions also answer
+//! ICE checks and run the DTLS-SRTP handshake on their media port before
+//! any media flows. With RTCP enabled each session takes an even port and
+//! the odd one above it for RTCP, and sends a sender or receiver report
+//! (RFC 3550) on every interval to the port the far end's RTCP comes from,
+//! or one above its RTP port until it has sent any. Reports received on
+//! either port give the far end's view of the stream we send and the round
+//! trip time. Every session also tracks loss runs for its own extended
+//! reports (RFC 3611) and keeps the VoIP metrics the far end sends. RTCP
+//! multiplexed onto an RTP port is never mistaken for media; SRTCP is not
+//! implemented, so no RTCP is sent or taken on SRTP sessions.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use dashmap::DashMap;
-use tokio::net::UdpSocket;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, oneshot, RwLock};
 use tokio::time::interval;
 use tracing::{debug, error, info, trace, warn};
 
-use crate::config::PortRange;
+use crate::config::{NetworkPlacement, PortRange, RtcpConfig, RtpBatchingConfig};
+use crate::protocols::rtcp_xr::{self, ExtendedReport, LossTracker, ReportBlock, VoipMetrics};
+use crate::protocols::rtp_socket::{BatchedUdpSocket, RtpSocketStats};
+use crate::protocols::ice::{IceCandidate, IceCredentials, IceLite};
+use crate::protocols::srtp::{CryptoAttribute, SrtpProfile, SrtpSession, SrtpStats};
+use crate::protocols::stun::{self, StunMessage};
+use crate::protocols::webrtc::{self, DtlsEndpoint, DtlsIdentity, DtlsSetup, Fingerprint, RemoteParameters, WebRtcTransport};
+use crate::utils::netbind;
 use crate::{Error, Result};
 
 /// RTP packet structure
@@ -158,11 +177,223 @@ pub enum RtcpPacketType {
     SourceDescription = 202,
     Goodbye = 203,
     ApplicationDefined = 204,
+    ExtendedReport = 207,
+}
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// 64-bit NTP timestamp of a wall-clock time
+pub fn ntp_timestamp(time: SystemTime) -> u64 {
+    let since_unix = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let fraction = ((since_unix.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    ((since_unix.as_secs() + NTP_UNIX_OFFSET) << 32) | fraction
+}
+
+/// Sender information of an SR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderInfo {
+    pub ntp_timestamp: u64,
+    /// RTP timestamp of the same instant
+    pub rtp_timestamp: u32,
+    pub packet_count: u32,
+    pub octet_count: u32,
+}
+
+/// Reception report block: how one source's stream arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceptionReport {
+    pub ssrc: u32,
+    /// Loss since the previous report, in units of 1/256
+    pub fraction_lost: u8,
+    /// 24-bit signed; duplicates can make it negative
+    pub cumulative_lost: i32,
+    /// Highest sequence number received, extended with wrap-arounds
+    pub highest_sequence: u32,
+    /// Interarrival jitter in timestamp units
+    pub jitter: u32,
+    /// Middle 32 bits of the NTP timestamp of the source's last SR
+    pub last_sr: u32,
+    /// Time since that SR arrived, in units of 1/65536 s
+    pub delay_since_last_sr: u32,
+}
+
+impl ReceptionReport {
+    const LEN: usize = 24;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        let lost = (self.cumulative_lost.clamp(-0x80_0000, 0x7F_FFFF) as u32) & 0xFF_FFFF;
+        out.extend_from_slice(&(((self.fraction_lost as u32) << 24) | lost).to_be_bytes());
+        out.extend_from_slice(&self.highest_sequence.to_be_bytes());
+        out.extend_from_slice(&self.jitter.to_be_bytes());
+        out.extend_from_slice(&self.last_sr.to_be_bytes());
+        out.extend_from_slice(&self.delay_since_last_sr.to_be_bytes());
+    }
+
+    fn parse(block: &[u8]) -> Self {
+        let word = |i: usize| u32::from_be_bytes([block[i], block[i + 1], block[i + 2], block[i + 3]]);
+        let lost = word(4) & 0xFF_FFFF;
+        // Sign-extend the 24-bit count
+        let cumulative_lost = ((lost << 8) as i32) >> 8;
+        Self {
+            ssrc: word(0),
+            fraction_lost: block[4],
+            cumulative_lost,
+            highest_sequence: word(8),
+            jitter: word(12),
+            last_sr: word(16),
+            delay_since_last_sr: word(20),
+        }
+    }
+
+    pub fn loss_pct(&self) -> f64 {
+        self.fraction_lost as f64 * 100.0 / 256.0
+    }
+}
+
+/// An SR, when `sender` is present, or an RR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtcpReport {
+    pub ssrc: u32,
+    pub sender: Option<SenderInfo>,
+    pub reports: Vec<ReceptionReport>,
+}
+
+impl RtcpReport {
+    pub fn encode(&self) -> Vec<u8> {
+        let packet_type = match self.sender {
+            Some(_) => RtcpPacketType::SenderReport,
+            None => RtcpPacketType::ReceiverReport,
+        };
+        let count = self.reports.len().min(31);
+        let mut out = vec![0x80 | count as u8, packet_type as u8, 0, 0];
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        if let Some(sender) = &self.sender {
+            out.extend_from_slice(&sender.ntp_timestamp.to_be_bytes());
+            out.extend_from_slice(&sender.rtp_timestamp.to_be_bytes());
+            out.extend_from_slice(&sender.packet_count.to_be_bytes());
+            out.extend_from_slice(&sender.octet_count.to_be_bytes());
+        }
+        for report in &self.reports[..count] {
+            report.encode(&mut out);
+        }
+        let words = (out.len() / 4 - 1) as u16;
+        out[2..4].copy_from_slice(&words.to_be_bytes());
+        out
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 8 || data[0] >> 6 != 2 {
+            return Err(Error::parse("Not an RTCP packet"));
+        }
+        let has_sender = match data[1] {
+            pt if pt == RtcpPacketType::SenderReport as u8 => true,
+            pt if pt == RtcpPacketType::ReceiverReport as u8 => false,
+            _ => return Err(Error::parse("Not an RTCP sender or receiver report")),
+        };
+        let length = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+        let count = (data[0] & 0x1F) as usize;
+        let blocks_at = if has_sender { 28 } else { 8 };
+        if length > data.len() || blocks_at + count * ReceptionReport::LEN > length {
+            return Err(Error::parse("RTCP report truncated"));
+        }
+
+        let word = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let sender = has_sender.then(|| SenderInfo {
+            ntp_timestamp: ((word(8) as u64) << 32) | word(12) as u64,
+            rtp_timestamp: word(16),
+            packet_count: word(20),
+            octet_count: word(24),
+        });
+        let reports = data[blocks_at..blocks_at + count * ReceptionReport::LEN]
+            .chunks_exact(ReceptionReport::LEN)
+            .map(ReceptionReport::parse)
+            .collect();
+        Ok(Self { ssrc: word(4), sender, reports })
+    }
+
+    /// The SR or RR a compound RTCP packet starts with, if it has one
+    pub fn find(compound: &[u8]) -> Option<Self> {
+        let mut rest = compound;
+        while rest.len() >= 4 {
+            let length = (u16::from_be_bytes([rest[2], rest[3]]) as usize + 1) * 4;
+            if length > rest.len() {
+                return None;
+            }
+            if rest[1] == RtcpPacketType::SenderReport as u8 || rest[1] == RtcpPacketType::ReceiverReport as u8 {
+                return Self::parse(&rest[..length]).ok();
+            }
+            rest = &rest[length..];
+        }
+        None
+    }
+}
+
+/// SDES packet carrying only the CNAME of `ssrc`, which every compound
+/// RTCP packet must include
+pub fn sdes_cname(ssrc: u32, cname: &str) -> Vec<u8> {
+    let cname = &cname.as_bytes()[..cname.len().min(255)];
+    let mut out = vec![0x81, RtcpPacketType::SourceDescription as u8, 0, 0];
+    out.extend_from_slice(&ssrc.to_be_bytes());
+    out.extend_from_slice(&[1, cname.len() as u8]);
+    out.extend_from_slice(cname);
+    // The item list ends with a null octet, padded to a whole word
+    out.push(0);
+    out.resize(out.len() + (4 - out.len() % 4) % 4, 0);
+    let words = (out.len() / 4 - 1) as u16;
+    out[2..4].copy_from_slice(&words.to_be_bytes());
+    out
+}
+
+/// What RTCP has told about a session's streams
+#[derive(Debug, Clone, Default)]
+pub struct RtcpStatistics {
+    pub reports_sent: u64,
+    pub reports_received: u64,
+    /// The far end's latest report on the stream we send
+    pub remote_report: Option<ReceptionReport>,
+    pub round_trip_ms: Option<f64>,
+    /// Middle 32 bits of the NTP timestamp of the far end's latest SR, and
+    /// when it arrived
+    pub last_sr: Option<(u32, Instant)>,
+}
+
+impl RtcpStatistics {
+    /// Take a report from the far end that arrived at NTP time `arrival`
+    pub fn on_report(&mut self, report: &RtcpReport, local_ssrc: u32, arrival: u64, now: Instant) {
+        self.reports_received += 1;
+        if let Some(sender) = &report.sender {
+            self.last_sr = Some(((sender.ntp_timestamp >> 16) as u32, now));
+        }
+        let Some(block) = report.reports.iter().find(|block| block.ssrc == local_ssrc) else {
+            return;
+        };
+        self.remote_report = Some(*block);
+        if block.last_sr != 0 {
+            // Arrival less our SR's send time and the far end's hold time
+            let round_trip = ((arrival >> 16) as u32).wrapping_sub(block.last_sr).wrapping_sub(block.delay_since_last_sr);
+            if round_trip < 0x8000_0000 {
+                self.round_trip_ms = Some(round_trip as f64 * 1000.0 / 65536.0);
+            }
+        }
+    }
+
+    /// Loss the far end reports on the stream we send, %
+    pub fn remote_loss_pct(&self) -> Option<f64> {
+        self.remote_report.map(|report| report.loss_pct())
+    }
+
+    /// Jitter the far end reports on the stream we send; 8 kHz clock assumed
+    pub fn remote_jitter_ms(&self) -> Option<f64> {
+        self.remote_report.map(|report| report.jitter as f64 / 8.0)
+    }
 }
 
 /// RTP stream statistics
 #[derive(Debug, Clone)]
 pub struct RtpStreamStats {
+    /// SSRC of the stream received, once a packet has arrived
     pub ssrc: u32,
     pub packets_sent: u64,
     pub packets_received: u64,
@@ -174,6 +405,18 @@ pub struct RtpStreamStats {
     pub last_timestamp: u32,
     pub last_packet_time: Instant,
     pub first_packet_time: Option<Instant>,
+    /// Loss runs, bursts and gaps of the received stream
+    pub loss: LossTracker,
+    pub base_sequence: u16,
+    pub max_sequence: u16,
+    /// Wrap-arounds of the received sequence number
+    pub cycles: u32,
+    /// Packets expected and received at the previous reception report
+    pub expected_prior: u32,
+    pub received_prior: u64,
+    pub last_sent_timestamp: u32,
+    pub last_sent_time: Option<Instant>,
+    pub rtcp: RtcpStatistics,
 }
 
 impl RtpStreamStats {
@@ -190,12 +433,35 @@ impl RtpStreamStats {
             last_timestamp: 0,
             last_packet_time: Instant::now(),
             first_packet_time: None,
+            loss: LossTracker::new(rtcp_xr::DEFAULT_GMIN),
+            base_sequence: 0,
+            max_sequence: 0,
+            cycles: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            last_sent_timestamp: 0,
+            last_sent_time: None,
+            rtcp: RtcpStatistics::default(),
         }
     }
 
     pub fn update_received(&mut self, packet: &RtpPacket) {
         self.packets_received += 1;
         self.bytes_received += packet.payload.len() as u64;
+        self.loss.record(packet.sequence_number);
+        self.ssrc = packet.ssrc;
+
+        // Extended highest sequence number, as RFC 3550 A.1 keeps it
+        let advance = packet.sequence_number.wrapping_sub(self.max_sequence);
+        if self.packets_received == 1 {
+            self.base_sequence = packet.sequence_number;
+            self.max_sequence = packet.sequence_number;
+        } else if advance != 0 && advance < 0x8000 {
+            if packet.sequence_number < self.max_sequence {
+                self.cycles += 1;
+            }
+            self.max_sequence = packet.sequence_number;
+        }
         
         let now = Instant::now();
         
@@ -238,6 +504,50 @@ impl RtpStreamStats {
     pub fn update_sent(&mut self, packet: &RtpPacket) {
         self.packets_sent += 1;
         self.bytes_sent += packet.payload.len() as u64;
+        self.last_sent_timestamp = packet.timestamp;
+        self.last_sent_time = Some(Instant::now());
+    }
+
+    /// Share of the received stream lost, in percent
+    pub fn loss_pct(&self) -> f64 {
+        let expected = self.packets_received + self.packets_lost as u64;
+        if expected == 0 {
+            0.0
+        } else {
+            self.packets_lost as f64 * 100.0 / expected as f64
+        }
+    }
+
+    /// Report block on the received stream, covering loss since the
+    /// previous one; `now` times the delay since the far end's last SR
+    pub fn reception_report(&mut self, now: Instant) -> ReceptionReport {
+        let highest_sequence = (self.cycles << 16) | self.max_sequence as u32;
+        let expected = highest_sequence.wrapping_sub(self.base_sequence as u32).wrapping_add(1);
+        let expected_interval = expected.wrapping_sub(self.expected_prior) as i64;
+        let received_interval = (self.packets_received - self.received_prior) as i64;
+        let lost_interval = expected_interval - received_interval;
+        self.expected_prior = expected;
+        self.received_prior = self.packets_received;
+
+        let fraction_lost = if expected_interval <= 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval).min(255) as u8
+        };
+        let (last_sr, delay_since_last_sr) = match self.rtcp.last_sr {
+            Some((last_sr, at)) => (last_sr, (now.duration_since(at).as_secs_f64() * 65536.0) as u32),
+            None => (0, 0),
+        };
+        ReceptionReport {
+            ssrc: self.ssrc,
+            fraction_lost,
+            cumulative_lost: (expected as i64 - self.packets_received as i64).clamp(-0x80_0000, 0x7F_FFFF) as i32,
+            highest_sequence,
+            // Jitter is kept in ms; 8 kHz clock assumed
+            jitter: (self.jitter * 8.0) as u32,
+            last_sr,
+            delay_since_last_sr,
+        }
     }
 
     pub fn packet_loss_rate(&self) -> f64 {
@@ -253,7 +563,11 @@ impl RtpStreamStats {
 #[derive(Debug, Clone)]
 pub struct RtpSession {
     pub id: String,
+    /// Local address the session's socket is bound to
+    pub local_ip: IpAddr,
     pub local_port: u16,
+    /// Network device the socket is bound to, when pinned to one
+    pub device: Option<String>,
     pub remote_addr: Option<SocketAddr>,
     pub ssrc: u32,
     pub payload_type: u8,
@@ -262,6 +576,14 @@ pub struct RtpSession {
     pub created_at: Instant,
     pub last_activity: Instant,
     pub stats: RtpStreamStats,
+    /// Take the remote address from the next packet received, as after a
+    /// restore where the far end may have moved
+    pub relatch: bool,
+    /// VoIP metrics in the far end's latest extended report, describing
+    /// the stream we send
+    pub remote_metrics: Option<VoipMetrics>,
+    /// Where the far end's RTCP comes from, which is where ours goes
+    pub rtcp_remote: Option<SocketAddr>,
 }
 
 impl RtpSession {
@@ -274,7 +596,9 @@ impl RtpSession {
 
         Self {
             id,
+            local_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             local_port,
+            device: None,
             remote_addr: None,
             ssrc,
             payload_type,
@@ -283,6 +607,9 @@ impl RtpSession {
             created_at: Instant::now(),
             last_activity: Instant::now(),
             stats: RtpStreamStats::new(ssrc),
+            relatch: false,
+            remote_metrics: None,
+            rtcp_remote: None,
         }
     }
 
@@ -295,6 +622,29 @@ impl RtpSession {
     pub fn update_activity(&mut self) {
         self.last_activity = Instant::now();
     }
+
+    /// The session's next report: an SR when it has sent media within
+    /// `sending_window`, otherwise an RR, with a block on the received
+    /// stream once there is one
+    pub fn rtcp_report(&mut self, sending_window: Duration, now: Instant, wallclock: SystemTime) -> RtcpReport {
+        let stats = &mut self.stats;
+        let sender = match stats.last_sent_time {
+            Some(sent_at) if now.duration_since(sent_at) < sending_window => {
+                // Extrapolate the RTP clock from the last packet sent; 8 kHz assumed
+                let elapsed = (now.duration_since(sent_at).as_millis() as u32).wrapping_mul(8);
+                Some(SenderInfo {
+                    ntp_timestamp: ntp_timestamp(wallclock),
+                    rtp_timestamp: stats.last_sent_timestamp.wrapping_add(elapsed),
+                    packet_count: stats.packets_sent as u32,
+                    octet_count: stats.bytes_sent as u32,
+                })
+            }
+            _ => None,
+        };
+        let reports = if stats.packets_received > 0 { vec![stats.reception_report(now)] } else { Vec::new() };
+        stats.rtcp.reports_sent += 1;
+        RtcpReport { ssrc: self.ssrc, sender, reports }
+    }
 }
 
 /// RTP events
@@ -322,7 +672,20 @@ pub enum RtpEvent {
 pub struct RtpHandler {
     port_range: PortRange,
     sessions: Arc<DashMap<String, RtpSession>>,
-    sockets: Arc<DashMap<u16, Arc<UdpSocket>>>,
+    sockets: Arc<DashMap<u16, Arc<BatchedUdpSocket>>>,
+    /// RTCP sockets, one port above their session's RTP port
+    rtcp_sockets: Arc<DashMap<u16, Arc<BatchedUdpSocket>>>,
+    /// SRTP contexts of the sessions that negotiated it
+    srtp: Arc<DashMap<String, SrtpSession>>,
+    /// ICE and DTLS state of WebRTC sessions
+    webrtc: Arc<DashMap<String, WebRtcTransport>>,
+    /// Outstanding Binding requests to the STUN server, by transaction ID
+    stun_transactions: Arc<DashMap<[u8; 12], oneshot::Sender<SocketAddr>>>,
+    /// Certificate presented on DTLS legs, generated on first use
+    dtls_identity: OnceLock<DtlsIdentity>,
+    batching: RtpBatchingConfig,
+    network: NetworkPlacement,
+    rtcp: RtcpConfig,
     event_tx: mpsc::UnboundedSender<RtpEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<RtpEvent>>,
     next_port: Arc<RwLock<u16>>,
@@ -331,8 +694,12 @@ pub struct RtpHandler {
 
 impl RtpHandler {
     pub fn new(port_range: PortRange) -> Result<Self> {
+        Self::with_batching(port_range, RtpBatchingConfig::default())
+    }
+
+    pub fn with_batching(port_range: PortRange, batching: RtpBatchingConfig) -> Result<Self> {
         if port_range.min >= port_range.max {
-            return Err(Error::parse("Invalid RTP port range"));
+            return Err(Error::invalid_config("Invalid RTP port range"));
         }
 
         let (event_tx, event_rx) = mpsc::unbounded_channel();
@@ -342,6 +709,14 @@ impl RtpHandler {
             port_range,
             sessions: Arc::new(DashMap::new()),
             sockets: Arc::new(DashMap::new()),
+            rtcp_sockets: Arc::new(DashMap::new()),
+            srtp: Arc::new(DashMap::new()),
+            webrtc: Arc::new(DashMap::new()),
+            stun_transactions: Arc::new(DashMap::new()),
+            dtls_identity: OnceLock::new(),
+            batching,
+            network: NetworkPlacement::default(),
+            rtcp: RtcpConfig::default(),
             event_tx,
             event_rx: Some(event_rx),
             next_port: Arc::new(RwLock::new(min_port)),
@@ -353,6 +728,17 @@ impl RtpHandler {
         self.event_rx.take()
     }
 
+    /// Open media sockets in the service network's namespace and device
+    pub fn set_network_placement(&mut self, network: NetworkPlacement) {
+        self.network = network;
+    }
+
+    /// Send and receive RTCP as configured; takes effect for sessions
+    /// created afterwards
+    pub fn set_rtcp(&mut self, rtcp: RtcpConfig) {
+        self.rtcp = rtcp;
+    }
+
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting RTP handler");
 
@@ -372,6 +758,20 @@ impl RtpHandler {
             Self::statistics_loop(sessions_stats, event_tx_stats).await;
         });
 
+        // Start RTCP reporting task
+        if self.rtcp.enabled {
+            let sessions_rtcp = Arc::clone(&self.sessions);
+            let sockets_rtcp = Arc::clone(&self.sockets);
+            let rtcp_sockets_rtcp = Arc::clone(&self.rtcp_sockets);
+            let srtp_rtcp = Arc::clone(&self.srtp);
+            let webrtc_rtcp = Arc::clone(&self.webrtc);
+            let report_interval = Duration::from_secs(self.rtcp.interval_secs);
+
+            tokio::spawn(async move {
+                Self::rtcp_loop(sessions_rtcp, sockets_rtcp, rtcp_sockets_rtcp, srtp_rtcp, webrtc_rtcp, report_interval).await;
+            });
+        }
+
         self.is_running = true;
         info!("RTP handler started successfully");
         Ok(())
@@ -423,53 +823,83 @@ impl RtpHandler {
         }
     }
 
+    /// Send every plain RTP session with a far end its SR or RR, with the
+    /// CNAME every compound packet carries
+    async fn rtcp_loop(
+        sessions: Arc<DashMap<String, RtpSession>>,
+        sockets: Arc<DashMap<u16, Arc<BatchedUdpSocket>>>,
+        rtcp_sockets: Arc<DashMap<u16, Arc<BatchedUdpSocket>>>,
+        srtp: Arc<DashMap<String, SrtpSession>>,
+        webrtc: Arc<DashMap<String, WebRtcTransport>>,
+        report_interval: Duration,
+    ) {
+        let mut rtcp_interval = interval(report_interval);
+
+        loop {
+            rtcp_interval.tick().await;
+            let now = Instant::now();
+            let wallclock = SystemTime::now();
+
+            let mut outgoing = Vec::new();
+            for mut session in sessions.iter_mut() {
+                if srtp.contains_key(&session.id) || webrtc.contains_key(&session.id) {
+                    continue;
+                }
+                let Some(remote) = session.remote_addr else {
+                    continue;
+                };
+                let muxed = session.rtcp_remote == Some(remote);
+                let target = session
+                    .rtcp_remote
+                    .unwrap_or_else(|| SocketAddr::new(remote.ip(), remote.port().saturating_add(1)));
+                // A sender is still one if it sent since the report before last
+                let report = session.rtcp_report(report_interval * 2, now, wallclock);
+                let mut compound = report.encode();
+                compound.extend(sdes_cname(session.ssrc, &format!("{:08x}@{}", session.ssrc, session.local_ip)));
+                outgoing.push((session.id.clone(), session.local_port, muxed, target, compound));
+            }
+
+            for (session_id, port, muxed, target, compound) in outgoing {
+                let rtcp_socket = if muxed { None } else { rtcp_sockets.get(&port).map(|socket| Arc::clone(socket.value())) };
+                let socket = rtcp_socket.or_else(|| sockets.get(&port).map(|socket| Arc::clone(socket.value())));
+                let Some(socket) = socket else {
+                    continue;
+                };
+                match socket.send_to(&compound, target).await {
+                    Ok(_) => trace!("Sent RTCP report for session {} to {}", session_id, target),
+                    Err(e) => debug!("Failed to send RTCP report for session {} to {}: {}", session_id, target, e),
+                }
+            }
+        }
+    }
+
     async fn receive_loop(
-        socket: Arc<UdpSocket>,
+        socket: Arc<BatchedUdpSocket>,
         port: u16,
         sessions: Arc<DashMap<String, RtpSession>>,
+        srtp: Arc<DashMap<String, SrtpSession>>,
+        webrtc: Arc<DashMap<String, WebRtcTransport>>,
+        stun_transactions: Arc<DashMap<[u8; 12], oneshot::Sender<SocketAddr>>>,
         event_tx: mpsc::UnboundedSender<RtpEvent>,
     ) {
-        let mut buffer = vec![0u8; 2048];
+        let mut buffers = socket.recv_buffers();
 
         loop {
-            match socket.recv_from(&mut buffer).await {
-                Ok((size, source)) => {
-                    let data = Bytes::copy_from_slice(&buffer[..size]);
-                    
-                    match RtpPacket::decode(data) {
-                        Ok(packet) => {
-                            trace!("Received RTP packet: SSRC={}, PT={}, Seq={}, TS={}",
-                                packet.ssrc, packet.payload_type, packet.sequence_number, packet.timestamp);
-
-                            // Find session by port and update statistics
-                            let mut found_session = false;
-                            for mut session in sessions.iter_mut() {
-                                if session.local_port == port {
-                                    session.update_activity();
-                                    session.stats.update_received(&packet);
-                                    
-                                    // Update remote address if not set
-                                    if session.remote_addr.is_none() {
-                                        session.remote_addr = Some(source);
-                                    }
-
-                                    let _ = event_tx.send(RtpEvent::PacketReceived {
-                                        session_id: session.id.clone(),
-                                        packet,
-                                        source,
-                                    });
-                                    
-                                    found_session = true;
-                                    break;
-                                }
-                            }
-
-                            if !found_session {
-                                debug!("Received RTP packet for unknown session on port {}", port);
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Failed to decode RTP packet from {}: {}", source, e);
+            match socket.recv_batch(&mut buffers).await {
+                Ok(datagrams) => {
+                    for datagram in datagrams {
+                        let session_id = sessions
+                            .iter()
+                            .find(|session| session.local_port == port)
+                            .map(|session| session.id.clone());
+                        if stun::is_stun(&datagram.data) || webrtc::is_dtls(&datagram.data) {
+                            Self::dispatch_transport(
+                                &datagram.data, datagram.source, session_id, &socket, &sessions, &srtp, &webrtc, &stun_transactions,
+                            ).await;
+                        } else if webrtc::is_rtcp(&datagram.data) {
+                            Self::dispatch_rtcp(&datagram.data, datagram.source, port, session_id, &sessions, &srtp);
+                        } else {
+                            Self::dispatch_datagram(datagram.data, datagram.source, port, session_id, &sessions, &srtp, &webrtc, &event_tx);
                         }
                     }
                 }
@@ -480,30 +910,284 @@ impl RtpHandler {
         }
     }
 
+    /// Read the session's RTCP port; anything but RTCP is dropped
+    async fn rtcp_receive_loop(
+        socket: Arc<BatchedUdpSocket>,
+        port: u16,
+        sessions: Arc<DashMap<String, RtpSession>>,
+        srtp: Arc<DashMap<String, SrtpSession>>,
+    ) {
+        let mut buffers = socket.recv_buffers();
+
+        loop {
+            match socket.recv_batch(&mut buffers).await {
+                Ok(datagrams) => {
+                    for datagram in datagrams.into_iter().filter(|datagram| webrtc::is_rtcp(&datagram.data)) {
+                        let session_id = sessions
+                            .iter()
+                            .find(|session| session.local_port == port)
+                            .map(|session| session.id.clone());
+                        Self::dispatch_rtcp(&datagram.data, datagram.source, port, session_id, &sessions, &srtp);
+                    }
+                }
+                Err(e) => {
+                    error!("RTCP receive error on port {}: {}", port + 1, e);
+                }
+            }
+        }
+    }
+
+    /// Handle STUN and DTLS: answers to gathering requests, ICE checks and
+    /// handshake records of WebRTC sessions
+    async fn dispatch_transport(
+        data: &[u8],
+        source: SocketAddr,
+        session_id: Option<String>,
+        socket: &BatchedUdpSocket,
+        sessions: &DashMap<String, RtpSession>,
+        srtp: &DashMap<String, SrtpSession>,
+        webrtc: &DashMap<String, WebRtcTransport>,
+        stun_transactions: &DashMap<[u8; 12], oneshot::Sender<SocketAddr>>,
+    ) {
+        if stun::is_stun(data) {
+            if let Some(response) = StunMessage::parse(data).ok().filter(|message| message.message_type == stun::BINDING_SUCCESS) {
+                if let (Some((_, waiter)), Some(mapped)) =
+                    (stun_transactions.remove(&response.transaction_id), response.xor_mapped_address())
+                {
+                    let _ = waiter.send(mapped);
+                }
+                return;
+            }
+        }
+
+        let Some(session_id) = session_id else {
+            return;
+        };
+        let latched = sessions.get(&session_id).is_some_and(|session| session.remote_addr.is_some());
+        let output = match webrtc.get_mut(&session_id) {
+            Some(mut transport) => transport.handle(data, source, latched),
+            None => return,
+        };
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("WebRTC transport of RTP session {} failed: {}", session_id, e);
+                return;
+            }
+        };
+
+        for reply in &output.replies {
+            if let Err(e) = socket.send_to(reply, source).await {
+                debug!("Failed to answer {} for RTP session {}: {}", source, session_id, e);
+            }
+        }
+        if let Some(address) = output.latch {
+            if let Some(mut session) = sessions.get_mut(&session_id) {
+                if session.remote_addr != Some(address) {
+                    info!("Latched WebRTC session {} to {}", session_id, address);
+                    session.remote_addr = Some(address);
+                }
+            }
+        }
+        if let Some(context) = output.srtp {
+            info!("DTLS-SRTP ({}) established on RTP session {}", context.profile().sdp_name(), session_id);
+            srtp.insert(session_id, context);
+        }
+    }
+
+    /// Keep what the far end's RTCP reports: its SR timing, its reception
+    /// of our stream and the VoIP metrics of an extended report
+    fn dispatch_rtcp(
+        data: &[u8],
+        source: SocketAddr,
+        port: u16,
+        session_id: Option<String>,
+        sessions: &DashMap<String, RtpSession>,
+        srtp: &DashMap<String, SrtpSession>,
+    ) {
+        // SRTCP is not unprotected here
+        let Some(mut session) = session_id.filter(|id| !srtp.contains_key(id)).and_then(|id| sessions.get_mut(&id)) else {
+            trace!("Dropped RTCP from {} on port {}", source, port);
+            return;
+        };
+        let report = RtcpReport::find(data);
+        let extended = ExtendedReport::find(data);
+        if report.is_none() && extended.is_none() {
+            trace!("Dropped RTCP from {} on port {}", source, port);
+            return;
+        }
+        session.rtcp_remote = Some(source);
+
+        if let Some(report) = report {
+            let ssrc = session.ssrc;
+            session.stats.rtcp.on_report(&report, ssrc, ntp_timestamp(SystemTime::now()), Instant::now());
+            trace!(
+                "RTCP report from {} for session {}: remote loss {:?}%, RTT {:?} ms",
+                source, session.id, session.stats.rtcp.remote_loss_pct(), session.stats.rtcp.round_trip_ms
+            );
+        }
+        if let Some(metrics) = extended.as_ref().and_then(ExtendedReport::voip_metrics) {
+            debug!(
+                "RTCP XR from {} for session {}: loss {:.1}%, MOS-LQ {:?}",
+                source, session.id, metrics.loss_pct(), metrics.mos_lq()
+            );
+            session.remote_metrics = Some(*metrics);
+        }
+    }
+
+    fn dispatch_datagram(
+        data: Bytes,
+        source: SocketAddr,
+        port: u16,
+        session_id: Option<String>,
+        sessions: &DashMap<String, RtpSession>,
+        srtp: &DashMap<String, SrtpSession>,
+        webrtc: &DashMap<String, WebRtcTransport>,
+        event_tx: &mpsc::UnboundedSender<RtpEvent>,
+    ) {
+        let data = match session_id.as_ref().and_then(|id| srtp.get_mut(id)) {
+            Some(mut context) => match context.unprotect(&data) {
+                Ok(plain) => Bytes::from(plain),
+                Err(e) => {
+                    debug!("Dropped SRTP packet from {} on port {}: {}", source, port, e);
+                    return;
+                }
+            },
+            // A WebRTC session has no media before its handshake keys it
+            None if session_id.as_deref().is_some_and(|id| webrtc.contains_key(id)) => {
+                debug!("Dropped media from {} on port {} before DTLS-SRTP was established", source, port);
+                return;
+            }
+            None => data,
+        };
+
+        match RtpPacket::decode(data) {
+            Ok(packet) => {
+                trace!("Received RTP packet: SSRC={}, PT={}, Seq={}, TS={}",
+                    packet.ssrc, packet.payload_type, packet.sequence_number, packet.timestamp);
+
+                // Find session by port and update statistics
+                let mut found_session = false;
+                for mut session in sessions.iter_mut() {
+                    if session.local_port == port {
+                        session.update_activity();
+                        session.stats.update_received(&packet);
+                        
+                        // Update remote address if not set
+                        if session.remote_addr.is_none() {
+                            session.remote_addr = Some(source);
+                        } else if session.relatch {
+                            if session.remote_addr != Some(source) {
+                                info!("Re-latched RTP session {} to {}", session.id, source);
+                            }
+                            session.remote_addr = Some(source);
+                            session.relatch = false;
+                        }
+
+                        let _ = event_tx.send(RtpEvent::PacketReceived {
+                            session_id: session.id.clone(),
+                            packet,
+                            source,
+                        });
+                        
+                        found_session = true;
+                        break;
+                    }
+                }
+
+                if !found_session {
+                    debug!("Received RTP packet for unknown session on port {}", port);
+                }
+            }
+            Err(e) => {
+                warn!("Failed to decode RTP packet from {}: {}", source, e);
+            }
+        }
+    }
+
     pub async fn create_session(&self, session_id: String, payload_type: u8) -> Result<RtpSession> {
+        self.create_session_on(session_id, payload_type, IpAddr::V4(Ipv4Addr::UNSPECIFIED), None).await
+    }
+
+    /// Create a session whose socket is bound to a specific local address and,
+    /// optionally, network device
+    pub async fn create_session_on(
+        &self,
+        session_id: String,
+        payload_type: u8,
+        bind_ip: IpAddr,
+        device: Option<&str>,
+    ) -> Result<RtpSession> {
         let port = self.allocate_port().await?;
-        
+        let session = RtpSession::new(session_id, port, payload_type);
+        self.bind_session(session, bind_ip, device).await
+    }
+
+    /// Recreate a session on its previous port with its previous SSRC,
+    /// sequence and timestamp base, so both far ends see one continuous
+    /// stream; the remote address is re-latched from the next packet
+    pub async fn restore_session(&self, mut session: RtpSession) -> Result<RtpSession> {
+        if self.sockets.contains_key(&session.local_port) {
+            return Err(Error::resource_exhausted(format!("RTP port {} is already in use", session.local_port)));
+        }
+        session.created_at = Instant::now();
+        session.last_activity = Instant::now();
+        session.stats = RtpStreamStats::new(session.ssrc);
+        session.relatch = true;
+        let bind_ip = session.local_ip;
+        let device = session.device.take();
+        self.bind_session(session, bind_ip, device.as_deref()).await
+    }
+
+    async fn bind_session(&self, mut session: RtpSession, bind_ip: IpAddr, device: Option<&str>) -> Result<RtpSession> {
+        let port = session.local_port;
+
         // Create and bind socket
-        let bind_addr = format!("0.0.0.0:{}", port);
-        let socket = UdpSocket::bind(&bind_addr).await
+        let bind_addr = SocketAddr::new(bind_ip, port);
+        let placement = NetworkPlacement {
+            device: device.map(str::to_string).or_else(|| self.network.device.clone()),
+            netns: self.network.netns.clone(),
+        };
+        let socket = netbind::bind_udp(bind_addr, &placement)
             .map_err(|e| Error::network(format!("Failed to bind RTP socket to {}: {}", bind_addr, e)))?;
 
-        let socket = Arc::new(socket);
+        let socket = Arc::new(BatchedUdpSocket::new(socket, self.batching.clone()));
         self.sockets.insert(port, Arc::clone(&socket));
 
         // Start receiver task for this socket
         let socket_recv = Arc::clone(&socket);
         let sessions_recv = Arc::clone(&self.sessions);
+        let srtp_recv = Arc::clone(&self.srtp);
+        let webrtc_recv = Arc::clone(&self.webrtc);
+        let stun_recv = Arc::clone(&self.stun_transactions);
         let event_tx_recv = self.event_tx.clone();
 
         tokio::spawn(async move {
-            Self::receive_loop(socket_recv, port, sessions_recv, event_tx_recv).await;
+            Self::receive_loop(socket_recv, port, sessions_recv, srtp_recv, webrtc_recv, stun_recv, event_tx_recv).await;
         });
 
-        let session = RtpSession::new(session_id.clone(), port, payload_type);
-        self.sessions.insert(session_id, session.clone());
+        if self.rtcp.enabled {
+            // Without its own port RTCP still works when the far end multiplexes it
+            let rtcp_addr = SocketAddr::new(bind_ip, port.wrapping_add(1));
+            match netbind::bind_udp(rtcp_addr, &placement) {
+                Ok(rtcp_socket) => {
+                    let rtcp_socket = Arc::new(BatchedUdpSocket::new(rtcp_socket, self.batching.clone()));
+                    self.rtcp_sockets.insert(port, Arc::clone(&rtcp_socket));
+                    let sessions_rtcp = Arc::clone(&self.sessions);
+                    let srtp_rtcp = Arc::clone(&self.srtp);
+                    tokio::spawn(async move {
+                        Self::rtcp_receive_loop(rtcp_socket, port, sessions_rtcp, srtp_rtcp).await;
+                    });
+                }
+                Err(e) => warn!("No RTCP port for RTP session {}: failed to bind {}: {}", session.id, rtcp_addr, e),
+            }
+        }
+
+        session.local_ip = bind_ip;
+        session.device = device.map(str::to_string);
+        self.sessions.insert(session.id.clone(), session.clone());
 
-        info!("Created RTP session {} on port {}", session.id, port);
+        info!("Created RTP session {} on {}", session.id, bind_addr);
         Ok(session)
     }
 
@@ -535,7 +1219,7 @@ impl RtpHandler {
         packet.marker = marker;
         packet.payload = payload;
 
-        let encoded = packet.encode();
+        let encoded = self.protect(session_id, packet.encode())?;
         socket.send_to(&encoded, remote_addr).await?;
 
         // Update statistics
@@ -551,6 +1235,210 @@ impl RtpHandler {
         Ok(())
     }
 
+    /// Send several packets on a session with as few syscalls as the socket allows.
+    ///
+    /// Each entry is a payload, its timestamp offset and the marker bit.
+    pub async fn send_packets(&self, session_id: &str, payloads: Vec<(Bytes, u32, bool)>) -> Result<()> {
+        let session = self.sessions.get(session_id)
+            .ok_or_else(|| Error::rtp("RTP session not found"))?
+            .clone();
+
+        let socket = self.sockets.get(&session.local_port)
+            .map(|socket| Arc::clone(socket.value()))
+            .ok_or_else(|| Error::rtp("RTP socket not found"))?;
+
+        let remote_addr = session.remote_addr
+            .ok_or_else(|| Error::rtp("Remote address not set"))?;
+
+        let mut packets = Vec::with_capacity(payloads.len());
+        let mut datagrams = Vec::with_capacity(payloads.len());
+        for (payload, timestamp_offset, marker) in payloads {
+            let mut packet = RtpPacket::new(
+                session.payload_type,
+                session.next_sequence_number().await,
+                session.timestamp_base + timestamp_offset,
+                session.ssrc,
+            );
+            packet.marker = marker;
+            packet.payload = payload;
+            datagrams.push((self.protect(session_id, packet.encode())?, remote_addr));
+            packets.push(packet);
+        }
+
+        socket.send_batch(&datagrams).await?;
+
+        if let Some(mut session) = self.sessions.get_mut(session_id) {
+            session.update_activity();
+            for packet in &packets {
+                session.stats.update_sent(packet);
+            }
+        }
+
+        trace!("Sent {} RTP packets in batch: session={}", packets.len(), session_id);
+        Ok(())
+    }
+
+    /// Relay a packet received on another session, keeping its timestamp,
+    /// payload type and marker but numbering it into this session's stream
+    pub async fn forward_packet(&self, session_id: &str, received: &RtpPacket) -> Result<()> {
+        let session = self.sessions.get(session_id)
+            .ok_or_else(|| Error::rtp("RTP session not found"))?
+            .clone();
+
+        let socket = self.sockets.get(&session.local_port)
+            .map(|socket| Arc::clone(socket.value()))
+            .ok_or_else(|| Error::rtp("RTP socket not found"))?;
+
+        let remote_addr = session.remote_addr
+            .ok_or_else(|| Error::rtp("Remote address not set"))?;
+
+        let mut packet = RtpPacket::new(
+            received.payload_type,
+            session.next_sequence_number().await,
+            received.timestamp,
+            session.ssrc,
+        );
+        packet.marker = received.marker;
+        packet.payload = received.payload.clone();
+
+        let encoded = self.protect(session_id, packet.encode())?;
+        socket.send_to(&encoded, remote_addr).await?;
+
+        if let Some(mut session) = self.sessions.get_mut(session_id) {
+            session.update_activity();
+            session.stats.update_sent(&packet);
+        }
+        Ok(())
+    }
+
+    /// Protect a session's media with SRTP: `local` keys what is sent,
+    /// `remote` what is received
+    pub fn enable_srtp(&self, session_id: &str, local: &CryptoAttribute, remote: &CryptoAttribute) -> Result<()> {
+        if !self.sessions.contains_key(session_id) {
+            return Err(Error::rtp("RTP session not found"));
+        }
+        self.srtp.insert(session_id.to_string(), SrtpSession::new(local, remote));
+        info!("Enabled SRTP ({}) on RTP session {}", local.profile.sdp_name(), session_id);
+        Ok(())
+    }
+
+    /// Crypto suite protecting a session, if any
+    pub fn srtp_profile(&self, session_id: &str) -> Option<SrtpProfile> {
+        self.srtp.get(session_id).map(|context| context.profile())
+    }
+
+    /// Whether the relay holds SRTP or DTLS-SRTP keys for a session
+    pub fn is_encrypted(&self, session_id: &str) -> bool {
+        self.srtp.contains_key(session_id) || self.webrtc.contains_key(session_id)
+    }
+
+    pub fn get_srtp_statistics(&self, session_id: &str) -> Option<SrtpStats> {
+        self.srtp.get(session_id).map(|context| context.stats())
+    }
+
+    fn protect(&self, session_id: &str, encoded: Bytes) -> Result<Bytes> {
+        match self.srtp.get_mut(session_id) {
+            Some(mut context) => context.protect(&encoded).map(Bytes::from),
+            None if self.webrtc.contains_key(session_id) => Err(Error::rtp("DTLS-SRTP not yet established")),
+            None => Ok(encoded),
+        }
+    }
+
+    /// Fingerprint of the certificate this handler presents on DTLS legs
+    pub fn dtls_fingerprint(&self) -> Result<Fingerprint> {
+        Ok(self.dtls_identity()?.fingerprint().clone())
+    }
+
+    fn dtls_identity(&self) -> Result<&DtlsIdentity> {
+        if let Some(identity) = self.dtls_identity.get() {
+            return Ok(identity);
+        }
+        let _ = self.dtls_identity.set(DtlsIdentity::generate()?);
+        Ok(self.dtls_identity.get().expect("DTLS identity was just set"))
+    }
+
+    /// Run a session as a WebRTC leg: answer ICE checks with `local`
+    /// credentials and accept the DTLS handshake of the far end described
+    /// by `remote`. Enabling a session again leaves it as it is.
+    pub fn enable_webrtc(&self, session_id: &str, local: &IceCredentials, remote: &RemoteParameters) -> Result<()> {
+        if !self.sessions.contains_key(session_id) {
+            return Err(Error::rtp("RTP session not found"));
+        }
+        if self.webrtc.contains_key(session_id) {
+            return Ok(());
+        }
+        // The relay only takes the passive, server role
+        if matches!(remote.setup, DtlsSetup::Passive | DtlsSetup::Holdconn) {
+            return Err(Error::not_supported(format!("DTLS setup:{} needs the relay to be the DTLS client", remote.setup.as_str())));
+        }
+        let dtls = DtlsEndpoint::accept(self.dtls_identity()?, remote.fingerprint.clone())?;
+        let ice = IceLite::new(local.clone(), remote.ice.clone());
+        self.webrtc.insert(session_id.to_string(), WebRtcTransport::new(ice, dtls));
+        info!("Enabled WebRTC transport on RTP session {}", session_id);
+        Ok(())
+    }
+
+    /// Candidates for a session's socket: a host candidate on
+    /// `advertised_ip` and, when `stun_server` answers within `timeout`, a
+    /// server-reflexive one
+    pub async fn gather_candidates(
+        &self,
+        session_id: &str,
+        advertised_ip: IpAddr,
+        stun_server: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Vec<IceCandidate>> {
+        let port = self.sessions.get(session_id)
+            .map(|session| session.local_port)
+            .ok_or_else(|| Error::rtp("RTP session not found"))?;
+        let host = SocketAddr::new(advertised_ip, port);
+        let mut candidates = vec![IceCandidate::host(host)];
+
+        if let Some(server) = stun_server {
+            match self.query_stun_server(port, server, timeout).await {
+                Ok(mapped) if mapped != host => candidates.push(IceCandidate::server_reflexive(mapped, host)),
+                Ok(_) => {}
+                Err(e) => warn!("No server-reflexive candidate for RTP session {}: {}", session_id, e),
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Address `server` sees the socket on `port` from
+    async fn query_stun_server(&self, port: u16, server: &str, timeout: Duration) -> Result<SocketAddr> {
+        let socket = self.sockets.get(&port)
+            .map(|socket| Arc::clone(socket.value()))
+            .ok_or_else(|| Error::rtp("RTP socket not found"))?;
+        let server_addr = tokio::net::lookup_host(server)
+            .await
+            .map_err(|e| Error::network(format!("Failed to resolve STUN server {}: {}", server, e)))?
+            .next()
+            .ok_or_else(|| Error::network(format!("STUN server {} has no address", server)))?;
+
+        let request = StunMessage::binding_request();
+        let (tx, rx) = oneshot::channel();
+        self.stun_transactions.insert(request.transaction_id, tx);
+        let result = async {
+            socket.send_to(&request.encode(None, true), server_addr).await?;
+            tokio::time::timeout(timeout, rx)
+                .await
+                .map_err(|_| Error::network(format!("STUN server {} did not answer", server)))?
+                .map_err(|_| Error::network("STUN transaction abandoned"))
+        }
+        .await;
+        self.stun_transactions.remove(&request.transaction_id);
+        result
+    }
+
+    /// Syscall and offload counters for the socket on `port`
+    pub fn get_socket_statistics(&self, port: u16) -> Option<RtpSocketStats> {
+        self.sockets.get(&port).map(|socket| socket.stats())
+    }
+
+    pub fn get_all_socket_statistics(&self) -> Vec<(u16, RtpSocketStats)> {
+        self.sockets.iter().map(|entry| (*entry.key(), entry.stats())).collect()
+    }
+
     pub async fn set_remote_address(&self, session_id: &str, remote_addr: SocketAddr) -> Result<()> {
         if let Some(mut session) = self.sessions.get_mut(session_id) {
             session.remote_addr = Some(remote_addr);
@@ -569,6 +1457,58 @@ impl RtpHandler {
         self.sessions.get(session_id).map(|session| session.stats.clone())
     }
 
+    /// Extended report on the stream a session receives: a Loss RLE block
+    /// covering the packets since the previous report, when asked for, and
+    /// VoIP metrics of the whole stream so far, with the round trip measured
+    /// by RTCP reception reports when the far end sends them
+    pub fn extended_report(&self, session_id: &str, packet_ms: u32, loss_rle: bool) -> Option<ExtendedReport> {
+        let mut session = self.sessions.get_mut(session_id)?;
+        let source_ssrc = session.stats.ssrc;
+        let statistics = session.stats.loss.statistics();
+        let gmin = session.stats.loss.gmin();
+        let impairment = rtcp_xr::CodecImpairment::for_payload_type(session.payload_type);
+        let round_trip_ms = session.stats.rtcp.round_trip_ms.map(|ms| ms.round() as u32);
+
+        let mut report = ExtendedReport::new(session.ssrc);
+        if loss_rle {
+            let rle = session.stats.loss.take_loss_rle(source_ssrc);
+            report.blocks.push(ReportBlock::LossRle(rle));
+        }
+        report.blocks.push(ReportBlock::VoipMetrics(VoipMetrics::measure(
+            source_ssrc,
+            &statistics,
+            gmin,
+            packet_ms,
+            round_trip_ms,
+            impairment,
+        )));
+        Some(report)
+    }
+
+    /// Send an extended report from a session's socket to `target`, the
+    /// far end's RTCP address (its RTP address when RTCP is multiplexed)
+    pub async fn send_extended_report(&self, session_id: &str, report: &ExtendedReport, target: SocketAddr) -> Result<()> {
+        let port = self
+            .sessions
+            .get(session_id)
+            .map(|session| session.local_port)
+            .ok_or_else(|| Error::rtp("RTP session not found"))?;
+        if self.srtp.contains_key(session_id) {
+            return Err(Error::not_supported("RTCP XR on SRTP sessions needs SRTCP"));
+        }
+        let socket = self
+            .sockets
+            .get(&port)
+            .map(|socket| Arc::clone(&socket))
+            .ok_or_else(|| Error::rtp("RTP socket not found"))?;
+        socket
+            .send_to(&report.encode(), target)
+            .await
+            .map_err(|e| Error::rtp(format!("Failed to send RTCP XR to {}: {}", target, e)))?;
+        trace!("Sent RTCP XR for session {} to {}", session_id, target);
+        Ok(())
+    }
+
     pub fn get_all_sessions(&self) -> Vec<RtpSession> {
         self.sessions.iter().map(|entry| entry.value().clone()).collect()
     }
@@ -579,6 +1519,9 @@ impl RtpHandler {
             if let Some((_, socket)) = self.sockets.remove(&session.local_port) {
                 drop(socket); // Socket will be closed when dropped
             }
+            self.rtcp_sockets.remove(&session.local_port);
+            self.srtp.remove(session_id);
+            self.webrtc.remove(session_id);
 
             info!("Destroyed RTP session: {}", session_id);
             Ok(())
@@ -594,25 +1537,23 @@ impl RtpHandler {
         loop {
             let port = *next_port;
             
-            // Check if port is already in use
-            if !self.sockets.contains_key(&port) {
-                *next_port = if port >= self.port_range.max {
-                    self.port_range.min
-                } else {
-                    port + 1
-                };
-                return Ok(port);
-            }
-
             *next_port = if port >= self.port_range.max {
                 self.port_range.min
             } else {
                 port + 1
             };
 
+            // RTP takes even ports when RTCP has the odd one above
+            let pairable = !self.rtcp.enabled || (port % 2 == 0 && port < self.port_range.max);
+
+            // Check if port is already in use
+            if pairable && !self.sockets.contains_key(&port) {
+                return Ok(port);
+            }
+
             // Avoid infinite loop
             if *next_port == start_port {
-                return Err(Error::rtp("No available RTP ports"));
+                return Err(Error::resource_exhausted("No available RTP ports"));
             }
         }
     }
@@ -627,6 +1568,7 @@ impl RtpHandler {
         // Clear all sessions and sockets
         self.sessions.clear();
         self.sockets.clear();
+        self.rtcp_sockets.clear();
         
         self.is_running = false;
         info!("RTP handler stopped");
@@ -725,4 +1667,65 @@ mod tests {
         assert!(!samples.is_empty());
         assert_eq!(samples.len(), 800); // 100ms at 8kHz
     }
+
+    #[test]
+    fn test_rtcp_sender_report_in_compound_packet() {
+        let report = RtcpReport {
+            ssrc: 0x1111_1111,
+            sender: Some(SenderInfo { ntp_timestamp: 0x0000_0002_8000_0000, rtp_timestamp: 16000, packet_count: 100, octet_count: 16000 }),
+            reports: vec![ReceptionReport {
+                ssrc: 0x2222_2222,
+                fraction_lost: 26,
+                cumulative_lost: -3,
+                highest_sequence: 0x0001_0005,
+                jitter: 40,
+                last_sr: 0x0001_8000,
+                delay_since_last_sr: 0x4000,
+            }],
+        };
+        let sdes = sdes_cname(0x1111_1111, "gw@10.0.0.1");
+        assert_eq!(sdes.len() % 4, 0);
+
+        let mut compound = report.encode();
+        assert_eq!(compound.len(), 52);
+        compound.extend(sdes);
+        assert_eq!(RtcpReport::find(&compound), Some(report));
+
+        let receiver_only = RtcpReport { ssrc: 7, sender: None, reports: Vec::new() };
+        assert_eq!(RtcpReport::parse(&receiver_only.encode()).unwrap(), receiver_only);
+    }
+
+    #[test]
+    fn test_reception_report_loss_and_round_trip() {
+        let mut stats = RtpStreamStats::new(0x1111_1111);
+        for seq in (1..=10u16).filter(|seq| *seq != 4 && *seq != 5) {
+            stats.update_received(&RtpPacket::new(0, seq, seq as u32 * 160, 0x2222_2222));
+        }
+        let now = Instant::now();
+        let block = stats.reception_report(now);
+        assert_eq!(block.ssrc, 0x2222_2222);
+        assert_eq!(block.highest_sequence, 10);
+        assert_eq!(block.cumulative_lost, 2);
+        assert_eq!(block.fraction_lost, 51);
+        assert_eq!(stats.reception_report(now).fraction_lost, 0);
+
+        // Our SR left at NTP 1 s, was held 0.5 s, and the answer came at 2 s
+        let answer = RtcpReport {
+            ssrc: 0x2222_2222,
+            sender: None,
+            reports: vec![ReceptionReport {
+                ssrc: 0x1111_1111,
+                fraction_lost: 64,
+                cumulative_lost: 5,
+                highest_sequence: 200,
+                jitter: 80,
+                last_sr: 0x0001_0000,
+                delay_since_last_sr: 0x8000,
+            }],
+        };
+        stats.rtcp.on_report(&answer, 0x1111_1111, 2 << 32, now);
+        assert_eq!(stats.rtcp.round_trip_ms, Some(500.0));
+        assert_eq!(stats.rtcp.remote_loss_pct(), Some(25.0));
+        assert_eq!(stats.rtcp.remote_jitter_ms(), Some(10.0));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,573 @@
+//! Batched UDP sockets for RTP
+//!
+//! Wraps a tokio `UdpSocket` so that one syscall moves many datagrams:
+//! recvmmsg/sendmmsg on Linux, plus UDP GSO for same-destination bursts and
+//! optional GRO on receive. Other platforms fall back to a syscall per
+//! datagram behind the same interface.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bytes::Bytes;
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+use crate::config::RtpBatchingConfig;
+
+/// Receive slot size when GRO may hand back coalesced datagrams
+const GRO_BUFFER_SIZE: usize = 65536;
+/// Kernel limit on segments in one UDP_SEGMENT send
+const MAX_GSO_SEGMENTS: usize = 64;
+/// Stay below the 64 KiB UDP payload limit for a GSO super-datagram
+const MAX_GSO_BYTES: usize = 65000;
+
+/// Per-socket counters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RtpSocketStats {
+    pub recv_syscalls: u64,
+    pub datagrams_received: u64,
+    pub bytes_received: u64,
+    /// Receives the kernel coalesced with GRO
+    pub gro_coalesced: u64,
+    pub recv_errors: u64,
+    pub send_syscalls: u64,
+    pub datagrams_sent: u64,
+    pub bytes_sent: u64,
+    /// Messages sent as a single UDP_SEGMENT super-datagram
+    pub gso_sends: u64,
+    pub send_errors: u64,
+    pub gso_enabled: bool,
+    pub gro_enabled: bool,
+}
+
+impl RtpSocketStats {
+    /// Average datagrams moved per receive syscall
+    pub fn recv_batch_average(&self) -> f64 {
+        if self.recv_syscalls == 0 {
+            0.0
+        } else {
+            self.datagrams_received as f64 / self.recv_syscalls as f64
+        }
+    }
+
+    /// Average datagrams moved per send syscall
+    pub fn send_batch_average(&self) -> f64 {
+        if self.send_syscalls == 0 {
+            0.0
+        } else {
+            self.datagrams_sent as f64 / self.send_syscalls as f64
+        }
+    }
+}
+
+#[derive(Default)]
+struct StatsRecorder {
+    recv_syscalls: AtomicU64,
+    datagrams_received: AtomicU64,
+    bytes_received: AtomicU64,
+    gro_coalesced: AtomicU64,
+    recv_errors: AtomicU64,
+    send_syscalls: AtomicU64,
+    datagrams_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    gso_sends: AtomicU64,
+    send_errors: AtomicU64,
+}
+
+/// A received datagram
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    pub data: Bytes,
+    pub source: SocketAddr,
+}
+
+/// Reusable receive slots for [`BatchedUdpSocket::recv_batch`]
+pub struct RecvBuffers {
+    slots: Vec<Vec<u8>>,
+}
+
+impl RecvBuffers {
+    pub fn new(count: usize, size: usize) -> Self {
+        Self {
+            slots: (0..count.max(1)).map(|_| vec![0u8; size]).collect(),
+        }
+    }
+}
+
+/// One datagram as reported by the receive syscall
+#[derive(Debug)]
+struct Received {
+    slot: usize,
+    len: usize,
+    source: SocketAddr,
+    /// GRO segment size when the kernel coalesced several datagrams
+    segment_size: Option<usize>,
+}
+
+/// A run of packets sent as one message, segmented by the kernel when GSO applies
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    start: usize,
+    count: usize,
+    segment_size: Option<u16>,
+}
+
+/// UDP socket with syscall batching and segmentation offload
+pub struct BatchedUdpSocket {
+    socket: UdpSocket,
+    config: RtpBatchingConfig,
+    gso: AtomicBool,
+    gro: bool,
+    stats: StatsRecorder,
+}
+
+impl BatchedUdpSocket {
+    pub fn new(socket: UdpSocket, config: RtpBatchingConfig) -> Self {
+        let gro = config.enabled && config.gro && sys::enable_gro(&socket);
+        let gso = config.enabled && config.gso && sys::GSO_SUPPORTED;
+        Self {
+            socket,
+            config,
+            gso: AtomicBool::new(gso),
+            gro,
+            stats: StatsRecorder::default(),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Receive slots sized for this socket's batch and GRO settings
+    pub fn recv_buffers(&self) -> RecvBuffers {
+        let count = if self.config.enabled { self.config.recv_batch_size } else { 1 };
+        let size = if self.gro { GRO_BUFFER_SIZE } else { self.config.max_datagram_size.max(1) };
+        RecvBuffers::new(count, size)
+    }
+
+    /// Wait for at least one datagram and return everything available up to the batch size
+    pub async fn recv_batch(&self, buffers: &mut RecvBuffers) -> io::Result<Vec<Datagram>> {
+        let result = if self.config.enabled {
+            let gro = self.gro;
+            self.socket
+                .async_io(Interest::READABLE, || sys::recv_mmsg(&self.socket, &mut buffers.slots, gro))
+                .await
+        } else {
+            self.socket
+                .recv_from(&mut buffers.slots[0])
+                .await
+                .map(|(len, source)| vec![Received { slot: 0, len, source, segment_size: None }])
+        };
+
+        let received = match result {
+            Ok(received) => received,
+            Err(e) => {
+                self.stats.recv_errors.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+        self.stats.recv_syscalls.fetch_add(1, Ordering::Relaxed);
+
+        let mut datagrams = Vec::with_capacity(received.len());
+        for entry in received {
+            let data = &buffers.slots[entry.slot][..entry.len];
+            self.stats.bytes_received.fetch_add(entry.len as u64, Ordering::Relaxed);
+            match entry.segment_size {
+                Some(segment) if segment > 0 && segment < entry.len => {
+                    self.stats.gro_coalesced.fetch_add(1, Ordering::Relaxed);
+                    datagrams.extend(data.chunks(segment).map(|chunk| Datagram {
+                        data: Bytes::copy_from_slice(chunk),
+                        source: entry.source,
+                    }));
+                }
+                _ => datagrams.push(Datagram {
+                    data: Bytes::copy_from_slice(data),
+                    source: entry.source,
+                }),
+            }
+        }
+        self.stats.datagrams_received.fetch_add(datagrams.len() as u64, Ordering::Relaxed);
+        Ok(datagrams)
+    }
+
+    /// Send a single datagram
+    pub async fn send_to(&self, data: &[u8], target: SocketAddr) -> io::Result<usize> {
+        match self.socket.send_to(data, target).await {
+            Ok(sent) => {
+                self.stats.send_syscalls.fetch_add(1, Ordering::Relaxed);
+                self.stats.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+                self.stats.bytes_sent.fetch_add(sent as u64, Ordering::Relaxed);
+                Ok(sent)
+            }
+            Err(e) => {
+                self.stats.send_errors.fetch_add(1, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    /// Send datagrams in order, batching syscalls where possible
+    pub async fn send_batch(&self, packets: &[(Bytes, SocketAddr)]) -> io::Result<usize> {
+        if !self.config.enabled {
+            for (data, target) in packets {
+                self.send_to(data, *target).await?;
+            }
+            return Ok(packets.len());
+        }
+
+        for chunk in packets.chunks(self.config.send_batch_size.max(1)) {
+            self.send_chunk(chunk).await?;
+        }
+        Ok(packets.len())
+    }
+
+    async fn send_chunk(&self, packets: &[(Bytes, SocketAddr)]) -> io::Result<()> {
+        let mut messages = plan_messages(packets, self.gso.load(Ordering::Relaxed));
+        let mut next = 0;
+
+        while next < messages.len() {
+            let pending = &messages[next..];
+            let start = pending[0].start;
+            let uses_gso = pending.iter().any(|m| m.segment_size.is_some());
+
+            match self.socket
+                .async_io(Interest::WRITABLE, || sys::send_mmsg(&self.socket, packets, pending))
+                .await
+            {
+                Ok(sent) => {
+                    let sent_messages = &pending[..sent];
+                    let datagrams: usize = sent_messages.iter().map(|m| m.count).sum();
+                    let bytes: usize = sent_messages
+                        .iter()
+                        .flat_map(|m| &packets[m.start..m.start + m.count])
+                        .map(|(data, _)| data.len())
+                        .sum();
+                    let gso_sends = sent_messages.iter().filter(|m| m.segment_size.is_some()).count();
+
+                    self.stats.send_syscalls.fetch_add(1, Ordering::Relaxed);
+                    self.stats.datagrams_sent.fetch_add(datagrams as u64, Ordering::Relaxed);
+                    self.stats.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+                    self.stats.gso_sends.fetch_add(gso_sends as u64, Ordering::Relaxed);
+                    next += sent;
+                }
+                Err(e) if uses_gso && sys::is_gso_error(&e) && self.gso.swap(false, Ordering::Relaxed) => {
+                    warn!("UDP GSO unavailable on {:?} ({}), sending datagrams individually", self.socket.local_addr().ok(), e);
+                    messages = plan_messages(&packets[start..], false)
+                        .into_iter()
+                        .map(|m| Message { start: m.start + start, ..m })
+                        .collect();
+                    next = 0;
+                }
+                Err(e) => {
+                    self.stats.send_errors.fetch_add(1, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> RtpSocketStats {
+        RtpSocketStats {
+            recv_syscalls: self.stats.recv_syscalls.load(Ordering::Relaxed),
+            datagrams_received: self.stats.datagrams_received.load(Ordering::Relaxed),
+            bytes_received: self.stats.bytes_received.load(Ordering::Relaxed),
+            gro_coalesced: self.stats.gro_coalesced.load(Ordering::Relaxed),
+            recv_errors: self.stats.recv_errors.load(Ordering::Relaxed),
+            send_syscalls: self.stats.send_syscalls.load(Ordering::Relaxed),
+            datagrams_sent: self.stats.datagrams_sent.load(Ordering::Relaxed),
+            bytes_sent: self.stats.bytes_sent.load(Ordering::Relaxed),
+            gso_sends: self.stats.gso_sends.load(Ordering::Relaxed),
+            send_errors: self.stats.send_errors.load(Ordering::Relaxed),
+            gso_enabled: self.gso.load(Ordering::Relaxed),
+            gro_enabled: self.gro,
+        }
+    }
+}
+
+/// Group packets into messages. With GSO, consecutive packets to the same
+/// destination share a message as long as every segment but the last has the
+/// size of the first.
+fn plan_messages(packets: &[(Bytes, SocketAddr)], gso: bool) -> Vec<Message> {
+    let mut messages = Vec::new();
+    let mut index = 0;
+
+    while index < packets.len() {
+        let (first, target) = &packets[index];
+        let segment = first.len();
+        let mut count = 1;
+        let mut total = segment;
+
+        if gso && segment > 0 && segment <= u16::MAX as usize {
+            while index + count < packets.len() && count < MAX_GSO_SEGMENTS {
+                let (data, next_target) = &packets[index + count];
+                if next_target != target || data.is_empty() || data.len() > segment || total + data.len() > MAX_GSO_BYTES {
+                    break;
+                }
+                count += 1;
+                total += data.len();
+                if data.len() < segment {
+                    // A short segment can only end the message
+                    break;
+                }
+            }
+        }
+
+        messages.push(Message {
+            start: index,
+            count,
+            segment_size: (count > 1).then_some(segment as u16),
+        });
+        index += count;
+    }
+
+    messages
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::net::SocketAddr;
+    use std::os::fd::AsRawFd;
+    use std::ptr;
+
+    use bytes::Bytes;
+    use socket2::SockAddr;
+    use tokio::net::UdpSocket;
+
+    use super::{Message, Received};
+
+    pub const GSO_SUPPORTED: bool = true;
+
+    /// Control buffer large enough for one UDP_GRO / UDP_SEGMENT cmsg, suitably aligned
+    type ControlBuffer = [u64; 8];
+
+    pub fn enable_gro(socket: &UdpSocket) -> bool {
+        let on: libc::c_int = 1;
+        let rc = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                libc::UDP_GRO,
+                (&on as *const libc::c_int).cast(),
+                mem::size_of_val(&on) as libc::socklen_t,
+            )
+        };
+        rc == 0
+    }
+
+    pub fn recv_mmsg(socket: &UdpSocket, slots: &mut [Vec<u8>], gro: bool) -> io::Result<Vec<Received>> {
+        let count = slots.len();
+        let mut names: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; count];
+        let mut iovecs: Vec<libc::iovec> = slots
+            .iter_mut()
+            .map(|slot| libc::iovec { iov_base: slot.as_mut_ptr().cast(), iov_len: slot.len() })
+            .collect();
+        let mut controls: Vec<ControlBuffer> = vec![[0; 8]; count];
+
+        let mut headers: Vec<libc::mmsghdr> = names
+            .iter_mut()
+            .zip(iovecs.iter_mut())
+            .zip(controls.iter_mut())
+            .map(|((name, iovec), control)| {
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (name as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                if gro {
+                    header.msg_hdr.msg_control = control.as_mut_ptr().cast();
+                    header.msg_hdr.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+                }
+                header
+            })
+            .collect();
+
+        let received = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                count as libc::c_uint,
+                libc::MSG_DONTWAIT as _,
+                ptr::null_mut(),
+            )
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut out = Vec::with_capacity(received as usize);
+        for (slot, header) in headers.iter().take(received as usize).enumerate() {
+            let address = unsafe { SockAddr::new(names[slot], header.msg_hdr.msg_namelen) };
+            let Some(source) = address.as_socket() else { continue };
+            out.push(Received {
+                slot,
+                len: header.msg_len as usize,
+                source,
+                segment_size: if gro { gro_segment_size(&header.msg_hdr) } else { None },
+            });
+        }
+        Ok(out)
+    }
+
+    fn gro_segment_size(header: &libc::msghdr) -> Option<usize> {
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(header);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                    let size = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                    return usize::try_from(size).ok();
+                }
+                cmsg = libc::CMSG_NXTHDR(header, cmsg);
+            }
+        }
+        None
+    }
+
+    /// Send `messages` with one sendmmsg call, returning how many were sent
+    pub fn send_mmsg(socket: &UdpSocket, packets: &[(Bytes, SocketAddr)], messages: &[Message]) -> io::Result<usize> {
+        let addresses: Vec<SockAddr> = messages.iter().map(|m| SockAddr::from(packets[m.start].1)).collect();
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|(data, _)| libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() })
+            .collect();
+        let mut controls: Vec<ControlBuffer> = vec![[0; 8]; messages.len()];
+
+        let mut headers: Vec<libc::mmsghdr> = Vec::with_capacity(messages.len());
+        for (i, message) in messages.iter().enumerate() {
+            let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+            header.msg_hdr.msg_name = addresses[i].as_ptr() as *mut libc::c_void;
+            header.msg_hdr.msg_namelen = addresses[i].len();
+            header.msg_hdr.msg_iov = iovecs[message.start..].as_mut_ptr();
+            header.msg_hdr.msg_iovlen = message.count as _;
+            if let Some(segment) = message.segment_size {
+                header.msg_hdr.msg_control = controls[i].as_mut_ptr().cast();
+                header.msg_hdr.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as u32) } as _;
+                unsafe {
+                    let cmsg = libc::CMSG_FIRSTHDR(&header.msg_hdr);
+                    (*cmsg).cmsg_level = libc::SOL_UDP;
+                    (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                    (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+                    ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment);
+                }
+            }
+            headers.push(header);
+        }
+
+        let sent = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT as _,
+            )
+        };
+        if sent < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(sent as usize)
+        }
+    }
+
+    /// Errors that mean the kernel or device cannot segment for us
+    pub fn is_gso_error(error: &io::Error) -> bool {
+        matches!(
+            error.raw_os_error(),
+            Some(libc::EINVAL | libc::EIO | libc::ENOPROTOOPT | libc::EOPNOTSUPP)
+        )
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::net::SocketAddr;
+
+    use bytes::Bytes;
+    use tokio::net::UdpSocket;
+
+    use super::{Message, Received};
+
+    pub const GSO_SUPPORTED: bool = false;
+
+    pub fn enable_gro(_socket: &UdpSocket) -> bool {
+        false
+    }
+
+    pub fn recv_mmsg(socket: &UdpSocket, slots: &mut [Vec<u8>], _gro: bool) -> io::Result<Vec<Received>> {
+        let (len, source) = socket.try_recv_from(&mut slots[0])?;
+        Ok(vec![Received { slot: 0, len, source, segment_size: None }])
+    }
+
+    pub fn send_mmsg(socket: &UdpSocket, packets: &[(Bytes, SocketAddr)], messages: &[Message]) -> io::Result<usize> {
+        let (data, target) = &packets[messages[0].start];
+        socket.try_send_to(data, *target)?;
+        Ok(1)
+    }
+
+    pub fn is_gso_error(_error: &io::Error) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(len: usize, target: SocketAddr) -> (Bytes, SocketAddr) {
+        (Bytes::from(vec![0xAB; len]), target)
+    }
+
+    #[test]
+    fn test_plan_messages_groups_same_destination() {
+        let a: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        let b: SocketAddr = "192.0.2.2:4000".parse().unwrap();
+        let packets = vec![
+            packet(172, a),
+            packet(172, a),
+            packet(100, a),
+            packet(172, a),
+            packet(172, b),
+        ];
+
+        let planned = plan_messages(&packets, true);
+        assert_eq!(planned, vec![
+            Message { start: 0, count: 3, segment_size: Some(172) },
+            Message { start: 3, count: 1, segment_size: None },
+            Message { start: 4, count: 1, segment_size: None },
+        ]);
+
+        let plain = plan_messages(&packets, false);
+        assert_eq!(plain.len(), packets.len());
+        assert!(plain.iter().all(|m| m.count == 1 && m.segment_size.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_batched_loopback() {
+        let config = RtpBatchingConfig::default();
+        let receiver = BatchedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), config.clone());
+        let sender = BatchedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), config);
+        let target = receiver.local_addr().unwrap();
+
+        let packets: Vec<_> = (0..8u8).map(|i| (Bytes::from(vec![i; 160]), target)).collect();
+        assert_eq!(sender.send_batch(&packets).await.unwrap(), 8);
+
+        let mut buffers = receiver.recv_buffers();
+        let mut received = Vec::new();
+        while received.len() < packets.len() {
+            received.extend(receiver.recv_batch(&mut buffers).await.unwrap());
+        }
+
+        let firsts: Vec<u8> = received.iter().map(|d| d.data[0]).collect();
+        assert_eq!(firsts, (0..8u8).collect::<Vec<_>>());
+        assert!(received.iter().all(|d| d.data.len() == 160));
+
+        let sent = sender.stats();
+        assert_eq!(sent.datagrams_sent, 8);
+        assert!(sent.send_syscalls <= 8);
+        assert_eq!(receiver.stats().datagrams_received, 8);
+    }
+}
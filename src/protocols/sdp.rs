@@ -0,0 +1,527 @@
+//! SDP (Session Description Protocol) parsing and manipulation
+//!
+//! This module provides a lightweight, order-preserving SDP model used by
+//! the B2BUA to inspect and rewrite offers and answers. Lines the gateway
+//! does not understand are kept verbatim so re-serialized bodies stay as
+//! close to the original as possible.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{Error, Result};
+
+/// Generic `<type>=<value>` SDP line
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdpLine {
+    pub kind: char,
+    pub value: String,
+}
+
+impl SdpLine {
+    pub fn new<S: Into<String>>(kind: char, value: S) -> Self {
+        Self { kind, value: value.into() }
+    }
+
+    /// Attribute name for `a=` lines (`a=rtpmap:0 PCMU/8000` -> `rtpmap`)
+    pub fn attribute_name(&self) -> Option<&str> {
+        if self.kind != 'a' {
+            return None;
+        }
+        Some(self.value.split(':').next().unwrap_or(&self.value))
+    }
+
+    /// Attribute value for `a=` lines (`a=ptime:20` -> `20`)
+    pub fn attribute_value(&self) -> Option<&str> {
+        if self.kind != 'a' {
+            return None;
+        }
+        self.value.split_once(':').map(|(_, v)| v)
+    }
+}
+
+/// Parsed `a=rtpmap` entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtpMap {
+    pub payload_type: u8,
+    pub encoding: String,
+    pub clock_rate: u32,
+    pub channels: Option<u8>,
+}
+
+impl RtpMap {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (pt, rest) = value.split_once(' ')?;
+        let mut parts = rest.trim().split('/');
+        let encoding = parts.next()?.to_string();
+        let clock_rate = parts.next()?.parse().ok()?;
+        let channels = parts.next().and_then(|c| c.parse().ok());
+
+        Some(Self {
+            payload_type: pt.trim().parse().ok()?,
+            encoding,
+            clock_rate,
+            channels,
+        })
+    }
+
+    /// Well-known static payload type assignments (RFC 3551)
+    pub fn from_static(payload_type: u8) -> Option<Self> {
+        let (encoding, clock_rate) = match payload_type {
+            0 => ("PCMU", 8000),
+            3 => ("GSM", 8000),
+            4 => ("G723", 8000),
+            8 => ("PCMA", 8000),
+            9 => ("G722", 8000),
+            13 => ("CN", 8000),
+            18 => ("G729", 8000),
+            _ => return None,
+        };
+
+        Some(Self {
+            payload_type,
+            encoding: encoding.to_string(),
+            clock_rate,
+            channels: None,
+        })
+    }
+}
+
+/// Media description (`m=` line and everything up to the next `m=`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaDescription {
+    pub media_type: String,
+    pub port: u16,
+    pub port_count: Option<u16>,
+    pub protocol: String,
+    pub formats: Vec<String>,
+    pub lines: Vec<SdpLine>,
+}
+
+impl MediaDescription {
+    fn parse_m_line(value: &str) -> Result<Self> {
+        let mut parts = value.split_whitespace();
+        let media_type = parts.next()
+            .ok_or_else(|| Error::parse("SDP m= line missing media type"))?
+            .to_string();
+        let port_spec = parts.next()
+            .ok_or_else(|| Error::parse("SDP m= line missing port"))?;
+        let (port, port_count) = match port_spec.split_once('/') {
+            Some((p, n)) => (p, n.parse().ok()),
+            None => (port_spec, None),
+        };
+        let port = port.parse()
+            .map_err(|_| Error::parse(format!("Invalid SDP media port: {}", port_spec)))?;
+        let protocol = parts.next()
+            .ok_or_else(|| Error::parse("SDP m= line missing protocol"))?
+            .to_string();
+        let formats = parts.map(|f| f.to_string()).collect();
+
+        Ok(Self {
+            media_type,
+            port,
+            port_count,
+            protocol,
+            formats,
+            lines: Vec::new(),
+        })
+    }
+
+    /// A port of zero marks a rejected or disabled stream
+    pub fn is_rejected(&self) -> bool {
+        self.port == 0
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.media_type == "audio"
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.media_type == "video"
+    }
+
+    /// Decline the stream with port 0, keeping its m= line in place as
+    /// RFC 3264 requires
+    pub fn reject(&mut self) {
+        self.port = 0;
+        self.port_count = None;
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find(|l| l.attribute_name() == Some(name))
+            .map(|l| l.attribute_value().unwrap_or(""))
+    }
+
+    pub fn attributes<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.lines
+            .iter()
+            .filter(move |l| l.attribute_name() == Some(name))
+            .map(|l| l.attribute_value().unwrap_or(""))
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.lines.iter().any(|l| l.attribute_name() == Some(name))
+    }
+
+    /// Replace (or add) a single-valued attribute
+    pub fn set_attribute(&mut self, name: &str, value: Option<&str>) {
+        self.remove_attribute(name);
+        let line = match value {
+            Some(value) => format!("{}:{}", name, value),
+            None => name.to_string(),
+        };
+        self.lines.push(SdpLine::new('a', line));
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) {
+        self.lines.retain(|l| l.attribute_name() != Some(name));
+    }
+
+    /// Codec map for every payload type on the m= line
+    pub fn rtpmaps(&self) -> Vec<RtpMap> {
+        self.formats
+            .iter()
+            .filter_map(|f| f.parse::<u8>().ok())
+            .filter_map(|pt| self.rtpmap(pt))
+            .collect()
+    }
+
+    pub fn rtpmap(&self, payload_type: u8) -> Option<RtpMap> {
+        self.attributes("rtpmap")
+            .filter_map(RtpMap::parse)
+            .find(|m| m.payload_type == payload_type)
+            .or_else(|| RtpMap::from_static(payload_type))
+    }
+
+    /// Whether the stream carries RFC 3389 comfort noise at 8 kHz
+    pub fn has_comfort_noise(&self) -> bool {
+        self.rtpmaps().iter().any(|map| map.encoding.eq_ignore_ascii_case("CN") && map.clock_rate == 8000)
+    }
+
+    /// Keep only payload types accepted by `keep`, dropping their
+    /// rtpmap/fmtp lines as well
+    pub fn retain_payload_types<F: FnMut(&RtpMap) -> bool>(&mut self, mut keep: F) {
+        let mut removed: Vec<String> = Vec::new();
+        let mut kept: Vec<String> = Vec::new();
+
+        for format in &self.formats {
+            let keep_format = match format.parse::<u8>().ok().and_then(|pt| self.rtpmap(pt)) {
+                Some(map) => keep(&map),
+                None => true,
+            };
+            if keep_format {
+                kept.push(format.clone());
+            } else {
+                removed.push(format.clone());
+            }
+        }
+
+        self.formats = kept;
+        self.lines.retain(|l| {
+            match (l.attribute_name(), l.attribute_value()) {
+                (Some("rtpmap"), Some(v)) | (Some("fmtp"), Some(v)) | (Some("rtcp-fb"), Some(v)) => {
+                    let pt = v.split_whitespace().next().unwrap_or("");
+                    !removed.iter().any(|r| r == pt)
+                }
+                _ => true,
+            }
+        });
+    }
+
+    /// `b=AS:` bandwidth in kbps
+    pub fn bandwidth_kbps(&self) -> Option<u32> {
+        self.lines
+            .iter()
+            .filter(|l| l.kind == 'b')
+            .find_map(|l| l.value.strip_prefix("AS:"))
+            .and_then(|v| v.trim().parse().ok())
+    }
+
+    pub fn set_bandwidth_kbps(&mut self, kbps: u32) {
+        self.lines.retain(|l| !(l.kind == 'b' && l.value.starts_with("AS:")));
+        // b= lines follow c= and precede attributes
+        let pos = self.lines.iter().position(|l| l.kind == 'a').unwrap_or(self.lines.len());
+        self.lines.insert(pos, SdpLine::new('b', format!("AS:{}", kbps)));
+    }
+
+    pub fn ptime(&self) -> Option<u32> {
+        self.attribute("ptime").and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Stream direction attribute, defaulting to sendrecv
+    pub fn direction(&self) -> &str {
+        ["sendrecv", "sendonly", "recvonly", "inactive"]
+            .into_iter()
+            .find(|d| self.has_attribute(d))
+            .unwrap_or("sendrecv")
+    }
+
+    pub fn set_direction(&mut self, direction: &str) {
+        for d in ["sendrecv", "sendonly", "recvonly", "inactive"] {
+            self.remove_attribute(d);
+        }
+        self.lines.push(SdpLine::new('a', direction));
+    }
+
+    /// Media-level connection address, if the stream overrides the session's
+    pub fn connection_address(&self) -> Option<&str> {
+        connection_address(&self.lines)
+    }
+}
+
+fn connection_address(lines: &[SdpLine]) -> Option<&str> {
+    lines
+        .iter()
+        .find(|l| l.kind == 'c')
+        .and_then(|l| l.value.split_whitespace().nth(2))
+}
+
+fn connection_value(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(v4) => format!("IN IP4 {}", v4),
+        IpAddr::V6(v6) => format!("IN IP6 {}", v6),
+    }
+}
+
+/// Parsed session description
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionDescription {
+    pub lines: Vec<SdpLine>,
+    pub media: Vec<MediaDescription>,
+}
+
+impl SessionDescription {
+    pub fn parse(sdp: &str) -> Result<Self> {
+        let mut lines = Vec::new();
+        let mut media: Vec<MediaDescription> = Vec::new();
+
+        for raw in sdp.lines() {
+            let raw = raw.trim_end_matches('\r');
+            if raw.is_empty() {
+                continue;
+            }
+
+            let mut chars = raw.chars();
+            let kind = chars.next().unwrap_or(' ');
+            if chars.next() != Some('=') {
+                return Err(Error::parse(format!("Malformed SDP line: {}", raw)));
+            }
+            let value = &raw[2..];
+
+            if kind == 'm' {
+                media.push(MediaDescription::parse_m_line(value)?);
+            } else if let Some(current) = media.last_mut() {
+                current.lines.push(SdpLine::new(kind, value));
+            } else {
+                lines.push(SdpLine::new(kind, value));
+            }
+        }
+
+        if !lines.first().map(|l| l.kind == 'v').unwrap_or(false) {
+            return Err(Error::parse("SDP must start with v= line"));
+        }
+
+        Ok(Self { lines, media })
+    }
+
+    /// Session-level connection address
+    pub fn connection_address(&self) -> Option<&str> {
+        connection_address(&self.lines)
+    }
+
+    /// Point every `c=` line and the origin address at `address`
+    pub fn set_connection_address(&mut self, address: IpAddr) {
+        self.set_connection_address_except(address, &[]);
+    }
+
+    /// Point the session-level `c=` line, the origin address and the `c=`
+    /// lines of every stream not listed in `keep` at `address`
+    pub fn set_connection_address_except(&mut self, address: IpAddr, keep: &[usize]) {
+        let value = connection_value(address);
+
+        let streams = self
+            .media
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, _)| !keep.contains(index))
+            .flat_map(|(_, m)| m.lines.iter_mut());
+        for line in self.lines.iter_mut().chain(streams) {
+            if line.kind == 'c' {
+                line.value = value.clone();
+            }
+        }
+
+        if !self.lines.iter().any(|l| l.kind == 'c') {
+            // Session-level c= precedes t=
+            let pos = self.lines.iter().position(|l| l.kind == 't').unwrap_or(self.lines.len());
+            self.lines.insert(pos, SdpLine::new('c', value.clone()));
+        }
+
+        if let Some(origin) = self.lines.iter_mut().find(|l| l.kind == 'o') {
+            let addr = address.to_string();
+            let mut fields: Vec<&str> = origin.value.split_whitespace().collect();
+            if fields.len() == 6 {
+                fields[4] = if address.is_ipv4() { "IP4" } else { "IP6" };
+                fields[5] = &addr;
+                origin.value = fields.join(" ");
+            }
+        }
+    }
+
+    /// Remote RTP endpoint of the first active audio stream
+    pub fn audio_endpoint(&self) -> Option<SocketAddr> {
+        let stream = self.audio_streams().find(|m| !m.is_rejected())?;
+        let address = stream.connection_address().or_else(|| self.connection_address())?;
+        let ip: IpAddr = address.parse().ok()?;
+        Some(SocketAddr::new(ip, stream.port))
+    }
+
+    /// Index of the first active audio stream, the one the gateway relays
+    pub fn primary_audio(&self) -> Option<usize> {
+        self.media.iter().position(|m| m.is_audio() && !m.is_rejected())
+    }
+
+    /// Give stream `index` a `c=` line of its own carrying the address it
+    /// uses now, so rewriting the session address leaves it alone
+    pub fn pin_stream_connection(&mut self, index: usize) {
+        let session_address = self.lines.iter().find(|l| l.kind == 'c').map(|l| l.value.clone());
+        let Some(stream) = self.media.get_mut(index) else {
+            return;
+        };
+        if stream.lines.iter().any(|l| l.kind == 'c') {
+            return;
+        }
+        if let Some(value) = session_address {
+            // c= follows i= and precedes b= and attributes
+            let pos = stream.lines.iter().position(|l| l.kind != 'i').unwrap_or(stream.lines.len());
+            stream.lines.insert(pos, SdpLine::new('c', value));
+        }
+    }
+
+    pub fn audio_streams(&self) -> impl Iterator<Item = &MediaDescription> {
+        self.media.iter().filter(|m| m.is_audio())
+    }
+
+    pub fn audio_streams_mut(&mut self) -> impl Iterator<Item = &mut MediaDescription> {
+        self.media.iter_mut().filter(|m| m.is_audio())
+    }
+}
+
+impl fmt::Display for SessionDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            write!(f, "{}={}\r\n", line.kind, line.value)?;
+        }
+        for m in &self.media {
+            let port = match m.port_count {
+                Some(count) => format!("{}/{}", m.port, count),
+                None => m.port.to_string(),
+            };
+            write!(f, "m={} {} {}", m.media_type, port, m.protocol)?;
+            for format in &m.formats {
+                write!(f, " {}", format)?;
+            }
+            write!(f, "\r\n")?;
+            for line in &m.lines {
+                write!(f, "{}={}\r\n", line.kind, line.value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFER: &str = "v=0\r\n\
+        o=- 1 1 IN IP4 192.0.2.1\r\n\
+        s=-\r\n\
+        c=IN IP4 192.0.2.1\r\n\
+        t=0 0\r\n\
+        m=audio 10000 RTP/AVP 0 8 18 101\r\n\
+        a=rtpmap:18 G729/8000\r\n\
+        a=fmtp:18 annexb=no\r\n\
+        a=rtpmap:101 telephone-event/8000\r\n\
+        a=fmtp:101 0-16\r\n\
+        a=ptime:20\r\n";
+
+    #[test]
+    fn test_parse_and_roundtrip() {
+        let sdp = SessionDescription::parse(OFFER).unwrap();
+        assert_eq!(sdp.media.len(), 1);
+        assert_eq!(sdp.connection_address(), Some("192.0.2.1"));
+
+        let audio = &sdp.media[0];
+        assert_eq!(audio.port, 10000);
+        assert_eq!(audio.formats, vec!["0", "8", "18", "101"]);
+        assert_eq!(audio.rtpmap(0).unwrap().encoding, "PCMU");
+        assert_eq!(audio.ptime(), Some(20));
+        assert!(!audio.has_comfort_noise());
+        let with_cn = OFFER.replace("RTP/AVP 0 8 18 101", "RTP/AVP 0 13");
+        assert!(SessionDescription::parse(&with_cn).unwrap().media[0].has_comfort_noise());
+
+        let reparsed = SessionDescription::parse(&sdp.to_string()).unwrap();
+        assert_eq!(reparsed, sdp);
+    }
+
+    #[test]
+    fn test_retain_payload_types() {
+        let mut sdp = SessionDescription::parse(OFFER).unwrap();
+        sdp.media[0].retain_payload_types(|m| m.encoding != "G729");
+
+        let audio = &sdp.media[0];
+        assert_eq!(audio.formats, vec!["0", "8", "101"]);
+        assert!(!audio.attributes("fmtp").any(|v| v.starts_with("18 ")));
+        assert!(audio.attributes("fmtp").any(|v| v.starts_with("101 ")));
+    }
+
+    #[test]
+    fn test_bandwidth_and_direction() {
+        let mut sdp = SessionDescription::parse(OFFER).unwrap();
+        let audio = &mut sdp.media[0];
+        assert_eq!(audio.bandwidth_kbps(), None);
+        assert_eq!(audio.direction(), "sendrecv");
+
+        audio.set_bandwidth_kbps(80);
+        audio.set_direction("sendonly");
+        assert_eq!(audio.bandwidth_kbps(), Some(80));
+        assert_eq!(audio.direction(), "sendonly");
+    }
+
+    #[test]
+    fn test_rewrite_connection_address() {
+        let mut sdp = SessionDescription::parse(OFFER).unwrap();
+        assert_eq!(sdp.audio_endpoint(), Some("192.0.2.1:10000".parse().unwrap()));
+
+        sdp.set_connection_address("198.51.100.7".parse().unwrap());
+        sdp.media[0].port = 40000;
+        assert_eq!(sdp.audio_endpoint(), Some("198.51.100.7:40000".parse().unwrap()));
+        assert!(sdp.to_string().contains("o=- 1 1 IN IP4 198.51.100.7\r\n"));
+    }
+
+    #[test]
+    fn test_pinned_stream_keeps_its_address() {
+        let offer = format!("{}m=video 10002 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n", OFFER);
+        let mut sdp = SessionDescription::parse(&offer).unwrap();
+        assert_eq!(sdp.primary_audio(), Some(0));
+        assert!(sdp.media[1].is_video());
+
+        sdp.pin_stream_connection(1);
+        sdp.set_connection_address_except("198.51.100.7".parse().unwrap(), &[1]);
+        assert_eq!(sdp.connection_address(), Some("198.51.100.7"));
+        assert_eq!(sdp.media[1].connection_address(), Some("192.0.2.1"));
+        assert_eq!(sdp.media[1].lines[0].kind, 'c');
+
+        sdp.media[0].reject();
+        assert!(sdp.to_string().contains("m=audio 0 RTP/AVP"));
+        assert_eq!(sdp.primary_audio(), None);
+    }
+
+    #[test]
+    fn test_rejects_malformed_sdp() {
+        assert!(SessionDescription::parse("o=- 1 1 IN IP4 1.2.3.4\r\n").is_err());
+        assert!(SessionDescription::parse("v=0\r\nm=audio notaport RTP/AVP 0\r\n").is_err());
+    }
+}
@@ -3,17 +3,40 @@
 //! This module provides SIP (Session Initiation Protocol) functionality
 //! integrated with the external redfire-sip-stack library.
 
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
 use dashmap::DashMap;
-use tokio::sync::mpsc;
-use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use crate::config::SipConfig;
-use crate::Result;
+use crate::config::{CertificateUsage, SipConfig};
+use crate::protocols::mime::{self, BodyPart};
+use crate::protocols::sip_limits::{SizeLimitStats, SizeVerdict};
+use crate::protocols::sip_registrar::Registrar;
+use crate::protocols::sip_time::{SipTimeHeaders, SipTimestamp};
+use crate::protocols::sip_connection::{Connection, SipConnections};
+use crate::protocols::sip_tls::{SipTlsListener, StreamMessage};
+#[cfg(feature = "websocket")]
+use crate::protocols::sip_ws::{SipWsListener, WsMessage};
+use crate::protocols::sip_ws::WsConnections;
+use crate::protocols::sip_transport::{self, Transport, TransportSelector, TransportStats};
+use crate::services::certificates::CertificateManager;
+use crate::utils::{netbind, ClockStamp};
+use crate::{Error, Result};
+
+/// How long to wait for a TCP connection before falling back to UDP
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the registrar drops expired registrations
+const REGISTRAR_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
 
 // Import from external redfire-sip-stack library
 use redfire_sip_stack::{
@@ -28,7 +51,7 @@ use redfire_sip_stack::{
 // SipMessage is imported from redfire-sip-stack and provides full functionality
 
 /// SIP session states
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SessionState {
     Idle,
     Calling,
@@ -56,11 +79,19 @@ pub struct SipSession {
     pub remote_target: Option<SocketAddr>,
     pub sdp: Option<String>,
     pub remote_sdp: Option<String>,
+    /// Additional headers sent on the initial request
+    pub extra_headers: Vec<(String, String)>,
+    /// Encapsulated ISUP/QSIG carried next to the SDP of the initial INVITE
+    pub body_parts: Vec<BodyPart>,
+    /// TLS or TCP connection the dialog is carried on, when not over UDP
+    pub connection: Option<Connection>,
+    /// Server transaction of a received INVITE not finally answered yet
+    pub invite_transaction: Option<String>,
     pub created_at: Instant,
     pub last_activity: Instant,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SessionDirection {
     Inbound,
     Outbound,
@@ -87,6 +118,10 @@ impl SipSession {
             remote_target: None,
             sdp: None,
             remote_sdp: None,
+            extra_headers: Vec::new(),
+            body_parts: Vec::new(),
+            connection: None,
+            invite_transaction: None,
             created_at: now,
             last_activity: now,
         }
@@ -112,6 +147,10 @@ impl SipSession {
             remote_target: None,
             sdp: None,
             remote_sdp: None,
+            extra_headers: Vec::new(),
+            body_parts: Vec::new(),
+            connection: None,
+            invite_transaction: None,
             created_at: now,
             last_activity: now,
         }
@@ -135,27 +174,90 @@ pub enum SipEvent {
         from: String,
         to: String,
         sdp: Option<String>,
+        /// Headers from the initial INVITE, in received order
+        headers: Vec<(String, String)>,
+        /// Non-SDP parts of a multipart body, e.g. SIP-I ISUP or tunnelled QSIG
+        encapsulated: Vec<BodyPart>,
+        /// Transport address the INVITE arrived from, whatever its Via claims
+        source: SocketAddr,
     },
     CallRinging {
         session_id: String,
+        /// Headers of the 180, e.g. RSeq and CSeq when sent reliably
+        headers: Vec<(String, String)>,
+        /// When the 180 came off the wire
+        received: ClockStamp,
+    },
+    /// 183 Session Progress to an INVITE we sent, possibly with early media
+    SessionProgress {
+        session_id: String,
+        sdp: Option<String>,
+        headers: Vec<(String, String)>,
+        received: ClockStamp,
     },
     CallAnswered {
         session_id: String,
         sdp: Option<String>,
+        /// Headers of the 2xx, e.g. Session-Expires
+        headers: Vec<(String, String)>,
+        /// When the 2xx came off the wire, the answer time of the call
+        received: ClockStamp,
     },
     CallTerminated {
         session_id: String,
         reason: String,
     },
+    /// Final failure response (4xx-6xx) to an INVITE we sent
+    CallFailed {
+        session_id: String,
+        status_code: u16,
+        reason: String,
+    },
+    /// Mid-dialog re-INVITE or UPDATE from the peer, already answered
+    SessionRefreshed {
+        session_id: String,
+        /// New offer, e.g. putting the call on hold
+        sdp: Option<String>,
+        headers: Vec<(String, String)>,
+    },
+    /// Final response to a session refresh we sent
+    RefreshResponse {
+        session_id: String,
+        status_code: u16,
+        /// Answer to a re-INVITE that carried a new offer
+        sdp: Option<String>,
+        headers: Vec<(String, String)>,
+    },
+    /// PRACK from the peer for a reliable provisional we sent
+    PrackReceived {
+        session_id: String,
+        /// Server transaction to answer with `send_prack_response`
+        transaction_id: String,
+        rack: String,
+    },
+    /// REFER from the peer on an established dialog
+    ReferReceived {
+        session_id: String,
+        /// Server transaction to answer with `send_refer_response`
+        transaction_id: String,
+        refer_to: String,
+        headers: Vec<(String, String)>,
+    },
     DtmfReceived {
         session_id: String,
         digit: char,
         duration: u32,
     },
     RegistrationReceived {
+        /// Server transaction to answer with `send_register_response`
+        transaction_id: String,
         user: String,
         contact: String,
         expires: u32,
+        headers: Vec<(String, String)>,
+        /// Connection the REGISTER came in on, which calls to its contacts
+        /// have to use (RFC 5626 flow)
+        flow: Option<SocketAddr>,
     },
     Started {
         listen_address: String,
@@ -179,8 +281,26 @@ pub struct SipHandler {
     parser: SipParser,
     core_engine: Option<SipCoreEngine>,
     sessions: Arc<DashMap<String, SipSession>>,
+    /// Transactions and dialogs on TLS, TCP and WebSocket connections
+    connections: SipConnections,
+    transport: TransportSelector,
+    /// Date and Timestamp headers; none are added until set
+    time_headers: Option<SipTimeHeaders>,
     event_tx: mpsc::UnboundedSender<SipEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<SipEvent>>,
+    /// Listener and message tasks of the TLS port
+    tls_tasks: Vec<JoinHandle<()>>,
+    /// Certificates of the TLS port, also presented on the secure WebSocket port
+    tls_listener: Option<Arc<SipTlsListener>>,
+    /// Hands reloaded TLS certificates to the listener, when certificates are managed
+    certificate_manager: Option<Arc<CertificateManager>>,
+    /// Listener and message tasks of the WebSocket ports
+    websocket_tasks: Vec<JoinHandle<()>>,
+    websocket_connections: WsConnections,
+    /// Built-in registrar, when enabled
+    registrar: Option<Arc<Registrar>>,
+    /// Drops expired registrations
+    registrar_task: Option<JoinHandle<()>>,
     is_running: bool,
 }
 
@@ -199,21 +319,77 @@ impl SipHandler {
         let sip_core_config = SipCoreConfig::default();
         
         let core_engine = create_default_core().await
-            .map_err(|e| crate::Error::Sip(format!("Failed to create SIP core: {}", e)))?;
+            .map_err(|e| Error::invalid_config(format!("Failed to create SIP core: {}", e)))?;
         
         let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let sessions = Arc::new(DashMap::new());
+        let connections = SipConnections::new(&config, Arc::clone(&sessions), event_tx.clone());
+        let websocket_connections = connections.websockets();
+        let transport = TransportSelector::new(&config);
+        let registrar = if config.registrar.enabled {
+            Some(Arc::new(Registrar::from_config(&config)?))
+        } else {
+            None
+        };
 
         Ok(Self {
             config,
             parser,
             core_engine: Some(core_engine),
-            sessions: Arc::new(DashMap::new()),
+            sessions,
+            connections,
+            transport,
+            time_headers: None,
             event_tx,
             event_rx: Some(event_rx),
+            tls_tasks: Vec::new(),
+            tls_listener: None,
+            certificate_manager: None,
+            websocket_tasks: Vec::new(),
+            websocket_connections,
+            registrar,
+            registrar_task: None,
             is_running: false,
         })
     }
 
+    /// Add Date and Timestamp headers to requests and responses
+    pub fn set_time_headers(&mut self, time_headers: SipTimeHeaders) {
+        self.time_headers = Some(time_headers);
+    }
+
+    /// Take reloaded and renewed SIP TLS certificates from `certificates`
+    pub fn set_certificate_manager(&mut self, certificates: Arc<CertificateManager>) {
+        self.certificate_manager = Some(certificates);
+    }
+
+    /// Date and echoed Timestamp headers for a response to a request
+    /// received at `received`
+    pub fn response_time_headers(&self, request_timestamp: Option<&SipTimestamp>, received: Instant) -> Vec<(String, String)> {
+        self.time_headers
+            .as_ref()
+            .map(|time_headers| time_headers.response_headers(request_timestamp, received))
+            .unwrap_or_default()
+    }
+
+    /// Measure a message received from `source` against the size limits
+    /// before it is parsed
+    pub fn check_message_size(&self, data: &[u8], source: SocketAddr) -> SizeVerdict {
+        self.connections.check_message_size(data, source)
+    }
+
+    /// Oversized messages received per peer
+    pub fn size_limit_stats(&self) -> Vec<SizeLimitStats> {
+        self.connections.size_limit_stats()
+    }
+
+    /// Check the Vias of a request received from `source`, stamping
+    /// received and rport on the top one, and return where its responses are
+    /// sent. An error means the request gets 400 Bad Request.
+    pub fn accept_request(&self, headers: &mut [(String, String)], source: SocketAddr) -> Result<SocketAddr> {
+        self.connections.accept_request(headers, source)
+    }
+
     pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<SipEvent>> {
         self.event_rx.take()
     }
@@ -225,9 +401,30 @@ impl SipHandler {
         // Initialize the core engine if needed
         if self.core_engine.is_none() {
             let core = create_default_core().await
-                .map_err(|e| crate::Error::Sip(format!("Failed to start SIP core: {}", e)))?;
+                .map_err(|e| Error::network(format!("Failed to start SIP core: {}", e)))?;
             self.core_engine = Some(core);
         }
+
+        if self.config.tls.enabled && self.tls_tasks.is_empty() {
+            self.start_tls().await?;
+        }
+        if self.config.websocket.enabled && self.websocket_tasks.is_empty() {
+            self.start_websocket().await?;
+        }
+        if let (Some(registrar), None) = (&self.registrar, &self.registrar_task) {
+            info!("SIP registrar serving realm {}", registrar.realm());
+            let registrar = Arc::clone(registrar);
+            self.registrar_task = Some(tokio::spawn(async move {
+                let mut sweep = tokio::time::interval(REGISTRAR_SWEEP_INTERVAL);
+                loop {
+                    sweep.tick().await;
+                    let purged = registrar.purge_expired();
+                    if purged > 0 {
+                        debug!("Dropped {} expired registrations", purged);
+                    }
+                }
+            }));
+        }
         
         let _ = self.event_tx.send(SipEvent::Started {
             listen_address: format!("{}:{}", "0.0.0.0", self.config.listen_port),
@@ -236,26 +433,182 @@ impl SipHandler {
         Ok(())
     }
 
+    /// Listen for sips: trunks on the TLS port
+    async fn start_tls(&mut self) -> Result<()> {
+        let listener = self.tls_listener()?;
+        let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.config.tls.listen_port));
+        let socket = netbind::bind_tcp(addr, &self.config.network)?;
+        info!("SIP TLS listening on {}", socket.local_addr()?);
+
+        let (message_tx, mut message_rx) = mpsc::unbounded_channel::<StreamMessage>();
+        self.tls_tasks.push(listener.spawn(socket, self.config.size_limits.max_message_bytes, message_tx));
+        let connections = self.connections.clone();
+        self.tls_tasks.push(tokio::spawn(async move {
+            while let Some(message) = message_rx.recv().await {
+                debug!(
+                    "{}-byte SIP message over TLS from {} ({})",
+                    message.data.len(),
+                    message.peer,
+                    message.server_name.as_deref().unwrap_or("no SNI name")
+                );
+                let connection = Connection { transport: message.transport, peer: message.peer };
+                connections.receive_at(connection, &message.data, message.received);
+            }
+        }));
+        Ok(())
+    }
+
+    /// Certificates of the TLS configuration, loaded on first use and
+    /// replaced as the certificate manager reloads them
+    fn tls_listener(&mut self) -> Result<Arc<SipTlsListener>> {
+        if let Some(listener) = &self.tls_listener {
+            return Ok(Arc::clone(listener));
+        }
+        let listener = Arc::new(SipTlsListener::new(&self.config.tls, self.connections.streams())?);
+        if let Some(certificates) = &self.certificate_manager {
+            certificates.set_consumer(CertificateUsage::SipTls, listener.clone());
+        }
+        self.tls_listener = Some(Arc::clone(&listener));
+        Ok(listener)
+    }
+
+    /// Listen for browser and softphone clients on the WebSocket ports
+    #[cfg(feature = "websocket")]
+    async fn start_websocket(&mut self) -> Result<()> {
+        let config = self.config.websocket.clone();
+        let mut listeners = Vec::new();
+        if let Some(port) = config.listen_port {
+            listeners.push((port, SipWsListener::plain(&config, self.websocket_connections.clone())));
+        }
+        if let Some(port) = config.secure_port {
+            let tls = self.tls_listener()?;
+            listeners.push((port, SipWsListener::secure(&config, tls, self.websocket_connections.clone())));
+        }
+
+        let (message_tx, mut message_rx) = mpsc::unbounded_channel::<WsMessage>();
+        for (port, listener) in listeners {
+            let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+            let socket = netbind::bind_tcp(addr, &self.config.network)?;
+            info!("SIP {} listening on {}", listener.transport().via_name(), socket.local_addr()?);
+            let listener = Arc::new(listener);
+            self.websocket_tasks.push(listener.spawn(
+                socket,
+                self.config.size_limits.max_message_bytes,
+                message_tx.clone(),
+            ));
+        }
+        let connections = self.connections.clone();
+        self.websocket_tasks.push(tokio::spawn(async move {
+            while let Some(message) = message_rx.recv().await {
+                debug!(
+                    "{}-byte SIP message over {} from {}",
+                    message.data.len(),
+                    message.transport.via_name(),
+                    message.peer
+                );
+                let connection = Connection { transport: message.transport, peer: message.peer };
+                connections.receive_at(connection, &message.data, message.received);
+            }
+        }));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    async fn start_websocket(&mut self) -> Result<()> {
+        warn!("SIP WebSocket is configured but this build lacks the `websocket` feature");
+        Ok(())
+    }
+
+    /// Send a SIP message to a WebSocket client on the connection it opened
+    /// from `peer`
+    pub fn send_websocket(&self, peer: SocketAddr, data: Vec<u8>) -> Result<()> {
+        self.websocket_connections.send(peer, data)
+    }
+
+    /// Built-in registrar answering REGISTER requests, when enabled
+    pub fn registrar(&self) -> Option<Arc<Registrar>> {
+        self.registrar.clone()
+    }
+
+    /// Open WebSocket client connections
+    pub fn websocket_connections(&self) -> usize {
+        self.websocket_connections.len()
+    }
+
     pub async fn send_invite(
         &self,
         to_uri: &str,
         from_uri: &str,
         sdp: Option<&str>,
         target: SocketAddr,
+    ) -> Result<String> {
+        self.send_invite_with_headers(to_uri, from_uri, sdp, target, &[]).await
+    }
+
+    pub async fn send_invite_with_headers(
+        &self,
+        to_uri: &str,
+        from_uri: &str,
+        sdp: Option<&str>,
+        target: SocketAddr,
+        headers: &[(String, String)],
+    ) -> Result<String> {
+        self.send_invite_with_body(to_uri, from_uri, sdp, &[], target, headers).await
+    }
+
+    /// INVITE whose body carries `encapsulated` signalling next to the SDP,
+    /// as multipart/mixed when there is any
+    pub async fn send_invite_with_body(
+        &self,
+        to_uri: &str,
+        from_uri: &str,
+        sdp: Option<&str>,
+        encapsulated: &[BodyPart],
+        target: SocketAddr,
+        headers: &[(String, String)],
     ) -> Result<String> {
         info!("Sending SIP INVITE from {} to {} via {}", from_uri, to_uri, target);
+
+        let mut headers = headers.to_vec();
+        if let Some(ref time_headers) = self.time_headers {
+            headers.extend(time_headers.request_headers());
+        }
+        let body = mime::build_body(sdp, encapsulated);
+        let mut size = sip_transport::estimate_request_size(to_uri, None, &headers);
+        if let Some((content_type, body)) = &body {
+            size += content_type.len() + body.len();
+        }
+        let transport = self.select_transport(to_uri, size, target).await?;
         
         let call_id = utils::generate_call_id();
-        let session = SipSession::new_outbound(
+        let mut session = SipSession::new_outbound(
             call_id.clone(),
             from_uri.to_string(),
             to_uri.to_string(),
         );
+        session.extra_headers = headers;
+        session.sdp = sdp.map(str::to_string);
+        session.body_parts = encapsulated.to_vec();
         let session_id = session.id.clone();
-        
-        self.sessions.insert(call_id.clone(), session);
 
-        // TODO: Use core_engine to send actual SIP INVITE
+        // A trunk that connected to us over TLS, or one we switched to TCP
+        // for this request, is called on that connection
+        match self.connections.connection(target) {
+            Some(connection) => {
+                let body = body.as_ref().map(|(content_type, body)| (content_type.as_str(), body.as_slice()));
+                let headers = session.extra_headers.clone();
+                self.connections.send_invite(session, connection, &headers, body)?;
+                if connection.transport == Transport::Tcp && self.switches_to_tcp(to_uri, size) {
+                    self.transport.record(sip_transport::uri_host(to_uri), Transport::Tcp, Transport::Tcp);
+                }
+                debug!("INVITE to {} of about {} bytes sent over {:?}", to_uri, size, connection.transport);
+            }
+            None => {
+                self.sessions.insert(call_id.clone(), session);
+                // TODO: Use core_engine to send actual SIP INVITE
+                debug!("INVITE to {} of about {} bytes sent over {:?}", to_uri, size, transport);
+            }
+        }
         info!("Created SIP session: {} with call-id: {}", session_id, call_id);
         
         let _ = self.event_tx.send(SipEvent::InviteSent {
@@ -266,18 +619,199 @@ impl SipHandler {
         Ok(session_id)
     }
 
+    /// Whether a request of `size` bytes to `uri` moves from UDP to TCP
+    fn switches_to_tcp(&self, uri: &str, size: usize) -> bool {
+        self.transport.select(uri, size) == Transport::Tcp && self.transport.configured() == Transport::Udp
+    }
+
+    /// Pick the transport for one request, switching oversized UDP requests
+    /// to TCP and falling back to UDP if the connection cannot be set up. A
+    /// switch is counted once the request has gone out on the connection.
+    async fn select_transport(&self, uri: &str, size: usize, target: SocketAddr) -> Result<Transport> {
+        let trunk = sip_transport::uri_host(uri);
+        let selected = self.transport.select(uri, size);
+        if !self.switches_to_tcp(uri, size) {
+            self.transport.record(trunk, selected, selected);
+            return Ok(selected);
+        }
+        if self.connections.connection(target).is_some_and(|connection| connection.transport == Transport::Tcp) {
+            return Ok(Transport::Tcp);
+        }
+
+        let used = match timeout(TCP_CONNECT_TIMEOUT, TcpStream::connect(target)).await {
+            Ok(Ok(stream)) => {
+                self.connections.adopt_tcp(stream, target, self.config.size_limits.max_message_bytes);
+                return Ok(Transport::Tcp);
+            }
+            Ok(Err(e)) if self.transport.tcp_fallback() => {
+                warn!("TCP to {} failed for {}-byte request ({}), falling back to UDP", target, size, e);
+                Transport::Udp
+            }
+            Err(_) if self.transport.tcp_fallback() => {
+                warn!("TCP to {} timed out for {}-byte request, falling back to UDP", target, size);
+                Transport::Udp
+            }
+            Ok(Err(e)) => return Err(Error::network(format!("TCP connection to {} failed: {}", target, e))),
+            Err(_) => return Err(Error::network(format!("TCP connection to {} timed out", target))),
+        };
+        self.transport.record(trunk, selected, used);
+        Ok(used)
+    }
+
+    /// UDP-to-TCP switchovers and fallbacks per trunk
+    pub fn transport_stats(&self) -> Vec<TransportStats> {
+        self.transport.stats()
+    }
+
     pub async fn send_response(
         &self,
-        _session_id: &str,
+        session_id: &str,
+        status_code: u16,
+        reason_phrase: &str,
+        sdp: Option<&str>,
+    ) -> Result<()> {
+        if self.connections.respond_to_invite(session_id, status_code, reason_phrase, sdp, &[])? {
+            return Ok(());
+        }
+        warn!("SIP response requested but handler is in stub mode");
+        info!("Stub SIP response: {} {}", status_code, reason_phrase);
+        Ok(())
+    }
+
+    /// Final response carrying extra headers, e.g. Accept-Resource-Priority on
+    /// a 417 or Session-Expires on a 200
+    pub async fn send_response_with_headers(
+        &self,
+        session_id: &str,
         status_code: u16,
         reason_phrase: &str,
-        _sdp: Option<&str>,
+        sdp: Option<&str>,
+        headers: &[(String, String)],
     ) -> Result<()> {
+        if self.connections.respond_to_invite(session_id, status_code, reason_phrase, sdp, headers)? {
+            return Ok(());
+        }
         warn!("SIP response requested but handler is in stub mode");
         info!("Stub SIP response: {} {}", status_code, reason_phrase);
         Ok(())
     }
 
+    /// Session refresh on an established dialog, as re-INVITE with the
+    /// current offer or as UPDATE
+    pub async fn send_refresh(&self, session_id: &str, method: &str, headers: &[(String, String)]) -> Result<()> {
+        let sdp = match method {
+            "INVITE" => self.get_session(session_id).and_then(|session| session.sdp),
+            _ => None,
+        };
+        let body = sdp.as_deref().map(|sdp| (mime::CONTENT_TYPE_SDP, sdp.as_bytes()));
+        if self.connections.send_in_dialog(session_id, method, headers, body)? {
+            return Ok(());
+        }
+        warn!("SIP {} refresh requested but handler is in stub mode", method);
+        info!("Stub SIP {} refresh for session {}", method, session_id);
+        Ok(())
+    }
+
+    /// re-INVITE on an established dialog with a new offer, e.g. to hold
+    pub async fn send_reinvite(&self, session_id: &str, sdp: &str) -> Result<()> {
+        if let Some(mut session) = self.sessions.iter_mut().find(|session| session.id == session_id) {
+            session.sdp = Some(sdp.to_string());
+        }
+        let body = Some((mime::CONTENT_TYPE_SDP, sdp.as_bytes()));
+        if self.connections.send_in_dialog(session_id, "INVITE", &[], body)? {
+            return Ok(());
+        }
+        warn!("SIP re-INVITE requested but handler is in stub mode");
+        info!("Stub SIP re-INVITE for session {}", session_id);
+        Ok(())
+    }
+
+    /// BYE on an established dialog, with headers such as Reason
+    pub async fn send_bye(&self, session_id: &str, headers: &[(String, String)]) -> Result<()> {
+        if self.connections.send_in_dialog(session_id, "BYE", headers, None)? {
+            return Ok(());
+        }
+        warn!("SIP BYE requested but handler is in stub mode");
+        info!("Stub SIP BYE for session {}", session_id);
+        Ok(())
+    }
+
+    /// PRACK acknowledging a reliable provisional response to an INVITE we sent
+    pub async fn send_prack(&self, session_id: &str, rack: &str) -> Result<()> {
+        let headers = [("RAck".to_string(), rack.to_string())];
+        if self.connections.send_in_dialog(session_id, "PRACK", &headers, None)? {
+            return Ok(());
+        }
+        warn!("SIP PRACK requested but handler is in stub mode");
+        info!("Stub SIP PRACK for session {} (RAck: {})", session_id, rack);
+        Ok(())
+    }
+
+    pub async fn send_prack_response(&self, transaction_id: &str, status_code: u16, reason_phrase: &str) -> Result<()> {
+        if self.connections.has_transaction(transaction_id) {
+            return self.connections.respond(transaction_id, status_code, reason_phrase, &[], None);
+        }
+        warn!("SIP PRACK response requested but handler is in stub mode");
+        info!("Stub SIP PRACK response: {} {}", status_code, reason_phrase);
+        Ok(())
+    }
+
+    pub async fn send_refer_response(&self, transaction_id: &str, status_code: u16, reason_phrase: &str) -> Result<()> {
+        if self.connections.has_transaction(transaction_id) {
+            return self.connections.respond(transaction_id, status_code, reason_phrase, &[], None);
+        }
+        warn!("SIP REFER response requested but handler is in stub mode");
+        info!("Stub SIP REFER response: {} {}", status_code, reason_phrase);
+        Ok(())
+    }
+
+    /// NOTIFY within the subscription of `event`, e.g. sipfrag progress of a REFER
+    pub async fn send_notify(
+        &self,
+        session_id: &str,
+        event: &str,
+        subscription_state: &str,
+        content_type: &str,
+        body: &str,
+    ) -> Result<()> {
+        let headers = [
+            ("Event".to_string(), event.to_string()),
+            ("Subscription-State".to_string(), subscription_state.to_string()),
+        ];
+        if self.connections.send_in_dialog(session_id, "NOTIFY", &headers, Some((content_type, body.as_bytes())))? {
+            return Ok(());
+        }
+        warn!("SIP NOTIFY requested but handler is in stub mode");
+        info!("Stub SIP NOTIFY ({}, {}) for session {}", event, subscription_state, session_id);
+        Ok(())
+    }
+
+    /// CANCEL an INVITE we sent that has not been answered, e.g. a losing
+    /// ring group branch
+    pub async fn send_cancel(&self, session_id: &str) -> Result<()> {
+        if self.connections.cancel(session_id)? {
+            return Ok(());
+        }
+        warn!("SIP CANCEL requested but handler is in stub mode");
+        info!("Stub SIP CANCEL for session {}", session_id);
+        Ok(())
+    }
+
+    pub async fn send_register_response(
+        &self,
+        transaction_id: &str,
+        status_code: u16,
+        reason_phrase: &str,
+        headers: &[(String, String)],
+    ) -> Result<()> {
+        if self.connections.has_transaction(transaction_id) {
+            return self.connections.respond(transaction_id, status_code, reason_phrase, headers, None);
+        }
+        warn!("SIP REGISTER response requested but handler is in stub mode");
+        info!("Stub SIP REGISTER response: {} {}", status_code, reason_phrase);
+        Ok(())
+    }
+
     pub fn get_session(&self, session_id: &str) -> Option<SipSession> {
         for session in self.sessions.iter() {
             if session.id == session_id {
@@ -287,6 +821,15 @@ impl SipHandler {
         None
     }
 
+    /// Re-create a dialog carried over from a previous process, so in-dialog
+    /// requests keep matching it
+    pub fn restore_session(&self, mut session: SipSession) {
+        session.created_at = Instant::now();
+        session.last_activity = Instant::now();
+        info!("Restored SIP dialog {} (call-id {})", session.id, session.call_id);
+        self.sessions.insert(session.call_id.clone(), session);
+    }
+
     pub fn get_all_sessions(&self) -> Vec<SipSession> {
         self.sessions.iter().map(|entry| entry.value().clone()).collect()
     }
@@ -298,11 +841,29 @@ impl SipHandler {
     pub async fn stop(&mut self) -> Result<()> {
         info!("Stopping SIP handler stub");
         self.is_running = false;
+        for task in self.tls_tasks.drain(..).chain(self.websocket_tasks.drain(..)).chain(self.registrar_task.take()) {
+            task.abort();
+        }
+        self.tls_listener = None;
         self.sessions.clear();
         Ok(())
     }
 }
 
+/// Hangs up dialogs answered by services that play their own media
+#[async_trait]
+pub trait DialogHangup: Send + Sync {
+    /// Send BYE in the dialog of `session_id`
+    async fn hang_up(&self, session_id: &str) -> Result<()>;
+}
+
+#[async_trait]
+impl DialogHangup for RwLock<SipHandler> {
+    async fn hang_up(&self, session_id: &str) -> Result<()> {
+        self.read().await.send_bye(session_id, &[]).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -329,6 +890,14 @@ mod tests {
             max_sessions: 100,
             session_timeout: 300,
             register_interval: 3600,
+            udp_size_threshold: 1300,
+            tcp_fallback: true,
+            force_rport: Vec::new(),
+            size_limits: Default::default(),
+            tls: Default::default(),
+            websocket: Default::default(),
+            registrar: Default::default(),
+            network: Default::default(),
         };
 
         let handler = SipHandler::new(config).await;
@@ -344,6 +913,14 @@ mod tests {
             max_sessions: 100,
             session_timeout: 300,
             register_interval: 3600,
+            udp_size_threshold: 1300,
+            tcp_fallback: true,
+            force_rport: Vec::new(),
+            size_limits: Default::default(),
+            tls: Default::default(),
+            websocket: Default::default(),
+            registrar: Default::default(),
+            network: Default::default(),
         };
 
         let mut handler = SipHandler::new(config).await.unwrap();
@@ -360,4 +937,40 @@ mod tests {
         
         handler.stop().await.unwrap();
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_oversized_invite_sent_over_tcp() {
+        use tokio::io::AsyncReadExt;
+
+        let config = SipConfig {
+            listen_port: 0,
+            domain: "test.local".to_string(),
+            transport: crate::config::SipTransport::Udp,
+            max_sessions: 100,
+            session_timeout: 300,
+            register_interval: 3600,
+            udp_size_threshold: 600,
+            tcp_fallback: true,
+            force_rport: Vec::new(),
+            size_limits: Default::default(),
+            tls: Default::default(),
+            websocket: Default::default(),
+            registrar: Default::default(),
+            network: Default::default(),
+        };
+        let handler = SipHandler::new(config).await.unwrap();
+        let trunk = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = trunk.local_addr().unwrap();
+
+        let sdp = format!("v=0\r\n{}", "a=fmtp:101 0-15\r\n".repeat(40));
+        handler.send_invite("sip:5551000@trunk.example.com", "sip:gw@test.local", Some(&sdp), target).await.unwrap();
+        let (mut stream, _) = trunk.accept().await.unwrap();
+        let mut data = vec![0; 4096];
+        let read = stream.read(&mut data).await.unwrap();
+        let invite = String::from_utf8_lossy(&data[..read]);
+        assert!(invite.starts_with("INVITE sip:5551000@trunk.example.com SIP/2.0\r\nVia: SIP/2.0/TCP test.local:0;"));
+
+        let stats = handler.transport_stats();
+        assert_eq!((stats[0].switched_to_tcp, stats[0].tcp_fallbacks), (1, 0));
+    }
+}
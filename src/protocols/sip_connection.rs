@@ -0,0 +1,910 @@
+//! SIP transactions and dialogs on connections
+//!
+//! Requests received on TLS, TCP and WebSocket connections become the same
+//! `SipEvent`s the rest of the gateway reacts to, with a server transaction
+//! kept until the gateway answers them, and responses to requests the
+//! gateway sent on a connection are matched to their dialog by Call-ID.
+//! Responses and in-dialog requests go back on the connection the dialog
+//! uses, since a peer that connected to us may not accept connections of its
+//! own; a WebSocket client cannot accept any. REGISTERs carry the connection
+//! they came in on as their flow (RFC 5626), so calls to the registered
+//! contact are sent on it. Dialogs set up over UDP are left to the SIP core.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::{debug, trace, warn};
+use uuid::Uuid;
+
+use crate::config::SipConfig;
+use crate::protocols::mime;
+use crate::protocols::sip_limits::{SizeGuard, SizeLimitStats, SizeVerdict, REASON_TOO_LARGE, STATUS_TOO_LARGE};
+use crate::protocols::sip::{SessionState, SipEvent, SipSession};
+use crate::protocols::sip_message::{self, SipText, StartLine};
+use crate::protocols::sip_tls::{StreamConnections, StreamMessage};
+use crate::protocols::sip_transport::Transport;
+use crate::protocols::sip_via::ViaProcessor;
+use crate::protocols::sip_ws::WsConnections;
+use crate::utils::ClockStamp;
+use crate::{Error, Result};
+
+/// Methods answered on connections, for Allow headers
+const ALLOW: &str = "INVITE, ACK, CANCEL, BYE, OPTIONS, REGISTER, PRACK, UPDATE, REFER, NOTIFY, INFO";
+
+/// Connection a dialog or transaction is carried on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connection {
+    pub transport: Transport,
+    pub peer: SocketAddr,
+}
+
+/// Request waiting for the gateway's answer
+#[derive(Debug, Clone)]
+struct ServerTransaction {
+    connection: Connection,
+    request: SipText,
+    /// To tag of our responses
+    to_tag: String,
+}
+
+/// `value` as a name-addr, with `tag` added unless it carries one
+fn with_tag(value: &str, tag: Option<&str>) -> String {
+    let name_addr = if value.contains('<') { value.to_string() } else { format!("<{}>", value) };
+    match tag {
+        Some(tag) if sip_message::tag(value).is_none() => format!("{};tag={}", name_addr, tag),
+        _ => name_addr,
+    }
+}
+
+/// SDP of a received body, if any
+fn body_sdp(message: &SipText) -> Option<String> {
+    mime::split_body(message.content_type(), &message.body).ok().and_then(|(sdp, _)| sdp)
+}
+
+fn sdp_body(sdp: Option<&str>) -> Option<(&str, &[u8])> {
+    sdp.map(|sdp| (mime::CONTENT_TYPE_SDP, sdp.as_bytes()))
+}
+
+/// Handles SIP messages received on connections and sends on them
+#[derive(Clone)]
+pub struct SipConnections {
+    /// Dialogs by Call-ID, shared with the SIP handler
+    sessions: Arc<DashMap<String, SipSession>>,
+    transactions: Arc<DashMap<String, ServerTransaction>>,
+    /// INVITEs sent and not finally answered, by Call-ID, for CANCEL
+    invites: Arc<DashMap<String, SipText>>,
+    streams: StreamConnections,
+    websockets: WsConnections,
+    via_processor: ViaProcessor,
+    size_guard: Arc<SizeGuard>,
+    event_tx: mpsc::UnboundedSender<SipEvent>,
+    domain: String,
+    /// Ports we listen on per transport, for Via and Contact
+    ports: Vec<(Transport, u16)>,
+}
+
+impl SipConnections {
+    pub fn new(
+        config: &SipConfig,
+        sessions: Arc<DashMap<String, SipSession>>,
+        event_tx: mpsc::UnboundedSender<SipEvent>,
+    ) -> Self {
+        let mut ports = vec![
+            (Transport::Udp, config.listen_port),
+            (Transport::Tcp, config.listen_port),
+            (Transport::Tls, config.tls.listen_port),
+        ];
+        ports.extend(config.websocket.listen_port.map(|port| (Transport::Ws, port)));
+        ports.extend(config.websocket.secure_port.map(|port| (Transport::Wss, port)));
+        Self {
+            sessions,
+            transactions: Arc::new(DashMap::new()),
+            invites: Arc::new(DashMap::new()),
+            streams: StreamConnections::new(),
+            websockets: WsConnections::new(),
+            via_processor: ViaProcessor::new(config),
+            size_guard: Arc::new(SizeGuard::new(config.size_limits.clone())),
+            event_tx,
+            domain: config.domain.clone(),
+            ports,
+        }
+    }
+
+    /// TLS and TCP connections, for the listeners to register theirs in
+    pub fn streams(&self) -> StreamConnections {
+        self.streams.clone()
+    }
+
+    /// WebSocket connections, for the listeners to register theirs in
+    pub fn websockets(&self) -> WsConnections {
+        self.websockets.clone()
+    }
+
+    /// Carry SIP on a TCP connection we opened to `peer`, until either end
+    /// closes it
+    pub fn adopt_tcp(&self, stream: TcpStream, peer: SocketAddr, max_message_bytes: usize) -> Connection {
+        let connection = Connection { transport: Transport::Tcp, peer };
+        let (message_tx, mut message_rx) = mpsc::unbounded_channel::<StreamMessage>();
+        let serve = self.streams.serve(stream, peer, Transport::Tcp, None, max_message_bytes, message_tx);
+        tokio::spawn(async move {
+            if let Err(e) = serve.await {
+                debug!("SIP TCP connection to {} closed: {}", peer, e);
+            }
+        });
+        let connections = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = message_rx.recv().await {
+                connections.receive_at(connection, &message.data, message.received);
+            }
+        });
+        connection
+    }
+
+    /// Open connection with `peer`, if any
+    pub fn connection(&self, peer: SocketAddr) -> Option<Connection> {
+        self.streams
+            .transport(peer)
+            .or_else(|| self.websockets.transport(peer))
+            .map(|transport| Connection { transport, peer })
+    }
+
+    fn port(&self, transport: Transport) -> u16 {
+        self.ports.iter().find(|(t, _)| *t == transport).map_or(5060, |(_, port)| *port)
+    }
+
+    fn via(&self, transport: Transport) -> String {
+        format!(
+            "SIP/2.0/{} {}:{};branch={};rport",
+            transport.via_name(),
+            self.domain,
+            self.port(transport),
+            sip_message::new_branch()
+        )
+    }
+
+    fn contact(&self, transport: Transport) -> String {
+        format!(
+            "<sip:{}:{};transport={}>",
+            self.domain,
+            self.port(transport),
+            transport.via_name().to_ascii_lowercase()
+        )
+    }
+
+    fn send(&self, connection: Connection, message: &SipText) -> Result<()> {
+        trace!("Sending {} to {} over {}", message.start, connection.peer, connection.transport.via_name());
+        if connection.transport.is_websocket() {
+            self.websockets.send(connection.peer, message.to_bytes())
+        } else {
+            self.streams.send(connection.peer, message.to_bytes())
+        }
+    }
+
+    /// Measure a message received from `source` against the size limits
+    /// before it is parsed
+    pub fn check_message_size(&self, data: &[u8], source: SocketAddr) -> SizeVerdict {
+        self.size_guard.check(data, source)
+    }
+
+    /// Oversized messages received per peer
+    pub fn size_limit_stats(&self) -> Vec<SizeLimitStats> {
+        self.size_guard.stats()
+    }
+
+    /// Handle one message received on `connection`
+    pub fn receive(&self, connection: Connection, data: &[u8]) {
+        self.receive_at(connection, data, ClockStamp::now());
+    }
+
+    /// Handle one message read off `connection` at `received`, the time
+    /// answer supervision records for the responses it carries
+    pub fn receive_at(&self, connection: Connection, data: &[u8], received: ClockStamp) {
+        match self.check_message_size(data, connection.peer) {
+            SizeVerdict::Accept => {}
+            SizeVerdict::Reject(_) => {
+                // Only parsed far enough to address the 413; an ACK gets none
+                let request = SipText::parse(data).ok().filter(|request| request.method() != Some("ACK"));
+                if let Some(request) = request {
+                    if let Err(e) = self.reply(connection, &request, STATUS_TOO_LARGE, REASON_TOO_LARGE) {
+                        warn!("Failed to reject oversized SIP request from {}: {}", connection.peer, e);
+                    }
+                }
+                return;
+            }
+            SizeVerdict::Drop(_) => return,
+        }
+        let message = match SipText::parse(data) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Unparseable SIP message from {}: {}", connection.peer, e);
+                return;
+            }
+        };
+        let result = if message.method().is_some() {
+            self.receive_request(connection, message)
+        } else {
+            self.receive_response(message, received);
+            Ok(())
+        };
+        if let Err(e) = result {
+            warn!("Failed to answer SIP request from {}: {}", connection.peer, e);
+        }
+    }
+
+    /// Answer `request` straight away, outside any transaction
+    fn reply(&self, connection: Connection, request: &SipText, status_code: u16, reason: &str) -> Result<()> {
+        let mut response = request.reply(status_code, reason, Some(&format!("{:x}", rand::random::<u64>())));
+        if status_code == 405 || request.method() == Some("OPTIONS") {
+            response = response.with_header("Allow", ALLOW);
+        }
+        self.send(connection, &response)
+    }
+
+    fn open_transaction(&self, connection: Connection, request: SipText, to_tag: String) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.transactions.insert(id.clone(), ServerTransaction { connection, request, to_tag });
+        id
+    }
+
+    /// Check the Vias of a request received from `source`, stamping
+    /// received and rport on the top one, and return where its responses are
+    /// sent. An error means the request gets 400 Bad Request.
+    pub fn accept_request(&self, headers: &mut [(String, String)], source: SocketAddr) -> Result<SocketAddr> {
+        self.via_processor.receive_request(headers, source)
+    }
+
+    fn receive_request(&self, received_on: Connection, mut request: SipText) -> Result<()> {
+        // Responses go where the stamped top Via says, which on a connection
+        // is back down it
+        let connection = match self.accept_request(&mut request.headers, received_on.peer) {
+            Ok(destination) => self.connection(destination).unwrap_or(received_on),
+            Err(e) => return self.reply(received_on, &request, 400, &format!("Bad Request ({})", e)),
+        };
+        let method = request.method().unwrap_or_default().to_ascii_uppercase();
+        let Some(call_id) = request.call_id().map(str::to_string) else {
+            return self.reply(connection, &request, 400, "Bad Request (no Call-ID)");
+        };
+
+        match method.as_str() {
+            "REGISTER" => return self.receive_register(connection, request),
+            "OPTIONS" => return self.reply(connection, &request, 200, "OK"),
+            "INVITE" if !self.sessions.contains_key(&call_id) => {
+                return self.receive_invite(connection, received_on.peer, request, call_id);
+            }
+            _ => {}
+        }
+
+        let Some(mut session) = self.sessions.get_mut(&call_id) else {
+            // A stray ACK gets no response (RFC 3261 §17.2.3)
+            if method == "ACK" {
+                return Ok(());
+            }
+            return self.reply(connection, &request, 481, "Call/Transaction Does Not Exist");
+        };
+        session.update_activity();
+        if let Some((cseq, _)) = request.cseq() {
+            session.remote_cseq = session.remote_cseq.max(cseq);
+        }
+        let session_id = session.id.clone();
+        let local_tag = session.local_tag.clone();
+
+        match method.as_str() {
+            "ACK" => Ok(()),
+            "BYE" => {
+                session.state = SessionState::Terminated;
+                drop(session);
+                self.reply_in_dialog(connection, &request, &local_tag, 200, "OK")?;
+                let _ = self.event_tx.send(SipEvent::CallTerminated { session_id, reason: "BYE".to_string() });
+                Ok(())
+            }
+            "CANCEL" => {
+                let pending = session.invite_transaction.take();
+                session.state = SessionState::Terminated;
+                drop(session);
+                self.reply_in_dialog(connection, &request, &local_tag, 200, "OK")?;
+                if let Some(invite) = pending {
+                    self.respond(&invite, 487, "Request Terminated", &[], None)?;
+                }
+                let _ = self.event_tx.send(SipEvent::CallTerminated { session_id, reason: "CANCEL".to_string() });
+                Ok(())
+            }
+            "INVITE" | "UPDATE" => {
+                // Answered with our current offer, then passed on
+                let sdp = body_sdp(&request);
+                if sdp.is_some() {
+                    session.remote_sdp = sdp.clone();
+                }
+                let answer = session.sdp.clone();
+                drop(session);
+                let headers = request.headers.clone();
+                let transaction = self.open_transaction(connection, request, local_tag);
+                self.respond(&transaction, 200, "OK", &[], sdp_body(answer.as_deref()))?;
+                let _ = self.event_tx.send(SipEvent::SessionRefreshed { session_id, sdp, headers });
+                Ok(())
+            }
+            "PRACK" => {
+                drop(session);
+                let rack = request.header("RAck").unwrap_or_default().to_string();
+                let transaction_id = self.open_transaction(connection, request, local_tag);
+                let _ = self.event_tx.send(SipEvent::PrackReceived { session_id, transaction_id, rack });
+                Ok(())
+            }
+            "REFER" => {
+                drop(session);
+                let refer_to = request.header("Refer-To").or_else(|| request.header("r")).unwrap_or_default();
+                let refer_to = refer_to.to_string();
+                let headers = request.headers.clone();
+                let transaction_id = self.open_transaction(connection, request, local_tag);
+                let _ = self.event_tx.send(SipEvent::ReferReceived { session_id, transaction_id, refer_to, headers });
+                Ok(())
+            }
+            "INFO" | "NOTIFY" | "MESSAGE" => {
+                drop(session);
+                self.reply_in_dialog(connection, &request, &local_tag, 200, "OK")
+            }
+            _ => {
+                drop(session);
+                self.reply_in_dialog(connection, &request, &local_tag, 405, "Method Not Allowed")
+            }
+        }
+    }
+
+    fn reply_in_dialog(
+        &self,
+        connection: Connection,
+        request: &SipText,
+        local_tag: &str,
+        status_code: u16,
+        reason: &str,
+    ) -> Result<()> {
+        let mut response = request.reply(status_code, reason, Some(local_tag));
+        if status_code == 405 {
+            response = response.with_header("Allow", ALLOW);
+        }
+        self.send(connection, &response)
+    }
+
+    fn receive_invite(
+        &self,
+        connection: Connection,
+        source: SocketAddr,
+        request: SipText,
+        call_id: String,
+    ) -> Result<()> {
+        let (Some(from), Some(to)) = (request.header("From"), request.header("To")) else {
+            return self.reply(connection, &request, 400, "Bad Request (no From or To)");
+        };
+        let (sdp, encapsulated) = match mime::split_body(request.content_type(), &request.body) {
+            Ok(body) => body,
+            Err(e) => return self.reply(connection, &request, 400, &format!("Bad Request ({})", e)),
+        };
+        let (from_uri, to_uri) = (sip_message::uri(from).to_string(), sip_message::uri(to).to_string());
+
+        let mut session = SipSession::new_inbound(call_id.clone(), to.to_string(), from.to_string());
+        session.remote_tag = sip_message::tag(from).map(str::to_string);
+        session.remote_cseq = request.cseq().map_or(0, |(cseq, _)| cseq);
+        session.contact = request.header("Contact").map(str::to_string);
+        session.remote_target = Some(connection.peer);
+        session.remote_sdp = sdp.clone();
+        session.connection = Some(connection);
+        let session_id = session.id.clone();
+        let headers = request.headers.clone();
+
+        self.send(connection, &request.reply(100, "Trying", None))?;
+        let transaction = self.open_transaction(connection, request, session.local_tag.clone());
+        session.invite_transaction = Some(transaction);
+        self.sessions.insert(call_id.clone(), session);
+        debug!("INVITE {} from {} over {}", call_id, connection.peer, connection.transport.via_name());
+
+        let _ = self.event_tx.send(SipEvent::IncomingCall {
+            session_id,
+            call_id,
+            from: from_uri,
+            to: to_uri,
+            sdp,
+            headers,
+            encapsulated,
+            source,
+        });
+        Ok(())
+    }
+
+    fn receive_register(&self, connection: Connection, request: SipText) -> Result<()> {
+        let Some(to) = request.header("To") else {
+            return self.reply(connection, &request, 400, "Bad Request (no To)");
+        };
+        let to_uri = sip_message::uri(to);
+        let user = to_uri
+            .split_once(':')
+            .map_or(to_uri, |(_, rest)| rest)
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let contact = request.header("Contact").unwrap_or_default().to_string();
+        let expires = request
+            .header("Expires")
+            .and_then(|expires| expires.trim().parse().ok())
+            .or_else(|| {
+                contact
+                    .split(';')
+                    .find_map(|param| param.trim().strip_prefix("expires="))
+                    .and_then(|expires| expires.parse().ok())
+            })
+            .unwrap_or(3600);
+        let headers = request.headers.clone();
+        let to_tag = format!("{:x}", rand::random::<u64>());
+        let transaction_id = self.open_transaction(connection, request, to_tag);
+        let flow = Some(connection.peer);
+        let registration = SipEvent::RegistrationReceived { transaction_id, user, contact, expires, headers, flow };
+        let _ = self.event_tx.send(registration);
+        Ok(())
+    }
+
+    fn receive_response(&self, response: SipText, received: ClockStamp) {
+        let (Some(status_code), Some(call_id), Some((cseq, method))) =
+            (response.status_code(), response.call_id(), response.cseq())
+        else {
+            debug!("Dropping SIP response without Call-ID or CSeq");
+            return;
+        };
+        if !method.eq_ignore_ascii_case("INVITE") {
+            trace!("{} response {} to {}", method, status_code, call_id);
+            return;
+        }
+        let Some(mut session) = self.sessions.get_mut(call_id) else {
+            debug!("Response {} for unknown dialog {}", status_code, call_id);
+            return;
+        };
+        let (Some(connection), StartLine::Response { reason, .. }) = (session.connection, &response.start) else {
+            return;
+        };
+        session.update_activity();
+        let session_id = session.id.clone();
+        let headers = response.headers.clone();
+        let sdp = body_sdp(&response);
+        if let Some(to_tag) = response.header("To").and_then(sip_message::tag) {
+            session.remote_tag = Some(to_tag.to_string());
+        }
+
+        if status_code < 200 {
+            let event = match status_code {
+                100 => return,
+                180 => {
+                    session.state = SessionState::Ringing;
+                    SipEvent::CallRinging { session_id, headers, received }
+                }
+                _ => {
+                    session.state = SessionState::Early;
+                    SipEvent::SessionProgress { session_id, sdp, headers, received }
+                }
+            };
+            let _ = self.event_tx.send(event);
+            return;
+        }
+
+        // Every final response to an INVITE is acknowledged: a 2xx with a new
+        // transaction to the remote target, anything else hop by hop with the
+        // INVITE's own Via (RFC 3261 §17.1.1.3)
+        let invite = self.invites.remove(call_id).map(|(_, invite)| invite);
+        let ack = if (200..300).contains(&status_code) {
+            if let Some(contact) = response.header("Contact") {
+                session.contact = Some(contact.to_string());
+            }
+            Some(self.dialog_request(&session, connection.transport, "ACK", cseq))
+        } else {
+            invite.map(|invite| {
+                let uri = match &invite.start {
+                    StartLine::Request { uri, .. } => uri.clone(),
+                    StartLine::Response { .. } => String::new(),
+                };
+                let mut ack = SipText::request("ACK", &uri);
+                for name in ["Via", "From", "To", "Call-ID"] {
+                    let source = if name == "To" { &response } else { &invite };
+                    if let Some(value) = source.header(name) {
+                        ack = ack.with_header(name, value);
+                    }
+                }
+                ack.with_header("CSeq", format!("{} ACK", cseq))
+            })
+        };
+        if let Some(ack) = ack {
+            if let Err(e) = self.send(connection, &ack) {
+                warn!("Failed to acknowledge {} for {}: {}", status_code, call_id, e);
+            }
+        }
+
+        let event = if session.state == SessionState::Confirmed {
+            SipEvent::RefreshResponse { session_id, status_code, sdp, headers }
+        } else if (200..300).contains(&status_code) {
+            session.state = SessionState::Confirmed;
+            session.remote_sdp = sdp.clone();
+            SipEvent::CallAnswered { session_id, sdp, headers, received }
+        } else {
+            session.state = SessionState::Terminated;
+            SipEvent::CallFailed { session_id, status_code, reason: reason.clone() }
+        };
+        let _ = self.event_tx.send(event);
+    }
+
+    /// New request within the dialog of `session`
+    fn dialog_request(&self, session: &SipSession, transport: Transport, method: &str, cseq: u32) -> SipText {
+        let target = session
+            .contact
+            .as_deref()
+            .map(sip_message::uri)
+            .unwrap_or_else(|| sip_message::uri(&session.remote_uri));
+        SipText::request(method, target)
+            .with_header("Via", self.via(transport))
+            .with_header("Max-Forwards", "70")
+            .with_header("From", with_tag(&session.local_uri, Some(&session.local_tag)))
+            .with_header("To", with_tag(&session.remote_uri, session.remote_tag.as_deref()))
+            .with_header("Call-ID", session.call_id.clone())
+            .with_header("CSeq", format!("{} {}", cseq, method))
+            .with_header("Contact", self.contact(transport))
+    }
+
+    fn call_id_of(&self, session_id: &str) -> Option<String> {
+        self.sessions.iter().find(|session| session.id == session_id).map(|session| session.call_id.clone())
+    }
+
+    /// Whether `transaction_id` is one of ours, waiting for an answer
+    pub fn has_transaction(&self, transaction_id: &str) -> bool {
+        self.transactions.contains_key(transaction_id)
+    }
+
+    /// Answer a request received on a connection; a final answer ends the
+    /// transaction
+    pub fn respond(
+        &self,
+        transaction_id: &str,
+        status_code: u16,
+        reason: &str,
+        headers: &[(String, String)],
+        body: Option<(&str, &[u8])>,
+    ) -> Result<()> {
+        let transaction = if status_code >= 200 {
+            self.transactions.remove(transaction_id).map(|(_, transaction)| transaction)
+        } else {
+            self.transactions.get(transaction_id).map(|transaction| transaction.clone())
+        };
+        let transaction =
+            transaction.ok_or_else(|| Error::invalid_state(format!("No SIP transaction {}", transaction_id)))?;
+
+        let mut response = transaction
+            .request
+            .reply(status_code, reason, Some(&transaction.to_tag))
+            .with_headers(headers);
+        let dialog_forming = matches!(transaction.request.method(), Some("INVITE" | "UPDATE"));
+        if dialog_forming && (101..300).contains(&status_code) {
+            response = response.with_header("Contact", self.contact(transaction.connection.transport));
+        }
+        if let Some((content_type, body)) = body {
+            response = response.with_body(content_type, body.to_vec());
+        }
+        self.send(transaction.connection, &response)
+    }
+
+    /// Answer the INVITE that set up `session_id`; false when the dialog is
+    /// not on a connection
+    pub fn respond_to_invite(
+        &self,
+        session_id: &str,
+        status_code: u16,
+        reason: &str,
+        sdp: Option<&str>,
+        headers: &[(String, String)],
+    ) -> Result<bool> {
+        let Some(call_id) = self.call_id_of(session_id) else {
+            return Ok(false);
+        };
+        let transaction = {
+            let Some(mut session) = self.sessions.get_mut(&call_id) else {
+                return Ok(false);
+            };
+            if session.connection.is_none() {
+                return Ok(false);
+            }
+            let transaction = session
+                .invite_transaction
+                .clone()
+                .ok_or_else(|| Error::invalid_state(format!("INVITE of session {} already answered", session_id)))?;
+            if status_code >= 200 {
+                session.invite_transaction = None;
+                if (200..300).contains(&status_code) {
+                    session.state = SessionState::Confirmed;
+                    if let Some(sdp) = sdp {
+                        session.sdp = Some(sdp.to_string());
+                    }
+                } else {
+                    session.state = SessionState::Terminated;
+                }
+            } else if status_code > 100 {
+                session.state = SessionState::Early;
+            }
+            transaction
+        };
+        self.respond(&transaction, status_code, reason, headers, sdp_body(sdp))?;
+        Ok(true)
+    }
+
+    /// Send `method` within the dialog of `session_id`; false when the
+    /// dialog is not on a connection
+    pub fn send_in_dialog(
+        &self,
+        session_id: &str,
+        method: &str,
+        headers: &[(String, String)],
+        body: Option<(&str, &[u8])>,
+    ) -> Result<bool> {
+        let Some(call_id) = self.call_id_of(session_id) else {
+            return Ok(false);
+        };
+        let (connection, request) = {
+            let Some(mut session) = self.sessions.get_mut(&call_id) else {
+                return Ok(false);
+            };
+            let Some(connection) = session.connection else {
+                return Ok(false);
+            };
+            session.cseq += 1;
+            session.update_activity();
+            if method == "BYE" {
+                session.state = SessionState::Disconnected;
+            }
+            let mut request =
+                self.dialog_request(&session, connection.transport, method, session.cseq).with_headers(headers);
+            if let Some((content_type, body)) = body {
+                request = request.with_body(content_type, body.to_vec());
+            }
+            (connection, request)
+        };
+        if method == "INVITE" {
+            self.invites.insert(call_id, request.clone());
+        }
+        self.send(connection, &request)?;
+        Ok(true)
+    }
+
+    /// Send the initial INVITE of outbound `session` on `connection`
+    pub fn send_invite(
+        &self,
+        mut session: SipSession,
+        connection: Connection,
+        headers: &[(String, String)],
+        body: Option<(&str, &[u8])>,
+    ) -> Result<()> {
+        session.connection = Some(connection);
+        session.remote_target = Some(connection.peer);
+        session.state = SessionState::Calling;
+        let mut request =
+            self.dialog_request(&session, connection.transport, "INVITE", session.cseq).with_headers(headers);
+        if let Some((content_type, body)) = body {
+            request = request.with_body(content_type, body.to_vec());
+        }
+        self.send(connection, &request)?;
+        self.invites.insert(session.call_id.clone(), request);
+        self.sessions.insert(session.call_id.clone(), session);
+        Ok(())
+    }
+
+    /// CANCEL the unanswered INVITE of `session_id`; false when the dialog
+    /// is not on a connection
+    pub fn cancel(&self, session_id: &str) -> Result<bool> {
+        let Some(call_id) = self.call_id_of(session_id) else {
+            return Ok(false);
+        };
+        let Some(connection) = self.sessions.get(&call_id).and_then(|session| session.connection) else {
+            return Ok(false);
+        };
+        let invite = self
+            .invites
+            .get(&call_id)
+            .map(|invite| invite.clone())
+            .ok_or_else(|| Error::invalid_state(format!("Session {} has no INVITE to cancel", session_id)))?;
+        let StartLine::Request { uri, .. } = &invite.start else {
+            return Ok(false);
+        };
+        // Same Request-URI, Via, From, To and CSeq number (RFC 3261 §9.1)
+        let mut cancel = SipText::request("CANCEL", uri);
+        for name in ["Via", "Max-Forwards", "From", "To", "Call-ID"] {
+            if let Some(value) = invite.header(name) {
+                cancel = cancel.with_header(name, value);
+            }
+        }
+        let cseq = invite.cseq().map_or(1, |(cseq, _)| cseq);
+        self.send(connection, &cancel.with_header("CSeq", format!("{} CANCEL", cseq)))?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SipConfig {
+        SipConfig {
+            listen_port: 5060,
+            domain: "gw.example.com".to_string(),
+            transport: crate::config::SipTransport::Udp,
+            max_sessions: 100,
+            session_timeout: 300,
+            register_interval: 3600,
+            udp_size_threshold: 1300,
+            tcp_fallback: true,
+            force_rport: Vec::new(),
+            size_limits: Default::default(),
+            tls: Default::default(),
+            websocket: Default::default(),
+            registrar: Default::default(),
+            network: Default::default(),
+        }
+    }
+
+    /// Connections with one fake TLS peer whose outbound messages are returned
+    type Outbound = mpsc::UnboundedReceiver<Vec<u8>>;
+
+    fn connections() -> (SipConnections, mpsc::UnboundedReceiver<SipEvent>, Connection, Outbound) {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let connections = SipConnections::new(&config(), Arc::new(DashMap::new()), event_tx);
+        let connection = Connection { transport: Transport::Tls, peer: "198.51.100.5:41000".parse().unwrap() };
+        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        connections.streams.insert(connection.peer, Transport::Tls, outbound_tx);
+        (connections, event_rx, connection, outbound_rx)
+    }
+
+    fn sent(outbound_rx: &mut Outbound) -> SipText {
+        SipText::parse(&outbound_rx.try_recv().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_inbound_call_answered_and_released_on_connection() {
+        let (connections, mut events, connection, mut outbound) = connections();
+        let invite = "INVITE sip:5551000@gw.example.com SIP/2.0\r\n\
+            Via: SIP/2.0/TLS sbc.carrier.net;branch=z9hG4bKc1;rport\r\n\
+            From: <sip:2125550100@sbc.carrier.net>;tag=c1\r\n\
+            To: <sip:5551000@gw.example.com>\r\n\
+            Call-ID: tls-call-1\r\nCSeq: 1 INVITE\r\n\
+            Contact: <sip:2125550100@198.51.100.5:5061;transport=tls>\r\n\
+            Content-Type: application/sdp\r\nContent-Length: 5\r\n\r\nv=0\r\n";
+        connections.receive(connection, invite.as_bytes());
+        assert_eq!(sent(&mut outbound).status_code(), Some(100));
+        let session_id = match events.try_recv().unwrap() {
+            SipEvent::IncomingCall { session_id, from, to, sdp, .. } => {
+                assert_eq!(from, "sip:2125550100@sbc.carrier.net");
+                assert_eq!(to, "sip:5551000@gw.example.com");
+                assert_eq!(sdp.as_deref(), Some("v=0\r\n"));
+                session_id
+            }
+            other => panic!("unexpected event {:?}", other),
+        };
+
+        assert!(connections.respond_to_invite(&session_id, 200, "OK", Some("v=0\r\n"), &[]).unwrap());
+        let ok = sent(&mut outbound);
+        assert_eq!(ok.status_code(), Some(200));
+        assert!(sip_message::tag(ok.header("To").unwrap()).is_some());
+        let via = "SIP/2.0/TLS sbc.carrier.net;branch=z9hG4bKc1;rport=41000;received=198.51.100.5";
+        assert_eq!(ok.header("Via"), Some(via));
+        assert!(connections.respond_to_invite(&session_id, 200, "OK", None, &[]).is_err());
+
+        // Our BYE goes to the Contact on the same connection
+        assert!(connections.send_in_dialog(&session_id, "BYE", &[], None).unwrap());
+        let bye = sent(&mut outbound);
+        assert_eq!(bye.start, StartLine::Request {
+            method: "BYE".to_string(),
+            uri: "sip:2125550100@198.51.100.5:5061;transport=tls".to_string(),
+        });
+        assert_eq!(bye.header("To"), Some("<sip:2125550100@sbc.carrier.net>;tag=c1"));
+        assert_eq!(bye.cseq(), Some((2, "BYE")));
+
+        // Unknown dialogs get 481
+        let stray = b"BYE sip:gw SIP/2.0\r\nVia: SIP/2.0/TLS x;branch=z9hG4bKz\r\nCall-ID: nope\r\nCSeq: 2 BYE\r\n\r\n";
+        connections.receive(connection, stray);
+        assert_eq!(sent(&mut outbound).status_code(), Some(481));
+        // A Via without a branch is refused before anything else
+        let branchless = b"OPTIONS sip:gw SIP/2.0\r\nVia: SIP/2.0/TLS x\r\nCall-ID: o1\r\nCSeq: 1 OPTIONS\r\n\r\n";
+        connections.receive(connection, branchless);
+        assert_eq!(sent(&mut outbound).status_code(), Some(400));
+    }
+
+    #[test]
+    fn test_outbound_invite_responses_become_events() {
+        let (connections, mut events, connection, mut outbound) = connections();
+        let session = SipSession::new_outbound(
+            "out-1".to_string(),
+            "sip:2125550100@gw.example.com".to_string(),
+            "sip:5551000@sbc.carrier.net".to_string(),
+        );
+        let session_id = session.id.clone();
+        connections.send_invite(session, connection, &[], Some((mime::CONTENT_TYPE_SDP, b"v=0\r\n"))).unwrap();
+        let invite = sent(&mut outbound);
+        assert!(invite.header("Via").unwrap().starts_with("SIP/2.0/TLS gw.example.com:5061;branch=z9hG4bK"));
+
+        let respond = |code: u16, reason: &str, to_tag: &str| {
+            let mut response = invite.reply(code, reason, Some(to_tag));
+            if code == 200 {
+                response = response.with_header("Contact", "<sip:5551000@198.51.100.5:5061;transport=tls>");
+            }
+            connections.receive(connection, &response.to_bytes());
+        };
+        respond(180, "Ringing", "far1");
+        assert!(matches!(events.try_recv().unwrap(), SipEvent::CallRinging { .. }));
+        respond(200, "OK", "far1");
+        let answered = events.try_recv().unwrap();
+        assert!(matches!(answered, SipEvent::CallAnswered { session_id: ref id, .. } if *id == session_id));
+        let ack = sent(&mut outbound);
+        assert_eq!(ack.method(), Some("ACK"));
+        assert_eq!(ack.cseq(), Some((1, "ACK")));
+        assert_eq!(ack.header("To"), Some("<sip:5551000@sbc.carrier.net>;tag=far1"));
+
+        let failed = SipSession::new_outbound("out-2".to_string(), "sip:a@gw".to_string(), "sip:b@sbc".to_string());
+        connections.send_invite(failed, connection, &[], None).unwrap();
+        let invite = sent(&mut outbound);
+        connections.receive(connection, &invite.reply(486, "Busy Here", Some("far2")).to_bytes());
+        assert!(matches!(events.try_recv().unwrap(), SipEvent::CallFailed { status_code: 486, .. }));
+        let ack = sent(&mut outbound);
+        assert_eq!((ack.method(), ack.header("Via")), (Some("ACK"), invite.header("Via")));
+    }
+
+    #[test]
+    fn test_websocket_registration_flow() {
+        let (connections, mut events, _, _) = connections();
+        let client = Connection { transport: Transport::Wss, peer: "203.0.113.40:61000".parse().unwrap() };
+        let (outbound_tx, mut outbound) = mpsc::unbounded_channel();
+        connections.websockets.insert(client.peer, Transport::Wss, outbound_tx);
+
+        let register = "REGISTER sip:gw.example.com SIP/2.0\r\n\
+            Via: SIP/2.0/WSS d1f0a2.invalid;branch=z9hG4bKr1\r\n\
+            From: <sip:1001@gw.example.com>;tag=r1\r\nTo: <sip:1001@gw.example.com>\r\n\
+            Call-ID: reg-1\r\nCSeq: 1 REGISTER\r\n\
+            Contact: <sip:k4x9@d1f0a2.invalid;transport=ws>;expires=600\r\n\r\n";
+        connections.receive(client, register.as_bytes());
+        let transaction_id = match events.try_recv().unwrap() {
+            SipEvent::RegistrationReceived { transaction_id, user, expires, flow, .. } => {
+                assert_eq!((user.as_str(), expires, flow), ("1001", 600, Some(client.peer)));
+                transaction_id
+            }
+            other => panic!("unexpected event {:?}", other),
+        };
+        connections.respond(&transaction_id, 200, "OK", &[], None).unwrap();
+        assert_eq!(sent(&mut outbound).status_code(), Some(200));
+
+        // A call to the registered contact goes down the same WebSocket
+        let connection = connections.connection(client.peer).unwrap();
+        assert_eq!(connection, client);
+        let session = SipSession::new_outbound(
+            "ws-call-1".to_string(),
+            "sip:2125550100@gw.example.com".to_string(),
+            "sip:k4x9@d1f0a2.invalid;transport=ws".to_string(),
+        );
+        connections.send_invite(session, connection, &[], None).unwrap();
+        let invite = sent(&mut outbound);
+        assert!(invite.header("Via").unwrap().starts_with("SIP/2.0/WSS gw.example.com:8443;branch=z9hG4bK"));
+        assert_eq!(invite.header("Contact"), Some("<sip:gw.example.com:8443;transport=wss>"));
+    }
+
+    #[test]
+    fn test_oversized_requests_rejected_before_parsing() {
+        let mut config = config();
+        config.size_limits.max_header_bytes = 200;
+        let (event_tx, mut events) = mpsc::unbounded_channel();
+        let connections = SipConnections::new(&config, Arc::new(DashMap::new()), event_tx);
+        let connection = Connection { transport: Transport::Tcp, peer: "198.51.100.9:40000".parse().unwrap() };
+        let (outbound_tx, mut outbound) = mpsc::unbounded_channel();
+        connections.streams.insert(connection.peer, Transport::Tcp, outbound_tx);
+
+        let invite = format!(
+            "INVITE sip:5551000@gw.example.com SIP/2.0\r\nVia: SIP/2.0/TCP 198.51.100.9;branch=z9hG4bKo1\r\n\
+             From: <sip:a@b>;tag=1\r\nTo: <sip:5551000@gw.example.com>\r\nCall-ID: big-1\r\nCSeq: 1 INVITE\r\n\
+             Subject: {}\r\n\r\n",
+            "x".repeat(300)
+        );
+        connections.receive(connection, invite.as_bytes());
+        assert_eq!(sent(&mut outbound).status_code(), Some(STATUS_TOO_LARGE));
+        assert!(events.try_recv().is_err());
+        assert_eq!(connections.size_limit_stats()[0].rejected, 1);
+    }
+}
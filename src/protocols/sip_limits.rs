@@ -0,0 +1,267 @@
+//! Size limits on received SIP messages
+//!
+//! A misbehaving peer can send an INVITE of several megabytes, or one
+//! enormous header, and every copy the stack makes of it costs memory.
+//! Received messages are measured before they are parsed: the whole
+//! message, each header line (with its folded continuations) and the SDP
+//! body, or the SDP part of a multipart body. An oversized request is
+//! answered with 413 Request Entity Too Large, or dropped when configured;
+//! oversized responses are always dropped. Violations are counted per peer.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::{OversizeAction, SipSizeLimits};
+use crate::protocols::mime;
+
+pub const STATUS_TOO_LARGE: u16 = 413;
+pub const REASON_TOO_LARGE: &str = "Request Entity Too Large";
+
+/// Which limit a message broke
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeLimit {
+    Message,
+    Header,
+    Sdp,
+}
+
+impl fmt::Display for SizeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Message => "message",
+            Self::Header => "header",
+            Self::Sdp => "SDP body",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeViolation {
+    pub limit: SizeLimit,
+    pub size: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for SizeViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} bytes exceeds {}", self.limit, self.size, self.max)
+    }
+}
+
+/// What to do with a received message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeVerdict {
+    Accept,
+    /// Answer with 413 and go no further
+    Reject(SizeViolation),
+    Drop(SizeViolation),
+}
+
+/// Oversized messages from one peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SizeLimitStats {
+    pub peer: String,
+    pub oversize_messages: u64,
+    pub oversize_headers: u64,
+    pub oversize_sdp: u64,
+    /// Requests answered with 413
+    pub rejected: u64,
+    pub dropped: u64,
+}
+
+/// Measures received messages against the configured limits
+pub struct SizeGuard {
+    limits: SipSizeLimits,
+    stats: DashMap<IpAddr, SizeLimitStats>,
+}
+
+impl SizeGuard {
+    pub fn new(limits: SipSizeLimits) -> Self {
+        Self {
+            limits,
+            stats: DashMap::new(),
+        }
+    }
+
+    /// Buffer needed to tell a message at the limit from one over it
+    pub fn receive_buffer_size(&self) -> usize {
+        self.limits.max_message_bytes + 1
+    }
+
+    /// First limit `data` breaks, if any
+    pub fn measure(&self, data: &[u8]) -> Option<SizeViolation> {
+        let violation = |limit, size, max| (size > max).then_some(SizeViolation { limit, size, max });
+        if let Some(violation) = violation(SizeLimit::Message, data.len(), self.limits.max_message_bytes) {
+            return Some(violation);
+        }
+
+        let (head, body) = match find(data, b"\r\n\r\n") {
+            Some(end) => (&data[..end], &data[end + 4..]),
+            None => (data, &data[data.len()..]),
+        };
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.split("\r\n").skip(1).peekable();
+        let mut content_type = None;
+        while let Some(line) = lines.next() {
+            // Folded continuation lines belong to the header above
+            let mut size = line.len();
+            while let Some(next) = lines.next_if(|next| next.starts_with([' ', '\t'])) {
+                size += 2 + next.len();
+            }
+            if let Some(violation) = violation(SizeLimit::Header, size, self.limits.max_header_bytes) {
+                return Some(violation);
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("Content-Type") || name.eq_ignore_ascii_case("c") {
+                    content_type = Some(value.trim().to_string());
+                }
+            }
+        }
+
+        let sdp = match content_type.as_deref() {
+            Some(content_type) if content_type.to_ascii_lowercase().starts_with(mime::CONTENT_TYPE_SDP) => body.len(),
+            Some(content_type) => mime::split_body(Some(content_type), body)
+                .ok()
+                .and_then(|(sdp, _)| sdp)
+                .map_or(0, |sdp| sdp.len()),
+            None => 0,
+        };
+        violation(SizeLimit::Sdp, sdp, self.limits.max_sdp_bytes)
+    }
+
+    /// Measure a message received from `source` and count any violation
+    pub fn check(&self, data: &[u8], source: SocketAddr) -> SizeVerdict {
+        let Some(violation) = self.measure(data) else {
+            return SizeVerdict::Accept;
+        };
+        let is_request = !data.starts_with(b"SIP/2.0 ");
+        let reject = is_request && self.limits.action == OversizeAction::Reject;
+        warn!(
+            "Oversized SIP {} from {}: {}, {}",
+            if is_request { "request" } else { "response" },
+            source,
+            violation,
+            if reject { "rejecting" } else { "dropping" }
+        );
+
+        let mut stats = self.stats.entry(source.ip()).or_insert_with(|| SizeLimitStats {
+            peer: source.ip().to_string(),
+            ..Default::default()
+        });
+        match violation.limit {
+            SizeLimit::Message => stats.oversize_messages += 1,
+            SizeLimit::Header => stats.oversize_headers += 1,
+            SizeLimit::Sdp => stats.oversize_sdp += 1,
+        }
+        if reject {
+            stats.rejected += 1;
+            SizeVerdict::Reject(violation)
+        } else {
+            stats.dropped += 1;
+            SizeVerdict::Drop(violation)
+        }
+    }
+
+    /// Counters per peer, by peer address
+    pub fn stats(&self) -> Vec<SizeLimitStats> {
+        let mut stats: Vec<SizeLimitStats> = self.stats.iter().map(|entry| entry.value().clone()).collect();
+        stats.sort_by(|a, b| a.peer.cmp(&b.peer));
+        stats
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invite(extra_header: &str, sdp: &str) -> Vec<u8> {
+        format!(
+            "INVITE sip:1000@gw SIP/2.0\r\nVia: SIP/2.0/UDP 10.0.0.1;branch=z9hG4bK1\r\n{}Content-Type: application/sdp\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            extra_header,
+            sdp.len(),
+            sdp
+        )
+        .into_bytes()
+    }
+
+    fn guard(action: OversizeAction) -> SizeGuard {
+        SizeGuard::new(SipSizeLimits {
+            max_message_bytes: 2000,
+            max_header_bytes: 200,
+            max_sdp_bytes: 500,
+            action,
+        })
+    }
+
+    #[test]
+    fn test_each_limit() {
+        let guard = guard(OversizeAction::Reject);
+        assert_eq!(guard.measure(&invite("", "v=0\r\n")), None);
+
+        let huge = invite("", &"a=x\r\n".repeat(500));
+        assert_eq!(guard.measure(&huge).map(|v| v.limit), Some(SizeLimit::Message));
+
+        let long_header = format!("Subject: {}\r\n", "x".repeat(200));
+        assert_eq!(guard.measure(&invite(&long_header, "v=0\r\n")).map(|v| v.limit), Some(SizeLimit::Header));
+        // Folding does not hide a long header
+        let folded = format!("Subject: {}\r\n {}\r\n", "x".repeat(100), "y".repeat(100));
+        assert_eq!(guard.measure(&invite(&folded, "v=0\r\n")).map(|v| v.limit), Some(SizeLimit::Header));
+
+        let sdp = invite("", &"a=x\r\n".repeat(101));
+        assert_eq!(
+            guard.measure(&sdp),
+            Some(SizeViolation { limit: SizeLimit::Sdp, size: 505, max: 500 })
+        );
+    }
+
+    #[test]
+    fn test_sdp_inside_multipart() {
+        let guard = guard(OversizeAction::Reject);
+        let sdp = "a=x\r\n".repeat(101);
+        let (content_type, body) =
+            mime::build_body(Some(&sdp), &[mime::BodyPart::isup(&[0x01, 0x00])]).unwrap();
+        let mut message = format!(
+            "INVITE sip:1000@gw SIP/2.0\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        message.extend(&body);
+        assert_eq!(guard.measure(&message).map(|v| v.limit), Some(SizeLimit::Sdp));
+    }
+
+    #[test]
+    fn test_verdicts_and_counters() {
+        let source: SocketAddr = "198.51.100.7:5060".parse().unwrap();
+        let huge = invite("", &"a=x\r\n".repeat(500));
+        let response = [b"SIP/2.0 200 OK\r\n".as_slice(), &huge[28..]].concat();
+
+        let rejecting = guard(OversizeAction::Reject);
+        assert!(matches!(rejecting.check(&huge, source), SizeVerdict::Reject(_)));
+        assert!(matches!(rejecting.check(&response, source), SizeVerdict::Drop(_)));
+        assert_eq!(rejecting.check(&invite("", "v=0\r\n"), source), SizeVerdict::Accept);
+        assert_eq!(rejecting.stats(), vec![SizeLimitStats {
+            peer: "198.51.100.7".to_string(),
+            oversize_messages: 2,
+            oversize_headers: 0,
+            oversize_sdp: 0,
+            rejected: 1,
+            dropped: 1,
+        }]);
+
+        let dropping = guard(OversizeAction::Drop);
+        assert!(matches!(dropping.check(&huge, source), SizeVerdict::Drop(_)));
+        assert_eq!(dropping.receive_buffer_size(), 2001);
+    }
+}
@@ -0,0 +1,289 @@
+//! SIP message text for the connection-oriented transports
+//!
+//! Messages received on TLS, TCP and WebSocket connections are split here
+//! into their start line, headers and body, and the requests and responses
+//! the gateway sends back on those connections are built here. Header order
+//! is kept, folded header lines are joined into one value (RFC 3261 §7.3.1)
+//! and Content-Length is always written from the body.
+
+use std::fmt;
+
+use crate::{Error, Result};
+
+pub const SIP_VERSION: &str = "SIP/2.0";
+
+/// Magic cookie every RFC 3261 branch starts with (§8.1.1.7)
+pub const BRANCH_COOKIE: &str = "z9hG4bK";
+
+/// Long and compact forms of the headers that have one (RFC 3261 §7.3.3)
+const COMPACT_FORMS: [(&str, &str); 10] = [
+    ("Call-ID", "i"),
+    ("Contact", "m"),
+    ("Content-Encoding", "e"),
+    ("Content-Length", "l"),
+    ("Content-Type", "c"),
+    ("From", "f"),
+    ("Subject", "s"),
+    ("Supported", "k"),
+    ("To", "t"),
+    ("Via", "v"),
+];
+
+/// Whether header `name` as received is the header `wanted`, in either form
+pub fn header_is(name: &str, wanted: &str) -> bool {
+    name.eq_ignore_ascii_case(wanted)
+        || COMPACT_FORMS
+            .iter()
+            .any(|(long, compact)| long.eq_ignore_ascii_case(wanted) && name.eq_ignore_ascii_case(compact))
+}
+
+/// `tag` parameter of a From or To value
+pub fn tag(value: &str) -> Option<&str> {
+    // Parameters after the name-addr, so a tag inside <> is not mistaken for one
+    let params = value.rsplit_once('>').map_or(value, |(_, params)| params);
+    params
+        .split(';')
+        .skip(1)
+        .find_map(|param| param.trim().strip_prefix("tag="))
+        .filter(|tag| !tag.is_empty())
+}
+
+/// URI of a From, To or Contact value, without display name or parameters
+pub fn uri(value: &str) -> &str {
+    match value.split_once('<') {
+        Some((_, rest)) => rest.split('>').next().unwrap_or(rest),
+        None => value.split(';').next().unwrap_or(value),
+    }
+    .trim()
+}
+
+/// New client transaction branch
+pub fn new_branch() -> String {
+    format!("{}{:016x}", BRANCH_COOKIE, rand::random::<u64>())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartLine {
+    Request { method: String, uri: String },
+    Response { status_code: u16, reason: String },
+}
+
+impl fmt::Display for StartLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartLine::Request { method, uri } => write!(f, "{} {} {}", method, uri, SIP_VERSION),
+            StartLine::Response { status_code, reason } => write!(f, "{} {} {}", SIP_VERSION, status_code, reason),
+        }
+    }
+}
+
+/// One SIP request or response
+#[derive(Debug, Clone, PartialEq)]
+pub struct SipText {
+    pub start: StartLine,
+    /// In received or sent order, Content-Length excluded when building
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl SipText {
+    pub fn request(method: &str, uri: &str) -> Self {
+        Self {
+            start: StartLine::Request { method: method.to_string(), uri: uri.to_string() },
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn response(status_code: u16, reason: &str) -> Self {
+        Self {
+            start: StartLine::Response { status_code, reason: reason.to_string() },
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Parse one complete message, as framed by the transport
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let end = data
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| Error::parse("SIP message has no end of headers"))?;
+        let head = std::str::from_utf8(&data[..end]).map_err(|_| Error::parse("SIP headers are not UTF-8"))?;
+        let mut lines = head.split("\r\n");
+
+        let start_line = lines.next().unwrap_or_default();
+        let start = match start_line.strip_prefix("SIP/2.0 ") {
+            Some(status) => {
+                let (code, reason) = status.split_once(' ').unwrap_or((status, ""));
+                let status_code = code
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|code| (100..700).contains(code))
+                    .ok_or_else(|| Error::parse(format!("Invalid status line: {}", start_line)))?;
+                StartLine::Response { status_code, reason: reason.trim().to_string() }
+            }
+            None => {
+                let mut parts = start_line.split(' ');
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(method), Some(uri), Some(SIP_VERSION), None) if !method.is_empty() && !uri.is_empty() => {
+                        StartLine::Request { method: method.to_string(), uri: uri.to_string() }
+                    }
+                    _ => return Err(Error::parse(format!("Invalid request line: {}", start_line))),
+                }
+            }
+        };
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in lines {
+            if line.starts_with([' ', '\t']) {
+                let (_, value) = headers
+                    .last_mut()
+                    .ok_or_else(|| Error::parse("Continuation line before any header"))?;
+                value.push(' ');
+                value.push_str(line.trim());
+                continue;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| Error::parse(format!("Invalid header line: {}", line)))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut body = data[end + 4..].to_vec();
+        let length = headers
+            .iter()
+            .find(|(name, _)| header_is(name, "Content-Length"))
+            .map(|(_, value)| {
+                value.parse::<usize>().map_err(|_| Error::parse(format!("Invalid Content-Length: {}", value)))
+            })
+            .transpose()?;
+        if let Some(length) = length {
+            if length > body.len() {
+                return Err(Error::parse(format!("Body shorter than Content-Length {}", length)));
+            }
+            body.truncate(length);
+        }
+        headers.retain(|(name, _)| !header_is(name, "Content-Length"));
+
+        Ok(Self { start, headers, body })
+    }
+
+    pub fn method(&self) -> Option<&str> {
+        match &self.start {
+            StartLine::Request { method, .. } => Some(method),
+            StartLine::Response { .. } => None,
+        }
+    }
+
+    pub fn status_code(&self) -> Option<u16> {
+        match self.start {
+            StartLine::Response { status_code, .. } => Some(status_code),
+            StartLine::Request { .. } => None,
+        }
+    }
+
+    /// First value of header `name`
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header_is(header, name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn call_id(&self) -> Option<&str> {
+        self.header("Call-ID")
+    }
+
+    /// Sequence number and method of the CSeq
+    pub fn cseq(&self) -> Option<(u32, &str)> {
+        let (number, method) = self.header("CSeq")?.split_once(' ')?;
+        Some((number.trim().parse().ok()?, method.trim()))
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    /// Append a header
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    pub fn with_headers(mut self, headers: &[(String, String)]) -> Self {
+        self.headers.extend(headers.iter().cloned());
+        self
+    }
+
+    pub fn with_body(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self.headers.retain(|(name, _)| !header_is(name, "Content-Type"));
+        self.headers.push(("Content-Type".to_string(), content_type.to_string()));
+        self.body = body;
+        self
+    }
+
+    /// Response to this request, carrying its Vias, From, Call-ID and CSeq,
+    /// and its To with `to_tag` added when it has none (RFC 3261 §8.2.6.2)
+    pub fn reply(&self, status_code: u16, reason: &str, to_tag: Option<&str>) -> Self {
+        let mut response = Self::response(status_code, reason);
+        for (name, value) in &self.headers {
+            if ["Via", "From", "Call-ID", "CSeq"].iter().any(|wanted| header_is(name, wanted)) {
+                response.headers.push((name.clone(), value.clone()));
+            } else if header_is(name, "To") {
+                let value = match to_tag {
+                    Some(to_tag) if status_code > 100 && tag(value).is_none() => format!("{};tag={}", value, to_tag),
+                    _ => value.clone(),
+                };
+                response.headers.push((name.clone(), value));
+            }
+        }
+        response
+    }
+
+    /// Wire form, with Content-Length written from the body
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("{}\r\n", self.start);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        let mut data = head.into_bytes();
+        data.extend_from_slice(&self.body);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_reply() {
+        let data = b"INVITE sip:5551000@gw.example.com SIP/2.0\r\n\
+            v: SIP/2.0/TLS 198.51.100.5;branch=z9hG4bKa1\r\n\
+            f: \"Carrier\" <sip:2125550100@sbc.carrier.net>;tag=c1\r\n\
+            t: <sip:5551000@gw.example.com>\r\n\
+            i: call-1@sbc\r\n\
+            CSeq: 7 INVITE\r\n\
+            Subject: long\r\n folded\r\n\
+            l: 4\r\n\r\nv=0\r\nextra";
+        let request = SipText::parse(data).unwrap();
+        assert_eq!(request.method(), Some("INVITE"));
+        assert_eq!(request.call_id(), Some("call-1@sbc"));
+        assert_eq!(request.cseq(), Some((7, "INVITE")));
+        assert_eq!(request.header("Subject"), Some("long folded"));
+        assert_eq!(request.body, b"v=0\r");
+        assert_eq!(tag(request.header("From").unwrap()), Some("c1"));
+        assert_eq!(uri(request.header("From").unwrap()), "sip:2125550100@sbc.carrier.net");
+
+        let response = request.reply(180, "Ringing", Some("gw9"));
+        assert_eq!(response.status_code(), Some(180));
+        assert_eq!(response.header("To"), Some("<sip:5551000@gw.example.com>;tag=gw9"));
+        assert!(response.header("Subject").is_none());
+        let wire = response.to_bytes();
+        assert!(wire.starts_with(b"SIP/2.0 180 Ringing\r\nv: SIP/2.0/TLS 198.51.100.5;branch=z9hG4bKa1\r\n"));
+        assert!(wire.ends_with(b"Content-Length: 0\r\n\r\n"));
+        assert_eq!(SipText::parse(&wire).unwrap(), response);
+
+        assert!(SipText::parse(b"INVITE sip:gw\r\n\r\n").is_err());
+        assert!(SipText::parse(b"SIP/2.0 99 Odd\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,814 @@
+//! SIP registrar with digest authentication (RFC 3261 §10, RFC 2617)
+//!
+//! Phones and softphones register with the gateway itself. A REGISTER
+//! without valid credentials is challenged with an MD5 digest; nonces are
+//! issued by the registrar, expire after the configured lifetime (answered
+//! with `stale=true` so clients retry without prompting) and a reused nonce
+//! count is refused. Answers must use `qop=auth` with a nonce count, which
+//! only advances once the digest matches, and digests are compared in
+//! constant time. Credentials come from the configuration or an HTTP
+//! backend, either as a password or as the precomputed HA1.
+//!
+//! An address of record is the user part of the To URI, and may hold several
+//! contacts, each with its own expiry and q-value. Bindings are updated by
+//! contact URI, out-of-order REGISTERs from the same Call-ID are refused,
+//! and past the contact limit the least recently refreshed contact is
+//! replaced. The router looks registered users up to reach them directly.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::config::{CredentialSource, RegistrarUser, SipConfig, SipRegistrarConfig};
+use crate::utils::constant_time_eq;
+use crate::{Error, Result};
+
+const DIGEST_ALGORITHM: &str = "MD5";
+
+/// Hex MD5 of `data`
+fn md5_hex(data: &str) -> String {
+    hex::encode(Md5::digest(data.as_bytes()))
+}
+
+/// `MD5(username:realm:password)`, the secret a digest response is keyed by
+pub fn ha1(username: &str, realm: &str, password: &str) -> String {
+    md5_hex(&format!("{}:{}:{}", username, realm, password))
+}
+
+/// Expected `response` of a digest Authorization for `method`
+pub fn digest_response(ha1: &str, method: &str, credentials: &DigestCredentials) -> String {
+    let ha2 = md5_hex(&format!("{}:{}", method, credentials.uri));
+    match (&credentials.qop, &credentials.nc, &credentials.cnonce) {
+        (Some(qop), Some(nc), Some(cnonce)) => {
+            md5_hex(&format!("{}:{}:{}:{}:{}:{}", ha1, credentials.nonce, nc, cnonce, qop, ha2))
+        }
+        _ => md5_hex(&format!("{}:{}:{}", ha1, credentials.nonce, ha2)),
+    }
+}
+
+/// Parameters of a `key=value, key="quoted value"` list
+fn parse_params(text: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let Some((key, after)) = rest.split_once('=') else {
+            break;
+        };
+        let after = after.trim_start();
+        let (value, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim(), &after[end..])
+            }
+        };
+        params.insert(key.trim().to_ascii_lowercase(), value.to_string());
+        rest = remainder.trim_start().trim_start_matches(',').trim_start();
+    }
+    params
+}
+
+/// Credentials of a `Digest` Authorization header
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestCredentials {
+    pub username: String,
+    pub realm: String,
+    pub nonce: String,
+    pub uri: String,
+    pub response: String,
+    pub algorithm: Option<String>,
+    pub opaque: Option<String>,
+    pub qop: Option<String>,
+    pub nc: Option<String>,
+    pub cnonce: Option<String>,
+}
+
+impl DigestCredentials {
+    pub fn parse(header: &str) -> Result<Self> {
+        let header = header.trim();
+        let params = match header.split_once(char::is_whitespace) {
+            Some((scheme, params)) if scheme.eq_ignore_ascii_case("Digest") => parse_params(params),
+            _ => return Err(Error::parse("Authorization is not a Digest")),
+        };
+        let required = |name: &str| {
+            params
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::parse(format!("Digest Authorization has no {}", name)))
+        };
+        Ok(Self {
+            username: required("username")?,
+            realm: required("realm")?,
+            nonce: required("nonce")?,
+            uri: required("uri")?,
+            response: required("response")?,
+            algorithm: params.get("algorithm").cloned(),
+            opaque: params.get("opaque").cloned(),
+            qop: params.get("qop").cloned(),
+            nc: params.get("nc").cloned(),
+            cnonce: params.get("cnonce").cloned(),
+        })
+    }
+}
+
+/// Where the registrar looks up digest secrets
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    /// HA1 of `username` in `realm`; `None` for an unknown user
+    async fn ha1(&self, username: &str, realm: &str) -> Result<Option<String>>;
+}
+
+/// Users listed in the configuration
+pub struct ConfigCredentials {
+    users: HashMap<String, RegistrarUser>,
+}
+
+impl ConfigCredentials {
+    pub fn new(users: &[RegistrarUser]) -> Self {
+        Self { users: users.iter().map(|user| (user.username.clone(), user.clone())).collect() }
+    }
+}
+
+#[async_trait]
+impl CredentialStore for ConfigCredentials {
+    async fn ha1(&self, username: &str, realm: &str) -> Result<Option<String>> {
+        let Some(user) = self.users.get(username) else {
+            return Ok(None);
+        };
+        match (&user.ha1, &user.password) {
+            (Some(secret), _) => Ok(Some(secret.to_ascii_lowercase())),
+            (None, Some(password)) => Ok(Some(ha1(username, realm, password))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Lookup sent to the credentials backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialLookup {
+    pub username: String,
+    pub realm: String,
+}
+
+/// Backend answer; an `ha1` is used over a `password`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CredentialLookupResponse {
+    pub ha1: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Credentials looked up with a JSON POST to an HTTP backend
+pub struct HttpCredentials {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpCredentials {
+    pub fn new(url: impl Into<String>, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| Error::invalid_config(format!("Failed to create credentials client: {}", e)))?;
+        Ok(Self { url: url.into(), client })
+    }
+}
+
+#[async_trait]
+impl CredentialStore for HttpCredentials {
+    async fn ha1(&self, username: &str, realm: &str) -> Result<Option<String>> {
+        let lookup = CredentialLookup { username: username.to_string(), realm: realm.to_string() };
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&lookup)
+            .send()
+            .await
+            .map_err(|e| Error::network(format!("Credentials lookup failed: {}", e)))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(Error::network(format!("Credentials backend returned {}", response.status())));
+        }
+        let answer: CredentialLookupResponse = response
+            .json()
+            .await
+            .map_err(|e| Error::parse(format!("Invalid credentials response: {}", e)))?;
+        match (answer.ha1, answer.password) {
+            (Some(secret), _) => Ok(Some(secret.to_ascii_lowercase())),
+            (None, Some(password)) => Ok(Some(ha1(username, realm, &password))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// One contact of a REGISTER
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactEntry {
+    /// URI without angle brackets or parameters outside them
+    pub uri: String,
+    pub expires: Option<u32>,
+    pub q: Option<f32>,
+}
+
+impl ContactEntry {
+    /// Parse one Contact value, e.g. `"Desk" <sip:1001@192.0.2.10:5062>;expires=600;q=0.8`
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (uri, params) = match (value.find('<'), value.find('>')) {
+            (Some(open), Some(close)) if open < close => (&value[open + 1..close], &value[close + 1..]),
+            _ => value.split_once(';').unwrap_or((value, "")),
+        };
+        if uri.trim().is_empty() {
+            return Err(Error::parse(format!("Contact {:?} has no URI", value)));
+        }
+        let mut contact = ContactEntry { uri: uri.trim().to_string(), expires: None, q: None };
+        for param in params.split(';') {
+            match param.split_once('=') {
+                Some((name, v)) if name.trim().eq_ignore_ascii_case("expires") => {
+                    contact.expires = Some(v.trim().parse().map_err(|_| Error::parse("Invalid Contact expires"))?);
+                }
+                Some((name, v)) if name.trim().eq_ignore_ascii_case("q") => {
+                    contact.q = Some(v.trim().parse().map_err(|_| Error::parse("Invalid Contact q-value"))?);
+                }
+                _ => {}
+            }
+        }
+        Ok(contact)
+    }
+}
+
+/// Split a header value at commas outside quotes and angle brackets
+fn split_contacts(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut quoted, mut bracketed, mut start) = (false, false, 0);
+    for (index, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' if !quoted => bracketed = true,
+            '>' if !quoted => bracketed = false,
+            ',' if !quoted && !bracketed => {
+                parts.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts.into_iter().map(str::trim).filter(|part| !part.is_empty()).collect()
+}
+
+/// User part of a SIP URI or name-addr
+fn uri_user(value: &str) -> Option<&str> {
+    let start = value.find("sip:").map(|at| at + 4).or_else(|| value.find("sips:").map(|at| at + 5))?;
+    let rest = &value[start..];
+    let end = rest.find('@')?;
+    Some(&rest[..end])
+}
+
+/// Values of the header called `long`, or `compact` in its compact form
+fn header_values<'a>(
+    headers: &'a [(String, String)],
+    long: &'static str,
+    compact: &'static str,
+) -> impl Iterator<Item = &'a str> {
+    headers
+        .iter()
+        .filter(move |(name, _)| name.eq_ignore_ascii_case(long) || name.eq_ignore_ascii_case(compact))
+        .map(|(_, value)| value.as_str())
+}
+
+/// A REGISTER, from its headers
+#[derive(Debug, Clone)]
+pub struct Registration {
+    /// Address of record: the user part of the To URI
+    pub aor: String,
+    /// `None` for the `*` wildcard, which removes every binding
+    pub contacts: Option<Vec<ContactEntry>>,
+    /// Expires header
+    pub expires: Option<u32>,
+    pub call_id: String,
+    pub cseq: u32,
+    pub authorization: Option<String>,
+    /// Connection the REGISTER came in on, for clients such as WebSocket
+    /// ones that cannot be reached at their contact (RFC 5626)
+    pub flow: Option<SocketAddr>,
+}
+
+impl Registration {
+    pub fn from_headers(headers: &[(String, String)]) -> Result<Self> {
+        let first = |long, compact| header_values(headers, long, compact).next();
+        let to = first("To", "t").ok_or_else(|| Error::parse("REGISTER has no To header"))?;
+        let aor = uri_user(to).ok_or_else(|| Error::parse(format!("To {:?} has no user", to)))?;
+        let call_id = first("Call-ID", "i").ok_or_else(|| Error::parse("REGISTER has no Call-ID"))?;
+        let cseq = first("CSeq", "CSeq")
+            .and_then(|cseq| cseq.split_whitespace().next())
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| Error::parse("REGISTER has no valid CSeq"))?;
+        let expires = first("Expires", "Expires")
+            .map(|expires| expires.trim().parse().map_err(|_| Error::parse("Invalid Expires header")))
+            .transpose()?;
+
+        let raw: Vec<&str> = header_values(headers, "Contact", "m").flat_map(split_contacts).collect();
+        let contacts = if raw == ["*"] {
+            None
+        } else {
+            Some(raw.into_iter().map(ContactEntry::parse).collect::<Result<Vec<_>>>()?)
+        };
+
+        Ok(Self {
+            aor: aor.to_string(),
+            contacts,
+            expires,
+            call_id: call_id.trim().to_string(),
+            cseq,
+            authorization: first("Authorization", "Authorization").map(str::to_string),
+            flow: None,
+        })
+    }
+}
+
+/// Final answer to a REGISTER
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistrationReply {
+    pub status_code: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RegistrationReply {
+    fn new(status_code: u16, reason: &str) -> Self {
+        Self { status_code, reason: reason.to_string(), headers: Vec::new() }
+    }
+
+    fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+}
+
+/// Contact registered for an address of record
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub aor: String,
+    pub contact: String,
+    pub q: Option<f32>,
+    pub call_id: String,
+    pub cseq: u32,
+    pub expires_at: Instant,
+    pub refreshed_at: Instant,
+    /// Connection calls to the contact are sent on, when registered over one
+    pub flow: Option<SocketAddr>,
+}
+
+impl Binding {
+    /// Seconds left, as reported in the Contact of a 200 OK
+    pub fn remaining_secs(&self, now: Instant) -> u64 {
+        self.expires_at.saturating_duration_since(now).as_secs()
+    }
+
+    /// Address calls to the contact go to: the flow it was registered on,
+    /// else the contact URI's host when that is an IP address
+    pub fn address(&self) -> Option<SocketAddr> {
+        if self.flow.is_some() {
+            return self.flow;
+        }
+        let uri = self.contact.strip_prefix("sips:").or_else(|| self.contact.strip_prefix("sip:"))?;
+        let host_port = uri.rsplit_once('@').map_or(uri, |(_, host)| host);
+        let host_port = host_port.split(';').next()?;
+        if let Ok(addr) = host_port.parse() {
+            return Some(addr);
+        }
+        let default_port = if self.contact.starts_with("sips:") { 5061 } else { 5060 };
+        let host = host_port.trim_start_matches('[').trim_end_matches(']');
+        host.parse().ok().map(|ip| SocketAddr::new(ip, default_port))
+    }
+}
+
+/// Nonce issued in a challenge
+struct NonceState {
+    issued: Instant,
+    /// Highest nonce count used with it
+    nonce_count: u32,
+}
+
+/// Outcome of checking a REGISTER's credentials
+enum Authentication {
+    Accepted,
+    Challenge { stale: bool },
+    Refused(RegistrationReply),
+}
+
+/// Registrar answering REGISTER requests and holding the bindings
+pub struct Registrar {
+    config: SipRegistrarConfig,
+    realm: String,
+    opaque: String,
+    credentials: Arc<dyn CredentialStore>,
+    nonces: DashMap<String, NonceState>,
+    bindings: DashMap<String, Vec<Binding>>,
+}
+
+impl Registrar {
+    pub fn new(config: SipRegistrarConfig, domain: &str, credentials: Arc<dyn CredentialStore>) -> Self {
+        let realm = if config.realm.is_empty() { domain.to_string() } else { config.realm.clone() };
+        Self {
+            config,
+            realm,
+            opaque: hex::encode(rand::random::<[u8; 8]>()),
+            credentials,
+            nonces: DashMap::new(),
+            bindings: DashMap::new(),
+        }
+    }
+
+    /// Registrar of `config.registrar`, with the credential store it names
+    pub fn from_config(config: &SipConfig) -> Result<Self> {
+        let registrar = &config.registrar;
+        let credentials: Arc<dyn CredentialStore> = match registrar.credential_source {
+            CredentialSource::Config => Arc::new(ConfigCredentials::new(&registrar.users)),
+            CredentialSource::Http => {
+                let url = registrar
+                    .credentials_url
+                    .clone()
+                    .ok_or_else(|| Error::invalid_config("HTTP registrar credentials need a URL"))?;
+                Arc::new(HttpCredentials::new(url, Duration::from_millis(registrar.credentials_timeout_ms))?)
+            }
+        };
+        Ok(Self::new(registrar.clone(), &config.domain, credentials))
+    }
+
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    fn challenge(&self, stale: bool) -> RegistrationReply {
+        let now = Instant::now();
+        let lifetime = Duration::from_secs(self.config.nonce_lifetime_secs);
+        self.nonces.retain(|_, state| now.duration_since(state.issued) < lifetime * 2);
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        self.nonces.insert(nonce.clone(), NonceState { issued: now, nonce_count: 0 });
+
+        let mut challenge = format!(
+            "Digest realm=\"{}\", nonce=\"{}\", opaque=\"{}\", algorithm={}, qop=\"auth\"",
+            self.realm, nonce, self.opaque, DIGEST_ALGORITHM
+        );
+        if stale {
+            challenge.push_str(", stale=true");
+        }
+        RegistrationReply::new(401, "Unauthorized").header("WWW-Authenticate", challenge)
+    }
+
+    async fn authenticate(&self, registration: &Registration) -> Authentication {
+        let Some(header) = &registration.authorization else {
+            return Authentication::Challenge { stale: false };
+        };
+        let credentials = match DigestCredentials::parse(header) {
+            Ok(credentials) => credentials,
+            Err(e) => return Authentication::Refused(RegistrationReply::new(400, &format!("Bad Request ({})", e))),
+        };
+        let md5 = credentials.algorithm.as_deref().map_or(true, |algorithm| algorithm.eq_ignore_ascii_case(DIGEST_ALGORITHM));
+        if credentials.realm != self.realm || !md5 {
+            return Authentication::Challenge { stale: false };
+        }
+
+        // Without qop and a nonce count an answer could be replayed for the
+        // nonce's whole lifetime
+        let qop = credentials.qop.as_deref().is_some_and(|qop| qop.eq_ignore_ascii_case("auth"));
+        let (true, Some(nc), Some(_)) = (qop, credentials.nc.as_deref(), credentials.cnonce.as_deref()) else {
+            debug!("REGISTER from {} without qop=auth, nc and cnonce", credentials.username);
+            return Authentication::Challenge { stale: false };
+        };
+        let Ok(nonce_count) = u32::from_str_radix(nc, 16) else {
+            return Authentication::Refused(RegistrationReply::new(400, "Bad Request (invalid nonce count)"));
+        };
+        if let Some(challenge) = self.check_nonce(&credentials, nonce_count) {
+            return challenge;
+        }
+
+        let ha1 = match self.credentials.ha1(&credentials.username, &self.realm).await {
+            Ok(Some(ha1)) => ha1,
+            Ok(None) => {
+                debug!("REGISTER from unknown user {}", credentials.username);
+                return Authentication::Refused(RegistrationReply::new(403, "Forbidden"));
+            }
+            Err(e) => {
+                warn!("Credentials lookup for {} failed: {}", credentials.username, e);
+                return Authentication::Refused(RegistrationReply::new(500, "Server Internal Error"));
+            }
+        };
+        let expected = digest_response(&ha1, "REGISTER", &credentials);
+        if !constant_time_eq(expected.as_bytes(), credentials.response.to_ascii_lowercase().as_bytes()) {
+            warn!("REGISTER for {} failed digest authentication", registration.aor);
+            return Authentication::Refused(RegistrationReply::new(403, "Forbidden"));
+        }
+        if credentials.username != registration.aor {
+            warn!("{} tried to register {}", credentials.username, registration.aor);
+            return Authentication::Refused(RegistrationReply::new(403, "Forbidden"));
+        }
+
+        // Checked again now the digest is known good, in case a concurrent
+        // REGISTER used the count while the credentials were looked up
+        if let Some(challenge) = self.check_nonce(&credentials, nonce_count) {
+            return challenge;
+        }
+        if let Some(mut state) = self.nonces.get_mut(&credentials.nonce) {
+            state.nonce_count = nonce_count;
+        }
+        Authentication::Accepted
+    }
+
+    /// Challenge to answer `credentials` with unless their nonce is current
+    /// and `nonce_count` is higher than any accepted with it
+    fn check_nonce(&self, credentials: &DigestCredentials, nonce_count: u32) -> Option<Authentication> {
+        // Unknown nonces are treated as stale too, so phones registered
+        // before a restart re-authenticate without prompting their users
+        let lifetime = Duration::from_secs(self.config.nonce_lifetime_secs);
+        match self.nonces.get(&credentials.nonce) {
+            Some(state) if state.issued.elapsed() >= lifetime => Some(Authentication::Challenge { stale: true }),
+            Some(state) if nonce_count <= state.nonce_count => {
+                warn!("Replayed nonce count {} for {}", nonce_count, credentials.username);
+                Some(Authentication::Challenge { stale: false })
+            }
+            Some(_) => None,
+            None => Some(Authentication::Challenge { stale: true }),
+        }
+    }
+
+    /// Authenticate a REGISTER and update its bindings
+    pub async fn handle_register(&self, registration: &Registration) -> RegistrationReply {
+        match self.authenticate(registration).await {
+            Authentication::Accepted => {}
+            Authentication::Challenge { stale } => return self.challenge(stale),
+            Authentication::Refused(reply) => return reply,
+        }
+        let now = Instant::now();
+
+        let Some(contacts) = &registration.contacts else {
+            if registration.expires != Some(0) {
+                return RegistrationReply::new(400, "Bad Request (wildcard Contact needs Expires: 0)");
+            }
+            if self.bindings.remove(&registration.aor).is_some() {
+                info!("Removed every binding of {}", registration.aor);
+            }
+            return RegistrationReply::new(200, "OK");
+        };
+
+        // Work out every contact's expiry before touching the bindings
+        let mut updates = Vec::with_capacity(contacts.len());
+        for contact in contacts {
+            let expires = contact.expires.or(registration.expires).unwrap_or(self.config.default_expires_secs);
+            if expires != 0 && expires < self.config.min_expires_secs {
+                return RegistrationReply::new(423, "Interval Too Brief")
+                    .header("Min-Expires", self.config.min_expires_secs.to_string());
+            }
+            updates.push((contact, expires.min(self.config.max_expires_secs)));
+        }
+
+        let mut bindings = self.bindings.entry(registration.aor.clone()).or_default();
+        bindings.retain(|binding| binding.expires_at > now);
+        let out_of_order = updates.iter().any(|(contact, _)| {
+            bindings.iter().any(|binding| {
+                binding.contact == contact.uri && binding.call_id == registration.call_id && binding.cseq >= registration.cseq
+            })
+        });
+        if out_of_order {
+            return RegistrationReply::new(500, "Server Internal Error (out of order REGISTER)");
+        }
+
+        for (contact, expires) in updates {
+            bindings.retain(|binding| binding.contact != contact.uri);
+            if expires == 0 {
+                continue;
+            }
+            bindings.push(Binding {
+                aor: registration.aor.clone(),
+                contact: contact.uri.clone(),
+                q: contact.q,
+                call_id: registration.call_id.clone(),
+                cseq: registration.cseq,
+                expires_at: now + Duration::from_secs(u64::from(expires)),
+                refreshed_at: now,
+                flow: registration.flow,
+            });
+        }
+        while bindings.len() > self.config.max_contacts {
+            let oldest = bindings
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, binding)| binding.refreshed_at)
+                .map(|(index, _)| index)
+                .unwrap_or(0);
+            let replaced = bindings.remove(oldest);
+            debug!("Contact limit reached for {}, replacing {}", registration.aor, replaced.contact);
+        }
+
+        let mut reply = RegistrationReply::new(200, "OK");
+        for binding in bindings.iter() {
+            reply = reply.header("Contact", format!("<{}>;expires={}", binding.contact, binding.remaining_secs(now)));
+        }
+        debug!("{} has {} registered contact(s)", registration.aor, bindings.len());
+        let empty = bindings.is_empty();
+        drop(bindings);
+        if empty {
+            self.bindings.remove_if(&registration.aor, |_, bindings| bindings.is_empty());
+        }
+        reply
+    }
+
+    /// Unexpired contacts of `aor`, highest q-value first, then most recently refreshed
+    pub fn lookup(&self, aor: &str) -> Vec<Binding> {
+        let now = Instant::now();
+        let mut bindings: Vec<Binding> = self
+            .bindings
+            .get(aor)
+            .map(|bindings| bindings.iter().filter(|binding| binding.expires_at > now).cloned().collect())
+            .unwrap_or_default();
+        bindings.sort_by(|a, b| {
+            let (qa, qb) = (a.q.unwrap_or(1.0), b.q.unwrap_or(1.0));
+            qb.total_cmp(&qa).then(b.refreshed_at.cmp(&a.refreshed_at))
+        });
+        bindings
+    }
+
+    pub fn is_registered(&self, aor: &str) -> bool {
+        !self.lookup(aor).is_empty()
+    }
+
+    /// Drop expired bindings; returns how many were dropped
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut purged = 0;
+        self.bindings.retain(|_, bindings| {
+            let before = bindings.len();
+            bindings.retain(|binding| binding.expires_at > now);
+            purged += before - bindings.len();
+            !bindings.is_empty()
+        });
+        purged
+    }
+
+    /// Registered contacts across every address of record
+    pub fn binding_count(&self) -> usize {
+        self.bindings.iter().map(|entry| entry.value().len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registrar(max_contacts: usize) -> Registrar {
+        let config = SipRegistrarConfig {
+            enabled: true,
+            max_contacts,
+            users: vec![RegistrarUser { username: "1001".to_string(), password: Some("secret".to_string()), ha1: None }],
+            ..SipRegistrarConfig::default()
+        };
+        let credentials = Arc::new(ConfigCredentials::new(&config.users));
+        Registrar::new(config, "pbx.example.com", credentials)
+    }
+
+    fn register(contacts: &[&str], cseq: u32, authorization: Option<String>) -> Registration {
+        let mut headers = vec![
+            ("To".to_string(), "<sip:1001@pbx.example.com>".to_string()),
+            ("Call-ID".to_string(), "reg-1".to_string()),
+            ("CSeq".to_string(), format!("{} REGISTER", cseq)),
+            ("Contact".to_string(), contacts.join(", ")),
+        ];
+        if let Some(authorization) = authorization {
+            headers.push(("Authorization".to_string(), authorization));
+        }
+        Registration::from_headers(&headers).unwrap()
+    }
+
+    /// Authorization answering the challenge in `reply`
+    fn answer(reply: &RegistrationReply, username: &str, password: &str, nc: u32) -> String {
+        let challenge = parse_params(reply.headers[0].1.strip_prefix("Digest ").unwrap());
+        let mut credentials = DigestCredentials {
+            username: username.to_string(),
+            realm: challenge["realm"].clone(),
+            nonce: challenge["nonce"].clone(),
+            uri: "sip:pbx.example.com".to_string(),
+            response: String::new(),
+            algorithm: Some("MD5".to_string()),
+            opaque: Some(challenge["opaque"].clone()),
+            qop: Some("auth".to_string()),
+            nc: Some(format!("{:08x}", nc)),
+            cnonce: Some("0a4f113b".to_string()),
+        };
+        let ha1 = ha1(username, &credentials.realm, password);
+        credentials.response = digest_response(&ha1, "REGISTER", &credentials);
+        format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\", algorithm=MD5, \
+             opaque=\"{}\", qop=auth, nc={}, cnonce=\"{}\"",
+            credentials.username,
+            credentials.realm,
+            credentials.nonce,
+            credentials.uri,
+            credentials.response,
+            credentials.opaque.unwrap(),
+            credentials.nc.unwrap(),
+            credentials.cnonce.unwrap()
+        )
+    }
+
+    #[test]
+    fn test_digest_and_parsing() {
+        // RFC 2617 §3.5 example
+        let credentials = DigestCredentials::parse(
+            "Digest username=\"Mufasa\", realm=\"testrealm@host.com\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             uri=\"/dir/index.html\", qop=auth, nc=00000001, cnonce=\"0a4f113b\", \
+             response=\"6629fae49393a05397450978507c4ef1\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .unwrap();
+        assert_eq!(credentials.nc.as_deref(), Some("00000001"));
+        let ha1 = ha1("Mufasa", "testrealm@host.com", "Circle Of Life");
+        assert_eq!(digest_response(&ha1, "GET", &credentials), credentials.response);
+        assert!(DigestCredentials::parse("Basic dXNlcjpwYXNz").is_err());
+
+        let contacts = split_contacts("\"Desk, left\" <sip:1001@192.0.2.10:5062;transport=tcp>;q=0.8, sip:1001@192.0.2.11");
+        assert_eq!(contacts.len(), 2);
+        let desk = ContactEntry::parse(contacts[0]).unwrap();
+        assert_eq!((desk.uri.as_str(), desk.q), ("sip:1001@192.0.2.10:5062;transport=tcp", Some(0.8)));
+        let binding = Binding {
+            aor: "1001".to_string(),
+            contact: desk.uri,
+            q: None,
+            call_id: String::new(),
+            cseq: 1,
+            expires_at: Instant::now(),
+            refreshed_at: Instant::now(),
+            flow: None,
+        };
+        assert_eq!(binding.address(), Some("192.0.2.10:5062".parse().unwrap()));
+
+        // A WebSocket client's contact host is a placeholder; its flow is used
+        let browser = Binding {
+            contact: "sip:k4x9@d1f0a2.invalid;transport=ws".to_string(),
+            flow: Some("198.51.100.20:52000".parse().unwrap()),
+            ..binding
+        };
+        assert_eq!(browser.address(), Some("198.51.100.20:52000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_challenge_and_bindings() {
+        let registrar = registrar(2);
+        let challenge = registrar.handle_register(&register(&["<sip:1001@192.0.2.10:5062>"], 1, None)).await;
+        assert_eq!(challenge.status_code, 401);
+        assert!(challenge.headers[0].1.contains("realm=\"pbx.example.com\""));
+
+        let wrong = answer(&challenge, "1001", "guess", 1);
+        assert_eq!(registrar.handle_register(&register(&["<sip:1001@192.0.2.10>"], 2, Some(wrong))).await.status_code, 403);
+        // Answers without qop and a nonce count are challenged again
+        let legacy = answer(&challenge, "1001", "secret", 1).split(", qop=").next().unwrap().to_string();
+        let legacy = registrar.handle_register(&register(&["<sip:1001@192.0.2.10>"], 2, Some(legacy))).await;
+        assert_eq!(legacy.status_code, 401);
+
+        // The failed attempt did not use up its nonce count
+        let authorization = answer(&challenge, "1001", "secret", 1);
+        let reply = registrar
+            .handle_register(&register(&["<sip:1001@192.0.2.10:5062>;q=0.5", "<sip:1001@192.0.2.11>"], 3, Some(authorization.clone())))
+            .await;
+        assert_eq!(reply.status_code, 200);
+        assert_eq!(reply.headers.len(), 2);
+        let bindings = registrar.lookup("1001");
+        assert_eq!(bindings[0].contact, "sip:1001@192.0.2.11");
+
+        // The same nonce count again is a replay
+        let replay = registrar.handle_register(&register(&["<sip:1001@192.0.2.12>"], 4, Some(authorization))).await;
+        assert_eq!(replay.status_code, 401);
+
+        let authorization = answer(&challenge, "1001", "secret", 3);
+        let brief = registrar.handle_register(&register(&["<sip:1001@192.0.2.12>;expires=10"], 5, Some(authorization))).await;
+        assert_eq!((brief.status_code, brief.headers[0].1.as_str()), (423, "60"));
+
+        // A third contact replaces the least recently refreshed one
+        let authorization = answer(&challenge, "1001", "secret", 4);
+        registrar.handle_register(&register(&["<sip:1001@192.0.2.12>"], 6, Some(authorization))).await;
+        assert_eq!(registrar.binding_count(), 2);
+        assert!(registrar.lookup("1001").iter().all(|binding| binding.contact != "sip:1001@192.0.2.10:5062"));
+
+        let authorization = answer(&challenge, "1001", "secret", 5);
+        let out_of_order = registrar.handle_register(&register(&["<sip:1001@192.0.2.12>"], 6, Some(authorization))).await;
+        assert_eq!(out_of_order.status_code, 500);
+
+        let authorization = answer(&challenge, "1001", "secret", 6);
+        let mut unregister = register(&["*"], 7, Some(authorization));
+        unregister.expires = Some(0);
+        assert_eq!(registrar.handle_register(&unregister).await.status_code, 200);
+        assert!(!registrar.is_registered("1001"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Clock information exchanged with SIP peers
+//!
+//! The Date header (RFC 3261 §20.17) carries the gateway's idea of the time.
+//! Phones and some carriers set or check their clocks from it, so it is only
+//! sent while the gateway's own clock counts as synchronized. The Timestamp
+//! header (§20.38) lets the sender of a request estimate the round trip: the
+//! response echoes the request's value with the time the request was held
+//! before answering (§8.2.6.1), which the sender subtracts from the elapsed
+//! time.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::TimeSyncConfig;
+use crate::utils::TimeHealth;
+
+pub const DATE: &str = "Date";
+pub const TIMESTAMP: &str = "Timestamp";
+
+/// RFC 1123 date in GMT, the only form SIP allows
+pub fn format_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// A Timestamp header value
+#[derive(Debug, Clone, PartialEq)]
+pub struct SipTimestamp {
+    /// Sender's value, kept verbatim for echoing
+    pub value: String,
+    /// Seconds the responder held the request, on an echoed timestamp
+    pub delay: Option<f64>,
+}
+
+impl SipTimestamp {
+    /// Timestamp for a request sent now, in seconds since the epoch
+    pub fn now() -> Self {
+        Self {
+            value: format!("{:.3}", Utc::now().timestamp_millis() as f64 / 1000.0),
+            delay: None,
+        }
+    }
+
+    /// Parse `value [delay]`, both decimal numbers of seconds
+    pub fn parse(header: &str) -> Option<Self> {
+        let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.') && s.parse::<f64>().is_ok();
+        let mut parts = header.split_whitespace();
+        let value = parts.next().filter(|value| is_number(value))?;
+        let delay = match parts.next() {
+            Some(delay) if is_number(delay) => Some(delay.parse().ok()?),
+            Some(_) => return None,
+            None => None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self { value: value.to_string(), delay })
+    }
+
+    /// The Timestamp header among `headers`, if present and well formed
+    pub fn from_headers(headers: &[(String, String)]) -> Option<Self> {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(TIMESTAMP))
+            .and_then(|(_, value)| Self::parse(value))
+    }
+
+    /// Echo for a response to a request held for `held` before answering
+    pub fn echo(&self, held: Duration) -> Self {
+        Self {
+            value: self.value.clone(),
+            delay: Some(held.as_secs_f64()),
+        }
+    }
+
+    pub fn header_value(&self) -> String {
+        match self.delay {
+            Some(delay) => format!("{} {:.3}", self.value, delay),
+            None => self.value.clone(),
+        }
+    }
+
+    /// Network round trip of a request sent at `sent` whose response,
+    /// received at `received`, echoed this timestamp
+    pub fn round_trip(&self, sent: Instant, received: Instant) -> Duration {
+        let held = Duration::try_from_secs_f64(self.delay.unwrap_or(0.0)).unwrap_or_default();
+        received.saturating_duration_since(sent).saturating_sub(held)
+    }
+}
+
+/// Date and Timestamp headers the gateway adds to its messages
+#[derive(Debug, Clone)]
+pub struct SipTimeHeaders {
+    date: bool,
+    timestamp: bool,
+    health: Arc<TimeHealth>,
+}
+
+impl SipTimeHeaders {
+    pub fn new(config: &TimeSyncConfig, health: Arc<TimeHealth>) -> Self {
+        Self {
+            date: config.sip_date_header,
+            timestamp: config.sip_timestamp,
+            health,
+        }
+    }
+
+    fn date_header(&self, now: Instant) -> Option<(String, String)> {
+        (self.date && self.health.is_synchronized(now)).then(|| (DATE.to_string(), format_date(Utc::now())))
+    }
+
+    /// Headers for a request sent now
+    pub fn request_headers(&self) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = self.date_header(Instant::now()).into_iter().collect();
+        if self.timestamp {
+            headers.push((TIMESTAMP.to_string(), SipTimestamp::now().header_value()));
+        }
+        headers
+    }
+
+    /// Headers for a response sent now to a request received at `received`
+    pub fn response_headers(&self, request_timestamp: Option<&SipTimestamp>, received: Instant) -> Vec<(String, String)> {
+        let now = Instant::now();
+        let mut headers: Vec<(String, String)> = self.date_header(now).into_iter().collect();
+        if let Some(timestamp) = request_timestamp.filter(|_| self.timestamp) {
+            let echo = timestamp.echo(now.saturating_duration_since(received));
+            headers.push((TIMESTAMP.to_string(), echo.header_value()));
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_timestamp_echo_and_round_trip() {
+        let request = SipTimestamp::parse("54.21").unwrap();
+        assert_eq!(request.delay, None);
+        assert_eq!(request.echo(Duration::from_millis(250)).header_value(), "54.21 0.250");
+
+        let echoed = SipTimestamp::parse(" 54.21  0.25 ").unwrap();
+        let sent = Instant::now();
+        assert_eq!(echoed.round_trip(sent, sent + Duration::from_millis(400)), Duration::from_millis(150));
+        // A responder claiming to have held it longer than the round trip
+        assert_eq!(echoed.round_trip(sent, sent + Duration::from_millis(100)), Duration::ZERO);
+
+        assert!(SipTimestamp::parse("").is_none());
+        assert!(SipTimestamp::parse("inf").is_none());
+        assert!(SipTimestamp::parse("1 2 3").is_none());
+        let headers = vec![("timestamp".to_string(), "12 0.5".to_string())];
+        assert_eq!(SipTimestamp::from_headers(&headers).unwrap().delay, Some(0.5));
+    }
+
+    #[test]
+    fn test_date_header_only_while_synchronized() {
+        assert_eq!(format_date(Utc.with_ymd_and_hms(2010, 11, 13, 23, 29, 0).unwrap()), "Sat, 13 Nov 2010 23:29:00 GMT");
+
+        let health = Arc::new(TimeHealth::new(Duration::from_secs(60)));
+        let headers = SipTimeHeaders::new(&TimeSyncConfig::default(), Arc::clone(&health));
+        let names = |headers: Vec<(String, String)>| headers.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names(headers.request_headers()), vec![DATE, TIMESTAMP]);
+
+        health.enter_holdover(Instant::now() - Duration::from_secs(120));
+        assert_eq!(names(headers.request_headers()), vec![TIMESTAMP]);
+        let request = SipTimestamp::parse("1.5").unwrap();
+        let response = headers.response_headers(Some(&request), Instant::now());
+        assert_eq!(response.len(), 1);
+        assert!(response[0].1.starts_with("1.5 0.0"));
+        assert!(headers.response_headers(None, Instant::now()).is_empty());
+    }
+}
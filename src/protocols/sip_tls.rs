@@ -0,0 +1,598 @@
+//! SIP over TLS listener for sips: trunks
+//!
+//! Carriers that require encryption send their traffic over TLS, normally to
+//! port 5061 (RFC 3261 §26.2). The certificate presented is picked by the
+//! SNI name in the ClientHello, with the default certificate for clients
+//! that send none or ask for a name that is not configured. Only the
+//! configured cipher suites are offered, in the configured order of
+//! preference. Certificates listed with the SIP TLS usage in the
+//! certificate manager are swapped in for new connections when it reloads
+//! them; files that fail to load leave the previous certificate in
+//! service. Messages are framed on the stream by
+//! their Content-Length (RFC 3261 §18.3). Open connections, and the TCP
+//! connections the gateway opens itself, are kept by peer address so
+//! responses and in-dialog requests go back on the connection in use.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::SupportedCipherSuite;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::config::{ManagedCertificate, SipTlsConfig};
+use crate::protocols::sip_transport::Transport;
+use crate::services::certificates::{pem_blocks, CertificateConsumer, LoadedCertificate};
+use crate::utils::ClockStamp;
+use crate::{Error, Result};
+
+/// How long a client has to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// PEM labels tried, in order, for the private key
+const KEY_LABELS: [&str; 3] = ["PRIVATE KEY", "RSA PRIVATE KEY", "EC PRIVATE KEY"];
+
+/// IANA name of a cipher suite; the TLS library prefixes TLS 1.3 suites
+/// with `TLS13_`
+fn iana_name(suite: SupportedCipherSuite) -> String {
+    let name = format!("{:?}", suite.suite());
+    match name.strip_prefix("TLS13_") {
+        Some(rest) => format!("TLS_{}", rest),
+        None => name,
+    }
+}
+
+/// Cipher suites named in the configuration, in order; the library
+/// defaults when none are named
+pub fn cipher_suites(names: &[String]) -> Result<Vec<SupportedCipherSuite>> {
+    if names.is_empty() {
+        return Ok(rustls::DEFAULT_CIPHER_SUITES.to_vec());
+    }
+    names
+        .iter()
+        .map(|name| {
+            rustls::ALL_CIPHER_SUITES
+                .iter()
+                .copied()
+                .find(|suite| iana_name(*suite).eq_ignore_ascii_case(name))
+                .ok_or_else(|| Error::invalid_config(format!("Unknown TLS cipher suite: {}", name)))
+        })
+        .collect()
+}
+
+/// Whether an SNI `name` is served by a certificate configured for
+/// `pattern`; `*.` matches exactly one label
+fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => name.split_once('.').is_some_and(|(_, rest)| rest.eq_ignore_ascii_case(domain)),
+        None => pattern.eq_ignore_ascii_case(name),
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| Error::invalid_config(format!("Cannot read {}: {}", path.display(), e)))
+}
+
+fn certified_key(cert_path: &Path, cert_pem: &str, key_path: &Path, key_pem: &str) -> Result<CertifiedKey> {
+    let chain: Vec<rustls::Certificate> = pem_blocks(cert_pem, "CERTIFICATE")?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    if chain.is_empty() {
+        return Err(Error::parse(format!("No certificate found in {}", cert_path.display())));
+    }
+    let mut key = None;
+    for label in KEY_LABELS {
+        key = pem_blocks(key_pem, label)?.into_iter().next();
+        if key.is_some() {
+            break;
+        }
+    }
+    let key = key.ok_or_else(|| Error::parse(format!("No private key found in {}", key_path.display())))?;
+    let signing_key = rustls::sign::any_supported_type(&rustls::PrivateKey(key))
+        .map_err(|_| Error::parse(format!("Unsupported private key in {}", key_path.display())))?;
+    Ok(CertifiedKey::new(chain, signing_key))
+}
+
+/// Certificate files for one SNI name, or the default, and the key last
+/// loaded from them
+struct ServedCertificate {
+    /// `None` for the default certificate
+    server_name: Option<String>,
+    cert_path: PathBuf,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ServedCertificate {
+    fn load(server_name: Option<String>, cert_path: PathBuf, key_path: PathBuf) -> Result<Self> {
+        let cert_pem = read_file(&cert_path)?;
+        let key_pem = read_file(&key_path)?;
+        let key = Arc::new(certified_key(&cert_path, &cert_pem, &key_path, &key_pem)?);
+        Ok(Self {
+            server_name,
+            cert_path,
+            key: RwLock::new(key),
+        })
+    }
+
+    fn current(&self) -> Arc<CertifiedKey> {
+        Arc::clone(&self.key.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn replace(&self, key: Arc<CertifiedKey>) {
+        *self.key.write().unwrap_or_else(|e| e.into_inner()) = key;
+    }
+}
+
+/// Picks the certificate for a ClientHello by its SNI name
+struct CertificateResolver {
+    /// The default certificate first, then one per SNI name
+    certificates: Vec<ServedCertificate>,
+}
+
+impl CertificateResolver {
+    fn load(config: &SipTlsConfig) -> Result<Self> {
+        let mut certificates = vec![ServedCertificate::load(None, config.cert_path.clone(), config.key_path.clone())?];
+        for sni in &config.sni {
+            certificates.push(ServedCertificate::load(
+                Some(sni.server_name.clone()),
+                sni.cert_path.clone(),
+                sni.key_path.clone(),
+            )?);
+        }
+        Ok(Self { certificates })
+    }
+
+    fn select(&self, server_name: Option<&str>) -> &ServedCertificate {
+        server_name
+            .and_then(|name| {
+                self.certificates[1..]
+                    .iter()
+                    .find(|certificate| certificate.server_name.as_deref().is_some_and(|pattern| name_matches(pattern, name)))
+            })
+            .unwrap_or(&self.certificates[0])
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.select(client_hello.server_name()).current())
+    }
+}
+
+/// One unit read off a SIP stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamFrame {
+    /// Double CRLF keep-alive ping (RFC 5626 §3.5.1), answered with one CRLF
+    KeepAlive,
+    Message(Vec<u8>),
+}
+
+/// Read the next message or keep-alive off a stream; `None` once the peer
+/// closes the connection between messages. Errors leave the framing lost,
+/// so the connection has to be closed.
+pub async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R, max_message_bytes: usize) -> Result<Option<StreamFrame>> {
+    let too_large = || Error::protocol(format!("SIP message over {} bytes", max_message_bytes));
+    let mut message = Vec::new();
+    let mut blank_lines = 0;
+    let mut content_length = None;
+
+    loop {
+        let mut line = Vec::new();
+        let limit = (max_message_bytes + 1 - message.len()) as u64;
+        if (&mut *reader).take(limit).read_until(b'\n', &mut line).await? == 0 {
+            if message.is_empty() {
+                return Ok(None);
+            }
+            return Err(Error::network("Connection closed in the middle of a SIP message"));
+        }
+        if message.len() + line.len() > max_message_bytes {
+            return Err(too_large());
+        }
+        if line == b"\r\n" || line == b"\n" {
+            if message.is_empty() {
+                blank_lines += 1;
+                if blank_lines == 2 {
+                    return Ok(Some(StreamFrame::KeepAlive));
+                }
+                continue;
+            }
+            message.extend_from_slice(&line);
+            break;
+        }
+        blank_lines = 0;
+        if !message.is_empty() && content_length.is_none() {
+            let text = String::from_utf8_lossy(&line);
+            if let Some((name, value)) = text.split_once(':') {
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("l") {
+                    content_length = Some(
+                        value
+                            .trim()
+                            .parse::<usize>()
+                            .map_err(|_| Error::parse(format!("Invalid Content-Length: {}", value.trim())))?,
+                    );
+                }
+            }
+        }
+        message.extend_from_slice(&line);
+    }
+
+    // Stream transports must carry Content-Length (RFC 3261 §18.3)
+    let length = content_length.ok_or_else(|| Error::protocol("SIP message over a stream has no Content-Length"))?;
+    if message.len() + length > max_message_bytes {
+        return Err(too_large());
+    }
+    let headers_end = message.len();
+    message.resize(headers_end + length, 0);
+    reader.read_exact(&mut message[headers_end..]).await?;
+    Ok(Some(StreamFrame::Message(message)))
+}
+
+/// Message received on a TLS or TCP connection
+#[derive(Debug, Clone)]
+pub struct StreamMessage {
+    pub peer: SocketAddr,
+    pub transport: Transport,
+    /// SNI name a TLS client asked for
+    pub server_name: Option<String>,
+    pub data: Vec<u8>,
+    /// When the message was read off the connection
+    pub received: ClockStamp,
+}
+
+/// Open TLS and TCP connections by peer address
+#[derive(Debug, Clone, Default)]
+pub struct StreamConnections {
+    senders: Arc<DashMap<SocketAddr, (Transport, mpsc::UnboundedSender<Vec<u8>>)>>,
+}
+
+impl StreamConnections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the outbound channel of a connection with `peer`
+    pub(crate) fn insert(&self, peer: SocketAddr, transport: Transport, sender: mpsc::UnboundedSender<Vec<u8>>) {
+        self.senders.insert(peer, (transport, sender));
+    }
+
+    /// Send a SIP message on the connection with `peer`
+    pub fn send(&self, peer: SocketAddr, data: Vec<u8>) -> Result<()> {
+        let sender = self
+            .senders
+            .get(&peer)
+            .ok_or_else(|| Error::network(format!("No SIP connection with {}", peer)))?;
+        sender
+            .1
+            .send(data)
+            .map_err(|_| Error::network(format!("SIP connection with {} is closing", peer)))
+    }
+
+    /// Transport of the open connection with `peer`
+    pub fn transport(&self, peer: SocketAddr) -> Option<Transport> {
+        self.senders.get(&peer).map(|entry| entry.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.senders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    /// Read messages off `stream` until it closes, passing them to
+    /// `message_tx`, while messages sent to `peer` are written to it. The
+    /// connection can be sent on as soon as this returns, before the
+    /// future is first polled.
+    pub fn serve<S: AsyncRead + AsyncWrite + Send + 'static>(
+        &self,
+        stream: S,
+        peer: SocketAddr,
+        transport: Transport,
+        server_name: Option<String>,
+        max_message_bytes: usize,
+        message_tx: mpsc::UnboundedSender<StreamMessage>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        self.insert(peer, transport, outbound_tx.clone());
+        // Ends once the connection is dropped from the table and the reader is done
+        tokio::spawn(async move {
+            while let Some(data) = outbound_rx.recv().await {
+                if let Err(e) = async {
+                    writer.write_all(&data).await?;
+                    writer.flush().await
+                }
+                .await
+                {
+                    debug!("SIP {} write to {} failed: {}", transport.via_name(), peer, e);
+                    break;
+                }
+            }
+        });
+
+        let senders = Arc::clone(&self.senders);
+        async move {
+            let mut reader = BufReader::new(reader);
+            let result = async {
+                while let Some(frame) = read_frame(&mut reader, max_message_bytes).await? {
+                    match frame {
+                        StreamFrame::KeepAlive => {
+                            let _ = outbound_tx.send(b"\r\n".to_vec());
+                        }
+                        StreamFrame::Message(data) => {
+                            let received = ClockStamp::now();
+                            let server_name = server_name.clone();
+                            let message = StreamMessage { peer, transport, server_name, data, received };
+                            if message_tx.send(message).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+            .await;
+            senders.remove_if(&peer, |_, (_, sender)| sender.same_channel(&outbound_tx));
+            result
+        }
+    }
+}
+
+/// TLS acceptor with reloadable, SNI-selected certificates
+pub struct SipTlsListener {
+    certificates: Arc<CertificateResolver>,
+    acceptor: TlsAcceptor,
+    connections: StreamConnections,
+}
+
+impl SipTlsListener {
+    /// Load the certificates and set up the cipher suites; accepted
+    /// connections are kept in `connections`
+    pub fn new(config: &SipTlsConfig, connections: StreamConnections) -> Result<Self> {
+        let certificates = Arc::new(CertificateResolver::load(config)?);
+        let mut server_config = rustls::ServerConfig::builder()
+            .with_cipher_suites(&cipher_suites(&config.cipher_suites)?)
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::invalid_config(format!("SIP TLS configuration rejected: {}", e)))?
+            .with_no_client_auth()
+            .with_cert_resolver(certificates.clone());
+        server_config.ignore_client_order = true;
+
+        Ok(Self {
+            certificates,
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            connections,
+        })
+    }
+
+    /// Acceptor presenting the listener's certificates, shared with the
+    /// secure WebSocket port
+    pub fn acceptor(&self) -> &TlsAcceptor {
+        &self.acceptor
+    }
+
+    /// Accept connections on `socket` until the task is aborted, passing
+    /// received messages to `message_tx`
+    pub fn spawn(
+        self: Arc<Self>,
+        socket: TcpListener,
+        max_message_bytes: usize,
+        message_tx: mpsc::UnboundedSender<StreamMessage>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match socket.accept().await {
+                    Ok((stream, peer)) => {
+                        let listener = Arc::clone(&self);
+                        let message_tx = message_tx.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = listener.serve(stream, peer, max_message_bytes, message_tx).await {
+                                debug!("SIP TLS connection from {} closed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("SIP TLS accept failed: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        })
+    }
+
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        &self,
+        stream: S,
+        peer: SocketAddr,
+        max_message_bytes: usize,
+        message_tx: mpsc::UnboundedSender<StreamMessage>,
+    ) -> Result<()> {
+        let stream = timeout(HANDSHAKE_TIMEOUT, self.acceptor.accept(stream))
+            .await
+            .map_err(|_| Error::timeout(format!("TLS handshake with {} timed out", peer)))??;
+        let server_name = stream.get_ref().1.server_name().map(str::to_string);
+        debug!("SIP TLS connection from {} for {}", peer, server_name.as_deref().unwrap_or("no SNI name"));
+
+        self.connections
+            .serve(stream, peer, Transport::Tls, server_name, max_message_bytes, message_tx)
+            .await
+    }
+}
+
+impl CertificateConsumer for SipTlsListener {
+    /// Serve the certificate for new connections wherever its file is
+    /// configured, for the default or for an SNI name
+    fn install(&self, managed: &ManagedCertificate, certificate: &LoadedCertificate) -> Result<()> {
+        let cert_path = Path::new(&managed.cert_path);
+        let served: Vec<&ServedCertificate> = self.certificates.certificates
+            .iter()
+            .filter(|served| served.cert_path == cert_path)
+            .collect();
+        if served.is_empty() {
+            warn!("Certificate {} is not configured on the SIP TLS listener", managed.id);
+            return Ok(());
+        }
+
+        let key_path = Path::new(&managed.key_path);
+        let key = Arc::new(certified_key(cert_path, &certificate.cert_pem, key_path, &certificate.key_pem)?);
+        for served in served {
+            served.replace(Arc::clone(&key));
+        }
+        info!("Reloaded SIP TLS certificate {}", cert_path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CertificateConfig, CertificateUsage, SipTlsSniCertificate};
+    use crate::services::certificates::CertificateManager;
+    use tokio::io::DuplexStream;
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::TlsConnector;
+
+    /// Self-signed certificate for `name`; returns the paths and the DER
+    fn write_certificate(dir: &Path, name: &str) -> (PathBuf, PathBuf, Vec<u8>) {
+        let certificate = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_pem = certificate.serialize_pem().unwrap();
+        let cert_path = dir.join(format!("{}.crt", name));
+        let key_path = dir.join(format!("{}.key", name));
+        std::fs::write(&cert_path, &cert_pem).unwrap();
+        std::fs::write(&key_path, certificate.serialize_private_key_pem()).unwrap();
+        let der = pem_blocks(&cert_pem, "CERTIFICATE").unwrap().remove(0);
+        (cert_path, key_path, der)
+    }
+
+    async fn connect(
+        listener: &Arc<SipTlsListener>,
+        server_name: &str,
+        trusted: &[&Vec<u8>],
+    ) -> (TlsStream<DuplexStream>, mpsc::UnboundedReceiver<StreamMessage>) {
+        let mut roots = rustls::RootCertStore::empty();
+        for der in trusted {
+            roots.add(&rustls::Certificate(der.to_vec())).unwrap();
+        }
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let (message_tx, message_rx) = mpsc::unbounded_channel();
+        let listener = Arc::clone(listener);
+        tokio::spawn(async move { listener.serve(server, "192.0.2.10:40000".parse().unwrap(), 4096, message_tx).await });
+
+        let name = rustls::ServerName::try_from(server_name).unwrap();
+        let stream = TlsConnector::from(Arc::new(client_config)).connect(name, client).await.unwrap();
+        (stream, message_rx)
+    }
+
+    fn presented(stream: &TlsStream<DuplexStream>) -> Vec<u8> {
+        stream.get_ref().1.peer_certificates().unwrap()[0].0.clone()
+    }
+
+    #[tokio::test]
+    async fn test_read_frame() {
+        let data: &[u8] = b"\r\n\r\nOPTIONS sip:gw SIP/2.0\r\nl: 4\r\n\r\nbodyBYE sip:gw SIP/2.0\r\n\r\n";
+        let mut reader = BufReader::new(data);
+        assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), Some(StreamFrame::KeepAlive));
+        match read_frame(&mut reader, 1024).await.unwrap() {
+            Some(StreamFrame::Message(message)) => assert!(message.ends_with(b"\r\n\r\nbody")),
+            other => panic!("unexpected frame {:?}", other),
+        }
+        // Content-Length is mandatory on streams
+        assert!(read_frame(&mut reader, 1024).await.is_err());
+        assert_eq!(read_frame(&mut BufReader::new(&b""[..]), 1024).await.unwrap(), None);
+
+        let oversized: &[u8] = b"INVITE sip:gw SIP/2.0\r\nContent-Length: 100\r\n\r\n";
+        assert!(read_frame(&mut BufReader::new(oversized), 64).await.is_err());
+        assert!(cipher_suites(&["TLS_AES_256_GCM_SHA384".to_string()]).is_ok());
+        assert!(cipher_suites(&["TLS_RSA_WITH_RC4_128_MD5".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sni_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path, default_der) = write_certificate(dir.path(), "gw.example.com");
+        let (carrier_cert, carrier_key, carrier_der) = write_certificate(dir.path(), "sip.carrier.example");
+        let config = SipTlsConfig {
+            enabled: true,
+            cert_path: cert_path.clone(),
+            key_path: key_path.clone(),
+            sni: vec![SipTlsSniCertificate {
+                server_name: "*.carrier.example".to_string(),
+                cert_path: carrier_cert,
+                key_path: carrier_key,
+            }],
+            ..SipTlsConfig::default()
+        };
+        let listener = Arc::new(SipTlsListener::new(&config, StreamConnections::new()).unwrap());
+
+        let (mut stream, mut message_rx) =
+            connect(&listener, "sip.carrier.example", &[&default_der, &carrier_der]).await;
+        assert_eq!(presented(&stream), carrier_der);
+        let options = b"OPTIONS sip:gw.example.com SIP/2.0\r\nContent-Length: 0\r\n\r\n";
+        stream.write_all(options).await.unwrap();
+        stream.write_all(b"\r\n\r\n").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut pong = [0u8; 2];
+        stream.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"\r\n");
+        let message = message_rx.recv().await.unwrap();
+        assert_eq!(message.server_name.as_deref(), Some("sip.carrier.example"));
+        assert_eq!((message.transport, message.data.as_slice()), (Transport::Tls, options.as_slice()));
+
+        // Responses go back on the connection the request came in on
+        let peer: SocketAddr = "192.0.2.10:40000".parse().unwrap();
+        assert_eq!(listener.connections.transport(peer), Some(Transport::Tls));
+        listener.connections.send(peer, b"SIP/2.0 200 OK\r\n\r\n".to_vec()).unwrap();
+        let mut response = [0u8; 18];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"SIP/2.0 200 OK\r\n\r\n");
+
+        let (stream, _) = connect(&listener, "gw.example.com", &[&default_der, &carrier_der]).await;
+        assert_eq!(presented(&stream), default_der);
+
+        // A certificate replaced on disk is served to new connections once
+        // the certificate manager reloads it
+        let certificates = CertificateManager::new(CertificateConfig {
+            enabled: true,
+            certificates: vec![ManagedCertificate {
+                id: "sip".to_string(),
+                usage: CertificateUsage::SipTls,
+                cert_path: cert_path.display().to_string(),
+                key_path: key_path.display().to_string(),
+                domains: vec![],
+                auto_renew: false,
+            }],
+            ..CertificateConfig::default()
+        });
+        certificates.set_consumer(CertificateUsage::SipTls, listener.clone());
+        let (_, _, renewed_der) = write_certificate(dir.path(), "gw.example.com");
+        certificates.reload("sip").await.unwrap();
+        let (stream, _) = connect(&listener, "gw.example.com", &[&renewed_der]).await;
+        assert_eq!(presented(&stream), renewed_der);
+
+        // A broken key leaves the previous certificate in service
+        std::fs::write(&key_path, "not a key").unwrap();
+        assert!(certificates.reload("sip").await.is_err());
+        let (stream, _) = connect(&listener, "gw.example.com", &[&renewed_der]).await;
+        assert_eq!(presented(&stream), renewed_der);
+    }
+}
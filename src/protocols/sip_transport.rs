@@ -0,0 +1,209 @@
+//! Transport selection for outgoing SIP requests
+//!
+//! RFC 3261 §18.1.1 requires a request larger than 1300 bytes (when the path
+//! MTU is unknown) to go over a congestion-controlled transport, since large
+//! UDP datagrams get fragmented and many carriers drop the fragments. When
+//! UDP is the configured transport, oversized requests are switched to TCP
+//! for that transaction, and sent over UDP anyway if the TCP connection
+//! cannot be set up. Switchovers and fallbacks are counted per trunk.
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{SipConfig, SipTransport};
+
+/// Via, From, To, Call-ID, CSeq, Contact and Max-Forwards of a typical request
+const BASE_HEADERS_SIZE: usize = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Udp,
+    Tcp,
+    Tls,
+    /// SIP over WebSocket (RFC 7118)
+    Ws,
+    Wss,
+}
+
+impl Transport {
+    /// Name of the transport in a Via sent-protocol
+    pub fn via_name(&self) -> &'static str {
+        match self {
+            Transport::Udp => "UDP",
+            Transport::Tcp => "TCP",
+            Transport::Tls => "TLS",
+            Transport::Ws => "WS",
+            Transport::Wss => "WSS",
+        }
+    }
+
+    pub fn is_websocket(&self) -> bool {
+        matches!(self, Transport::Ws | Transport::Wss)
+    }
+}
+
+impl From<&SipTransport> for Transport {
+    fn from(transport: &SipTransport) -> Self {
+        match transport {
+            SipTransport::Udp => Transport::Udp,
+            SipTransport::Tcp => Transport::Tcp,
+            SipTransport::Tls => Transport::Tls,
+        }
+    }
+}
+
+/// Transport counters for one trunk
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransportStats {
+    pub trunk: String,
+    pub requests: u64,
+    /// Requests moved from UDP to TCP because of their size
+    pub switched_to_tcp: u64,
+    /// Switched requests sent over UDP after the TCP connection failed
+    pub tcp_fallbacks: u64,
+}
+
+/// Rough size of a request, good enough to compare against the UDP threshold
+pub fn estimate_request_size(uri: &str, sdp: Option<&str>, headers: &[(String, String)]) -> usize {
+    let extra: usize = headers.iter().map(|(name, value)| name.len() + value.len() + 4).sum();
+    BASE_HEADERS_SIZE + 2 * uri.len() + extra + sdp.map_or(0, str::len)
+}
+
+/// Host part of a SIP URI, used as the trunk name in statistics
+pub fn uri_host(uri: &str) -> &str {
+    let rest = uri.split_once('@').map_or_else(|| uri.trim_start_matches("sip:"), |(_, host)| host);
+    rest.split([';', ':', '>']).next().unwrap_or(rest)
+}
+
+/// Transport named by a `transport=` URI parameter
+fn uri_transport(uri: &str) -> Option<Transport> {
+    let value = uri.split(';').skip(1).find_map(|param| param.strip_prefix("transport="))?;
+    match value.to_ascii_lowercase().as_str() {
+        "udp" => Some(Transport::Udp),
+        "tcp" => Some(Transport::Tcp),
+        "tls" => Some(Transport::Tls),
+        "ws" => Some(Transport::Ws),
+        "wss" => Some(Transport::Wss),
+        _ => None,
+    }
+}
+
+/// Chooses the transport per request and keeps per-trunk counters
+pub struct TransportSelector {
+    configured: Transport,
+    threshold: usize,
+    tcp_fallback: bool,
+    stats: DashMap<String, TransportStats>,
+}
+
+impl TransportSelector {
+    pub fn new(config: &SipConfig) -> Self {
+        Self {
+            configured: Transport::from(&config.transport),
+            threshold: config.udp_size_threshold,
+            tcp_fallback: config.tcp_fallback,
+            stats: DashMap::new(),
+        }
+    }
+
+    pub fn configured(&self) -> Transport {
+        self.configured
+    }
+
+    pub fn tcp_fallback(&self) -> bool {
+        self.tcp_fallback
+    }
+
+    /// Transport for a request of `size` bytes to `uri`; an explicit
+    /// `transport=` parameter on the URI wins
+    pub fn select(&self, uri: &str, size: usize) -> Transport {
+        if let Some(transport) = uri_transport(uri) {
+            return transport;
+        }
+        match self.configured {
+            Transport::Udp if size > self.threshold => Transport::Tcp,
+            configured => configured,
+        }
+    }
+
+    /// Count a request to `trunk` for which `selected` was chosen and `used` carried it
+    pub fn record(&self, trunk: &str, selected: Transport, used: Transport) {
+        let mut stats = self.stats.entry(trunk.to_string()).or_insert_with(|| TransportStats {
+            trunk: trunk.to_string(),
+            ..Default::default()
+        });
+        stats.requests += 1;
+        if self.configured == Transport::Udp && selected == Transport::Tcp {
+            stats.switched_to_tcp += 1;
+            if used == Transport::Udp {
+                stats.tcp_fallbacks += 1;
+            }
+        }
+    }
+
+    /// Counters ordered by trunk
+    pub fn stats(&self) -> Vec<TransportStats> {
+        let mut stats: Vec<TransportStats> = self.stats.iter().map(|entry| entry.value().clone()).collect();
+        stats.sort_by(|a, b| a.trunk.cmp(&b.trunk));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(transport: SipTransport) -> SipConfig {
+        SipConfig {
+            listen_port: 5060,
+            domain: "gw.example.com".to_string(),
+            transport,
+            max_sessions: 100,
+            session_timeout: 300,
+            register_interval: 3600,
+            udp_size_threshold: 1300,
+            tcp_fallback: true,
+            force_rport: Vec::new(),
+            size_limits: Default::default(),
+            tls: Default::default(),
+            websocket: Default::default(),
+            registrar: Default::default(),
+            network: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_large_udp_requests_switch_to_tcp() {
+        let selector = TransportSelector::new(&config(SipTransport::Udp));
+        let uri = "sip:5551234@sbc.carrier.net:5060";
+        assert_eq!(selector.select(uri, 900), Transport::Udp);
+        assert_eq!(selector.select(uri, 1301), Transport::Tcp);
+        assert_eq!(selector.select("sip:5551234@sbc.carrier.net;transport=tcp", 900), Transport::Tcp);
+        assert_eq!(selector.select("sip:5551234@sbc.carrier.net;transport=udp", 1500), Transport::Udp);
+        let browser = selector.select("sip:alice@df7jal23ls0d.invalid;transport=wss", 4000);
+        assert_eq!((browser, browser.via_name()), (Transport::Wss, "WSS"));
+
+        let tls = TransportSelector::new(&config(SipTransport::Tls));
+        assert_eq!(tls.select(uri, 4000), Transport::Tls);
+
+        let sdp = "a".repeat(1000);
+        assert!(estimate_request_size(uri, Some(&sdp), &[]) > 1300);
+        assert_eq!(uri_host(uri), "sbc.carrier.net");
+        assert_eq!(uri_host("sip:sbc.carrier.net;user=phone"), "sbc.carrier.net");
+    }
+
+    #[test]
+    fn test_switchovers_and_fallbacks_counted_per_trunk() {
+        let selector = TransportSelector::new(&config(SipTransport::Udp));
+        selector.record("a.carrier.net", Transport::Udp, Transport::Udp);
+        selector.record("a.carrier.net", Transport::Tcp, Transport::Tcp);
+        selector.record("a.carrier.net", Transport::Tcp, Transport::Udp);
+        selector.record("b.carrier.net", Transport::Udp, Transport::Udp);
+
+        assert_eq!(selector.stats(), vec![
+            TransportStats { trunk: "a.carrier.net".to_string(), requests: 3, switched_to_tcp: 2, tcp_fallbacks: 1 },
+            TransportStats { trunk: "b.carrier.net".to_string(), requests: 1, switched_to_tcp: 0, tcp_fallbacks: 0 },
+        ]);
+    }
+}
@@ -0,0 +1,354 @@
+//! Via processing and symmetric response routing
+//!
+//! A request's Via headers record the path its responses must retrace. The
+//! top Via names where the sender expects the response, but a PBX behind NAT
+//! writes its private address there. RFC 3261 §18.2.1 has the server note
+//! the packet's real source address in a `received` parameter, and RFC 3581
+//! lets the client ask, with an empty `rport`, for the source port as well
+//! and for the response to go back to that address and port. Some PBXes
+//! behind NAT never ask, so responses to configured peers are routed
+//! symmetrically regardless. Requests whose Vias are malformed or lack a
+//! branch are refused rather than answered to a guessed address.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::config::SipConfig;
+use crate::{Error, Result};
+
+pub const VIA: &str = "Via";
+/// Compact form of the Via header name (RFC 3261 §7.3.3)
+pub const VIA_COMPACT: &str = "v";
+
+const DEFAULT_PORT: u16 = 5060;
+const DEFAULT_TLS_PORT: u16 = 5061;
+/// WebSocket clients default to the HTTP ports (RFC 7118 §5.1)
+const DEFAULT_WS_PORT: u16 = 80;
+const DEFAULT_WSS_PORT: u16 = 443;
+
+fn is_via(name: &str) -> bool {
+    name.eq_ignore_ascii_case(VIA) || name.eq_ignore_ascii_case(VIA_COMPACT)
+}
+
+/// One Via entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Via {
+    /// Transport of `SIP/2.0/<transport>`, upper case
+    pub transport: String,
+    pub host: String,
+    pub port: Option<u16>,
+    /// Parameters in order; `None` for a flag such as an empty `rport`
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl Via {
+    /// Parse `SIP/2.0/UDP host[:port];params`
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::parse(format!("Invalid Via {:?}: {}", value, reason));
+        let value = value.trim();
+        let (protocol, rest) = value
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .ok_or_else(|| invalid("missing sent-by"))?;
+        let mut protocol_parts = protocol.split('/').map(str::trim);
+        let (Some(name), Some(version), Some(transport), None) =
+            (protocol_parts.next(), protocol_parts.next(), protocol_parts.next(), protocol_parts.next())
+        else {
+            return Err(invalid("sent-protocol is not SIP/2.0/<transport>"));
+        };
+        if !name.eq_ignore_ascii_case("SIP") || version != "2.0" || transport.is_empty() {
+            return Err(invalid("sent-protocol is not SIP/2.0/<transport>"));
+        }
+
+        let mut parts = rest.split(';');
+        let sent_by = parts.next().unwrap_or_default().trim();
+        let (host, port) = if let Some(v6) = sent_by.strip_prefix('[') {
+            let (host, after) = v6.split_once(']').ok_or_else(|| invalid("unterminated IPv6 reference"))?;
+            host.parse::<std::net::Ipv6Addr>().map_err(|_| invalid("bad IPv6 address"))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match sent_by.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (sent_by, None),
+            }
+        };
+        let host_ok = !host.is_empty()
+            && (host.contains(':') || host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-'));
+        if !host_ok {
+            return Err(invalid("bad host"));
+        }
+        let port = port
+            .map(|port| port.trim().parse::<u16>().ok().filter(|&port| port != 0).ok_or_else(|| invalid("bad port")))
+            .transpose()?;
+
+        let mut params = Vec::new();
+        for param in parts {
+            let param = param.trim();
+            if param.is_empty() {
+                return Err(invalid("empty parameter"));
+            }
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+                None => (param, None),
+            };
+            params.push((name.to_ascii_lowercase(), value));
+        }
+
+        Ok(Self {
+            transport: transport.to_ascii_uppercase(),
+            host: host.to_string(),
+            port,
+            params,
+        })
+    }
+
+    /// Value of parameter `name`: `Some(None)` for a flag, `None` when absent
+    pub fn param(&self, name: &str) -> Option<Option<&str>> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_deref())
+    }
+
+    pub fn set_param(&mut self, name: &str, value: Option<String>) {
+        match self.params.iter_mut().find(|(param, _)| param.eq_ignore_ascii_case(name)) {
+            Some(param) => param.1 = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn branch(&self) -> Option<&str> {
+        self.param("branch").flatten().filter(|branch| !branch.is_empty())
+    }
+
+    pub fn is_reliable(&self) -> bool {
+        self.transport != "UDP"
+    }
+
+    /// Port of the sent-by, or the transport's default
+    pub fn sent_by_port(&self) -> u16 {
+        self.port.unwrap_or(match self.transport.as_str() {
+            "TLS" => DEFAULT_TLS_PORT,
+            "WS" => DEFAULT_WS_PORT,
+            "WSS" => DEFAULT_WSS_PORT,
+            _ => DEFAULT_PORT,
+        })
+    }
+
+    fn ip_param(&self, name: &str) -> Option<IpAddr> {
+        self.param(name).flatten().and_then(|value| value.trim_matches(['[', ']']).parse().ok())
+    }
+}
+
+impl fmt::Display for Via {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIP/2.0/{} ", self.transport)?;
+        if self.host.contains(':') {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            f.write_str(&self.host)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        for (name, value) in &self.params {
+            match value {
+                Some(value) => write!(f, ";{}={}", name, value)?,
+                None => write!(f, ";{}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every Via of a message, top first, across repeated headers and
+/// comma-separated values
+pub fn parse_vias(headers: &[(String, String)]) -> Result<Vec<Via>> {
+    headers
+        .iter()
+        .filter(|(name, _)| is_via(name))
+        .flat_map(|(_, value)| value.split(','))
+        .map(Via::parse)
+        .collect()
+}
+
+/// Where a response to a request whose top Via is `via`, received from
+/// `source`, is sent (RFC 3261 §18.2.2, RFC 3581 §4)
+pub fn response_destination(via: &Via, source: SocketAddr) -> SocketAddr {
+    // Responses over a connection go back on it
+    if via.is_reliable() {
+        return source;
+    }
+    if let Some(maddr) = via.ip_param("maddr") {
+        return SocketAddr::new(maddr, via.sent_by_port());
+    }
+    let received = via.ip_param("received");
+    if let Some(rport) = via.param("rport").flatten().and_then(|port| port.parse::<u16>().ok()) {
+        return SocketAddr::new(received.unwrap_or(source.ip()), rport);
+    }
+    let host = received.or_else(|| via.host.parse().ok()).unwrap_or(source.ip());
+    SocketAddr::new(host, via.sent_by_port())
+}
+
+/// Checks and annotates the Vias of received requests
+#[derive(Debug, Clone, Default)]
+pub struct ViaProcessor {
+    /// Peers answered at the packet source whether or not they ask for rport
+    force_rport: Vec<String>,
+}
+
+impl ViaProcessor {
+    pub fn new(config: &SipConfig) -> Self {
+        Self {
+            force_rport: config.force_rport.clone(),
+        }
+    }
+
+    fn forces_rport(&self, via: &Via, source: SocketAddr) -> bool {
+        let source_ip = source.ip().to_string();
+        self.force_rport.iter().any(|peer| *peer == source_ip || peer.eq_ignore_ascii_case(&via.host))
+    }
+
+    /// Validate the Vias of a request received from `source`, add `received`
+    /// and fill `rport` on the top one, and return where its responses go.
+    /// An error means the request should be refused with 400 Bad Request.
+    pub fn receive_request(&self, headers: &mut [(String, String)], source: SocketAddr) -> Result<SocketAddr> {
+        let vias = parse_vias(headers)?;
+        let mut top = vias.into_iter().next().ok_or_else(|| Error::parse("Request has no Via"))?;
+        if top.branch().is_none() {
+            return Err(Error::parse(format!("Top Via {} has no branch", top)));
+        }
+
+        let symmetric = top.param("rport").is_some() || self.forces_rport(&top, source);
+        if symmetric {
+            // RFC 3581 §4: received is added even when it matches the sent-by
+            top.set_param("received", Some(source.ip().to_string()));
+            top.set_param("rport", Some(source.port().to_string()));
+        } else if top.host.parse::<IpAddr>().ok() != Some(source.ip()) {
+            top.set_param("received", Some(source.ip().to_string()));
+        }
+
+        let destination = response_destination(&top, source);
+        let (_, value) = headers
+            .iter_mut()
+            .find(|(name, _)| is_via(name))
+            .expect("a Via was parsed from these headers");
+        *value = match value.split_once(',') {
+            Some((_, rest)) => format!("{},{}", top, rest),
+            None => top.to_string(),
+        };
+        Ok(destination)
+    }
+}
+
+/// Check that a response belongs to the request sent with `branch`, and
+/// return the address the far end saw it come from, when it said
+pub fn check_response(headers: &[(String, String)], branch: &str) -> Result<Option<SocketAddr>> {
+    let vias = parse_vias(headers)?;
+    let top = vias.first().ok_or_else(|| Error::parse("Response has no Via"))?;
+    if top.branch() != Some(branch) {
+        return Err(Error::parse(format!("Response Via branch {:?} does not match {}", top.branch(), branch)));
+    }
+    let received = top.ip_param("received");
+    let rport = top.param("rport").flatten().and_then(|port| port.parse::<u16>().ok());
+    Ok(received.zip(rport).map(|(ip, port)| SocketAddr::new(ip, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(vias: &[&str]) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = vias.iter().map(|via| (VIA.to_string(), via.to_string())).collect();
+        headers.push(("Call-ID".to_string(), "abc@pbx".to_string()));
+        headers
+    }
+
+    fn processor(force_rport: &[&str]) -> ViaProcessor {
+        ViaProcessor { force_rport: force_rport.iter().map(|peer| peer.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_nated_pbx_gets_symmetric_response() {
+        let source: SocketAddr = "203.0.113.9:41234".parse().unwrap();
+
+        // Asks for rport from behind NAT
+        let mut request = headers(&["SIP/2.0/UDP 192.168.1.20:5060;branch=z9hG4bK77;rport"]);
+        let destination = processor(&[]).receive_request(&mut request, source).unwrap();
+        assert_eq!(destination, source);
+        assert_eq!(request[0].1, "SIP/2.0/UDP 192.168.1.20:5060;branch=z9hG4bK77;rport=41234;received=203.0.113.9");
+
+        // Does not ask: the response goes to the sent-by port at the real address
+        let mut request = headers(&["SIP/2.0/UDP 192.168.1.20;branch=z9hG4bK78"]);
+        let destination = processor(&[]).receive_request(&mut request, source).unwrap();
+        assert_eq!(destination, "203.0.113.9:5060".parse().unwrap());
+        assert_eq!(request[0].1, "SIP/2.0/UDP 192.168.1.20;branch=z9hG4bK78;received=203.0.113.9");
+
+        // Unless the peer is configured for symmetric routing
+        let mut request = headers(&["SIP/2.0/UDP 192.168.1.20;branch=z9hG4bK79"]);
+        let destination = processor(&["203.0.113.9"]).receive_request(&mut request, source).unwrap();
+        assert_eq!(destination, source);
+
+        // No received when the sent-by is the source, and none at all over TCP
+        let mut request = headers(&["SIP/2.0/TCP 203.0.113.9:5060;branch=z9hG4bK80"]);
+        let destination = processor(&[]).receive_request(&mut request, source).unwrap();
+        assert_eq!(destination, source);
+        assert_eq!(request[0].1, "SIP/2.0/TCP 203.0.113.9:5060;branch=z9hG4bK80");
+
+        // Browsers put a made-up .invalid host in the Via (RFC 7118 §5.2)
+        let mut request = headers(&["SIP/2.0/WSS df7jal23ls0d.invalid;branch=z9hG4bKws1"]);
+        let destination = processor(&[]).receive_request(&mut request, source).unwrap();
+        assert_eq!(destination, source);
+        let via = Via::parse(&request[0].1).unwrap();
+        assert_eq!(via.param("received"), Some(Some("203.0.113.9")));
+        assert_eq!(via.sent_by_port(), 443);
+    }
+
+    #[test]
+    fn test_multi_via_proxied_request() {
+        let source: SocketAddr = "198.51.100.5:5060".parse().unwrap();
+        let mut request = headers(&[
+            "SIP/2.0/UDP proxy.carrier.net;branch=z9hG4bKp1;rport, SIP/2.0/UDP [2001:db8::1]:5070;branch=z9hG4bKu1",
+            "SIP/2.0/TLS 10.1.1.1;branch=z9hG4bKu0;received=192.0.2.1",
+        ]);
+        request.insert(1, ("v".to_string(), "SIP/2.0/UDP 10.9.9.9;branch=z9hG4bKmid".to_string()));
+        let destination = processor(&[]).receive_request(&mut request, source).unwrap();
+        assert_eq!(destination, source);
+
+        // Only the top Via is touched
+        let vias = parse_vias(&request).unwrap();
+        assert_eq!(vias.len(), 4);
+        assert_eq!(vias[0].param("received"), Some(Some("198.51.100.5")));
+        assert_eq!(vias[1].host, "2001:db8::1");
+        assert_eq!(vias[1].port, Some(5070));
+        assert_eq!(vias[2].branch(), Some("z9hG4bKmid"));
+        assert_eq!(vias[3].sent_by_port(), 5061);
+        assert!(request[0].1.ends_with(", SIP/2.0/UDP [2001:db8::1]:5070;branch=z9hG4bKu1"));
+
+        // maddr wins over the source
+        let via = Via::parse("SIP/2.0/UDP 10.0.0.1;branch=z9hG4bK1;maddr=239.1.1.1").unwrap();
+        assert_eq!(response_destination(&via, source), "239.1.1.1:5060".parse().unwrap());
+    }
+
+    #[test]
+    fn test_malformed_vias_are_refused() {
+        let source: SocketAddr = "198.51.100.5:5060".parse().unwrap();
+        for bad in [
+            "SIP/2.0/UDP",
+            "SIP/3.0/UDP 10.0.0.1;branch=z9hG4bK1",
+            "SIP/2.0/UDP 10.0.0.1:70000;branch=z9hG4bK1",
+            "SIP/2.0/UDP 10.0.0.1;;branch=z9hG4bK1",
+            "SIP/2.0/UDP [2001:db8::1;branch=z9hG4bK1",
+            "SIP/2.0/UDP 10.0.0.1",
+        ] {
+            assert!(processor(&[]).receive_request(&mut headers(&[bad]), source).is_err(), "{}", bad);
+        }
+        assert!(processor(&[]).receive_request(&mut headers(&[]), source).is_err());
+    }
+
+    #[test]
+    fn test_response_must_match_our_branch() {
+        let response = headers(&["SIP/2.0/UDP 10.0.0.5:5060;branch=z9hG4bKours;rport=61000;received=203.0.113.50"]);
+        assert_eq!(check_response(&response, "z9hG4bKours").unwrap(), Some("203.0.113.50:61000".parse().unwrap()));
+        assert!(check_response(&response, "z9hG4bKother").is_err());
+    }
+}
@@ -0,0 +1,388 @@
+//! SIP over WebSocket listener for browser and softphone clients (RFC 7118)
+//!
+//! WebRTC clients such as SIP.js register and place calls over a WebSocket
+//! opened with the `sip` subprotocol, plain on the ws:// port or over TLS,
+//! with the SIP TLS listener's certificates, on the wss:// port. Each
+//! WebSocket message carries exactly one SIP message, so no Content-Length
+//! framing is needed. Browsers can be limited to configured Origins.
+//! Clients that send a double-CRLF keep-alive get a CRLF back, every
+//! connection is pinged, and connections silent for the idle timeout are
+//! closed. Open connections are kept by peer address so responses and
+//! requests for registered clients go back on the connection they came in
+//! on. The listener itself needs the `websocket` feature; without it the
+//! WebSocket configuration is ignored.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+#[cfg(feature = "websocket")]
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+#[cfg(feature = "websocket")]
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+#[cfg(feature = "websocket")]
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    task::JoinHandle,
+    time::{interval, timeout},
+};
+#[cfg(feature = "websocket")]
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{HeaderValue, StatusCode},
+    protocol::WebSocketConfig,
+    Message,
+};
+#[cfg(feature = "websocket")]
+use tracing::{debug, warn};
+
+#[cfg(feature = "websocket")]
+use crate::config::SipWebSocketConfig;
+#[cfg(feature = "websocket")]
+use crate::protocols::sip_tls::SipTlsListener;
+use crate::protocols::sip_transport::Transport;
+use crate::utils::ClockStamp;
+use crate::{Error, Result};
+
+/// WebSocket subprotocol for SIP (RFC 7118 §4.1)
+pub const SIP_SUBPROTOCOL: &str = "sip";
+
+/// How long a client has to complete the TLS and WebSocket handshakes
+#[cfg(feature = "websocket")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "websocket")]
+const KEEPALIVE: &[u8] = b"\r\n\r\n";
+
+/// Message received over a WebSocket
+#[derive(Debug, Clone)]
+pub struct WsMessage {
+    pub peer: SocketAddr,
+    /// WS or WSS, for the Via of responses and the Contact of registrations
+    pub transport: Transport,
+    pub data: Vec<u8>,
+    /// When the frame was read off the WebSocket
+    pub received: ClockStamp,
+}
+
+/// Open WebSocket connections by peer address
+#[derive(Debug, Clone, Default)]
+pub struct WsConnections {
+    senders: Arc<DashMap<SocketAddr, (Transport, mpsc::UnboundedSender<Vec<u8>>)>>,
+}
+
+impl WsConnections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the outbound channel of a connection from `peer`
+    #[cfg_attr(not(feature = "websocket"), allow(dead_code))]
+    pub(crate) fn insert(&self, peer: SocketAddr, transport: Transport, sender: mpsc::UnboundedSender<Vec<u8>>) {
+        self.senders.insert(peer, (transport, sender));
+    }
+
+    /// Send a SIP message on the connection from `peer`
+    pub fn send(&self, peer: SocketAddr, data: Vec<u8>) -> Result<()> {
+        let sender = self
+            .senders
+            .get(&peer)
+            .ok_or_else(|| Error::network(format!("No SIP WebSocket connection from {}", peer)))?;
+        sender
+            .1
+            .send(data)
+            .map_err(|_| Error::network(format!("SIP WebSocket connection from {} is closing", peer)))
+    }
+
+    /// WS or WSS, for the open connection from `peer`
+    pub fn transport(&self, peer: SocketAddr) -> Option<Transport> {
+        self.senders.get(&peer).map(|entry| entry.0)
+    }
+
+    pub fn is_connected(&self, peer: SocketAddr) -> bool {
+        self.senders.contains_key(&peer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.senders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+}
+
+/// Reason a WebSocket upgrade was refused, as an HTTP response
+#[cfg(feature = "websocket")]
+fn refuse(status: StatusCode, reason: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(reason.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Accepts SIP WebSocket connections on one port
+#[cfg(feature = "websocket")]
+pub struct SipWsListener {
+    config: SipWebSocketConfig,
+    /// Present on the wss:// port
+    tls: Option<Arc<SipTlsListener>>,
+    connections: WsConnections,
+}
+
+#[cfg(feature = "websocket")]
+impl SipWsListener {
+    /// Listener for plain ws:// connections
+    pub fn plain(config: &SipWebSocketConfig, connections: WsConnections) -> Self {
+        Self { config: config.clone(), tls: None, connections }
+    }
+
+    /// Listener for wss:// connections, presenting the certificates of `tls`
+    pub fn secure(config: &SipWebSocketConfig, tls: Arc<SipTlsListener>, connections: WsConnections) -> Self {
+        Self { config: config.clone(), tls: Some(tls), connections }
+    }
+
+    pub fn transport(&self) -> Transport {
+        if self.tls.is_some() {
+            Transport::Wss
+        } else {
+            Transport::Ws
+        }
+    }
+
+    /// Check the upgrade request's subprotocol and Origin, and agree to `sip`
+    fn check_upgrade(&self, request: &Request, mut response: Response) -> std::result::Result<Response, ErrorResponse> {
+        let offers_sip = request
+            .headers()
+            .get_all("Sec-WebSocket-Protocol")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|protocol| protocol.trim().eq_ignore_ascii_case(SIP_SUBPROTOCOL));
+        if !offers_sip {
+            return Err(refuse(StatusCode::BAD_REQUEST, "The sip WebSocket subprotocol is required"));
+        }
+
+        if !self.config.allowed_origins.is_empty() {
+            let origin = request.headers().get("Origin").and_then(|value| value.to_str().ok());
+            let allowed = origin.is_some_and(|origin| {
+                self.config.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin))
+            });
+            if !allowed {
+                return Err(refuse(StatusCode::FORBIDDEN, "Origin not allowed"));
+            }
+        }
+
+        response
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(SIP_SUBPROTOCOL));
+        Ok(response)
+    }
+
+    /// Accept connections on `socket` until the task is aborted, passing
+    /// received messages to `message_tx`
+    pub fn spawn(
+        self: Arc<Self>,
+        socket: TcpListener,
+        max_message_bytes: usize,
+        message_tx: mpsc::UnboundedSender<WsMessage>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match socket.accept().await {
+                    Ok((stream, peer)) => {
+                        let listener = Arc::clone(&self);
+                        let message_tx = message_tx.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = listener.serve(stream, peer, max_message_bytes, message_tx).await {
+                                debug!("SIP WebSocket connection from {} closed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("SIP WebSocket accept failed: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        })
+    }
+
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        peer: SocketAddr,
+        max_message_bytes: usize,
+        message_tx: mpsc::UnboundedSender<WsMessage>,
+    ) -> Result<()> {
+        match &self.tls {
+            Some(tls) => {
+                let stream = timeout(HANDSHAKE_TIMEOUT, tls.acceptor().accept(stream))
+                    .await
+                    .map_err(|_| Error::timeout(format!("TLS handshake with {} timed out", peer)))??;
+                self.serve_websocket(stream, peer, max_message_bytes, message_tx).await
+            }
+            None => self.serve_websocket(stream, peer, max_message_bytes, message_tx).await,
+        }
+    }
+
+    async fn serve_websocket<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        peer: SocketAddr,
+        max_message_bytes: usize,
+        message_tx: mpsc::UnboundedSender<WsMessage>,
+    ) -> Result<()> {
+        let mut ws_config = WebSocketConfig::default();
+        ws_config.max_message_size = Some(max_message_bytes);
+        ws_config.max_frame_size = Some(max_message_bytes);
+        let upgrade = tokio_tungstenite::accept_hdr_async_with_config(
+            stream,
+            |request: &Request, response: Response| self.check_upgrade(request, response),
+            Some(ws_config),
+        );
+        let websocket = timeout(HANDSHAKE_TIMEOUT, upgrade)
+            .await
+            .map_err(|_| Error::timeout(format!("WebSocket handshake with {} timed out", peer)))?
+            .map_err(|e| Error::network(format!("WebSocket handshake with {} failed: {}", peer, e)))?;
+        let transport = self.transport();
+        debug!("SIP {} connection from {}", transport.via_name(), peer);
+
+        let (mut sink, mut frames) = websocket.split();
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        self.connections.insert(peer, transport, outbound_tx);
+
+        let idle_timeout = Duration::from_secs(self.config.idle_timeout_secs);
+        let mut ping = interval(Duration::from_secs(self.config.ping_interval_secs.max(1)));
+        ping.tick().await;
+        let mut last_heard = Instant::now();
+
+        let result = loop {
+            tokio::select! {
+                frame = frames.next() => {
+                    let message = match frame {
+                        Some(Ok(message)) => message,
+                        Some(Err(e)) => break Err(Error::network(format!("WebSocket from {}: {}", peer, e))),
+                        None => break Ok(()),
+                    };
+                    let received = ClockStamp::now();
+                    last_heard = received.instant;
+                    // Pings are answered by the WebSocket library; pongs only count as activity
+                    let data = match message {
+                        Message::Text(text) => text.into_bytes(),
+                        Message::Binary(data) => data,
+                        Message::Close(_) => break Ok(()),
+                        _ => continue,
+                    };
+                    if data == KEEPALIVE {
+                        if let Err(e) = sink.send(Message::Text("\r\n".to_string())).await {
+                            break Err(Error::network(format!("WebSocket to {}: {}", peer, e)));
+                        }
+                        continue;
+                    }
+                    if message_tx.send(WsMessage { peer, transport, data, received }).is_err() {
+                        break Ok(());
+                    }
+                }
+                Some(data) = outbound_rx.recv() => {
+                    // SIP messages go as text frames unless the body is not UTF-8
+                    let message = match String::from_utf8(data) {
+                        Ok(text) => Message::Text(text),
+                        Err(e) => Message::Binary(e.into_bytes()),
+                    };
+                    if let Err(e) = sink.send(message).await {
+                        break Err(Error::network(format!("WebSocket to {}: {}", peer, e)));
+                    }
+                }
+                _ = ping.tick() => {
+                    if last_heard.elapsed() >= idle_timeout {
+                        let _ = sink.send(Message::Close(None)).await;
+                        break Err(Error::timeout(format!("SIP WebSocket from {} idle", peer)));
+                    }
+                    if let Err(e) = sink.send(Message::Ping(Vec::new())).await {
+                        break Err(Error::network(format!("WebSocket to {}: {}", peer, e)));
+                    }
+                }
+            }
+        };
+        self.connections.senders.remove(&peer);
+        result
+    }
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::WebSocketStream;
+
+    const PEER: &str = "198.51.100.20:52000";
+
+    fn listener(allowed_origins: &[&str]) -> Arc<SipWsListener> {
+        let config = SipWebSocketConfig {
+            enabled: true,
+            allowed_origins: allowed_origins.iter().map(|origin| origin.to_string()).collect(),
+            ..SipWebSocketConfig::default()
+        };
+        Arc::new(SipWsListener::plain(&config, WsConnections::new()))
+    }
+
+    async fn connect(
+        listener: &Arc<SipWsListener>,
+        subprotocol: Option<&'static str>,
+        origin: &'static str,
+    ) -> (Result<WebSocketStream<DuplexStream>>, mpsc::UnboundedReceiver<WsMessage>) {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let (message_tx, message_rx) = mpsc::unbounded_channel();
+        let listener = Arc::clone(listener);
+        tokio::spawn(async move { listener.serve(server, PEER.parse().unwrap(), 4096, message_tx).await });
+
+        let mut request = "ws://gw.example.com/".into_client_request().unwrap();
+        if let Some(subprotocol) = subprotocol {
+            request.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(subprotocol));
+        }
+        request.headers_mut().insert("Origin", HeaderValue::from_static(origin));
+        let result = tokio_tungstenite::client_async(request, client)
+            .await
+            .map(|(websocket, _)| websocket)
+            .map_err(|e| Error::network(e.to_string()));
+        (result, message_rx)
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_checks() {
+        let listener = listener(&["https://phone.example.com"]);
+        assert!(connect(&listener, None, "https://phone.example.com").await.0.is_err());
+        assert!(connect(&listener, Some("sip"), "https://evil.example.net").await.0.is_err());
+        assert!(connect(&listener, Some("chat, sip"), "https://phone.example.com").await.0.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_messages_and_keepalive() {
+        let listener = listener(&[]);
+        let (websocket, mut message_rx) = connect(&listener, Some("sip"), "https://phone.example.com").await;
+        let mut websocket = websocket.unwrap();
+        let peer: SocketAddr = PEER.parse().unwrap();
+
+        let register = "REGISTER sip:gw.example.com SIP/2.0\r\nVia: SIP/2.0/WS df7jal23ls0d.invalid;branch=z9hG4bK1\r\n\r\n";
+        websocket.send(Message::Text(register.to_string())).await.unwrap();
+        let message = message_rx.recv().await.unwrap();
+        assert_eq!((message.peer, message.transport), (peer, Transport::Ws));
+        assert_eq!(message.data, register.as_bytes());
+
+        websocket.send(Message::Text("\r\n\r\n".to_string())).await.unwrap();
+        assert_eq!(websocket.next().await.unwrap().unwrap(), Message::Text("\r\n".to_string()));
+
+        // Responses go back on the connection the request came in on
+        assert!(listener.connections.is_connected(peer));
+        listener.connections.send(peer, b"SIP/2.0 200 OK\r\n\r\n".to_vec()).unwrap();
+        assert_eq!(websocket.next().await.unwrap().unwrap(), Message::Text("SIP/2.0 200 OK\r\n\r\n".to_string()));
+        assert!(listener.connections.send("192.0.2.1:5000".parse().unwrap(), Vec::new()).is_err());
+
+        websocket.close(None).await.unwrap();
+        while message_rx.recv().await.is_some() {}
+        assert!(!listener.connections.is_connected(peer));
+    }
+}
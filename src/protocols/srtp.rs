@@ -0,0 +1,526 @@
+//! SRTP media encryption with SDES keying
+//!
+//! Keys are exchanged in `a=crypto` attributes of RTP/SAVP streams (RFC
+//! 4568). Each side announces the key it encrypts with, so a session holds
+//! the local key for packets sent and the remote key for packets received.
+//! Packets are encrypted with AES in counter mode and authenticated with
+//! HMAC-SHA1 (RFC 3711), with the 80-bit or 32-bit tag of the negotiated
+//! suite. Session keys are derived once, since the key derivation rate is
+//! zero. Received packets are checked against a 64-packet replay window.
+//! Only RTP is protected; RTCP is not relayed.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use aes::Aes128;
+use base64::Engine;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+use crate::protocols::sdp::{MediaDescription, SdpLine};
+use crate::{Error, Result};
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+type HmacSha1 = Hmac<Sha1>;
+
+const MASTER_KEY_LEN: usize = 16;
+const MASTER_SALT_LEN: usize = 14;
+const AUTH_KEY_LEN: usize = 20;
+
+/// Key derivation labels (RFC 3711 §4.3.2)
+const LABEL_CIPHER_KEY: u8 = 0x00;
+const LABEL_AUTH_KEY: u8 = 0x01;
+const LABEL_SALT: u8 = 0x02;
+
+/// Packets behind the highest index still accepted once
+const REPLAY_WINDOW: u64 = 64;
+
+/// SDES crypto suites
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SrtpProfile {
+    #[serde(rename = "AES_CM_128_HMAC_SHA1_80")]
+    AesCm128HmacSha1_80,
+    #[serde(rename = "AES_CM_128_HMAC_SHA1_32")]
+    AesCm128HmacSha1_32,
+}
+
+impl SrtpProfile {
+    pub fn sdp_name(&self) -> &'static str {
+        match self {
+            SrtpProfile::AesCm128HmacSha1_80 => "AES_CM_128_HMAC_SHA1_80",
+            SrtpProfile::AesCm128HmacSha1_32 => "AES_CM_128_HMAC_SHA1_32",
+        }
+    }
+
+    pub fn from_sdp_name(name: &str) -> Option<Self> {
+        match name {
+            "AES_CM_128_HMAC_SHA1_80" => Some(SrtpProfile::AesCm128HmacSha1_80),
+            "AES_CM_128_HMAC_SHA1_32" => Some(SrtpProfile::AesCm128HmacSha1_32),
+            _ => None,
+        }
+    }
+
+    /// Bytes of authentication tag appended to each packet
+    pub fn tag_len(&self) -> usize {
+        match self {
+            SrtpProfile::AesCm128HmacSha1_80 => 10,
+            SrtpProfile::AesCm128HmacSha1_32 => 4,
+        }
+    }
+}
+
+/// Master key and salt carried in an `inline:` key parameter
+#[derive(Clone, PartialEq, Eq)]
+pub struct SrtpKey {
+    pub master_key: [u8; MASTER_KEY_LEN],
+    pub master_salt: [u8; MASTER_SALT_LEN],
+}
+
+impl SrtpKey {
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut key = Self { master_key: [0; MASTER_KEY_LEN], master_salt: [0; MASTER_SALT_LEN] };
+        rng.fill(&mut key.master_key);
+        rng.fill(&mut key.master_salt);
+        key
+    }
+
+    fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode([&self.master_key[..], &self.master_salt[..]].concat())
+    }
+
+    fn from_base64(encoded: &str) -> Option<Self> {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(encoded).ok()?;
+        if bytes.len() != MASTER_KEY_LEN + MASTER_SALT_LEN {
+            return None;
+        }
+        let mut key = Self { master_key: [0; MASTER_KEY_LEN], master_salt: [0; MASTER_SALT_LEN] };
+        key.master_key.copy_from_slice(&bytes[..MASTER_KEY_LEN]);
+        key.master_salt.copy_from_slice(&bytes[MASTER_KEY_LEN..]);
+        Some(key)
+    }
+}
+
+impl fmt::Debug for SrtpKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys stay out of logs and traces
+        f.write_str("SrtpKey(..)")
+    }
+}
+
+/// `a=crypto` attribute value: tag, suite and key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptoAttribute {
+    pub tag: u32,
+    pub profile: SrtpProfile,
+    pub key: SrtpKey,
+}
+
+impl CryptoAttribute {
+    /// Fresh key for `profile` under `tag`
+    pub fn generate(tag: u32, profile: SrtpProfile) -> Self {
+        Self { tag, profile, key: SrtpKey::generate() }
+    }
+
+    /// Parse the value of an `a=crypto` line. Attributes with a key
+    /// identifier (MKI), several keys or session parameters are not
+    /// supported and give `None`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split_whitespace();
+        let tag = parts.next()?.parse().ok()?;
+        let profile = SrtpProfile::from_sdp_name(parts.next()?)?;
+        let key_params = parts.next()?;
+        if parts.next().is_some() || key_params.contains(';') {
+            return None;
+        }
+        let mut key_fields = key_params.strip_prefix("inline:")?.split('|');
+        let key = SrtpKey::from_base64(key_fields.next()?)?;
+        // A lifetime may follow; an MKI has a colon
+        if key_fields.any(|field| field.contains(':')) {
+            return None;
+        }
+        Some(Self { tag, profile, key })
+    }
+}
+
+impl fmt::Display for CryptoAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} inline:{}", self.tag, self.profile.sdp_name(), self.key.to_base64())
+    }
+}
+
+fn is_secure(protocol: &str) -> bool {
+    protocol.eq_ignore_ascii_case("RTP/SAVP") || protocol.eq_ignore_ascii_case("RTP/SAVPF")
+}
+
+fn has_feedback(protocol: &str) -> bool {
+    protocol.eq_ignore_ascii_case("RTP/AVPF") || protocol.eq_ignore_ascii_case("RTP/SAVPF")
+}
+
+/// First supported key offered on a stream; `None` for plain RTP. A secure
+/// stream offering no supported suite is an error, answered with 488.
+pub fn offered_crypto(stream: &MediaDescription) -> Result<Option<CryptoAttribute>> {
+    if !is_secure(&stream.protocol) {
+        return Ok(None);
+    }
+    stream
+        .attributes("crypto")
+        .find_map(CryptoAttribute::parse)
+        .map(Some)
+        .ok_or_else(|| Error::not_supported("No supported SRTP crypto suite offered"))
+}
+
+/// Make a stream RTP/SAVP carrying `crypto` as its only key
+pub fn secure_stream(stream: &mut MediaDescription, crypto: &CryptoAttribute) {
+    stream.protocol = if has_feedback(&stream.protocol) { "RTP/SAVPF" } else { "RTP/SAVP" }.to_string();
+    stream.remove_attribute("crypto");
+    stream.lines.push(SdpLine::new('a', format!("crypto:{}", crypto)));
+}
+
+/// Make a stream plain RTP/AVP, dropping any keys
+pub fn plain_stream(stream: &mut MediaDescription) {
+    stream.protocol = if has_feedback(&stream.protocol) { "RTP/AVPF" } else { "RTP/AVP" }.to_string();
+    stream.remove_attribute("crypto");
+}
+
+/// Keys of a relayed call chosen at offer time, put to use on the answer
+#[derive(Debug, Clone, Default)]
+pub struct PendingSrtp {
+    /// Key leg A offered, which it encrypts with
+    pub leg_a_remote: Option<CryptoAttribute>,
+    /// Key answered to leg A, which the gateway encrypts with toward it
+    pub leg_a_local: Option<CryptoAttribute>,
+    /// Key offered to leg B, which the gateway encrypts with toward it
+    pub leg_b_local: Option<CryptoAttribute>,
+}
+
+/// Length of the RTP header, CSRCs and extension included
+fn header_len(packet: &[u8]) -> Result<usize> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return Err(Error::rtp("Not an RTP packet"));
+    }
+    let mut len = 12 + 4 * (packet[0] & 0x0f) as usize;
+    if packet[0] & 0x10 != 0 {
+        let words = packet
+            .get(len + 2..len + 4)
+            .ok_or_else(|| Error::rtp("Invalid extension header"))?;
+        len += 4 + 4 * u16::from_be_bytes([words[0], words[1]]) as usize;
+    }
+    if len > packet.len() {
+        return Err(Error::rtp("RTP header longer than the packet"));
+    }
+    Ok(len)
+}
+
+fn sequence_and_ssrc(packet: &[u8]) -> (u16, u32) {
+    (
+        u16::from_be_bytes([packet[2], packet[3]]),
+        u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+    )
+}
+
+/// Session keys derived from a master key
+struct SessionKeys {
+    cipher_key: [u8; MASTER_KEY_LEN],
+    salt: [u8; MASTER_SALT_LEN],
+    auth_key: [u8; AUTH_KEY_LEN],
+}
+
+impl SessionKeys {
+    fn derive(master: &SrtpKey) -> Self {
+        let mut keys = Self {
+            cipher_key: [0; MASTER_KEY_LEN],
+            salt: [0; MASTER_SALT_LEN],
+            auth_key: [0; AUTH_KEY_LEN],
+        };
+        Self::derive_key(master, LABEL_CIPHER_KEY, &mut keys.cipher_key);
+        Self::derive_key(master, LABEL_SALT, &mut keys.salt);
+        Self::derive_key(master, LABEL_AUTH_KEY, &mut keys.auth_key);
+        keys
+    }
+
+    /// AES-CM keystream under the master key, starting from the master salt
+    /// with the label in the key id
+    fn derive_key(master: &SrtpKey, label: u8, out: &mut [u8]) {
+        let mut iv = [0u8; 16];
+        iv[..MASTER_SALT_LEN].copy_from_slice(&master.master_salt);
+        iv[7] ^= label;
+        out.fill(0);
+        Aes128Ctr::new(&master.master_key.into(), &iv.into()).apply_keystream(out);
+    }
+
+    /// Encrypt or decrypt a payload of packet `index` from `ssrc`
+    fn apply_keystream(&self, ssrc: u32, index: u64, payload: &mut [u8]) {
+        let mut iv = [0u8; 16];
+        iv[..MASTER_SALT_LEN].copy_from_slice(&self.salt);
+        for (byte, ssrc_byte) in iv[4..8].iter_mut().zip(ssrc.to_be_bytes()) {
+            *byte ^= ssrc_byte;
+        }
+        for (byte, index_byte) in iv[8..14].iter_mut().zip(&index.to_be_bytes()[2..]) {
+            *byte ^= index_byte;
+        }
+        Aes128Ctr::new(&self.cipher_key.into(), &iv.into()).apply_keystream(payload);
+    }
+
+    fn mac(&self, authenticated: &[u8], roc: u32) -> Result<HmacSha1> {
+        let mut mac = <HmacSha1 as Mac>::new_from_slice(&self.auth_key)
+            .map_err(|_| Error::internal("Invalid SRTP authentication key"))?;
+        mac.update(authenticated);
+        mac.update(&roc.to_be_bytes());
+        Ok(mac)
+    }
+}
+
+/// Rollover counter and replay window of one SSRC
+struct StreamState {
+    roc: u32,
+    /// Highest sequence number seen
+    s_l: u16,
+    highest: u64,
+    /// Bit n set when packet `highest - n` has been seen
+    window: u64,
+}
+
+impl StreamState {
+    fn new(seq: u16) -> Self {
+        Self { roc: 0, s_l: seq, highest: seq as u64, window: 0 }
+    }
+
+    /// Rollover counter and packet index for `seq` (RFC 3711 §3.3.1);
+    /// `None` for a packet from before the stream started
+    fn estimate(&self, seq: u16) -> Option<(u32, u64)> {
+        let roc = if self.s_l < 32768 {
+            if seq as i32 - self.s_l as i32 > 32768 {
+                self.roc.checked_sub(1)?
+            } else {
+                self.roc
+            }
+        } else if (self.s_l as i32 - 32768) > seq as i32 {
+            self.roc.wrapping_add(1)
+        } else {
+            self.roc
+        };
+        Some((roc, ((roc as u64) << 16) | seq as u64))
+    }
+
+    fn is_fresh(&self, index: u64) -> bool {
+        index > self.highest || {
+            let behind = self.highest - index;
+            behind < REPLAY_WINDOW && self.window & (1 << behind) == 0
+        }
+    }
+
+    fn accept(&mut self, roc: u32, seq: u16, index: u64) {
+        if index > self.highest {
+            let ahead = index - self.highest;
+            self.window = if ahead >= REPLAY_WINDOW { 0 } else { self.window << ahead };
+            self.highest = index;
+            self.roc = roc;
+            self.s_l = seq;
+        }
+        let behind = self.highest - index;
+        if behind < REPLAY_WINDOW {
+            self.window |= 1 << behind;
+        }
+    }
+}
+
+/// One direction of SRTP under one key
+struct SrtpContext {
+    profile: SrtpProfile,
+    keys: SessionKeys,
+    streams: HashMap<u32, StreamState>,
+}
+
+impl SrtpContext {
+    fn new(crypto: &CryptoAttribute) -> Self {
+        Self { profile: crypto.profile, keys: SessionKeys::derive(&crypto.key), streams: HashMap::new() }
+    }
+
+    fn protect(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let header_len = header_len(packet)?;
+        let (seq, ssrc) = sequence_and_ssrc(packet);
+        let state = self.streams.entry(ssrc).or_insert_with(|| StreamState::new(seq));
+        let (roc, index) = state
+            .estimate(seq)
+            .ok_or_else(|| Error::rtp("RTP sequence number behind the start of the stream"))?;
+        state.accept(roc, seq, index);
+
+        let mut protected = Vec::with_capacity(packet.len() + self.profile.tag_len());
+        protected.extend_from_slice(packet);
+        self.keys.apply_keystream(ssrc, index, &mut protected[header_len..]);
+        let tag = self.keys.mac(&protected, roc)?.finalize().into_bytes();
+        protected.extend_from_slice(&tag[..self.profile.tag_len()]);
+        Ok(protected)
+    }
+
+    fn unprotect(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let tag_len = self.profile.tag_len();
+        if packet.len() < tag_len {
+            return Err(Error::rtp("SRTP packet too short"));
+        }
+        let (authenticated, tag) = packet.split_at(packet.len() - tag_len);
+        let header_len = header_len(authenticated)?;
+        let (seq, ssrc) = sequence_and_ssrc(authenticated);
+
+        let first;
+        let state = match self.streams.get(&ssrc) {
+            Some(state) => state,
+            None => {
+                first = StreamState::new(seq);
+                &first
+            }
+        };
+        let (roc, index) = state
+            .estimate(seq)
+            .ok_or_else(|| Error::rtp("SRTP packet from before the start of the stream"))?;
+        if !state.is_fresh(index) {
+            return Err(Error::rtp("Replayed SRTP packet"));
+        }
+        self.keys
+            .mac(authenticated, roc)?
+            .verify_truncated_left(tag)
+            .map_err(|_| Error::rtp("SRTP authentication failed"))?;
+
+        self.streams.entry(ssrc).or_insert_with(|| StreamState::new(seq)).accept(roc, seq, index);
+        let mut plain = authenticated.to_vec();
+        self.keys.apply_keystream(ssrc, index, &mut plain[header_len..]);
+        Ok(plain)
+    }
+}
+
+/// SRTP packet counters of one session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SrtpStats {
+    pub protected: u64,
+    pub unprotected: u64,
+    /// Received packets dropped for failed authentication or replay
+    pub rejected: u64,
+}
+
+/// Both directions of an SRTP-protected RTP session
+pub struct SrtpSession {
+    outbound: SrtpContext,
+    inbound: SrtpContext,
+    stats: SrtpStats,
+}
+
+impl SrtpSession {
+    /// `local` encrypts what is sent, `remote` is the key the far end sends with
+    pub fn new(local: &CryptoAttribute, remote: &CryptoAttribute) -> Self {
+        Self {
+            outbound: SrtpContext::new(local),
+            inbound: SrtpContext::new(remote),
+            stats: SrtpStats::default(),
+        }
+    }
+
+    pub fn profile(&self) -> SrtpProfile {
+        self.outbound.profile
+    }
+
+    /// Encrypt and authenticate an encoded RTP packet
+    pub fn protect(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let protected = self.outbound.protect(packet)?;
+        self.stats.protected += 1;
+        Ok(protected)
+    }
+
+    /// Authenticate and decrypt a received SRTP packet
+    pub fn unprotect(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        match self.inbound.unprotect(packet) {
+            Ok(plain) => {
+                self.stats.unprotected += 1;
+                Ok(plain)
+            }
+            Err(e) => {
+                self.stats.rejected += 1;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn stats(&self) -> SrtpStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::rtp::RtpPacket;
+    use crate::protocols::sdp::SessionDescription;
+
+    fn hex(text: &str) -> Vec<u8> {
+        hex::decode(text).unwrap()
+    }
+
+    #[test]
+    fn test_key_derivation() {
+        // RFC 3711 appendix B.3
+        let mut master = SrtpKey { master_key: [0; 16], master_salt: [0; 14] };
+        master.master_key.copy_from_slice(&hex("E1F97A0D3E018BE0D64FA32C06DE4139"));
+        master.master_salt.copy_from_slice(&hex("0EC675AD498AFEEBB6960B3AABE6"));
+        let keys = SessionKeys::derive(&master);
+        assert_eq!(keys.cipher_key.to_vec(), hex("C61E7A93744F39EE10734AFE3FF7A087"));
+        assert_eq!(keys.salt.to_vec(), hex("30CBBC08863D8C85D49DB34A9AE1"));
+    }
+
+    #[test]
+    fn test_protect_round_trip() {
+        for profile in [SrtpProfile::AesCm128HmacSha1_80, SrtpProfile::AesCm128HmacSha1_32] {
+            let ours = CryptoAttribute::generate(1, profile);
+            let theirs = CryptoAttribute::generate(1, profile);
+            let mut sender = SrtpSession::new(&ours, &theirs);
+            let mut receiver = SrtpSession::new(&theirs, &ours);
+
+            for seq in [65534u16, 65535, 0, 1] {
+                let mut packet = RtpPacket::new(0, seq, 160 * seq as u32, 0x1234_5678);
+                packet.payload = vec![0xd5; 160].into();
+                let plain = packet.encode();
+                let protected = sender.protect(&plain).unwrap();
+                assert_eq!(protected.len(), plain.len() + profile.tag_len());
+                assert_ne!(&protected[12..plain.len()], &plain[12..]);
+                assert_eq!(receiver.unprotect(&protected).unwrap(), plain.to_vec());
+                // The same packet again is a replay
+                assert!(receiver.unprotect(&protected).is_err());
+
+                let mut tampered = protected.clone();
+                tampered[20] ^= 1;
+                assert!(receiver.unprotect(&tampered).is_err());
+            }
+            assert_eq!(receiver.stats(), SrtpStats { protected: 0, unprotected: 4, rejected: 8 });
+        }
+    }
+
+    #[test]
+    fn test_sdp_crypto() {
+        let crypto = CryptoAttribute::generate(7, SrtpProfile::AesCm128HmacSha1_80);
+        assert_eq!(CryptoAttribute::parse(&crypto.to_string()), Some(crypto.clone()));
+        assert!(CryptoAttribute::parse(&format!("{}|2^31|1:4", crypto)).is_none());
+        assert!(CryptoAttribute::parse(&format!("{}|2^31", crypto)).is_some());
+
+        let offer = format!(
+            "v=0\r\no=- 1 1 IN IP4 192.0.2.1\r\ns=-\r\nc=IN IP4 192.0.2.1\r\nt=0 0\r\n\
+             m=audio 4000 RTP/SAVP 0\r\na=crypto:1 F8_128_HMAC_SHA1_80 inline:{}\r\na=crypto:{}\r\n",
+            crypto.key.to_base64(),
+            crypto
+        );
+        let mut sdp = SessionDescription::parse(&offer).unwrap();
+        assert_eq!(offered_crypto(&sdp.media[0]).unwrap(), Some(crypto.clone()));
+
+        plain_stream(&mut sdp.media[0]);
+        assert_eq!(sdp.media[0].protocol, "RTP/AVP");
+        assert_eq!(offered_crypto(&sdp.media[0]).unwrap(), None);
+
+        secure_stream(&mut sdp.media[0], &crypto);
+        assert_eq!(sdp.media[0].protocol, "RTP/SAVP");
+        assert_eq!(sdp.media[0].attributes("crypto").count(), 1);
+
+        sdp.media[0].remove_attribute("crypto");
+        assert!(offered_crypto(&sdp.media[0]).is_err());
+    }
+}
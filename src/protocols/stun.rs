@@ -0,0 +1,282 @@
+//! STUN message codec (RFC 5389)
+//!
+//! Covers what ICE connectivity checks and server-reflexive candidate
+//! gathering need: Binding requests and responses, XOR-MAPPED-ADDRESS,
+//! short-term credential MESSAGE-INTEGRITY and FINGERPRINT. STUN shares the
+//! media port with DTLS and RTP, and is told apart by its first two bits
+//! and magic cookie.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+
+use crate::{Error, Result};
+
+type HmacSha1 = Hmac<Sha1>;
+
+pub const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+const HEADER_LEN: usize = 20;
+const INTEGRITY_LEN: usize = 20;
+const FINGERPRINT_XOR: u32 = 0x5354_554E;
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+pub const BINDING_REQUEST: u16 = 0x0001;
+pub const BINDING_SUCCESS: u16 = 0x0101;
+pub const BINDING_ERROR: u16 = 0x0111;
+
+pub const ATTR_USERNAME: u16 = 0x0006;
+pub const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+pub const ATTR_ERROR_CODE: u16 = 0x0009;
+pub const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+pub const ATTR_PRIORITY: u16 = 0x0024;
+pub const ATTR_USE_CANDIDATE: u16 = 0x0025;
+pub const ATTR_FINGERPRINT: u16 = 0x8028;
+pub const ATTR_ICE_CONTROLLED: u16 = 0x8029;
+pub const ATTR_ICE_CONTROLLING: u16 = 0x802A;
+
+/// Whether a datagram received on a media port is a STUN message
+pub fn is_stun(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN
+        && data[0] & 0xC0 == 0
+        && u32::from_be_bytes([data[4], data[5], data[6], data[7]]) == MAGIC_COOKIE
+}
+
+/// A decoded STUN message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StunMessage {
+    pub message_type: u16,
+    pub transaction_id: [u8; 12],
+    pub attributes: Vec<(u16, Vec<u8>)>,
+}
+
+impl StunMessage {
+    pub fn new(message_type: u16, transaction_id: [u8; 12]) -> Self {
+        Self { message_type, transaction_id, attributes: Vec::new() }
+    }
+
+    /// Binding request with a fresh transaction ID
+    pub fn binding_request() -> Self {
+        Self::new(BINDING_REQUEST, rand::thread_rng().gen())
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !is_stun(data) {
+            return Err(Error::parse("Not a STUN message"));
+        }
+        let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if length % 4 != 0 || data.len() != HEADER_LEN + length {
+            return Err(Error::parse("Invalid STUN message length"));
+        }
+
+        let mut message = Self::new(u16::from_be_bytes([data[0], data[1]]), [0; 12]);
+        message.transaction_id.copy_from_slice(&data[8..HEADER_LEN]);
+        for (_, kind, value) in attributes(data)? {
+            message.attributes.push((kind, value.to_vec()));
+        }
+        Ok(message)
+    }
+
+    pub fn attribute(&self, kind: u16) -> Option<&[u8]> {
+        self.attributes.iter().find(|(k, _)| *k == kind).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn has_attribute(&self, kind: u16) -> bool {
+        self.attribute(kind).is_some()
+    }
+
+    pub fn add_attribute(&mut self, kind: u16, value: Vec<u8>) {
+        self.attributes.push((kind, value));
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.attribute(ATTR_USERNAME).and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    pub fn add_xor_mapped_address(&mut self, address: SocketAddr) {
+        let mut value = vec![0, 0];
+        value.extend_from_slice(&(address.port() ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+        match address.ip() {
+            IpAddr::V4(ip) => {
+                value[1] = 0x01;
+                value.extend_from_slice(&(u32::from(ip) ^ MAGIC_COOKIE).to_be_bytes());
+            }
+            IpAddr::V6(ip) => {
+                value[1] = 0x02;
+                let mask = self.ipv6_mask();
+                value.extend(ip.octets().iter().zip(mask.iter()).map(|(octet, mask)| octet ^ mask));
+            }
+        }
+        self.add_attribute(ATTR_XOR_MAPPED_ADDRESS, value);
+    }
+
+    pub fn xor_mapped_address(&self) -> Option<SocketAddr> {
+        let value = self.attribute(ATTR_XOR_MAPPED_ADDRESS)?;
+        if value.len() < 4 {
+            return None;
+        }
+        let port = u16::from_be_bytes([value[2], value[3]]) ^ (MAGIC_COOKIE >> 16) as u16;
+        let ip = match (value[1], value.len()) {
+            (0x01, 8) => {
+                let xored = u32::from_be_bytes([value[4], value[5], value[6], value[7]]);
+                IpAddr::V4(Ipv4Addr::from(xored ^ MAGIC_COOKIE))
+            }
+            (0x02, 20) => {
+                let mut octets = [0u8; 16];
+                for (octet, (byte, mask)) in octets.iter_mut().zip(value[4..].iter().zip(self.ipv6_mask().iter())) {
+                    *octet = byte ^ mask;
+                }
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return None,
+        };
+        Some(SocketAddr::new(ip, port))
+    }
+
+    fn ipv6_mask(&self) -> [u8; 16] {
+        let mut mask = [0u8; 16];
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(&self.transaction_id);
+        mask
+    }
+
+    /// Encode the message, appending MESSAGE-INTEGRITY keyed with `integrity_key`
+    /// and a FINGERPRINT when asked
+    pub fn encode(&self, integrity_key: Option<&[u8]>, fingerprint: bool) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(128);
+        buf.extend_from_slice(&self.message_type.to_be_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf.extend_from_slice(&self.transaction_id);
+        for (kind, value) in &self.attributes {
+            push_attribute(&mut buf, *kind, value);
+        }
+
+        if let Some(key) = integrity_key {
+            // The length covers the integrity attribute while it is computed
+            set_length(&mut buf, 4 + INTEGRITY_LEN);
+            let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(&buf);
+            let tag = mac.finalize().into_bytes();
+            push_attribute(&mut buf, ATTR_MESSAGE_INTEGRITY, &tag);
+        }
+        if fingerprint {
+            set_length(&mut buf, 8);
+            let crc = CRC32.checksum(&buf) ^ FINGERPRINT_XOR;
+            push_attribute(&mut buf, ATTR_FINGERPRINT, &crc.to_be_bytes());
+        }
+        set_length(&mut buf, 0);
+        buf
+    }
+}
+
+fn push_attribute(buf: &mut Vec<u8>, kind: u16, value: &[u8]) {
+    buf.extend_from_slice(&kind.to_be_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+    buf.resize(buf.len() + (4 - value.len() % 4) % 4, 0);
+}
+
+/// Set the header length to the attributes encoded so far plus `pending` bytes
+fn set_length(buf: &mut [u8], pending: usize) {
+    let length = (buf.len() - HEADER_LEN + pending) as u16;
+    buf[2..4].copy_from_slice(&length.to_be_bytes());
+}
+
+/// Attributes of a raw message with the offset each starts at
+fn attributes(data: &[u8]) -> Result<Vec<(usize, u16, &[u8])>> {
+    let mut found = Vec::new();
+    let mut offset = HEADER_LEN;
+    while offset < data.len() {
+        if offset + 4 > data.len() {
+            return Err(Error::parse("Truncated STUN attribute"));
+        }
+        let kind = u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let start = offset + 4;
+        if start + length > data.len() {
+            return Err(Error::parse("Truncated STUN attribute"));
+        }
+        found.push((offset, kind, &data[start..start + length]));
+        offset = start + length + (4 - length % 4) % 4;
+    }
+    Ok(found)
+}
+
+/// Check the MESSAGE-INTEGRITY of a raw message against `key`. A message
+/// without the attribute fails.
+pub fn verify_integrity(data: &[u8], key: &[u8]) -> bool {
+    let Ok(found) = attributes(data) else {
+        return false;
+    };
+    let Some(&(offset, _, tag)) = found.iter().find(|(_, kind, _)| *kind == ATTR_MESSAGE_INTEGRITY) else {
+        return false;
+    };
+    let mut header = [0u8; HEADER_LEN];
+    header.copy_from_slice(&data[..HEADER_LEN]);
+    header[2..4].copy_from_slice(&((offset - HEADER_LEN + 4 + INTEGRITY_LEN) as u16).to_be_bytes());
+
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(&header);
+    mac.update(&data[HEADER_LEN..offset]);
+    mac.verify_slice(tag).is_ok()
+}
+
+/// Check the FINGERPRINT of a raw message, when it carries one
+pub fn verify_fingerprint(data: &[u8]) -> bool {
+    let Ok(found) = attributes(data) else {
+        return false;
+    };
+    match found.iter().find(|(_, kind, _)| *kind == ATTR_FINGERPRINT) {
+        Some(&(offset, _, value)) if value.len() == 4 => {
+            let expected = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+            CRC32.checksum(&data[..offset]) ^ FINGERPRINT_XOR == expected
+        }
+        Some(_) => false,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrity_and_fingerprint() {
+        let mut request = StunMessage::binding_request();
+        request.add_attribute(ATTR_USERNAME, b"local:remote".to_vec());
+        request.add_attribute(ATTR_USE_CANDIDATE, Vec::new());
+        let encoded = request.encode(Some(b"password"), true);
+
+        assert!(is_stun(&encoded));
+        assert!(verify_integrity(&encoded, b"password"));
+        assert!(!verify_integrity(&encoded, b"other"));
+        assert!(verify_fingerprint(&encoded));
+
+        let parsed = StunMessage::parse(&encoded).unwrap();
+        assert_eq!(parsed.message_type, BINDING_REQUEST);
+        assert_eq!(parsed.transaction_id, request.transaction_id);
+        assert_eq!(parsed.username(), Some("local:remote"));
+        assert!(parsed.has_attribute(ATTR_USE_CANDIDATE));
+
+        let mut tampered = encoded.clone();
+        tampered[HEADER_LEN + 5] ^= 1;
+        assert!(!verify_integrity(&tampered, b"password"));
+        assert!(!verify_fingerprint(&tampered));
+    }
+
+    #[test]
+    fn test_xor_mapped_address() {
+        for address in ["192.0.2.1:32853", "[2001:db8::1]:32853"] {
+            let address: SocketAddr = address.parse().unwrap();
+            let mut response = StunMessage::new(BINDING_SUCCESS, [7; 12]);
+            response.add_xor_mapped_address(address);
+            let parsed = StunMessage::parse(&response.encode(None, false)).unwrap();
+            assert_eq!(parsed.xor_mapped_address(), Some(address));
+        }
+        assert!(!is_stun(&[0x80; 40]));
+    }
+}
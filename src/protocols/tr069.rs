@@ -464,7 +464,7 @@ impl Tr069Service {
             if let Some(param) = data_model.get(&name) {
                 result.push(param.clone());
             } else {
-                return Err(Error::internal(format!("Parameter not found: {}", name)));
+                return Err(Error::invalid_config(format!("Parameter not found: {}", name)));
             }
         }
 
@@ -478,11 +478,11 @@ impl Tr069Service {
         for param in &parameters {
             if let Some(existing_param) = data_model.get_mut(&param.name) {
                 if !existing_param.writable {
-                    return Err(Error::internal(format!("Parameter is not writable: {}", param.name)));
+                    return Err(Error::not_supported(format!("Parameter is not writable: {}", param.name)));
                 }
                 existing_param.value = param.value.clone();
             } else {
-                return Err(Error::internal(format!("Parameter not found: {}", param.name)));
+                return Err(Error::invalid_config(format!("Parameter not found: {}", param.name)));
             }
         }
 
@@ -0,0 +1,392 @@
+//! T.38 IFP packets over UDPTL (ITU-T T.38)
+//!
+//! A fax relayed with T.38 is a series of IFP packets: indicators for the
+//! tones and modem trainings on the line, and the HDLC frames and image data
+//! the modems carry. IFP packets are ASN.1 aligned PER encoded as in T.38
+//! clause 8 and sent one per UDPTL datagram (clause 9.1), each datagram
+//! repeating the packets sent before it so that a lost one is recovered from
+//! the next to arrive. Only redundancy is sent; datagrams protected with FEC
+//! are read for their primary packet alone.
+
+use std::collections::VecDeque;
+
+use crate::{Error, Result};
+
+/// T.38 version the gateway offers; later versions only add extensions
+pub const T38_VERSION: u8 = 0;
+
+/// T.30 signal announced by an indicator packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum T30Indicator {
+    NoSignal,
+    Cng,
+    Ced,
+    V21Preamble,
+    V27_2400Training,
+    V27_4800Training,
+    V29_7200Training,
+    V29_9600Training,
+    V17_7200ShortTraining,
+    V17_7200LongTraining,
+    V17_9600ShortTraining,
+    V17_9600LongTraining,
+    V17_12000ShortTraining,
+    V17_12000LongTraining,
+    V17_14400ShortTraining,
+    V17_14400LongTraining,
+}
+
+impl T30Indicator {
+    const ALL: [T30Indicator; 16] = [
+        T30Indicator::NoSignal,
+        T30Indicator::Cng,
+        T30Indicator::Ced,
+        T30Indicator::V21Preamble,
+        T30Indicator::V27_2400Training,
+        T30Indicator::V27_4800Training,
+        T30Indicator::V29_7200Training,
+        T30Indicator::V29_9600Training,
+        T30Indicator::V17_7200ShortTraining,
+        T30Indicator::V17_7200LongTraining,
+        T30Indicator::V17_9600ShortTraining,
+        T30Indicator::V17_9600LongTraining,
+        T30Indicator::V17_12000ShortTraining,
+        T30Indicator::V17_12000LongTraining,
+        T30Indicator::V17_14400ShortTraining,
+        T30Indicator::V17_14400LongTraining,
+    ];
+}
+
+/// Modulation of a data packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    V21,
+    V27_2400,
+    V27_4800,
+    V29_7200,
+    V29_9600,
+    V17_7200,
+    V17_9600,
+    V17_12000,
+    V17_14400,
+}
+
+impl DataType {
+    const ALL: [DataType; 9] = [
+        DataType::V21,
+        DataType::V27_2400,
+        DataType::V27_4800,
+        DataType::V29_7200,
+        DataType::V29_9600,
+        DataType::V17_7200,
+        DataType::V17_9600,
+        DataType::V17_12000,
+        DataType::V17_14400,
+    ];
+}
+
+/// What one field of a data packet carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    HdlcData,
+    HdlcSigEnd,
+    HdlcFcsOk,
+    HdlcFcsBad,
+    HdlcFcsOkSigEnd,
+    HdlcFcsBadSigEnd,
+    T4NonEcmData,
+    T4NonEcmSigEnd,
+}
+
+impl FieldType {
+    const ALL: [FieldType; 8] = [
+        FieldType::HdlcData,
+        FieldType::HdlcSigEnd,
+        FieldType::HdlcFcsOk,
+        FieldType::HdlcFcsBad,
+        FieldType::HdlcFcsOkSigEnd,
+        FieldType::HdlcFcsBadSigEnd,
+        FieldType::T4NonEcmData,
+        FieldType::T4NonEcmSigEnd,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataField {
+    pub field_type: FieldType,
+    /// Empty for fields that only mark an event, e.g. FCS OK
+    pub data: Vec<u8>,
+}
+
+/// One T.38 Internet Facsimile Protocol packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfpPacket {
+    Indicator(T30Indicator),
+    Data { data_type: DataType, fields: Vec<DataField> },
+}
+
+fn code<T: Copy + PartialEq>(all: &[T], value: T) -> u8 {
+    all.iter().position(|known| *known == value).unwrap_or_default() as u8
+}
+
+fn from_code<T: Copy>(all: &[T], code: u8, what: &str) -> Result<T> {
+    all.get(code as usize)
+        .copied()
+        .ok_or_else(|| Error::parse(format!("Unknown T.38 {} {}", what, code)))
+}
+
+/// PER length determinant; lengths of 16K and over are fragmented, which
+/// no IFP packet that fits a datagram needs
+fn encode_length(out: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        out.extend_from_slice(&(0x8000 | (len & 0x3fff) as u16).to_be_bytes());
+    }
+}
+
+fn decode_length(data: &mut &[u8]) -> Result<usize> {
+    let (&first, rest) = data.split_first().ok_or_else(|| Error::parse("Truncated UDPTL length"))?;
+    *data = rest;
+    match first & 0xc0 {
+        0x00 | 0x40 => Ok(first as usize),
+        0x80 => {
+            let (&second, rest) = data.split_first().ok_or_else(|| Error::parse("Truncated UDPTL length"))?;
+            *data = rest;
+            Ok((((first & 0x3f) as usize) << 8) | second as usize)
+        }
+        _ => Err(Error::not_supported("Fragmented UDPTL length")),
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(Error::parse("Truncated UDPTL packet"));
+    }
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Ok(taken)
+}
+
+impl IfpPacket {
+    /// Field types gained an extension marker in T.38 version 1, which
+    /// moves them one bit along
+    fn field_shift(version: u8) -> u8 {
+        if version == 0 { 4 } else { 3 }
+    }
+
+    pub fn encode(&self, version: u8) -> Vec<u8> {
+        let (data_type, fields) = match self {
+            IfpPacket::Indicator(indicator) => return vec![code(&T30Indicator::ALL, *indicator) << 1],
+            IfpPacket::Data { data_type, fields } => (*data_type, fields),
+        };
+        let present = if fields.is_empty() { 0 } else { 0x80 };
+        let mut out = vec![present | 0x40 | (code(&DataType::ALL, data_type) << 1)];
+        if fields.is_empty() {
+            return out;
+        }
+        encode_length(&mut out, fields.len());
+        for field in fields {
+            let present = if field.data.is_empty() { 0 } else { 0x80 };
+            out.push(present | (code(&FieldType::ALL, field.field_type) << Self::field_shift(version)));
+            if !field.data.is_empty() {
+                out.extend_from_slice(&((field.data.len() - 1) as u16).to_be_bytes());
+                out.extend_from_slice(&field.data);
+            }
+        }
+        out
+    }
+
+    pub fn decode(mut data: &[u8], version: u8) -> Result<Self> {
+        let (&first, rest) = data.split_first().ok_or_else(|| Error::parse("Empty IFP packet"))?;
+        data = rest;
+        if first & 0x20 != 0 {
+            return Err(Error::not_supported("Extended T.38 indicator or data type"));
+        }
+        let type_code = (first >> 1) & 0x0f;
+        if first & 0x40 == 0 {
+            return from_code(&T30Indicator::ALL, type_code, "indicator").map(IfpPacket::Indicator);
+        }
+        let data_type = from_code(&DataType::ALL, type_code, "data type")?;
+        let mut fields = Vec::new();
+        if first & 0x80 != 0 {
+            for _ in 0..decode_length(&mut data)? {
+                let header = take(&mut data, 1)?[0];
+                if version > 0 && header & 0x40 != 0 {
+                    return Err(Error::not_supported("Extended T.38 field type"));
+                }
+                let field_type = from_code(&FieldType::ALL, (header >> Self::field_shift(version)) & 0x07, "field type")?;
+                let field_data = if header & 0x80 != 0 {
+                    let len = take(&mut data, 2)?;
+                    let len = u16::from_be_bytes([len[0], len[1]]) as usize + 1;
+                    take(&mut data, len)?.to_vec()
+                } else {
+                    Vec::new()
+                };
+                fields.push(DataField { field_type, data: field_data });
+            }
+        }
+        Ok(IfpPacket::Data { data_type, fields })
+    }
+}
+
+/// One UDPTL datagram: an encoded IFP packet and those sent before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdptlPacket {
+    pub sequence: u16,
+    pub primary: Vec<u8>,
+    /// Packets `sequence - 1`, `sequence - 2`, ... most recent first
+    pub secondary: Vec<Vec<u8>>,
+}
+
+impl UdptlPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.sequence.to_be_bytes().to_vec();
+        encode_length(&mut out, self.primary.len());
+        out.extend_from_slice(&self.primary);
+        // Error recovery CHOICE: secondary IFP packets
+        out.push(0x00);
+        encode_length(&mut out, self.secondary.len());
+        for packet in &self.secondary {
+            encode_length(&mut out, packet.len());
+            out.extend_from_slice(packet);
+        }
+        out
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self> {
+        let sequence = take(&mut data, 2)?;
+        let sequence = u16::from_be_bytes([sequence[0], sequence[1]]);
+        let len = decode_length(&mut data)?;
+        let primary = take(&mut data, len)?.to_vec();
+        let mut secondary = Vec::new();
+        // FEC-protected datagrams are read for their primary packet only
+        if take(&mut data, 1)?[0] & 0x80 == 0 {
+            for _ in 0..decode_length(&mut data)? {
+                let len = decode_length(&mut data)?;
+                secondary.push(take(&mut data, len)?.to_vec());
+            }
+        }
+        Ok(Self { sequence, primary, secondary })
+    }
+}
+
+/// Numbers outgoing IFP packets and repeats the latest ones in each datagram
+#[derive(Debug)]
+pub struct UdptlSender {
+    sequence: u16,
+    redundancy: usize,
+    max_datagram: usize,
+    /// Encoded packets already sent, most recent first
+    sent: VecDeque<Vec<u8>>,
+}
+
+impl UdptlSender {
+    pub fn new(redundancy: usize, max_datagram: usize) -> Self {
+        Self {
+            sequence: 0,
+            redundancy,
+            max_datagram,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Datagram carrying `ifp`, with as many earlier packets as fit
+    pub fn datagram(&mut self, ifp: Vec<u8>) -> Vec<u8> {
+        let mut packet = UdptlPacket {
+            sequence: self.sequence,
+            primary: ifp,
+            secondary: self.sent.iter().cloned().collect(),
+        };
+        let mut datagram = packet.encode();
+        while datagram.len() > self.max_datagram && packet.secondary.pop().is_some() {
+            datagram = packet.encode();
+        }
+        self.sent.push_front(packet.primary);
+        self.sent.truncate(self.redundancy);
+        self.sequence = self.sequence.wrapping_add(1);
+        datagram
+    }
+}
+
+/// Puts received IFP packets back in order, recovering lost ones from the
+/// redundancy of later datagrams and dropping repeats
+#[derive(Debug, Default)]
+pub struct UdptlReceiver {
+    expected: Option<u16>,
+    /// Packets lost beyond what redundancy recovered
+    pub lost: u64,
+    pub recovered: u64,
+}
+
+impl UdptlReceiver {
+    /// Encoded IFP packets of a datagram not delivered before, oldest first
+    pub fn receive(&mut self, mut packet: UdptlPacket) -> Vec<Vec<u8>> {
+        let expected = *self.expected.get_or_insert(packet.sequence);
+        let ahead = packet.sequence.wrapping_sub(expected);
+        if ahead >= 0x8000 {
+            // A repeat, or older than what was already delivered
+            return Vec::new();
+        }
+        let mut delivered = Vec::new();
+        for behind in (1..=ahead as usize).rev() {
+            match packet.secondary.get_mut(behind - 1) {
+                Some(ifp) => {
+                    self.recovered += 1;
+                    delivered.push(std::mem::take(ifp));
+                }
+                None => self.lost += 1,
+            }
+        }
+        delivered.push(packet.primary);
+        self.expected = Some(packet.sequence.wrapping_add(1));
+        delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ifp_encoding() {
+        let ced = IfpPacket::Indicator(T30Indicator::Ced);
+        assert_eq!(ced.encode(0), vec![0x04]);
+        assert_eq!(IfpPacket::decode(&[0x04], 0).unwrap(), ced);
+
+        let hdlc = IfpPacket::Data {
+            data_type: DataType::V21,
+            fields: vec![
+                DataField { field_type: FieldType::HdlcData, data: vec![0xff, 0x13, 0x80] },
+                DataField { field_type: FieldType::HdlcFcsOkSigEnd, data: Vec::new() },
+            ],
+        };
+        let encoded = hdlc.encode(0);
+        assert_eq!(encoded, vec![0xc0, 0x02, 0x80, 0x00, 0x02, 0xff, 0x13, 0x80, 0x40]);
+        assert_eq!(IfpPacket::decode(&encoded, 0).unwrap(), hdlc);
+        assert_eq!(hdlc.encode(2)[8], 0x20);
+        assert_eq!(IfpPacket::decode(&hdlc.encode(2), 2).unwrap(), hdlc);
+        assert!(IfpPacket::decode(&encoded[..6], 0).is_err());
+    }
+
+    #[test]
+    fn test_redundancy_recovers_lost_datagrams() {
+        let mut sender = UdptlSender::new(2, 400);
+        let datagrams: Vec<Vec<u8>> = (0..5u8).map(|n| sender.datagram(vec![n])).collect();
+        let decoded = UdptlPacket::decode(&datagrams[4]).unwrap();
+        assert_eq!((decoded.sequence, decoded.secondary.clone()), (4, vec![vec![3], vec![2]]));
+
+        let mut receiver = UdptlReceiver::default();
+        let mut received = Vec::new();
+        // Datagrams 1 to 3 are lost; 2 and 3 come back as redundancy of 4
+        for index in [0, 4, 4] {
+            received.extend(receiver.receive(UdptlPacket::decode(&datagrams[index]).unwrap()));
+        }
+        assert_eq!(received, vec![vec![0], vec![2], vec![3], vec![4]]);
+        assert_eq!((receiver.recovered, receiver.lost), (2, 1));
+
+        let mut small = UdptlSender::new(3, 8);
+        small.datagram(vec![0; 4]);
+        assert_eq!(UdptlPacket::decode(&small.datagram(vec![1; 4])).unwrap().secondary.len(), 0);
+    }
+}
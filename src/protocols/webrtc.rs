@@ -0,0 +1,646 @@
+//! WebRTC media transport: ICE-lite, DTLS-SRTP and rtcp-mux
+//!
+//! A browser leg runs RTP, RTCP, STUN and DTLS over one port. The relay
+//! answers the browser's ICE checks as a lite agent and always takes the
+//! passive DTLS role, so it is the DTLS server on every WebRTC leg. The
+//! handshake authenticates the peer by the certificate fingerprint
+//! signalled in SDP, and its exported keying material (RFC 5764) keys an
+//! SRTP session on the leg. Datagrams are demultiplexed by their first
+//! byte (RFC 7983); muxed RTCP is recognised and not relayed.
+//!
+//! DTLS is done with OpenSSL behind the `webrtc` feature. Without it the
+//! SDP handling remains, but no WebRTC leg can be set up.
+
+#[cfg(feature = "webrtc")]
+use std::collections::VecDeque;
+use std::fmt;
+#[cfg(feature = "webrtc")]
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+
+#[cfg(feature = "webrtc")]
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    srtp::SrtpProfileId,
+    ssl::{ErrorCode, Ssl, SslContext, SslMethod, SslOptions, SslStream, SslVerifyMode},
+    x509::{X509NameBuilder, X509Ref, X509},
+};
+
+use crate::protocols::ice::{IceCandidate, IceCredentials, IceLite};
+use crate::protocols::sdp::{MediaDescription, SdpLine, SessionDescription};
+#[cfg(feature = "webrtc")]
+use crate::protocols::srtp::{CryptoAttribute, SrtpKey, SrtpProfile};
+use crate::protocols::srtp::SrtpSession;
+use crate::protocols::stun;
+use crate::{Error, Result};
+
+#[cfg(feature = "webrtc")]
+const SRTP_PROFILES: &str = "SRTP_AES128_CM_SHA1_80:SRTP_AES128_CM_SHA1_32";
+#[cfg(feature = "webrtc")]
+const KEYING_LABEL: &str = "EXTRACTOR-dtls_srtp";
+#[cfg(feature = "webrtc")]
+const DTLS_MTU: u32 = 1200;
+#[cfg(feature = "webrtc")]
+const CERTIFICATE_DAYS: u32 = 30;
+
+/// Fingerprint hash functions of RFC 8122 §5 the relay can check
+const HASH_FUNCTIONS: &[&str] = &["sha-1", "sha-224", "sha-256", "sha-384", "sha-512", "md5"];
+
+/// Media-level attributes only a WebRTC stream carries
+const STREAM_ATTRIBUTES: &[&str] = &[
+    "ice-ufrag", "ice-pwd", "ice-options", "ice-lite", "fingerprint", "setup", "candidate",
+    "end-of-candidates", "rtcp-mux", "rtcp-rsize", "rtcp", "mid", "msid", "ssrc", "ssrc-group",
+    "rtcp-fb", "extmap", "extmap-allow-mixed", "crypto",
+];
+
+/// Session-level attributes only a WebRTC offer or answer carries
+const SESSION_ATTRIBUTES: &[&str] = &[
+    "group", "msid-semantic", "ice-lite", "ice-options", "ice-ufrag", "ice-pwd", "fingerprint",
+    "setup", "extmap-allow-mixed",
+];
+
+#[cfg(feature = "webrtc")]
+fn dtls_error(e: impl fmt::Display) -> Error {
+    Error::protocol(format!("DTLS: {}", e))
+}
+
+/// Whether a datagram received on a media port is a DTLS record
+pub fn is_dtls(data: &[u8]) -> bool {
+    matches!(data.first(), Some(20..=63))
+}
+
+/// Whether an RTP-range datagram is RTCP multiplexed onto the RTP port
+/// (RFC 5761 §4): RTCP packet types 192-223 land on payload types 64-95
+pub fn is_rtcp(data: &[u8]) -> bool {
+    data.len() >= 2 && matches!(data[0], 128..=191) && matches!(data[1] & 0x7F, 64..=95)
+}
+
+/// Certificate fingerprint as carried in `a=fingerprint`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Hash function name as written in SDP, lower case
+    pub algorithm: String,
+    pub digest: Vec<u8>,
+}
+
+impl Fingerprint {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (algorithm, digest) = value.trim().split_once(' ')?;
+        let digest = digest
+            .trim()
+            .split(':')
+            .map(|byte| u8::from_str_radix(byte, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let algorithm = algorithm.to_ascii_lowercase();
+        if !HASH_FUNCTIONS.contains(&algorithm.as_str()) {
+            return None;
+        }
+        Some(Self { algorithm, digest })
+    }
+
+    /// Fingerprint of `certificate` under `algorithm`
+    #[cfg(feature = "webrtc")]
+    pub fn of(certificate: &X509Ref, algorithm: &str) -> Result<Self> {
+        let digest = Self::message_digest(algorithm)
+            .ok_or_else(|| Error::not_supported(format!("Unsupported fingerprint hash {}", algorithm)))?;
+        let digest = certificate.digest(digest).map_err(dtls_error)?;
+        Ok(Self { algorithm: algorithm.to_string(), digest: digest.to_vec() })
+    }
+
+    #[cfg(feature = "webrtc")]
+    fn message_digest(algorithm: &str) -> Option<MessageDigest> {
+        MessageDigest::from_name(&algorithm.replace('-', ""))
+    }
+
+    #[cfg(feature = "webrtc")]
+    pub fn matches(&self, certificate: &X509Ref) -> bool {
+        Self::of(certificate, &self.algorithm).is_ok_and(|fingerprint| fingerprint == *self)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.algorithm)?;
+        for (index, byte) in self.digest.iter().enumerate() {
+            write!(f, "{}{:02X}", if index == 0 { ' ' } else { ':' }, byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// DTLS role signalled in `a=setup` (RFC 4145, RFC 5763)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DtlsSetup {
+    Active,
+    Passive,
+    Actpass,
+    Holdconn,
+}
+
+impl DtlsSetup {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "active" => Some(DtlsSetup::Active),
+            "passive" => Some(DtlsSetup::Passive),
+            "actpass" => Some(DtlsSetup::Actpass),
+            "holdconn" => Some(DtlsSetup::Holdconn),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DtlsSetup::Active => "active",
+            DtlsSetup::Passive => "passive",
+            DtlsSetup::Actpass => "actpass",
+            DtlsSetup::Holdconn => "holdconn",
+        }
+    }
+}
+
+/// Self-signed certificate the relay presents on DTLS legs
+#[cfg(feature = "webrtc")]
+pub struct DtlsIdentity {
+    context: SslContext,
+    fingerprint: Fingerprint,
+}
+
+#[cfg(feature = "webrtc")]
+impl DtlsIdentity {
+    /// Generate a P-256 key and certificate, fingerprinted with SHA-256
+    pub fn generate() -> Result<Self> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(dtls_error)?;
+        let key = PKey::from_ec_key(EcKey::generate(&group).map_err(dtls_error)?).map_err(dtls_error)?;
+
+        let certificate = (|| {
+            let mut name = X509NameBuilder::new()?;
+            name.append_entry_by_text("CN", "redfire-gateway")?;
+            let name = name.build();
+            let mut serial = BigNum::new()?;
+            serial.rand(64, MsbOption::MAYBE_ZERO, false)?;
+
+            let mut builder = X509::builder()?;
+            builder.set_version(2)?;
+            builder.set_serial_number(&serial.to_asn1_integer()?)?;
+            builder.set_subject_name(&name)?;
+            builder.set_issuer_name(&name)?;
+            builder.set_pubkey(&key)?;
+            builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+            builder.set_not_after(&Asn1Time::days_from_now(CERTIFICATE_DAYS)?)?;
+            builder.sign(&key, MessageDigest::sha256())?;
+            Ok::<_, openssl::error::ErrorStack>(builder.build())
+        })()
+        .map_err(dtls_error)?;
+
+        let mut context = SslContext::builder(SslMethod::dtls()).map_err(dtls_error)?;
+        context.set_certificate(&certificate).map_err(dtls_error)?;
+        context.set_private_key(&key).map_err(dtls_error)?;
+        context.check_private_key().map_err(dtls_error)?;
+        context.set_tlsext_use_srtp(SRTP_PROFILES).map_err(dtls_error)?;
+        // Peers are self-signed; they are checked against the SDP fingerprint instead
+        context.set_verify_callback(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT, |_, _| true);
+        context.set_options(SslOptions::NO_QUERY_MTU);
+
+        Ok(Self { fingerprint: Fingerprint::of(&certificate, "sha-256")?, context: context.build() })
+    }
+
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+}
+
+/// Datagrams in and out of an SSL stream that never touches a socket
+#[cfg(feature = "webrtc")]
+#[derive(Default)]
+struct DatagramChannel {
+    incoming: VecDeque<Vec<u8>>,
+    outgoing: Vec<Vec<u8>>,
+}
+
+#[cfg(feature = "webrtc")]
+impl Read for DatagramChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let datagram = self.incoming.pop_front().ok_or(io::ErrorKind::WouldBlock)?;
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "webrtc")]
+impl Write for DatagramChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Server side of a DTLS-SRTP association, fed one datagram at a time.
+/// Lost flights are recovered by the client's retransmissions.
+#[cfg(feature = "webrtc")]
+pub struct DtlsEndpoint {
+    stream: SslStream<DatagramChannel>,
+    remote_fingerprint: Fingerprint,
+    connected: bool,
+}
+
+/// Records to send back and, once the handshake completes, the SRTP session it keyed
+#[derive(Default)]
+pub struct DtlsOutput {
+    pub datagrams: Vec<Vec<u8>>,
+    pub srtp: Option<SrtpSession>,
+}
+
+#[cfg(feature = "webrtc")]
+impl DtlsEndpoint {
+    pub fn accept(identity: &DtlsIdentity, remote_fingerprint: Fingerprint) -> Result<Self> {
+        let mut ssl = Ssl::new(&identity.context).map_err(dtls_error)?;
+        ssl.set_mtu(DTLS_MTU).map_err(dtls_error)?;
+        ssl.set_accept_state();
+        let stream = SslStream::new(ssl, DatagramChannel::default()).map_err(dtls_error)?;
+        Ok(Self { stream, remote_fingerprint, connected: false })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn handle(&mut self, datagram: &[u8]) -> Result<DtlsOutput> {
+        self.stream.get_mut().incoming.push_back(datagram.to_vec());
+        let mut output = DtlsOutput::default();
+        if self.connected {
+            // Alerts or a retransmitted final flight; no application data is expected
+            let mut buf = [0u8; 1500];
+            let _ = self.stream.ssl_read(&mut buf);
+        } else {
+            match self.stream.do_handshake() {
+                Ok(()) => {
+                    self.connected = true;
+                    output.srtp = Some(self.srtp_session()?);
+                }
+                Err(e) if e.code() == ErrorCode::WANT_READ => {}
+                Err(e) => return Err(dtls_error(format!("handshake failed: {}", e))),
+            }
+        }
+        output.datagrams = std::mem::take(&mut self.stream.get_mut().outgoing);
+        Ok(output)
+    }
+
+    /// Check the peer against its signalled fingerprint and key SRTP from
+    /// the handshake: the client's key protects what it sends, the
+    /// server's what the relay sends
+    fn srtp_session(&self) -> Result<SrtpSession> {
+        let ssl = self.stream.ssl();
+        let certificate = ssl.peer_certificate().ok_or_else(|| dtls_error("peer sent no certificate"))?;
+        if !self.remote_fingerprint.matches(&certificate) {
+            return Err(dtls_error("peer certificate does not match the signalled fingerprint"));
+        }
+
+        let profile = match ssl.selected_srtp_profile().map(|profile| profile.id()) {
+            Some(id) if id == SrtpProfileId::SRTP_AES128_CM_SHA1_80 => SrtpProfile::AesCm128HmacSha1_80,
+            Some(id) if id == SrtpProfileId::SRTP_AES128_CM_SHA1_32 => SrtpProfile::AesCm128HmacSha1_32,
+            _ => return Err(Error::not_supported("No SRTP protection profile negotiated over DTLS")),
+        };
+        let (client, server) = exported_keys(ssl)?;
+        let local = CryptoAttribute { tag: 1, profile, key: server };
+        let remote = CryptoAttribute { tag: 1, profile, key: client };
+        Ok(SrtpSession::new(&local, &remote))
+    }
+}
+
+/// Client and server master keys exported from a DTLS-SRTP handshake,
+/// laid out as client key, server key, client salt, server salt
+#[cfg(feature = "webrtc")]
+fn exported_keys(ssl: &openssl::ssl::SslRef) -> Result<(SrtpKey, SrtpKey)> {
+    let mut material = [0u8; 2 * (16 + 14)];
+    ssl.export_keying_material(&mut material, KEYING_LABEL, None).map_err(dtls_error)?;
+    let key = |key: usize, salt: usize| {
+        let mut srtp_key = SrtpKey { master_key: [0; 16], master_salt: [0; 14] };
+        srtp_key.master_key.copy_from_slice(&material[key..key + 16]);
+        srtp_key.master_salt.copy_from_slice(&material[salt..salt + 14]);
+        srtp_key
+    };
+    Ok((key(0, 32), key(16, 46)))
+}
+
+/// Stand-in for the relay's certificate in builds without DTLS
+#[cfg(not(feature = "webrtc"))]
+pub enum DtlsIdentity {}
+
+#[cfg(not(feature = "webrtc"))]
+impl DtlsIdentity {
+    pub fn generate() -> Result<Self> {
+        Err(Error::not_supported("WebRTC legs require the `webrtc` feature"))
+    }
+
+    pub fn fingerprint(&self) -> &Fingerprint {
+        match *self {}
+    }
+}
+
+/// Stand-in for a DTLS association in builds without DTLS
+#[cfg(not(feature = "webrtc"))]
+pub enum DtlsEndpoint {}
+
+#[cfg(not(feature = "webrtc"))]
+impl DtlsEndpoint {
+    pub fn accept(identity: &DtlsIdentity, _remote_fingerprint: Fingerprint) -> Result<Self> {
+        match *identity {}
+    }
+
+    pub fn is_connected(&self) -> bool {
+        match *self {}
+    }
+
+    pub fn handle(&mut self, _datagram: &[u8]) -> Result<DtlsOutput> {
+        match *self {}
+    }
+}
+
+/// What a datagram on a WebRTC leg asks of its session
+#[derive(Default)]
+pub struct TransportOutput {
+    /// STUN responses and DTLS records to send back to the source
+    pub replies: Vec<Vec<u8>>,
+    /// SRTP session keyed by a completed handshake
+    pub srtp: Option<SrtpSession>,
+    /// Address media should now be sent to
+    pub latch: Option<SocketAddr>,
+}
+
+/// ICE and DTLS state of one WebRTC leg
+pub struct WebRtcTransport {
+    ice: IceLite,
+    dtls: DtlsEndpoint,
+}
+
+impl WebRtcTransport {
+    pub fn new(ice: IceLite, dtls: DtlsEndpoint) -> Self {
+        Self { ice, dtls }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.dtls.is_connected()
+    }
+
+    /// Handle a STUN or DTLS datagram from `source`. A valid check latches
+    /// the leg when nothing is latched yet; a nominating check always does.
+    pub fn handle(&mut self, data: &[u8], source: SocketAddr, latched: bool) -> Result<TransportOutput> {
+        let mut output = TransportOutput::default();
+        if stun::is_stun(data) {
+            if let Some(check) = self.ice.handle_request(data, source) {
+                output.replies.push(check.response);
+                if check.nominated || !latched {
+                    output.latch = Some(source);
+                }
+            }
+        } else if is_dtls(data) {
+            let dtls = self.dtls.handle(data)?;
+            output.replies = dtls.datagrams;
+            output.srtp = dtls.srtp;
+        }
+        Ok(output)
+    }
+}
+
+/// The far end's ICE and DTLS parameters from its SDP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteParameters {
+    pub ice: IceCredentials,
+    pub fingerprint: Fingerprint,
+    pub setup: DtlsSetup,
+}
+
+/// The relay's ICE and DTLS parameters toward a WebRTC leg
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalParameters {
+    pub ice: IceCredentials,
+    pub fingerprint: Fingerprint,
+    pub setup: DtlsSetup,
+    pub candidates: Vec<IceCandidate>,
+    /// Media ID echoed back to the browser
+    pub mid: Option<String>,
+}
+
+/// WebRTC parameters of a relayed call chosen at offer time, put to use on the answer
+#[derive(Debug, Clone, Default)]
+pub struct PendingWebRtc {
+    /// Leg A is a browser: its parameters and the relay's toward it
+    pub leg_a: Option<(RemoteParameters, LocalParameters)>,
+    /// Leg B is offered WebRTC with these parameters
+    pub leg_b: Option<LocalParameters>,
+}
+
+/// Whether a stream is DTLS-SRTP over ICE
+pub fn is_webrtc(stream: &MediaDescription) -> bool {
+    stream.protocol.eq_ignore_ascii_case("UDP/TLS/RTP/SAVPF") || stream.protocol.eq_ignore_ascii_case("UDP/TLS/RTP/SAVP")
+}
+
+/// Attribute of a stream, falling back to the session level
+fn stream_attribute<'a>(sdp: &'a SessionDescription, stream: &'a MediaDescription, name: &str) -> Option<&'a str> {
+    stream.attribute(name).or_else(|| {
+        sdp.lines
+            .iter()
+            .find(|line| line.attribute_name() == Some(name))
+            .map(|line| line.attribute_value().unwrap_or(""))
+    })
+}
+
+/// ICE credentials, fingerprint and DTLS role of a WebRTC stream
+pub fn remote_parameters(sdp: &SessionDescription, stream: &MediaDescription) -> Result<RemoteParameters> {
+    let required = |name: &str| {
+        stream_attribute(sdp, stream, name)
+            .ok_or_else(|| Error::not_supported(format!("WebRTC stream without a={}", name)))
+    };
+    let ice = IceCredentials::new(required("ice-ufrag")?, required("ice-pwd")?);
+    let fingerprint = Fingerprint::parse(required("fingerprint")?)
+        .ok_or_else(|| Error::not_supported("Unsupported DTLS fingerprint"))?;
+    // An endpoint that does not say is active (RFC 4145 §4)
+    let setup = stream_attribute(sdp, stream, "setup").and_then(DtlsSetup::parse).unwrap_or(DtlsSetup::Active);
+    Ok(RemoteParameters { ice, fingerprint, setup })
+}
+
+fn strip_session_attributes(sdp: &mut SessionDescription) {
+    sdp.lines
+        .retain(|line| !line.attribute_name().is_some_and(|name| SESSION_ATTRIBUTES.contains(&name)));
+}
+
+/// Make stream `index` a WebRTC stream carrying the relay's parameters
+pub fn webrtc_stream(sdp: &mut SessionDescription, index: usize, local: &LocalParameters) {
+    strip_session_attributes(sdp);
+    sdp.lines.push(SdpLine::new('a', "ice-lite"));
+    let Some(stream) = sdp.media.get_mut(index) else {
+        return;
+    };
+
+    stream.protocol = "UDP/TLS/RTP/SAVPF".to_string();
+    stream.lines.retain(|line| !line.attribute_name().is_some_and(|name| STREAM_ATTRIBUTES.contains(&name)));
+    let mut attributes = vec![
+        format!("ice-ufrag:{}", local.ice.ufrag),
+        format!("ice-pwd:{}", local.ice.pwd),
+        format!("fingerprint:{}", local.fingerprint),
+        format!("setup:{}", local.setup.as_str()),
+    ];
+    attributes.extend(local.mid.as_ref().map(|mid| format!("mid:{}", mid)));
+    attributes.push("rtcp-mux".to_string());
+    attributes.extend(local.candidates.iter().map(|candidate| format!("candidate:{}", candidate)));
+    attributes.push("end-of-candidates".to_string());
+    stream.lines.extend(attributes.into_iter().map(|value| SdpLine::new('a', value)));
+}
+
+/// Make stream `index` plain RTP/AVP, dropping its WebRTC attributes and
+/// the session's, for a leg that does not speak WebRTC
+pub fn strip_webrtc(sdp: &mut SessionDescription, index: usize) {
+    strip_session_attributes(sdp);
+    if let Some(stream) = sdp.media.get_mut(index) {
+        stream.protocol = "RTP/AVP".to_string();
+        stream.lines.retain(|line| !line.attribute_name().is_some_and(|name| STREAM_ATTRIBUTES.contains(&name)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "webrtc")]
+    fn client_stream(identity: &DtlsIdentity) -> SslStream<DatagramChannel> {
+        let mut ssl = Ssl::new(&identity.context).unwrap();
+        ssl.set_mtu(DTLS_MTU).unwrap();
+        ssl.set_connect_state();
+        SslStream::new(ssl, DatagramChannel::default()).unwrap()
+    }
+
+    #[cfg(feature = "webrtc")]
+    #[test]
+    fn test_dtls_srtp_handshake() {
+        let relay = DtlsIdentity::generate().unwrap();
+        let browser = DtlsIdentity::generate().unwrap();
+        let mut server = DtlsEndpoint::accept(&relay, browser.fingerprint().clone()).unwrap();
+        let mut client = client_stream(&browser);
+
+        let mut server_srtp = None;
+        for _ in 0..10 {
+            let _ = client.do_handshake();
+            for datagram in std::mem::take(&mut client.get_mut().outgoing) {
+                let output = server.handle(&datagram).unwrap();
+                server_srtp = server_srtp.or(output.srtp);
+                client.get_mut().incoming.extend(output.datagrams);
+            }
+            if server.is_connected() && client.do_handshake().is_ok() {
+                break;
+            }
+        }
+        assert!(server.is_connected());
+        let mut server_srtp = server_srtp.unwrap();
+
+        // The client keys its own session from the same export
+        assert!(relay.fingerprint().matches(&client.ssl().peer_certificate().unwrap()));
+        let (client_key, server_key) = exported_keys(client.ssl()).unwrap();
+        let profile = server_srtp.profile();
+        let mut client_srtp = SrtpSession::new(
+            &CryptoAttribute { tag: 1, profile, key: client_key },
+            &CryptoAttribute { tag: 1, profile, key: server_key },
+        );
+
+        let mut packet = vec![0x80, 0x00, 0x00, 0x01, 0, 0, 0, 160, 0x12, 0x34, 0x56, 0x78];
+        packet.extend_from_slice(&[0xD5; 160]);
+        let protected = server_srtp.protect(&packet).unwrap();
+        assert_eq!(client_srtp.unprotect(&protected).unwrap(), packet);
+        let protected = client_srtp.protect(&packet).unwrap();
+        assert_eq!(server_srtp.unprotect(&protected).unwrap(), packet);
+    }
+
+    #[cfg(feature = "webrtc")]
+    #[test]
+    fn test_fingerprint_check() {
+        let relay = DtlsIdentity::generate().unwrap();
+        let browser = DtlsIdentity::generate().unwrap();
+        let impostor = DtlsIdentity::generate().unwrap();
+        let mut server = DtlsEndpoint::accept(&relay, impostor.fingerprint().clone()).unwrap();
+        let mut client = client_stream(&browser);
+
+        let mut failed = false;
+        for _ in 0..10 {
+            let _ = client.do_handshake();
+            for datagram in std::mem::take(&mut client.get_mut().outgoing) {
+                match server.handle(&datagram) {
+                    Ok(output) => client.get_mut().incoming.extend(output.datagrams),
+                    Err(_) => failed = true,
+                }
+            }
+            if failed {
+                break;
+            }
+        }
+        assert!(failed);
+
+        let signalled = relay.fingerprint().to_string();
+        assert!(signalled.starts_with("sha-256 "));
+        assert_eq!(Fingerprint::parse(&signalled).as_ref(), Some(relay.fingerprint()));
+    }
+
+    #[test]
+    fn test_sdp() {
+        let offer = "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0\r\n\
+            a=msid-semantic: WMS\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111 0\r\nc=IN IP4 0.0.0.0\r\n\
+            a=rtcp:9 IN IP4 0.0.0.0\r\na=ice-ufrag:Brws\r\na=ice-pwd:browserpasswordbrowser\r\n\
+            a=fingerprint:sha-256 AB:CD:EF\r\na=setup:actpass\r\na=mid:0\r\na=sendrecv\r\na=rtcp-mux\r\n\
+            a=rtpmap:111 opus/48000/2\r\na=rtcp-fb:111 transport-cc\r\na=ssrc:1 cname:x\r\n";
+        let parsed = SessionDescription::parse(offer).unwrap();
+        let stream = &parsed.media[0];
+        assert!(is_webrtc(stream));
+        assert_eq!(Fingerprint::parse("md4 00:11"), None);
+        assert_eq!(
+            remote_parameters(&parsed, stream).unwrap(),
+            RemoteParameters {
+                ice: IceCredentials::new("Brws", "browserpasswordbrowser"),
+                fingerprint: Fingerprint { algorithm: "sha-256".to_string(), digest: vec![0xAB, 0xCD, 0xEF] },
+                setup: DtlsSetup::Actpass,
+            }
+        );
+
+        let mut plain = parsed.clone();
+        strip_webrtc(&mut plain, 0);
+        let plain = plain.to_string();
+        assert!(plain.contains("m=audio 9 RTP/AVP 111 0\r\n"));
+        for attribute in ["group", "msid-semantic", "ice-", "fingerprint", "setup", "mid", "rtcp", "ssrc"] {
+            assert!(!plain.contains(&format!("a={}", attribute)), "{} left in {}", attribute, plain);
+        }
+        assert!(plain.contains("a=sendrecv\r\na=rtpmap:111 opus/48000/2\r\n"));
+
+        let mut answer = SessionDescription::parse(&plain).unwrap();
+        let local = LocalParameters {
+            ice: IceCredentials::new("relayufr", "relaypasswordrelaypasswo"),
+            fingerprint: Fingerprint { algorithm: "sha-256".to_string(), digest: vec![1, 2] },
+            setup: DtlsSetup::Passive,
+            candidates: vec![IceCandidate::host("192.0.2.10:20000".parse().unwrap())],
+            mid: Some("0".to_string()),
+        };
+        webrtc_stream(&mut answer, 0, &local);
+        let answer = answer.to_string();
+        assert!(answer.contains("t=0 0\r\na=ice-lite\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111 0\r\n"));
+        assert!(answer.contains(
+            "a=ice-ufrag:relayufr\r\na=ice-pwd:relaypasswordrelaypasswo\r\na=fingerprint:sha-256 01:02\r\n\
+             a=setup:passive\r\na=mid:0\r\na=rtcp-mux\r\n\
+             a=candidate:1 1 udp 2130706431 192.0.2.10 20000 typ host\r\na=end-of-candidates\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_demux() {
+        assert!(is_dtls(&[22, 254, 253]));
+        assert!(!is_dtls(&[0x80, 0]));
+        assert!(is_rtcp(&[0x81, 200, 0, 6]));
+        assert!(!is_rtcp(&[0x80, 0, 0, 1]));
+        assert!(!is_rtcp(&[0x80, 111, 0, 1]));
+    }
+}
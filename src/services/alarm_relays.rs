@@ -0,0 +1,283 @@
+//! Bay alarm panel outputs
+//!
+//! Cabinet installations wire each equipment shelf to the bay alarm panel
+//! with one dry contact per severity: critical, major and minor. Each
+//! configured output is a relay on a GPIO line, energised while an alarm of
+//! its severity is active and released once the last one clears. Lines are
+//! driven through sysfs, exported on first use, or held open on a GPIO
+//! character device. Outputs wired to normally-closed contacts are set
+//! active-low, so that an unpowered unit shows as alarmed.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::{AlarmRelayConfig, AlarmRelayOutput, GpioDriver, RelaySeverity};
+use crate::services::alarms::{Alarm, AlarmManager, AlarmSeverity, AlarmState};
+use crate::{Error, Result};
+
+/// A GPIO line driving one relay
+pub trait RelayLine: Send {
+    /// Set the electrical level of the line
+    fn set_level(&mut self, high: bool) -> Result<()>;
+}
+
+/// Line under `/sys/class/gpio`
+pub struct SysfsLine {
+    value_path: PathBuf,
+}
+
+impl SysfsLine {
+    /// Export `gpio` under `root` if needed and make it an output
+    pub fn open(root: &Path, gpio: u32) -> Result<Self> {
+        let line_dir = root.join(format!("gpio{}", gpio));
+        if !line_dir.exists() {
+            std::fs::write(root.join("export"), gpio.to_string())
+                .map_err(|e| Error::invalid_config(format!("Cannot export GPIO {}: {}", gpio, e)))?;
+        }
+        std::fs::write(line_dir.join("direction"), "out")
+            .map_err(|e| Error::invalid_config(format!("Cannot make GPIO {} an output: {}", gpio, e)))?;
+        Ok(Self { value_path: line_dir.join("value") })
+    }
+}
+
+impl RelayLine for SysfsLine {
+    fn set_level(&mut self, high: bool) -> Result<()> {
+        std::fs::write(&self.value_path, if high { "1" } else { "0" })?;
+        Ok(())
+    }
+}
+
+/// `struct gpiohandle_request` of the GPIO character device ABI
+#[repr(C)]
+struct GpioHandleRequest {
+    line_offsets: [u32; 64],
+    flags: u32,
+    default_values: [u8; 64],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: libc::c_int,
+}
+
+/// `struct gpiohandle_data`
+#[repr(C)]
+struct GpioHandleData {
+    values: [u8; 64],
+}
+
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+/// `_IOWR(0xB4, 0x03, struct gpiohandle_request)`
+const GPIO_GET_LINEHANDLE_IOCTL: u32 = 0xC16C_B403;
+/// `_IOWR(0xB4, 0x09, struct gpiohandle_data)`
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: u32 = 0xC040_B409;
+
+const CONSUMER_LABEL: &[u8] = b"redfire-alarm";
+
+/// Line on a GPIO character device, requested as an output and held until
+/// dropped
+pub struct GpiodLine {
+    handle: File,
+}
+
+impl GpiodLine {
+    pub fn open(chip: &Path, offset: u32, initial_high: bool) -> Result<Self> {
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let chip_file = File::open(chip)
+            .map_err(|e| Error::invalid_config(format!("Cannot open GPIO chip {}: {}", chip.display(), e)))?;
+        let mut request = GpioHandleRequest {
+            line_offsets: [0; 64],
+            flags: GPIOHANDLE_REQUEST_OUTPUT,
+            default_values: [0; 64],
+            consumer_label: [0; 32],
+            lines: 1,
+            fd: -1,
+        };
+        request.line_offsets[0] = offset;
+        request.default_values[0] = u8::from(initial_high);
+        request.consumer_label[..CONSUMER_LABEL.len()].copy_from_slice(CONSUMER_LABEL);
+
+        // SAFETY: the chip descriptor is open for the duration of the call and
+        // `request` is a fully initialised gpiohandle_request the kernel fills in
+        if unsafe { libc::ioctl(chip_file.as_raw_fd(), GPIO_GET_LINEHANDLE_IOCTL as _, &mut request) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(Error::invalid_config(format!("Cannot request line {} of {}: {}", offset, chip.display(), e)));
+        }
+        // SAFETY: the kernel returned a new descriptor that nothing else owns
+        let handle = unsafe { File::from_raw_fd(request.fd) };
+        Ok(Self { handle })
+    }
+}
+
+impl RelayLine for GpiodLine {
+    fn set_level(&mut self, high: bool) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut data = GpioHandleData { values: [0; 64] };
+        data.values[0] = u8::from(high);
+        // SAFETY: the line handle is open and `data` is a gpiohandle_data
+        if unsafe { libc::ioctl(self.handle.as_raw_fd(), GPIOHANDLE_SET_LINE_VALUES_IOCTL as _, &mut data) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
+
+/// Whether an alarm of `severity` is signalled on a `relay` output
+fn signals(relay: RelaySeverity, severity: &AlarmSeverity) -> bool {
+    matches!(
+        (relay, severity),
+        (RelaySeverity::Critical, AlarmSeverity::Critical)
+            | (RelaySeverity::Major, AlarmSeverity::Major)
+            | (RelaySeverity::Minor, AlarmSeverity::Minor)
+    )
+}
+
+struct RelayOutput {
+    config: AlarmRelayOutput,
+    line: Box<dyn RelayLine>,
+    /// Last state written; `None` until the first update
+    energised: Option<bool>,
+}
+
+/// Relay outputs following the active alarms
+pub struct AlarmRelays {
+    outputs: Vec<RelayOutput>,
+    poll_interval: Duration,
+}
+
+impl AlarmRelays {
+    /// Open every configured output line
+    pub fn open(config: &AlarmRelayConfig) -> Result<Self> {
+        let mut lines: Vec<(AlarmRelayOutput, Box<dyn RelayLine>)> = Vec::new();
+        for output in &config.outputs {
+            let line: Box<dyn RelayLine> = match output.driver {
+                GpioDriver::Sysfs => Box::new(SysfsLine::open(&config.sysfs_root, output.line)?),
+                GpioDriver::Gpiod => {
+                    let chip = output
+                        .chip
+                        .as_deref()
+                        .ok_or_else(|| Error::invalid_config(format!("Alarm relay line {} names no chip", output.line)))?;
+                    // Requested released; the first update sets the real state
+                    Box::new(GpiodLine::open(chip, output.line, output.active_low)?)
+                }
+            };
+            lines.push((output.clone(), line));
+        }
+        Ok(Self::with_lines(lines, Duration::from_millis(config.poll_interval_ms)))
+    }
+
+    /// Outputs on lines opened elsewhere
+    pub fn with_lines(lines: Vec<(AlarmRelayOutput, Box<dyn RelayLine>)>, poll_interval: Duration) -> Self {
+        Self {
+            outputs: lines
+                .into_iter()
+                .map(|(config, line)| RelayOutput { config, line, energised: None })
+                .collect(),
+            poll_interval,
+        }
+    }
+
+    /// Energise the outputs whose severity has an active alarm and release
+    /// the others; suppressed alarms are not signalled, and lines are only
+    /// written when their state changes
+    pub fn update(&mut self, alarms: &[Alarm]) -> Result<()> {
+        for output in &mut self.outputs {
+            let energised = alarms.iter().any(|alarm| {
+                signals(output.config.severity, &alarm.severity)
+                    && alarm.state != AlarmState::Suppressed
+                    && (output.config.include_acknowledged || alarm.acknowledged_time.is_none())
+            });
+            if output.energised == Some(energised) {
+                continue;
+            }
+            output.line.set_level(energised != output.config.active_low)?;
+            output.energised = Some(energised);
+            info!(
+                "{:?} alarm relay on line {} {}",
+                output.config.severity,
+                output.config.line,
+                if energised { "energised" } else { "released" }
+            );
+        }
+        Ok(())
+    }
+
+    /// Follow `alarm_manager` until the task is aborted
+    pub fn spawn(mut self, alarm_manager: Arc<AlarmManager>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.poll_interval);
+            loop {
+                ticker.tick().await;
+                let alarms = alarm_manager.get_active_alarms().await;
+                if let Err(e) = self.update(&alarms) {
+                    warn!("Cannot drive alarm relays: {}", e);
+                    // Write every line again on the next poll
+                    for output in &mut self.outputs {
+                        output.energised = None;
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::alarms::{AlarmConfig, AlarmSource, AlarmType};
+
+    fn output(severity: RelaySeverity, line: u32, active_low: bool) -> AlarmRelayOutput {
+        AlarmRelayOutput {
+            severity,
+            driver: GpioDriver::Sysfs,
+            chip: None,
+            line,
+            active_low,
+            include_acknowledged: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relays_follow_alarms() {
+        let root = std::env::temp_dir().join(format!("redfire-gpio-{:x}", rand::random::<u64>()));
+        for gpio in [20, 21] {
+            std::fs::create_dir_all(root.join(format!("gpio{}", gpio))).unwrap();
+        }
+        let config = AlarmRelayConfig {
+            enabled: true,
+            poll_interval_ms: 1000,
+            sysfs_root: root.clone(),
+            outputs: vec![output(RelaySeverity::Critical, 20, false), output(RelaySeverity::Major, 21, true)],
+        };
+        let mut relays = AlarmRelays::open(&config).unwrap();
+        let value = |gpio: u32| std::fs::read_to_string(root.join(format!("gpio{}/value", gpio))).unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("gpio20/direction")).unwrap(), "out");
+
+        relays.update(&[]).unwrap();
+        assert_eq!((value(20).as_str(), value(21).as_str()), ("0", "1"));
+
+        let manager = AlarmManager::new(AlarmConfig::default());
+        let source = AlarmSource { component: "span".to_string(), instance: "1".to_string(), location: None };
+        let id = manager
+            .raise_alarm(AlarmSeverity::Major, AlarmType::Communication, source, "Loss of signal".to_string(), None, None, None)
+            .await
+            .unwrap();
+        relays.update(&manager.get_active_alarms().await).unwrap();
+        assert_eq!((value(20).as_str(), value(21).as_str()), ("0", "0"));
+
+        // Acknowledged alarms release outputs that leave them out
+        manager.acknowledge_alarm(&id, "noc".to_string()).await.unwrap();
+        relays.update(&manager.get_active_alarms().await).unwrap();
+        assert_eq!(value(21), "1");
+
+        // A missing line that cannot be exported is a configuration error
+        let missing = AlarmRelayConfig { outputs: vec![output(RelaySeverity::Minor, 99, false)], ..config };
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(AlarmRelays::open(&missing).is_err());
+    }
+}
@@ -234,7 +234,7 @@ impl AlarmManager {
             // Check limits
             if active_alarms.len() >= self.config.max_active_alarms {
                 warn!("Maximum active alarms limit reached: {}", self.config.max_active_alarms);
-                return Err(Error::internal("Maximum active alarms limit reached"));
+                return Err(Error::resource_exhausted("Maximum active alarms limit reached"));
             }
             
             active_alarms.insert(alarm_id.clone(), alarm.clone());
@@ -275,7 +275,7 @@ impl AlarmManager {
                 
                 alarm
             } else {
-                return Err(Error::internal(format!("Alarm not found: {}", alarm_id)));
+                return Err(Error::invalid_state(format!("Alarm not found: {}", alarm_id)));
             }
         };
 
@@ -304,7 +304,7 @@ impl AlarmManager {
                 alarm.acknowledged_time = Some(now);
                 alarm.acknowledged_by = Some(acknowledged_by.clone());
             } else {
-                return Err(Error::internal(format!("Alarm not found: {}", alarm_id)));
+                return Err(Error::invalid_state(format!("Alarm not found: {}", alarm_id)));
             }
         }
 
@@ -329,7 +329,7 @@ impl AlarmManager {
             if let Some(alarm) = active_alarms.get_mut(alarm_id) {
                 alarm.state = AlarmState::Suppressed;
             } else {
-                return Err(Error::internal(format!("Alarm not found: {}", alarm_id)));
+                return Err(Error::invalid_state(format!("Alarm not found: {}", alarm_id)));
             }
         }
 
@@ -0,0 +1,425 @@
+//! Answer supervision for SIP and TDM call legs
+//!
+//! This module separates real answer from early media in both call
+//! directions. SIP 180/183 and Q.931 ALERTING/PROGRESS with in-band
+//! information are tracked as early media; only SIP 200 OK to INVITE and
+//! Q.931 CONNECT count as answer. Timestamps are taken from the moment the
+//! signal was received, not when it was processed, so CDR answer times stay
+//! within the accuracy dialer customers audit against.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, info, warn};
+
+use crate::services::cdr::{CdrService, DisconnectReason};
+use crate::utils::ClockStamp;
+use crate::Result;
+
+/// Maximum tolerated skew between signal receipt and the recorded answer time
+pub const ANSWER_TIMESTAMP_TOLERANCE: Duration = Duration::from_millis(50);
+
+/// Q.931 progress indicator: in-band information or appropriate pattern now available
+pub const Q931_PI_INBAND_AVAILABLE: u8 = 8;
+
+/// Direction of the call through the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallDirection {
+    /// SIP-originated call terminating on a TDM span
+    SipToTdm,
+    /// TDM-originated call terminating on a SIP trunk
+    TdmToSip,
+    /// SIP-originated call relayed to another SIP trunk
+    SipToSip,
+}
+
+/// Supervision signal observed on the terminating leg
+#[derive(Debug, Clone, PartialEq)]
+pub enum SupervisionSignal {
+    /// SIP provisional response (180/183), with or without SDP
+    SipProvisional { status_code: u16, has_sdp: bool },
+    /// SIP 200 OK to the initial INVITE
+    SipAnswer,
+    /// Q.931 ALERTING, optionally carrying a progress indicator
+    Q931Alerting { progress_indicator: Option<u8> },
+    /// Q.931 PROGRESS
+    Q931Progress { progress_indicator: u8 },
+    /// Q.931 CONNECT
+    Q931Connect,
+}
+
+/// Supervision state of a call
+#[derive(Debug, Clone, PartialEq)]
+pub enum SupervisionState {
+    Proceeding,
+    Ringing,
+    EarlyMedia,
+    Answered,
+}
+
+/// Result from an answering machine detection hook
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmdResult {
+    Human,
+    Machine,
+    Unknown,
+}
+
+/// Hook invoked on answer so an external answering machine detector can
+/// classify the called party
+#[async_trait::async_trait]
+pub trait AnswerMachineDetector: Send + Sync {
+    async fn on_answer(&self, call_id: &str, answered_at: DateTime<Utc>) -> AmdResult;
+}
+
+/// Per-call supervision record
+#[derive(Debug, Clone)]
+pub struct SupervisedCall {
+    pub call_id: String,
+    pub direction: CallDirection,
+    pub state: SupervisionState,
+    pub cdr_id: Option<String>,
+    pub setup_at: DateTime<Utc>,
+    /// Monotonic reading of `setup_at`, for post-dial delay
+    pub setup_instant: Instant,
+    pub ringing_at: Option<DateTime<Utc>>,
+    pub early_media_at: Option<DateTime<Utc>>,
+    pub answered_at: Option<DateTime<Utc>>,
+    pub amd_result: Option<AmdResult>,
+}
+
+/// Answer supervision events
+#[derive(Debug, Clone)]
+pub enum SupervisionEvent {
+    Ringing {
+        call_id: String,
+        at: DateTime<Utc>,
+    },
+    EarlyMedia {
+        call_id: String,
+        at: DateTime<Utc>,
+    },
+    Answered {
+        call_id: String,
+        at: DateTime<Utc>,
+        post_dial_delay: Duration,
+    },
+    AmdCompleted {
+        call_id: String,
+        result: AmdResult,
+    },
+    TimestampSkew {
+        call_id: String,
+        skew: Duration,
+    },
+}
+
+/// Answer supervision service
+pub struct AnswerSupervisor {
+    calls: Arc<DashMap<String, SupervisedCall>>,
+    cdr_service: Option<Arc<CdrService>>,
+    amd_hook: Arc<RwLock<Option<Arc<dyn AnswerMachineDetector>>>>,
+    event_tx: mpsc::UnboundedSender<SupervisionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<SupervisionEvent>>,
+}
+
+impl AnswerSupervisor {
+    pub fn new() -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        Self {
+            calls: Arc::new(DashMap::new()),
+            cdr_service: None,
+            amd_hook: Arc::new(RwLock::new(None)),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<SupervisionEvent>> {
+        self.event_rx.take()
+    }
+
+    pub fn set_cdr_service(&mut self, cdr_service: Arc<CdrService>) {
+        self.cdr_service = Some(cdr_service);
+    }
+
+    /// CDR service answer times are written to, for opening the record
+    /// passed to `track_call`
+    pub fn cdr_service(&self) -> Option<&Arc<CdrService>> {
+        self.cdr_service.as_ref()
+    }
+
+    /// Register an answering machine detection hook
+    pub async fn set_amd_hook(&self, hook: Arc<dyn AnswerMachineDetector>) {
+        *self.amd_hook.write().await = Some(hook);
+    }
+
+    pub async fn clear_amd_hook(&self) {
+        *self.amd_hook.write().await = None;
+    }
+
+    /// Start supervising a call at setup time
+    pub fn track_call(&self, call_id: &str, direction: CallDirection, cdr_id: Option<String>) {
+        let setup = ClockStamp::now();
+        self.calls.insert(call_id.to_string(), SupervisedCall {
+            call_id: call_id.to_string(),
+            direction,
+            state: SupervisionState::Proceeding,
+            cdr_id,
+            setup_at: setup.utc,
+            setup_instant: setup.instant,
+            ringing_at: None,
+            early_media_at: None,
+            answered_at: None,
+            amd_result: None,
+        });
+    }
+
+    /// Stop supervising a call released with Q.850 `cause`, closing its CDR
+    pub fn release_call(&self, call_id: &str, cause: u16) -> Option<SupervisedCall> {
+        let (_, call) = self.calls.remove(call_id)?;
+        if let (Some(cdr), Some(cdr_id)) = (self.cdr_service.clone(), call.cdr_id.clone()) {
+            let ended = ClockStamp::now();
+            tokio::spawn(async move {
+                if let Err(e) = cdr.finalize_call_record(&cdr_id, ended, DisconnectReason::from_q850(cause)).await {
+                    warn!("Failed to finalize CDR {}: {}", cdr_id, e);
+                }
+            });
+        }
+        Some(call)
+    }
+
+    pub fn get_call(&self, call_id: &str) -> Option<SupervisedCall> {
+        self.calls.get(call_id).map(|entry| entry.value().clone())
+    }
+
+    /// Classify a signal without applying it
+    pub fn classify(signal: &SupervisionSignal) -> SupervisionState {
+        match signal {
+            SupervisionSignal::SipProvisional { status_code: 183, has_sdp: true } => SupervisionState::EarlyMedia,
+            SupervisionSignal::SipProvisional { status_code: 180, has_sdp: true } => SupervisionState::EarlyMedia,
+            SupervisionSignal::SipProvisional { .. } => SupervisionState::Ringing,
+            SupervisionSignal::Q931Alerting { progress_indicator: Some(Q931_PI_INBAND_AVAILABLE) } => SupervisionState::EarlyMedia,
+            SupervisionSignal::Q931Alerting { .. } => SupervisionState::Ringing,
+            SupervisionSignal::Q931Progress { progress_indicator: Q931_PI_INBAND_AVAILABLE } => SupervisionState::EarlyMedia,
+            SupervisionSignal::Q931Progress { .. } => SupervisionState::Proceeding,
+            SupervisionSignal::SipAnswer | SupervisionSignal::Q931Connect => SupervisionState::Answered,
+        }
+    }
+
+    /// Apply a supervision signal.
+    ///
+    /// `received_at` must be captured where the message came off the wire
+    /// (SIP socket read or Q.931 frame decode). Early media never moves the
+    /// call to answered; an answer is only recorded once.
+    pub async fn on_signal(
+        &self,
+        call_id: &str,
+        signal: SupervisionSignal,
+        received_at: DateTime<Utc>,
+        received_instant: Instant,
+    ) -> Result<SupervisionState> {
+        let new_state = Self::classify(&signal);
+
+        let (state, answered, cdr_id, post_dial_delay) = {
+            let mut call = match self.calls.get_mut(call_id) {
+                Some(call) => call,
+                None => {
+                    debug!("Supervision signal for untracked call {}", call_id);
+                    return Ok(new_state);
+                }
+            };
+
+            if call.state == SupervisionState::Answered {
+                return Ok(SupervisionState::Answered);
+            }
+
+            let mut answered = false;
+            match new_state {
+                SupervisionState::Ringing => {
+                    call.ringing_at.get_or_insert(received_at);
+                    if call.state != SupervisionState::EarlyMedia {
+                        call.state = SupervisionState::Ringing;
+                    }
+                }
+                SupervisionState::EarlyMedia => {
+                    call.early_media_at.get_or_insert(received_at);
+                    call.state = SupervisionState::EarlyMedia;
+                }
+                SupervisionState::Answered => {
+                    call.answered_at = Some(received_at);
+                    call.state = SupervisionState::Answered;
+                    answered = true;
+                }
+                SupervisionState::Proceeding => {}
+            }
+
+            let post_dial_delay = received_instant.saturating_duration_since(call.setup_instant);
+
+            (call.state.clone(), answered, call.cdr_id.clone(), post_dial_delay)
+        };
+
+        match state {
+            SupervisionState::Ringing if !answered => {
+                let _ = self.event_tx.send(SupervisionEvent::Ringing {
+                    call_id: call_id.to_string(),
+                    at: received_at,
+                });
+            }
+            SupervisionState::EarlyMedia => {
+                let _ = self.event_tx.send(SupervisionEvent::EarlyMedia {
+                    call_id: call_id.to_string(),
+                    at: received_at,
+                });
+                if let (Some(cdr), Some(cdr_id)) = (&self.cdr_service, &cdr_id) {
+                    cdr.update_early_media(cdr_id, received_at).await?;
+                }
+            }
+            _ => {}
+        }
+
+        if answered {
+            let skew = received_instant.elapsed();
+            if skew > ANSWER_TIMESTAMP_TOLERANCE {
+                warn!("Answer for call {} processed {:?} after receipt", call_id, skew);
+                let _ = self.event_tx.send(SupervisionEvent::TimestampSkew {
+                    call_id: call_id.to_string(),
+                    skew,
+                });
+            }
+
+            if let (Some(cdr), Some(cdr_id)) = (&self.cdr_service, &cdr_id) {
+                cdr.update_call_answered_at(cdr_id, ClockStamp::new(received_at, received_instant)).await?;
+            }
+
+            let _ = self.event_tx.send(SupervisionEvent::Answered {
+                call_id: call_id.to_string(),
+                at: received_at,
+                post_dial_delay,
+            });
+            info!("Call {} answered (post-dial delay {:?})", call_id, post_dial_delay);
+
+            self.run_amd(call_id, received_at).await;
+        }
+
+        Ok(state)
+    }
+
+    async fn run_amd(&self, call_id: &str, answered_at: DateTime<Utc>) {
+        let hook = self.amd_hook.read().await.clone();
+        let hook = match hook {
+            Some(hook) => hook,
+            None => return,
+        };
+
+        let calls = Arc::clone(&self.calls);
+        let event_tx = self.event_tx.clone();
+        let call_id = call_id.to_string();
+
+        // Detection listens to the first seconds of audio, so never block signaling on it
+        tokio::spawn(async move {
+            let result = hook.on_answer(&call_id, answered_at).await;
+            if let Some(mut call) = calls.get_mut(&call_id) {
+                call.amd_result = Some(result.clone());
+            }
+            let _ = event_tx.send(SupervisionEvent::AmdCompleted { call_id, result });
+        });
+    }
+}
+
+impl Default for AnswerSupervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_classification() {
+        assert_eq!(
+            AnswerSupervisor::classify(&SupervisionSignal::SipProvisional { status_code: 180, has_sdp: false }),
+            SupervisionState::Ringing
+        );
+        assert_eq!(
+            AnswerSupervisor::classify(&SupervisionSignal::SipProvisional { status_code: 183, has_sdp: true }),
+            SupervisionState::EarlyMedia
+        );
+        assert_eq!(
+            AnswerSupervisor::classify(&SupervisionSignal::Q931Progress { progress_indicator: 8 }),
+            SupervisionState::EarlyMedia
+        );
+        assert_eq!(
+            AnswerSupervisor::classify(&SupervisionSignal::Q931Connect),
+            SupervisionState::Answered
+        );
+    }
+
+    #[tokio::test]
+    async fn test_early_media_is_not_answer() {
+        let supervisor = AnswerSupervisor::new();
+        supervisor.track_call("call-1", CallDirection::SipToTdm, None);
+
+        let state = supervisor
+            .on_signal("call-1", SupervisionSignal::Q931Alerting { progress_indicator: Some(8) }, Utc::now(), Instant::now())
+            .await
+            .unwrap();
+        assert_eq!(state, SupervisionState::EarlyMedia);
+
+        let call = supervisor.get_call("call-1").unwrap();
+        assert!(call.answered_at.is_none());
+        assert!(call.early_media_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_answer_uses_receipt_timestamp() {
+        let supervisor = AnswerSupervisor::new();
+        supervisor.track_call("call-2", CallDirection::TdmToSip, None);
+
+        let received_at = Utc::now() - chrono::Duration::milliseconds(20);
+        supervisor
+            .on_signal("call-2", SupervisionSignal::SipAnswer, received_at, Instant::now())
+            .await
+            .unwrap();
+
+        // A later duplicate answer must not move the timestamp
+        supervisor
+            .on_signal("call-2", SupervisionSignal::SipAnswer, Utc::now(), Instant::now())
+            .await
+            .unwrap();
+
+        let call = supervisor.get_call("call-2").unwrap();
+        assert_eq!(call.answered_at, Some(received_at));
+        assert_eq!(call.state, SupervisionState::Answered);
+    }
+
+    #[tokio::test]
+    async fn test_post_dial_delay_ignores_wall_clock_step() {
+        let mut supervisor = AnswerSupervisor::new();
+        let mut events = supervisor.take_event_receiver().unwrap();
+        supervisor.track_call("call-3", CallDirection::SipToTdm, None);
+        let setup = supervisor.get_call("call-3").unwrap();
+
+        // Wall clock stepped back an hour while the call was ringing
+        let received_at = setup.setup_at - chrono::Duration::hours(1);
+        let received_instant = setup.setup_instant + Duration::from_secs(3);
+        supervisor
+            .on_signal("call-3", SupervisionSignal::Q931Connect, received_at, received_instant)
+            .await
+            .unwrap();
+
+        let mut post_dial = None;
+        while let Ok(event) = events.try_recv() {
+            if let SupervisionEvent::Answered { post_dial_delay, .. } = event {
+                post_dial = Some(post_dial_delay);
+            }
+        }
+        assert_eq!(post_dial, Some(Duration::from_secs(3)));
+    }
+}
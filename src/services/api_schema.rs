@@ -0,0 +1,404 @@
+//! OpenAPI description of the management API
+//!
+//! The schemas are generated from the same request and response types the
+//! gateway and `b2bua-cli` exchange, and the management API tests check the
+//! endpoint list against the operations it mounts, so the document follows
+//! the API as it changes. It is served at [`API_SCHEMA_PATH`] for client
+//! generators and integrators, and printed by `b2bua-cli schema`.
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use serde_json::{json, Map, Value};
+
+use crate::config::PortDescription;
+use crate::core::control::ControlStatus;
+use crate::services::call_gapping::{ActiveGap, CALL_GAPS_PATH};
+use crate::services::call_trace::{call_trace_path, CallTrace, TraceSelector, CALL_TRACE_PATH};
+use crate::services::canary::{CanaryMetrics, CANARY_PATH};
+use crate::services::audio_tap::{
+    audio_tap_path, TapAuditRecord, TapRequest, TapSession, AUDIO_TAPS_PATH, AUDIO_TAP_AUDIT_PATH,
+};
+use crate::services::campaigns::{campaign_path, Campaign, CampaignRequest, CampaignSummary, CAMPAIGNS_PATH};
+use crate::services::codec_negotiation::{
+    CodecNegotiationCounter, TranscodingHeadroom, CODEC_NEGOTIATION_PATH, TRANSCODING_HEADROOM_PATH,
+};
+use crate::services::early_media::{EarlyMediaCounter, EARLY_MEDIA_PATH};
+use crate::services::media_security::{MediaSecurityCounter, MEDIA_SECURITY_PATH};
+use crate::services::management_api::{
+    ActiveCallReport, AlarmReport, CdrReport, RtpSessionReport, ServiceReport, SpanReport, TestSessionReport,
+    TimingReport, ACTIVE_CALLS_PATH, ALARMS_PATH, CDRS_PATH, CONFIG_PATH, RTP_SESSIONS_PATH, SERVICES_PATH,
+    SPANS_PATH, STATUS_PATH, TEST_SESSIONS_PATH, TIMING_PATH,
+};
+use crate::services::prompts::{prompt_path, PromptInfo, PromptPackSummary, PROMPTS_PATH};
+use crate::services::ports::{PortEntry, PORTS_PATH};
+use crate::services::capacity::{CapacityReport, CAPACITY_PATH};
+use crate::services::channel_history::{ChannelCall, CHANNEL_HISTORY_PATH};
+use crate::services::profiling::{HeapStats, CPU_PROFILE_PATH, HEAP_STATS_PATH};
+use crate::services::reroute::{RerouteCounter, REROUTE_COUNTERS_PATH};
+use crate::services::route_provisioning::{ImportReport, ROUTES_PATH, TRUNKS_PATH};
+use crate::services::shadow_routing::{ShadowRoutingSummary, SHADOW_ROUTING_PATH};
+use crate::services::support_tunnel::{
+    TunnelAuditRecord, TunnelRequest, TunnelSession, SUPPORT_TUNNEL_AUDIT_PATH, SUPPORT_TUNNEL_PATH,
+};
+use crate::services::test_numbers::{TestCallRecord, TEST_CALLS_PATH};
+use crate::services::paging::{PageRecord, PAGING_PATH};
+use crate::services::release_causes::{ReleaseCauseReport, RELEASE_CAUSES_PATH};
+use crate::services::trunk_registration::{TrunkRegistrationStatus, TRUNK_REGISTRATIONS_PATH};
+use crate::services::transcoding_latency::{TranscodingLatencyReport, TRANSCODING_LATENCY_PATH};
+use crate::services::takeover::{takeover_path, TakeoverRecord, TakeoverRequest};
+use crate::services::upgrade::{UpgradeRequest, UPGRADE_PATH};
+
+/// Management API path serving the OpenAPI document
+pub const API_SCHEMA_PATH: &str = "/api/schema";
+
+const OPENAPI_VERSION: &str = "3.0.3";
+
+/// Request or response body of an endpoint
+#[derive(Clone, Copy)]
+enum Body {
+    Empty,
+    Json(fn(&mut SchemaGenerator) -> Schema),
+    Binary,
+    Csv,
+}
+
+fn json<T: schemars::JsonSchema>() -> Body {
+    Body::Json(SchemaGenerator::subschema_for::<T>)
+}
+
+/// Query, path or header parameter
+struct Parameter {
+    name: &'static str,
+    location: &'static str,
+    kind: &'static str,
+    required: bool,
+}
+
+const fn path(name: &'static str, kind: &'static str) -> Parameter {
+    Parameter { name, location: "path", kind, required: true }
+}
+
+const fn query(name: &'static str, kind: &'static str, required: bool) -> Parameter {
+    Parameter { name, location: "query", kind, required }
+}
+
+/// `Authorization: Bearer <token>`, for endpoints open only to named operators
+const fn bearer() -> Parameter {
+    Parameter { name: "Authorization", location: "header", kind: "string", required: true }
+}
+
+struct Endpoint {
+    method: &'static str,
+    path: String,
+    summary: &'static str,
+    parameters: Vec<Parameter>,
+    request: Body,
+    response: Body,
+}
+
+impl Endpoint {
+    fn new(method: &'static str, path: impl Into<String>, summary: &'static str) -> Self {
+        Self {
+            method,
+            path: path.into(),
+            summary,
+            parameters: Vec::new(),
+            request: Body::Empty,
+            response: Body::Empty,
+        }
+    }
+
+    fn parameter(mut self, parameter: Parameter) -> Self {
+        self.parameters.push(parameter);
+        self
+    }
+
+    fn request(mut self, body: Body) -> Self {
+        self.request = body;
+        self
+    }
+
+    fn response(mut self, body: Body) -> Self {
+        self.response = body;
+        self
+    }
+
+    fn operation(&self, gen: &mut SchemaGenerator) -> Value {
+        let response = match self.response {
+            Body::Empty => json!({ "description": "Success" }),
+            Body::Json(schema) => json!({
+                "description": "Success",
+                "content": { "application/json": { "schema": schema(gen) } },
+            }),
+            Body::Binary => json!({
+                "description": "Success",
+                "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } },
+            }),
+            Body::Csv => json!({
+                "description": "Success",
+                "content": { "text/csv": { "schema": { "type": "string" } } },
+            }),
+        };
+        let mut operation = json!({ "summary": self.summary, "responses": { "200": response } });
+        // Operator-token endpoints do not take the API secret
+        if self.parameters.iter().any(|parameter| parameter.name == "Authorization") {
+            operation["security"] = json!([]);
+        }
+
+        if !self.parameters.is_empty() {
+            let parameters: Vec<Value> = self
+                .parameters
+                .iter()
+                .map(|parameter| {
+                    json!({
+                        "name": parameter.name,
+                        "in": parameter.location,
+                        "required": parameter.required,
+                        "schema": { "type": parameter.kind },
+                    })
+                })
+                .collect();
+            operation["parameters"] = Value::from(parameters);
+        }
+        match self.request {
+            Body::Empty => {}
+            Body::Json(schema) => {
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": schema(gen) } },
+                });
+            }
+            Body::Binary => {
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } },
+                });
+            }
+            Body::Csv => {
+                operation["requestBody"] = json!({
+                    "required": true,
+                    "content": { "text/csv": { "schema": { "type": "string" } } },
+                });
+            }
+        }
+        operation
+    }
+}
+
+/// Every endpoint of the management API
+fn endpoints() -> Vec<Endpoint> {
+    let span_port = format!("{}/spans/{{span_id}}", PORTS_PATH);
+    let channel_port = format!("{}/channels/{{channel_id}}", span_port);
+    let call_gap = format!("{}/{{prefix}}", CALL_GAPS_PATH);
+    let prompt = prompt_path("{language}", "{event}");
+    let campaign = campaign_path("{id}");
+    let audio_tap = audio_tap_path("{id}");
+
+    vec![
+        Endpoint::new("get", API_SCHEMA_PATH, "This OpenAPI document").response(json::<Value>()),
+        Endpoint::new("get", STATUS_PATH, "Uptime, call counts and span summaries").response(json::<ControlStatus>()),
+        Endpoint::new("get", SPANS_PATH, "TDM spans and their channels").response(json::<Vec<SpanReport>>()),
+        Endpoint::new("get", ACTIVE_CALLS_PATH, "Calls in progress").response(json::<Vec<ActiveCallReport>>()),
+        Endpoint::new("get", RTP_SESSIONS_PATH, "RTP sessions with RTCP loss, jitter and round-trip time")
+            .response(json::<Vec<RtpSessionReport>>()),
+        Endpoint::new("get", SERVICES_PATH, "Interfaces, protocols and services and whether they are running")
+            .response(json::<Vec<ServiceReport>>()),
+        Endpoint::new("get", ALARMS_PATH, "Active alarms").response(json::<Vec<AlarmReport>>()),
+        Endpoint::new("get", CDRS_PATH, "Stored CDRs, most recent first")
+            .parameter(query("hours", "integer", false))
+            .parameter(query("caller", "string", false))
+            .parameter(query("callee", "string", false))
+            .parameter(query("correlation_id", "string", false))
+            .parameter(query("limit", "integer", false))
+            .response(json::<Vec<CdrReport>>()),
+        Endpoint::new("get", TIMING_PATH, "Clock sources and the selected one").response(json::<TimingReport>()),
+        Endpoint::new("get", TEST_SESSIONS_PATH, "Loopback and BERT test sessions")
+            .response(json::<Vec<TestSessionReport>>()),
+        Endpoint::new("get", CONFIG_PATH, "Running configuration, secrets redacted").response(json::<Value>()),
+        Endpoint::new("get", CAPACITY_PATH, "Peak calls, busy hour traffic and channels required per trunk")
+            .parameter(query("period", "string", false))
+            .parameter(query("trunk", "string", false))
+            .parameter(query("format", "string", false))
+            .response(json::<CapacityReport>()),
+        Endpoint::new("post", takeover_path("{call_id}"), "Move one leg of a live call to a new target")
+            .parameter(path("call_id", "string"))
+            .request(json::<TakeoverRequest>())
+            .response(json::<TakeoverRecord>()),
+        Endpoint::new("get", CALL_GAPS_PATH, "List active call gaps").response(json::<Vec<ActiveGap>>()),
+        Endpoint::new("post", call_gap.clone(), "Gap calls to a number prefix").parameter(path("prefix", "string")),
+        Endpoint::new("delete", call_gap, "Remove the gap on a number prefix").parameter(path("prefix", "string")),
+        Endpoint::new("get", CALL_TRACE_PATH, "List trace selectors").response(json::<Vec<TraceSelector>>()),
+        Endpoint::new("post", CALL_TRACE_PATH, "Start tracing calls matching a selector")
+            .request(json::<TraceSelector>()),
+        Endpoint::new("delete", CALL_TRACE_PATH, "Stop tracing calls matching a selector")
+            .request(json::<TraceSelector>()),
+        Endpoint::new("get", call_trace_path("{call_id}"), "Trace of one call")
+            .parameter(path("call_id", "string"))
+            .response(json::<CallTrace>()),
+        Endpoint::new("get", CODEC_NEGOTIATION_PATH, "Relayed and transcoded calls per trunk")
+            .response(json::<Vec<CodecNegotiationCounter>>()),
+        Endpoint::new("get", TRANSCODING_HEADROOM_PATH, "Free transcoding capacity")
+            .response(json::<TranscodingHeadroom>()),
+        Endpoint::new("get", TRANSCODING_LATENCY_PATH, "Per-frame transcoding latency by codec and backend")
+            .response(json::<Vec<TranscodingLatencyReport>>()),
+        Endpoint::new("get", REROUTE_COUNTERS_PATH, "Reroutes and releases per trunk and status")
+            .response(json::<Vec<RerouteCounter>>()),
+        Endpoint::new("get", EARLY_MEDIA_PATH, "Calls per trunk whose early media was blocked or cut off")
+            .response(json::<Vec<EarlyMediaCounter>>()),
+        Endpoint::new("get", MEDIA_SECURITY_PATH, "Encrypted, plain and downgraded call legs per trunk")
+            .response(json::<Vec<MediaSecurityCounter>>()),
+        Endpoint::new("get", RELEASE_CAUSES_PATH, "Release causes per trunk, route and hour, with the top failures")
+            .parameter(query("hours", "integer", false))
+            .parameter(query("trunk", "string", false))
+            .parameter(query("route", "string", false))
+            .parameter(query("top", "integer", false))
+            .response(json::<ReleaseCauseReport>()),
+        Endpoint::new("get", SHADOW_ROUTING_PATH, "Differences between active and candidate routing")
+            .response(json::<ShadowRoutingSummary>()),
+        Endpoint::new("get", SUPPORT_TUNNEL_PATH, "Open support tunnel, if any")
+            .response(json::<Option<TunnelSession>>()),
+        Endpoint::new("post", SUPPORT_TUNNEL_PATH, "Open the support tunnel")
+            .request(json::<TunnelRequest>())
+            .response(json::<TunnelSession>()),
+        Endpoint::new("delete", SUPPORT_TUNNEL_PATH, "Close the support tunnel")
+            .parameter(query("operator", "string", true))
+            .response(json::<TunnelSession>()),
+        Endpoint::new("get", SUPPORT_TUNNEL_AUDIT_PATH, "Support tunnel audit trail")
+            .response(json::<Vec<TunnelAuditRecord>>()),
+        Endpoint::new("post", UPGRADE_PATH, "Restart into a new binary, keeping established calls up")
+            .request(json::<UpgradeRequest>()),
+        Endpoint::new("get", PORTS_PATH, "Descriptions of every span and channel").response(json::<Vec<PortEntry>>()),
+        Endpoint::new("get", CHANNEL_HISTORY_PATH, "Recent calls on a span's channels, newest first")
+            .parameter(query("span", "integer", true))
+            .parameter(query("channel", "integer", false))
+            .response(json::<Vec<ChannelCall>>()),
+        Endpoint::new("put", span_port.clone(), "Describe a span")
+            .parameter(path("span_id", "integer"))
+            .request(json::<PortDescription>()),
+        Endpoint::new("put", channel_port, "Describe a channel")
+            .parameter(path("span_id", "integer"))
+            .parameter(path("channel_id", "integer"))
+            .request(json::<PortDescription>()),
+        Endpoint::new("get", ROUTES_PATH, "Routing table as CSV").response(Body::Csv),
+        Endpoint::new("put", ROUTES_PATH, "Replace the routing table from CSV, or preview the change")
+            .parameter(query("dry_run", "boolean", false))
+            .request(Body::Csv)
+            .response(json::<ImportReport>()),
+        Endpoint::new("get", TRUNKS_PATH, "Registering trunks as CSV").response(Body::Csv),
+        Endpoint::new("put", TRUNKS_PATH, "Replace the registering trunks from CSV, or preview the change")
+            .parameter(query("dry_run", "boolean", false))
+            .request(Body::Csv)
+            .response(json::<ImportReport>()),
+        Endpoint::new("get", CPU_PROFILE_PATH, "Sample a CPU profile")
+            .parameter(query("format", "string", false))
+            .parameter(query("seconds", "integer", false))
+            .response(Body::Binary),
+        Endpoint::new("get", HEAP_STATS_PATH, "Allocator statistics").response(json::<HeapStats>()),
+        Endpoint::new("get", PROMPTS_PATH, "Prompt packs and the standard prompts each lacks")
+            .response(json::<Vec<PromptPackSummary>>()),
+        Endpoint::new("put", prompt.clone(), "Upload a WAV prompt, validated before it is stored")
+            .parameter(path("language", "string"))
+            .parameter(path("event", "string"))
+            .request(Body::Binary)
+            .response(json::<PromptInfo>()),
+        Endpoint::new("delete", prompt, "Remove a prompt")
+            .parameter(path("language", "string"))
+            .parameter(path("event", "string")),
+        Endpoint::new("get", TEST_CALLS_PATH, "Active and recent calls to the test numbers")
+            .response(json::<Vec<TestCallRecord>>()),
+        Endpoint::new("get", PAGING_PATH, "Active and recent pages to the multicast paging groups")
+            .response(json::<Vec<PageRecord>>()),
+        Endpoint::new("get", TRUNK_REGISTRATIONS_PATH, "Registration state and next retry of each registering trunk")
+            .response(json::<Vec<TrunkRegistrationStatus>>()),
+        Endpoint::new("get", CANARY_PATH, "Canary call results per destination").response(json::<Vec<CanaryMetrics>>()),
+        Endpoint::new("get", CAMPAIGNS_PATH, "Notification campaigns with outcome counts, newest first")
+            .response(json::<Vec<CampaignSummary>>()),
+        Endpoint::new("post", CAMPAIGNS_PATH, "Start or schedule a notification campaign")
+            .request(json::<CampaignRequest>())
+            .response(json::<Campaign>()),
+        Endpoint::new("get", campaign.clone(), "A campaign with the outcome of every destination")
+            .parameter(path("id", "string"))
+            .response(json::<Campaign>()),
+        Endpoint::new("delete", campaign, "Cancel a campaign; calls already up finish")
+            .parameter(path("id", "string"))
+            .response(json::<CampaignSummary>()),
+        Endpoint::new("get", AUDIO_TAPS_PATH, "Running channel audio taps")
+            .parameter(bearer())
+            .response(json::<Vec<TapSession>>()),
+        Endpoint::new("post", AUDIO_TAPS_PATH, "Tap raw audio of a span channel or TDM call")
+            .parameter(bearer())
+            .request(json::<TapRequest>())
+            .response(json::<TapSession>()),
+        Endpoint::new("delete", audio_tap, "Stop a channel audio tap")
+            .parameter(bearer())
+            .parameter(path("id", "string"))
+            .response(json::<TapSession>()),
+        Endpoint::new("get", AUDIO_TAP_AUDIT_PATH, "Audio tap audit trail, refusals included")
+            .parameter(bearer())
+            .response(json::<Vec<TapAuditRecord>>()),
+    ]
+}
+
+/// The OpenAPI document of the management API
+pub fn openapi_document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    for endpoint in endpoints() {
+        let operation = endpoint.operation(&mut gen);
+        let item = paths.entry(endpoint.path.clone()).or_insert_with(|| json!({}));
+        item[endpoint.method] = operation;
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": format!("{} management API", crate::NAME),
+            "version": crate::VERSION,
+        },
+        "paths": paths,
+        "security": [{ "apiSecret": [] }],
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": { "apiSecret": { "type": "http", "scheme": "bearer" } },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect_refs(value: &Value, refs: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(reference)) = map.get("$ref") {
+                    refs.push(reference.clone());
+                }
+                map.values().for_each(|value| collect_refs(value, refs));
+            }
+            Value::Array(values) => values.iter().for_each(|value| collect_refs(value, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_document_covers_endpoints_and_resolves_refs() {
+        let document = openapi_document();
+        let paths = document["paths"].as_object().unwrap();
+        for path in [CALL_GAPS_PATH, CALL_TRACE_PATH, SUPPORT_TUNNEL_PATH, UPGRADE_PATH, PORTS_PATH, API_SCHEMA_PATH, STATUS_PATH, CDRS_PATH] {
+            assert!(paths.contains_key(path), "{} missing", path);
+        }
+        assert!(paths["/api/v1/b2bua/calls/{call_id}/takeover"]["post"]["requestBody"].is_object());
+        assert!(paths[SUPPORT_TUNNEL_PATH]["get"].is_object());
+        assert!(paths[SUPPORT_TUNNEL_PATH]["delete"].is_object());
+
+        let mut refs = Vec::new();
+        collect_refs(&document, &mut refs);
+        assert!(!refs.is_empty());
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        for reference in refs {
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.contains_key(name), "unresolved {}", reference);
+        }
+    }
+}
@@ -0,0 +1,720 @@
+//! Raw TDM channel audio taps
+//!
+//! For compliance recording and for chasing audio faults down to the line,
+//! an operator can tap the 64 kbit/s G.711 audio of a span channel, or of
+//! both channels of a TDM call, exactly as the driver reads it from and
+//! writes it to the span, before any transcoding. Each direction of each
+//! channel goes to a WAV file under the configured directory or, as RTP
+//! with an SSRC of its own, to one of the configured multicast groups.
+//!
+//! Only configured operators get near tapped audio. Each presents a bearer
+//! token naming them and their role: auditors see taps and the audit trail,
+//! tappers also start taps on their spans and stop their own, supervisors
+//! stop anyone's. Every start, stop, expiry, failure and refusal is kept in
+//! an audit trail that is also appended to a log file. A tap ends when its
+//! time is up, and a call tap also when the call is released, so the next
+//! call on the timeslot is never captured.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::config::{AudioTapConfig, ChannelType, FreeTdmSpan, Layer1Type, TapOperator, TapRole};
+use crate::interfaces::backend::{AudioDirection, ChannelAudioSink};
+use crate::interfaces::freetdm::FreeTdmEvent;
+use crate::protocols::rtp::RtpPacket;
+use crate::services::tandem::TandemService;
+use crate::{Error, Result};
+
+/// Management API path listing (GET) and starting (POST) taps
+pub const AUDIO_TAPS_PATH: &str = "/api/v1/audio-taps";
+/// Management API path serving the tap audit trail
+pub const AUDIO_TAP_AUDIT_PATH: &str = "/api/v1/audio-taps/audit";
+
+/// Management API path stopping (DELETE) one tap
+pub fn audio_tap_path(id: &str) -> String {
+    format!("{}/{}", AUDIO_TAPS_PATH, id)
+}
+
+/// Audit records kept in memory; the log file keeps them all
+const MAX_AUDIT_RECORDS: usize = 500;
+
+/// Streams stay on the local segment unless a recorder is routed to
+const MULTICAST_TTL: u32 = 1;
+
+/// What to tap
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TapTarget {
+    Channel { span_id: u32, channel_id: u8 },
+    /// Both channels of a tandem call, or the channel of a TDM call
+    Call { call_id: String },
+}
+
+/// Where tapped audio goes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TapSink {
+    /// WAV files under the configured directory
+    File,
+    /// RTP to one of the configured multicast groups
+    Multicast { group: SocketAddr },
+}
+
+/// Operator request to start a tap; the operator is named by their token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TapRequest {
+    /// Warrant, ticket or reason recorded in the audit trail
+    pub reason: String,
+    pub target: TapTarget,
+    pub sink: TapSink,
+    /// Tap lifetime; the configured default when omitted
+    pub duration_secs: Option<u64>,
+}
+
+/// One tapped timeslot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct TapChannel {
+    pub span_id: u32,
+    pub channel_id: u8,
+}
+
+impl std::fmt::Display for TapChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.span_id, self.channel_id)
+    }
+}
+
+/// A running tap
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TapSession {
+    pub id: String,
+    pub operator: String,
+    pub reason: String,
+    pub target: TapTarget,
+    pub sink: TapSink,
+    pub channels: Vec<TapChannel>,
+    /// Files being written, for file taps
+    pub files: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Audio octets captured so far, both directions
+    pub octets: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TapAuditAction {
+    Started,
+    /// Stopped by an operator, or by the gateway stopping
+    Stopped,
+    /// Ended when its lifetime ran out
+    Expired,
+    /// Ended when the tapped call was released
+    CallEnded,
+    /// Could not be started
+    Failed,
+    /// Refused for want of a valid token, role or span
+    Denied,
+}
+
+/// One entry of the audit trail
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TapAuditRecord {
+    pub at: DateTime<Utc>,
+    pub action: TapAuditAction,
+    pub tap_id: Option<String>,
+    pub operator: Option<String>,
+    pub detail: String,
+}
+
+/// G.711 law carried on a span type
+fn payload_type(trunk_type: &Layer1Type) -> u8 {
+    match trunk_type {
+        Layer1Type::E1 => 8,
+        Layer1Type::T1 => 0,
+    }
+}
+
+/// Header of a mono 8 kHz G.711 WAV file holding `octets` samples
+fn wav_header(payload_type: u8, octets: u32) -> Vec<u8> {
+    let format: u16 = if payload_type == 8 { 6 } else { 7 };
+    let mut header = Vec::with_capacity(58);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(50 + octets).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&18u32.to_le_bytes());
+    header.extend_from_slice(&format.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&8000u32.to_le_bytes());
+    header.extend_from_slice(&8000u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&8u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(b"fact");
+    header.extend_from_slice(&4u32.to_le_bytes());
+    header.extend_from_slice(&octets.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&octets.to_le_bytes());
+    header
+}
+
+enum Output {
+    /// WAV file whose header is rewritten with the final length on close
+    File { writer: BufWriter<File>, octets: u32 },
+    Multicast { socket: Arc<UdpSocket>, group: SocketAddr },
+}
+
+/// One direction of one tapped channel
+struct Stream {
+    output: Output,
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+}
+
+impl Stream {
+    fn write(&mut self, octets: &[u8]) {
+        match self.output {
+            Output::File { ref mut writer, octets: ref mut written } => {
+                if let Err(e) = writer.write_all(octets) {
+                    debug!("Tap file write failed: {}", e);
+                    return;
+                }
+                *written = written.saturating_add(octets.len() as u32);
+            }
+            Output::Multicast { ref socket, group } => {
+                let mut packet = RtpPacket::new(self.payload_type, self.sequence, self.timestamp, self.ssrc);
+                packet.payload = Bytes::copy_from_slice(octets);
+                if let Err(e) = socket.send_to(&packet.encode(), group) {
+                    debug!("Tap stream to {} failed: {}", group, e);
+                }
+                self.sequence = self.sequence.wrapping_add(1);
+            }
+        }
+        self.timestamp = self.timestamp.wrapping_add(octets.len() as u32);
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        if let Output::File { ref mut writer, octets } = self.output {
+            writer.seek(SeekFrom::Start(0))?;
+            writer.write_all(&wav_header(self.payload_type, octets))?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+struct ActiveTap {
+    session: TapSession,
+    streams: Mutex<HashMap<(TapChannel, AudioDirection), Stream>>,
+    octets: AtomicU64,
+    /// Expiry timer
+    task: JoinHandle<()>,
+}
+
+impl ActiveTap {
+    fn covers(&self, span_id: u32, channel_id: u8) -> bool {
+        self.session.channels.contains(&TapChannel { span_id, channel_id })
+    }
+
+    fn snapshot(&self) -> TapSession {
+        TapSession { octets: self.octets.load(Ordering::Relaxed), ..self.session.clone() }
+    }
+}
+
+/// Span type and bearer channels of a configured span
+struct SpanInfo {
+    trunk_type: Layer1Type,
+    channels: Vec<u8>,
+}
+
+/// Starts, feeds, ends and audits channel audio taps
+pub struct AudioTap {
+    config: AudioTapConfig,
+    spans: DashMap<u32, SpanInfo>,
+    tandem: Option<Arc<TandemService>>,
+    taps: DashMap<String, ActiveTap>,
+    audit: Mutex<VecDeque<TapAuditRecord>>,
+}
+
+impl AudioTap {
+    pub fn new(config: AudioTapConfig, spans: &[FreeTdmSpan]) -> Self {
+        let tap = Self {
+            config,
+            spans: DashMap::new(),
+            tandem: None,
+            taps: DashMap::new(),
+            audit: Mutex::new(VecDeque::new()),
+        };
+        for span in spans {
+            tap.add_span(span);
+        }
+        tap
+    }
+
+    /// Resolve call taps against the tandem service's calls
+    pub fn set_tandem_service(&mut self, tandem: Arc<TandemService>) {
+        self.tandem = Some(tandem);
+    }
+
+    /// Make a hot-inserted span tappable
+    pub fn add_span(&self, span: &FreeTdmSpan) {
+        let channels = span
+            .channels
+            .iter()
+            .filter(|channel| matches!(channel.channel_type, ChannelType::BChannel))
+            .map(|channel| channel.id)
+            .collect();
+        self.spans.insert(span.span_id, SpanInfo { trunk_type: span.trunk_type.clone(), channels });
+    }
+
+    /// Lifetime granted to a request
+    pub fn duration(&self, requested: Option<u64>) -> Result<Duration> {
+        let secs = requested.unwrap_or(self.config.default_duration_secs);
+        if secs == 0 || secs > self.config.max_duration_secs {
+            return Err(Error::invalid_config(format!(
+                "Audio tap duration must be between 1 and {} seconds",
+                self.config.max_duration_secs
+            )));
+        }
+        Ok(Duration::from_secs(secs))
+    }
+
+    /// The operator holding `token`, if they hold at least `role`; refusals are audited
+    pub fn authorize(&self, token: &str, role: TapRole) -> Result<&TapOperator> {
+        let Some(operator) = self.config.operators.iter().find(|operator| same_secret(&operator.token, token)) else {
+            self.record(TapAuditAction::Denied, None, None, "unknown token".to_string());
+            return Err(denied("Unknown audio tap token"));
+        };
+        if operator.role < role {
+            let detail = format!("{:?} role needed, {:?} held", role, operator.role);
+            self.record(TapAuditAction::Denied, None, Some(&operator.name), detail);
+            return Err(denied(format!("Operator {} may not do that", operator.name)));
+        }
+        Ok(operator)
+    }
+
+    /// Start a tap for the operator holding `token`
+    pub fn start(self: &Arc<Self>, token: &str, request: TapRequest) -> Result<TapSession> {
+        let operator = self.authorize(token, TapRole::Tapper)?;
+        let duration = self.duration(request.duration_secs)?;
+        if request.reason.trim().is_empty() {
+            return Err(Error::invalid_config("Audio tap requests must give a reason"));
+        }
+        let channels = self.resolve(&request.target)?;
+        if let Some(channel) = channels
+            .iter()
+            .find(|channel| !operator.spans.is_empty() && !operator.spans.contains(&channel.span_id))
+        {
+            let detail = format!("span {} is outside the operator's spans", channel.span_id);
+            self.record(TapAuditAction::Denied, None, Some(&operator.name), detail);
+            return Err(denied(format!("Operator {} may not tap span {}", operator.name, channel.span_id)));
+        }
+        if let TapSink::Multicast { group } = request.sink {
+            if !self.config.multicast_groups.contains(&group) {
+                let detail = format!("multicast group {} is not allowed", group);
+                self.record(TapAuditAction::Denied, None, Some(&operator.name), detail);
+                return Err(denied(format!("Audio taps may not stream to {}", group)));
+            }
+        }
+        if self.taps.len() >= self.config.max_taps {
+            return Err(Error::resource_exhausted(format!("{} audio taps already running", self.taps.len())));
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let (streams, files) = match self.open_streams(&id, &channels, &request.sink) {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.record(TapAuditAction::Failed, None, Some(&operator.name), e.to_string());
+                return Err(e);
+            }
+        };
+        let started_at = Utc::now();
+        let session = TapSession {
+            id: id.clone(),
+            operator: operator.name.clone(),
+            reason: request.reason,
+            target: request.target,
+            sink: request.sink,
+            channels,
+            files,
+            started_at,
+            expires_at: started_at + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero()),
+            octets: 0,
+        };
+
+        let tap = Arc::clone(self);
+        let expiry_id = id.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            tap.finish(&expiry_id, TapAuditAction::Expired, "lifetime elapsed".to_string());
+        });
+        self.taps.insert(id.clone(), ActiveTap {
+            session: session.clone(),
+            streams: Mutex::new(streams),
+            octets: AtomicU64::new(0),
+            task,
+        });
+
+        let channels: Vec<String> = session.channels.iter().map(ToString::to_string).collect();
+        let channels = channels.join(", ");
+        info!(
+            "Audio tap {} on {} started by {} for {:?}: {}",
+            id, channels, session.operator, duration, session.reason
+        );
+        let detail = format!("{} to {:?} for {}s: {}", channels, session.sink, duration.as_secs(), session.reason);
+        self.record(TapAuditAction::Started, Some(&id), Some(&session.operator), detail);
+        Ok(session)
+    }
+
+    /// Stop a tap; tappers may stop only their own
+    pub fn stop(&self, token: &str, id: &str) -> Result<TapSession> {
+        let operator = self.authorize(token, TapRole::Tapper)?;
+        let owner = self
+            .taps
+            .get(id)
+            .map(|tap| tap.session.operator.clone())
+            .ok_or_else(|| Error::invalid_state(format!("No audio tap {}", id)))?;
+        if owner != operator.name && operator.role < TapRole::Supervisor {
+            let detail = format!("tap belongs to {}", owner);
+            self.record(TapAuditAction::Denied, Some(id), Some(&operator.name), detail);
+            return Err(denied(format!("Operator {} may not stop {}'s tap", operator.name, owner)));
+        }
+        self.finish(id, TapAuditAction::Stopped, format!("stopped by {}", operator.name))
+            .ok_or_else(|| Error::invalid_state(format!("No audio tap {}", id)))
+    }
+
+    /// End every tap, as the gateway stops
+    pub fn stop_all(&self, detail: &str) {
+        let ids: Vec<String> = self.taps.iter().map(|tap| tap.key().clone()).collect();
+        for id in ids {
+            self.finish(&id, TapAuditAction::Stopped, detail.to_string());
+        }
+    }
+
+    /// Running taps
+    pub fn taps(&self, token: &str) -> Result<Vec<TapSession>> {
+        self.authorize(token, TapRole::Auditor)?;
+        let mut taps: Vec<TapSession> = self.taps.iter().map(|tap| tap.snapshot()).collect();
+        taps.sort_by_key(|tap| tap.started_at);
+        Ok(taps)
+    }
+
+    /// Audit trail, oldest first
+    pub fn audit(&self, token: &str) -> Result<Vec<TapAuditRecord>> {
+        self.authorize(token, TapRole::Auditor)?;
+        Ok(self.audit.lock().unwrap().iter().cloned().collect())
+    }
+
+    /// End call taps on a channel whose call was released
+    pub fn on_event(&self, event: &FreeTdmEvent) {
+        let FreeTdmEvent::CallHangup { span_id, channel_id, .. } = *event else {
+            return;
+        };
+        let ended: Vec<String> = self
+            .taps
+            .iter()
+            .filter(|tap| matches!(tap.session.target, TapTarget::Call { .. }) && tap.covers(span_id, channel_id))
+            .map(|tap| tap.key().clone())
+            .collect();
+        for id in ended {
+            self.finish(&id, TapAuditAction::CallEnded, format!("call released on {}/{}", span_id, channel_id));
+        }
+    }
+
+    /// Channels a target covers
+    fn resolve(&self, target: &TapTarget) -> Result<Vec<TapChannel>> {
+        let channels = match *target {
+            TapTarget::Channel { span_id, channel_id } => vec![TapChannel { span_id, channel_id }],
+            TapTarget::Call { ref call_id } => {
+                if let Some(call) = self.tandem.as_ref().and_then(|tandem| tandem.get_call(call_id)) {
+                    vec![
+                        TapChannel { span_id: call.ingress.span_id, channel_id: call.ingress.channel_id },
+                        TapChannel { span_id: call.egress.span_id, channel_id: call.egress.channel_id },
+                    ]
+                } else {
+                    // Calls the gateway answers itself are named after their channel
+                    let (span_id, channel_id) = call_id
+                        .strip_prefix("ftdm-")
+                        .and_then(|rest| rest.split_once('-'))
+                        .and_then(|(span, channel)| Some((span.parse().ok()?, channel.parse().ok()?)))
+                        .ok_or_else(|| Error::invalid_state(format!("No TDM call {}", call_id)))?;
+                    vec![TapChannel { span_id, channel_id }]
+                }
+            }
+        };
+        for channel in &channels {
+            let span = self.spans.get(&channel.span_id);
+            if !span.is_some_and(|span| span.channels.contains(&channel.channel_id)) {
+                return Err(Error::invalid_config(format!("{} is not a configured bearer channel", channel)));
+            }
+        }
+        Ok(channels)
+    }
+
+    /// Files or sockets for both directions of every channel
+    fn open_streams(
+        &self,
+        id: &str,
+        channels: &[TapChannel],
+        sink: &TapSink,
+    ) -> Result<(HashMap<(TapChannel, AudioDirection), Stream>, Vec<String>)> {
+        let mut streams = HashMap::new();
+        let mut files = Vec::new();
+        let directory: PathBuf = self.config.directory.join(id);
+        let socket = match *sink {
+            TapSink::File => {
+                std::fs::create_dir_all(&directory)?;
+                None
+            }
+            TapSink::Multicast { .. } => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.set_multicast_ttl_v4(MULTICAST_TTL)?;
+                socket.set_nonblocking(true)?;
+                Some(Arc::new(socket))
+            }
+        };
+
+        for &channel in channels {
+            let payload_type = self
+                .spans
+                .get(&channel.span_id)
+                .map(|span| payload_type(&span.trunk_type))
+                .unwrap_or(0);
+            for (direction, suffix) in [(AudioDirection::Receive, "rx"), (AudioDirection::Transmit, "tx")] {
+                let output = match (sink, &socket) {
+                    (TapSink::Multicast { group }, Some(socket)) => {
+                        Output::Multicast { socket: Arc::clone(socket), group: *group }
+                    }
+                    _ => {
+                        let path = directory.join(format!("{}-{}-{}.wav", channel.span_id, channel.channel_id, suffix));
+                        let mut writer = BufWriter::new(File::create(&path)?);
+                        writer.write_all(&wav_header(payload_type, 0))?;
+                        files.push(path.display().to_string());
+                        Output::File { writer, octets: 0 }
+                    }
+                };
+                let stream = Stream {
+                    output,
+                    payload_type,
+                    ssrc: rand::random(),
+                    sequence: rand::random(),
+                    timestamp: rand::random(),
+                };
+                streams.insert((channel, direction), stream);
+            }
+        }
+        Ok((streams, files))
+    }
+
+    /// Close a tap's files and record why it ended
+    fn finish(&self, id: &str, action: TapAuditAction, detail: String) -> Option<TapSession> {
+        let (_, tap) = self.taps.remove(id)?;
+        tap.task.abort();
+        for stream in tap.streams.lock().unwrap().values_mut() {
+            if let Err(e) = stream.close() {
+                warn!("Failed to finish audio tap {} file: {}", id, e);
+            }
+        }
+        let session = tap.snapshot();
+        info!("Audio tap {} ended after {} octets: {}", id, session.octets, detail);
+        self.record(action, Some(id), Some(&session.operator), detail);
+        Some(session)
+    }
+
+    fn record(&self, action: TapAuditAction, tap_id: Option<&str>, operator: Option<&str>, detail: String) {
+        let record = TapAuditRecord {
+            at: Utc::now(),
+            action,
+            tap_id: tap_id.map(str::to_string),
+            operator: operator.map(str::to_string),
+            detail,
+        };
+        if action == TapAuditAction::Denied {
+            warn!("Audio tap request refused for {}: {}", operator.unwrap_or("unknown operator"), record.detail);
+        }
+
+        if !self.config.audit_log_path.is_empty() {
+            if let Err(e) = append_audit(&self.config.audit_log_path, &record) {
+                warn!("Failed to write audio tap audit log {}: {}", self.config.audit_log_path, e);
+            }
+        }
+        let mut audit = self.audit.lock().unwrap();
+        audit.push_back(record);
+        while audit.len() > MAX_AUDIT_RECORDS {
+            audit.pop_front();
+        }
+    }
+}
+
+impl ChannelAudioSink for AudioTap {
+    fn wants(&self, span_id: u32, channel_id: u8) -> bool {
+        self.taps.iter().any(|tap| tap.covers(span_id, channel_id))
+    }
+
+    fn audio(&self, span_id: u32, channel_id: u8, direction: AudioDirection, octets: &[u8]) {
+        let channel = TapChannel { span_id, channel_id };
+        for tap in self.taps.iter() {
+            if let Some(stream) = tap.streams.lock().unwrap().get_mut(&(channel, direction)) {
+                stream.write(octets);
+                tap.octets.fetch_add(octets.len() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Refusal reported as a permission error
+fn denied(message: impl Into<String>) -> Error {
+    Error::Io(std::io::Error::new(std::io::ErrorKind::PermissionDenied, message.into()))
+}
+
+/// Compare tokens without returning early on the first difference
+fn same_secret(expected: &str, presented: &str) -> bool {
+    let (expected, presented) = (expected.as_bytes(), presented.as_bytes());
+    let difference = expected
+        .iter()
+        .zip(presented)
+        .fold(expected.len() ^ presented.len(), |acc, (a, b)| acc | usize::from(a ^ b));
+    difference == 0
+}
+
+fn append_audit(path: &str, record: &TapAuditRecord) -> std::io::Result<()> {
+    let line = serde_json::to_string(record).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FreeTdmChannel, SignalingType};
+    use crate::services::prompts::{parse_prompt, PromptFormat};
+
+    const TAPPER: &str = "tapper-token-0001";
+    const AUDITOR: &str = "auditor-token-001";
+    const SUPERVISOR: &str = "supervisor-token1";
+
+    fn operator(name: &str, token: &str, role: TapRole, spans: Vec<u32>) -> TapOperator {
+        TapOperator { name: name.to_string(), token: token.to_string(), role, spans }
+    }
+
+    fn span(span_id: u32, trunk_type: Layer1Type) -> FreeTdmSpan {
+        FreeTdmSpan {
+            span_id,
+            name: format!("span{}", span_id),
+            trunk_type,
+            d_channel: 16,
+            channels: (1..=2)
+                .map(|id| FreeTdmChannel {
+                    id,
+                    channel_type: ChannelType::BChannel,
+                    enabled: true,
+                    signaling: SignalingType::Pri,
+                    description: Default::default(),
+                })
+                .collect(),
+            backend: "freetdm".to_string(),
+            description: Default::default(),
+            regulatory_pack: None,
+        }
+    }
+
+    fn audio_tap(directory: PathBuf) -> Arc<AudioTap> {
+        let config = AudioTapConfig {
+            enabled: true,
+            operators: vec![
+                operator("alice", TAPPER, TapRole::Tapper, vec![1]),
+                operator("audit", AUDITOR, TapRole::Auditor, Vec::new()),
+                operator("boss", SUPERVISOR, TapRole::Supervisor, Vec::new()),
+            ],
+            directory,
+            multicast_groups: vec!["239.1.1.1:5004".parse().unwrap()],
+            audit_log_path: String::new(),
+            ..Default::default()
+        };
+        Arc::new(AudioTap::new(config, &[span(1, Layer1Type::E1), span(2, Layer1Type::T1)]))
+    }
+
+    fn request(target: TapTarget, sink: TapSink) -> TapRequest {
+        TapRequest { reason: "WARRANT-17".to_string(), target, sink, duration_secs: None }
+    }
+
+    #[tokio::test]
+    async fn test_roles_and_spans_are_enforced_and_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let tap = audio_tap(dir.path().to_path_buf());
+        let channel = |span_id| TapTarget::Channel { span_id, channel_id: 1 };
+
+        assert!(tap.taps("not-a-token").is_err());
+        assert!(tap.start(AUDITOR, request(channel(1), TapSink::File)).is_err());
+        assert!(tap.start(TAPPER, request(channel(2), TapSink::File)).is_err());
+        let group = "239.9.9.9:5004".parse().unwrap();
+        assert!(tap.start(TAPPER, request(channel(1), TapSink::Multicast { group })).is_err());
+        let unknown = TapTarget::Channel { span_id: 1, channel_id: 16 };
+        assert!(tap.start(TAPPER, request(unknown, TapSink::File)).is_err());
+
+        let session = tap.start(SUPERVISOR, request(channel(2), TapSink::File)).unwrap();
+        assert!(tap.stop(TAPPER, &session.id).is_err());
+        assert_eq!(tap.taps(AUDITOR).unwrap().len(), 1);
+        tap.stop(SUPERVISOR, &session.id).unwrap();
+
+        let actions: Vec<TapAuditAction> = tap.audit(AUDITOR).unwrap().iter().map(|record| record.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                TapAuditAction::Denied,
+                TapAuditAction::Denied,
+                TapAuditAction::Denied,
+                TapAuditAction::Denied,
+                TapAuditAction::Started,
+                TapAuditAction::Denied,
+                TapAuditAction::Stopped,
+            ]
+        );
+        let refused = tap.audit(AUDITOR).unwrap();
+        assert_eq!(refused[2].operator.as_deref(), Some("alice"));
+        assert!(refused[2].detail.contains("span 2"));
+    }
+
+    #[tokio::test]
+    async fn test_call_tap_writes_both_directions_until_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let tap = audio_tap(dir.path().to_path_buf());
+        let target = TapTarget::Call { call_id: "ftdm-1-2".to_string() };
+        let session = tap.start(TAPPER, request(target, TapSink::File)).unwrap();
+        assert_eq!(session.channels, vec![TapChannel { span_id: 1, channel_id: 2 }]);
+        assert_eq!(session.files.len(), 2);
+
+        assert!(tap.wants(1, 2));
+        assert!(!tap.wants(1, 1));
+        tap.audio(1, 2, AudioDirection::Receive, &[0xd5; 160]);
+        tap.audio(1, 2, AudioDirection::Transmit, &[0x55; 80]);
+        assert_eq!(tap.taps(AUDITOR).unwrap()[0].octets, 240);
+
+        tap.on_event(&FreeTdmEvent::CallHangup { span_id: 1, channel_id: 2, cause: 16 });
+        assert!(!tap.wants(1, 2));
+        assert_eq!(tap.audit(AUDITOR).unwrap().last().unwrap().action, TapAuditAction::CallEnded);
+
+        let rx = std::fs::read(dir.path().join(&session.id).join("1-2-rx.wav")).unwrap();
+        let info = parse_prompt("tap", &rx).unwrap();
+        assert_eq!((info.format, info.duration_ms), (PromptFormat::Pcma, 20));
+        let tx = std::fs::read(dir.path().join(&session.id).join("1-2-tx.wav")).unwrap();
+        assert_eq!(parse_prompt("tap", &tx).unwrap().duration_ms, 10);
+    }
+}
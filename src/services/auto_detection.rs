@@ -1,14 +1,20 @@
 //! Auto-detection service for protocol and hardware detection
-
-use std::collections::HashMap;
-use std::sync::Arc;
+//!
+//! Switch variant, framing and timeslot mapping are inferred from what the
+//! span actually observes: raw D-channel messages, framer status reports and
+//! bearer activity. Mismatches against the configured settings produce
+//! suggestions that can optionally be queued for automatic application.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use tokio::sync::{mpsc, RwLock};
 use tokio::time::{interval, Interval};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::config::{Layer1Type, SignalingType};
+use crate::config::{E1Framing, GatewayConfig, Layer1Type, SignalingType, T1Framing};
+use crate::protocols::q931::{IE_CAUSE, IE_CHANNEL_ID, IE_DISPLAY, IE_FACILITY};
 use crate::{Error, Result};
 
 /// Detected protocol information
@@ -34,7 +40,7 @@ pub struct LineCharacteristics {
 }
 
 /// Switch type detection result
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum SwitchType {
     EuroISDN,        // European ISDN
     NationalISDN2,   // North American NI-2
@@ -46,6 +52,22 @@ pub enum SwitchType {
     Unknown,
 }
 
+impl SwitchType {
+    /// Name used for `pri.switch_type` in the gateway configuration
+    pub fn config_name(&self) -> &'static str {
+        match self {
+            SwitchType::EuroISDN => "euroISDN",
+            SwitchType::NationalISDN2 => "national",
+            SwitchType::NationalISDN1 => "ni1",
+            SwitchType::Dms100 => "dms100",
+            SwitchType::Ess5 => "5ess",
+            SwitchType::Lucent5e => "lucent5e",
+            SwitchType::Nortel => "nortel",
+            SwitchType::Unknown => "unknown",
+        }
+    }
+}
+
 /// Mobile network type detection
 #[derive(Debug, Clone, PartialEq)]
 pub enum MobileNetworkType {
@@ -56,12 +78,88 @@ pub enum MobileNetworkType {
     Unknown,
 }
 
+/// Layer 1 status reported by the span framer
+#[derive(Debug, Clone)]
+pub struct LineObservation {
+    pub line_type: Layer1Type,
+    /// Framing the span is configured for: crc4, no-crc4, esf or d4
+    pub configured_framing: String,
+    /// Framing the framer aligned to while hunting all formats, if any
+    pub aligned_framing: Option<String>,
+    pub line_code: String,
+    pub frames: u64,
+    pub crc_errors: u64,
+    /// Remote alarm indication received from the far end
+    pub remote_alarm: bool,
+    pub signal_level: f64,
+}
+
+/// Disagreement between the configured span and what the far end does
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpanMismatch {
+    SwitchType { configured: String, detected: SwitchType },
+    Framing { configured: String, detected: String },
+    /// Framing aligns but CRC checks mostly fail: the far end does not send CRC
+    Crc { configured: String, crc_error_ratio: f64 },
+    /// B-channels are numbered high to low on one side
+    InvertedTimeslots { samples: u32 },
+}
+
+impl SpanMismatch {
+    fn kind(&self) -> &'static str {
+        match self {
+            SpanMismatch::SwitchType { .. } => "switch_type",
+            SpanMismatch::Framing { .. } => "framing",
+            SpanMismatch::Crc { .. } => "crc",
+            SpanMismatch::InvertedTimeslots { .. } => "inverted_timeslots",
+        }
+    }
+}
+
+/// Span setting that would resolve a mismatch
+#[derive(Debug, Clone)]
+pub enum SpanSetting {
+    SwitchType(String),
+    E1Framing(E1Framing),
+    T1Framing(T1Framing),
+    /// Needs a far-end or cross-connect change; never applied automatically
+    ReverseTimeslots,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpanSettingsSuggestion {
+    pub span_id: u32,
+    pub setting: SpanSetting,
+    pub confidence: f64,
+    pub reason: String,
+}
+
+impl SpanSettingsSuggestion {
+    /// Write the setting into `config`; false when it cannot be applied locally
+    pub fn apply_to(&self, config: &mut GatewayConfig) -> bool {
+        match &self.setting {
+            SpanSetting::SwitchType(switch_type) => config.pri.switch_type = switch_type.clone(),
+            SpanSetting::E1Framing(framing) => config.e1.framing = framing.clone(),
+            SpanSetting::T1Framing(framing) => config.t1.framing = framing.clone(),
+            SpanSetting::ReverseTimeslots => return false,
+        }
+        true
+    }
+
+    fn is_applicable(&self) -> bool {
+        !matches!(self.setting, SpanSetting::ReverseTimeslots)
+    }
+}
+
 /// Detection events
 #[derive(Debug, Clone)]
 pub enum DetectionEvent {
     ProtocolDetected { span_id: u32, protocol: DetectedProtocol },
     LineCharacteristicsDetected { span_id: u32, characteristics: LineCharacteristics },
-    SwitchTypeDetected { span_id: u32, switch_type: SwitchType },
+    SwitchTypeDetected { span_id: u32, switch_type: SwitchType, confidence: f64 },
+    MismatchDetected { span_id: u32, mismatch: SpanMismatch, confidence: f64 },
+    SettingsSuggested { span_id: u32, suggestion: SpanSettingsSuggestion },
+    SettingsApplied { span_id: u32, suggestion: SpanSettingsSuggestion },
     MobileNetworkDetected { span_id: u32, network_type: MobileNetworkType },
     DetectionFailed { span_id: u32, error: String },
     DetectionStarted { span_id: u32 },
@@ -80,6 +178,14 @@ pub struct AutoDetectionConfig {
     pub enable_switch_detection: bool,
     pub enable_mobile_detection: bool,
     pub confidence_threshold: f64, // Minimum confidence for positive detection
+    /// Switch type the span is configured for (`pri.switch_type`)
+    pub configured_switch_type: String,
+    /// D-channel messages needed before a switch type reaches full confidence
+    pub min_d_channel_messages: u32,
+    /// Bearer activity samples needed before judging timeslot mapping
+    pub min_bearer_samples: u32,
+    /// Queue confident suggestions for the gateway to apply
+    pub auto_apply: bool,
 }
 
 impl Default for AutoDetectionConfig {
@@ -94,6 +200,10 @@ impl Default for AutoDetectionConfig {
             enable_switch_detection: true,
             enable_mobile_detection: false,
             confidence_threshold: 0.8,
+            configured_switch_type: "euroISDN".to_string(),
+            min_d_channel_messages: 20,
+            min_bearer_samples: 5,
+            auto_apply: false,
         }
     }
 }
@@ -110,6 +220,13 @@ pub struct SpanDetectionState {
     detected_switch: Option<SwitchType>,
     detected_mobile: Option<MobileNetworkType>,
     is_detecting: bool,
+    switch_confidence: f64,
+    d_channel_messages: u32,
+    switch_scores: HashMap<SwitchType, f64>,
+    line_observation: Option<LineObservation>,
+    bearer_samples: u32,
+    inverted_samples: u32,
+    reported_mismatches: HashSet<&'static str>,
 }
 
 /// Auto-detection service
@@ -118,6 +235,7 @@ pub struct AutoDetectionService {
     span_states: Arc<RwLock<HashMap<u32, SpanDetectionState>>>,
     event_tx: mpsc::UnboundedSender<DetectionEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<DetectionEvent>>,
+    pending_settings: Mutex<Vec<SpanSettingsSuggestion>>,
     detection_interval: Option<Interval>,
     is_running: bool,
 }
@@ -131,6 +249,7 @@ impl AutoDetectionService {
             span_states: Arc::new(RwLock::new(HashMap::new())),
             event_tx,
             event_rx: Some(event_rx),
+            pending_settings: Mutex::new(Vec::new()),
             detection_interval: None,
             is_running: false,
         }
@@ -191,6 +310,13 @@ impl AutoDetectionService {
             detected_switch: None,
             detected_mobile: None,
             is_detecting: true,
+            switch_confidence: 0.0,
+            d_channel_messages: 0,
+            switch_scores: HashMap::new(),
+            line_observation: None,
+            bearer_samples: 0,
+            inverted_samples: 0,
+            reported_mismatches: HashSet::new(),
         };
 
         {
@@ -222,6 +348,81 @@ impl AutoDetectionService {
         states.get(&span_id).cloned()
     }
 
+    /// Feed a raw Q.931 message received on the span's D-channel
+    pub async fn observe_d_channel(&self, span_id: u32, message: &[u8]) -> Result<()> {
+        if !self.config.enable_switch_detection {
+            return Ok(());
+        }
+
+        {
+            let mut states = self.span_states.write().await;
+            let Some(state) = states.get_mut(&span_id) else {
+                return Ok(());
+            };
+            state.d_channel_messages += 1;
+            for (switch_type, weight) in switch_indicators(message) {
+                *state.switch_scores.entry(switch_type).or_insert(0.0) += weight;
+            }
+        }
+
+        self.detect_switch_type(span_id).await
+    }
+
+    /// Feed a framer status report for the span
+    pub async fn observe_line_status(&self, span_id: u32, observation: LineObservation) -> Result<()> {
+        if !self.config.enable_line_detection {
+            return Ok(());
+        }
+
+        {
+            let mut states = self.span_states.write().await;
+            let Some(state) = states.get_mut(&span_id) else {
+                return Ok(());
+            };
+            state.line_observation = Some(observation);
+        }
+
+        self.detect_line_characteristics(span_id).await
+    }
+
+    /// Record that a call signalled on `signalled_channel` carried media on `active_timeslot`
+    pub async fn observe_bearer_activity(&self, span_id: u32, signalled_channel: u8, active_timeslot: u8) -> Result<()> {
+        {
+            let mut states = self.span_states.write().await;
+            let Some(state) = states.get_mut(&span_id) else {
+                return Ok(());
+            };
+            let line_type = state
+                .line_observation
+                .as_ref()
+                .map(|observation| observation.line_type.clone())
+                .unwrap_or(Layer1Type::E1);
+            let mirrored = mirrored_timeslot(&line_type, signalled_channel);
+            // The middle channel maps onto itself and says nothing about direction
+            if mirrored == signalled_channel {
+                return Ok(());
+            }
+            state.bearer_samples += 1;
+            if active_timeslot == mirrored {
+                state.inverted_samples += 1;
+            }
+        }
+
+        self.detect_timeslot_mapping(span_id).await;
+        Ok(())
+    }
+
+    /// Suggestions queued for automatic application
+    pub fn take_pending_settings(&self) -> Vec<SpanSettingsSuggestion> {
+        std::mem::take(&mut *self.pending_settings.lock().unwrap())
+    }
+
+    /// Report that the gateway applied a queued suggestion
+    pub fn confirm_applied(&self, suggestion: SpanSettingsSuggestion) {
+        info!("Applied detected setting on span {}: {:?}", suggestion.span_id, suggestion.setting);
+        let _ = self.event_tx.send(DetectionEvent::SettingsApplied { span_id: suggestion.span_id, suggestion });
+    }
+
     /// Get all detection states
     pub async fn get_all_detection_states(&self) -> HashMap<u32, SpanDetectionState> {
         let states = self.span_states.read().await;
@@ -262,19 +463,11 @@ impl AutoDetectionService {
             return Ok(());
         }
 
-        // Simulate detection process
-        if self.config.enable_line_detection {
-            self.detect_line_characteristics(span_id).await?;
-        }
-
+        // Line and switch detection run as observations arrive
         if self.config.enable_protocol_detection {
             self.detect_protocol(span_id).await?;
         }
 
-        if self.config.enable_switch_detection {
-            self.detect_switch_type(span_id).await?;
-        }
-
         if self.config.enable_mobile_detection {
             self.detect_mobile_network(span_id).await?;
         }
@@ -286,22 +479,18 @@ impl AutoDetectionService {
     }
 
     async fn detect_line_characteristics(&self, span_id: u32) -> Result<()> {
-        // Simulate line characteristic detection
-        // In a real implementation, this would:
-        // 1. Analyze received signal patterns
-        // 2. Detect framing format (CRC4, ESF, etc.)
-        // 3. Identify line coding (HDB3, B8ZS, AMI)
-        // 4. Measure signal levels and quality
-        // 5. Detect alarm conditions
+        let Some(observation) = self.span_states.read().await.get(&span_id).and_then(|state| state.line_observation.clone()) else {
+            return Ok(());
+        };
 
         let characteristics = LineCharacteristics {
-            line_type: Layer1Type::E1, // Detected as E1
-            framing: "crc4".to_string(),
-            line_code: "hdb3".to_string(),
-            clock_source: "external".to_string(),
-            signal_level: -12.5, // dBm
-            error_rate: 0.00001,
-            alarm_status: vec![],
+            line_type: observation.line_type.clone(),
+            framing: observation.aligned_framing.clone().unwrap_or_else(|| observation.configured_framing.clone()),
+            line_code: observation.line_code.clone(),
+            clock_source: "recovered".to_string(),
+            signal_level: observation.signal_level,
+            error_rate: crc_error_ratio(&observation),
+            alarm_status: if observation.remote_alarm { vec!["RAI".to_string()] } else { Vec::new() },
             detected_at: Instant::now(),
         };
 
@@ -313,12 +502,21 @@ impl AutoDetectionService {
             }
         }
 
+        debug!("Detected line characteristics for span {}: {:?}/{}/{}",
+               span_id, characteristics.line_type, characteristics.framing, characteristics.line_code);
+
         let _ = self.event_tx.send(DetectionEvent::LineCharacteristicsDetected {
             span_id,
             characteristics,
         });
 
-        debug!("Detected line characteristics for span {}: E1/CRC4/HDB3", span_id);
+        if let Some((mismatch, setting, confidence)) = line_mismatch(&observation) {
+            let reason = match &mismatch {
+                SpanMismatch::Framing { detected, .. } => format!("Framer aligned to {}", detected),
+                _ => format!("{:.0}% of CRC checks failing with framing aligned", crc_error_ratio(&observation) * 100.0),
+            };
+            self.report_mismatch(span_id, mismatch, setting, confidence, reason).await;
+        }
 
         Ok(())
     }
@@ -368,46 +566,115 @@ impl AutoDetectionService {
     }
 
     async fn detect_switch_type(&self, span_id: u32) -> Result<()> {
-        // Simulate switch type detection
-        // In a real implementation, this would:
-        // 1. Analyze Q.931 message patterns
-        // 2. Look for vendor-specific information elements
-        // 3. Detect call setup procedures
-        // 4. Identify switch-specific behaviors
-
-        let switch_type = self.analyze_switch_patterns(span_id).await;
+        let Some((switch_type, confidence)) = self.analyze_switch_patterns(span_id).await else {
+            return Ok(());
+        };
+        if confidence < self.config.confidence_threshold {
+            return Ok(());
+        }
 
-        if switch_type != SwitchType::Unknown {
-            // Update state
-            {
-                let mut states = self.span_states.write().await;
-                if let Some(state) = states.get_mut(&span_id) {
-                    state.detected_switch = Some(switch_type.clone());
-                }
+        // Update state, reporting only when the verdict changes
+        {
+            let mut states = self.span_states.write().await;
+            let Some(state) = states.get_mut(&span_id) else {
+                return Ok(());
+            };
+            state.switch_confidence = confidence;
+            if state.detected_switch.as_ref() == Some(&switch_type) {
+                return Ok(());
             }
+            state.detected_switch = Some(switch_type.clone());
+        }
 
-            let _ = self.event_tx.send(DetectionEvent::SwitchTypeDetected {
-                span_id,
-                switch_type: switch_type.clone(),
-            });
+        let _ = self.event_tx.send(DetectionEvent::SwitchTypeDetected {
+            span_id,
+            switch_type: switch_type.clone(),
+            confidence,
+        });
+
+        info!("Detected switch type for span {}: {:?} (confidence: {:.2})", span_id, switch_type, confidence);
 
-            info!("Detected switch type for span {}: {:?}", span_id, switch_type);
+        let configured = &self.config.configured_switch_type;
+        if !configured.eq_ignore_ascii_case(switch_type.config_name()) {
+            let mismatch = SpanMismatch::SwitchType { configured: configured.clone(), detected: switch_type.clone() };
+            let setting = SpanSetting::SwitchType(switch_type.config_name().to_string());
+            let reason = format!("D-channel traffic matches {:?}", switch_type);
+            self.report_mismatch(span_id, mismatch, setting, confidence, reason).await;
         }
 
         Ok(())
     }
 
-    async fn analyze_switch_patterns(&self, _span_id: u32) -> SwitchType {
-        // Simulate switch type analysis based on message patterns
-        // In reality, this would analyze:
-        // - Information element usage patterns
-        // - Call reference value allocation
-        // - Cause code preferences
-        // - Progress indicator usage
-        // - Facility information elements
+    /// Best-scoring switch variant, scaled down until enough messages are seen
+    async fn analyze_switch_patterns(&self, span_id: u32) -> Option<(SwitchType, f64)> {
+        let states = self.span_states.read().await;
+        let state = states.get(&span_id)?;
+
+        let total: f64 = state.switch_scores.values().sum();
+        let (switch_type, score) = state
+            .switch_scores
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1))?;
+        if total <= 0.0 {
+            return None;
+        }
+
+        let sample_factor =
+            (state.d_channel_messages as f64 / self.config.min_d_channel_messages.max(1) as f64).min(1.0);
+        Some((switch_type.clone(), score / total * sample_factor))
+    }
+
+    async fn detect_timeslot_mapping(&self, span_id: u32) {
+        let (samples, inverted) = {
+            let states = self.span_states.read().await;
+            match states.get(&span_id) {
+                Some(state) => (state.bearer_samples, state.inverted_samples),
+                None => return,
+            }
+        };
+        if samples < self.config.min_bearer_samples {
+            return;
+        }
 
-        // For simulation, randomly detect EuroISDN (most common in Europe)
-        SwitchType::EuroISDN
+        let confidence = inverted as f64 / samples as f64;
+        if confidence >= self.config.confidence_threshold {
+            let reason = format!("{} of {} calls carried media on the mirrored timeslot", inverted, samples);
+            self.report_mismatch(
+                span_id,
+                SpanMismatch::InvertedTimeslots { samples },
+                SpanSetting::ReverseTimeslots,
+                confidence,
+                reason,
+            )
+            .await;
+        }
+    }
+
+    /// Emit a mismatch and its suggestion once per kind, queueing it when auto-apply is on
+    async fn report_mismatch(
+        &self,
+        span_id: u32,
+        mismatch: SpanMismatch,
+        setting: SpanSetting,
+        confidence: f64,
+        reason: String,
+    ) {
+        {
+            let mut states = self.span_states.write().await;
+            match states.get_mut(&span_id) {
+                Some(state) if state.reported_mismatches.insert(mismatch.kind()) => {}
+                _ => return,
+            }
+        }
+
+        warn!("Span {} mismatch: {:?} (confidence: {:.2})", span_id, mismatch, confidence);
+        let _ = self.event_tx.send(DetectionEvent::MismatchDetected { span_id, mismatch, confidence });
+
+        let suggestion = SpanSettingsSuggestion { span_id, setting, confidence, reason };
+        if self.config.auto_apply && confidence >= self.config.confidence_threshold && suggestion.is_applicable() {
+            self.pending_settings.lock().unwrap().push(suggestion.clone());
+        }
+        let _ = self.event_tx.send(DetectionEvent::SettingsSuggested { span_id, suggestion });
     }
 
     async fn detect_mobile_network(&self, span_id: u32) -> Result<()> {
@@ -487,7 +754,7 @@ impl AutoDetectionService {
                         span_id,
                         error: "Maximum retry attempts reached".to_string(),
                     });
-                    return Err(Error::internal("Maximum retry attempts reached"));
+                    return Err(Error::timeout("Maximum retry attempts reached"));
                 }
             }
         }
@@ -532,17 +799,8 @@ impl AutoDetectionService {
             }
 
             if let Some(switch) = &state.detected_switch {
-                config.switch_type = match switch {
-                    SwitchType::EuroISDN => "euroISDN".to_string(),
-                    SwitchType::NationalISDN2 => "national".to_string(),
-                    SwitchType::NationalISDN1 => "ni1".to_string(),
-                    SwitchType::Dms100 => "dms100".to_string(),
-                    SwitchType::Ess5 => "5ess".to_string(),
-                    SwitchType::Lucent5e => "lucent5e".to_string(),
-                    SwitchType::Nortel => "nortel".to_string(),
-                    SwitchType::Unknown => "unknown".to_string(),
-                };
-                confidence_sum += 0.85; // Good confidence for switch detection
+                config.switch_type = switch.config_name().to_string();
+                confidence_sum += state.switch_confidence;
                 confidence_count += 1;
             }
 
@@ -571,6 +829,155 @@ impl AutoDetectionService {
     }
 }
 
+fn crc_error_ratio(observation: &LineObservation) -> f64 {
+    if observation.frames == 0 {
+        0.0
+    } else {
+        (observation.crc_errors as f64 / observation.frames as f64).min(1.0)
+    }
+}
+
+/// Framing or CRC disagreement in a framer report, with the fix and confidence
+fn line_mismatch(observation: &LineObservation) -> Option<(SpanMismatch, SpanSetting, f64)> {
+    let configured = observation.configured_framing.to_ascii_lowercase();
+    let framing_setting = |framing: &str| match framing {
+        "crc4" => Some(SpanSetting::E1Framing(E1Framing::Crc4)),
+        "no-crc4" => Some(SpanSetting::E1Framing(E1Framing::NoCrc4)),
+        "esf" => Some(SpanSetting::T1Framing(T1Framing::Esf)),
+        "d4" => Some(SpanSetting::T1Framing(T1Framing::D4)),
+        _ => None,
+    };
+
+    if let Some(aligned) = &observation.aligned_framing {
+        let aligned = aligned.to_ascii_lowercase();
+        if aligned != configured {
+            // A far end we disagree with usually answers with remote alarm
+            let confidence = if observation.remote_alarm { 0.95 } else { 0.85 };
+            let setting = framing_setting(&aligned)?;
+            return Some((SpanMismatch::Framing { configured, detected: aligned }, setting, confidence));
+        }
+    }
+
+    let ratio = crc_error_ratio(observation);
+    if observation.frames >= 1000 && ratio >= 0.5 {
+        let setting = match configured.as_str() {
+            "crc4" => SpanSetting::E1Framing(E1Framing::NoCrc4),
+            "esf" => SpanSetting::T1Framing(T1Framing::D4),
+            _ => return None,
+        };
+        let confidence = (0.5 + ratio / 2.0).min(0.99);
+        return Some((SpanMismatch::Crc { configured, crc_error_ratio: ratio }, setting, confidence));
+    }
+
+    None
+}
+
+/// Timeslot a B-channel lands on when one side numbers channels in reverse
+fn mirrored_timeslot(line_type: &Layer1Type, channel: u8) -> u8 {
+    match line_type {
+        Layer1Type::E1 => 32u8.saturating_sub(channel),
+        Layer1Type::T1 => 25u8.saturating_sub(channel),
+    }
+}
+
+/// Switch variants suggested by the protocol details of one D-channel message
+fn switch_indicators(message: &[u8]) -> Vec<(SwitchType, f64)> {
+    let mut found = Vec::new();
+    let (Some(&discriminator), Some(&cref_length)) = (message.first(), message.get(1)) else {
+        return found;
+    };
+    let header = 3 + (cref_length & 0x0f) as usize;
+    let Some(&message_type) = message.get(header - 1) else {
+        return found;
+    };
+
+    match discriminator {
+        0x08 => {}
+        // Maintenance SERVICE messages are a North American practice
+        0x03 => {
+            found.push((SwitchType::NationalISDN2, 1.0));
+            found.push((SwitchType::Lucent5e, 1.0));
+            found.push((SwitchType::Dms100, 0.5));
+            return found;
+        }
+        _ => return found,
+    }
+
+    // FACILITY carries ETSI supplementary services
+    if message_type == 0x62 {
+        found.push((SwitchType::EuroISDN, 1.0));
+    }
+
+    let mut pos = header;
+    let mut codeset = 0;
+    let mut next_codeset = None;
+    while pos < message.len() {
+        let id = message[pos];
+        if id & 0x80 != 0 {
+            if id & 0xf0 == 0x90 {
+                if id & 0x08 == 0 {
+                    codeset = id & 0x07;
+                } else {
+                    next_codeset = Some(id & 0x07);
+                }
+                match id & 0x07 {
+                    5 => {
+                        found.push((SwitchType::NationalISDN2, 1.5));
+                        found.push((SwitchType::Lucent5e, 0.5));
+                    }
+                    6 => {
+                        found.push((SwitchType::Lucent5e, 1.5));
+                        found.push((SwitchType::Dms100, 1.0));
+                    }
+                    _ => {}
+                }
+            }
+            pos += 1;
+            continue;
+        }
+
+        let Some(&length) = message.get(pos + 1) else {
+            break;
+        };
+        let Some(contents) = message.get(pos + 2..pos + 2 + length as usize) else {
+            break;
+        };
+        if next_codeset.take().unwrap_or(codeset) == 0 {
+            match id {
+                IE_CAUSE => match contents.first().map(|octet| (octet >> 5) & 0x03) {
+                    Some(0) => found.push((SwitchType::EuroISDN, 0.25)),
+                    Some(2) => {
+                        found.push((SwitchType::Lucent5e, 1.0));
+                        found.push((SwitchType::Ess5, 1.0));
+                    }
+                    _ => {}
+                },
+                IE_CHANNEL_ID if contents.len() >= 3 => {
+                    // Explicit interface identifier: NFAS groups
+                    if contents[0] & 0x40 != 0 {
+                        found.push((SwitchType::NationalISDN2, 0.5));
+                        found.push((SwitchType::Dms100, 0.5));
+                        found.push((SwitchType::Lucent5e, 0.5));
+                    }
+                    // Channels above 24 only exist on E1
+                    if contents[contents.len() - 1] & 0x7f > 24 {
+                        found.push((SwitchType::EuroISDN, 2.0));
+                    }
+                }
+                IE_FACILITY => found.push((SwitchType::EuroISDN, 1.0)),
+                IE_DISPLAY => {
+                    found.push((SwitchType::Dms100, 1.0));
+                    found.push((SwitchType::NationalISDN2, 0.5));
+                }
+                _ => {}
+            }
+        }
+        pos += 2 + length as usize;
+    }
+
+    found
+}
+
 /// Recommended configuration based on detection results
 #[derive(Debug, Clone)]
 pub struct RecommendedConfig {
@@ -625,4 +1032,78 @@ mod tests {
         assert!(result.is_some());
         assert_eq!(result.unwrap().span_id, 1);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_switch_type_detected_from_d_channel() {
+        let config = AutoDetectionConfig {
+            configured_switch_type: "national".to_string(),
+            min_d_channel_messages: 4,
+            auto_apply: true,
+            ..Default::default()
+        };
+        let mut service = AutoDetectionService::new(config);
+        let mut events = service.take_event_receiver().unwrap();
+        service.start_detection(1).await.unwrap();
+
+        // SETUP for B-channel 25 carrying a facility element
+        let setup = [0x08, 0x02, 0x00, 0x01, 0x05, 0x18, 0x03, 0xa9, 0x83, 0x99, 0x1c, 0x02, 0x91, 0xa1];
+        for _ in 0..3 {
+            service.observe_d_channel(1, &setup).await.unwrap();
+        }
+        // Not enough traffic yet for full confidence
+        assert_eq!(service.get_detection_results(1).await.unwrap().detected_switch, None);
+
+        service.observe_d_channel(1, &setup).await.unwrap();
+        let state = service.get_detection_results(1).await.unwrap();
+        assert_eq!(state.detected_switch, Some(SwitchType::EuroISDN));
+
+        let mut saw_mismatch = false;
+        while let Ok(event) = events.try_recv() {
+            if let DetectionEvent::MismatchDetected { mismatch, confidence, .. } = event {
+                assert!(matches!(mismatch, SpanMismatch::SwitchType { detected: SwitchType::EuroISDN, .. }));
+                assert!(confidence >= 0.8);
+                saw_mismatch = true;
+            }
+        }
+        assert!(saw_mismatch);
+
+        let pending = service.take_pending_settings();
+        assert_eq!(pending.len(), 1);
+        let mut gateway_config = GatewayConfig::default_config();
+        gateway_config.pri.switch_type = "national".to_string();
+        assert!(pending[0].apply_to(&mut gateway_config));
+        assert_eq!(gateway_config.pri.switch_type, "euroISDN");
+    }
+
+    #[tokio::test]
+    async fn test_crc4_mismatch_and_inverted_timeslots() {
+        let service = AutoDetectionService::new(AutoDetectionConfig::default());
+        service.start_detection(2).await.unwrap();
+
+        let observation = LineObservation {
+            line_type: Layer1Type::E1,
+            configured_framing: "crc4".to_string(),
+            aligned_framing: Some("crc4".to_string()),
+            line_code: "hdb3".to_string(),
+            frames: 8000,
+            crc_errors: 7900,
+            remote_alarm: false,
+            signal_level: -6.0,
+        };
+        let (mismatch, setting, confidence) = line_mismatch(&observation).unwrap();
+        assert!(matches!(mismatch, SpanMismatch::Crc { .. }));
+        assert!(matches!(setting, SpanSetting::E1Framing(E1Framing::NoCrc4)));
+        assert!(confidence > 0.9);
+        service.observe_line_status(2, observation).await.unwrap();
+        assert!(service.get_detection_results(2).await.unwrap().detected_characteristics.is_some());
+        // Suggestions are only queued with auto-apply enabled
+        assert!(service.take_pending_settings().is_empty());
+
+        for channel in [1u8, 2, 3, 5, 10] {
+            service.observe_bearer_activity(2, channel, 32 - channel).await.unwrap();
+        }
+        let state = service.get_detection_results(2).await.unwrap();
+        assert!(state.reported_mismatches.contains("inverted_timeslots"));
+        assert!(state.reported_mismatches.contains("crc"));
+    }
+}
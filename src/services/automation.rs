@@ -0,0 +1,473 @@
+//! Scripted maintenance actions
+//!
+//! Common NOC runbooks written as one-line rules the gateway evaluates
+//! itself, such as
+//! `if trunk carrier-a down for 5m then busy-out span 2, webhook noc`.
+//!
+//! Conditions are `trunk <name> down` (the span of that name, or
+//! `span-<n>`), `span <n> down` and `alarm <severity> active` (an active
+//! alarm of that severity or worse), optionally followed by `for <time>`
+//! in seconds, minutes or hours (`30s`, `5m`, `1h`). Actions, separated by
+//! commas, are `busy-out span <n>`, `restore span <n>`, `restart span <n>`
+//! and `webhook <name>`.
+//!
+//! A rule fires once when its condition has held for the whole time and
+//! rearms when the condition clears. Every action, including those a
+//! dry run only pretends to take, is logged and kept in an audit trail.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::AutomationConfig;
+use crate::services::alarms::AlarmSeverity;
+use crate::{Error, Result};
+
+/// Audit records kept in memory; the log file keeps them all
+const MAX_AUDIT_RECORDS: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleCondition {
+    TrunkDown(String),
+    SpanDown(u32),
+    /// An alarm of this severity or worse is active
+    AlarmActive(AlarmSeverity),
+}
+
+impl RuleCondition {
+    pub fn holds(&self, state: &ObservedState) -> bool {
+        match self {
+            RuleCondition::TrunkDown(trunk) => state.spans.iter().any(|span| span.trunk == *trunk && !span.up),
+            RuleCondition::SpanDown(span_id) => state.spans.iter().any(|span| span.span_id == *span_id && !span.up),
+            RuleCondition::AlarmActive(severity) => {
+                state.alarms.iter().any(|alarm| severity_rank(alarm) >= severity_rank(severity))
+            }
+        }
+    }
+}
+
+impl fmt::Display for RuleCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleCondition::TrunkDown(trunk) => write!(f, "trunk {} down", trunk),
+            RuleCondition::SpanDown(span_id) => write!(f, "span {} down", span_id),
+            RuleCondition::AlarmActive(severity) => write!(f, "alarm {} active", severity_name(severity)),
+        }
+    }
+}
+
+fn severity_rank(severity: &AlarmSeverity) -> u8 {
+    match severity {
+        AlarmSeverity::Critical => 4,
+        AlarmSeverity::Major => 3,
+        AlarmSeverity::Minor => 2,
+        AlarmSeverity::Warning => 1,
+        AlarmSeverity::Indeterminate | AlarmSeverity::Cleared => 0,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleAction {
+    /// Block the span's idle channels so no new calls use it
+    BusyOut(u32),
+    /// Return a busied-out span to service
+    Restore(u32),
+    RestartSpan(u32),
+    /// POST the firing to the named webhook
+    Webhook(String),
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleAction::BusyOut(span_id) => write!(f, "busy-out span {}", span_id),
+            RuleAction::Restore(span_id) => write!(f, "restore span {}", span_id),
+            RuleAction::RestartSpan(span_id) => write!(f, "restart span {}", span_id),
+            RuleAction::Webhook(name) => write!(f, "webhook {}", name),
+        }
+    }
+}
+
+/// A parsed maintenance rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutomationRule {
+    pub name: String,
+    pub condition: RuleCondition,
+    /// How long the condition must hold before the rule fires
+    pub hold_for: Duration,
+    pub actions: Vec<RuleAction>,
+}
+
+impl AutomationRule {
+    /// Parse `if <condition> [for <time>] then <action>[, <action>...]`
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        let invalid = |reason: String| Error::invalid_config(format!("Automation rule {}: {}", name, reason));
+        let text = text.trim();
+        let body = text
+            .strip_prefix("if ")
+            .ok_or_else(|| invalid("must start with \"if\"".to_string()))?;
+        let (condition, actions) = body
+            .split_once(" then ")
+            .ok_or_else(|| invalid("has no \"then\"".to_string()))?;
+
+        let words: Vec<&str> = condition.split_whitespace().collect();
+        let (words, hold_for) = match words[..] {
+            [ref words @ .., "for", time] => (words, parse_duration(time).ok_or_else(|| invalid(format!("invalid time {}", time)))?),
+            ref words => (words, Duration::ZERO),
+        };
+        let span = |span_id: &str| parse_span(span_id).ok_or_else(|| invalid(format!("invalid span {}", span_id)));
+        let condition = match *words {
+            ["trunk", trunk, "down"] => RuleCondition::TrunkDown(trunk.to_string()),
+            ["span", span_id, "down"] => RuleCondition::SpanDown(span(span_id)?),
+            ["alarm", severity, "active"] => RuleCondition::AlarmActive(
+                parse_severity(severity).ok_or_else(|| invalid(format!("unknown alarm severity {}", severity)))?,
+            ),
+            _ => return Err(invalid(format!("unknown condition \"{}\"", condition.trim()))),
+        };
+
+        let actions = actions
+            .split(',')
+            .map(|action| {
+                match action.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["busy-out", "span", span_id] => Ok(RuleAction::BusyOut(span(span_id)?)),
+                    ["restore", "span", span_id] => Ok(RuleAction::Restore(span(span_id)?)),
+                    ["restart", "span", span_id] => Ok(RuleAction::RestartSpan(span(span_id)?)),
+                    ["webhook", webhook] => Ok(RuleAction::Webhook(webhook.to_string())),
+                    _ => Err(invalid(format!("unknown action \"{}\"", action.trim()))),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { name: name.to_string(), condition, hold_for, actions })
+    }
+
+    /// Names of the webhooks the rule calls
+    pub fn webhooks(&self) -> impl Iterator<Item = &str> {
+        self.actions.iter().filter_map(|action| match action {
+            RuleAction::Webhook(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+}
+
+fn parse_span(span_id: &str) -> Option<u32> {
+    span_id.parse().ok()
+}
+
+fn parse_duration(time: &str) -> Option<Duration> {
+    let (value, unit) = time.split_at(time.find(|c: char| !c.is_ascii_digit()).unwrap_or(time.len()));
+    let value: u64 = value.parse().ok()?;
+    let secs = match unit {
+        "" | "s" => value,
+        "m" => value * 60,
+        "h" => value * 3600,
+        _ => return None,
+    };
+    Some(Duration::from_secs(secs))
+}
+
+const SEVERITIES: [AlarmSeverity; 4] = [
+    AlarmSeverity::Critical,
+    AlarmSeverity::Major,
+    AlarmSeverity::Minor,
+    AlarmSeverity::Warning,
+];
+
+fn severity_name(severity: &AlarmSeverity) -> &'static str {
+    match severity {
+        AlarmSeverity::Critical => "critical",
+        AlarmSeverity::Major => "major",
+        AlarmSeverity::Minor => "minor",
+        AlarmSeverity::Warning => "warning",
+        AlarmSeverity::Indeterminate => "indeterminate",
+        AlarmSeverity::Cleared => "cleared",
+    }
+}
+
+fn parse_severity(severity: &str) -> Option<AlarmSeverity> {
+    SEVERITIES.into_iter().find(|candidate| severity_name(candidate) == severity)
+}
+
+/// A span as the rules see it
+#[derive(Debug, Clone)]
+pub struct ObservedSpan {
+    pub span_id: u32,
+    /// Span name, or `span-<n>` for an unnamed span
+    pub trunk: String,
+    pub up: bool,
+}
+
+/// Gateway state the rules are evaluated against
+#[derive(Debug, Clone, Default)]
+pub struct ObservedState {
+    pub spans: Vec<ObservedSpan>,
+    /// Severities of the active alarms
+    pub alarms: Vec<AlarmSeverity>,
+}
+
+/// An action a rule calls for, to be carried out and then recorded
+#[derive(Debug, Clone)]
+pub struct Firing {
+    pub rule: String,
+    pub condition: String,
+    pub action: RuleAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// Dry run; nothing was done
+    DryRun,
+    Done,
+    Failed { error: String },
+}
+
+/// One entry of the audit trail
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutomationAuditRecord {
+    pub at: DateTime<Utc>,
+    pub rule: String,
+    pub condition: String,
+    pub action: String,
+    pub outcome: AuditOutcome,
+}
+
+/// Body POSTed to a webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub node_id: String,
+    pub rule: String,
+    pub condition: String,
+    pub fired_at: DateTime<Utc>,
+}
+
+struct RuleState {
+    since: Instant,
+    fired: bool,
+}
+
+/// Evaluates the maintenance rules and audits what they do
+pub struct AutomationEngine {
+    config: AutomationConfig,
+    rules: Vec<AutomationRule>,
+    /// Rules whose condition holds, by name
+    holding: DashMap<String, RuleState>,
+    client: reqwest::Client,
+    audit: Mutex<VecDeque<AutomationAuditRecord>>,
+}
+
+impl AutomationEngine {
+    pub fn new(config: AutomationConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| AutomationRule::parse(&rule.name, &rule.rule))
+            .collect::<Result<Vec<_>>>()?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.webhook_timeout_ms))
+            .build()
+            .map_err(|e| Error::invalid_config(format!("Failed to create automation webhook client: {}", e)))?;
+        info!(
+            "Loaded {} automation rules{}",
+            rules.len(),
+            if config.dry_run { " in dry-run mode" } else { "" }
+        );
+        Ok(Self {
+            config,
+            rules,
+            holding: DashMap::new(),
+            client,
+            audit: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    pub fn evaluation_interval(&self) -> Duration {
+        Duration::from_secs(self.config.evaluation_interval_secs)
+    }
+
+    /// Actions of the rules whose condition has now held long enough. In a
+    /// dry run they are audited here and nothing is returned to carry out
+    pub fn evaluate(&self, state: &ObservedState, now: Instant) -> Vec<Firing> {
+        let mut firings = Vec::new();
+        for rule in &self.rules {
+            if !rule.condition.holds(state) {
+                if self.holding.remove(&rule.name).is_some_and(|(_, held)| held.fired) {
+                    info!("Automation rule {} rearmed: {} cleared", rule.name, rule.condition);
+                }
+                continue;
+            }
+            let mut held = self.holding.entry(rule.name.clone()).or_insert(RuleState { since: now, fired: false });
+            if held.fired || now.duration_since(held.since) < rule.hold_for {
+                continue;
+            }
+            held.fired = true;
+            for action in &rule.actions {
+                let firing = Firing {
+                    rule: rule.name.clone(),
+                    condition: rule.condition.to_string(),
+                    action: action.clone(),
+                };
+                if self.config.dry_run {
+                    self.record(&firing, AuditOutcome::DryRun);
+                } else {
+                    firings.push(firing);
+                }
+            }
+        }
+        firings
+    }
+
+    /// POST a webhook firing in the background, auditing the outcome
+    pub fn send_webhook(self: &Arc<Self>, firing: Firing, node_id: String) {
+        let engine = Arc::clone(self);
+        tokio::spawn(async move {
+            let outcome = match engine.post_webhook(&firing, node_id).await {
+                Ok(()) => AuditOutcome::Done,
+                Err(e) => AuditOutcome::Failed { error: e.to_string() },
+            };
+            engine.record(&firing, outcome);
+        });
+    }
+
+    async fn post_webhook(&self, firing: &Firing, node_id: String) -> Result<()> {
+        let RuleAction::Webhook(ref name) = firing.action else {
+            return Err(Error::invalid_state(format!("{} is not a webhook", firing.action)));
+        };
+        let url = self
+            .config
+            .webhooks
+            .get(name)
+            .ok_or_else(|| Error::invalid_config(format!("Unknown webhook {}", name)))?;
+        let payload = WebhookPayload {
+            node_id,
+            rule: firing.rule.clone(),
+            condition: firing.condition.clone(),
+            fired_at: Utc::now(),
+        };
+        let response = self
+            .client
+            .post(url)
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| Error::network(format!("Webhook {} request failed: {}", name, e)))?;
+        if !response.status().is_success() {
+            return Err(Error::network(format!("Webhook {} returned {}", name, response.status())));
+        }
+        Ok(())
+    }
+
+    /// Audit trail, oldest first
+    pub fn audit(&self) -> Vec<AutomationAuditRecord> {
+        self.audit.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Log and audit what became of a firing
+    pub fn record(&self, firing: &Firing, outcome: AuditOutcome) {
+        let record = AutomationAuditRecord {
+            at: Utc::now(),
+            rule: firing.rule.clone(),
+            condition: firing.condition.clone(),
+            action: firing.action.to_string(),
+            outcome,
+        };
+        match record.outcome {
+            AuditOutcome::Failed { ref error } => {
+                warn!("Automation rule {} ({}) failed to {}: {}", record.rule, record.condition, record.action, error)
+            }
+            ref outcome => info!("Automation rule {} ({}): {} ({:?})", record.rule, record.condition, record.action, outcome),
+        }
+
+        if !self.config.audit_log_path.is_empty() {
+            if let Err(e) = append_audit(&self.config.audit_log_path, &record) {
+                warn!("Failed to write automation audit log {}: {}", self.config.audit_log_path, e);
+            }
+        }
+        let mut audit = self.audit.lock().unwrap();
+        audit.push_back(record);
+        while audit.len() > MAX_AUDIT_RECORDS {
+            audit.pop_front();
+        }
+    }
+}
+
+fn append_audit(path: &str, record: &AutomationAuditRecord) -> std::io::Result<()> {
+    let line = serde_json::to_string(record).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AutomationRuleConfig;
+
+    fn state(carrier_a_up: bool) -> ObservedState {
+        ObservedState {
+            spans: vec![
+                ObservedSpan { span_id: 1, trunk: "carrier-a".to_string(), up: carrier_a_up },
+                ObservedSpan { span_id: 2, trunk: "span-2".to_string(), up: true },
+            ],
+            alarms: vec![AlarmSeverity::Major],
+        }
+    }
+
+    #[test]
+    fn test_rule_parsing() {
+        let rule = AutomationRule::parse("outage", "if trunk carrier-a down for 5m then busy-out span 2, webhook noc").unwrap();
+        assert_eq!(rule.condition, RuleCondition::TrunkDown("carrier-a".to_string()));
+        assert_eq!(rule.hold_for, Duration::from_secs(300));
+        assert_eq!(rule.actions, vec![RuleAction::BusyOut(2), RuleAction::Webhook("noc".to_string())]);
+        assert_eq!(rule.webhooks().collect::<Vec<_>>(), vec!["noc"]);
+
+        let rule = AutomationRule::parse("alarm", "if alarm minor active then restart span 1").unwrap();
+        assert_eq!(rule.hold_for, Duration::ZERO);
+        assert!(rule.condition.holds(&state(true)));
+
+        assert!(AutomationRule::parse("x", "trunk a down then busy-out span 2").is_err());
+        assert!(AutomationRule::parse("x", "if trunk a down for 5d then busy-out span 2").is_err());
+        assert!(AutomationRule::parse("x", "if span two down then busy-out span 2").is_err());
+        assert!(AutomationRule::parse("x", "if span 1 down then reboot").is_err());
+    }
+
+    #[test]
+    fn test_rules_fire_once_per_episode() {
+        let mut config = AutomationConfig {
+            enabled: true,
+            dry_run: false,
+            ..Default::default()
+        };
+        config.rules.push(AutomationRuleConfig {
+            name: "outage".to_string(),
+            rule: "if trunk carrier-a down for 5m then busy-out span 2".to_string(),
+        });
+        let engine = AutomationEngine::new(config.clone()).unwrap();
+        let start = Instant::now();
+        assert!(engine.evaluate(&state(false), start).is_empty());
+        assert!(engine.evaluate(&state(false), start + Duration::from_secs(299)).is_empty());
+        let firings = engine.evaluate(&state(false), start + Duration::from_secs(300));
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].action, RuleAction::BusyOut(2));
+        assert!(engine.evaluate(&state(false), start + Duration::from_secs(600)).is_empty());
+
+        // Clearing rearms the rule
+        assert!(engine.evaluate(&state(true), start + Duration::from_secs(601)).is_empty());
+        assert!(engine.evaluate(&state(false), start + Duration::from_secs(602)).is_empty());
+        assert_eq!(engine.evaluate(&state(false), start + Duration::from_secs(902)).len(), 1);
+
+        config.dry_run = true;
+        let dry_run = AutomationEngine::new(config).unwrap();
+        dry_run.evaluate(&state(false), start);
+        assert!(dry_run.evaluate(&state(false), start + Duration::from_secs(300)).is_empty());
+        let audit = dry_run.audit();
+        assert_eq!(audit.len(), 1);
+        assert_eq!((audit[0].action.as_str(), &audit[0].outcome), ("busy-out span 2", &AuditOutcome::DryRun));
+    }
+}
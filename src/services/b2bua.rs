@@ -3,24 +3,89 @@
 //! This module provides comprehensive B2BUA functionality for call relay,
 //! session management, and media bridging between two SIP call legs.
 
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, oneshot, RwLock};
 use tokio::time::interval;
 use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
-use crate::config::{B2buaConfig, RouteType, NumberTranslation};
+use crate::config::{
+    B2buaConfig, EarlyMediaPolicy, QuirkProfile, RerouteAction, RingGroup, RouteType, RoutingRule, NumberTranslation,
+};
+use crate::interfaces::freetdm::FreeTdmEvent;
+use crate::protocols::mime::BodyPart;
+use crate::protocols::q931::{self, MessageType, Q931Message};
 use crate::protocols::sip::{SipEvent, SipHandler};
+use crate::protocols::sip_registrar::Registration;
+use crate::protocols::sip_time::SipTimestamp;
+use crate::protocols::sip_transport;
+use crate::protocols::rtcp_xr::VoipMetrics;
 use crate::protocols::rtp::{RtpEvent, RtpHandler};
+use crate::protocols::sdp::SessionDescription;
+use crate::protocols::udptl::IfpPacket;
+use crate::protocols::ice::IceCredentials;
+use crate::protocols::srtp::{self, CryptoAttribute, PendingSrtp, SrtpProfile};
+use crate::protocols::webrtc::{self, DtlsSetup, LocalParameters, PendingWebRtc};
+use crate::services::answer_supervision::{AnswerSupervisor, CallDirection, SupervisionSignal};
+use crate::services::cdr::{extract_custom_fields, sip_user_to_user, CallType, CallingPartyCategory, CdrService};
+use crate::services::call_trace::{CallTracer, TraceSelector, TraceSubsystem};
+use crate::services::call_gapping::{ActiveGap, CallGapController, GapDecision, GappingEvent};
+use crate::services::cps_shaping::{AdmissionOutcome, CpsShaper};
+use crate::services::alarms::AlarmManager;
+use crate::services::media_security::{LegSecurity, MediaSecurityCounter, MediaSecurityMonitor, MediaSecurityStatus};
+use crate::services::media_inactivity::{MediaCallType, MediaInactivity};
+use crate::services::media_interfaces::{MediaInterfaceSelector, ResolvedInterface};
+use crate::services::media_policy::{MediaPolicyEnforcer, PolicyOutcome, SdpRole};
+use crate::services::no_answer::{self, LegAttempt, LegKind, LegOutcome, NoAnswerPolicy, CAUSE_NO_ANSWER};
+use crate::services::number_lookup::{NumberLookup, NumberLookupResult, NumberLookupRunner};
+use crate::services::quirks::QuirkRegistry;
+use crate::services::quality_baseline::{QualityBaselineMonitor, QualityEvent, QualitySample};
+use crate::services::vq_reporting::RtcpXrReporter;
+use crate::services::reroute::{RerouteCounter, RerouteDecider};
+use crate::services::early_media::{EarlyMediaCounter, EarlyMediaGate};
+use crate::services::release_causes::{self, ReleaseCauseQuery, ReleaseCauseReport, ReleaseCauseStats, CAUSE_NORMAL_CLEARING};
+use crate::services::prompts::{PromptLibrary, SelectedPrompt};
+use crate::services::test_numbers::{TestCallRecord, TestNumbers};
+use crate::services::paging::{PageRecord, Paging};
+use crate::services::precedence::{self, PrecedenceLevel, PrecedencePolicy, PreemptionCandidate, ResourcePriority};
+use crate::services::reject_announcements::{self, RejectReason};
+use crate::services::reliable_provisional::{self, CallProvisionals, DeferredAnswer, Provisional, RAck, ReliableSend, ReliableSender, SenderAction, TdmIndication};
+use crate::services::session_timer::{self, CallSessionTimers, LegTimer, SessionTimer, TimerAction, UasNegotiation};
+use crate::services::trunk_registration::{TrunkRegistrar, TrunkRegistrationStatus};
+use crate::services::codec_negotiation::{CodecNegotiationCounter, CodecNegotiator, OfferOutcome, TranscodingHeadroom};
+use crate::services::transcoding_latency::{TranscodingLatencyMonitor, TranscodingLatencyReport};
+use crate::services::fax_relay::{FaxMode, FaxRelay, FaxTone};
+use crate::services::route_advertisement::RouteAdvertiser;
+use crate::services::shadow_routing::{RouteDecision, ShadowRouter, ShadowRoutingSummary};
+use crate::services::upgrade::{self, CallSnapshot, DialogSnapshot, RtpEndpoint, SnapshotCall, UpgradeRequest};
+use crate::services::ring_group::RingFork;
+use crate::services::routing_hook::{RouteResolution, RoutingHook, RoutingHookRequest, RoutingHookRunner};
+use crate::services::takeover::{self, CallTakeover, TakeoverRecord, TakeoverRequest};
+use crate::services::transfer::{self, CallTransfers, ReferTo, TransferRecord};
+use crate::services::call_hold::{self, CallHolds, HoldTreatment};
+use crate::services::correlation;
+use crate::services::survivability::{
+    RegisterRequest, SurvivabilityEvent, SurvivabilityMode, SurvivabilityService,
+};
+use crate::services::trunk_failure::{
+    FailureDisposition, TrunkFailureEvent, TrunkFailureHandler, CAUSE_NETWORK_OUT_OF_ORDER,
+    CAUSE_RECOVERY_ON_TIMER_EXPIRY,
+};
+use crate::utils::ClockStamp;
 use crate::{Error, Result};
 
 /// B2BUA call leg identifier
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
 pub enum CallLeg {
     A, // Incoming call leg
     B, // Outgoing call leg
@@ -41,6 +106,9 @@ pub enum B2buaCallState {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct B2buaCall {
     pub id: String,
+    /// Gateway-global ID shared by both legs and passed on to other systems
+    #[serde(default)]
+    pub correlation_id: String,
     pub state: B2buaCallState,
     pub leg_a_session_id: String,
     pub leg_b_session_id: Option<String>,
@@ -60,16 +128,99 @@ pub struct B2buaCall {
     #[serde(skip, default)]
     pub call_duration: Option<Duration>,
     pub routing_info: RoutingInfo,
+    /// Custom CDR fields extracted from the inbound signaling
+    #[serde(default)]
+    pub custom_fields: BTreeMap<String, String>,
+    /// Relay endpoints advertised to each leg when pinned to a media interface
+    #[serde(default)]
+    pub media_anchor: Option<MediaAnchor>,
+    /// Leg B attempts in the order they were placed
+    #[serde(default)]
+    pub leg_attempts: Vec<LegAttempt>,
+    /// ISUP/QSIG received with leg A's INVITE, passed on to leg B unchanged
+    #[serde(default)]
+    pub encapsulated: Vec<BodyPart>,
+    /// Ring group branches still ringing; leg B is set once one answers
+    #[serde(default)]
+    pub fork: Option<RingFork>,
+    /// Leg A's offer after media policy, compared with leg B's answer to
+    /// tell relayed from transcoded calls
+    #[serde(default)]
+    pub leg_a_offer: Option<String>,
+    /// When leg B first sent early media, for the route's early media limit
+    #[serde(default)]
+    pub early_media_at: Option<DateTime<Utc>>,
+    /// SRTP keys of each leg while the relay terminates SRTP
+    #[serde(skip)]
+    pub srtp: PendingSrtp,
+    /// ICE and DTLS parameters of the legs that are WebRTC
+    #[serde(skip)]
+    pub webrtc: PendingWebRtc,
+    /// Media protection each leg negotiated
+    #[serde(default)]
+    pub media_security: MediaSecurityStatus,
+    /// Resource-Priority of a priority call; routine calls have none
+    #[serde(default)]
+    pub precedence: Option<ResourcePriority>,
+    /// Session interval granted to leg A's INVITE
+    #[serde(default)]
+    pub session_timer: Option<SessionTimer>,
+    /// Running session timers of each leg once the call is answered
+    #[serde(skip)]
+    pub session_timers: CallSessionTimers,
+    /// 100rel state of each leg while the call is being set up
+    #[serde(skip)]
+    pub provisionals: CallProvisionals,
+}
+
+/// Advertised relay endpoints written into each leg's SDP
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaAnchor {
+    pub leg_a: Option<SocketAddr>,
+    pub leg_b: Option<SocketAddr>,
 }
 
 /// Call routing information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoutingInfo {
     pub route_type: RouteType,
+    /// Id of the routing rule the call matched
+    #[serde(default)]
+    pub route: Option<String>,
     pub target_gateway: Option<String>,
     pub number_translation: Option<NumberTranslation>,
     pub codec_preference: Vec<String>,
     pub priority: u8,
+    /// Number dialed on leg B in place of the translated callee
+    #[serde(default)]
+    pub callee_override: Option<String>,
+    /// Headers added to the leg B INVITE
+    #[serde(default)]
+    pub extra_headers: BTreeMap<String, String>,
+    /// TDM spans used instead of a SIP leg B, in preference order
+    #[serde(default)]
+    pub egress_spans: Vec<u32>,
+    /// Portability dip of the callee; signalled on leg B as `rn`/`npdi`
+    #[serde(default)]
+    pub number_lookup: Option<NumberLookupResult>,
+    /// Caller name from a CNAM dip, used as the leg B display name
+    #[serde(default)]
+    pub caller_name: Option<String>,
+    /// Ring-no-answer timer and forwarding targets for leg B
+    #[serde(default)]
+    pub no_answer: Option<NoAnswerPolicy>,
+    /// Targets offered the call after a re-routable failure
+    #[serde(default)]
+    pub alternate_targets: Vec<String>,
+    /// Route-level failover actions, consulted before trunk and default actions
+    #[serde(default)]
+    pub failover_actions: BTreeMap<String, RerouteAction>,
+    /// Group the call is forked to instead of a single leg B
+    #[serde(default)]
+    pub ring_group: Option<RingGroup>,
+    /// Early media limits of the matched route
+    #[serde(default)]
+    pub early_media: Option<EarlyMediaPolicy>,
 }
 
 /// B2BUA media relay information
@@ -93,6 +244,7 @@ pub struct MediaRelay {
 pub enum B2buaEvent {
     CallEstablishing {
         call_id: String,
+        correlation_id: String,
         caller: String,
         callee: String,
     },
@@ -103,6 +255,8 @@ pub enum B2buaEvent {
     CallTerminated {
         call_id: String,
         reason: String,
+        /// Q.850 release cause when the gateway released the call itself
+        cause: Option<u16>,
         duration: Option<Duration>,
     },
     MediaRelayStarted {
@@ -120,6 +274,125 @@ pub enum B2buaEvent {
         callee: String,
         route: RoutingInfo,
     },
+    TrunkFailure {
+        event: TrunkFailureEvent,
+    },
+    RoutingHookFailed {
+        callee: String,
+        reason: String,
+        fallback_used: bool,
+    },
+    /// The call is routed on the dialed number
+    NumberLookupFailed {
+        number: String,
+        reason: String,
+    },
+    Survivability {
+        event: SurvivabilityEvent,
+    },
+    QualityAlert {
+        event: QualityEvent,
+    },
+    /// RTCP-XR VoIP metrics of one leg of an ended call
+    CallQuality {
+        call_id: String,
+        leg: CallLeg,
+        metrics: VoipMetrics,
+    },
+    /// A call released to make room for a call of higher precedence
+    CallPreempted {
+        call_id: String,
+        level: PrecedenceLevel,
+        preempted_by: ResourcePriority,
+        cause: u16,
+    },
+    CallGapping {
+        event: GappingEvent,
+    },
+    /// Leg A should hear `announcement` and then be released with `release_status`
+    AnnouncementRequested {
+        session_id: String,
+        callee: String,
+        announcement: String,
+        /// Recording chosen from the prompt packs, if any covers the announcement
+        prompt: Option<SelectedPrompt>,
+        release_status: u16,
+        /// Q.850 cause of the release, for calls refused by the gateway
+        release_cause: Option<u16>,
+        /// Leg A was answered so the announcement is heard past providers
+        /// that drop early media; it is released with BYE
+        answered: bool,
+    },
+    /// Q.931 ALERTING or PROGRESS for the TDM side of a call, from a
+    /// provisional response on leg B
+    TdmProgress {
+        call_id: String,
+        status_code: u16,
+        indication: TdmIndication,
+    },
+    /// Leg B is placed on a local TDM span rather than a SIP trunk; the
+    /// channel it is placed on is passed back with `bind_tdm_channel`
+    TdmBreakout {
+        call_id: String,
+        callee: String,
+        egress_spans: Vec<u32>,
+        /// Encapsulated ISUP/QSIG from leg A for the SS7 or QSIG stack
+        encapsulated: Vec<BodyPart>,
+        correlation_id: String,
+        /// User-user information for the SETUP, carrying the correlation ID
+        /// when it is propagated over Q.931
+        user_user: Option<Vec<u8>>,
+    },
+    /// Leg B went unanswered and the call was offered to `target`
+    CallForwarded {
+        call_id: String,
+        target: String,
+        kind: LegKind,
+        attempt: usize,
+    },
+    /// Leg B failed with a re-routable status and the call was offered to `target`
+    CallRerouted {
+        call_id: String,
+        status_code: u16,
+        target: String,
+        attempt: usize,
+    },
+    /// An operator takeover was requested, completed or failed
+    CallTakeover {
+        record: TakeoverRecord,
+    },
+    /// A party transferred the call with REFER, or the transfer completed or failed
+    CallTransfer {
+        record: TransferRecord,
+    },
+    /// A party held or resumed the call
+    CallHold {
+        call_id: String,
+        held_by: CallLeg,
+        on_hold: bool,
+        /// The held party hears music from the gateway
+        music: bool,
+        /// HOLD or RETRIEVE for the span of a call broken out to TDM, when
+        /// the hold is passed on rather than answered with music
+        tdm_message: Option<MessageType>,
+    },
+    /// Fax was detected on a call broken out to TDM and switched to T.38,
+    /// or left on G.711 when the SIP leg refused it
+    FaxRelay {
+        call_id: String,
+        tone: FaxTone,
+        mode: FaxMode,
+    },
+    /// T.38 packet from the SIP leg for the fax modem on the call's span
+    FaxIfp {
+        call_id: String,
+        packet: IfpPacket,
+    },
+    /// A leg B attempt ended; feeds the per-leg CDR record
+    LegAttemptCompleted {
+        call_id: String,
+        attempt: LegAttempt,
+    },
     Error {
         call_id: Option<String>,
         message: String,
@@ -127,14 +400,71 @@ pub enum B2buaEvent {
 }
 
 
-/// B2BUA service implementation
-pub struct B2buaService {
+/// Configuration and services shared by the B2BUA's event loops and call
+/// handlers; each loop holds its own clone
+#[derive(Clone)]
+struct B2buaContext {
     config: B2buaConfig,
     sip_handler: Arc<RwLock<SipHandler>>,
     rtp_handler: Arc<RwLock<RtpHandler>>,
     calls: Arc<DashMap<String, B2buaCall>>,
     media_relays: Arc<DashMap<String, MediaRelay>>,
     event_tx: mpsc::UnboundedSender<B2buaEvent>,
+    cps_shaper: Option<Arc<CpsShaper>>,
+    routing_hook: Option<Arc<RoutingHookRunner>>,
+    number_lookup: Option<Arc<NumberLookupRunner>>,
+    survivability: Option<Arc<SurvivabilityService>>,
+    answer_supervisor: Arc<AnswerSupervisor>,
+    trunk_failure: Arc<TrunkFailureHandler>,
+    rtcp_xr: Option<Arc<RtcpXrReporter>>,
+    call_gapping: Option<Arc<CallGapController>>,
+    reroute: Option<Arc<RerouteDecider>>,
+    takeover: Option<Arc<CallTakeover>>,
+    transfers: Option<Arc<CallTransfers>>,
+    holds: Option<Arc<CallHolds>>,
+    fax: Option<Arc<FaxRelay>>,
+    quirks: Arc<QuirkRegistry>,
+    call_tracer: Arc<CallTracer>,
+    codec_negotiator: Arc<CodecNegotiator>,
+    /// Media silence timers, by whether a call carries voice, fax or modem
+    media_inactivity: Arc<MediaInactivity>,
+    early_media: Arc<EarlyMediaGate>,
+    media_security: Arc<MediaSecurityMonitor>,
+    release_causes: Arc<ReleaseCauseStats>,
+    prompts: Option<Arc<PromptLibrary>>,
+    test_numbers: Option<Arc<TestNumbers>>,
+    paging: Option<Arc<Paging>>,
+    shadow_routing: Option<Arc<ShadowRouter>>,
+    /// Leg-B sessions opened to re-establish preserved calls, mapped to their call
+    reestablish_sessions: Arc<DashMap<String, String>>,
+    /// CANCEL signals for calls waiting on the call-setup rate shaper
+    queued_invites: Arc<DashMap<String, oneshot::Sender<()>>>,
+}
+
+/// An INVITE opening leg A, as received
+struct IncomingInvite {
+    session_id: String,
+    from: String,
+    to: String,
+    sdp: Option<String>,
+    headers: Vec<(String, String)>,
+    encapsulated: Vec<BodyPart>,
+    source: SocketAddr,
+}
+
+/// B2BUA service implementation
+pub struct B2buaService {
+    context: B2buaContext,
+    survivability_rx: Option<mpsc::UnboundedReceiver<SurvivabilityEvent>>,
+    /// Calls broken out to TDM, by the span and channel carrying leg B
+    tdm_channels: Arc<DashMap<(u32, u8), String>>,
+    trunk_failure_rx: Option<mpsc::UnboundedReceiver<TrunkFailureEvent>>,
+    quality_monitor: Option<Arc<QualityBaselineMonitor>>,
+    quality_rx: Option<mpsc::UnboundedReceiver<QualityEvent>>,
+    call_gapping_rx: Option<mpsc::UnboundedReceiver<GappingEvent>>,
+    transcoding_latency: Arc<TranscodingLatencyMonitor>,
+    trunk_registrar: Option<Arc<TrunkRegistrar>>,
+    route_advertiser: Option<Arc<RouteAdvertiser>>,
     event_rx: Option<mpsc::UnboundedReceiver<B2buaEvent>>,
     sip_event_rx: Option<mpsc::UnboundedReceiver<SipEvent>>,
     rtp_event_rx: Option<mpsc::UnboundedReceiver<RtpEvent>>,
@@ -142,20 +472,162 @@ pub struct B2buaService {
 }
 
 impl B2buaService {
+    /// With `cdr_service`, every call gets a CDR whose answer time is the
+    /// receipt of the answer on leg B
     pub fn new(
         config: B2buaConfig,
         sip_handler: Arc<RwLock<SipHandler>>,
         rtp_handler: Arc<RwLock<RtpHandler>>,
+        cdr_service: Option<Arc<CdrService>>,
     ) -> Result<Self> {
         let (event_tx, event_rx) = mpsc::unbounded_channel();
 
-        Ok(Self {
-            config,
+        let mut answer_supervisor = AnswerSupervisor::new();
+        if let Some(cdr_service) = cdr_service {
+            answer_supervisor.set_cdr_service(cdr_service);
+        }
+
+        let cps_shaper = if config.cps_shaping.enabled {
+            Some(Arc::new(CpsShaper::new(config.cps_shaping.clone())))
+        } else {
+            None
+        };
+
+        // Reject unknown or malformed media interfaces up front
+        Self::media_interface_selector(&config)?;
+
+        let quirks = Arc::new(QuirkRegistry::new(&config.quirks)?);
+        let routing_hook = RoutingHookRunner::from_config(&config.routing_hook)?.map(Arc::new);
+        let number_lookup = NumberLookupRunner::from_config(&config.number_lookup)?.map(Arc::new);
+
+        let (survivability, survivability_rx) = if config.survivability.enabled {
+            let mut service = SurvivabilityService::new(config.survivability.clone())?;
+            let rx = service.take_event_receiver();
+            (Some(Arc::new(service)), rx)
+        } else {
+            (None, None)
+        };
+
+        let mut trunk_failure = TrunkFailureHandler::new(config.trunk_failure.clone());
+        let trunk_failure_rx = trunk_failure.take_event_receiver();
+
+        let (quality_monitor, quality_rx) = if config.quality_alerting.enabled {
+            let mut monitor = QualityBaselineMonitor::new(config.quality_alerting.clone());
+            let rx = monitor.take_event_receiver();
+            (Some(Arc::new(monitor)), rx)
+        } else {
+            (None, None)
+        };
+        let rtcp_xr = config
+            .rtcp_xr
+            .enabled
+            .then(|| Arc::new(RtcpXrReporter::new(config.rtcp_xr.clone(), quality_monitor.clone())));
+
+        let (call_gapping, call_gapping_rx) = if config.call_gapping.enabled {
+            let mut controller = CallGapController::new(config.call_gapping.clone());
+            let rx = controller.take_event_receiver();
+            (Some(Arc::new(controller)), rx)
+        } else {
+            (None, None)
+        };
+
+        let reroute = config
+            .reroute
+            .enabled
+            .then(|| Arc::new(RerouteDecider::new(config.reroute.clone())));
+        let takeover = config
+            .takeover
+            .enabled
+            .then(|| Arc::new(CallTakeover::new(config.takeover.clone())));
+        let transfers = config
+            .transfer
+            .enabled
+            .then(|| Arc::new(CallTransfers::new(config.transfer.clone())));
+        let holds = if config.hold.enabled {
+            Some(Arc::new(CallHolds::new(&config.hold)?))
+        } else {
+            None
+        };
+        let fax = config.fax.enabled.then(|| Arc::new(FaxRelay::new(config.fax.clone())));
+        let call_tracer = Arc::new(CallTracer::new(config.call_trace.clone()));
+        let transcoding_latency = Arc::new(TranscodingLatencyMonitor::new(config.transcoding_latency.clone()));
+        let mut codec_negotiator = CodecNegotiator::new(config.codec_negotiation.clone(), &config.media_policies);
+        if config.transcoding_latency.enabled {
+            codec_negotiator = codec_negotiator
+                .with_latency_monitor(Arc::clone(&transcoding_latency), config.transcoding_backend.clone());
+        }
+        let codec_negotiator = Arc::new(codec_negotiator);
+        let route_advertiser = if config.route_advertisement.enabled {
+            let advertiser = RouteAdvertiser::new(config.route_advertisement.clone(), config.clustering.node_id.clone())?;
+            Some(Arc::new(advertiser))
+        } else {
+            None
+        };
+        let shadow_routing = config
+            .shadow_routing
+            .enabled
+            .then(|| Arc::new(ShadowRouter::new(config.shadow_routing.clone())));
+        let prompts = if config.prompts.enabled {
+            Some(Arc::new(PromptLibrary::load(config.prompts.clone())?))
+        } else {
+            None
+        };
+        let test_numbers = config
+            .test_numbers
+            .enabled
+            .then(|| Arc::new(TestNumbers::new(config.test_numbers.clone(), prompts.clone(), sip_handler.clone())));
+        let paging = config.paging.enabled.then(|| Arc::new(Paging::new(config.paging.clone(), sip_handler.clone())));
+        let trunk_registrar = config
+            .trunk_registration
+            .enabled
+            .then(|| Arc::new(TrunkRegistrar::new(config.trunk_registration.clone())));
+
+        let context = B2buaContext {
             sip_handler,
             rtp_handler,
             calls: Arc::new(DashMap::new()),
             media_relays: Arc::new(DashMap::new()),
             event_tx,
+            cps_shaper,
+            routing_hook,
+            number_lookup,
+            survivability,
+            answer_supervisor: Arc::new(answer_supervisor),
+            trunk_failure: Arc::new(trunk_failure),
+            rtcp_xr,
+            call_gapping,
+            reroute,
+            takeover,
+            transfers,
+            holds,
+            fax,
+            quirks,
+            call_tracer,
+            codec_negotiator,
+            media_inactivity: Arc::new(MediaInactivity::new(config.media_inactivity.clone())),
+            early_media: Arc::new(EarlyMediaGate::new()),
+            media_security: Arc::new(MediaSecurityMonitor::new(&config.media_policies)),
+            release_causes: Arc::new(ReleaseCauseStats::new(config.release_causes.clone())),
+            prompts,
+            test_numbers,
+            paging,
+            shadow_routing,
+            reestablish_sessions: Arc::new(DashMap::new()),
+            queued_invites: Arc::new(DashMap::new()),
+            config,
+        };
+
+        Ok(Self {
+            context,
+            survivability_rx,
+            tdm_channels: Arc::new(DashMap::new()),
+            trunk_failure_rx,
+            quality_monitor,
+            quality_rx,
+            call_gapping_rx,
+            transcoding_latency,
+            trunk_registrar,
+            route_advertiser,
             event_rx: Some(event_rx),
             sip_event_rx: None,
             rtp_event_rx: None,
@@ -167,6 +639,25 @@ impl B2buaService {
         self.event_rx.take()
     }
 
+    /// Consult a custom routing hook before the static routing table,
+    /// using the configured timeout and failure policy
+    pub fn set_routing_hook(&mut self, hook: Arc<dyn RoutingHook>) {
+        self.context.routing_hook = Some(Arc::new(RoutingHookRunner::new(hook, &self.context.config.routing_hook)));
+    }
+
+    /// Dip callees against a custom number database before routing
+    pub fn set_number_lookup(&mut self, lookup: Arc<dyn NumberLookup>) {
+        let runner = NumberLookupRunner::new(lookup, &self.context.config.number_lookup);
+        self.context.number_lookup = Some(Arc::new(runner));
+    }
+
+    /// Raise SRTP downgrade alarms on trunks whose policy asks for them,
+    /// and alarms for transcoding over its latency budget
+    pub fn set_alarm_manager(&self, alarm_manager: Arc<AlarmManager>) {
+        self.context.media_security.set_alarm_manager(Arc::clone(&alarm_manager));
+        self.transcoding_latency.set_alarm_manager(alarm_manager);
+    }
+
     pub fn set_sip_event_receiver(&mut self, rx: mpsc::UnboundedReceiver<SipEvent>) {
         self.sip_event_rx = Some(rx);
     }
@@ -178,50 +669,151 @@ impl B2buaService {
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting B2BUA service");
 
+        // Pick up calls left by the process this one replaced before taking traffic
+        if self.context.config.upgrade_assist.enabled {
+            match self.restore_snapshot().await {
+                Ok(0) => {}
+                Ok(restored) => info!("Restored {} calls from the upgrade snapshot", restored),
+                Err(e) => warn!("Failed to restore calls from the upgrade snapshot: {}", e),
+            }
+        }
+
         // Start SIP event processing
         if let Some(sip_rx) = self.sip_event_rx.take() {
-            let calls_sip = Arc::clone(&self.calls);
-            let event_tx_sip = self.event_tx.clone();
-            let config_sip = self.config.clone();
-            let sip_handler_sip = Arc::clone(&self.sip_handler);
-            let rtp_handler_sip = Arc::clone(&self.rtp_handler);
-
+            let ctx = self.context.clone();
             tokio::spawn(async move {
-                Self::process_sip_events(
-                    sip_rx,
-                    calls_sip,
-                    event_tx_sip,
-                    config_sip,
-                    sip_handler_sip,
-                    rtp_handler_sip,
-                ).await;
+                Self::process_sip_events(sip_rx, ctx).await;
             });
         }
 
         // Start RTP event processing
         if let Some(rtp_rx) = self.rtp_event_rx.take() {
-            let calls_rtp = Arc::clone(&self.calls);
-            let media_relays_rtp = Arc::clone(&self.media_relays);
-            let event_tx_rtp = self.event_tx.clone();
-
+            let ctx = self.context.clone();
             tokio::spawn(async move {
-                Self::process_rtp_events(rtp_rx, calls_rtp, media_relays_rtp, event_tx_rtp).await;
+                Self::process_rtp_events(rtp_rx, ctx).await;
             });
         }
 
         // Start call monitoring
-        let calls_monitor = Arc::clone(&self.calls);
-        let event_tx_monitor = self.event_tx.clone();
-        let call_timeout = Duration::from_secs(self.config.call_timeout as u64);
+        tokio::spawn(Self::call_monitor_loop(self.context.clone()));
 
-        tokio::spawn(async move {
-            Self::call_monitor_loop(calls_monitor, event_tx_monitor, call_timeout).await;
-        });
+        // Drive re-establishment and expiry of calls preserved across trunk failures
+        tokio::spawn(Self::trunk_failure_loop(self.context.clone()));
+
+        // Forward calls whose leg B rings past the route's no-answer timer
+        tokio::spawn(Self::no_answer_loop(self.context.clone()));
+
+        // Release calls whose early media runs past the route's limit
+        tokio::spawn(Self::early_media_loop(self.context.clone()));
+
+        // Abandon operator takeovers whose new leg never answers
+        if let Some(takeover) = self.context.takeover.clone() {
+            let event_tx_takeover = self.context.event_tx.clone();
+            tokio::spawn(async move {
+                let mut poll_interval = interval(Duration::from_secs(1));
+                loop {
+                    poll_interval.tick().await;
+                    for record in takeover.expire(Instant::now()) {
+                        // Implementation would CANCEL the unanswered new leg
+                        let _ = event_tx_takeover.send(B2buaEvent::CallTakeover { record });
+                    }
+                }
+            });
+        }
+
+        // Fail transfers whose target never answers
+        if let Some(transfers) = self.context.transfers.clone() {
+            let event_tx_transfer = self.context.event_tx.clone();
+            let sip_handler_transfer = Arc::clone(&self.context.sip_handler);
+            tokio::spawn(async move {
+                let mut poll_interval = interval(Duration::from_secs(1));
+                loop {
+                    poll_interval.tick().await;
+                    for record in transfers.expire(Instant::now()) {
+                        let sip_handler = sip_handler_transfer.read().await;
+                        if let Some(new_session_id) = &record.new_session_id {
+                            if let Err(e) = sip_handler.send_cancel(new_session_id).await {
+                                warn!("Failed to CANCEL unanswered transfer target {}: {}", new_session_id, e);
+                            }
+                        }
+                        Self::notify_transferor(&sip_handler, &record.transferor_session_id, 408, "Request Timeout").await;
+                        let _ = event_tx_transfer.send(B2buaEvent::CallTransfer { record });
+                    }
+                }
+            });
+        }
+
+        if let Some(ref survivability) = self.context.survivability {
+            survivability.spawn_monitor();
+        }
+
+        if let Some(ref trunk_registrar) = self.trunk_registrar {
+            trunk_registrar.spawn_monitor();
+        }
+
+        // Tell upstream proxies what this gateway serves and how much room is left
+        if let Some(ref advertiser) = self.route_advertiser {
+            let calls_advertised = Arc::clone(&self.context.calls);
+            let max_calls = self.context.config.max_concurrent_calls;
+            advertiser.spawn(move || (calls_advertised.len() as u32, max_calls));
+        }
+
+        if let Some(mut survivability_rx) = self.survivability_rx.take() {
+            let event_tx_survivability = self.context.event_tx.clone();
+            tokio::spawn(async move {
+                while let Some(event) = survivability_rx.recv().await {
+                    let _ = event_tx_survivability.send(B2buaEvent::Survivability { event });
+                }
+            });
+        }
+
+        if let Some(mut trunk_failure_rx) = self.trunk_failure_rx.take() {
+            let event_tx_trunk = self.context.event_tx.clone();
+            tokio::spawn(async move {
+                while let Some(event) = trunk_failure_rx.recv().await {
+                    let _ = event_tx_trunk.send(B2buaEvent::TrunkFailure { event });
+                }
+            });
+        }
+
+        if let Some(mut call_gapping_rx) = self.call_gapping_rx.take() {
+            let event_tx_gapping = self.context.event_tx.clone();
+            tokio::spawn(async move {
+                while let Some(event) = call_gapping_rx.recv().await {
+                    let _ = event_tx_gapping.send(B2buaEvent::CallGapping { event });
+                }
+            });
+        }
+
+        if let Some(mut quality_rx) = self.quality_rx.take() {
+            let event_tx_quality = self.context.event_tx.clone();
+            tokio::spawn(async move {
+                while let Some(event) = quality_rx.recv().await {
+                    let _ = event_tx_quality.send(B2buaEvent::QualityAlert { event });
+                }
+            });
+        }
+
+        if self.context.config.session_timers.enabled {
+            tokio::spawn(Self::session_timer_loop(self.context.clone()));
+        }
+
+        if self.context.config.reliable_provisionals.enabled {
+            tokio::spawn(Self::reliable_provisional_loop(self.context.clone()));
+        }
+
+        if let Some(reporter) = self.context.rtcp_xr.clone() {
+            let calls_xr = Arc::clone(&self.context.calls);
+            let rtp_handler_xr = Arc::clone(&self.context.rtp_handler);
+            tokio::spawn(async move {
+                Self::rtcp_xr_loop(calls_xr, rtp_handler_xr, reporter).await;
+            });
+        }
 
         // Start media relay monitoring
-        let media_relays_monitor = Arc::clone(&self.media_relays);
-        let event_tx_media = self.event_tx.clone();
-        let media_timeout = Duration::from_secs(self.config.media_timeout as u64);
+        let media_relays_monitor = Arc::clone(&self.context.media_relays);
+        let event_tx_media = self.context.event_tx.clone();
+        let media_timeout = Duration::from_secs(self.context.config.media_timeout as u64);
 
         tokio::spawn(async move {
             Self::media_monitor_loop(media_relays_monitor, event_tx_media, media_timeout).await;
@@ -232,50 +824,263 @@ impl B2buaService {
         Ok(())
     }
 
-    async fn process_sip_events(
-        mut sip_rx: mpsc::UnboundedReceiver<SipEvent>,
-        calls: Arc<DashMap<String, B2buaCall>>,
-        event_tx: mpsc::UnboundedSender<B2buaEvent>,
-        config: B2buaConfig,
-        sip_handler: Arc<RwLock<SipHandler>>,
-        rtp_handler: Arc<RwLock<RtpHandler>>,
-    ) {
+    async fn process_sip_events(mut sip_rx: mpsc::UnboundedReceiver<SipEvent>, ctx: B2buaContext) {
+        let B2buaContext {
+            calls,
+            event_tx,
+            sip_handler,
+            rtp_handler,
+            survivability,
+            takeover,
+            transfers,
+            holds,
+            fax,
+            trunk_failure,
+            reestablish_sessions,
+            queued_invites,
+            test_numbers,
+            paging,
+            rtcp_xr,
+            ..
+        } = &ctx;
+
         while let Some(event) = sip_rx.recv().await {
+            // Responses carry their socket receipt time for answer supervision;
+            // gapping and timestamps of our own replies use the dequeue time
+            let received_instant = Instant::now();
+
             match event {
-                SipEvent::IncomingCall { session_id, call_id: _, from, to, sdp } => {
-                    if let Err(e) = Self::handle_incoming_call(
+                SipEvent::IncomingCall { session_id, call_id: _, from, to, sdp, headers, encapsulated, source } => {
+                    let invite = IncomingInvite { session_id, from, to, sdp, headers, encapsulated, source };
+                    Self::dispatch_invite(&ctx, invite, received_instant).await;
+                }
+                SipEvent::RegistrationReceived { transaction_id, user, contact, expires, headers, .. } if survivability.is_some() => {
+                    // Proxied REGISTERs wait on the upstream registrar
+                    let survivability = survivability.clone().unwrap();
+                    let sip_handler = Arc::clone(sip_handler);
+                    let timestamp = SipTimestamp::from_headers(&headers);
+                    tokio::spawn(async move {
+                        let mut reply = survivability
+                            .handle_register(RegisterRequest { user, contact, expires, headers })
+                            .await;
+                        let sip_handler = sip_handler.read().await;
+                        // The echoed delay includes the wait on the upstream
+                        // registrar; our Date replaces one passed through from it
+                        let time_headers = sip_handler.response_time_headers(timestamp.as_ref(), received_instant);
+                        reply.headers.retain(|(name, _)| !time_headers.iter().any(|(ours, _)| ours.eq_ignore_ascii_case(name)));
+                        reply.headers.extend(time_headers);
+                        if let Err(e) = sip_handler.send_register_response(
+                            &transaction_id,
+                            reply.status_code,
+                            &reply.reason,
+                            &reply.headers,
+                        ).await {
+                            error!("Failed to answer REGISTER: {}", e);
+                        }
+                    });
+                }
+                SipEvent::RegistrationReceived { transaction_id, headers, flow, .. } => {
+                    // Without survivability, the built-in registrar answers, if enabled
+                    let Some(registrar) = sip_handler.read().await.registrar() else {
+                        trace!("REGISTER {} ignored; no registrar is enabled", transaction_id);
+                        continue;
+                    };
+                    let sip_handler = Arc::clone(sip_handler);
+                    let timestamp = SipTimestamp::from_headers(&headers);
+                    tokio::spawn(async move {
+                        let (status_code, reason, mut reply_headers) = match Registration::from_headers(&headers) {
+                            Ok(mut registration) => {
+                                registration.flow = flow;
+                                let reply = registrar.handle_register(&registration).await;
+                                (reply.status_code, reply.reason, reply.headers)
+                            }
+                            Err(e) => (400, format!("Bad Request ({})", e), Vec::new()),
+                        };
+                        let sip_handler = sip_handler.read().await;
+                        reply_headers.extend(sip_handler.response_time_headers(timestamp.as_ref(), received_instant));
+                        if let Err(e) = sip_handler.send_register_response(&transaction_id, status_code, &reason, &reply_headers).await {
+                            error!("Failed to answer REGISTER: {}", e);
+                        }
+                    });
+                }
+                SipEvent::CallRinging { session_id, .. } if transfers.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    if let Some(record) = transfers.as_ref().and_then(|t| t.pending(&session_id)) {
+                        Self::notify_transferor(&sip_handler.read().await, &record.transferor_session_id, 180, "Ringing").await;
+                    }
+                }
+                SipEvent::SessionProgress { session_id, .. } if transfers.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    if let Some(record) = transfers.as_ref().and_then(|t| t.pending(&session_id)) {
+                        Self::notify_transferor(&sip_handler.read().await, &record.transferor_session_id, 183, "Session Progress").await;
+                    }
+                }
+                SipEvent::CallRinging { session_id, headers, received } => {
+                    if let Err(e) = Self::handle_provisional(
+                        &ctx,
+                        session_id,
+                        180,
+                        None,
+                        headers,
+                        received.utc,
+                        received.instant,
+                    ).await {
+                        error!("Failed to handle ringing: {}", e);
+                    }
+                }
+                SipEvent::SessionProgress { session_id, sdp, headers, received } => {
+                    if let Err(e) = Self::handle_provisional(
+                        &ctx,
                         session_id,
-                        from,
-                        to,
+                        183,
                         sdp,
-                        &calls,
-                        &event_tx,
-                        &config,
-                        &sip_handler,
-                        &rtp_handler,
+                        headers,
+                        received.utc,
+                        received.instant,
+                    ).await {
+                        error!("Failed to handle session progress: {}", e);
+                    }
+                }
+                SipEvent::CallAnswered { session_id, .. } if reestablish_sessions.contains_key(&session_id) => {
+                    if let Some((_, call_id)) = reestablish_sessions.remove(&session_id) {
+                        Self::handle_call_reestablished(&call_id, session_id, calls, trunk_failure);
+                    }
+                }
+                SipEvent::CallAnswered { session_id, .. } if takeover.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    if let Some(takeover) = takeover {
+                        Self::complete_takeover(takeover, session_id, calls, event_tx);
+                    }
+                }
+                SipEvent::CallTerminated { session_id, reason } if takeover.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    if let Some(record) = takeover.as_ref().and_then(|t| t.fail(&session_id, reason)) {
+                        let _ = event_tx.send(B2buaEvent::CallTakeover { record });
+                    }
+                }
+                SipEvent::CallFailed { session_id, status_code, reason } if takeover.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    let failure = format!("{} {}", status_code, reason);
+                    if let Some(record) = takeover.as_ref().and_then(|t| t.fail(&session_id, failure)) {
+                        let _ = event_tx.send(B2buaEvent::CallTakeover { record });
+                    }
+                }
+                SipEvent::ReferReceived { session_id, transaction_id, refer_to, headers } => {
+                    if let Err(e) = Self::handle_refer(
+                        &ctx,
+                        &session_id,
+                        &transaction_id,
+                        &refer_to,
+                        &headers,
                     ).await {
-                        error!("Failed to handle incoming call: {}", e);
+                        error!("Failed to handle REFER on {}: {}", session_id, e);
+                    }
+                }
+                SipEvent::CallAnswered { session_id, sdp, .. } if transfers.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    if let Some(transfers) = transfers {
+                        Self::complete_transfer(&ctx, transfers, session_id, sdp).await;
+                    }
+                }
+                SipEvent::CallTerminated { session_id, reason } if transfers.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    if let Some(record) = transfers.as_ref().and_then(|t| t.fail(&session_id, 487, reason)) {
+                        Self::notify_transferor(&sip_handler.read().await, &record.transferor_session_id, 487, "Request Terminated").await;
+                        let _ = event_tx.send(B2buaEvent::CallTransfer { record });
+                    }
+                }
+                SipEvent::CallFailed { session_id, status_code, reason } if transfers.as_ref().is_some_and(|t| t.is_pending(&session_id)) => {
+                    if let Some(record) = transfers.as_ref().and_then(|t| t.fail(&session_id, status_code, reason.clone())) {
+                        Self::notify_transferor(&sip_handler.read().await, &record.transferor_session_id, status_code, &reason).await;
+                        let _ = event_tx.send(B2buaEvent::CallTransfer { record });
+                    }
+                }
+                SipEvent::CallTerminated { session_id, .. } if queued_invites.contains_key(&session_id) => {
+                    // The stack has already answered the CANCEL and the INVITE
+                    if let Some((_, cancel)) = queued_invites.remove(&session_id) {
+                        let _ = cancel.send(());
+                    }
+                }
+                SipEvent::CallTerminated { session_id, .. } if test_numbers.as_ref().is_some_and(|t| t.is_active(&session_id)) => {
+                    if let Some(record) = test_numbers.as_ref().and_then(|t| t.hangup(&session_id)) {
+                        info!("Test call {} to {} ended after {} packets received", session_id, record.number, record.packets_received);
+                    }
+                }
+                SipEvent::CallTerminated { session_id, .. } if paging.as_ref().is_some_and(|p| p.is_active(&session_id)) => {
+                    if let Some(record) = paging.as_ref().and_then(|p| p.hangup(&session_id)) {
+                        info!("Page {} to {} ended after {} packets forwarded", session_id, record.number, record.packets_forwarded);
                     }
                 }
-                SipEvent::CallAnswered { session_id, sdp } => {
+                SipEvent::CallTerminated { session_id, reason } if reestablish_sessions.contains_key(&session_id) => {
+                    // A failed re-establishment attempt; the preserved call itself stays up
+                    reestablish_sessions.remove(&session_id);
+                    debug!("Re-establishment attempt {} failed: {}", session_id, reason);
+                }
+                SipEvent::CallFailed { session_id, status_code, reason } if reestablish_sessions.contains_key(&session_id) => {
+                    reestablish_sessions.remove(&session_id);
+                    debug!("Re-establishment attempt {} rejected: {} {}", session_id, status_code, reason);
+                }
+                SipEvent::CallFailed { session_id, status_code, reason } => {
+                    Self::handle_call_failed(&ctx, &session_id, status_code, reason).await;
+                }
+                SipEvent::CallAnswered { session_id, sdp, headers, received } => {
                     if let Err(e) = Self::handle_call_answered(
+                        &ctx,
                         session_id,
                         sdp,
-                        &calls,
-                        &event_tx,
-                        &sip_handler,
+                        headers,
+                        received.utc,
+                        received.instant,
                     ).await {
                         error!("Failed to handle call answered: {}", e);
                     }
                 }
+                SipEvent::PrackReceived { session_id, transaction_id, rack } => {
+                    if let Err(e) = Self::handle_prack(&session_id, &transaction_id, &rack, calls, sip_handler).await {
+                        error!("Failed to handle PRACK: {}", e);
+                    }
+                }
+                SipEvent::SessionRefreshed { session_id, sdp, headers } => {
+                    Self::handle_session_refreshed(&session_id, &headers, calls);
+                    if let (Some(holds), Some(sdp)) = (holds, sdp) {
+                        if let Err(e) = Self::handle_hold_offer(&ctx, holds, &session_id, &sdp).await {
+                            error!("Failed to handle hold offer: {}", e);
+                        }
+                    }
+                }
+                SipEvent::RefreshResponse { session_id, status_code, sdp, .. } if fax.as_ref().is_some_and(|f| f.is_offered(&session_id)) => {
+                    if let Some(fax) = fax {
+                        let answered = fax.answered(&session_id, status_code, sdp.as_deref(), event_tx);
+                        if let Some((call_id, tone, mode)) = answered {
+                            let _ = event_tx.send(B2buaEvent::FaxRelay { call_id, tone, mode });
+                        }
+                    }
+                }
+                SipEvent::RefreshResponse { session_id, status_code, headers, .. } => {
+                    Self::handle_refresh_response(&ctx, &session_id, status_code, &headers).await;
+                }
                 SipEvent::CallTerminated { session_id, reason } => {
-                    if let Err(e) = Self::handle_call_terminated(
-                        session_id,
-                        reason,
-                        &calls,
-                        &event_tx,
-                        &sip_handler,
-                    ).await {
+                    if holds.is_some() || fax.is_some() {
+                        let call_id = calls
+                            .iter()
+                            .find(|entry| {
+                                entry.leg_a_session_id == session_id || entry.leg_b_session_id.as_deref() == Some(session_id.as_str())
+                            })
+                            .map(|entry| entry.key().clone());
+                        if let Some(call_id) = call_id {
+                            if let Some(holds) = holds {
+                                holds.release(&call_id);
+                            }
+                            if let Some(fax) = fax {
+                                fax.release(&call_id);
+                            }
+                        }
+                    }
+                    if let Some(reporter) = rtcp_xr {
+                        let call = calls
+                            .iter()
+                            .find(|entry| {
+                                entry.leg_a_session_id == session_id || entry.leg_b_session_id.as_deref() == Some(session_id.as_str())
+                            })
+                            .map(|entry| entry.value().clone());
+                        if let Some(call) = call {
+                            Self::report_call_quality(reporter, &call, rtp_handler, event_tx).await;
+                        }
+                    }
+                    if let Err(e) = Self::handle_call_terminated(&ctx, session_id, reason).await {
                         error!("Failed to handle call terminated: {}", e);
                     }
                 }
@@ -286,21 +1091,11 @@ impl B2buaService {
         }
     }
 
-    async fn process_rtp_events(
-        mut rtp_rx: mpsc::UnboundedReceiver<RtpEvent>,
-        calls: Arc<DashMap<String, B2buaCall>>,
-        media_relays: Arc<DashMap<String, MediaRelay>>,
-        event_tx: mpsc::UnboundedSender<B2buaEvent>,
-    ) {
+    async fn process_rtp_events(mut rtp_rx: mpsc::UnboundedReceiver<RtpEvent>, ctx: B2buaContext) {
         while let Some(event) = rtp_rx.recv().await {
             match event {
                 RtpEvent::PacketReceived { session_id, packet, source: _ } => {
-                    if let Err(e) = Self::handle_rtp_packet(
-                        session_id,
-                        packet,
-                        &calls,
-                        &media_relays,
-                    ).await {
+                    if let Err(e) = Self::handle_rtp_packet(&ctx, session_id, packet).await {
                         error!("Failed to handle RTP packet: {}", e);
                     }
                 }
@@ -315,130 +1110,805 @@ impl B2buaService {
         }
     }
 
-    async fn handle_incoming_call(
-        session_id: String,
-        from: String,
-        to: String,
-        sdp: Option<String>,
-        calls: &Arc<DashMap<String, B2buaCall>>,
-        event_tx: &mpsc::UnboundedSender<B2buaEvent>,
-        config: &B2buaConfig,
-        sip_handler: &Arc<RwLock<SipHandler>>,
-        rtp_handler: &Arc<RwLock<RtpHandler>>,
-    ) -> Result<()> {
-        // Check concurrent call limit
-        if calls.len() >= config.max_concurrent_calls as usize {
-            warn!("Maximum concurrent calls reached, rejecting call");
-            return Err(Error::b2bua("Maximum concurrent calls reached"));
-        }
+    /// Answer test and paging numbers, turn away gapped calls and hand the
+    /// rest on for routing
+    async fn dispatch_invite(ctx: &B2buaContext, invite: IncomingInvite, received_instant: Instant) {
+        let callee = Self::extract_user_from_uri(&invite.to).unwrap_or_else(|_| invite.to.clone());
 
-        // Extract caller and callee information
-        let caller = Self::extract_user_from_uri(&from)?;
-        let callee = Self::extract_user_from_uri(&to)?;
+        // Test numbers are answered here, ahead of gapping and routing
+        if let Some(test_numbers) = ctx.test_numbers.as_ref().filter(|t| t.kind_for(&callee).is_some()) {
+            Self::answer_test_call(ctx, test_numbers, &invite, &callee).await;
+            return;
+        }
 
-        // Determine routing for this call
-        let routing_info = Self::determine_routing(&callee, config)?;
+        // So are paging numbers, whose audio goes to the paging speakers
+        if let Some(paging) = ctx.paging.as_ref().filter(|p| p.is_paging_number(&callee)) {
+            Self::answer_page(ctx, paging, &invite, &callee).await;
+            return;
+        }
 
-        // Create B2BUA call
-        let call_id = Uuid::new_v4().to_string();
-        let call = B2buaCall {
-            id: call_id.clone(),
-            state: B2buaCallState::Establishing,
-            leg_a_session_id: session_id,
-            leg_b_session_id: None,
-            leg_a_rtp_session_id: None,
-            leg_b_rtp_session_id: None,
-            caller: caller.clone(),
-            callee: callee.clone(),
-            destination_uri: Self::build_destination_uri(&callee, &routing_info)?,
-            created_at: Instant::now(),
-            connected_at: None,
-            terminated_at: None,
-            last_activity: Instant::now(),
-            call_duration: None,
-            routing_info: routing_info.clone(),
-        };
+        // Calls to a gapped destination are turned away before routing
+        if let Some(gapper) = &ctx.call_gapping {
+            if let GapDecision::Gapped { prefix, retry_after } = gapper.admit(&callee, received_instant) {
+                debug!("Gapped call to {} (gap {}, next admission in {:?})", callee, prefix, retry_after);
+                let status = gapper.reject_status();
+                let phrase = Self::congestion_reason_phrase(status);
+                if let Err(e) = Self::reject_call(ctx, &invite, RejectReason::Congestion, status, phrase).await {
+                    error!("Failed to reject gapped call: {}", e);
+                }
+                return;
+            }
+        }
 
-        calls.insert(call_id.clone(), call);
+        if ctx.cps_shaper.is_none() && ctx.routing_hook.is_none() && ctx.number_lookup.is_none() {
+            Self::route_incoming_call(ctx, invite, None).await;
+            return;
+        }
 
-        // Emit routing decision event
-        let _ = event_tx.send(B2buaEvent::RoutingDecision {
-            call_id: call_id.clone(),
-            caller: caller.clone(),
-            callee: callee.clone(),
-            route: routing_info.clone(),
+        // Shaped, dipped or hook-routed calls wait off the event loop so
+        // a queued burst or slow lookup does not stall other signaling.
+        // The CANCEL signal is registered before then, so none is missed
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        ctx.queued_invites.insert(invite.session_id.clone(), cancel_tx);
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            Self::route_incoming_call(&ctx, invite, Some(cancel_rx)).await;
         });
+    }
 
-        // Emit call establishing event
-        let _ = event_tx.send(B2buaEvent::CallEstablishing {
-            call_id: call_id.clone(),
-            caller: caller.clone(),
-            callee: callee.clone(),
-        });
+    /// Route an incoming call and, once its trunk's setup rate admits it,
+    /// place leg B; a CANCEL on `cancel` before then abandons the call
+    async fn route_incoming_call(ctx: &B2buaContext, invite: IncomingInvite, cancel: Option<oneshot::Receiver<()>>) {
+        let route = match Self::resolve_route(ctx, &invite.from, &invite.to, &invite.headers).await {
+            Ok(route) => route,
+            Err(e) => {
+                ctx.queued_invites.remove(&invite.session_id);
+                error!("Failed to route incoming call: {}", e);
+                return;
+            }
+        };
+        let trunk = match &route {
+            RouteResolution::Route(info) => info.target_gateway.clone(),
+            _ => None,
+        }.unwrap_or_else(|| "default".to_string());
+
+        let admit = async {
+            match &ctx.cps_shaper {
+                // Tenants are told apart by where the INVITE came from, not
+                // by anything the caller can write into it
+                Some(shaper) => shaper.admit(&trunk, shaper.tenant_for(invite.source.ip())).await,
+                None => AdmissionOutcome::Admitted { queued_for: Duration::ZERO },
+            }
+        };
+        let admission = match cancel {
+            // Polled first, so a CANCEL received while routing also counts
+            Some(cancel) => tokio::select! {
+                biased;
+                _ = cancel => None,
+                admission = admit => Some(admission),
+            },
+            None => Some(admit.await),
+        };
+        ctx.queued_invites.remove(&invite.session_id);
+        let Some(admission) = admission else {
+            debug!("Call {} cancelled before admission on trunk {}", invite.session_id, trunk);
+            return;
+        };
+
+        match admission {
+            AdmissionOutcome::Admitted { .. } => {
+                if let Err(e) = Self::handle_incoming_call(ctx, invite, route).await {
+                    error!("Failed to handle incoming call: {}", e);
+                }
+            }
+            AdmissionOutcome::Rejected { reason, response_code } => {
+                let phrase = Self::congestion_reason_phrase(response_code);
+                if let Err(e) = Self::reject_call(ctx, &invite, RejectReason::Congestion, response_code, phrase).await {
+                    error!("Failed to reject shaped call: {}", e);
+                }
+                let _ = ctx.event_tx.send(B2buaEvent::Error {
+                    call_id: None,
+                    message: format!("Call setup rate exceeded on trunk {}: {}", trunk, reason),
+                });
+            }
+        }
+    }
+
+    async fn handle_incoming_call(ctx: &B2buaContext, invite: IncomingInvite, route: RouteResolution) -> Result<()> {
+        let B2buaContext {
+            calls,
+            event_tx,
+            config,
+            sip_handler,
+            answer_supervisor: supervisor,
+            call_tracer: tracer,
+            codec_negotiator: negotiator,
+            media_inactivity,
+            media_security,
+            ..
+        } = ctx;
+        let prompts = ctx.prompts.as_deref();
+        let IncomingInvite { session_id, from, to, headers, .. } = &invite;
+
+        // A call requiring a priority the gateway does not understand is refused
+        let policy = config.precedence.enabled.then(|| PrecedencePolicy::new(&config.precedence));
+        if let Some(policy) = policy.as_ref().filter(|policy| policy.refuses(headers)) {
+            let accept = vec![(precedence::ACCEPT_RESOURCE_PRIORITY_HEADER.to_string(), policy.accepted_values())];
+            sip_handler.read().await.send_response_with_headers(
+                session_id,
+                precedence::STATUS_UNKNOWN_RESOURCE_PRIORITY,
+                "Unknown Resource-Priority",
+                None,
+                &accept,
+            ).await?;
+            return Err(Error::not_supported(format!("Call from {} requires an unknown Resource-Priority", from)));
+        }
+        let resource_priority = policy.as_ref().and_then(|policy| policy.resource_priority(headers));
+
+        // So is a session interval below our Min-SE
+        let timers = &config.session_timers;
+        let session_timer = match timers.enabled.then(|| session_timer::negotiate_uas(timers, headers)) {
+            Some(UasNegotiation::TooSmall { min_se }) => {
+                let min_se_header = vec![(session_timer::MIN_SE_HEADER.to_string(), min_se.to_string())];
+                sip_handler.read().await.send_response_with_headers(
+                    session_id,
+                    session_timer::STATUS_INTERVAL_TOO_SMALL,
+                    "Session Interval Too Small",
+                    None,
+                    &min_se_header,
+                ).await?;
+                let reason = format!("Call from {} asked for a session interval below {}s", from, min_se);
+                return Err(Error::protocol(reason));
+            }
+            Some(UasNegotiation::Timer(timer)) => Some(timer),
+            None => None,
+        };
+
+        // Leg A gets reliable provisionals when it asks for them
+        let reliable = &config.reliable_provisionals;
+        if reliable_provisional::required(headers) && !reliable.enabled {
+            let unsupported = vec![("Unsupported".to_string(), reliable_provisional::OPTION_TAG.to_string())];
+            sip_handler.read().await.send_response_with_headers(
+                session_id,
+                reliable_provisional::STATUS_BAD_EXTENSION,
+                "Bad Extension",
+                None,
+                &unsupported,
+            ).await?;
+            return Err(Error::not_supported(format!("Call from {} requires 100rel", from)));
+        }
+        let leg_a_sender = (reliable.enabled && reliable_provisional::supported(headers))
+            .then(|| ReliableSender::new(reliable_provisional::cseq(headers).unwrap_or(1)));
+
+        // Check concurrent call limit; a priority call may preempt its way in
+        if calls.len() >= config.max_concurrent_calls as usize {
+            let trunk = match &route {
+                RouteResolution::Route(info) => info.target_gateway.as_deref(),
+                _ => None,
+            };
+            let preempted = match (&policy, &resource_priority) {
+                (Some(policy), Some(priority)) if policy.preemption() => {
+                    Self::preempt_call(ctx, priority, trunk).await
+                }
+                _ => false,
+            };
+            if !preempted {
+                warn!("Maximum concurrent calls reached, rejecting call");
+                let error = Error::resource_exhausted("Maximum concurrent calls reached");
+                let (status, reason) = error.sip_response();
+                Self::reject_call(ctx, &invite, RejectReason::CallLimit, status, reason).await?;
+                return Err(error);
+            }
+        }
+
+        // Extract caller and callee information
+        let caller = Self::extract_user_from_uri(from)?;
+        let callee = Self::extract_user_from_uri(to)?;
+
+        let mut routing_info = match route {
+            RouteResolution::Route(routing_info) => routing_info,
+            RouteResolution::Reject { status_code, reason } => {
+                match RejectReason::from_status(status_code) {
+                    Some(reject_reason) => {
+                        Self::reject_call(ctx, &invite, reject_reason, status_code, &reason).await?;
+                    }
+                    None => sip_handler.read().await.send_response(session_id, status_code, &reason, None).await?,
+                }
+                return Err(Error::routing(format!("Call to {} rejected by routing hook: {} {}", callee, status_code, reason)));
+            }
+            RouteResolution::Announce { announcement, status_code } => {
+                // Early media carries the announcement; the media server
+                // releases leg A once it has played
+                sip_handler.read().await.send_response(session_id, 183, "Session Progress", None).await?;
+                let tenant = Self::extract_host_from_uri(from);
+                let accept_language = headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("Accept-Language"))
+                    .map(|(_, value)| value.as_str());
+                let prompt = prompts.and_then(|library| library.select(&announcement, None, tenant.as_deref(), accept_language));
+                let _ = event_tx.send(B2buaEvent::AnnouncementRequested {
+                    session_id: session_id.clone(),
+                    callee,
+                    announcement,
+                    prompt,
+                    release_status: status_code,
+                    release_cause: None,
+                    answered: false,
+                });
+                return Ok(());
+            }
+        };
+
+        // Decline the streams the gateway does not relay or pass through, then
+        // apply the egress trunk's media policy to the offer
+        let trunk = routing_info.target_gateway.clone().unwrap_or_default();
+        let enforcer = MediaPolicyEnforcer::new(&config.media_policies);
+        let sdp = invite.sdp.as_deref().map(|offer| enforcer.limit_streams(&trunk, &config.media_streams, offer));
+        let sdp = match sdp {
+            Some(offer) => match enforcer.enforce(&trunk, &offer, SdpRole::Offer) {
+                PolicyOutcome::Accepted { sdp, .. } => Some(sdp),
+                PolicyOutcome::Rejected { response_code, reason } => {
+                    let sip_handler = sip_handler.read().await;
+                    sip_handler.send_response(
+                        session_id,
+                        response_code,
+                        Self::policy_reason_phrase(response_code),
+                        None,
+                    ).await?;
+                    return Err(Error::not_supported(format!("Offer rejected by media policy: {}", reason)));
+                }
+            },
+            None => None,
+        };
+        let leg_a_offer = sdp.clone();
+
+        // Order the offer by trunk codec priority, preferring a codec both legs
+        // share; calls needing transcoding are refused once none is left
+        let ingress = Self::extract_host_from_uri(from);
+        let sdp = match sdp {
+            Some(offer) => match negotiator.admit_offer(ingress.as_deref(), &trunk, &offer, config.enable_codec_transcoding) {
+                OfferOutcome::Offer(sdp) => Some(sdp),
+                OfferOutcome::Rejected { status_code, cause } => {
+                    sip_handler.read().await.send_response(session_id, status_code, "Not Acceptable Here", None).await?;
+                    let _ = event_tx.send(B2buaEvent::Error {
+                        call_id: None,
+                        message: format!("Call to {} needs transcoding and none is left (cause {})", callee, cause),
+                    });
+                    return Err(Error::resource_exhausted(format!("No transcoding capacity for call to {}", callee)));
+                }
+            },
+            None => None,
+        };
+
+        // Leg A's offer is held to the SRTP requirement of the trunk it came from
+        let leg_a_security = sdp.as_deref().and_then(LegSecurity::negotiated);
+        if let (Some(ingress), Some(security)) = (ingress.as_deref(), leg_a_security) {
+            if let Some(status_code) = media_security.record(ingress, CallLeg::A, security).await {
+                let phrase = Self::policy_reason_phrase(status_code);
+                sip_handler.read().await.send_response(session_id, status_code, phrase, None).await?;
+                let _ = event_tx.send(B2buaEvent::Error {
+                    call_id: None,
+                    message: format!("Call from {} to {} refused: plain RTP where SRTP is required", ingress, callee),
+                });
+                return Err(Error::not_supported(format!("Plain RTP offered by {} where SRTP is required", ingress)));
+            }
+        }
+
+        // The priority travels on to leg B, which negotiates its own session interval
+        if let Some(priority) = &resource_priority {
+            routing_info.extra_headers.insert(precedence::RESOURCE_PRIORITY_HEADER.to_string(), priority.to_string());
+        }
+        if config.session_timers.enabled {
+            session_timer::add_request_headers(&config.session_timers, &mut routing_info.extra_headers);
+        }
+        if config.reliable_provisionals.enabled {
+            reliable_provisional::add_request_headers(&config.reliable_provisionals, &mut routing_info.extra_headers);
+        }
+        let correlation_id = correlation::assign(&config.correlation, headers, None);
+        correlation::add_request_header(&config.correlation, &correlation_id, &mut routing_info.extra_headers);
+
+        // Create B2BUA call
+        let call_id = Uuid::new_v4().to_string();
+        let user_user = sip_user_to_user(headers);
+        let call = B2buaCall {
+            id: call_id.clone(),
+            correlation_id: correlation_id.clone(),
+            state: B2buaCallState::Establishing,
+            leg_a_session_id: session_id.clone(),
+            leg_b_session_id: None,
+            leg_a_rtp_session_id: None,
+            leg_b_rtp_session_id: None,
+            caller: caller.clone(),
+            callee: callee.clone(),
+            destination_uri: Self::build_destination_uri(&callee, &routing_info)?,
+            created_at: Instant::now(),
+            connected_at: None,
+            terminated_at: None,
+            last_activity: Instant::now(),
+            call_duration: None,
+            routing_info: routing_info.clone(),
+            custom_fields: extract_custom_fields(&config.cdr_custom_fields, headers, user_user.as_deref()),
+            media_anchor: None,
+            leg_attempts: Vec::new(),
+            encapsulated: invite.encapsulated.clone(),
+            fork: routing_info.ring_group.clone().map(RingFork::new),
+            leg_a_offer,
+            early_media_at: None,
+            srtp: PendingSrtp::default(),
+            webrtc: PendingWebRtc::default(),
+            media_security: MediaSecurityStatus::default(),
+            precedence: resource_priority,
+            session_timer,
+            session_timers: CallSessionTimers::default(),
+            provisionals: CallProvisionals { leg_a: leg_a_sender, ..Default::default() },
+        };
+
+        if let Some(ref offer) = call.leg_a_offer {
+            media_inactivity.on_sdp(&call_id, offer);
+        }
+        let cdr_id = match supervisor.cdr_service() {
+            Some(cdr) => cdr
+                .start_call_record(&call, CallingPartyCategory::Unknown, CallType::Voice)
+                .await
+                .map_err(|e| warn!("Failed to open CDR for call {}: {}", call_id, e))
+                .ok(),
+            None => None,
+        };
+        let direction = if routing_info.egress_spans.is_empty() {
+            CallDirection::SipToSip
+        } else {
+            CallDirection::SipToTdm
+        };
+        calls.insert(call_id.clone(), call);
+        supervisor.track_call(&call_id, direction, cdr_id);
+        if tracer.begin_call(&call_id, &correlation_id, &caller, &callee, routing_info.target_gateway.as_deref()) {
+            tracer.record(&call_id, TraceSubsystem::Signaling, || {
+                format!("INVITE from {} to {}, offer: {}", from, to, sdp.as_deref().unwrap_or("none"))
+            });
+            tracer.record(&call_id, TraceSubsystem::Signaling, || {
+                format!("Routed {:?} to {:?}", routing_info.route_type, routing_info.target_gateway)
+            });
+        }
+
+        // Emit routing decision event
+        let _ = event_tx.send(B2buaEvent::RoutingDecision {
+            call_id: call_id.clone(),
+            caller: caller.clone(),
+            callee: callee.clone(),
+            route: routing_info.clone(),
+        });
+
+        // Emit call establishing event
+        let _ = event_tx.send(B2buaEvent::CallEstablishing {
+            call_id: call_id.clone(),
+            correlation_id: correlation_id.clone(),
+            caller: caller.clone(),
+            callee: callee.clone(),
+        });
 
         // Set up media relay if enabled
-        if config.enable_media_relay {
-            Self::setup_media_relay(
+        let sdp = if config.enable_media_relay {
+            let tenant = Self::extract_host_from_uri(from);
+            let srtp_to_b = enforcer.policy_for(&trunk).is_some_and(|policy| policy.srtp || policy.require_srtp);
+            let webrtc_to_b = enforcer.policy_for(&trunk).is_some_and(|policy| policy.webrtc);
+            match Self::setup_media_relay(
+                ctx,
                 &call_id,
-                sdp.as_deref(),
-                rtp_handler,
-                event_tx,
-            ).await?;
+                sdp,
+                routing_info.target_gateway.as_deref(),
+                tenant.as_deref(),
+                srtp_to_b,
+                webrtc_to_b,
+            ).await {
+                // Leg A offered SRTP or WebRTC media the relay cannot terminate
+                Err(Error::NotSupported(reason)) => {
+                    if let Some((_, call)) = calls.remove(&call_id) {
+                        supervisor.release_call(&call_id, release_causes::cause_for_sip_status(488));
+                        sip_handler.read().await.send_response(&call.leg_a_session_id, 488, "Not Acceptable Here", None).await?;
+                    }
+                    return Err(Error::not_supported(format!("Offer to {} refused: {}", callee, reason)));
+                }
+                result => result?,
+            }
+        } else {
+            sdp
+        };
+
+        // Survivability breakout places leg B on a TDM span
+        if !routing_info.egress_spans.is_empty() {
+            let _ = event_tx.send(B2buaEvent::TdmBreakout {
+                call_id: call_id.clone(),
+                callee: routing_info.callee_override.clone().unwrap_or_else(|| callee.clone()),
+                egress_spans: routing_info.egress_spans.clone(),
+                encapsulated: invite.encapsulated.clone(),
+                user_user: correlation::user_user(&config.correlation, &correlation_id),
+                correlation_id: correlation_id.clone(),
+            });
+            tracer.record(&call_id, TraceSubsystem::Signaling, || {
+                format!("Broken out to TDM spans {:?}", routing_info.egress_spans)
+            });
+            info!(correlation_id = %correlation_id, "B2BUA call broken out to TDM: {} -> {}", caller, callee);
+            return Ok(());
+        }
+
+        if let Some(group) = &routing_info.ring_group {
+            let placed = Self::ring_group_members(ctx, &call_id, &caller, &callee, sdp.as_deref()).await;
+            tracer.record(&call_id, TraceSubsystem::Signaling, || {
+                format!("Forked to ring group {} ({:?}), {} branches", group.name, group.strategy, placed)
+            });
+            if placed == 0 {
+                if let Some((_, call)) = calls.remove(&call_id) {
+                    supervisor.release_call(&call_id, release_causes::cause_for_sip_status(480));
+                    sip_handler.read().await.send_response(&call.leg_a_session_id, 480, "Temporarily Unavailable", None).await?;
+                }
+                let reason = format!("No member of ring group {} could be called", group.name);
+                return Err(Error::resource_exhausted(reason));
+            }
+            info!(correlation_id = %correlation_id, "B2BUA call forked to ring group {}: {} -> {}", group.name, caller, callee);
+            return Ok(());
         }
 
         // Initiate outbound call (leg B)
         Self::initiate_outbound_call(
+            ctx,
             &call_id,
             &caller,
             &callee,
             &routing_info,
             sdp.as_deref(),
-            calls,
-            sip_handler,
+            LegKind::Primary,
         ).await?;
+        tracer.record(&call_id, TraceSubsystem::Signaling, || {
+            format!("Leg B INVITE sent, offer: {}", sdp.as_deref().unwrap_or("none"))
+        });
 
-        info!("B2BUA call established: {} -> {}", caller, callee);
+        info!(correlation_id = %correlation_id, "B2BUA call established: {} -> {}", caller, callee);
         Ok(())
     }
 
-    async fn handle_call_answered(
+    /// Relay a 183 from leg B to leg A, as plain ringing when the trunk's
+    /// quirk profile marks its early media unusable or the route blocks
+    /// backward early media
+    /// Relay a provisional response from leg B to leg A, reliably when leg A
+    /// supports 100rel. Reliable provisionals from leg B are PRACKed and
+    /// relayed once, in RSeq order
+    async fn handle_provisional(
+        ctx: &B2buaContext,
         session_id: String,
+        status_code: u16,
         sdp: Option<String>,
-        calls: &Arc<DashMap<String, B2buaCall>>,
-        event_tx: &mpsc::UnboundedSender<B2buaEvent>,
+        headers: Vec<(String, String)>,
+        received_at: DateTime<Utc>,
+        received_instant: Instant,
+    ) -> Result<()> {
+        let B2buaContext {
+            calls,
+            event_tx,
+            sip_handler,
+            rtp_handler,
+            answer_supervisor: supervisor,
+            quirks,
+            early_media,
+            ..
+        } = ctx;
+        let Some(call) = Self::find_call_by_leg_b(calls, &session_id).and_then(|call_id| calls.get(&call_id).map(|call| call.clone())) else {
+            return Ok(());
+        };
+
+        let rseq = reliable_provisional::rseq(&headers).filter(|_| reliable_provisional::required(&headers));
+        if let Some(rseq) = rseq {
+            let fresh = calls
+                .get_mut(&call.id)
+                .map_or(false, |mut call| call.provisionals.leg_b.entry(session_id.clone()).or_default().receive(rseq));
+            if !fresh {
+                trace!("Discarded repeated or out of order provisional {} of call {}", rseq, call.id);
+                return Ok(());
+            }
+            let rack = RAck {
+                rseq,
+                cseq: reliable_provisional::cseq(&headers).unwrap_or(1),
+                method: "INVITE".to_string(),
+            };
+            sip_handler.read().await.send_prack(&session_id, &rack.to_string()).await?;
+        }
+
+        let trunk = call.routing_info.target_gateway.as_deref().unwrap_or_default();
+        let (mut status_code, mut relay_sdp) = quirks.for_trunk(trunk).map_or((status_code, true), |profile| profile.provisional(status_code));
+
+        // The early media limit runs from the first 183 carrying media
+        let first_early_media = sdp.is_some() && call.early_media_at.is_none();
+        if first_early_media {
+            if let Some(mut call) = calls.get_mut(&call.id) {
+                call.early_media_at = Some(received_at);
+            }
+        }
+        let policy = call.routing_info.early_media.clone().unwrap_or_default();
+        if policy.block_backward && sdp.is_some() {
+            (status_code, relay_sdp) = (180, false);
+            if first_early_media {
+                debug!("Blocked backward early media of call {} from {}", call.id, trunk);
+                early_media.record_blocked(trunk);
+            }
+        }
+        let sdp = match sdp.filter(|_| relay_sdp) {
+            Some(early_media) => Some(Self::anchor_answer(&call, early_media, rtp_handler).await?),
+            None => None,
+        };
+
+        let signal = SupervisionSignal::SipProvisional { status_code, has_sdp: sdp.is_some() };
+        supervisor.on_signal(&call.id, signal, received_at, received_instant).await?;
+
+        // ALERTING goes to the TDM side once; later ringing maps to PROGRESS
+        let indication = calls.get_mut(&call.id).and_then(|mut call| {
+            let indication = reliable_provisional::tdm_indication(status_code, sdp.is_some(), call.provisionals.alerted)?;
+            call.provisionals.alerted |= indication.message_type == MessageType::Alerting;
+            Some(indication)
+        });
+        if let Some(indication) = indication {
+            let _ = event_tx.send(B2buaEvent::TdmProgress { call_id: call.id.clone(), status_code, indication });
+        }
+
+        Self::relay_provisional(&call.id, status_code, sdp, calls, sip_handler).await
+    }
+
+    /// Send an 18x to leg A, reliably and queued behind any unacknowledged
+    /// reliable provisional when leg A supports 100rel
+    async fn relay_provisional(
+        call_id: &str,
+        status_code: u16,
+        sdp: Option<String>,
+        calls: &DashMap<String, B2buaCall>,
         sip_handler: &Arc<RwLock<SipHandler>>,
     ) -> Result<()> {
-        // Find call by session ID
-        let call_id = {
-            let mut found_call_id = None;
-            for call_entry in calls.iter() {
-                let call = call_entry.value();
-                if call.leg_b_session_id.as_ref() == Some(&session_id) {
-                    found_call_id = Some(call.id.clone());
-                    break;
+        let reason = if status_code == 180 { "Ringing" } else { "Session Progress" };
+        let (leg_a, reliable) = {
+            let Some(mut call) = calls.get_mut(call_id) else {
+                return Ok(());
+            };
+            let provisional = Provisional { status_code, reason: reason.to_string(), sdp: sdp.clone() };
+            let reliable = call.provisionals.leg_a.as_mut().map(|sender| sender.send(provisional, Instant::now()));
+            (call.leg_a_session_id.clone(), reliable)
+        };
+
+        let sip_handler = sip_handler.read().await;
+        match reliable {
+            Some(Some(send)) => Self::send_reliable(&sip_handler, &leg_a, &send).await,
+            // Goes out once leg A acknowledges the one before
+            Some(None) => Ok(()),
+            None => sip_handler.send_response(&leg_a, status_code, reason, sdp.as_deref()).await,
+        }
+    }
+
+    async fn send_reliable(sip_handler: &SipHandler, session_id: &str, send: &ReliableSend) -> Result<()> {
+        let provisional = &send.provisional;
+        sip_handler.send_response_with_headers(
+            session_id,
+            provisional.status_code,
+            &provisional.reason,
+            provisional.sdp.as_deref(),
+            &send.headers(),
+        ).await
+    }
+
+    /// PRACK from leg A: release the next queued provisional, and the 2xx
+    /// once no unacknowledged provisional carries SDP
+    async fn handle_prack(
+        session_id: &str,
+        transaction_id: &str,
+        rack: &str,
+        calls: &DashMap<String, B2buaCall>,
+        sip_handler: &Arc<RwLock<SipHandler>>,
+    ) -> Result<()> {
+        let outcome = {
+            let Some(mut entry) = calls.iter_mut().find(|entry| entry.leg_a_session_id == session_id) else {
+                return sip_handler.read().await.send_prack_response(transaction_id, 481, "Call/Transaction Does Not Exist").await;
+            };
+            let call = entry.value_mut();
+            let acknowledged = match (call.provisionals.leg_a.as_mut(), RAck::parse(rack)) {
+                (Some(sender), Some(rack)) => sender.acknowledge(&rack, Instant::now()),
+                _ => None,
+            };
+            acknowledged.map(|next| {
+                let answer = call
+                    .provisionals
+                    .leg_a
+                    .as_mut()
+                    .filter(|sender| !sender.holds_answer())
+                    .and_then(|sender| sender.deferred_answer.take());
+                if answer.is_some() {
+                    call.provisionals.leg_a = None;
                 }
-            }
-            found_call_id
+                (next, answer)
+            })
+        };
+
+        let sip_handler = sip_handler.read().await;
+        let Some((next, answer)) = outcome else {
+            return sip_handler.send_prack_response(transaction_id, 481, "Call/Transaction Does Not Exist").await;
         };
+        sip_handler.send_prack_response(transaction_id, 200, "OK").await?;
+        if let Some(answer) = answer {
+            return sip_handler.send_response_with_headers(session_id, 200, "OK", answer.sdp.as_deref(), &answer.headers).await;
+        }
+        match next {
+            Some(send) => Self::send_reliable(&sip_handler, session_id, &send).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn handle_call_answered(
+        ctx: &B2buaContext,
+        session_id: String,
+        sdp: Option<String>,
+        headers: Vec<(String, String)>,
+        received_at: DateTime<Utc>,
+        received_instant: Instant,
+    ) -> Result<()> {
+        let B2buaContext {
+            calls,
+            event_tx,
+            config,
+            sip_handler,
+            rtp_handler,
+            answer_supervisor: supervisor,
+            call_tracer: tracer,
+            codec_negotiator: negotiator,
+            media_inactivity,
+            media_security,
+            ..
+        } = ctx;
+        // Find call by session ID
+        let call_id = Self::find_call_by_leg_b(calls, &session_id);
 
         if let Some(call_id) = call_id {
             // Update call state
             if let Some(mut call) = calls.get_mut(&call_id) {
+                // The first ring group branch to answer becomes leg B
+                let mut cancelled = Vec::new();
+                let mut answered_attempt = call.leg_attempts.len().checked_sub(1);
+                if let Some((winner, losers)) = call.fork.as_mut().and_then(|fork| fork.answer(&session_id)) {
+                    answered_attempt = Some(winner);
+                    for (loser, attempt) in losers {
+                        if let Some(attempt) = call.leg_attempts.get_mut(attempt) {
+                            if attempt.close(LegOutcome::Cancelled, received_at) {
+                                let _ = event_tx.send(B2buaEvent::LegAttemptCompleted {
+                                    call_id: call_id.clone(),
+                                    attempt: attempt.clone(),
+                                });
+                            }
+                        }
+                        cancelled.push(loser);
+                    }
+                    if let Some(attempt) = call.leg_attempts.get(winner).cloned() {
+                        call.routing_info.target_gateway = Some(attempt.target);
+                        call.destination_uri = attempt.destination_uri;
+                    }
+                    call.leg_b_session_id = Some(session_id.clone());
+                    call.fork = None;
+                }
+
+                // The answer from the trunk is held to the same policy as the offer
+                let trunk = call.routing_info.target_gateway.clone().unwrap_or_default();
+                let sdp = match sdp {
+                    Some(answer) => match MediaPolicyEnforcer::new(&config.media_policies).enforce(&trunk, &answer, SdpRole::Answer) {
+                        PolicyOutcome::Accepted { sdp, .. } => Some(sdp),
+                        PolicyOutcome::Rejected { response_code, reason } => {
+                            warn!("Answer for call {} violates media policy: {}", call_id, reason);
+                            tracer.record(&call_id, TraceSubsystem::Signaling, || {
+                                format!("Answer rejected by media policy: {}", reason)
+                            });
+                            drop(call);
+                            return Self::refuse_answer(
+                                ctx,
+                                &call_id,
+                                &session_id,
+                                response_code,
+                                format!("Answer rejected by media policy: {}", reason),
+                            ).await;
+                        }
+                    },
+                    None => None,
+                };
+                // So is its SRTP requirement; leg A has not been answered yet
+                let leg_b_security = sdp.as_deref().and_then(LegSecurity::negotiated);
+                if let Some(security) = leg_b_security {
+                    if let Some(status_code) = media_security.record(&trunk, CallLeg::B, security).await {
+                        tracer.record(&call_id, TraceSubsystem::Signaling, || {
+                            format!("Answer refused: plain RTP from {} where SRTP is required", trunk)
+                        });
+                        drop(call);
+                        return Self::refuse_answer(
+                            ctx,
+                            &call_id,
+                            &session_id,
+                            status_code,
+                            format!("Answer from {} refused: plain RTP where SRTP is required", trunk),
+                        ).await;
+                    }
+                }
+                if let Some(ref answer) = sdp {
+                    media_inactivity.on_sdp(&call_id, answer);
+                }
+                if let (Some(offer), Some(answer)) = (call.leg_a_offer.as_deref(), sdp.as_deref()) {
+                    if let Some(path) = negotiator.record_answer(&call_id, &trunk, offer, answer) {
+                        tracer.record(&call_id, TraceSubsystem::Media, || format!("Media {:?} on trunk {}", path, trunk));
+                    }
+                }
+                let sdp = match sdp {
+                    Some(answer) => Some(Self::anchor_answer(&call, answer, rtp_handler).await?),
+                    None => None,
+                };
+                call.media_security = MediaSecurityStatus {
+                    leg_a: sdp.as_deref().and_then(LegSecurity::negotiated),
+                    leg_b: leg_b_security,
+                };
+                let security = call.media_security;
+                tracer.record(&call_id, TraceSubsystem::Media, || {
+                    let describe = |leg: Option<LegSecurity>| leg.map_or_else(|| "unknown".to_string(), |leg| leg.to_string());
+                    format!("Media security: leg A {}, leg B {}", describe(security.leg_a), describe(security.leg_b))
+                });
+
+                // Only an answer that passed the policies answers the call
+                supervisor.on_signal(&call_id, SupervisionSignal::SipAnswer, received_at, received_instant).await?;
                 call.state = B2buaCallState::Connected;
-                call.connected_at = Some(Instant::now());
+                call.connected_at = Some(received_instant);
                 call.last_activity = Instant::now();
+                if config.session_timers.enabled {
+                    let leg_b = session_timer::negotiate_uac(&config.session_timers, &headers);
+                    call.session_timers = CallSessionTimers {
+                        leg_a: call.session_timer.map(|timer| LegTimer::new(&timer, false, received_instant)),
+                        leg_b: Some(LegTimer::new(&leg_b, true, received_instant)),
+                    };
+                }
+                if let Some(attempt) = answered_attempt.and_then(|index| call.leg_attempts.get_mut(index)) {
+                    if attempt.close(LegOutcome::Answered, received_at) {
+                        let _ = event_tx.send(B2buaEvent::LegAttemptCompleted {
+                            call_id: call_id.clone(),
+                            attempt: attempt.clone(),
+                        });
+                    }
+                }
 
                 let duration_to_connect = call.connected_at.unwrap()
                     .duration_since(call.created_at);
 
-                // Send 200 OK to leg A
+                // Send 200 OK to leg A, with the session interval it was granted,
+                // unless it has yet to PRACK a provisional carrying SDP
+                let mut response_headers = call.session_timer.as_ref().map(session_timer::response_headers).unwrap_or_default();
+                response_headers.extend(correlation::response_headers(&config.correlation, &call.correlation_id));
+                let deferred = match call.provisionals.leg_a.as_mut() {
+                    Some(sender) if sender.holds_answer() => {
+                        sender.deferred_answer = Some(DeferredAnswer { sdp: sdp.clone(), headers: response_headers.clone() });
+                        true
+                    }
+                    _ => {
+                        call.provisionals.leg_a = None;
+                        false
+                    }
+                };
                 let sip_handler = sip_handler.read().await;
-                sip_handler.send_response(
-                    &call.leg_a_session_id,
-                    200,
-                    "OK",
-                    sdp.as_deref(),
-                ).await?;
+                if !deferred {
+                    sip_handler.send_response_with_headers(
+                        &call.leg_a_session_id,
+                        200,
+                        "OK",
+                        sdp.as_deref(),
+                        &response_headers,
+                    ).await?;
+                }
+                for loser in &cancelled {
+                    if let Err(e) = sip_handler.send_cancel(loser).await {
+                        warn!("Failed to CANCEL ring group branch {} of call {}: {}", loser, call_id, e);
+                    }
+                }
+
+                tracer.record(&call_id, TraceSubsystem::Signaling, || {
+                    format!(
+                        "Leg B answered after {:?}, 200 OK to leg A, answer: {}",
+                        duration_to_connect,
+                        sdp.as_deref().unwrap_or("none")
+                    )
+                });
 
                 // Emit call connected event
                 let _ = event_tx.send(B2buaEvent::CallConnected {
@@ -446,7 +1916,7 @@ impl B2buaService {
                     duration_to_connect,
                 });
 
-                info!("B2BUA call connected: {}", call_id);
+                info!(correlation_id = %call.correlation_id, "B2BUA call connected: {}", call_id);
             }
         }
 
@@ -454,12 +1924,21 @@ impl B2buaService {
     }
 
     async fn handle_call_terminated(
+        ctx: &B2buaContext,
         session_id: String,
         reason: String,
-        calls: &Arc<DashMap<String, B2buaCall>>,
-        event_tx: &mpsc::UnboundedSender<B2buaEvent>,
-        sip_handler: &Arc<RwLock<SipHandler>>,
     ) -> Result<()> {
+        let B2buaContext {
+            calls,
+            event_tx,
+            sip_handler,
+            answer_supervisor: supervisor,
+            trunk_failure,
+            release_causes: causes,
+            call_tracer: tracer,
+            codec_negotiator: negotiator,
+            ..
+        } = ctx;
         // Find and terminate call
         let call_to_terminate = {
             let mut found_call = None;
@@ -474,9 +1953,22 @@ impl B2buaService {
             found_call
         };
 
-        if let Some(call) = call_to_terminate {
+        if let Some(mut call) = call_to_terminate {
             // Terminate both legs
             let sip_handler = sip_handler.read().await;
+
+            // The caller gave up while the ring group was still ringing
+            let ringing = call.fork.as_mut().map(RingFork::cancel_all).unwrap_or_default();
+            for (branch, attempt) in ringing {
+                if let Err(e) = sip_handler.send_cancel(&branch).await {
+                    warn!("Failed to CANCEL ring group branch {} of call {}: {}", branch, call.id, e);
+                }
+                if let Some(attempt) = call.leg_attempts.get_mut(attempt) {
+                    if attempt.close(LegOutcome::Released { cause: None }, Utc::now()) {
+                        let _ = event_tx.send(B2buaEvent::LegAttemptCompleted { call_id: call.id.clone(), attempt: attempt.clone() });
+                    }
+                }
+            }
             
             // Terminate the other leg
             if call.leg_a_session_id != session_id {
@@ -494,28 +1986,54 @@ impl B2buaService {
                 Instant::now().duration_since(connected)
             });
 
+            // An unanswered leg B ends with the call
+            if let Some(mut attempt) = call.leg_attempts.last().cloned() {
+                if attempt.close(LegOutcome::Released { cause: None }, Utc::now()) {
+                    let _ = event_tx.send(B2buaEvent::LegAttemptCompleted { call_id: call.id.clone(), attempt });
+                }
+            }
+
             // Remove call from active calls
             calls.remove(&call.id);
+            supervisor.release_call(&call.id, CAUSE_NORMAL_CLEARING);
+            trunk_failure.forget(&call.id);
+            Self::record_release_cause(causes, &call, CAUSE_NORMAL_CLEARING);
+            negotiator.release(&call.id);
+            tracer.record(&call.id, TraceSubsystem::Signaling, || {
+                format!("BYE on session {}: {} (duration {:?})", session_id, reason, duration)
+            });
+            tracer.end_call(&call.id);
 
             // Emit call terminated event
             let _ = event_tx.send(B2buaEvent::CallTerminated {
                 call_id: call.id.clone(),
                 reason,
+                cause: None,
                 duration,
             });
 
-            info!("B2BUA call terminated: {} (duration: {:?})", call.id, duration);
+            info!(correlation_id = %call.correlation_id, "B2BUA call terminated: {} (duration: {:?})", call.id, duration);
         }
 
         Ok(())
     }
 
     async fn handle_rtp_packet(
+        ctx: &B2buaContext,
         session_id: String,
         packet: crate::protocols::rtp::RtpPacket,
-        calls: &Arc<DashMap<String, B2buaCall>>,
-        media_relays: &Arc<DashMap<String, MediaRelay>>,
     ) -> Result<()> {
+        let B2buaContext {
+            calls,
+            media_relays,
+            call_tracer: tracer,
+            media_inactivity,
+            sip_handler,
+            event_tx,
+            ..
+        } = ctx;
+        let fax = ctx.fax.as_deref();
+        let mut fax_detected = None;
         // Find call and relay packet to the other leg
         for call_entry in calls.iter() {
             let call = call_entry.value();
@@ -532,6 +2050,8 @@ impl B2buaService {
             };
 
             if let Some(target_session) = relay_to_session {
+                media_inactivity.on_packet(&call.id, packet.payload_type, &packet.payload, Instant::now());
+
                 // Update media relay statistics
                 if let Some(mut relay) = media_relays.get_mut(&call.id) {
                     relay.last_activity = Instant::now();
@@ -548,116 +2068,1653 @@ impl B2buaService {
                 // Relay packet (implementation would forward to RTP handler)
                 trace!("Relaying RTP packet from {} to {} for call {}",
                     session_id, target_session, call.id);
+                tracer.record(&call.id, TraceSubsystem::Media, || {
+                    format!(
+                        "RTP {} -> {}: PT {} seq {} ts {} ({} bytes)",
+                        session_id, target_session, packet.payload_type, packet.sequence_number,
+                        packet.timestamp, packet.payload.len()
+                    )
+                });
+
+                // Fax is relayed as T.38 only where the call meets a span
+                if let Some(fax) = fax.filter(|_| call.state == B2buaCallState::Connected && !call.routing_info.egress_spans.is_empty()) {
+                    let leg = if call.leg_a_rtp_session_id.as_ref() == Some(&session_id) { CallLeg::A } else { CallLeg::B };
+                    if let Some(tone) = fax.on_audio(&call.id, leg, packet.payload_type, &packet.payload) {
+                        fax_detected = Some((call.clone(), tone));
+                    }
+                }
             }
             
             break;
         }
 
+        if let (Some(fax), Some((call, tone))) = (fax, fax_detected) {
+            Self::switch_to_fax(fax, &call, tone, sip_handler, event_tx).await;
+        }
+
         Ok(())
     }
 
-    async fn setup_media_relay(
-        call_id: &str,
-        sdp: Option<&str>,
-        rtp_handler: &Arc<RwLock<RtpHandler>>,
+    /// Re-INVITE the SIP leg of a call found to carry fax to T.38, or leave
+    /// the fax on G.711 when T.38 is off or leg A has no anchored address
+    /// to receive UDPTL on
+    async fn switch_to_fax(
+        fax: &FaxRelay,
+        call: &B2buaCall,
+        tone: FaxTone,
+        sip_handler: &Arc<RwLock<SipHandler>>,
         event_tx: &mpsc::UnboundedSender<B2buaEvent>,
-    ) -> Result<()> {
-        let rtp_handler = rtp_handler.read().await;
-        
-        // Create RTP sessions for both legs
-        let leg_a_session = rtp_handler.create_session(
-            format!("{}_leg_a", call_id),
-            0, // payload type will be determined from SDP
-        ).await?;
-
-        let leg_b_session = rtp_handler.create_session(
-            format!("{}_leg_b", call_id),
-            0,
-        ).await?;
-
-        // Emit media relay started event
-        let _ = event_tx.send(B2buaEvent::MediaRelayStarted {
-            call_id: call_id.to_string(),
-            leg_a_port: leg_a_session.local_port,
-            leg_b_port: leg_b_session.local_port,
-        });
+    ) {
+        let address = call
+            .media_anchor
+            .as_ref()
+            .and_then(|anchor| anchor.leg_a)
+            .filter(|_| fax.t38_enabled());
+        if let Some(address) = address.map(|addr| addr.ip()) {
+            let result = match fax.offer_t38(&call.id, &call.leg_a_session_id, address).await {
+                Ok(offer) => sip_handler.read().await.send_reinvite(&call.leg_a_session_id, &offer).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => return,
+                Err(e) => warn!("Failed to offer T.38 on call {}: {}", call.id, e),
+            }
+        }
+        fax.pass_through(&call.id);
+        let _ = event_tx.send(B2buaEvent::FaxRelay { call_id: call.id.clone(), tone, mode: FaxMode::Passthrough });
+    }
 
-        info!("Media relay set up for call {}: ports {} <-> {}",
-            call_id, leg_a_session.local_port, leg_b_session.local_port);
+    /// Answer a call to a test number with the gateway's own media, or
+    /// refuse it with 488 when the offer cannot be played to
+    async fn answer_test_call(ctx: &B2buaContext, test_numbers: &TestNumbers, invite: &IncomingInvite, callee: &str) {
+        let B2buaContext { config, sip_handler, .. } = ctx;
+        let IncomingInvite { session_id, from, sdp, headers, .. } = invite;
+        let caller = Self::extract_user_from_uri(from).unwrap_or_else(|_| from.to_string());
+        let tenant = Self::extract_host_from_uri(from);
+        let accept_language = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Accept-Language"))
+            .map(|(_, value)| value.as_str());
+        let selector = Self::media_interface_selector(config).unwrap_or_default();
+        let interface = selector.select(CallLeg::A, None, tenant.as_deref());
 
-        Ok(())
+        let answer = test_numbers.answer(session_id, callee, &caller, sdp.as_deref(), accept_language, interface).await;
+        let response = match answer {
+            Ok(answer) => sip_handler.read().await.send_response(session_id, 200, "OK", Some(&answer)).await,
+            Err(e) => {
+                warn!("Refusing test call from {} to {}: {}", caller, callee, e);
+                sip_handler.read().await.send_response(session_id, 488, "Not Acceptable Here", None).await
+            }
+        };
+        if let Err(e) = response {
+            error!("Failed to answer test call {}: {}", session_id, e);
+        }
+    }
+
+    /// Answer a call to a paging number, or refuse it with 488 when the
+    /// offer lacks the paging codec
+    async fn answer_page(ctx: &B2buaContext, paging: &Paging, invite: &IncomingInvite, callee: &str) {
+        let B2buaContext { config, sip_handler, .. } = ctx;
+        let IncomingInvite { session_id, from, sdp, .. } = invite;
+        let caller = Self::extract_user_from_uri(from).unwrap_or_else(|_| from.to_string());
+        let tenant = Self::extract_host_from_uri(from);
+        let selector = Self::media_interface_selector(config).unwrap_or_default();
+        let interface = selector.select(CallLeg::A, None, tenant.as_deref());
+
+        let response = match paging.answer(session_id, callee, &caller, sdp.as_deref(), interface).await {
+            Ok(answer) => sip_handler.read().await.send_response(session_id, 200, "OK", Some(&answer)).await,
+            Err(e) => {
+                warn!("Refusing page from {} to {}: {}", caller, callee, e);
+                sip_handler.read().await.send_response(session_id, 488, "Not Acceptable Here", None).await
+            }
+        };
+        if let Err(e) = response {
+            error!("Failed to answer page {}: {}", session_id, e);
+        }
+    }
+
+    fn media_interface_selector(config: &B2buaConfig) -> Result<MediaInterfaceSelector> {
+        MediaInterfaceSelector::new(
+            &config.media_interfaces,
+            &config.media_interface_rules,
+            config.default_media_interface.as_deref(),
+        )
+    }
+
+    /// Set up relay sessions for both legs and return the offer to send toward leg B.
+    ///
+    /// Legs pinned to a media interface are bound to that interface and the
+    /// offer is rewritten to advertise leg B's relay endpoint. When both legs
+    /// are pinned the relay terminates SRTP: leg A's SDES key is taken from
+    /// its offer, and leg B is offered a key of the gateway's own when
+    /// `srtp_to_b` is set or plain RTP otherwise. A WebRTC offer from leg A
+    /// is bridged the same way, its ICE and DTLS parameters kept for the
+    /// answer, and leg B is offered WebRTC itself when `webrtc_to_b` is set.
+    async fn setup_media_relay(
+        ctx: &B2buaContext,
+        call_id: &str,
+        sdp: Option<String>,
+        trunk: Option<&str>,
+        tenant: Option<&str>,
+        srtp_to_b: bool,
+        webrtc_to_b: bool,
+    ) -> Result<Option<String>> {
+        let B2buaContext { config, calls, rtp_handler, event_tx, .. } = ctx;
+        let webrtc_config = &config.webrtc;
+        let selector = Self::media_interface_selector(config)?;
+        let rtp_handler = rtp_handler.read().await;
+        let leg_a_interface = selector.select(CallLeg::A, trunk, tenant);
+        let leg_b_interface = selector.select(CallLeg::B, trunk, tenant);
+
+        // Parse the offer before allocating ports, so a refused offer holds none
+        let mut offer = match sdp {
+            Some(offer) => Some((SessionDescription::parse(&offer)?, offer)),
+            None => None,
+        };
+        let anchored = leg_a_interface.is_some() && leg_b_interface.is_some();
+        let webrtc_to_b = webrtc_to_b && webrtc_config.enabled && anchored && offer.is_some();
+        let mut pending = PendingSrtp::default();
+        let mut leg_a_webrtc = None;
+        if let Some((parsed, _)) = offer.as_mut() {
+            let webrtc_offer = parsed.primary_audio().filter(|&index| webrtc::is_webrtc(&parsed.media[index]));
+            if let Some(index) = webrtc_offer {
+                if !webrtc_config.enabled {
+                    return Err(Error::not_supported("WebRTC media is not enabled"));
+                }
+                if !anchored {
+                    return Err(Error::not_supported("WebRTC needs both legs anchored on media interfaces"));
+                }
+                let remote = webrtc::remote_parameters(parsed, &parsed.media[index])?;
+                if matches!(remote.setup, DtlsSetup::Passive | DtlsSetup::Holdconn) {
+                    return Err(Error::not_supported("WebRTC offer wants the relay to be the DTLS client"));
+                }
+                let mid = parsed.media[index].attribute("mid").map(str::to_string);
+                webrtc::strip_webrtc(parsed, index);
+                // Only the audio stream is bridged to leg B
+                for (other, stream) in parsed.media.iter_mut().enumerate() {
+                    if other != index {
+                        stream.reject();
+                    }
+                }
+                leg_a_webrtc = Some((remote, mid));
+            }
+        }
+        if anchored {
+            if let Some((parsed, _)) = offer.as_mut() {
+                if let Some(stream) = parsed.primary_audio().and_then(|index| parsed.media.get_mut(index)) {
+                    pending.leg_a_remote = srtp::offered_crypto(stream)?;
+                    pending.leg_a_local = pending
+                        .leg_a_remote
+                        .as_ref()
+                        .map(|remote| CryptoAttribute::generate(remote.tag, remote.profile));
+                    if srtp_to_b && !webrtc_to_b {
+                        let profile = pending.leg_a_remote.as_ref().map_or(SrtpProfile::AesCm128HmacSha1_80, |remote| remote.profile);
+                        let local = CryptoAttribute::generate(1, profile);
+                        srtp::secure_stream(stream, &local);
+                        pending.leg_b_local = Some(local);
+                    } else {
+                        srtp::plain_stream(stream);
+                    }
+                }
+            }
+        }
+
+        // Create RTP sessions for both legs
+        let leg_a_session = Self::create_leg_session(
+            &rtp_handler,
+            format!("{}_leg_a", call_id),
+            leg_a_interface,
+        ).await?;
+
+        let leg_b_session = Self::create_leg_session(
+            &rtp_handler,
+            format!("{}_leg_b", call_id),
+            leg_b_interface,
+        ).await?;
+
+        let anchor = MediaAnchor {
+            leg_a: leg_a_interface.map(|i| SocketAddr::new(i.advertised_ip, leg_a_session.local_port)),
+            leg_b: leg_b_interface.map(|i| SocketAddr::new(i.advertised_ip, leg_b_session.local_port)),
+        };
+
+        let mut webrtc_pending = PendingWebRtc::default();
+        let stun_timeout = Duration::from_millis(webrtc_config.stun_timeout_ms);
+        if let (Some((remote, mid)), Some(interface)) = (leg_a_webrtc, leg_a_interface) {
+            webrtc_pending.leg_a = Some((remote, LocalParameters {
+                ice: IceCredentials::generate(),
+                fingerprint: rtp_handler.dtls_fingerprint()?,
+                setup: DtlsSetup::Passive,
+                candidates: rtp_handler.gather_candidates(
+                    &leg_a_session.id,
+                    interface.advertised_ip,
+                    webrtc_config.stun_server.as_deref(),
+                    stun_timeout,
+                ).await?,
+                mid,
+            }));
+        }
+        if let Some(interface) = leg_b_interface.filter(|_| webrtc_to_b) {
+            webrtc_pending.leg_b = Some(LocalParameters {
+                ice: IceCredentials::generate(),
+                fingerprint: rtp_handler.dtls_fingerprint()?,
+                setup: DtlsSetup::Actpass,
+                candidates: rtp_handler.gather_candidates(
+                    &leg_b_session.id,
+                    interface.advertised_ip,
+                    webrtc_config.stun_server.as_deref(),
+                    stun_timeout,
+                ).await?,
+                mid: Some("0".to_string()),
+            });
+        }
+
+        let sdp = match offer {
+            Some((mut parsed, offer)) => {
+                // A browser's media address is learned from its ICE checks
+                if let Some(remote) = parsed.audio_endpoint().filter(|_| webrtc_pending.leg_a.is_none()) {
+                    rtp_handler.set_remote_address(&leg_a_session.id, remote).await?;
+                }
+                match anchor.leg_b {
+                    Some(endpoint) => {
+                        Self::rewrite_media_endpoint(&mut parsed, endpoint);
+                        if let (Some(local), Some(index)) = (&webrtc_pending.leg_b, parsed.primary_audio()) {
+                            webrtc::webrtc_stream(&mut parsed, index, local);
+                        }
+                        Some(parsed.to_string())
+                    }
+                    None => Some(offer),
+                }
+            }
+            None => None,
+        };
+
+        if let Some(mut call) = calls.get_mut(call_id) {
+            call.leg_a_rtp_session_id = Some(leg_a_session.id.clone());
+            call.leg_b_rtp_session_id = Some(leg_b_session.id.clone());
+            if anchor != MediaAnchor::default() {
+                call.media_anchor = Some(anchor);
+            }
+            call.srtp = pending;
+            call.webrtc = webrtc_pending;
+        }
+
+        // Emit media relay started event
+        let _ = event_tx.send(B2buaEvent::MediaRelayStarted {
+            call_id: call_id.to_string(),
+            leg_a_port: leg_a_session.local_port,
+            leg_b_port: leg_b_session.local_port,
+        });
+
+        info!("Media relay set up for call {}: {}:{} <-> {}:{}",
+            call_id, leg_a_session.local_ip, leg_a_session.local_port,
+            leg_b_session.local_ip, leg_b_session.local_port);
+
+        Ok(sdp)
+    }
+
+    async fn create_leg_session(
+        rtp_handler: &RtpHandler,
+        session_id: String,
+        interface: Option<&ResolvedInterface>,
+    ) -> Result<crate::protocols::rtp::RtpSession> {
+        match interface {
+            Some(interface) => {
+                debug!("Binding {} to media interface {} ({})", session_id, interface.name, interface.bind_ip);
+                rtp_handler.create_session_on(session_id, 0, interface.bind_ip, interface.device.as_deref()).await
+            }
+            None => rtp_handler.create_session(session_id, 0).await,
+        }
+    }
+
+    /// Point the leg B answer at the relay and rewrite it for leg A when pinned.
+    /// With SRTP terminated, each leg's keys are installed on its relay session
+    /// and leg A is answered with its own key or with plain RTP. WebRTC legs
+    /// start their ICE and DTLS transport instead, and a browser leg A is
+    /// answered with the relay's ICE and DTLS parameters.
+    async fn anchor_answer(
+        call: &B2buaCall,
+        answer: String,
+        rtp_handler: &Arc<RwLock<RtpHandler>>,
+    ) -> Result<String> {
+        let (Some(leg_b_session), Some(anchor)) = (&call.leg_b_rtp_session_id, &call.media_anchor) else {
+            return Ok(answer);
+        };
+
+        let rtp_handler = rtp_handler.read().await;
+        let mut parsed = SessionDescription::parse(&answer)?;
+        let leg_b_webrtc = match (&call.webrtc.leg_b, parsed.primary_audio()) {
+            (Some(local), Some(index)) if webrtc::is_webrtc(&parsed.media[index]) => Some((local, index)),
+            _ => None,
+        };
+        match leg_b_webrtc {
+            Some((local, index)) => {
+                let remote = webrtc::remote_parameters(&parsed, &parsed.media[index])?;
+                rtp_handler.enable_webrtc(leg_b_session, &local.ice, &remote)?;
+                webrtc::strip_webrtc(&mut parsed, index);
+            }
+            None => {
+                if let Some(remote) = parsed.audio_endpoint() {
+                    rtp_handler.set_remote_address(leg_b_session, remote).await?;
+                }
+            }
+        }
+        let Some(endpoint) = anchor.leg_a else {
+            return Ok(answer);
+        };
+
+        if let (Some(leg_a_session), Some(index)) = (
+            call.leg_a_rtp_session_id.as_deref().filter(|_| anchor.leg_b.is_some()),
+            parsed.primary_audio(),
+        ) {
+            let stream = &mut parsed.media[index];
+            if let (Some(local), Some(remote)) = (&call.srtp.leg_b_local, srtp::offered_crypto(stream)?) {
+                rtp_handler.enable_srtp(leg_b_session, local, &remote)?;
+            }
+            match (&call.webrtc.leg_a, &call.srtp.leg_a_local, &call.srtp.leg_a_remote) {
+                (Some((remote, local)), _, _) => {
+                    rtp_handler.enable_webrtc(leg_a_session, &local.ice, remote)?;
+                    webrtc::webrtc_stream(&mut parsed, index, local);
+                }
+                (None, Some(local), Some(remote)) => {
+                    srtp::secure_stream(stream, local);
+                    rtp_handler.enable_srtp(leg_a_session, local, remote)?;
+                }
+                _ => srtp::plain_stream(stream),
+            }
+        }
+        Self::rewrite_media_endpoint(&mut parsed, endpoint);
+        Ok(parsed.to_string())
+    }
+
+    /// Advertise `endpoint` as the connection address and port of the
+    /// primary audio stream. Any other active stream is not relayed and keeps
+    /// the endpoint's own address.
+    fn rewrite_media_endpoint(sdp: &mut SessionDescription, endpoint: SocketAddr) {
+        let primary = sdp.primary_audio();
+        let passed: Vec<usize> = (0..sdp.media.len())
+            .filter(|&index| Some(index) != primary && !sdp.media[index].is_rejected())
+            .collect();
+        for &index in &passed {
+            sdp.pin_stream_connection(index);
+        }
+        sdp.set_connection_address_except(endpoint.ip(), &passed);
+        if let Some(stream) = primary.and_then(|index| sdp.media.get_mut(index)) {
+            stream.port = endpoint.port();
+        }
     }
 
     async fn initiate_outbound_call(
+        ctx: &B2buaContext,
         call_id: &str,
         caller: &str,
         callee: &str,
         routing_info: &RoutingInfo,
         sdp: Option<&str>,
-        calls: &Arc<DashMap<String, B2buaCall>>,
-        sip_handler: &Arc<RwLock<SipHandler>>,
+        kind: LegKind,
     ) -> Result<()> {
+        let B2buaContext { calls, sip_handler, quirks, .. } = ctx;
         let destination_uri = Self::build_destination_uri(callee, routing_info)?;
-        let from_uri = format!("sip:{}@gateway", caller);
+        let from_uri = match &routing_info.caller_name {
+            Some(name) => format!("\"{}\" <sip:{}@gateway>", name.replace('"', ""), caller),
+            None => format!("sip:{}@gateway", caller),
+        };
 
         // Initiate outbound SIP call
         let sip_handler = sip_handler.read().await;
         let target_addr = Self::resolve_target_address(&destination_uri).await?;
         
-        let leg_b_session_id = sip_handler.send_invite(
+        let mut headers: Vec<(String, String)> = routing_info.extra_headers.clone().into_iter().collect();
+        let quirk = routing_info.target_gateway.as_deref().and_then(|trunk| quirks.for_trunk(trunk));
+        let destination_uri = match quirk {
+            Some(profile) => {
+                profile.filter_headers(&mut headers);
+                profile.request_uri(&destination_uri, sip_transport::estimate_request_size(&destination_uri, sdp, &headers))
+            }
+            None => destination_uri,
+        };
+        let encapsulated = calls.get(call_id).map(|call| call.encapsulated.clone()).unwrap_or_default();
+        let leg_b_session_id = sip_handler.send_invite_with_body(
             &destination_uri,
             &from_uri,
             sdp,
+            &encapsulated,
             target_addr,
+            &headers,
         ).await?;
 
         // Update call with leg B session ID
         if let Some(mut call) = calls.get_mut(call_id) {
-            call.leg_b_session_id = Some(leg_b_session_id);
+            let target = routing_info.target_gateway.clone().unwrap_or_default();
+            call.leg_attempts.push(LegAttempt::new(target.clone(), destination_uri.clone(), kind));
+            let attempt = call.leg_attempts.len() - 1;
             call.last_activity = Instant::now();
+            match call.fork.as_mut().filter(|_| kind == LegKind::Fork) {
+                // Branches stay off leg B until one of them answers
+                Some(fork) => fork.ring(leg_b_session_id, attempt, &target),
+                None => {
+                    call.leg_b_session_id = Some(leg_b_session_id);
+                    call.destination_uri = destination_uri.clone();
+                }
+            }
         }
 
         info!("Initiated outbound call leg B for call {}: {} -> {}",
             call_id, caller, destination_uri);
 
-        Ok(())
+        Ok(())
+    }
+
+    /// Place branches to the ring group's next members, offering `sdp` to
+    /// each; returns how many INVITEs went out
+    async fn ring_group_members(
+        ctx: &B2buaContext,
+        call_id: &str,
+        caller: &str,
+        callee: &str,
+        sdp: Option<&str>,
+    ) -> usize {
+        let B2buaContext { calls, .. } = ctx;
+        let (members, routing_info) = {
+            let Some(mut call) = calls.get_mut(call_id) else {
+                return 0;
+            };
+            let Some(fork) = call.fork.as_mut() else {
+                return 0;
+            };
+            (fork.next_members(), call.routing_info.clone())
+        };
+
+        let mut placed = 0;
+        for member in members {
+            let mut branch = routing_info.clone();
+            branch.target_gateway = Some(member.target.clone());
+            match Self::initiate_outbound_call(
+                ctx,
+                call_id,
+                caller,
+                callee,
+                &branch,
+                sdp,
+                LegKind::Fork,
+            ).await {
+                Ok(()) => placed += 1,
+                Err(e) => warn!("Ring group branch of call {} to {} failed: {}", call_id, member.target, e),
+            }
+        }
+        placed
+    }
+
+    /// A ring group branch ended without answering: hunt on to the next
+    /// member, or release the call once no branch is left ringing
+    async fn end_fork_branch(
+        ctx: &B2buaContext,
+        call_id: &str,
+        session_id: &str,
+        outcome: LegOutcome,
+        failure: Option<(u16, String)>,
+        now: DateTime<Utc>,
+    ) {
+        let B2buaContext { calls, event_tx, sip_handler, .. } = ctx;
+        let (caller, callee, leg_a_session_id) = {
+            let Some(mut call) = calls.get_mut(call_id) else {
+                return;
+            };
+            let Some(attempt) = call.fork.as_mut().and_then(|fork| fork.drop_branch(session_id)) else {
+                return;
+            };
+            if let Some(attempt) = call.leg_attempts.get_mut(attempt) {
+                if attempt.close(outcome, now) {
+                    let _ = event_tx.send(B2buaEvent::LegAttemptCompleted {
+                        call_id: call_id.to_string(),
+                        attempt: attempt.clone(),
+                    });
+                }
+            }
+            call.last_activity = Instant::now();
+            if call.fork.as_ref().is_some_and(RingFork::ringing) {
+                // Other members are still ringing
+                return;
+            }
+            (call.caller.clone(), call.callee.clone(), call.leg_a_session_id.clone())
+        };
+
+        if outcome == LegOutcome::NoAnswer {
+            if let Err(e) = sip_handler.read().await.send_cancel(session_id).await {
+                warn!("Failed to CANCEL unanswered branch {} of call {}: {}", session_id, call_id, e);
+            }
+        }
+
+        // Offerless INVITE to the next member, as for no-answer forwarding;
+        // members that cannot be called are skipped
+        while calls.get(call_id).is_some_and(|call| call.fork.as_ref().is_some_and(|fork| !fork.exhausted())) {
+            if Self::ring_group_members(ctx, call_id, &caller, &callee, None).await > 0 {
+                return;
+            }
+        }
+
+        match failure {
+            Some((status_code, reason)) => {
+                let sip_handler = sip_handler.read().await;
+                if let Err(e) = sip_handler.send_response(&leg_a_session_id, status_code, &reason, None).await {
+                    warn!("Failed to relay {} to leg A of call {}: {}", status_code, call_id, e);
+                }
+                Self::release_call(
+                    ctx,
+                    call_id,
+                    format!("{} {}", status_code, reason),
+                    Some(release_causes::cause_for_sip_status(status_code)),
+                );
+            }
+            None => {
+                Self::release_call(
+                    ctx,
+                    call_id,
+                    "No answer from any ring group member".to_string(),
+                    Some(CAUSE_NO_ANSWER),
+                );
+            }
+        }
+    }
+
+    async fn call_monitor_loop(ctx: B2buaContext) {
+        let B2buaContext {
+            config,
+            calls,
+            event_tx,
+            trunk_failure,
+            call_tracer: tracer,
+            codec_negotiator: negotiator,
+            media_inactivity,
+            release_causes: causes,
+            ..
+        } = &ctx;
+        let timeout = Duration::from_secs(config.call_timeout as u64);
+        let mut monitor_interval = interval(Duration::from_secs(30));
+
+        loop {
+            monitor_interval.tick().await;
+            let now = Instant::now();
+
+            let mut timed_out_calls: Vec<(String, String, Option<u16>)> = calls
+                .iter()
+                .filter(|entry| {
+                    let call = entry.value();
+                    // Media still flowing keeps a call alive between signalling
+                    let last_activity = media_inactivity
+                        .last_packet(&call.id)
+                        .map_or(call.last_activity, |at| at.max(call.last_activity));
+                    // Preserved calls are bounded by their own preservation window
+                    now.duration_since(last_activity) > timeout && !trunk_failure.is_preserved(&call.id)
+                })
+                .map(|entry| (entry.key().clone(), "Call timeout".to_string(), None))
+                .collect();
+
+            // Answered calls whose media went silent for longer than their
+            // call type allows
+            for (call_id, call_type, silent) in media_inactivity.expired(now) {
+                let answered = calls.get(&call_id).is_some_and(|call| call.state == B2buaCallState::Connected);
+                if answered && !trunk_failure.is_preserved(&call_id) && !timed_out_calls.iter().any(|(id, ..)| *id == call_id) {
+                    let reason = format!("No {} media for {}s", call_type, silent.as_secs());
+                    timed_out_calls.push((call_id, reason, Some(CAUSE_RECOVERY_ON_TIMER_EXPIRY)));
+                }
+            }
+
+            for (call_id, reason, cause) in timed_out_calls {
+                if let Some((_, call)) = calls.remove(&call_id) {
+                    info!(correlation_id = %call.correlation_id, "B2BUA call timed out: {} ({})", call_id, reason);
+                    Self::record_release_cause(causes, &call, cause.unwrap_or(CAUSE_RECOVERY_ON_TIMER_EXPIRY));
+                    negotiator.release(&call_id);
+                    media_inactivity.release(&call_id);
+                    tracer.record(&call_id, TraceSubsystem::Signaling, || reason.clone());
+                    let _ = event_tx.send(B2buaEvent::CallTerminated {
+                        call_id,
+                        reason,
+                        cause,
+                        duration: call.connected_at.map(|connected| {
+                            now.duration_since(connected)
+                        }),
+                    });
+                }
+            }
+
+            // Calls released on other paths leave their traces open until here
+            tracer.end_released(|call_id| calls.contains_key(call_id));
+            media_inactivity.retain(|call_id| calls.contains_key(call_id));
+        }
+    }
+
+    async fn trunk_failure_loop(ctx: B2buaContext) {
+        let B2buaContext { calls, trunk_failure, reestablish_sessions, sip_handler, .. } = &ctx;
+        let mut poll_interval = interval(Duration::from_secs(1));
+
+        loop {
+            poll_interval.tick().await;
+            let poll = trunk_failure.poll(Instant::now());
+
+            for preserved in poll.expired {
+                reestablish_sessions.retain(|_, call_id| *call_id != preserved.call_id);
+                Self::release_call(
+                    &ctx,
+                    &preserved.call_id,
+                    format!("Trunk {} did not recover within preservation window", preserved.trunk),
+                    Some(CAUSE_RECOVERY_ON_TIMER_EXPIRY),
+                );
+            }
+
+            for preserved in poll.reestablish {
+                let (caller, destination_uri, headers) = match calls.get(&preserved.call_id) {
+                    Some(call) => {
+                        let headers: Vec<(String, String)> = call.routing_info.extra_headers.clone().into_iter().collect();
+                        (call.caller.clone(), call.destination_uri.clone(), headers)
+                    }
+                    None => {
+                        trunk_failure.forget(&preserved.call_id);
+                        continue;
+                    }
+                };
+
+                // Offerless INVITE: the trunk offers in its 200 and media stays on the existing relay
+                let result: Result<String> = async {
+                    let target_addr = Self::resolve_target_address(&destination_uri).await?;
+                    let sip_handler = sip_handler.read().await;
+                    sip_handler.send_invite_with_headers(
+                        &destination_uri,
+                        &format!("sip:{}@gateway", caller),
+                        None,
+                        target_addr,
+                        &headers,
+                    ).await
+                }.await;
+
+                match result {
+                    Ok(session_id) => {
+                        debug!("Re-establishing call {} on trunk {} (attempt {})",
+                            preserved.call_id, preserved.trunk, preserved.attempts);
+                        reestablish_sessions.insert(session_id, preserved.call_id);
+                    }
+                    Err(e) => {
+                        debug!("Re-establishment attempt {} for call {} failed: {}",
+                            preserved.attempts, preserved.call_id, e);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn no_answer_loop(ctx: B2buaContext) {
+        let B2buaContext { calls, .. } = &ctx;
+        let mut poll_interval = interval(Duration::from_secs(1));
+
+        loop {
+            poll_interval.tick().await;
+            let now = Utc::now();
+
+            let unanswered: Vec<String> = calls
+                .iter()
+                .filter(|entry| Self::no_answer_expired(entry.value(), now))
+                .map(|entry| entry.key().clone())
+                .collect();
+
+            for call_id in unanswered {
+                Self::forward_unanswered_call(&ctx, &call_id, now).await;
+            }
+
+            let expired_branches: Vec<(String, String)> = calls
+                .iter()
+                .flat_map(|entry| {
+                    let call = entry.value();
+                    let expired = call.fork.as_ref().map(|fork| fork.expired(&call.leg_attempts, now)).unwrap_or_default();
+                    expired.into_iter().map(|session_id| (call.id.clone(), session_id)).collect::<Vec<_>>()
+                })
+                .collect();
+
+            for (call_id, session_id) in expired_branches {
+                debug!("Ring group branch {} of call {} not answered in time", session_id, call_id);
+                Self::end_fork_branch(&ctx, &call_id, &session_id, LegOutcome::NoAnswer, None, now).await;
+            }
+        }
+    }
+
+    async fn early_media_loop(ctx: B2buaContext) {
+        let B2buaContext { calls, early_media: gate, .. } = &ctx;
+        let mut poll_interval = interval(Duration::from_secs(1));
+
+        loop {
+            poll_interval.tick().await;
+            let now = Utc::now();
+
+            let expired: Vec<(String, String, u64)> = calls
+                .iter()
+                .filter_map(|entry| {
+                    let call = entry.value();
+                    let policy = call.routing_info.early_media.as_ref()?;
+                    let started = call.early_media_at?;
+                    let unanswered = matches!(call.state, B2buaCallState::Establishing | B2buaCallState::Ringing);
+                    (unanswered && policy.expired(started, now)).then(|| {
+                        let trunk = call.routing_info.target_gateway.clone().unwrap_or_default();
+                        (call.id.clone(), trunk, policy.max_duration_secs.unwrap_or_default())
+                    })
+                })
+                .collect();
+
+            for (call_id, trunk, limit) in expired {
+                warn!("Call {} from {} played early media for {}s without answer", call_id, trunk, limit);
+                gate.record_cut_off(&trunk);
+                Self::release_call(
+                    &ctx,
+                    &call_id,
+                    format!("Early media exceeded {}s without answer", limit),
+                    Some(CAUSE_RECOVERY_ON_TIMER_EXPIRY),
+                );
+            }
+        }
+    }
+
+    fn no_answer_expired(call: &B2buaCall, now: DateTime<Utc>) -> bool {
+        // Ring group branches run on their members' ring times
+        if call.fork.is_some() || !matches!(call.state, B2buaCallState::Establishing | B2buaCallState::Ringing) {
+            return false;
+        }
+        match (&call.routing_info.no_answer, call.leg_attempts.last()) {
+            (Some(policy), Some(attempt)) => attempt.timed_out(policy.timeout(), now),
+            _ => false,
+        }
+    }
+
+    /// Abandon the ringing leg B and offer the call to the next target,
+    /// releasing it with cause 19 once every target has been tried
+    async fn forward_unanswered_call(
+        ctx: &B2buaContext,
+        call_id: &str,
+        now: DateTime<Utc>,
+    ) {
+        let B2buaContext { calls, event_tx, .. } = ctx;
+        let forward = {
+            let Some(mut call) = calls.get_mut(call_id) else {
+                return;
+            };
+            let Some(policy) = call.routing_info.no_answer.clone() else {
+                return;
+            };
+
+            if let Some(abandoned) = call.leg_b_session_id.take() {
+                // Implementation would CANCEL the abandoned leg B
+                debug!("Leg B {} of call {} not answered within {:?}", abandoned, call_id, policy.timeout());
+            }
+            if let Some(attempt) = call.leg_attempts.last_mut() {
+                if attempt.close(LegOutcome::NoAnswer, now) {
+                    let _ = event_tx.send(B2buaEvent::LegAttemptCompleted {
+                        call_id: call_id.to_string(),
+                        attempt: attempt.clone(),
+                    });
+                }
+            }
+
+            policy.next_target(call.leg_attempts.len()).map(|next| {
+                // The Diversion header names the party originally called
+                let diverting_uri = call
+                    .leg_attempts
+                    .first()
+                    .map(|attempt| attempt.destination_uri.clone())
+                    .unwrap_or_else(|| call.destination_uri.clone());
+                let (name, value) = no_answer::diversion_header(&diverting_uri, call.leg_attempts.len());
+
+                call.routing_info.target_gateway = Some(next.target.clone());
+                call.routing_info.extra_headers.insert(name, value);
+                call.state = B2buaCallState::Establishing;
+                call.last_activity = Instant::now();
+                (next, call.caller.clone(), call.callee.clone(), call.routing_info.clone(), call.leg_attempts.len() + 1)
+            })
+        };
+
+        let Some((next, caller, callee, routing_info, attempt)) = forward else {
+            Self::release_call(ctx, call_id, "No answer from any target".to_string(), Some(CAUSE_NO_ANSWER));
+            return;
+        };
+
+        info!("Forwarding unanswered call {} to {} ({:?}, attempt {})", call_id, next.target, next.kind, attempt);
+        let _ = event_tx.send(B2buaEvent::CallForwarded {
+            call_id: call_id.to_string(),
+            target: next.target.clone(),
+            kind: next.kind,
+            attempt,
+        });
+
+        // Offerless INVITE: the new target offers in its 200 and media stays on the relay
+        if let Err(e) = Self::initiate_outbound_call(
+            ctx,
+            call_id,
+            &caller,
+            &callee,
+            &routing_info,
+            None,
+            next.kind,
+        ).await {
+            Self::release_call(ctx, call_id, format!("Forwarding to {} failed: {}", next.target, e), None);
+        }
+    }
+
+    /// Leg B was refused: offer the call to the route's next alternate when the
+    /// status is re-routable, otherwise pass the failure back to leg A
+    async fn handle_call_failed(
+        ctx: &B2buaContext,
+        session_id: &str,
+        status_code: u16,
+        reason: String,
+    ) {
+        let B2buaContext { calls, event_tx, sip_handler, .. } = ctx;
+        let reroute = ctx.reroute.as_deref();
+        let Some(call_id) = Self::find_call_by_leg_b(calls, session_id) else {
+            debug!("Failure response {} for unknown leg B {}", status_code, session_id);
+            return;
+        };
+
+        // Ring group branches hunt within the group rather than re-route
+        if calls.get(&call_id).is_some_and(|call| call.fork.as_ref().is_some_and(|fork| fork.is_branch(session_id))) {
+            Self::end_fork_branch(
+                ctx,
+                &call_id,
+                session_id,
+                LegOutcome::Failed { status: status_code },
+                Some((status_code, reason)),
+                Utc::now(),
+            ).await;
+            return;
+        }
+
+        let reroute_to = {
+            let Some(mut call) = calls.get_mut(&call_id) else {
+                return;
+            };
+            call.leg_b_session_id = None;
+            if let Some(attempt) = call.leg_attempts.last_mut() {
+                if attempt.close(LegOutcome::Failed { status: status_code }, Utc::now()) {
+                    let _ = event_tx.send(B2buaEvent::LegAttemptCompleted {
+                        call_id: call_id.clone(),
+                        attempt: attempt.clone(),
+                    });
+                }
+            }
+
+            let trunk = call.routing_info.target_gateway.clone().unwrap_or_default();
+            let attempts = call.leg_attempts.len();
+            reroute.and_then(|decider| {
+                let next = call
+                    .routing_info
+                    .alternate_targets
+                    .get(attempts.saturating_sub(1))
+                    .filter(|_| attempts < decider.max_attempts())
+                    .cloned();
+                let action = match next {
+                    Some(_) => decider.action(status_code, &trunk, &call.routing_info.failover_actions),
+                    None => RerouteAction::Release,
+                };
+                decider.record(&trunk, status_code, action);
+                if action == RerouteAction::Release {
+                    return None;
+                }
+
+                let target = next?;
+                call.routing_info.target_gateway = Some(target.clone());
+                call.state = B2buaCallState::Establishing;
+                call.last_activity = Instant::now();
+                Some((target, call.caller.clone(), call.callee.clone(), call.routing_info.clone(), attempts + 1))
+            })
+        };
+
+        let Some((target, caller, callee, routing_info, attempt)) = reroute_to else {
+            let leg_a_session_id = calls.get(&call_id).map(|call| call.leg_a_session_id.clone());
+            if let Some(leg_a_session_id) = leg_a_session_id {
+                let sip_handler = sip_handler.read().await;
+                if let Err(e) = sip_handler.send_response(&leg_a_session_id, status_code, &reason, None).await {
+                    warn!("Failed to relay {} to leg A of call {}: {}", status_code, call_id, e);
+                }
+            }
+            Self::release_call(
+                ctx,
+                &call_id,
+                format!("{} {}", status_code, reason),
+                Some(release_causes::cause_for_sip_status(status_code)),
+            );
+            return;
+        };
+
+        info!("Re-routing call {} to {} after {} {} (attempt {})", call_id, target, status_code, reason, attempt);
+        let _ = event_tx.send(B2buaEvent::CallRerouted {
+            call_id: call_id.clone(),
+            status_code,
+            target: target.clone(),
+            attempt,
+        });
+
+        // Offerless INVITE, as for no-answer forwarding
+        if let Err(e) = Self::initiate_outbound_call(
+            ctx,
+            &call_id,
+            &caller,
+            &callee,
+            &routing_info,
+            None,
+            LegKind::Alternate,
+        ).await {
+            Self::release_call(ctx, &call_id, format!("Re-routing to {} failed: {}", target, e), None);
+        }
+    }
+
+    /// The new leg of an operator takeover answered: swap it in for the leg it replaces
+    fn complete_takeover(
+        takeover: &CallTakeover,
+        session_id: String,
+        calls: &DashMap<String, B2buaCall>,
+        event_tx: &mpsc::UnboundedSender<B2buaEvent>,
+    ) {
+        let Some(record) = takeover.complete(&session_id) else {
+            return;
+        };
+
+        match calls.get_mut(&record.request.call_id) {
+            Some(mut call) => {
+                let replaced = match record.request.leg {
+                    CallLeg::A => Some(std::mem::replace(&mut call.leg_a_session_id, session_id)),
+                    CallLeg::B => {
+                        call.routing_info.target_gateway = Some(record.request.target.clone());
+                        if let Some(uri) = &record.destination_uri {
+                            call.destination_uri = uri.clone();
+                        }
+                        call.leg_b_session_id.replace(session_id)
+                    }
+                };
+                call.last_activity = Instant::now();
+                // The new dialog's session starts over
+                let timer = match record.request.leg {
+                    CallLeg::A => call.session_timers.leg_a.as_mut(),
+                    CallLeg::B => call.session_timers.leg_b.as_mut(),
+                };
+                if let Some(timer) = timer {
+                    timer.refreshed(&[], Instant::now());
+                }
+                // Implementation would BYE the replaced leg unless the Replaces header already ended it
+                info!("Call {} leg {:?} taken over by {}: {:?} -> {}",
+                    record.request.call_id, record.request.leg, record.request.operator, replaced, record.request.target);
+            }
+            None => {
+                // The call ended while the new leg was ringing; implementation would BYE the new leg
+                warn!("Call {} ended before takeover {} completed", record.request.call_id, record.id);
+            }
+        }
+        let _ = event_tx.send(B2buaEvent::CallTakeover { record });
+    }
+
+    /// REFER from a party of a connected call: accept it, report progress
+    /// to the transferor and INVITE the target on the transferee's behalf,
+    /// with the Replaces of an attended transfer
+    async fn handle_refer(
+        ctx: &B2buaContext,
+        session_id: &str,
+        transaction_id: &str,
+        refer_to: &str,
+        headers: &[(String, String)],
+    ) -> Result<()> {
+        let B2buaContext { calls, event_tx, sip_handler, quirks, .. } = ctx;
+        let transfers = ctx.transfers.as_deref();
+        let sip_handler = sip_handler.read().await;
+        let Some(transfers) = transfers else {
+            return sip_handler.send_refer_response(transaction_id, 403, "Forbidden").await;
+        };
+        let Some(call) = calls
+            .iter()
+            .find(|entry| entry.leg_a_session_id == session_id || entry.leg_b_session_id.as_deref() == Some(session_id))
+            .map(|entry| entry.value().clone())
+        else {
+            return sip_handler.send_refer_response(transaction_id, 481, "Call/Transaction Does Not Exist").await;
+        };
+        let Some(target) = ReferTo::parse(refer_to) else {
+            return sip_handler.send_refer_response(transaction_id, 400, "Bad Refer-To").await;
+        };
+
+        let (transferor, transferee) = if call.leg_a_session_id == session_id {
+            (CallLeg::A, &call.callee)
+        } else {
+            (CallLeg::B, &call.caller)
+        };
+        let referred_by = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(transfer::REFERRED_BY_HEADER) || name.eq_ignore_ascii_case("b"))
+            .map(|(_, value)| value.clone());
+        let record = TransferRecord::new(&call.id, transferor, session_id, &target, referred_by.clone());
+
+        let refusal = if call.state != B2buaCallState::Connected {
+            Some((403, "Call Not Connected"))
+        } else if !transfers.allows(target.kind()) {
+            Some((403, "Transfer Not Allowed"))
+        } else if transfers.in_progress(&call.id) {
+            Some((491, "Request Pending"))
+        } else {
+            None
+        };
+        if let Some((status_code, reason)) = refusal {
+            let record = transfers.refuse(record, status_code, reason.to_string());
+            let _ = event_tx.send(B2buaEvent::CallTransfer { record });
+            return sip_handler.send_refer_response(transaction_id, status_code, reason).await;
+        }
+
+        sip_handler.send_refer_response(transaction_id, 202, "Accepted").await?;
+        Self::notify_transferor(&sip_handler, session_id, 100, "Trying").await;
+
+        // Offerless INVITE: the target offers in its 200 and media stays on the relay
+        let mut invite_headers = Vec::new();
+        if let Some(replaces) = &target.replaces {
+            invite_headers.push(("Replaces".to_string(), replaces.clone()));
+        }
+        if let Some(referred_by) = referred_by {
+            invite_headers.push((transfer::REFERRED_BY_HEADER.to_string(), referred_by));
+        }
+        let trunk = Self::extract_host_from_uri(&target.uri).unwrap_or_default();
+        let destination_uri = match quirks.for_trunk(&trunk) {
+            Some(profile) => {
+                profile.filter_headers(&mut invite_headers);
+                profile.request_uri(&target.uri, sip_transport::estimate_request_size(&target.uri, None, &invite_headers))
+            }
+            None => target.uri.clone(),
+        };
+        let placed = match Self::resolve_target_address(&destination_uri).await {
+            Ok(target_addr) => sip_handler.send_invite_with_headers(
+                &destination_uri,
+                &format!("sip:{}@gateway", transferee),
+                None,
+                target_addr,
+                &invite_headers,
+            ).await,
+            Err(e) => Err(e),
+        };
+
+        let record = match placed {
+            Ok(new_session_id) => transfers.begin(record, new_session_id),
+            Err(e) => {
+                Self::notify_transferor(&sip_handler, session_id, 503, "Service Unavailable").await;
+                transfers.refuse(record, 503, e.to_string())
+            }
+        };
+        let _ = event_tx.send(B2buaEvent::CallTransfer { record });
+        Ok(())
+    }
+
+    /// NOTIFY the transferor of the target's latest response
+    async fn notify_transferor(sip_handler: &SipHandler, session_id: &str, status_code: u16, reason: &str) {
+        let notified = sip_handler.send_notify(
+            session_id,
+            transfer::EVENT_PACKAGE,
+            transfer::subscription_state(status_code),
+            transfer::SIPFRAG_CONTENT_TYPE,
+            &transfer::sipfrag(status_code, reason),
+        ).await;
+        if let Err(e) = notified {
+            warn!("Failed to NOTIFY transferor on {}: {}", session_id, e);
+        }
+    }
+
+    /// re-INVITE from a party of a connected call: a sendonly or inactive
+    /// offer holds the call, and the holding party offering to receive
+    /// again resumes it
+    async fn handle_hold_offer(
+        ctx: &B2buaContext,
+        holds: &Arc<CallHolds>,
+        session_id: &str,
+        sdp: &str,
+    ) -> Result<()> {
+        let B2buaContext { calls, .. } = ctx;
+        let Some(call) = calls
+            .iter()
+            .find(|entry| entry.leg_a_session_id == session_id || entry.leg_b_session_id.as_deref() == Some(session_id))
+            .map(|entry| entry.value().clone())
+        else {
+            return Ok(());
+        };
+        if call.state != B2buaCallState::Connected {
+            return Ok(());
+        }
+        let leg = if call.leg_a_session_id == session_id { CallLeg::A } else { CallLeg::B };
+        let on_hold = call_hold::is_hold_offer(&SessionDescription::parse(sdp)?);
+        Self::set_hold(ctx, holds, &call, leg, on_hold, Some(sdp)).await?;
+        Ok(())
+    }
+
+    /// Hold or resume `call` for `leg`. The other party hears music when it
+    /// is configured and its media is PCMU; otherwise `offer` is passed on
+    /// to it. False when the call was already held, or `leg` is not the
+    /// party holding it
+    async fn set_hold(
+        ctx: &B2buaContext,
+        holds: &Arc<CallHolds>,
+        call: &B2buaCall,
+        leg: CallLeg,
+        on_hold: bool,
+        offer: Option<&str>,
+    ) -> Result<bool> {
+        let B2buaContext { event_tx, sip_handler, rtp_handler, .. } = ctx;
+        let anchor = call.media_anchor.clone().unwrap_or_default();
+        let (held_session, held_rtp_session, held_endpoint) = match leg {
+            CallLeg::A => (call.leg_b_session_id.clone(), call.leg_b_rtp_session_id.clone(), anchor.leg_b),
+            CallLeg::B => (Some(call.leg_a_session_id.clone()), call.leg_a_rtp_session_id.clone(), anchor.leg_a),
+        };
+
+        let treatment = if on_hold {
+            if holds.held_by(&call.id).is_some() {
+                return Ok(false);
+            }
+            let music_session = match held_rtp_session.filter(|_| holds.has_music()) {
+                Some(rtp_session) => {
+                    let payload_type = rtp_handler.read().await.get_session(&rtp_session).map(|session| session.payload_type);
+                    (payload_type == Some(call_hold::MUSIC_PAYLOAD_TYPE)).then_some(rtp_session)
+                }
+                None => None,
+            };
+            let treatment = if music_session.is_some() { HoldTreatment::Music } else { HoldTreatment::Relayed };
+            holds.hold(&call.id, leg, treatment);
+            if let Some(rtp_session) = music_session {
+                holds.play_music(&call.id, Arc::clone(rtp_handler), rtp_session);
+            }
+            treatment
+        } else {
+            match holds.resume(&call.id, leg) {
+                Some(treatment) => treatment,
+                None => return Ok(false),
+            }
+        };
+
+        if treatment == HoldTreatment::Relayed {
+            if let (Some(held_session), Some(offer)) = (held_session, offer) {
+                let mut offer = SessionDescription::parse(offer)?;
+                if let Some(endpoint) = held_endpoint {
+                    Self::rewrite_media_endpoint(&mut offer, endpoint);
+                }
+                sip_handler.read().await.send_reinvite(&held_session, &offer.to_string()).await?;
+            }
+        }
+        // Only leg A's holds go to the span; leg B is the span
+        let tdm_message = (leg == CallLeg::A && treatment == HoldTreatment::Relayed).then(|| call_hold::tdm_message(on_hold));
+        let _ = event_tx.send(B2buaEvent::CallHold {
+            call_id: call.id.clone(),
+            held_by: leg,
+            on_hold,
+            music: treatment == HoldTreatment::Music,
+            tdm_message,
+        });
+        Ok(true)
+    }
+
+    /// The transfer target answered: swap its leg in for the transferor's,
+    /// point the relay at its media and tear down the transferor's leg
+    async fn complete_transfer(ctx: &B2buaContext, transfers: &CallTransfers, session_id: String, sdp: Option<String>) {
+        let B2buaContext { calls, event_tx, sip_handler, rtp_handler, .. } = ctx;
+        let Some(record) = transfers.complete(&session_id) else {
+            return;
+        };
+
+        let rtp_session = match calls.get_mut(&record.call_id) {
+            Some(mut call) => {
+                let rtp_session = match record.transferor {
+                    CallLeg::A => {
+                        call.leg_a_session_id = session_id.clone();
+                        call.leg_a_rtp_session_id.clone()
+                    }
+                    CallLeg::B => {
+                        call.leg_b_session_id = Some(session_id.clone());
+                        call.routing_info.target_gateway = Self::extract_host_from_uri(&record.target);
+                        call.destination_uri = record.target.clone();
+                        call.leg_b_rtp_session_id.clone()
+                    }
+                };
+                call.last_activity = Instant::now();
+                // The new dialog's session starts over
+                let timer = match record.transferor {
+                    CallLeg::A => call.session_timers.leg_a.as_mut(),
+                    CallLeg::B => call.session_timers.leg_b.as_mut(),
+                };
+                if let Some(timer) = timer {
+                    timer.refreshed(&[], Instant::now());
+                }
+                rtp_session
+            }
+            None => {
+                warn!("Call {} ended before transfer {} completed", record.call_id, record.id);
+                if let Err(e) = sip_handler.read().await.send_bye(&session_id, &[]).await {
+                    warn!("Failed to release transfer target {}: {}", session_id, e);
+                }
+                let _ = event_tx.send(B2buaEvent::CallTransfer { record });
+                return;
+            }
+        };
+
+        // Bridge the transferee to the target's media
+        let remote = sdp.as_deref().and_then(|sdp| SessionDescription::parse(sdp).ok()).and_then(|sdp| sdp.audio_endpoint());
+        if let (Some(rtp_session), Some(remote)) = (rtp_session, remote) {
+            if let Err(e) = rtp_handler.read().await.set_remote_address(&rtp_session, remote).await {
+                warn!("Failed to bridge media of transfer {}: {}", record.id, e);
+            }
+        }
+
+        let sip_handler = sip_handler.read().await;
+        Self::notify_transferor(&sip_handler, &record.transferor_session_id, 200, "OK").await;
+        if let Err(e) = sip_handler.send_bye(&record.transferor_session_id, &[]).await {
+            warn!("Failed to release transferor leg {}: {}", record.transferor_session_id, e);
+        }
+        info!("Call {} transferred by leg {:?} to {}", record.call_id, record.transferor, record.target);
+        let _ = event_tx.send(B2buaEvent::CallTransfer { record });
+    }
+
+    fn handle_call_reestablished(
+        call_id: &str,
+        session_id: String,
+        calls: &DashMap<String, B2buaCall>,
+        trunk_failure: &TrunkFailureHandler,
+    ) {
+        if let Some(mut call) = calls.get_mut(call_id) {
+            call.leg_b_session_id = Some(session_id);
+            call.last_activity = Instant::now();
+            if let Some(timer) = call.session_timers.leg_b.as_mut() {
+                timer.refreshed(&[], Instant::now());
+            }
+        }
+        trunk_failure.recover(call_id, Instant::now());
+    }
+
+    /// Release a call whose answer on leg B is refused: leg B gets a BYE
+    /// and leg A the final response `status_code`, and the call is released
+    /// with the matching Q.850 cause
+    async fn refuse_answer(
+        ctx: &B2buaContext,
+        call_id: &str,
+        leg_b_session_id: &str,
+        status_code: u16,
+        reason: String,
+    ) -> Result<()> {
+        let B2buaContext { event_tx, sip_handler, .. } = ctx;
+        let _ = event_tx.send(B2buaEvent::Error { call_id: Some(call_id.to_string()), message: reason.clone() });
+        let cause = release_causes::cause_for_sip_status(status_code);
+        let released = Self::release_call(ctx, call_id, reason.clone(), Some(cause));
+        let Some(call) = released else {
+            return Ok(());
+        };
+        let sip_handler = sip_handler.read().await;
+        let text = release_causes::cause_description(cause);
+        let bye_headers = vec![("Reason".to_string(), format!("Q.850;cause={};text=\"{}\"", cause, text))];
+        if let Err(e) = sip_handler.send_bye(leg_b_session_id, &bye_headers).await {
+            warn!("Failed to release leg B of call {}: {}", call_id, e);
+        }
+        let phrase = Self::policy_reason_phrase(status_code);
+        sip_handler.send_response(&call.leg_a_session_id, status_code, phrase, None).await?;
+        Err(Error::not_supported(reason))
+    }
+
+    /// Remove a call and report its release with a gateway-chosen cause
+    fn release_call(
+        ctx: &B2buaContext,
+        call_id: &str,
+        reason: String,
+        cause: Option<u16>,
+    ) -> Option<B2buaCall> {
+        let B2buaContext {
+            calls,
+            event_tx,
+            answer_supervisor: supervisor,
+            trunk_failure,
+            release_causes: causes,
+            ..
+        } = ctx;
+        let (_, call) = calls.remove(call_id)?;
+        let cause_code = cause.unwrap_or(CAUSE_NORMAL_CLEARING);
+        supervisor.release_call(call_id, cause_code);
+        trunk_failure.forget(call_id);
+        Self::record_release_cause(causes, &call, cause_code);
+
+        // Send BYE to both legs (implementation would handle this)
+        info!(correlation_id = %call.correlation_id, "Released B2BUA call {}: {} (cause {:?})", call_id, reason, cause);
+        let _ = event_tx.send(B2buaEvent::CallTerminated {
+            call_id: call_id.to_string(),
+            reason,
+            cause,
+            duration: call.connected_at.map(|connected| Instant::now().duration_since(connected)),
+        });
+        Some(call)
+    }
+
+    /// Refuse leg A with `status_code`, first playing the announcement
+    /// configured for `reason` when there is one; the media server then
+    /// releases the call with the status and the reason's Q.850 cause
+    async fn reject_call(
+        ctx: &B2buaContext,
+        invite: &IncomingInvite,
+        reason: RejectReason,
+        status_code: u16,
+        reason_phrase: &str,
+    ) -> Result<()> {
+        let B2buaContext { config, sip_handler, event_tx, .. } = ctx;
+        let prompts = ctx.prompts.as_deref();
+        let IncomingInvite { session_id, from, to, headers, .. } = invite;
+        let ingress = Self::extract_host_from_uri(from);
+        let Some(rule) = reject_announcements::rule_for(&config.reject_announcements, reason, ingress.as_deref()) else {
+            return sip_handler.read().await.send_response(session_id, status_code, reason_phrase, None).await;
+        };
+
+        let (status, phrase) = if rule.answer { (200, "OK") } else { (183, "Session Progress") };
+        sip_handler.read().await.send_response(session_id, status, phrase, None).await?;
+        let accept_language = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Accept-Language"))
+            .map(|(_, value)| value.as_str());
+        let prompt = prompts.and_then(|library| library.select(&rule.announcement, None, ingress.as_deref(), accept_language));
+        debug!("Announcing {} rejection to {} ({} {})", reason, session_id, status_code, reason_phrase);
+        let _ = event_tx.send(B2buaEvent::AnnouncementRequested {
+            session_id: session_id.to_string(),
+            callee: Self::extract_user_from_uri(to).unwrap_or_else(|_| to.to_string()),
+            announcement: rule.announcement.clone(),
+            prompt,
+            release_status: status_code,
+            release_cause: Some(reason.q850_cause()),
+            answered: rule.answer,
+        });
+        Ok(())
+    }
+
+    /// Release the lowest call `priority` outranks, sending both its legs a
+    /// BYE with the preemption reason; false when no call ranks below it
+    async fn preempt_call(
+        ctx: &B2buaContext,
+        priority: &ResourcePriority,
+        trunk: Option<&str>,
+    ) -> bool {
+        let B2buaContext { calls, event_tx, sip_handler, .. } = ctx;
+        let preemption = {
+            let active: Vec<B2buaCall> = calls.iter().map(|entry| entry.value().clone()).collect();
+            let candidates = active.iter().map(|call| PreemptionCandidate {
+                call_id: &call.id,
+                level: call.precedence.as_ref().map_or(PrecedenceLevel::Routine, |p| p.level),
+                trunk: call.routing_info.target_gateway.as_deref(),
+                created_at: call.created_at,
+            });
+            precedence::select_preemption(priority.level, trunk, candidates)
+        };
+        let Some(preemption) = preemption else {
+            return false;
+        };
+
+        let reason = format!("Preempted by {} call", priority);
+        let Some(call) = Self::release_call(ctx, &preemption.call_id, reason, Some(preemption.cause)) else {
+            return false;
+        };
+
+        let headers = precedence::reason_headers(preemption.cause);
+        let sip_handler = sip_handler.read().await;
+        for session_id in std::iter::once(&call.leg_a_session_id).chain(&call.leg_b_session_id) {
+            if let Err(e) = sip_handler.send_bye(session_id, &headers).await {
+                warn!("Failed to send BYE to preempted call {}: {}", call.id, e);
+            }
+        }
+        info!("Preempted {} call {} for a {} call (cause {})", preemption.level, call.id, priority, preemption.cause);
+        let _ = event_tx.send(B2buaEvent::CallPreempted {
+            call_id: call.id,
+            level: preemption.level,
+            preempted_by: priority.clone(),
+            cause: preemption.cause,
+        });
+        true
+    }
+
+    fn record_release_cause(causes: &ReleaseCauseStats, call: &B2buaCall, cause: u16) {
+        let trunk = call.routing_info.target_gateway.as_deref().unwrap_or_default();
+        let route = call.routing_info.route.as_deref().unwrap_or_default();
+        causes.record(trunk, route, &call.callee, cause, Utc::now());
+    }
+
+    /// Refresh the session of each leg the gateway refreshes, and release
+    /// calls whose session lapsed on either leg
+    async fn session_timer_loop(ctx: B2buaContext) {
+        let B2buaContext { config, calls, sip_handler, quirks, .. } = &ctx;
+        let use_update = config.session_timers.use_update;
+        let mut poll_interval = interval(Duration::from_secs(1));
+
+        loop {
+            poll_interval.tick().await;
+            let now = Instant::now();
+
+            let mut refreshes = Vec::new();
+            let mut lapsed = Vec::new();
+            for mut entry in calls.iter_mut() {
+                let call = entry.value_mut();
+                if call.state != B2buaCallState::Connected {
+                    continue;
+                }
+                let trunk_method = call
+                    .routing_info
+                    .target_gateway
+                    .as_deref()
+                    .and_then(|trunk| quirks.for_trunk(trunk))
+                    .map(|profile| profile.mid_dialog_method());
+                let legs = [
+                    (CallLeg::A, Some(call.leg_a_session_id.clone()), call.session_timers.leg_a.as_mut()),
+                    (CallLeg::B, call.leg_b_session_id.clone(), call.session_timers.leg_b.as_mut()),
+                ];
+                for (leg, session_id, timer) in legs {
+                    let (Some(session_id), Some(timer)) = (session_id, timer) else {
+                        continue;
+                    };
+                    match timer.action(now) {
+                        Some(TimerAction::Refresh) => {
+                            timer.refresh_sent = Some(now);
+                            let method = match (leg, trunk_method) {
+                                (CallLeg::B, Some("UPDATE")) => "UPDATE",
+                                _ if use_update => "UPDATE",
+                                _ => "INVITE",
+                            };
+                            refreshes.push((session_id, method, timer.refresh_headers()));
+                        }
+                        Some(TimerAction::Expired) => {
+                            lapsed.push((call.id.clone(), format!("Session timer expired on leg {:?}", leg)));
+                            break;
+                        }
+                        None => {}
+                    }
+                }
+            }
+
+            if !refreshes.is_empty() {
+                let sip_handler = sip_handler.read().await;
+                for (session_id, method, headers) in refreshes {
+                    if let Err(e) = sip_handler.send_refresh(&session_id, method, &headers).await {
+                        warn!("Failed to send session refresh on {}: {}", session_id, e);
+                    }
+                }
+            }
+
+            for (call_id, reason) in lapsed {
+                Self::release_lapsed_session(&ctx, &call_id, reason).await;
+            }
+        }
+    }
+
+    /// Retransmit reliable provisionals leg A has not acknowledged, and
+    /// refuse calls whose caller never PRACKs
+    async fn reliable_provisional_loop(ctx: B2buaContext) {
+        let B2buaContext { calls, sip_handler, .. } = &ctx;
+        let mut poll_interval = interval(reliable_provisional::T1 / 2);
+
+        loop {
+            poll_interval.tick().await;
+            let now = Instant::now();
+
+            let mut retransmits = Vec::new();
+            let mut timed_out = Vec::new();
+            for mut entry in calls.iter_mut() {
+                let call = entry.value_mut();
+                match call.provisionals.leg_a.as_mut().and_then(|sender| sender.poll(now)) {
+                    Some(SenderAction::Retransmit(send)) => retransmits.push((call.leg_a_session_id.clone(), send)),
+                    Some(SenderAction::TimedOut) => timed_out.push(call.id.clone()),
+                    None => {}
+                }
+            }
+
+            if !retransmits.is_empty() {
+                let sip_handler = sip_handler.read().await;
+                for (session_id, send) in retransmits {
+                    if let Err(e) = Self::send_reliable(&sip_handler, &session_id, &send).await {
+                        warn!("Failed to retransmit reliable provisional on {}: {}", session_id, e);
+                    }
+                }
+            }
+
+            for call_id in timed_out {
+                warn!("Releasing call {}: reliable provisional never acknowledged", call_id);
+                let Some(call) = Self::release_call(
+                    &ctx,
+                    &call_id,
+                    "Reliable provisional response not acknowledged".to_string(),
+                    Some(CAUSE_RECOVERY_ON_TIMER_EXPIRY),
+                ) else {
+                    continue;
+                };
+                let sip_handler = sip_handler.read().await;
+                if let Err(e) = sip_handler.send_response(&call.leg_a_session_id, 500, "Server Internal Error", None).await {
+                    warn!("Failed to refuse call {}: {}", call_id, e);
+                }
+                // Leg B may have answered while the 2xx to leg A waited
+                if let Some(leg_b) = &call.leg_b_session_id {
+                    let result = if call.state == B2buaCallState::Connected {
+                        sip_handler.send_bye(leg_b, &[]).await
+                    } else {
+                        sip_handler.send_cancel(leg_b).await
+                    };
+                    if let Err(e) = result {
+                        warn!("Failed to release leg B of call {}: {}", call_id, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The peer refreshed a leg it is the refresher of
+    fn handle_session_refreshed(session_id: &str, headers: &[(String, String)], calls: &DashMap<String, B2buaCall>) {
+        for mut entry in calls.iter_mut() {
+            let call = entry.value_mut();
+            let timer = if call.leg_a_session_id == session_id {
+                call.session_timers.leg_a.as_mut()
+            } else if call.leg_b_session_id.as_deref() == Some(session_id) {
+                call.session_timers.leg_b.as_mut()
+            } else {
+                continue;
+            };
+            if let Some(timer) = timer {
+                timer.refreshed(headers, Instant::now());
+                debug!("Session {} of call {} refreshed by peer", session_id, call.id);
+            }
+            call.last_activity = Instant::now();
+            return;
+        }
+    }
+
+    /// Final response to a refresh we sent: 2xx restarts the session, 422
+    /// raises the interval for the next attempt, 491 retries, and anything
+    /// else means the far end is gone and the call is released
+    async fn handle_refresh_response(
+        ctx: &B2buaContext,
+        session_id: &str,
+        status_code: u16,
+        headers: &[(String, String)],
+    ) {
+        let B2buaContext { calls, .. } = ctx;
+        let failed = {
+            let Some(mut entry) = calls
+                .iter_mut()
+                .find(|entry| entry.leg_a_session_id == session_id || entry.leg_b_session_id.as_deref() == Some(session_id))
+            else {
+                return;
+            };
+            let call = entry.value_mut();
+            let timer = if call.leg_a_session_id == session_id {
+                call.session_timers.leg_a.as_mut()
+            } else {
+                call.session_timers.leg_b.as_mut()
+            };
+            let Some(timer) = timer else {
+                return;
+            };
+            let retried = match status_code {
+                200..=299 => {
+                    timer.refreshed(headers, Instant::now());
+                    call.last_activity = Instant::now();
+                    true
+                }
+                session_timer::STATUS_INTERVAL_TOO_SMALL => timer.raise_interval(headers),
+                491 => {
+                    timer.refresh_sent = None;
+                    true
+                }
+                _ => false,
+            };
+            (!retried).then(|| call.id.clone())
+        };
+
+        if let Some(call_id) = failed {
+            let reason = format!("Session refresh on {} failed with {}", session_id, status_code);
+            Self::release_lapsed_session(ctx, &call_id, reason).await;
+        }
+    }
+
+    /// Release a call whose session lapsed, sending BYE on both legs
+    async fn release_lapsed_session(
+        ctx: &B2buaContext,
+        call_id: &str,
+        reason: String,
+    ) {
+        let B2buaContext { sip_handler, .. } = ctx;
+        warn!("Releasing call {}: {}", call_id, reason);
+        let Some(call) = Self::release_call(ctx, call_id, reason, Some(CAUSE_RECOVERY_ON_TIMER_EXPIRY)) else {
+            return;
+        };
+
+        let sip_handler = sip_handler.read().await;
+        for session_id in std::iter::once(&call.leg_a_session_id).chain(&call.leg_b_session_id) {
+            if let Err(e) = sip_handler.send_bye(session_id, &[]).await {
+                warn!("Failed to send BYE on lapsed session {}: {}", session_id, e);
+            }
+        }
     }
 
-    async fn call_monitor_loop(
+    /// Extended reports on the legs of every connected call
+    async fn rtcp_xr_loop(
         calls: Arc<DashMap<String, B2buaCall>>,
-        event_tx: mpsc::UnboundedSender<B2buaEvent>,
-        timeout: Duration,
+        rtp_handler: Arc<RwLock<RtpHandler>>,
+        reporter: Arc<RtcpXrReporter>,
     ) {
-        let mut monitor_interval = interval(Duration::from_secs(30));
+        let mut report_interval = interval(Duration::from_secs(reporter.config().report_interval_secs));
 
         loop {
-            monitor_interval.tick().await;
-            let now = Instant::now();
-
-            let timed_out_calls: Vec<String> = calls
+            report_interval.tick().await;
+            let connected: Vec<B2buaCall> = calls
                 .iter()
-                .filter(|entry| {
-                    let call = entry.value();
-                    now.duration_since(call.last_activity) > timeout
-                })
-                .map(|entry| entry.key().clone())
+                .filter(|entry| entry.state == B2buaCallState::Connected)
+                .map(|entry| entry.value().clone())
                 .collect();
-
-            for call_id in timed_out_calls {
-                if let Some((_, call)) = calls.remove(&call_id) {
-                    info!("B2BUA call timed out: {}", call_id);
-                    let _ = event_tx.send(B2buaEvent::CallTerminated {
-                        call_id,
-                        reason: "Call timeout".to_string(),
-                        duration: call.connected_at.map(|connected| {
-                            now.duration_since(connected)
-                        }),
-                    });
-                }
+            let rtp_handler = rtp_handler.read().await;
+            for call in &connected {
+                reporter.send_reports(call, &rtp_handler).await;
             }
         }
     }
 
+    /// Final RTCP-XR metrics of a call about to be released
+    async fn report_call_quality(
+        reporter: &RtcpXrReporter,
+        call: &B2buaCall,
+        rtp_handler: &Arc<RwLock<RtpHandler>>,
+        event_tx: &mpsc::UnboundedSender<B2buaEvent>,
+    ) {
+        let legs = reporter.call_ended(call, &*rtp_handler.read().await);
+        for (leg, metrics) in legs {
+            let _ = event_tx.send(B2buaEvent::CallQuality { call_id: call.id.clone(), leg, metrics });
+        }
+    }
+
     async fn media_monitor_loop(
         media_relays: Arc<DashMap<String, MediaRelay>>,
         event_tx: mpsc::UnboundedSender<B2buaEvent>,
@@ -695,6 +3752,17 @@ impl B2buaService {
     }
 
     // Helper methods
+    fn find_call_by_leg_b(calls: &DashMap<String, B2buaCall>, session_id: &str) -> Option<String> {
+        calls
+            .iter()
+            .find(|entry| {
+                let call = entry.value();
+                call.leg_b_session_id.as_deref() == Some(session_id)
+                    || call.fork.as_ref().is_some_and(|fork| fork.is_branch(session_id))
+            })
+            .map(|entry| entry.key().clone())
+    }
+
     fn extract_user_from_uri(uri: &str) -> Result<String> {
         // Extract user portion from SIP URI
         if let Some(start) = uri.find("sip:") {
@@ -706,16 +3774,144 @@ impl B2buaService {
         Err(Error::parse("Invalid SIP URI format"))
     }
 
+    fn extract_host_from_uri(uri: &str) -> Option<String> {
+        let after_at = &uri[uri.find('@')? + 1..];
+        let host = after_at
+            .split(|c| c == ':' || c == ';' || c == '>')
+            .next()
+            .unwrap_or(after_at);
+        if host.is_empty() {
+            None
+        } else {
+            Some(host.to_string())
+        }
+    }
+
+    /// Route a call from its request URIs, consulting the routing hook when one is set
+    async fn resolve_route(
+        ctx: &B2buaContext,
+        from: &str,
+        to: &str,
+        headers: &[(String, String)],
+    ) -> Result<RouteResolution> {
+        let B2buaContext { config, event_tx, .. } = ctx;
+        let routing_hook = ctx.routing_hook.as_deref();
+        let number_lookup = ctx.number_lookup.as_deref();
+        let survivability = ctx.survivability.as_deref();
+        let shadow = ctx.shadow_routing.as_deref();
+        let callee = Self::extract_user_from_uri(to)?;
+
+        // Ported numbers are routed on their routing number
+        let (dip, caller_name) = match number_lookup {
+            Some(lookup) => {
+                let caller = Self::extract_user_from_uri(from)?;
+                Self::dip_numbers(lookup, &caller, &callee, config.number_lookup.caller_name, event_tx).await
+            }
+            None => (None, None),
+        };
+        let route_number = dip.as_ref().and_then(|d| d.routing_number.as_deref()).unwrap_or(&callee);
+        let mut static_route = Self::determine_routing(route_number, config)?;
+        static_route.number_lookup = dip;
+        static_route.caller_name = caller_name;
+
+        // The candidate table only observes; the call follows the active route
+        if let Some(shadow) = shadow {
+            let mut candidate = Self::determine_routing_with(route_number, shadow.routing_table(), config)?;
+            candidate.number_lookup = static_route.number_lookup.clone();
+            shadow.record(
+                &callee,
+                Self::route_decision(&callee, &static_route)?,
+                Self::route_decision(&callee, &candidate)?,
+            );
+        }
+
+        // While the hosted PBX is unreachable calls stay local or break out
+        if let Some(survivability) = survivability.filter(|s| s.mode() == SurvivabilityMode::Survivability) {
+            return Ok(survivability.route(&callee, static_route));
+        }
+
+        let Some(routing_hook) = routing_hook else {
+            return Ok(RouteResolution::Route(static_route));
+        };
+
+        let request = RoutingHookRequest {
+            caller: Self::extract_user_from_uri(from)?,
+            callee: callee.clone(),
+            tenant: Self::extract_host_from_uri(from),
+            static_target: static_route.target_gateway.clone(),
+            headers: headers.iter().cloned().collect(),
+        };
+        let outcome = routing_hook.resolve(&request, static_route).await;
+
+        if let Some(reason) = outcome.failure {
+            let _ = event_tx.send(B2buaEvent::RoutingHookFailed {
+                callee,
+                reason,
+                fallback_used: matches!(outcome.resolution, RouteResolution::Route(_)),
+            });
+        }
+        Ok(outcome.resolution)
+    }
+
+    /// Portability dip of the callee and, when enabled, caller name dip of the caller
+    async fn dip_numbers(
+        lookup: &NumberLookupRunner,
+        caller: &str,
+        callee: &str,
+        caller_name: bool,
+        event_tx: &mpsc::UnboundedSender<B2buaEvent>,
+    ) -> (Option<NumberLookupResult>, Option<String>) {
+        let (callee_dip, caller_dip) = tokio::join!(lookup.lookup(callee), async {
+            if caller_name {
+                Some(lookup.lookup(caller).await)
+            } else {
+                None
+            }
+        });
+
+        let report = |number: &str, e: Error| {
+            let _ = event_tx.send(B2buaEvent::NumberLookupFailed {
+                number: number.to_string(),
+                reason: e.to_string(),
+            });
+        };
+        let dip = callee_dip.map_err(|e| report(callee, e)).ok();
+        let name = match caller_dip {
+            Some(Ok(result)) => result.caller_name,
+            Some(Err(e)) => {
+                report(caller, e);
+                None
+            }
+            None => None,
+        };
+        (dip, name)
+    }
+
     fn determine_routing(callee: &str, config: &B2buaConfig) -> Result<RoutingInfo> {
+        Self::determine_routing_with(callee, &config.routing_table, config)
+    }
+
+    fn determine_routing_with(callee: &str, routing_table: &[RoutingRule], config: &B2buaConfig) -> Result<RoutingInfo> {
         // Simple routing logic - in practice this would be more sophisticated
-        for rule in &config.routing_table {
+        for rule in routing_table {
             if callee.contains(&rule.pattern) {
                 return Ok(RoutingInfo {
                     route_type: rule.route_type.clone(),
+                    route: Some(rule.id.clone()),
                     target_gateway: Some(rule.target.clone()),
                     number_translation: rule.translation.clone(),
                     codec_preference: vec!["PCMU".to_string(), "PCMA".to_string()],
                     priority: rule.priority,
+                    callee_override: None,
+                    extra_headers: BTreeMap::new(),
+                    egress_spans: Vec::new(),
+                    number_lookup: None,
+                    caller_name: None,
+                    no_answer: NoAnswerPolicy::from_rule(rule),
+                    alternate_targets: rule.alternate_targets.clone(),
+                    failover_actions: rule.failover_actions.clone(),
+                    ring_group: config.ring_groups.iter().find(|group| group.name == rule.target).cloned(),
+                    early_media: rule.early_media.clone(),
                 });
             }
         }
@@ -723,15 +3919,37 @@ impl B2buaService {
         // Default routing
         Ok(RoutingInfo {
             route_type: RouteType::Direct,
+            route: None,
             target_gateway: config.default_route_gateway.clone(),
             number_translation: None,
             codec_preference: vec!["PCMU".to_string(), "PCMA".to_string()],
             priority: 100,
+            callee_override: None,
+            extra_headers: BTreeMap::new(),
+            egress_spans: Vec::new(),
+            number_lookup: None,
+            caller_name: None,
+            no_answer: None,
+            alternate_targets: Vec::new(),
+            failover_actions: BTreeMap::new(),
+            ring_group: None,
+            early_media: None,
+        })
+    }
+
+    /// Where a routing decision sends the call, for shadow comparison
+    fn route_decision(callee: &str, routing_info: &RoutingInfo) -> Result<RouteDecision> {
+        Ok(RouteDecision {
+            route_type: format!("{:?}", routing_info.route_type),
+            target: routing_info.target_gateway.clone(),
+            destination_uri: Self::build_destination_uri(callee, routing_info)?,
+            alternate_targets: routing_info.alternate_targets.clone(),
+            ring_group: routing_info.ring_group.as_ref().map(|group| group.name.clone()),
         })
     }
 
     fn build_destination_uri(callee: &str, routing_info: &RoutingInfo) -> Result<String> {
-        let mut target_number = callee.to_string();
+        let mut target_number = routing_info.callee_override.clone().unwrap_or_else(|| callee.to_string());
 
         // Apply number translation if configured
         if let Some(translation) = &routing_info.number_translation {
@@ -745,6 +3963,14 @@ impl B2buaService {
             }
         }
 
+        // RFC 4694: the routing number and dip indicator travel in the user part
+        if let Some(dip) = &routing_info.number_lookup {
+            if let Some(routing_number) = &dip.routing_number {
+                target_number = format!("{};rn={}", target_number, routing_number);
+            }
+            target_number.push_str(";npdi");
+        }
+
         match &routing_info.target_gateway {
             Some(gateway) => Ok(format!("sip:{}@{}", target_number, gateway)),
             None => Ok(format!("sip:{}@localhost", target_number)),
@@ -768,56 +3994,595 @@ impl B2buaService {
     }
 
     // Public API methods
+    pub fn get_survivability(&self) -> Option<Arc<SurvivabilityService>> {
+        self.context.survivability.clone()
+    }
+
     pub fn get_active_calls(&self) -> Vec<B2buaCall> {
-        self.calls.iter().map(|entry| entry.value().clone()).collect()
+        self.context.calls.iter().map(|entry| entry.value().clone()).collect()
     }
 
     pub fn get_call(&self, call_id: &str) -> Option<B2buaCall> {
-        self.calls.get(call_id).map(|entry| entry.value().clone())
+        self.context.calls.get(call_id).map(|entry| entry.value().clone())
+    }
+
+    /// Live call carrying `correlation_id`
+    pub fn get_call_by_correlation(&self, correlation_id: &str) -> Option<B2buaCall> {
+        self.context.calls
+            .iter()
+            .find(|entry| entry.correlation_id == correlation_id)
+            .map(|entry| entry.value().clone())
     }
 
     pub fn get_active_call_count(&self) -> usize {
-        self.calls.len()
+        self.context.calls.len()
+    }
+
+    /// Reason phrase for a configured rate-limiting rejection status
+    fn congestion_reason_phrase(code: u16) -> &'static str {
+        match code {
+            403 => "Forbidden",
+            480 => "Temporarily Unavailable",
+            486 => "Busy Here",
+            503 => "Service Unavailable",
+            600 => "Busy Everywhere",
+            603 => "Decline",
+            _ => "Call Rate Exceeded",
+        }
+    }
+
+    fn policy_reason_phrase(code: u16) -> &'static str {
+        match code {
+            415 => "Unsupported Media Type",
+            488 => "Not Acceptable Here",
+            606 => "Not Acceptable",
+            _ => "Media Policy Violation",
+        }
+    }
+
+    pub fn answer_supervisor(&self) -> Arc<AnswerSupervisor> {
+        Arc::clone(&self.context.answer_supervisor)
     }
 
     pub fn get_media_relay_stats(&self, call_id: &str) -> Option<MediaRelay> {
-        self.media_relays.get(call_id).map(|entry| entry.value().clone())
+        self.context.media_relays.get(call_id).map(|entry| entry.value().clone())
     }
 
     pub async fn terminate_call(&self, call_id: &str, reason: &str) -> Result<()> {
-        if let Some((_, call)) = self.calls.remove(call_id) {
-            // Terminate both legs
-            let sip_handler = self.sip_handler.read().await;
-            
-            // Send BYE to both legs (implementation would handle this)
-            // This is simplified for the core structure
-            
-            let _ = self.event_tx.send(B2buaEvent::CallTerminated {
-                call_id: call_id.to_string(),
-                reason: reason.to_string(),
-                duration: call.connected_at.map(|connected| {
-                    Instant::now().duration_since(connected)
-                }),
-            });
+        if let Some(reporter) = &self.context.rtcp_xr {
+            let call = self.context.calls.get(call_id).map(|entry| entry.value().clone());
+            if let Some(call) = call {
+                Self::report_call_quality(reporter, &call, &self.context.rtp_handler, &self.context.event_tx).await;
+            }
+        }
+        self.context.codec_negotiator.release(call_id);
+        Self::release_call(&self.context, call_id, reason.to_string(), None)
+        .map(|_| ())
+        .ok_or_else(|| Error::invalid_state("Call not found"))
+    }
+
+    /// Apply the configured failure action to calls routed over a failed trunk.
+    ///
+    /// Calls still being set up are always released; established calls are
+    /// released or preserved according to the trunk's failure policy.
+    pub fn handle_trunk_down(&self, trunk: &str) -> FailureDisposition {
+        if let Some(survivability) = &self.context.survivability {
+            survivability.on_trunk_down(trunk);
+        }
+
+        let mut established = Vec::new();
+        let mut setting_up = Vec::new();
+        for entry in self.context.calls.iter() {
+            let call = entry.value();
+            if call.routing_info.target_gateway.as_deref() != Some(trunk) {
+                continue;
+            }
+            if call.state == B2buaCallState::Connected {
+                established.push(call.id.clone());
+            } else {
+                setting_up.push(call.id.clone());
+            }
+        }
+
+        let reason = format!("Trunk {} failed", trunk);
+        for call_id in &setting_up {
+            self.release_trunk_call(call_id, reason.clone(), CAUSE_NETWORK_OUT_OF_ORDER);
+        }
+
+        let disposition = self.context.trunk_failure.on_trunk_down(trunk, &established, Instant::now());
+        if let FailureDisposition::Release { cause } = disposition {
+            for call_id in &established {
+                self.release_trunk_call(call_id, reason.clone(), cause);
+            }
+        }
+        disposition
+    }
+
+    /// Recover every call preserved on a trunk that is reachable again
+    pub fn handle_trunk_up(&self, trunk: &str) -> Vec<String> {
+        if let Some(survivability) = &self.context.survivability {
+            survivability.on_trunk_up(trunk);
+        }
+
+        let recovered = self.context.trunk_failure.on_trunk_up(trunk, Instant::now());
+        for call_id in &recovered {
+            if let Some(mut call) = self.context.calls.get_mut(call_id) {
+                call.last_activity = Instant::now();
+            }
+        }
+        recovered
+    }
+
+    /// Feed measured call quality into the baseline of the trunk the call was routed over
+    pub fn record_call_quality(&self, call_id: &str, sample: QualitySample) {
+        let Some(monitor) = &self.quality_monitor else {
+            return;
+        };
+        let trunk = self
+            .calls
+            .get(call_id)
+            .and_then(|call| call.routing_info.target_gateway.clone());
+        if let Some(trunk) = trunk {
+            monitor.record(&trunk, sample, Utc::now(), Instant::now());
+        }
+    }
+
+    /// Relay ALERTING or PROGRESS from the span a call was broken out to as
+    /// an 18x to leg A, with `sdp` describing the span's in-band media.
+    /// `received` is when the message was decoded off the D-channel
+    pub async fn report_tdm_progress(
+        &self,
+        call_id: &str,
+        message: &Q931Message,
+        sdp: Option<String>,
+        received: ClockStamp,
+    ) -> Result<()> {
+        let Some((status_code, inband)) = reliable_provisional::provisional_for_tdm(message) else {
+            return Ok(());
+        };
+        let progress_indicator = message.progress();
+        let signal = match message.message_type {
+            MessageType::Alerting => SupervisionSignal::Q931Alerting { progress_indicator },
+            _ => SupervisionSignal::Q931Progress { progress_indicator: progress_indicator.unwrap_or_default() },
+        };
+        self.context.answer_supervisor.on_signal(call_id, signal, received.utc, received.instant).await?;
+        let B2buaContext { calls, sip_handler, .. } = &self.context;
+        Self::relay_provisional(call_id, status_code, sdp.filter(|_| inband), calls, sip_handler).await
+    }
+
+    /// Record that leg B of a call broken out to TDM was placed on
+    /// `channel_id` of `span_id`, so the span's answer reaches the call
+    pub fn bind_tdm_channel(&self, call_id: &str, span_id: u32, channel_id: u8) {
+        self.tdm_channels.insert((span_id, channel_id), call_id.to_string());
+    }
+
+    /// Q.931 CONNECT from the span a call was broken out to: the call is
+    /// answered as of `received`
+    pub async fn report_tdm_answer(&self, call_id: &str, received: ClockStamp) -> Result<()> {
+        let signal = SupervisionSignal::Q931Connect;
+        self.context.answer_supervisor.on_signal(call_id, signal, received.utc, received.instant).await?;
+        Ok(())
+    }
+
+    /// Apply a FreeTDM event from the spans calls are broken out to
+    pub async fn handle_tdm_event(&self, event: &FreeTdmEvent) -> Result<()> {
+        match event {
+            FreeTdmEvent::CallAnswered { span_id, channel_id, received } => {
+                let call_id = self.tdm_channels.get(&(*span_id, *channel_id)).map(|entry| entry.value().clone());
+                match call_id {
+                    Some(call_id) => self.report_tdm_answer(&call_id, *received).await,
+                    None => Ok(()),
+                }
+            }
+            FreeTdmEvent::CallHangup { span_id, channel_id, .. } => {
+                self.tdm_channels.remove(&(*span_id, *channel_id));
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Q.932 HOLD or RETRIEVE from the span a call was broken out to, i.e.
+    /// from leg B. Leg A hears music while held if it is configured; the
+    /// span sends no audio while it holds, so there is no offer to pass on.
+    /// Returns the acknowledgement or rejection to send back on the span
+    pub async fn report_tdm_hold(&self, call_id: &str, message: &Q931Message) -> Result<Q931Message> {
+        let on_hold = match message.message_type {
+            MessageType::Hold => true,
+            MessageType::Retrieve => false,
+            other => return Err(Error::protocol(format!("{:?} is not a hold request", other))),
+        };
+        let call = self.context.calls.get(call_id).map(|call| call.clone());
+        let refused = match (&self.context.holds, call) {
+            (None, _) => Some(q931::CAUSE_FACILITY_NOT_IMPLEMENTED),
+            (Some(holds), Some(call)) if call.state == B2buaCallState::Connected => {
+                let changed = Self::set_hold(&self.context, holds, &call, CallLeg::B, on_hold, None).await?;
+                (!changed).then_some(q931::CAUSE_WRONG_CALL_STATE)
+            }
+            (Some(_), _) => Some(q931::CAUSE_WRONG_CALL_STATE),
+        };
+        call_hold::tdm_reply(message, refused).ok_or_else(|| Error::invalid_state("No reply to a hold request"))
+    }
+
+    /// T.38 packet from the fax modem on the span a call was broken out to,
+    /// for the SIP leg
+    pub async fn send_fax_ifp(&self, call_id: &str, packet: &IfpPacket) -> Result<()> {
+        let fax = self.context.fax.as_ref().ok_or_else(|| Error::not_supported("Fax relay is disabled"))?;
+        fax.send(call_id, packet).await
+    }
+
+    /// How fax is carried on a call, once detected
+    pub fn fax_mode(&self, call_id: &str) -> Option<FaxMode> {
+        self.context.fax.as_ref().and_then(|fax| fax.mode(call_id))
+    }
+
+    /// Feed a downstream release cause for a call into congestion-triggered gapping
+    pub fn report_release_cause(&self, call_id: &str, cause: u16) {
+        let Some(gapper) = &self.context.call_gapping else {
+            return;
+        };
+        let callee = self.context.calls.get(call_id).map(|call| call.callee.clone());
+        if let Some(callee) = callee {
+            gapper.on_release(&callee, cause, Instant::now());
+        }
+    }
+
+    /// Call gaps currently in force, for the NOC
+    pub fn active_call_gaps(&self) -> Vec<ActiveGap> {
+        self.context.call_gapping
+            .as_ref()
+            .map(|gapper| gapper.active_gaps(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// Apply a gap by hand using the configured interval and duration
+    pub fn apply_call_gap(&self, prefix: &str) -> Result<()> {
+        let gapper = self
+            .call_gapping
+            .as_ref()
+            .ok_or_else(|| Error::not_supported("Call gapping is disabled"))?;
+        gapper.apply_gap(
+            prefix,
+            Duration::from_millis(self.context.config.call_gapping.gap_interval_ms),
+            Duration::from_secs(self.context.config.call_gapping.gap_duration_secs),
+            None,
+            Instant::now(),
+        );
+        Ok(())
+    }
+
+    /// Move one leg of a live call to a new target on an operator's request;
+    /// refused requests are audited as well
+    pub async fn take_over_call(&self, request: TakeoverRequest) -> Result<TakeoverRecord> {
+        let takeover = self
+            .takeover
+            .as_ref()
+            .ok_or_else(|| Error::not_supported("Call takeover is disabled"))?;
+
+        match self.start_takeover(takeover, &request).await {
+            Ok(record) => {
+                let _ = self.context.event_tx.send(B2buaEvent::CallTakeover { record: record.clone() });
+                Ok(record)
+            }
+            Err(e) => {
+                let record = takeover.refuse(request, e.to_string());
+                let _ = self.context.event_tx.send(B2buaEvent::CallTakeover { record });
+                Err(e)
+            }
+        }
+    }
+
+    async fn start_takeover(&self, takeover: &CallTakeover, request: &TakeoverRequest) -> Result<TakeoverRecord> {
+        if takeover.in_progress(&request.call_id) {
+            return Err(Error::invalid_state("A takeover of this call is already in progress"));
+        }
+        let call = self
+            .calls
+            .get(&request.call_id)
+            .map(|call| call.clone())
+            .ok_or_else(|| Error::invalid_state("Call not found"))?;
+        if call.state != B2buaCallState::Connected {
+            return Err(Error::invalid_state("Only connected calls can be taken over"));
+        }
+
+        let (session_id, from_party) = match request.leg {
+            CallLeg::A => (Some(call.leg_a_session_id.clone()), &call.callee),
+            CallLeg::B => (call.leg_b_session_id.clone(), &call.caller),
+        };
+        let session_id = session_id.ok_or_else(|| Error::invalid_state("Call leg has no signaling session"))?;
 
-            info!("Manually terminated B2BUA call: {} ({})", call_id, reason);
-            Ok(())
+        let destination_uri = if request.target.starts_with("sip:") {
+            request.target.clone()
+        } else if request.leg == CallLeg::B {
+            let mut routing_info = call.routing_info.clone();
+            routing_info.target_gateway = Some(request.target.clone());
+            Self::build_destination_uri(&call.callee, &routing_info)?
         } else {
-            Err(Error::b2bua("Call not found"))
+            format!("sip:{}@{}", call.caller, request.target)
+        };
+
+        // Offerless INVITE: the new party offers in its 200 and media stays on the relay
+        let sip_handler = self.context.sip_handler.read().await;
+        let headers: Vec<(String, String)> = sip_handler
+            .get_session(&session_id)
+            .map(|session| takeover::replaces_header(&session))
+            .into_iter()
+            .collect();
+        let target_addr = Self::resolve_target_address(&destination_uri).await?;
+        let destination_uri = match self.context.quirks.for_trunk(&request.target) {
+            Some(profile) => profile.request_uri(&destination_uri, sip_transport::estimate_request_size(&destination_uri, None, &headers)),
+            None => destination_uri,
+        };
+        let new_session_id = sip_handler.send_invite_with_headers(
+            &destination_uri,
+            &format!("sip:{}@gateway", from_party),
+            None,
+            target_addr,
+            &headers,
+        ).await?;
+
+        let previous_target = match request.leg {
+            CallLeg::A => None,
+            CallLeg::B => call.routing_info.target_gateway.clone(),
+        };
+        Ok(takeover.begin(request.clone(), previous_target, Some(session_id), new_session_id, destination_uri))
+    }
+
+    /// Operator takeovers, most recent first
+    pub fn takeover_history(&self) -> Vec<TakeoverRecord> {
+        self.context.takeover.as_ref().map(|takeover| takeover.history()).unwrap_or_default()
+    }
+
+    /// Transfers requested with REFER, most recent first
+    pub fn transfer_history(&self) -> Vec<TransferRecord> {
+        self.context.transfers.as_ref().map(|transfers| transfers.history()).unwrap_or_default()
+    }
+
+    /// Lift a gap before it expires
+    pub fn remove_call_gap(&self, prefix: &str) -> bool {
+        self.context.call_gapping.as_ref().is_some_and(|gapper| gapper.remove_gap(prefix))
+    }
+
+    /// Quirk profile applied to `trunk`, for mid-dialog handling in the SIP layer
+    pub fn trunk_quirks(&self, trunk: &str) -> Option<QuirkProfile> {
+        self.context.quirks.for_trunk(trunk).cloned()
+    }
+
+    /// Numbers, trunks and calls selected for per-call tracing, shared with
+    /// the media relay and transcoding services
+    pub fn call_tracer(&self) -> Arc<CallTracer> {
+        Arc::clone(&self.context.call_tracer)
+    }
+
+    /// Trace calls matching `selector`; a Call-ID of a live call is traced
+    /// from now on
+    pub fn add_trace_selector(&self, selector: TraceSelector) {
+        let live = match &selector {
+            TraceSelector::CallId(call_id) => self.get_call(call_id),
+            TraceSelector::CorrelationId(correlation_id) => self.get_call_by_correlation(correlation_id),
+            _ => None,
+        };
+        if let Some(call) = live {
+            let tracer = &self.context.call_tracer;
+            tracer.trace_live_call(&call.id, &call.correlation_id, selector.clone());
+            tracer.record(&call.id, TraceSubsystem::Signaling, || "Tracing started on live call".to_string());
+        }
+        self.context.call_tracer.add_selector(selector);
+    }
+
+    /// Failure responses per trunk and status, and how many were re-routed
+    pub fn reroute_counters(&self) -> Vec<RerouteCounter> {
+        self.context.reroute.as_ref().map(|decider| decider.counters()).unwrap_or_default()
+    }
+
+    /// Announcement prompt packs, when enabled
+    pub fn prompt_library(&self) -> Option<Arc<PromptLibrary>> {
+        self.context.prompts.clone()
+    }
+
+    /// Active and recent calls to the test numbers
+    pub fn test_calls(&self) -> Vec<TestCallRecord> {
+        self.context.test_numbers.as_ref().map(|numbers| numbers.calls()).unwrap_or_default()
+    }
+
+    /// Active and recent pages to the multicast paging groups
+    pub fn pages(&self) -> Vec<PageRecord> {
+        self.context.paging.as_ref().map(|paging| paging.pages()).unwrap_or_default()
+    }
+
+    /// Whether a call's media has been seen to carry voice, fax or modem
+    pub fn media_call_type(&self, call_id: &str) -> MediaCallType {
+        self.context.media_inactivity.call_type(call_id)
+    }
+
+    /// Release causes per trunk, route and hour, with the top failures ranked
+    pub fn release_cause_report(&self, query: &ReleaseCauseQuery) -> ReleaseCauseReport {
+        self.context.release_causes.report(query, Utc::now())
+    }
+
+    /// Registration state and next retry of each registering trunk
+    pub fn trunk_registrations(&self) -> Vec<TrunkRegistrationStatus> {
+        self.trunk_registrar.as_ref().map(|registrar| registrar.statuses()).unwrap_or_default()
+    }
+
+    /// Calls per trunk whose early media was blocked or cut off by route policy
+    pub fn early_media_counters(&self) -> Vec<EarlyMediaCounter> {
+        self.context.early_media.counters()
+    }
+
+    /// Encrypted, plain and downgraded call legs per trunk
+    pub fn media_security_counters(&self) -> Vec<MediaSecurityCounter> {
+        self.context.media_security.counters()
+    }
+
+    /// Answered calls per trunk that were relayed or needed transcoding
+    pub fn codec_negotiation_counters(&self) -> Vec<CodecNegotiationCounter> {
+        self.context.codec_negotiator.counters()
+    }
+
+    /// Free transcoding capacity and how admission is reacting to it
+    pub fn transcoding_headroom(&self) -> TranscodingHeadroom {
+        self.context.codec_negotiator.headroom()
+    }
+
+    /// Latency monitor to hand to the transcoding service, so the frames it
+    /// measures steer which fallback codecs new calls are offered
+    pub fn transcoding_latency_monitor(&self) -> Arc<TranscodingLatencyMonitor> {
+        Arc::clone(&self.transcoding_latency)
+    }
+
+    /// Per-frame transcoding latency by codec and backend
+    pub fn transcoding_latency(&self) -> Vec<TranscodingLatencyReport> {
+        self.transcoding_latency.report()
+    }
+
+    /// How the candidate routing table differs from the active one on live calls
+    pub fn shadow_routing_summary(&self) -> ShadowRoutingSummary {
+        self.context.shadow_routing.as_ref().map(|shadow| shadow.summary()).unwrap_or_default()
+    }
+
+    /// Established calls with their dialogs and relay endpoints, for the
+    /// process that replaces this one; calls still being set up are left out
+    pub async fn snapshot_calls(&self) -> CallSnapshot {
+        let connected: Vec<B2buaCall> = self
+            .calls
+            .iter()
+            .filter(|entry| entry.state == B2buaCallState::Connected)
+            .map(|entry| entry.value().clone())
+            .collect();
+
+        let sip_handler = self.context.sip_handler.read().await;
+        let rtp_handler = self.context.rtp_handler.read().await;
+        let mut calls = Vec::with_capacity(connected.len());
+        for call in connected {
+            // SRTP and DTLS keys are not written to disk, so a relay that
+            // decrypts could not resume the stream; such calls end with the process
+            let encrypted = [&call.leg_a_rtp_session_id, &call.leg_b_rtp_session_id]
+                .into_iter()
+                .flatten()
+                .any(|session_id| rtp_handler.is_encrypted(session_id));
+            if encrypted {
+                warn!("Call {} relays encrypted media and is not preserved across the upgrade", call.id);
+                continue;
+            }
+
+            let dialog = |session_id: Option<&String>| {
+                session_id
+                    .and_then(|id| sip_handler.get_session(id))
+                    .map(|session| DialogSnapshot::from(&session))
+            };
+            let leg_a_dialog = dialog(Some(&call.leg_a_session_id));
+            let leg_b_dialog = dialog(call.leg_b_session_id.as_ref());
+            let mut media = Vec::with_capacity(2);
+            for session_id in [&call.leg_a_rtp_session_id, &call.leg_b_rtp_session_id] {
+                let endpoint = match session_id.as_ref().and_then(|id| rtp_handler.get_session(id)) {
+                    Some(session) => Some(RtpEndpoint::capture(&session).await),
+                    None => None,
+                };
+                media.push(endpoint);
+            }
+            let leg_b_media = media.pop().flatten();
+            let leg_a_media = media.pop().flatten();
+
+            calls.push(SnapshotCall {
+                connected_for_ms: call.connected_at.map(|at| at.elapsed().as_millis() as u64).unwrap_or(0),
+                call,
+                leg_a_dialog,
+                leg_b_dialog,
+                leg_a_media,
+                leg_b_media,
+            });
+        }
+        CallSnapshot::new(calls)
+    }
+
+    /// Re-create calls handed over by the previous process; a call whose
+    /// relay ports cannot be rebound is dropped. Returns the calls restored
+    pub async fn restore_calls(&self, snapshot: CallSnapshot) -> usize {
+        let sip_handler = self.context.sip_handler.read().await;
+        let rtp_handler = self.context.rtp_handler.read().await;
+        let mut restored = 0;
+        for entry in snapshot.calls {
+            let mut call = entry.call;
+            let mut rebound = true;
+            for endpoint in [entry.leg_a_media, entry.leg_b_media].into_iter().flatten() {
+                if let Err(e) = rtp_handler.restore_session(endpoint.into_session()).await {
+                    warn!("Dropping restored call {}: {}", call.id, e);
+                    rebound = false;
+                    break;
+                }
+            }
+            if !rebound {
+                for session_id in [&call.leg_a_rtp_session_id, &call.leg_b_rtp_session_id].into_iter().flatten() {
+                    let _ = rtp_handler.destroy_session(session_id).await;
+                }
+                continue;
+            }
+            for dialog in [entry.leg_a_dialog, entry.leg_b_dialog].into_iter().flatten() {
+                sip_handler.restore_session(dialog.into_session());
+            }
+
+            let now = Instant::now();
+            call.created_at = now;
+            call.last_activity = now;
+            call.connected_at = Some(now.checked_sub(Duration::from_millis(entry.connected_for_ms)).unwrap_or(now));
+            info!("Restored call {} ({} -> {})", call.id, call.caller, call.callee);
+            self.context.calls.insert(call.id.clone(), call);
+            restored += 1;
+        }
+        restored
+    }
+
+    /// Snapshot established calls and restart into the configured binary
+    /// without releasing them, once it matches the requested checksum;
+    /// returns only if the upgrade could not start
+    pub async fn upgrade(&self, request: &UpgradeRequest) -> Error {
+        if !self.context.config.upgrade_assist.enabled {
+            return Error::not_supported("Upgrade assist is disabled");
+        }
+        let binary = match &self.context.config.upgrade_assist.binary {
+            Some(binary) => PathBuf::from(binary),
+            None => match std::env::current_exe() {
+                Ok(binary) => binary,
+                Err(e) => return e.into(),
+            },
+        };
+        if let Err(e) = upgrade::verify_binary(&binary, &request.sha256) {
+            return e;
+        }
+        let snapshot = self.snapshot_calls().await;
+        let snapshot_path = Path::new(&self.context.config.upgrade_assist.snapshot_path);
+        if let Err(e) = upgrade::write_snapshot(snapshot_path, &snapshot) {
+            return e;
         }
+        upgrade::exec_binary(&binary)
+    }
+
+    async fn restore_snapshot(&self) -> Result<usize> {
+        let assist = &self.context.config.upgrade_assist;
+        let max_age = Duration::from_secs(assist.max_snapshot_age_secs);
+        match upgrade::take_snapshot(Path::new(&assist.snapshot_path), max_age)? {
+            Some(snapshot) => Ok(self.restore_calls(snapshot).await),
+            None => Ok(0),
+        }
+    }
+
+    fn release_trunk_call(&self, call_id: &str, reason: String, cause: u16) {
+        self.context.codec_negotiator.release(call_id);
+        Self::release_call(&self.context, call_id, reason, Some(cause));
     }
 
     pub async fn stop(&mut self) -> Result<()> {
         info!("Stopping B2BUA service");
         
         // Terminate all active calls
-        let active_calls: Vec<String> = self.calls.iter().map(|entry| entry.key().clone()).collect();
+        let active_calls: Vec<String> = self.context.calls.iter().map(|entry| entry.key().clone()).collect();
         for call_id in active_calls {
             let _ = self.terminate_call(&call_id, "Service shutdown").await;
         }
 
-        self.calls.clear();
-        self.media_relays.clear();
+        self.context.calls.clear();
+        self.context.media_relays.clear();
         self.is_running = false;
         
         info!("B2BUA service stopped");
@@ -839,6 +4604,14 @@ mod tests {
             max_sessions: 100,
             session_timeout: 300,
             register_interval: 3600,
+            udp_size_threshold: 1300,
+            tcp_fallback: true,
+            force_rport: Vec::new(),
+            size_limits: Default::default(),
+            tls: Default::default(),
+            websocket: Default::default(),
+            registrar: Default::default(),
+            network: Default::default(),
         };
 
         let rtp_config = PortRange { min: 10000, max: 10100 };
@@ -861,7 +4634,7 @@ mod tests {
             routing_table: vec![],
         };
 
-        let service = B2buaService::new(b2bua_config, sip_handler, rtp_handler);
+        let service = B2buaService::new(b2bua_config, sip_handler, rtp_handler, None);
         assert!(service.is_ok());
     }
 
@@ -872,6 +4645,13 @@ mod tests {
         assert_eq!(user, "1234");
     }
 
+    #[test]
+    fn test_host_extraction() {
+        let host = B2buaService::extract_host_from_uri("<sip:1234@tenant.example.com:5060>");
+        assert_eq!(host, Some("tenant.example.com".to_string()));
+        assert_eq!(B2buaService::extract_host_from_uri("sip:1234"), None);
+    }
+
     #[test]
     fn test_routing_determination() {
         let config = B2buaConfig {
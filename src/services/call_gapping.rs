@@ -0,0 +1,336 @@
+//! Call gapping towards congested destinations (ITU-T Q.541 style)
+//!
+//! Downstream releases carrying congestion causes are counted per
+//! destination prefix. When a prefix crosses the trigger rate a gap is put
+//! in force: at most one call per gap interval is let through and the rest
+//! are rejected locally, giving the congested network room to recover. Gaps
+//! lapse after the configured duration unless congestion keeps re-triggering
+//! them, and the NOC can list, add or lift gaps by hand.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::CallGappingConfig;
+
+/// Management API path listing active gaps
+pub const CALL_GAPS_PATH: &str = "/api/v1/b2bua/call-gaps";
+
+/// Admission decision for a new call attempt
+#[derive(Debug, Clone, PartialEq)]
+pub enum GapDecision {
+    Admitted,
+    Gapped { prefix: String, retry_after: Duration },
+}
+
+/// A gap in force, as shown to operators
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ActiveGap {
+    pub prefix: String,
+    pub gap_interval_ms: u64,
+    pub started_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Congestion cause that triggered the gap; `None` when applied by hand
+    pub triggering_cause: Option<u16>,
+    pub calls_admitted: u64,
+    pub calls_gapped: u64,
+}
+
+/// Call gapping events
+#[derive(Debug, Clone)]
+pub enum GappingEvent {
+    GapApplied {
+        prefix: String,
+        interval: Duration,
+        duration: Duration,
+        cause: Option<u16>,
+    },
+    GapExtended {
+        prefix: String,
+        expires_at: DateTime<Utc>,
+    },
+    GapLifted {
+        prefix: String,
+        calls_gapped: u64,
+        expired: bool,
+    },
+}
+
+#[derive(Debug)]
+struct GapState {
+    info: ActiveGap,
+    interval: Duration,
+    expires: Instant,
+    next_admit: Instant,
+}
+
+/// Tracks congestion per destination prefix and enforces gaps
+pub struct CallGapController {
+    config: CallGappingConfig,
+    gaps: DashMap<String, GapState>,
+    congestion: DashMap<String, VecDeque<Instant>>,
+    event_tx: mpsc::UnboundedSender<GappingEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<GappingEvent>>,
+}
+
+impl CallGapController {
+    pub fn new(config: CallGappingConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            config,
+            gaps: DashMap::new(),
+            congestion: DashMap::new(),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<GappingEvent>> {
+        self.event_rx.take()
+    }
+
+    pub fn reject_status(&self) -> u16 {
+        self.config.reject_status
+    }
+
+    /// Destination prefix used to count congestion for a called number
+    pub fn prefix_of(&self, number: &str) -> Option<String> {
+        let prefix: String = number
+            .chars()
+            .filter(char::is_ascii_digit)
+            .take(self.config.prefix_digits)
+            .collect();
+        (!prefix.is_empty()).then_some(prefix)
+    }
+
+    /// Longest gapped prefix covering `number`
+    fn matching_gap(&self, number: &str) -> Option<String> {
+        let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+        self.gaps
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|prefix| digits.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())
+    }
+
+    /// Decide whether a call to `number` may proceed
+    pub fn admit(&self, number: &str, now: Instant) -> GapDecision {
+        let Some(prefix) = self.matching_gap(number) else {
+            return GapDecision::Admitted;
+        };
+
+        let expired = match self.gaps.get_mut(&prefix) {
+            Some(gap) if now >= gap.expires => true,
+            Some(mut gap) => {
+                if now >= gap.next_admit {
+                    gap.next_admit = now + gap.interval;
+                    gap.info.calls_admitted += 1;
+                    return GapDecision::Admitted;
+                }
+                gap.info.calls_gapped += 1;
+                return GapDecision::Gapped { retry_after: gap.next_admit - now, prefix };
+            }
+            None => false,
+        };
+        if expired {
+            self.lift(&prefix, true);
+        }
+        // A shorter gap may still cover the number
+        self.admit(number, now)
+    }
+
+    /// Count a downstream release; congestion causes may trigger a gap
+    pub fn on_release(&self, number: &str, cause: u16, now: Instant) {
+        if !self.config.congestion_causes.contains(&cause) {
+            return;
+        }
+        let Some(prefix) = self.prefix_of(number) else {
+            return;
+        };
+
+        let window = Duration::from_secs(self.config.trigger_window_secs.max(1));
+        let triggered = {
+            let mut releases = self.congestion.entry(prefix.clone()).or_default();
+            releases.push_back(now);
+            while releases.front().is_some_and(|at| now.duration_since(*at) > window) {
+                releases.pop_front();
+            }
+            if releases.len() as u32 >= self.config.trigger_count {
+                releases.clear();
+                true
+            } else {
+                false
+            }
+        };
+
+        if triggered {
+            self.apply_gap(
+                &prefix,
+                Duration::from_millis(self.config.gap_interval_ms),
+                Duration::from_secs(self.config.gap_duration_secs),
+                Some(cause),
+                now,
+            );
+        }
+    }
+
+    /// Put a gap in force, or extend the one already covering `prefix`
+    pub fn apply_gap(&self, prefix: &str, interval: Duration, duration: Duration, cause: Option<u16>, now: Instant) {
+        let expires_at = Utc::now() + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
+
+        if let Some(mut gap) = self.gaps.get_mut(prefix) {
+            gap.expires = now + duration;
+            gap.interval = interval;
+            gap.info.gap_interval_ms = interval.as_millis() as u64;
+            gap.info.expires_at = expires_at;
+            let _ = self.event_tx.send(GappingEvent::GapExtended { prefix: prefix.to_string(), expires_at });
+            return;
+        }
+
+        warn!("Applying call gap to {}: one call per {:?} for {:?} (cause {:?})", prefix, interval, duration, cause);
+        self.gaps.insert(prefix.to_string(), GapState {
+            info: ActiveGap {
+                prefix: prefix.to_string(),
+                gap_interval_ms: interval.as_millis() as u64,
+                started_at: Utc::now(),
+                expires_at,
+                triggering_cause: cause,
+                calls_admitted: 0,
+                calls_gapped: 0,
+            },
+            interval,
+            expires: now + duration,
+            next_admit: now,
+        });
+        let _ = self.event_tx.send(GappingEvent::GapApplied { prefix: prefix.to_string(), interval, duration, cause });
+    }
+
+    /// Lift a gap before it expires
+    pub fn remove_gap(&self, prefix: &str) -> bool {
+        self.lift(prefix, false)
+    }
+
+    fn lift(&self, prefix: &str, expired: bool) -> bool {
+        match self.gaps.remove(prefix) {
+            Some((_, gap)) => {
+                info!("Call gap on {} lifted after gapping {} calls", prefix, gap.info.calls_gapped);
+                let _ = self.event_tx.send(GappingEvent::GapLifted {
+                    prefix: prefix.to_string(),
+                    calls_gapped: gap.info.calls_gapped,
+                    expired,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gaps currently in force; lapsed gaps are lifted on the way
+    pub fn active_gaps(&self, now: Instant) -> Vec<ActiveGap> {
+        let expired: Vec<String> = self
+            .gaps
+            .iter()
+            .filter(|entry| now >= entry.expires)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for prefix in expired {
+            self.lift(&prefix, true);
+        }
+
+        let mut gaps: Vec<ActiveGap> = self.gaps.iter().map(|entry| entry.info.clone()).collect();
+        gaps.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> CallGapController {
+        CallGapController::new(CallGappingConfig {
+            enabled: true,
+            trigger_count: 3,
+            trigger_window_secs: 10,
+            gap_interval_ms: 1000,
+            gap_duration_secs: 30,
+            prefix_digits: 4,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_congestion_triggers_gap() {
+        let mut gapper = controller();
+        let mut events = gapper.take_event_receiver().unwrap();
+        let start = Instant::now();
+
+        // Busy is not congestion, and two congestion releases stay below the trigger
+        gapper.on_release("+1 212 555 0100", 17, start);
+        gapper.on_release("12125550100", 34, start);
+        gapper.on_release("12125550101", 42, start + Duration::from_secs(1));
+        assert_eq!(gapper.admit("12125550199", start), GapDecision::Admitted);
+        assert!(events.try_recv().is_err());
+
+        gapper.on_release("12125550102", 42, start + Duration::from_secs(2));
+        assert!(matches!(events.try_recv().unwrap(), GappingEvent::GapApplied { cause: Some(42), .. }));
+
+        let now = start + Duration::from_secs(3);
+        assert_eq!(gapper.admit("12125550199", now), GapDecision::Admitted);
+        match gapper.admit("12125550198", now + Duration::from_millis(200)) {
+            GapDecision::Gapped { prefix, retry_after } => {
+                assert_eq!(prefix, "1212");
+                assert_eq!(retry_after, Duration::from_millis(800));
+            }
+            other => panic!("unexpected decision {:?}", other),
+        }
+        // Other destinations are unaffected
+        assert_eq!(gapper.admit("14155550100", now), GapDecision::Admitted);
+        assert_eq!(gapper.admit("12125550197", now + Duration::from_secs(1)), GapDecision::Admitted);
+
+        let gaps = gapper.active_gaps(now);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].calls_admitted, 2);
+        assert_eq!(gaps[0].calls_gapped, 1);
+        assert_eq!(gaps[0].triggering_cause, Some(42));
+    }
+
+    #[test]
+    fn test_gaps_expire_and_can_be_managed_by_hand() {
+        let mut gapper = controller();
+        let mut events = gapper.take_event_receiver().unwrap();
+        let start = Instant::now();
+
+        gapper.apply_gap("44", Duration::from_secs(5), Duration::from_secs(60), None, start);
+        gapper.apply_gap("4420", Duration::from_secs(10), Duration::from_secs(10), None, start);
+        assert_eq!(gapper.admit("442071234567", start), GapDecision::Admitted);
+        // The longer prefix governs while it lasts
+        assert!(matches!(
+            gapper.admit("442071234568", start + Duration::from_secs(6)),
+            GapDecision::Gapped { ref prefix, .. } if prefix == "4420"
+        ));
+
+        // After the specific gap lapses the broader one applies
+        let later = start + Duration::from_secs(11);
+        assert_eq!(gapper.admit("442071234569", later), GapDecision::Admitted);
+        assert_eq!(gapper.active_gaps(later).len(), 1);
+
+        assert!(gapper.remove_gap("44"));
+        assert!(!gapper.remove_gap("44"));
+        assert!(gapper.active_gaps(later).is_empty());
+
+        let lifted: Vec<bool> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                GappingEvent::GapLifted { expired, .. } => Some(expired),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lifted, vec![true, false]);
+    }
+}
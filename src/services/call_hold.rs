@@ -0,0 +1,226 @@
+//! Call hold and resume
+//!
+//! A party holds the call with a re-INVITE whose audio is sendonly or
+//! inactive (or, from older endpoints, addressed to 0.0.0.0), and resumes it
+//! with one that is sendrecv again. On a PRI the same requests arrive as
+//! Q.932 HOLD and RETRIEVE, which are acknowledged or rejected on the
+//! D-channel. With a music on hold file configured, the gateway keeps the
+//! hold to itself and loops the music into the held party's media through
+//! the relay; without one the hold is passed on to the other leg as a
+//! re-INVITE, or to the span as HOLD/RETRIEVE.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tracing::{debug, info};
+
+use crate::config::CallHoldConfig;
+use crate::protocols::q931::{MessageType, Q931Message};
+use crate::protocols::rtp::RtpHandler;
+use crate::protocols::sdp::SessionDescription;
+use crate::services::b2bua::CallLeg;
+use crate::services::prompts::read_pcmu;
+use crate::{Error, Result};
+
+/// Music is sent as PCMU only, like the gateway's other local media
+pub const MUSIC_PAYLOAD_TYPE: u8 = 0;
+
+const PACKET_INTERVAL: Duration = Duration::from_millis(20);
+const SAMPLES_PER_PACKET: usize = 160;
+
+/// Whether an offer puts the call on hold: every active audio stream is
+/// sendonly or inactive, or the session is addressed to 0.0.0.0 (RFC 2543)
+pub fn is_hold_offer(sdp: &SessionDescription) -> bool {
+    let mut streams = sdp.audio_streams().filter(|stream| !stream.is_rejected()).peekable();
+    if streams.peek().is_none() {
+        return false;
+    }
+    sdp.connection_address() == Some("0.0.0.0") || streams.all(|stream| matches!(stream.direction(), "sendonly" | "inactive"))
+}
+
+/// Q.932 message passing a hold or resume on to a span
+pub fn tdm_message(on_hold: bool) -> MessageType {
+    if on_hold {
+        MessageType::Hold
+    } else {
+        MessageType::Retrieve
+    }
+}
+
+/// Reply to a HOLD or RETRIEVE from a span: the acknowledgement, or the
+/// rejection with `cause` when the request was refused
+pub fn tdm_reply(request: &Q931Message, refused: Option<u8>) -> Option<Q931Message> {
+    let message_type = match (request.message_type, refused) {
+        (MessageType::Hold, None) => MessageType::HoldAcknowledge,
+        (MessageType::Hold, Some(_)) => MessageType::HoldReject,
+        (MessageType::Retrieve, None) => MessageType::RetrieveAcknowledge,
+        (MessageType::Retrieve, Some(_)) => MessageType::RetrieveReject,
+        _ => return None,
+    };
+    let reply = Q931Message::new(message_type, request.call_reference, !request.from_destination);
+    Some(match refused {
+        Some(cause) => reply.with_cause(cause),
+        None => reply,
+    })
+}
+
+/// The `index`th packet of looped music
+fn music_frame(music: &[u8], index: usize) -> Bytes {
+    let start = index * SAMPLES_PER_PACKET % music.len();
+    let frame: Vec<u8> = music.iter().cycle().skip(start).take(SAMPLES_PER_PACKET).copied().collect();
+    Bytes::from(frame)
+}
+
+/// How a hold was put into effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoldTreatment {
+    /// The held party hears music from the gateway
+    Music,
+    /// The hold was passed on to the other leg
+    Relayed,
+}
+
+struct HeldCall {
+    held_by: CallLeg,
+    treatment: HoldTreatment,
+    since: Instant,
+    player: Option<JoinHandle<()>>,
+}
+
+/// Calls on hold and the music playing to them
+pub struct CallHolds {
+    /// Music on hold as PCMU samples
+    music: Option<Arc<Vec<u8>>>,
+    held: DashMap<String, HeldCall>,
+}
+
+impl CallHolds {
+    pub fn new(config: &CallHoldConfig) -> Result<Self> {
+        let music = match &config.music_on_hold {
+            Some(path) => Some(Arc::new(Self::load_music(path)?)),
+            None => None,
+        };
+        Ok(Self { music, held: DashMap::new() })
+    }
+
+    fn load_music(path: &Path) -> Result<Vec<u8>> {
+        let music = read_pcmu(path)?;
+        if music.is_empty() {
+            return Err(Error::invalid_config(format!("Music on hold file {} has no audio", path.display())));
+        }
+        info!("Loaded {}s of music on hold from {}", music.len() / 8000, path.display());
+        Ok(music)
+    }
+
+    pub fn has_music(&self) -> bool {
+        self.music.is_some()
+    }
+
+    /// The party holding `call_id`, if it is held
+    pub fn held_by(&self, call_id: &str) -> Option<CallLeg> {
+        self.held.get(call_id).map(|held| held.held_by)
+    }
+
+    /// Record `call_id` as held by `leg`; false if it already is held
+    pub fn hold(&self, call_id: &str, leg: CallLeg, treatment: HoldTreatment) -> bool {
+        if self.held.contains_key(call_id) {
+            return false;
+        }
+        self.held.insert(call_id.to_string(), HeldCall { held_by: leg, treatment, since: Instant::now(), player: None });
+        info!("Call {} held by leg {:?} ({:?})", call_id, leg, treatment);
+        true
+    }
+
+    /// Loop the music into `rtp_session`, the relay session facing the held
+    /// party, until the call is resumed or the session goes away
+    pub fn play_music(&self, call_id: &str, rtp_handler: Arc<RwLock<RtpHandler>>, rtp_session: String) {
+        let (Some(music), Some(mut held)) = (self.music.clone(), self.held.get_mut(call_id)) else {
+            return;
+        };
+        let call_id = call_id.to_string();
+        held.player = Some(tokio::spawn(async move {
+            let mut ticker = interval(PACKET_INTERVAL);
+            for index in 0.. {
+                ticker.tick().await;
+                let offset = (index * SAMPLES_PER_PACKET) as u32;
+                let sent = rtp_handler
+                    .read()
+                    .await
+                    .send_packet(&rtp_session, music_frame(&music, index), offset, index == 0)
+                    .await;
+                if let Err(e) = sent {
+                    debug!("Music on hold for call {} stopped: {}", call_id, e);
+                    break;
+                }
+            }
+        }));
+    }
+
+    /// Take `call_id` off hold if `leg` is the party holding it, stopping
+    /// any music, and return how the hold had been treated
+    pub fn resume(&self, call_id: &str, leg: CallLeg) -> Option<HoldTreatment> {
+        let (_, held) = self.held.remove_if(call_id, |_, held| held.held_by == leg)?;
+        if let Some(player) = held.player {
+            player.abort();
+        }
+        info!("Call {} resumed by leg {:?} after {:?}", call_id, leg, held.since.elapsed());
+        Some(held.treatment)
+    }
+
+    /// Forget a call that has ended
+    pub fn release(&self, call_id: &str) {
+        if let Some((_, held)) = self.held.remove(call_id) {
+            if let Some(player) = held.player {
+                player.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::q931::{CAUSE_WRONG_CALL_STATE, IE_CAUSE};
+
+    const OFFER: &str = "v=0\r\no=- 1 2 IN IP4 192.0.2.10\r\ns=-\r\nc=IN IP4 192.0.2.10\r\nt=0 0\r\n\
+        m=audio 4000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+
+    #[test]
+    fn test_hold_offers_and_tdm_mapping() {
+        assert!(!is_hold_offer(&SessionDescription::parse(OFFER).unwrap()));
+        let sendonly = OFFER.replace("a=rtpmap", "a=sendonly\r\na=rtpmap");
+        assert!(is_hold_offer(&SessionDescription::parse(&sendonly).unwrap()));
+        let zeroed = OFFER.replace("c=IN IP4 192.0.2.10", "c=IN IP4 0.0.0.0");
+        assert!(is_hold_offer(&SessionDescription::parse(&zeroed).unwrap()));
+
+        let hold = Q931Message::new(MessageType::Hold, 12, false);
+        let ack = tdm_reply(&hold, None).unwrap();
+        assert_eq!((ack.message_type, ack.call_reference, ack.from_destination), (MessageType::HoldAcknowledge, 12, true));
+        let reject = tdm_reply(&Q931Message::new(MessageType::Retrieve, 12, false), Some(CAUSE_WRONG_CALL_STATE)).unwrap();
+        assert_eq!(reject.message_type, MessageType::RetrieveReject);
+        assert!(reject.ie(IE_CAUSE).is_some());
+        assert!(tdm_reply(&Q931Message::new(MessageType::Setup, 12, false), None).is_none());
+    }
+
+    #[test]
+    fn test_hold_and_resume() {
+        let holds = CallHolds::new(&CallHoldConfig { enabled: true, music_on_hold: None }).unwrap();
+        assert!(holds.hold("call-1", CallLeg::A, HoldTreatment::Relayed));
+        assert!(!holds.hold("call-1", CallLeg::B, HoldTreatment::Relayed));
+        // Only the holding party can resume
+        assert_eq!(holds.resume("call-1", CallLeg::B), None);
+        assert_eq!(holds.resume("call-1", CallLeg::A), Some(HoldTreatment::Relayed));
+        assert_eq!(holds.held_by("call-1"), None);
+
+        let music: Vec<u8> = (0..200).collect();
+        assert_eq!(music_frame(&music, 0)[..], music[..160]);
+        let wrapped = music_frame(&music, 1);
+        assert_eq!((wrapped[0], wrapped[39], wrapped[40]), (160, 199, 0));
+    }
+}
@@ -0,0 +1,268 @@
+//! Per-call debug tracing
+//!
+//! A number, trunk, live Call-ID or correlation ID can be marked as traced. Signaling,
+//! media and transcoding then log every step of the matching calls at trace
+//! level under the `call_trace` target, which the logger lets through
+//! whatever the global level, and collect the same records into the call's
+//! trace. Other calls log as usual, so one problem call can be followed in
+//! production without turning on debug logging for everything.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use dashmap::{DashMap, DashSet};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::CallTraceConfig;
+
+/// Log target of per-call trace records
+pub const CALL_TRACE_TARGET: &str = "call_trace";
+
+/// Management API path listing trace selectors
+pub const CALL_TRACE_PATH: &str = "/api/v1/b2bua/traces";
+
+/// Management API path for the trace of `call_id`
+pub fn call_trace_path(call_id: &str) -> String {
+    format!("{}/calls/{}", CALL_TRACE_PATH, call_id)
+}
+
+/// What marks a call as traced
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum TraceSelector {
+    /// Calling or called number
+    Number(String),
+    /// Route target the call leaves on
+    Trunk(String),
+    CallId(String),
+    /// Correlation ID shared by a call's legs
+    CorrelationId(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceSubsystem {
+    Signaling,
+    Media,
+    Transcoding,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TraceRecord {
+    pub timestamp: DateTime<Utc>,
+    pub subsystem: TraceSubsystem,
+    pub message: String,
+}
+
+/// Everything logged for one traced call
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallTrace {
+    pub call_id: String,
+    #[serde(default)]
+    pub correlation_id: String,
+    /// Selector that marked the call
+    pub selector: TraceSelector,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub records: Vec<TraceRecord>,
+    /// Records beyond the per-call limit
+    pub dropped: usize,
+}
+
+/// Decides which calls are traced and collects their records
+pub struct CallTracer {
+    config: CallTraceConfig,
+    selectors: DashSet<TraceSelector>,
+    active: DashMap<String, CallTrace>,
+    completed: Mutex<VecDeque<CallTrace>>,
+}
+
+impl CallTracer {
+    pub fn new(config: CallTraceConfig) -> Self {
+        let selectors = DashSet::new();
+        for number in &config.numbers {
+            selectors.insert(TraceSelector::Number(number.clone()));
+        }
+        for trunk in &config.trunks {
+            selectors.insert(TraceSelector::Trunk(trunk.clone()));
+        }
+        Self {
+            config,
+            selectors,
+            active: DashMap::new(),
+            completed: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn add_selector(&self, selector: TraceSelector) {
+        info!("Call tracing enabled for {:?}", selector);
+        self.selectors.insert(selector);
+    }
+
+    pub fn remove_selector(&self, selector: &TraceSelector) -> bool {
+        self.selectors.remove(selector).is_some()
+    }
+
+    pub fn selectors(&self) -> Vec<TraceSelector> {
+        self.selectors.iter().map(|selector| selector.key().clone()).collect()
+    }
+
+    /// Start tracing a new call when a selector matches it
+    pub fn begin_call(&self, call_id: &str, correlation_id: &str, caller: &str, callee: &str, trunk: Option<&str>) -> bool {
+        let matched = self.selectors.iter().find(|selector| match selector.key() {
+            TraceSelector::Number(number) => number == caller || number == callee,
+            TraceSelector::Trunk(name) => trunk == Some(name.as_str()),
+            TraceSelector::CallId(id) => id == call_id,
+            TraceSelector::CorrelationId(id) => id == correlation_id,
+        });
+        match matched {
+            Some(selector) => {
+                self.start(call_id, correlation_id, selector.key().clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start tracing a call already in progress, marked by `selector`
+    pub fn trace_live_call(&self, call_id: &str, correlation_id: &str, selector: TraceSelector) {
+        if !self.active.contains_key(call_id) {
+            self.start(call_id, correlation_id, selector);
+        }
+    }
+
+    pub fn is_traced(&self, call_id: &str) -> bool {
+        self.active.contains_key(call_id)
+    }
+
+    /// Log and keep a record for `call_id` if it is traced; `message` is
+    /// only built for traced calls
+    pub fn record(&self, call_id: &str, subsystem: TraceSubsystem, message: impl FnOnce() -> String) {
+        let Some(mut trace) = self.active.get_mut(call_id) else {
+            return;
+        };
+        let message = message();
+        tracing::trace!(target: CALL_TRACE_TARGET, call_id, correlation_id = trace.correlation_id.as_str(), ?subsystem, "{}", message);
+        if trace.records.len() < self.config.max_records_per_call {
+            trace.records.push(TraceRecord { timestamp: Utc::now(), subsystem, message });
+        } else {
+            trace.dropped += 1;
+        }
+    }
+
+    /// Close the trace of a released call and keep it for retrieval
+    pub fn end_call(&self, call_id: &str) {
+        let Some((_, mut trace)) = self.active.remove(call_id) else {
+            return;
+        };
+        trace.finished_at = Some(Utc::now());
+        if let Ok(mut completed) = self.completed.lock() {
+            completed.push_back(trace);
+            while completed.len() > self.config.max_completed {
+                completed.pop_front();
+            }
+        }
+    }
+
+    /// Close traces of calls that `is_live` no longer knows about
+    pub fn end_released(&self, is_live: impl Fn(&str) -> bool) {
+        let released: Vec<String> = self
+            .active
+            .iter()
+            .filter(|entry| !is_live(entry.key()))
+            .map(|entry| entry.key().clone())
+            .collect();
+        for call_id in released {
+            self.end_call(&call_id);
+        }
+    }
+
+    /// Trace of a live or recently finished call, by Call-ID or correlation ID
+    pub fn trace(&self, id: &str) -> Option<CallTrace> {
+        if let Some(trace) = self.active.get(id) {
+            return Some(trace.clone());
+        }
+        if let Some(trace) = self.active.iter().find(|trace| trace.correlation_id == id) {
+            return Some(trace.clone());
+        }
+        let completed = self.completed.lock().ok()?;
+        completed.iter().rev().find(|trace| trace.call_id == id || trace.correlation_id == id).cloned()
+    }
+
+    fn start(&self, call_id: &str, correlation_id: &str, selector: TraceSelector) {
+        info!(correlation_id, "Tracing call {} ({:?})", call_id, selector);
+        self.active.insert(call_id.to_string(), CallTrace {
+            call_id: call_id.to_string(),
+            correlation_id: correlation_id.to_string(),
+            selector,
+            started_at: Utc::now(),
+            finished_at: None,
+            records: Vec::new(),
+            dropped: 0,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracer() -> CallTracer {
+        CallTracer::new(CallTraceConfig {
+            numbers: vec!["5551234".to_string()],
+            trunks: vec!["carrier-b".to_string()],
+            max_records_per_call: 2,
+            max_completed: 1,
+        })
+    }
+
+    #[test]
+    fn test_selectors_pick_traced_calls() {
+        let tracer = tracer();
+        assert!(tracer.begin_call("c1", "x1", "5551234", "911", None));
+        assert!(tracer.begin_call("c2", "x2", "100", "200", Some("carrier-b")));
+        assert!(!tracer.begin_call("c3", "x3", "100", "200", Some("carrier-a")));
+
+        tracer.add_selector(TraceSelector::CallId("c4".to_string()));
+        assert!(tracer.begin_call("c4", "x4", "100", "200", None));
+        tracer.trace_live_call("c3", "x3", TraceSelector::CallId("c3".to_string()));
+        assert!(tracer.is_traced("c3"));
+        assert_eq!(tracer.trace("c3").unwrap().selector, TraceSelector::CallId("c3".to_string()));
+
+        assert!(tracer.remove_selector(&TraceSelector::Number("5551234".to_string())));
+        assert!(!tracer.begin_call("c5", "x5", "5551234", "911", None));
+
+        // A correlation ID finds the call on this gateway, whatever its Call-ID
+        tracer.add_selector(TraceSelector::CorrelationId("upstream-9".to_string()));
+        assert!(tracer.begin_call("c6", "upstream-9", "100", "200", None));
+        assert_eq!(tracer.trace("upstream-9").unwrap().call_id, "c6");
+    }
+
+    #[test]
+    fn test_records_are_collected_only_for_traced_calls() {
+        let tracer = tracer();
+        tracer.begin_call("c1", "x1", "5551234", "911", None);
+        for subsystem in [TraceSubsystem::Signaling, TraceSubsystem::Media, TraceSubsystem::Transcoding] {
+            tracer.record("c1", subsystem, || format!("{:?} step", subsystem));
+        }
+        tracer.record("other", TraceSubsystem::Signaling, || unreachable!("untraced calls build no message"));
+
+        let trace = tracer.trace("c1").unwrap();
+        assert_eq!(trace.records.len(), 2);
+        assert_eq!(trace.records[1].subsystem, TraceSubsystem::Media);
+        assert_eq!(trace.dropped, 1);
+
+        tracer.begin_call("c2", "x2", "5551234", "911", None);
+        tracer.end_released(|call_id| call_id == "c2");
+        assert!(!tracer.is_traced("c1"));
+        assert!(tracer.trace("c1").unwrap().finished_at.is_some());
+
+        // Only the newest finished trace is kept
+        tracer.end_call("c2");
+        assert!(tracer.trace("c1").is_none());
+        assert!(tracer.trace("c2").is_some());
+    }
+}
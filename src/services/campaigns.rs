@@ -0,0 +1,704 @@
+//! Outbound notification campaigns
+//!
+//! A campaign is a batch of numbers to call and a prompt to play each one
+//! that answers: a site evacuation notice, a maintenance window, a service
+//! outage. Calls go out round-robin over the campaign's trunks at the
+//! campaign's calls per second, within the gateway-wide limit on calls up at
+//! once. An answered call hears the prompt once and is hung up. Busy,
+//! unanswered and failed calls are tried again after the retry delay, up to
+//! the configured attempts, each retry on the next trunk. A campaign can be
+//! scheduled to start later; cancelling it stops new attempts and lets calls
+//! already up finish. The outcome of every destination is kept for the
+//! management API until the campaign ages out.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{interval, Instant, Interval, MissedTickBehavior};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::{CampaignConfig, CampaignTrunk};
+use crate::protocols::rtp::RtpPacket;
+use crate::protocols::sdp::SessionDescription;
+use crate::services::canary::{dialog_target, header, host_of, new_tag, SipExchange};
+use crate::services::prompts::{is_event_name, read_pcmu};
+use crate::services::survivability::RegisterReply;
+use crate::{Error, Result};
+
+/// Campaigns: GET lists them, POST submits a [`CampaignRequest`]
+pub const CAMPAIGNS_PATH: &str = "/api/v1/campaigns";
+
+const PCMU: u8 = 0;
+const PACKET_INTERVAL: Duration = Duration::from_millis(20);
+const SAMPLES_PER_PACKET: usize = 160;
+/// Wait for the far end to confirm a CANCEL or BYE
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One campaign: GET shows every destination, DELETE cancels it
+pub fn campaign_path(id: &str) -> String {
+    format!("{}/{}", CAMPAIGNS_PATH, id)
+}
+
+/// A campaign as submitted
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CampaignRequest {
+    pub name: String,
+    /// Numbers to call, digits with an optional leading `+`
+    pub destinations: Vec<String>,
+    /// Prompt file in the campaign prompt directory, without `.wav`
+    pub prompt: String,
+    /// Trunks to call over; all configured trunks when empty
+    #[serde(default)]
+    pub trunks: Vec<String>,
+    /// Call attempts per second; the configured default when absent
+    pub cps: Option<f64>,
+    /// When to start; at once when absent
+    pub start_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignStatus {
+    /// Waiting for its start time
+    Scheduled,
+    Running,
+    Completed,
+    Cancelled,
+}
+
+/// Outcome of a destination's latest attempt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CallOutcome {
+    Pending,
+    Calling,
+    Answered { played_ms: u64 },
+    Busy,
+    NoAnswer,
+    Rejected { status_code: u16, reason: String },
+    Failed { reason: String },
+    /// Never called because the campaign was cancelled
+    Cancelled,
+}
+
+impl CallOutcome {
+    pub fn from_result(result: Result<DialResult>) -> Self {
+        match result {
+            Ok(dial) => match dial.status_code {
+                200..=299 => CallOutcome::Answered { played_ms: dial.played_ms },
+                486 | 600 => CallOutcome::Busy,
+                408 | 480 | 487 => CallOutcome::NoAnswer,
+                status_code => CallOutcome::Rejected { status_code, reason: dial.reason },
+            },
+            Err(e) => CallOutcome::Failed { reason: e.to_string() },
+        }
+    }
+
+    /// Whether another attempt may reach the destination; a 4xx or 6xx
+    /// rejection will not change by calling again
+    pub fn retryable(&self) -> bool {
+        match self {
+            CallOutcome::Busy | CallOutcome::NoAnswer | CallOutcome::Failed { .. } => true,
+            CallOutcome::Rejected { status_code, .. } => (500..600).contains(status_code),
+            _ => false,
+        }
+    }
+}
+
+/// One destination of a campaign
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CampaignCall {
+    pub destination: String,
+    pub outcome: CallOutcome,
+    pub attempts: u32,
+    /// Trunk of the latest attempt
+    pub trunk: Option<String>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Campaign {
+    pub id: String,
+    pub name: String,
+    pub prompt: String,
+    pub trunks: Vec<String>,
+    pub cps: f64,
+    pub status: CampaignStatus,
+    pub created_at: DateTime<Utc>,
+    pub start_at: Option<DateTime<Utc>>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub calls: Vec<CampaignCall>,
+}
+
+impl Campaign {
+    pub fn summary(&self) -> CampaignSummary {
+        let count = |is: fn(&CallOutcome) -> bool| self.calls.iter().filter(|call| is(&call.outcome)).count();
+        CampaignSummary {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            status: self.status,
+            created_at: self.created_at,
+            finished_at: self.finished_at,
+            destinations: self.calls.len(),
+            pending: count(|outcome| matches!(outcome, CallOutcome::Pending | CallOutcome::Calling)),
+            answered: count(|outcome| matches!(outcome, CallOutcome::Answered { .. })),
+            busy: count(|outcome| matches!(outcome, CallOutcome::Busy)),
+            no_answer: count(|outcome| matches!(outcome, CallOutcome::NoAnswer)),
+            rejected: count(|outcome| matches!(outcome, CallOutcome::Rejected { .. })),
+            failed: count(|outcome| matches!(outcome, CallOutcome::Failed { .. })),
+            cancelled: count(|outcome| matches!(outcome, CallOutcome::Cancelled)),
+        }
+    }
+}
+
+/// A campaign's progress without its destinations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CampaignSummary {
+    pub id: String,
+    pub name: String,
+    pub status: CampaignStatus,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub destinations: usize,
+    /// Not yet called, or being called
+    pub pending: usize,
+    pub answered: usize,
+    pub busy: usize,
+    pub no_answer: usize,
+    pub rejected: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+}
+
+/// Final response to a campaign call and how much of the prompt was played
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialResult {
+    pub status_code: u16,
+    pub reason: String,
+    pub played_ms: u64,
+}
+
+/// Places one notification call
+#[async_trait]
+pub trait CampaignDialer: Send + Sync {
+    /// Call `destination` over `trunk`, play `prompt` (mu-law) once if it
+    /// answers within `ring_timeout`, then hang up
+    async fn call(&self, trunk: &CampaignTrunk, destination: &str, prompt: &[u8], ring_timeout: Duration)
+        -> Result<DialResult>;
+}
+
+/// Dialer speaking SIP and RTP over UDP to the trunk's proxy
+pub struct UdpCampaignDialer;
+
+impl UdpCampaignDialer {
+    /// Final response to the INVITE, skipping the 200 answering a CANCEL
+    async fn invite_response(exchange: &SipExchange, deadline: Instant) -> Result<(RegisterReply, String)> {
+        loop {
+            let (reply, body) = exchange.final_response(deadline).await?;
+            if header(&reply, "CSeq").is_some_and(|cseq| cseq.trim_end().ends_with("INVITE")) {
+                return Ok((reply, body));
+            }
+        }
+    }
+
+    /// Send `prompt` to `remote` in real time, returning the milliseconds sent
+    async fn play(socket: &UdpSocket, remote: SocketAddr, prompt: &[u8]) -> Result<u64> {
+        let ssrc = rand::random();
+        let mut sequence: u16 = rand::random();
+        let mut timestamp: u32 = rand::random();
+        let mut ticker = interval(PACKET_INTERVAL);
+        for chunk in prompt.chunks(SAMPLES_PER_PACKET) {
+            ticker.tick().await;
+            let mut packet = RtpPacket::new(PCMU, sequence, timestamp, ssrc);
+            packet.payload = Bytes::copy_from_slice(chunk);
+            socket
+                .send_to(&packet.encode(), remote)
+                .await
+                .map_err(|e| Error::network(format!("Campaign RTP to {} failed: {}", remote, e)))?;
+            sequence = sequence.wrapping_add(1);
+            timestamp = timestamp.wrapping_add(chunk.len() as u32);
+        }
+        // 8 samples per millisecond
+        Ok(prompt.len() as u64 / 8)
+    }
+}
+
+#[async_trait]
+impl CampaignDialer for UdpCampaignDialer {
+    async fn call(
+        &self,
+        trunk: &CampaignTrunk,
+        destination: &str,
+        prompt: &[u8],
+        ring_timeout: Duration,
+    ) -> Result<DialResult> {
+        let exchange = SipExchange::connect(&trunk.proxy).await?;
+        let ip = exchange.local.ip();
+        let rtp = UdpSocket::bind((ip, 0))
+            .await
+            .map_err(|e| Error::network(format!("Campaign RTP socket failed: {}", e)))?;
+        let rtp_port = rtp.local_addr().map_err(|e| Error::network(e.to_string()))?.port();
+        let sdp = format!(
+            "v=0\r\no=campaign {} 1 IN IP4 {}\r\ns=notification\r\nc=IN IP4 {}\r\nt=0 0\r\n\
+             m=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=ptime:20\r\na=sendonly\r\n",
+            rand::random::<u32>(),
+            ip,
+            ip,
+            rtp_port
+        );
+
+        let domain = host_of(&trunk.proxy);
+        let uri = format!("sip:{}@{}", destination, domain);
+        let from = format!("<sip:{}@{}>;tag={}", trunk.caller_id, domain, new_tag());
+        let call_id = format!("{:x}@{}", rand::random::<u64>(), ip);
+        let invite_branch = SipExchange::new_branch();
+        let headers = vec![
+            ("From".to_string(), from.clone()),
+            ("To".to_string(), format!("<{}>", uri)),
+            ("Call-ID".to_string(), call_id.clone()),
+            ("CSeq".to_string(), "1 INVITE".to_string()),
+            ("Contact".to_string(), format!("<sip:{}@{}>", trunk.caller_id, exchange.local)),
+        ];
+        exchange.send("INVITE", &uri, &invite_branch, &headers, Some(&sdp)).await?;
+
+        let (reply, body) = match Self::invite_response(&exchange, Instant::now() + ring_timeout).await {
+            Err(Error::Timeout(_)) => {
+                // CANCEL belongs to the INVITE transaction; the INVITE then
+                // ends with 487, or with a 200 if the answer crossed it
+                let mut cancel = headers[..3].to_vec();
+                cancel.push(("CSeq".to_string(), "1 CANCEL".to_string()));
+                exchange.send("CANCEL", &uri, &invite_branch, &cancel, None).await?;
+                match Self::invite_response(&exchange, Instant::now() + CLOSE_TIMEOUT).await {
+                    Err(Error::Timeout(_)) => {
+                        return Ok(DialResult { status_code: 408, reason: "Request Timeout".to_string(), played_ms: 0 })
+                    }
+                    other => other?,
+                }
+            }
+            other => other?,
+        };
+
+        let to = header(&reply, "To").unwrap_or_default().to_string();
+        let dialog_headers = |cseq: &str| {
+            vec![
+                ("From".to_string(), from.clone()),
+                ("To".to_string(), to.clone()),
+                ("Call-ID".to_string(), call_id.clone()),
+                ("CSeq".to_string(), cseq.to_string()),
+            ]
+        };
+        if reply.status_code >= 300 {
+            exchange.send("ACK", &uri, &invite_branch, &dialog_headers("1 ACK"), None).await?;
+            return Ok(DialResult { status_code: reply.status_code, reason: reply.reason, played_ms: 0 });
+        }
+
+        let target = dialog_target(&reply, &uri);
+        exchange.send("ACK", &target, &SipExchange::new_branch(), &dialog_headers("1 ACK"), None).await?;
+        let remote = SessionDescription::parse(&body).ok().and_then(|sdp| sdp.audio_endpoint());
+        let played = match remote {
+            Some(remote) => Self::play(&rtp, remote, prompt).await,
+            None => Err(Error::protocol(format!("Answer from {} has no audio endpoint", destination))),
+        };
+
+        exchange.send("BYE", &target, &SipExchange::new_branch(), &dialog_headers("2 BYE"), None).await?;
+        if let Err(e) = exchange.final_response(Instant::now() + CLOSE_TIMEOUT).await {
+            warn!("Campaign call to {} not confirmed closed: {}", destination, e);
+        }
+        Ok(DialResult { status_code: reply.status_code, reason: reply.reason, played_ms: played? })
+    }
+}
+
+/// Whether `number` can be dialled: digits with an optional leading `+`
+fn is_dialable(number: &str) -> bool {
+    let digits = number.strip_prefix('+').unwrap_or(number);
+    (1..=32).contains(&digits.len()) && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Runs campaigns and keeps their outcomes
+pub struct CampaignScheduler {
+    config: CampaignConfig,
+    dialer: Arc<dyn CampaignDialer>,
+    campaigns: DashMap<String, Arc<Mutex<Campaign>>>,
+    /// One permit per call up, shared by all campaigns
+    calls: Arc<Semaphore>,
+}
+
+impl CampaignScheduler {
+    pub fn new(config: CampaignConfig, dialer: Arc<dyn CampaignDialer>) -> Self {
+        let calls = Arc::new(Semaphore::new(config.max_concurrent_calls));
+        Self { config, dialer, campaigns: DashMap::new(), calls }
+    }
+
+    /// Check a campaign, load its prompt and start or schedule it
+    pub fn submit(self: &Arc<Self>, request: CampaignRequest) -> Result<Campaign> {
+        let invalid = |reason: String| Error::parse(format!("Campaign {}: {}", request.name, reason));
+        if request.name.trim().is_empty() {
+            return Err(Error::parse("Campaign needs a name"));
+        }
+        let max_destinations = self.config.max_destinations;
+        if request.destinations.is_empty() || request.destinations.len() > max_destinations {
+            return Err(invalid(format!("needs between 1 and {} destinations", max_destinations)));
+        }
+        if let Some(number) = request.destinations.iter().find(|number| !is_dialable(number)) {
+            return Err(invalid(format!("{} is not a dialable number", number)));
+        }
+        let cps = request.cps.unwrap_or(self.config.default_cps);
+        if !(cps > 0.0 && cps <= self.config.max_cps) {
+            return Err(invalid(format!("calls per second must be positive and at most {}", self.config.max_cps)));
+        }
+        let trunks: Vec<CampaignTrunk> = if request.trunks.is_empty() {
+            self.config.trunks.clone()
+        } else {
+            request
+                .trunks
+                .iter()
+                .map(|name| {
+                    let trunk = self.config.trunks.iter().find(|trunk| &trunk.name == name);
+                    trunk.cloned().ok_or_else(|| invalid(format!("no campaign trunk {}", name)))
+                })
+                .collect::<Result<_>>()?
+        };
+        if !is_event_name(&request.prompt) {
+            return Err(invalid(format!("{} is not a prompt name", request.prompt)));
+        }
+        let prompt: Arc<[u8]> =
+            read_pcmu(&self.config.prompt_directory.join(format!("{}.wav", request.prompt)))?.into();
+
+        let campaign = Campaign {
+            id: Uuid::new_v4().to_string(),
+            name: request.name,
+            prompt: request.prompt,
+            trunks: trunks.iter().map(|trunk| trunk.name.clone()).collect(),
+            cps,
+            status: CampaignStatus::Scheduled,
+            created_at: Utc::now(),
+            start_at: request.start_at,
+            started_at: None,
+            finished_at: None,
+            calls: request
+                .destinations
+                .into_iter()
+                .map(|destination| CampaignCall {
+                    destination,
+                    outcome: CallOutcome::Pending,
+                    attempts: 0,
+                    trunk: None,
+                    last_attempt_at: None,
+                })
+                .collect(),
+        };
+        info!(
+            "Campaign {} ({}) submitted: {} destinations over {}",
+            campaign.id,
+            campaign.name,
+            campaign.calls.len(),
+            campaign.trunks.join(", ")
+        );
+        let shared = Arc::new(Mutex::new(campaign.clone()));
+        self.campaigns.insert(campaign.id.clone(), Arc::clone(&shared));
+        tokio::spawn(Arc::clone(self).run(shared, trunks, prompt));
+        Ok(campaign)
+    }
+
+    /// Wait for the start time, then call every destination
+    async fn run(self: Arc<Self>, campaign: Arc<Mutex<Campaign>>, trunks: Vec<CampaignTrunk>, prompt: Arc<[u8]>) {
+        let (id, start_at, cps, destinations) = match campaign.lock() {
+            Ok(campaign) => (campaign.id.clone(), campaign.start_at, campaign.cps, campaign.calls.len()),
+            Err(_) => return,
+        };
+        if let Some(wait) = start_at.and_then(|at| (at - Utc::now()).to_std().ok()) {
+            tokio::time::sleep(wait).await;
+        }
+        if let Ok(mut campaign) = campaign.lock() {
+            if campaign.status == CampaignStatus::Cancelled {
+                return;
+            }
+            campaign.status = CampaignStatus::Running;
+            campaign.started_at = Some(Utc::now());
+        }
+        info!("Campaign {} started at {} calls per second", id, cps);
+
+        // Attempts of the whole campaign, retries included, share one pace
+        let mut pace = interval(Duration::from_secs_f64(1.0 / cps));
+        pace.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let pace = Arc::new(AsyncMutex::new(pace));
+        let trunks = Arc::new(trunks);
+        let mut calls = JoinSet::new();
+        for index in 0..destinations {
+            let (campaign, trunks, prompt, pace) =
+                (Arc::clone(&campaign), Arc::clone(&trunks), Arc::clone(&prompt), Arc::clone(&pace));
+            calls.spawn(Arc::clone(&self).call_destination(campaign, index, trunks, prompt, pace));
+        }
+        while calls.join_next().await.is_some() {}
+
+        if let Ok(mut campaign) = campaign.lock() {
+            if campaign.status == CampaignStatus::Running {
+                campaign.status = CampaignStatus::Completed;
+            }
+            campaign.finished_at = Some(Utc::now());
+            let summary = campaign.summary();
+            info!(
+                "Campaign {} finished: {} answered, {} busy, {} unanswered, {} rejected, {} failed",
+                id, summary.answered, summary.busy, summary.no_answer, summary.rejected, summary.failed
+            );
+        }
+        self.prune();
+    }
+
+    /// Attempt one destination until it answers, cannot be reached or runs
+    /// out of attempts
+    async fn call_destination(
+        self: Arc<Self>,
+        campaign: Arc<Mutex<Campaign>>,
+        index: usize,
+        trunks: Arc<Vec<CampaignTrunk>>,
+        prompt: Arc<[u8]>,
+        pace: Arc<AsyncMutex<Interval>>,
+    ) {
+        let ring_timeout = Duration::from_secs(self.config.ring_timeout_secs);
+        for attempt in 0..self.config.max_attempts {
+            if attempt > 0 {
+                tokio::time::sleep(Duration::from_secs(self.config.retry_delay_secs)).await;
+            }
+            let Ok(_permit) = self.calls.acquire().await else {
+                return;
+            };
+            pace.lock().await.tick().await;
+
+            let trunk = &trunks[(index + attempt as usize) % trunks.len()];
+            let destination = {
+                let Ok(mut campaign) = campaign.lock() else {
+                    return;
+                };
+                if campaign.status == CampaignStatus::Cancelled {
+                    return;
+                }
+                let call = &mut campaign.calls[index];
+                call.outcome = CallOutcome::Calling;
+                call.attempts += 1;
+                call.trunk = Some(trunk.name.clone());
+                call.last_attempt_at = Some(Utc::now());
+                call.destination.clone()
+            };
+            let outcome = CallOutcome::from_result(self.dialer.call(trunk, &destination, &prompt, ring_timeout).await);
+            let retry = outcome.retryable();
+            match campaign.lock() {
+                Ok(mut campaign) => campaign.calls[index].outcome = outcome,
+                Err(_) => return,
+            }
+            if !retry {
+                return;
+            }
+        }
+    }
+
+    /// Stop a campaign's new attempts; calls already up finish
+    pub fn cancel(&self, id: &str) -> Result<CampaignSummary> {
+        let campaign = self
+            .campaigns
+            .get(id)
+            .map(|campaign| Arc::clone(campaign.value()))
+            .ok_or_else(|| Error::invalid_state(format!("No campaign {}", id)))?;
+        let mut campaign = campaign.lock().map_err(|_| Error::internal("Campaign lock poisoned"))?;
+        if matches!(campaign.status, CampaignStatus::Completed | CampaignStatus::Cancelled) {
+            return Err(Error::invalid_state(format!("Campaign {} has already finished", id)));
+        }
+        if campaign.status == CampaignStatus::Scheduled {
+            campaign.finished_at = Some(Utc::now());
+        }
+        campaign.status = CampaignStatus::Cancelled;
+        for call in campaign.calls.iter_mut().filter(|call| call.outcome == CallOutcome::Pending) {
+            call.outcome = CallOutcome::Cancelled;
+        }
+        info!("Campaign {} cancelled", id);
+        Ok(campaign.summary())
+    }
+
+    /// Every campaign kept, newest first
+    pub fn campaigns(&self) -> Vec<CampaignSummary> {
+        let mut summaries: Vec<CampaignSummary> = self
+            .campaigns
+            .iter()
+            .filter_map(|campaign| campaign.value().lock().ok().map(|campaign| campaign.summary()))
+            .collect();
+        summaries.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        summaries
+    }
+
+    pub fn campaign(&self, id: &str) -> Option<Campaign> {
+        let campaign = self.campaigns.get(id).map(|campaign| Arc::clone(campaign.value()))?;
+        let campaign = campaign.lock().ok()?;
+        Some(campaign.clone())
+    }
+
+    /// Forget the oldest finished campaigns beyond those retained
+    fn prune(&self) {
+        let mut finished: Vec<(DateTime<Utc>, String)> = self
+            .campaigns
+            .iter()
+            .filter_map(|campaign| {
+                let finished_at = campaign.value().lock().ok()?.finished_at?;
+                Some((finished_at, campaign.key().clone()))
+            })
+            .collect();
+        finished.sort_by(|a, b| b.0.cmp(&a.0));
+        for (_, id) in finished.into_iter().skip(self.config.retained_campaigns) {
+            self.campaigns.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers 5550001, is busy at 5550002, rejects 5550003 and never
+    /// reaches anyone else
+    struct FakeDialer {
+        attempts: Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait]
+    impl CampaignDialer for FakeDialer {
+        async fn call(&self, trunk: &CampaignTrunk, destination: &str, prompt: &[u8], _: Duration)
+            -> Result<DialResult> {
+            self.attempts.lock().unwrap().push((trunk.name.clone(), destination.to_string()));
+            let result = |status_code: u16, reason: &str, played_ms| DialResult {
+                status_code,
+                reason: reason.to_string(),
+                played_ms,
+            };
+            match destination {
+                "5550001" => Ok(result(200, "OK", prompt.len() as u64 / 8)),
+                "5550002" => Ok(result(486, "Busy Here", 0)),
+                "5550003" => Ok(result(404, "Not Found", 0)),
+                _ => Err(Error::timeout("No response")),
+            }
+        }
+    }
+
+    /// One second of mu-law silence as a WAV file
+    fn write_prompt(directory: &std::path::Path, name: &str) {
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36u32 + 8000).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&7u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.resize(wav.len() + 8000, 0xff);
+        std::fs::write(directory.join(format!("{}.wav", name)), wav).unwrap();
+    }
+
+    fn scheduler(directory: &std::path::Path) -> (Arc<CampaignScheduler>, Arc<FakeDialer>) {
+        let trunk = |name: &str| CampaignTrunk {
+            name: name.to_string(),
+            proxy: "192.0.2.10:5060".to_string(),
+            caller_id: "18005550100".to_string(),
+        };
+        let config = CampaignConfig {
+            enabled: true,
+            trunks: vec![trunk("carrier-a"), trunk("carrier-b")],
+            prompt_directory: directory.to_path_buf(),
+            default_cps: 10.0,
+            max_attempts: 2,
+            retry_delay_secs: 60,
+            ..Default::default()
+        };
+        let dialer = Arc::new(FakeDialer { attempts: Mutex::new(Vec::new()) });
+        (Arc::new(CampaignScheduler::new(config, dialer.clone())), dialer)
+    }
+
+    fn request(destinations: &[&str]) -> CampaignRequest {
+        CampaignRequest {
+            name: "evacuation".to_string(),
+            destinations: destinations.iter().map(|number| number.to_string()).collect(),
+            prompt: "evacuate-now".to_string(),
+            trunks: Vec::new(),
+            cps: None,
+            start_at: None,
+        }
+    }
+
+    async fn finished(scheduler: &CampaignScheduler, id: &str) -> Campaign {
+        loop {
+            let campaign = scheduler.campaign(id).unwrap();
+            if campaign.finished_at.is_some() {
+                return campaign;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_outcomes_and_retries() {
+        let directory = tempfile::tempdir().unwrap();
+        write_prompt(directory.path(), "evacuate-now");
+        let (scheduler, dialer) = scheduler(directory.path());
+
+        let campaign = scheduler.submit(request(&["5550001", "5550002", "5550003", "+15550004"])).unwrap();
+        assert_eq!(campaign.trunks, vec!["carrier-a", "carrier-b"]);
+        let campaign = finished(&scheduler, &campaign.id).await;
+        assert_eq!(campaign.status, CampaignStatus::Completed);
+
+        let outcomes: Vec<(&CallOutcome, u32)> =
+            campaign.calls.iter().map(|call| (&call.outcome, call.attempts)).collect();
+        assert_eq!(outcomes[0], (&CallOutcome::Answered { played_ms: 1000 }, 1));
+        assert_eq!(outcomes[1], (&CallOutcome::Busy, 2));
+        assert_eq!(outcomes[2], (&CallOutcome::Rejected { status_code: 404, reason: "Not Found".to_string() }, 1));
+        assert!(matches!(outcomes[3], (CallOutcome::Failed { .. }, 2)));
+
+        // A retry moves to the next trunk
+        let attempts = dialer.attempts.lock().unwrap();
+        let busy: Vec<&str> =
+            attempts.iter().filter(|(_, number)| number == "5550002").map(|(trunk, _)| trunk.as_str()).collect();
+        assert_eq!(busy, vec!["carrier-b", "carrier-a"]);
+
+        let summary = &scheduler.campaigns()[0];
+        assert_eq!((summary.answered, summary.busy, summary.rejected, summary.failed), (1, 1, 1, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejects_bad_requests_and_cancels_scheduled() {
+        let directory = tempfile::tempdir().unwrap();
+        write_prompt(directory.path(), "evacuate-now");
+        let (scheduler, dialer) = scheduler(directory.path());
+
+        assert!(scheduler.submit(request(&[])).is_err());
+        assert!(scheduler.submit(request(&["555-0001"])).is_err());
+        assert!(scheduler.submit(CampaignRequest { cps: Some(500.0), ..request(&["5550001"]) }).is_err());
+        let unknown_trunk = CampaignRequest { trunks: vec!["nope".to_string()], ..request(&["5550001"]) };
+        assert!(scheduler.submit(unknown_trunk).is_err());
+        assert!(scheduler.submit(CampaignRequest { prompt: "../etc".to_string(), ..request(&["5550001"]) }).is_err());
+        assert!(scheduler.submit(CampaignRequest { prompt: "missing".to_string(), ..request(&["5550001"]) }).is_err());
+
+        let later = Utc::now() + chrono::Duration::hours(1);
+        let scheduled = CampaignRequest { start_at: Some(later), ..request(&["5550001"]) };
+        let campaign = scheduler.submit(scheduled).unwrap();
+        assert_eq!(campaign.status, CampaignStatus::Scheduled);
+        let summary = scheduler.cancel(&campaign.id).unwrap();
+        assert_eq!((summary.status, summary.cancelled), (CampaignStatus::Cancelled, 1));
+        assert!(scheduler.cancel(&campaign.id).is_err());
+
+        tokio::time::sleep(Duration::from_secs(2 * 3600)).await;
+        assert!(dialer.attempts.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,659 @@
+//! Synthetic monitoring with canary calls
+//!
+//! The canary registers with the upstream registrar as an ordinary SIP
+//! endpoint and, every interval, calls numbers that route out over the
+//! gateway's own trunks to a loop-around line. Once a call is answered it
+//! sends a test tone for a few seconds and measures what comes back. Each
+//! call records whether it connected, its post-dial delay and the MOS of the
+//! looped audio; a run of failures to one destination raises an alarm that
+//! clears on the next call that passes.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::config::CanaryConfig;
+use crate::protocols::rtp::{RtpPacket, RtpStreamStats};
+use crate::protocols::sdp::SessionDescription;
+use crate::services::alarms::{AlarmManager, AlarmSeverity, AlarmSource, AlarmType};
+use crate::services::survivability::{parse_response, RegisterReply};
+use crate::{Error, Result};
+
+/// Management API path listing canary metrics per destination
+pub const CANARY_PATH: &str = "/api/v1/canary";
+
+/// G.711 mu-law digital milliwatt: one cycle of a 1 kHz tone at 0 dBm0
+pub(crate) const DIGITAL_MILLIWATT: [u8; 8] = [0x1e, 0x0b, 0x0b, 0x1e, 0x9e, 0x8b, 0x8b, 0x9e];
+const PCMU: u8 = 0;
+const PACKET_INTERVAL: Duration = Duration::from_millis(20);
+const SAMPLES_PER_PACKET: u32 = 160;
+/// How long to keep listening for looped audio after the last packet sent
+const LOOP_DRAIN: Duration = Duration::from_millis(500);
+/// Wait for the answer to a BYE; the call is over either way
+const BYE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How a canary call ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum CanaryOutcome {
+    Passed,
+    RegistrationFailed { reason: String },
+    /// Final failure response to the INVITE
+    Rejected { status_code: u16, reason: String },
+    /// No final response within the setup timeout, or a network error
+    Unreachable { reason: String },
+    /// Answered, but none of the test tone came back
+    NoMedia,
+}
+
+/// One canary call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CanaryResult {
+    pub destination: String,
+    pub started_at: DateTime<Utc>,
+    pub outcome: CanaryOutcome,
+    /// Post-dial delay: INVITE to the first ringing, progress or answer
+    pub pdd_ms: Option<u64>,
+    pub mos: Option<f64>,
+    pub loss_pct: Option<f64>,
+    pub jitter_ms: Option<f64>,
+}
+
+impl CanaryResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == CanaryOutcome::Passed
+    }
+}
+
+/// Canary metrics of one destination
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CanaryMetrics {
+    pub destination: String,
+    pub calls: u64,
+    pub failures: u64,
+    /// Consecutive failures up to the latest call
+    pub failure_streak: u32,
+    /// Mean over calls that passed
+    pub average_pdd_ms: Option<f64>,
+    /// Mean over calls that passed
+    pub average_mos: Option<f64>,
+    pub last: Option<CanaryResult>,
+}
+
+/// What one test call got back from the network
+#[derive(Debug, Clone)]
+pub struct CallAttempt {
+    /// Final response to the INVITE
+    pub status_code: u16,
+    pub reason: String,
+    pub pdd: Option<Duration>,
+    /// Tone sent and looped back while the call was up
+    pub media: Option<RtpStreamStats>,
+}
+
+/// SIP endpoint placing the canary calls
+#[async_trait]
+pub trait CanaryAgent: Send + Sync {
+    async fn register(&self) -> Result<()>;
+
+    /// Call `destination`, loop the test tone for `duration` once answered
+    /// and hang up; errors mean no final response was received
+    async fn call(&self, destination: &str, duration: Duration) -> Result<CallAttempt>;
+}
+
+/// MOS estimated from loss and jitter with the simplified E-model, assuming
+/// a short path since a loop-around call has no one-way delay to measure
+pub fn estimate_mos(loss_pct: f64, jitter_ms: f64) -> f64 {
+    let effective_latency = jitter_ms * 2.0 + 10.0;
+    let mut r = if effective_latency < 160.0 {
+        93.2 - effective_latency / 40.0
+    } else {
+        93.2 - (effective_latency - 120.0) / 10.0
+    };
+    r -= loss_pct * 2.5;
+    let r = r.clamp(0.0, 100.0);
+    1.0 + 0.035 * r + 0.000007 * r * (r - 60.0) * (100.0 - r)
+}
+
+pub(crate) fn new_tag() -> String {
+    format!("{:x}", rand::random::<u32>())
+}
+
+/// host part of a `host:port` address
+pub(crate) fn host_of(address: &str) -> &str {
+    address.rsplit_once(':').map(|(host, _)| host).unwrap_or(address)
+}
+
+/// One SIP transaction at a time over UDP
+pub(crate) struct SipExchange {
+    socket: UdpSocket,
+    peer: String,
+    pub(crate) local: SocketAddr,
+}
+
+impl SipExchange {
+    pub(crate) async fn connect(peer: &str) -> Result<Self> {
+        let network_err = |e: std::io::Error| Error::network(format!("Canary connection to {} failed: {}", peer, e));
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(network_err)?;
+        socket.connect(peer).await.map_err(network_err)?;
+        let local = socket.local_addr().map_err(network_err)?;
+        Ok(Self { socket, peer: peer.to_string(), local })
+    }
+
+    pub(crate) fn new_branch() -> String {
+        format!("z9hG4bK{:x}", rand::random::<u64>())
+    }
+
+    pub(crate) async fn send(&self, method: &str, uri: &str, branch: &str, headers: &[(String, String)], sdp: Option<&str>) -> Result<()> {
+        let mut message = format!(
+            "{} {} SIP/2.0\r\nVia: SIP/2.0/UDP {};branch={};rport\r\nMax-Forwards: 70\r\n",
+            method, uri, self.local, branch
+        );
+        for (name, value) in headers {
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        match sdp {
+            Some(sdp) => message.push_str(&format!("Content-Type: application/sdp\r\nContent-Length: {}\r\n\r\n{}", sdp.len(), sdp)),
+            None => message.push_str("Content-Length: 0\r\n\r\n"),
+        }
+        self.socket
+            .send(message.as_bytes())
+            .await
+            .map_err(|e| Error::network(format!("{} to {} failed: {}", method, self.peer, e)))?;
+        Ok(())
+    }
+
+    /// Next response and its body
+    pub(crate) async fn recv(&self, deadline: tokio::time::Instant) -> Result<(RegisterReply, String)> {
+        let mut buf = vec![0u8; 8192];
+        let len = tokio::time::timeout_at(deadline, self.socket.recv(&mut buf))
+            .await
+            .map_err(|_| Error::timeout(format!("No response from {}", self.peer)))?
+            .map_err(|e| Error::network(format!("Receiving from {} failed: {}", self.peer, e)))?;
+        let reply = parse_response(&buf[..len])?;
+        let body = String::from_utf8_lossy(&buf[..len])
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        Ok((reply, body))
+    }
+
+    pub(crate) async fn final_response(&self, deadline: tokio::time::Instant) -> Result<(RegisterReply, String)> {
+        loop {
+            let (reply, body) = self.recv(deadline).await?;
+            if reply.status_code >= 200 {
+                return Ok((reply, body));
+            }
+        }
+    }
+}
+
+pub(crate) fn header<'a>(reply: &'a RegisterReply, name: &str) -> Option<&'a str> {
+    reply.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+}
+
+/// Where in-dialog requests go: the answer's Contact, else the request URI
+pub(crate) fn dialog_target(reply: &RegisterReply, request_uri: &str) -> String {
+    header(reply, "Contact")
+        .map(|contact| contact.trim().trim_start_matches('<').split(['>', ';']).next().unwrap_or_default().to_string())
+        .filter(|contact| !contact.is_empty())
+        .unwrap_or_else(|| request_uri.to_string())
+}
+
+/// Canary speaking SIP and RTP over UDP
+pub struct UdpCanaryAgent {
+    config: CanaryConfig,
+}
+
+impl UdpCanaryAgent {
+    pub fn new(config: CanaryConfig) -> Self {
+        Self { config }
+    }
+
+    fn domain(&self) -> &str {
+        host_of(&self.config.registrar)
+    }
+
+    fn proxy(&self) -> &str {
+        if self.config.proxy.is_empty() { &self.config.registrar } else { &self.config.proxy }
+    }
+
+    fn setup_deadline(&self) -> tokio::time::Instant {
+        tokio::time::Instant::now() + Duration::from_secs(self.config.setup_timeout_secs)
+    }
+
+    /// Send the test tone to `remote` for `duration`, recording what comes back
+    async fn loop_audio(socket: &UdpSocket, remote: SocketAddr, duration: Duration) -> Result<RtpStreamStats> {
+        let ssrc = rand::random();
+        let mut stats = RtpStreamStats::new(ssrc);
+        let mut sequence: u16 = rand::random();
+        let mut timestamp: u32 = rand::random();
+        let payload = Bytes::from(DIGITAL_MILLIWATT.repeat(SAMPLES_PER_PACKET as usize / DIGITAL_MILLIWATT.len()));
+
+        let sending_until = tokio::time::Instant::now() + duration;
+        let listening_until = sending_until + LOOP_DRAIN;
+        let mut ticker = interval(PACKET_INTERVAL);
+        let mut buf = vec![0u8; 2048];
+        loop {
+            tokio::select! {
+                _ = ticker.tick(), if tokio::time::Instant::now() < sending_until => {
+                    let mut packet = RtpPacket::new(PCMU, sequence, timestamp, ssrc);
+                    packet.payload = payload.clone();
+                    socket
+                        .send_to(&packet.encode(), remote)
+                        .await
+                        .map_err(|e| Error::network(format!("Canary RTP to {} failed: {}", remote, e)))?;
+                    stats.update_sent(&packet);
+                    sequence = sequence.wrapping_add(1);
+                    timestamp = timestamp.wrapping_add(SAMPLES_PER_PACKET);
+                }
+                received = socket.recv_from(&mut buf) => {
+                    if let Ok((len, _)) = received {
+                        if let Ok(packet) = RtpPacket::decode(Bytes::copy_from_slice(&buf[..len])) {
+                            stats.update_received(&packet);
+                        }
+                    }
+                }
+                _ = tokio::time::sleep_until(listening_until) => break,
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[async_trait]
+impl CanaryAgent for UdpCanaryAgent {
+    async fn register(&self) -> Result<()> {
+        let exchange = SipExchange::connect(&self.config.registrar).await?;
+        let aor = format!("<sip:{}@{}>", self.config.user, self.domain());
+        let headers = vec![
+            ("From".to_string(), format!("{};tag={}", aor, new_tag())),
+            ("To".to_string(), aor),
+            ("Call-ID".to_string(), format!("{:x}@{}", rand::random::<u64>(), exchange.local.ip())),
+            ("CSeq".to_string(), "1 REGISTER".to_string()),
+            ("Contact".to_string(), format!("<sip:{}@{}>", self.config.user, exchange.local)),
+            ("Expires".to_string(), self.config.register_expires_secs.to_string()),
+        ];
+        exchange
+            .send("REGISTER", &format!("sip:{}", self.domain()), &SipExchange::new_branch(), &headers, None)
+            .await?;
+        let (reply, _) = exchange.final_response(self.setup_deadline()).await?;
+        if reply.status_code >= 300 {
+            return Err(Error::protocol(format!("REGISTER rejected: {} {}", reply.status_code, reply.reason)));
+        }
+        Ok(())
+    }
+
+    async fn call(&self, destination: &str, duration: Duration) -> Result<CallAttempt> {
+        let exchange = SipExchange::connect(self.proxy()).await?;
+        let rtp = UdpSocket::bind((exchange.local.ip(), 0))
+            .await
+            .map_err(|e| Error::network(format!("Canary RTP socket failed: {}", e)))?;
+        let rtp_port = rtp.local_addr().map_err(|e| Error::network(e.to_string()))?.port();
+        let ip = exchange.local.ip();
+        let sdp = format!(
+            "v=0\r\no=canary {} 1 IN IP4 {}\r\ns=canary\r\nc=IN IP4 {}\r\nt=0 0\r\nm=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=ptime:20\r\na=sendrecv\r\n",
+            rand::random::<u32>(), ip, ip, rtp_port
+        );
+
+        let uri = format!("sip:{}@{}", destination, self.domain());
+        let from = format!("<sip:{}@{}>;tag={}", self.config.user, self.domain(), new_tag());
+        let call_id = format!("{:x}@{}", rand::random::<u64>(), ip);
+        let invite_branch = SipExchange::new_branch();
+        let headers = vec![
+            ("From".to_string(), from.clone()),
+            ("To".to_string(), format!("<{}>", uri)),
+            ("Call-ID".to_string(), call_id.clone()),
+            ("CSeq".to_string(), "1 INVITE".to_string()),
+            ("Contact".to_string(), format!("<sip:{}@{}>", self.config.user, exchange.local)),
+        ];
+
+        let started = Instant::now();
+        exchange.send("INVITE", &uri, &invite_branch, &headers, Some(&sdp)).await?;
+        let deadline = self.setup_deadline();
+        let mut pdd = None;
+        let (reply, body) = loop {
+            let (reply, body) = exchange.recv(deadline).await?;
+            // 100 Trying is hop-by-hop and says nothing about the far end
+            if reply.status_code >= 180 && pdd.is_none() {
+                pdd = Some(started.elapsed());
+            }
+            if reply.status_code >= 200 {
+                break (reply, body);
+            }
+        };
+
+        let to = header(&reply, "To").unwrap_or_default().to_string();
+        let dialog_headers = |cseq: &str| {
+            vec![
+                ("From".to_string(), from.clone()),
+                ("To".to_string(), to.clone()),
+                ("Call-ID".to_string(), call_id.clone()),
+                ("CSeq".to_string(), cseq.to_string()),
+            ]
+        };
+        if reply.status_code >= 300 {
+            // The ACK of a failure response belongs to the INVITE transaction
+            exchange.send("ACK", &uri, &invite_branch, &dialog_headers("1 ACK"), None).await?;
+            return Ok(CallAttempt { status_code: reply.status_code, reason: reply.reason, pdd, media: None });
+        }
+
+        let target = dialog_target(&reply, &uri);
+        exchange.send("ACK", &target, &SipExchange::new_branch(), &dialog_headers("1 ACK"), None).await?;
+
+        let remote = SessionDescription::parse(&body).ok().and_then(|sdp| sdp.audio_endpoint());
+        let media = match remote {
+            Some(remote) => Some(Self::loop_audio(&rtp, remote, duration).await?),
+            None => None,
+        };
+
+        exchange.send("BYE", &target, &SipExchange::new_branch(), &dialog_headers("2 BYE"), None).await?;
+        if let Err(e) = exchange.final_response(tokio::time::Instant::now() + BYE_TIMEOUT).await {
+            warn!("Canary call to {} not confirmed closed: {}", destination, e);
+        }
+        Ok(CallAttempt { status_code: reply.status_code, reason: reply.reason, pdd, media })
+    }
+}
+
+/// Places canary calls and keeps their metrics and alarms
+pub struct CanaryMonitor {
+    config: CanaryConfig,
+    agent: Arc<dyn CanaryAgent>,
+    metrics: DashMap<String, CanaryMetrics>,
+    /// Alarm raised per failing destination
+    alarms: DashMap<String, String>,
+    alarm_manager: Option<Arc<AlarmManager>>,
+    registered_at: Mutex<Option<Instant>>,
+}
+
+impl CanaryMonitor {
+    pub fn new(config: CanaryConfig, agent: Arc<dyn CanaryAgent>) -> Self {
+        Self {
+            config,
+            agent,
+            metrics: DashMap::new(),
+            alarms: DashMap::new(),
+            alarm_manager: None,
+            registered_at: Mutex::new(None),
+        }
+    }
+
+    pub fn set_alarm_manager(&mut self, alarm_manager: Arc<AlarmManager>) {
+        self.alarm_manager = Some(alarm_manager);
+    }
+
+    /// Register again once half of the granted expiry has passed
+    async fn ensure_registered(&self) -> Result<()> {
+        let refresh = Duration::from_secs(u64::from(self.config.register_expires_secs) / 2);
+        let current = self
+            .registered_at
+            .lock()
+            .map(|at| at.is_some_and(|at| at.elapsed() < refresh))
+            .unwrap_or(false);
+        if current {
+            return Ok(());
+        }
+
+        let result = self.agent.register().await;
+        if let Ok(mut at) = self.registered_at.lock() {
+            *at = result.as_ref().ok().map(|_| Instant::now());
+        }
+        result
+    }
+
+    async fn call(&self, destination: &str) -> CanaryResult {
+        let started_at = Utc::now();
+        let result = |outcome| CanaryResult {
+            destination: destination.to_string(),
+            started_at,
+            outcome,
+            pdd_ms: None,
+            mos: None,
+            loss_pct: None,
+            jitter_ms: None,
+        };
+
+        let attempt = match self.agent.call(destination, Duration::from_secs(self.config.call_duration_secs)).await {
+            Ok(attempt) => attempt,
+            Err(e) => return result(CanaryOutcome::Unreachable { reason: e.to_string() }),
+        };
+        let pdd_ms = attempt.pdd.map(|pdd| pdd.as_millis() as u64);
+        if attempt.status_code >= 300 {
+            return CanaryResult {
+                pdd_ms,
+                ..result(CanaryOutcome::Rejected { status_code: attempt.status_code, reason: attempt.reason })
+            };
+        }
+
+        match attempt.media {
+            Some(stats) if stats.packets_received > 0 => {
+                let sent = stats.packets_sent.max(1) as f64;
+                let loss_pct = (sent - stats.packets_received.min(stats.packets_sent) as f64) / sent * 100.0;
+                CanaryResult {
+                    pdd_ms,
+                    mos: Some(estimate_mos(loss_pct, stats.jitter)),
+                    loss_pct: Some(loss_pct),
+                    jitter_ms: Some(stats.jitter),
+                    ..result(CanaryOutcome::Passed)
+                }
+            }
+            _ => CanaryResult { pdd_ms, ..result(CanaryOutcome::NoMedia) },
+        }
+    }
+
+    /// Call every destination once
+    pub async fn run_round(&self) -> Vec<CanaryResult> {
+        let mut results = Vec::new();
+        let registration = self.ensure_registered().await;
+        for destination in &self.config.destinations {
+            let result = match &registration {
+                Ok(()) => self.call(destination).await,
+                Err(e) => CanaryResult {
+                    destination: destination.clone(),
+                    started_at: Utc::now(),
+                    outcome: CanaryOutcome::RegistrationFailed { reason: e.to_string() },
+                    pdd_ms: None,
+                    mos: None,
+                    loss_pct: None,
+                    jitter_ms: None,
+                },
+            };
+            self.record(&result).await;
+            results.push(result);
+        }
+        results
+    }
+
+    async fn record(&self, result: &CanaryResult) {
+        let streak = {
+            let mut metrics = self.metrics.entry(result.destination.clone()).or_insert_with(|| CanaryMetrics {
+                destination: result.destination.clone(),
+                ..CanaryMetrics::default()
+            });
+            let passed = metrics.calls - metrics.failures;
+            metrics.calls += 1;
+            if result.passed() {
+                metrics.failure_streak = 0;
+                let mean = |average: Option<f64>, value: Option<f64>| match (average, value) {
+                    (Some(average), Some(value)) => Some(average + (value - average) / (passed + 1) as f64),
+                    (None, value) => value,
+                    (average, None) => average,
+                };
+                metrics.average_pdd_ms = mean(metrics.average_pdd_ms, result.pdd_ms.map(|pdd| pdd as f64));
+                metrics.average_mos = mean(metrics.average_mos, result.mos);
+            } else {
+                metrics.failures += 1;
+                metrics.failure_streak += 1;
+            }
+            metrics.last = Some(result.clone());
+            metrics.failure_streak
+        };
+
+        if result.passed() {
+            info!("Canary call to {} passed: PDD {:?} ms, MOS {:.2}", result.destination, result.pdd_ms, result.mos.unwrap_or_default());
+        } else {
+            warn!("Canary call to {} failed ({} in a row): {:?}", result.destination, streak, result.outcome);
+        }
+        self.update_alarm(result, streak).await;
+    }
+
+    async fn update_alarm(&self, result: &CanaryResult, streak: u32) {
+        let Some(alarms) = &self.alarm_manager else { return };
+        if result.passed() {
+            if let Some((_, alarm_id)) = self.alarms.remove(&result.destination) {
+                let _ = alarms.clear_alarm(&alarm_id, "canary".to_string()).await;
+            }
+            return;
+        }
+        if streak < self.config.failure_streak_alarm || self.alarms.contains_key(&result.destination) {
+            return;
+        }
+
+        let raised = alarms.raise_alarm(
+            AlarmSeverity::Major,
+            AlarmType::Quality,
+            AlarmSource {
+                component: "canary".to_string(),
+                instance: result.destination.clone(),
+                location: None,
+            },
+            format!("{} consecutive canary calls to {} failed", streak, result.destination),
+            None,
+            Some(format!("{:?}", result.outcome)),
+            Some("Check the trunks and upstream the canary calls route through".to_string()),
+        ).await;
+        match raised {
+            Ok(alarm_id) => {
+                self.alarms.insert(result.destination.clone(), alarm_id);
+            }
+            Err(e) => warn!("Failed to raise canary alarm: {}", e),
+        }
+    }
+
+    /// Metrics per destination, by destination
+    pub fn metrics(&self) -> Vec<CanaryMetrics> {
+        let mut metrics: Vec<CanaryMetrics> = self.metrics.iter().map(|entry| entry.value().clone()).collect();
+        metrics.sort_by(|a, b| a.destination.cmp(&b.destination));
+        metrics
+    }
+
+    /// Spawn the loop placing a round of canary calls every interval
+    pub fn spawn_monitor(self: &Arc<Self>) -> JoinHandle<()> {
+        let monitor = Arc::clone(self);
+        let period = Duration::from_secs(self.config.interval_secs.max(1));
+
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                monitor.run_round().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Agent answering from a script of outcomes, one per call
+    struct ScriptedAgent {
+        register_fails: bool,
+        registrations: AtomicU32,
+        /// Final status and packets looped back per call; `None` times out
+        script: Mutex<Vec<Option<(u16, u64)>>>,
+    }
+
+    #[async_trait]
+    impl CanaryAgent for ScriptedAgent {
+        async fn register(&self) -> Result<()> {
+            self.registrations.fetch_add(1, Ordering::Relaxed);
+            if self.register_fails {
+                return Err(Error::protocol("REGISTER rejected: 403 Forbidden"));
+            }
+            Ok(())
+        }
+
+        async fn call(&self, _destination: &str, _duration: Duration) -> Result<CallAttempt> {
+            match self.script.lock().unwrap().remove(0) {
+                None => Err(Error::timeout("No response")),
+                Some((status_code, received)) => {
+                    let mut media = RtpStreamStats::new(1);
+                    media.packets_sent = 100;
+                    media.packets_received = received;
+                    Ok(CallAttempt {
+                        status_code,
+                        reason: String::new(),
+                        pdd: Some(Duration::from_millis(1200)),
+                        media: (status_code < 300).then_some(media),
+                    })
+                }
+            }
+        }
+    }
+
+    fn monitor(register_fails: bool, script: Vec<Option<(u16, u64)>>) -> (Arc<ScriptedAgent>, CanaryMonitor) {
+        let agent = Arc::new(ScriptedAgent { register_fails, registrations: AtomicU32::new(0), script: Mutex::new(script) });
+        let config = CanaryConfig {
+            enabled: true,
+            registrar: "192.0.2.10:5060".to_string(),
+            destinations: vec!["18005550199".to_string()],
+            ..CanaryConfig::default()
+        };
+        (Arc::clone(&agent), CanaryMonitor::new(config, agent))
+    }
+
+    #[test]
+    fn test_mos_estimate() {
+        assert!(estimate_mos(0.0, 0.0) > 4.3);
+        assert!(estimate_mos(5.0, 20.0) < estimate_mos(1.0, 20.0));
+        assert!(estimate_mos(100.0, 500.0) >= 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_outcomes_and_failure_streak() {
+        let script = vec![Some((200, 98)), Some((503, 0)), None, Some((200, 0)), Some((200, 100))];
+        let (agent, monitor) = monitor(false, script);
+        let mut outcomes = Vec::new();
+        for _ in 0..5 {
+            outcomes.push(monitor.run_round().await.remove(0));
+        }
+
+        assert_eq!(outcomes[0].outcome, CanaryOutcome::Passed);
+        assert_eq!(outcomes[0].pdd_ms, Some(1200));
+        assert!((outcomes[0].loss_pct.unwrap() - 2.0).abs() < 1e-9);
+        assert!(matches!(outcomes[1].outcome, CanaryOutcome::Rejected { status_code: 503, .. }));
+        assert!(matches!(outcomes[2].outcome, CanaryOutcome::Unreachable { .. }));
+        assert_eq!(outcomes[3].outcome, CanaryOutcome::NoMedia);
+        assert_eq!(outcomes[4].outcome, CanaryOutcome::Passed);
+
+        let metrics = &monitor.metrics()[0];
+        assert_eq!((metrics.calls, metrics.failures, metrics.failure_streak), (5, 3, 0));
+        assert_eq!(metrics.average_pdd_ms, Some(1200.0));
+        // One registration covers every round within its expiry
+        assert_eq!(agent.registrations.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_registration_failure_fails_the_round() {
+        let (agent, monitor) = monitor(true, Vec::new());
+        let results = monitor.run_round().await;
+        assert!(matches!(results[0].outcome, CanaryOutcome::RegistrationFailed { .. }));
+        monitor.run_round().await;
+        assert_eq!(agent.registrations.load(Ordering::Relaxed), 2);
+        assert_eq!(monitor.metrics()[0].failure_streak, 2);
+    }
+}
@@ -0,0 +1,334 @@
+//! Capacity planning reports
+//!
+//! Deciding when to order another span takes more than today's peak: it
+//! takes the traffic each trunk carries in its busy hour, week after week.
+//! Busy channels are sampled on every trunk at a fixed interval and folded
+//! into hourly buckets holding the peak and the mean, which is the hour's
+//! traffic in Erlangs. A weekly or monthly report gives per trunk the peak
+//! concurrent calls, the time-consistent busy hour (the hour of day with the
+//! highest average traffic over the period), and the channels Erlang B says
+//! that traffic needs at the configured grade of service.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, TimeZone, Timelike, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::CapacityConfig;
+use crate::services::cdr::csv_escape;
+
+/// Management API path producing a capacity report, filtered by the
+/// `period`, `trunk` and `format` query parameters
+pub const CAPACITY_PATH: &str = "/api/v1/reports/capacity";
+
+/// Channels considered before Erlang B gives up on a traffic level
+const MAX_CHANNELS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CapacityPeriod {
+    #[default]
+    Week,
+    Month,
+}
+
+impl CapacityPeriod {
+    pub fn days(self) -> u32 {
+        match self {
+            Self::Week => 7,
+            Self::Month => 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// Filter and format of a capacity report
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CapacityQuery {
+    pub period: Option<CapacityPeriod>,
+    pub trunk: Option<String>,
+    pub format: Option<ReportFormat>,
+}
+
+/// Traffic and sizing of one trunk over the report period
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TrunkCapacity {
+    pub trunk: String,
+    /// B-channels at the most recent sample
+    pub channels: usize,
+    pub peak_concurrent: u32,
+    pub peak_at: Option<DateTime<Utc>>,
+    /// Hour of day (UTC) with the highest average traffic
+    pub busy_hour: Option<u32>,
+    /// Average traffic in the busy hour
+    pub busy_hour_erlangs: f64,
+    /// Traffic of the single busiest hour
+    pub peak_hour_erlangs: f64,
+    pub average_erlangs: f64,
+    /// Busy hour traffic as a percentage of the channels
+    pub utilisation: f64,
+    /// Channels carrying the busy hour traffic at the grade of service
+    pub channels_required: usize,
+    /// Percentage of busy hour calls the present channels would block
+    pub blocking: f64,
+    pub hours_sampled: usize,
+}
+
+/// Capacity of every trunk over a week or month
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CapacityReport {
+    pub period: CapacityPeriod,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub grade_of_service: f64,
+    pub trunks: Vec<TrunkCapacity>,
+}
+
+impl CapacityReport {
+    const CSV_COLUMNS: &'static [&'static str] = &[
+        "trunk", "channels", "peak_concurrent", "peak_at", "busy_hour", "busy_hour_erlangs",
+        "peak_hour_erlangs", "average_erlangs", "utilisation", "channels_required", "blocking",
+        "hours_sampled",
+    ];
+
+    /// One row per trunk, with a header
+    pub fn to_csv(&self) -> String {
+        let mut csv = Self::CSV_COLUMNS.join(",");
+        csv.push('\n');
+        for trunk in &self.trunks {
+            let fields = [
+                csv_escape(&trunk.trunk),
+                trunk.channels.to_string(),
+                trunk.peak_concurrent.to_string(),
+                trunk.peak_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+                trunk.busy_hour.map(|hour| hour.to_string()).unwrap_or_default(),
+                format!("{:.2}", trunk.busy_hour_erlangs),
+                format!("{:.2}", trunk.peak_hour_erlangs),
+                format!("{:.2}", trunk.average_erlangs),
+                format!("{:.1}", trunk.utilisation),
+                trunk.channels_required.to_string(),
+                format!("{:.2}", trunk.blocking),
+                trunk.hours_sampled.to_string(),
+            ];
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+/// Erlang B blocking probability of `erlangs` offered to `channels`
+pub fn erlang_b(erlangs: f64, channels: usize) -> f64 {
+    let mut blocking = 1.0;
+    for m in 1..=channels {
+        blocking = erlangs * blocking / (m as f64 + erlangs * blocking);
+    }
+    blocking
+}
+
+/// Fewest channels carrying `erlangs` with at most `grade_of_service` blocking
+pub fn channels_required(erlangs: f64, grade_of_service: f64) -> usize {
+    if erlangs <= 0.0 {
+        return 0;
+    }
+    let mut blocking = 1.0;
+    for m in 1..=MAX_CHANNELS {
+        blocking = erlangs * blocking / (m as f64 + erlangs * blocking);
+        if blocking <= grade_of_service {
+            return m;
+        }
+    }
+    MAX_CHANNELS
+}
+
+#[derive(Debug, Clone, Default)]
+struct HourBucket {
+    samples: u32,
+    busy_total: u64,
+    peak: u32,
+    peak_at: Option<DateTime<Utc>>,
+    channels: usize,
+}
+
+impl HourBucket {
+    fn erlangs(&self) -> f64 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            self.busy_total as f64 / self.samples as f64
+        }
+    }
+}
+
+fn hour_of(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(3600)
+}
+
+fn hour_start(hour: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(hour * 3600, 0).single().unwrap_or_default()
+}
+
+/// Hourly busy channel samples per trunk
+pub struct CapacityStats {
+    config: CapacityConfig,
+    buckets: DashMap<(String, i64), HourBucket>,
+}
+
+impl CapacityStats {
+    pub fn new(config: CapacityConfig) -> Self {
+        Self {
+            config,
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Record `busy` of a trunk's `channels` in use at `at`
+    pub fn sample(&self, trunk: &str, channels: usize, busy: u32, at: DateTime<Utc>) {
+        if !self.config.enabled {
+            return;
+        }
+        let hour = hour_of(at);
+        let key = (trunk.to_string(), hour);
+        let new_bucket = !self.buckets.contains_key(&key);
+        {
+            let mut bucket = self.buckets.entry(key).or_default();
+            bucket.samples += 1;
+            bucket.busy_total += u64::from(busy);
+            bucket.channels = channels;
+            if busy > bucket.peak || bucket.peak_at.is_none() {
+                bucket.peak = busy;
+                bucket.peak_at = Some(at);
+            }
+        }
+        if new_bucket {
+            let oldest = hour - i64::from(self.config.retention_days) * 24 + 1;
+            self.buckets.retain(|(_, hour), _| *hour >= oldest);
+        }
+    }
+
+    /// Traffic and sizing per trunk over the week or month up to `now`
+    pub fn report(&self, query: &CapacityQuery, now: DateTime<Utc>) -> CapacityReport {
+        let period = query.period.unwrap_or_default();
+        let current = hour_of(now);
+        let oldest = current - i64::from(period.days()) * 24 + 1;
+
+        let mut trunks: BTreeMap<String, Vec<(i64, HourBucket)>> = BTreeMap::new();
+        for entry in self.buckets.iter() {
+            let ((trunk, hour), bucket) = (entry.key(), entry.value());
+            if *hour < oldest || *hour > current || query.trunk.as_ref().map_or(false, |wanted| wanted != trunk) {
+                continue;
+            }
+            trunks.entry(trunk.clone()).or_default().push((*hour, bucket.clone()));
+        }
+
+        let grade_of_service = self.config.grade_of_service;
+        let trunks = trunks
+            .into_iter()
+            .map(|(trunk, mut hours)| {
+                hours.sort_by_key(|(hour, _)| *hour);
+                let channels = hours.last().map_or(0, |(_, bucket)| bucket.channels);
+                let peak = hours
+                    .iter()
+                    .map(|(_, bucket)| bucket)
+                    .max_by(|a, b| a.peak.cmp(&b.peak).then_with(|| b.peak_at.cmp(&a.peak_at)));
+
+                // Average traffic of each hour of day across the period
+                let mut by_hour_of_day: HashMap<u32, (f64, u32)> = HashMap::new();
+                for (hour, bucket) in &hours {
+                    let (total, days) = by_hour_of_day.entry(hour_start(*hour).hour()).or_default();
+                    *total += bucket.erlangs();
+                    *days += 1;
+                }
+                let busy_hour = by_hour_of_day
+                    .into_iter()
+                    .map(|(hour, (total, days))| (hour, total / f64::from(days)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1).then_with(|| b.0.cmp(&a.0)));
+                let busy_hour_erlangs = busy_hour.map_or(0.0, |(_, erlangs)| erlangs);
+
+                TrunkCapacity {
+                    trunk,
+                    channels,
+                    peak_concurrent: peak.map_or(0, |bucket| bucket.peak),
+                    peak_at: peak.and_then(|bucket| bucket.peak_at),
+                    busy_hour: busy_hour.map(|(hour, _)| hour),
+                    busy_hour_erlangs,
+                    peak_hour_erlangs: hours.iter().map(|(_, bucket)| bucket.erlangs()).fold(0.0, f64::max),
+                    average_erlangs: hours.iter().map(|(_, bucket)| bucket.erlangs()).sum::<f64>() / hours.len() as f64,
+                    utilisation: if channels > 0 { busy_hour_erlangs * 100.0 / channels as f64 } else { 0.0 },
+                    channels_required: channels_required(busy_hour_erlangs, grade_of_service),
+                    blocking: erlang_b(busy_hour_erlangs, channels) * 100.0,
+                    hours_sampled: hours.len(),
+                }
+            })
+            .collect();
+
+        CapacityReport {
+            period,
+            from: hour_start(oldest),
+            to: now,
+            grade_of_service,
+            trunks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_erlang_b() {
+        // Standard table values: 30 channels carry 20.3 E at 1% blocking
+        assert_eq!(channels_required(20.3, 0.01), 30);
+        assert_eq!(channels_required(0.0, 0.01), 0);
+        assert!((erlang_b(20.3, 30) - 0.01).abs() < 0.001);
+        assert_eq!(erlang_b(5.0, 0), 1.0);
+    }
+
+    #[test]
+    fn test_busy_hour_and_peak() {
+        let stats = CapacityStats::new(CapacityConfig::default());
+        let now = Utc.with_ymd_and_hms(2026, 10, 15, 23, 0, 0).unwrap();
+        for day in 0..3 {
+            let midnight = Utc.with_ymd_and_hms(2026, 10, 13 + day, 0, 0, 0).unwrap();
+            // 10:00 carries 20 E every day, 14:00 one day only at 25 E
+            stats.sample("span-1", 30, 20, midnight + Duration::hours(10));
+            stats.sample("span-1", 30, 20, midnight + Duration::hours(10) + Duration::minutes(30));
+            stats.sample("span-1", 30, 2, midnight + Duration::hours(14));
+        }
+        stats.sample("span-1", 30, 25, Utc.with_ymd_and_hms(2026, 10, 14, 14, 30, 0).unwrap());
+        stats.sample("span-2", 23, 1, now);
+        // Too old for a weekly report
+        stats.sample("span-1", 30, 29, now - Duration::days(10));
+
+        let report = stats.report(&CapacityQuery::default(), now);
+        assert_eq!(report.period, CapacityPeriod::Week);
+        assert_eq!(report.trunks.len(), 2);
+        let span = &report.trunks[0];
+        assert_eq!(span.trunk, "span-1");
+        assert_eq!((span.peak_concurrent, span.busy_hour), (25, Some(10)));
+        assert_eq!(span.peak_at, Some(Utc.with_ymd_and_hms(2026, 10, 14, 14, 30, 0).unwrap()));
+        assert_eq!(span.busy_hour_erlangs, 20.0);
+        assert_eq!(span.channels_required, 30);
+        assert_eq!(span.hours_sampled, 6);
+
+        let monthly = stats.report(&CapacityQuery { period: Some(CapacityPeriod::Month), trunk: Some("span-1".to_string()), ..Default::default() }, now);
+        assert_eq!(monthly.trunks.len(), 1);
+        assert_eq!(monthly.trunks[0].peak_concurrent, 29);
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("trunk,channels,peak_concurrent,"));
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.lines().nth(2).unwrap().starts_with("span-2,23,1,"));
+    }
+}
@@ -3,7 +3,7 @@
 //! This module provides comprehensive call detail recording and billing
 //! functionality for telecommunications compliance and revenue management.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::PathBuf;
@@ -18,10 +18,17 @@ use tokio::time::interval;
 use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
-use crate::config::RouteType;
+use crate::config::{CdrCustomField, CdrFieldSource, RouteType};
+use crate::protocols::rtcp_xr::VoipMetrics;
 use crate::services::b2bua::{B2buaCall, B2buaCallState};
+use crate::services::cdr_sequence::{CdrSequence, CdrSequencer};
 use crate::services::media_relay::MediaRelayStats;
+use crate::services::media_security::{LegSecurity, MediaSecurityStatus};
+use crate::services::no_answer::LegAttempt;
+use crate::services::precedence::ResourcePriority;
 use crate::services::transcoding::CodecType;
+use crate::services::transfer::{TransferOutcome, TransferRecord};
+use crate::utils::{ClockStamp, TimeHealth};
 use crate::{Error, Result};
 
 /// Call Detail Record structure
@@ -29,6 +36,9 @@ use crate::{Error, Result};
 pub struct CallDetailRecord {
     pub id: String,
     pub call_id: String,
+    /// Correlation ID the call carried across its legs and other systems
+    #[serde(default)]
+    pub correlation_id: Option<String>,
     pub session_id: String,
     pub caller: String,
     pub callee: String,
@@ -38,6 +48,8 @@ pub struct CallDetailRecord {
     pub call_type: CallType,
     pub route_type: RouteType,
     pub start_time: DateTime<Utc>,
+    #[serde(default)]
+    pub early_media_time: Option<DateTime<Utc>>,
     pub answer_time: Option<DateTime<Utc>>,
     pub end_time: Option<DateTime<Utc>>,
     pub duration_seconds: u64,
@@ -48,6 +60,189 @@ pub struct CallDetailRecord {
     pub routing_info: RoutingCdrInfo,
     pub media_info: MediaCdrInfo,
     pub compliance_info: ComplianceInfo,
+    /// Operator-defined fields extracted from SIP headers or Q.931 UUI
+    #[serde(default)]
+    pub custom_fields: BTreeMap<String, String>,
+    /// Cluster-wide sequence identifier, assigned when the record is written
+    #[serde(default)]
+    pub sequence: Option<CdrSequence>,
+    /// Whether the start and end times came from a synchronized clock
+    #[serde(default)]
+    pub timestamp_quality: TimestampQuality,
+    /// Timestamps need checking before the record is rated
+    #[serde(default)]
+    pub billing_review: bool,
+    /// Resource-Priority the call arrived with; routine calls have none
+    #[serde(default)]
+    pub precedence: Option<ResourcePriority>,
+    /// Transfers requested with REFER, in the order they were made
+    #[serde(default)]
+    pub transfers: Vec<TransferRecord>,
+    /// Monotonic reading behind `start_time`; answer and end times are
+    /// mapped from it. Absent on records read back from storage
+    #[serde(skip)]
+    pub start_clock: Option<ClockStamp>,
+    #[serde(skip)]
+    pub answer_instant: Option<Instant>,
+}
+
+impl CallDetailRecord {
+    /// Billed length of the call ending at `end`: from answer if answered,
+    /// otherwise from start. Measured on the monotonic clock when the record
+    /// was started in this process, so wall-clock steps do not distort it
+    pub fn measured_duration(&self, end: &ClockStamp) -> Duration {
+        match self.start_clock {
+            Some(start) => end.instant.saturating_duration_since(self.answer_instant.unwrap_or(start.instant)),
+            None => end
+                .utc
+                .signed_duration_since(self.answer_time.unwrap_or(self.start_time))
+                .to_std()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// UTC of `stamp` on this record's timeline
+    fn timeline_utc(&self, stamp: &ClockStamp) -> DateTime<Utc> {
+        self.start_clock.map(|start| start.utc_at(stamp.instant)).unwrap_or(stamp.utc)
+    }
+
+    /// Take the clock's state at the end of the call into account; a record
+    /// stamped in holdover at either end is held for billing review
+    fn note_end_timing(&mut self, quality: TimestampQuality) {
+        if quality == TimestampQuality::Holdover && self.timestamp_quality != TimestampQuality::Holdover {
+            warn!("CDR {} ended with the clock in holdover; flagged for billing review", self.id);
+            self.timestamp_quality = TimestampQuality::Holdover;
+        }
+        self.billing_review = self.timestamp_quality == TimestampQuality::Holdover;
+    }
+
+    /// Fixed CSV columns, followed by one column per configured custom field
+    pub const CSV_COLUMNS: &'static [&'static str] = &[
+        "id", "sequence", "call_id", "caller", "callee", "original_called_number",
+        "start_time", "answer_time", "end_time", "duration_seconds",
+        "billable_duration_seconds", "disconnect_reason", "route_type",
+        "rule_id", "ingress_port", "egress_port", "cost", "currency",
+        "timestamp_quality", "billing_review", "leg_a_media_security", "leg_b_media_security",
+        "resource_priority", "precedence_level", "transferred_to", "correlation_id",
+    ];
+
+    /// Target of the last completed transfer
+    pub fn transferred_to(&self) -> Option<&str> {
+        self.transfers
+            .iter()
+            .rev()
+            .find(|transfer| transfer.outcome == TransferOutcome::Completed)
+            .map(|transfer| transfer.target.as_str())
+    }
+
+    pub fn csv_header(custom_field_names: &[String]) -> String {
+        Self::CSV_COLUMNS
+            .iter()
+            .map(|c| c.to_string())
+            .chain(custom_field_names.iter().map(|name| csv_escape(name)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn to_csv_row(&self, custom_field_names: &[String]) -> String {
+        let fmt_time = |t: &Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339()).unwrap_or_default();
+
+        let mut fields = vec![
+            self.id.clone(),
+            self.sequence.as_ref().map(ToString::to_string).unwrap_or_default(),
+            self.call_id.clone(),
+            self.caller.clone(),
+            self.callee.clone(),
+            self.original_called_number.clone(),
+            self.start_time.to_rfc3339(),
+            fmt_time(&self.answer_time),
+            fmt_time(&self.end_time),
+            self.duration_seconds.to_string(),
+            self.billable_duration_seconds.to_string(),
+            self.disconnect_reason.as_ref().map(|r| format!("{:?}", r)).unwrap_or_default(),
+            format!("{:?}", self.route_type),
+            self.routing_info.rule_id.clone(),
+            self.routing_info.ingress_port.clone().unwrap_or_default(),
+            self.routing_info.egress_port.clone().unwrap_or_default(),
+            format!("{:.4}", self.billing_info.cost),
+            self.billing_info.currency.clone(),
+            format!("{:?}", self.timestamp_quality),
+            self.billing_review.to_string(),
+            self.media_info.leg_a_security.map(|s| s.to_string()).unwrap_or_default(),
+            self.media_info.leg_b_security.map(|s| s.to_string()).unwrap_or_default(),
+            self.precedence.as_ref().map(ToString::to_string).unwrap_or_default(),
+            self.precedence.as_ref().map(|p| p.level.isdn_level().to_string()).unwrap_or_default(),
+            self.transferred_to().unwrap_or_default().to_string(),
+            self.correlation_id.clone().unwrap_or_default(),
+        ];
+        for name in custom_field_names {
+            fields.push(self.custom_fields.get(name).cloned().unwrap_or_default());
+        }
+
+        fields.iter().map(|f| csv_escape(f)).collect::<Vec<_>>().join(",")
+    }
+}
+
+pub(crate) fn csv_escape(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Extract configured custom CDR fields from inbound signaling
+pub fn extract_custom_fields(
+    fields: &[CdrCustomField],
+    sip_headers: &[(String, String)],
+    q931_uui: Option<&[u8]>,
+) -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+
+    for field in fields {
+        let value = match &field.source {
+            CdrFieldSource::SipHeader { header } => sip_headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(header))
+                .map(|(_, value)| value.trim().to_string()),
+            CdrFieldSource::Q931Uui => q931_uui.map(decode_uui),
+        };
+
+        if let Some(mut value) = value {
+            if let Some(max_length) = field.max_length {
+                if value.chars().count() > max_length {
+                    value = value.chars().take(max_length).collect();
+                }
+            }
+            values.insert(field.name.clone(), value);
+        }
+    }
+
+    values
+}
+
+/// User-user information carried in a SIP `User-to-User` header (RFC 7433),
+/// as interworked from a Q.931 user-user IE. Only hex encoding is defined
+pub fn sip_user_to_user(sip_headers: &[(String, String)]) -> Option<Vec<u8>> {
+    let (_, value) = sip_headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("User-to-User"))?;
+    let mut parts = value.split(';');
+    let data = parts.next()?.trim().trim_matches('"');
+    let hex_encoded = parts.all(|param| {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        !name.trim().eq_ignore_ascii_case("encoding") || value.trim().eq_ignore_ascii_case("hex")
+    });
+    hex_encoded.then(|| hex::decode(data).ok()).flatten()
+}
+
+/// Decode a Q.931 user-user IE payload. IA5 payloads (protocol
+/// discriminator 0x04) are returned as text, anything else as hex.
+fn decode_uui(uui: &[u8]) -> String {
+    match uui.split_first() {
+        Some((0x04, text)) if text.iter().all(|b| b.is_ascii() && !b.is_ascii_control()) => {
+            String::from_utf8_lossy(text).to_string()
+        }
+        _ => hex::encode(uui),
+    }
 }
 
 /// Calling party category for billing purposes
@@ -77,6 +272,30 @@ pub enum CallType {
     Conference,
 }
 
+/// State of the gateway clock behind a record's timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampQuality {
+    /// Locked to the timing reference, or within the allowed holdover
+    Synchronized,
+    /// In holdover past `time_sync.max_holdover_secs` when the call started
+    /// or ended
+    Holdover,
+    /// Not known, e.g. no timing service or a record written before the check
+    #[default]
+    Unknown,
+}
+
+impl TimestampQuality {
+    fn at(health: Option<&TimeHealth>, instant: Instant) -> Self {
+        match health {
+            Some(health) if health.is_synchronized(instant) => Self::Synchronized,
+            Some(_) => Self::Holdover,
+            None => Self::Unknown,
+        }
+    }
+}
+
 /// Call disconnect reasons
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DisconnectReason {
@@ -93,6 +312,25 @@ pub enum DisconnectReason {
     Forbidden,
     NotFound,
     ServerError,
+    Preempted,
+}
+
+impl DisconnectReason {
+    /// Map a Q.850 release cause to a CDR disconnect reason
+    pub fn from_q850(cause: u16) -> Self {
+        match cause {
+            16 | 31 => DisconnectReason::Normal,
+            17 => DisconnectReason::Busy,
+            18 | 19 => DisconnectReason::NoAnswer,
+            21 => DisconnectReason::Rejected,
+            1 | 3 => DisconnectReason::NotFound,
+            34 | 42 | 44 => DisconnectReason::Congestion,
+            38 | 41 => DisconnectReason::NetworkError,
+            8 | 9 => DisconnectReason::Preempted,
+            102 => DisconnectReason::Timeout,
+            _ => DisconnectReason::ProviderDisconnect,
+        }
+    }
 }
 
 /// Quality metrics for the call
@@ -109,6 +347,19 @@ pub struct QualityMetrics {
     pub rtp_bytes_sent: u64,
     pub rtp_bytes_received: u64,
     pub transcoding_used: bool,
+    /// Loss structure from RTCP-XR, when it was measured
+    #[serde(default)]
+    pub burst_gap: Option<BurstGapMetrics>,
+}
+
+/// Burst and gap loss of the trunk leg, as RTCP-XR VoIP metrics report it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BurstGapMetrics {
+    pub burst_density_pct: f32,
+    pub burst_duration_ms: u16,
+    pub gap_density_pct: f32,
+    pub gap_duration_ms: u16,
+    pub gmin: u8,
 }
 
 /// Billing information
@@ -148,6 +399,14 @@ pub struct RoutingCdrInfo {
     pub number_translation_applied: bool,
     pub routing_decision_time_ms: u64,
     pub failover_attempts: u32,
+    /// Every leg B attempt, including no-answer forwarding
+    #[serde(default)]
+    pub legs: Vec<LegAttempt>,
+    /// Labelled TDM ports, e.g. `span 3 channel 5 (MainSt-PBX, circuit ABC123)`
+    #[serde(default)]
+    pub ingress_port: Option<String>,
+    #[serde(default)]
+    pub egress_port: Option<String>,
 }
 
 /// Media information for CDR
@@ -159,6 +418,11 @@ pub struct MediaCdrInfo {
     pub media_relay_used: bool,
     pub dtmf_events: Vec<DtmfEvent>,
     pub media_processing_enabled: bool,
+    /// SRTP suite or plaintext negotiated on each leg
+    #[serde(default)]
+    pub leg_a_security: Option<LegSecurity>,
+    #[serde(default)]
+    pub leg_b_security: Option<LegSecurity>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -387,9 +651,11 @@ pub struct CdrService {
     active_cdrs: Arc<DashMap<String, CallDetailRecord>>,
     billing_rates: Arc<RwLock<Vec<BillingRate>>>,
     storage: Arc<dyn CdrStorage>,
+    sequencer: Arc<CdrSequencer>,
     event_tx: mpsc::UnboundedSender<CdrEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<CdrEvent>>,
     default_billing_config: BillingConfig,
+    time_health: Option<Arc<TimeHealth>>,
     is_running: bool,
 }
 
@@ -427,30 +693,46 @@ impl CdrService {
             active_cdrs: Arc::new(DashMap::new()),
             billing_rates: Arc::new(RwLock::new(Vec::new())),
             storage,
+            sequencer: Arc::new(CdrSequencer::new("local")),
             event_tx,
             event_rx: Some(event_rx),
             default_billing_config: billing_config,
+            time_health: None,
             is_running: false,
         }
     }
 
+    /// Grade record timestamps by the clock's holdover; set before `start`
+    pub fn set_time_health(&mut self, health: Arc<TimeHealth>) {
+        self.time_health = Some(health);
+    }
+
     pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<CdrEvent>> {
         self.event_rx.take()
     }
 
+    /// Number written CDRs under `node_id`, starting a new epoch
+    pub fn set_node_id(&mut self, node_id: &str) {
+        self.sequencer = Arc::new(CdrSequencer::new(node_id));
+    }
+
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting CDR service");
 
         // Start CDR finalization task
         let active_cdrs_finalizer = Arc::clone(&self.active_cdrs);
         let storage_finalizer = Arc::clone(&self.storage);
+        let sequencer_finalizer = Arc::clone(&self.sequencer);
         let event_tx_finalizer = self.event_tx.clone();
+        let time_health_finalizer = self.time_health.clone();
 
         tokio::spawn(async move {
             Self::cdr_finalizer_loop(
                 active_cdrs_finalizer,
                 storage_finalizer,
+                sequencer_finalizer,
                 event_tx_finalizer,
+                time_health_finalizer,
             ).await;
         });
 
@@ -465,28 +747,109 @@ impl CdrService {
         caller_category: CallingPartyCategory,
         call_type: CallType,
     ) -> Result<String> {
+        let mut cdr = self.build_record(
+            &call.id,
+            &call.leg_a_session_id,
+            &call.caller,
+            &call.callee,
+            &call.callee, // Would be updated by routing
+            caller_category,
+            call_type,
+            RoutingCdrInfo {
+                rule_id: "unknown".to_string(),
+                route_type: call.routing_info.route_type.clone(),
+                target_gateway: call.routing_info.target_gateway.clone().unwrap_or_default(),
+                number_translation_applied: call.routing_info.number_translation.is_some(),
+                routing_decision_time_ms: 0,
+                failover_attempts: 0,
+                legs: Vec::new(),
+                ingress_port: None,
+                egress_port: None,
+            },
+        ).await?;
+        cdr.custom_fields = call.custom_fields.clone();
+        cdr.correlation_id = Some(call.correlation_id.clone()).filter(|id| !id.is_empty());
+
+        Ok(self.insert_record(cdr))
+    }
+
+    /// Start a CDR for a TDM-to-TDM tandem call that never created SIP legs
+    pub async fn start_tandem_call_record(
+        &self,
+        call_id: &str,
+        caller: &str,
+        original_called_number: &str,
+        translated_called_number: &str,
+        rule_id: &str,
+        egress_span: u32,
+        ingress_port: String,
+        egress_port: String,
+    ) -> Result<String> {
+        let cdr = self.build_record(
+            call_id,
+            call_id,
+            caller,
+            original_called_number,
+            translated_called_number,
+            CallingPartyCategory::Subscriber,
+            CallType::Voice,
+            RoutingCdrInfo {
+                rule_id: rule_id.to_string(),
+                route_type: RouteType::Trunk,
+                target_gateway: format!("span-{}", egress_span),
+                number_translation_applied: original_called_number != translated_called_number,
+                routing_decision_time_ms: 0,
+                failover_attempts: 0,
+                legs: Vec::new(),
+                ingress_port: Some(ingress_port),
+                egress_port: Some(egress_port),
+            },
+        ).await?;
+
+        Ok(self.insert_record(cdr))
+    }
+
+    async fn build_record(
+        &self,
+        call_id: &str,
+        session_id: &str,
+        caller: &str,
+        original_called_number: &str,
+        translated_called_number: &str,
+        caller_category: CallingPartyCategory,
+        call_type: CallType,
+        routing_info: RoutingCdrInfo,
+    ) -> Result<CallDetailRecord> {
         let cdr_id = Uuid::new_v4().to_string();
-        let start_time = Utc::now();
+        let start_clock = ClockStamp::now();
+        let timestamp_quality = TimestampQuality::at(self.time_health.as_deref(), start_clock.instant);
+        if timestamp_quality == TimestampQuality::Holdover {
+            warn!("Call {} started with the clock in holdover; CDR flagged for billing review", call_id);
+        }
+        let route_type = routing_info.route_type.clone();
+        let emergency_call = matches!(route_type, RouteType::Emergency);
 
         // Determine billing information
         let billing_info = self.calculate_billing_info(
-            &call.callee,
-            &call.caller,
+            translated_called_number,
+            caller,
             call_type.clone(),
         ).await?;
 
-        let cdr = CallDetailRecord {
-            id: cdr_id.clone(),
-            call_id: call.id.clone(),
-            session_id: call.leg_a_session_id.clone(),
-            caller: call.caller.clone(),
-            callee: call.callee.clone(),
-            original_called_number: call.callee.clone(),
-            translated_called_number: call.callee.clone(), // Would be updated by routing
+        Ok(CallDetailRecord {
+            id: cdr_id,
+            call_id: call_id.to_string(),
+            correlation_id: None,
+            session_id: session_id.to_string(),
+            caller: caller.to_string(),
+            callee: translated_called_number.to_string(),
+            original_called_number: original_called_number.to_string(),
+            translated_called_number: translated_called_number.to_string(),
             calling_party_category: caller_category,
             call_type,
-            route_type: call.routing_info.route_type.clone(),
-            start_time,
+            route_type,
+            start_time: start_clock.utc,
+            early_media_time: None,
             answer_time: None,
             end_time: None,
             duration_seconds: 0,
@@ -504,16 +867,10 @@ impl CdrService {
                 rtp_bytes_sent: 0,
                 rtp_bytes_received: 0,
                 transcoding_used: false,
+                burst_gap: None,
             },
             billing_info,
-            routing_info: RoutingCdrInfo {
-                rule_id: "unknown".to_string(),
-                route_type: call.routing_info.route_type.clone(),
-                target_gateway: call.routing_info.target_gateway.clone().unwrap_or_default(),
-                number_translation_applied: call.routing_info.number_translation.is_some(),
-                routing_decision_time_ms: 0,
-                failover_attempts: 0,
-            },
+            routing_info,
             media_info: MediaCdrInfo {
                 leg_a_codec: "unknown".to_string(),
                 leg_b_codec: "unknown".to_string(),
@@ -521,12 +878,14 @@ impl CdrService {
                 media_relay_used: false,
                 dtmf_events: Vec::new(),
                 media_processing_enabled: false,
+                leg_a_security: None,
+                leg_b_security: None,
             },
             compliance_info: ComplianceInfo {
                 jurisdiction: "US".to_string(),
-                emergency_call: matches!(call.routing_info.route_type, RouteType::Emergency),
+                emergency_call,
                 lawful_intercept_required: false,
-                data_retention_class: if matches!(call.routing_info.route_type, RouteType::Emergency) {
+                data_retention_class: if emergency_call {
                     DataRetentionClass::Legal
                 } else {
                     DataRetentionClass::Standard
@@ -538,26 +897,51 @@ impl CdrService {
                     location_tracking_enabled: false,
                 },
             },
-        };
+            custom_fields: BTreeMap::new(),
+            sequence: None,
+            timestamp_quality,
+            billing_review: timestamp_quality == TimestampQuality::Holdover,
+            precedence: None,
+            transfers: Vec::new(),
+            start_clock: Some(start_clock),
+            answer_instant: None,
+        })
+    }
 
-        self.active_cdrs.insert(cdr_id.clone(), cdr);
+    fn insert_record(&self, cdr: CallDetailRecord) -> String {
+        let cdr_id = cdr.id.clone();
+        let call_id = cdr.call_id.clone();
 
         // Emit call started event
         let _ = self.event_tx.send(CdrEvent::CallStarted {
             cdr_id: cdr_id.clone(),
-            call_id: call.id.clone(),
-            caller: call.caller.clone(),
-            callee: call.callee.clone(),
+            call_id: call_id.clone(),
+            caller: cdr.caller.clone(),
+            callee: cdr.callee.clone(),
         });
 
-        info!("Started CDR record: {} for call {}", cdr_id, call.id);
-        Ok(cdr_id)
+        self.active_cdrs.insert(cdr_id.clone(), cdr);
+
+        info!("Started CDR record: {} for call {}", cdr_id, call_id);
+        cdr_id
     }
 
     pub async fn update_call_answered(&self, cdr_id: &str) -> Result<()> {
+        self.update_call_answered_at(cdr_id, ClockStamp::now()).await
+    }
+
+    /// Record answer using the time the answer signal was received.
+    ///
+    /// Only the first answer is kept so late duplicates (retransmitted
+    /// 200 OK, repeated CONNECT) cannot move the billing start.
+    pub async fn update_call_answered_at(&self, cdr_id: &str, answered: ClockStamp) -> Result<()> {
         if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
-            let answer_time = Utc::now();
+            if cdr.answer_time.is_some() {
+                return Ok(());
+            }
+            let answer_time = cdr.timeline_utc(&answered);
             cdr.answer_time = Some(answer_time);
+            cdr.answer_instant = Some(answered.instant);
 
             // Emit call answered event
             let _ = self.event_tx.send(CdrEvent::CallAnswered {
@@ -571,6 +955,38 @@ impl CdrService {
         Ok(())
     }
 
+    /// Record the first early media indication, kept separate from answer
+    pub async fn update_early_media(&self, cdr_id: &str, early_media_time: DateTime<Utc>) -> Result<()> {
+        if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
+            if cdr.early_media_time.is_none() {
+                cdr.early_media_time = Some(early_media_time);
+                debug!("Updated CDR {} with early media time", cdr_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record a completed leg B attempt; legs after the first count as failovers
+    pub async fn record_leg_attempt(&self, cdr_id: &str, attempt: LegAttempt) -> Result<()> {
+        if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
+            cdr.routing_info.legs.push(attempt);
+            cdr.routing_info.failover_attempts = cdr.routing_info.legs.len().saturating_sub(1) as u32;
+            debug!("Recorded leg {} on CDR {}", cdr.routing_info.legs.len(), cdr_id);
+        }
+
+        Ok(())
+    }
+
+    /// Merge custom fields into an active CDR (e.g. Q.931 UUI on TDM legs)
+    pub async fn set_custom_fields(&self, cdr_id: &str, fields: BTreeMap<String, String>) -> Result<()> {
+        if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
+            cdr.custom_fields.extend(fields);
+        }
+
+        Ok(())
+    }
+
     pub async fn update_media_info(
         &self,
         cdr_id: &str,
@@ -586,6 +1002,8 @@ impl CdrService {
             cdr.quality_metrics.rtp_bytes_sent = media_stats.bytes_relayed_a_to_b + media_stats.bytes_relayed_b_to_a;
             cdr.quality_metrics.rtp_bytes_received = media_stats.bytes_relayed_a_to_b + media_stats.bytes_relayed_b_to_a;
             cdr.quality_metrics.packet_loss_rate = media_stats.packet_loss_rate as f32;
+            cdr.quality_metrics.jitter_ms = media_stats.max_jitter_ms() as f32;
+            cdr.quality_metrics.latency_ms = media_stats.average_latency_ms as f32;
             cdr.quality_metrics.transcoding_used = transcoding_backend.is_some();
 
             // Update media info
@@ -600,24 +1018,72 @@ impl CdrService {
         Ok(())
     }
 
+    /// Record RTCP-XR VoIP metrics of the trunk leg: its MOS and loss, and
+    /// how that loss was spread into bursts and gaps
+    pub async fn update_rtcp_xr(&self, cdr_id: &str, metrics: &VoipMetrics) -> Result<()> {
+        if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
+            let quality = &mut cdr.quality_metrics;
+            quality.mos_score = metrics.mos_lq().map(|mos| mos as f32).or(quality.mos_score);
+            quality.packet_loss_rate = metrics.loss_pct() as f32;
+            quality.burst_gap = Some(BurstGapMetrics {
+                burst_density_pct: metrics.burst_density_pct() as f32,
+                burst_duration_ms: metrics.burst_duration_ms,
+                gap_density_pct: metrics.gap_density_pct() as f32,
+                gap_duration_ms: metrics.gap_duration_ms,
+                gmin: metrics.gmin,
+            });
+            debug!("Updated CDR {} with RTCP XR metrics", cdr_id);
+        }
+
+        Ok(())
+    }
+
+    /// Record the Resource-Priority of a priority call
+    pub async fn update_precedence(&self, cdr_id: &str, precedence: ResourcePriority) -> Result<()> {
+        if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
+            debug!("Updated CDR {} with precedence {}", cdr_id, precedence);
+            cdr.precedence = Some(precedence);
+        }
+
+        Ok(())
+    }
+
+    /// Record a transfer of the call, replacing the earlier state of the same transfer
+    pub async fn record_transfer(&self, cdr_id: &str, transfer: TransferRecord) -> Result<()> {
+        if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
+            debug!("Updated CDR {} with transfer {} to {}", cdr_id, transfer.id, transfer.target);
+            match cdr.transfers.iter_mut().find(|recorded| recorded.id == transfer.id) {
+                Some(recorded) => *recorded = transfer,
+                None => cdr.transfers.push(transfer),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record the media protection each leg negotiated
+    pub async fn update_media_security(&self, cdr_id: &str, security: MediaSecurityStatus) -> Result<()> {
+        if let Some(mut cdr) = self.active_cdrs.get_mut(cdr_id) {
+            cdr.media_info.leg_a_security = security.leg_a;
+            cdr.media_info.leg_b_security = security.leg_b;
+            debug!("Updated CDR {} with media security", cdr_id);
+        }
+
+        Ok(())
+    }
+
     pub async fn finalize_call_record(
         &self,
         cdr_id: &str,
-        end_time: DateTime<Utc>,
+        ended: ClockStamp,
         disconnect_reason: DisconnectReason,
     ) -> Result<()> {
         if let Some((_, mut cdr)) = self.active_cdrs.remove(cdr_id) {
+            let end_time = cdr.timeline_utc(&ended);
             cdr.end_time = Some(end_time);
             cdr.disconnect_reason = Some(disconnect_reason.clone());
-
-            // Calculate duration
-            let duration = if let Some(answer_time) = cdr.answer_time {
-                end_time.signed_duration_since(answer_time)
-            } else {
-                end_time.signed_duration_since(cdr.start_time)
-            };
-
-            cdr.duration_seconds = duration.num_seconds().max(0) as u64;
+            cdr.duration_seconds = cdr.measured_duration(&ended).as_secs();
+            cdr.note_end_timing(TimestampQuality::at(self.time_health.as_deref(), ended.instant));
 
             // Calculate billable duration
             cdr.billable_duration_seconds = self.calculate_billable_duration(
@@ -632,6 +1098,7 @@ impl CdrService {
             );
 
             // Store CDR
+            cdr.sequence = Some(self.sequencer.next());
             if let Err(e) = self.storage.store_cdr(&cdr).await {
                 error!("Failed to store CDR {}: {}", cdr_id, e);
                 let _ = self.event_tx.send(CdrEvent::Error {
@@ -750,40 +1217,49 @@ impl CdrService {
     async fn cdr_finalizer_loop(
         active_cdrs: Arc<DashMap<String, CallDetailRecord>>,
         storage: Arc<dyn CdrStorage>,
+        sequencer: Arc<CdrSequencer>,
         event_tx: mpsc::UnboundedSender<CdrEvent>,
+        time_health: Option<Arc<TimeHealth>>,
     ) {
         let mut finalizer_interval = interval(Duration::from_secs(300)); // 5 minutes
 
         loop {
             finalizer_interval.tick().await;
-            let now = Utc::now();
-            let max_age = chrono::Duration::hours(24); // Auto-finalize after 24 hours
+            let now = ClockStamp::now();
+            let max_age = Duration::from_secs(24 * 3600); // Auto-finalize after 24 hours
 
             // Find CDRs that should be auto-finalized
             let to_finalize: Vec<(String, CallDetailRecord)> = active_cdrs
                 .iter()
                 .filter(|entry| {
-                    now.signed_duration_since(entry.value().start_time) > max_age
+                    let age = match entry.value().start_clock {
+                        Some(start) => now.duration_since(&start),
+                        None => now.utc.signed_duration_since(entry.value().start_time).to_std().unwrap_or_default(),
+                    };
+                    age > max_age
                 })
                 .map(|entry| (entry.key().clone(), entry.value().clone()))
                 .collect();
 
             for (cdr_id, mut cdr) in to_finalize {
                 // Auto-finalize with timeout reason
-                cdr.end_time = Some(now);
+                let end_time = cdr.timeline_utc(&now);
+                cdr.end_time = Some(end_time);
                 cdr.disconnect_reason = Some(DisconnectReason::Timeout);
-                cdr.duration_seconds = max_age.num_seconds() as u64;
+                cdr.duration_seconds = max_age.as_secs();
+                cdr.note_end_timing(TimestampQuality::at(time_health.as_deref(), now.instant));
 
                 // Remove from active CDRs
                 active_cdrs.remove(&cdr_id);
 
                 // Store CDR
+                cdr.sequence = Some(sequencer.next());
                 if let Err(e) = storage.store_cdr(&cdr).await {
                     error!("Failed to auto-finalize CDR {}: {}", cdr_id, e);
                 } else {
                     let _ = event_tx.send(CdrEvent::CallEnded {
                         cdr_id: cdr_id.clone(),
-                        end_time: now,
+                        end_time,
                         reason: DisconnectReason::Timeout,
                         duration: Duration::from_secs(cdr.duration_seconds),
                     });
@@ -806,6 +1282,15 @@ impl CdrService {
         self.active_cdrs.len()
     }
 
+    pub async fn query_cdrs(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        filters: HashMap<String, String>,
+    ) -> Result<Vec<CallDetailRecord>> {
+        self.storage.query_cdrs(start_time, end_time, filters).await
+    }
+
     pub async fn get_cdr_statistics(
         &self,
         start_time: DateTime<Utc>,
@@ -818,7 +1303,7 @@ impl CdrService {
         info!("Stopping CDR service");
 
         // Finalize all active CDRs
-        let now = Utc::now();
+        let now = ClockStamp::now();
         let active_cdr_ids: Vec<String> = self.active_cdrs.iter().map(|entry| entry.key().clone()).collect();
         
         for cdr_id in active_cdr_ids {
@@ -832,19 +1317,16 @@ impl CdrService {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use std::collections::HashMap;
     use tempfile::TempDir;
 
-    #[tokio::test]
-    async fn test_file_cdr_storage() {
-        let temp_dir = TempDir::new().unwrap();
-        let storage = FileCdrStorage::new(temp_dir.path().to_path_buf(), 10);
-
-        let cdr = CallDetailRecord {
+    pub(crate) fn sample_cdr() -> CallDetailRecord {
+        CallDetailRecord {
             id: "test-cdr".to_string(),
             call_id: "test-call".to_string(),
+            correlation_id: Some("corr-1".to_string()),
             session_id: "test-session".to_string(),
             caller: "1000".to_string(),
             callee: "2000".to_string(),
@@ -854,6 +1336,7 @@ mod tests {
             call_type: CallType::Voice,
             route_type: RouteType::Direct,
             start_time: Utc::now(),
+            early_media_time: None,
             answer_time: Some(Utc::now()),
             end_time: Some(Utc::now()),
             duration_seconds: 60,
@@ -871,6 +1354,7 @@ mod tests {
                 rtp_bytes_sent: 480000,
                 rtp_bytes_received: 472000,
                 transcoding_used: false,
+                burst_gap: None,
             },
             billing_info: BillingInfo {
                 account_id: "test-account".to_string(),
@@ -892,6 +1376,9 @@ mod tests {
                 number_translation_applied: false,
                 routing_decision_time_ms: 5,
                 failover_attempts: 0,
+                legs: Vec::new(),
+                ingress_port: None,
+                egress_port: None,
             },
             media_info: MediaCdrInfo {
                 leg_a_codec: "G711U".to_string(),
@@ -900,6 +1387,8 @@ mod tests {
                 media_relay_used: true,
                 dtmf_events: vec![],
                 media_processing_enabled: false,
+                leg_a_security: Some(LegSecurity::Plaintext),
+                leg_b_security: None,
             },
             compliance_info: ComplianceInfo {
                 jurisdiction: "US".to_string(),
@@ -913,7 +1402,27 @@ mod tests {
                     location_tracking_enabled: false,
                 },
             },
-        };
+            custom_fields: BTreeMap::from([("campaign".to_string(), "spring, 2025".to_string())]),
+            sequence: None,
+            timestamp_quality: TimestampQuality::Synchronized,
+            billing_review: false,
+            precedence: None,
+            transfers: Vec::new(),
+            start_clock: None,
+            answer_instant: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_cdr_storage() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FileCdrStorage::new(temp_dir.path().to_path_buf(), 10);
+        let cdr = sample_cdr();
+
+        let header = CallDetailRecord::csv_header(&["campaign".to_string()]);
+        assert!(header.ends_with(",currency,timestamp_quality,billing_review,leg_a_media_security,leg_b_media_security,resource_priority,precedence_level,transferred_to,correlation_id,campaign"));
+        let row = cdr.to_csv_row(&["campaign".to_string()]);
+        assert!(row.ends_with(",false,plaintext,,,,,corr-1,\"spring, 2025\""));
 
         let result = storage.store_cdr(&cdr).await;
         assert!(result.is_ok());
@@ -951,4 +1460,101 @@ mod tests {
         let cost = service.calculate_call_cost(120, &billing_info);
         assert_eq!(cost, 0.20); // 2 minutes * $0.10/minute
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_duration_survives_wall_clock_steps() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FileCdrStorage::new(temp_dir.path().to_path_buf(), 10));
+        let mut service = CdrService::new(storage, BillingConfig::default());
+        let mut events = service.take_event_receiver().unwrap();
+
+        let cdr_id = service
+            .start_tandem_call_record("call-1", "1000", "2000", "2000", "rule", 2, "1/1".to_string(), "2/1".to_string())
+            .await
+            .unwrap();
+        let start = service.active_cdrs.get(&cdr_id).unwrap().start_clock.unwrap();
+
+        // Answered 5s in, after NTP stepped the wall clock back ten minutes
+        let answered = ClockStamp::new(start.utc - chrono::Duration::minutes(10), start.instant + Duration::from_secs(5));
+        service.update_call_answered_at(&cdr_id, answered).await.unwrap();
+
+        // Released two minutes later, after a step forward of an hour
+        let ended = ClockStamp::new(start.utc + chrono::Duration::hours(1), answered.instant + Duration::from_secs(120));
+        service.finalize_call_record(&cdr_id, ended, DisconnectReason::Normal).await.unwrap();
+
+        let (mut answer_time, mut ended_event) = (None, None);
+        while let Ok(event) = events.try_recv() {
+            match event {
+                CdrEvent::CallAnswered { answer_time: at, .. } => answer_time = Some(at),
+                CdrEvent::CallEnded { end_time, duration, .. } => ended_event = Some((end_time, duration)),
+                _ => {}
+            }
+        }
+        assert_eq!(answer_time, Some(start.utc + chrono::Duration::seconds(5)));
+        assert_eq!(ended_event, Some((start.utc + chrono::Duration::seconds(125), Duration::from_secs(120))));
+    }
+
+    #[tokio::test]
+    async fn test_holdover_flags_record_for_billing_review() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FileCdrStorage::new(temp_dir.path().to_path_buf(), 10));
+        let mut service = CdrService::new(storage, BillingConfig::default());
+        let health = Arc::new(TimeHealth::new(Duration::from_secs(600)));
+        service.set_time_health(Arc::clone(&health));
+
+        let cdr_id = service
+            .start_tandem_call_record("call-1", "1000", "2000", "2000", "rule", 2, "1/1".to_string(), "2/1".to_string())
+            .await
+            .unwrap();
+        let start = service.active_cdrs.get(&cdr_id).unwrap().start_clock.unwrap();
+        assert_eq!(service.active_cdrs.get(&cdr_id).unwrap().timestamp_quality, TimestampQuality::Synchronized);
+
+        // Reference lost as the call started; released 15 minutes later
+        health.enter_holdover(start.instant);
+        let ended = ClockStamp::new(start.utc, start.instant + Duration::from_secs(900));
+        service.finalize_call_record(&cdr_id, ended, DisconnectReason::Normal).await.unwrap();
+
+        let file = std::fs::read_dir(temp_dir.path()).unwrap().next().unwrap().unwrap().path();
+        let stored: CallDetailRecord = serde_json::from_str(std::fs::read_to_string(file).unwrap().lines().last().unwrap()).unwrap();
+        assert_eq!(stored.timestamp_quality, TimestampQuality::Holdover);
+        assert!(stored.billing_review);
+    }
+
+    #[test]
+    fn test_custom_field_extraction() {
+        let fields = vec![
+            CdrCustomField {
+                name: "campaign_id".to_string(),
+                source: CdrFieldSource::SipHeader { header: "X-Campaign-ID".to_string() },
+                max_length: Some(8),
+            },
+            CdrCustomField {
+                name: "charge_info".to_string(),
+                source: CdrFieldSource::SipHeader { header: "P-Charge-Info".to_string() },
+                max_length: None,
+            },
+            CdrCustomField {
+                name: "uui".to_string(),
+                source: CdrFieldSource::Q931Uui,
+                max_length: None,
+            },
+        ];
+        let headers = vec![
+            ("x-campaign-id".to_string(), " spring-sale-2025 ".to_string()),
+            ("From".to_string(), "<sip:1000@example.com>".to_string()),
+        ];
+        let uui = [0x04, b'A', b'C', b'C', b'T', b'1'];
+
+        let values = extract_custom_fields(&fields, &headers, Some(&uui));
+        assert_eq!(values.get("campaign_id"), Some(&"spring-s".to_string()));
+        assert_eq!(values.get("charge_info"), None);
+        assert_eq!(values.get("uui"), Some(&"ACCT1".to_string()));
+
+        let values = extract_custom_fields(&fields, &[], Some(&[0x00, 0xff]));
+        assert_eq!(values.get("uui"), Some(&"00ff".to_string()));
+
+        // Interworked from the SETUP into the INVITE
+        let headers = vec![("User-to-User".to_string(), "044143435431;encoding=hex;purpose=isdn-uui".to_string())];
+        assert_eq!(sip_user_to_user(&headers), Some(uui.to_vec()));
+    }
+}
@@ -0,0 +1,185 @@
+//! Recent calls per B-channel
+//!
+//! Field technicians chasing a fault on one timeslot need to know what
+//! happened on it earlier: who called whom, when, how the call was released
+//! and how it sounded. Each channel keeps its last few calls in a small ring
+//! buffer, fed from the span drivers' call events, and the call in progress
+//! is listed alongside them.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ChannelHistoryConfig;
+use crate::interfaces::freetdm::FreeTdmEvent;
+
+/// Management API path listing a span's recent calls, filtered by the
+/// `span` and optional `channel` query parameters
+pub const CHANNEL_HISTORY_PATH: &str = "/api/v1/channels/history";
+
+/// One call on a B-channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ChannelCall {
+    pub span_id: u32,
+    pub channel_id: u8,
+    pub calling_number: Option<String>,
+    pub called_number: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub answered_at: Option<DateTime<Utc>>,
+    /// Unset while the call is in progress
+    pub ended_at: Option<DateTime<Utc>>,
+    /// Q.850 cause the call was released with, when one was signalled
+    pub release_cause: Option<u16>,
+    pub mos: Option<f32>,
+}
+
+/// Last few calls of every channel
+pub struct ChannelHistory {
+    calls_per_channel: usize,
+    active: DashMap<(u32, u8), ChannelCall>,
+    finished: DashMap<(u32, u8), VecDeque<ChannelCall>>,
+}
+
+impl ChannelHistory {
+    pub fn new(config: &ChannelHistoryConfig) -> Self {
+        Self {
+            calls_per_channel: config.calls_per_channel,
+            active: DashMap::new(),
+            finished: DashMap::new(),
+        }
+    }
+
+    /// Follow a span driver's call events
+    pub fn on_event(&self, event: &FreeTdmEvent) {
+        match *event {
+            FreeTdmEvent::IncomingCall { span_id, channel_id, ref calling_number, ref called_number, .. } => {
+                // A call still open here missed its hangup; close it unexplained
+                self.finish(span_id, channel_id, None);
+                self.active.insert((span_id, channel_id), ChannelCall {
+                    span_id,
+                    channel_id,
+                    calling_number: calling_number.clone(),
+                    called_number: called_number.clone(),
+                    started_at: Utc::now(),
+                    answered_at: None,
+                    ended_at: None,
+                    release_cause: None,
+                    mos: None,
+                });
+            }
+            FreeTdmEvent::CallAnswered { span_id, channel_id, received } => {
+                if let Some(mut call) = self.active.get_mut(&(span_id, channel_id)) {
+                    call.answered_at.get_or_insert(received.utc);
+                }
+            }
+            FreeTdmEvent::CallHangup { span_id, channel_id, cause } => self.finish(span_id, channel_id, Some(cause)),
+            FreeTdmEvent::SpanDown { span_id } => {
+                let channels: Vec<u8> = self
+                    .active
+                    .iter()
+                    .filter(|call| call.span_id == span_id)
+                    .map(|call| call.channel_id)
+                    .collect();
+                for channel_id in channels {
+                    self.finish(span_id, channel_id, None);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Voice quality measured for the call on a channel, in progress or the
+    /// one that just ended
+    pub fn record_mos(&self, span_id: u32, channel_id: u8, mos: f32) {
+        if let Some(mut call) = self.active.get_mut(&(span_id, channel_id)) {
+            call.mos = Some(mos);
+        } else if let Some(mut calls) = self.finished.get_mut(&(span_id, channel_id)) {
+            if let Some(call) = calls.back_mut() {
+                call.mos = Some(mos);
+            }
+        }
+    }
+
+    fn finish(&self, span_id: u32, channel_id: u8, cause: Option<u16>) {
+        let Some((_, mut call)) = self.active.remove(&(span_id, channel_id)) else {
+            return;
+        };
+        call.ended_at = Some(Utc::now());
+        call.release_cause = cause;
+        let mut calls = self.finished.entry((span_id, channel_id)).or_default();
+        if calls.len() == self.calls_per_channel {
+            calls.pop_front();
+        }
+        calls.push_back(call);
+    }
+
+    /// Calls on `span_id`, or on one of its channels, newest first with
+    /// calls in progress ahead of finished ones
+    pub fn history(&self, span_id: u32, channel_id: Option<u8>) -> Vec<ChannelCall> {
+        let on_port = |&(span, channel): &(u32, u8)| span == span_id && channel_id.map_or(true, |wanted| channel == wanted);
+        let mut active: Vec<ChannelCall> = self
+            .active
+            .iter()
+            .filter(|call| on_port(call.key()))
+            .map(|call| call.value().clone())
+            .collect();
+        let mut finished: Vec<ChannelCall> = self
+            .finished
+            .iter()
+            .filter(|calls| on_port(calls.key()))
+            .flat_map(|calls| calls.value().iter().cloned().collect::<Vec<_>>())
+            .collect();
+        active.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        finished.sort_by(|a, b| b.ended_at.cmp(&a.ended_at));
+        active.extend(finished);
+        active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ClockStamp;
+
+    fn call(channel_id: u8, caller: &str) -> FreeTdmEvent {
+        FreeTdmEvent::IncomingCall {
+            span_id: 1,
+            channel_id,
+            calling_number: Some(caller.to_string()),
+            called_number: Some("5551000".to_string()),
+            user_user: None,
+        }
+    }
+
+    #[test]
+    fn test_history_keeps_last_calls_per_channel() {
+        let history = ChannelHistory::new(&ChannelHistoryConfig { enabled: true, calls_per_channel: 2 });
+        for caller in ["5550001", "5550002", "5550003"] {
+            history.on_event(&call(7, caller));
+            history.on_event(&FreeTdmEvent::CallAnswered { span_id: 1, channel_id: 7, received: ClockStamp::now() });
+            history.on_event(&FreeTdmEvent::CallHangup { span_id: 1, channel_id: 7, cause: 16 });
+        }
+        history.record_mos(1, 7, 4.1);
+        history.on_event(&call(8, "5550004"));
+
+        let calls = history.history(1, Some(7));
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].calling_number.as_deref(), Some("5550003"));
+        assert_eq!((calls[0].release_cause, calls[0].mos), (Some(16), Some(4.1)));
+        assert!(calls[0].answered_at.is_some() && calls[0].ended_at.is_some());
+        assert_eq!(calls[1].calling_number.as_deref(), Some("5550002"));
+
+        // The call in progress leads the span's list
+        let calls = history.history(1, None);
+        assert_eq!(calls.len(), 3);
+        assert_eq!((calls[0].channel_id, calls[0].ended_at), (8, None));
+        assert!(history.history(2, None).is_empty());
+
+        history.on_event(&FreeTdmEvent::SpanDown { span_id: 1 });
+        let calls = history.history(1, Some(8));
+        assert!(calls[0].ended_at.is_some() && calls[0].release_cause.is_none());
+    }
+}
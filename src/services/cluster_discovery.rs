@@ -0,0 +1,949 @@
+//! Cluster peer auto-discovery via mDNS and DNS-SD
+//!
+//! Each node advertises a DNS-SD service instance (PTR/SRV/TXT/A) whose TXT
+//! record carries the node's capabilities and an HMAC token over its
+//! identity. Advertisements can be exchanged by multicast on the local link
+//! or browsed from a unicast DNS domain. A discovered peer is only reported
+//! as joinable after it answers a challenge on its sync port, proving it
+//! holds the cluster's shared secret. The same port answers the liveness
+//! probes used to measure per-peer round-trip time.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use sha2::Sha256;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, timeout};
+use tracing::{debug, info, warn};
+
+use crate::config::{ClusterDiscoveryConfig, ClusteringConfig};
+use crate::services::clustering::NodeCapabilities;
+use crate::{Error, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const AUTH_MAGIC: &str = "REDFIRE-AUTH/1";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// Resource records understood by the discovery codec
+#[derive(Debug, Clone, PartialEq)]
+pub enum DnsRecord {
+    Ptr { name: String, target: String },
+    Srv { name: String, priority: u16, weight: u16, port: u16, target: String },
+    Txt { name: String, entries: Vec<String> },
+    A { name: String, address: Ipv4Addr },
+}
+
+impl DnsRecord {
+    pub fn name(&self) -> &str {
+        match self {
+            DnsRecord::Ptr { name, .. }
+            | DnsRecord::Srv { name, .. }
+            | DnsRecord::Txt { name, .. }
+            | DnsRecord::A { name, .. } => name,
+        }
+    }
+}
+
+/// Minimal DNS message encoding/decoding for service discovery
+pub struct DnsMessage;
+
+impl DnsMessage {
+    pub fn encode_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&0x0100u16.to_be_bytes()); // recursion desired
+        buf.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        Self::encode_name(&mut buf, name);
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+        buf
+    }
+
+    pub fn encode_response(records: &[DnsRecord], ttl: u32) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(512);
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&0x8400u16.to_be_bytes()); // response, authoritative
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&[0, 0, 0, 0]);
+
+        for record in records {
+            Self::encode_name(&mut buf, record.name());
+            let (rtype, rdata) = match record {
+                DnsRecord::Ptr { target, .. } => {
+                    let mut data = Vec::new();
+                    Self::encode_name(&mut data, target);
+                    (TYPE_PTR, data)
+                }
+                DnsRecord::Srv { priority, weight, port, target, .. } => {
+                    let mut data = Vec::new();
+                    data.extend_from_slice(&priority.to_be_bytes());
+                    data.extend_from_slice(&weight.to_be_bytes());
+                    data.extend_from_slice(&port.to_be_bytes());
+                    Self::encode_name(&mut data, target);
+                    (TYPE_SRV, data)
+                }
+                DnsRecord::Txt { entries, .. } => {
+                    let mut data = Vec::new();
+                    for entry in entries {
+                        let bytes = &entry.as_bytes()[..entry.len().min(255)];
+                        data.push(bytes.len() as u8);
+                        data.extend_from_slice(bytes);
+                    }
+                    (TYPE_TXT, data)
+                }
+                DnsRecord::A { address, .. } => (TYPE_A, address.octets().to_vec()),
+            };
+            buf.extend_from_slice(&rtype.to_be_bytes());
+            buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+            buf.extend_from_slice(&ttl.to_be_bytes());
+            buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            buf.extend_from_slice(&rdata);
+        }
+        buf
+    }
+
+    /// Questions carried by a query, as (name, type) pairs
+    pub fn parse_questions(data: &[u8]) -> Result<Vec<(String, u16)>> {
+        let header = Self::header(data)?;
+        if header.is_response {
+            return Ok(vec![]);
+        }
+        let mut pos = 12;
+        let mut questions = Vec::with_capacity(header.qdcount as usize);
+        for _ in 0..header.qdcount {
+            let name = Self::read_name(data, &mut pos)?;
+            let qtype = Self::read_u16(data, pos)?;
+            pos += 4;
+            questions.push((name, qtype));
+        }
+        Ok(questions)
+    }
+
+    /// Answer, authority and additional records of a response
+    pub fn parse_records(data: &[u8]) -> Result<Vec<DnsRecord>> {
+        let header = Self::header(data)?;
+        if !header.is_response {
+            return Ok(vec![]);
+        }
+        let mut pos = 12;
+        for _ in 0..header.qdcount {
+            Self::read_name(data, &mut pos)?;
+            pos += 4;
+        }
+
+        let total = header.ancount as usize + header.nscount as usize + header.arcount as usize;
+        let mut records = Vec::with_capacity(total);
+        for _ in 0..total {
+            let name = Self::read_name(data, &mut pos)?;
+            let rtype = Self::read_u16(data, pos)?;
+            let rdlength = Self::read_u16(data, pos + 8)? as usize;
+            let start = pos + 10;
+            let end = start + rdlength;
+            if end > data.len() {
+                return Err(Error::parse("DNS record data truncated"));
+            }
+            pos = end;
+
+            let record = match rtype {
+                TYPE_PTR => {
+                    let mut p = start;
+                    DnsRecord::Ptr { name, target: Self::read_name(data, &mut p)? }
+                }
+                TYPE_SRV if rdlength >= 7 => {
+                    let mut p = start + 6;
+                    DnsRecord::Srv {
+                        name,
+                        priority: Self::read_u16(data, start)?,
+                        weight: Self::read_u16(data, start + 2)?,
+                        port: Self::read_u16(data, start + 4)?,
+                        target: Self::read_name(data, &mut p)?,
+                    }
+                }
+                TYPE_TXT => {
+                    let mut entries = Vec::new();
+                    let mut p = start;
+                    while p < end {
+                        let len = data[p] as usize;
+                        let entry_end = (p + 1 + len).min(end);
+                        entries.push(String::from_utf8_lossy(&data[p + 1..entry_end]).into_owned());
+                        p = entry_end;
+                    }
+                    DnsRecord::Txt { name, entries }
+                }
+                TYPE_A if rdlength == 4 => DnsRecord::A {
+                    name,
+                    address: Ipv4Addr::new(data[start], data[start + 1], data[start + 2], data[start + 3]),
+                },
+                _ => continue,
+            };
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn header(data: &[u8]) -> Result<DnsHeader> {
+        if data.len() < 12 {
+            return Err(Error::parse("DNS message shorter than header"));
+        }
+        Ok(DnsHeader {
+            is_response: data[2] & 0x80 != 0,
+            qdcount: Self::read_u16(data, 4)?,
+            ancount: Self::read_u16(data, 6)?,
+            nscount: Self::read_u16(data, 8)?,
+            arcount: Self::read_u16(data, 10)?,
+        })
+    }
+
+    fn encode_name(buf: &mut Vec<u8>, name: &str) {
+        for label in name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()) {
+            let bytes = &label.as_bytes()[..label.len().min(63)];
+            buf.push(bytes.len() as u8);
+            buf.extend_from_slice(bytes);
+        }
+        buf.push(0);
+    }
+
+    fn read_name(data: &[u8], pos: &mut usize) -> Result<String> {
+        let mut labels = Vec::new();
+        let mut cursor = *pos;
+        let mut jumped = false;
+        let mut jumps = 0;
+
+        loop {
+            let len = *data.get(cursor).ok_or_else(|| Error::parse("DNS name truncated"))? as usize;
+            if len & 0xC0 == 0xC0 {
+                let low = *data.get(cursor + 1).ok_or_else(|| Error::parse("DNS pointer truncated"))? as usize;
+                if !jumped {
+                    *pos = cursor + 2;
+                }
+                jumped = true;
+                jumps += 1;
+                if jumps > 16 {
+                    return Err(Error::parse("DNS name compression loop"));
+                }
+                cursor = ((len & 0x3F) << 8) | low;
+                continue;
+            }
+            if len == 0 {
+                if !jumped {
+                    *pos = cursor + 1;
+                }
+                break;
+            }
+            let label = data
+                .get(cursor + 1..cursor + 1 + len)
+                .ok_or_else(|| Error::parse("DNS label truncated"))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            cursor += 1 + len;
+        }
+        Ok(labels.join("."))
+    }
+
+    fn read_u16(data: &[u8], pos: usize) -> Result<u16> {
+        data.get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(|| Error::parse("DNS message truncated"))
+    }
+}
+
+struct DnsHeader {
+    is_response: bool,
+    qdcount: u16,
+    ancount: u16,
+    nscount: u16,
+    arcount: u16,
+}
+
+/// A node's advertised identity and capabilities
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerAdvertisement {
+    pub cluster_id: String,
+    pub node_id: String,
+    pub address: SocketAddr,
+    pub capabilities: NodeCapabilities,
+    /// Unix time the advertisement was signed
+    pub timestamp: u64,
+    pub token: String,
+}
+
+impl PeerAdvertisement {
+    pub fn new(
+        cluster_id: &str,
+        node_id: &str,
+        address: SocketAddr,
+        capabilities: NodeCapabilities,
+        secret: &str,
+    ) -> Self {
+        let mut advertisement = Self {
+            cluster_id: cluster_id.to_string(),
+            node_id: node_id.to_string(),
+            address,
+            capabilities,
+            timestamp: chrono::Utc::now().timestamp().max(0) as u64,
+            token: String::new(),
+        };
+        advertisement.token = hex::encode(advertisement.signature(secret));
+        advertisement
+    }
+
+    fn signed_payload(&self) -> String {
+        format!("advert|{}|{}|{}|{}", self.cluster_id, self.node_id, self.address.port(), self.timestamp)
+    }
+
+    fn signature(&self, secret: &str) -> Vec<u8> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(self.signed_payload().as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Check cluster membership, freshness and the advertisement token
+    pub fn verify(&self, cluster_id: &str, secret: &str, now: u64, max_skew: u64) -> std::result::Result<(), String> {
+        if self.cluster_id != cluster_id {
+            return Err(format!("belongs to cluster {}", self.cluster_id));
+        }
+        if now.abs_diff(self.timestamp) > max_skew {
+            return Err(format!("advertisement timestamp {} outside allowed skew", self.timestamp));
+        }
+        let token = hex::decode(&self.token).map_err(|_| "malformed token".to_string())?;
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(self.signed_payload().as_bytes());
+        mac.verify_slice(&token).map_err(|_| "advertisement token mismatch".to_string())
+    }
+
+    pub fn instance_name(&self, service: &str) -> String {
+        format!("{}.{}", self.node_id, service)
+    }
+
+    pub fn txt_entries(&self) -> Vec<String> {
+        vec![
+            "ver=1".to_string(),
+            format!("cluster={}", self.cluster_id),
+            format!("node={}", self.node_id),
+            format!("max_calls={}", self.capabilities.max_calls),
+            format!("transcoding={}", self.capabilities.supports_transcoding),
+            format!("backend={}", self.capabilities.transcoding_backend),
+            format!("codecs={}", self.capabilities.supported_codecs.join(",")),
+            format!("ts={}", self.timestamp),
+            format!("auth={}", self.token),
+        ]
+    }
+
+    /// DNS-SD records announcing this node under `service` in `domain`
+    pub fn to_records(&self, service: &str, domain: &str) -> Vec<DnsRecord> {
+        let service = format!("{}.{}", service, domain);
+        let instance = self.instance_name(&service);
+        let host = format!("{}.{}", self.node_id, domain);
+        let mut records = vec![
+            DnsRecord::Ptr { name: service, target: instance.clone() },
+            DnsRecord::Srv {
+                name: instance.clone(),
+                priority: 0,
+                weight: 0,
+                port: self.address.port(),
+                target: host.clone(),
+            },
+            DnsRecord::Txt { name: instance, entries: self.txt_entries() },
+        ];
+        if let IpAddr::V4(address) = self.address.ip() {
+            records.push(DnsRecord::A { name: host, address });
+        }
+        records
+    }
+
+    /// Assemble advertisements from a set of records.
+    ///
+    /// `resolve` maps SRV targets without an accompanying A record to an address.
+    pub fn from_records<F>(records: &[DnsRecord], service: &str, mut resolve: F) -> Vec<PeerAdvertisement>
+    where
+        F: FnMut(&str) -> Option<IpAddr>,
+    {
+        let mut hosts: HashMap<&str, IpAddr> = HashMap::new();
+        let mut srv: HashMap<&str, (u16, &str)> = HashMap::new();
+        let mut txt: HashMap<&str, &[String]> = HashMap::new();
+        for record in records {
+            match record {
+                DnsRecord::A { name, address } => {
+                    hosts.insert(name, IpAddr::V4(*address));
+                }
+                DnsRecord::Srv { name, port, target, .. } => {
+                    srv.insert(name, (*port, target));
+                }
+                DnsRecord::Txt { name, entries } => {
+                    txt.insert(name, entries);
+                }
+                DnsRecord::Ptr { .. } => {}
+            }
+        }
+
+        let suffix = format!(".{}", service);
+        records
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::Ptr { name, target } if name.eq_ignore_ascii_case(service) && target.ends_with(&suffix) => {
+                    Some(target.as_str())
+                }
+                _ => None,
+            })
+            .filter_map(|instance| {
+                let (port, host) = *srv.get(instance)?;
+                let entries = txt.get(instance)?;
+                let ip = hosts.get(host).copied().or_else(|| resolve(host))?;
+                Self::from_txt(entries, SocketAddr::new(ip, port))
+            })
+            .collect()
+    }
+
+    fn from_txt(entries: &[String], address: SocketAddr) -> Option<Self> {
+        let fields: HashMap<&str, &str> = entries.iter().filter_map(|e| e.split_once('=')).collect();
+        if fields.get("ver") != Some(&"1") {
+            return None;
+        }
+        Some(Self {
+            cluster_id: fields.get("cluster")?.to_string(),
+            node_id: fields.get("node")?.to_string(),
+            address,
+            capabilities: NodeCapabilities {
+                max_calls: fields.get("max_calls").and_then(|v| v.parse().ok()).unwrap_or(0),
+                supports_transcoding: fields.get("transcoding") == Some(&"true"),
+                transcoding_backend: fields.get("backend").unwrap_or(&"").to_string(),
+                supported_codecs: fields
+                    .get("codecs")
+                    .map(|c| c.split(',').filter(|s| !s.is_empty()).map(str::to_string).collect())
+                    .unwrap_or_default(),
+            },
+            timestamp: fields.get("ts")?.parse().ok()?,
+            token: fields.get("auth")?.to_string(),
+        })
+    }
+}
+
+/// How a peer was found
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiscoverySource {
+    Mdns,
+    DnsSd,
+}
+
+/// Discovery events
+#[derive(Debug, Clone)]
+pub enum DiscoveryEvent {
+    /// Peer proved possession of the cluster secret and may join
+    PeerAuthenticated {
+        advertisement: PeerAdvertisement,
+        source: DiscoverySource,
+    },
+    /// Already-authenticated peer re-advertised itself
+    PeerRefreshed { node_id: String },
+    PeerRejected {
+        node_id: String,
+        address: SocketAddr,
+        reason: String,
+    },
+    PeerExpired { node_id: String },
+}
+
+#[derive(Debug, Clone)]
+struct KnownPeer {
+    address: SocketAddr,
+    last_seen: Instant,
+}
+
+/// Advertises the local node and authenticates discovered peers
+pub struct ClusterDiscovery {
+    config: ClusterDiscoveryConfig,
+    cluster_id: String,
+    node_id: String,
+    sync_port: u16,
+    capabilities: NodeCapabilities,
+    peers: Arc<DashMap<String, KnownPeer>>,
+    pending_challenges: Arc<DashMap<String, oneshot::Sender<(String, Vec<u8>)>>>,
+    pending_pings: DashMap<String, oneshot::Sender<()>>,
+    auth_socket: OnceCell<Arc<UdpSocket>>,
+    event_tx: mpsc::UnboundedSender<DiscoveryEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<DiscoveryEvent>>,
+}
+
+impl ClusterDiscovery {
+    pub fn new(cluster: &ClusteringConfig, capabilities: NodeCapabilities) -> Result<Self> {
+        let config = cluster.discovery.clone();
+        if config.shared_secret.is_empty() {
+            return Err(Error::invalid_config("cluster discovery requires a shared_secret"));
+        }
+        if !config.mdns && config.dns_domain.is_none() {
+            return Err(Error::invalid_config("cluster discovery needs mdns or a dns_domain"));
+        }
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        Ok(Self {
+            config,
+            cluster_id: cluster.cluster_id.clone(),
+            node_id: cluster.node_id.clone(),
+            sync_port: cluster.sync_port,
+            capabilities,
+            peers: Arc::new(DashMap::new()),
+            pending_challenges: Arc::new(DashMap::new()),
+            pending_pings: DashMap::new(),
+            auth_socket: OnceCell::new(),
+            event_tx,
+            event_rx: Some(event_rx),
+        })
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<DiscoveryEvent>> {
+        self.event_rx.take()
+    }
+
+    pub async fn start(self: &Arc<Self>) -> Result<()> {
+        let local_ip = self.advertise_ip().await?;
+        let auth_socket = Arc::new(
+            UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), self.sync_port))
+                .await
+                .map_err(|e| Error::network(format!("Failed to bind cluster auth port {}: {}", self.sync_port, e)))?,
+        );
+
+        let _ = self.auth_socket.set(Arc::clone(&auth_socket));
+        let this = Arc::clone(self);
+        let socket = Arc::clone(&auth_socket);
+        tokio::spawn(async move { this.auth_loop(socket).await });
+
+        let this = Arc::clone(self);
+        tokio::spawn(async move { this.expiry_loop().await });
+
+        if self.config.mdns {
+            let mdns_socket = Arc::new(Self::bind_mdns()?);
+            let this = Arc::clone(self);
+            let socket = Arc::clone(&mdns_socket);
+            tokio::spawn(async move { this.mdns_announce_loop(socket, local_ip).await });
+
+            let this = Arc::clone(self);
+            let auth = Arc::clone(&auth_socket);
+            tokio::spawn(async move { this.mdns_receive_loop(mdns_socket, auth, local_ip).await });
+        }
+
+        if let Some(domain) = self.config.dns_domain.clone() {
+            let server = self.dns_server()?;
+            let this = Arc::clone(self);
+            tokio::spawn(async move { this.dns_sd_browse_loop(auth_socket, domain, server).await });
+        }
+
+        info!(
+            "Cluster discovery started for node {} (mdns: {}, domain: {:?})",
+            self.node_id, self.config.mdns, self.config.dns_domain
+        );
+        Ok(())
+    }
+
+    fn advertisement(&self, ip: IpAddr) -> PeerAdvertisement {
+        PeerAdvertisement::new(
+            &self.cluster_id,
+            &self.node_id,
+            SocketAddr::new(ip, self.sync_port),
+            self.capabilities.clone(),
+            &self.config.shared_secret,
+        )
+    }
+
+    async fn advertise_ip(&self) -> Result<IpAddr> {
+        if let Some(address) = &self.config.advertise_address {
+            return address
+                .parse()
+                .map_err(|_| Error::invalid_config(format!("Invalid advertise_address: {}", address)));
+        }
+        // Connecting a UDP socket selects the outbound interface without sending anything
+        let probe = UdpSocket::bind("0.0.0.0:0").await?;
+        probe.connect(SocketAddr::V4(SocketAddrV4::new(MDNS_GROUP, MDNS_PORT))).await?;
+        Ok(probe.local_addr()?.ip())
+    }
+
+    fn dns_server(&self) -> Result<SocketAddr> {
+        let server = match &self.config.dns_server {
+            Some(server) => server.clone(),
+            None => std::fs::read_to_string("/etc/resolv.conf")?
+                .lines()
+                .find_map(|line| line.trim().strip_prefix("nameserver").map(|s| s.trim().to_string()))
+                .ok_or_else(|| Error::invalid_config("No DNS server configured for cluster discovery"))?,
+        };
+        server
+            .parse::<SocketAddr>()
+            .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+            .map_err(|_| Error::invalid_config(format!("Invalid DNS server address: {}", server)))
+    }
+
+    fn bind_mdns() -> Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+        socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_ttl_v4(255)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+
+    async fn mdns_announce_loop(self: Arc<Self>, socket: Arc<UdpSocket>, local_ip: IpAddr) {
+        let group = SocketAddr::V4(SocketAddrV4::new(MDNS_GROUP, MDNS_PORT));
+        let service = format!("{}.local", self.config.service_type);
+        let mut announce = interval(Duration::from_secs(self.config.announce_interval_secs.max(1)));
+
+        loop {
+            announce.tick().await;
+            let records = self.advertisement(local_ip).to_records(&self.config.service_type, "local");
+            let ttl = (self.config.announce_interval_secs * 3) as u32;
+            if let Err(e) = socket.send_to(&DnsMessage::encode_response(&records, ttl), group).await {
+                warn!("Failed to send mDNS announcement: {}", e);
+            }
+            // Ask peers to announce themselves so a fresh node converges quickly
+            let _ = socket.send_to(&DnsMessage::encode_query(0, &service, TYPE_PTR), group).await;
+        }
+    }
+
+    async fn mdns_receive_loop(self: Arc<Self>, socket: Arc<UdpSocket>, auth: Arc<UdpSocket>, local_ip: IpAddr) {
+        let service = format!("{}.local", self.config.service_type);
+        let group = SocketAddr::V4(SocketAddrV4::new(MDNS_GROUP, MDNS_PORT));
+        let mut buf = vec![0u8; 9000];
+
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("mDNS receive error: {}", e);
+                    continue;
+                }
+            };
+            let data = &buf[..len];
+
+            if let Ok(questions) = DnsMessage::parse_questions(data) {
+                if questions.iter().any(|(name, qtype)| *qtype == TYPE_PTR && name.eq_ignore_ascii_case(&service)) {
+                    let records = self.advertisement(local_ip).to_records(&self.config.service_type, "local");
+                    let ttl = (self.config.announce_interval_secs * 3) as u32;
+                    let _ = socket.send_to(&DnsMessage::encode_response(&records, ttl), group).await;
+                }
+            }
+
+            let records = match DnsMessage::parse_records(data) {
+                Ok(records) if !records.is_empty() => records,
+                _ => continue,
+            };
+            for advertisement in PeerAdvertisement::from_records(&records, &service, |_| Some(from.ip())) {
+                let this = Arc::clone(&self);
+                let auth = Arc::clone(&auth);
+                tokio::spawn(async move {
+                    this.handle_advertisement(advertisement, DiscoverySource::Mdns, &auth).await;
+                });
+            }
+        }
+    }
+
+    async fn dns_sd_browse_loop(self: Arc<Self>, auth: Arc<UdpSocket>, domain: String, server: SocketAddr) {
+        let service = format!("{}.{}", self.config.service_type, domain);
+        let mut browse = interval(Duration::from_secs(self.config.announce_interval_secs.max(1)));
+
+        loop {
+            browse.tick().await;
+            let advertisements = match self.browse_domain(&service, server).await {
+                Ok(advertisements) => advertisements,
+                Err(e) => {
+                    warn!("DNS-SD browse of {} failed: {}", service, e);
+                    continue;
+                }
+            };
+            for advertisement in advertisements {
+                self.handle_advertisement(advertisement, DiscoverySource::DnsSd, &auth).await;
+            }
+        }
+    }
+
+    async fn browse_domain(&self, service: &str, server: SocketAddr) -> Result<Vec<PeerAdvertisement>> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(server).await?;
+
+        let mut records = Self::dns_lookup(&socket, service, TYPE_PTR).await?;
+        let instances: Vec<String> = records
+            .iter()
+            .filter_map(|r| match r {
+                DnsRecord::Ptr { target, .. } => Some(target.clone()),
+                _ => None,
+            })
+            .collect();
+
+        // Servers may omit SRV/TXT from the additional section
+        for instance in instances {
+            if !records.iter().any(|r| matches!(r, DnsRecord::Srv { name, .. } if *name == instance)) {
+                records.extend(Self::dns_lookup(&socket, &instance, TYPE_SRV).await?);
+            }
+            if !records.iter().any(|r| matches!(r, DnsRecord::Txt { name, .. } if *name == instance)) {
+                records.extend(Self::dns_lookup(&socket, &instance, TYPE_TXT).await?);
+            }
+        }
+
+        let mut resolved = HashMap::new();
+        let targets: Vec<String> = records
+            .iter()
+            .filter_map(|r| match r {
+                DnsRecord::Srv { target, .. } => Some(target.clone()),
+                _ => None,
+            })
+            .collect();
+        for target in targets {
+            if let Ok(mut addrs) = tokio::net::lookup_host((target.as_str(), 0)).await {
+                if let Some(addr) = addrs.next() {
+                    resolved.insert(target, addr.ip());
+                }
+            }
+        }
+
+        Ok(PeerAdvertisement::from_records(&records, service, |host| resolved.get(host).copied()))
+    }
+
+    async fn dns_lookup(socket: &UdpSocket, name: &str, qtype: u16) -> Result<Vec<DnsRecord>> {
+        let id: u16 = rand::random();
+        socket.send(&DnsMessage::encode_query(id, name, qtype)).await?;
+        let mut buf = vec![0u8; 4096];
+        let len = timeout(Duration::from_secs(3), socket.recv(&mut buf))
+            .await
+            .map_err(|_| Error::timeout(format!("DNS query for {} timed out", name)))??;
+        if len < 2 || u16::from_be_bytes([buf[0], buf[1]]) != id {
+            return Err(Error::protocol(format!("Unexpected DNS response for {}", name)));
+        }
+        DnsMessage::parse_records(&buf[..len])
+    }
+
+    async fn handle_advertisement(&self, advertisement: PeerAdvertisement, source: DiscoverySource, auth: &UdpSocket) {
+        if advertisement.node_id == self.node_id {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
+        if let Err(reason) = advertisement.verify(
+            &self.cluster_id,
+            &self.config.shared_secret,
+            now,
+            self.config.max_clock_skew_secs,
+        ) {
+            // Foreign clusters sharing the link are expected; only report our own
+            if advertisement.cluster_id == self.cluster_id {
+                self.reject(&advertisement, reason);
+            }
+            return;
+        }
+
+        if let Some(mut peer) = self.peers.get_mut(&advertisement.node_id) {
+            if peer.address == advertisement.address {
+                peer.last_seen = Instant::now();
+                let _ = self.event_tx.send(DiscoveryEvent::PeerRefreshed { node_id: advertisement.node_id.clone() });
+                return;
+            }
+        }
+
+        match self.challenge(&advertisement, auth).await {
+            Ok(()) => {
+                info!("Authenticated cluster peer {} at {}", advertisement.node_id, advertisement.address);
+                self.peers.insert(
+                    advertisement.node_id.clone(),
+                    KnownPeer { address: advertisement.address, last_seen: Instant::now() },
+                );
+                let _ = self.event_tx.send(DiscoveryEvent::PeerAuthenticated { advertisement, source });
+            }
+            Err(reason) => self.reject(&advertisement, reason),
+        }
+    }
+
+    fn reject(&self, advertisement: &PeerAdvertisement, reason: String) {
+        warn!("Rejected cluster peer {} at {}: {}", advertisement.node_id, advertisement.address, reason);
+        let _ = self.event_tx.send(DiscoveryEvent::PeerRejected {
+            node_id: advertisement.node_id.clone(),
+            address: advertisement.address,
+            reason,
+        });
+    }
+
+    /// Send a fresh nonce to the peer's sync port and verify its keyed response
+    async fn challenge(&self, advertisement: &PeerAdvertisement, auth: &UdpSocket) -> std::result::Result<(), String> {
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let (tx, rx) = oneshot::channel();
+        self.pending_challenges.insert(nonce.clone(), tx);
+
+        let message = format!("{} CHALLENGE {} {} {}", AUTH_MAGIC, self.cluster_id, self.node_id, nonce);
+        if let Err(e) = auth.send_to(message.as_bytes(), advertisement.address).await {
+            self.pending_challenges.remove(&nonce);
+            return Err(format!("challenge send failed: {}", e));
+        }
+
+        let result = timeout(Duration::from_millis(self.config.auth_timeout_ms), rx).await;
+        self.pending_challenges.remove(&nonce);
+        let (responder, mac) = match result {
+            Ok(Ok(response)) => response,
+            _ => return Err("no challenge response".to_string()),
+        };
+        if responder != advertisement.node_id {
+            return Err(format!("challenge answered by {}", responder));
+        }
+        verify_challenge(&self.config.shared_secret, &self.cluster_id, &responder, &nonce, &mac)
+            .then_some(())
+            .ok_or_else(|| "challenge response mismatch".to_string())
+    }
+
+    /// Round-trip time to a peer's sync port, or `None` if it did not answer
+    pub async fn measure_rtt(&self, address: SocketAddr) -> Option<Duration> {
+        let socket = self.auth_socket.get()?;
+        let nonce = hex::encode(rand::random::<[u8; 8]>());
+        let (tx, rx) = oneshot::channel();
+        self.pending_pings.insert(nonce.clone(), tx);
+
+        let started = Instant::now();
+        let message = format!("{} PING {} {}", AUTH_MAGIC, self.cluster_id, nonce);
+        let answered = match socket.send_to(message.as_bytes(), address).await {
+            Ok(_) => matches!(timeout(Duration::from_millis(self.config.auth_timeout_ms), rx).await, Ok(Ok(()))),
+            Err(_) => false,
+        };
+        self.pending_pings.remove(&nonce);
+        answered.then(|| started.elapsed())
+    }
+
+    async fn auth_loop(self: Arc<Self>, socket: Arc<UdpSocket>) {
+        let mut buf = vec![0u8; 1024];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("Cluster auth receive error: {}", e);
+                    continue;
+                }
+            };
+            let text = String::from_utf8_lossy(&buf[..len]);
+            let fields: Vec<&str> = text.split_whitespace().collect();
+
+            match fields.as_slice() {
+                [AUTH_MAGIC, "CHALLENGE", cluster, from_node, nonce] if *cluster == self.cluster_id => {
+                    debug!("Answering cluster auth challenge from {} ({})", from_node, from);
+                    let mac = hex::encode(challenge_response(&self.config.shared_secret, cluster, &self.node_id, nonce));
+                    let reply = format!("{} RESPONSE {} {} {} {}", AUTH_MAGIC, cluster, self.node_id, nonce, mac);
+                    let _ = socket.send_to(reply.as_bytes(), from).await;
+                }
+                [AUTH_MAGIC, "RESPONSE", cluster, node, nonce, mac] if *cluster == self.cluster_id => {
+                    if let (Some((_, tx)), Ok(mac)) = (self.pending_challenges.remove(*nonce), hex::decode(mac)) {
+                        let _ = tx.send((node.to_string(), mac));
+                    }
+                }
+                [AUTH_MAGIC, "PING", cluster, nonce] if *cluster == self.cluster_id => {
+                    let reply = format!("{} PONG {} {}", AUTH_MAGIC, cluster, nonce);
+                    let _ = socket.send_to(reply.as_bytes(), from).await;
+                }
+                [AUTH_MAGIC, "PONG", cluster, nonce] if *cluster == self.cluster_id => {
+                    if let Some((_, tx)) = self.pending_pings.remove(*nonce) {
+                        let _ = tx.send(());
+                    }
+                }
+                _ => debug!("Ignoring unexpected cluster auth datagram from {}", from),
+            }
+        }
+    }
+
+    async fn expiry_loop(self: Arc<Self>) {
+        let ttl = Duration::from_secs(self.config.announce_interval_secs.max(1) * 3);
+        let mut check = interval(ttl);
+        loop {
+            check.tick().await;
+            let expired: Vec<String> = self
+                .peers
+                .iter()
+                .filter(|p| p.last_seen.elapsed() > ttl)
+                .map(|p| p.key().clone())
+                .collect();
+            for node_id in expired {
+                self.peers.remove(&node_id);
+                info!("Discovered cluster peer {} stopped advertising", node_id);
+                let _ = self.event_tx.send(DiscoveryEvent::PeerExpired { node_id });
+            }
+        }
+    }
+}
+
+fn challenge_response(secret: &str, cluster_id: &str, node_id: &str, nonce: &str) -> Vec<u8> {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("challenge|{}|{}|{}", cluster_id, node_id, nonce).as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn verify_challenge(secret: &str, cluster_id: &str, node_id: &str, nonce: &str, response: &[u8]) -> bool {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("challenge|{}|{}|{}", cluster_id, node_id, nonce).as_bytes());
+    mac.verify_slice(response).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities() -> NodeCapabilities {
+        NodeCapabilities {
+            max_calls: 500,
+            supports_transcoding: true,
+            transcoding_backend: "cpu".to_string(),
+            supported_codecs: vec!["g711u".to_string(), "g729".to_string()],
+        }
+    }
+
+    fn advertisement() -> PeerAdvertisement {
+        PeerAdvertisement::new("c1", "node-2", "192.0.2.10:8080".parse().unwrap(), capabilities(), "s3cret")
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let ad = advertisement();
+        let records = ad.to_records("_redfire-cluster._udp", "local");
+        let parsed = DnsMessage::parse_records(&DnsMessage::encode_response(&records, 90)).unwrap();
+        assert_eq!(parsed, records);
+
+        let found = PeerAdvertisement::from_records(&parsed, "_redfire-cluster._udp.local", |_| None);
+        assert_eq!(found, vec![ad]);
+    }
+
+    #[test]
+    fn test_advertisement_verification() {
+        let ad = advertisement();
+        let now = ad.timestamp;
+        assert!(ad.verify("c1", "s3cret", now, 60).is_ok());
+        assert!(ad.verify("c1", "wrong", now, 60).is_err());
+        assert!(ad.verify("c2", "s3cret", now, 60).is_err());
+        assert!(ad.verify("c1", "s3cret", now + 600, 60).is_err());
+
+        let mut forged = ad.clone();
+        forged.node_id = "node-9".to_string();
+        assert!(forged.verify("c1", "s3cret", now, 60).is_err());
+    }
+
+    #[test]
+    fn test_challenge_response() {
+        let mac = challenge_response("s3cret", "c1", "node-2", "abcd");
+        assert!(verify_challenge("s3cret", "c1", "node-2", "abcd", &mac));
+        assert!(!verify_challenge("s3cret", "c1", "node-2", "abce", &mac));
+        assert!(!verify_challenge("other", "c1", "node-2", "abcd", &mac));
+    }
+
+    #[test]
+    fn test_query_questions() {
+        let query = DnsMessage::encode_query(7, "_redfire-cluster._udp.local", TYPE_PTR);
+        assert_eq!(
+            DnsMessage::parse_questions(&query).unwrap(),
+            vec![("_redfire-cluster._udp.local".to_string(), TYPE_PTR)]
+        );
+        assert!(DnsMessage::parse_records(&query).unwrap().is_empty());
+    }
+}
@@ -6,19 +6,21 @@
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, RwLock};
 use tokio::time::interval;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
 use uuid::Uuid;
 
 use crate::config::{ClusteringConfig, SharedStateBackend, ConsensusAlgorithm};
 use crate::services::b2bua::B2buaCallState;
-use crate::Result;
+use crate::services::cluster_discovery::{ClusterDiscovery, DiscoveryEvent};
+use crate::{Error, Result};
 
 /// Cluster node information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,7 +51,7 @@ pub struct NodeLoad {
     pub rtp_sessions: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NodeCapabilities {
     pub max_calls: u32,
     pub supports_transcoding: bool,
@@ -199,12 +201,100 @@ pub enum ClusteringEvent {
         from_node: String,
         transactions_synced: u32,
     },
+    PeerRejected {
+        node_id: String,
+        address: SocketAddr,
+        reason: String,
+    },
     Error {
         node_id: Option<String>,
         message: String,
     },
 }
 
+/// Snapshot of cluster replication and peer health metrics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClusterMetrics {
+    /// Seconds since the last sync round that completed without errors
+    pub replication_lag_secs: f64,
+    pub last_sync_duration_ms: u64,
+    pub sync_rounds: u64,
+    pub sync_failures: u64,
+    pub transactions_synced: u64,
+    pub consensus_rounds: u64,
+    pub consensus_failures: u64,
+    pub migrations: u64,
+    pub migration_failures: u64,
+    /// Round-trip time to each reachable peer in milliseconds
+    pub peer_rtt_ms: HashMap<String, f64>,
+}
+
+/// Lock-free recorder behind [`ClusterMetrics`]
+#[derive(Debug)]
+struct ClusterMetricsRecorder {
+    started_at: Instant,
+    last_successful_sync: Mutex<Option<Instant>>,
+    last_sync_duration_ms: AtomicU64,
+    sync_rounds: AtomicU64,
+    sync_failures: AtomicU64,
+    transactions_synced: AtomicU64,
+    consensus_rounds: AtomicU64,
+    consensus_failures: AtomicU64,
+    migrations: AtomicU64,
+    migration_failures: AtomicU64,
+    peer_rtt: DashMap<String, Duration>,
+}
+
+impl ClusterMetricsRecorder {
+    fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            last_successful_sync: Mutex::new(None),
+            last_sync_duration_ms: AtomicU64::new(0),
+            sync_rounds: AtomicU64::new(0),
+            sync_failures: AtomicU64::new(0),
+            transactions_synced: AtomicU64::new(0),
+            consensus_rounds: AtomicU64::new(0),
+            consensus_failures: AtomicU64::new(0),
+            migrations: AtomicU64::new(0),
+            migration_failures: AtomicU64::new(0),
+            peer_rtt: DashMap::new(),
+        }
+    }
+
+    fn record_sync_round(&self, started: Instant, failures: u64, synced: u64) {
+        self.sync_rounds.fetch_add(1, Ordering::Relaxed);
+        self.sync_failures.fetch_add(failures, Ordering::Relaxed);
+        self.transactions_synced.fetch_add(synced, Ordering::Relaxed);
+        self.last_sync_duration_ms.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+        if failures == 0 {
+            if let Ok(mut last) = self.last_successful_sync.lock() {
+                *last = Some(Instant::now());
+            }
+        }
+    }
+
+    fn snapshot(&self, now: Instant) -> ClusterMetrics {
+        let last_success = self.last_successful_sync.lock().ok().and_then(|last| *last);
+        ClusterMetrics {
+            replication_lag_secs: now.duration_since(last_success.unwrap_or(self.started_at)).as_secs_f64(),
+            last_sync_duration_ms: self.last_sync_duration_ms.load(Ordering::Relaxed),
+            sync_rounds: self.sync_rounds.load(Ordering::Relaxed),
+            sync_failures: self.sync_failures.load(Ordering::Relaxed),
+            transactions_synced: self.transactions_synced.load(Ordering::Relaxed),
+            consensus_rounds: self.consensus_rounds.load(Ordering::Relaxed),
+            consensus_failures: self.consensus_failures.load(Ordering::Relaxed),
+            migrations: self.migrations.load(Ordering::Relaxed),
+            migration_failures: self.migration_failures.load(Ordering::Relaxed),
+            peer_rtt_ms: self
+                .peer_rtt
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().as_secs_f64() * 1000.0))
+                .collect(),
+        }
+    }
+}
+
 /// Anycast address management
 #[derive(Debug, Clone)]
 pub struct AnycastManager {
@@ -287,6 +377,8 @@ pub struct ClusteringService {
     event_rx: Option<mpsc::UnboundedReceiver<ClusteringEvent>>,
     shared_state: Option<Arc<dyn SharedStateManager>>,
     consensus: Option<Arc<dyn ConsensusManager>>,
+    discovery: Option<Arc<ClusterDiscovery>>,
+    metrics: Arc<ClusterMetricsRecorder>,
     is_running: bool,
 }
 
@@ -356,6 +448,8 @@ impl ClusteringService {
             event_rx: Some(event_rx),
             shared_state: None,
             consensus: None,
+            discovery: None,
+            metrics: Arc::new(ClusterMetricsRecorder::new()),
             config,
             is_running: false,
         })
@@ -380,6 +474,11 @@ impl ClusteringService {
         // Assign anycast addresses
         self.assign_anycast_addresses().await?;
 
+        // Discover peers instead of relying on a static peer list
+        if self.config.discovery.enabled {
+            self.start_discovery().await?;
+        }
+
         // Start cluster monitoring
         let nodes_monitor = Arc::clone(&self.cluster_nodes);
         let event_tx_monitor = self.event_tx.clone();
@@ -395,6 +494,7 @@ impl ClusteringService {
             let shared_state_sync = Arc::clone(self.shared_state.as_ref().unwrap());
             let node_id_sync = self.node_id.clone();
             let event_tx_sync = self.event_tx.clone();
+            let metrics_sync = Arc::clone(&self.metrics);
 
             tokio::spawn(async move {
                 Self::transaction_sync_loop(
@@ -402,6 +502,7 @@ impl ClusteringService {
                     shared_state_sync,
                     node_id_sync,
                     event_tx_sync,
+                    metrics_sync,
                 ).await;
             });
         }
@@ -409,11 +510,26 @@ impl ClusteringService {
         // Start consensus participation
         let consensus_participant = Arc::clone(self.consensus.as_ref().unwrap());
         let event_tx_consensus = self.event_tx.clone();
+        let metrics_consensus = Arc::clone(&self.metrics);
+        let node_id_consensus = self.node_id.clone();
 
         tokio::spawn(async move {
-            Self::consensus_loop(consensus_participant, event_tx_consensus).await;
+            Self::consensus_loop(consensus_participant, event_tx_consensus, metrics_consensus, node_id_consensus).await;
         });
 
+        // Measure round-trip time to discovered peers
+        if let Some(discovery) = &self.discovery {
+            let discovery_rtt = Arc::clone(discovery);
+            let nodes_rtt = Arc::clone(&self.cluster_nodes);
+            let metrics_rtt = Arc::clone(&self.metrics);
+            let node_id_rtt = self.node_id.clone();
+            let heartbeat_interval = Duration::from_secs(self.config.heartbeat_interval as u64);
+
+            tokio::spawn(async move {
+                Self::peer_rtt_loop(discovery_rtt, nodes_rtt, metrics_rtt, node_id_rtt, heartbeat_interval).await;
+            });
+        }
+
         self.is_running = true;
         info!("Clustering service started successfully");
         Ok(())
@@ -462,12 +578,7 @@ impl ClusteringService {
                 memory_usage: 0.0,
                 rtp_sessions: 0,
             },
-            capabilities: NodeCapabilities {
-                max_calls: 1000,
-                supports_transcoding: true,
-                transcoding_backend: "auto".to_string(),
-                supported_codecs: vec!["g711u".to_string(), "g711a".to_string()],
-            },
+            capabilities: Self::local_capabilities(),
         };
 
         self.cluster_nodes.insert(self.node_id.clone(), node.clone());
@@ -481,6 +592,80 @@ impl ClusteringService {
         Ok(())
     }
 
+    fn local_capabilities() -> NodeCapabilities {
+        NodeCapabilities {
+            max_calls: 1000,
+            supports_transcoding: true,
+            transcoding_backend: "auto".to_string(),
+            supported_codecs: vec!["g711u".to_string(), "g711a".to_string()],
+        }
+    }
+
+    async fn start_discovery(&mut self) -> Result<()> {
+        let mut discovery = ClusterDiscovery::new(&self.config, Self::local_capabilities())?;
+        let mut discovery_rx = discovery.take_event_receiver()
+            .ok_or_else(|| Error::clustering("Discovery event receiver already taken"))?;
+        let discovery = Arc::new(discovery);
+        discovery.start().await?;
+        self.discovery = Some(discovery);
+
+        let nodes = Arc::clone(&self.cluster_nodes);
+        let event_tx = self.event_tx.clone();
+
+        tokio::spawn(async move {
+            while let Some(event) = discovery_rx.recv().await {
+                Self::handle_discovery_event(&nodes, &event_tx, event);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn handle_discovery_event(
+        nodes: &DashMap<String, ClusterNode>,
+        event_tx: &mpsc::UnboundedSender<ClusteringEvent>,
+        event: DiscoveryEvent,
+    ) {
+        match event {
+            DiscoveryEvent::PeerAuthenticated { advertisement, source } => {
+                info!("Discovered peer {} via {:?}", advertisement.node_id, source);
+                let node = ClusterNode {
+                    node_id: advertisement.node_id.clone(),
+                    address: advertisement.address,
+                    last_seen: Instant::now(),
+                    status: NodeStatus::Active,
+                    capabilities: advertisement.capabilities,
+                    ..ClusterNode::default()
+                };
+                nodes.insert(advertisement.node_id.clone(), node);
+
+                let _ = event_tx.send(ClusteringEvent::NodeJoined {
+                    node_id: advertisement.node_id,
+                    address: advertisement.address,
+                });
+            }
+            DiscoveryEvent::PeerRefreshed { node_id } => {
+                if let Some(mut node) = nodes.get_mut(&node_id) {
+                    node.last_seen = Instant::now();
+                    if matches!(node.status, NodeStatus::Failed) {
+                        node.status = NodeStatus::Active;
+                        let _ = event_tx.send(ClusteringEvent::NodeStatusChanged {
+                            node_id: node_id.clone(),
+                            old_status: NodeStatus::Failed,
+                            new_status: NodeStatus::Active,
+                        });
+                    }
+                }
+            }
+            DiscoveryEvent::PeerRejected { node_id, address, reason } => {
+                let _ = event_tx.send(ClusteringEvent::PeerRejected { node_id, address, reason });
+            }
+            DiscoveryEvent::PeerExpired { node_id } => {
+                debug!("Peer {} no longer advertised; leaving failure detection to heartbeats", node_id);
+            }
+        }
+    }
+
     async fn assign_anycast_addresses(&self) -> Result<()> {
         // Assign anycast addresses based on node priority
         let priority = 100; // Would calculate based on load, capabilities, etc.
@@ -547,41 +732,60 @@ impl ClusteringService {
         shared_state: Arc<dyn SharedStateManager>,
         node_id: String,
         event_tx: mpsc::UnboundedSender<ClusteringEvent>,
+        metrics: Arc<ClusterMetricsRecorder>,
     ) {
         let mut sync_interval = interval(Duration::from_secs(10));
+        let mut round: u64 = 0;
 
         loop {
             sync_interval.tick().await;
-
-            // Sync local transactions to shared state
-            for transaction_entry in transactions.iter() {
-                let transaction = transaction_entry.value();
-                if let Err(e) = shared_state.store_transaction(transaction).await {
-                    error!("Failed to sync transaction {}: {}", transaction.transaction_id, e);
+            round += 1;
+            let started = Instant::now();
+            let span = info_span!("cluster_state_sync", node_id = %node_id, round);
+
+            let (failures, synced_count) = async {
+                let mut failures = 0u64;
+
+                // Sync local transactions to shared state
+                let local: Vec<DistributedTransaction> =
+                    transactions.iter().map(|entry| entry.value().clone()).collect();
+                for transaction in &local {
+                    if let Err(e) = shared_state.store_transaction(transaction).await {
+                        failures += 1;
+                        error!("Failed to sync transaction {}: {}", transaction.transaction_id, e);
+                    }
                 }
-            }
 
-            // Load remote transactions from shared state
-            match shared_state.list_transactions(&node_id).await {
-                Ok(remote_transactions) => {
-                    let mut synced_count = 0;
-                    for transaction in remote_transactions {
-                        if !transactions.contains_key(&transaction.transaction_id) {
-                            transactions.insert(transaction.transaction_id.clone(), transaction);
-                            synced_count += 1;
+                // Load remote transactions from shared state
+                let mut synced_count = 0;
+                match shared_state.list_transactions(&node_id).await {
+                    Ok(remote_transactions) => {
+                        for transaction in remote_transactions {
+                            if !transactions.contains_key(&transaction.transaction_id) {
+                                transactions.insert(transaction.transaction_id.clone(), transaction);
+                                synced_count += 1;
+                            }
                         }
                     }
-
-                    if synced_count > 0 {
-                        let _ = event_tx.send(ClusteringEvent::StateSync {
-                            from_node: "cluster".to_string(),
-                            transactions_synced: synced_count,
-                        });
+                    Err(e) => {
+                        failures += 1;
+                        error!("Failed to sync transactions from shared state: {}", e);
                     }
                 }
-                Err(e) => {
-                    error!("Failed to sync transactions from shared state: {}", e);
-                }
+
+                debug!(stored = local.len(), synced = synced_count, failures, "State sync round complete");
+                (failures, synced_count)
+            }
+            .instrument(span)
+            .await;
+
+            metrics.record_sync_round(started, failures, synced_count as u64);
+
+            if synced_count > 0 {
+                let _ = event_tx.send(ClusteringEvent::StateSync {
+                    from_node: "cluster".to_string(),
+                    transactions_synced: synced_count,
+                });
             }
         }
     }
@@ -589,16 +793,67 @@ impl ClusteringService {
     async fn consensus_loop(
         consensus: Arc<dyn ConsensusManager>,
         event_tx: mpsc::UnboundedSender<ClusteringEvent>,
+        metrics: Arc<ClusterMetricsRecorder>,
+        node_id: String,
     ) {
         let mut consensus_interval = interval(Duration::from_secs(30));
+        let mut round: u64 = 0;
 
         loop {
             consensus_interval.tick().await;
+            round += 1;
+            metrics.consensus_rounds.fetch_add(1, Ordering::Relaxed);
+            let span = info_span!("cluster_consensus_round", node_id = %node_id, round);
 
             // Participate in leader election if needed
-            if !consensus.is_leader().await {
-                if let Ok(leader) = consensus.elect_leader().await {
-                    debug!("New leader elected: {}", leader);
+            async {
+                if !consensus.is_leader().await {
+                    match consensus.elect_leader().await {
+                        Ok(leader) => debug!("New leader elected: {}", leader),
+                        Err(e) => {
+                            metrics.consensus_failures.fetch_add(1, Ordering::Relaxed);
+                            let _ = event_tx.send(ClusteringEvent::Error {
+                                node_id: Some(node_id.clone()),
+                                message: format!("Leader election failed: {}", e),
+                            });
+                        }
+                    }
+                }
+            }
+            .instrument(span)
+            .await;
+        }
+    }
+
+    async fn peer_rtt_loop(
+        discovery: Arc<ClusterDiscovery>,
+        nodes: Arc<DashMap<String, ClusterNode>>,
+        metrics: Arc<ClusterMetricsRecorder>,
+        node_id: String,
+        heartbeat_interval: Duration,
+    ) {
+        let mut probe_interval = interval(heartbeat_interval);
+
+        loop {
+            probe_interval.tick().await;
+            let peers: Vec<(String, SocketAddr)> = nodes
+                .iter()
+                .filter(|entry| *entry.key() != node_id)
+                .map(|entry| (entry.key().clone(), entry.value().address))
+                .collect();
+
+            for (peer_id, address) in peers {
+                match discovery.measure_rtt(address).await {
+                    Some(rtt) => {
+                        metrics.peer_rtt.insert(peer_id.clone(), rtt);
+                        if let Some(mut node) = nodes.get_mut(&peer_id) {
+                            node.last_seen = Instant::now();
+                        }
+                    }
+                    None => {
+                        metrics.peer_rtt.remove(&peer_id);
+                        debug!("Peer {} did not answer RTT probe", peer_id);
+                    }
                 }
             }
         }
@@ -671,17 +926,36 @@ impl ClusteringService {
         Ok(())
     }
 
+    #[instrument(name = "cluster_migration", skip(self), fields(node_id = %self.node_id))]
     pub async fn migrate_transaction(
         &self,
         transaction_id: &str,
         target_node: &str,
     ) -> Result<()> {
-        if let Some(transaction) = self.distributed_transactions.get(transaction_id) {
+        let result = self.propose_migration(transaction_id, target_node).await;
+        match &result {
+            Ok(true) => {
+                self.metrics.migrations.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                self.metrics.migration_failures.fetch_add(1, Ordering::Relaxed);
+                warn!("Migration of transaction {} failed: {}", transaction_id, e);
+            }
+        }
+        result.map(|_| ())
+    }
+
+    /// Returns whether the transaction was migrated
+    async fn propose_migration(&self, transaction_id: &str, target_node: &str) -> Result<bool> {
+        // Clone out of the map so the entry is not locked across the removal below
+        let transaction = self.distributed_transactions.get(transaction_id).map(|entry| entry.value().clone());
+        if let Some(transaction) = transaction {
             // Create migration proposal
             let proposal = ConsensusProposal {
                 id: Uuid::new_v4().to_string(),
                 proposal_type: ProposalType::TransactionMigration,
-                data: serde_json::to_value(&transaction.value())?,
+                data: serde_json::to_value(&transaction)?,
                 proposer: self.node_id.clone(),
                 created_at: Instant::now(),
             };
@@ -702,12 +976,13 @@ impl ClusteringService {
                         });
 
                         info!("Migrated transaction {} to node {}", transaction_id, target_node);
+                        return Ok(true);
                     }
                 }
             }
         }
 
-        Ok(())
+        Ok(false)
     }
 
     pub fn get_cluster_nodes(&self) -> Vec<ClusterNode> {
@@ -718,6 +993,11 @@ impl ClusteringService {
         self.distributed_transactions.iter().map(|entry| entry.value().clone()).collect()
     }
 
+    /// Replication lag, sync/consensus/migration counters and per-peer RTT
+    pub fn get_metrics(&self) -> ClusterMetrics {
+        self.metrics.snapshot(Instant::now())
+    }
+
     pub async fn stop(&mut self) -> Result<()> {
         info!("Stopping clustering service");
 
@@ -911,6 +1191,7 @@ mod tests {
                 password: None,
             },
             consensus_algorithm: ConsensusAlgorithm::Raft,
+            discovery: Default::default(),
         };
 
         let service = ClusteringService::new(config);
@@ -932,4 +1213,22 @@ mod tests {
         let assigned3 = manager.assign_address("node3", 1).await.unwrap();
         assert!(assigned3.is_some());
     }
+
+    #[test]
+    fn test_replication_lag_tracks_failed_sync_rounds() {
+        let recorder = ClusterMetricsRecorder::new();
+        let started = Instant::now();
+
+        recorder.record_sync_round(started, 0, 3);
+        let healthy = recorder.snapshot(Instant::now() + Duration::from_secs(1));
+        assert!(healthy.replication_lag_secs < 2.0);
+        assert_eq!(healthy.transactions_synced, 3);
+
+        // A failing round does not advance the last successful sync
+        recorder.record_sync_round(started, 2, 0);
+        let lagging = recorder.snapshot(Instant::now() + Duration::from_secs(30));
+        assert!(lagging.replication_lag_secs >= 30.0);
+        assert_eq!(lagging.sync_rounds, 2);
+        assert_eq!(lagging.sync_failures, 2);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,518 @@
+//! Codec ordering for leg B offers and relayed/transcoded accounting
+//!
+//! Leg A's offer is reordered before it goes to the egress trunk: the
+//! trunk's outbound priority applies, or the ingress trunk's inbound
+//! priority when the egress trunk has none. With transcoding enabled,
+//! prioritised codecs leg A did not offer are appended as transcoded
+//! fallbacks; avoiding transcoding keeps every shared codec ahead of them
+//! so the call is relayed transparently whenever the legs can agree. Each
+//! answered call is counted per egress trunk as relayed or transcoded to
+//! show how much DSP time the policy saves.
+//!
+//! With transcoding admission enabled, calls answered as transcoded are
+//! counted against the configured DSP capacity. As free capacity runs low
+//! shared codecs are always offered first; once it is nearly exhausted no
+//! fallbacks are offered and calls that cannot be relayed are rejected with
+//! their own cause, so existing calls keep their quality. Codecs that no
+//! backend can currently transcode within its latency budget are not
+//! offered as fallbacks either.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use dashmap::{DashMap, DashSet};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::{CodecNegotiationConfig, TranscodingBackend, TrunkMediaPolicy};
+use crate::protocols::sdp::{MediaDescription, RtpMap, SdpLine, SessionDescription};
+use crate::services::transcoding::CodecType;
+use crate::services::transcoding_latency::TranscodingLatencyMonitor;
+
+/// Management API path listing relayed/transcoded counters
+pub const CODEC_NEGOTIATION_PATH: &str = "/api/v1/b2bua/codec-negotiation";
+
+/// Management API path reporting free transcoding capacity
+pub const TRANSCODING_HEADROOM_PATH: &str = "/api/v1/b2bua/transcoding-headroom";
+
+/// Q.850 bearer capability not presently available, for calls rejected
+/// because they would need transcoding capacity that is not left
+pub const CAUSE_BEARER_CAPABILITY_UNAVAILABLE: u16 = 58;
+
+/// Static payload types a transcoded fallback can be offered on
+const FALLBACK_PAYLOAD_TYPES: [u8; 4] = [0, 8, 9, 18];
+
+/// How the media of an answered call is carried between the legs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaPath {
+    /// Leg B answered with a codec leg A offered; packets pass through
+    Relayed,
+    /// Leg B answered with a codec leg A did not offer
+    Transcoded,
+}
+
+/// Answered calls on one egress trunk by media path
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CodecNegotiationCounter {
+    pub trunk: String,
+    pub relayed: u64,
+    pub transcoded: u64,
+}
+
+/// How close transcoding is to running out of capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscodingCongestion {
+    Normal,
+    /// Shared codecs are offered ahead of transcoded fallbacks
+    PreferRelay,
+    /// Only calls that can be relayed are admitted
+    Exhausted,
+}
+
+/// Free transcoding capacity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TranscodingHeadroom {
+    pub capacity: u32,
+    pub active: u32,
+    pub available: u32,
+    /// Free share of capacity, 0-100; 100 when admission is disabled
+    pub headroom_percent: f64,
+    pub congestion: TranscodingCongestion,
+    /// Calls rejected for lack of transcoding capacity
+    pub rejected: u64,
+}
+
+/// Offer to send to leg B, or why the call is turned away
+#[derive(Debug, Clone, PartialEq)]
+pub enum OfferOutcome {
+    Offer(String),
+    Rejected { status_code: u16, cause: u16 },
+}
+
+/// Orders leg B offers by trunk codec priority and counts media paths
+pub struct CodecNegotiator {
+    config: CodecNegotiationConfig,
+    policies: Vec<TrunkMediaPolicy>,
+    counters: DashMap<String, CodecNegotiationCounter>,
+    /// Calls currently answered as transcoded
+    transcoded_calls: DashSet<String>,
+    rejected: AtomicU64,
+    /// Latency budgets and the backend new sessions prefer
+    latency: Option<(Arc<TranscodingLatencyMonitor>, TranscodingBackend)>,
+}
+
+impl CodecNegotiator {
+    pub fn new(config: CodecNegotiationConfig, policies: &[TrunkMediaPolicy]) -> Self {
+        Self {
+            config,
+            policies: policies.to_vec(),
+            counters: DashMap::new(),
+            transcoded_calls: DashSet::new(),
+            rejected: AtomicU64::new(0),
+            latency: None,
+        }
+    }
+
+    /// Leave out fallbacks no backend can transcode within its latency budget
+    pub fn with_latency_monitor(mut self, monitor: Arc<TranscodingLatencyMonitor>, preferred: TranscodingBackend) -> Self {
+        self.latency = Some((monitor, preferred));
+        self
+    }
+
+    fn can_transcode(&self, codec: &CodecType) -> bool {
+        match self.latency {
+            Some((ref monitor, ref preferred)) => monitor.can_transcode(codec, preferred),
+            None => true,
+        }
+    }
+
+    /// Codec order for a call from `ingress` to `egress`; empty when
+    /// neither trunk has one configured
+    pub fn priority(&self, ingress: Option<&str>, egress: &str) -> &[String] {
+        let policy = |trunk: &str| self.policies.iter().find(|policy| policy.trunk == trunk);
+        match policy(egress).filter(|policy| !policy.outbound_codec_priority.is_empty()) {
+            Some(policy) => &policy.outbound_codec_priority,
+            None => ingress
+                .and_then(policy)
+                .map(|policy| policy.inbound_codec_priority.as_slice())
+                .unwrap_or(&[]),
+        }
+    }
+
+    /// Reorder leg A's `offer` for the egress trunk, adding transcoded
+    /// fallbacks when `transcoding` is available. Unparseable offers are
+    /// passed through unchanged.
+    pub fn order_offer(&self, ingress: Option<&str>, egress: &str, offer: &str, transcoding: bool) -> String {
+        self.order(ingress, egress, offer, transcoding, self.config.avoid_transcoding)
+    }
+
+    /// Order leg A's `offer` as [`order_offer`](Self::order_offer) does,
+    /// tightened by transcoding admission under congestion
+    pub fn admit_offer(&self, ingress: Option<&str>, egress: &str, offer: &str, transcoding: bool) -> OfferOutcome {
+        match self.congestion() {
+            TranscodingCongestion::Normal => OfferOutcome::Offer(self.order_offer(ingress, egress, offer, transcoding)),
+            TranscodingCongestion::PreferRelay => OfferOutcome::Offer(self.order(ingress, egress, offer, transcoding, true)),
+            TranscodingCongestion::Exhausted if self.requires_transcoding(ingress, egress, offer) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                warn!("Rejecting call to trunk {}: it needs transcoding and none is left", egress);
+                OfferOutcome::Rejected {
+                    status_code: self.config.admission.reject_code,
+                    cause: CAUSE_BEARER_CAPABILITY_UNAVAILABLE,
+                }
+            }
+            TranscodingCongestion::Exhausted => OfferOutcome::Offer(self.order(ingress, egress, offer, false, true)),
+        }
+    }
+
+    /// Whether leg A offered none of the codecs the trunks prioritise
+    fn requires_transcoding(&self, ingress: Option<&str>, egress: &str, offer: &str) -> bool {
+        let priority = self.priority(ingress, egress);
+        if priority.is_empty() {
+            return false;
+        }
+        let Some(offered) = codecs(offer) else {
+            return false;
+        };
+        !priority.iter().any(|name| offered.contains(&CodecType::from_name(name)))
+    }
+
+    fn order(&self, ingress: Option<&str>, egress: &str, offer: &str, transcoding: bool, avoid_transcoding: bool) -> String {
+        let priority = self.priority(ingress, egress);
+        if priority.is_empty() {
+            return offer.to_string();
+        }
+        let mut session = match SessionDescription::parse(offer) {
+            Ok(session) => session,
+            Err(e) => {
+                warn!("Not reordering unparseable offer for trunk {}: {}", egress, e);
+                return offer.to_string();
+            }
+        };
+
+        for stream in session.audio_streams_mut().filter(|m| !m.is_rejected()) {
+            Self::order_stream(stream, priority, transcoding, avoid_transcoding, |codec| self.can_transcode(codec));
+        }
+        debug!("Offer for trunk {} ordered by {:?}", egress, priority);
+        session.to_string()
+    }
+
+    fn order_stream(
+        stream: &mut MediaDescription,
+        priority: &[String],
+        transcoding: bool,
+        avoid_transcoding: bool,
+        can_transcode: impl Fn(&CodecType) -> bool,
+    ) {
+        let rank = |codec: &CodecType| {
+            priority
+                .iter()
+                .position(|name| CodecType::from_name(name).to_name().eq_ignore_ascii_case(codec.to_name()))
+                .unwrap_or(priority.len())
+        };
+
+        // (rank, transcoded, format); shared codecs keep their relative order
+        let mut formats: Vec<(usize, bool, String)> = stream
+            .formats
+            .iter()
+            .map(|format| {
+                let codec = format.parse::<u8>().ok().and_then(|pt| stream.rtpmap(pt));
+                let rank = match codec {
+                    Some(map) if !is_signaling_payload(&map) => rank(&CodecType::from_name(&map.encoding)),
+                    // DTMF, comfort noise and unknown formats stay last
+                    _ => usize::MAX,
+                };
+                (rank, false, format.clone())
+            })
+            .collect();
+
+        if transcoding {
+            let offered: Vec<CodecType> = stream
+                .rtpmaps()
+                .iter()
+                .map(|map| CodecType::from_name(&map.encoding))
+                .collect();
+            for name in priority {
+                let codec = CodecType::from_name(name);
+                if offered.contains(&codec) {
+                    continue;
+                }
+                if !can_transcode(&codec) {
+                    debug!("Not offering {} as a transcoded fallback: over its latency budget", name);
+                    continue;
+                }
+                let Some(map) = static_rtpmap(&codec) else {
+                    debug!("No static payload type to offer {} as a transcoded fallback", name);
+                    continue;
+                };
+                let format = map.payload_type.to_string();
+                if formats.iter().any(|(_, _, existing)| *existing == format) {
+                    continue;
+                }
+                formats.push((rank(&codec), true, format));
+                stream.lines.push(SdpLine::new(
+                    'a',
+                    format!("rtpmap:{} {}/{}", map.payload_type, map.encoding, map.clock_rate),
+                ));
+            }
+        }
+
+        if avoid_transcoding {
+            formats.sort_by_key(|(rank, transcoded, _)| (*rank == usize::MAX, *transcoded, *rank));
+        } else {
+            formats.sort_by_key(|(rank, _, _)| *rank);
+        }
+        stream.formats = formats.into_iter().map(|(_, _, format)| format).collect();
+    }
+
+    /// Classify an answered call by comparing leg B's chosen codec with
+    /// leg A's offer, and count it against the egress trunk
+    pub fn record_answer(&self, call_id: &str, trunk: &str, leg_a_offer: &str, answer: &str) -> Option<MediaPath> {
+        let offered = codecs(leg_a_offer)?;
+        let chosen = codecs(answer)?.into_iter().next()?;
+        let path = if offered.contains(&chosen) {
+            MediaPath::Relayed
+        } else {
+            MediaPath::Transcoded
+        };
+
+        let mut counter = self
+            .counters
+            .entry(trunk.to_string())
+            .or_insert_with(|| CodecNegotiationCounter {
+                trunk: trunk.to_string(),
+                ..Default::default()
+            });
+        match path {
+            MediaPath::Relayed => counter.relayed += 1,
+            MediaPath::Transcoded => {
+                counter.transcoded += 1;
+                self.transcoded_calls.insert(call_id.to_string());
+            }
+        }
+        Some(path)
+    }
+
+    /// Return a released call's transcoding capacity
+    pub fn release(&self, call_id: &str) {
+        self.transcoded_calls.remove(call_id);
+    }
+
+    pub fn congestion(&self) -> TranscodingCongestion {
+        self.headroom().congestion
+    }
+
+    pub fn headroom(&self) -> TranscodingHeadroom {
+        let admission = &self.config.admission;
+        let active = self.transcoded_calls.len() as u32;
+        let rejected = self.rejected.load(Ordering::Relaxed);
+        if !admission.enabled || admission.capacity == 0 {
+            return TranscodingHeadroom {
+                capacity: admission.capacity,
+                active,
+                available: admission.capacity.saturating_sub(active),
+                headroom_percent: 100.0,
+                congestion: TranscodingCongestion::Normal,
+                rejected,
+            };
+        }
+
+        let available = admission.capacity.saturating_sub(active);
+        let headroom_percent = available as f64 * 100.0 / admission.capacity as f64;
+        let congestion = if available == 0 || headroom_percent < admission.reject_below_percent as f64 {
+            TranscodingCongestion::Exhausted
+        } else if headroom_percent < admission.prefer_relay_below_percent as f64 {
+            TranscodingCongestion::PreferRelay
+        } else {
+            TranscodingCongestion::Normal
+        };
+        TranscodingHeadroom {
+            capacity: admission.capacity,
+            active,
+            available,
+            headroom_percent,
+            congestion,
+            rejected,
+        }
+    }
+
+    /// Counters ordered by trunk
+    pub fn counters(&self) -> Vec<CodecNegotiationCounter> {
+        let mut counters: Vec<CodecNegotiationCounter> = self.counters.iter().map(|entry| entry.value().clone()).collect();
+        counters.sort_by(|a, b| a.trunk.cmp(&b.trunk));
+        counters
+    }
+}
+
+/// Media codecs of the first active audio stream, in preference order
+fn codecs(sdp: &str) -> Option<Vec<CodecType>> {
+    let session = SessionDescription::parse(sdp).ok()?;
+    let stream = session.audio_streams().find(|m| !m.is_rejected())?;
+    Some(
+        stream
+            .rtpmaps()
+            .iter()
+            .filter(|map| !is_signaling_payload(map))
+            .map(|map| CodecType::from_name(&map.encoding))
+            .collect(),
+    )
+}
+
+fn static_rtpmap(codec: &CodecType) -> Option<RtpMap> {
+    FALLBACK_PAYLOAD_TYPES
+        .iter()
+        .filter_map(|pt| RtpMap::from_static(*pt))
+        .find(|map| CodecType::from_name(&map.encoding) == *codec)
+}
+
+fn is_signaling_payload(map: &RtpMap) -> bool {
+    map.encoding.eq_ignore_ascii_case("telephone-event") || map.encoding.eq_ignore_ascii_case("CN")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::config::{TranscodingAdmissionConfig, TranscodingLatencyConfig};
+
+    const OFFER: &str = "v=0\r\n\
+        o=- 1 1 IN IP4 192.0.2.1\r\n\
+        s=-\r\n\
+        c=IN IP4 192.0.2.1\r\n\
+        t=0 0\r\n\
+        m=audio 10000 RTP/AVP 0 18 101\r\n\
+        a=rtpmap:18 G729/8000\r\n\
+        a=rtpmap:101 telephone-event/8000\r\n";
+
+    fn policy(trunk: &str, outbound: &[&str], inbound: &[&str]) -> TrunkMediaPolicy {
+        TrunkMediaPolicy {
+            trunk: trunk.to_string(),
+            allowed_codecs: Vec::new(),
+            max_bandwidth_kbps: None,
+            ptime_ms: None,
+            reject_code: 488,
+            outbound_codec_priority: outbound.iter().map(|c| c.to_string()).collect(),
+            inbound_codec_priority: inbound.iter().map(|c| c.to_string()).collect(),
+            media_streams: None,
+            srtp: false,
+            require_srtp: false,
+            srtp_downgrade: Default::default(),
+            webrtc: false,
+        }
+    }
+
+    fn formats(sdp: &str) -> Vec<String> {
+        let session = SessionDescription::parse(sdp).unwrap();
+        let stream = session.audio_streams().next().unwrap();
+        stream.formats.clone()
+    }
+
+    #[test]
+    fn test_shared_codecs_stay_ahead_of_transcoded_fallbacks() {
+        let policies = [policy("carrier-a", &["PCMA", "G729", "PCMU"], &[])];
+        let negotiator = CodecNegotiator::new(CodecNegotiationConfig::default(), &policies);
+
+        let relayed = negotiator.order_offer(None, "carrier-a", OFFER, false);
+        assert_eq!(formats(&relayed), vec!["18", "0", "101"]);
+
+        let avoided = negotiator.order_offer(None, "carrier-a", OFFER, true);
+        assert_eq!(formats(&avoided), vec!["18", "0", "8", "101"]);
+        assert!(avoided.contains("a=rtpmap:8 PCMA/8000"));
+
+        let strict = CodecNegotiator::new(
+            CodecNegotiationConfig { avoid_transcoding: false, ..Default::default() },
+            &policies,
+        );
+        let transcoded = strict.order_offer(None, "carrier-a", OFFER, true);
+        assert_eq!(formats(&transcoded), vec!["8", "18", "0", "101"]);
+    }
+
+    #[test]
+    fn test_ingress_priority_and_media_path_counters() {
+        let policies = [policy("pbx.example.com", &[], &["G729"]), policy("carrier-b", &[], &[])];
+        let negotiator = CodecNegotiator::new(CodecNegotiationConfig::default(), &policies);
+
+        let ordered = negotiator.order_offer(Some("pbx.example.com"), "carrier-b", OFFER, false);
+        assert_eq!(formats(&ordered), vec!["18", "0", "101"]);
+        assert_eq!(negotiator.order_offer(None, "carrier-b", OFFER, true), OFFER);
+
+        let answer = |pt: &str, codec: &str| {
+            format!("v=0\r\no=- 2 2 IN IP4 198.51.100.1\r\ns=-\r\nc=IN IP4 198.51.100.1\r\nt=0 0\r\n\
+                m=audio 20000 RTP/AVP {} 101\r\na=rtpmap:{} {}/8000\r\n", pt, pt, codec)
+        };
+        assert_eq!(negotiator.record_answer("call-1", "carrier-b", OFFER, &answer("18", "G729")), Some(MediaPath::Relayed));
+        assert_eq!(negotiator.record_answer("call-2", "carrier-b", OFFER, &answer("8", "PCMA")), Some(MediaPath::Transcoded));
+        assert_eq!(negotiator.record_answer("call-3", "carrier-a", OFFER, &answer("0", "PCMU")), Some(MediaPath::Relayed));
+
+        let counters = negotiator.counters();
+        assert_eq!(counters.len(), 2);
+        assert_eq!(counters[0], CodecNegotiationCounter { trunk: "carrier-a".to_string(), relayed: 1, transcoded: 0 });
+        assert_eq!(counters[1], CodecNegotiationCounter { trunk: "carrier-b".to_string(), relayed: 1, transcoded: 1 });
+    }
+
+    #[test]
+    fn test_admission_tightens_as_headroom_runs_out() {
+        let policies = [policy("carrier-a", &["PCMA", "G729", "PCMU"], &[]), policy("carrier-c", &["G722"], &[])];
+        let config = CodecNegotiationConfig {
+            avoid_transcoding: false,
+            admission: TranscodingAdmissionConfig {
+                enabled: true,
+                capacity: 10,
+                prefer_relay_below_percent: 30,
+                reject_below_percent: 10,
+                ..Default::default()
+            },
+        };
+        let negotiator = CodecNegotiator::new(config, &policies);
+        let answer = "v=0\r\no=- 2 2 IN IP4 198.51.100.1\r\ns=-\r\nc=IN IP4 198.51.100.1\r\nt=0 0\r\n\
+            m=audio 20000 RTP/AVP 8\r\na=rtpmap:8 PCMA/8000\r\n";
+
+        let offer = |egress| match negotiator.admit_offer(None, egress, OFFER, true) {
+            OfferOutcome::Offer(sdp) => Some(formats(&sdp)),
+            OfferOutcome::Rejected { .. } => None,
+        };
+        assert_eq!(offer("carrier-a"), Some(vec!["8".to_string(), "18".to_string(), "0".to_string(), "101".to_string()]));
+
+        for call in 0..8 {
+            negotiator.record_answer(&format!("call-{}", call), "carrier-a", OFFER, answer);
+        }
+        assert_eq!(negotiator.congestion(), TranscodingCongestion::PreferRelay);
+        assert_eq!(offer("carrier-a"), Some(vec!["18".to_string(), "0".to_string(), "8".to_string(), "101".to_string()]));
+
+        negotiator.record_answer("call-8", "carrier-a", OFFER, answer);
+        negotiator.record_answer("call-9", "carrier-a", OFFER, answer);
+        assert_eq!(negotiator.congestion(), TranscodingCongestion::Exhausted);
+        assert_eq!(offer("carrier-a"), Some(vec!["18".to_string(), "0".to_string(), "101".to_string()]));
+        assert_eq!(
+            negotiator.admit_offer(None, "carrier-c", OFFER, true),
+            OfferOutcome::Rejected { status_code: 488, cause: CAUSE_BEARER_CAPABILITY_UNAVAILABLE }
+        );
+
+        negotiator.release("call-0");
+        negotiator.release("call-1");
+        let headroom = negotiator.headroom();
+        assert_eq!(headroom.available, 2);
+        assert_eq!(headroom.congestion, TranscodingCongestion::PreferRelay);
+        assert_eq!(headroom.rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_fallbacks_over_latency_budget_are_not_offered() {
+        let monitor = Arc::new(TranscodingLatencyMonitor::new(TranscodingLatencyConfig {
+            enabled: true,
+            budget_us: 500,
+            ..Default::default()
+        }));
+        for _ in 0..100 {
+            monitor.record(&CodecType::G711a, &TranscodingBackend::Cpu, Duration::from_micros(800)).await;
+        }
+        let policies = [policy("carrier-a", &["PCMA", "G729", "PCMU"], &[])];
+        let negotiator = CodecNegotiator::new(CodecNegotiationConfig::default(), &policies)
+            .with_latency_monitor(monitor, TranscodingBackend::Cpu);
+        let offer = negotiator.order_offer(None, "carrier-a", OFFER, true);
+        assert_eq!(formats(&offer), vec!["18", "0", "101"]);
+    }
+}
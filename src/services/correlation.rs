@@ -0,0 +1,131 @@
+//! Call correlation IDs
+//!
+//! Every B2BUA call carries one correlation ID shared by both legs. It is
+//! the ID leg A arrived with in the correlation header, when an upstream
+//! system already assigned a usable one, and a new UUID otherwise. The ID is
+//! sent on in the same header of every leg B INVITE and of leg A's 200 OK,
+//! and as IA5 user-user information in the SETUP of a call broken out to
+//! TDM. CDRs, call traces and captured signaling record it, so one
+//! identifier finds a call's records on this gateway and the systems it
+//! talks to.
+
+use std::collections::BTreeMap;
+
+use uuid::Uuid;
+
+use crate::config::CorrelationConfig;
+
+/// Longest ID accepted from another system; Q.931 user-user information
+/// holds it with room to spare
+pub const MAX_LEN: usize = 64;
+
+/// User-user protocol discriminator for IA5 characters
+const UUI_IA5: u8 = 0x04;
+
+/// Whether `id` can be carried unchanged in a SIP header and in IA5
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:@".contains(&b))
+}
+
+/// Correlation ID for a new call: the one leg A arrived with when accepted,
+/// otherwise a new one
+pub fn assign(config: &CorrelationConfig, headers: &[(String, String)], uui: Option<&[u8]>) -> String {
+    let inbound = || {
+        let from_header = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&config.header))
+            .map(|(_, value)| value.trim().to_string());
+        from_header.or_else(|| uui.filter(|_| config.q931_user_user).and_then(from_user_user))
+    };
+    config
+        .accept_inbound
+        .then(inbound)
+        .flatten()
+        .filter(|id| is_valid(id))
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// Add the correlation header to the headers of a leg B INVITE
+pub fn add_request_header(config: &CorrelationConfig, id: &str, headers: &mut BTreeMap<String, String>) {
+    if config.propagate {
+        headers.insert(config.header.clone(), id.to_string());
+    }
+}
+
+/// Headers returning the ID to leg A in its 200 OK
+pub fn response_headers(config: &CorrelationConfig, id: &str) -> Vec<(String, String)> {
+    if config.propagate {
+        vec![(config.header.clone(), id.to_string())]
+    } else {
+        Vec::new()
+    }
+}
+
+/// User-user information carrying `id` in a Q.931 SETUP, when configured
+pub fn user_user(config: &CorrelationConfig, id: &str) -> Option<Vec<u8>> {
+    (config.propagate && config.q931_user_user).then(|| {
+        let mut contents = vec![UUI_IA5];
+        contents.extend_from_slice(id.as_bytes());
+        contents
+    })
+}
+
+/// Correlation ID in IA5 user-user information
+pub fn from_user_user(uui: &[u8]) -> Option<String> {
+    match uui.split_first() {
+        Some((&UUI_IA5, text)) => std::str::from_utf8(text).ok().filter(|id| is_valid(id)).map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Correlation ID in the header section of a raw SIP message
+pub fn from_sip_message(header: &str, message: &str) -> Option<String> {
+    message
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(header))
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|id| is_valid(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inbound_id_is_kept_when_valid() {
+        let config = CorrelationConfig::default();
+        let headers = vec![("x-correlation-id".to_string(), " upstream-42 ".to_string())];
+        assert_eq!(assign(&config, &headers, None), "upstream-42");
+        assert_eq!(assign(&config, &[], Some(&b"\x04tdm-7"[..])), "tdm-7");
+
+        // Unusable or unwanted inbound IDs are replaced
+        let bad = vec![("X-Correlation-ID".to_string(), "two words".to_string())];
+        assert!(Uuid::parse_str(&assign(&config, &bad, None)).is_ok());
+        let config = CorrelationConfig { accept_inbound: false, ..Default::default() };
+        assert_ne!(assign(&config, &headers, None), "upstream-42");
+    }
+
+    #[test]
+    fn test_id_travels_in_sip_and_q931() {
+        let config = CorrelationConfig::default();
+        let mut headers = BTreeMap::new();
+        add_request_header(&config, "call-1", &mut headers);
+        assert_eq!(headers.get("X-Correlation-ID").map(String::as_str), Some("call-1"));
+
+        let uui = user_user(&config, "call-1").unwrap();
+        assert_eq!(from_user_user(&uui).as_deref(), Some("call-1"));
+        assert_eq!(from_user_user(b"\x00\x01"), None);
+
+        let message = "INVITE sip:100@gw SIP/2.0\r\nX-Correlation-ID: call-1\r\n\r\nX-Correlation-ID: body";
+        assert_eq!(from_sip_message("x-correlation-id", message).as_deref(), Some("call-1"));
+
+        let quiet = CorrelationConfig { propagate: false, ..Default::default() };
+        assert!(user_user(&quiet, "call-1").is_none());
+        assert!(response_headers(&quiet, "call-1").is_empty());
+    }
+}
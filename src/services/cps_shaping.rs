@@ -0,0 +1,491 @@
+//! Call-setup rate shaping for the B2BUA
+//!
+//! This module smooths bursts of new calls (typically from predictive
+//! dialers) with a token bucket per trunk and per tenant, a tenant being
+//! the set of source networks its INVITEs arrive from. Instead of
+//! rejecting excess INVITEs outright, calls are held in a short queue until
+//! a token becomes available or the configured maximum queue delay expires.
+//! Each bucket's queue is first in, first out: a call only takes a token
+//! once every call queued before it on the same bucket has been admitted or
+//! turned away. A call abandoned while queued (e.g. CANCELled) gives up
+//! its place as soon as its admission future is dropped.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, info, warn};
+
+use crate::config::{CpsShapingConfig, ShapingScope};
+use crate::services::craft;
+
+/// Result of an admission request
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdmissionOutcome {
+    /// Call admitted, possibly after waiting in the queue
+    Admitted { queued_for: Duration },
+    /// Call rejected because the queue was full or the delay budget ran out
+    Rejected { reason: String, response_code: u16 },
+}
+
+/// Rate shaping events
+#[derive(Debug, Clone)]
+pub enum ShapingEvent {
+    CallQueued {
+        scope: ShapingScope,
+        id: String,
+        queue_depth: usize,
+    },
+    CallAdmitted {
+        trunk: String,
+        tenant: Option<String>,
+        queued_for: Duration,
+    },
+    CallRejected {
+        trunk: String,
+        tenant: Option<String>,
+        reason: String,
+    },
+}
+
+/// Classic token bucket refilled continuously at `rate` tokens per second
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let capacity = (burst.max(1)) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Time until one token is available (zero if available now)
+    pub fn time_to_token(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        if self.rate <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+struct ShapedBucket {
+    bucket: Mutex<TokenBucket>,
+    /// Held by the call at the head of the queue; tokio's mutex is fair, so
+    /// waiters get it in arrival order
+    head: Mutex<()>,
+    queued: AtomicUsize,
+}
+
+impl ShapedBucket {
+    fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket::new(rate, burst)),
+            head: Mutex::new(()),
+            queued: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// A call's place in the queues of the buckets it waits on; leaving the
+/// queue, however the wait ends, takes it out of every count
+struct QueueSlot<'a> {
+    buckets: &'a [(ShapingScope, String, Arc<ShapedBucket>)],
+    held: bool,
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        if self.held {
+            for (_, _, bucket) in self.buckets {
+                bucket.queued.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// Per-trunk and per-tenant call-setup rate shaper
+pub struct CpsShaper {
+    config: CpsShapingConfig,
+    buckets: DashMap<(ShapingScope, String), Arc<ShapedBucket>>,
+    /// Tenant source networks in configuration order
+    tenants: Vec<(IpAddr, u8, String)>,
+    event_tx: mpsc::UnboundedSender<ShapingEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ShapingEvent>>,
+}
+
+impl CpsShaper {
+    pub fn new(config: CpsShapingConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        let buckets = DashMap::new();
+        let mut tenants = Vec::new();
+        for bucket in &config.buckets {
+            buckets.insert(
+                (bucket.scope, bucket.id.clone()),
+                Arc::new(ShapedBucket::new(bucket.rate_cps, bucket.burst)),
+            );
+            if bucket.scope == ShapingScope::Tenant {
+                tenants.extend(bucket.sources.iter()
+                    .filter_map(|source| craft::parse_cidr(source))
+                    .map(|(network, prefix)| (network, prefix, bucket.id.clone())));
+            }
+        }
+
+        info!("Created CPS shaper with {} buckets (max queue delay {}ms)",
+            buckets.len(), config.max_queue_delay_ms);
+
+        Self {
+            config,
+            buckets,
+            tenants,
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<ShapingEvent>> {
+        self.event_rx.take()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Tenant whose source networks include `source`
+    pub fn tenant_for(&self, source: IpAddr) -> Option<&str> {
+        self.tenants
+            .iter()
+            .find(|(network, prefix, _)| in_network(source, *network, *prefix))
+            .map(|(_, _, tenant)| tenant.as_str())
+    }
+
+    fn bucket_for(&self, scope: ShapingScope, id: &str) -> Option<Arc<ShapedBucket>> {
+        if let Some(bucket) = self.buckets.get(&(scope, id.to_string())) {
+            return Some(Arc::clone(bucket.value()));
+        }
+
+        match (scope, self.config.default_trunk_cps) {
+            (ShapingScope::Trunk, Some(rate)) => {
+                let bucket = self.buckets
+                    .entry((scope, id.to_string()))
+                    .or_insert_with(|| Arc::new(ShapedBucket::new(rate, rate.ceil() as u32)));
+                Some(Arc::clone(bucket.value()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Wait for a call-setup token on the trunk (and tenant, if any).
+    ///
+    /// Returns immediately when shaping is disabled or no bucket applies.
+    pub async fn admit(&self, trunk: &str, tenant: Option<&str>) -> AdmissionOutcome {
+        if !self.config.enabled {
+            return AdmissionOutcome::Admitted { queued_for: Duration::ZERO };
+        }
+
+        let mut buckets: Vec<(ShapingScope, String, Arc<ShapedBucket>)> = Vec::new();
+        if let Some(bucket) = self.bucket_for(ShapingScope::Trunk, trunk) {
+            buckets.push((ShapingScope::Trunk, trunk.to_string(), bucket));
+        }
+        if let Some(tenant) = tenant {
+            if let Some(bucket) = self.bucket_for(ShapingScope::Tenant, tenant) {
+                buckets.push((ShapingScope::Tenant, tenant.to_string(), bucket));
+            }
+        }
+
+        if buckets.is_empty() {
+            return AdmissionOutcome::Admitted { queued_for: Duration::ZERO };
+        }
+
+        let start = Instant::now();
+        let mut slot = QueueSlot { buckets: &buckets, held: false };
+        let outcome = self.wait_for_token(&buckets, start, &mut slot).await;
+        drop(slot);
+
+        match &outcome {
+            AdmissionOutcome::Admitted { queued_for } => {
+                debug!("Call admitted on trunk {} after {:?}", trunk, queued_for);
+                let _ = self.event_tx.send(ShapingEvent::CallAdmitted {
+                    trunk: trunk.to_string(),
+                    tenant: tenant.map(|t| t.to_string()),
+                    queued_for: *queued_for,
+                });
+            }
+            AdmissionOutcome::Rejected { reason, .. } => {
+                warn!("Call rejected by CPS shaper on trunk {}: {}", trunk, reason);
+                let _ = self.event_tx.send(ShapingEvent::CallRejected {
+                    trunk: trunk.to_string(),
+                    tenant: tenant.map(|t| t.to_string()),
+                    reason: reason.clone(),
+                });
+            }
+        }
+
+        outcome
+    }
+
+    /// Wait behind earlier calls on every bucket, then for a token on all
+    /// of them, within the maximum queue delay from `start`
+    async fn wait_for_token(
+        &self,
+        buckets: &[(ShapingScope, String, Arc<ShapedBucket>)],
+        start: Instant,
+        slot: &mut QueueSlot<'_>,
+    ) -> AdmissionOutcome {
+        let max_delay = Duration::from_millis(self.config.max_queue_delay_ms);
+        let deadline = tokio::time::Instant::from_std(start) + max_delay;
+
+        // Buckets are always taken trunk first, so two calls never wait on each other
+        let mut heads = Vec::with_capacity(buckets.len());
+        for (_, _, bucket) in buckets {
+            let head = match bucket.head.try_lock() {
+                Ok(head) => head,
+                Err(_) => {
+                    if !slot.held {
+                        slot.held = true;
+                        if self.enqueue(buckets) {
+                            return self.rejected("queue full");
+                        }
+                    }
+                    match tokio::time::timeout_at(deadline, bucket.head.lock()).await {
+                        Ok(head) => head,
+                        Err(_) => return self.rejected("queue delay exceeded"),
+                    }
+                }
+            };
+            heads.push(head);
+        }
+
+        loop {
+            let wait = self.try_take(buckets).await;
+            if wait.is_zero() {
+                return AdmissionOutcome::Admitted { queued_for: start.elapsed() };
+            }
+
+            if start.elapsed().saturating_add(wait) > max_delay {
+                return self.rejected("queue delay exceeded");
+            }
+
+            if !slot.held {
+                slot.held = true;
+                if self.enqueue(buckets) {
+                    return self.rejected("queue full");
+                }
+            }
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Count a call as waiting on every bucket; true when that overflows any queue
+    fn enqueue(&self, buckets: &[(ShapingScope, String, Arc<ShapedBucket>)]) -> bool {
+        let mut full = false;
+        for (scope, id, bucket) in buckets {
+            let depth = bucket.queued.fetch_add(1, Ordering::SeqCst) + 1;
+            let _ = self.event_tx.send(ShapingEvent::CallQueued {
+                scope: *scope,
+                id: id.clone(),
+                queue_depth: depth,
+            });
+            full |= depth > self.config.max_queue_depth;
+        }
+        full
+    }
+
+    /// Take a token from every bucket if all have one; otherwise return
+    /// the longest wait among them without consuming anything.
+    async fn try_take(&self, buckets: &[(ShapingScope, String, Arc<ShapedBucket>)]) -> Duration {
+        let now = Instant::now();
+        let mut guards = Vec::with_capacity(buckets.len());
+        for (_, _, bucket) in buckets {
+            guards.push(bucket.bucket.lock().await);
+        }
+
+        let wait = guards
+            .iter_mut()
+            .map(|g| g.time_to_token(now))
+            .max()
+            .unwrap_or(Duration::ZERO);
+
+        if wait.is_zero() {
+            for guard in guards.iter_mut() {
+                guard.take();
+            }
+        }
+
+        wait
+    }
+
+    fn rejected(&self, reason: &str) -> AdmissionOutcome {
+        AdmissionOutcome::Rejected {
+            reason: reason.to_string(),
+            response_code: self.config.reject_code,
+        }
+    }
+
+    /// Number of calls currently waiting on a bucket
+    pub fn queue_depth(&self, scope: ShapingScope, id: &str) -> usize {
+        self.buckets
+            .get(&(scope, id.to_string()))
+            .map(|b| b.queued.load(Ordering::SeqCst))
+            .unwrap_or(0)
+    }
+}
+
+/// Whether `address` lies within `network`/`prefix`
+fn in_network(address: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CpsBucketConfig;
+
+    fn config(rate: f64, burst: u32, max_delay_ms: u64) -> CpsShapingConfig {
+        CpsShapingConfig {
+            enabled: true,
+            max_queue_delay_ms: max_delay_ms,
+            max_queue_depth: 10,
+            reject_code: 503,
+            default_trunk_cps: None,
+            buckets: vec![CpsBucketConfig {
+                scope: ShapingScope::Trunk,
+                id: "dialer".to_string(),
+                rate_cps: rate,
+                burst,
+                sources: vec![],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let mut bucket = TokenBucket::new(10.0, 1);
+        let now = Instant::now();
+        assert_eq!(bucket.time_to_token(now), Duration::ZERO);
+        bucket.take();
+
+        let wait = bucket.time_to_token(now);
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_burst_is_queued_not_rejected() {
+        let shaper = CpsShaper::new(config(20.0, 1, 1000));
+
+        assert!(matches!(shaper.admit("dialer", None).await, AdmissionOutcome::Admitted { .. }));
+        match shaper.admit("dialer", None).await {
+            AdmissionOutcome::Admitted { queued_for } => assert!(queued_for >= Duration::from_millis(30)),
+            other => panic!("expected admission, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_when_delay_budget_exceeded() {
+        let shaper = CpsShaper::new(config(1.0, 1, 10));
+
+        assert!(matches!(shaper.admit("dialer", None).await, AdmissionOutcome::Admitted { .. }));
+        assert_eq!(
+            shaper.admit("dialer", None).await,
+            AdmissionOutcome::Rejected { reason: "queue delay exceeded".to_string(), response_code: 503 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queue_is_fifo() {
+        let shaper = Arc::new(CpsShaper::new(config(10.0, 1, 1000)));
+        assert!(matches!(shaper.admit("dialer", None).await, AdmissionOutcome::Admitted { .. }));
+
+        let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+        for call in 0..3 {
+            let shaper = Arc::clone(&shaper);
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                assert!(matches!(shaper.admit("dialer", None).await, AdmissionOutcome::Admitted { .. }));
+                let _ = order_tx.send(call);
+            });
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(order_tx);
+
+        let mut order = Vec::new();
+        while let Some(call) = order_rx.recv().await {
+            order.push(call);
+        }
+        assert_eq!(order, vec![0, 1, 2]);
+        assert_eq!(shaper.queue_depth(ShapingScope::Trunk, "dialer"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_call_leaves_queue() {
+        let shaper = CpsShaper::new(config(10.0, 1, 1000));
+        assert!(matches!(shaper.admit("dialer", None).await, AdmissionOutcome::Admitted { .. }));
+
+        let abandoned = tokio::time::timeout(Duration::from_millis(20), shaper.admit("dialer", None)).await;
+        assert!(abandoned.is_err());
+        assert_eq!(shaper.queue_depth(ShapingScope::Trunk, "dialer"), 0);
+        assert!(matches!(shaper.admit("dialer", None).await, AdmissionOutcome::Admitted { .. }));
+    }
+
+    #[test]
+    fn test_tenant_matched_on_source() {
+        let mut config = config(10.0, 1, 1000);
+        config.buckets.push(CpsBucketConfig {
+            scope: ShapingScope::Tenant,
+            id: "acme".to_string(),
+            rate_cps: 5.0,
+            burst: 5,
+            sources: vec!["198.51.100.0/24".to_string(), "2001:db8::/32".to_string()],
+        });
+        let shaper = CpsShaper::new(config);
+
+        assert_eq!(shaper.tenant_for("198.51.100.7".parse().unwrap()), Some("acme"));
+        assert_eq!(shaper.tenant_for("2001:db8::1".parse().unwrap()), Some("acme"));
+        assert_eq!(shaper.tenant_for("203.0.113.7".parse().unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn test_unshaped_trunk_is_admitted() {
+        let shaper = CpsShaper::new(config(1.0, 1, 10));
+        for _ in 0..5 {
+            assert!(matches!(shaper.admit("other", None).await, AdmissionOutcome::Admitted { .. }));
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Serial craft console for headless field units
+//!
+//! A minimal line-oriented shell on a serial port, for units installed in
+//! cabinets where the network configuration itself may be broken. Status
+//! commands read the snapshot last published by the gateway; changing the
+//! management address, restarting services and factory resets are handed to
+//! the gateway as requests. When a craft password is configured nothing is
+//! accepted until it has been entered, and idle sessions are locked again.
+
+use std::net::IpAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::CraftConsoleConfig;
+use crate::interfaces::freetdm::{ChannelState, SpanStatus};
+use crate::services::alarms::Alarm;
+use crate::{Error, Result};
+
+/// Services the console may restart
+pub const RESTARTABLE_SERVICES: [&str; 3] = ["gateway", "sip", "spans"];
+
+const HELP: &[&str] = &[
+    "status                          node, span and alarm summary",
+    "spans                           span and channel status",
+    "alarms                          active alarms",
+    "ip                              management interface addresses",
+    "set ip <address/prefix> [<gw>]  change the management address",
+    "restart <gateway|sip|spans>     restart a service",
+    "factory-reset confirm           restore the default configuration",
+    "logout                          end the session",
+];
+
+/// Gateway state shown on the console
+#[derive(Debug, Clone, Default)]
+pub struct CraftSnapshot {
+    pub node_id: String,
+    pub running: bool,
+    pub uptime: Duration,
+    pub spans: Vec<SpanStatus>,
+    pub alarms: Vec<Alarm>,
+    pub management_addresses: Vec<String>,
+}
+
+/// Changes requested from the console, carried out by the gateway owner
+#[derive(Debug, Clone, PartialEq)]
+pub enum CraftRequest {
+    SetManagementAddress {
+        interface: String,
+        address: String,
+        gateway: Option<String>,
+    },
+    RestartService {
+        service: String,
+    },
+    FactoryReset,
+}
+
+/// Result of one console line
+#[derive(Debug, Default)]
+pub struct CraftReply {
+    pub output: Vec<String>,
+    pub request: Option<CraftRequest>,
+    pub logout: bool,
+}
+
+impl CraftReply {
+    fn lines(output: Vec<String>) -> Self {
+        Self { output, ..Default::default() }
+    }
+
+    fn line(line: impl Into<String>) -> Self {
+        Self::lines(vec![line.into()])
+    }
+}
+
+/// Command interpreter for one console session
+pub struct CraftShell {
+    config: CraftConsoleConfig,
+    authenticated: bool,
+}
+
+impl CraftShell {
+    pub fn new(config: CraftConsoleConfig) -> Self {
+        let authenticated = config.password.is_none();
+        Self { config, authenticated }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Require the password again, e.g. after the idle timeout
+    pub fn lock(&mut self) {
+        self.authenticated = self.config.password.is_none();
+    }
+
+    pub fn prompt(&self, snapshot: &CraftSnapshot) -> String {
+        if self.authenticated {
+            format!("{}> ", if snapshot.node_id.is_empty() { "craft" } else { &snapshot.node_id })
+        } else {
+            "Password: ".to_string()
+        }
+    }
+
+    pub fn handle_line(&mut self, line: &str, snapshot: &CraftSnapshot) -> CraftReply {
+        let line = line.trim();
+        if !self.authenticated {
+            if self.config.password.as_deref() == Some(line) {
+                self.authenticated = true;
+                return CraftReply::line("Logged in. Type 'help' for commands.");
+            }
+            warn!("Failed craft console login on {}", self.config.device);
+            return CraftReply::line("Login incorrect");
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => CraftReply::default(),
+            ["help"] | ["?"] => CraftReply::lines(HELP.iter().map(|line| line.to_string()).collect()),
+            ["status"] => CraftReply::lines(Self::status(snapshot)),
+            ["spans"] => CraftReply::lines(Self::spans(snapshot)),
+            ["alarms"] => CraftReply::lines(Self::alarms(snapshot)),
+            ["ip"] => CraftReply::lines(self.ip(snapshot)),
+            ["set", "ip", address, rest @ ..] if rest.len() <= 1 => self.set_ip(address, rest.first().copied()),
+            ["restart", service] if RESTARTABLE_SERVICES.contains(service) => {
+                info!("Craft console restarting {}", service);
+                CraftReply {
+                    output: vec![format!("Restarting {}", service)],
+                    request: Some(CraftRequest::RestartService { service: service.to_string() }),
+                    logout: false,
+                }
+            }
+            ["restart", ..] => CraftReply::line(format!("Usage: restart <{}>", RESTARTABLE_SERVICES.join("|"))),
+            ["factory-reset", "confirm"] => {
+                warn!("Craft console factory reset requested");
+                CraftReply {
+                    output: vec!["Restoring factory configuration and restarting".to_string()],
+                    request: Some(CraftRequest::FactoryReset),
+                    logout: true,
+                }
+            }
+            ["factory-reset", ..] => {
+                CraftReply::line("This erases all configuration. Type 'factory-reset confirm' to proceed.")
+            }
+            ["logout"] | ["exit"] | ["quit"] => {
+                self.lock();
+                CraftReply { output: vec!["Bye".to_string()], request: None, logout: true }
+            }
+            _ => CraftReply::line(format!("Unknown command '{}'. Type 'help' for commands.", line)),
+        }
+    }
+
+    fn status(snapshot: &CraftSnapshot) -> Vec<String> {
+        let up = snapshot.spans.iter().filter(|span| span.is_up).count();
+        vec![
+            format!("Node:    {}", snapshot.node_id),
+            format!("State:   {}", if snapshot.running { "running" } else { "stopped" }),
+            format!("Uptime:  {}s", snapshot.uptime.as_secs()),
+            format!("Spans:   {} up, {} down", up, snapshot.spans.len() - up),
+            format!("Alarms:  {} active", snapshot.alarms.len()),
+            format!(
+                "Mgmt IP: {}",
+                if snapshot.management_addresses.is_empty() { "none".to_string() } else { snapshot.management_addresses.join(", ") }
+            ),
+        ]
+    }
+
+    fn spans(snapshot: &CraftSnapshot) -> Vec<String> {
+        if snapshot.spans.is_empty() {
+            return vec!["No spans configured".to_string()];
+        }
+        snapshot
+            .spans
+            .iter()
+            .map(|span| {
+                let busy = span.channels.iter().filter(|channel| channel.state == ChannelState::InUse).count();
+                format!(
+                    "{:>3} {:<16} {:<5} {:?} {}/{} busy{}{}",
+                    span.span_id,
+                    span.name,
+                    if span.is_up { "UP" } else { "DOWN" },
+                    span.trunk_type,
+                    busy,
+                    span.channels.len(),
+                    span.description.summary().map(|summary| format!(" ({})", summary)).unwrap_or_default(),
+                    if span.alarms.is_empty() { String::new() } else { format!(" [{}]", span.alarms.join(", ")) }
+                )
+            })
+            .collect()
+    }
+
+    fn alarms(snapshot: &CraftSnapshot) -> Vec<String> {
+        if snapshot.alarms.is_empty() {
+            return vec!["No active alarms".to_string()];
+        }
+        snapshot
+            .alarms
+            .iter()
+            .map(|alarm| {
+                format!(
+                    "{} {:?} {}",
+                    alarm.raised_time.format("%Y-%m-%d %H:%M:%S"),
+                    alarm.severity,
+                    alarm.description
+                )
+            })
+            .collect()
+    }
+
+    fn ip(&self, snapshot: &CraftSnapshot) -> Vec<String> {
+        let mut lines = vec![format!("Interface:  {}", self.config.management_interface)];
+        lines.extend(snapshot.management_addresses.iter().map(|address| format!("Address:    {}", address)));
+        if let Some(address) = &self.config.management_address {
+            lines.push(format!("Configured: {}", address));
+        }
+        if let Some(gateway) = &self.config.management_gateway {
+            lines.push(format!("Gateway:    {}", gateway));
+        }
+        lines
+    }
+
+    fn set_ip(&mut self, address: &str, gateway: Option<&str>) -> CraftReply {
+        if parse_cidr(address).is_none() {
+            return CraftReply::line(format!("Invalid address '{}', expected e.g. 192.0.2.10/24", address));
+        }
+        if let Some(gateway) = gateway {
+            if gateway.parse::<IpAddr>().is_err() {
+                return CraftReply::line(format!("Invalid gateway '{}'", gateway));
+            }
+        }
+
+        self.config.management_address = Some(address.to_string());
+        self.config.management_gateway = gateway.map(str::to_string);
+        info!("Craft console setting management address {} via {:?}", address, gateway);
+        CraftReply {
+            output: vec![format!("Setting {} on {}", address, self.config.management_interface)],
+            request: Some(CraftRequest::SetManagementAddress {
+                interface: self.config.management_interface.clone(),
+                address: address.to_string(),
+                gateway: gateway.map(str::to_string),
+            }),
+            logout: false,
+        }
+    }
+}
+
+/// Address and prefix length of a CIDR string
+pub fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix) = cidr.split_once('/')?;
+    let address: IpAddr = address.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    (prefix <= max).then_some((address, prefix))
+}
+
+/// Addresses currently assigned to `interface`
+pub async fn interface_addresses(interface: &str) -> Vec<String> {
+    match Command::new("ip").args(["-o", "addr", "show", "dev", interface]).output().await {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().skip_while(|field| *field != "inet" && *field != "inet6");
+                fields.next()?;
+                fields.next().map(str::to_string)
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Replace the addresses on `interface` and the default route
+pub async fn apply_management_address(interface: &str, address: &str, gateway: Option<&str>) -> Result<()> {
+    if parse_cidr(address).is_none() {
+        return Err(Error::invalid_config(format!("Invalid management address {}", address)));
+    }
+
+    let mut commands: Vec<Vec<&str>> = vec![
+        vec!["addr", "flush", "dev", interface],
+        vec!["addr", "add", address, "dev", interface],
+        vec!["link", "set", "dev", interface, "up"],
+    ];
+    if let Some(gateway) = gateway {
+        commands.push(vec!["route", "replace", "default", "via", gateway, "dev", interface]);
+    }
+
+    for args in commands {
+        let status = Command::new("ip").args(&args).status().await?;
+        if !status.success() {
+            return Err(Error::network(format!("ip {} failed with {}", args.join(" "), status)));
+        }
+    }
+    info!("Management address on {} set to {}", interface, address);
+    Ok(())
+}
+
+/// Serial craft console service
+pub struct CraftConsole {
+    config: CraftConsoleConfig,
+    snapshot: Arc<RwLock<CraftSnapshot>>,
+    event_tx: mpsc::UnboundedSender<CraftRequest>,
+    event_rx: Option<mpsc::UnboundedReceiver<CraftRequest>>,
+}
+
+impl CraftConsole {
+    pub fn new(config: CraftConsoleConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            config,
+            snapshot: Arc::new(RwLock::new(CraftSnapshot::default())),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<CraftRequest>> {
+        self.event_rx.take()
+    }
+
+    /// Replace the state shown by status commands
+    pub async fn publish(&self, snapshot: CraftSnapshot) {
+        *self.snapshot.write().await = snapshot;
+    }
+
+    /// Open the serial device at the configured speed, keeping its line discipline
+    pub fn open_device(&self) -> Result<tokio::fs::File> {
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&self.config.device)?;
+        let speed = baud_constant(self.config.baud_rate)
+            .ok_or_else(|| Error::invalid_config(format!("Unsupported baud rate {}", self.config.baud_rate)))?;
+
+        // SAFETY: the descriptor is open for the duration of these calls and
+        // `termios` is fully initialised by tcgetattr before it is modified
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(file.as_raw_fd(), &mut termios) != 0
+                || libc::cfsetispeed(&mut termios, speed) != 0
+                || libc::cfsetospeed(&mut termios, speed) != 0
+                || libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, &termios) != 0
+            {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        Ok(tokio::fs::File::from_std(file))
+    }
+
+    /// Run sessions on `io` until it closes
+    pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(&self, io: S) -> Result<()> {
+        let (reader, mut writer) = tokio::io::split(io);
+        let mut lines = BufReader::new(reader).lines();
+        let mut shell = CraftShell::new(self.config.clone());
+        let idle = Duration::from_secs(self.config.idle_timeout_secs.max(1));
+
+        writer.write_all(b"\r\nRedfire Gateway craft console\r\n").await?;
+        loop {
+            let prompt = shell.prompt(&*self.snapshot.read().await);
+            writer.write_all(prompt.as_bytes()).await?;
+            writer.flush().await?;
+
+            let line = match tokio::time::timeout(idle, lines.next_line()).await {
+                Ok(Ok(Some(line))) => line,
+                Ok(Ok(None)) => return Ok(()),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => {
+                    if shell.is_authenticated() && self.config.password.is_some() {
+                        shell.lock();
+                        writer.write_all(b"\r\nSession locked after inactivity\r\n").await?;
+                    }
+                    continue;
+                }
+            };
+
+            let reply = shell.handle_line(&line, &*self.snapshot.read().await);
+            for output in &reply.output {
+                writer.write_all(output.as_bytes()).await?;
+                writer.write_all(b"\r\n").await?;
+            }
+            if let Some(request) = reply.request {
+                let _ = self.event_tx.send(request);
+            }
+            if reply.logout {
+                shell.lock();
+            }
+        }
+    }
+
+    /// Serve the configured serial device, reopening it if it goes away
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        let console = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                let result = match console.open_device() {
+                    Ok(device) => {
+                        info!("Craft console on {} at {} baud", console.config.device, console.config.baud_rate);
+                        console.serve(device).await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    warn!("Craft console on {} failed: {}", console.config.device, e);
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        })
+    }
+}
+
+fn baud_constant(baud_rate: u32) -> Option<libc::speed_t> {
+    Some(match baud_rate {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell() -> CraftShell {
+        CraftShell::new(CraftConsoleConfig {
+            enabled: true,
+            password: Some("s3cret".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_password_gates_commands() {
+        let mut shell = shell();
+        let snapshot = CraftSnapshot { node_id: "gw-cab-12".to_string(), ..Default::default() };
+
+        assert_eq!(shell.prompt(&snapshot), "Password: ");
+        assert_eq!(shell.handle_line("status", &snapshot).output, vec!["Login incorrect"]);
+        assert!(!shell.is_authenticated());
+
+        shell.handle_line("s3cret\r", &snapshot);
+        assert_eq!(shell.prompt(&snapshot), "gw-cab-12> ");
+        let status = shell.handle_line("status", &snapshot).output;
+        assert!(status.contains(&"Spans:   0 up, 0 down".to_string()));
+        assert!(status.contains(&"Mgmt IP: none".to_string()));
+
+        assert!(shell.handle_line("logout", &snapshot).logout);
+        assert!(!shell.is_authenticated());
+    }
+
+    #[test]
+    fn test_requests_are_validated() {
+        let mut shell = CraftShell::new(CraftConsoleConfig::default());
+        let snapshot = CraftSnapshot::default();
+
+        assert!(shell.handle_line("set ip 10.0.0.300/24", &snapshot).request.is_none());
+        assert!(shell.handle_line("set ip 10.0.0.5/33", &snapshot).request.is_none());
+        assert_eq!(
+            shell.handle_line("set ip 10.0.0.5/24 10.0.0.1", &snapshot).request,
+            Some(CraftRequest::SetManagementAddress {
+                interface: "eth0".to_string(),
+                address: "10.0.0.5/24".to_string(),
+                gateway: Some("10.0.0.1".to_string()),
+            })
+        );
+        assert!(shell.handle_line("ip", &snapshot).output.contains(&"Configured: 10.0.0.5/24".to_string()));
+
+        assert!(shell.handle_line("restart snmp", &snapshot).request.is_none());
+        assert_eq!(
+            shell.handle_line("restart sip", &snapshot).request,
+            Some(CraftRequest::RestartService { service: "sip".to_string() })
+        );
+
+        assert!(shell.handle_line("factory-reset", &snapshot).request.is_none());
+        assert_eq!(shell.handle_line("factory-reset confirm", &snapshot).request, Some(CraftRequest::FactoryReset));
+    }
+}
@@ -10,6 +10,9 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, RwLock};
 use tracing::{debug, info};
 
+use crate::protocols::q931::Q931Message;
+use crate::services::correlation;
+use crate::services::ports::PortDirectory;
 use crate::Result;
 
 /// Debug configuration
@@ -21,6 +24,8 @@ pub struct DebugConfig {
     pub max_history_size: usize,
     pub capture_content: bool,
     pub filter_patterns: Vec<String>,
+    /// SIP header whose correlation ID is recorded with captured messages
+    pub correlation_header: String,
 }
 
 impl Default for DebugConfig {
@@ -32,6 +37,7 @@ impl Default for DebugConfig {
             max_history_size: 10000,
             capture_content: true,
             filter_patterns: Vec::new(),
+            correlation_header: "X-Correlation-ID".to_string(),
         }
     }
 }
@@ -67,6 +73,9 @@ pub struct DebugMessage {
     pub destination: Option<SocketAddr>,
     pub call_reference: Option<u16>,
     pub call_id: Option<String>,
+    /// Correlation ID of the call, from the SIP header or Q.931 user-user
+    #[serde(default)]
+    pub correlation_id: Option<String>,
     pub message_type: String,
     pub content: Option<String>,
     pub raw_data: Option<Vec<u8>>,
@@ -172,6 +181,9 @@ pub struct BChannelStatus {
     pub codec: Option<String>,
     pub quality_metrics: ChannelQualityMetrics,
     pub last_activity: DateTime<Utc>,
+    /// Summary of the port's provisioned description
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -205,6 +217,7 @@ pub struct DebugService {
     event_tx: mpsc::UnboundedSender<DebugEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<DebugEvent>>,
     message_counter: Arc<RwLock<u64>>,
+    ports: Option<Arc<PortDirectory>>,
     is_running: bool,
 }
 
@@ -219,10 +232,15 @@ impl DebugService {
             event_tx,
             event_rx: Some(event_rx),
             message_counter: Arc::new(RwLock::new(0)),
+            ports: None,
             is_running: false,
         }
     }
 
+    pub fn set_port_directory(&mut self, ports: Arc<PortDirectory>) {
+        self.ports = Some(ports);
+    }
+
     pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<DebugEvent>> {
         self.event_rx.take()
     }
@@ -328,6 +346,7 @@ impl DebugService {
             destination: Some(destination),
             call_reference: None,
             call_id,
+            correlation_id: correlation::from_sip_message(&config.correlation_header, message),
             message_type: sip_info.method
                 .or_else(|| sip_info.response_code.map(|c| format!("{} {}", c, 
                     sip_info.response_phrase.as_deref().unwrap_or("Unknown"))))
@@ -380,6 +399,9 @@ impl DebugService {
             destination: None,
             call_reference: Some(q931_info.call_reference_value),
             call_id: None,
+            correlation_id: Q931Message::decode(raw_data)
+                .ok()
+                .and_then(|message| message.user_user().and_then(correlation::from_user_user)),
             message_type: q931_info.message_name.clone(),
             content: None,
             raw_data: if config.capture_content { Some(raw_data.to_vec()) } else { None },
@@ -432,6 +454,7 @@ impl DebugService {
             destination: None,
             call_reference: None,
             call_id: None,
+            correlation_id: None,
             message_type: frame_type_name.to_string(),
             content: None,
             raw_data: if config.capture_content { Some(raw_data.to_vec()) } else { None },
@@ -476,6 +499,7 @@ impl DebugService {
                 error_count: 0,
             },
             last_activity: now,
+            description: None,
         });
 
         // Update state and handle state transitions
@@ -517,6 +541,13 @@ impl DebugService {
         channels.values()
             .filter(|status| span_id.map_or(true, |s| status.span_id == s))
             .cloned()
+            .map(|mut status| {
+                status.description = self.ports
+                    .as_ref()
+                    .and_then(|ports| ports.channel(status.span_id, status.channel_id))
+                    .and_then(|description| description.summary());
+                status
+            })
             .collect()
     }
 
@@ -536,6 +567,16 @@ impl DebugService {
             .collect()
     }
 
+    /// Captured messages of the call carrying `correlation_id`, oldest first
+    pub async fn messages_for_correlation(&self, correlation_id: &str) -> Vec<DebugMessage> {
+        let history = self.message_history.read().await;
+        history
+            .iter()
+            .filter(|msg| msg.correlation_id.as_deref() == Some(correlation_id))
+            .cloned()
+            .collect()
+    }
+
     /// Clear debug message history
     pub async fn clear_history(&self) -> Result<usize> {
         let mut history = self.message_history.write().await;
@@ -724,6 +765,12 @@ impl DebugService {
             0x01 => "ALERTING",
             0x02 => "CALL PROCEEDING",
             0x03 => "PROGRESS",
+            0x24 => "HOLD",
+            0x28 => "HOLD ACK",
+            0x30 => "HOLD REJECT",
+            0x31 => "RETRIEVE",
+            0x33 => "RETRIEVE ACK",
+            0x37 => "RETRIEVE REJECT",
             0x45 => "DISCONNECT",
             0x4D => "RELEASE",
             0x5A => "RELEASE COMPLETE",
@@ -913,4 +960,27 @@ mod tests {
         assert_eq!(channels[0].state, BChannelState::Connected);
         assert_eq!(channels[0].call_id, Some("test-call".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_captures_are_found_by_correlation_id() {
+        let service = DebugService::new(DebugConfig::default());
+        service.set_sip_debug(true).await.unwrap();
+        service.set_tdm_debug(true).await.unwrap();
+        let peer: SocketAddr = "192.0.2.1:5060".parse().unwrap();
+        let gateway: SocketAddr = "192.0.2.2:5060".parse().unwrap();
+
+        let invite = "INVITE sip:2000@gw SIP/2.0\r\nCall-ID: a@peer\r\nX-Correlation-ID: corr-7\r\n\r\n";
+        service.capture_sip_message(MessageDirection::Incoming, peer, gateway, invite, None).await.unwrap();
+        let other = "INVITE sip:3000@gw SIP/2.0\r\nCall-ID: b@peer\r\n\r\n";
+        service.capture_sip_message(MessageDirection::Incoming, peer, gateway, other, None).await.unwrap();
+        let setup = Q931Message::new(crate::protocols::q931::MessageType::Setup, 3, false)
+            .with_called_number("2000")
+            .with_user_user(&b"\x04corr-7"[..]);
+        service.capture_q931_message(MessageDirection::Outgoing, 1, &setup.encode()).await.unwrap();
+
+        let messages = service.messages_for_correlation("corr-7").await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].protocol, ProtocolType::Sip);
+        assert_eq!(messages[1].protocol, ProtocolType::Q931);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,521 @@
+//! Demo mode
+//!
+//! `redfire-gateway --demo` runs without TDM hardware, SIP peers or clock
+//! references. Two simulated spans carry a handful of synthetic tandem calls
+//! that come and go, alarms are raised and cleared in rotation, the clock
+//! sources drift and a BERT runs on one channel. All of it is served through
+//! the management API and the metrics endpoint exactly as a live gateway
+//! serves it, so the dashboard, redfire-diag and monitoring can be shown and
+//! developed against realistic-looking data.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use tokio::task::JoinHandle;
+
+use crate::config::{CapacityConfig, GatewayConfig};
+use crate::core::control::{ControlStatus, SpanSummary};
+use crate::services::capacity::{CapacityQuery, CapacityReport, CapacityStats};
+use crate::services::management_api::{
+    ActiveCallReport, AlarmReport, CdrQuery, CdrReport, ChannelReport, ClockSourceReport, ManagementSource,
+    RtpSessionReport, ServiceReport, SpanReport, TestSessionReport, TimingReport,
+};
+use crate::services::metrics::{self, MetricsRegistry};
+use crate::Result;
+
+/// How often the simulated traffic moves on
+pub const DEMO_TICK: Duration = Duration::from_secs(2);
+
+/// Most synthetic calls up at once
+const MAX_CALLS: usize = 8;
+/// Ticks each alarm in the rotation stays raised
+const ALARM_TICKS: u64 = 20;
+/// Released calls kept as CDRs
+const MAX_CDRS: usize = 1000;
+/// Channel the demo BERT runs on
+const BERT_CHANNEL: u16 = 12;
+
+/// Simulated spans: id, name and trunk type
+const SPANS: [(u32, &str, &str); 2] = [(1, "demo-e1-1", "e1"), (2, "demo-t1-1", "t1")];
+
+struct DemoAlarm {
+    severity: &'static str,
+    alarm_type: &'static str,
+    source: &'static str,
+    description: &'static str,
+    /// Span the alarm takes down while raised
+    span_down: Option<u32>,
+}
+
+const ALARMS: [DemoAlarm; 4] = [
+    DemoAlarm {
+        severity: "minor",
+        alarm_type: "quality",
+        source: "span/1",
+        description: "Controlled frame slips above threshold",
+        span_down: None,
+    },
+    DemoAlarm {
+        severity: "major",
+        alarm_type: "communication",
+        source: "span/2",
+        description: "Loss of signal",
+        span_down: Some(2),
+    },
+    DemoAlarm {
+        severity: "warning",
+        alarm_type: "processing",
+        source: "sip/trunk-a",
+        description: "SIP trunk response time degraded",
+        span_down: None,
+    },
+    DemoAlarm {
+        severity: "minor",
+        alarm_type: "equipment",
+        source: "timing/gps0",
+        description: "GPS antenna open circuit",
+        span_down: None,
+    },
+];
+
+/// Index of the alarm that takes the GPS reference away
+const GPS_ALARM: usize = 3;
+
+/// B-channels of a simulated span, without the D-channel
+fn span_channels(trunk_type: &str) -> Vec<u8> {
+    match trunk_type {
+        "e1" => (1..=31).filter(|channel| *channel != 16).collect(),
+        _ => (1..=23).collect(),
+    }
+}
+
+struct DemoCall {
+    id: String,
+    route: String,
+    calling_number: String,
+    called_number: String,
+    ingress: (u32, u8),
+    egress: (u32, u8),
+    started_tick: u64,
+    /// Ticks until the call releases
+    hold_ticks: u64,
+    start_time: DateTime<Utc>,
+    started: Instant,
+}
+
+impl DemoCall {
+    /// Answered two ticks after it was placed
+    fn connected(&self, tick: u64) -> bool {
+        tick >= self.started_tick + 2
+    }
+
+    fn uses(&self, span_id: u32, channel_id: u8) -> bool {
+        self.ingress == (span_id, channel_id) || self.egress == (span_id, channel_id)
+    }
+}
+
+struct DemoState {
+    tick: u64,
+    next_call: u64,
+    calls: Vec<DemoCall>,
+    /// Rotation index and raise time of the current alarm
+    alarm: Option<(usize, DateTime<Utc>)>,
+    cdrs: VecDeque<CdrReport>,
+    phase_offset_ns: i64,
+    frequency_offset_ppb: i64,
+    bert_bits: u64,
+    bert_errors: u64,
+}
+
+impl DemoState {
+    fn span_up(&self, span_id: u32) -> bool {
+        !matches!(self.alarm, Some((index, _)) if ALARMS[index].span_down == Some(span_id))
+    }
+
+    fn release(&mut self, call: DemoCall, cause: &str) {
+        let now = Utc::now();
+        let answered = call.connected(self.tick);
+        self.cdrs.push_front(CdrReport {
+            id: format!("cdr-{}", call.id),
+            call_id: call.id,
+            correlation_id: None,
+            caller: call.calling_number,
+            callee: call.called_number,
+            start_time: call.start_time,
+            answer_time: answered.then(|| call.start_time + chrono::Duration::seconds(2 * DEMO_TICK.as_secs() as i64)),
+            end_time: Some(now),
+            duration_secs: call.started.elapsed().as_secs(),
+            disconnect_reason: Some(cause.to_string()),
+        });
+        self.cdrs.truncate(MAX_CDRS);
+    }
+}
+
+/// Synthetic gateway state, moved on by `tick`
+pub struct DemoGateway {
+    node_id: String,
+    started: Instant,
+    state: Mutex<DemoState>,
+    capacity: CapacityStats,
+    metrics: MetricsRegistry,
+}
+
+impl DemoGateway {
+    pub fn new(node_id: &str) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            started: Instant::now(),
+            state: Mutex::new(DemoState {
+                tick: 0,
+                next_call: 1,
+                calls: Vec::new(),
+                alarm: None,
+                cdrs: VecDeque::new(),
+                phase_offset_ns: 0,
+                frequency_offset_ppb: 0,
+                bert_bits: 0,
+                bert_errors: 0,
+            }),
+            capacity: CapacityStats::new(CapacityConfig::default()),
+            metrics: MetricsRegistry::new(),
+        }
+    }
+
+    /// Move the simulation on by one step: rotate the alarm, release and
+    /// answer calls, place new ones and let the clocks drift
+    pub fn tick(&self) {
+        let mut rng = rand::thread_rng();
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+
+        if (tick - 1) % ALARM_TICKS == 0 {
+            let index = state.alarm.map_or(0, |(index, _)| (index + 1) % ALARMS.len());
+            state.alarm = Some((index, Utc::now()));
+        }
+
+        // Calls end when their time is up or a span they use goes down
+        let calls = std::mem::take(&mut state.calls);
+        for call in calls {
+            if !state.span_up(call.ingress.0) || !state.span_up(call.egress.0) {
+                state.release(call, "network_failure");
+            } else if tick >= call.started_tick + call.hold_ticks {
+                state.release(call, "normal");
+            } else {
+                state.calls.push(call);
+            }
+        }
+
+        if state.calls.len() < MAX_CALLS && rng.gen_bool(0.5) {
+            let free: Vec<(u32, u8)> = SPANS
+                .iter()
+                .filter(|(span_id, _, _)| state.span_up(*span_id))
+                .flat_map(|(span_id, _, trunk_type)| span_channels(trunk_type).into_iter().map(move |channel| (*span_id, channel)))
+                .filter(|(span_id, channel)| !state.calls.iter().any(|call| call.uses(*span_id, *channel)))
+                .collect();
+            if free.len() >= 2 {
+                let ingress = free[rng.gen_range(0..free.len())];
+                let egress = loop {
+                    let egress = free[rng.gen_range(0..free.len())];
+                    if egress != ingress {
+                        break egress;
+                    }
+                };
+                let id = format!("demo-{}", state.next_call);
+                state.next_call += 1;
+                state.calls.push(DemoCall {
+                    id,
+                    route: format!("span{}-to-span{}", ingress.0, egress.0),
+                    calling_number: format!("555{:04}", rng.gen_range(0..10000)),
+                    called_number: format!("0207946{:04}", rng.gen_range(0..10000)),
+                    ingress,
+                    egress,
+                    started_tick: tick,
+                    hold_ticks: rng.gen_range(10..=90),
+                    start_time: Utc::now(),
+                    started: Instant::now(),
+                });
+            }
+        }
+
+        state.phase_offset_ns = (state.phase_offset_ns + rng.gen_range(-20..=20)).clamp(-500, 500);
+        state.frequency_offset_ppb = (state.frequency_offset_ppb + rng.gen_range(-1..=1)).clamp(-10, 10);
+        state.bert_bits += 2_048_000 * DEMO_TICK.as_secs();
+        if rng.gen_bool(0.1) {
+            state.bert_errors += 1;
+        }
+    }
+
+    /// Tick and sample channel usage for the capacity report
+    fn tick_and_sample(&self) {
+        self.tick();
+        let now = Utc::now();
+        for span in self.span_reports() {
+            let busy = span.channels.iter().filter(|channel| channel.state == "in_use").count();
+            self.capacity.sample(&span.name, span.channels.len(), busy as u32, now);
+        }
+    }
+
+    /// Tick every `interval` until the task is aborted
+    pub fn spawn(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let demo = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                demo.tick_and_sample();
+            }
+        })
+    }
+
+    fn span_reports(&self) -> Vec<SpanReport> {
+        let state = self.state.lock().unwrap();
+        SPANS
+            .iter()
+            .map(|(span_id, name, trunk_type)| {
+                let up = state.span_up(*span_id);
+                SpanReport {
+                    span_id: *span_id,
+                    name: name.to_string(),
+                    trunk_type: trunk_type.to_string(),
+                    up,
+                    alarms: if up { Vec::new() } else { vec!["LOS".to_string()] },
+                    channels: span_channels(trunk_type)
+                        .into_iter()
+                        .map(|channel_id| ChannelReport {
+                            channel_id,
+                            state: if !up {
+                                "out_of_service"
+                            } else if state.calls.iter().any(|call| call.uses(*span_id, channel_id)) {
+                                "in_use"
+                            } else {
+                                "idle"
+                            }
+                            .to_string(),
+                            enabled: true,
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ManagementSource for DemoGateway {
+    async fn status(&self) -> ControlStatus {
+        let spans: Vec<SpanSummary> = self
+            .span_reports()
+            .into_iter()
+            .map(|span| SpanSummary {
+                span_id: span.span_id,
+                name: span.name,
+                up: span.up,
+                channels: span.channels.len(),
+                busy_channels: span.channels.iter().filter(|channel| channel.state == "in_use").count(),
+                alarms: span.alarms,
+            })
+            .collect();
+        let calls = self.state.lock().unwrap().calls.len() as u32;
+        ControlStatus {
+            node_id: self.node_id.clone(),
+            version: crate::VERSION.to_string(),
+            running: true,
+            uptime_secs: self.started.elapsed().as_secs(),
+            active_calls: calls,
+            active_channels: calls * 2,
+            sip_sessions: 0,
+            rtp_sessions: 0,
+            spans,
+        }
+    }
+
+    async fn spans(&self) -> Vec<SpanReport> {
+        self.span_reports()
+    }
+
+    async fn active_calls(&self) -> Vec<ActiveCallReport> {
+        let state = self.state.lock().unwrap();
+        state
+            .calls
+            .iter()
+            .map(|call| ActiveCallReport {
+                id: call.id.clone(),
+                route: call.route.clone(),
+                calling_number: Some(call.calling_number.clone()),
+                called_number: call.called_number.clone(),
+                ingress: format!("{}/{}", call.ingress.0, call.ingress.1),
+                egress: format!("{}/{}", call.egress.0, call.egress.1),
+                state: if call.connected(state.tick) { "connected" } else { "proceeding" }.to_string(),
+                duration_secs: call.started.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    async fn rtp_sessions(&self) -> Vec<RtpSessionReport> {
+        // The simulated tandem calls never leave TDM
+        Vec::new()
+    }
+
+    async fn services(&self) -> Vec<ServiceReport> {
+        let running = ["freetdm", "tandem", "cdr", "alarms", "timing"];
+        ["tdmoe", "freetdm", "sip", "rtp", "tandem", "cdr", "alarms", "timing"]
+            .into_iter()
+            .map(|name| ServiceReport {
+                name: name.to_string(),
+                state: if running.contains(&name) { "running" } else { "disabled" }.to_string(),
+            })
+            .collect()
+    }
+
+    async fn alarms(&self) -> Vec<AlarmReport> {
+        let state = self.state.lock().unwrap();
+        state
+            .alarm
+            .iter()
+            .map(|(index, raised_time)| {
+                let alarm = &ALARMS[*index];
+                AlarmReport {
+                    id: format!("demo-alarm-{}", index),
+                    severity: alarm.severity.to_string(),
+                    alarm_type: alarm.alarm_type.to_string(),
+                    source: alarm.source.to_string(),
+                    description: alarm.description.to_string(),
+                    raised_time: *raised_time,
+                    acknowledged: false,
+                    event_count: 1,
+                }
+            })
+            .collect()
+    }
+
+    async fn cdrs(&self, query: &CdrQuery, limit: usize) -> Result<Vec<CdrReport>> {
+        let since = Utc::now() - chrono::Duration::hours(i64::from(query.hours.unwrap_or(24)));
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .cdrs
+            .iter()
+            .filter(|cdr| cdr.start_time >= since)
+            .filter(|cdr| query.caller.as_ref().map_or(true, |caller| &cdr.caller == caller))
+            .filter(|cdr| query.callee.as_ref().map_or(true, |callee| &cdr.callee == callee))
+            .filter(|cdr| query.correlation_id.is_none() || cdr.correlation_id == query.correlation_id)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn timing(&self) -> Option<TimingReport> {
+        let state = self.state.lock().unwrap();
+        let gps_lost = matches!(state.alarm, Some((GPS_ALARM, _)));
+        let now = Utc::now();
+        let source = |source_id: &str, source_type: &str, stratum: &str, active: bool| ClockSourceReport {
+            source_id: source_id.to_string(),
+            source_type: source_type.to_string(),
+            stratum: stratum.to_string(),
+            active,
+            holdover: false,
+            last_sync: active.then_some(now),
+            phase_offset_ns: 0,
+            frequency_offset_ppb: 0,
+        };
+        let mut gps = source("gps0", "gps", "stratum0", !gps_lost);
+        gps.phase_offset_ns = state.phase_offset_ns;
+        gps.frequency_offset_ppb = state.frequency_offset_ppb;
+        let mut ntp = source("ntp0", "ntp", "stratum2", true);
+        ntp.phase_offset_ns = state.phase_offset_ns * 40;
+        Some(TimingReport {
+            running: true,
+            stratum: if gps_lost { "stratum2" } else { "stratum1" }.to_string(),
+            selected_source: Some(if gps_lost { "ntp0" } else { "gps0" }.to_string()),
+            sources: vec![gps, source("internal", "internal", "stratum4", true), ntp],
+        })
+    }
+
+    async fn test_sessions(&self) -> Vec<TestSessionReport> {
+        let state = self.state.lock().unwrap();
+        vec![TestSessionReport {
+            kind: "bert".to_string(),
+            channel: BERT_CHANNEL,
+            status: "running".to_string(),
+            elapsed_secs: self.started.elapsed().as_secs(),
+            errors: state.bert_errors,
+            error_rate: if state.bert_bits > 0 { state.bert_errors as f64 / state.bert_bits as f64 } else { 0.0 },
+        }]
+    }
+
+    async fn capacity(&self, query: &CapacityQuery) -> Option<CapacityReport> {
+        Some(self.capacity.report(query, Utc::now()))
+    }
+
+    async fn metrics(&self) -> Result<String> {
+        let status = self.status().await;
+        let alarms = self.alarms().await;
+        self.metrics.set(metrics::UP, &[], 1.0);
+        self.metrics.set(metrics::UPTIME, &[], status.uptime_secs as f64);
+        self.metrics.set(metrics::ACTIVE_CHANNELS, &[], status.active_channels as f64);
+        self.metrics.set(metrics::SIP_SESSIONS, &[], status.sip_sessions as f64);
+        self.metrics.set(metrics::RTP_SESSIONS, &[], status.rtp_sessions as f64);
+        {
+            let mut rng = rand::thread_rng();
+            self.metrics.set(metrics::CPU_USAGE, &[], rng.gen_range(8.0..20.0));
+            self.metrics.set(metrics::MEMORY_USAGE, &[], rng.gen_range(24.0..28.0));
+            self.metrics.set(metrics::LOAD_AVERAGE, &[], rng.gen_range(0.3..1.2));
+        }
+        for severity in ["critical", "major", "minor", "warning"] {
+            let count = alarms.iter().filter(|alarm| alarm.severity == severity).count();
+            self.metrics.set(metrics::ACTIVE_ALARMS, &[severity], count as f64);
+        }
+        self.metrics.render()
+    }
+
+    async fn config(&self) -> Option<GatewayConfig> {
+        let mut config = GatewayConfig::default_config();
+        config.general.node_id = self.node_id.clone();
+        Some(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_simulated_traffic() {
+        let demo = DemoGateway::new("demo");
+        for _ in 0..ALARM_TICKS * ALARMS.len() as u64 {
+            demo.tick_and_sample();
+
+            let calls = demo.active_calls().await;
+            assert!(calls.len() <= MAX_CALLS);
+            let spans = demo.spans().await;
+            let busy: usize = spans
+                .iter()
+                .map(|span| span.channels.iter().filter(|channel| channel.state == "in_use").count())
+                .sum();
+            // Each call holds two channels of its own
+            assert_eq!(busy, calls.len() * 2);
+
+            let alarms = demo.alarms().await;
+            assert_eq!(alarms.len(), 1);
+            if alarms[0].description == "Loss of signal" {
+                assert!(!spans[1].up);
+                assert!(calls.iter().all(|call| !call.ingress.starts_with("2/") && !call.egress.starts_with("2/")));
+            }
+        }
+
+        // Every alarm in the rotation has been raised once
+        let timing = demo.timing().await.unwrap();
+        assert_eq!(timing.selected_source.as_deref(), Some("ntp0"));
+        let status = demo.status().await;
+        assert_eq!(status.spans.len(), 2);
+        assert_eq!(status.spans[0].channels, 30);
+        assert!(demo.metrics().await.unwrap().contains(metrics::ACTIVE_ALARMS));
+        let capacity = demo.capacity(&CapacityQuery::default()).await.unwrap();
+        assert_eq!(capacity.trunks.len(), 2);
+        assert_eq!(capacity.trunks[0].channels, 30);
+    }
+}
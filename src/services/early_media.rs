@@ -0,0 +1,94 @@
+//! Early media gating
+//!
+//! Some fraudulent far ends deliver chargeable content in 183 Session
+//! Progress and never answer, so the call is never billed as connected. A
+//! route can cap how long early media may flow before answer, releasing the
+//! unanswered call once the cap is reached, and can block backward early
+//! media altogether so the caller hears plain ringing instead. Both are
+//! counted per trunk, and calls cut off at the cap are released with cause
+//! 102 (recovery on timer expiry).
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::EarlyMediaPolicy;
+
+/// Management API path listing early media gating counters
+pub const EARLY_MEDIA_PATH: &str = "/api/v1/b2bua/early-media";
+
+/// Gating outcomes for one trunk
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct EarlyMediaCounter {
+    pub trunk: String,
+    /// Calls whose backward early media was not passed to the caller
+    pub blocked: u64,
+    /// Unanswered calls released at the early media limit
+    pub cut_off: u64,
+}
+
+impl EarlyMediaPolicy {
+    /// Whether early media that began at `started` has run past the limit
+    pub fn expired(&self, started: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.max_duration_secs
+            .is_some_and(|limit| (now - started).num_milliseconds() >= (limit as i64).saturating_mul(1000))
+    }
+}
+
+/// Counts calls affected by early media gating
+#[derive(Default)]
+pub struct EarlyMediaGate {
+    counters: DashMap<String, EarlyMediaCounter>,
+}
+
+impl EarlyMediaGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn counter(&self, trunk: &str) -> dashmap::mapref::one::RefMut<'_, String, EarlyMediaCounter> {
+        self.counters.entry(trunk.to_string()).or_insert_with(|| EarlyMediaCounter {
+            trunk: trunk.to_string(),
+            ..EarlyMediaCounter::default()
+        })
+    }
+
+    pub fn record_blocked(&self, trunk: &str) {
+        self.counter(trunk).blocked += 1;
+    }
+
+    pub fn record_cut_off(&self, trunk: &str) {
+        self.counter(trunk).cut_off += 1;
+    }
+
+    /// Counters by trunk
+    pub fn counters(&self) -> Vec<EarlyMediaCounter> {
+        let mut counters: Vec<EarlyMediaCounter> = self.counters.iter().map(|entry| entry.value().clone()).collect();
+        counters.sort_by(|a, b| a.trunk.cmp(&b.trunk));
+        counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_limit_and_counters() {
+        let started = Utc::now();
+        let policy = EarlyMediaPolicy { max_duration_secs: Some(30), block_backward: false };
+        assert!(!policy.expired(started, started + Duration::seconds(29)));
+        assert!(policy.expired(started, started + Duration::seconds(30)));
+        assert!(!EarlyMediaPolicy::default().expired(started, started + Duration::days(1)));
+
+        let gate = EarlyMediaGate::new();
+        gate.record_cut_off("carrier-b");
+        gate.record_blocked("carrier-a");
+        gate.record_cut_off("carrier-b");
+        let counters = gate.counters();
+        assert_eq!(counters[0], EarlyMediaCounter { trunk: "carrier-a".to_string(), blocked: 1, cut_off: 0 });
+        assert_eq!(counters[1].cut_off, 2);
+    }
+}
@@ -0,0 +1,485 @@
+//! Fax detection and T.38 relay on calls broken out to TDM
+//!
+//! Fax sent as G.711 over a SIP trunk fails on the first burst of loss or
+//! jitter the fax modems cannot ride out. The relay listens to the audio of
+//! calls broken out to TDM for fax tones: CED (2100 Hz) from the answering
+//! machine, the V.21 flags that open every T.30 exchange and, if configured,
+//! CNG (1100 Hz) from the caller. Once one is heard the SIP leg is
+//! re-INVITEd to T.38. When it accepts, the fax is carried between the SIP
+//! leg and the span as IFP packets over UDPTL, with the B-channel's fax
+//! modem terminating the T.30 session on the TDM side. When it refuses, the
+//! call stays on G.711 and the fax is passed through untouched.
+
+use std::f64::consts::PI;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use chrono::Utc;
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+use crate::config::FaxRelayConfig;
+use crate::protocols::sdp::{MediaDescription, SessionDescription};
+use crate::protocols::udptl::{IfpPacket, UdptlPacket, UdptlReceiver, UdptlSender, T38_VERSION};
+use crate::services::b2bua::{B2buaEvent, CallLeg};
+use crate::services::prompts::{alaw_to_linear, ulaw_to_linear};
+use crate::{Error, Result};
+
+const SAMPLE_RATE: f64 = 8000.0;
+/// Samples analysed at a time: 20 ms
+const BLOCK: usize = 160;
+const BLOCK_MS: usize = 20;
+/// Mean square below which a block is too quiet to hold a tone (about -44 dBm0)
+const MIN_POWER: f64 = 10_000.0;
+/// Share of a block's power a tone's frequencies must carry
+const TONE_SHARE: f64 = 0.7;
+
+/// A tone that marks a call as fax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FaxTone {
+    Cng,
+    Ced,
+    /// HDLC flags on V.21 channel 2 ahead of a T.30 frame
+    V21Preamble,
+}
+
+impl FaxTone {
+    const ALL: [FaxTone; 3] = [FaxTone::Cng, FaxTone::Ced, FaxTone::V21Preamble];
+
+    /// Goertzel bins the tone falls in; V.21 shifts between 1650 and 1850 Hz
+    fn frequencies(self) -> &'static [f64] {
+        match self {
+            FaxTone::Cng => &[1100.0],
+            FaxTone::Ced => &[2100.0],
+            FaxTone::V21Preamble => &[1600.0, 1650.0, 1700.0, 1750.0, 1800.0, 1850.0, 1900.0],
+        }
+    }
+
+    /// How long the tone must last before the call is taken as fax
+    fn min_duration_ms(self) -> usize {
+        match self {
+            // CNG comes in 0.5 s bursts
+            FaxTone::Cng => 400,
+            FaxTone::Ced => 500,
+            // A T.30 preamble lasts 1 s
+            FaxTone::V21Preamble => 300,
+        }
+    }
+}
+
+/// How a call's fax is carried once detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FaxMode {
+    T38,
+    Passthrough,
+}
+
+/// Power of `samples` at `frequency`
+fn goertzel(samples: &[f64], frequency: f64) -> f64 {
+    let coefficient = 2.0 * (2.0 * PI * frequency / SAMPLE_RATE).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for sample in samples {
+        let s0 = sample + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    s1 * s1 + s2 * s2 - coefficient * s1 * s2
+}
+
+/// Listens to one call's G.711 audio for fax tones
+#[derive(Debug, Default)]
+pub struct ToneDetector {
+    samples: Vec<f64>,
+    /// Consecutive blocks each tone has filled, in `FaxTone::ALL` order
+    runs: [usize; 3],
+}
+
+impl ToneDetector {
+    /// Feed one RTP payload; returns the first of `tones` to last long enough
+    pub fn feed(&mut self, payload_type: u8, payload: &[u8], tones: &[FaxTone]) -> Option<FaxTone> {
+        let decode: fn(u8) -> i16 = match payload_type {
+            0 => ulaw_to_linear,
+            8 => alaw_to_linear,
+            _ => return None,
+        };
+        self.samples.extend(payload.iter().map(|&sample| decode(sample) as f64));
+
+        let mut detected = None;
+        while self.samples.len() >= BLOCK {
+            let block: Vec<f64> = self.samples.drain(..BLOCK).collect();
+            let power: f64 = block.iter().map(|sample| sample * sample).sum();
+            for (run, tone) in self.runs.iter_mut().zip(FaxTone::ALL) {
+                let in_tone: f64 = tone.frequencies().iter().map(|&frequency| goertzel(&block, frequency)).sum();
+                // A pure tone on a bin has power * BLOCK / 2 there
+                let present = power / BLOCK as f64 >= MIN_POWER && in_tone >= TONE_SHARE * power * BLOCK as f64 / 2.0;
+                *run = if present { *run + 1 } else { 0 };
+                if tones.contains(&tone) && *run * BLOCK_MS >= tone.min_duration_ms() {
+                    detected = detected.or(Some(tone));
+                }
+            }
+        }
+        detected
+    }
+}
+
+/// Value of a T.38 attribute, whose names endpoints spell in any case
+fn t38_attribute<'a>(stream: &'a MediaDescription, name: &str) -> Option<&'a str> {
+    stream
+        .lines
+        .iter()
+        .find(|line| line.attribute_name().is_some_and(|attribute| attribute.eq_ignore_ascii_case(name)))
+        .and_then(|line| line.attribute_value())
+}
+
+/// T.38 offer receiving UDPTL on `address`:`port`
+pub fn t38_offer(address: IpAddr, port: u16, config: &FaxRelayConfig) -> String {
+    let family = if address.is_ipv4() { "IP4" } else { "IP6" };
+    let version = Utc::now().timestamp();
+    format!(
+        "v=0\r\no=- {} {} IN {} {}\r\ns=-\r\nc=IN {} {}\r\nt=0 0\r\n\
+         m=image {} udptl t38\r\na=T38FaxVersion:{}\r\na=T38MaxBitRate:{}\r\n\
+         a=T38FaxRateManagement:transferredTCF\r\na=T38FaxMaxBuffer:{}\r\n\
+         a=T38FaxMaxDatagram:{}\r\na=T38FaxUdpEC:t38UDPRedundancy\r\n",
+        version, version, family, address, family, address, port, T38_VERSION, config.max_bit_rate,
+        config.max_datagram * 4, config.max_datagram
+    )
+}
+
+/// Where and how the SIP leg takes T.38, from its answer
+#[derive(Debug, Clone, PartialEq)]
+pub struct T38Answer {
+    pub remote: SocketAddr,
+    pub version: u8,
+    pub max_datagram: Option<usize>,
+}
+
+impl T38Answer {
+    /// The accepted T.38 stream of an answer, if it has one
+    pub fn from_sdp(sdp: &str) -> Option<Self> {
+        let sdp = SessionDescription::parse(sdp).ok()?;
+        let stream = sdp.media.iter().find(|stream| {
+            stream.media_type.eq_ignore_ascii_case("image")
+                && !stream.is_rejected()
+                && stream.formats.iter().any(|format| format.eq_ignore_ascii_case("t38"))
+        })?;
+        let address: IpAddr = stream.connection_address().or(sdp.connection_address())?.parse().ok()?;
+        Some(Self {
+            remote: SocketAddr::new(address, stream.port),
+            version: t38_attribute(stream, "T38FaxVersion").and_then(|value| value.trim().parse().ok()).unwrap_or(0),
+            max_datagram: t38_attribute(stream, "T38FaxMaxDatagram").and_then(|value| value.trim().parse().ok()),
+        })
+    }
+}
+
+enum FaxState {
+    /// Each leg's audio, A then B, is listened to on its own
+    Listening([ToneDetector; 2]),
+    /// A tone was heard; the SIP leg is being switched over
+    Detected,
+    /// re-INVITE to T.38 sent on the SIP leg
+    Offered { session_id: String, socket: Arc<UdpSocket> },
+    T38 {
+        socket: Arc<UdpSocket>,
+        remote: SocketAddr,
+        version: u8,
+        sender: UdptlSender,
+        receiver: JoinHandle<()>,
+    },
+    Passthrough,
+}
+
+struct FaxCall {
+    tone: Option<FaxTone>,
+    state: FaxState,
+}
+
+/// Fax detection and T.38 sessions of calls broken out to TDM
+pub struct FaxRelay {
+    config: FaxRelayConfig,
+    tones: Vec<FaxTone>,
+    calls: DashMap<String, FaxCall>,
+}
+
+impl FaxRelay {
+    pub fn new(config: FaxRelayConfig) -> Self {
+        let tones = FaxTone::ALL
+            .into_iter()
+            .filter(|tone| *tone != FaxTone::Cng || config.switch_on_cng)
+            .collect();
+        Self { config, tones, calls: DashMap::new() }
+    }
+
+    pub fn t38_enabled(&self) -> bool {
+        self.config.t38
+    }
+
+    /// Listen to a packet of audio from one of the call's legs; returns the
+    /// tone that first shows the call is fax, after which the call must be
+    /// switched over
+    pub fn on_audio(&self, call_id: &str, leg: CallLeg, payload_type: u8, payload: &[u8]) -> Option<FaxTone> {
+        let mut call = self.calls.entry(call_id.to_string()).or_insert_with(|| FaxCall {
+            tone: None,
+            state: FaxState::Listening(Default::default()),
+        });
+        let FaxState::Listening(detectors) = &mut call.state else {
+            return None;
+        };
+        let detector = match leg {
+            CallLeg::A => &mut detectors[0],
+            CallLeg::B => &mut detectors[1],
+        };
+        let tone = detector.feed(payload_type, payload, &self.tones)?;
+        info!("Fax {:?} detected from leg {:?} of call {}", tone, leg, call_id);
+        call.tone = Some(tone);
+        call.state = FaxState::Detected;
+        Some(tone)
+    }
+
+    /// Open a UDPTL port for the call and return the T.38 offer to re-INVITE
+    /// its SIP leg (`session_id`) with, advertising `address`
+    pub async fn offer_t38(&self, call_id: &str, session_id: &str, address: IpAddr) -> Result<String> {
+        let bind_ip: IpAddr = match address {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(bind_ip, 0))
+            .await
+            .map_err(|e| Error::network(format!("Failed to open UDPTL port: {}", e)))?;
+        let port = socket.local_addr()?.port();
+        let mut call = self.calls.get_mut(call_id).ok_or_else(|| Error::invalid_state("No fax detected on the call"))?;
+        call.state = FaxState::Offered { session_id: session_id.to_string(), socket: Arc::new(socket) };
+        Ok(t38_offer(address, port, &self.config))
+    }
+
+    /// Whether a T.38 re-INVITE is outstanding on `session_id`
+    pub fn is_offered(&self, session_id: &str) -> bool {
+        self.offered_call(session_id).is_some()
+    }
+
+    fn offered_call(&self, session_id: &str) -> Option<String> {
+        self.calls
+            .iter()
+            .find(|call| matches!(&call.state, FaxState::Offered { session_id: offered, .. } if offered == session_id))
+            .map(|call| call.key().clone())
+    }
+
+    /// Final response to the T.38 re-INVITE on `session_id`. A 2xx with a
+    /// T.38 stream starts the relay, delivering packets from the SIP leg as
+    /// [`B2buaEvent::FaxIfp`]; anything else leaves the fax on G.711.
+    /// Returns the call, the tone that was detected and how fax is carried
+    pub fn answered(
+        &self,
+        session_id: &str,
+        status_code: u16,
+        sdp: Option<&str>,
+        event_tx: &mpsc::UnboundedSender<B2buaEvent>,
+    ) -> Option<(String, FaxTone, FaxMode)> {
+        let call_id = self.offered_call(session_id)?;
+        let mut call = self.calls.get_mut(&call_id)?;
+        let tone = call.tone?;
+        let FaxState::Offered { socket, .. } = &call.state else {
+            return None;
+        };
+        let socket = Arc::clone(socket);
+        let answer = sdp.filter(|_| (200..300).contains(&status_code)).and_then(T38Answer::from_sdp);
+        let Some(answer) = answer else {
+            info!("SIP leg of call {} refused T.38 ({}); passing fax through as G.711", call_id, status_code);
+            call.state = FaxState::Passthrough;
+            return Some((call_id, tone, FaxMode::Passthrough));
+        };
+
+        let max_datagram = answer.max_datagram.map_or(self.config.max_datagram, |max| max.min(self.config.max_datagram));
+        let receiver = tokio::spawn(Self::receive_loop(
+            call_id.clone(),
+            Arc::clone(&socket),
+            answer.remote,
+            answer.version,
+            self.config.max_datagram,
+            event_tx.clone(),
+        ));
+        info!("Call {} switched to T.38 version {} with {}", call_id, answer.version, answer.remote);
+        call.state = FaxState::T38 {
+            socket,
+            remote: answer.remote,
+            version: answer.version,
+            sender: UdptlSender::new(self.config.udptl_redundancy, max_datagram),
+            receiver,
+        };
+        Some((call_id, tone, FaxMode::T38))
+    }
+
+    /// Leave a call's fax on G.711, e.g. when T.38 is not offered
+    pub fn pass_through(&self, call_id: &str) {
+        if let Some(mut call) = self.calls.get_mut(call_id) {
+            call.state = FaxState::Passthrough;
+        }
+    }
+
+    async fn receive_loop(
+        call_id: String,
+        socket: Arc<UdpSocket>,
+        remote: SocketAddr,
+        version: u8,
+        max_datagram: usize,
+        event_tx: mpsc::UnboundedSender<B2buaEvent>,
+    ) {
+        let mut receiver = UdptlReceiver::default();
+        let mut buffer = vec![0u8; max_datagram.max(1500)];
+        loop {
+            let (len, source) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!("UDPTL receive for call {} stopped: {}", call_id, e);
+                    return;
+                }
+            };
+            if source.ip() != remote.ip() {
+                debug!("Dropped UDPTL datagram for call {} from {}", call_id, source);
+                continue;
+            }
+            let packet = match UdptlPacket::decode(&buffer[..len]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    debug!("Dropped UDPTL datagram for call {}: {}", call_id, e);
+                    continue;
+                }
+            };
+            for ifp in receiver.receive(packet) {
+                match IfpPacket::decode(&ifp, version) {
+                    Ok(packet) => {
+                        if event_tx.send(B2buaEvent::FaxIfp { call_id: call_id.clone(), packet }).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => debug!("Dropped IFP packet for call {}: {}", call_id, e),
+                }
+            }
+        }
+    }
+
+    /// Send an IFP packet from the span's fax modem to the SIP leg
+    pub async fn send(&self, call_id: &str, packet: &IfpPacket) -> Result<()> {
+        let (socket, remote, datagram) = {
+            let mut call = self.calls.get_mut(call_id).ok_or_else(|| Error::invalid_state("No fax on the call"))?;
+            let FaxState::T38 { socket, remote, version, sender, .. } = &mut call.state else {
+                return Err(Error::invalid_state("Call is not relaying T.38"));
+            };
+            (Arc::clone(socket), *remote, sender.datagram(packet.encode(*version)))
+        };
+        socket.send_to(&datagram, remote).await?;
+        Ok(())
+    }
+
+    /// How the call's fax is carried, once it has been detected
+    pub fn mode(&self, call_id: &str) -> Option<FaxMode> {
+        match self.calls.get(call_id)?.state {
+            FaxState::T38 { .. } => Some(FaxMode::T38),
+            FaxState::Passthrough => Some(FaxMode::Passthrough),
+            _ => None,
+        }
+    }
+
+    /// Forget a call that has ended, closing its UDPTL port
+    pub fn release(&self, call_id: &str) {
+        if let Some((_, call)) = self.calls.remove(call_id) {
+            if let FaxState::T38 { receiver, .. } = call.state {
+                receiver.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::udptl::T30Indicator;
+    use crate::services::prompts::linear_to_ulaw;
+
+    /// 20 ms PCMU packets of the sum of `frequencies`
+    fn packets(frequencies: &[f64], count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|packet| {
+                (0..BLOCK)
+                    .map(|i| {
+                        let t = (packet * BLOCK + i) as f64 / SAMPLE_RATE;
+                        let sample: f64 = frequencies.iter().map(|f| 6000.0 * (2.0 * PI * f * t).sin()).sum();
+                        linear_to_ulaw(sample as i16)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tone_detection() {
+        let tones = [FaxTone::Ced, FaxTone::V21Preamble];
+        let detect = |frequencies: &[f64]| {
+            let mut detector = ToneDetector::default();
+            packets(frequencies, 50)
+                .iter()
+                .enumerate()
+                .find_map(|(index, payload)| detector.feed(0, payload, &tones).map(|tone| (index, tone)))
+        };
+        assert_eq!(detect(&[2100.0]), Some((24, FaxTone::Ced)));
+        assert_eq!(detect(&[1750.0]), Some((14, FaxTone::V21Preamble)));
+        // CNG is only heard when asked for; speech-like and DTMF audio never
+        assert_eq!(detect(&[1100.0]), None);
+        assert_eq!(detect(&[300.0, 700.0, 1200.0, 2500.0]), None);
+        assert_eq!(detect(&[697.0, 1209.0]), None);
+
+        let relay = FaxRelay::new(FaxRelayConfig { enabled: true, switch_on_cng: true, ..Default::default() });
+        let cng = packets(&[1100.0], 20);
+        assert_eq!(cng.iter().find_map(|payload| relay.on_audio("call-1", CallLeg::A, 0, payload)), Some(FaxTone::Cng));
+        assert_eq!(relay.on_audio("call-1", CallLeg::A, 0, &cng[0]), None);
+    }
+
+    #[tokio::test]
+    async fn test_t38_offer_answer_and_relay() {
+        let config = FaxRelayConfig { enabled: true, ..Default::default() };
+        let relay = FaxRelay::new(config);
+        let ced = packets(&[2100.0], 25);
+        assert!(ced.iter().any(|payload| relay.on_audio("call-1", CallLeg::B, 0, payload).is_some()));
+
+        let offer = relay.offer_t38("call-1", "leg-a", "127.0.0.1".parse().unwrap()).await.unwrap();
+        let local = T38Answer::from_sdp(&offer).unwrap();
+        assert_eq!((local.version, local.max_datagram), (0, Some(400)));
+        assert!(relay.is_offered("leg-a"));
+
+        let far_end = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let answer = format!(
+            "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+             m=image {} udptl t38\r\na=t38faxversion:0\r\na=T38FaxMaxDatagram:200\r\n",
+            far_end.local_addr().unwrap().port()
+        );
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let answered = relay.answered("leg-a", 200, Some(&answer), &event_tx);
+        assert_eq!(answered, Some(("call-1".to_string(), FaxTone::Ced, FaxMode::T38)));
+        assert_eq!(relay.mode("call-1"), Some(FaxMode::T38));
+
+        // Span to SIP leg
+        relay.send("call-1", &IfpPacket::Indicator(T30Indicator::Ced)).await.unwrap();
+        let mut buffer = [0u8; 512];
+        let (len, from) = far_end.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(UdptlPacket::decode(&buffer[..len]).unwrap().primary, vec![0x04]);
+
+        // SIP leg to span
+        let reply = UdptlPacket { sequence: 0, primary: vec![0x06], secondary: Vec::new() };
+        far_end.send_to(&reply.encode(), from).await.unwrap();
+        match event_rx.recv().await {
+            Some(B2buaEvent::FaxIfp { call_id, packet }) => {
+                assert_eq!(call_id, "call-1");
+                assert_eq!(packet, IfpPacket::Indicator(T30Indicator::V21Preamble));
+            }
+            other => panic!("Expected an IFP packet, got {:?}", other),
+        }
+        relay.release("call-1");
+        assert_eq!(relay.mode("call-1"), None);
+    }
+}
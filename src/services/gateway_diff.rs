@@ -0,0 +1,309 @@
+//! Configuration and state comparison between two gateways
+//!
+//! When a call works through one site and fails through another, the cause
+//! is usually a difference in configuration, software version, route or
+//! trunk state. A [`GatewaySnapshot`] captures all of these from one
+//! gateway's management API; [`compare`] lines two snapshots up and lists
+//! every value that differs, grouped by section. List entries carrying an
+//! `id` or `name` are matched by it rather than by position, so a route
+//! inserted at the top of one table shows up as one extra route instead of
+//! every route below it changing. Secrets are redacted by the serving
+//! gateway, so snapshots can be exported and attached to a ticket.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::core::control::ControlStatus;
+use crate::services::management_api::SpanReport;
+use crate::services::trunk_registration::TrunkRegistrationStatus;
+
+/// Stands in for secrets in a served configuration
+pub const REDACTED: &str = "<redacted>";
+
+/// Keys of list entries that identify them across gateways
+const IDENTITY_KEYS: [&str; 3] = ["id", "name", "trunk"];
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.contains("password") || key.contains("secret") || key == "ha1" || key == "community"
+}
+
+/// Replace every non-empty secret in a serialized configuration
+pub fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret(key) && !matches!(value, Value::Null) && value.as_str() != Some("") {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// Configuration and key state of one gateway at one moment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewaySnapshot {
+    /// Management API the snapshot was taken from
+    pub endpoint: String,
+    pub taken_at: DateTime<Utc>,
+    pub status: ControlStatus,
+    pub spans: Vec<SpanReport>,
+    /// `None` where the gateway does not report registrations
+    pub trunk_registrations: Option<Vec<TrunkRegistrationStatus>>,
+    /// Running configuration, secrets redacted; `None` where it is not served
+    pub config: Option<Value>,
+}
+
+impl GatewaySnapshot {
+    /// Comparable values of the snapshot, by section
+    fn sections(&self) -> Vec<(DiffSection, Value)> {
+        let mut config = self.config.clone().unwrap_or(Value::Null);
+        let b2bua_routes = take(&mut config, &["b2bua", "routing_table"]);
+        let tandem_routes = take(&mut config, &["tandem", "routes"]);
+        let spans: Map<String, Value> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let disabled = span.channels.iter().filter(|channel| !channel.enabled).count();
+                (span.span_id.to_string(), json!({
+                    "name": span.name,
+                    "trunk_type": span.trunk_type,
+                    "up": span.up,
+                    "alarms": span.alarms,
+                    "channels": span.channels.len(),
+                    "disabled_channels": disabled,
+                }))
+            })
+            .collect();
+        let trunks = self.trunk_registrations.as_ref().map_or(Value::Null, |registrations| {
+            registrations
+                .iter()
+                .map(|registration| {
+                    (registration.trunk.clone(), json!({
+                        "registrar": registration.registrar,
+                        "state": registration.state,
+                        "last_status": registration.last_status,
+                    }))
+                })
+                .collect::<Map<String, Value>>()
+                .into()
+        });
+        vec![
+            (DiffSection::Software, json!({ "version": self.status.version })),
+            (DiffSection::Spans, Value::Object(spans)),
+            (DiffSection::Trunks, trunks),
+            (DiffSection::Routing, json!({ "b2bua": b2bua_routes, "tandem": tandem_routes })),
+            (DiffSection::Config, config),
+        ]
+    }
+}
+
+/// Remove and return the value at `path`, `Null` where there is none
+fn take(value: &mut Value, path: &[&str]) -> Value {
+    let Some((last, parents)) = path.split_last() else {
+        return Value::Null;
+    };
+    parents
+        .iter()
+        .try_fold(value, |value, key| value.get_mut(*key))
+        .and_then(|parent| parent.as_object_mut())
+        .and_then(|parent| parent.remove(*last))
+        .unwrap_or(Value::Null)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffSection {
+    Software,
+    Spans,
+    Trunks,
+    Routing,
+    Config,
+}
+
+impl DiffSection {
+    pub const ALL: [DiffSection; 5] = [
+        DiffSection::Software,
+        DiffSection::Spans,
+        DiffSection::Trunks,
+        DiffSection::Routing,
+        DiffSection::Config,
+    ];
+}
+
+impl fmt::Display for DiffSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DiffSection::Software => "software",
+            DiffSection::Spans => "spans",
+            DiffSection::Trunks => "trunks",
+            DiffSection::Routing => "routing",
+            DiffSection::Config => "config",
+        })
+    }
+}
+
+/// One value that differs; `None` on the side lacking it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Difference {
+    pub section: DiffSection,
+    /// Dotted path within the section, list entries as `[id]` or `[index]`
+    pub path: String,
+    pub left: Option<Value>,
+    pub right: Option<Value>,
+}
+
+/// Both snapshots and everything differing between them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffReport {
+    pub left: GatewaySnapshot,
+    pub right: GatewaySnapshot,
+    pub differences: Vec<Difference>,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    pub fn section(&self, section: DiffSection) -> impl Iterator<Item = &Difference> {
+        self.differences.iter().filter(move |difference| difference.section == section)
+    }
+}
+
+/// Compare two snapshots, section by section
+pub fn compare(left: GatewaySnapshot, right: GatewaySnapshot) -> DiffReport {
+    let mut differences = Vec::new();
+    for ((section, left_value), (_, right_value)) in left.sections().into_iter().zip(right.sections()) {
+        diff_values(section, String::new(), Some(&left_value), Some(&right_value), &mut differences);
+    }
+    DiffReport { left, right, differences }
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Identity key every entry of both lists carries, unique within each list
+fn identity_key(left: &[Value], right: &[Value]) -> Option<&'static str> {
+    let unique = |values: &[Value], key: &str| {
+        let ids: Option<BTreeSet<&str>> = values.iter().map(|value| value.get(key)?.as_str()).collect();
+        ids.is_some_and(|ids| ids.len() == values.len())
+    };
+    IDENTITY_KEYS.into_iter().find(|key| unique(left, key) && unique(right, key))
+}
+
+fn diff_values(section: DiffSection, path: String, left: Option<&Value>, right: Option<&Value>, out: &mut Vec<Difference>) {
+    match (left, right) {
+        (Some(Value::Object(left)), Some(Value::Object(right))) => {
+            let keys: BTreeSet<&String> = left.keys().chain(right.keys()).collect();
+            for key in keys {
+                diff_values(section, child_path(&path, key), left.get(key), right.get(key), out);
+            }
+        }
+        (Some(Value::Array(left)), Some(Value::Array(right))) => {
+            match identity_key(left, right) {
+                Some(key) => {
+                    let id = |value: &Value| value[key].as_str().unwrap_or_default().to_string();
+                    let ids: BTreeSet<String> = left.iter().chain(right).map(id).collect();
+                    for entry in ids {
+                        let find = |values: &[Value]| values.iter().find(|value| id(value) == entry);
+                        diff_values(section, format!("{}[{}]", path, entry), find(left), find(right), out);
+                    }
+                }
+                None => {
+                    for i in 0..left.len().max(right.len()) {
+                        diff_values(section, format!("{}[{}]", path, i), left.get(i), right.get(i), out);
+                    }
+                }
+            }
+        }
+        (left, right) if left != right => out.push(Difference {
+            section,
+            path,
+            left: left.cloned(),
+            right: right.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GatewayConfig;
+
+    fn snapshot(endpoint: &str, version: &str, config: &GatewayConfig) -> GatewaySnapshot {
+        let mut config = serde_json::to_value(config).unwrap();
+        redact_secrets(&mut config);
+        GatewaySnapshot {
+            endpoint: endpoint.to_string(),
+            taken_at: Utc::now(),
+            status: ControlStatus {
+                node_id: endpoint.to_string(),
+                version: version.to_string(),
+                running: true,
+                uptime_secs: 0,
+                active_calls: 0,
+                active_channels: 0,
+                sip_sessions: 0,
+                rtp_sessions: 0,
+                spans: Vec::new(),
+            },
+            spans: Vec::new(),
+            trunk_registrations: None,
+            config: Some(config),
+        }
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut value = json!({
+            "snmp": { "community": "private", "auth_password": null },
+            "users": [{ "username": "1001", "password": "hunter2", "ha1": "" }],
+        });
+        redact_secrets(&mut value);
+        assert_eq!(value["snmp"]["community"], REDACTED);
+        assert_eq!(value["snmp"]["auth_password"], Value::Null);
+        assert_eq!(value["users"][0]["password"], REDACTED);
+        assert_eq!(value["users"][0]["username"], "1001");
+        assert_eq!(value["users"][0]["ha1"], "");
+    }
+
+    #[test]
+    fn test_compare_matches_routes_by_id() {
+        let site_a = GatewayConfig::default_config();
+        let mut site_b = site_a.clone();
+        let mut extra = site_b.b2bua.routing_table[0].clone();
+        extra.id = "aaa-first".to_string();
+        site_b.b2bua.routing_table.insert(0, extra);
+        site_b.general.max_calls += 1;
+
+        let same = compare(snapshot("a", "1.0.0", &site_a), snapshot("b", "1.0.0", &site_a));
+        assert!(same.is_empty());
+
+        let report = compare(snapshot("a", "1.0.0", &site_a), snapshot("b", "1.1.0", &site_b));
+        let software: Vec<&Difference> = report.section(DiffSection::Software).collect();
+        assert_eq!(software[0].path, "version");
+        let routing: Vec<&Difference> = report.section(DiffSection::Routing).collect();
+        assert_eq!(routing.len(), 1);
+        assert_eq!(routing[0].path, "b2bua[aaa-first]");
+        assert!(routing[0].left.is_none());
+        let config: Vec<&Difference> = report.section(DiffSection::Config).collect();
+        assert_eq!(config.len(), 1);
+        assert_eq!(config[0].path, "general.max_calls");
+    }
+}
@@ -0,0 +1,369 @@
+//! Fleet heartbeat to a central controller
+//!
+//! A managed-service operator running many units wants them on one screen
+//! without polling each. With the heartbeat enabled the gateway POSTs a
+//! summary of itself to the fleet controller every interval: version,
+//! uptime, health, span and trunk state, alarm counts by severity and
+//! licence status. The body is signed with HMAC-SHA256 under the configured
+//! shared secret, hex-encoded in [`SIGNATURE_HEADER`], so the controller can
+//! tell a unit's report from a forged one; the send time and a sequence
+//! number let it spot replays. A summary the controller does not take is
+//! retried with exponential backoff and then dropped, as the next interval
+//! brings a fresh one.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use crate::config::HeartbeatConfig;
+use crate::core::control::ControlStatus;
+use crate::services::management_api::{AlarmReport, ManagementSource, ServiceReport, SpanReport};
+use crate::services::status_page::{Health, PublicStatus, TrunkCount};
+use crate::{Error, Result};
+
+/// Header carrying `sha256=<hex HMAC of the body>`
+pub const SIGNATURE_HEADER: &str = "x-redfire-signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LicenseState {
+    Valid,
+    Expired,
+    /// More calls up than the unit is licensed for
+    OverCapacity,
+    /// No licence provisioned
+    Unlicensed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LicenseReport {
+    pub id: Option<String>,
+    pub expires: Option<NaiveDate>,
+    pub licensed_calls: u32,
+    pub state: LicenseState,
+}
+
+/// One span as the controller sees it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SpanHealth {
+    pub span_id: u32,
+    pub name: String,
+    /// e1 or t1
+    pub trunk_type: String,
+    pub up: bool,
+    pub alarms: Vec<String>,
+}
+
+/// Body of each heartbeat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HeartbeatReport {
+    pub node_id: String,
+    pub version: String,
+    /// Counts up from 1 each time the gateway starts
+    pub sequence: u64,
+    pub sent_at: DateTime<Utc>,
+    pub health: Health,
+    pub uptime_secs: u64,
+    pub active_calls: u32,
+    pub spans: Vec<SpanHealth>,
+    pub trunks: Vec<TrunkCount>,
+    pub services: Vec<ServiceReport>,
+    /// Active alarms by severity
+    pub alarms: BTreeMap<String, usize>,
+    pub license: LicenseReport,
+}
+
+impl HeartbeatReport {
+    pub fn build(
+        status: &ControlStatus,
+        spans: &[SpanReport],
+        services: Vec<ServiceReport>,
+        alarms: &[AlarmReport],
+        license: LicenseReport,
+    ) -> Self {
+        let summary = PublicStatus::summarize(status, spans, services, alarms);
+        let mut by_severity = BTreeMap::new();
+        for alarm in alarms {
+            *by_severity.entry(alarm.severity.clone()).or_insert(0) += 1;
+        }
+        Self {
+            node_id: status.node_id.clone(),
+            version: status.version.clone(),
+            sequence: 0,
+            sent_at: Utc::now(),
+            health: summary.health,
+            uptime_secs: status.uptime_secs,
+            active_calls: status.active_calls,
+            spans: spans
+                .iter()
+                .map(|span| SpanHealth {
+                    span_id: span.span_id,
+                    name: span.name.clone(),
+                    trunk_type: span.trunk_type.clone(),
+                    up: span.up,
+                    alarms: span.alarms.clone(),
+                })
+                .collect(),
+            trunks: summary.trunks,
+            services: summary.services,
+            alarms: by_severity,
+            license,
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 of `body` under `secret`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// How heartbeats have been faring
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HeartbeatStatus {
+    pub sent: u64,
+    /// Summaries dropped after their last retry
+    pub failed: u64,
+    pub consecutive_failures: u32,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// Sends the heartbeat every interval
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    /// Calls the unit is licensed for
+    licensed_calls: u32,
+    source: Arc<dyn ManagementSource>,
+    client: reqwest::Client,
+    sequence: AtomicU64,
+    status: Mutex<HeartbeatStatus>,
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig, licensed_calls: u32, source: Arc<dyn ManagementSource>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .map_err(|e| Error::invalid_config(format!("Failed to create heartbeat client: {}", e)))?;
+        Ok(Self {
+            config,
+            licensed_calls,
+            source,
+            client,
+            sequence: AtomicU64::new(0),
+            status: Mutex::new(HeartbeatStatus::default()),
+        })
+    }
+
+    pub fn license(&self, active_calls: u32, today: NaiveDate) -> LicenseReport {
+        let state = if self.config.license_id.is_none() {
+            LicenseState::Unlicensed
+        } else if self.config.license_expires.is_some_and(|expires| expires < today) {
+            LicenseState::Expired
+        } else if active_calls > self.licensed_calls {
+            LicenseState::OverCapacity
+        } else {
+            LicenseState::Valid
+        };
+        LicenseReport {
+            id: self.config.license_id.clone(),
+            expires: self.config.license_expires,
+            licensed_calls: self.licensed_calls,
+            state,
+        }
+    }
+
+    /// Summary of the gateway as it stands
+    pub async fn report(&self) -> HeartbeatReport {
+        let status = self.source.status().await;
+        let spans = self.source.spans().await;
+        let services = self.source.services().await;
+        let alarms = self.source.alarms().await;
+        let license = self.license(status.active_calls, Utc::now().date_naive());
+        let mut report = HeartbeatReport::build(&status, &spans, services, &alarms, license);
+        report.sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        report
+    }
+
+    /// Wait before retry `attempt`, counting from 1
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let doubled = self.config.retry_initial_ms.saturating_mul(1 << attempt.saturating_sub(1).min(20));
+        Duration::from_millis(doubled.min(self.config.retry_max_ms))
+    }
+
+    /// Send one summary, retrying until the controller takes it or the
+    /// retries run out
+    pub async fn send(&self) -> Result<()> {
+        let report = self.report().await;
+        let body = serde_json::to_vec(&report)?;
+        let signature = sign(&self.config.secret, &body);
+
+        let mut attempt = 0;
+        let result = loop {
+            match self.post(&body, &signature).await {
+                Ok(()) => break Ok(()),
+                Err(e) if attempt < self.config.max_retries => {
+                    attempt += 1;
+                    let delay = self.retry_delay(attempt);
+                    debug!("Heartbeat {} not taken ({}), retrying in {:?}", report.sequence, e, delay);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        let mut status = self.status.lock().unwrap();
+        match result {
+            Ok(()) => {
+                if status.consecutive_failures > 0 {
+                    let failures = status.consecutive_failures;
+                    info!("Fleet controller took heartbeat {} after {} failures", report.sequence, failures);
+                }
+                status.sent += 1;
+                status.consecutive_failures = 0;
+                status.last_success = Some(Utc::now());
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Heartbeat {} dropped after {} attempts: {}", report.sequence, attempt + 1, e);
+                status.failed += 1;
+                status.consecutive_failures += 1;
+                status.last_error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    async fn post(&self, body: &[u8], signature: &str) -> Result<()> {
+        let response = self
+            .client
+            .post(&self.config.controller_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| Error::network(format!("Heartbeat request failed: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(Error::network(format!("Fleet controller returned {}", response.status())));
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> HeartbeatStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Send a summary every interval until the task is aborted
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!("Reporting to fleet controller {} every {}s", self.config.controller_url, self.config.interval_secs);
+            let mut ticks = interval(Duration::from_secs(self.config.interval_secs));
+            // A slow round of retries pushes the next summary back rather than bunching them
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let _ = self.send().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::demo::DemoGateway;
+    use axum::body::Bytes;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use tokio::net::TcpListener;
+
+    fn config(controller_url: String) -> HeartbeatConfig {
+        HeartbeatConfig {
+            enabled: true,
+            controller_url,
+            secret: "fleet-shared-secret".to_string(),
+            max_retries: 2,
+            retry_initial_ms: 10,
+            retry_max_ms: 15,
+            license_id: Some("LIC-0042".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_signature_license_and_backoff() {
+        // RFC 4231 test case 2
+        let signature = sign("Jefe", b"what do ya want for nothing?");
+        assert_eq!(signature, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+
+        let mut config = config("http://127.0.0.1:1/".to_string());
+        config.retry_initial_ms = 2000;
+        config.retry_max_ms = 30_000;
+        config.license_expires = NaiveDate::from_ymd_opt(2026, 12, 31);
+        let heartbeat = Heartbeat::new(config, 30, Arc::new(DemoGateway::new("gw1"))).unwrap();
+        let delays: Vec<u64> = (1..=6).map(|attempt| heartbeat.retry_delay(attempt).as_millis() as u64).collect();
+        assert_eq!(delays, vec![2000, 4000, 8000, 16_000, 30_000, 30_000]);
+
+        let today = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+        assert_eq!(heartbeat.license(12, today).state, LicenseState::Valid);
+        assert_eq!(heartbeat.license(31, today).state, LicenseState::OverCapacity);
+        let later = NaiveDate::from_ymd_opt(2027, 1, 1).unwrap();
+        assert_eq!(heartbeat.license(0, later).state, LicenseState::Expired);
+    }
+
+    #[tokio::test]
+    async fn test_signed_report_retried_until_taken() {
+        let received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>> = Arc::default();
+        let store = Arc::clone(&received);
+        let router = Router::new().route(
+            "/heartbeat",
+            post(move |headers: HeaderMap, body: Bytes| {
+                let store = Arc::clone(&store);
+                async move {
+                    let mut received = store.lock().unwrap();
+                    received.push((headers, body));
+                    // The first attempt is turned away
+                    if received.len() == 1 {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::NO_CONTENT
+                    }
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/heartbeat", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let heartbeat = Heartbeat::new(config(url), 30, Arc::new(DemoGateway::new("gw-lab-7"))).unwrap();
+        heartbeat.send().await.unwrap();
+        let status = heartbeat.status();
+        assert_eq!((status.sent, status.failed, status.consecutive_failures), (1, 0, 0));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        let expected = format!("sha256={}", sign("fleet-shared-secret", body));
+        assert_eq!(headers[SIGNATURE_HEADER], expected.as_str());
+        let report: HeartbeatReport = serde_json::from_slice(body).unwrap();
+        assert_eq!((report.node_id.as_str(), report.sequence), ("gw-lab-7", 1));
+        assert_eq!(report.license.id.as_deref(), Some("LIC-0042"));
+        assert!(!report.spans.is_empty());
+    }
+}
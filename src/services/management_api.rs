@@ -0,0 +1,1107 @@
+//! HTTP management API
+//!
+//! The running gateway answers JSON queries for its status, spans, active
+//! calls, RTP sessions, services, alarms, CDRs, timing, test sessions, capacity and running
+//! configuration (secrets redacted), which is what
+//! redfire-diag and integrators read. Everything it serves comes from a
+//! [`ManagementSource`], implemented by the gateway and locked per request,
+//! either directly or through the optional services the source hands out:
+//! B2BUA operations, the support tunnel, campaigns, audio taps, port
+//! descriptions, channel history and profiling. A path whose service is not
+//! running answers 503 with a JSON error rather than 404, so every path in
+//! the OpenAPI document is served. The CSV provisioning of routes and trunks
+//! rewrites the configuration file and is only served when enabled. With a
+//! secret configured every request must carry it as a bearer token, except
+//! the audio taps, which take their operators' own tokens. Without one the
+//! API may only listen on loopback.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, RawQuery, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::handler::Handler;
+use axum::middleware::{self, Next};
+use axum::routing::{on, MethodFilter};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::config::{GatewayConfig, ManagementApiConfig, PortDescription};
+use crate::core::control::ControlStatus;
+use crate::services::audio_tap::{
+    audio_tap_path, AudioTap, TapAuditRecord, TapRequest, TapSession, AUDIO_TAPS_PATH, AUDIO_TAP_AUDIT_PATH,
+};
+use crate::services::b2bua::B2buaService;
+use crate::services::call_gapping::{ActiveGap, CALL_GAPS_PATH};
+use crate::services::call_trace::{call_trace_path, CallTrace, TraceSelector, CALL_TRACE_PATH};
+use crate::services::campaigns::{
+    campaign_path, Campaign, CampaignRequest, CampaignScheduler, CampaignSummary, CAMPAIGNS_PATH,
+};
+use crate::services::canary::{CanaryMetrics, CanaryMonitor, CANARY_PATH};
+use crate::services::capacity::{CapacityQuery, CapacityReport, ReportFormat, CAPACITY_PATH};
+use crate::services::channel_history::{ChannelCall, ChannelHistory, CHANNEL_HISTORY_PATH};
+use crate::services::codec_negotiation::{
+    CodecNegotiationCounter, TranscodingHeadroom, CODEC_NEGOTIATION_PATH, TRANSCODING_HEADROOM_PATH,
+};
+use crate::services::api_schema::{openapi_document, API_SCHEMA_PATH};
+use crate::services::early_media::{EarlyMediaCounter, EARLY_MEDIA_PATH};
+use crate::services::gateway_diff::redact_secrets;
+use crate::services::media_security::{MediaSecurityCounter, MEDIA_SECURITY_PATH};
+use crate::services::metrics::METRICS_PATH;
+use crate::services::paging::{PageRecord, PAGING_PATH};
+use crate::services::ports::{PortDirectory, PortEntry, PORTS_PATH};
+use crate::services::profiling::{ProfilingService, CPU_PROFILE_PATH, HEAP_STATS_PATH};
+use crate::services::prompts::{prompt_path, PromptInfo, PromptLibrary, PromptPackSummary, PROMPTS_PATH};
+use crate::services::release_causes::{ReleaseCauseQuery, ReleaseCauseReport, RELEASE_CAUSES_PATH};
+use crate::services::reroute::{RerouteCounter, REROUTE_COUNTERS_PATH};
+use crate::services::route_provisioning::{self, ROUTES_PATH, TRUNKS_PATH};
+use crate::services::shadow_routing::{ShadowRoutingSummary, SHADOW_ROUTING_PATH};
+use crate::services::support_tunnel::{
+    SupportTunnel, TunnelAuditRecord, TunnelRequest, TunnelSession, SUPPORT_TUNNEL_AUDIT_PATH, SUPPORT_TUNNEL_PATH,
+};
+use crate::services::takeover::{takeover_path, TakeoverRecord, TakeoverRequest};
+use crate::services::test_numbers::{TestCallRecord, TEST_CALLS_PATH};
+use crate::services::transcoding_latency::{TranscodingLatencyReport, TRANSCODING_LATENCY_PATH};
+use crate::services::trunk_registration::{TrunkRegistrationStatus, TRUNK_REGISTRATIONS_PATH};
+use crate::services::upgrade::{UpgradeRequest, UPGRADE_PATH};
+use crate::utils::{constant_time_eq, netbind};
+use crate::{Error, ErrorCategory, Result};
+
+/// Management API path reporting uptime, call counts and span summaries
+pub const STATUS_PATH: &str = "/api/v1/status";
+/// Management API path listing TDM spans and their channels
+pub const SPANS_PATH: &str = "/api/v1/spans";
+/// Management API path listing calls in progress
+pub const ACTIVE_CALLS_PATH: &str = "/api/v1/calls";
+/// Management API path listing RTP sessions with their RTCP quality
+pub const RTP_SESSIONS_PATH: &str = "/api/v1/rtp";
+/// Management API path listing the gateway's services and their state
+pub const SERVICES_PATH: &str = "/api/v1/services";
+/// Management API path listing active alarms
+pub const ALARMS_PATH: &str = "/api/v1/alarms";
+/// Management API path querying stored CDRs
+pub const CDRS_PATH: &str = "/api/v1/cdrs";
+/// Management API path reporting clock sources and the selected one
+pub const TIMING_PATH: &str = "/api/v1/timing";
+/// Management API path listing loopback and BERT test sessions
+pub const TEST_SESSIONS_PATH: &str = "/api/v1/tests";
+/// Management API path serving the running configuration, secrets redacted
+pub const CONFIG_PATH: &str = "/api/v1/config";
+
+/// One B-channel of a span
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ChannelReport {
+    pub channel_id: u8,
+    /// idle, in_use, blocked or out_of_service
+    pub state: String,
+    pub enabled: bool,
+}
+
+/// A TDM span with its channels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SpanReport {
+    pub span_id: u32,
+    pub name: String,
+    /// e1 or t1
+    pub trunk_type: String,
+    pub up: bool,
+    pub alarms: Vec<String>,
+    pub channels: Vec<ChannelReport>,
+}
+
+/// A call in progress
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ActiveCallReport {
+    pub id: String,
+    pub route: String,
+    pub calling_number: Option<String>,
+    pub called_number: String,
+    /// `span/channel` the call came in on
+    pub ingress: String,
+    /// `span/channel` the call went out on
+    pub egress: String,
+    /// proceeding or connected
+    pub state: String,
+    pub duration_secs: u64,
+}
+
+/// An RTP session: what it received, and what the far end reports in RTCP
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RtpSessionReport {
+    pub id: String,
+    pub local_port: u16,
+    pub remote_addr: Option<String>,
+    pub payload_type: u8,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub loss_pct: f64,
+    pub jitter_ms: f64,
+    /// Loss of our stream reported by the far end
+    pub remote_loss_pct: Option<f64>,
+    pub remote_jitter_ms: Option<f64>,
+    pub round_trip_ms: Option<f64>,
+    pub rtcp_reports_sent: u64,
+    pub rtcp_reports_received: u64,
+}
+
+/// One of the gateway's interfaces, protocols or services
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ServiceReport {
+    pub name: String,
+    /// running, stopped or disabled
+    pub state: String,
+}
+
+/// An active alarm
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AlarmReport {
+    pub id: String,
+    /// critical, major, minor, warning, indeterminate or cleared
+    pub severity: String,
+    pub alarm_type: String,
+    /// `component/instance` raising the alarm
+    pub source: String,
+    pub description: String,
+    pub raised_time: DateTime<Utc>,
+    pub acknowledged: bool,
+    pub event_count: u32,
+}
+
+/// Filter of a CDR query
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CdrQuery {
+    /// Calls started this many hours back from now; 24 when omitted
+    pub hours: Option<u32>,
+    pub caller: Option<String>,
+    pub callee: Option<String>,
+    /// The call carrying this correlation ID
+    pub correlation_id: Option<String>,
+    /// Most recent first, up to the configured maximum
+    pub limit: Option<usize>,
+}
+
+/// A stored CDR, abridged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CdrReport {
+    pub id: String,
+    pub call_id: String,
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    pub caller: String,
+    pub callee: String,
+    pub start_time: DateTime<Utc>,
+    pub answer_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub duration_secs: u64,
+    pub disconnect_reason: Option<String>,
+}
+
+/// One clock source
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ClockSourceReport {
+    pub source_id: String,
+    /// internal, gps, ntp, ptp, tdmoe or external
+    pub source_type: String,
+    pub stratum: String,
+    pub active: bool,
+    pub holdover: bool,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub phase_offset_ns: i64,
+    pub frequency_offset_ppb: i64,
+}
+
+/// Clock selection and sources
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TimingReport {
+    pub running: bool,
+    pub stratum: String,
+    pub selected_source: Option<String>,
+    pub sources: Vec<ClockSourceReport>,
+}
+
+/// A loopback or BERT test
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TestSessionReport {
+    /// loopback or bert
+    pub kind: String,
+    pub channel: u16,
+    /// idle, running, completed, failed or stopped
+    pub status: String,
+    pub elapsed_secs: u64,
+    /// Packets lost for a loopback, errored bits for a BERT
+    pub errors: u64,
+    /// Packet loss or bit error ratio
+    pub error_rate: f64,
+}
+
+/// Where the management API reads gateway state
+#[async_trait]
+pub trait ManagementSource: Send + Sync {
+    async fn status(&self) -> ControlStatus;
+    async fn spans(&self) -> Vec<SpanReport>;
+    async fn active_calls(&self) -> Vec<ActiveCallReport>;
+    async fn rtp_sessions(&self) -> Vec<RtpSessionReport>;
+    async fn services(&self) -> Vec<ServiceReport>;
+    async fn alarms(&self) -> Vec<AlarmReport>;
+    /// Stored CDRs matching `query`, most recent first, at most `limit`
+    async fn cdrs(&self, query: &CdrQuery, limit: usize) -> Result<Vec<CdrReport>>;
+    async fn timing(&self) -> Option<TimingReport>;
+    async fn test_sessions(&self) -> Vec<TestSessionReport>;
+    /// Capacity planning report, unless capacity sampling is disabled
+    async fn capacity(&self, query: &CapacityQuery) -> Option<CapacityReport>;
+    /// Prometheus exposition of the gateway's metrics
+    async fn metrics(&self) -> Result<String>;
+    /// Configuration the gateway is running, if it has one
+    async fn config(&self) -> Option<GatewayConfig>;
+    /// B2BUA serving SIP calls, if one runs in this process
+    async fn b2bua(&self) -> Option<Arc<B2buaService>> {
+        None
+    }
+    async fn support_tunnel(&self) -> Option<Arc<SupportTunnel>> {
+        None
+    }
+    async fn canary(&self) -> Option<Arc<CanaryMonitor>> {
+        None
+    }
+    async fn campaigns(&self) -> Option<Arc<CampaignScheduler>> {
+        None
+    }
+    async fn audio_tap(&self) -> Option<Arc<AudioTap>> {
+        None
+    }
+    async fn port_directory(&self) -> Option<Arc<PortDirectory>> {
+        None
+    }
+    async fn channel_history(&self) -> Option<Arc<ChannelHistory>> {
+        None
+    }
+    async fn profiling(&self) -> Option<Arc<ProfilingService>> {
+        None
+    }
+}
+
+#[derive(Clone)]
+struct ApiState {
+    source: Arc<dyn ManagementSource>,
+    max_cdrs: usize,
+    /// Whether every request has presented the API secret
+    authenticated: bool,
+}
+
+/// Failure of one request, answered as `{"error": ...}`
+struct ApiError(StatusCode, String);
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        // Refused audio tap tokens
+        let denied = matches!(&e, Error::Io(io) if io.kind() == std::io::ErrorKind::PermissionDenied);
+        let status = match e.category() {
+            ErrorCategory::Config if denied => StatusCode::FORBIDDEN,
+            ErrorCategory::Protocol | ErrorCategory::Config => StatusCode::BAD_REQUEST,
+            ErrorCategory::Routing => StatusCode::NOT_FOUND,
+            ErrorCategory::State => StatusCode::CONFLICT,
+            ErrorCategory::ResourceExhausted => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCategory::Unsupported => StatusCode::NOT_IMPLEMENTED,
+            ErrorCategory::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCategory::Transport | ErrorCategory::Hardware | ErrorCategory::Internal => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        Self(status, e.to_string())
+    }
+}
+
+impl ApiError {
+    fn not_found(message: impl Into<String>) -> Self {
+        Self(StatusCode::NOT_FOUND, message.into())
+    }
+
+    /// The service behind a path is not running in this gateway
+    fn not_enabled(service: &str) -> Self {
+        Self(StatusCode::SERVICE_UNAVAILABLE, format!("{} is not enabled", service))
+    }
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+async fn status(State(state): State<ApiState>) -> Json<ControlStatus> {
+    Json(state.source.status().await)
+}
+
+async fn spans(State(state): State<ApiState>) -> Json<Vec<SpanReport>> {
+    Json(state.source.spans().await)
+}
+
+async fn active_calls(State(state): State<ApiState>) -> Json<Vec<ActiveCallReport>> {
+    Json(state.source.active_calls().await)
+}
+
+async fn rtp_sessions(State(state): State<ApiState>) -> Json<Vec<RtpSessionReport>> {
+    Json(state.source.rtp_sessions().await)
+}
+
+async fn services(State(state): State<ApiState>) -> Json<Vec<ServiceReport>> {
+    Json(state.source.services().await)
+}
+
+async fn alarms(State(state): State<ApiState>) -> Json<Vec<AlarmReport>> {
+    Json(state.source.alarms().await)
+}
+
+async fn cdrs(State(state): State<ApiState>, Query(query): Query<CdrQuery>) -> std::result::Result<Json<Vec<CdrReport>>, ApiError> {
+    let limit = query.limit.unwrap_or(state.max_cdrs).min(state.max_cdrs);
+    Ok(Json(state.source.cdrs(&query, limit).await?))
+}
+
+async fn timing(State(state): State<ApiState>) -> std::result::Result<Json<TimingReport>, ApiError> {
+    state
+        .source
+        .timing()
+        .await
+        .map(Json)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "Timing service is not running".to_string()))
+}
+
+async fn test_sessions(State(state): State<ApiState>) -> Json<Vec<TestSessionReport>> {
+    Json(state.source.test_sessions().await)
+}
+
+async fn capacity(State(state): State<ApiState>, Query(query): Query<CapacityQuery>) -> std::result::Result<Response, ApiError> {
+    let report = state
+        .source
+        .capacity(&query)
+        .await
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "Capacity sampling is disabled".to_string()))?;
+    Ok(match query.format.unwrap_or_default() {
+        ReportFormat::Json => Json(report).into_response(),
+        ReportFormat::Csv => ([(header::CONTENT_TYPE, "text/csv")], report.to_csv()).into_response(),
+    })
+}
+
+async fn metrics(State(state): State<ApiState>) -> std::result::Result<Response, ApiError> {
+    let body = state.source.metrics().await?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response())
+}
+
+async fn config(State(state): State<ApiState>) -> std::result::Result<Json<serde_json::Value>, ApiError> {
+    let config = state
+        .source
+        .config()
+        .await
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "No configuration is loaded".to_string()))?;
+    let mut config = serde_json::to_value(config).map_err(|e| Error::internal(e.to_string()))?;
+    redact_secrets(&mut config);
+    Ok(Json(config))
+}
+
+async fn schema() -> Json<serde_json::Value> {
+    Json(openapi_document())
+}
+
+async fn b2bua(state: &ApiState) -> ApiResult<Arc<B2buaService>> {
+    state.source.b2bua().await.ok_or_else(|| ApiError::not_enabled("The B2BUA"))
+}
+
+async fn take_over_call(
+    State(state): State<ApiState>,
+    Path(call_id): Path<String>,
+    Json(request): Json<TakeoverRequest>,
+) -> ApiResult<Json<TakeoverRecord>> {
+    if request.call_id != call_id {
+        let message = format!("Takeover of {} posted for call {}", request.call_id, call_id);
+        return Err(ApiError(StatusCode::BAD_REQUEST, message));
+    }
+    Ok(Json(b2bua(&state).await?.take_over_call(request).await?))
+}
+
+async fn call_gaps(State(state): State<ApiState>) -> ApiResult<Json<Vec<ActiveGap>>> {
+    Ok(Json(b2bua(&state).await?.active_call_gaps()))
+}
+
+async fn apply_call_gap(State(state): State<ApiState>, Path(prefix): Path<String>) -> ApiResult<StatusCode> {
+    b2bua(&state).await?.apply_call_gap(&prefix)?;
+    Ok(StatusCode::OK)
+}
+
+async fn remove_call_gap(State(state): State<ApiState>, Path(prefix): Path<String>) -> ApiResult<StatusCode> {
+    if b2bua(&state).await?.remove_call_gap(&prefix) {
+        Ok(StatusCode::OK)
+    } else {
+        Err(ApiError::not_found(format!("No call gap on {}", prefix)))
+    }
+}
+
+async fn trace_selectors(State(state): State<ApiState>) -> ApiResult<Json<Vec<TraceSelector>>> {
+    Ok(Json(b2bua(&state).await?.call_tracer().selectors()))
+}
+
+async fn add_trace_selector(
+    State(state): State<ApiState>,
+    Json(selector): Json<TraceSelector>,
+) -> ApiResult<StatusCode> {
+    b2bua(&state).await?.add_trace_selector(selector);
+    Ok(StatusCode::OK)
+}
+
+async fn remove_trace_selector(
+    State(state): State<ApiState>,
+    Json(selector): Json<TraceSelector>,
+) -> ApiResult<StatusCode> {
+    if b2bua(&state).await?.call_tracer().remove_selector(&selector) {
+        Ok(StatusCode::OK)
+    } else {
+        Err(ApiError::not_found("No such trace selector"))
+    }
+}
+
+async fn call_trace(State(state): State<ApiState>, Path(call_id): Path<String>) -> ApiResult<Json<CallTrace>> {
+    b2bua(&state)
+        .await?
+        .call_tracer()
+        .trace(&call_id)
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("No trace of call {}", call_id)))
+}
+
+async fn codec_negotiation(State(state): State<ApiState>) -> ApiResult<Json<Vec<CodecNegotiationCounter>>> {
+    Ok(Json(b2bua(&state).await?.codec_negotiation_counters()))
+}
+
+async fn transcoding_headroom(State(state): State<ApiState>) -> ApiResult<Json<TranscodingHeadroom>> {
+    Ok(Json(b2bua(&state).await?.transcoding_headroom()))
+}
+
+async fn transcoding_latency(State(state): State<ApiState>) -> ApiResult<Json<Vec<TranscodingLatencyReport>>> {
+    Ok(Json(b2bua(&state).await?.transcoding_latency()))
+}
+
+async fn reroute_counters(State(state): State<ApiState>) -> ApiResult<Json<Vec<RerouteCounter>>> {
+    Ok(Json(b2bua(&state).await?.reroute_counters()))
+}
+
+async fn early_media(State(state): State<ApiState>) -> ApiResult<Json<Vec<EarlyMediaCounter>>> {
+    Ok(Json(b2bua(&state).await?.early_media_counters()))
+}
+
+async fn media_security(State(state): State<ApiState>) -> ApiResult<Json<Vec<MediaSecurityCounter>>> {
+    Ok(Json(b2bua(&state).await?.media_security_counters()))
+}
+
+async fn release_causes(
+    State(state): State<ApiState>,
+    Query(query): Query<ReleaseCauseQuery>,
+) -> ApiResult<Json<ReleaseCauseReport>> {
+    Ok(Json(b2bua(&state).await?.release_cause_report(&query)))
+}
+
+async fn shadow_routing(State(state): State<ApiState>) -> ApiResult<Json<ShadowRoutingSummary>> {
+    Ok(Json(b2bua(&state).await?.shadow_routing_summary()))
+}
+
+async fn test_calls(State(state): State<ApiState>) -> ApiResult<Json<Vec<TestCallRecord>>> {
+    Ok(Json(b2bua(&state).await?.test_calls()))
+}
+
+async fn pages(State(state): State<ApiState>) -> ApiResult<Json<Vec<PageRecord>>> {
+    Ok(Json(b2bua(&state).await?.pages()))
+}
+
+async fn trunk_registrations(State(state): State<ApiState>) -> ApiResult<Json<Vec<TrunkRegistrationStatus>>> {
+    Ok(Json(b2bua(&state).await?.trunk_registrations()))
+}
+
+async fn upgrade(State(state): State<ApiState>, Json(request): Json<UpgradeRequest>) -> ApiError {
+    if !state.authenticated {
+        return ApiError(StatusCode::FORBIDDEN, "Upgrades need a management API secret".to_string());
+    }
+    match b2bua(&state).await {
+        // Only returns if the new binary could not be executed
+        Ok(service) => service.upgrade(&request).await.into(),
+        Err(e) => e,
+    }
+}
+
+async fn prompt_library(state: &ApiState) -> ApiResult<Arc<PromptLibrary>> {
+    b2bua(state).await?.prompt_library().ok_or_else(|| ApiError::not_enabled("The prompt library"))
+}
+
+async fn prompt_packs(State(state): State<ApiState>) -> ApiResult<Json<Vec<PromptPackSummary>>> {
+    Ok(Json(prompt_library(&state).await?.packs()))
+}
+
+async fn upload_prompt(
+    State(state): State<ApiState>,
+    Path((language, event)): Path<(String, String)>,
+    audio: Bytes,
+) -> ApiResult<Json<PromptInfo>> {
+    Ok(Json(prompt_library(&state).await?.upload(&language, &event, &audio)?))
+}
+
+async fn remove_prompt(
+    State(state): State<ApiState>,
+    Path((language, event)): Path<(String, String)>,
+) -> ApiResult<StatusCode> {
+    prompt_library(&state).await?.remove(&language, &event)?;
+    Ok(StatusCode::OK)
+}
+
+async fn support_tunnel(state: &ApiState) -> ApiResult<Arc<SupportTunnel>> {
+    state.source.support_tunnel().await.ok_or_else(|| ApiError::not_enabled("The support tunnel"))
+}
+
+async fn tunnel_status(State(state): State<ApiState>) -> ApiResult<Json<Option<TunnelSession>>> {
+    Ok(Json(support_tunnel(&state).await?.status()))
+}
+
+async fn open_tunnel(
+    State(state): State<ApiState>,
+    Json(request): Json<TunnelRequest>,
+) -> ApiResult<Json<TunnelSession>> {
+    Ok(Json(support_tunnel(&state).await?.open(request).await?))
+}
+
+#[derive(Deserialize)]
+struct CloseTunnelQuery {
+    operator: String,
+}
+
+async fn close_tunnel(
+    State(state): State<ApiState>,
+    Query(query): Query<CloseTunnelQuery>,
+) -> ApiResult<Json<TunnelSession>> {
+    Ok(Json(support_tunnel(&state).await?.close(&query.operator).await?))
+}
+
+async fn tunnel_audit(State(state): State<ApiState>) -> ApiResult<Json<Vec<TunnelAuditRecord>>> {
+    Ok(Json(support_tunnel(&state).await?.audit()))
+}
+
+async fn port_directory(state: &ApiState) -> ApiResult<Arc<PortDirectory>> {
+    state.source.port_directory().await.ok_or_else(|| ApiError::not_enabled("Port descriptions"))
+}
+
+async fn ports(State(state): State<ApiState>) -> ApiResult<Json<Vec<PortEntry>>> {
+    Ok(Json(port_directory(&state).await?.entries()))
+}
+
+async fn describe_span(
+    State(state): State<ApiState>,
+    Path(span_id): Path<u32>,
+    Json(description): Json<PortDescription>,
+) -> ApiResult<StatusCode> {
+    port_directory(&state).await?.set_span(span_id, description)?;
+    Ok(StatusCode::OK)
+}
+
+async fn describe_channel(
+    State(state): State<ApiState>,
+    Path((span_id, channel_id)): Path<(u32, u8)>,
+    Json(description): Json<PortDescription>,
+) -> ApiResult<StatusCode> {
+    port_directory(&state).await?.set_channel(span_id, channel_id, description)?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct ChannelHistoryQuery {
+    span: u32,
+    channel: Option<u8>,
+}
+
+async fn channel_history(
+    State(state): State<ApiState>,
+    Query(query): Query<ChannelHistoryQuery>,
+) -> ApiResult<Json<Vec<ChannelCall>>> {
+    let history = state.source.channel_history().await.ok_or_else(|| ApiError::not_enabled("Channel history"))?;
+    Ok(Json(history.history(query.span, query.channel)))
+}
+
+/// Both profiling paths, answered by the profiling service itself
+async fn profile(state: &ApiState, path: &str, query: Option<String>) -> ApiResult<Response> {
+    let profiling = state.source.profiling().await.ok_or_else(|| ApiError::not_enabled("Profiling"))?;
+    let response = profiling.handle_request(path, query.as_deref().unwrap_or_default()).await?;
+    Ok(([(header::CONTENT_TYPE, response.content_type)], response.body).into_response())
+}
+
+async fn cpu_profile(State(state): State<ApiState>, RawQuery(query): RawQuery) -> ApiResult<Response> {
+    profile(&state, CPU_PROFILE_PATH, query).await
+}
+
+async fn heap_stats(State(state): State<ApiState>, RawQuery(query): RawQuery) -> ApiResult<Response> {
+    profile(&state, HEAP_STATS_PATH, query).await
+}
+
+async fn canary(State(state): State<ApiState>) -> ApiResult<Json<Vec<CanaryMetrics>>> {
+    let canary = state.source.canary().await.ok_or_else(|| ApiError::not_enabled("Canary calling"))?;
+    Ok(Json(canary.metrics()))
+}
+
+async fn campaign_scheduler(state: &ApiState) -> ApiResult<Arc<CampaignScheduler>> {
+    state.source.campaigns().await.ok_or_else(|| ApiError::not_enabled("Campaign calling"))
+}
+
+async fn campaigns(State(state): State<ApiState>) -> ApiResult<Json<Vec<CampaignSummary>>> {
+    Ok(Json(campaign_scheduler(&state).await?.campaigns()))
+}
+
+async fn start_campaign(
+    State(state): State<ApiState>,
+    Json(request): Json<CampaignRequest>,
+) -> ApiResult<Json<Campaign>> {
+    Ok(Json(campaign_scheduler(&state).await?.submit(request)?))
+}
+
+async fn campaign(State(state): State<ApiState>, Path(id): Path<String>) -> ApiResult<Json<Campaign>> {
+    campaign_scheduler(&state)
+        .await?
+        .campaign(&id)
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("No campaign {}", id)))
+}
+
+async fn cancel_campaign(State(state): State<ApiState>, Path(id): Path<String>) -> ApiResult<Json<CampaignSummary>> {
+    Ok(Json(campaign_scheduler(&state).await?.cancel(&id)?))
+}
+
+/// The audio tap service and the caller's `Authorization: Bearer` token
+async fn audio_tap(state: &ApiState, headers: &HeaderMap) -> ApiResult<(Arc<AudioTap>, String)> {
+    let tap = state.source.audio_tap().await.ok_or_else(|| ApiError::not_enabled("Audio tapping"))?;
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError(StatusCode::UNAUTHORIZED, "Audio taps need a bearer token".to_string()))?;
+    Ok((tap, token.to_string()))
+}
+
+async fn audio_taps(State(state): State<ApiState>, headers: HeaderMap) -> ApiResult<Json<Vec<TapSession>>> {
+    let (tap, token) = audio_tap(&state, &headers).await?;
+    Ok(Json(tap.taps(&token)?))
+}
+
+async fn start_audio_tap(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Json(request): Json<TapRequest>,
+) -> ApiResult<Json<TapSession>> {
+    let (tap, token) = audio_tap(&state, &headers).await?;
+    Ok(Json(tap.start(&token, request)?))
+}
+
+async fn stop_audio_tap(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> ApiResult<Json<TapSession>> {
+    let (tap, token) = audio_tap(&state, &headers).await?;
+    Ok(Json(tap.stop(&token, &id)?))
+}
+
+async fn audio_tap_audit(State(state): State<ApiState>, headers: HeaderMap) -> ApiResult<Json<Vec<TapAuditRecord>>> {
+    let (tap, token) = audio_tap(&state, &headers).await?;
+    Ok(Json(tap.audit(&token)?))
+}
+
+/// Pass requests carrying `secret` as their bearer token
+async fn require_secret(State(secret): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if token.is_some_and(|token| constant_time_eq(token.as_bytes(), secret.as_bytes())) {
+        return next.run(request).await;
+    }
+    let refused = ApiError(StatusCode::UNAUTHORIZED, "Management API secret required".to_string());
+    ([(header::WWW_AUTHENTICATE, "Bearer")], refused).into_response()
+}
+
+/// Router that records the method and OpenAPI path of everything it mounts
+struct ApiRouter {
+    router: Router<ApiState>,
+    operations: Vec<(&'static str, String)>,
+}
+
+impl ApiRouter {
+    /// Mount `handler` at `path`, written with OpenAPI `{param}` segments
+    fn mount<H, T>(mut self, method: &'static str, path: impl Into<String>, handler: H) -> Self
+    where
+        H: Handler<T, ApiState>,
+        T: 'static,
+    {
+        let path = path.into();
+        let filter = match method {
+            "get" => MethodFilter::GET,
+            "post" => MethodFilter::POST,
+            "put" => MethodFilter::PUT,
+            "delete" => MethodFilter::DELETE,
+            other => panic!("Management API does not serve {} requests", other),
+        };
+        let route: Vec<String> = path
+            .split('/')
+            .map(|segment| match segment.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+                Some(name) => format!(":{}", name),
+                None => segment.to_string(),
+            })
+            .collect();
+        self.router = self.router.route(&route.join("/"), on(filter, handler));
+        self.operations.push((method, path));
+        self
+    }
+
+    /// Require the API secret on everything mounted so far
+    fn authenticated(mut self, secret: Option<&Arc<str>>) -> Self {
+        if let Some(secret) = secret {
+            self.router = self.router.route_layer(middleware::from_fn_with_state(Arc::clone(secret), require_secret));
+        }
+        self
+    }
+}
+
+/// Management API bound to its port, not yet serving
+pub struct ManagementApi {
+    listener: TcpListener,
+    router: Router,
+    operations: Vec<(&'static str, String)>,
+    secret: Option<Arc<str>>,
+}
+
+impl ManagementApi {
+    pub async fn bind(config: &ManagementApiConfig, source: Arc<dyn ManagementSource>) -> Result<Self> {
+        let listen = config.listen;
+        let secret: Option<Arc<str>> = config.secret.as_deref().map(Arc::from);
+        if secret.is_none() && !listen.ip().is_loopback() {
+            return Err(Error::invalid_config(format!("Management API on {} needs a secret", listen)));
+        }
+        let listener = netbind::bind_tcp(listen, &config.network)
+            .map_err(|e| Error::network(format!("Management API cannot listen on {}: {}", listen, e)))?;
+        let api = ApiRouter { router: Router::new(), operations: Vec::new() }
+            .mount("get", STATUS_PATH, status)
+            .mount("get", SPANS_PATH, spans)
+            .mount("get", ACTIVE_CALLS_PATH, active_calls)
+            .mount("get", RTP_SESSIONS_PATH, rtp_sessions)
+            .mount("get", SERVICES_PATH, services)
+            .mount("get", ALARMS_PATH, alarms)
+            .mount("get", CDRS_PATH, cdrs)
+            .mount("get", TIMING_PATH, timing)
+            .mount("get", TEST_SESSIONS_PATH, test_sessions)
+            .mount("get", CAPACITY_PATH, capacity)
+            .mount("get", METRICS_PATH, metrics)
+            .mount("get", CONFIG_PATH, config)
+            .mount("get", API_SCHEMA_PATH, schema)
+            .mount("post", takeover_path("{call_id}"), take_over_call)
+            .mount("get", CALL_GAPS_PATH, call_gaps)
+            .mount("post", format!("{}/{{prefix}}", CALL_GAPS_PATH), apply_call_gap)
+            .mount("delete", format!("{}/{{prefix}}", CALL_GAPS_PATH), remove_call_gap)
+            .mount("get", CALL_TRACE_PATH, trace_selectors)
+            .mount("post", CALL_TRACE_PATH, add_trace_selector)
+            .mount("delete", CALL_TRACE_PATH, remove_trace_selector)
+            .mount("get", call_trace_path("{call_id}"), call_trace)
+            .mount("get", CODEC_NEGOTIATION_PATH, codec_negotiation)
+            .mount("get", TRANSCODING_HEADROOM_PATH, transcoding_headroom)
+            .mount("get", TRANSCODING_LATENCY_PATH, transcoding_latency)
+            .mount("get", REROUTE_COUNTERS_PATH, reroute_counters)
+            .mount("get", EARLY_MEDIA_PATH, early_media)
+            .mount("get", MEDIA_SECURITY_PATH, media_security)
+            .mount("get", RELEASE_CAUSES_PATH, release_causes)
+            .mount("get", SHADOW_ROUTING_PATH, shadow_routing)
+            .mount("get", TEST_CALLS_PATH, test_calls)
+            .mount("get", PAGING_PATH, pages)
+            .mount("get", TRUNK_REGISTRATIONS_PATH, trunk_registrations)
+            .mount("post", UPGRADE_PATH, upgrade)
+            .mount("get", PROMPTS_PATH, prompt_packs)
+            .mount("put", prompt_path("{language}", "{event}"), upload_prompt)
+            .mount("delete", prompt_path("{language}", "{event}"), remove_prompt)
+            .mount("get", SUPPORT_TUNNEL_PATH, tunnel_status)
+            .mount("post", SUPPORT_TUNNEL_PATH, open_tunnel)
+            .mount("delete", SUPPORT_TUNNEL_PATH, close_tunnel)
+            .mount("get", SUPPORT_TUNNEL_AUDIT_PATH, tunnel_audit)
+            .mount("get", PORTS_PATH, ports)
+            .mount("put", format!("{}/spans/{{span_id}}", PORTS_PATH), describe_span)
+            .mount("put", format!("{}/spans/{{span_id}}/channels/{{channel_id}}", PORTS_PATH), describe_channel)
+            .mount("get", CHANNEL_HISTORY_PATH, channel_history)
+            .mount("get", CPU_PROFILE_PATH, cpu_profile)
+            .mount("get", HEAP_STATS_PATH, heap_stats)
+            .mount("get", CANARY_PATH, canary)
+            .mount("get", CAMPAIGNS_PATH, campaigns)
+            .mount("post", CAMPAIGNS_PATH, start_campaign)
+            .mount("get", campaign_path("{id}"), campaign)
+            .mount("delete", campaign_path("{id}"), cancel_campaign)
+            .authenticated(secret.as_ref())
+            .mount("get", AUDIO_TAPS_PATH, audio_taps)
+            .mount("post", AUDIO_TAPS_PATH, start_audio_tap)
+            .mount("delete", audio_tap_path("{id}"), stop_audio_tap)
+            .mount("get", AUDIO_TAP_AUDIT_PATH, audio_tap_audit);
+        Ok(Self {
+            listener,
+            router: api.router.with_state(ApiState {
+                source,
+                max_cdrs: config.max_cdrs,
+                authenticated: secret.is_some(),
+            }),
+            operations: api.operations,
+            secret,
+        })
+    }
+
+    /// Also serve CSV export and import of the routes and trunks in the
+    /// configuration file at `config_path`
+    pub fn with_provisioning(mut self, config_path: PathBuf) -> Self {
+        let mut provisioning = route_provisioning::router(config_path);
+        if let Some(secret) = &self.secret {
+            provisioning = provisioning.route_layer(middleware::from_fn_with_state(Arc::clone(secret), require_secret));
+        }
+        self.router = self.router.merge(provisioning);
+        for path in [ROUTES_PATH, TRUNKS_PATH] {
+            self.operations.extend([("get", path.to_string()), ("put", path.to_string())]);
+        }
+        self
+    }
+
+    /// Method and OpenAPI path of every operation served, which is what
+    /// [`openapi_document`] describes
+    pub fn operations(&self) -> &[(&'static str, String)] {
+        &self.operations
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve until the task is aborted
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            if let Ok(addr) = self.listener.local_addr() {
+                info!("Management API listening on http://{}", addr);
+            }
+            if let Err(e) = axum::serve(self.listener, self.router).await {
+                error!("Management API stopped: {}", e);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeGateway;
+
+    fn api_config() -> ManagementApiConfig {
+        ManagementApiConfig { listen: "127.0.0.1:0".parse().unwrap(), max_cdrs: 3, ..Default::default() }
+    }
+
+    #[async_trait]
+    impl ManagementSource for FakeGateway {
+        async fn status(&self) -> ControlStatus {
+            ControlStatus {
+                node_id: "gw-1".to_string(),
+                version: crate::VERSION.to_string(),
+                running: true,
+                uptime_secs: 90,
+                active_calls: 1,
+                active_channels: 1,
+                sip_sessions: 0,
+                rtp_sessions: 0,
+                spans: Vec::new(),
+            }
+        }
+        async fn spans(&self) -> Vec<SpanReport> {
+            Vec::new()
+        }
+        async fn active_calls(&self) -> Vec<ActiveCallReport> {
+            Vec::new()
+        }
+        async fn rtp_sessions(&self) -> Vec<RtpSessionReport> {
+            Vec::new()
+        }
+        async fn services(&self) -> Vec<ServiceReport> {
+            vec![ServiceReport { name: "sip".to_string(), state: "running".to_string() }]
+        }
+        async fn alarms(&self) -> Vec<AlarmReport> {
+            Vec::new()
+        }
+        async fn cdrs(&self, query: &CdrQuery, limit: usize) -> Result<Vec<CdrReport>> {
+            let start_time = Utc::now();
+            Ok((0..limit)
+                .map(|i| CdrReport {
+                    id: format!("cdr-{}", i),
+                    call_id: format!("call-{}", i),
+                    correlation_id: None,
+                    caller: query.caller.clone().unwrap_or_default(),
+                    callee: "5551234".to_string(),
+                    start_time,
+                    answer_time: None,
+                    end_time: None,
+                    duration_secs: 0,
+                    disconnect_reason: None,
+                })
+                .collect())
+        }
+        async fn timing(&self) -> Option<TimingReport> {
+            None
+        }
+        async fn test_sessions(&self) -> Vec<TestSessionReport> {
+            Vec::new()
+        }
+        async fn capacity(&self, query: &CapacityQuery) -> Option<CapacityReport> {
+            Some(CapacityReport {
+                period: query.period.unwrap_or_default(),
+                from: Utc::now(),
+                to: Utc::now(),
+                grade_of_service: 0.01,
+                trunks: Vec::new(),
+            })
+        }
+        async fn metrics(&self) -> Result<String> {
+            Ok("redfire_up 1\n".to_string())
+        }
+        async fn config(&self) -> Option<GatewayConfig> {
+            let mut config = GatewayConfig::default_config();
+            config.snmp.community = "private".to_string();
+            Some(config)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() {
+        let api = ManagementApi::bind(&api_config(), Arc::new(FakeGateway)).await.unwrap();
+        let base = format!("http://{}", api.local_addr().unwrap());
+        let task = api.spawn();
+
+        let status: ControlStatus = reqwest::get(format!("{}{}", base, STATUS_PATH)).await.unwrap().json().await.unwrap();
+        assert_eq!((status.node_id.as_str(), status.uptime_secs), ("gw-1", 90));
+
+        // The limit is capped by the configured maximum
+        let url = format!("{}{}?caller=1001&limit=10", base, CDRS_PATH);
+        let cdrs: Vec<CdrReport> = reqwest::get(url).await.unwrap().json().await.unwrap();
+        assert_eq!(cdrs.len(), 3);
+        assert_eq!(cdrs[0].caller, "1001");
+
+        let timing = reqwest::get(format!("{}{}", base, TIMING_PATH)).await.unwrap();
+        assert_eq!(timing.status(), reqwest::StatusCode::NOT_FOUND);
+        let metrics = reqwest::get(format!("{}{}", base, METRICS_PATH)).await.unwrap().text().await.unwrap();
+        assert_eq!(metrics, "redfire_up 1\n");
+        let config: serde_json::Value = reqwest::get(format!("{}{}", base, CONFIG_PATH)).await.unwrap().json().await.unwrap();
+        assert_eq!(config["snmp"]["community"], crate::services::gateway_diff::REDACTED);
+        let csv = reqwest::get(format!("{}{}?period=month&format=csv", base, CAPACITY_PATH)).await.unwrap();
+        assert_eq!(csv.headers()[reqwest::header::CONTENT_TYPE], "text/csv");
+        assert!(csv.text().await.unwrap().starts_with("trunk,"));
+        let missing = reqwest::get(format!("{}/api/v1/nothing", base)).await.unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+
+        // Without a secret nobody may restart the gateway
+        let upgrade = reqwest::Client::new().post(format!("{}{}", base, UPGRADE_PATH));
+        let upgrade = upgrade.json(&serde_json::json!({ "sha256": "00" })).send().await.unwrap();
+        assert_eq!(upgrade.status(), reqwest::StatusCode::FORBIDDEN);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_secret() {
+        let open = ManagementApiConfig { listen: "0.0.0.0:0".parse().unwrap(), ..api_config() };
+        assert!(ManagementApi::bind(&open, Arc::new(FakeGateway)).await.is_err());
+
+        let config = ManagementApiConfig { secret: Some("s3cret".to_string()), ..api_config() };
+        let api = ManagementApi::bind(&config, Arc::new(FakeGateway)).await.unwrap();
+        let base = format!("http://{}", api.local_addr().unwrap());
+        let task = api.spawn();
+
+        let client = reqwest::Client::new();
+        let status = format!("{}{}", base, STATUS_PATH);
+        assert_eq!(client.get(&status).send().await.unwrap().status(), reqwest::StatusCode::UNAUTHORIZED);
+        let wrong = client.get(&status).bearer_auth("s3cre").send().await.unwrap();
+        assert_eq!(wrong.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(client.get(&status).bearer_auth("s3cret").send().await.unwrap().status().is_success());
+        let upgrade = client.post(format!("{}{}", base, UPGRADE_PATH)).json(&serde_json::json!({ "sha256": "00" }));
+        let upgrade = upgrade.send().await.unwrap();
+        assert_eq!(upgrade.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        // Audio taps check their operators' tokens instead
+        let taps = client.get(format!("{}{}", base, AUDIO_TAPS_PATH)).bearer_auth("operator").send().await.unwrap();
+        assert_eq!(taps.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_schema_matches_mounted_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let api = ManagementApi::bind(&api_config(), Arc::new(FakeGateway))
+            .await
+            .unwrap()
+            .with_provisioning(dir.path().join("gateway.toml"));
+        let mut mounted: Vec<(String, String)> =
+            api.operations().iter().map(|(method, path)| (method.to_string(), path.clone())).collect();
+
+        let document = openapi_document();
+        let mut documented: Vec<(String, String)> = document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| item.as_object().unwrap().keys().map(move |method| (method.clone(), path.clone())))
+            .collect();
+        mounted.sort();
+        documented.sort();
+        assert_eq!(mounted, documented);
+    }
+
+    /// `path` from the OpenAPI document with its parameters filled in
+    fn sample_path(path: &str) -> String {
+        path.split('/')
+            .map(|segment| match segment {
+                "{span_id}" | "{channel_id}" => "1",
+                _ if segment.starts_with('{') => "sample",
+                _ => segment,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[tokio::test]
+    async fn test_every_documented_path_is_served() {
+        let dir = tempfile::tempdir().unwrap();
+        let api = ManagementApi::bind(&api_config(), Arc::new(FakeGateway))
+            .await
+            .unwrap()
+            .with_provisioning(dir.path().join("gateway.toml"));
+        let base = format!("http://{}", api.local_addr().unwrap());
+        let task = api.spawn();
+        let client = reqwest::Client::new();
+
+        let document = openapi_document();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+                let url = format!("{}{}", base, sample_path(path));
+                let response = client.request(method.clone(), url).send().await.unwrap();
+                let status = response.status();
+                assert_ne!(status, reqwest::StatusCode::METHOD_NOT_ALLOWED, "{} {} is not served", method, path);
+                // A missing service or entity answers with an error body, an unrouted path with none
+                if status == reqwest::StatusCode::NOT_FOUND {
+                    let body: serde_json::Value = response.json().await.unwrap_or_default();
+                    assert!(body["error"].is_string(), "{} {} is not served", method, path);
+                }
+            }
+        }
+
+        // Services the fake gateway lacks answer 503, not 404
+        let tunnel = reqwest::get(format!("{}{}", base, SUPPORT_TUNNEL_PATH)).await.unwrap();
+        assert_eq!(tunnel.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        task.abort();
+    }
+}
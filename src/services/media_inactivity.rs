@@ -0,0 +1,228 @@
+//! Media inactivity timers by call type
+//!
+//! A voice call whose RTP has stopped for a minute is almost always a ghost:
+//! the far end went away without signalling it. Fax and modem calls passed
+//! through as audio legitimately go quiet for longer, during T.30 phase
+//! changes, ECM retransmissions or modem retrains, and releasing them on the
+//! voice timer cuts long transmissions short. Each call is classified from
+//! its SDP (a T.38 stream, V.152 voice-band data) and from the RFC 4733 tone
+//! events in its media (CNG and CED for fax, answer tones with phase
+//! reversals for modems), and its inactivity timer follows the class. Calls
+//! are released with cause 102 (recovery on timer expiry).
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::MediaInactivityConfig;
+use crate::protocols::sdp::SessionDescription;
+
+/// What a call's media carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaCallType {
+    #[default]
+    Voice,
+    Fax,
+    Modem,
+}
+
+impl fmt::Display for MediaCallType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Voice => "voice",
+            Self::Fax => "fax",
+            Self::Modem => "modem",
+        })
+    }
+}
+
+impl MediaCallType {
+    /// Class announced by an offer or answer
+    pub fn from_sdp(sdp: &SessionDescription) -> Option<Self> {
+        let t38 = sdp.media.iter().any(|stream| {
+            stream.media_type.eq_ignore_ascii_case("image")
+                && !stream.is_rejected()
+                && stream.formats.iter().any(|format| format.eq_ignore_ascii_case("t38"))
+        });
+        if t38 {
+            return Some(Self::Fax);
+        }
+        // V.152 voice-band data carries fax or modem; the longer timer covers both
+        let vbd = sdp
+            .audio_streams()
+            .any(|stream| stream.attributes("gpmd").any(|value| value.to_ascii_lowercase().contains("vbd=yes")));
+        vbd.then_some(Self::Modem)
+    }
+
+    /// Class shown by an RFC 4734 modem, fax and text telephony event
+    pub fn from_tone_event(event: u8) -> Option<Self> {
+        match event {
+            // ANS (CED) and CNG
+            32 | 36 => Some(Self::Fax),
+            // /ANS, ANSam and /ANSam
+            33..=35 => Some(Self::Modem),
+            _ => None,
+        }
+    }
+}
+
+impl MediaInactivityConfig {
+    /// Silence allowed on a call of `call_type`
+    pub fn timeout(&self, call_type: MediaCallType) -> Duration {
+        Duration::from_secs(match call_type {
+            MediaCallType::Voice => self.voice_secs,
+            MediaCallType::Fax => self.fax_secs,
+            MediaCallType::Modem => self.modem_secs,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct CallMedia {
+    call_type: MediaCallType,
+    /// Payload type of telephone events, from the latest SDP carrying one
+    events: Option<u8>,
+    last_packet: Option<Instant>,
+    /// Every audio stream is inactive, e.g. the call is on hold
+    on_hold: bool,
+}
+
+/// Media activity and type of each call
+pub struct MediaInactivity {
+    config: MediaInactivityConfig,
+    calls: DashMap<String, CallMedia>,
+}
+
+impl MediaInactivity {
+    pub fn new(config: MediaInactivityConfig) -> Self {
+        Self {
+            config,
+            calls: DashMap::new(),
+        }
+    }
+
+    /// Take note of an offer or answer exchanged on the call
+    pub fn on_sdp(&self, call_id: &str, sdp: &str) {
+        let Ok(sdp) = SessionDescription::parse(sdp) else {
+            return;
+        };
+        let mut media = self.calls.entry(call_id.to_string()).or_default();
+        if let Some(detected) = MediaCallType::from_sdp(&sdp) {
+            Self::classify(call_id, &mut media, detected);
+        }
+        let events = sdp
+            .audio_streams()
+            .flat_map(|stream| stream.rtpmaps())
+            .find(|map| map.encoding.eq_ignore_ascii_case("telephone-event"))
+            .map(|map| map.payload_type);
+        media.events = events.or(media.events);
+        let mut streams = sdp.audio_streams().filter(|stream| !stream.is_rejected()).peekable();
+        media.on_hold = streams.peek().is_some() && streams.all(|stream| stream.direction() == "inactive");
+    }
+
+    /// Take note of an RTP packet relayed on the call
+    pub fn on_packet(&self, call_id: &str, payload_type: u8, payload: &[u8], at: Instant) {
+        let mut media = self.calls.entry(call_id.to_string()).or_default();
+        media.last_packet = Some(at);
+        if media.events == Some(payload_type) {
+            if let Some(detected) = payload.first().and_then(|&event| MediaCallType::from_tone_event(event)) {
+                Self::classify(call_id, &mut media, detected);
+            }
+        }
+    }
+
+    /// A call stays fax or modem once detected; only voice is reclassified
+    fn classify(call_id: &str, media: &mut CallMedia, detected: MediaCallType) {
+        if media.call_type == MediaCallType::Voice && detected != MediaCallType::Voice {
+            info!("Call {} carries {}", call_id, detected);
+            media.call_type = detected;
+        }
+    }
+
+    pub fn call_type(&self, call_id: &str) -> MediaCallType {
+        self.calls.get(call_id).map(|media| media.call_type).unwrap_or_default()
+    }
+
+    /// When the call's last RTP packet was relayed
+    pub fn last_packet(&self, call_id: &str) -> Option<Instant> {
+        self.calls.get(call_id).and_then(|media| media.last_packet)
+    }
+
+    /// Calls whose media has been silent longer than their type allows, with
+    /// the type and how long. Calls on hold, and calls whose media never
+    /// started, are left to the signalling timeout
+    pub fn expired(&self, now: Instant) -> Vec<(String, MediaCallType, Duration)> {
+        if !self.config.enabled {
+            return Vec::new();
+        }
+        self.calls
+            .iter()
+            .filter(|media| !media.on_hold)
+            .filter_map(|media| {
+                let silent = now.saturating_duration_since(media.last_packet?);
+                (silent > self.config.timeout(media.call_type)).then(|| (media.key().clone(), media.call_type, silent))
+            })
+            .collect()
+    }
+
+    pub fn release(&self, call_id: &str) {
+        self.calls.remove(call_id);
+    }
+
+    /// Forget calls released on other paths
+    pub fn retain(&self, keep: impl Fn(&str) -> bool) {
+        self.calls.retain(|call_id, _| keep(call_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFER: &str = "v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=-\r\nc=IN IP4 10.0.0.1\r\nt=0 0\r\n\
+        m=audio 4000 RTP/AVP 0 101\r\na=rtpmap:0 PCMU/8000\r\na=rtpmap:101 telephone-event/8000\r\n";
+
+    #[test]
+    fn test_sdp_classification() {
+        let t38 = SessionDescription::parse(&format!("{}m=image 4002 udptl t38\r\n", OFFER)).unwrap();
+        assert_eq!(MediaCallType::from_sdp(&t38), Some(MediaCallType::Fax));
+        let vbd = SessionDescription::parse(&format!("{}a=gpmd:0 vbd=yes\r\n", OFFER)).unwrap();
+        assert_eq!(MediaCallType::from_sdp(&vbd), Some(MediaCallType::Modem));
+        assert_eq!(MediaCallType::from_sdp(&SessionDescription::parse(OFFER).unwrap()), None);
+    }
+
+    #[test]
+    fn test_timers_follow_detected_call_type() {
+        let config = MediaInactivityConfig::default();
+        let media = MediaInactivity::new(config.clone());
+        let start = Instant::now();
+        for call_id in ["voice", "fax", "held"] {
+            media.on_sdp(call_id, OFFER);
+            media.on_packet(call_id, 0, &[0xff; 160], start);
+        }
+        // CNG from the calling fax machine, then a long T.30 pause
+        media.on_packet("fax", 101, &[36, 0x0a, 0x00, 0xa0], start);
+        media.on_packet("fax", 101, &[32, 0x0a, 0x00, 0xa0], start);
+        assert_eq!(media.call_type("fax"), MediaCallType::Fax);
+        media.on_sdp("held", &OFFER.replace("a=rtpmap:0", "a=inactive\r\na=rtpmap:0"));
+        media.on_sdp("silent", OFFER);
+
+        let later = start + config.timeout(MediaCallType::Voice) + Duration::from_secs(1);
+        let expired = media.expired(later);
+        assert_eq!(expired, vec![("voice".to_string(), MediaCallType::Voice, Duration::from_secs(61))]);
+
+        let expired = media.expired(start + config.timeout(MediaCallType::Fax) + Duration::from_secs(1));
+        let mut calls: Vec<&str> = expired.iter().map(|(call_id, ..)| call_id.as_str()).collect();
+        calls.sort();
+        assert_eq!(calls, vec!["fax", "voice"]);
+
+        media.retain(|call_id| call_id != "voice");
+        media.release("fax");
+        assert!(media.expired(later + Duration::from_secs(3600)).is_empty());
+    }
+}
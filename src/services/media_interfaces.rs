@@ -0,0 +1,154 @@
+//! Media interface selection for relayed calls
+//!
+//! Each B2BUA leg can be pinned to a named local interface: the egress leg by
+//! the trunk it is routed to and the ingress leg by the caller's tenant domain.
+//! The chosen interface decides the address RTP is bound to and the address
+//! written into that leg's SDP `c=` lines.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::config::{MediaInterface, MediaInterfaceRule};
+use crate::services::b2bua::CallLeg;
+use crate::{Error, Result};
+
+/// A media interface with its addresses parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedInterface {
+    pub name: String,
+    pub bind_ip: IpAddr,
+    /// Address advertised in SDP for this interface
+    pub advertised_ip: IpAddr,
+    pub device: Option<String>,
+}
+
+/// Picks the media interface for each leg of a call
+#[derive(Debug, Clone, Default)]
+pub struct MediaInterfaceSelector {
+    interfaces: HashMap<String, ResolvedInterface>,
+    rules: Vec<MediaInterfaceRule>,
+    default_interface: Option<String>,
+}
+
+impl MediaInterfaceSelector {
+    pub fn new(
+        interfaces: &[MediaInterface],
+        rules: &[MediaInterfaceRule],
+        default_interface: Option<&str>,
+    ) -> Result<Self> {
+        let mut resolved = HashMap::new();
+        for interface in interfaces {
+            let bind_ip: IpAddr = interface.bind_address.parse().map_err(|_| {
+                Error::invalid_config(format!("Invalid bind address for media interface {}: {}", interface.name, interface.bind_address))
+            })?;
+            let advertised_ip = match &interface.advertised_address {
+                Some(address) => address.parse().map_err(|_| {
+                    Error::invalid_config(format!("Invalid advertised address for media interface {}: {}", interface.name, address))
+                })?,
+                None if bind_ip.is_unspecified() => {
+                    return Err(Error::invalid_config(format!(
+                        "Media interface {} binds a wildcard address and needs an advertised_address",
+                        interface.name
+                    )));
+                }
+                None => bind_ip,
+            };
+            resolved.insert(interface.name.clone(), ResolvedInterface {
+                name: interface.name.clone(),
+                bind_ip,
+                advertised_ip,
+                device: interface.device.clone(),
+            });
+        }
+
+        let referenced = rules.iter().map(|r| r.interface.as_str()).chain(default_interface);
+        for name in referenced {
+            if !resolved.contains_key(name) {
+                return Err(Error::invalid_config(format!("Unknown media interface: {}", name)));
+            }
+        }
+
+        Ok(Self {
+            interfaces: resolved,
+            rules: rules.to_vec(),
+            default_interface: default_interface.map(str::to_string),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interfaces.is_empty()
+    }
+
+    pub fn interface(&self, name: &str) -> Option<&ResolvedInterface> {
+        self.interfaces.get(name)
+    }
+
+    /// Interface for a leg: leg A follows tenant rules, leg B follows trunk rules
+    pub fn select(&self, leg: CallLeg, trunk: Option<&str>, tenant: Option<&str>) -> Option<&ResolvedInterface> {
+        let rule = self.rules.iter().find(|rule| match leg {
+            CallLeg::A => rule.tenant.is_some() && rule.tenant.as_deref() == tenant,
+            CallLeg::B => rule.trunk.is_some() && rule.trunk.as_deref() == trunk,
+        });
+
+        rule.map(|r| r.interface.as_str())
+            .or(self.default_interface.as_deref())
+            .and_then(|name| self.interfaces.get(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, bind: &str, advertised: Option<&str>) -> MediaInterface {
+        MediaInterface {
+            name: name.to_string(),
+            bind_address: bind.to_string(),
+            advertised_address: advertised.map(str::to_string),
+            device: None,
+        }
+    }
+
+    fn rule(trunk: Option<&str>, tenant: Option<&str>, interface: &str) -> MediaInterfaceRule {
+        MediaInterfaceRule {
+            trunk: trunk.map(str::to_string),
+            tenant: tenant.map(str::to_string),
+            interface: interface.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_select_by_leg() {
+        let selector = MediaInterfaceSelector::new(
+            &[
+                interface("core", "10.0.0.5", None),
+                interface("carrier", "192.0.2.10", None),
+                interface("customer", "10.1.0.5", Some("203.0.113.7")),
+            ],
+            &[
+                rule(Some("carrier-a"), None, "carrier"),
+                rule(None, Some("acme.example.com"), "customer"),
+            ],
+            Some("core"),
+        ).unwrap();
+
+        let b = selector.select(CallLeg::B, Some("carrier-a"), Some("acme.example.com")).unwrap();
+        assert_eq!(b.name, "carrier");
+
+        let a = selector.select(CallLeg::A, Some("carrier-a"), Some("acme.example.com")).unwrap();
+        assert_eq!(a.name, "customer");
+        assert_eq!(a.advertised_ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+
+        let fallback = selector.select(CallLeg::B, Some("carrier-b"), None).unwrap();
+        assert_eq!(fallback.name, "core");
+    }
+
+    #[test]
+    fn test_rejects_unknown_or_unadvertisable_interfaces() {
+        assert!(MediaInterfaceSelector::new(&[], &[rule(Some("t"), None, "missing")], None).is_err());
+        assert!(MediaInterfaceSelector::new(&[interface("any", "0.0.0.0", None)], &[], None).is_err());
+
+        let selector = MediaInterfaceSelector::new(&[interface("core", "10.0.0.5", None)], &[], None).unwrap();
+        assert!(selector.select(CallLeg::A, None, None).is_none());
+    }
+}
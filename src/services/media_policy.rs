@@ -0,0 +1,340 @@
+//! Per-trunk media policy enforcement for SDP negotiation
+//!
+//! Before an offer is forwarded to a trunk (or an answer is relayed back),
+//! the B2BUA runs it through the trunk's policy: disallowed codecs are
+//! stripped, streams whose bandwidth exceeds the cap are trimmed, and a
+//! fixed packetization can be forced. Offers whose primary audio stream is
+//! left with no usable codec are rejected with the policy's response code;
+//! additional audio streams in that state are declined instead. Video and
+//! additional audio streams are declined or passed through ahead of the
+//! policy, so a video-capable endpoint still gets an audio call.
+
+use std::collections::HashMap;
+
+use tracing::{debug, warn};
+
+use crate::config::{MediaStreamsConfig, StreamHandling, TrunkMediaPolicy};
+use crate::protocols::sdp::{RtpMap, SessionDescription};
+use crate::services::transcoding::CodecType;
+
+/// Combined IP/UDP/RTP header overhead per packet in bytes
+const PACKET_OVERHEAD_BYTES: u32 = 40;
+const DEFAULT_PTIME_MS: u32 = 20;
+
+/// Which side of the negotiation an SDP body belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SdpRole {
+    Offer,
+    Answer,
+}
+
+/// Result of applying a media policy
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyOutcome {
+    /// SDP passed the policy; `modified` is set when it was rewritten
+    Accepted { sdp: String, modified: bool },
+    /// SDP violates the policy and the call must be refused
+    Rejected { response_code: u16, reason: String },
+}
+
+/// Applies per-trunk media policies to SDP bodies
+#[derive(Debug, Clone, Default)]
+pub struct MediaPolicyEnforcer {
+    policies: HashMap<String, TrunkMediaPolicy>,
+}
+
+impl MediaPolicyEnforcer {
+    pub fn new(policies: &[TrunkMediaPolicy]) -> Self {
+        Self {
+            policies: policies
+                .iter()
+                .map(|p| (p.trunk.clone(), p.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn policy_for(&self, trunk: &str) -> Option<&TrunkMediaPolicy> {
+        self.policies.get(trunk)
+    }
+
+    /// Enforce the trunk's policy on an SDP body.
+    ///
+    /// Trunks without a policy pass the SDP through untouched.
+    pub fn enforce(&self, trunk: &str, sdp: &str, role: SdpRole) -> PolicyOutcome {
+        let policy = match self.policies.get(trunk) {
+            Some(policy) => policy,
+            None => return PolicyOutcome::Accepted { sdp: sdp.to_string(), modified: false },
+        };
+
+        let mut session = match SessionDescription::parse(sdp) {
+            Ok(session) => session,
+            Err(e) => {
+                warn!("Rejecting unparseable SDP {:?} on trunk {}: {}", role, trunk, e);
+                return Self::rejected(policy, format!("malformed SDP: {}", e));
+            }
+        };
+
+        let original = session.clone();
+        let ptime = policy.ptime_ms.unwrap_or(DEFAULT_PTIME_MS);
+        let primary = session.primary_audio();
+
+        let streams = session.media.iter_mut().enumerate().filter(|(_, m)| m.is_audio() && !m.is_rejected());
+        for (index, stream) in streams {
+            let stream_ptime = policy.ptime_ms.or_else(|| stream.ptime()).unwrap_or(ptime);
+
+            stream.retain_payload_types(|map| {
+                if Self::is_signaling_payload(map) {
+                    return true;
+                }
+                if !Self::codec_allowed(policy, &map.encoding) {
+                    debug!("Stripping disallowed codec {} on trunk {}", map.encoding, trunk);
+                    return false;
+                }
+                match (policy.max_bandwidth_kbps, Self::estimated_bandwidth_kbps(map, stream_ptime)) {
+                    (Some(max), Some(needed)) if needed > max => {
+                        debug!("Stripping codec {} on trunk {}: {} kbps exceeds {} kbps",
+                            map.encoding, trunk, needed, max);
+                        false
+                    }
+                    _ => true,
+                }
+            });
+
+            let has_media_codec = stream
+                .rtpmaps()
+                .iter()
+                .any(|map| !Self::is_signaling_payload(map));
+            if !has_media_codec && Some(index) != primary {
+                // Only the primary audio stream decides whether the call goes ahead
+                debug!("Declining audio stream {} on trunk {}: no permitted codec", index, trunk);
+                *stream = original.media[index].clone();
+                stream.reject();
+                continue;
+            }
+            if !has_media_codec {
+                return Self::rejected(policy, format!(
+                    "no permitted audio codec remains in {:?} for trunk {}",
+                    role, trunk
+                ));
+            }
+
+            if let Some(forced) = policy.ptime_ms {
+                stream.set_attribute("ptime", Some(&forced.to_string()));
+                stream.remove_attribute("maxptime");
+            }
+
+            if let Some(max) = policy.max_bandwidth_kbps {
+                if stream.bandwidth_kbps().map(|bw| bw > max).unwrap_or(false) {
+                    stream.set_bandwidth_kbps(max);
+                }
+            }
+        }
+
+        let modified = session != original;
+        PolicyOutcome::Accepted { sdp: session.to_string(), modified }
+    }
+
+    /// Decline the video and additional audio streams of an offer that the
+    /// trunk's handling, or else `defaults`, does not pass through.
+    /// Unparseable offers are returned unchanged for [`enforce`](Self::enforce)
+    /// to judge.
+    pub fn limit_streams(&self, trunk: &str, defaults: &MediaStreamsConfig, offer: &str) -> String {
+        let handling = self
+            .policies
+            .get(trunk)
+            .and_then(|policy| policy.media_streams.as_ref())
+            .unwrap_or(defaults);
+        let Ok(mut session) = SessionDescription::parse(offer) else {
+            return offer.to_string();
+        };
+
+        let primary = session.primary_audio();
+        let mut declined = 0;
+        for (index, stream) in session.media.iter_mut().enumerate() {
+            if stream.is_rejected() || Some(index) == primary {
+                continue;
+            }
+            let action = if stream.is_video() {
+                handling.video
+            } else if stream.is_audio() {
+                handling.extra_audio
+            } else {
+                continue;
+            };
+            if action == StreamHandling::Reject {
+                debug!("Declining {} stream {} for trunk {}", stream.media_type, index, trunk);
+                stream.reject();
+                declined += 1;
+            }
+        }
+
+        if declined == 0 {
+            offer.to_string()
+        } else {
+            session.to_string()
+        }
+    }
+
+    fn rejected(policy: &TrunkMediaPolicy, reason: String) -> PolicyOutcome {
+        PolicyOutcome::Rejected {
+            response_code: policy.reject_code,
+            reason,
+        }
+    }
+
+    fn codec_allowed(policy: &TrunkMediaPolicy, encoding: &str) -> bool {
+        if policy.allowed_codecs.is_empty() {
+            return true;
+        }
+        let codec = CodecType::from_name(encoding);
+        policy
+            .allowed_codecs
+            .iter()
+            .any(|allowed| CodecType::from_name(allowed).to_name().eq_ignore_ascii_case(codec.to_name()))
+    }
+
+    /// DTMF and comfort noise are never stripped by codec policy
+    fn is_signaling_payload(map: &RtpMap) -> bool {
+        map.encoding.eq_ignore_ascii_case("telephone-event") || map.encoding.eq_ignore_ascii_case("CN")
+    }
+
+    /// Nominal IP bandwidth of a codec at the given packetization
+    pub fn estimated_bandwidth_kbps(map: &RtpMap, ptime_ms: u32) -> Option<u32> {
+        let payload_kbps = match CodecType::from_name(&map.encoding) {
+            CodecType::G711u | CodecType::G711a | CodecType::G722 => 64,
+            CodecType::G729 => 8,
+            CodecType::G726 => 32,
+            CodecType::Ilbc => 15,
+            CodecType::Amr => 12,
+            CodecType::AmrWb => 24,
+            CodecType::Opus => 32,
+            CodecType::Speex => 24,
+            CodecType::Evs => 24,
+            CodecType::Custom(_) => return None,
+        };
+        let packets_per_second = 1000 / ptime_ms.max(1);
+        let overhead_kbps = PACKET_OVERHEAD_BYTES * 8 * packets_per_second / 1000;
+        Some(payload_kbps + overhead_kbps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFER: &str = "v=0\r\n\
+        o=- 1 1 IN IP4 192.0.2.1\r\n\
+        s=-\r\n\
+        c=IN IP4 192.0.2.1\r\n\
+        t=0 0\r\n\
+        m=audio 10000 RTP/AVP 0 8 18 101\r\n\
+        b=AS:200\r\n\
+        a=rtpmap:18 G729/8000\r\n\
+        a=rtpmap:101 telephone-event/8000\r\n\
+        a=ptime:30\r\n";
+
+    fn policy(allowed: &[&str], max_bw: Option<u32>, ptime: Option<u32>) -> TrunkMediaPolicy {
+        TrunkMediaPolicy {
+            trunk: "carrier-a".to_string(),
+            allowed_codecs: allowed.iter().map(|c| c.to_string()).collect(),
+            max_bandwidth_kbps: max_bw,
+            ptime_ms: ptime,
+            reject_code: 488,
+            outbound_codec_priority: Vec::new(),
+            inbound_codec_priority: Vec::new(),
+            media_streams: None,
+            srtp: false,
+            require_srtp: false,
+            srtp_downgrade: Default::default(),
+            webrtc: false,
+        }
+    }
+
+    #[test]
+    fn test_strips_disallowed_codecs_and_forces_ptime() {
+        let enforcer = MediaPolicyEnforcer::new(&[policy(&["g711u", "G729"], Some(100), Some(20))]);
+
+        match enforcer.enforce("carrier-a", OFFER, SdpRole::Offer) {
+            PolicyOutcome::Accepted { sdp, modified } => {
+                assert!(modified);
+                let session = SessionDescription::parse(&sdp).unwrap();
+                let audio = &session.media[0];
+                assert_eq!(audio.formats, vec!["0", "18", "101"]);
+                assert_eq!(audio.ptime(), Some(20));
+                assert_eq!(audio.bandwidth_kbps(), Some(100));
+            }
+            other => panic!("expected acceptance, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bandwidth_cap_strips_wide_codecs() {
+        let enforcer = MediaPolicyEnforcer::new(&[policy(&[], Some(30), None)]);
+
+        match enforcer.enforce("carrier-a", OFFER, SdpRole::Offer) {
+            PolicyOutcome::Accepted { sdp, .. } => {
+                let session = SessionDescription::parse(&sdp).unwrap();
+                assert_eq!(session.media[0].formats, vec!["18", "101"]);
+            }
+            other => panic!("expected acceptance, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rejects_offer_without_permitted_codec() {
+        let mut p = policy(&["opus"], None, None);
+        p.reject_code = 415;
+        let enforcer = MediaPolicyEnforcer::new(&[p]);
+
+        match enforcer.enforce("carrier-a", OFFER, SdpRole::Offer) {
+            PolicyOutcome::Rejected { response_code, .. } => assert_eq!(response_code, 415),
+            other => panic!("expected rejection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_video_and_extra_audio_do_not_fail_the_call() {
+        let offer = format!(
+            "{}m=video 10002 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\nm=audio 10004 RTP/AVP 8\r\n",
+            OFFER
+        );
+        let enforcer = MediaPolicyEnforcer::new(&[policy(&["G729"], None, None)]);
+
+        let limited = enforcer.limit_streams("carrier-a", &MediaStreamsConfig::default(), &offer);
+        let session = SessionDescription::parse(&limited).unwrap();
+        let ports: Vec<u16> = session.media.iter().map(|m| m.port).collect();
+        assert_eq!(ports, vec![10000, 0, 0]);
+        assert_eq!(session.media[1].formats, vec!["96"]);
+
+        // Passed through, the extra audio stream is declined by codec policy
+        // rather than failing the call
+        let pass = MediaStreamsConfig { video: StreamHandling::PassThrough, extra_audio: StreamHandling::PassThrough };
+        let limited = enforcer.limit_streams("carrier-a", &pass, &offer);
+        assert_eq!(limited, offer);
+        match enforcer.enforce("carrier-a", &limited, SdpRole::Offer) {
+            PolicyOutcome::Accepted { sdp, .. } => {
+                let session = SessionDescription::parse(&sdp).unwrap();
+                let ports: Vec<u16> = session.media.iter().map(|m| m.port).collect();
+                assert_eq!(ports, vec![10000, 10002, 0]);
+                assert_eq!(session.media[0].formats, vec!["18", "101"]);
+                assert_eq!(session.media[2].formats, vec!["8"]);
+            }
+            other => panic!("expected acceptance, got {:?}", other),
+        }
+
+        // The trunk's own handling wins over the B2BUA-wide one
+        let mut own = policy(&[], None, None);
+        own.media_streams = Some(pass);
+        let enforcer = MediaPolicyEnforcer::new(&[own]);
+        assert_eq!(enforcer.limit_streams("carrier-a", &MediaStreamsConfig::default(), &offer), offer);
+    }
+
+    #[test]
+    fn test_unknown_trunk_passes_through() {
+        let enforcer = MediaPolicyEnforcer::new(&[policy(&["opus"], None, None)]);
+        assert_eq!(
+            enforcer.enforce("other", OFFER, SdpRole::Offer),
+            PolicyOutcome::Accepted { sdp: OFFER.to_string(), modified: false }
+        );
+    }
+}
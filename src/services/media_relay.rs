@@ -16,7 +16,10 @@ use tokio::time::interval;
 use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
-use crate::protocols::rtp::{RtpPacket, RtpSession, RtpHandler, RtpEvent};
+use crate::protocols::rtp::{RtpPacket, RtpSession, RtpHandler, RtpEvent, RtpStreamStats};
+use crate::protocols::srtp::SrtpProfile;
+use crate::services::call_trace::{CallTracer, TraceSubsystem};
+use crate::services::media_repair::{MediaRepair, MediaRepairStats, SilenceSuppressor, CN_PAYLOAD_TYPE};
 use crate::services::transcoding::{TranscodingService, CodecType, TranscodingEvent};
 use crate::{Error, Result};
 
@@ -47,6 +50,13 @@ pub struct MediaEndpoint {
     pub codec: CodecType,
     pub ssrc: u32,
     pub payload_type: u8,
+    /// SRTP suite protecting this leg, if it negotiated one
+    #[serde(default)]
+    pub srtp: Option<SrtpProfile>,
+    /// Peer negotiated RFC 3389 comfort noise, so silence sent to it is
+    /// suppressed; audio to any other leg is kept continuous
+    #[serde(default)]
+    pub comfort_noise: bool,
     #[serde(skip, default)]
     pub last_packet_time: Option<Instant>,
 }
@@ -78,6 +88,45 @@ pub struct MediaRelayStats {
     pub packet_loss_rate: f64,
     pub codec_a: CodecType,
     pub codec_b: CodecType,
+    /// Reception quality of each leg, from its RTP stream and RTCP reports
+    #[serde(default)]
+    pub leg_a_rtcp: Option<RtcpLegStats>,
+    #[serde(default)]
+    pub leg_b_rtcp: Option<RtcpLegStats>,
+    /// Jitter buffer of the packets received on each leg
+    #[serde(default)]
+    pub leg_a_jitter_buffer: Option<JitterBufferStats>,
+    #[serde(default)]
+    pub leg_b_jitter_buffer: Option<JitterBufferStats>,
+    /// Concealment, comfort noise and silence suppression of the audio sent
+    /// to each leg
+    #[serde(default)]
+    pub leg_a_repair: Option<MediaRepairStats>,
+    #[serde(default)]
+    pub leg_b_repair: Option<MediaRepairStats>,
+}
+
+/// Quality of one relay leg: what the gateway receives and what the far
+/// end reports receiving
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RtcpLegStats {
+    pub loss_pct: f64,
+    pub jitter_ms: f64,
+    pub remote_loss_pct: Option<f64>,
+    pub remote_jitter_ms: Option<f64>,
+    pub round_trip_ms: Option<f64>,
+}
+
+impl From<&RtpStreamStats> for RtcpLegStats {
+    fn from(stats: &RtpStreamStats) -> Self {
+        Self {
+            loss_pct: stats.loss_pct(),
+            jitter_ms: stats.jitter,
+            remote_loss_pct: stats.rtcp.remote_loss_pct(),
+            remote_jitter_ms: stats.rtcp.remote_jitter_ms(),
+            round_trip_ms: stats.rtcp.round_trip_ms,
+        }
+    }
 }
 
 impl MediaRelayStats {
@@ -94,9 +143,67 @@ impl MediaRelayStats {
             packet_loss_rate: 0.0,
             codec_a,
             codec_b,
+            leg_a_rtcp: None,
+            leg_b_rtcp: None,
+            leg_a_jitter_buffer: None,
+            leg_b_jitter_buffer: None,
+            leg_a_repair: None,
+            leg_b_repair: None,
         }
     }
 
+    /// Take a leg's latest stream statistics; loss and latency become the
+    /// worse of the two legs
+    pub fn update_leg_quality(&mut self, leg_a: bool, quality: RtcpLegStats) {
+        if leg_a {
+            self.leg_a_rtcp = Some(quality);
+        } else {
+            self.leg_b_rtcp = Some(quality);
+        }
+        let legs = || self.leg_a_rtcp.iter().chain(&self.leg_b_rtcp);
+        self.packet_loss_rate = legs()
+            .map(|leg| leg.loss_pct.max(leg.remote_loss_pct.unwrap_or(0.0)))
+            .fold(0.0, f64::max);
+        self.average_latency_ms = legs()
+            .filter_map(|leg| leg.round_trip_ms)
+            .map(|rtt| rtt / 2.0)
+            .fold(0.0, f64::max);
+    }
+
+    /// Take a leg's latest jitter buffer state; the buffer size becomes the
+    /// packets both hold
+    pub fn update_jitter_buffer(&mut self, leg_a: bool, buffer: JitterBufferStats) {
+        if leg_a {
+            self.leg_a_jitter_buffer = Some(buffer);
+        } else {
+            self.leg_b_jitter_buffer = Some(buffer);
+        }
+        self.jitter_buffer_size = self
+            .leg_a_jitter_buffer
+            .iter()
+            .chain(&self.leg_b_jitter_buffer)
+            .map(|buffer| buffer.buffered_packets as u32)
+            .sum();
+    }
+
+    /// Take the latest repair and suppression counts of the audio sent to a leg
+    pub fn update_repair(&mut self, leg_a: bool, repair: MediaRepairStats) {
+        if leg_a {
+            self.leg_a_repair = Some(repair);
+        } else {
+            self.leg_b_repair = Some(repair);
+        }
+    }
+
+    /// Worst jitter either leg receives or reports
+    pub fn max_jitter_ms(&self) -> f64 {
+        self.leg_a_rtcp
+            .iter()
+            .chain(&self.leg_b_rtcp)
+            .map(|leg| leg.jitter_ms.max(leg.remote_jitter_ms.unwrap_or(0.0)))
+            .fold(0.0, f64::max)
+    }
+
     pub fn total_packets(&self) -> u64 {
         self.packets_relayed_a_to_b + self.packets_relayed_b_to_a
     }
@@ -114,8 +221,17 @@ pub struct MediaProcessingConfig {
     pub enable_automatic_gain_control: bool,
     pub enable_dtmf_detection: bool,
     pub enable_silence_detection: bool,
+    /// Most packets a jitter buffer holds; 0 relays without one
     pub jitter_buffer_size: u32,
+    /// Bounds of the adaptive playout delay; equal bounds fix it
+    pub jitter_buffer_min_ms: u32,
+    pub jitter_buffer_max_ms: u32,
+    /// Conceal lost G.711 frames sent to legs that play out continuously
     pub packet_loss_concealment: bool,
+    /// Play received and implied silence as comfort noise on those legs
+    pub comfort_noise: bool,
+    /// Suppress silence toward peers that negotiated comfort noise
+    pub voice_activity_detection: bool,
 }
 
 impl Default for MediaProcessingConfig {
@@ -127,7 +243,11 @@ impl Default for MediaProcessingConfig {
             enable_dtmf_detection: true,
             enable_silence_detection: true,
             jitter_buffer_size: 50,
+            jitter_buffer_min_ms: 20,
+            jitter_buffer_max_ms: 200,
             packet_loss_concealment: true,
+            comfort_noise: true,
+            voice_activity_detection: true,
         }
     }
 }
@@ -186,87 +306,233 @@ pub enum RelayDirection {
     BToA,
 }
 
-/// Jitter buffer for packet reordering and delay compensation
+/// RTP timestamp units per millisecond; 8 kHz clock assumed
+const RTP_UNITS_PER_MS: f64 = 8.0;
+
+/// Playout clock of comfort noise and concealment: one 20 ms frame
+const PLAYOUT_TICK: Duration = Duration::from_millis(20);
+
+/// Playout delay as a multiple of the measured interarrival jitter
+const JITTER_DEPTH_FACTOR: f64 = 3.0;
+
+/// Most the playout delay shrinks per packet once jitter has settled, ms
+const DEPTH_SHRINK_STEP_MS: f64 = 0.5;
+
+/// Depth and discard accounting of one jitter buffer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JitterBufferStats {
+    /// Playout delay now, ms
+    pub current_depth_ms: f64,
+    /// Least and most the playout delay has been
+    pub min_depth_ms: f64,
+    pub max_depth_ms: f64,
+    /// Interarrival jitter the delay follows, ms
+    pub jitter_ms: f64,
+    pub buffered_packets: usize,
+    /// Arrived after a higher sequence number and put back in order
+    pub reordered_packets: u64,
+    /// Arrived after their turn to play had passed
+    pub late_discards: u64,
+    pub duplicate_discards: u64,
+    /// Dropped because the buffer was full
+    pub overflow_discards: u64,
+    /// Given up on when the packet after them was due to play
+    pub lost_packets: u64,
+}
+
+/// Adaptive jitter buffer: packets are put back in sequence order and each
+/// is held until its playout time, the delay after the earliest it could
+/// have arrived. The delay follows the measured interarrival jitter between
+/// a minimum and a maximum, growing at once and shrinking gradually; equal
+/// bounds make a fixed buffer.
 #[derive(Debug)]
 pub struct JitterBuffer {
-    packets: HashMap<u16, (RtpPacket, Instant)>,
-    expected_sequence: u16,
+    /// Packets by sequence number, with their timestamp in ms after the reference
+    packets: HashMap<u16, (RtpPacket, f64)>,
+    expected_sequence: Option<u16>,
+    highest_sequence: Option<u16>,
     max_size: usize,
-    target_delay_ms: u64,
-    created_at: Instant,
+    min_delay_ms: f64,
+    max_delay_ms: f64,
+    depth_ms: f64,
+    /// Arrival and timestamp of the first packet, which playout times count from
+    reference: Option<(Instant, u32)>,
+    /// Least and latest transit, arrival less timestamp, ms
+    min_transit_ms: f64,
+    last_transit_ms: Option<f64>,
+    stats: JitterBufferStats,
 }
 
 impl JitterBuffer {
-    pub fn new(max_size: usize, target_delay_ms: u64) -> Self {
+    pub fn new(max_size: usize, min_delay_ms: u32, max_delay_ms: u32) -> Self {
+        let min_delay_ms = min_delay_ms as f64;
+        let max_delay_ms = (max_delay_ms as f64).max(min_delay_ms);
         Self {
             packets: HashMap::new(),
-            expected_sequence: 0,
-            max_size,
-            target_delay_ms,
-            created_at: Instant::now(),
+            expected_sequence: None,
+            highest_sequence: None,
+            max_size: max_size.max(1),
+            min_delay_ms,
+            max_delay_ms,
+            depth_ms: min_delay_ms,
+            reference: None,
+            min_transit_ms: f64::INFINITY,
+            last_transit_ms: None,
+            stats: JitterBufferStats {
+                current_depth_ms: min_delay_ms,
+                min_depth_ms: min_delay_ms,
+                max_depth_ms: min_delay_ms,
+                ..Default::default()
+            },
         }
     }
 
+    /// Buffer a packet arriving now; returns the packets due to play, in order
     pub fn add_packet(&mut self, packet: RtpPacket) -> Vec<RtpPacket> {
-        let arrival_time = Instant::now();
+        self.add_packet_at(packet, Instant::now())
+    }
+
+    pub fn add_packet_at(&mut self, packet: RtpPacket, now: Instant) -> Vec<RtpPacket> {
         let sequence = packet.sequence_number;
+        let (reference_at, reference_ts) = *self.reference.get_or_insert((now, packet.timestamp));
+        let expected = *self.expected_sequence.get_or_insert(sequence);
 
-        // Add packet to buffer
-        self.packets.insert(sequence, (packet, arrival_time));
+        if (sequence.wrapping_sub(expected) as i16) < 0 {
+            self.stats.late_discards += 1;
+            return self.release(now);
+        }
+        if self.packets.contains_key(&sequence) {
+            self.stats.duplicate_discards += 1;
+            return self.release(now);
+        }
+        match self.highest_sequence {
+            Some(highest) if (sequence.wrapping_sub(highest) as i16) < 0 => self.stats.reordered_packets += 1,
+            _ => self.highest_sequence = Some(sequence),
+        }
 
-        // Remove packets that are too old
-        let max_age = Duration::from_millis(self.target_delay_ms * 2);
-        self.packets.retain(|_, (_, time)| arrival_time.duration_since(*time) < max_age);
+        // Interarrival jitter as RFC 3550 estimates it
+        let arrival_ms = now.duration_since(reference_at).as_secs_f64() * 1000.0;
+        let media_ms = packet.timestamp.wrapping_sub(reference_ts) as i32 as f64 / RTP_UNITS_PER_MS;
+        let transit_ms = arrival_ms - media_ms;
+        if let Some(last) = self.last_transit_ms {
+            self.stats.jitter_ms += ((transit_ms - last).abs() - self.stats.jitter_ms) / 16.0;
+        }
+        self.last_transit_ms = Some(transit_ms);
+        self.min_transit_ms = self.min_transit_ms.min(transit_ms);
+        self.adapt();
 
-        // Limit buffer size
+        self.packets.insert(sequence, (packet, media_ms));
         if self.packets.len() > self.max_size {
-            // Remove oldest packets
-            let mut sorted: Vec<_> = self.packets.iter().map(|(k, v)| (*k, v.1)).collect();
-            sorted.sort_by_key(|(_, time)| *time);
-            let to_remove = sorted.len() - self.max_size;
-            let keys_to_remove: Vec<u16> = sorted.iter().take(to_remove).map(|(seq, _)| *seq).collect();
-            for seq in keys_to_remove {
-                self.packets.remove(&seq);
+            // Drop the packet next in line, so the rest keep their playout times
+            if let Some(oldest) = self.packets.keys().copied().min_by_key(|seq| seq.wrapping_sub(expected)) {
+                self.packets.remove(&oldest);
+                self.expected_sequence = Some(oldest.wrapping_add(1));
+                self.stats.overflow_discards += 1;
             }
         }
+        self.release(now)
+    }
 
-        // Extract ready packets
-        self.extract_ready_packets()
+    /// Move the playout delay towards a multiple of the jitter
+    fn adapt(&mut self) {
+        let target = (self.stats.jitter_ms * JITTER_DEPTH_FACTOR).clamp(self.min_delay_ms, self.max_delay_ms);
+        self.depth_ms = if target > self.depth_ms {
+            target
+        } else {
+            (self.depth_ms - DEPTH_SHRINK_STEP_MS).max(target)
+        };
+        self.stats.current_depth_ms = self.depth_ms;
+        self.stats.min_depth_ms = self.stats.min_depth_ms.min(self.depth_ms);
+        self.stats.max_depth_ms = self.stats.max_depth_ms.max(self.depth_ms);
     }
 
-    fn extract_ready_packets(&mut self) -> Vec<RtpPacket> {
+    /// Packets whose playout time has come by `now`, skipping missing ones
+    /// once the packet after them is due
+    fn release(&mut self, now: Instant) -> Vec<RtpPacket> {
         let mut ready_packets = Vec::new();
-        let target_delay = Duration::from_millis(self.target_delay_ms);
-
-        // Find packets that are ready to be played
-        loop {
-            if let Some((packet, arrival_time)) = self.packets.remove(&self.expected_sequence) {
-                // Check if packet has been in buffer long enough
-                if arrival_time.elapsed() >= target_delay {
-                    ready_packets.push(packet);
-                    self.expected_sequence = self.expected_sequence.wrapping_add(1);
-                } else {
-                    // Put packet back, not ready yet
-                    self.packets.insert(self.expected_sequence, (packet, arrival_time));
-                    break;
+        let Some((reference_at, _)) = self.reference else {
+            return ready_packets;
+        };
+        // Latest timestamp due to play, ms after the reference
+        let horizon_ms =
+            now.duration_since(reference_at).as_secs_f64() * 1000.0 - self.min_transit_ms - self.depth_ms;
+
+        while let Some(expected) = self.expected_sequence {
+            match self.packets.get(&expected).map(|(_, media_ms)| *media_ms <= horizon_ms) {
+                Some(true) => {
+                    if let Some((packet, _)) = self.packets.remove(&expected) {
+                        ready_packets.push(packet);
+                    }
+                    self.expected_sequence = Some(expected.wrapping_add(1));
+                }
+                Some(false) => break,
+                None => {
+                    let next = self
+                        .packets
+                        .iter()
+                        .map(|(seq, (_, media_ms))| (*seq, *media_ms))
+                        .min_by_key(|(seq, _)| seq.wrapping_sub(expected));
+                    match next {
+                        Some((next, media_ms)) if media_ms <= horizon_ms => {
+                            self.stats.lost_packets += u64::from(next.wrapping_sub(expected));
+                            self.expected_sequence = Some(next);
+                        }
+                        _ => break,
+                    }
                 }
-            } else {
-                break;
             }
         }
-
+        self.stats.buffered_packets = self.packets.len();
         ready_packets
     }
 
     pub fn get_buffer_size(&self) -> usize {
         self.packets.len()
     }
+
+    pub fn stats(&self) -> JitterBufferStats {
+        self.stats.clone()
+    }
+}
+
+/// What happens to the audio on its way out to one leg
+#[derive(Debug)]
+enum PlayoutStage {
+    /// Kept continuous for a leg that plays whatever arrives
+    Repair(MediaRepair),
+    /// Silence suppressed for a peer doing comfort noise
+    Suppress(SilenceSuppressor),
+}
+
+/// Relay, direction and counts of a leg's playout
+type LegPlayoutTarget = (String, RelayDirection, MediaRepairStats);
+
+#[derive(Debug)]
+struct LegPlayout {
+    relay_id: String,
+    direction: RelayDirection,
+    stage: PlayoutStage,
+}
+
+impl LegPlayout {
+    fn stats(&self) -> MediaRepairStats {
+        match &self.stage {
+            PlayoutStage::Repair(repair) => repair.stats(),
+            PlayoutStage::Suppress(suppressor) => suppressor.stats(),
+        }
+    }
+
+    /// Whether the audio goes to leg A
+    fn toward_a(&self) -> bool {
+        matches!(self.direction, RelayDirection::BToA)
+    }
 }
 
 /// Media relay service
 pub struct MediaRelayService {
     relay_sessions: Arc<DashMap<String, MediaRelaySession>>,
     jitter_buffers: Arc<DashMap<String, RwLock<JitterBuffer>>>,
+    playout: Arc<DashMap<String, LegPlayout>>,
     rtp_handler: Arc<RwLock<RtpHandler>>,
     transcoding_service: Arc<RwLock<TranscodingService>>,
     processing_config: MediaProcessingConfig,
@@ -274,6 +540,7 @@ pub struct MediaRelayService {
     event_rx: Option<mpsc::UnboundedReceiver<MediaRelayEvent>>,
     rtp_event_rx: Option<mpsc::UnboundedReceiver<RtpEvent>>,
     transcoding_event_rx: Option<mpsc::UnboundedReceiver<TranscodingEvent>>,
+    call_tracer: Option<Arc<CallTracer>>,
     is_running: bool,
 }
 
@@ -288,6 +555,7 @@ impl MediaRelayService {
         Self {
             relay_sessions: Arc::new(DashMap::new()),
             jitter_buffers: Arc::new(DashMap::new()),
+            playout: Arc::new(DashMap::new()),
             rtp_handler,
             transcoding_service,
             processing_config,
@@ -295,10 +563,16 @@ impl MediaRelayService {
             event_rx: Some(event_rx),
             rtp_event_rx: None,
             transcoding_event_rx: None,
+            call_tracer: None,
             is_running: false,
         }
     }
 
+    /// Log relayed media of traced calls into their per-call trace
+    pub fn set_call_tracer(&mut self, tracer: Arc<CallTracer>) {
+        self.call_tracer = Some(tracer);
+    }
+
     pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<MediaRelayEvent>> {
         self.event_rx.take()
     }
@@ -318,18 +592,24 @@ impl MediaRelayService {
         if let Some(rtp_rx) = self.rtp_event_rx.take() {
             let relay_sessions_rtp = Arc::clone(&self.relay_sessions);
             let jitter_buffers_rtp = Arc::clone(&self.jitter_buffers);
+            let playout_rtp = Arc::clone(&self.playout);
             let event_tx_rtp = self.event_tx.clone();
             let transcoding_service_rtp = Arc::clone(&self.transcoding_service);
             let processing_config_rtp = self.processing_config.clone();
+            let tracer_rtp = self.call_tracer.clone();
+            let rtp_handler_rtp = Arc::clone(&self.rtp_handler);
 
             tokio::spawn(async move {
                 Self::process_rtp_events(
                     rtp_rx,
+                    rtp_handler_rtp,
                     relay_sessions_rtp,
                     jitter_buffers_rtp,
+                    playout_rtp,
                     event_tx_rtp,
                     transcoding_service_rtp,
                     processing_config_rtp,
+                    tracer_rtp,
                 ).await;
             });
         }
@@ -348,6 +628,23 @@ impl MediaRelayService {
             });
         }
 
+        // Play comfort noise and concealment into legs whose audio has paused
+        let relay_sessions_playout = Arc::clone(&self.relay_sessions);
+        let playout = Arc::clone(&self.playout);
+        let rtp_handler_playout = Arc::clone(&self.rtp_handler);
+        let transcoding_service_playout = Arc::clone(&self.transcoding_service);
+        let event_tx_playout = self.event_tx.clone();
+
+        tokio::spawn(async move {
+            Self::playout_loop(
+                relay_sessions_playout,
+                playout,
+                rtp_handler_playout,
+                transcoding_service_playout,
+                event_tx_playout,
+            ).await;
+        });
+
         // Start statistics monitoring
         let relay_sessions_stats = Arc::clone(&self.relay_sessions);
         let event_tx_stats = self.event_tx.clone();
@@ -371,11 +668,14 @@ impl MediaRelayService {
 
     async fn process_rtp_events(
         mut rtp_rx: mpsc::UnboundedReceiver<RtpEvent>,
+        rtp_handler: Arc<RwLock<RtpHandler>>,
         relay_sessions: Arc<DashMap<String, MediaRelaySession>>,
         jitter_buffers: Arc<DashMap<String, RwLock<JitterBuffer>>>,
+        playout: Arc<DashMap<String, LegPlayout>>,
         event_tx: mpsc::UnboundedSender<MediaRelayEvent>,
         transcoding_service: Arc<RwLock<TranscodingService>>,
         processing_config: MediaProcessingConfig,
+        tracer: Option<Arc<CallTracer>>,
     ) {
         while let Some(event) = rtp_rx.recv().await {
             match event {
@@ -383,15 +683,28 @@ impl MediaRelayService {
                     if let Err(e) = Self::handle_rtp_packet(
                         session_id,
                         packet,
+                        &rtp_handler,
                         &relay_sessions,
                         &jitter_buffers,
+                        &playout,
                         &event_tx,
                         &transcoding_service,
                         &processing_config,
+                        tracer.as_deref(),
                     ).await {
                         error!("Failed to handle RTP packet: {}", e);
                     }
                 }
+                RtpEvent::StreamStatistics { session_id, stats } => {
+                    let quality = RtcpLegStats::from(&stats);
+                    for mut relay in relay_sessions.iter_mut() {
+                        let leg_a = relay.leg_a_session_id == session_id;
+                        if leg_a || relay.leg_b_session_id == session_id {
+                            relay.stats.update_leg_quality(leg_a, quality);
+                            break;
+                        }
+                    }
+                }
                 _ => {
                     trace!("Unhandled RTP event: {:?}", event);
                 }
@@ -402,11 +715,14 @@ impl MediaRelayService {
     async fn handle_rtp_packet(
         session_id: String,
         packet: RtpPacket,
+        rtp_handler: &Arc<RwLock<RtpHandler>>,
         relay_sessions: &Arc<DashMap<String, MediaRelaySession>>,
         jitter_buffers: &Arc<DashMap<String, RwLock<JitterBuffer>>>,
+        playout: &DashMap<String, LegPlayout>,
         event_tx: &mpsc::UnboundedSender<MediaRelayEvent>,
         transcoding_service: &Arc<RwLock<TranscodingService>>,
         processing_config: &MediaProcessingConfig,
+        tracer: Option<&CallTracer>,
     ) -> Result<()> {
         // Find relay session that owns this RTP session
         let mut relay_session: Option<MediaRelaySession> = None;
@@ -430,6 +746,8 @@ impl MediaRelayService {
             _ => return Ok(()), // No relay session found
         };
 
+        let (packet_seq, packet_ts, packet_len) = (packet.sequence_number, packet.timestamp, packet.payload.len());
+
         // Apply media processing if enabled
         let processed_packet = Self::apply_media_processing(
             packet,
@@ -446,25 +764,35 @@ impl MediaRelayService {
             if !jitter_buffers.contains_key(&jitter_buffer_key) {
                 let buffer = JitterBuffer::new(
                     processing_config.jitter_buffer_size as usize,
-                    20, // 20ms target delay
+                    processing_config.jitter_buffer_min_ms,
+                    processing_config.jitter_buffer_max_ms,
                 );
                 jitter_buffers.insert(jitter_buffer_key.clone(), RwLock::new(buffer));
             }
 
-            if let Some(buffer_lock) = jitter_buffers.get(&jitter_buffer_key) {
-                let mut buffer = buffer_lock.write().await;
-                buffer.add_packet(processed_packet)
-            } else {
-                vec![processed_packet]
+            let (ready, buffer_stats) = match jitter_buffers.get(&jitter_buffer_key) {
+                Some(buffer_lock) => {
+                    let mut buffer = buffer_lock.write().await;
+                    (buffer.add_packet(processed_packet), Some(buffer.stats()))
+                }
+                None => (vec![processed_packet], None),
+            };
+            if let (Some(buffer_stats), Some(mut relay)) = (buffer_stats, relay_sessions.get_mut(&relay_session.id)) {
+                relay.stats.update_jitter_buffer(matches!(direction, RelayDirection::AToB), buffer_stats);
             }
+            ready
         } else {
             vec![processed_packet]
         };
+        let ready_packets =
+            Self::play_out(ready_packets, &relay_session, &direction, playout, processing_config, relay_sessions);
 
         // Relay packets
+        let released = ready_packets.len();
         for packet_to_relay in ready_packets {
             Self::relay_packet(
                 packet_to_relay,
+                rtp_handler,
                 &relay_session,
                 &direction,
                 transcoding_service,
@@ -472,10 +800,60 @@ impl MediaRelayService {
                 event_tx,
             ).await?;
         }
+        if let Some(tracer) = tracer {
+            tracer.record(&relay_session.call_id, TraceSubsystem::Media, || {
+                format!(
+                    "Relay {} {:?}: seq {} ts {} ({} bytes), jitter buffer released {}",
+                    relay_session.id, direction, packet_seq, packet_ts, packet_len, released
+                )
+            });
+        }
 
         Ok(())
     }
 
+    /// Repair or silence-suppress released packets for the leg they go to
+    fn play_out(
+        packets: Vec<RtpPacket>,
+        relay_session: &MediaRelaySession,
+        direction: &RelayDirection,
+        playout: &DashMap<String, LegPlayout>,
+        config: &MediaProcessingConfig,
+        relay_sessions: &DashMap<String, MediaRelaySession>,
+    ) -> Vec<RtpPacket> {
+        let target = match direction {
+            RelayDirection::AToB => &relay_session.leg_b_endpoint,
+            RelayDirection::BToA => &relay_session.leg_a_endpoint,
+        };
+        let suppress = target.comfort_noise && config.voice_activity_detection;
+        let repair = !target.comfort_noise && (config.packet_loss_concealment || config.comfort_noise);
+        if !suppress && !repair {
+            return packets;
+        }
+
+        let mut leg = playout.entry(format!("{}_{:?}", relay_session.id, direction)).or_insert_with(|| LegPlayout {
+            relay_id: relay_session.id.clone(),
+            direction: direction.clone(),
+            stage: if suppress {
+                PlayoutStage::Suppress(SilenceSuppressor::new())
+            } else {
+                PlayoutStage::Repair(MediaRepair::new(config.packet_loss_concealment, config.comfort_noise))
+            },
+        });
+        let played = match &mut leg.stage {
+            PlayoutStage::Repair(repair) => packets.into_iter().flat_map(|packet| repair.receive(packet)).collect(),
+            PlayoutStage::Suppress(suppressor) => {
+                packets.into_iter().filter_map(|packet| suppressor.process(packet)).collect()
+            }
+        };
+        let (toward_a, stats) = (leg.toward_a(), leg.stats());
+        drop(leg);
+        if let Some(mut relay) = relay_sessions.get_mut(&relay_session.id) {
+            relay.stats.update_repair(toward_a, stats);
+        }
+        played
+    }
+
     async fn apply_media_processing(
         mut packet: RtpPacket,
         relay_session: &MediaRelaySession,
@@ -589,6 +967,7 @@ impl MediaRelayService {
 
     async fn relay_packet(
         packet: RtpPacket,
+        rtp_handler: &Arc<RwLock<RtpHandler>>,
         relay_session: &MediaRelaySession,
         direction: &RelayDirection,
         transcoding_service: &Arc<RwLock<TranscodingService>>,
@@ -602,8 +981,8 @@ impl MediaRelayService {
 
         let mut final_packet = packet.clone();
 
-        // Apply transcoding if needed
-        if relay_session.relay_mode == RelayMode::Transcoding {
+        // Apply transcoding if needed; comfort noise is codec independent
+        if relay_session.relay_mode == RelayMode::Transcoding && packet.payload_type != CN_PAYLOAD_TYPE {
             if let Some(transcoding_session_id) = &relay_session.transcoding_session_id {
                 let transcoding = transcoding_service.read().await;
                 match transcoding.transcode_packet(
@@ -644,7 +1023,11 @@ impl MediaRelayService {
             }
         }
 
-        // Forward packet (in real implementation, this would send via RTP handler)
+        // Forward on the other leg, protected there if it negotiated SRTP
+        if let Err(e) = rtp_handler.read().await.forward_packet(target_session_id, &final_packet).await {
+            debug!("Cannot relay RTP packet to {}: {}", target_session_id, e);
+            return Ok(());
+        }
         trace!("Relayed RTP packet: {} -> {} ({} bytes)",
             relay_session.id, target_session_id, final_packet.payload.len());
 
@@ -681,6 +1064,55 @@ impl MediaRelayService {
         }
     }
 
+    /// Each frame interval, play out what repaired legs are owed while no
+    /// audio arrives for them
+    async fn playout_loop(
+        relay_sessions: Arc<DashMap<String, MediaRelaySession>>,
+        playout: Arc<DashMap<String, LegPlayout>>,
+        rtp_handler: Arc<RwLock<RtpHandler>>,
+        transcoding_service: Arc<RwLock<TranscodingService>>,
+        event_tx: mpsc::UnboundedSender<MediaRelayEvent>,
+    ) {
+        let mut ticker = interval(PLAYOUT_TICK);
+
+        loop {
+            ticker.tick().await;
+            playout.retain(|_, leg| relay_sessions.contains_key(&leg.relay_id));
+
+            let due: Vec<(LegPlayoutTarget, RtpPacket)> = playout
+                .iter_mut()
+                .filter_map(|mut leg| {
+                    let packet = match &mut leg.stage {
+                        PlayoutStage::Repair(repair) => repair.tick()?,
+                        PlayoutStage::Suppress(_) => return None,
+                    };
+                    let target = (leg.relay_id.clone(), leg.direction.clone(), leg.stats());
+                    Some((target, packet))
+                })
+                .collect();
+
+            for ((relay_id, direction, stats), packet) in due {
+                let Some(relay_session) = relay_sessions.get_mut(&relay_id).map(|mut relay| {
+                    relay.stats.update_repair(matches!(direction, RelayDirection::BToA), stats);
+                    relay.value().clone()
+                }) else {
+                    continue;
+                };
+                if let Err(e) = Self::relay_packet(
+                    packet,
+                    &rtp_handler,
+                    &relay_session,
+                    &direction,
+                    &transcoding_service,
+                    &relay_sessions,
+                    &event_tx,
+                ).await {
+                    debug!("Cannot play out repaired audio for {}: {}", relay_id, e);
+                }
+            }
+        }
+    }
+
     async fn statistics_monitor_loop(
         relay_sessions: Arc<DashMap<String, MediaRelaySession>>,
         event_tx: mpsc::UnboundedSender<MediaRelayEvent>,
@@ -779,6 +1211,11 @@ impl MediaRelayService {
             None
         };
 
+        let (leg_a_srtp, leg_b_srtp) = {
+            let rtp_handler = self.rtp_handler.read().await;
+            (rtp_handler.srtp_profile(leg_a_session_id), rtp_handler.srtp_profile(leg_b_session_id))
+        };
+
         let session = MediaRelaySession {
             id: session_id.clone(),
             call_id: call_id.to_string(),
@@ -791,6 +1228,8 @@ impl MediaRelayService {
                 codec: leg_a_codec.clone(),
                 ssrc: 0,
                 payload_type: 0,
+                srtp: leg_a_srtp,
+                comfort_noise: false,
                 last_packet_time: None,
             },
             leg_b_endpoint: MediaEndpoint {
@@ -800,6 +1239,8 @@ impl MediaRelayService {
                 codec: leg_b_codec.clone(),
                 ssrc: 0,
                 payload_type: 0,
+                srtp: leg_b_srtp,
+                comfort_noise: false,
                 last_packet_time: None,
             },
             relay_mode: relay_mode.clone(),
@@ -811,6 +1252,12 @@ impl MediaRelayService {
 
         self.relay_sessions.insert(session_id.clone(), session);
 
+        if let Some(ref tracer) = self.call_tracer {
+            tracer.record(call_id, TraceSubsystem::Media, || {
+                format!("Relay session {} between {} and {} ({:?})", session_id, leg_a_session_id, leg_b_session_id, relay_mode)
+            });
+        }
+
         // Emit session started event
         let _ = self.event_tx.send(MediaRelayEvent::SessionStarted {
             session_id: session_id.clone(),
@@ -832,9 +1279,10 @@ impl MediaRelayService {
                 let _ = transcoding.destroy_transcoding_session(transcoding_session_id).await;
             }
 
-            // Clean up jitter buffers
+            // Clean up jitter buffers and playout state
             self.jitter_buffers.remove(&format!("{}_AToB", session_id));
             self.jitter_buffers.remove(&format!("{}_BToA", session_id));
+            self.playout.retain(|_, leg| leg.relay_id != session_id);
 
             // Emit session ended event
             let _ = self.event_tx.send(MediaRelayEvent::SessionEnded {
@@ -842,12 +1290,31 @@ impl MediaRelayService {
                 stats: session.stats.clone(),
             });
 
+            if let Some(ref tracer) = self.call_tracer {
+                tracer.record(&session.call_id, TraceSubsystem::Media, || {
+                    format!("Relay session {} closed: {:?}", session_id, session.stats)
+                });
+            }
             info!("Destroyed media relay session: {}", session_id);
         }
 
         Ok(())
     }
 
+    /// Record whether a leg's peer negotiated comfort noise (see
+    /// [`MediaDescription::has_comfort_noise`]), which decides whether audio
+    /// to it is repaired or silence-suppressed
+    ///
+    /// [`MediaDescription::has_comfort_noise`]: crate::protocols::sdp::MediaDescription::has_comfort_noise
+    pub fn set_comfort_noise(&self, session_id: &str, leg_a: bool, negotiated: bool) {
+        if let Some(mut session) = self.relay_sessions.get_mut(session_id) {
+            let endpoint = if leg_a { &mut session.leg_a_endpoint } else { &mut session.leg_b_endpoint };
+            endpoint.comfort_noise = negotiated;
+        }
+        let toward = if leg_a { RelayDirection::BToA } else { RelayDirection::AToB };
+        self.playout.remove(&format!("{}_{:?}", session_id, toward));
+    }
+
     pub fn get_relay_session(&self, session_id: &str) -> Option<MediaRelaySession> {
         self.relay_sessions.get(session_id).map(|entry| entry.value().clone())
     }
@@ -878,20 +1345,58 @@ mod tests {
     use crate::protocols::rtp::RtpHandler;
     use crate::services::transcoding::{TranscodingService, TranscodingBackend};
 
-    #[tokio::test]
-    async fn test_jitter_buffer() {
-        let mut buffer = JitterBuffer::new(10, 20);
-        
-        let packet1 = RtpPacket::new(0, 100, 8000, 12345);
-        let packet2 = RtpPacket::new(0, 102, 8160, 12345);
-        let packet3 = RtpPacket::new(0, 101, 8080, 12345);
-
-        // Add packets out of order
-        buffer.add_packet(packet1);
-        buffer.add_packet(packet2);
-        let ready = buffer.add_packet(packet3);
+    #[test]
+    fn test_jitter_buffer_reorders_and_accounts() {
+        let mut buffer = JitterBuffer::new(10, 20, 20);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        let sequences = |ready: Vec<RtpPacket>| ready.iter().map(|packet| packet.sequence_number).collect::<Vec<_>>();
+
+        // Nothing plays before the 20 ms delay; 102 overtakes 101
+        assert!(buffer.add_packet_at(RtpPacket::new(0, 100, 8000, 12345), at(0)).is_empty());
+        assert_eq!(sequences(buffer.add_packet_at(RtpPacket::new(0, 102, 8320, 12345), at(40))), vec![100]);
+        assert_eq!(sequences(buffer.add_packet_at(RtpPacket::new(0, 101, 8160, 12345), at(41))), vec![101]);
+
+        // 103 is given up on once 104 is due, and is late when it turns up
+        assert_eq!(sequences(buffer.add_packet_at(RtpPacket::new(0, 104, 8640, 12345), at(80))), vec![102]);
+        assert_eq!(sequences(buffer.add_packet_at(RtpPacket::new(0, 105, 8800, 12345), at(101))), vec![104]);
+        buffer.add_packet_at(RtpPacket::new(0, 103, 8480, 12345), at(102));
+        buffer.add_packet_at(RtpPacket::new(0, 105, 8800, 12345), at(103));
+
+        let stats = buffer.stats();
+        assert_eq!(stats.reordered_packets, 1);
+        assert_eq!(stats.lost_packets, 1);
+        assert_eq!(stats.late_discards, 1);
+        assert_eq!(stats.duplicate_discards, 1);
+        assert_eq!(stats.buffered_packets, 1);
+        assert_eq!((stats.min_depth_ms, stats.max_depth_ms), (20.0, 20.0));
+    }
 
-        assert!(ready.len() > 0);
+    #[test]
+    fn test_jitter_buffer_adapts_depth() {
+        let mut buffer = JitterBuffer::new(50, 20, 120);
+        let start = Instant::now();
+
+        // Packets 20 ms apart arriving alternately 0 and 30 ms late
+        for seq in 0..40u16 {
+            let delay = if seq % 2 == 0 { 0 } else { 30 };
+            let arrival = start + Duration::from_millis(seq as u64 * 20 + delay);
+            buffer.add_packet_at(RtpPacket::new(0, seq, seq as u32 * 160, 1), arrival);
+        }
+        let grown = buffer.stats();
+        assert!(grown.jitter_ms > 20.0);
+        assert!(grown.current_depth_ms > 60.0 && grown.current_depth_ms <= 120.0);
+
+        // Steady arrivals let it shrink back, gradually
+        for seq in 40..400u16 {
+            let arrival = start + Duration::from_millis(seq as u64 * 20 + 30);
+            buffer.add_packet_at(RtpPacket::new(0, seq, seq as u32 * 160, 1), arrival);
+        }
+        let settled = buffer.stats();
+        assert_eq!(settled.current_depth_ms, 20.0);
+        assert_eq!(settled.max_depth_ms, grown.max_depth_ms);
+        assert_eq!(settled.lost_packets + settled.late_discards, 0);
     }
 
     #[tokio::test]
@@ -936,4 +1441,18 @@ mod tests {
         assert_eq!(stats.total_packets(), 150);
         assert_eq!(stats.total_bytes(), 12000);
     }
+
+    #[test]
+    fn test_leg_quality_takes_worse_leg() {
+        let mut stats = MediaRelayStats::new(CodecType::G711u, CodecType::G711a);
+        stats.update_leg_quality(true, RtcpLegStats { loss_pct: 1.0, jitter_ms: 4.0, round_trip_ms: Some(80.0), ..Default::default() });
+        stats.update_leg_quality(
+            false,
+            RtcpLegStats { loss_pct: 0.5, jitter_ms: 2.0, remote_loss_pct: Some(3.0), remote_jitter_ms: Some(9.0), round_trip_ms: Some(40.0) },
+        );
+
+        assert_eq!(stats.packet_loss_rate, 3.0);
+        assert_eq!(stats.average_latency_ms, 40.0);
+        assert_eq!(stats.max_jitter_ms(), 9.0);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,667 @@
+//! Packet loss concealment and comfort noise
+//!
+//! A TDM timeslot plays 8000 samples a second whatever arrives from the SIP
+//! side, so a lost packet or a silence-suppressed gap has to become audio
+//! rather than a click or dead air. [`MediaRepair`] keeps a G.711 stream
+//! continuous: a lost frame is concealed by repeating the last pitch period
+//! before it (waveform substitution as in G.711 Appendix I), attenuated from
+//! the second 10 ms and blended into comfort noise by 60 ms; RFC 3389
+//! comfort noise payloads are consumed and played as noise at the level they
+//! carry, as are gaps a peer leaves without sending any. The first frame
+//! after a repair is cross-faded in. Toward a SIP peer that negotiated
+//! comfort noise, [`SilenceSuppressor`] runs voice activity detection over
+//! the audio and replaces silence with comfort noise updates.
+//!
+//! Spectral information in comfort noise payloads is ignored and noise is
+//! played white, which RFC 3389 permits; updates sent carry the level only.
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::protocols::rtp::RtpPacket;
+use crate::services::prompts::{alaw_to_linear, linear_to_alaw, linear_to_ulaw, ulaw_to_linear};
+
+/// Static payload type of RFC 3389 comfort noise
+pub const CN_PAYLOAD_TYPE: u8 = 13;
+
+const PCMU: u8 = 0;
+const PCMA: u8 = 8;
+const SAMPLES_PER_MS: usize = 8;
+const DEFAULT_FRAME_SAMPLES: usize = 160;
+
+/// Pitch periods searched, 200 Hz down to 66 Hz
+const MIN_PITCH: usize = 40;
+const MAX_PITCH: usize = 120;
+/// Audio the pitch search matches against
+const CORRELATION_WINDOW: usize = 160;
+const HISTORY_SAMPLES: usize = MAX_PITCH + CORRELATION_WINDOW;
+/// Concealment plays at full level for 10 ms, then fades into noise by 60 ms
+const ATTENUATION_START: usize = 10 * SAMPLES_PER_MS;
+const CONCEALMENT_LIMIT: usize = 60 * SAMPLES_PER_MS;
+/// Cross-fade from repaired audio into the next received frame
+const CROSSFADE_SAMPLES: usize = 4 * SAMPLES_PER_MS;
+/// Longest gap filled when the packet after it arrives; longer ones were
+/// already played out by [`MediaRepair::tick`] or start a new stream
+const MAX_FILL_SAMPLES: usize = 120 * SAMPLES_PER_MS;
+/// Playout ticks without a packet before a frame is concealed
+const STALL_TICKS: u32 = 3;
+
+/// Comfort noise level, -dBov, until a peer or the audio says otherwise
+const DEFAULT_NOISE_LEVEL: u8 = 70;
+/// Loudest background taken from the received audio itself, -dBov; a
+/// louder floor is speech
+const MEASURED_NOISE_LIMIT: u8 = 45;
+/// Frames quieter than this are never speech, -dBov
+const SILENCE_LEVEL: f64 = 60.0;
+/// How far above the noise floor a frame must be to count as speech, dB
+const SPEECH_MARGIN_DB: f64 = 9.0;
+/// How fast the noise floor follows louder background noise, dB per frame
+const FLOOR_RISE_DB: f64 = 0.05;
+/// Frames still sent after speech ends, so word endings are not clipped
+const HANGOVER_FRAMES: u32 = 10;
+/// Frames between comfort noise updates while the level holds
+const NOISE_UPDATE_FRAMES: u32 = 50;
+/// Level change that sends a comfort noise update at once, dB
+const NOISE_UPDATE_STEP_DB: u8 = 3;
+
+fn is_g711(payload_type: u8) -> bool {
+    payload_type == PCMU || payload_type == PCMA
+}
+
+fn decode(payload_type: u8, payload: &[u8]) -> Vec<i16> {
+    match payload_type {
+        PCMA => payload.iter().map(|sample| alaw_to_linear(*sample)).collect(),
+        _ => payload.iter().map(|sample| ulaw_to_linear(*sample)).collect(),
+    }
+}
+
+fn encode(payload_type: u8, samples: &[i16]) -> Bytes {
+    match payload_type {
+        PCMA => samples.iter().map(|sample| linear_to_alaw(*sample)).collect(),
+        _ => samples.iter().map(|sample| linear_to_ulaw(*sample)).collect(),
+    }
+}
+
+/// Level of `samples` in -dBov, as comfort noise carries it: 0 is full
+/// scale, 127 silence
+pub fn level_dbov(samples: &[i16]) -> u8 {
+    let power = samples.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / samples.len().max(1) as f64;
+    if power <= 0.0 {
+        return 127;
+    }
+    (-10.0 * (power / 32768f64.powi(2)).log10()).round().clamp(0.0, 127.0) as u8
+}
+
+/// RFC 3389 comfort noise payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComfortNoise {
+    /// Noise level, -dBov
+    pub level: u8,
+    pub reflection_coefficients: Vec<u8>,
+}
+
+impl ComfortNoise {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let (level, coefficients) = payload.split_first()?;
+        Some(Self { level: level & 0x7f, reflection_coefficients: coefficients.to_vec() })
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut payload = vec![self.level & 0x7f];
+        payload.extend_from_slice(&self.reflection_coefficients);
+        payload.into()
+    }
+}
+
+/// White noise at a given level
+#[derive(Debug)]
+struct NoiseSource {
+    state: u32,
+}
+
+impl NoiseSource {
+    fn new() -> Self {
+        Self { state: rand::random::<u32>() | 1 }
+    }
+
+    fn samples(&mut self, level: u8, len: usize) -> Vec<i16> {
+        // Uniform noise of amplitude a has an RMS of a / sqrt(3)
+        let amplitude = 32768.0 * 10f64.powf(-(level as f64) / 20.0) * 3f64.sqrt();
+        (0..len)
+            .map(|_| {
+                self.state ^= self.state << 13;
+                self.state ^= self.state >> 17;
+                self.state ^= self.state << 5;
+                let unit = self.state as f64 / u32::MAX as f64 * 2.0 - 1.0;
+                (unit * amplitude).clamp(i16::MIN as f64, i16::MAX as f64) as i16
+            })
+            .collect()
+    }
+}
+
+/// Background noise level, following quieter frames at once and louder
+/// ones slowly so speech does not raise it
+#[derive(Debug)]
+struct NoiseFloor {
+    level: Option<f64>,
+}
+
+impl NoiseFloor {
+    fn update(&mut self, frame_level: u8) -> f64 {
+        let frame_level = frame_level as f64;
+        let level = match self.level {
+            Some(level) if frame_level < level => level - FLOOR_RISE_DB,
+            _ => frame_level,
+        };
+        self.level = Some(level);
+        level
+    }
+}
+
+/// Waveform substitution from the last pitch period received
+#[derive(Debug, Default)]
+struct Concealer {
+    history: Vec<i16>,
+    pitch: usize,
+    /// Samples concealed in the current loss
+    concealed: usize,
+}
+
+impl Concealer {
+    fn remember(&mut self, samples: &[i16]) {
+        self.history.extend_from_slice(samples);
+        let excess = self.history.len().saturating_sub(HISTORY_SAMPLES);
+        self.history.drain(..excess);
+        self.concealed = 0;
+    }
+
+    /// Lag whose audio best matches the latest window
+    fn estimate_pitch(&self) -> usize {
+        let len = self.history.len();
+        let window = &self.history[len - CORRELATION_WINDOW..];
+        let score = |lag: usize| {
+            let lagged = &self.history[len - CORRELATION_WINDOW - lag..len - lag];
+            let correlation: f64 = window.iter().zip(lagged).map(|(a, b)| *a as f64 * *b as f64).sum();
+            let energy: f64 = lagged.iter().map(|sample| (*sample as f64).powi(2)).sum();
+            if energy > 0.0 { correlation / energy.sqrt() } else { 0.0 }
+        };
+        (MIN_PITCH..=MAX_PITCH)
+            .map(|lag| (lag, score(lag)))
+            .fold((MIN_PITCH, f64::MIN), |best, (lag, score)| if score > best.1 { (lag, score) } else { best })
+            .0
+    }
+
+    /// Continue the last pitch period over `noise`, fading from one to the
+    /// other; None until enough audio has been received to repeat
+    fn conceal(&mut self, noise: &[i16]) -> Option<Vec<i16>> {
+        if self.history.len() < HISTORY_SAMPLES {
+            return None;
+        }
+        if self.concealed == 0 {
+            self.pitch = self.estimate_pitch();
+        }
+        let period = &self.history[self.history.len() - self.pitch..];
+        let samples = noise
+            .iter()
+            .map(|noise| {
+                let gain = if self.concealed < ATTENUATION_START {
+                    1.0
+                } else {
+                    let faded = (self.concealed - ATTENUATION_START) as f64;
+                    (1.0 - faded / (CONCEALMENT_LIMIT - ATTENUATION_START) as f64).max(0.0)
+                };
+                let repeated = period[self.concealed % self.pitch] as f64;
+                self.concealed += 1;
+                (repeated * gain + *noise as f64 * (1.0 - gain)) as i16
+            })
+            .collect();
+        Some(samples)
+    }
+
+    fn exhausted(&self) -> bool {
+        self.concealed >= CONCEALMENT_LIMIT
+    }
+}
+
+/// Frames repaired or suppressed on one stream
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaRepairStats {
+    /// Frames made up for lost packets
+    pub concealed_frames: u64,
+    /// Frames of comfort noise played into silence
+    pub comfort_noise_frames: u64,
+    pub comfort_noise_received: u64,
+    /// Arrived after the time they covered had been played out repaired
+    pub superseded_packets: u64,
+    /// Silent frames not sent to a peer doing comfort noise
+    pub suppressed_frames: u64,
+    pub comfort_noise_sent: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Playout {
+    Voice,
+    Concealing,
+    /// After comfort noise or a gap the peer left on purpose
+    Silence,
+}
+
+/// Keeps a received G.711 stream continuous across loss and silence
+#[derive(Debug)]
+pub struct MediaRepair {
+    conceal: bool,
+    comfort_noise: bool,
+    payload_type: Option<u8>,
+    ssrc: u32,
+    /// Output sequence numbers, contiguous across inserted frames
+    next_sequence: Option<u16>,
+    /// Timestamp of the next sample to play
+    next_timestamp: Option<u32>,
+    last_received_sequence: Option<u16>,
+    frame_samples: usize,
+    state: Playout,
+    idle_ticks: u32,
+    /// Level the peer's comfort noise asked for
+    noise_level: Option<u8>,
+    floor: NoiseFloor,
+    noise: NoiseSource,
+    concealer: Concealer,
+    stats: MediaRepairStats,
+}
+
+impl MediaRepair {
+    pub fn new(conceal: bool, comfort_noise: bool) -> Self {
+        Self {
+            conceal,
+            comfort_noise,
+            payload_type: None,
+            ssrc: 0,
+            next_sequence: None,
+            next_timestamp: None,
+            last_received_sequence: None,
+            frame_samples: DEFAULT_FRAME_SAMPLES,
+            state: Playout::Voice,
+            idle_ticks: 0,
+            noise_level: None,
+            floor: NoiseFloor { level: None },
+            noise: NoiseSource::new(),
+            concealer: Concealer::default(),
+            stats: MediaRepairStats::default(),
+        }
+    }
+
+    /// A packet released by the jitter buffer; returns what to play, with
+    /// any gap before it filled in
+    pub fn receive(&mut self, packet: RtpPacket) -> Vec<RtpPacket> {
+        if packet.payload_type == CN_PAYLOAD_TYPE {
+            self.stats.comfort_noise_received += 1;
+            if let Some(noise) = ComfortNoise::parse(&packet.payload) {
+                self.noise_level = Some(noise.level);
+            }
+            if !self.comfort_noise {
+                return vec![self.pass(packet)];
+            }
+            self.state = Playout::Silence;
+            self.last_received_sequence = Some(packet.sequence_number);
+            return Vec::new();
+        }
+        if !is_g711(packet.payload_type) || packet.payload.is_empty() {
+            return vec![self.pass(packet)];
+        }
+
+        let mut samples = decode(packet.payload_type, &packet.payload);
+        let mut timestamp = packet.timestamp;
+        let mut modified = false;
+        let mut played = Vec::new();
+        self.payload_type = Some(packet.payload_type);
+        self.ssrc = packet.ssrc;
+        self.frame_samples = samples.len();
+        self.idle_ticks = 0;
+
+        if let Some(next) = self.next_timestamp {
+            let offset = timestamp.wrapping_sub(next) as i32;
+            if offset < 0 {
+                let covered = offset.unsigned_abs() as usize;
+                if covered >= samples.len() {
+                    self.stats.superseded_packets += 1;
+                    return played;
+                }
+                samples.drain(..covered);
+                timestamp = next;
+                modified = true;
+            } else if offset > 0 && offset as usize <= MAX_FILL_SAMPLES {
+                let lost = self
+                    .last_received_sequence
+                    .is_some_and(|last| packet.sequence_number != last.wrapping_add(1));
+                played.extend(self.fill(next, offset as usize, lost));
+            }
+        }
+
+        let repaired = self.state != Playout::Voice;
+        if repaired && (self.conceal || self.comfort_noise) {
+            let lost = self.state == Playout::Concealing;
+            let fade = CROSSFADE_SAMPLES.min(samples.len());
+            if let Some(continuation) = self.synthesize(lost, fade, false) {
+                for (i, (sample, repair)) in samples.iter_mut().zip(continuation).enumerate() {
+                    let weight = (i + 1) as f64 / (fade + 1) as f64;
+                    *sample = (*sample as f64 * weight + repair as f64 * (1.0 - weight)) as i16;
+                }
+                modified = true;
+            }
+        }
+
+        self.floor.update(level_dbov(&samples));
+        self.concealer.remember(&samples);
+        self.state = Playout::Voice;
+        self.last_received_sequence = Some(packet.sequence_number);
+        self.next_timestamp = Some(timestamp.wrapping_add(samples.len() as u32));
+
+        let mut out = packet;
+        if modified {
+            out.payload = encode(out.payload_type, &samples);
+        }
+        out.timestamp = timestamp;
+        out.sequence_number = self.take_sequence(out.sequence_number);
+        played.push(out);
+        played
+    }
+
+    /// Called every packet interval; plays comfort noise through silence and
+    /// conceals frames that have not arrived in time
+    pub fn tick(&mut self) -> Option<RtpPacket> {
+        let next = self.next_timestamp?;
+        let lost = match self.state {
+            Playout::Silence if self.comfort_noise => false,
+            Playout::Voice | Playout::Concealing if self.conceal => {
+                self.idle_ticks += 1;
+                if self.idle_ticks < STALL_TICKS {
+                    return None;
+                }
+                if self.concealer.exhausted() {
+                    self.state = Playout::Silence;
+                    if !self.comfort_noise {
+                        return None;
+                    }
+                    false
+                } else {
+                    true
+                }
+            }
+            _ => return None,
+        };
+        self.fill(next, self.frame_samples, lost).pop()
+    }
+
+    pub fn stats(&self) -> MediaRepairStats {
+        self.stats.clone()
+    }
+
+    /// A packet played as it came, numbered into the repaired stream once
+    /// there is one
+    fn pass(&mut self, mut packet: RtpPacket) -> RtpPacket {
+        if self.next_sequence.is_some() {
+            packet.sequence_number = self.take_sequence(packet.sequence_number);
+        }
+        packet
+    }
+
+    /// Comfort noise level: the peer's, else the background of its audio
+    fn fill_level(&self) -> u8 {
+        self.noise_level.unwrap_or_else(|| {
+            self.floor.level.map_or(DEFAULT_NOISE_LEVEL, |floor| (floor.round() as u8).max(MEASURED_NOISE_LIMIT))
+        })
+    }
+
+    /// Frames covering `len` samples from `timestamp`
+    fn fill(&mut self, timestamp: u32, len: usize, lost: bool) -> Vec<RtpPacket> {
+        let Some(payload_type) = self.payload_type else {
+            return Vec::new();
+        };
+        let mut frames = Vec::new();
+        let mut done = 0;
+        while done < len {
+            let frame_len = self.frame_samples.min(len - done);
+            let Some(samples) = self.synthesize(lost, frame_len, true) else {
+                break;
+            };
+            let frame_timestamp = timestamp.wrapping_add(done as u32);
+            let mut packet = RtpPacket::new(payload_type, self.take_sequence(0), frame_timestamp, self.ssrc);
+            packet.payload = encode(payload_type, &samples);
+            frames.push(packet);
+            done += frame_len;
+        }
+        if done > 0 {
+            self.next_timestamp = Some(timestamp.wrapping_add(done as u32));
+        }
+        frames
+    }
+
+    /// Audio standing in for `len` missing samples: the concealed waveform
+    /// for a loss, comfort noise for silence
+    fn synthesize(&mut self, lost: bool, len: usize, count: bool) -> Option<Vec<i16>> {
+        let level = self.fill_level();
+        let noise = if self.comfort_noise { self.noise.samples(level, len) } else { vec![0; len] };
+        if lost && self.conceal && self.state != Playout::Silence {
+            if let Some(samples) = self.concealer.conceal(&noise) {
+                if count {
+                    self.state = Playout::Concealing;
+                    self.stats.concealed_frames += 1;
+                }
+                return Some(samples);
+            }
+        }
+        if !self.comfort_noise {
+            return None;
+        }
+        if count {
+            if !lost {
+                self.state = Playout::Silence;
+            }
+            self.stats.comfort_noise_frames += 1;
+        }
+        Some(noise)
+    }
+
+    fn take_sequence(&mut self, first: u16) -> u16 {
+        let sequence = *self.next_sequence.get_or_insert(first);
+        self.next_sequence = Some(sequence.wrapping_add(1));
+        sequence
+    }
+}
+
+/// Voice activity detection for a peer that negotiated comfort noise: speech
+/// is sent, silence becomes comfort noise updates
+#[derive(Debug)]
+pub struct SilenceSuppressor {
+    floor: NoiseFloor,
+    hangover: u32,
+    speaking: bool,
+    next_sequence: Option<u16>,
+    /// Level of the last update sent and frames since
+    last_update: Option<(u8, u32)>,
+    stats: MediaRepairStats,
+}
+
+impl Default for SilenceSuppressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SilenceSuppressor {
+    pub fn new() -> Self {
+        Self {
+            floor: NoiseFloor { level: None },
+            hangover: 0,
+            speaking: true,
+            next_sequence: None,
+            last_update: None,
+            stats: MediaRepairStats::default(),
+        }
+    }
+
+    /// Whether a frame at `level` is speech, keeping the noise floor current
+    fn is_speech(&mut self, level: u8) -> bool {
+        let floor = self.floor.update(level);
+        if (level as f64) < SILENCE_LEVEL && (level as f64) < floor - SPEECH_MARGIN_DB {
+            self.hangover = HANGOVER_FRAMES;
+            return true;
+        }
+        if self.hangover > 0 {
+            self.hangover -= 1;
+            return true;
+        }
+        false
+    }
+
+    /// The packet to send in place of `packet`, if any
+    pub fn process(&mut self, packet: RtpPacket) -> Option<RtpPacket> {
+        if !is_g711(packet.payload_type) || packet.payload.is_empty() {
+            let mut packet = packet;
+            if let Some(sequence) = self.next_sequence {
+                packet.sequence_number = sequence;
+                self.next_sequence = Some(sequence.wrapping_add(1));
+            }
+            return Some(packet);
+        }
+        let level = level_dbov(&decode(packet.payload_type, &packet.payload));
+        let first = *self.next_sequence.get_or_insert(packet.sequence_number);
+
+        if self.is_speech(level) {
+            let mut out = packet;
+            // The first packet of a talkspurt carries the marker
+            out.marker = !self.speaking;
+            out.sequence_number = first;
+            self.next_sequence = Some(first.wrapping_add(1));
+            self.speaking = true;
+            self.last_update = None;
+            return Some(out);
+        }
+
+        self.speaking = false;
+        let update_due = match self.last_update {
+            None => true,
+            Some((sent, frames)) => {
+                frames + 1 >= NOISE_UPDATE_FRAMES || sent.abs_diff(level) >= NOISE_UPDATE_STEP_DB
+            }
+        };
+        if !update_due {
+            if let Some((_, frames)) = self.last_update.as_mut() {
+                *frames += 1;
+            }
+            self.stats.suppressed_frames += 1;
+            return None;
+        }
+        self.last_update = Some((level, 0));
+        self.stats.comfort_noise_sent += 1;
+        let mut update = RtpPacket::new(CN_PAYLOAD_TYPE, first, packet.timestamp, packet.ssrc);
+        update.payload = ComfortNoise { level, reflection_coefficients: Vec::new() }.encode();
+        self.next_sequence = Some(first.wrapping_add(1));
+        Some(update)
+    }
+
+    pub fn stats(&self) -> MediaRepairStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn tone(start: usize, len: usize, amplitude: f64) -> Vec<i16> {
+        (start..start + len).map(|n| (amplitude * (2.0 * PI * 200.0 * n as f64 / 8000.0).sin()) as i16).collect()
+    }
+
+    fn packet(payload_type: u8, sequence: u16, samples: &[i16]) -> RtpPacket {
+        let mut packet = RtpPacket::new(payload_type, sequence, sequence as u32 * 160, 0x1234);
+        packet.payload = encode(payload_type, samples);
+        packet
+    }
+
+    #[test]
+    fn test_loss_is_concealed_and_silence_filled() {
+        let mut repair = MediaRepair::new(true, true);
+        for seq in 0..4u16 {
+            assert_eq!(repair.receive(packet(PCMU, seq, &tone(seq as usize * 160, 160, 8000.0))).len(), 1);
+        }
+
+        // Packet 4 is lost: packet 5 arrives with a concealed frame before it
+        let played = repair.receive(packet(PCMU, 5, &tone(800, 160, 8000.0)));
+        assert_eq!(played.len(), 2);
+        assert_eq!((played[0].timestamp, played[1].timestamp), (640, 800));
+        assert_eq!((played[0].sequence_number, played[1].sequence_number), (4, 5));
+        // A 200 Hz tone repeats every 40 samples, so the concealed frame
+        // continues it closely
+        let expected = tone(640, 160, 8000.0);
+        let concealed = decode(PCMU, &played[0].payload);
+        let error = concealed.iter().zip(&expected).map(|(a, b)| (*a as f64 - *b as f64).abs()).sum::<f64>() / 160.0;
+        assert!(error < 800.0, "concealment strayed by {}", error);
+        assert_eq!(repair.stats().concealed_frames, 1);
+
+        // Comfort noise is consumed and played out on each tick
+        let mut cn = RtpPacket::new(CN_PAYLOAD_TYPE, 6, 960, 0x1234);
+        cn.payload = ComfortNoise { level: 50, reflection_coefficients: vec![] }.encode();
+        assert!(repair.receive(cn).is_empty());
+        let noise = repair.tick().unwrap();
+        assert_eq!((noise.payload_type, noise.timestamp, noise.sequence_number), (PCMU, 960, 6));
+        assert!((level_dbov(&decode(PCMU, &noise.payload)) as i32 - 50).abs() <= 2);
+        assert_eq!(repair.stats().comfort_noise_frames, 1);
+
+        // Speech resumes after the noise with contiguous numbering
+        let played = repair.receive(packet(PCMU, 7, &tone(1120, 160, 8000.0)));
+        assert_eq!(played.len(), 1);
+        assert_eq!((played[0].timestamp, played[0].sequence_number), (1120, 7));
+    }
+
+    #[test]
+    fn test_stalled_stream_fades_into_noise() {
+        let mut repair = MediaRepair::new(true, true);
+        for seq in 0..4u16 {
+            repair.receive(packet(PCMA, seq, &tone(seq as usize * 160, 160, 8000.0)));
+        }
+        let ticks: Vec<Option<RtpPacket>> = (0..12).map(|_| repair.tick()).collect();
+        assert!(ticks[..2].iter().all(Option::is_none));
+        let frames: Vec<&RtpPacket> = ticks.iter().flatten().collect();
+        assert_eq!(frames.len(), 10);
+        assert!(frames.windows(2).all(|pair| pair[1].timestamp == pair[0].timestamp + 160));
+        let stats = repair.stats();
+        assert_eq!(stats.concealed_frames, 3);
+        assert_eq!(stats.comfort_noise_frames, 7);
+
+        // The late packet for time already played is dropped
+        assert!(repair.receive(packet(PCMA, 4, &tone(640, 160, 8000.0))).is_empty());
+        assert_eq!(repair.stats().superseded_packets, 1);
+    }
+
+    #[test]
+    fn test_silence_suppression_sends_comfort_noise() {
+        let mut suppressor = SilenceSuppressor::new();
+        let quiet = |seq: u16| packet(PCMU, seq, &tone(0, 160, 30.0));
+        let loud = |seq: u16| packet(PCMU, seq, &tone(0, 160, 10000.0));
+
+        // The floor settles on the background before anything is suppressed
+        for seq in 0..5 {
+            suppressor.process(quiet(seq));
+        }
+        let sent: Vec<Option<RtpPacket>> = (5..10).map(|seq| suppressor.process(quiet(seq))).collect();
+        assert!(sent.iter().flatten().all(|packet| packet.payload_type == CN_PAYLOAD_TYPE));
+
+        let speech = suppressor.process(loud(10)).unwrap();
+        assert_eq!(speech.payload_type, PCMU);
+        assert!(speech.marker);
+        let next = suppressor.process(loud(11)).unwrap();
+        assert!(!next.marker);
+        assert_eq!(next.sequence_number, speech.sequence_number + 1);
+
+        // Hangover keeps the word ending before silence is suppressed again
+        let hangover = HANGOVER_FRAMES as usize;
+        let after: Vec<Option<RtpPacket>> =
+            (12..15 + hangover as u16).map(|seq| suppressor.process(quiet(seq))).collect();
+        assert!(after[..hangover].iter().all(|sent| sent.as_ref().is_some_and(|p| p.payload_type == PCMU)));
+        assert_eq!(after[hangover].as_ref().map(|p| p.payload_type), Some(CN_PAYLOAD_TYPE));
+        assert!(after[hangover + 1].is_none());
+        assert!(suppressor.stats().suppressed_frames > 0);
+    }
+}
@@ -0,0 +1,276 @@
+//! Media encryption status and SRTP downgrade handling
+//!
+//! Each call leg is recorded as carrying SRTP, with its crypto suite,
+//! DTLS-SRTP or plain RTP, from the primary audio stream of the SDP that
+//! leg settled on.
+//! A trunk's media policy can require SRTP; a leg with that trunk which
+//! negotiates plain RTP is a downgrade, and either refuses the call or lets
+//! it through under a security alarm raised on the trunk. The alarm clears
+//! once a later leg with the trunk is encrypted again. Encrypted, plain and
+//! downgraded legs are counted per trunk, and the status of both legs is
+//! carried into the call's CDR.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::{SrtpDowngradeAction, TrunkMediaPolicy};
+use crate::protocols::sdp::SessionDescription;
+use crate::protocols::srtp::{self, SrtpProfile};
+use crate::protocols::webrtc;
+use crate::services::alarms::{AlarmManager, AlarmSeverity, AlarmSource, AlarmType};
+use crate::services::b2bua::CallLeg;
+
+/// Management API path listing media security counters
+pub const MEDIA_SECURITY_PATH: &str = "/api/v1/b2bua/media-security";
+
+const ALARM_COMPONENT: &str = "media-security";
+
+/// Protection of one leg's media
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegSecurity {
+    Plaintext,
+    Srtp(SrtpProfile),
+    /// SRTP keyed by a DTLS handshake, as on WebRTC legs
+    DtlsSrtp,
+}
+
+impl LegSecurity {
+    /// Protection of the primary audio stream of `sdp`; `None` when it has
+    /// no usable audio stream
+    pub fn negotiated(sdp: &str) -> Option<Self> {
+        let sdp = SessionDescription::parse(sdp).ok()?;
+        let stream = sdp.media.get(sdp.primary_audio()?)?;
+        if webrtc::is_webrtc(stream) {
+            return Some(LegSecurity::DtlsSrtp);
+        }
+        Some(match srtp::offered_crypto(stream) {
+            Ok(Some(crypto)) => LegSecurity::Srtp(crypto.profile),
+            _ => LegSecurity::Plaintext,
+        })
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, LegSecurity::Srtp(_) | LegSecurity::DtlsSrtp)
+    }
+}
+
+impl fmt::Display for LegSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LegSecurity::Plaintext => f.write_str("plaintext"),
+            LegSecurity::Srtp(profile) => f.write_str(profile.sdp_name()),
+            LegSecurity::DtlsSrtp => f.write_str("DTLS-SRTP"),
+        }
+    }
+}
+
+/// Media protection of both legs of a call, once negotiated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaSecurityStatus {
+    pub leg_a: Option<LegSecurity>,
+    pub leg_b: Option<LegSecurity>,
+}
+
+/// Legs negotiated with one trunk
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MediaSecurityCounter {
+    pub trunk: String,
+    pub encrypted: u64,
+    pub plaintext: u64,
+    /// Plain RTP legs where the trunk's policy requires SRTP
+    pub downgrades: u64,
+    /// Downgrades that refused the call
+    pub rejected: u64,
+}
+
+/// Checks negotiated legs against the trunks' SRTP requirements
+pub struct MediaSecurityMonitor {
+    policies: HashMap<String, TrunkMediaPolicy>,
+    counters: DashMap<String, MediaSecurityCounter>,
+    /// Security alarm raised per trunk
+    alarms: DashMap<String, String>,
+    alarm_manager: OnceLock<Arc<AlarmManager>>,
+}
+
+impl MediaSecurityMonitor {
+    pub fn new(policies: &[TrunkMediaPolicy]) -> Self {
+        Self {
+            policies: policies
+                .iter()
+                .filter(|policy| policy.require_srtp)
+                .map(|policy| (policy.trunk.clone(), policy.clone()))
+                .collect(),
+            counters: DashMap::new(),
+            alarms: DashMap::new(),
+            alarm_manager: OnceLock::new(),
+        }
+    }
+
+    /// Raise downgrade alarms through `alarm_manager`; only the first one set is used
+    pub fn set_alarm_manager(&self, alarm_manager: Arc<AlarmManager>) {
+        let _ = self.alarm_manager.set(alarm_manager);
+    }
+
+    fn counter(&self, trunk: &str) -> dashmap::mapref::one::RefMut<'_, String, MediaSecurityCounter> {
+        self.counters.entry(trunk.to_string()).or_insert_with(|| MediaSecurityCounter {
+            trunk: trunk.to_string(),
+            ..MediaSecurityCounter::default()
+        })
+    }
+
+    /// Count a leg negotiated with `trunk` and apply the trunk's policy.
+    /// Returns the status code to refuse the call with when a downgrade
+    /// rejects it.
+    pub async fn record(&self, trunk: &str, leg: CallLeg, security: LegSecurity) -> Option<u16> {
+        {
+            let mut counter = self.counter(trunk);
+            if security.is_encrypted() {
+                counter.encrypted += 1;
+            } else {
+                counter.plaintext += 1;
+            }
+        }
+        let policy = self.policies.get(trunk)?;
+
+        if security.is_encrypted() {
+            self.clear_alarm(trunk).await;
+            return None;
+        }
+
+        warn!("Leg {:?} with trunk {} negotiated plain RTP where SRTP is required", leg, trunk);
+        let mut counter = self.counter(trunk);
+        counter.downgrades += 1;
+        match policy.srtp_downgrade {
+            SrtpDowngradeAction::Reject => {
+                counter.rejected += 1;
+                Some(policy.reject_code)
+            }
+            SrtpDowngradeAction::Alarm => {
+                drop(counter);
+                self.raise_alarm(trunk).await;
+                None
+            }
+        }
+    }
+
+    async fn raise_alarm(&self, trunk: &str) {
+        let Some(alarms) = self.alarm_manager.get() else {
+            return;
+        };
+        if self.alarms.contains_key(trunk) {
+            return;
+        }
+        let source = AlarmSource { component: ALARM_COMPONENT.to_string(), instance: trunk.to_string(), location: None };
+        let result = alarms
+            .raise_alarm(
+                AlarmSeverity::Major,
+                AlarmType::Security,
+                source,
+                format!("Media with trunk {} negotiated plain RTP where SRTP is required", trunk),
+                None,
+                Some("SRTP downgrade".to_string()),
+                Some("Check the trunk's SRTP support and crypto suites".to_string()),
+            )
+            .await;
+        match result {
+            Ok(alarm_id) => {
+                self.alarms.insert(trunk.to_string(), alarm_id);
+            }
+            Err(e) => warn!("Failed to raise SRTP downgrade alarm for {}: {}", trunk, e),
+        }
+    }
+
+    async fn clear_alarm(&self, trunk: &str) {
+        let (Some(alarms), Some((_, alarm_id))) = (self.alarm_manager.get(), self.alarms.remove(trunk)) else {
+            return;
+        };
+        let _ = alarms.clear_alarm(&alarm_id, ALARM_COMPONENT.to_string()).await;
+    }
+
+    /// Counters by trunk
+    pub fn counters(&self) -> Vec<MediaSecurityCounter> {
+        let mut counters: Vec<MediaSecurityCounter> = self.counters.iter().map(|entry| entry.value().clone()).collect();
+        counters.sort_by(|a, b| a.trunk.cmp(&b.trunk));
+        counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::srtp::CryptoAttribute;
+    use crate::services::alarms::AlarmConfig;
+
+    fn policy(trunk: &str, action: SrtpDowngradeAction) -> TrunkMediaPolicy {
+        TrunkMediaPolicy {
+            trunk: trunk.to_string(),
+            allowed_codecs: Vec::new(),
+            max_bandwidth_kbps: None,
+            ptime_ms: None,
+            reject_code: 488,
+            outbound_codec_priority: Vec::new(),
+            inbound_codec_priority: Vec::new(),
+            media_streams: None,
+            srtp: true,
+            require_srtp: true,
+            srtp_downgrade: action,
+            webrtc: false,
+        }
+    }
+
+    #[test]
+    fn test_negotiated_security() {
+        let crypto = CryptoAttribute::generate(1, SrtpProfile::AesCm128HmacSha1_32);
+        let body = |proto: &str, extra: &str| {
+            format!(
+                "v=0\r\no=- 1 1 IN IP4 192.0.2.1\r\ns=-\r\nc=IN IP4 192.0.2.1\r\nt=0 0\r\nm=audio 4000 {} 0\r\n{}",
+                proto, extra
+            )
+        };
+        let secure = body("RTP/SAVP", &format!("a=crypto:{}\r\n", crypto));
+        assert_eq!(LegSecurity::negotiated(&secure), Some(LegSecurity::Srtp(SrtpProfile::AesCm128HmacSha1_32)));
+        assert_eq!(LegSecurity::negotiated(&body("RTP/AVP", "")), Some(LegSecurity::Plaintext));
+        assert_eq!(LegSecurity::negotiated(&body("UDP/TLS/RTP/SAVPF", "")), Some(LegSecurity::DtlsSrtp));
+        assert_eq!(LegSecurity::negotiated("not sdp"), None);
+        assert_eq!(LegSecurity::Srtp(SrtpProfile::AesCm128HmacSha1_80).to_string(), "AES_CM_128_HMAC_SHA1_80");
+    }
+
+    #[tokio::test]
+    async fn test_downgrades() {
+        let monitor = MediaSecurityMonitor::new(&[
+            policy("strict", SrtpDowngradeAction::Reject),
+            policy("audited", SrtpDowngradeAction::Alarm),
+        ]);
+        let alarms = Arc::new(AlarmManager::new(AlarmConfig::default()));
+        monitor.set_alarm_manager(Arc::clone(&alarms));
+        let srtp = LegSecurity::Srtp(SrtpProfile::AesCm128HmacSha1_80);
+
+        assert_eq!(monitor.record("strict", CallLeg::B, LegSecurity::Plaintext).await, Some(488));
+        assert_eq!(monitor.record("strict", CallLeg::B, srtp).await, None);
+        assert_eq!(monitor.record("open", CallLeg::A, LegSecurity::Plaintext).await, None);
+        assert!(alarms.get_active_alarms().await.is_empty());
+
+        // Repeated downgrades share one alarm, cleared by an encrypted leg
+        for _ in 0..2 {
+            assert_eq!(monitor.record("audited", CallLeg::A, LegSecurity::Plaintext).await, None);
+        }
+        assert_eq!(alarms.get_active_alarms().await.len(), 1);
+        monitor.record("audited", CallLeg::A, srtp).await;
+        assert!(alarms.get_active_alarms().await.is_empty());
+
+        let counters = monitor.counters();
+        assert_eq!(counters.iter().map(|c| c.trunk.as_str()).collect::<Vec<_>>(), ["audited", "open", "strict"]);
+        assert_eq!(
+            counters[2],
+            MediaSecurityCounter { trunk: "strict".to_string(), encrypted: 1, plaintext: 1, downgrades: 1, rejected: 1 }
+        );
+        assert_eq!((counters[0].downgrades, counters[0].rejected), (2, 0));
+    }
+}
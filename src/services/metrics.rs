@@ -0,0 +1,310 @@
+//! Prometheus metrics and the Grafana dashboard built from them
+//!
+//! Every metric the gateway exports is registered here from one table. The
+//! scrape endpoint encodes the registry, and the dashboard generator walks
+//! the same registry for names, help text and labels, so a dashboard
+//! generated by a build charts exactly the metrics that build exports.
+
+use prometheus::core::Collector;
+use prometheus::{Encoder, GaugeVec, IntCounterVec, Opts, Registry, TextEncoder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{Error, Result};
+
+/// Prometheus scrape path
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Grafana datasource input the generated dashboard asks for on import
+const DATASOURCE_INPUT: &str = "DS_PROMETHEUS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+/// Name, help text and labels of one exported metric
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricDescriptor {
+    pub name: String,
+    pub help: String,
+    pub kind: MetricKind,
+    pub labels: Vec<String>,
+}
+
+struct MetricSpec {
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+    labels: &'static [&'static str],
+}
+
+const fn gauge(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> MetricSpec {
+    MetricSpec { name, help, kind: MetricKind::Gauge, labels }
+}
+
+const fn counter(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> MetricSpec {
+    MetricSpec { name, help, kind: MetricKind::Counter, labels }
+}
+
+pub const UP: &str = "redfire_up";
+pub const UPTIME: &str = "redfire_uptime_seconds";
+pub const ACTIVE_CHANNELS: &str = "redfire_active_channels";
+pub const SIP_SESSIONS: &str = "redfire_sip_sessions";
+pub const RTP_SESSIONS: &str = "redfire_rtp_sessions";
+pub const CPU_USAGE: &str = "redfire_cpu_usage_percent";
+pub const MEMORY_USAGE: &str = "redfire_memory_usage_percent";
+pub const LOAD_AVERAGE: &str = "redfire_load_average";
+pub const NETWORK_ERRORS: &str = "redfire_network_errors_total";
+pub const ACTIVE_ALARMS: &str = "redfire_active_alarms";
+pub const CANARY_CALLS: &str = "redfire_canary_calls_total";
+pub const CANARY_FAILURES: &str = "redfire_canary_failures_total";
+pub const CANARY_PDD: &str = "redfire_canary_pdd_milliseconds";
+pub const CANARY_MOS: &str = "redfire_canary_mos";
+
+const METRICS: &[MetricSpec] = &[
+    gauge(UP, "1 while the gateway is running", &[]),
+    gauge(UPTIME, "Time since the gateway started", &[]),
+    gauge(ACTIVE_CHANNELS, "B-channels carrying a call", &[]),
+    gauge(SIP_SESSIONS, "Open SIP sessions", &[]),
+    gauge(RTP_SESSIONS, "Open RTP sessions", &[]),
+    gauge(CPU_USAGE, "Host CPU usage", &[]),
+    gauge(MEMORY_USAGE, "Host memory usage", &[]),
+    gauge(LOAD_AVERAGE, "Host one-minute load average", &[]),
+    counter(NETWORK_ERRORS, "Network interface errors", &["direction"]),
+    gauge(ACTIVE_ALARMS, "Uncleared alarms", &["severity"]),
+    counter(CANARY_CALLS, "Canary test calls placed", &["destination"]),
+    counter(CANARY_FAILURES, "Canary test calls that failed", &["destination"]),
+    gauge(CANARY_PDD, "Mean post-dial delay of passing canary calls", &["destination"]),
+    gauge(CANARY_MOS, "Mean MOS of passing canary calls", &["destination"]),
+];
+
+/// Registry of every metric the gateway exports
+pub struct MetricsRegistry {
+    registry: Registry,
+    gauges: Vec<(&'static str, GaugeVec)>,
+    counters: Vec<(&'static str, IntCounterVec)>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let mut gauges = Vec::new();
+        let mut counters = Vec::new();
+        for spec in METRICS {
+            let opts = Opts::new(spec.name, spec.help);
+            // The table is fixed at build time, so a bad entry is a bug caught by the tests
+            match spec.kind {
+                MetricKind::Gauge => {
+                    let metric = GaugeVec::new(opts, spec.labels).expect("valid metric definition");
+                    registry.register(Box::new(metric.clone())).expect("unique metric name");
+                    gauges.push((spec.name, metric));
+                }
+                MetricKind::Counter => {
+                    let metric = IntCounterVec::new(opts, spec.labels).expect("valid metric definition");
+                    registry.register(Box::new(metric.clone())).expect("unique metric name");
+                    counters.push((spec.name, metric));
+                }
+            }
+        }
+        Self { registry, gauges, counters }
+    }
+
+    /// Set a gauge; unknown names are ignored
+    pub fn set(&self, name: &str, labels: &[&str], value: f64) {
+        if let Some((_, gauge)) = self.gauges.iter().find(|(gauge, _)| *gauge == name) {
+            gauge.with_label_values(labels).set(value);
+        }
+    }
+
+    /// Raise a counter to `total`, for sources that keep their own running
+    /// count; a lower total (the source restarted) leaves it unchanged
+    pub fn set_total(&self, name: &str, labels: &[&str], total: u64) {
+        if let Some((_, counter)) = self.counters.iter().find(|(counter, _)| *counter == name) {
+            let counter = counter.with_label_values(labels);
+            let current = counter.get();
+            if total > current {
+                counter.inc_by(total - current);
+            }
+        }
+    }
+
+    /// Prometheus text exposition of every metric
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| Error::internal(format!("Failed to encode metrics: {}", e)))?;
+        String::from_utf8(buffer).map_err(|e| Error::internal(format!("Metrics are not UTF-8: {}", e)))
+    }
+
+    /// Descriptions of the registered metrics, read back from the registry
+    pub fn descriptors(&self) -> Vec<MetricDescriptor> {
+        let gauges = self.gauges.iter().flat_map(|(_, gauge)| describe(gauge, MetricKind::Gauge));
+        let counters = self.counters.iter().flat_map(|(_, counter)| describe(counter, MetricKind::Counter));
+        let mut descriptors: Vec<MetricDescriptor> = gauges.chain(counters).collect();
+        let order = |name: &str| METRICS.iter().position(|spec| spec.name == name);
+        descriptors.sort_by_key(|descriptor| order(&descriptor.name));
+        descriptors
+    }
+}
+
+impl Default for MetricsRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn describe(collector: &dyn Collector, kind: MetricKind) -> Vec<MetricDescriptor> {
+    collector
+        .desc()
+        .into_iter()
+        .map(|desc| MetricDescriptor {
+            name: desc.fq_name.clone(),
+            help: desc.help.clone(),
+            kind,
+            labels: desc.variable_labels.clone(),
+        })
+        .collect()
+}
+
+/// Panel title from a metric name, e.g. `Canary calls per second` for
+/// `redfire_canary_calls_total`; the unit suffix goes to the axis instead
+fn panel_title(descriptor: &MetricDescriptor) -> String {
+    let name = descriptor.name.trim_start_matches("redfire_");
+    let name = ["_total", "_seconds", "_milliseconds", "_percent"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+    let mut title = name.replace('_', " ");
+    if let Some(first) = title.get(..1).map(str::to_uppercase) {
+        title.replace_range(..1, &first);
+    }
+    match descriptor.kind {
+        MetricKind::Counter => format!("{} per second", title),
+        MetricKind::Gauge => title,
+    }
+}
+
+/// Grafana unit for a metric, from its name's unit suffix
+fn panel_unit(descriptor: &MetricDescriptor) -> &'static str {
+    let name = descriptor.name.as_str();
+    if descriptor.kind == MetricKind::Counter {
+        "ops"
+    } else if name.ends_with("_seconds") {
+        "s"
+    } else if name.ends_with("_milliseconds") {
+        "ms"
+    } else if name.ends_with("_percent") {
+        "percent"
+    } else {
+        "short"
+    }
+}
+
+fn panel_query(descriptor: &MetricDescriptor) -> (String, String) {
+    let mut labels = vec!["instance".to_string()];
+    labels.extend(descriptor.labels.iter().cloned());
+    let selector = format!("{}{{instance=~\"$instance\"}}", descriptor.name);
+    let expr = match descriptor.kind {
+        MetricKind::Counter => format!("sum by ({}) (rate({}[$__rate_interval]))", labels.join(", "), selector),
+        MetricKind::Gauge => selector,
+    };
+    let legend = labels.iter().map(|label| format!("{{{{{}}}}}", label)).collect::<Vec<_>>().join(" ");
+    (expr, legend)
+}
+
+/// Grafana dashboard JSON with one time series panel per metric
+pub fn grafana_dashboard(descriptors: &[MetricDescriptor]) -> Value {
+    let datasource = json!({ "type": "prometheus", "uid": format!("${{{}}}", DATASOURCE_INPUT) });
+    let panels: Vec<Value> = descriptors
+        .iter()
+        .enumerate()
+        .map(|(index, descriptor)| {
+            let (expr, legend) = panel_query(descriptor);
+            json!({
+                "id": index + 1,
+                "type": "timeseries",
+                "title": panel_title(descriptor),
+                "description": descriptor.help,
+                "datasource": datasource,
+                "gridPos": { "h": 8, "w": 12, "x": (index % 2) * 12, "y": (index / 2) * 8 },
+                "fieldConfig": { "defaults": { "unit": panel_unit(descriptor) }, "overrides": [] },
+                "targets": [{ "refId": "A", "datasource": datasource, "expr": expr, "legendFormat": legend }],
+            })
+        })
+        .collect();
+
+    json!({
+        "__inputs": [{
+            "name": DATASOURCE_INPUT,
+            "label": "Prometheus",
+            "type": "datasource",
+            "pluginId": "prometheus",
+            "pluginName": "Prometheus",
+        }],
+        "uid": "redfire-gateway",
+        "title": "Redfire Gateway",
+        "description": format!("Generated by redfire-gateway {}", crate::VERSION),
+        "tags": ["redfire"],
+        "timezone": "browser",
+        "schemaVersion": 38,
+        "version": 1,
+        "refresh": "30s",
+        "time": { "from": "now-6h", "to": "now" },
+        "templating": {
+            "list": [{
+                "name": "instance",
+                "label": "Instance",
+                "type": "query",
+                "datasource": datasource,
+                "query": format!("label_values({}, instance)", UP),
+                "refresh": 1,
+                "includeAll": true,
+                "multi": true,
+            }],
+        },
+        "panels": panels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_renders_and_describes_every_metric() {
+        let metrics = MetricsRegistry::new();
+        metrics.set(UP, &[], 1.0);
+        metrics.set_total(CANARY_CALLS, &["5551000"], 12);
+        metrics.set_total(CANARY_CALLS, &["5551000"], 10);
+        let text = metrics.render().unwrap();
+        assert!(text.contains("# HELP redfire_up 1 while the gateway is running"));
+        assert!(text.contains("redfire_canary_calls_total{destination=\"5551000\"} 12"));
+
+        let descriptors = metrics.descriptors();
+        assert_eq!(descriptors.len(), METRICS.len());
+        assert_eq!(descriptors[0].name, UP);
+        let calls = descriptors.iter().find(|descriptor| descriptor.name == CANARY_CALLS).unwrap();
+        assert_eq!((calls.kind, calls.labels.clone()), (MetricKind::Counter, vec!["destination".to_string()]));
+    }
+
+    #[test]
+    fn test_dashboard_has_a_panel_per_metric() {
+        let descriptors = MetricsRegistry::new().descriptors();
+        let dashboard = grafana_dashboard(&descriptors);
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(panels.len(), descriptors.len());
+
+        let calls = panels.iter().find(|panel| panel["title"] == "Canary calls per second").unwrap();
+        assert_eq!(
+            calls["targets"][0]["expr"],
+            "sum by (instance, destination) (rate(redfire_canary_calls_total{instance=~\"$instance\"}[$__rate_interval]))"
+        );
+        assert_eq!(calls["targets"][0]["legendFormat"], "{{instance}} {{destination}}");
+        let uptime = panels.iter().find(|panel| panel["title"] == "Uptime").unwrap();
+        assert_eq!(uptime["fieldConfig"]["defaults"]["unit"], "s");
+    }
+}
@@ -4,30 +4,166 @@ pub mod performance;
 pub mod alarms;
 pub mod testing;
 pub mod auto_detection;
+#[cfg(feature = "snmp")]
 pub mod snmp;
+#[cfg(feature = "snmp")]
+pub mod snmp_v3;
 pub mod debug;
 pub mod interface_testing;
 pub mod test_automation;
 pub mod timing;
 pub mod b2bua;
+#[cfg(feature = "clustering")]
 pub mod clustering;
+#[cfg(feature = "clustering")]
+pub mod cluster_discovery;
 pub mod transcoding;
 pub mod sip_router;
 pub mod media_relay;
 pub mod cdr;
+pub mod cdr_sequence;
+pub mod tandem;
+pub mod cps_shaping;
+pub mod answer_supervision;
+pub mod media_policy;
+pub mod media_interfaces;
+pub mod certificates;
+pub mod trunk_failure;
+pub mod profiling;
+pub mod routing_hook;
+pub mod survivability;
+pub mod number_lookup;
+pub mod quality_baseline;
+pub mod call_gapping;
+pub mod no_answer;
+pub mod craft;
+pub mod self_test;
+pub mod reroute;
+pub mod takeover;
+pub mod quirks;
+pub mod ports;
+pub mod call_trace;
+pub mod ring_group;
+pub mod codec_negotiation;
+pub mod route_advertisement;
+pub mod shadow_routing;
+pub mod support_tunnel;
+pub mod upgrade;
+pub mod api_schema;
+pub mod canary;
+pub mod early_media;
+pub mod media_inactivity;
+pub mod media_security;
+pub mod prompts;
+pub mod test_numbers;
+pub mod trunk_registration;
+pub mod channel_history;
+pub mod metrics;
+pub mod paging;
+pub mod release_causes;
+pub mod management_api;
+pub mod route_provisioning;
+pub mod capacity;
+pub mod demo;
+pub mod alarm_relays;
+pub mod vq_reporting;
+pub mod precedence;
+pub mod session_timer;
+pub mod reject_announcements;
+pub mod reliable_provisional;
+pub mod transfer;
+pub mod gateway_diff;
+pub mod call_hold;
+pub mod automation;
+pub mod transcoding_latency;
+pub mod fax_relay;
+pub mod correlation;
+pub mod status_page;
+pub mod campaigns;
+pub mod media_repair;
+pub mod audio_tap;
+pub mod heartbeat;
 
 pub use performance::{PerformanceMonitor, PerformanceMetrics, PerformanceEvent, PerformanceAlert};
 pub use alarms::{AlarmManager, Alarm, AlarmSeverity, AlarmType, AlarmEvent, AlarmStatistics};
 pub use testing::{TestingService, LoopbackConfig, BertConfig, TestEvent, LoopbackType, BertPattern};
-pub use auto_detection::{AutoDetectionService, DetectionEvent, SwitchType, MobileNetworkType};
+pub use auto_detection::{
+    AutoDetectionService, DetectionEvent, LineObservation, MobileNetworkType, SpanMismatch, SpanSetting,
+    SpanSettingsSuggestion, SwitchType,
+};
+#[cfg(feature = "snmp")]
 pub use snmp::{SnmpService, SnmpEvent, SnmpTrap, Oid};
+#[cfg(feature = "snmp")]
+pub use snmp_v3::{UsmEngine, EngineState, VacmView};
 pub use debug::{DebugService, DebugEvent, BChannelStatus, BChannelState, DebugMessage};
 pub use interface_testing::{InterfaceTestingService, InterfaceTestType, TestPattern, InterfaceTestEvent, InterfaceTestResult};
 pub use test_automation::{TestAutomationService, TestScenario, AutomationEvent, SessionSummary};
 pub use timing::{TimingService, StratumLevel, ClockSourceType, ClockStatus, TimingEvent, TimingConfig, TdmClockQuality};
-pub use b2bua::{B2buaService, B2buaCall, B2buaCallState, B2buaEvent, CallLeg, MediaRelay, RoutingInfo};
-pub use clustering::{ClusteringService, ClusterNode, DistributedTransaction, ClusteringEvent, AnycastManager};
-pub use transcoding::{TranscodingService, TranscodingSession, TranscodingEvent, CodecType, GpuDevice};
+pub use b2bua::{B2buaService, B2buaCall, B2buaCallState, B2buaEvent, CallLeg, MediaAnchor, MediaRelay, RoutingInfo};
+#[cfg(feature = "clustering")]
+pub use clustering::{ClusteringService, ClusterNode, ClusterMetrics, DistributedTransaction, ClusteringEvent, AnycastManager};
+#[cfg(feature = "clustering")]
+pub use cluster_discovery::{ClusterDiscovery, DiscoveryEvent, PeerAdvertisement};
+pub use transcoding::{TranscodingService, TranscodingSession, TranscodingEvent, CodecType, GpuDevice, OpusParameters};
 pub use sip_router::{SipRouter, RoutingDecision, RoutingContext, RouteTarget, RoutingEvent};
-pub use media_relay::{MediaRelayService, MediaRelaySession, MediaRelayEvent, RelayDirection, JitterBuffer};
-pub use cdr::{CdrService, CallDetailRecord, CdrEvent, BillingInfo, QualityMetrics};
\ No newline at end of file
+pub use media_relay::{MediaRelayService, MediaRelaySession, MediaRelayEvent, RelayDirection, JitterBuffer, JitterBufferStats};
+pub use cdr::{CdrService, CdrStorage, CallDetailRecord, CdrEvent, BillingInfo, QualityMetrics, TimestampQuality};
+pub use cdr_sequence::{CdrSequence, CdrSequencer, CdrSequenceTracker, DeduplicatingCdrStorage, SequenceReport};
+pub use tandem::{TandemService, TandemCall, TandemEvent, TdmChannel};
+pub use cps_shaping::{CpsShaper, AdmissionOutcome, ShapingEvent, TokenBucket};
+pub use answer_supervision::{AnswerSupervisor, AnswerMachineDetector, AmdResult, SupervisionSignal, SupervisionEvent};
+pub use media_interfaces::{MediaInterfaceSelector, ResolvedInterface};
+pub use media_policy::{MediaPolicyEnforcer, PolicyOutcome, SdpRole};
+pub use certificates::{CertificateManager, CertificateEvent, CertificateIssuer, LoadedCertificate};
+pub use trunk_failure::{TrunkFailureHandler, TrunkFailureEvent, FailureDisposition};
+pub use profiling::{ProfilingService, ProfilingEvent, ProfileFormat, HeapStats};
+pub use routing_hook::{RoutingHook, RoutingHookRequest, RoutingHookResponse, RoutingHookRunner, RouteResolution};
+pub use survivability::{SurvivabilityService, SurvivabilityEvent, SurvivabilityMode, RegistrationBinding};
+pub use number_lookup::{NumberLookup, NumberLookupResult, NumberLookupRunner};
+pub use quality_baseline::{QualityBaselineMonitor, QualityEvent, QualityMetric, QualitySample};
+pub use call_gapping::{CallGapController, ActiveGap, GapDecision, GappingEvent};
+pub use no_answer::{NoAnswerPolicy, LegAttempt, LegKind, LegOutcome};
+pub use craft::{CraftConsole, CraftRequest, CraftShell, CraftSnapshot};
+pub use self_test::{SelfTest, SelfTestReport, CheckOutcome, CheckStatus};
+pub use reroute::{RerouteDecider, RerouteCounter};
+pub use takeover::{CallTakeover, TakeoverRequest, TakeoverRecord, TakeoverOutcome};
+pub use quirks::QuirkRegistry;
+pub use ports::{PortDirectory, PortEntry};
+pub use call_trace::{CallTrace, CallTracer, TraceSelector, TraceSubsystem};
+pub use ring_group::RingFork;
+pub use codec_negotiation::{CodecNegotiator, CodecNegotiationCounter, MediaPath, OfferOutcome, TranscodingHeadroom};
+pub use route_advertisement::{RouteAdvertiser, RouteAdvertisement, AdvertisementTarget};
+pub use shadow_routing::{ShadowRouter, ShadowRoutingSummary, RouteDecision, RouteDifference};
+pub use support_tunnel::{SupportTunnel, TunnelRequest, TunnelSession, TunnelAuditRecord};
+pub use upgrade::{CallSnapshot, SnapshotCall, UpgradeRequest};
+pub use api_schema::openapi_document;
+pub use canary::{CanaryAgent, CanaryMetrics, CanaryMonitor, CanaryOutcome, CanaryResult, UdpCanaryAgent};
+pub use early_media::{EarlyMediaCounter, EarlyMediaGate};
+pub use media_inactivity::{MediaCallType, MediaInactivity};
+pub use media_security::{LegSecurity, MediaSecurityCounter, MediaSecurityMonitor, MediaSecurityStatus};
+pub use prompts::{PromptFormat, PromptInfo, PromptLibrary, PromptPackSummary, SelectedPrompt};
+pub use test_numbers::{TestCallRecord, TestNumbers};
+pub use trunk_registration::{TrunkRegistrar, TrunkRegistrationState, TrunkRegistrationStatus};
+pub use channel_history::{ChannelCall, ChannelHistory};
+pub use metrics::{grafana_dashboard, MetricDescriptor, MetricKind, MetricsRegistry};
+pub use paging::{PageRecord, Paging};
+pub use release_causes::{FailureCause, ReleaseCauseQuery, ReleaseCauseReport, ReleaseCauseStats};
+pub use management_api::{ManagementApi, ManagementSource};
+pub use route_provisioning::{ImportReport, ImportedTable, ProvisioningTable, TableDiff};
+pub use capacity::{CapacityQuery, CapacityReport, CapacityStats};
+pub use demo::DemoGateway;
+pub use alarm_relays::{AlarmRelays, RelayLine};
+pub use vq_reporting::{RtcpXrReporter, VqPublisher, VqReport, VqReportKind};
+pub use precedence::{PrecedenceLevel, PrecedencePolicy, Preemption, ResourcePriority};
+pub use session_timer::{CallSessionTimers, LegTimer, Refresher, SessionTimer};
+pub use reject_announcements::RejectReason;
+pub use reliable_provisional::{CallProvisionals, RAck, ReliableReceiver, ReliableSender, TdmIndication};
+pub use transfer::{CallTransfers, ReferTo, TransferKind, TransferOutcome, TransferRecord};
+pub use transcoding_latency::{BackendSelection, TranscodingLatencyMonitor, TranscodingLatencyReport};
+pub use automation::{AutomationAuditRecord, AutomationEngine, AutomationRule, RuleAction, RuleCondition};
+pub use fax_relay::{FaxMode, FaxRelay, FaxTone};
+pub use status_page::{PublicStatus, StatusPage};
+pub use campaigns::{Campaign, CampaignScheduler, CampaignSummary, UdpCampaignDialer};
+pub use media_repair::{MediaRepair, MediaRepairStats, SilenceSuppressor};
+pub use audio_tap::{AudioTap, TapAuditRecord, TapRequest, TapSession, TapSink, TapTarget};
+pub use heartbeat::{Heartbeat, HeartbeatReport, HeartbeatStatus};
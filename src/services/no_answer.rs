@@ -0,0 +1,192 @@
+//! Ring-no-answer timers and forwarding to alternate targets
+//!
+//! A route may bound how long leg B is allowed to ring. When the timer runs
+//! out the unanswered leg is abandoned and the call is offered to the
+//! route's next alternate target, and once those are exhausted to its divert
+//! target (typically voicemail). Forwarded legs carry a Diversion header
+//! naming the originally called party, and every attempt is kept so the CDR
+//! can show each leg.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RoutingRule;
+
+/// Q.850 cause used when no target answered
+pub const CAUSE_NO_ANSWER: u16 = 19;
+
+/// Diversion reason for ring-no-answer forwarding (RFC 5806)
+pub const DIVERSION_REASON_NO_ANSWER: &str = "no-answer";
+
+/// No-answer handling copied from the matching routing rule
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NoAnswerPolicy {
+    pub timeout_secs: u64,
+    pub alternates: Vec<String>,
+    pub divert_to: Option<String>,
+}
+
+/// Role of a leg B attempt within the call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegKind {
+    Primary,
+    Alternate,
+    Divert,
+    /// Branch of a call forked to a ring group
+    Fork,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegOutcome {
+    Answered,
+    NoAnswer,
+    /// Final failure response from the target
+    Failed { status: u16 },
+    Released { cause: Option<u16> },
+    /// Cancelled because another fork branch answered
+    Cancelled,
+}
+
+/// One leg B attempt, as recorded in the CDR
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LegAttempt {
+    pub target: String,
+    pub destination_uri: String,
+    pub kind: LegKind,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub outcome: Option<LegOutcome>,
+}
+
+impl LegAttempt {
+    pub fn new(target: impl Into<String>, destination_uri: impl Into<String>, kind: LegKind) -> Self {
+        Self {
+            target: target.into(),
+            destination_uri: destination_uri.into(),
+            kind,
+            started_at: Utc::now(),
+            ended_at: None,
+            outcome: None,
+        }
+    }
+
+    /// Close the attempt; later outcomes for the same leg are ignored
+    pub fn close(&mut self, outcome: LegOutcome, at: DateTime<Utc>) -> bool {
+        if self.outcome.is_some() {
+            return false;
+        }
+        self.outcome = Some(outcome);
+        self.ended_at = Some(at);
+        true
+    }
+
+    /// Whether the attempt has rung unanswered for longer than `timeout`
+    pub fn timed_out(&self, timeout: Duration, now: DateTime<Utc>) -> bool {
+        self.outcome.is_none() && (now - self.started_at).to_std().is_ok_and(|rung| rung >= timeout)
+    }
+}
+
+/// Where the call goes after an unanswered attempt
+#[derive(Debug, Clone, PartialEq)]
+pub struct NextTarget {
+    pub target: String,
+    pub kind: LegKind,
+}
+
+impl NoAnswerPolicy {
+    pub fn from_rule(rule: &RoutingRule) -> Option<Self> {
+        rule.no_answer_timeout_secs.map(|timeout_secs| Self {
+            timeout_secs,
+            alternates: rule.alternate_targets.clone(),
+            divert_to: rule.no_answer_divert.clone(),
+        })
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    /// Target after `attempts_made` legs (the first being the primary route)
+    pub fn next_target(&self, attempts_made: usize) -> Option<NextTarget> {
+        let index = attempts_made.checked_sub(1)?;
+        if let Some(target) = self.alternates.get(index) {
+            return Some(NextTarget { target: target.clone(), kind: LegKind::Alternate });
+        }
+        if index == self.alternates.len() {
+            return self.divert_to.clone().map(|target| NextTarget { target, kind: LegKind::Divert });
+        }
+        None
+    }
+}
+
+/// Diversion header for a leg forwarded away from `diverting_uri`
+pub fn diversion_header(diverting_uri: &str, counter: usize) -> (String, String) {
+    (
+        "Diversion".to_string(),
+        format!("<{}>;reason={};counter={}", diverting_uri, DIVERSION_REASON_NO_ANSWER, counter),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RouteType;
+
+    fn rule() -> RoutingRule {
+        RoutingRule {
+            id: "sales".to_string(),
+            pattern: "^4000$".to_string(),
+            route_type: RouteType::Direct,
+            target: "pbx-a.example.com".to_string(),
+            priority: 10,
+            translation: None,
+            codec_preference: vec![],
+            no_answer_timeout_secs: Some(20),
+            alternate_targets: vec!["pbx-b.example.com".to_string(), "pbx-c.example.com".to_string()],
+            no_answer_divert: Some("voicemail.example.com".to_string()),
+            failover_actions: Default::default(),
+            early_media: None,
+        }
+    }
+
+    #[test]
+    fn test_targets_follow_alternates_then_divert() {
+        let policy = NoAnswerPolicy::from_rule(&rule()).unwrap();
+        assert_eq!(policy.timeout(), Duration::from_secs(20));
+
+        let targets: Vec<(String, LegKind)> = (1..=4)
+            .filter_map(|made| policy.next_target(made))
+            .map(|next| (next.target, next.kind))
+            .collect();
+        assert_eq!(targets, vec![
+            ("pbx-b.example.com".to_string(), LegKind::Alternate),
+            ("pbx-c.example.com".to_string(), LegKind::Alternate),
+            ("voicemail.example.com".to_string(), LegKind::Divert),
+        ]);
+
+        let mut plain = rule();
+        plain.no_answer_timeout_secs = None;
+        assert!(NoAnswerPolicy::from_rule(&plain).is_none());
+    }
+
+    #[test]
+    fn test_attempt_timer_and_outcome() {
+        let mut attempt = LegAttempt::new("pbx-a.example.com", "sip:4000@pbx-a.example.com", LegKind::Primary);
+        let start = attempt.started_at;
+        assert!(!attempt.timed_out(Duration::from_secs(20), start + chrono::Duration::seconds(19)));
+        assert!(attempt.timed_out(Duration::from_secs(20), start + chrono::Duration::seconds(20)));
+
+        assert!(attempt.close(LegOutcome::NoAnswer, start + chrono::Duration::seconds(20)));
+        assert!(!attempt.close(LegOutcome::Answered, start + chrono::Duration::seconds(21)));
+        assert_eq!(attempt.outcome, Some(LegOutcome::NoAnswer));
+        assert!(!attempt.timed_out(Duration::from_secs(20), start + chrono::Duration::seconds(60)));
+
+        let (name, value) = diversion_header(&attempt.destination_uri, 1);
+        assert_eq!(name, "Diversion");
+        assert_eq!(value, "<sip:4000@pbx-a.example.com>;reason=no-answer;counter=1");
+    }
+}
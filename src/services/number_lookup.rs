@@ -0,0 +1,462 @@
+//! Number portability and caller name lookups
+//!
+//! Before a call is routed the dialed number can be dipped against an
+//! external database: an HTTP service, a SIP redirect server answering with
+//! a 302 whose Contact carries `rn=`, or ENUM NAPTR records. The routing
+//! number (LRN) returned drives prefix-based route selection and is signalled
+//! on leg B as `;rn=...;npdi`. Answers are cached; a lookup that fails or
+//! exceeds its time budget leaves the call routed on the dialed number.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+use crate::config::{NumberLookupConfig, NumberLookupKind};
+use crate::services::survivability::parse_response;
+use crate::{Error, Result};
+
+/// Answer for one number; an empty result means the number is not ported
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NumberLookupResult {
+    #[serde(alias = "lrn")]
+    pub routing_number: Option<String>,
+    #[serde(alias = "cnam")]
+    pub caller_name: Option<String>,
+}
+
+/// External number database
+#[async_trait]
+pub trait NumberLookup: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn lookup(&self, number: &str) -> Result<NumberLookupResult>;
+}
+
+/// Lookup through an HTTP GET returning `{"lrn": ..., "cnam": ...}`
+pub struct HttpNumberLookup {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpNumberLookup {
+    pub fn new(url: impl Into<String>, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| Error::invalid_config(format!("Failed to create number lookup client: {}", e)))?;
+        Ok(Self { url: url.into(), client })
+    }
+}
+
+#[async_trait]
+impl NumberLookup for HttpNumberLookup {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn lookup(&self, number: &str) -> Result<NumberLookupResult> {
+        let response = self.client
+            .get(self.url.replace("{number}", number))
+            .send()
+            .await
+            .map_err(|e| Error::network(format!("Number lookup request failed: {}", e)))?;
+
+        // Unknown numbers are not an error; they simply are not ported
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(NumberLookupResult::default());
+        }
+        if !response.status().is_success() {
+            return Err(Error::routing(format!("Number lookup returned {}", response.status())));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| Error::parse(format!("Invalid number lookup response: {}", e)))
+    }
+}
+
+/// Lookup by sending an INVITE to a redirect server
+pub struct SipRedirectLookup {
+    server: String,
+    timeout: Duration,
+}
+
+impl SipRedirectLookup {
+    pub fn new(server: impl Into<String>, timeout: Duration) -> Self {
+        Self { server: server.into(), timeout }
+    }
+}
+
+#[async_trait]
+impl NumberLookup for SipRedirectLookup {
+    fn name(&self) -> &str {
+        &self.server
+    }
+
+    async fn lookup(&self, number: &str) -> Result<NumberLookupResult> {
+        let network_err = |e: std::io::Error| Error::network(format!("Redirect query to {} failed: {}", self.server, e));
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(network_err)?;
+        socket.connect(&self.server).await.map_err(network_err)?;
+        let local = socket.local_addr().map_err(network_err)?;
+
+        let host = self.server.rsplit_once(':').map(|(host, _)| host).unwrap_or(&self.server);
+        let message = format!(
+            "INVITE sip:{number}@{host};user=phone SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch:x}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:gateway@{local}>;tag={tag:x}\r\n\
+             To: <sip:{number}@{host}>\r\n\
+             Call-ID: {call_id:x}@{local}\r\n\
+             CSeq: 1 INVITE\r\n\
+             Content-Length: 0\r\n\r\n",
+            branch = rand::random::<u64>(),
+            tag = rand::random::<u32>(),
+            call_id = rand::random::<u64>(),
+        );
+        socket.send(message.as_bytes()).await.map_err(network_err)?;
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buf = vec![0u8; 4096];
+        let reply = loop {
+            let len = tokio::time::timeout_at(deadline, socket.recv(&mut buf))
+                .await
+                .map_err(|_| Error::timeout(format!("Redirect server {} timed out", self.server)))?
+                .map_err(network_err)?;
+            let reply = parse_response(&buf[..len])?;
+            if reply.status_code >= 200 {
+                break reply;
+            }
+        };
+
+        match reply.status_code {
+            300..=399 => {
+                let routing_number = reply
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| name.eq_ignore_ascii_case("Contact") || name == "m")
+                    .find_map(|(_, contact)| uri_param(contact, "rn"));
+                Ok(NumberLookupResult { routing_number, caller_name: None })
+            }
+            404 => Ok(NumberLookupResult::default()),
+            status => Err(Error::routing(format!("Redirect server answered {} {}", status, reply.reason))),
+        }
+    }
+}
+
+/// Lookup through ENUM NAPTR records (RFC 6116)
+pub struct EnumNumberLookup {
+    resolver: String,
+    domain: String,
+    timeout: Duration,
+}
+
+impl EnumNumberLookup {
+    pub fn new(resolver: impl Into<String>, domain: impl Into<String>, timeout: Duration) -> Self {
+        Self { resolver: resolver.into(), domain: domain.into(), timeout }
+    }
+}
+
+#[async_trait]
+impl NumberLookup for EnumNumberLookup {
+    fn name(&self) -> &str {
+        &self.resolver
+    }
+
+    async fn lookup(&self, number: &str) -> Result<NumberLookupResult> {
+        let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+        let id = rand::random::<u16>();
+        let query = enum_query(id, &digits, &self.domain);
+
+        let network_err = |e: std::io::Error| Error::network(format!("ENUM query to {} failed: {}", self.resolver, e));
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(network_err)?;
+        socket.connect(&self.resolver).await.map_err(network_err)?;
+        socket.send(&query).await.map_err(network_err)?;
+
+        let mut buf = vec![0u8; 4096];
+        let len = tokio::time::timeout(self.timeout, socket.recv(&mut buf))
+            .await
+            .map_err(|_| Error::timeout(format!("ENUM resolver {} timed out", self.resolver)))?
+            .map_err(network_err)?;
+
+        let Some(uri) = parse_naptr_response(&buf[..len], id, &format!("+{}", digits))? else {
+            return Ok(NumberLookupResult::default());
+        };
+        Ok(NumberLookupResult { routing_number: uri_param(&uri, "rn"), caller_name: None })
+    }
+}
+
+/// DNS query for the NAPTR records of `digits` under `domain`
+fn enum_query(id: u16, digits: &str, domain: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(64);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in digits.chars().rev().map(String::from).chain(domain.split('.').map(str::to_string)) {
+        if label.is_empty() {
+            continue;
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    // QTYPE NAPTR, QCLASS IN
+    query.extend_from_slice(&[0x00, 0x23, 0x00, 0x01]);
+    query
+}
+
+/// URI produced by the preferred `E2U+pstn` NAPTR record applied to `aus`
+fn parse_naptr_response(data: &[u8], id: u16, aus: &str) -> Result<Option<String>> {
+    let truncated = || Error::parse("Truncated DNS response");
+    let read_u16 = |pos: usize| -> Result<u16> {
+        data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or_else(truncated)
+    };
+
+    if data.len() < 12 || read_u16(0)? != id {
+        return Err(Error::parse("DNS response does not match query"));
+    }
+    match read_u16(2)? & 0x000f {
+        0 => {}
+        // NXDOMAIN: no record, number is not ported
+        3 => return Ok(None),
+        rcode => return Err(Error::routing(format!("ENUM query failed with rcode {}", rcode))),
+    }
+    let (questions, answers) = (read_u16(4)?, read_u16(6)?);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(data, pos)? + 4;
+    }
+
+    let mut best: Option<(u16, u16, String)> = None;
+    for _ in 0..answers {
+        pos = skip_name(data, pos)?;
+        let rtype = read_u16(pos)?;
+        let rdlength = read_u16(pos + 8)? as usize;
+        let rdata = pos + 10;
+        pos = rdata + rdlength;
+        if rtype != 35 || pos > data.len() {
+            continue;
+        }
+
+        let (order, preference) = (read_u16(rdata)?, read_u16(rdata + 2)?);
+        let mut field = rdata + 4;
+        let mut strings = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let len = *data.get(field).ok_or_else(truncated)? as usize;
+            let bytes = data.get(field + 1..field + 1 + len).ok_or_else(truncated)?;
+            strings.push(String::from_utf8_lossy(bytes).into_owned());
+            field += 1 + len;
+        }
+        let (services, regexp) = (&strings[1], &strings[2]);
+        if !services.to_ascii_uppercase().starts_with("E2U+PSTN") {
+            continue;
+        }
+        if !matches!(&best, Some((o, p, _)) if (*o, *p) <= (order, preference)) {
+            best = Some((order, preference, regexp.clone()));
+        }
+    }
+
+    best.map(|(_, _, regexp)| apply_naptr_regexp(&regexp, aus)).transpose()
+}
+
+fn skip_name(data: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let len = *data.get(pos).ok_or_else(|| Error::parse("Truncated DNS name"))?;
+        match len {
+            0 => return Ok(pos + 1),
+            // Compression pointer ends the name
+            l if l & 0xc0 == 0xc0 => return Ok(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
+
+/// Apply a NAPTR substitution such as `!^.*$!tel:+1415...;npdi;rn=+1415...!`
+fn apply_naptr_regexp(regexp: &str, aus: &str) -> Result<String> {
+    let delimiter = regexp.chars().next().ok_or_else(|| Error::parse("Empty NAPTR regexp"))?;
+    let parts: Vec<&str> = regexp[delimiter.len_utf8()..].split(delimiter).collect();
+    let [pattern, replacement, ..] = parts.as_slice() else {
+        return Err(Error::parse(format!("Malformed NAPTR regexp: {}", regexp)));
+    };
+    let pattern = Regex::new(pattern).map_err(|e| Error::parse(format!("Invalid NAPTR regexp: {}", e)))?;
+    // NAPTR backreferences are written \1..\9
+    let replacement = Regex::new(r"\\([0-9])").unwrap().replace_all(replacement, "$${$1}");
+    Ok(pattern.replace(aus, replacement.as_ref()).into_owned())
+}
+
+/// Value of a `;name=value` parameter anywhere in a URI or Contact header
+fn uri_param(uri: &str, name: &str) -> Option<String> {
+    uri.split([';', '>', '?'])
+        .skip(1)
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.to_string())
+}
+
+/// Applies a number lookup with a time budget and an answer cache
+pub struct NumberLookupRunner {
+    lookup: Arc<dyn NumberLookup>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    cache_max_entries: usize,
+    cache: DashMap<String, (NumberLookupResult, Instant)>,
+}
+
+impl NumberLookupRunner {
+    pub fn new(lookup: Arc<dyn NumberLookup>, config: &NumberLookupConfig) -> Self {
+        Self {
+            lookup,
+            timeout: Duration::from_millis(config.timeout_ms),
+            cache_ttl: Duration::from_secs(config.cache_ttl_secs),
+            cache_max_entries: config.cache_max_entries,
+            cache: DashMap::new(),
+        }
+    }
+
+    /// Build the lookup described by the configuration, if enabled
+    pub fn from_config(config: &NumberLookupConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let server = || config.server.clone()
+            .ok_or_else(|| Error::invalid_config("Number lookup needs a server"));
+        let lookup: Arc<dyn NumberLookup> = match config.kind {
+            NumberLookupKind::Http => {
+                let url = config.url.as_deref()
+                    .ok_or_else(|| Error::invalid_config("HTTP number lookup needs a url"))?;
+                Arc::new(HttpNumberLookup::new(url, timeout)?)
+            }
+            NumberLookupKind::SipRedirect => Arc::new(SipRedirectLookup::new(server()?, timeout)),
+            NumberLookupKind::Enum => Arc::new(EnumNumberLookup::new(server()?, config.enum_domain.clone(), timeout)),
+        };
+
+        Ok(Some(Self::new(lookup, config)))
+    }
+
+    pub async fn lookup(&self, number: &str) -> Result<NumberLookupResult> {
+        if let Some(entry) = self.cache.get(number) {
+            if entry.1 > Instant::now() {
+                return Ok(entry.0.clone());
+            }
+        }
+
+        let result = match tokio::time::timeout(self.timeout, self.lookup.lookup(number)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::timeout(format!("Number lookup did not answer within {:?}", self.timeout))),
+        };
+
+        match &result {
+            Ok(answer) => {
+                debug!("Number lookup {} answered for {}: {:?}", self.lookup.name(), number, answer);
+                self.cache_answer(number, answer.clone());
+            }
+            Err(e) => warn!("Number lookup {} failed for {}: {}", self.lookup.name(), number, e),
+        }
+        result
+    }
+
+    fn cache_answer(&self, number: &str, answer: NumberLookupResult) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+        if self.cache.len() >= self.cache_max_entries {
+            let now = Instant::now();
+            self.cache.retain(|_, (_, expires_at)| *expires_at > now);
+            if self.cache.len() >= self.cache_max_entries {
+                return;
+            }
+        }
+        self.cache.insert(number.to_string(), (answer, Instant::now() + self.cache_ttl));
+    }
+
+    pub fn cached_entries(&self) -> usize {
+        self.cache.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    struct CountingLookup {
+        queries: AtomicU32,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl NumberLookup for CountingLookup {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        async fn lookup(&self, number: &str) -> Result<NumberLookupResult> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            Ok(NumberLookupResult {
+                routing_number: number.starts_with("415").then(|| "6505550000".to_string()),
+                caller_name: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runner_caches_and_times_out() {
+        let config = NumberLookupConfig { enabled: true, timeout_ms: 50, ..Default::default() };
+        let lookup = Arc::new(CountingLookup { queries: AtomicU32::new(0), delay: Duration::ZERO });
+        let runner = NumberLookupRunner::new(lookup.clone(), &config);
+
+        let first = runner.lookup("4155550100").await.unwrap();
+        assert_eq!(first.routing_number.as_deref(), Some("6505550000"));
+        assert_eq!(runner.lookup("4155550100").await.unwrap(), first);
+        assert_eq!(lookup.queries.load(Ordering::SeqCst), 1);
+
+        let slow = Arc::new(CountingLookup { queries: AtomicU32::new(0), delay: Duration::from_millis(200) });
+        let runner = NumberLookupRunner::new(slow, &config);
+        let error = runner.lookup("4155550100").await.unwrap_err();
+        assert!(error.is_retryable());
+        assert_eq!(runner.cached_entries(), 0);
+    }
+
+    #[test]
+    fn test_enum_naptr_and_redirect_parsing() {
+        let query = enum_query(0x1234, "14155550100", "e164.arpa");
+        assert_eq!(&query[12..16], b"\x010\x010");
+
+        // Response: header, echoed question, one NAPTR answer pointing at the question
+        let regexp = b"!^.*$!tel:+14155550100;npdi;rn=+16505550000!";
+        let mut rdata = vec![0, 10, 0, 100, 1, b'u', 8];
+        rdata.extend_from_slice(b"E2U+pstn");
+        rdata.push(regexp.len() as u8);
+        rdata.extend_from_slice(regexp);
+        rdata.push(0);
+
+        let mut response = query.clone();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 1;
+        response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x23, 0x00, 0x01, 0, 0, 0x0e, 0x10]);
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(&rdata);
+
+        let uri = parse_naptr_response(&response, 0x1234, "+14155550100").unwrap().unwrap();
+        assert_eq!(uri_param(&uri, "rn").as_deref(), Some("+16505550000"));
+        assert!(parse_naptr_response(&response, 0x4321, "+14155550100").is_err());
+
+        assert_eq!(
+            uri_param("<sip:+14155550100;rn=+16505550000;npdi@lnp.example.com>", "rn").as_deref(),
+            Some("+16505550000")
+        );
+        assert_eq!(uri_param("<sip:+14155550100@lnp.example.com>", "rn"), None);
+    }
+}
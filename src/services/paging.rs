@@ -0,0 +1,413 @@
+//! Overhead paging over multicast RTP
+//!
+//! Calls to a paging number are answered by the gateway, which forwards the
+//! caller's audio to a multicast group that the overhead paging speakers on
+//! the LAN listen on. With zones configured, the caller picks one by pressing
+//! its digit (RFC 4733 telephone-events) at the start of the call; audio is
+//! held back until a zone is chosen or the selection window closes and the
+//! default group is paged. Pages still up after the configured limit are
+//! hung up with a BYE. Active and recent pages are listed through the
+//! management API.
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::{PagingCodec, PagingConfig, PagingZone};
+use crate::protocols::rtp::RtpPacket;
+use crate::protocols::sdp::SessionDescription;
+use crate::protocols::sip::DialogHangup;
+use crate::services::media_interfaces::ResolvedInterface;
+use crate::services::test_numbers::TestNumbers;
+use crate::{Error, Result};
+
+/// Management API path listing active and recent pages
+pub const PAGING_PATH: &str = "/api/v1/paging";
+
+/// Digits of RFC 4733 events 0-11
+const DTMF_DIGITS: &[u8; 12] = b"0123456789*#";
+
+/// One call to a paging number
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PageRecord {
+    pub session_id: String,
+    pub number: String,
+    pub caller: String,
+    /// Zone the caller picked; `None` pages the default group
+    pub zone: Option<String>,
+    /// Multicast group paged, once chosen
+    pub group: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// `None` while the page is up
+    pub ended_at: Option<DateTime<Utc>>,
+    pub packets_forwarded: u64,
+}
+
+struct ActivePage {
+    record: Arc<Mutex<PageRecord>>,
+    task: JoinHandle<()>,
+}
+
+impl PagingCodec {
+    fn payload_type(self) -> u8 {
+        match self {
+            PagingCodec::Pcmu => 0,
+            PagingCodec::Pcma => 8,
+        }
+    }
+
+    fn encoding(self) -> &'static str {
+        match self {
+            PagingCodec::Pcmu => "PCMU",
+            PagingCodec::Pcma => "PCMA",
+        }
+    }
+}
+
+/// Digit of an RFC 4733 telephone-event payload, once its end bit is set
+fn telephone_event_digit(payload: &[u8]) -> Option<char> {
+    let (&event, &flags) = (payload.first()?, payload.get(1)?);
+    let end = flags & 0x80 != 0;
+    DTMF_DIGITS.get(event as usize).filter(|_| end).map(|&digit| digit as char)
+}
+
+/// Answers paging numbers and forwards their audio to multicast groups
+pub struct Paging {
+    config: PagingConfig,
+    dialogs: Arc<dyn DialogHangup>,
+    active: DashMap<String, ActivePage>,
+    recent: Mutex<VecDeque<PageRecord>>,
+}
+
+impl Paging {
+    pub fn new(config: PagingConfig, dialogs: Arc<dyn DialogHangup>) -> Self {
+        Self {
+            config,
+            dialogs,
+            active: DashMap::new(),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn is_paging_number(&self, callee: &str) -> bool {
+        self.config.numbers.iter().any(|number| number == callee)
+    }
+
+    pub fn is_active(&self, session_id: &str) -> bool {
+        self.active.contains_key(session_id)
+    }
+
+    fn zone(&self, digit: char) -> Option<&PagingZone> {
+        self.config.zones.iter().find(|zone| zone.digit == digit)
+    }
+
+    /// Start forwarding a call to a paging number and return the SDP answer.
+    /// Offers without the paging codec are refused.
+    pub async fn answer(
+        self: &Arc<Self>,
+        session_id: &str,
+        number: &str,
+        caller: &str,
+        offer: Option<&str>,
+        interface: Option<&ResolvedInterface>,
+    ) -> Result<String> {
+        if !self.is_paging_number(number) {
+            return Err(Error::routing(format!("{} is not a paging number", number)));
+        }
+        let offer = SessionDescription::parse(offer.ok_or_else(|| Error::not_supported("Paging needs an SDP offer"))?)?;
+        let stream = offer
+            .audio_streams()
+            .find(|stream| !stream.is_rejected())
+            .ok_or_else(|| Error::not_supported("Offer has no audio stream"))?;
+        let codec = self.config.codec;
+        if !stream.rtpmaps().iter().any(|map| map.payload_type == codec.payload_type()) {
+            return Err(Error::not_supported(format!("Paging only answers {}", codec.encoding())));
+        }
+        let events = stream
+            .rtpmaps()
+            .into_iter()
+            .find(|map| map.encoding.eq_ignore_ascii_case("telephone-event") && map.clock_rate == 8000)
+            .map(|map| map.payload_type);
+        let remote = offer.audio_endpoint().ok_or_else(|| Error::not_supported("Offer has no audio address"))?;
+
+        let (bind_ip, advertised_ip) = match interface {
+            Some(interface) => (interface.bind_ip, interface.advertised_ip),
+            None => (IpAddr::from([0, 0, 0, 0]), TestNumbers::local_address_towards(remote).await?),
+        };
+        let rtp = UdpSocket::bind(SocketAddr::new(bind_ip, 0))
+            .await
+            .map_err(|e| Error::network(format!("Paging media socket failed: {}", e)))?;
+        let multicast = self.multicast_socket()?;
+
+        let address_type = if advertised_ip.is_ipv4() { "IP4" } else { "IP6" };
+        let formats = match events {
+            Some(events) => format!("{} {}", codec.payload_type(), events),
+            None => codec.payload_type().to_string(),
+        };
+        let mut answer = format!(
+            "v=0\r\no=redfire {} 1 IN {} {}\r\ns=paging\r\nc=IN {} {}\r\nt=0 0\r\nm=audio {} RTP/AVP {}\r\na=rtpmap:{} {}/8000\r\n",
+            rand::random::<u32>(), address_type, advertised_ip, address_type, advertised_ip,
+            rtp.local_addr()?.port(), formats, codec.payload_type(), codec.encoding()
+        );
+        if let Some(events) = events {
+            answer.push_str(&format!("a=rtpmap:{} telephone-event/8000\r\na=fmtp:{} 0-15\r\n", events, events));
+        }
+        answer.push_str("a=ptime:20\r\na=recvonly\r\n");
+
+        let record = Arc::new(Mutex::new(PageRecord {
+            session_id: session_id.to_string(),
+            number: number.to_string(),
+            caller: caller.to_string(),
+            zone: None,
+            group: None,
+            started_at: Utc::now(),
+            ended_at: None,
+            packets_forwarded: 0,
+        }));
+
+        let this = Arc::clone(self);
+        let task_record = Arc::clone(&record);
+        let task_session = session_id.to_string();
+        // Forwarding starts once the page is listed, so a failure can always retire it
+        let (start_tx, start_rx) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            if start_rx.await.is_err() {
+                return;
+            }
+            if let Err(e) = this.run_page(rtp, multicast, events, &task_record).await {
+                warn!("Page {} failed: {}", task_session, e);
+            } else {
+                info!("Page {} reached its {}s limit", task_session, this.config.max_duration_secs);
+            }
+            if let Err(e) = this.dialogs.hang_up(&task_session).await {
+                warn!("Failed to hang up page {}: {}", task_session, e);
+            }
+            this.finish(&task_session);
+        });
+        self.active.insert(session_id.to_string(), ActivePage { record, task });
+        let _ = start_tx.send(());
+
+        info!("Answered paging number {} for {}", number, caller);
+        Ok(answer)
+    }
+
+    /// The caller hung up
+    pub fn hangup(&self, session_id: &str) -> Option<PageRecord> {
+        let (_, page) = self.active.remove(session_id)?;
+        page.task.abort();
+        Some(self.retire(&page.record))
+    }
+
+    /// Active pages, then ended ones most recent first
+    pub fn pages(&self) -> Vec<PageRecord> {
+        let mut pages: Vec<PageRecord> = self.active.iter().map(|page| page.record.lock().unwrap().clone()).collect();
+        pages.sort_by_key(|page| page.started_at);
+        pages.extend(self.recent.lock().unwrap().iter().cloned());
+        pages
+    }
+
+    fn finish(&self, session_id: &str) {
+        if let Some((_, page)) = self.active.remove(session_id) {
+            self.retire(&page.record);
+        }
+    }
+
+    fn retire(&self, record: &Mutex<PageRecord>) -> PageRecord {
+        let mut record = record.lock().unwrap();
+        record.ended_at = Some(Utc::now());
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_front(record.clone());
+        recent.truncate(self.config.max_recent);
+        record.clone()
+    }
+
+    fn multicast_socket(&self) -> Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        let local = self.config.interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        if let Some(interface) = self.config.interface {
+            socket.set_multicast_if_v4(&interface)?;
+        }
+        socket.set_multicast_ttl_v4(self.config.ttl)?;
+        // Speakers on this host, e.g. a monitoring receiver, hear pages too
+        socket.set_multicast_loop_v4(true)?;
+        socket.bind(&SocketAddr::V4(SocketAddrV4::new(local, 0)).into())?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+
+    fn group_address(group: &str) -> Result<SocketAddr> {
+        group.parse().map_err(|_| Error::invalid_config(format!("Invalid paging group {}", group)))
+    }
+
+    async fn run_page(
+        &self,
+        rtp: UdpSocket,
+        multicast: UdpSocket,
+        events: Option<u8>,
+        record: &Mutex<PageRecord>,
+    ) -> Result<()> {
+        let payload_type = self.config.codec.payload_type();
+        let ends_at = tokio::time::Instant::now() + Duration::from_secs(self.config.max_duration_secs);
+        let select_until = tokio::time::Instant::now() + Duration::from_secs(self.config.zone_select_secs);
+        // Without zones, or without a way to signal digits, page the default group at once
+        let mut group = if self.config.zones.is_empty() || events.is_none() {
+            Some(Self::group_address(&self.config.group)?)
+        } else {
+            None
+        };
+        if group.is_some() {
+            record.lock().unwrap().group = Some(self.config.group.clone());
+        }
+
+        let ssrc: u32 = rand::random();
+        let mut sequence: u16 = rand::random();
+        let timestamp_offset: u32 = rand::random();
+        let mut buf = vec![0u8; 2048];
+
+        loop {
+            let received = tokio::select! {
+                _ = tokio::time::sleep_until(ends_at) => return Ok(()),
+                _ = tokio::time::sleep_until(select_until), if group.is_none() => {
+                    info!("No paging zone picked; paging {}", self.config.group);
+                    group = Some(Self::group_address(&self.config.group)?);
+                    record.lock().unwrap().group = Some(self.config.group.clone());
+                    continue;
+                }
+                received = rtp.recv_from(&mut buf) => received?,
+            };
+            let (len, _) = received;
+            let Ok(packet) = RtpPacket::decode(Bytes::copy_from_slice(&buf[..len])) else {
+                continue;
+            };
+
+            if Some(packet.payload_type) == events {
+                if group.is_none() {
+                    if let Some(zone) = telephone_event_digit(&packet.payload).and_then(|digit| self.zone(digit)) {
+                        info!("Paging zone {} ({})", zone.name, zone.group);
+                        group = Some(Self::group_address(&zone.group)?);
+                        let mut record = record.lock().unwrap();
+                        record.zone = Some(zone.name.clone());
+                        record.group = Some(zone.group.clone());
+                    }
+                }
+                // Digits are never played to the speakers
+                continue;
+            }
+            let Some(destination) = group else {
+                continue;
+            };
+            if packet.payload_type != payload_type {
+                continue;
+            }
+
+            // Re-originated under our own SSRC so receivers see one steady stream
+            let mut forwarded = RtpPacket::new(payload_type, sequence, packet.timestamp.wrapping_add(timestamp_offset), ssrc);
+            forwarded.marker = packet.marker;
+            forwarded.payload = packet.payload;
+            multicast.send_to(&forwarded.encode(), destination).await?;
+            sequence = sequence.wrapping_add(1);
+            record.lock().unwrap().packets_forwarded += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    /// Records the dialogs hung up
+    #[derive(Default)]
+    struct Hangups(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl DialogHangup for Hangups {
+        async fn hang_up(&self, session_id: &str) -> Result<()> {
+            self.0.lock().unwrap().push(session_id.to_string());
+            Ok(())
+        }
+    }
+
+    const OFFER: &str = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+                         m=audio 40000 RTP/AVP 0 101\r\na=rtpmap:101 telephone-event/8000\r\n";
+
+    fn paging(zones: Vec<PagingZone>, max_duration_secs: u64, hangups: Arc<Hangups>) -> Arc<Paging> {
+        let config = PagingConfig {
+            enabled: true,
+            numbers: vec!["5557243".to_string()],
+            group: "239.192.0.1:5004".to_string(),
+            zones,
+            max_duration_secs,
+            ..Default::default()
+        };
+        Arc::new(Paging::new(config, hangups))
+    }
+
+    fn event(digit: u8, end: bool) -> [u8; 4] {
+        [digit, if end { 0x8a } else { 0x0a }, 0x03, 0x20]
+    }
+
+    #[test]
+    fn test_telephone_event_digits() {
+        assert_eq!(telephone_event_digit(&event(3, true)), Some('3'));
+        assert_eq!(telephone_event_digit(&event(10, true)), Some('*'));
+        assert_eq!(telephone_event_digit(&event(3, false)), None);
+        assert_eq!(telephone_event_digit(&event(16, true)), None);
+        assert_eq!(telephone_event_digit(&[3]), None);
+    }
+
+    #[tokio::test]
+    async fn test_answer_offers_paging_codec_and_events() {
+        let zone = PagingZone {
+            digit: '2',
+            name: "Warehouse".to_string(),
+            group: "239.192.0.2:5004".to_string(),
+        };
+        let hangups = Arc::new(Hangups::default());
+        let paging = paging(vec![zone], 300, Arc::clone(&hangups));
+        assert!(paging.is_paging_number("5557243"));
+        assert!(!paging.is_paging_number("5551234"));
+
+        let offer = OFFER;
+        let pcma_only = offer.replace("RTP/AVP 0 101", "RTP/AVP 8 101");
+        assert!(paging.answer("s0", "5557243", "2125550100", Some(&pcma_only), None).await.is_err());
+        assert!(paging.answer("s0", "5551234", "2125550100", Some(offer), None).await.is_err());
+
+        let answer = paging.answer("s1", "5557243", "2125550100", Some(offer), None).await.unwrap();
+        assert!(answer.contains("RTP/AVP 0 101") && answer.contains("a=rtpmap:101 telephone-event/8000"));
+        assert!(answer.contains("a=recvonly"));
+        assert!(paging.is_active("s1"));
+
+        // Waiting for a zone digit, nothing has been paged yet
+        let record = paging.hangup("s1").unwrap();
+        assert_eq!((record.zone, record.group, record.packets_forwarded), (None, None, 0));
+        assert!(record.ended_at.is_some());
+        assert!(!paging.is_active("s1"));
+        assert_eq!(paging.pages().len(), 1);
+        assert!(hangups.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_page_at_duration_limit_is_hung_up() {
+        let hangups = Arc::new(Hangups::default());
+        let paging = paging(Vec::new(), 1, Arc::clone(&hangups));
+        paging.answer("s1", "5557243", "2125550100", Some(OFFER), None).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(*hangups.0.lock().unwrap(), vec!["s1".to_string()]);
+        assert!(!paging.is_active("s1"));
+        assert!(paging.pages()[0].ended_at.is_some());
+    }
+}
@@ -0,0 +1,220 @@
+//! Channel-bank style provisioning view of spans and channels
+//!
+//! Operators attach a name, location, customer and circuit ID to each span
+//! and channel, in the configuration or at run time through the
+//! provisioning API. Alarms, CDRs and diagnostics label ports through this
+//! directory, so "span 3 down" reads "span 3 (MainSt-PBX, circuit ABC123)
+//! down". A channel without its own description inherits its span's.
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{FreeTdmSpan, PortDescription};
+use crate::{Error, Result};
+
+/// Provisioning API path listing every port
+pub const PORTS_PATH: &str = "/api/v1/provisioning/ports";
+
+/// Provisioning API path for the description of `span_id`
+pub fn span_port_path(span_id: u32) -> String {
+    format!("{}/spans/{}", PORTS_PATH, span_id)
+}
+
+/// Provisioning API path for the description of one channel
+pub fn channel_port_path(span_id: u32, channel_id: u8) -> String {
+    format!("{}/spans/{}/channels/{}", PORTS_PATH, span_id, channel_id)
+}
+
+impl PortDescription {
+    pub fn is_empty(&self) -> bool {
+        *self == PortDescription::default()
+    }
+
+    /// Comma-separated summary, e.g. `MainSt-PBX, circuit ABC123`
+    pub fn summary(&self) -> Option<String> {
+        let parts: Vec<String> = [
+            self.name.clone(),
+            self.location.clone(),
+            self.customer.clone(),
+            self.circuit_id.as_ref().map(|circuit| format!("circuit {}", circuit)),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect();
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+
+    /// `port` followed by the summary in parentheses, when there is one
+    pub fn label(&self, port: &str) -> String {
+        match self.summary() {
+            Some(summary) => format!("{} ({})", port, summary),
+            None => port.to_string(),
+        }
+    }
+}
+
+/// One row of the provisioning view
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PortEntry {
+    pub span_id: u32,
+    /// `None` for the span itself
+    pub channel_id: Option<u8>,
+    pub description: PortDescription,
+}
+
+/// Descriptions of the configured spans and channels
+#[derive(Debug, Default)]
+pub struct PortDirectory {
+    ports: DashMap<(u32, Option<u8>), PortDescription>,
+}
+
+impl PortDirectory {
+    pub fn new(spans: &[FreeTdmSpan]) -> Self {
+        let directory = Self::default();
+        for span in spans {
+            directory.add_span(span);
+        }
+        directory
+    }
+
+    /// Add the ports of a span brought into service at run time
+    pub fn add_span(&self, span: &FreeTdmSpan) {
+        self.ports.insert((span.span_id, None), span.description.clone());
+        for channel in &span.channels {
+            self.ports.insert((span.span_id, Some(channel.id)), channel.description.clone());
+        }
+    }
+
+    pub fn remove_span(&self, span_id: u32) {
+        self.ports.retain(|(span, _), _| *span != span_id);
+    }
+
+    pub fn span(&self, span_id: u32) -> Option<PortDescription> {
+        self.ports.get(&(span_id, None)).map(|entry| entry.value().clone())
+    }
+
+    /// The channel's own description, or its span's when it has none
+    pub fn channel(&self, span_id: u32, channel_id: u8) -> Option<PortDescription> {
+        let own = self.ports.get(&(span_id, Some(channel_id)))?.value().clone();
+        if own.is_empty() {
+            self.span(span_id)
+        } else {
+            Some(own)
+        }
+    }
+
+    pub fn set_span(&self, span_id: u32, description: PortDescription) -> Result<()> {
+        self.set((span_id, None), description)
+            .ok_or_else(|| Error::invalid_config(format!("Span {} is not configured", span_id)))
+    }
+
+    pub fn set_channel(&self, span_id: u32, channel_id: u8, description: PortDescription) -> Result<()> {
+        self.set((span_id, Some(channel_id)), description)
+            .ok_or_else(|| Error::invalid_config(format!("Channel {}/{} is not configured", span_id, channel_id)))
+    }
+
+    /// e.g. `span 3 (MainSt-PBX, circuit ABC123)`
+    pub fn span_label(&self, span_id: u32) -> String {
+        let port = format!("span {}", span_id);
+        self.span(span_id).map_or_else(|| port.clone(), |description| description.label(&port))
+    }
+
+    /// e.g. `span 3 channel 5 (MainSt-PBX, circuit ABC123)`
+    pub fn channel_label(&self, span_id: u32, channel_id: u8) -> String {
+        let port = format!("span {} channel {}", span_id, channel_id);
+        self.channel(span_id, channel_id)
+            .map_or_else(|| port.clone(), |description| description.label(&port))
+    }
+
+    /// Every port ordered by span, the span before its channels
+    pub fn entries(&self) -> Vec<PortEntry> {
+        let mut entries: Vec<PortEntry> = self
+            .ports
+            .iter()
+            .map(|entry| PortEntry {
+                span_id: entry.key().0,
+                channel_id: entry.key().1,
+                description: entry.value().clone(),
+            })
+            .collect();
+        entries.sort_by_key(|entry| (entry.span_id, entry.channel_id));
+        entries
+    }
+
+    fn set(&self, key: (u32, Option<u8>), description: PortDescription) -> Option<()> {
+        let mut entry = self.ports.get_mut(&key)?;
+        *entry = description;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelType, FreeTdmChannel, Layer1Type, SignalingType};
+
+    fn span(span_id: u32, description: PortDescription) -> FreeTdmSpan {
+        FreeTdmSpan {
+            span_id,
+            name: format!("span{}", span_id),
+            trunk_type: Layer1Type::T1,
+            d_channel: 24,
+            channels: (1..=23)
+                .map(|id| FreeTdmChannel {
+                    id,
+                    channel_type: ChannelType::BChannel,
+                    enabled: true,
+                    signaling: SignalingType::Pri,
+                    description: Default::default(),
+                })
+                .collect(),
+            backend: "freetdm".to_string(),
+            description,
+            regulatory_pack: None,
+        }
+    }
+
+    #[test]
+    fn test_labels_include_configured_description() {
+        let directory = PortDirectory::new(&[
+            span(1, PortDescription::default()),
+            span(3, PortDescription {
+                name: Some("MainSt-PBX".to_string()),
+                circuit_id: Some("ABC123".to_string()),
+                ..Default::default()
+            }),
+        ]);
+
+        assert_eq!(directory.span_label(3), "span 3 (MainSt-PBX, circuit ABC123)");
+        assert_eq!(directory.span_label(1), "span 1");
+        assert_eq!(directory.span_label(9), "span 9");
+        // Channels inherit the span's description
+        assert_eq!(directory.channel_label(3, 5), "span 3 channel 5 (MainSt-PBX, circuit ABC123)");
+    }
+
+    #[test]
+    fn test_provisioning_updates_ports() {
+        let directory = PortDirectory::new(&[span(2, PortDescription::default())]);
+        directory
+            .set_channel(2, 7, PortDescription {
+                customer: Some("Acme Dental".to_string()),
+                location: Some("Rack 4".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(directory.channel_label(2, 7), "span 2 channel 7 (Rack 4, Acme Dental)");
+        assert_eq!(directory.channel_label(2, 8), "span 2 channel 8");
+
+        assert!(directory.set_span(5, PortDescription::default()).is_err());
+        assert!(directory.set_channel(2, 30, PortDescription::default()).is_err());
+
+        let entries = directory.entries();
+        assert_eq!(entries.len(), 24);
+        assert_eq!(entries[0].channel_id, None);
+        assert_eq!(entries[7].channel_id, Some(7));
+        assert_eq!(span_port_path(2), "/api/v1/provisioning/ports/spans/2");
+        assert_eq!(channel_port_path(2, 7), "/api/v1/provisioning/ports/spans/2/channels/7");
+    }
+}
@@ -0,0 +1,307 @@
+//! Priority and precedence call handling (MLPP)
+//!
+//! Callers mark priority calls with the SIP Resource-Priority header (RFC
+//! 4412) as `namespace.value`; on TDM the same ranking travels as the ISDN
+//! MLPP precedence level (Q.955.3). Both map onto the five MLPP levels,
+//! flash override down to routine, and calls without a marking are routine.
+//!
+//! When a priority call finds the gateway at capacity, the lowest call of
+//! lower precedence is preempted to make room for it. The preempted call is
+//! released with Q.850 cause 9 when the new call takes over its trunk and
+//! cause 8 otherwise, and its SIP legs carry the RFC 4411 preemption reason.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PrecedenceConfig;
+
+pub const RESOURCE_PRIORITY_HEADER: &str = "Resource-Priority";
+pub const ACCEPT_RESOURCE_PRIORITY_HEADER: &str = "Accept-Resource-Priority";
+
+/// Option tag a caller puts in Require when the call must not proceed
+/// without its priority being understood
+pub const OPTION_TAG: &str = "resource-priority";
+
+/// Response to a call requiring a priority the gateway does not understand
+pub const STATUS_UNKNOWN_RESOURCE_PRIORITY: u16 = 417;
+
+/// Q.850 preemption
+pub const CAUSE_PREEMPTION: u16 = 8;
+
+/// Q.850 preemption, circuit reserved for reuse
+pub const CAUSE_PREEMPTION_CIRCUIT_RESERVED: u16 = 9;
+
+/// Namespaces whose priority values are built in (RFC 4412 section 9)
+pub const BUILTIN_NAMESPACES: &[&str] = &["dsn", "drsn", "q735", "ets", "wps"];
+
+/// Priority values of a built-in namespace, highest first
+fn builtin_values(namespace: &str) -> Option<&'static [&'static str]> {
+    match namespace {
+        "dsn" => Some(&["flash-override", "flash", "immediate", "priority", "routine"]),
+        "drsn" => Some(&["flash-override-override", "flash-override", "flash", "immediate", "priority", "routine"]),
+        "q735" | "ets" | "wps" => Some(&["0", "1", "2", "3", "4"]),
+        _ => None,
+    }
+}
+
+/// MLPP precedence level; the discriminant is the ISDN precedence level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrecedenceLevel {
+    FlashOverride = 0,
+    Flash = 1,
+    Immediate = 2,
+    Priority = 3,
+    Routine = 4,
+}
+
+impl PrecedenceLevel {
+    const LEVELS: [PrecedenceLevel; 5] = [
+        PrecedenceLevel::FlashOverride,
+        PrecedenceLevel::Flash,
+        PrecedenceLevel::Immediate,
+        PrecedenceLevel::Priority,
+        PrecedenceLevel::Routine,
+    ];
+
+    pub fn from_isdn(level: u8) -> Option<Self> {
+        Self::LEVELS.get(level as usize).copied()
+    }
+
+    pub fn isdn_level(self) -> u8 {
+        self as u8
+    }
+
+    /// Whether a call at this level may preempt one at `other`
+    pub fn outranks(self, other: PrecedenceLevel) -> bool {
+        self < other
+    }
+}
+
+impl fmt::Display for PrecedenceLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PrecedenceLevel::FlashOverride => "flash-override",
+            PrecedenceLevel::Flash => "flash",
+            PrecedenceLevel::Immediate => "immediate",
+            PrecedenceLevel::Priority => "priority",
+            PrecedenceLevel::Routine => "routine",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A call's Resource-Priority value and the precedence it ranks at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourcePriority {
+    pub namespace: String,
+    pub value: String,
+    pub level: PrecedenceLevel,
+}
+
+impl fmt::Display for ResourcePriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.namespace, self.value)
+    }
+}
+
+/// Accepted namespaces and how their values rank
+#[derive(Debug, Clone)]
+pub struct PrecedencePolicy {
+    /// Values of each accepted namespace, highest first
+    namespaces: BTreeMap<String, Vec<String>>,
+    preemption: bool,
+}
+
+impl PrecedencePolicy {
+    pub fn new(config: &PrecedenceConfig) -> Self {
+        let namespaces = config
+            .namespaces
+            .iter()
+            .filter_map(|namespace| {
+                let values = match config.custom_namespaces.get(namespace) {
+                    Some(values) => values.clone(),
+                    None => builtin_values(namespace)?.iter().map(|value| value.to_string()).collect(),
+                };
+                Some((namespace.clone(), values))
+            })
+            .collect();
+        Self {
+            namespaces,
+            preemption: config.preemption,
+        }
+    }
+
+    pub fn preemption(&self) -> bool {
+        self.preemption
+    }
+
+    /// Precedence of `namespace.value`, counted up from routine for the
+    /// namespace's last value; values above flash override share it
+    pub fn level(&self, namespace: &str, value: &str) -> Option<PrecedenceLevel> {
+        let values = self.namespaces.get(&namespace.to_ascii_lowercase())?;
+        let position = values.iter().position(|known| known.eq_ignore_ascii_case(value))?;
+        let steps_above_routine = values.len() - 1 - position;
+        let level = PrecedenceLevel::Routine.isdn_level().saturating_sub(steps_above_routine.min(4) as u8);
+        PrecedenceLevel::from_isdn(level)
+    }
+
+    /// Highest-ranked value among the call's Resource-Priority headers, in
+    /// the namespaces this gateway accepts
+    pub fn resource_priority(&self, headers: &[(String, String)]) -> Option<ResourcePriority> {
+        resource_priority_values(headers)
+            .filter_map(|(namespace, value)| {
+                let level = self.level(&namespace, &value)?;
+                Some(ResourcePriority { namespace: namespace.to_ascii_lowercase(), value, level })
+            })
+            .min_by_key(|priority| priority.level)
+    }
+
+    /// Whether the call must be refused with 417: it requires priority
+    /// handling but carries no value this gateway understands
+    pub fn refuses(&self, headers: &[(String, String)]) -> bool {
+        let required = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Require"))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|tag| tag.trim().eq_ignore_ascii_case(OPTION_TAG));
+        required && self.resource_priority(headers).is_none()
+    }
+
+    /// Accept-Resource-Priority value listing every value accepted
+    pub fn accepted_values(&self) -> String {
+        self.namespaces
+            .iter()
+            .flat_map(|(namespace, values)| values.iter().map(move |value| format!("{}.{}", namespace, value)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// `(namespace, value)` pairs of every Resource-Priority header
+fn resource_priority_values(headers: &[(String, String)]) -> impl Iterator<Item = (String, String)> + '_ {
+    headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case(RESOURCE_PRIORITY_HEADER))
+        .flat_map(|(_, value)| value.split(','))
+        .filter_map(|item| {
+            let (namespace, value) = item.trim().split_once('.')?;
+            Some((namespace.to_string(), value.to_string()))
+        })
+}
+
+/// An established or establishing call that could be preempted
+#[derive(Debug, Clone)]
+pub struct PreemptionCandidate<'a> {
+    pub call_id: &'a str,
+    pub level: PrecedenceLevel,
+    pub trunk: Option<&'a str>,
+    pub created_at: Instant,
+}
+
+/// The call chosen to make room for a higher-precedence one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preemption {
+    pub call_id: String,
+    pub level: PrecedenceLevel,
+    pub cause: u16,
+}
+
+/// Pick the call a `level` call bound for `trunk` preempts: the lowest
+/// precedence first, then one on the same trunk so its circuit is reused,
+/// then the most recent
+pub fn select_preemption<'a>(
+    level: PrecedenceLevel,
+    trunk: Option<&str>,
+    candidates: impl IntoIterator<Item = PreemptionCandidate<'a>>,
+) -> Option<Preemption> {
+    let same_trunk = |candidate: &PreemptionCandidate<'_>| trunk.is_some() && candidate.trunk == trunk;
+    let victim = candidates
+        .into_iter()
+        .filter(|candidate| level.outranks(candidate.level))
+        .max_by_key(|candidate| (candidate.level, same_trunk(candidate), candidate.created_at))?;
+
+    Some(Preemption {
+        call_id: victim.call_id.to_string(),
+        level: victim.level,
+        cause: if same_trunk(&victim) { CAUSE_PREEMPTION_CIRCUIT_RESERVED } else { CAUSE_PREEMPTION },
+    })
+}
+
+/// Reason headers on the BYEs releasing a preempted call: the Q.850 cause
+/// and the RFC 4411 preemption reason
+pub fn reason_headers(cause: u16) -> Vec<(String, String)> {
+    let text = match cause {
+        CAUSE_PREEMPTION_CIRCUIT_RESERVED => "Preemption - circuit reserved for reuse",
+        _ => "Preemption",
+    };
+    vec![
+        ("Reason".to_string(), format!("Q.850;cause={};text=\"{}\"", cause, text)),
+        ("Reason".to_string(), "preemption;cause=1;text=\"UA Preemption\"".to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_resource_priority_levels() {
+        let mut config = PrecedenceConfig::default();
+        config.namespaces.push("esnet".to_string());
+        config.custom_namespaces.insert("esnet".to_string(), vec!["high".to_string(), "normal".to_string()]);
+        let policy = PrecedencePolicy::new(&config);
+
+        assert_eq!(policy.level("dsn", "flash"), Some(PrecedenceLevel::Flash));
+        assert_eq!(policy.level("drsn", "flash-override-override"), Some(PrecedenceLevel::FlashOverride));
+        assert_eq!(policy.level("wps", "0"), Some(PrecedenceLevel::FlashOverride));
+        assert_eq!(policy.level("esnet", "high"), Some(PrecedenceLevel::Priority));
+        assert_eq!(policy.level("dsn", "urgent"), None);
+
+        // The highest value in an accepted namespace wins
+        let call = headers(&[("Resource-Priority", "foo.1, wps.3"), ("resource-priority", "dsn.immediate")]);
+        let priority = policy.resource_priority(&call).unwrap();
+        assert_eq!(priority.to_string(), "dsn.immediate");
+        assert_eq!(priority.level.isdn_level(), 2);
+        assert!(!policy.refuses(&call));
+
+        let unknown = headers(&[("Resource-Priority", "foo.1"), ("Require", "timer, resource-priority")]);
+        assert!(policy.resource_priority(&unknown).is_none());
+        assert!(policy.refuses(&unknown));
+        assert!(policy.accepted_values().contains("esnet.normal"));
+    }
+
+    #[test]
+    fn test_preemption_selection() {
+        let start = Instant::now();
+        let candidate = |call_id, level, trunk, age_secs| PreemptionCandidate {
+            call_id,
+            level,
+            trunk,
+            created_at: start + Duration::from_secs(age_secs),
+        };
+        let calls = || vec![
+            candidate("a", PrecedenceLevel::Routine, Some("carrier-a"), 0),
+            candidate("b", PrecedenceLevel::Routine, Some("carrier-b"), 5),
+            candidate("c", PrecedenceLevel::Priority, Some("carrier-a"), 10),
+        ];
+
+        // The newer routine call goes, unless the other one frees the trunk needed
+        let preemption = select_preemption(PrecedenceLevel::Flash, Some("carrier-c"), calls()).unwrap();
+        assert_eq!((preemption.call_id.as_str(), preemption.cause), ("b", CAUSE_PREEMPTION));
+        let preemption = select_preemption(PrecedenceLevel::Flash, Some("carrier-a"), calls()).unwrap();
+        assert_eq!((preemption.call_id.as_str(), preemption.cause), ("a", CAUSE_PREEMPTION_CIRCUIT_RESERVED));
+
+        // Calls cannot preempt their equals
+        assert!(select_preemption(PrecedenceLevel::Routine, None, calls()).is_none());
+        assert!(reason_headers(CAUSE_PREEMPTION)[0].1.contains("cause=8"));
+    }
+}
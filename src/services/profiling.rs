@@ -0,0 +1,348 @@
+//! On-demand runtime profiling for the management API
+//!
+//! CPU profiles are sampled with pprof for a bounded period and returned as
+//! pprof protobuf or an SVG flamegraph; heap statistics come from jemalloc.
+//! Both need the `profiling` feature. Only one CPU profile runs at a time and
+//! requests over the configured duration or size limits are refused.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::ProfilingConfig;
+use crate::{Error, Result};
+
+/// Management API path serving CPU profiles
+pub const CPU_PROFILE_PATH: &str = "/api/v1/debug/pprof/profile";
+/// Management API path serving allocator statistics
+pub const HEAP_STATS_PATH: &str = "/api/v1/debug/pprof/heap";
+
+/// Encoding of a CPU profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    /// pprof protobuf, readable by `go tool pprof`
+    Protobuf,
+    /// SVG flamegraph
+    Flamegraph,
+}
+
+impl ProfileFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ProfileFormat::Protobuf => "application/octet-stream",
+            ProfileFormat::Flamegraph => "image/svg+xml",
+        }
+    }
+}
+
+/// A captured CPU profile
+#[derive(Debug, Clone)]
+pub struct CpuProfile {
+    pub format: ProfileFormat,
+    pub duration: Duration,
+    pub samples: u64,
+    pub data: Vec<u8>,
+}
+
+/// Allocator statistics in bytes
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HeapStats {
+    pub allocated: u64,
+    pub active: u64,
+    pub resident: u64,
+    pub mapped: u64,
+    pub retained: u64,
+    pub metadata: u64,
+}
+
+/// Body returned to the management API
+#[derive(Debug, Clone)]
+pub struct ProfilingResponse {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+/// Profiling events
+#[derive(Debug, Clone)]
+pub enum ProfilingEvent {
+    CpuProfileStarted {
+        seconds: u64,
+        format: ProfileFormat,
+    },
+    CpuProfileCompleted {
+        duration: Duration,
+        samples: u64,
+        bytes: usize,
+    },
+    RequestRejected {
+        reason: String,
+    },
+}
+
+/// Marks a CPU profile as running until the capture ends, even when the
+/// request waiting on it is dropped part way
+struct ActiveCpuProfile(Arc<AtomicBool>);
+
+impl Drop for ActiveCpuProfile {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Serves CPU profiles and heap statistics within configured limits
+pub struct ProfilingService {
+    config: ProfilingConfig,
+    cpu_profile_active: Arc<AtomicBool>,
+    event_tx: mpsc::UnboundedSender<ProfilingEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ProfilingEvent>>,
+}
+
+impl ProfilingService {
+    pub fn new(config: ProfilingConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            config,
+            cpu_profile_active: Arc::new(AtomicBool::new(false)),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<ProfilingEvent>> {
+        self.event_rx.take()
+    }
+
+    /// Handle a management API request for one of the profiling paths
+    pub async fn handle_request(&self, path: &str, query: &str) -> Result<ProfilingResponse> {
+        match path {
+            CPU_PROFILE_PATH => {
+                let mut seconds = None;
+                let mut format = ProfileFormat::Protobuf;
+                for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+                    match key {
+                        "seconds" => {
+                            seconds = Some(value.parse().map_err(|_| {
+                                Error::parse(format!("Invalid profile duration: {}", value))
+                            })?);
+                        }
+                        "format" => {
+                            format = match value {
+                                "protobuf" | "pb" => ProfileFormat::Protobuf,
+                                "flamegraph" | "svg" => ProfileFormat::Flamegraph,
+                                other => return Err(Error::parse(format!("Unknown profile format: {}", other))),
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+
+                let profile = self.cpu_profile(seconds, format).await?;
+                Ok(ProfilingResponse {
+                    content_type: format.content_type(),
+                    body: profile.data,
+                })
+            }
+            HEAP_STATS_PATH => {
+                let stats = self.heap_stats()?;
+                let body = serde_json::to_vec(&stats)
+                    .map_err(|e| Error::internal(format!("Failed to encode heap statistics: {}", e)))?;
+                Ok(ProfilingResponse {
+                    content_type: "application/json",
+                    body,
+                })
+            }
+            other => Err(Error::not_supported(format!("Unknown profiling endpoint: {}", other))),
+        }
+    }
+
+    /// Sample the process for `seconds` (or the configured default)
+    pub async fn cpu_profile(&self, seconds: Option<u64>, format: ProfileFormat) -> Result<CpuProfile> {
+        let seconds = self.check_cpu_request(seconds)?;
+
+        if self.cpu_profile_active.swap(true, Ordering::SeqCst) {
+            return Err(self.reject("A CPU profile is already running".to_string()));
+        }
+        let active = ActiveCpuProfile(Arc::clone(&self.cpu_profile_active));
+
+        let _ = self.event_tx.send(ProfilingEvent::CpuProfileStarted { seconds, format });
+        info!("Starting {}s CPU profile ({:?})", seconds, format);
+
+        let frequency = self.config.frequency_hz;
+        let profile = tokio::task::spawn_blocking(move || {
+            let _active = active;
+            capture_cpu_profile(Duration::from_secs(seconds), frequency, format)
+        })
+        .await
+        .map_err(|e| Error::internal(format!("CPU profile task failed: {}", e)))
+        .and_then(|result| result)?;
+
+        if profile.data.len() > self.config.max_profile_bytes {
+            return Err(self.reject(format!(
+                "Profile of {} bytes exceeds the {} byte limit",
+                profile.data.len(),
+                self.config.max_profile_bytes
+            )));
+        }
+
+        let _ = self.event_tx.send(ProfilingEvent::CpuProfileCompleted {
+            duration: profile.duration,
+            samples: profile.samples,
+            bytes: profile.data.len(),
+        });
+        info!("CPU profile complete: {} samples, {} bytes", profile.samples, profile.data.len());
+        Ok(profile)
+    }
+
+    /// Current allocator statistics
+    pub fn heap_stats(&self) -> Result<HeapStats> {
+        if !self.config.enabled {
+            return Err(self.reject("Profiling is disabled".to_string()));
+        }
+        read_heap_stats()
+    }
+
+    pub fn is_cpu_profile_active(&self) -> bool {
+        self.cpu_profile_active.load(Ordering::SeqCst)
+    }
+
+    fn check_cpu_request(&self, seconds: Option<u64>) -> Result<u64> {
+        if !self.config.enabled {
+            return Err(self.reject("Profiling is disabled".to_string()));
+        }
+        let seconds = seconds.unwrap_or(self.config.default_cpu_seconds);
+        if seconds == 0 || seconds > self.config.max_cpu_seconds {
+            return Err(self.reject(format!(
+                "CPU profile duration must be between 1 and {} seconds",
+                self.config.max_cpu_seconds
+            )));
+        }
+        Ok(seconds)
+    }
+
+    fn reject(&self, reason: String) -> Error {
+        warn!("Profiling request rejected: {}", reason);
+        let _ = self.event_tx.send(ProfilingEvent::RequestRejected { reason: reason.clone() });
+        Error::invalid_state(reason)
+    }
+}
+
+#[cfg(feature = "profiling")]
+fn capture_cpu_profile(duration: Duration, frequency: i32, format: ProfileFormat) -> Result<CpuProfile> {
+    use pprof::protos::Message;
+
+    let started = std::time::Instant::now();
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(frequency)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|e| Error::internal(format!("Failed to start CPU profiler: {}", e)))?;
+
+    std::thread::sleep(duration);
+
+    let report = guard
+        .report()
+        .build()
+        .map_err(|e| Error::internal(format!("Failed to build CPU profile: {}", e)))?;
+    let samples = report.data.values().map(|count| (*count).max(0) as u64).sum();
+
+    let mut data = Vec::new();
+    match format {
+        ProfileFormat::Protobuf => {
+            let profile = report
+                .pprof()
+                .map_err(|e| Error::internal(format!("Failed to encode CPU profile: {}", e)))?;
+            profile
+                .write_to_vec(&mut data)
+                .map_err(|e| Error::internal(format!("Failed to encode CPU profile: {}", e)))?;
+        }
+        ProfileFormat::Flamegraph => {
+            report
+                .flamegraph(&mut data)
+                .map_err(|e| Error::internal(format!("Failed to render flamegraph: {}", e)))?;
+        }
+    }
+
+    Ok(CpuProfile {
+        format,
+        duration: started.elapsed(),
+        samples,
+        data,
+    })
+}
+
+#[cfg(not(feature = "profiling"))]
+fn capture_cpu_profile(_duration: Duration, _frequency: i32, _format: ProfileFormat) -> Result<CpuProfile> {
+    Err(Error::not_supported("CPU profiling requires the `profiling` feature"))
+}
+
+#[cfg(feature = "profiling")]
+fn read_heap_stats() -> Result<HeapStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    let map_err = |e: tikv_jemalloc_ctl::Error| Error::internal(format!("Failed to read jemalloc statistics: {}", e));
+
+    // Statistics are cached until the epoch advances
+    epoch::advance().map_err(map_err)?;
+    Ok(HeapStats {
+        allocated: stats::allocated::read().map_err(map_err)? as u64,
+        active: stats::active::read().map_err(map_err)? as u64,
+        resident: stats::resident::read().map_err(map_err)? as u64,
+        mapped: stats::mapped::read().map_err(map_err)? as u64,
+        retained: stats::retained::read().map_err(map_err)? as u64,
+        metadata: stats::metadata::read().map_err(map_err)? as u64,
+    })
+}
+
+#[cfg(not(feature = "profiling"))]
+fn read_heap_stats() -> Result<HeapStats> {
+    Err(Error::not_supported("Heap statistics require the `profiling` feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(enabled: bool) -> ProfilingService {
+        ProfilingService::new(ProfilingConfig {
+            enabled,
+            max_cpu_seconds: 10,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_requests_outside_limits_are_rejected() {
+        let disabled = service(false);
+        assert!(disabled.cpu_profile(Some(1), ProfileFormat::Protobuf).await.is_err());
+        assert!(disabled.heap_stats().is_err());
+
+        let enabled = service(true);
+        assert!(enabled.cpu_profile(Some(11), ProfileFormat::Protobuf).await.is_err());
+        assert!(enabled.cpu_profile(Some(0), ProfileFormat::Flamegraph).await.is_err());
+        assert!(!enabled.is_cpu_profile_active());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path_and_format() {
+        let service = service(true);
+        assert!(service.handle_request("/api/v1/debug/pprof/goroutine", "").await.is_err());
+        assert!(service.handle_request(CPU_PROFILE_PATH, "seconds=1&format=text").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_profile_releases_slot() {
+        let service = service(true);
+        let first = service.cpu_profile(Some(1), ProfileFormat::Protobuf).await;
+        assert!(!service.is_cpu_profile_active());
+
+        // Without the feature every capture fails; either way the slot is free again
+        let second = service.cpu_profile(Some(1), ProfileFormat::Protobuf).await;
+        assert_eq!(first.is_ok(), second.is_ok());
+    }
+}
@@ -0,0 +1,469 @@
+//! Multi-language announcement prompt packs
+//!
+//! A prompt pack is the set of announcements recorded in one language, kept
+//! as `<directory>/<language>/<event>.wav`. When a call is to hear an
+//! announcement, the pack is chosen by the caller's Accept-Language, then by
+//! the trunk or tenant rules, then the default language, skipping any pack
+//! that lacks the prompt. Packs are loaded at startup and prompts can be
+//! uploaded or removed at run time through the management API; every file
+//! is checked to be 8 kHz mono G.711 or 16-bit PCM before it is accepted.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::{is_language_tag, PromptsConfig};
+use crate::{Error, Result};
+
+/// Management API path listing the prompt packs
+pub const PROMPTS_PATH: &str = "/api/v1/prompts";
+
+/// Management API path for one prompt; PUT uploads it, DELETE removes it
+pub fn prompt_path(language: &str, event: &str) -> String {
+    format!("{}/{}/{}", PROMPTS_PATH, language, event)
+}
+
+/// Played when no circuit or trunk capacity is left
+pub const ALL_CIRCUITS_BUSY: &str = "all-circuits-busy";
+/// Played when the dialed number cannot be routed
+pub const INVALID_NUMBER: &str = "invalid-number";
+/// Played while only survivability fallback routes are available
+pub const SURVIVABILITY_NOTICE: &str = "survivability-unavailable";
+
+/// Events every pack is expected to cover
+pub const STANDARD_EVENTS: [&str; 3] = [ALL_CIRCUITS_BUSY, INVALID_NUMBER, SURVIVABILITY_NOTICE];
+
+/// Longest announcement accepted
+const MAX_PROMPT_MS: u64 = 5 * 60 * 1000;
+
+const SAMPLE_RATE: u32 = 8000;
+
+/// Audio encoding of a prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptFormat {
+    Pcmu,
+    Pcma,
+    /// 16-bit linear PCM
+    Linear16,
+}
+
+/// A validated prompt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PromptInfo {
+    pub event: String,
+    pub format: PromptFormat,
+    pub duration_ms: u64,
+}
+
+/// The prompts recorded in one language
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PromptPackSummary {
+    pub language: String,
+    pub prompts: Vec<PromptInfo>,
+    /// Standard events this pack has no prompt for
+    pub missing: Vec<String>,
+}
+
+/// Prompt chosen for an announcement
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectedPrompt {
+    pub language: String,
+    pub event: String,
+    pub path: PathBuf,
+}
+
+/// Whether `event` can name a prompt file: lowercase letters, digits and `-`
+pub(crate) fn is_event_name(event: &str) -> bool {
+    (1..=64).contains(&event.len()) && event.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// Check that `data` is a WAV file the media path can play as-is
+pub fn parse_prompt(event: &str, data: &[u8]) -> Result<PromptInfo> {
+    parse_wav(event, data).map(|(info, _)| info)
+}
+
+/// The prompt and its audio samples
+fn parse_wav<'a>(event: &str, data: &'a [u8]) -> Result<(PromptInfo, &'a [u8])> {
+    let invalid = |reason: &str| Error::parse(format!("Prompt {}: {}", event, reason));
+
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let mut format = None;
+    let mut audio: Option<&[u8]> = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let len = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let body = data.get(pos + 8..pos + 8 + len).ok_or_else(|| invalid("truncated chunk"))?;
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let audio_format = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                if channels != 1 || sample_rate != SAMPLE_RATE {
+                    return Err(invalid("must be 8 kHz mono"));
+                }
+                format = Some(match (audio_format, bits) {
+                    (1, 16) => PromptFormat::Linear16,
+                    (6, 8) => PromptFormat::Pcma,
+                    (7, 8) => PromptFormat::Pcmu,
+                    _ => return Err(invalid("must be G.711 or 16-bit PCM")),
+                });
+            }
+            b"data" => audio = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        pos += 8 + len + (len & 1);
+    }
+
+    let format = format.ok_or_else(|| invalid("no format chunk"))?;
+    let audio = audio.filter(|audio| !audio.is_empty()).ok_or_else(|| invalid("no audio"))?;
+    let bytes_per_sample = if format == PromptFormat::Linear16 { 2 } else { 1 };
+    let duration_ms = audio.len() as u64 * 1000 / (SAMPLE_RATE as u64 * bytes_per_sample);
+    if duration_ms > MAX_PROMPT_MS {
+        return Err(invalid("longer than 5 minutes"));
+    }
+
+    Ok((PromptInfo { event: event.to_string(), format, duration_ms }, audio))
+}
+
+/// G.711 A-law sample to 16-bit linear
+pub(crate) fn alaw_to_linear(value: u8) -> i16 {
+    let value = value ^ 0x55;
+    let segment = (value >> 4) & 0x07;
+    let mut magnitude = ((value & 0x0f) as i16) << 4;
+    magnitude += if segment == 0 { 8 } else { 0x108 };
+    if segment > 1 {
+        magnitude <<= segment - 1;
+    }
+    if value & 0x80 != 0 { magnitude } else { -magnitude }
+}
+
+/// G.711 mu-law sample to 16-bit linear
+pub(crate) fn ulaw_to_linear(value: u8) -> i16 {
+    let value = !value;
+    let exponent = (value >> 4) & 0x07;
+    let magnitude = (((((value & 0x0f) as i16) << 3) + 0x84) << exponent) - 0x84;
+    if value & 0x80 != 0 { -magnitude } else { magnitude }
+}
+
+/// 16-bit linear sample to G.711 mu-law
+pub(crate) fn linear_to_ulaw(sample: i16) -> u8 {
+    const BIAS: i32 = 0x84;
+    const CLIP: i32 = 32635;
+    let sign = if sample < 0 { 0x80 } else { 0 };
+    let magnitude = (sample as i32).abs().min(CLIP) + BIAS;
+    let exponent = 31 - ((magnitude >> 7) as u32).leading_zeros();
+    let mantissa = (magnitude >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+/// 16-bit linear sample to G.711 A-law
+pub(crate) fn linear_to_alaw(sample: i16) -> u8 {
+    let (sign, magnitude) = if sample >= 0 { (0x80, sample as i32) } else { (0x00, -(sample as i32) - 1) };
+    // 13-bit magnitude: segment 0 holds the first 32 steps, each further
+    // segment doubles the step size
+    let magnitude = magnitude >> 3;
+    let code = if magnitude < 32 {
+        (magnitude >> 1) as u8
+    } else {
+        let segment = 27 - (magnitude as u32).leading_zeros();
+        ((segment << 4) as u8) | ((magnitude >> segment) & 0x0f) as u8
+    };
+    (sign | code) ^ 0x55
+}
+
+/// A stored prompt as mu-law samples, ready to send as PCMU
+pub fn read_pcmu(path: &Path) -> Result<Vec<u8>> {
+    let data = std::fs::read(path)?;
+    let (info, audio) = parse_wav(&path.display().to_string(), &data)?;
+    Ok(match info.format {
+        PromptFormat::Pcmu => audio.to_vec(),
+        PromptFormat::Pcma => audio.iter().map(|sample| linear_to_ulaw(alaw_to_linear(*sample))).collect(),
+        PromptFormat::Linear16 => audio
+            .chunks_exact(2)
+            .map(|sample| linear_to_ulaw(i16::from_le_bytes([sample[0], sample[1]])))
+            .collect(),
+    })
+}
+
+/// Languages in an Accept-Language header, most preferred first
+fn caller_languages(accept_language: &str) -> Vec<String> {
+    let mut ranked: Vec<(f32, usize, String)> = accept_language
+        .split(',')
+        .enumerate()
+        .filter_map(|(index, item)| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            (quality > 0.0 && is_language_tag(&tag)).then_some((quality, index, tag))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal).then(a.1.cmp(&b.1)));
+    ranked.into_iter().map(|(_, _, tag)| tag).collect()
+}
+
+/// The prompt packs on disk and the rules choosing between them
+pub struct PromptLibrary {
+    config: PromptsConfig,
+    /// Prompts by language, then event
+    packs: DashMap<String, BTreeMap<String, PromptInfo>>,
+}
+
+impl PromptLibrary {
+    /// Load every valid prompt under the configured directory; invalid
+    /// files are logged and left out
+    pub fn load(config: PromptsConfig) -> Result<Self> {
+        let library = Self { config, packs: DashMap::new() };
+        let entries = match std::fs::read_dir(&library.config.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(library),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries.flatten() {
+            let language = entry.file_name().to_string_lossy().to_ascii_lowercase();
+            if !entry.path().is_dir() || !is_language_tag(&language) {
+                continue;
+            }
+            for file in std::fs::read_dir(entry.path())?.flatten() {
+                let path = file.path();
+                let Some(event) = path.file_stem().and_then(|stem| stem.to_str()).filter(|stem| is_event_name(stem)) else {
+                    continue;
+                };
+                if path.extension().and_then(|ext| ext.to_str()) != Some("wav") {
+                    continue;
+                }
+                match std::fs::read(&path).map_err(Error::from).and_then(|data| parse_prompt(event, &data)) {
+                    Ok(info) => {
+                        library.packs.entry(language.clone()).or_default().insert(info.event.clone(), info);
+                    }
+                    Err(e) => warn!("Skipping prompt {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        info!("Loaded {} prompt packs from {}", library.packs.len(), library.config.directory);
+        Ok(library)
+    }
+
+    fn prompt_file(&self, language: &str, event: &str) -> PathBuf {
+        PathBuf::from(&self.config.directory).join(language).join(format!("{}.wav", event))
+    }
+
+    /// Validate and store a prompt, replacing any previous recording
+    pub fn upload(&self, language: &str, event: &str, data: &[u8]) -> Result<PromptInfo> {
+        let language = language.to_ascii_lowercase();
+        if !is_language_tag(&language) {
+            return Err(Error::invalid_config(format!("Invalid prompt language {}", language)));
+        }
+        if !is_event_name(event) {
+            return Err(Error::invalid_config(format!("Invalid prompt event {}", event)));
+        }
+        let info = parse_prompt(event, data)?;
+
+        // Write beside the prompt and rename, so a playing prompt is never half-written
+        let path = self.prompt_file(&language, event);
+        let staging = path.with_extension("wav.tmp");
+        std::fs::create_dir_all(path.parent().expect("prompt file has a language directory"))?;
+        std::fs::write(&staging, data)?;
+        std::fs::rename(&staging, &path)?;
+
+        info!("Stored {} prompt {} ({} ms)", language, event, info.duration_ms);
+        self.packs.entry(language).or_default().insert(event.to_string(), info.clone());
+        Ok(info)
+    }
+
+    pub fn remove(&self, language: &str, event: &str) -> Result<()> {
+        let language = language.to_ascii_lowercase();
+        let removed = self.packs.get_mut(&language).and_then(|mut pack| pack.remove(event));
+        if removed.is_none() {
+            return Err(Error::invalid_state(format!("No {} prompt in the {} pack", event, language)));
+        }
+        self.packs.remove_if(&language, |_, pack| pack.is_empty());
+        std::fs::remove_file(self.prompt_file(&language, event))?;
+        Ok(())
+    }
+
+    /// Every pack with the standard events it is missing
+    pub fn packs(&self) -> Vec<PromptPackSummary> {
+        let mut packs: Vec<PromptPackSummary> = self
+            .packs
+            .iter()
+            .map(|pack| PromptPackSummary {
+                language: pack.key().clone(),
+                prompts: pack.value().values().cloned().collect(),
+                missing: STANDARD_EVENTS
+                    .iter()
+                    .filter(|event| !pack.value().contains_key(**event))
+                    .map(|event| event.to_string())
+                    .collect(),
+            })
+            .collect();
+        packs.sort_by(|a, b| a.language.cmp(&b.language));
+        packs
+    }
+
+    /// The pack for `language` with `event`, falling back from a regional
+    /// pack such as `fr-ca` to `fr`
+    pub fn lookup(&self, language: &str, event: &str) -> Option<SelectedPrompt> {
+        let primary = language.split('-').next().unwrap_or(language);
+        [language, primary].into_iter().find_map(|candidate| {
+            self.packs.get(candidate)?.contains_key(event).then(|| SelectedPrompt {
+                language: candidate.to_string(),
+                event: event.to_string(),
+                path: self.prompt_file(candidate, event),
+            })
+        })
+    }
+
+    /// Prompt for `event`: the caller's preference first, then the trunk and
+    /// tenant rules, then the default language
+    pub fn select(
+        &self,
+        event: &str,
+        trunk: Option<&str>,
+        tenant: Option<&str>,
+        accept_language: Option<&str>,
+    ) -> Option<SelectedPrompt> {
+        let preferred = accept_language
+            .filter(|_| self.config.honor_caller_preference)
+            .map(caller_languages)
+            .unwrap_or_default();
+        let configured = self
+            .config
+            .selection
+            .iter()
+            .filter(|rule| {
+                rule.trunk.as_deref().map_or(true, |rule_trunk| Some(rule_trunk) == trunk)
+                    && rule.tenant.as_deref().map_or(true, |rule_tenant| Some(rule_tenant) == tenant)
+            })
+            .map(|rule| rule.language.to_ascii_lowercase());
+
+        preferred
+            .into_iter()
+            .chain(configured)
+            .chain(std::iter::once(self.config.default_language.to_ascii_lowercase()))
+            .find_map(|language| self.lookup(&language, event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PromptSelectionRule;
+
+    fn wav(audio_format: u16, bits: u16, samples: usize) -> Vec<u8> {
+        let data_len = samples * bits as usize / 8;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&audio_format.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        wav.extend_from_slice(&(SAMPLE_RATE * bits as u32 / 8).to_le_bytes());
+        wav.extend_from_slice(&(bits / 8).to_le_bytes());
+        wav.extend_from_slice(&bits.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data_len as u32).to_le_bytes());
+        wav.resize(wav.len() + data_len, 0xff);
+        wav
+    }
+
+    #[test]
+    fn test_parse_prompt() {
+        let info = parse_prompt(INVALID_NUMBER, &wav(7, 8, 16000)).unwrap();
+        assert_eq!(info.format, PromptFormat::Pcmu);
+        assert_eq!(info.duration_ms, 2000);
+        assert_eq!(parse_prompt(INVALID_NUMBER, &wav(1, 16, 8000)).unwrap().duration_ms, 1000);
+
+        assert!(parse_prompt(INVALID_NUMBER, &wav(3, 32, 8000)).is_err());
+        assert!(parse_prompt(INVALID_NUMBER, &wav(7, 8, 0)).is_err());
+        assert!(parse_prompt(INVALID_NUMBER, b"ID3\x04 not a wav").is_err());
+        let mut truncated = wav(7, 8, 8000);
+        truncated.truncate(100);
+        assert!(parse_prompt(INVALID_NUMBER, &truncated).is_err());
+    }
+
+    #[test]
+    fn test_g711_conversion() {
+        assert_eq!(linear_to_ulaw(0), 0xff);
+        assert_eq!(linear_to_ulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_ulaw(i16::MIN), 0x00);
+        assert_eq!(ulaw_to_linear(0xff), 0);
+        assert_eq!(ulaw_to_linear(0x80), 32124);
+        assert_eq!(ulaw_to_linear(0x00), -32124);
+        // A-law code 0xd5 is the smallest positive step
+        assert_eq!(alaw_to_linear(0xd5), 8);
+        assert_eq!(alaw_to_linear(0x2a), -32256);
+        for sample in [0i16, 8, -8, 1000, -1000, 32000, i16::MIN] {
+            let step = (sample as i32).abs() / 16 + 16;
+            assert!((alaw_to_linear(linear_to_alaw(sample)) as i32 - sample as i32).abs() <= step);
+        }
+        assert_eq!(linear_to_alaw(alaw_to_linear(0xd5)), 0xd5);
+    }
+
+    #[test]
+    fn test_caller_languages_ranked_by_quality() {
+        assert_eq!(caller_languages("en;q=0.5, fr-CA, fr;q=0.9, *;q=0.1, de;q=0"), vec!["fr-ca", "fr", "en"]);
+    }
+
+    #[test]
+    fn test_upload_and_select() {
+        let directory = std::env::temp_dir().join(format!("redfire-prompts-{}", uuid::Uuid::new_v4()));
+        let library = PromptLibrary::load(PromptsConfig {
+            enabled: true,
+            directory: directory.to_string_lossy().into_owned(),
+            selection: vec![PromptSelectionRule {
+                trunk: None,
+                tenant: Some("acme.example.com".to_string()),
+                language: "es".to_string(),
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+
+        library.upload("en", ALL_CIRCUITS_BUSY, &wav(7, 8, 8000)).unwrap();
+        library.upload("en", INVALID_NUMBER, &wav(7, 8, 8000)).unwrap();
+        library.upload("es", ALL_CIRCUITS_BUSY, &wav(6, 8, 8000)).unwrap();
+        library.upload("fr", ALL_CIRCUITS_BUSY, &wav(1, 16, 8000)).unwrap();
+        assert!(library.upload("en", SURVIVABILITY_NOTICE, b"not audio").is_err());
+        assert!(library.upload("../etc", INVALID_NUMBER, &wav(7, 8, 8000)).is_err());
+
+        let select = |event, tenant, accept| library.select(event, None, tenant, accept).map(|prompt| prompt.language);
+        assert_eq!(select(ALL_CIRCUITS_BUSY, None, None).as_deref(), Some("en"));
+        assert_eq!(select(ALL_CIRCUITS_BUSY, Some("acme.example.com"), None).as_deref(), Some("es"));
+        // A regional preference falls back to its primary language
+        assert_eq!(select(ALL_CIRCUITS_BUSY, Some("acme.example.com"), Some("fr-CA")).as_deref(), Some("fr"));
+        // Packs without the prompt are skipped
+        assert_eq!(select(INVALID_NUMBER, Some("acme.example.com"), Some("fr")).as_deref(), Some("en"));
+        assert_eq!(select(SURVIVABILITY_NOTICE, None, None), None);
+
+        // Packs on disk are found again on restart
+        let reloaded = PromptLibrary::load(library.config.clone()).unwrap();
+        let packs = reloaded.packs();
+        assert_eq!(packs.iter().map(|pack| pack.language.as_str()).collect::<Vec<_>>(), vec!["en", "es", "fr"]);
+        assert_eq!(packs[0].missing, vec![SURVIVABILITY_NOTICE.to_string()]);
+
+        reloaded.remove("fr", ALL_CIRCUITS_BUSY).unwrap();
+        assert!(reloaded.remove("fr", ALL_CIRCUITS_BUSY).is_err());
+        assert_eq!(reloaded.packs().len(), 2);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
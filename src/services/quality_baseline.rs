@@ -0,0 +1,332 @@
+//! Voice quality baselines and degradation alerting per trunk
+//!
+//! Each trunk learns what normal MOS, packet loss and jitter look like for
+//! every hour of the day. Recent samples are compared against the baseline
+//! for the current hour, and a metric that stays worse than its baseline by
+//! the configured margin for the sustain period raises an alert. Baselines
+//! stop learning while a metric is degraded so an incident does not become
+//! the new normal.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Duration as ChronoDuration, Timelike, Utc};
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::QualityAlertingConfig;
+
+/// Quality measured for one call or reporting interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualitySample {
+    pub mos: f64,
+    pub loss_pct: f64,
+    pub jitter_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QualityMetric {
+    Mos,
+    PacketLoss,
+    Jitter,
+}
+
+impl QualityMetric {
+    const ALL: [QualityMetric; 3] = [QualityMetric::Mos, QualityMetric::PacketLoss, QualityMetric::Jitter];
+
+    fn value(self, sample: &QualitySample) -> f64 {
+        match self {
+            QualityMetric::Mos => sample.mos,
+            QualityMetric::PacketLoss => sample.loss_pct,
+            QualityMetric::Jitter => sample.jitter_ms,
+        }
+    }
+
+    /// How much worse `current` is than `baseline`; positive means degraded
+    fn degradation(self, baseline: f64, current: f64) -> f64 {
+        match self {
+            QualityMetric::Mos => baseline - current,
+            QualityMetric::PacketLoss | QualityMetric::Jitter => current - baseline,
+        }
+    }
+
+    fn threshold(self, config: &QualityAlertingConfig) -> f64 {
+        match self {
+            QualityMetric::Mos => config.mos_drop,
+            QualityMetric::PacketLoss => config.loss_increase_pct,
+            QualityMetric::Jitter => config.jitter_increase_ms,
+        }
+    }
+}
+
+/// Learned normal value of one metric in one hour-of-day slot
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricBaseline {
+    pub mean: f64,
+    pub samples: u32,
+}
+
+impl MetricBaseline {
+    fn learn(&mut self, value: f64, weight: f64) {
+        self.mean = if self.samples == 0 { value } else { self.mean + weight * (value - self.mean) };
+        self.samples = self.samples.saturating_add(1);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HourBaseline {
+    mos: MetricBaseline,
+    loss: MetricBaseline,
+    jitter: MetricBaseline,
+}
+
+impl HourBaseline {
+    fn metric(&mut self, metric: QualityMetric) -> &mut MetricBaseline {
+        match metric {
+            QualityMetric::Mos => &mut self.mos,
+            QualityMetric::PacketLoss => &mut self.loss,
+            QualityMetric::Jitter => &mut self.jitter,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct MetricState {
+    deviating_since: Option<Instant>,
+    alerting: bool,
+}
+
+#[derive(Debug)]
+struct TrunkQuality {
+    hours: [HourBaseline; 24],
+    recent: VecDeque<(Instant, QualitySample)>,
+    states: [MetricState; 3],
+}
+
+impl Default for TrunkQuality {
+    fn default() -> Self {
+        Self {
+            hours: [HourBaseline::default(); 24],
+            recent: VecDeque::new(),
+            states: [MetricState::default(); 3],
+        }
+    }
+}
+
+/// Quality alerting events
+#[derive(Debug, Clone)]
+pub enum QualityEvent {
+    DegradationDetected {
+        trunk: String,
+        metric: QualityMetric,
+        baseline: f64,
+        current: f64,
+        duration: Duration,
+    },
+    DegradationCleared {
+        trunk: String,
+        metric: QualityMetric,
+        baseline: f64,
+        current: f64,
+    },
+}
+
+/// Learns per-trunk quality baselines and alerts on sustained deviations
+pub struct QualityBaselineMonitor {
+    config: QualityAlertingConfig,
+    trunks: DashMap<String, TrunkQuality>,
+    event_tx: mpsc::UnboundedSender<QualityEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<QualityEvent>>,
+}
+
+impl QualityBaselineMonitor {
+    pub fn new(config: QualityAlertingConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            config,
+            trunks: DashMap::new(),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<QualityEvent>> {
+        self.event_rx.take()
+    }
+
+    fn hour_of_day(&self, at: DateTime<Utc>) -> usize {
+        (at + ChronoDuration::hours(self.config.utc_offset_hours as i64)).hour() as usize
+    }
+
+    /// Record a sample for `trunk` taken at wall-clock `at`, then re-evaluate the trunk
+    pub fn record(&self, trunk: &str, sample: QualitySample, at: DateTime<Utc>, now: Instant) {
+        let hour = self.hour_of_day(at);
+        let window = Duration::from_secs(self.config.window_secs.max(1));
+        let mut quality = self.trunks.entry(trunk.to_string()).or_default();
+
+        quality.recent.push_back((now, sample));
+        while quality
+            .recent
+            .front()
+            .is_some_and(|(taken, _)| now.duration_since(*taken) > window)
+        {
+            quality.recent.pop_front();
+        }
+
+        self.evaluate(trunk, &mut quality, hour, now);
+
+        // Only healthy metrics teach the baseline
+        for (index, metric) in QualityMetric::ALL.into_iter().enumerate() {
+            if quality.states[index].deviating_since.is_none() {
+                quality.hours[hour].metric(metric).learn(metric.value(&sample), self.config.baseline_weight);
+            }
+        }
+    }
+
+    fn evaluate(&self, trunk: &str, quality: &mut TrunkQuality, hour: usize, now: Instant) {
+        let count = quality.recent.len() as f64;
+        if count == 0.0 {
+            return;
+        }
+        let sustain = Duration::from_secs(self.config.sustain_secs);
+
+        for (index, metric) in QualityMetric::ALL.into_iter().enumerate() {
+            let baseline = *quality.hours[hour].metric(metric);
+            if baseline.samples < self.config.min_baseline_samples {
+                continue;
+            }
+            let current = quality.recent.iter().map(|(_, sample)| metric.value(sample)).sum::<f64>() / count;
+            let degraded = metric.degradation(baseline.mean, current) >= metric.threshold(&self.config);
+            let state = &mut quality.states[index];
+
+            match (degraded, state.deviating_since) {
+                (true, None) => state.deviating_since = Some(now),
+                (true, Some(since)) if !state.alerting && now.duration_since(since) >= sustain => {
+                    state.alerting = true;
+                    warn!(
+                        "Voice quality degraded on trunk {}: {:?} at {:.2} against baseline {:.2}",
+                        trunk, metric, current, baseline.mean
+                    );
+                    let _ = self.event_tx.send(QualityEvent::DegradationDetected {
+                        trunk: trunk.to_string(),
+                        metric,
+                        baseline: baseline.mean,
+                        current,
+                        duration: now.duration_since(since),
+                    });
+                }
+                (false, Some(_)) => {
+                    if state.alerting {
+                        info!("Voice quality recovered on trunk {}: {:?} at {:.2}", trunk, metric, current);
+                        let _ = self.event_tx.send(QualityEvent::DegradationCleared {
+                            trunk: trunk.to_string(),
+                            metric,
+                            baseline: baseline.mean,
+                            current,
+                        });
+                    }
+                    *state = MetricState::default();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Learned baseline for a trunk's metric in the hour containing `at`
+    pub fn baseline(&self, trunk: &str, metric: QualityMetric, at: DateTime<Utc>) -> Option<MetricBaseline> {
+        let hour = self.hour_of_day(at);
+        self.trunks.get_mut(trunk).map(|mut quality| *quality.hours[hour].metric(metric))
+    }
+
+    /// Metrics currently alerting on a trunk
+    pub fn alerting_metrics(&self, trunk: &str) -> Vec<QualityMetric> {
+        self.trunks
+            .get(trunk)
+            .map(|quality| {
+                QualityMetric::ALL
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(index, _)| quality.states[*index].alerting)
+                    .map(|(_, metric)| metric)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOD: QualitySample = QualitySample { mos: 4.3, loss_pct: 0.1, jitter_ms: 5.0 };
+    const BAD: QualitySample = QualitySample { mos: 3.5, loss_pct: 0.2, jitter_ms: 6.0 };
+
+    fn monitor() -> QualityBaselineMonitor {
+        QualityBaselineMonitor::new(QualityAlertingConfig {
+            enabled: true,
+            min_baseline_samples: 10,
+            window_secs: 30,
+            sustain_secs: 300,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_sustained_mos_drop_alerts_and_clears() {
+        let mut monitor = monitor();
+        let mut events = monitor.take_event_receiver().unwrap();
+        let at = Utc::now();
+        let start = Instant::now();
+
+        for i in 0..20 {
+            monitor.record("carrier-a", GOOD, at, start + Duration::from_secs(i));
+        }
+        let baseline = monitor.baseline("carrier-a", QualityMetric::Mos, at).unwrap();
+        assert!((baseline.mean - 4.3).abs() < 1e-9);
+
+        // Degraded for four minutes: not yet sustained
+        let degraded_from = start + Duration::from_secs(100);
+        for i in (0..240).step_by(10) {
+            monitor.record("carrier-a", BAD, at, degraded_from + Duration::from_secs(i));
+        }
+        assert!(events.try_recv().is_err());
+
+        monitor.record("carrier-a", BAD, at, degraded_from + Duration::from_secs(330));
+        match events.try_recv().unwrap() {
+            QualityEvent::DegradationDetected { trunk, metric, .. } => {
+                assert_eq!(trunk, "carrier-a");
+                assert_eq!(metric, QualityMetric::Mos);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert_eq!(monitor.alerting_metrics("carrier-a"), vec![QualityMetric::Mos]);
+        // The incident was not learned
+        assert!((monitor.baseline("carrier-a", QualityMetric::Mos, at).unwrap().mean - 4.3).abs() < 1e-9);
+
+        let recovered = degraded_from + Duration::from_secs(400);
+        monitor.record("carrier-a", GOOD, at, recovered);
+        assert!(matches!(events.try_recv().unwrap(), QualityEvent::DegradationCleared { metric: QualityMetric::Mos, .. }));
+        assert!(monitor.alerting_metrics("carrier-a").is_empty());
+    }
+
+    #[test]
+    fn test_baselines_are_per_hour_and_need_samples() {
+        let mut monitor = monitor();
+        let mut events = monitor.take_event_receiver().unwrap();
+        let night = "2024-03-01T03:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let day = "2024-03-01T15:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let start = Instant::now();
+
+        for i in 0..20 {
+            monitor.record("carrier-b", GOOD, night, start + Duration::from_secs(i));
+        }
+        // The daytime slot has no baseline yet, so poor daytime quality is only learned
+        for i in 0..40 {
+            monitor.record("carrier-b", BAD, day, start + Duration::from_secs(100 + i * 10));
+        }
+        assert!(events.try_recv().is_err());
+        assert!((monitor.baseline("carrier-b", QualityMetric::Mos, day).unwrap().mean - 3.5).abs() < 1e-9);
+        assert!((monitor.baseline("carrier-b", QualityMetric::Mos, night).unwrap().mean - 4.3).abs() < 1e-9);
+    }
+}
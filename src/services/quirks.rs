@@ -0,0 +1,193 @@
+//! Carrier quirk profiles
+//!
+//! Interoperability workarounds are grouped into named profiles and assigned
+//! to trunks, so a carrier's oddities are described once instead of as
+//! scattered per-trunk flags. A small database of common profiles is built
+//! in; configured profiles add to it or replace a built-in of the same name.
+
+use std::collections::BTreeMap;
+
+use crate::config::{QuirkProfile, QuirksConfig};
+use crate::{Error, Result};
+
+/// Request size above which RFC 3261 §18.1.1 requires TCP
+pub const RFC3261_UDP_LIMIT: usize = 1300;
+
+/// Built-in profiles for commonly seen carrier behaviour
+pub fn builtin_profiles() -> Vec<QuirkProfile> {
+    vec![
+        QuirkProfile {
+            name: "legacy-softswitch".to_string(),
+            no_prack: true,
+            broken_reinvite: true,
+            ..Default::default()
+        },
+        QuirkProfile {
+            name: "user-phone-required".to_string(),
+            user_phone: true,
+            ..Default::default()
+        },
+        QuirkProfile {
+            name: "no-early-media".to_string(),
+            suppress_183: true,
+            ..Default::default()
+        },
+        QuirkProfile {
+            name: "udp-fragmentation".to_string(),
+            max_udp_size: Some(RFC3261_UDP_LIMIT),
+            ..Default::default()
+        },
+    ]
+}
+
+impl QuirkProfile {
+    /// Request-URI for an INVITE of roughly `request_size` bytes
+    pub fn request_uri(&self, uri: &str, request_size: usize) -> String {
+        let mut uri = uri.to_string();
+        if self.user_phone && !uri.contains(";user=phone") {
+            uri.push_str(";user=phone");
+        }
+        if self.max_udp_size.is_some_and(|limit| request_size > limit) && !uri.contains(";transport=") {
+            uri.push_str(";transport=tcp");
+        }
+        uri
+    }
+
+    /// Drop headers and option tags the peer cannot cope with
+    pub fn filter_headers(&self, headers: &mut Vec<(String, String)>) {
+        if !self.no_prack {
+            return;
+        }
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case("RSeq") && !name.eq_ignore_ascii_case("RAck"));
+        for (name, value) in headers.iter_mut() {
+            if name.eq_ignore_ascii_case("Supported") || name.eq_ignore_ascii_case("Require") {
+                *value = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.eq_ignore_ascii_case("100rel"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+            }
+        }
+        headers.retain(|(_, value)| !value.is_empty());
+    }
+
+    /// Status to relay towards the caller for a provisional from the peer, and
+    /// whether its SDP may be passed on
+    pub fn provisional(&self, status_code: u16) -> (u16, bool) {
+        match status_code {
+            183 if self.suppress_183 => (180, false),
+            _ => (status_code, true),
+        }
+    }
+
+    /// Method used for mid-dialog session changes towards the peer
+    pub fn mid_dialog_method(&self) -> &'static str {
+        if self.broken_reinvite {
+            "UPDATE"
+        } else {
+            "INVITE"
+        }
+    }
+}
+
+/// Quirk profiles resolved per trunk
+#[derive(Debug, Clone, Default)]
+pub struct QuirkRegistry {
+    profiles: BTreeMap<String, QuirkProfile>,
+    trunks: BTreeMap<String, String>,
+}
+
+impl QuirkRegistry {
+    pub fn new(config: &QuirksConfig) -> Result<Self> {
+        let mut profiles: BTreeMap<String, QuirkProfile> =
+            builtin_profiles().into_iter().map(|profile| (profile.name.clone(), profile)).collect();
+        for profile in &config.profiles {
+            if profile.name.is_empty() {
+                return Err(Error::invalid_config("Quirk profile needs a name"));
+            }
+            profiles.insert(profile.name.clone(), profile.clone());
+        }
+        if let Some((trunk, name)) = config.trunks.iter().find(|(_, name)| !profiles.contains_key(*name)) {
+            return Err(Error::invalid_config(format!("Trunk {} uses unknown quirk profile {}", trunk, name)));
+        }
+
+        Ok(Self {
+            profiles,
+            trunks: config.trunks.clone(),
+        })
+    }
+
+    /// Profile assigned to `trunk`, if any
+    pub fn for_trunk(&self, trunk: &str) -> Option<&QuirkProfile> {
+        self.trunks.get(trunk).and_then(|name| self.profiles.get(name))
+    }
+
+    /// Every known profile, built-in and configured
+    pub fn profiles(&self) -> impl Iterator<Item = &QuirkProfile> {
+        self.profiles.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trunks_resolve_builtin_and_configured_profiles() {
+        let config = QuirksConfig {
+            profiles: vec![QuirkProfile {
+                name: "carrier-x".to_string(),
+                user_phone: true,
+                max_udp_size: Some(1300),
+                ..Default::default()
+            }],
+            trunks: [
+                ("sbc.carrier-x.net".to_string(), "carrier-x".to_string()),
+                ("old.switch.net".to_string(), "legacy-softswitch".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let registry = QuirkRegistry::new(&config).unwrap();
+
+        assert!(registry.for_trunk("old.switch.net").unwrap().no_prack);
+        assert_eq!(registry.for_trunk("old.switch.net").unwrap().mid_dialog_method(), "UPDATE");
+        assert!(registry.for_trunk("other.net").is_none());
+
+        let carrier_x = registry.for_trunk("sbc.carrier-x.net").unwrap();
+        assert_eq!(carrier_x.request_uri("sip:5551234@sbc.carrier-x.net", 900), "sip:5551234@sbc.carrier-x.net;user=phone");
+        assert_eq!(
+            carrier_x.request_uri("sip:5551234@sbc.carrier-x.net", 1500),
+            "sip:5551234@sbc.carrier-x.net;user=phone;transport=tcp"
+        );
+
+        let mut unknown = config.clone();
+        unknown.trunks.insert("new.net".to_string(), "missing".to_string());
+        assert!(QuirkRegistry::new(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_prack_and_early_media_workarounds() {
+        let profile = QuirkProfile {
+            name: "test".to_string(),
+            no_prack: true,
+            suppress_183: true,
+            ..Default::default()
+        };
+        let mut headers = vec![
+            ("Supported".to_string(), "timer, 100rel".to_string()),
+            ("Require".to_string(), "100rel".to_string()),
+            ("X-Account".to_string(), "42".to_string()),
+        ];
+        profile.filter_headers(&mut headers);
+        assert_eq!(headers, vec![
+            ("Supported".to_string(), "timer".to_string()),
+            ("X-Account".to_string(), "42".to_string()),
+        ]);
+
+        assert_eq!(profile.provisional(183), (180, false));
+        assert_eq!(profile.provisional(180), (180, true));
+        assert_eq!(QuirkProfile::default().provisional(183), (183, true));
+    }
+}
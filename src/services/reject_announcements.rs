@@ -0,0 +1,137 @@
+//! Announcements played to callers the gateway refuses
+//!
+//! A bare SIP failure code is often masked by the caller's provider into a
+//! generic tone. Where an announcement is configured for the reason a call
+//! is refused, leg A is instead given early media (or answered, for
+//! providers that drop early media) and hears the announcement, after which
+//! the media server releases it with the status and Q.850 cause the plain
+//! rejection would have carried. Rules are set per reason and can be
+//! overridden per ingress trunk, identified by the caller's host.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{RejectAnnouncementConfig, RejectAnnouncementRule};
+
+/// Q.850 call rejected
+pub const CAUSE_CALL_REJECTED: u16 = 21;
+
+/// Q.850 unallocated number
+pub const CAUSE_UNALLOCATED_NUMBER: u16 = 1;
+
+/// Q.850 no circuit/channel available
+pub const CAUSE_NO_CIRCUIT_AVAILABLE: u16 = 34;
+
+/// Q.850 switching equipment congestion
+pub const CAUSE_SWITCHING_EQUIPMENT_CONGESTION: u16 = 42;
+
+/// Why the gateway refuses a call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RejectReason {
+    /// Caller or destination barred, e.g. by the routing hook
+    Blocked,
+    /// Concurrent call limit reached
+    CallLimit,
+    /// The dialed number does not exist or is incomplete
+    InvalidDestination,
+    /// Call setup rate or destination gap in force
+    Congestion,
+}
+
+impl RejectReason {
+    pub const ALL: [RejectReason; 4] = [
+        RejectReason::Blocked,
+        RejectReason::CallLimit,
+        RejectReason::InvalidDestination,
+        RejectReason::Congestion,
+    ];
+
+    /// Configuration key of the reason
+    pub fn key(self) -> &'static str {
+        match self {
+            RejectReason::Blocked => "blocked",
+            RejectReason::CallLimit => "call-limit",
+            RejectReason::InvalidDestination => "invalid-destination",
+            RejectReason::Congestion => "congestion",
+        }
+    }
+
+    /// Reason behind a rejection status chosen elsewhere, e.g. by the routing hook
+    pub fn from_status(status_code: u16) -> Option<Self> {
+        match status_code {
+            403 | 603 => Some(RejectReason::Blocked),
+            404 | 410 | 484 | 604 => Some(RejectReason::InvalidDestination),
+            503 => Some(RejectReason::Congestion),
+            _ => None,
+        }
+    }
+
+    /// Q.850 cause the call is released with
+    pub fn q850_cause(self) -> u16 {
+        match self {
+            RejectReason::Blocked => CAUSE_CALL_REJECTED,
+            RejectReason::CallLimit => CAUSE_NO_CIRCUIT_AVAILABLE,
+            RejectReason::InvalidDestination => CAUSE_UNALLOCATED_NUMBER,
+            RejectReason::Congestion => CAUSE_SWITCHING_EQUIPMENT_CONGESTION,
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.key())
+    }
+}
+
+/// Rule for refusing a call for `reason` from `trunk`: the trunk's own rule
+/// first, then the reason's. A rule without an announcement sends only the
+/// SIP status, so a trunk can opt out of a reason announced elsewhere
+pub fn rule_for<'a>(
+    config: &'a RejectAnnouncementConfig,
+    reason: RejectReason,
+    trunk: Option<&str>,
+) -> Option<&'a RejectAnnouncementRule> {
+    if !config.enabled {
+        return None;
+    }
+    trunk
+        .and_then(|trunk| config.trunks.get(trunk))
+        .and_then(|rules| rules.get(reason.key()))
+        .or_else(|| config.causes.get(reason.key()))
+        .filter(|rule| !rule.announcement.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_rule_selection() {
+        let mut config = RejectAnnouncementConfig { enabled: true, ..Default::default() };
+        config.causes.insert(
+            "blocked".to_string(),
+            RejectAnnouncementRule { announcement: "number-blocked".to_string(), answer: false },
+        );
+        config.trunks.insert(
+            "carrier-a.example.com".to_string(),
+            BTreeMap::from([
+                ("blocked".to_string(), RejectAnnouncementRule { announcement: "number-blocked".to_string(), answer: true }),
+                ("call-limit".to_string(), RejectAnnouncementRule::default()),
+            ]),
+        );
+
+        let rule = rule_for(&config, RejectReason::Blocked, Some("carrier-a.example.com")).unwrap();
+        assert!(rule.answer);
+        assert!(!rule_for(&config, RejectReason::Blocked, Some("carrier-b.example.com")).unwrap().answer);
+        assert_eq!(rule_for(&config, RejectReason::CallLimit, None).unwrap().announcement, "all-circuits-busy");
+        // The trunk opted out of the default call limit announcement
+        assert!(rule_for(&config, RejectReason::CallLimit, Some("carrier-a.example.com")).is_none());
+
+        assert_eq!(RejectReason::from_status(484), Some(RejectReason::InvalidDestination));
+        assert_eq!(RejectReason::from_status(486), None);
+        assert_eq!(RejectReason::Blocked.q850_cause(), 21);
+    }
+}
@@ -0,0 +1,332 @@
+//! Release cause statistics
+//!
+//! Every B2BUA call ends with a Q.850 cause, either one chosen by the
+//! gateway or the RFC 3398 equivalent of leg B's failure status. Releases
+//! are counted per hour, trunk, route and cause, with the called number
+//! prefixes behind each count, so the NOC can see which failures dominate
+//! and which destinations drive them. Causes that reflect the called party
+//! (normal clearing, busy, no answer, rejected) are counted but not ranked
+//! as failures.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeZone, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ReleaseCauseConfig;
+
+/// Management API path reporting release causes and the top failures
+pub const RELEASE_CAUSES_PATH: &str = "/api/v1/b2bua/release-causes";
+
+pub const CAUSE_NORMAL_CLEARING: u16 = 16;
+
+/// Trunks and destinations listed behind each ranked failure cause
+const TOP_DRIVERS: usize = 5;
+
+/// Causes set by the called party rather than a failure along the way
+const SUBSCRIBER_CAUSES: [u16; 6] = [16, 17, 18, 19, 21, 31];
+
+/// Q.850 equivalent of a SIP failure status, per RFC 3398 §8.2.6.1
+pub fn cause_for_sip_status(status: u16) -> u16 {
+    match status {
+        404 | 485 | 604 => 1,
+        410 => 22,
+        484 | 414 => 28,
+        486 | 600 => 17,
+        480 => 18,
+        401 | 402 | 403 | 407 | 603 => 21,
+        482 | 483 => 25,
+        405 => 63,
+        406 | 415 | 501 => 79,
+        502 => 38,
+        400 | 481 | 500 | 503 => 41,
+        408 | 504 => 102,
+        606 => 58,
+        _ => 127,
+    }
+}
+
+/// Short Q.850 name of `cause`
+pub fn cause_description(cause: u16) -> &'static str {
+    match cause {
+        1 => "Unallocated number",
+        2 => "No route to specified transit network",
+        3 => "No route to destination",
+        16 => "Normal call clearing",
+        17 => "User busy",
+        18 => "No user responding",
+        19 => "No answer from user",
+        21 => "Call rejected",
+        22 => "Number changed",
+        25 => "Exchange routing error",
+        27 => "Destination out of order",
+        28 => "Invalid number format",
+        29 => "Facility rejected",
+        31 => "Normal, unspecified",
+        34 => "No circuit/channel available",
+        38 => "Network out of order",
+        41 => "Temporary failure",
+        42 => "Switching equipment congestion",
+        44 => "Requested channel not available",
+        47 => "Resource unavailable, unspecified",
+        58 => "Bearer capability not presently available",
+        63 => "Service or option not available",
+        65 => "Bearer capability not implemented",
+        79 => "Service or option not implemented",
+        88 => "Incompatible destination",
+        102 => "Recovery on timer expiry",
+        111 => "Protocol error, unspecified",
+        127 => "Interworking, unspecified",
+        _ => "Unknown cause",
+    }
+}
+
+/// Whether `cause` counts towards the failure ranking
+pub fn is_failure(cause: u16) -> bool {
+    !SUBSCRIBER_CAUSES.contains(&cause)
+}
+
+/// Filter and size of a release cause report
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ReleaseCauseQuery {
+    /// Hours back from now, up to the retention
+    pub hours: Option<u32>,
+    pub trunk: Option<String>,
+    pub route: Option<String>,
+    /// Failure causes ranked
+    pub top: Option<usize>,
+}
+
+/// Releases attributed to one trunk or destination prefix
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReleaseDriver {
+    pub name: String,
+    pub calls: u64,
+}
+
+/// One ranked failure cause
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FailureCause {
+    pub cause: u16,
+    pub description: String,
+    pub calls: u64,
+    /// Percent of all failed releases in the report
+    pub share: f64,
+    pub trunks: Vec<ReleaseDriver>,
+    /// Called number prefixes
+    pub destinations: Vec<ReleaseDriver>,
+}
+
+/// Releases with one cause on one trunk and route in one hour
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReleaseCauseCount {
+    pub hour: DateTime<Utc>,
+    pub trunk: String,
+    pub route: String,
+    pub cause: u16,
+    pub calls: u64,
+}
+
+/// Release causes over the requested hours
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReleaseCauseReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub releases: u64,
+    pub failures: u64,
+    /// Failure causes, most frequent first
+    pub top_failures: Vec<FailureCause>,
+    pub hourly: Vec<ReleaseCauseCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BucketKey {
+    hour: i64,
+    trunk: String,
+    route: String,
+    cause: u16,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    calls: u64,
+    destinations: HashMap<String, u64>,
+}
+
+/// Release counts per hour, trunk, route and cause
+pub struct ReleaseCauseStats {
+    config: ReleaseCauseConfig,
+    buckets: DashMap<BucketKey, Bucket>,
+}
+
+fn hour_of(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(3600)
+}
+
+fn hour_start(hour: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(hour * 3600, 0).single().unwrap_or_default()
+}
+
+/// Most frequent first, ties by name
+fn ranked(counts: HashMap<String, u64>, limit: usize) -> Vec<ReleaseDriver> {
+    let mut drivers: Vec<ReleaseDriver> = counts.into_iter().map(|(name, calls)| ReleaseDriver { name, calls }).collect();
+    drivers.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.name.cmp(&b.name)));
+    drivers.truncate(limit);
+    drivers
+}
+
+impl ReleaseCauseStats {
+    pub fn new(config: ReleaseCauseConfig) -> Self {
+        Self {
+            config,
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Count a call to `destination` released with `cause`
+    pub fn record(&self, trunk: &str, route: &str, destination: &str, cause: u16, at: DateTime<Utc>) {
+        if !self.config.enabled {
+            return;
+        }
+        let hour = hour_of(at);
+        let key = BucketKey {
+            hour,
+            trunk: trunk.to_string(),
+            route: route.to_string(),
+            cause,
+        };
+        let prefix: String = destination
+            .chars()
+            .filter(char::is_ascii_digit)
+            .take(self.config.destination_digits)
+            .collect();
+        let new_bucket = !self.buckets.contains_key(&key);
+        {
+            let mut bucket = self.buckets.entry(key).or_default();
+            bucket.calls += 1;
+            *bucket.destinations.entry(prefix).or_default() += 1;
+        }
+        if new_bucket {
+            let oldest = hour - i64::from(self.config.retention_hours) + 1;
+            self.buckets.retain(|key, _| key.hour >= oldest);
+        }
+    }
+
+    /// Releases over the last `query.hours`, with the failure causes ranked
+    pub fn report(&self, query: &ReleaseCauseQuery, now: DateTime<Utc>) -> ReleaseCauseReport {
+        let hours = query.hours.unwrap_or(self.config.retention_hours).clamp(1, self.config.retention_hours.max(1));
+        let top = query.top.unwrap_or(self.config.top);
+        let current = hour_of(now);
+        let oldest = current - i64::from(hours) + 1;
+
+        let mut releases = 0;
+        let mut failures = 0;
+        let mut hourly = Vec::new();
+        let mut causes: HashMap<u16, (u64, HashMap<String, u64>, HashMap<String, u64>)> = HashMap::new();
+        for entry in self.buckets.iter() {
+            let (key, bucket) = (entry.key(), entry.value());
+            let selected = key.hour >= oldest
+                && key.hour <= current
+                && query.trunk.as_ref().map_or(true, |trunk| *trunk == key.trunk)
+                && query.route.as_ref().map_or(true, |route| *route == key.route);
+            if !selected {
+                continue;
+            }
+            releases += bucket.calls;
+            hourly.push(ReleaseCauseCount {
+                hour: hour_start(key.hour),
+                trunk: key.trunk.clone(),
+                route: key.route.clone(),
+                cause: key.cause,
+                calls: bucket.calls,
+            });
+            if !is_failure(key.cause) {
+                continue;
+            }
+            failures += bucket.calls;
+            let (calls, trunks, destinations) = causes.entry(key.cause).or_default();
+            *calls += bucket.calls;
+            *trunks.entry(key.trunk.clone()).or_default() += bucket.calls;
+            for (prefix, count) in &bucket.destinations {
+                *destinations.entry(prefix.clone()).or_default() += count;
+            }
+        }
+
+        let mut top_failures: Vec<FailureCause> = causes
+            .into_iter()
+            .map(|(cause, (calls, trunks, destinations))| FailureCause {
+                cause,
+                description: cause_description(cause).to_string(),
+                calls,
+                share: calls as f64 * 100.0 / failures as f64,
+                trunks: ranked(trunks, TOP_DRIVERS),
+                destinations: ranked(destinations, TOP_DRIVERS),
+            })
+            .collect();
+        top_failures.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.cause.cmp(&b.cause)));
+        top_failures.truncate(top);
+        hourly.sort_by(|a, b| {
+            (a.hour, &a.trunk, &a.route, a.cause).cmp(&(b.hour, &b.trunk, &b.route, b.cause))
+        });
+
+        ReleaseCauseReport {
+            from: hour_start(oldest),
+            to: now,
+            releases,
+            failures,
+            top_failures,
+            hourly,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_sip_status_mapping() {
+        assert_eq!(cause_for_sip_status(404), 1);
+        assert_eq!(cause_for_sip_status(486), 17);
+        assert_eq!(cause_for_sip_status(503), 41);
+        assert_eq!(cause_for_sip_status(504), 102);
+        assert_eq!(cause_for_sip_status(599), 127);
+        assert!(!is_failure(cause_for_sip_status(486)));
+        assert!(is_failure(cause_for_sip_status(503)));
+        assert_eq!(cause_description(34), "No circuit/channel available");
+    }
+
+    #[test]
+    fn test_top_failures_and_destinations() {
+        let stats = ReleaseCauseStats::new(ReleaseCauseConfig::default());
+        let now = Utc.with_ymd_and_hms(2026, 10, 15, 12, 30, 0).unwrap();
+        for _ in 0..3 {
+            stats.record("carrier-a", "intl", "+44 20 7946 0000", 41, now);
+        }
+        stats.record("carrier-b", "intl", "442079460001", 41, now - Duration::hours(1));
+        stats.record("carrier-a", "intl", "33140000000", 1, now);
+        stats.record("carrier-a", "local", "5551234", 16, now);
+        stats.record("carrier-a", "local", "5551234", 17, now);
+        // Outside the report window
+        stats.record("carrier-a", "intl", "442079460000", 1, now - Duration::hours(5));
+
+        let report = stats.report(&ReleaseCauseQuery { hours: Some(2), ..Default::default() }, now);
+        assert_eq!((report.releases, report.failures), (7, 5));
+        assert_eq!(report.from, Utc.with_ymd_and_hms(2026, 10, 15, 11, 0, 0).unwrap());
+        let causes: Vec<(u16, u64)> = report.top_failures.iter().map(|failure| (failure.cause, failure.calls)).collect();
+        assert_eq!(causes, vec![(41, 4), (1, 1)]);
+        let congestion = &report.top_failures[0];
+        assert_eq!(congestion.share, 80.0);
+        assert_eq!(congestion.destinations, vec![ReleaseDriver { name: "442079".to_string(), calls: 4 }]);
+        assert_eq!(congestion.trunks[0], ReleaseDriver { name: "carrier-a".to_string(), calls: 3 });
+        assert_eq!(report.hourly.first().map(|count| count.trunk.as_str()), Some("carrier-b"));
+
+        let query = ReleaseCauseQuery { route: Some("local".to_string()), top: Some(1), ..Default::default() };
+        let local = stats.report(&query, now);
+        assert_eq!((local.releases, local.failures), (2, 0));
+        assert!(local.top_failures.is_empty());
+    }
+}
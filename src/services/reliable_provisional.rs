@@ -0,0 +1,374 @@
+//! Reliable provisional responses (RFC 3262) on both legs of a B2BUA call
+//!
+//! Leg B is offered 100rel; each reliable 18x it sends is PRACKed once and
+//! relayed in RSeq order, so ringing and early media reach the caller exactly
+//! once. Leg A gets its 18x reliably when its INVITE supports 100rel: one at
+//! a time, retransmitted from T1 with doubling until PRACKed, and a 2xx is
+//! held back while a provisional carrying SDP is unacknowledged. Each
+//! provisional is also mapped to the Q.931 ALERTING or PROGRESS the TDM side
+//! of the call signals, and the reverse for calls broken out to a span.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::config::ReliableProvisionalConfig;
+use crate::protocols::q931::{MessageType, Q931Message};
+use crate::services::answer_supervision::Q931_PI_INBAND_AVAILABLE;
+
+/// Option tag of the reliable provisional extension
+pub const OPTION_TAG: &str = "100rel";
+
+pub const RSEQ_HEADER: &str = "RSeq";
+pub const RACK_HEADER: &str = "RAck";
+
+/// Response to an INVITE requiring an extension we do not support
+pub const STATUS_BAD_EXTENSION: u16 = 420;
+
+/// Q.931 progress description: call is not end-to-end ISDN
+pub const Q931_PI_NOT_END_TO_END_ISDN: u8 = 1;
+
+/// RTT estimate the retransmission interval starts from
+pub const T1: Duration = Duration::from_millis(500);
+
+/// Value of the first header called `name` or its compact form
+fn header<'a>(headers: &'a [(String, String)], name: &str, compact: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name) || header.eq_ignore_ascii_case(compact))
+        .map(|(_, value)| value.as_str())
+}
+
+fn lists_100rel(headers: &[(String, String)], names: &[&str]) -> bool {
+    headers
+        .iter()
+        .filter(|(name, _)| names.iter().any(|known| name.eq_ignore_ascii_case(known)))
+        .flat_map(|(_, value)| value.split(','))
+        .any(|tag| tag.trim().eq_ignore_ascii_case(OPTION_TAG))
+}
+
+/// The request or response supports or requires 100rel
+pub fn supported(headers: &[(String, String)]) -> bool {
+    lists_100rel(headers, &["Supported", "k", "Require"])
+}
+
+/// The request or response requires 100rel
+pub fn required(headers: &[(String, String)]) -> bool {
+    lists_100rel(headers, &["Require"])
+}
+
+/// RSeq of a reliable provisional response
+pub fn rseq(headers: &[(String, String)]) -> Option<u32> {
+    header(headers, RSEQ_HEADER, RSEQ_HEADER)?.trim().parse().ok()
+}
+
+/// Sequence number of the CSeq header
+pub fn cseq(headers: &[(String, String)]) -> Option<u32> {
+    header(headers, "CSeq", "CSeq")?.split_whitespace().next()?.parse().ok()
+}
+
+/// Offer, or require, 100rel in the INVITE placing leg B
+pub fn add_request_headers(config: &ReliableProvisionalConfig, headers: &mut BTreeMap<String, String>) {
+    let name = if config.require_on_leg_b { "Require" } else { "Supported" };
+    let tags = headers.entry(name.to_string()).or_default();
+    if !tags.split(',').any(|tag| tag.trim().eq_ignore_ascii_case(OPTION_TAG)) {
+        if !tags.is_empty() {
+            tags.push_str(", ");
+        }
+        tags.push_str(OPTION_TAG);
+    }
+}
+
+/// RAck header of a PRACK: the RSeq and CSeq of the response acknowledged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RAck {
+    pub rseq: u32,
+    pub cseq: u32,
+    pub method: String,
+}
+
+impl RAck {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split_whitespace();
+        let rseq = parts.next()?.parse().ok()?;
+        let cseq = parts.next()?.parse().ok()?;
+        let method = parts.next()?.to_ascii_uppercase();
+        parts.next().is_none().then_some(Self { rseq, cseq, method })
+    }
+}
+
+impl fmt::Display for RAck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.rseq, self.cseq, self.method)
+    }
+}
+
+/// A provisional response to send reliably to leg A
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provisional {
+    pub status_code: u16,
+    pub reason: String,
+    pub sdp: Option<String>,
+}
+
+/// Reliable provisional ready to go out, with its Require and RSeq headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReliableSend {
+    pub provisional: Provisional,
+    pub rseq: u32,
+}
+
+impl ReliableSend {
+    pub fn headers(&self) -> Vec<(String, String)> {
+        vec![
+            ("Require".to_string(), OPTION_TAG.to_string()),
+            (RSEQ_HEADER.to_string(), self.rseq.to_string()),
+        ]
+    }
+}
+
+/// What the sender's retransmission timer asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SenderAction {
+    Retransmit(ReliableSend),
+    /// No PRACK within 64*T1; the INVITE is to be refused with a 5xx
+    TimedOut,
+}
+
+#[derive(Debug, Clone)]
+struct Outstanding {
+    send: ReliableSend,
+    interval: Duration,
+    retransmit_at: Instant,
+    give_up_at: Instant,
+}
+
+/// 2xx from leg B waiting for leg A to PRACK a provisional carrying SDP
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeferredAnswer {
+    pub sdp: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+/// UAS side of 100rel towards leg A. Only one reliable provisional is
+/// outstanding at a time; later ones queue behind its PRACK
+#[derive(Debug, Clone)]
+pub struct ReliableSender {
+    invite_cseq: u32,
+    next_rseq: u32,
+    outstanding: Option<Outstanding>,
+    queue: VecDeque<Provisional>,
+    pub deferred_answer: Option<DeferredAnswer>,
+}
+
+impl ReliableSender {
+    /// Sender for the INVITE with sequence number `invite_cseq`, starting
+    /// from a random RSeq as the RFC asks
+    pub fn new(invite_cseq: u32) -> Self {
+        Self::with_rseq(invite_cseq, rand::thread_rng().gen_range(1..=(1 << 31) - 1))
+    }
+
+    pub fn with_rseq(invite_cseq: u32, first_rseq: u32) -> Self {
+        Self {
+            invite_cseq,
+            next_rseq: first_rseq,
+            outstanding: None,
+            queue: VecDeque::new(),
+            deferred_answer: None,
+        }
+    }
+
+    /// Queue a provisional, returning it if it can go out straight away
+    pub fn send(&mut self, provisional: Provisional, now: Instant) -> Option<ReliableSend> {
+        self.queue.push_back(provisional);
+        self.next(now)
+    }
+
+    fn next(&mut self, now: Instant) -> Option<ReliableSend> {
+        if self.outstanding.is_some() {
+            return None;
+        }
+        let provisional = self.queue.pop_front()?;
+        let send = ReliableSend { provisional, rseq: self.next_rseq };
+        self.next_rseq = self.next_rseq.wrapping_add(1);
+        self.outstanding = Some(Outstanding {
+            send: send.clone(),
+            interval: T1,
+            retransmit_at: now + T1,
+            give_up_at: now + T1 * 64,
+        });
+        Some(send)
+    }
+
+    /// Match a PRACK against the outstanding provisional. A match releases
+    /// the next queued provisional, if any; `None` means no match and the
+    /// PRACK is answered 481
+    pub fn acknowledge(&mut self, rack: &RAck, now: Instant) -> Option<Option<ReliableSend>> {
+        let matches = self.outstanding.as_ref().is_some_and(|outstanding| {
+            rack.rseq == outstanding.send.rseq && rack.cseq == self.invite_cseq && rack.method == "INVITE"
+        });
+        if !matches {
+            return None;
+        }
+        self.outstanding = None;
+        Some(self.next(now))
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Option<SenderAction> {
+        let outstanding = self.outstanding.as_mut()?;
+        if now >= outstanding.give_up_at {
+            self.outstanding = None;
+            self.queue.clear();
+            return Some(SenderAction::TimedOut);
+        }
+        if now < outstanding.retransmit_at {
+            return None;
+        }
+        outstanding.interval *= 2;
+        outstanding.retransmit_at = now + outstanding.interval;
+        Some(SenderAction::Retransmit(outstanding.send.clone()))
+    }
+
+    /// A 2xx must wait while an unacknowledged provisional carries SDP
+    pub fn holds_answer(&self) -> bool {
+        self.outstanding
+            .iter()
+            .map(|outstanding| &outstanding.send.provisional)
+            .chain(&self.queue)
+            .any(|provisional| provisional.sdp.is_some())
+    }
+
+    /// Nothing left to send or acknowledge, e.g. once the INVITE is answered
+    pub fn is_idle(&self) -> bool {
+        self.outstanding.is_none() && self.queue.is_empty()
+    }
+}
+
+/// UAC side of 100rel on one leg B branch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReliableReceiver {
+    last_rseq: Option<u32>,
+}
+
+impl ReliableReceiver {
+    /// Whether a reliable provisional with `rseq` is new and in order, so it
+    /// is processed and PRACKed. Retransmissions and responses arriving
+    /// ahead of a missing one are discarded
+    pub fn receive(&mut self, rseq: u32) -> bool {
+        let in_order = self.last_rseq.map_or(true, |last| rseq == last.wrapping_add(1));
+        if in_order {
+            self.last_rseq = Some(rseq);
+        }
+        in_order
+    }
+}
+
+/// 100rel state of a call: the sender towards leg A when it supports the
+/// extension, and a receiver per leg B branch
+#[derive(Debug, Clone, Default)]
+pub struct CallProvisionals {
+    pub leg_a: Option<ReliableSender>,
+    pub leg_b: BTreeMap<String, ReliableReceiver>,
+    /// The TDM side has been sent ALERTING; later ringing maps to PROGRESS
+    pub alerted: bool,
+}
+
+/// Q.931 message the TDM side of a call signals for a provisional
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TdmIndication {
+    pub message_type: MessageType,
+    pub progress_indicator: Option<u8>,
+}
+
+/// Map an 18x to Q.931. 180 is ALERTING, once per call; 183 with SDP, or
+/// ringing after ALERTING with SDP, is PROGRESS with in-band information.
+/// Early media on 180 travels as progress indicator 8 on the ALERTING.
+/// Anything else, e.g. 183 without SDP, has no TDM counterpart
+pub fn tdm_indication(status_code: u16, has_sdp: bool, alerted: bool) -> Option<TdmIndication> {
+    let inband = has_sdp.then_some(Q931_PI_INBAND_AVAILABLE);
+    match status_code {
+        180 if !alerted => Some(TdmIndication { message_type: MessageType::Alerting, progress_indicator: inband }),
+        180 | 183 if has_sdp => Some(TdmIndication { message_type: MessageType::Progress, progress_indicator: inband }),
+        _ => None,
+    }
+}
+
+/// Map ALERTING or PROGRESS from a TDM span to the 18x for the SIP caller,
+/// and whether it comes with in-band media
+pub fn provisional_for_tdm(message: &Q931Message) -> Option<(u16, bool)> {
+    let inband = matches!(message.progress(), Some(Q931_PI_NOT_END_TO_END_ISDN | Q931_PI_INBAND_AVAILABLE));
+    match message.message_type {
+        MessageType::Alerting => Some((180, inband)),
+        MessageType::Progress if inband => Some((183, true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provisional(status_code: u16, sdp: bool) -> Provisional {
+        Provisional {
+            status_code,
+            reason: "Ringing".to_string(),
+            sdp: sdp.then(|| "v=0".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_sender_queues_and_retransmits() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::with_rseq(1, 100);
+        let first = sender.send(provisional(180, false), start).unwrap();
+        assert_eq!(first.rseq, 100);
+        assert_eq!(first.headers()[1], ("RSeq".to_string(), "100".to_string()));
+        // The second waits for the first to be acknowledged
+        assert!(sender.send(provisional(183, true), start).is_none());
+        assert!(sender.holds_answer());
+
+        assert!(sender.poll(start + Duration::from_millis(100)).is_none());
+        assert!(matches!(sender.poll(start + T1), Some(SenderAction::Retransmit(send)) if send.rseq == 100));
+        assert!(sender.poll(start + T1 * 2).is_none());
+        assert!(matches!(sender.poll(start + T1 * 3), Some(SenderAction::Retransmit(_))));
+
+        assert!(sender.acknowledge(&RAck::parse("101 1 INVITE").unwrap(), start).is_none());
+        let next = sender.acknowledge(&RAck::parse("100 1 invite").unwrap(), start).unwrap().unwrap();
+        assert_eq!((next.rseq, next.provisional.status_code), (101, 183));
+        assert_eq!(sender.poll(start + T1 * 64), Some(SenderAction::TimedOut));
+        assert!(sender.is_idle());
+    }
+
+    #[test]
+    fn test_receiver_ordering_and_tdm_mapping() {
+        let mut receiver = ReliableReceiver::default();
+        assert!(receiver.receive(7));
+        assert!(!receiver.receive(7));
+        assert!(!receiver.receive(9));
+        assert!(receiver.receive(8));
+        assert_eq!(RAck { rseq: 8, cseq: 2, method: "INVITE".to_string() }.to_string(), "8 2 INVITE");
+
+        let alerting = tdm_indication(180, false, false).unwrap();
+        assert_eq!((alerting.message_type, alerting.progress_indicator), (MessageType::Alerting, None));
+        let progress = tdm_indication(183, true, true).unwrap();
+        assert_eq!((progress.message_type, progress.progress_indicator), (MessageType::Progress, Some(8)));
+        assert!(tdm_indication(180, false, true).is_none());
+        assert!(tdm_indication(183, false, false).is_none());
+
+        let message = Q931Message::new(MessageType::Progress, 1, true).with_progress(Q931_PI_INBAND_AVAILABLE);
+        assert_eq!(provisional_for_tdm(&message), Some((183, true)));
+        assert_eq!(provisional_for_tdm(&Q931Message::new(MessageType::Alerting, 1, true)), Some((180, false)));
+        assert!(provisional_for_tdm(&Q931Message::new(MessageType::Progress, 1, true)).is_none());
+
+        let mut headers = BTreeMap::from([("Supported".to_string(), "timer".to_string())]);
+        add_request_headers(&ReliableProvisionalConfig::default(), &mut headers);
+        assert_eq!(headers["Supported"], "timer, 100rel");
+        let config = ReliableProvisionalConfig { require_on_leg_b: true, ..Default::default() };
+        add_request_headers(&config, &mut headers);
+        assert_eq!(headers["Require"], "100rel");
+        assert!(supported(&[("k".to_string(), "100rel".to_string())]));
+        assert!(!required(&[("Supported".to_string(), "100rel".to_string())]));
+    }
+}
@@ -0,0 +1,135 @@
+//! Failover decisions for leg B failure responses
+//!
+//! Carriers disagree on what a failure status means: a 503 from one is a
+//! transient outage worth another target, a 403 from another is a final
+//! policy answer. Each final 4xx/5xx/6xx on leg B is looked up in the
+//! route's table, then the trunk's, then the gateway defaults, and either
+//! moves the call to the route's next alternate or is passed back to the
+//! caller. Outcomes are counted per trunk and status for the NOC.
+
+use std::collections::BTreeMap;
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{RerouteAction, RerouteConfig};
+
+/// Management API path listing re-route counters
+pub const REROUTE_COUNTERS_PATH: &str = "/api/v1/b2bua/reroutes";
+
+/// Failure outcomes for one trunk and status
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RerouteCounter {
+    pub trunk: String,
+    pub status: u16,
+    pub rerouted: u64,
+    pub released: u64,
+}
+
+/// Classifies leg B failures and counts what was done with them
+pub struct RerouteDecider {
+    config: RerouteConfig,
+    counters: DashMap<(String, u16), RerouteCounter>,
+}
+
+impl RerouteDecider {
+    pub fn new(config: RerouteConfig) -> Self {
+        Self {
+            config,
+            counters: DashMap::new(),
+        }
+    }
+
+    pub fn max_attempts(&self) -> usize {
+        self.config.max_attempts
+    }
+
+    /// Action for `status` on `trunk`, with the route's own table taking precedence
+    pub fn action(&self, status: u16, trunk: &str, route_actions: &BTreeMap<String, RerouteAction>) -> RerouteAction {
+        let trunk_actions = self.config.trunk_actions.get(trunk);
+        [Some(route_actions), trunk_actions, Some(&self.config.default_actions)]
+            .into_iter()
+            .flatten()
+            .find_map(|actions| lookup(actions, status))
+            .unwrap_or(RerouteAction::Release)
+    }
+
+    pub fn record(&self, trunk: &str, status: u16, action: RerouteAction) {
+        let mut counter = self
+            .counters
+            .entry((trunk.to_string(), status))
+            .or_insert_with(|| RerouteCounter {
+                trunk: trunk.to_string(),
+                status,
+                ..Default::default()
+            });
+        match action {
+            RerouteAction::Reroute => counter.rerouted += 1,
+            RerouteAction::Release => counter.released += 1,
+        }
+    }
+
+    /// Counters ordered by trunk, then status
+    pub fn counters(&self) -> Vec<RerouteCounter> {
+        let mut counters: Vec<RerouteCounter> = self.counters.iter().map(|entry| entry.value().clone()).collect();
+        counters.sort_by(|a, b| (&a.trunk, a.status).cmp(&(&b.trunk, b.status)));
+        counters
+    }
+}
+
+/// Exact status first, then its class
+fn lookup(actions: &BTreeMap<String, RerouteAction>, status: u16) -> Option<RerouteAction> {
+    actions
+        .get(&status.to_string())
+        .or_else(|| actions.get(&format!("{}xx", status / 100)))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(entries: &[(&str, RerouteAction)]) -> BTreeMap<String, RerouteAction> {
+        entries.iter().map(|(key, action)| (key.to_string(), *action)).collect()
+    }
+
+    #[test]
+    fn test_defaults_follow_carrier_semantics() {
+        let decider = RerouteDecider::new(RerouteConfig::default());
+        let none = BTreeMap::new();
+        assert_eq!(decider.action(503, "carrier-a", &none), RerouteAction::Reroute);
+        assert_eq!(decider.action(502, "carrier-a", &none), RerouteAction::Reroute);
+        assert_eq!(decider.action(486, "carrier-a", &none), RerouteAction::Release);
+        assert_eq!(decider.action(404, "carrier-a", &none), RerouteAction::Release);
+        assert_eq!(decider.action(600, "carrier-a", &none), RerouteAction::Release);
+        assert_eq!(decider.action(408, "carrier-a", &none), RerouteAction::Reroute);
+    }
+
+    #[test]
+    fn test_route_then_trunk_overrides_and_counters() {
+        let mut config = RerouteConfig::default();
+        config
+            .trunk_actions
+            .insert("carrier-b".to_string(), actions(&[("403", RerouteAction::Reroute), ("5xx", RerouteAction::Release)]));
+        let decider = RerouteDecider::new(config);
+
+        let none = BTreeMap::new();
+        assert_eq!(decider.action(403, "carrier-a", &none), RerouteAction::Release);
+        assert_eq!(decider.action(403, "carrier-b", &none), RerouteAction::Reroute);
+        // The trunk's class entry beats the default exact entry
+        assert_eq!(decider.action(503, "carrier-b", &none), RerouteAction::Release);
+
+        let route = actions(&[("403", RerouteAction::Release), ("486", RerouteAction::Reroute)]);
+        assert_eq!(decider.action(403, "carrier-b", &route), RerouteAction::Release);
+        assert_eq!(decider.action(486, "carrier-a", &route), RerouteAction::Reroute);
+
+        decider.record("carrier-b", 503, RerouteAction::Reroute);
+        decider.record("carrier-b", 503, RerouteAction::Reroute);
+        decider.record("carrier-a", 486, RerouteAction::Release);
+        assert_eq!(decider.counters(), vec![
+            RerouteCounter { trunk: "carrier-a".to_string(), status: 486, rerouted: 0, released: 1 },
+            RerouteCounter { trunk: "carrier-b".to_string(), status: 503, rerouted: 2, released: 0 },
+        ]);
+    }
+}
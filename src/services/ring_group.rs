@@ -0,0 +1,183 @@
+//! Ring groups: forking inbound calls to several SIP targets
+//!
+//! A routing rule whose target names a ring group offers the call to the
+//! group's members instead of a single leg B. Simultaneous groups ring every
+//! member at once and CANCEL the others when one answers; sequential groups
+//! hunt through the members, cancelling each after its ring time. Every
+//! branch is kept as a leg attempt so the CDR shows who was tried and how
+//! each branch ended.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{RingGroup, RingGroupMember, RingStrategy};
+use crate::services::no_answer::LegAttempt;
+
+/// A leg B branch of a forked call that has not answered yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkBranch {
+    /// Index into the call's leg attempts
+    pub attempt: usize,
+    pub ring_timeout_secs: u64,
+}
+
+/// Forking state of a call routed to a ring group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RingFork {
+    pub group: RingGroup,
+    next_member: usize,
+    /// Ringing branches keyed by leg B session
+    branches: BTreeMap<String, ForkBranch>,
+}
+
+impl RingFork {
+    pub fn new(group: RingGroup) -> Self {
+        Self {
+            group,
+            next_member: 0,
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Members to ring next: all of them once for a simultaneous group, one
+    /// at a time for a sequential hunt
+    pub fn next_members(&mut self) -> Vec<RingGroupMember> {
+        let remaining = &self.group.members[self.next_member.min(self.group.members.len())..];
+        let members: Vec<RingGroupMember> = match self.group.strategy {
+            RingStrategy::Simultaneous => remaining.to_vec(),
+            RingStrategy::Sequential => remaining.iter().take(1).cloned().collect(),
+        };
+        self.next_member += members.len();
+        members
+    }
+
+    /// Ring time of `member` within this group
+    pub fn ring_timeout(&self, member: &RingGroupMember) -> Duration {
+        Duration::from_secs(member.ring_timeout_secs.unwrap_or(self.group.ring_timeout_secs))
+    }
+
+    /// Track a branch placed to the member at `target` as leg attempt `attempt`
+    pub fn ring(&mut self, session_id: String, attempt: usize, target: &str) {
+        let ring_timeout_secs = match self.group.members.iter().find(|member| member.target == target) {
+            Some(member) => self.ring_timeout(member).as_secs(),
+            None => self.group.ring_timeout_secs,
+        };
+        self.branches.insert(session_id, ForkBranch { attempt, ring_timeout_secs });
+    }
+
+    pub fn is_branch(&self, session_id: &str) -> bool {
+        self.branches.contains_key(session_id)
+    }
+
+    /// Whether any branch is still ringing
+    pub fn ringing(&self) -> bool {
+        !self.branches.is_empty()
+    }
+
+    /// Take the answering branch; returns its attempt and the branches to
+    /// CANCEL, or `None` if `session_id` is not ringing in this fork
+    pub fn answer(&mut self, session_id: &str) -> Option<(usize, Vec<(String, usize)>)> {
+        let winner = self.branches.remove(session_id)?;
+        let losers = std::mem::take(&mut self.branches)
+            .into_iter()
+            .map(|(session, branch)| (session, branch.attempt))
+            .collect();
+        self.next_member = self.group.members.len();
+        Some((winner.attempt, losers))
+    }
+
+    /// Stop tracking a branch that failed or was cancelled; returns its attempt
+    pub fn drop_branch(&mut self, session_id: &str) -> Option<usize> {
+        self.branches.remove(session_id).map(|branch| branch.attempt)
+    }
+
+    /// Take every ringing branch, e.g. when the caller hangs up
+    pub fn cancel_all(&mut self) -> Vec<(String, usize)> {
+        self.next_member = self.group.members.len();
+        std::mem::take(&mut self.branches)
+            .into_iter()
+            .map(|(session, branch)| (session, branch.attempt))
+            .collect()
+    }
+
+    /// Branches that have rung past their member's ring time
+    pub fn expired(&self, attempts: &[LegAttempt], now: DateTime<Utc>) -> Vec<String> {
+        self.branches
+            .iter()
+            .filter(|(_, branch)| {
+                attempts
+                    .get(branch.attempt)
+                    .is_some_and(|attempt| attempt.timed_out(Duration::from_secs(branch.ring_timeout_secs), now))
+            })
+            .map(|(session, _)| session.clone())
+            .collect()
+    }
+
+    /// No branch is ringing and no member is left to try
+    pub fn exhausted(&self) -> bool {
+        self.branches.is_empty() && self.next_member >= self.group.members.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::no_answer::LegKind;
+
+    fn group(strategy: RingStrategy) -> RingGroup {
+        RingGroup {
+            name: "sales".to_string(),
+            strategy,
+            members: vec![
+                RingGroupMember { target: "desk-1.example.com".to_string(), ring_timeout_secs: Some(10) },
+                RingGroupMember { target: "desk-2.example.com".to_string(), ring_timeout_secs: None },
+                RingGroupMember { target: "mobile.example.com".to_string(), ring_timeout_secs: None },
+            ],
+            ring_timeout_secs: 20,
+        }
+    }
+
+    #[test]
+    fn test_simultaneous_answer_cancels_losers() {
+        let mut fork = RingFork::new(group(RingStrategy::Simultaneous));
+        let members = fork.next_members();
+        assert_eq!(members.len(), 3);
+        assert!(fork.next_members().is_empty());
+        for (attempt, member) in members.iter().enumerate() {
+            fork.ring(format!("leg-{}", attempt), attempt, &member.target);
+        }
+
+        let (winner, losers) = fork.answer("leg-1").unwrap();
+        assert_eq!(winner, 1);
+        assert_eq!(losers, vec![("leg-0".to_string(), 0), ("leg-2".to_string(), 2)]);
+        assert!(fork.exhausted());
+        assert!(fork.answer("leg-0").is_none());
+    }
+
+    #[test]
+    fn test_sequential_hunt_uses_member_ring_times() {
+        let mut fork = RingFork::new(group(RingStrategy::Sequential));
+        let first = fork.next_members();
+        assert_eq!(first.len(), 1);
+        assert_eq!(fork.ring_timeout(&first[0]), Duration::from_secs(10));
+        fork.ring("leg-0".to_string(), 0, &first[0].target);
+
+        let attempts = vec![LegAttempt::new("desk-1.example.com", "sip:4000@desk-1.example.com", LegKind::Fork)];
+        let start = attempts[0].started_at;
+        assert!(fork.expired(&attempts, start + chrono::Duration::seconds(9)).is_empty());
+        assert_eq!(fork.expired(&attempts, start + chrono::Duration::seconds(10)), vec!["leg-0".to_string()]);
+
+        assert_eq!(fork.drop_branch("leg-0"), Some(0));
+        assert!(!fork.exhausted());
+        let second = fork.next_members();
+        assert_eq!(second[0].target, "desk-2.example.com");
+        assert_eq!(fork.ring_timeout(&second[0]), Duration::from_secs(20));
+        fork.ring("leg-1".to_string(), 1, &second[0].target);
+
+        assert_eq!(fork.cancel_all(), vec![("leg-1".to_string(), 1)]);
+        assert!(fork.exhausted());
+    }
+}
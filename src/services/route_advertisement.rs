@@ -0,0 +1,312 @@
+//! Route advertisement to upstream proxies
+//!
+//! The gateway periodically tells its upstream SIP proxy farm which number
+//! ranges it serves and how many more calls it can take, so load balancers
+//! can steer traffic on live capability rather than static weights. The
+//! advertisement rides on an OPTIONS request as custom headers, or is
+//! POSTed as JSON to a REST callback. A target that does not acknowledge is
+//! logged and retried on the next interval.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+use crate::config::{AdvertisementMethod, RouteAdvertisementConfig};
+use crate::services::survivability::parse_response;
+use crate::{Error, Result};
+
+/// Header carrying the advertising gateway's node ID
+pub const GATEWAY_ID_HEADER: &str = "X-Gateway-Id";
+/// Header listing the serviceable number prefixes, comma separated
+pub const RANGES_HEADER: &str = "X-Gateway-Ranges";
+/// Header carrying `active=<n>;max=<n>;available=<n>`
+pub const CAPACITY_HEADER: &str = "X-Gateway-Capacity";
+
+/// What the gateway can serve right now
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteAdvertisement {
+    pub gateway_id: String,
+    pub number_ranges: Vec<String>,
+    pub active_calls: u32,
+    pub max_calls: u32,
+    pub available_calls: u32,
+}
+
+impl RouteAdvertisement {
+    pub fn new(gateway_id: &str, number_ranges: &[String], active_calls: u32, max_calls: u32) -> Self {
+        Self {
+            gateway_id: gateway_id.to_string(),
+            number_ranges: number_ranges.to_vec(),
+            active_calls,
+            max_calls,
+            available_calls: max_calls.saturating_sub(active_calls),
+        }
+    }
+
+    /// Custom headers carrying the advertisement on an OPTIONS request
+    pub fn headers(&self) -> Vec<(String, String)> {
+        vec![
+            (GATEWAY_ID_HEADER.to_string(), self.gateway_id.clone()),
+            (RANGES_HEADER.to_string(), self.number_ranges.join(",")),
+            (
+                CAPACITY_HEADER.to_string(),
+                format!("active={};max={};available={}", self.active_calls, self.max_calls, self.available_calls),
+            ),
+        ]
+    }
+}
+
+/// Upstream receiving advertisements
+#[async_trait]
+pub trait AdvertisementTarget: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn advertise(&self, advertisement: &RouteAdvertisement) -> Result<()>;
+}
+
+/// OPTIONS over UDP to a proxy as host:port
+pub struct SipOptionsTarget {
+    proxy: String,
+    timeout: Duration,
+}
+
+impl SipOptionsTarget {
+    pub fn new(proxy: impl Into<String>, timeout: Duration) -> Self {
+        Self { proxy: proxy.into(), timeout }
+    }
+
+    fn proxy_host(&self) -> &str {
+        self.proxy.rsplit_once(':').map(|(host, _)| host).unwrap_or(&self.proxy)
+    }
+}
+
+#[async_trait]
+impl AdvertisementTarget for SipOptionsTarget {
+    fn name(&self) -> &str {
+        &self.proxy
+    }
+
+    async fn advertise(&self, advertisement: &RouteAdvertisement) -> Result<()> {
+        let network_err = |e: std::io::Error| Error::network(format!("OPTIONS to {} failed: {}", self.proxy, e));
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(network_err)?;
+        socket.connect(&self.proxy).await.map_err(network_err)?;
+        let local = socket.local_addr().map_err(network_err)?;
+
+        let host = self.proxy_host();
+        let mut message = format!(
+            "OPTIONS sip:{host} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch:x}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:{gateway}@{local}>;tag={tag:x}\r\n\
+             To: <sip:{host}>\r\n\
+             Call-ID: {call_id:x}@{local}\r\n\
+             CSeq: 1 OPTIONS\r\n",
+            host = host,
+            local = local,
+            gateway = advertisement.gateway_id,
+            branch = rand::random::<u64>(),
+            tag = rand::random::<u32>(),
+            call_id = rand::random::<u64>(),
+        );
+        for (name, value) in advertisement.headers() {
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        message.push_str("Content-Length: 0\r\n\r\n");
+        socket.send(message.as_bytes()).await.map_err(network_err)?;
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let len = tokio::time::timeout_at(deadline, socket.recv(&mut buf))
+                .await
+                .map_err(|_| Error::timeout(format!("OPTIONS to {} timed out", self.proxy)))?
+                .map_err(network_err)?;
+            let reply = parse_response(&buf[..len])?;
+            match reply.status_code {
+                100..=199 => continue,
+                200..=299 => return Ok(()),
+                status => {
+                    return Err(Error::network(format!("{} rejected advertisement: {} {}", self.proxy, status, reply.reason)))
+                }
+            }
+        }
+    }
+}
+
+/// JSON POST to a REST callback
+pub struct RestTarget {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl RestTarget {
+    pub fn new(url: impl Into<String>, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| Error::invalid_config(format!("Failed to create route advertisement client: {}", e)))?;
+        Ok(Self { url: url.into(), client })
+    }
+}
+
+#[async_trait]
+impl AdvertisementTarget for RestTarget {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn advertise(&self, advertisement: &RouteAdvertisement) -> Result<()> {
+        let response = self.client
+            .post(&self.url)
+            .json(advertisement)
+            .send()
+            .await
+            .map_err(|e| Error::network(format!("Route advertisement to {} failed: {}", self.url, e)))?;
+
+        if !response.status().is_success() {
+            return Err(Error::network(format!("{} rejected advertisement: {}", self.url, response.status())));
+        }
+        Ok(())
+    }
+}
+
+/// Pushes advertisements to every configured target
+pub struct RouteAdvertiser {
+    config: RouteAdvertisementConfig,
+    gateway_id: String,
+    targets: Vec<Arc<dyn AdvertisementTarget>>,
+}
+
+impl RouteAdvertiser {
+    pub fn new(config: RouteAdvertisementConfig, gateway_id: impl Into<String>) -> Result<Self> {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let targets = config
+            .targets
+            .iter()
+            .map(|target| -> Result<Arc<dyn AdvertisementTarget>> {
+                let target: Arc<dyn AdvertisementTarget> = match config.method {
+                    AdvertisementMethod::SipOptions => Arc::new(SipOptionsTarget::new(target.clone(), timeout)),
+                    AdvertisementMethod::Rest => Arc::new(RestTarget::new(target.clone(), timeout)?),
+                };
+                Ok(target)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::with_targets(config, gateway_id, targets))
+    }
+
+    pub fn with_targets(
+        config: RouteAdvertisementConfig,
+        gateway_id: impl Into<String>,
+        targets: Vec<Arc<dyn AdvertisementTarget>>,
+    ) -> Self {
+        Self {
+            config,
+            gateway_id: gateway_id.into(),
+            targets,
+        }
+    }
+
+    pub fn advertisement(&self, active_calls: u32, max_calls: u32) -> RouteAdvertisement {
+        RouteAdvertisement::new(&self.gateway_id, &self.config.number_ranges, active_calls, max_calls)
+    }
+
+    /// Send one advertisement to every target; returns how many acknowledged
+    pub async fn advertise(&self, advertisement: &RouteAdvertisement) -> usize {
+        let mut acknowledged = 0;
+        for target in &self.targets {
+            match target.advertise(advertisement).await {
+                Ok(()) => {
+                    debug!("Advertised {} free calls to {}", advertisement.available_calls, target.name());
+                    acknowledged += 1;
+                }
+                Err(e) => warn!("Route advertisement to {} failed: {}", target.name(), e),
+            }
+        }
+        acknowledged
+    }
+
+    /// Spawn the periodic advertisement loop; `capacity` reports active and
+    /// maximum calls at each tick
+    pub fn spawn<F>(self: &Arc<Self>, capacity: F) -> JoinHandle<()>
+    where
+        F: Fn() -> (u32, u32) + Send + Sync + 'static,
+    {
+        let advertiser = Arc::clone(self);
+        let period = Duration::from_secs(self.config.interval_secs.max(1));
+
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            loop {
+                ticker.tick().await;
+                let (active, max) = capacity();
+                advertiser.advertise(&advertiser.advertisement(active, max)).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct RecordingTarget {
+        fail: bool,
+        seen: Mutex<Vec<RouteAdvertisement>>,
+    }
+
+    #[async_trait]
+    impl AdvertisementTarget for RecordingTarget {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn advertise(&self, advertisement: &RouteAdvertisement) -> Result<()> {
+            self.seen.lock().unwrap().push(advertisement.clone());
+            if self.fail {
+                return Err(Error::network("proxy unreachable"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_advertisement_headers() {
+        let ranges = vec!["+1212555".to_string(), "+1646".to_string()];
+        let advertisement = RouteAdvertisement::new("node-1", &ranges, 520, 500);
+        assert_eq!(advertisement.available_calls, 0);
+        assert_eq!(
+            advertisement.headers(),
+            vec![
+                (GATEWAY_ID_HEADER.to_string(), "node-1".to_string()),
+                (RANGES_HEADER.to_string(), "+1212555,+1646".to_string()),
+                (CAPACITY_HEADER.to_string(), "active=520;max=500;available=0".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_target_does_not_stop_others() {
+        let config = RouteAdvertisementConfig {
+            enabled: true,
+            number_ranges: vec!["+4420".to_string()],
+            ..Default::default()
+        };
+        let down = Arc::new(RecordingTarget { fail: true, seen: Mutex::new(Vec::new()) });
+        let up = Arc::new(RecordingTarget { fail: false, seen: Mutex::new(Vec::new()) });
+        let advertiser = RouteAdvertiser::with_targets(config, "node-2", vec![down.clone() as Arc<dyn AdvertisementTarget>, up.clone()]);
+
+        let advertisement = advertiser.advertisement(120, 500);
+        assert_eq!(advertiser.advertise(&advertisement).await, 1);
+        assert_eq!(down.seen.lock().unwrap().len(), 1);
+        let seen = up.seen.lock().unwrap();
+        assert_eq!(seen[0].available_calls, 380);
+        assert_eq!(seen[0].number_ranges, vec!["+4420".to_string()]);
+    }
+}
@@ -0,0 +1,807 @@
+//! Bulk provisioning of routing rules and trunks from CSV
+//!
+//! Route sets are kept in spreadsheets; a sheet exported as CSV replaces the
+//! routing table (`b2bua.routing_table`) or the registering trunks
+//! (`b2bua.trunk_registration.trunks`) of a configuration file in one go.
+//! Every row is checked before anything is written, and the import is
+//! previewed as a diff against the current table, keyed by route ID or
+//! trunk name. Applying validates the whole resulting configuration, then
+//! swaps the file in with a rename so a failed import leaves it untouched.
+//!
+//! The table is written into the named file itself, over whatever its
+//! includes supply, and the file is rewritten without its comments. The
+//! gateway reads the new table the next time it loads its configuration.
+//! Route columns a sheet cannot carry, failover actions and the early media
+//! policy, are kept from the existing rule with the same ID.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::{GatewayConfig, NumberTranslation, RouteType, RoutingRule, TrunkRegistrationEntry};
+use crate::services::cdr::csv_escape;
+use crate::{Error, Result};
+
+/// Provisioning API path exporting and importing the routing table as CSV
+pub const ROUTES_PATH: &str = "/api/v1/provisioning/routes";
+
+/// Provisioning API path exporting and importing the registering trunks as CSV
+pub const TRUNKS_PATH: &str = "/api/v1/provisioning/trunks";
+
+/// Separator of list columns, which would otherwise clash with the CSV comma
+const LIST_SEPARATOR: &str = ";";
+
+/// Configuration table a sheet provisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvisioningTable {
+    Routes,
+    Trunks,
+}
+
+impl ProvisioningTable {
+    const ROUTE_COLUMNS: &'static [&'static str] = &[
+        "id",
+        "pattern",
+        "route_type",
+        "target",
+        "priority",
+        "prefix_strip",
+        "prefix_add",
+        "suffix_strip",
+        "suffix_add",
+        "codec_preference",
+        "no_answer_timeout_secs",
+        "alternate_targets",
+        "no_answer_divert",
+    ];
+
+    const TRUNK_COLUMNS: &'static [&'static str] = &["trunk", "registrar", "user", "contact", "expires_secs"];
+
+    /// Columns of the sheet, in export order
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            ProvisioningTable::Routes => Self::ROUTE_COLUMNS,
+            ProvisioningTable::Trunks => Self::TRUNK_COLUMNS,
+        }
+    }
+
+    /// Columns every sheet must have; the rest may be left out
+    fn required_columns(&self) -> &'static [&'static str] {
+        match self {
+            ProvisioningTable::Routes => &["id", "pattern", "route_type", "target"],
+            ProvisioningTable::Trunks => &["trunk", "registrar", "user", "contact"],
+        }
+    }
+
+    /// Where the table sits in the configuration document
+    fn location(&self) -> &'static [&'static str] {
+        match self {
+            ProvisioningTable::Routes => &["b2bua", "routing_table"],
+            ProvisioningTable::Trunks => &["b2bua", "trunk_registration", "trunks"],
+        }
+    }
+
+    /// Rows of the table in `config`, keyed by their first column
+    fn rows(&self, config: &GatewayConfig) -> Vec<Vec<String>> {
+        match self {
+            ProvisioningTable::Routes => config.b2bua.routing_table.iter().map(route_row).collect(),
+            ProvisioningTable::Trunks => config.b2bua.trunk_registration.trunks.iter().map(trunk_row).collect(),
+        }
+    }
+
+    /// The table in `config` as CSV, one row per route or trunk, with a header
+    pub fn to_csv(&self, config: &GatewayConfig) -> String {
+        let mut csv = self.columns().join(",");
+        csv.push('\n');
+        for row in self.rows(config) {
+            let fields: Vec<String> = row.iter().map(|field| csv_escape(field)).collect();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+impl fmt::Display for ProvisioningTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvisioningTable::Routes => f.write_str("routes"),
+            ProvisioningTable::Trunks => f.write_str("trunks"),
+        }
+    }
+}
+
+fn route_type_name(route_type: &RouteType) -> &'static str {
+    match route_type {
+        RouteType::Direct => "direct",
+        RouteType::Gateway => "gateway",
+        RouteType::Trunk => "trunk",
+        RouteType::Emergency => "emergency",
+    }
+}
+
+fn route_row(rule: &RoutingRule) -> Vec<String> {
+    let translation = rule.translation.clone().unwrap_or(NumberTranslation {
+        prefix_strip: None,
+        prefix_add: None,
+        suffix_strip: None,
+        suffix_add: None,
+    });
+    vec![
+        rule.id.clone(),
+        rule.pattern.clone(),
+        route_type_name(&rule.route_type).to_string(),
+        rule.target.clone(),
+        rule.priority.to_string(),
+        translation.prefix_strip.unwrap_or_default(),
+        translation.prefix_add.unwrap_or_default(),
+        translation.suffix_strip.unwrap_or_default(),
+        translation.suffix_add.unwrap_or_default(),
+        rule.codec_preference.join(LIST_SEPARATOR),
+        rule.no_answer_timeout_secs.map(|secs| secs.to_string()).unwrap_or_default(),
+        rule.alternate_targets.join(LIST_SEPARATOR),
+        rule.no_answer_divert.clone().unwrap_or_default(),
+    ]
+}
+
+fn trunk_row(entry: &TrunkRegistrationEntry) -> Vec<String> {
+    vec![
+        entry.trunk.clone(),
+        entry.registrar.clone(),
+        entry.user.clone(),
+        entry.contact.clone(),
+        entry.expires_secs.to_string(),
+    ]
+}
+
+/// A problem with an import; `line` is the sheet line it was found on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImportProblem {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ImportProblem {
+    fn at(line: usize, message: impl Into<String>) -> Self {
+        Self { line: Some(line), message: message.into() }
+    }
+}
+
+impl fmt::Display for ImportProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Records of a CSV document with the line each starts on. Quoted fields
+/// may hold commas, doubled quotes and line breaks; blank lines are skipped.
+fn parse_records(text: &str) -> std::result::Result<Vec<(usize, Vec<String>)>, ImportProblem> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push('\n');
+            }
+            '\r' if !quoted => {}
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.is_empty()) {
+                    records.push((start, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start = line;
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(ImportProblem::at(start, "quoted field is not closed"));
+    }
+    record.push(field);
+    if record.iter().any(|field| !field.is_empty()) {
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+/// One data row, read by column name
+struct Row<'a> {
+    line: usize,
+    columns: &'a HashMap<&'a str, usize>,
+    fields: Vec<String>,
+}
+
+impl Row<'_> {
+    fn get(&self, column: &str) -> &str {
+        self.columns
+            .get(column)
+            .and_then(|&index| self.fields.get(index))
+            .map(|field| field.trim())
+            .unwrap_or("")
+    }
+
+    fn optional(&self, column: &str) -> Option<String> {
+        let value = self.get(column);
+        (!value.is_empty()).then(|| value.to_string())
+    }
+
+    fn required(&self, column: &str) -> std::result::Result<String, ImportProblem> {
+        self.optional(column).ok_or_else(|| ImportProblem::at(self.line, format!("{} is empty", column)))
+    }
+
+    fn number<T: std::str::FromStr>(&self, column: &str) -> std::result::Result<Option<T>, ImportProblem> {
+        self.optional(column)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| ImportProblem::at(self.line, format!("{} {:?} is not a valid number", column, value)))
+            })
+            .transpose()
+    }
+
+    fn list(&self, column: &str) -> Vec<String> {
+        self.get(column)
+            .split(LIST_SEPARATOR)
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Table read from a sheet
+#[derive(Debug, Clone)]
+pub enum ImportedTable {
+    Routes(Vec<RoutingRule>),
+    Trunks(Vec<TrunkRegistrationEntry>),
+}
+
+impl ImportedTable {
+    /// Read `csv` as a sheet for `table`. `current` supplies the route
+    /// settings a sheet has no columns for. Every row is checked, so all
+    /// problems are reported at once.
+    pub fn parse(
+        table: ProvisioningTable,
+        csv: &str,
+        current: &GatewayConfig,
+    ) -> std::result::Result<Self, Vec<ImportProblem>> {
+        let mut records = parse_records(csv).map_err(|problem| vec![problem])?.into_iter();
+        let Some((header_line, header)) = records.next() else {
+            return Err(vec![ImportProblem::at(1, "the sheet has no header row")]);
+        };
+
+        let mut problems = Vec::new();
+        let mut columns = HashMap::new();
+        for (index, name) in header.iter().enumerate() {
+            let name = name.trim();
+            match table.columns().iter().find(|column| **column == name) {
+                Some(column) => {
+                    if columns.insert(*column, index).is_some() {
+                        problems.push(ImportProblem::at(header_line, format!("column {} appears twice", name)));
+                    }
+                }
+                None => problems.push(ImportProblem::at(header_line, format!("unknown column {:?}", name))),
+            }
+        }
+        for column in table.required_columns() {
+            if !columns.contains_key(column) {
+                problems.push(ImportProblem::at(header_line, format!("missing column {}", column)));
+            }
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        let existing: HashMap<&str, &RoutingRule> =
+            current.b2bua.routing_table.iter().map(|rule| (rule.id.as_str(), rule)).collect();
+        let mut keys = HashMap::new();
+        let mut routes = Vec::new();
+        let mut trunks = Vec::new();
+        for (line, fields) in records {
+            if fields.len() > header.len() {
+                problems.push(ImportProblem::at(line, format!("{} fields where the header has {}", fields.len(), header.len())));
+                continue;
+            }
+            let row = Row { line, columns: &columns, fields };
+            let key = row.get(table.columns()[0]).to_string();
+            match keys.get(&key) {
+                Some(first) if !key.is_empty() => {
+                    problems.push(ImportProblem::at(line, format!("{} is already defined on line {}", key, first)));
+                }
+                _ => {
+                    keys.insert(key, line);
+                }
+            }
+            match table {
+                ProvisioningTable::Routes => match parse_route(&row, &existing) {
+                    Ok(rule) => routes.push(rule),
+                    Err(problem) => problems.push(problem),
+                },
+                ProvisioningTable::Trunks => match parse_trunk(&row) {
+                    Ok(entry) => trunks.push(entry),
+                    Err(problem) => problems.push(problem),
+                },
+            }
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        Ok(match table {
+            ProvisioningTable::Routes => ImportedTable::Routes(routes),
+            ProvisioningTable::Trunks => ImportedTable::Trunks(trunks),
+        })
+    }
+
+    pub fn table(&self) -> ProvisioningTable {
+        match self {
+            ImportedTable::Routes(_) => ProvisioningTable::Routes,
+            ImportedTable::Trunks(_) => ProvisioningTable::Trunks,
+        }
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        match self {
+            ImportedTable::Routes(routes) => routes.iter().map(route_row).collect(),
+            ImportedTable::Trunks(trunks) => trunks.iter().map(trunk_row).collect(),
+        }
+    }
+
+    fn to_toml(&self) -> Result<toml::Value> {
+        let value = match self {
+            ImportedTable::Routes(routes) => toml::Value::try_from(routes),
+            ImportedTable::Trunks(trunks) => toml::Value::try_from(trunks),
+        };
+        value.map_err(|e| Error::internal(format!("Failed to serialize {}: {}", self.table(), e)))
+    }
+}
+
+fn parse_route(row: &Row<'_>, existing: &HashMap<&str, &RoutingRule>) -> std::result::Result<RoutingRule, ImportProblem> {
+    let id = row.required("id")?;
+    let route_type = match row.get("route_type").to_ascii_lowercase().as_str() {
+        "direct" => RouteType::Direct,
+        "gateway" => RouteType::Gateway,
+        "trunk" => RouteType::Trunk,
+        "emergency" => RouteType::Emergency,
+        other => {
+            return Err(ImportProblem::at(
+                row.line,
+                format!("route_type {:?} is not one of direct, gateway, trunk or emergency", other),
+            ))
+        }
+    };
+    let translation = NumberTranslation {
+        prefix_strip: row.optional("prefix_strip"),
+        prefix_add: row.optional("prefix_add"),
+        suffix_strip: row.optional("suffix_strip"),
+        suffix_add: row.optional("suffix_add"),
+    };
+    let translates = translation.prefix_strip.is_some()
+        || translation.prefix_add.is_some()
+        || translation.suffix_strip.is_some()
+        || translation.suffix_add.is_some();
+    let previous = existing.get(id.as_str());
+
+    Ok(RoutingRule {
+        pattern: row.required("pattern")?,
+        route_type,
+        target: row.required("target")?,
+        priority: row.number("priority")?.unwrap_or(0),
+        translation: translates.then_some(translation),
+        codec_preference: row.list("codec_preference"),
+        no_answer_timeout_secs: row.number("no_answer_timeout_secs")?,
+        alternate_targets: row.list("alternate_targets"),
+        no_answer_divert: row.optional("no_answer_divert"),
+        failover_actions: previous.map(|rule| rule.failover_actions.clone()).unwrap_or_default(),
+        early_media: previous.and_then(|rule| rule.early_media.clone()),
+        id,
+    })
+}
+
+fn parse_trunk(row: &Row<'_>) -> std::result::Result<TrunkRegistrationEntry, ImportProblem> {
+    Ok(TrunkRegistrationEntry {
+        trunk: row.required("trunk")?,
+        registrar: row.required("registrar")?,
+        user: row.required("user")?,
+        contact: row.required("contact")?,
+        expires_secs: row.number("expires_secs")?.unwrap_or(3600),
+    })
+}
+
+/// One column of a changed row
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FieldChange {
+    pub column: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// A row present before and after an import, with different contents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RowChange {
+    pub key: String,
+    pub fields: Vec<FieldChange>,
+}
+
+/// What an import changes, by route ID or trunk name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TableDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<RowChange>,
+    pub unchanged: usize,
+}
+
+impl TableDiff {
+    fn between(columns: &[&str], before: Vec<Vec<String>>, after: Vec<Vec<String>>) -> Self {
+        let before: BTreeMap<String, Vec<String>> = before.into_iter().map(|row| (row[0].clone(), row)).collect();
+        let after_keys: HashSet<String> = after.iter().map(|row| row[0].clone()).collect();
+        let mut diff = TableDiff {
+            removed: before.keys().filter(|key| !after_keys.contains(*key)).cloned().collect(),
+            ..TableDiff::default()
+        };
+        for row in after {
+            let Some(old) = before.get(&row[0]) else {
+                diff.added.push(row[0].clone());
+                continue;
+            };
+            let fields: Vec<FieldChange> = columns
+                .iter()
+                .zip(old.iter().zip(&row))
+                .filter(|(_, (before, after))| before != after)
+                .map(|(column, (before, after))| FieldChange {
+                    column: column.to_string(),
+                    before: before.clone(),
+                    after: after.clone(),
+                })
+                .collect();
+            if fields.is_empty() {
+                diff.unchanged += 1;
+            } else {
+                diff.changed.push(RowChange { key: row[0].clone(), fields });
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for TableDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for key in &self.added {
+            writeln!(f, "+ {}", key)?;
+        }
+        for key in &self.removed {
+            writeln!(f, "- {}", key)?;
+        }
+        for change in &self.changed {
+            let fields: Vec<String> = change
+                .fields
+                .iter()
+                .map(|field| format!("{} {:?} -> {:?}", field.column, field.before, field.after))
+                .collect();
+            writeln!(f, "~ {}: {}", change.key, fields.join(", "))?;
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged
+        )
+    }
+}
+
+/// Outcome of an import, previewed or applied
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImportReport {
+    pub table: ProvisioningTable,
+    pub diff: TableDiff,
+    /// Problems that stopped the import; nothing is written while there are any
+    pub problems: Vec<ImportProblem>,
+    pub applied: bool,
+}
+
+impl ImportReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Replace the value at `location` in `document`, creating tables on the way
+fn set_table(document: &mut toml::Table, location: &[&str], value: toml::Value) -> Result<()> {
+    let (last, parents) = location.split_last().expect("table location is never empty");
+    let mut table = document;
+    for key in parents {
+        let entry = table
+            .entry(key.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        table = entry
+            .as_table_mut()
+            .ok_or_else(|| Error::invalid_config(format!("{} in the configuration is not a table", key)))?;
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+/// Check `csv` against the configuration at `config_path` and, unless
+/// `dry_run`, write it in. Errors are for a configuration file that cannot
+/// be read or written; problems with the sheet are in the report.
+pub fn import(config_path: &Path, table: ProvisioningTable, csv: &str, dry_run: bool) -> Result<ImportReport> {
+    let merged = GatewayConfig::load_merged(config_path)?;
+    let current: GatewayConfig = toml::Value::Table(merged.clone())
+        .try_into()
+        .map_err(|e| Error::parse(format!("Invalid TOML: {}", e)))?;
+    let mut report = ImportReport { table, diff: TableDiff::default(), problems: Vec::new(), applied: false };
+
+    let imported = match ImportedTable::parse(table, csv, &current) {
+        Ok(imported) => imported,
+        Err(problems) => {
+            report.problems = problems;
+            return Ok(report);
+        }
+    };
+    report.diff = TableDiff::between(table.columns(), table.rows(&current), imported.rows());
+
+    // The file's own value replaces whatever its includes hold, so the
+    // merged document with the table swapped is what the file will load as
+    let value = imported.to_toml()?;
+    let mut candidate = merged;
+    set_table(&mut candidate, table.location(), value.clone())?;
+    let validated = toml::Value::Table(candidate)
+        .try_into()
+        .map_err(|e| Error::parse(format!("Invalid TOML: {}", e)))
+        .and_then(|config: GatewayConfig| config.validate());
+    if let Err(e) = validated {
+        report.problems.push(ImportProblem { line: None, message: e.to_string() });
+    }
+    if dry_run || !report.is_valid() || report.diff.is_empty() {
+        return Ok(report);
+    }
+
+    let contents = std::fs::read_to_string(config_path)?;
+    let mut document: toml::Table = toml::from_str(&contents)
+        .map_err(|e| Error::parse(format!("Invalid TOML in {}: {}", config_path.display(), e)))?;
+    set_table(&mut document, table.location(), value)?;
+    let contents = toml::to_string_pretty(&document)
+        .map_err(|e| Error::internal(format!("Failed to serialize config: {}", e)))?;
+    let staging = config_path.with_extension("toml.tmp");
+    std::fs::write(&staging, contents)?;
+    std::fs::rename(&staging, config_path)?;
+
+    info!("Imported {} into {}: {}", table, config_path.display(), report.diff.to_string().replace('\n', "; "));
+    report.applied = true;
+    Ok(report)
+}
+
+/// Query of an import request
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportQuery {
+    /// Report the diff without writing the configuration
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Clone)]
+struct ProvisioningState {
+    config_path: Arc<PathBuf>,
+    /// Imports rewrite the configuration file one at a time
+    import_lock: Arc<Mutex<()>>,
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+async fn export_table(state: &ProvisioningState, table: ProvisioningTable) -> Response {
+    match GatewayConfig::load_from_file(state.config_path.as_path()) {
+        Ok(config) => ([(header::CONTENT_TYPE, "text/csv")], table.to_csv(&config)).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+async fn import_table(state: &ProvisioningState, table: ProvisioningTable, dry_run: bool, csv: String) -> Response {
+    let state = state.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let _guard = state.import_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        import(&state.config_path, table, &csv, dry_run)
+    })
+    .await;
+    match result {
+        Ok(Ok(report)) if report.is_valid() => Json(report).into_response(),
+        Ok(Ok(report)) => (StatusCode::UNPROCESSABLE_ENTITY, Json(report)).into_response(),
+        Ok(Err(e)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+async fn export_routes(State(state): State<ProvisioningState>) -> Response {
+    export_table(&state, ProvisioningTable::Routes).await
+}
+
+async fn import_routes(State(state): State<ProvisioningState>, Query(query): Query<ImportQuery>, csv: String) -> Response {
+    import_table(&state, ProvisioningTable::Routes, query.dry_run, csv).await
+}
+
+async fn export_trunks(State(state): State<ProvisioningState>) -> Response {
+    export_table(&state, ProvisioningTable::Trunks).await
+}
+
+async fn import_trunks(State(state): State<ProvisioningState>, Query(query): Query<ImportQuery>, csv: String) -> Response {
+    import_table(&state, ProvisioningTable::Trunks, query.dry_run, csv).await
+}
+
+/// Routes exporting and importing the tables of the configuration at `config_path`
+pub fn router(config_path: PathBuf) -> Router {
+    Router::new()
+        .route(ROUTES_PATH, get(export_routes).put(import_routes))
+        .route(TRUNKS_PATH, get(export_trunks).put(import_trunks))
+        .with_state(ProvisioningState { config_path: Arc::new(config_path), import_lock: Arc::new(Mutex::new(())) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "id,pattern,route_type,target,priority,prefix_strip,codec_preference,alternate_targets\n\
+        emergency,^(911|112)$,emergency,emergency.psap.local,1,,g711u,\n\
+        local,^[2-9][0-9]{3}$,direct,localhost,5,,g711a;g711u,\n\
+        intl,011,Trunk,carrier-a,20,011,g729,\"carrier-b;carrier-c\"\n";
+
+    fn write_config(dir: &Path) -> PathBuf {
+        let path = dir.join("gateway.toml");
+        GatewayConfig::default_config().save_to_file(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_parse_records() {
+        let records = parse_records("a,b\r\n\r\n\"x, y\",\"say \"\"hi\"\"\"\n\"two\nlines\",z").unwrap();
+        assert_eq!(records[0], (1, vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(records[1], (3, vec!["x, y".to_string(), "say \"hi\"".to_string()]));
+        assert_eq!(records[2], (4, vec!["two\nlines".to_string(), "z".to_string()]));
+        assert_eq!(parse_records("a,\"b\n").unwrap_err(), ImportProblem::at(1, "quoted field is not closed"));
+    }
+
+    #[test]
+    fn test_round_trip_and_problems() {
+        let config = GatewayConfig::default_config();
+        let exported = ProvisioningTable::Routes.to_csv(&config);
+        let ImportedTable::Routes(routes) = ImportedTable::parse(ProvisioningTable::Routes, &exported, &config).unwrap() else {
+            panic!("expected routes");
+        };
+        assert_eq!(routes.iter().map(route_row).collect::<Vec<_>>(), ProvisioningTable::Routes.rows(&config));
+
+        let ImportedTable::Routes(routes) = ImportedTable::parse(ProvisioningTable::Routes, SHEET, &config).unwrap() else {
+            panic!("expected routes");
+        };
+        let intl = &routes[2];
+        assert!(matches!(intl.route_type, RouteType::Trunk));
+        assert_eq!(intl.translation.as_ref().unwrap().prefix_strip.as_deref(), Some("011"));
+        assert_eq!(intl.alternate_targets, ["carrier-b", "carrier-c"]);
+        assert!(routes[0].translation.is_none());
+
+        let bad = "id,pattern,route_type,target,priority,colour\n";
+        let problems = ImportedTable::parse(ProvisioningTable::Routes, bad, &config).unwrap_err();
+        assert_eq!(problems, [ImportProblem::at(1, "unknown column \"colour\"")]);
+
+        let bad = "id,pattern,route_type,target,priority\n\
+            a,1,direct,x,5\n\
+            a,2,direct,x,5\n\
+            b,3,carrier,x,5\n\
+            c,,direct,x,300\n";
+        let problems: Vec<String> = ImportedTable::parse(ProvisioningTable::Routes, bad, &config)
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                "line 3: a is already defined on line 2",
+                "line 4: route_type \"carrier\" is not one of direct, gateway, trunk or emergency",
+                "line 5: pattern is empty",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dry_run_and_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(dir.path());
+        let before = std::fs::read_to_string(&path).unwrap();
+
+        let report = import(&path, ProvisioningTable::Routes, SHEET, true).unwrap();
+        assert!(report.is_valid() && !report.applied);
+        assert_eq!(report.diff.added, ["intl"]);
+        assert_eq!(report.diff.unchanged, 1);
+        assert_eq!(
+            report.diff.changed,
+            [RowChange {
+                key: "local".to_string(),
+                fields: vec![FieldChange { column: "priority".to_string(), before: "10".to_string(), after: "5".to_string() }],
+            }]
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+
+        // A sheet the configuration rejects is not written
+        let invalid = "id,pattern,route_type,target,no_answer_timeout_secs\nlocal,1,direct,x,0\n";
+        let report = import(&path, ProvisioningTable::Routes, invalid, false).unwrap();
+        assert!(!report.applied);
+        assert_eq!(report.problems[0].line, None);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+
+        let report = import(&path, ProvisioningTable::Routes, SHEET, false).unwrap();
+        assert!(report.applied);
+        let config = GatewayConfig::load_from_file(&path).unwrap();
+        assert_eq!(config.b2bua.routing_table.len(), 3);
+        assert_eq!(ProvisioningTable::Routes.to_csv(&config).lines().count(), 4);
+        assert!(import(&path, ProvisioningTable::Routes, SHEET, true).unwrap().diff.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(dir.path());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let task = tokio::spawn(async move { axum::serve(listener, router(path)).await });
+        let client = reqwest::Client::new();
+
+        let sheet = "trunk,registrar,user,contact\ncarrier-a,sip.carrier.example:5060,gw1,<sip:gw1@203.0.113.5>\n";
+        let preview: ImportReport = client
+            .put(format!("{}{}?dry_run=true", base, TRUNKS_PATH))
+            .body(sheet)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!((preview.diff.added.len(), preview.applied), (1, false));
+
+        let rejected = client.put(format!("{}{}", base, TRUNKS_PATH)).body("trunk\n").send().await.unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+
+        let applied: ImportReport =
+            client.put(format!("{}{}", base, TRUNKS_PATH)).body(sheet).send().await.unwrap().json().await.unwrap();
+        assert!(applied.applied);
+        let exported = reqwest::get(format!("{}{}", base, TRUNKS_PATH)).await.unwrap().text().await.unwrap();
+        assert_eq!(exported, "trunk,registrar,user,contact,expires_secs\ncarrier-a,sip.carrier.example:5060,gw1,<sip:gw1@203.0.113.5>,3600\n");
+        task.abort();
+    }
+}
@@ -0,0 +1,400 @@
+//! Pluggable routing decisions
+//!
+//! Before the static routing table is applied, the B2BUA can hand the call
+//! context to a user-supplied hook: an HTTP endpoint receiving a JSON POST, or
+//! a WASM module run in-process. The hook answers with a target, a rejection,
+//! or nothing (use the static route). Hooks that fail or exceed their time
+//! budget fall back to the static route unless configured to fail closed.
+//!
+//! WASM modules exchange the same JSON documents through linear memory and
+//! must export `memory`, `alloc(len: i32) -> i32` and
+//! `route(ptr: i32, len: i32) -> i64`, the result packing the response
+//! pointer in the high 32 bits and its length in the low 32 bits.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::{RouteType, RoutingHookConfig, RoutingHookKind};
+use crate::services::b2bua::RoutingInfo;
+use crate::{Error, Result};
+
+/// Call context sent to the hook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingHookRequest {
+    pub caller: String,
+    pub callee: String,
+    /// Domain of the calling party
+    pub tenant: Option<String>,
+    /// Trunk the static routing table would choose
+    pub static_target: Option<String>,
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Hook answer; every field is optional and an empty answer keeps the static route
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingHookResponse {
+    pub target: Option<String>,
+    pub route_type: Option<RouteType>,
+    /// Number to dial on the egress leg
+    pub callee: Option<String>,
+    /// Headers added to the egress INVITE
+    pub headers: BTreeMap<String, String>,
+    /// SIP status to reject the call with
+    pub reject: Option<u16>,
+    pub reason: Option<String>,
+}
+
+/// Outcome of routing a call
+#[derive(Debug, Clone)]
+pub enum RouteResolution {
+    Route(RoutingInfo),
+    Reject {
+        status_code: u16,
+        reason: String,
+    },
+    /// Play an announcement, then release with `status_code`
+    Announce {
+        announcement: String,
+        status_code: u16,
+    },
+}
+
+/// User-supplied routing logic
+#[async_trait]
+pub trait RoutingHook: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn decide(&self, request: &RoutingHookRequest) -> Result<RoutingHookResponse>;
+}
+
+/// Routing hook calling an HTTP endpoint
+pub struct HttpRoutingHook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpRoutingHook {
+    pub fn new(url: impl Into<String>, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| Error::invalid_config(format!("Failed to create routing hook client: {}", e)))?;
+        Ok(Self { url: url.into(), client })
+    }
+}
+
+#[async_trait]
+impl RoutingHook for HttpRoutingHook {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn decide(&self, request: &RoutingHookRequest) -> Result<RoutingHookResponse> {
+        let response = self.client
+            .post(&self.url)
+            .json(request)
+            .send()
+            .await
+            .map_err(|e| Error::network(format!("Routing hook request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(Error::routing(format!("Routing hook returned {}", response.status())));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| Error::parse(format!("Invalid routing hook response: {}", e)))
+    }
+}
+
+/// Routing hook running a WASM module
+#[cfg(feature = "wasm-routing")]
+pub struct WasmRoutingHook {
+    name: String,
+    engine: wasmtime::Engine,
+    module: wasmtime::Module,
+    fuel: u64,
+}
+
+#[cfg(feature = "wasm-routing")]
+impl WasmRoutingHook {
+    pub fn from_file(path: &str, fuel: u64) -> Result<Self> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = wasmtime::Engine::new(&config)
+            .map_err(|e| Error::internal(format!("Failed to create WASM engine: {}", e)))?;
+        let module = wasmtime::Module::from_file(&engine, path)
+            .map_err(|e| Error::invalid_config(format!("Failed to load routing module {}: {}", path, e)))?;
+        Ok(Self { name: path.to_string(), engine, module, fuel })
+    }
+
+    fn run(engine: &wasmtime::Engine, module: &wasmtime::Module, fuel: u64, input: &[u8]) -> Result<Vec<u8>> {
+        let wasm_err = |e: wasmtime::Error| Error::routing(format!("Routing module failed: {}", e));
+
+        // A fresh store per call keeps invocations isolated
+        let mut store = wasmtime::Store::new(engine, ());
+        store.set_fuel(fuel).map_err(wasm_err)?;
+        let instance = wasmtime::Linker::new(engine)
+            .instantiate(&mut store, module)
+            .map_err(wasm_err)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| Error::routing("Routing module does not export memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(wasm_err)?;
+        let route = instance
+            .get_typed_func::<(i32, i32), i64>(&mut store, "route")
+            .map_err(wasm_err)?;
+
+        let len = i32::try_from(input.len()).map_err(|_| Error::routing("Routing request too large"))?;
+        let ptr = alloc.call(&mut store, len).map_err(wasm_err)?;
+        memory.write(&mut store, ptr as usize, input).map_err(|e| Error::routing(e.to_string()))?;
+
+        let packed = route.call(&mut store, (ptr, len)).map_err(wasm_err)? as u64;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        let mut output = vec![0u8; out_len];
+        memory.read(&store, out_ptr, &mut output).map_err(|e| Error::routing(e.to_string()))?;
+        Ok(output)
+    }
+}
+
+#[cfg(feature = "wasm-routing")]
+#[async_trait]
+impl RoutingHook for WasmRoutingHook {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn decide(&self, request: &RoutingHookRequest) -> Result<RoutingHookResponse> {
+        let input = serde_json::to_vec(request)
+            .map_err(|e| Error::internal(format!("Failed to encode routing request: {}", e)))?;
+        let (engine, module, fuel) = (self.engine.clone(), self.module.clone(), self.fuel);
+
+        let output = tokio::task::spawn_blocking(move || Self::run(&engine, &module, fuel, &input))
+            .await
+            .map_err(|e| Error::internal(format!("Routing module task failed: {}", e)))??;
+
+        serde_json::from_slice(&output)
+            .map_err(|e| Error::parse(format!("Invalid routing module response: {}", e)))
+    }
+}
+
+/// Applies a routing hook with a time budget and static-route fallback
+pub struct RoutingHookRunner {
+    hook: Arc<dyn RoutingHook>,
+    timeout: Duration,
+    fail_closed: bool,
+}
+
+/// Result of consulting the hook; `failure` is set when the hook could not decide
+#[derive(Debug, Clone)]
+pub struct HookOutcome {
+    pub resolution: RouteResolution,
+    pub failure: Option<String>,
+}
+
+impl RoutingHookRunner {
+    pub fn new(hook: Arc<dyn RoutingHook>, config: &RoutingHookConfig) -> Self {
+        Self {
+            hook,
+            timeout: Duration::from_millis(config.timeout_ms),
+            fail_closed: config.fail_closed,
+        }
+    }
+
+    /// Build the hook described by the configuration, if enabled
+    pub fn from_config(config: &RoutingHookConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let hook: Arc<dyn RoutingHook> = match config.kind {
+            RoutingHookKind::Http => {
+                let url = config.url.as_deref()
+                    .ok_or_else(|| Error::invalid_config("HTTP routing hook needs a url"))?;
+                Arc::new(HttpRoutingHook::new(url, timeout)?)
+            }
+            #[cfg(feature = "wasm-routing")]
+            RoutingHookKind::Wasm => {
+                let module = config.module.as_deref()
+                    .ok_or_else(|| Error::invalid_config("WASM routing hook needs a module"))?;
+                Arc::new(WasmRoutingHook::from_file(module, config.wasm_fuel)?)
+            }
+            #[cfg(not(feature = "wasm-routing"))]
+            RoutingHookKind::Wasm => {
+                return Err(Error::not_supported("WASM routing hooks require the `wasm-routing` feature"));
+            }
+        };
+
+        Ok(Some(Self::new(hook, config)))
+    }
+
+    pub async fn resolve(&self, request: &RoutingHookRequest, static_route: RoutingInfo) -> HookOutcome {
+        let decision = match tokio::time::timeout(self.timeout, self.hook.decide(request)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::timeout(format!("Routing hook did not answer within {:?}", self.timeout))),
+        };
+
+        match decision {
+            Ok(response) => {
+                debug!("Routing hook {} answered for {}: {:?}", self.hook.name(), request.callee, response);
+                HookOutcome {
+                    resolution: apply_response(response, static_route),
+                    failure: None,
+                }
+            }
+            Err(e) => {
+                warn!("Routing hook {} failed for {}: {}", self.hook.name(), request.callee, e);
+                let resolution = if self.fail_closed {
+                    let (status_code, reason) = e.sip_response();
+                    RouteResolution::Reject { status_code, reason: reason.to_string() }
+                } else {
+                    RouteResolution::Route(static_route)
+                };
+                HookOutcome { resolution, failure: Some(e.to_string()) }
+            }
+        }
+    }
+}
+
+fn apply_response(response: RoutingHookResponse, mut route: RoutingInfo) -> RouteResolution {
+    if let Some(status_code) = response.reject {
+        return RouteResolution::Reject {
+            status_code,
+            reason: response.reason.unwrap_or_else(|| "Rejected by routing policy".to_string()),
+        };
+    }
+
+    if let Some(target) = response.target {
+        route.target_gateway = Some(target);
+        // Static translations and groups were written for the static target
+        route.number_translation = None;
+        route.ring_group = None;
+    }
+    if let Some(route_type) = response.route_type {
+        route.route_type = route_type;
+    }
+    if response.callee.is_some() {
+        // The hook's number is dialed as given
+        route.callee_override = response.callee;
+        route.number_translation = None;
+    }
+    route.extra_headers.extend(response.headers);
+
+    RouteResolution::Route(route)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NumberTranslation;
+
+    struct FixedHook(Option<RoutingHookResponse>, Duration);
+
+    #[async_trait]
+    impl RoutingHook for FixedHook {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        async fn decide(&self, _request: &RoutingHookRequest) -> Result<RoutingHookResponse> {
+            tokio::time::sleep(self.1).await;
+            self.0.clone().ok_or_else(|| Error::network("unreachable"))
+        }
+    }
+
+    fn static_route() -> RoutingInfo {
+        RoutingInfo {
+            route_type: RouteType::Direct,
+            route: None,
+            target_gateway: Some("static.example.com".to_string()),
+            number_translation: Some(NumberTranslation {
+                prefix_strip: Some("9".to_string()),
+                prefix_add: None,
+                suffix_strip: None,
+                suffix_add: None,
+            }),
+            codec_preference: vec![],
+            priority: 100,
+            callee_override: None,
+            extra_headers: BTreeMap::new(),
+            egress_spans: Vec::new(),
+            number_lookup: None,
+            caller_name: None,
+            no_answer: None,
+            alternate_targets: Vec::new(),
+            failover_actions: Default::default(),
+            ring_group: None,
+            early_media: None,
+        }
+    }
+
+    fn request() -> RoutingHookRequest {
+        RoutingHookRequest {
+            caller: "1000".to_string(),
+            callee: "2000".to_string(),
+            tenant: Some("acme.example.com".to_string()),
+            static_target: Some("static.example.com".to_string()),
+            headers: BTreeMap::new(),
+        }
+    }
+
+    fn runner(response: Option<RoutingHookResponse>, delay_ms: u64, fail_closed: bool) -> RoutingHookRunner {
+        let config = RoutingHookConfig {
+            enabled: true,
+            timeout_ms: 50,
+            fail_closed,
+            ..Default::default()
+        };
+        RoutingHookRunner::new(Arc::new(FixedHook(response, Duration::from_millis(delay_ms))), &config)
+    }
+
+    #[tokio::test]
+    async fn test_hook_overrides_static_route() {
+        let response = RoutingHookResponse {
+            target: Some("hook.example.com".to_string()),
+            callee: Some("442000".to_string()),
+            headers: BTreeMap::from([("X-Route".to_string(), "lcr".to_string())]),
+            ..Default::default()
+        };
+        let outcome = runner(Some(response), 0, false).resolve(&request(), static_route()).await;
+
+        assert!(outcome.failure.is_none());
+        match outcome.resolution {
+            RouteResolution::Route(route) => {
+                assert_eq!(route.target_gateway.as_deref(), Some("hook.example.com"));
+                assert!(route.number_translation.is_none());
+                assert_eq!(route.callee_override.as_deref(), Some("442000"));
+                assert_eq!(route.extra_headers.get("X-Route").map(String::as_str), Some("lcr"));
+            }
+            other => panic!("unexpected resolution: {:?}", other),
+        }
+
+        let reject = RoutingHookResponse { reject: Some(403), ..Default::default() };
+        let outcome = runner(Some(reject), 0, false).resolve(&request(), static_route()).await;
+        assert!(matches!(outcome.resolution, RouteResolution::Reject { status_code: 403, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_failures_fall_back_or_fail_closed() {
+        let slow = runner(Some(RoutingHookResponse::default()), 500, false);
+        let outcome = slow.resolve(&request(), static_route()).await;
+        assert!(outcome.failure.is_some());
+        match outcome.resolution {
+            RouteResolution::Route(route) => assert_eq!(route.target_gateway.as_deref(), Some("static.example.com")),
+            other => panic!("unexpected resolution: {:?}", other),
+        }
+
+        let outcome = runner(None, 0, true).resolve(&request(), static_route()).await;
+        assert!(matches!(outcome.resolution, RouteResolution::Reject { status_code: 503, .. }));
+    }
+}
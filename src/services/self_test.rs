@@ -0,0 +1,311 @@
+//! Power-on self-test
+//!
+//! Runs before the gateway goes in service: TDM cards must be present with
+//! acceptable firmware, spans may be looped back briefly, NTP servers and the
+//! GPS receiver must respond, and configured certificates must load and be
+//! within their validity period. Failures raise alarms; a failure of a check
+//! configured as critical keeps the gateway out of service.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncReadExt;
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::config::{CertificateConfig, SelfTestCheck, SelfTestConfig};
+use crate::interfaces::TdmBackendSet;
+use crate::services::alarms::{AlarmManager, AlarmSeverity, AlarmSource, AlarmType};
+use crate::services::certificates::load_certificate;
+use crate::services::ports::PortDirectory;
+use crate::{Error, Result};
+
+const NTP_PORT: u16 = 123;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    /// Not applicable or not supported by the driver
+    Skipped,
+}
+
+/// Result of one check against one subject (card, span, server, certificate)
+#[derive(Debug, Clone)]
+pub struct CheckOutcome {
+    pub check: SelfTestCheck,
+    pub subject: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckOutcome {
+    fn new(check: SelfTestCheck, subject: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { check, subject: subject.into(), status, detail: detail.into() }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SelfTestReport {
+    pub outcomes: Vec<CheckOutcome>,
+}
+
+impl SelfTestReport {
+    pub fn extend(&mut self, outcomes: Vec<CheckOutcome>) {
+        self.outcomes.extend(outcomes);
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckOutcome> {
+        self.outcomes.iter().filter(|outcome| outcome.status == CheckStatus::Failed)
+    }
+
+    /// Failures that keep the gateway out of service
+    pub fn blocking_failures<'a>(&'a self, critical: &'a [SelfTestCheck]) -> impl Iterator<Item = &'a CheckOutcome> {
+        self.failures().filter(move |outcome| critical.contains(&outcome.check))
+    }
+}
+
+/// Power-on self-test runner
+pub struct SelfTest {
+    config: SelfTestConfig,
+}
+
+impl SelfTest {
+    pub fn new(config: SelfTestConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn critical_checks(&self) -> &[SelfTestCheck] {
+        &self.config.critical_checks
+    }
+
+    /// Card presence and firmware of every TDM backend
+    pub fn check_hardware(&self, backends: &TdmBackendSet) -> Vec<CheckOutcome> {
+        backends
+            .backends()
+            .map(|backend| {
+                let outcome = |status, detail: String| CheckOutcome::new(SelfTestCheck::TdmHardware, backend.name(), status, detail);
+                let info = match backend.probe_hardware() {
+                    Ok(info) => info,
+                    Err(e) => return outcome(CheckStatus::Failed, e.to_string()),
+                };
+                let firmware = info.firmware.as_deref().unwrap_or("unknown");
+                match self.config.min_firmware.get(backend.name()) {
+                    Some(minimum) if !info.firmware.as_deref().is_some_and(|actual| version_at_least(actual, minimum)) => {
+                        outcome(CheckStatus::Failed, format!("firmware {} is older than required {}", firmware, minimum))
+                    }
+                    _ => outcome(CheckStatus::Passed, format!("firmware {}", firmware)),
+                }
+            })
+            .collect()
+    }
+
+    /// Brief loopback of every span, when allowed
+    pub async fn check_spans(&self, backends: &TdmBackendSet, ports: &PortDirectory) -> Vec<CheckOutcome> {
+        let mut outcomes = Vec::new();
+        if !self.config.span_loopback {
+            return outcomes;
+        }
+        let duration = Duration::from_millis(self.config.loopback_duration_ms);
+
+        for span in backends.span_statuses() {
+            let subject = ports.span_label(span.span_id);
+            let outcome = match backends.loopback_test(span.span_id, duration).await {
+                Ok(true) => CheckOutcome::new(SelfTestCheck::SpanLoopback, subject, CheckStatus::Passed, "loopback clean"),
+                Ok(false) => CheckOutcome::new(SelfTestCheck::SpanLoopback, subject, CheckStatus::Failed, "looped signal corrupted"),
+                Err(Error::NotSupported(reason)) => CheckOutcome::new(SelfTestCheck::SpanLoopback, subject, CheckStatus::Skipped, reason),
+                Err(e) => CheckOutcome::new(SelfTestCheck::SpanLoopback, subject, CheckStatus::Failed, e.to_string()),
+            };
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    /// NTP servers answer and the GPS receiver produces sentences
+    pub async fn check_timing(&self) -> Vec<CheckOutcome> {
+        let timeout = Duration::from_millis(self.config.timing_timeout_ms);
+        let mut outcomes = Vec::new();
+
+        for server in &self.config.ntp_servers {
+            let outcome = match sntp_round_trip(server, timeout).await {
+                Ok(rtt) => CheckOutcome::new(SelfTestCheck::Timing, server, CheckStatus::Passed, format!("answered in {:?}", rtt)),
+                Err(e) => CheckOutcome::new(SelfTestCheck::Timing, server, CheckStatus::Failed, e.to_string()),
+            };
+            outcomes.push(outcome);
+        }
+
+        if let Some(device) = &self.config.gps_device {
+            let outcome = match gps_sentence(device, timeout).await {
+                Ok(sentence) => CheckOutcome::new(SelfTestCheck::Timing, device, CheckStatus::Passed, sentence),
+                Err(e) => CheckOutcome::new(SelfTestCheck::Timing, device, CheckStatus::Failed, e.to_string()),
+            };
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    /// Configured certificates load and are currently valid
+    pub fn check_certificates(&self, certificates: &CertificateConfig, now: DateTime<Utc>) -> Vec<CheckOutcome> {
+        if !certificates.enabled {
+            return Vec::new();
+        }
+        certificates
+            .certificates
+            .iter()
+            .map(|managed| {
+                let outcome = |status, detail: String| CheckOutcome::new(SelfTestCheck::Certificates, &managed.id, status, detail);
+                match load_certificate(managed) {
+                    Ok(loaded) if now < loaded.not_before => outcome(CheckStatus::Failed, format!("not valid before {}", loaded.not_before)),
+                    Ok(loaded) if now >= loaded.not_after => outcome(CheckStatus::Failed, format!("expired {}", loaded.not_after)),
+                    Ok(loaded) => outcome(CheckStatus::Passed, format!("valid until {}", loaded.not_after)),
+                    Err(e) => outcome(CheckStatus::Failed, e.to_string()),
+                }
+            })
+            .collect()
+    }
+
+    /// Raise an alarm per failed check; critical failures raise critical alarms
+    pub async fn raise_alarms(&self, report: &SelfTestReport, alarm_manager: &AlarmManager) {
+        for failure in report.failures() {
+            let critical = self.config.critical_checks.contains(&failure.check);
+            warn!("Self-test {:?} failed for {}: {}", failure.check, failure.subject, failure.detail);
+            let result = alarm_manager.raise_alarm(
+                if critical { AlarmSeverity::Critical } else { AlarmSeverity::Major },
+                match failure.check {
+                    SelfTestCheck::TdmHardware | SelfTestCheck::SpanLoopback => AlarmType::Equipment,
+                    SelfTestCheck::Timing => AlarmType::Communication,
+                    SelfTestCheck::Certificates => AlarmType::Security,
+                },
+                AlarmSource {
+                    component: "self-test".to_string(),
+                    instance: failure.subject.clone(),
+                    location: None,
+                },
+                format!("Self-test {:?} failed: {}", failure.check, failure.detail),
+                Some(HashMap::from([("blocks_service".to_string(), critical.to_string())])),
+                None,
+                Some(if critical { "Correct the fault and restart the gateway".to_string() } else { "Investigate the fault".to_string() }),
+            ).await;
+            if let Err(e) = result {
+                warn!("Failed to raise self-test alarm: {}", e);
+            }
+        }
+        info!("Self-test finished: {} checks, {} failed", report.outcomes.len(), report.failures().count());
+    }
+}
+
+/// Whether dotted version `actual` is at least `minimum`
+pub fn version_at_least(actual: &str, minimum: &str) -> bool {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    let (actual, minimum) = (parts(actual), parts(minimum));
+    let len = actual.len().max(minimum.len());
+    let pad = |mut v: Vec<u64>| {
+        v.resize(len, 0);
+        v
+    };
+    pad(actual) >= pad(minimum)
+}
+
+/// Round trip of one SNTP (RFC 4330) client query to `server`
+pub async fn sntp_round_trip(server: &str, timeout: Duration) -> Result<Duration> {
+    let target = if server.contains(':') { server.to_string() } else { format!("{}:{}", server, NTP_PORT) };
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(&target).await?;
+
+    // LI 0, version 4, mode 3 (client)
+    let mut request = [0u8; 48];
+    request[0] = 0x23;
+    let sent = Instant::now();
+    socket.send(&request).await?;
+
+    let mut response = [0u8; 48];
+    let received = tokio::time::timeout(timeout, socket.recv(&mut response))
+        .await
+        .map_err(|_| Error::timeout(format!("No NTP response from {}", server)))??;
+    // Mode 4 (server) with a synchronised stratum
+    if received < 48 || response[0] & 0x07 != 4 || response[1] == 0 || response[1] > 15 {
+        return Err(Error::protocol(format!("Unsynchronised or invalid NTP response from {}", server)));
+    }
+    Ok(sent.elapsed())
+}
+
+/// First NMEA sentence read from the GPS receiver
+pub async fn gps_sentence(device: &str, timeout: Duration) -> Result<String> {
+    let mut file = tokio::fs::File::open(device).await?;
+    let read = async {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 256];
+        loop {
+            let n = file.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(Error::hardware(format!("{} closed without NMEA output", device)));
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buffer);
+            if let Some(start) = text.find("$G") {
+                if let Some(end) = text[start..].find('\n') {
+                    return Ok(text[start..start + end].trim().to_string());
+                }
+            }
+        }
+    };
+    tokio::time::timeout(timeout, read)
+        .await
+        .map_err(|_| Error::timeout(format!("No NMEA sentence from {}", device)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CertificateUsage, ManagedCertificate};
+
+    #[test]
+    fn test_firmware_versions_and_blocking_failures() {
+        assert!(version_at_least("3.1.0", "3.1"));
+        assert!(version_at_least("2.11.1", "2.9.3"));
+        assert!(!version_at_least("2.9", "2.10"));
+        assert!(!version_at_least("", "1.0"));
+
+        let report = SelfTestReport {
+            outcomes: vec![
+                CheckOutcome::new(SelfTestCheck::TdmHardware, "DAHDI", CheckStatus::Passed, "firmware 3.1.0"),
+                CheckOutcome::new(SelfTestCheck::Timing, "ntp1", CheckStatus::Failed, "timeout"),
+                CheckOutcome::new(SelfTestCheck::SpanLoopback, "span 1", CheckStatus::Skipped, "unsupported"),
+            ],
+        };
+        let critical = SelfTestConfig::default().critical_checks;
+        assert_eq!(report.failures().count(), 1);
+        assert_eq!(report.blocking_failures(&critical).count(), 0);
+        assert_eq!(report.blocking_failures(&[SelfTestCheck::Timing]).count(), 1);
+    }
+
+    #[test]
+    fn test_missing_certificate_fails() {
+        let self_test = SelfTest::new(SelfTestConfig { enabled: true, ..Default::default() });
+        let certificates = CertificateConfig {
+            enabled: true,
+            certificates: vec![ManagedCertificate {
+                id: "sip-tls".to_string(),
+                usage: CertificateUsage::SipTls,
+                cert_path: "/nonexistent/cert.pem".to_string(),
+                key_path: "/nonexistent/key.pem".to_string(),
+                domains: vec![],
+                auto_renew: false,
+            }],
+            ..Default::default()
+        };
+
+        let outcomes = self_test.check_certificates(&certificates, Utc::now());
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].status, CheckStatus::Failed);
+        assert_eq!(outcomes[0].subject, "sip-tls");
+        assert!(self_test.check_certificates(&CertificateConfig::default(), Utc::now()).is_empty());
+    }
+}
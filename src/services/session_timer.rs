@@ -0,0 +1,316 @@
+//! SIP session timers (RFC 4028) on both legs of a B2BUA call
+//!
+//! Each leg is its own dialog with its own negotiated session interval.
+//! Leg A's interval is granted from the caller's Session-Expires and Min-SE,
+//! refusing intervals below the configured minimum with 422; leg B's is
+//! requested in the outgoing INVITE and taken from the 2xx. Whichever side
+//! is the refresher sends a re-INVITE or UPDATE every half interval. When
+//! the gateway refreshes and the refresh fails, or the peer refreshes and
+//! lets the session lapse, the call is released on both legs.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::SessionTimerConfig;
+
+pub const SESSION_EXPIRES_HEADER: &str = "Session-Expires";
+pub const MIN_SE_HEADER: &str = "Min-SE";
+
+/// Option tag of the session timer extension
+pub const OPTION_TAG: &str = "timer";
+
+/// Response to a request whose session interval is below our Min-SE
+pub const STATUS_INTERVAL_TOO_SMALL: u16 = 422;
+
+/// Smallest Min-SE the RFC allows
+pub const MIN_SE_FLOOR_SECS: u32 = 90;
+
+/// Side of the initial INVITE transaction that refreshes the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Refresher {
+    Uac,
+    Uas,
+}
+
+impl Refresher {
+    fn as_str(self) -> &'static str {
+        match self {
+            Refresher::Uac => "uac",
+            Refresher::Uas => "uas",
+        }
+    }
+}
+
+/// Session interval negotiated on one dialog
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionTimer {
+    pub interval_secs: u32,
+    pub refresher: Refresher,
+    /// The peer supports the extension, so responses may require it
+    pub peer_supports: bool,
+}
+
+/// Outcome of an incoming INVITE's session timer request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UasNegotiation {
+    Timer(SessionTimer),
+    /// Refuse with 422, advertising this Min-SE
+    TooSmall { min_se: u32 },
+}
+
+/// Value of the first header called `name` or its compact form
+fn header<'a>(headers: &'a [(String, String)], name: &str, compact: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name) || header.eq_ignore_ascii_case(compact))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Interval and refresher parameter of a Session-Expires header
+fn session_expires(headers: &[(String, String)]) -> Option<(u32, Option<Refresher>)> {
+    let value = header(headers, SESSION_EXPIRES_HEADER, "x")?;
+    let mut parts = value.split(';').map(str::trim);
+    let interval = parts.next()?.parse().ok()?;
+    let refresher = parts
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("refresher"))
+        .and_then(|(_, value)| match value.trim().to_ascii_lowercase().as_str() {
+            "uac" => Some(Refresher::Uac),
+            "uas" => Some(Refresher::Uas),
+            _ => None,
+        });
+    Some((interval, refresher))
+}
+
+fn min_se(headers: &[(String, String)]) -> Option<u32> {
+    header(headers, MIN_SE_HEADER, MIN_SE_HEADER)?.split(';').next()?.trim().parse().ok()
+}
+
+fn supports_timer(headers: &[(String, String)]) -> bool {
+    headers
+        .iter()
+        .filter(|(name, _)| ["Supported", "k", "Require"].iter().any(|known| name.eq_ignore_ascii_case(known)))
+        .flat_map(|(_, value)| value.split(','))
+        .any(|tag| tag.trim().eq_ignore_ascii_case(OPTION_TAG))
+}
+
+/// Grant leg A a session interval from the caller's INVITE. The caller's
+/// interval is shortened to the configured one but never below either
+/// side's Min-SE; when the caller leaves the refresher open the configured
+/// side takes it, and callers without the extension leave it to us
+pub fn negotiate_uas(config: &SessionTimerConfig, headers: &[(String, String)]) -> UasNegotiation {
+    let peer_supports = supports_timer(headers);
+    let requested = session_expires(headers);
+    if let Some((interval, _)) = requested {
+        if interval < config.min_se_secs {
+            return UasNegotiation::TooSmall { min_se: config.min_se_secs };
+        }
+    }
+
+    let floor = min_se(headers).unwrap_or(MIN_SE_FLOOR_SECS).max(config.min_se_secs);
+    let interval_secs = requested
+        .map_or(config.session_expires_secs, |(interval, _)| interval.min(config.session_expires_secs))
+        .max(floor);
+    let refresher = match requested.and_then(|(_, refresher)| refresher) {
+        Some(refresher) => refresher,
+        None if peer_supports => config.leg_a_refresher,
+        None => Refresher::Uas,
+    };
+    UasNegotiation::Timer(SessionTimer { interval_secs, refresher, peer_supports })
+}
+
+/// Leg B's session interval from the 2xx to our INVITE; a peer without the
+/// extension leaves us to refresh at the interval we asked for
+pub fn negotiate_uac(config: &SessionTimerConfig, headers: &[(String, String)]) -> SessionTimer {
+    match session_expires(headers) {
+        Some((interval_secs, refresher)) => SessionTimer {
+            interval_secs: interval_secs.max(config.min_se_secs),
+            refresher: refresher.unwrap_or(Refresher::Uac),
+            peer_supports: true,
+        },
+        None => SessionTimer {
+            interval_secs: config.session_expires_secs,
+            refresher: Refresher::Uac,
+            peer_supports: false,
+        },
+    }
+}
+
+/// Add the session timer request to the headers of the INVITE placing leg B
+pub fn add_request_headers(config: &SessionTimerConfig, headers: &mut BTreeMap<String, String>) {
+    headers.insert(SESSION_EXPIRES_HEADER.to_string(), config.session_expires_secs.to_string());
+    headers.insert(MIN_SE_HEADER.to_string(), config.min_se_secs.to_string());
+    let supported = headers.entry("Supported".to_string()).or_default();
+    if !supported.split(',').any(|tag| tag.trim().eq_ignore_ascii_case(OPTION_TAG)) {
+        if !supported.is_empty() {
+            supported.push_str(", ");
+        }
+        supported.push_str(OPTION_TAG);
+    }
+}
+
+/// Headers of the 2xx answering leg A
+pub fn response_headers(timer: &SessionTimer) -> Vec<(String, String)> {
+    let mut headers = vec![(
+        SESSION_EXPIRES_HEADER.to_string(),
+        format!("{};refresher={}", timer.interval_secs, timer.refresher.as_str()),
+    )];
+    if timer.peer_supports {
+        headers.push(("Require".to_string(), OPTION_TAG.to_string()));
+    }
+    headers
+}
+
+/// Due work on a leg's session timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerAction {
+    /// Send a refresh now
+    Refresh,
+    /// The session lapsed: release the call
+    Expired,
+}
+
+/// Running session timer of one leg
+#[derive(Debug, Clone)]
+pub struct LegTimer {
+    pub interval: Duration,
+    /// The gateway refreshes this leg
+    pub local_refresher: bool,
+    pub last_refresh: Instant,
+    /// When the outstanding refresh was sent
+    pub refresh_sent: Option<Instant>,
+}
+
+impl LegTimer {
+    /// Timer of a dialog where the gateway was the UAC (`local_uac`) or
+    /// UAS of the initial INVITE
+    pub fn new(timer: &SessionTimer, local_uac: bool, now: Instant) -> Self {
+        let local_refresher = (timer.refresher == Refresher::Uac) == local_uac;
+        Self {
+            interval: Duration::from_secs(timer.interval_secs as u64),
+            local_refresher,
+            last_refresh: now,
+            refresh_sent: None,
+        }
+    }
+
+    /// Refresh at half the interval; a peer refresher gets until shortly
+    /// before expiry, by a third of the interval or 32 seconds
+    pub fn action(&self, now: Instant) -> Option<TimerAction> {
+        let elapsed = now.saturating_duration_since(self.last_refresh);
+        if self.local_refresher {
+            if elapsed >= self.interval {
+                Some(TimerAction::Expired)
+            } else if self.refresh_sent.is_none() && elapsed >= self.interval / 2 {
+                Some(TimerAction::Refresh)
+            } else {
+                None
+            }
+        } else {
+            let grace = (self.interval / 3).min(Duration::from_secs(32));
+            (elapsed >= self.interval - grace).then_some(TimerAction::Expired)
+        }
+    }
+
+    /// Headers of a refresh we send; the sender of a refresh is its UAC
+    pub fn refresh_headers(&self) -> Vec<(String, String)> {
+        vec![
+            (SESSION_EXPIRES_HEADER.to_string(), format!("{};refresher=uac", self.interval.as_secs())),
+            ("Supported".to_string(), OPTION_TAG.to_string()),
+        ]
+    }
+
+    /// A refresh succeeded, ours answered 2xx or the peer's received, or a
+    /// new dialog replaced the leg; the interval follows any Session-Expires
+    pub fn refreshed(&mut self, headers: &[(String, String)], now: Instant) {
+        if let Some((interval, _)) = session_expires(headers) {
+            self.interval = Duration::from_secs(interval.max(MIN_SE_FLOOR_SECS) as u64);
+        }
+        self.last_refresh = now;
+        self.refresh_sent = None;
+    }
+
+    /// Our refresh was refused with 422: retry at the peer's Min-SE
+    pub fn raise_interval(&mut self, headers: &[(String, String)]) -> bool {
+        match min_se(headers) {
+            Some(min_se) if Duration::from_secs(min_se as u64) > self.interval => {
+                self.interval = Duration::from_secs(min_se as u64);
+                self.refresh_sent = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Session timers of both legs of a call
+#[derive(Debug, Clone, Default)]
+pub struct CallSessionTimers {
+    pub leg_a: Option<LegTimer>,
+    pub leg_b: Option<LegTimer>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_uas_negotiation() {
+        let config = SessionTimerConfig { enabled: true, ..Default::default() };
+
+        // Long intervals are shortened, the caller's refresher is kept
+        let invite = headers(&[("Supported", "100rel, timer"), ("Session-Expires", "7200;refresher=uac")]);
+        let UasNegotiation::Timer(timer) = negotiate_uas(&config, &invite) else { panic!("expected a timer") };
+        assert_eq!((timer.interval_secs, timer.refresher, timer.peer_supports), (1800, Refresher::Uac, true));
+        assert_eq!(response_headers(&timer)[0].1, "1800;refresher=uac");
+
+        // Too short for our Min-SE
+        let short = headers(&[("x", "60")]);
+        assert_eq!(negotiate_uas(&config, &short), UasNegotiation::TooSmall { min_se: 90 });
+
+        // A caller without the extension leaves refreshing to us
+        let UasNegotiation::Timer(timer) = negotiate_uas(&config, &[]) else { panic!("expected a timer") };
+        assert_eq!((timer.refresher, timer.peer_supports), (Refresher::Uas, false));
+        assert_eq!(response_headers(&timer).len(), 1);
+
+        let answer = headers(&[("Session-Expires", "900;refresher=uas"), ("Require", "timer")]);
+        assert_eq!(negotiate_uac(&config, &answer).refresher, Refresher::Uas);
+
+        let mut invite = BTreeMap::from([("Supported".to_string(), "100rel".to_string())]);
+        add_request_headers(&config, &mut invite);
+        assert_eq!(invite["Supported"], "100rel, timer");
+        assert_eq!(invite["Session-Expires"], "1800");
+    }
+
+    #[test]
+    fn test_leg_timer_actions() {
+        let start = Instant::now();
+        let timer = SessionTimer { interval_secs: 120, refresher: Refresher::Uac, peer_supports: true };
+
+        // We placed the leg and refresh it
+        let mut local = LegTimer::new(&timer, true, start);
+        assert!(local.local_refresher);
+        assert_eq!(local.action(start + Duration::from_secs(59)), None);
+        assert_eq!(local.action(start + Duration::from_secs(60)), Some(TimerAction::Refresh));
+        local.refresh_sent = Some(start + Duration::from_secs(60));
+        assert_eq!(local.action(start + Duration::from_secs(90)), None);
+        assert_eq!(local.action(start + Duration::from_secs(120)), Some(TimerAction::Expired));
+        local.refreshed(&headers(&[("Session-Expires", "300;refresher=uac")]), start + Duration::from_secs(100));
+        assert_eq!(local.interval, Duration::from_secs(300));
+        assert_eq!(local.action(start + Duration::from_secs(200)), None);
+        assert!(!local.raise_interval(&headers(&[("Min-SE", "200")])));
+
+        // The caller refreshes leg A; it lapses 32s before expiry
+        let remote = LegTimer::new(&timer, false, start);
+        assert!(!remote.local_refresher);
+        assert_eq!(remote.action(start + Duration::from_secs(80)), None);
+        assert_eq!(remote.action(start + Duration::from_secs(88)), Some(TimerAction::Expired));
+    }
+}
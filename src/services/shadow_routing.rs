@@ -0,0 +1,221 @@
+//! Shadow routing: dry runs of a candidate routing table on live traffic
+//!
+//! With a candidate table configured, every call routed by the static table
+//! is also routed through the candidate. The call always follows the active
+//! decision; where the candidate would have sent it elsewhere the difference
+//! is logged, counted per (active, candidate) target pair and kept in a short
+//! history, so a large routing change can be checked against real traffic
+//! before it is activated.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::{RoutingRule, ShadowRoutingConfig};
+
+/// Management API path serving the shadow routing diff summary
+pub const SHADOW_ROUTING_PATH: &str = "/api/v1/b2bua/shadow-routing";
+
+/// The parts of a routing decision that decide where a call goes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RouteDecision {
+    pub route_type: String,
+    pub target: Option<String>,
+    /// Request-URI leg B would be sent to, after number translation
+    pub destination_uri: String,
+    pub alternate_targets: Vec<String>,
+    pub ring_group: Option<String>,
+}
+
+/// A call the candidate table would have routed differently
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RouteDifference {
+    pub callee: String,
+    pub active: RouteDecision,
+    pub candidate: RouteDecision,
+    /// Names of the decision fields that differ
+    pub fields: Vec<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// Calls moved from one target to another by the candidate table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RouteChangeCount {
+    pub active_target: Option<String>,
+    pub candidate_target: Option<String>,
+    pub calls: u64,
+}
+
+/// Diff report of the candidate table against live traffic
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ShadowRoutingSummary {
+    pub evaluated: u64,
+    pub differed: u64,
+    /// Differences per target pair, most frequent first
+    pub changes: Vec<RouteChangeCount>,
+    /// Latest differences, newest last
+    pub recent: Vec<RouteDifference>,
+}
+
+/// Compares active and candidate routing decisions
+pub struct ShadowRouter {
+    config: ShadowRoutingConfig,
+    evaluated: AtomicU64,
+    differed: AtomicU64,
+    changes: DashMap<(Option<String>, Option<String>), u64>,
+    recent: Mutex<VecDeque<RouteDifference>>,
+}
+
+impl ShadowRouter {
+    pub fn new(config: ShadowRoutingConfig) -> Self {
+        Self {
+            config,
+            evaluated: AtomicU64::new(0),
+            differed: AtomicU64::new(0),
+            changes: DashMap::new(),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The candidate table
+    pub fn routing_table(&self) -> &[RoutingRule] {
+        &self.config.routing_table
+    }
+
+    /// Record the decisions for one call; returns the difference, if any
+    pub fn record(&self, callee: &str, active: RouteDecision, candidate: RouteDecision) -> Option<RouteDifference> {
+        self.evaluated.fetch_add(1, Ordering::Relaxed);
+        let fields = differing_fields(&active, &candidate);
+        if fields.is_empty() {
+            return None;
+        }
+
+        self.differed.fetch_add(1, Ordering::Relaxed);
+        info!(
+            "Shadow routing differs for {}: {} -> {} ({})",
+            callee,
+            active.destination_uri,
+            candidate.destination_uri,
+            fields.join(", ")
+        );
+        *self
+            .changes
+            .entry((active.target.clone(), candidate.target.clone()))
+            .or_insert(0) += 1;
+
+        let difference = RouteDifference {
+            callee: callee.to_string(),
+            active,
+            candidate,
+            fields,
+            at: Utc::now(),
+        };
+        if let Ok(mut recent) = self.recent.lock() {
+            recent.push_back(difference.clone());
+            while recent.len() > self.config.max_recent {
+                recent.pop_front();
+            }
+        }
+        Some(difference)
+    }
+
+    pub fn summary(&self) -> ShadowRoutingSummary {
+        let mut changes: Vec<RouteChangeCount> = self
+            .changes
+            .iter()
+            .map(|entry| RouteChangeCount {
+                active_target: entry.key().0.clone(),
+                candidate_target: entry.key().1.clone(),
+                calls: *entry.value(),
+            })
+            .collect();
+        changes.sort_by(|a, b| {
+            b.calls
+                .cmp(&a.calls)
+                .then_with(|| (&a.active_target, &a.candidate_target).cmp(&(&b.active_target, &b.candidate_target)))
+        });
+
+        ShadowRoutingSummary {
+            evaluated: self.evaluated.load(Ordering::Relaxed),
+            differed: self.differed.load(Ordering::Relaxed),
+            changes,
+            recent: self.recent.lock().map(|recent| recent.iter().cloned().collect()).unwrap_or_default(),
+        }
+    }
+}
+
+fn differing_fields(active: &RouteDecision, candidate: &RouteDecision) -> Vec<String> {
+    [
+        ("route_type", active.route_type != candidate.route_type),
+        ("target", active.target != candidate.target),
+        ("destination_uri", active.destination_uri != candidate.destination_uri),
+        ("alternate_targets", active.alternate_targets != candidate.alternate_targets),
+        ("ring_group", active.ring_group != candidate.ring_group),
+    ]
+    .into_iter()
+    .filter(|(_, differs)| *differs)
+    .map(|(field, _)| field.to_string())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decision(target: &str, uri: &str) -> RouteDecision {
+        RouteDecision {
+            route_type: "Trunk".to_string(),
+            target: Some(target.to_string()),
+            destination_uri: uri.to_string(),
+            alternate_targets: Vec::new(),
+            ring_group: None,
+        }
+    }
+
+    #[test]
+    fn test_differences_are_counted_and_bounded() {
+        let router = ShadowRouter::new(ShadowRoutingConfig {
+            enabled: true,
+            routing_table: Vec::new(),
+            max_recent: 2,
+        });
+
+        let same = decision("carrier-a", "sip:2125550100@carrier-a");
+        assert!(router.record("2125550100", same.clone(), same).is_none());
+
+        for callee in ["2125550101", "2125550102", "2125550103"] {
+            let difference = router
+                .record(
+                    callee,
+                    decision("carrier-a", &format!("sip:{}@carrier-a", callee)),
+                    decision("carrier-b", &format!("sip:{}@carrier-b", callee)),
+                )
+                .unwrap();
+            assert_eq!(difference.fields, vec!["target".to_string(), "destination_uri".to_string()]);
+        }
+        let translated = router
+            .record(
+                "4420",
+                decision("carrier-a", "sip:4420@carrier-a"),
+                decision("carrier-a", "sip:0114420@carrier-a"),
+            )
+            .unwrap();
+        assert_eq!(translated.fields, vec!["destination_uri".to_string()]);
+
+        let summary = router.summary();
+        assert_eq!(summary.evaluated, 5);
+        assert_eq!(summary.differed, 4);
+        assert_eq!(summary.changes[0].active_target.as_deref(), Some("carrier-a"));
+        assert_eq!(summary.changes[0].candidate_target.as_deref(), Some("carrier-b"));
+        assert_eq!(summary.changes[0].calls, 3);
+        assert_eq!(summary.changes[1].calls, 1);
+        assert_eq!(summary.recent.len(), 2);
+        assert_eq!(summary.recent[1].callee, "4420");
+    }
+}
@@ -2,6 +2,8 @@
 //! 
 //! This module provides SIP routing functionality that leverages the
 //! external redfire-sip-stack library for message parsing and validation.
+//! Calls to users registered with the built-in registrar are routed to
+//! their registered contact ahead of the routing rules.
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -14,6 +16,7 @@ use tokio::sync::{mpsc, RwLock};
 use tracing::{info, warn};
 
 use crate::config::{RouteType, RoutingRule};
+use crate::protocols::sip_registrar::Registrar;
 use crate::{Error, Result};
 
 /// SIP routing decision
@@ -40,6 +43,9 @@ pub struct RoutingContext {
     pub timestamp: Instant,
 }
 
+/// Rule ID of decisions reaching a registered contact
+pub const REGISTRAR_RULE_ID: &str = "registrar";
+
 /// Route target information
 #[derive(Debug, Clone)]
 pub struct RouteTarget {
@@ -119,6 +125,8 @@ pub struct SipRouter {
     routing_rules: Arc<RwLock<Vec<RoutingRule>>>,
     route_targets: Arc<DashMap<String, RouteTarget>>,
     load_balance_algorithm: LoadBalanceAlgorithm,
+    /// Registered users are reached at their contacts
+    registrar: Option<Arc<Registrar>>,
     event_tx: mpsc::UnboundedSender<RoutingEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<RoutingEvent>>,
     is_running: bool,
@@ -137,6 +145,7 @@ impl SipRouter {
             routing_rules: Arc::new(RwLock::new(routing_rules)),
             route_targets: Arc::new(DashMap::new()),
             load_balance_algorithm,
+            registrar: None,
             event_tx,
             event_rx: Some(event_rx),
             is_running: false,
@@ -147,6 +156,51 @@ impl SipRouter {
         self.event_rx.take()
     }
 
+    /// Route calls for registered users to their contacts
+    pub fn set_registrar(&mut self, registrar: Arc<Registrar>) {
+        self.registrar = Some(registrar);
+    }
+
+    /// Decision reaching the preferred contact of a registered callee
+    fn route_to_registration(&self, context: &RoutingContext) -> Option<RoutingDecision> {
+        let registrar = self.registrar.as_ref()?;
+        let binding = registrar
+            .lookup(&context.callee)
+            .into_iter()
+            .find(|binding| binding.address().is_some())?;
+        let target_address = binding.address()?;
+
+        let target = RouteTarget {
+            id: format!("{}@{}", binding.aor, target_address),
+            address: target_address,
+            weight: 1,
+            priority: 0,
+            max_calls: u32::MAX,
+            current_calls: 0,
+            health_status: HealthStatus::Unknown,
+            last_health_check: Instant::now(),
+            response_time_ms: 0,
+            success_rate: 100.0,
+        };
+        let _ = self.event_tx.send(RoutingEvent::RouteResolved {
+            call_id: context.call_id.clone(),
+            rule_id: REGISTRAR_RULE_ID.to_string(),
+            target,
+            decision_time_ms: 0,
+        });
+        info!("Routed call {} to registered contact {}", context.call_id, binding.contact);
+
+        Some(RoutingDecision {
+            rule_id: REGISTRAR_RULE_ID.to_string(),
+            target_uri: binding.contact,
+            target_address,
+            translated_number: context.callee.clone(),
+            priority: 0,
+            route_type: RouteType::Direct,
+            load_balance_weight: 1,
+        })
+    }
+
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting SIP router stub - external library integration required");
         self.is_running = true;
@@ -161,6 +215,9 @@ impl SipRouter {
     }
 
     pub async fn route_call(&self, context: RoutingContext) -> Result<RoutingDecision> {
+        if let Some(decision) = self.route_to_registration(&context) {
+            return Ok(decision);
+        }
         warn!("SIP routing requested but router is in stub mode");
         
         let start_time = Instant::now();
@@ -216,7 +273,7 @@ impl SipRouter {
             info!("Removed stub routing target: {} at {}", target.id, target.address);
             Ok(())
         } else {
-            Err(Error::b2bua("Target not found"))
+            Err(Error::routing("Target not found"))
         }
     }
 
@@ -225,7 +282,7 @@ impl SipRouter {
             target.current_calls = call_count;
             Ok(())
         } else {
-            Err(Error::b2bua("Target not found"))
+            Err(Error::routing("Target not found"))
         }
     }
 
@@ -300,6 +357,11 @@ mod tests {
                 priority: 1,
                 translation: None,
                 codec_preference: vec![],
+                no_answer_timeout_secs: None,
+                alternate_targets: vec![],
+                no_answer_divert: None,
+                failover_actions: Default::default(),
+                early_media: None,
             }
         ];
 
@@ -332,6 +394,66 @@ mod tests {
         router.stop().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_registered_callee() {
+        use crate::config::{RegistrarUser, SipRegistrarConfig};
+        use crate::protocols::sip_registrar::{self, ConfigCredentials, ContactEntry, DigestCredentials, Registration};
+
+        let users = [RegistrarUser { username: "2000".to_string(), password: Some("pw".to_string()), ha1: None }];
+        let config = SipRegistrarConfig { enabled: true, users: users.to_vec(), ..SipRegistrarConfig::default() };
+        let registrar = Arc::new(Registrar::new(config, "gw.example.com", Arc::new(ConfigCredentials::new(&users))));
+
+        let mut registration = Registration {
+            aor: "2000".to_string(),
+            contacts: Some(vec![ContactEntry::parse("<sip:2000@192.168.1.50:5062>").unwrap()]),
+            expires: None,
+            call_id: "r1".to_string(),
+            cseq: 1,
+            authorization: None,
+            flow: None,
+        };
+        let challenge = registrar.handle_register(&registration).await;
+        let nonce = challenge.headers[0].1.split("nonce=\"").nth(1).unwrap().split('"').next().unwrap();
+        let mut credentials = DigestCredentials {
+            username: "2000".to_string(),
+            realm: "gw.example.com".to_string(),
+            nonce: nonce.to_string(),
+            uri: "sip:gw.example.com".to_string(),
+            response: String::new(),
+            algorithm: None,
+            opaque: None,
+            qop: Some("auth".to_string()),
+            nc: Some("00000001".to_string()),
+            cnonce: Some("c0ffee".to_string()),
+        };
+        let ha1 = sip_registrar::ha1("2000", "gw.example.com", "pw");
+        credentials.response = sip_registrar::digest_response(&ha1, "REGISTER", &credentials);
+        registration.cseq = 2;
+        registration.authorization = Some(format!(
+            "Digest username=\"2000\", realm=\"gw.example.com\", nonce=\"{}\", uri=\"sip:gw.example.com\", \
+             response=\"{}\", qop=auth, nc=00000001, cnonce=\"c0ffee\"",
+            credentials.nonce, credentials.response
+        ));
+        assert_eq!(registrar.handle_register(&registration).await.status_code, 200);
+
+        let mut router = SipRouter::new(vec![], LoadBalanceAlgorithm::RoundRobin);
+        router.set_registrar(registrar);
+        let context = |callee: &str| RoutingContext {
+            call_id: "call".to_string(),
+            caller: "1000".to_string(),
+            callee: callee.to_string(),
+            original_uri: format!("sip:{}@gw.example.com", callee),
+            source_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 5060),
+            headers: HashMap::new(),
+            timestamp: Instant::now(),
+        };
+        let decision = router.route_call(context("2000")).await.unwrap();
+        assert_eq!(decision.rule_id, REGISTRAR_RULE_ID);
+        assert_eq!(decision.target_uri, "sip:2000@192.168.1.50:5062");
+        assert_eq!(decision.target_address, "192.168.1.50:5062".parse().unwrap());
+        assert_eq!(router.route_call(context("3000")).await.unwrap().rule_id, "stub-rule");
+    }
+
     #[tokio::test]
     async fn test_target_management() {
         let router = SipRouter::new(vec![], LoadBalanceAlgorithm::RoundRobin);
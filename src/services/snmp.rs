@@ -2,16 +2,28 @@
 
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use dashmap::DashMap;
 use tokio::net::UdpSocket;
-use tokio::sync::{mpsc, RwLock};
-use tracing::{error, info};
-
-use crate::config::SnmpConfig;
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tracing::{debug, error, info, warn};
+
+use crate::config::{SnmpConfig, SnmpInformTarget};
+use crate::services::snmp_v3::{
+    self, encode_secure_message, open_remote_message, EngineState, IncomingRequest, OutgoingSecurity,
+    Pdu, ScopedPdu, UsmEngine, V3Message,
+};
+use crate::utils::netbind;
 use crate::{Error, Result};
 
+/// sysUpTime.0
+const SYS_UPTIME_OID: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 3, 0];
+/// snmpTrapOID.0
+const SNMP_TRAP_OID: [u32; 11] = [1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
 /// SNMP version
 #[derive(Debug, Clone, PartialEq)]
 pub enum SnmpVersion {
@@ -70,6 +82,9 @@ pub enum SnmpValue {
     TimeTicks(u32),
     Opaque(Vec<u8>),
     Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
 }
 
 /// Object Identifier (OID)
@@ -186,6 +201,19 @@ pub enum SnmpEvent {
         source: SocketAddr, 
         community: String 
     },
+    UsmFailure {
+        source: SocketAddr,
+        user: String,
+        reason: String,
+    },
+    InformAcknowledged {
+        destination: SocketAddr,
+        request_id: i32,
+    },
+    InformFailed {
+        destination: SocketAddr,
+        error: String,
+    },
     Error { 
         source: SocketAddr, 
         error: String 
@@ -198,6 +226,10 @@ pub struct SnmpService {
     socket: Option<Arc<UdpSocket>>,
     mib_tree: Arc<RwLock<HashMap<Oid, MibNode>>>,
     trap_destinations: Arc<RwLock<Vec<SocketAddr>>>,
+    usm: Option<Arc<UsmEngine>>,
+    pending_replies: Arc<DashMap<i32, oneshot::Sender<Vec<u8>>>>,
+    remote_engines: Arc<DashMap<SocketAddr, RemoteEngine>>,
+    next_msg_id: AtomicI32,
     event_tx: mpsc::UnboundedSender<SnmpEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<SnmpEvent>>,
     is_running: bool,
@@ -214,6 +246,10 @@ impl SnmpService {
             socket: None,
             mib_tree: Arc::new(RwLock::new(HashMap::new())),
             trap_destinations: Arc::new(RwLock::new(Vec::new())),
+            usm: None,
+            pending_replies: Arc::new(DashMap::new()),
+            remote_engines: Arc::new(DashMap::new()),
+            next_msg_id: AtomicI32::new(rand::random::<i32>() & 0x3fff_ffff),
             event_tx,
             event_rx: Some(event_rx),
             is_running: false,
@@ -234,8 +270,10 @@ impl SnmpService {
         info!("Starting SNMP agent on {}:{}", self.config.bind_address, self.config.port);
 
         // Bind to socket
-        let addr = format!("{}:{}", self.config.bind_address, self.config.port);
-        let socket = UdpSocket::bind(&addr).await
+        let addr: SocketAddr = format!("{}:{}", self.config.bind_address, self.config.port)
+            .parse()
+            .map_err(|e| Error::invalid_config(format!("Invalid SNMP bind address: {}", e)))?;
+        let socket = netbind::bind_udp(addr, &self.config.network)
             .map_err(|e| Error::network(format!("Failed to bind SNMP socket: {}", e)))?;
         
         self.socket = Some(Arc::new(socket));
@@ -243,6 +281,12 @@ impl SnmpService {
         // Initialize MIB tree
         self.initialize_mib().await?;
 
+        // Bring up the USM engine with a persisted engine ID and boot count
+        if matches!(self.config.version, crate::config::SnmpVersion::V3) {
+            let state = EngineState::load_and_increment(&self.config.v3)?;
+            self.usm = Some(Arc::new(UsmEngine::new(&self.config.v3, state)?));
+        }
+
         self.is_running = true;
 
         // Start message processing loop
@@ -251,6 +295,8 @@ impl SnmpService {
             let event_tx = self.event_tx.clone();
             let mib_tree = Arc::clone(&self.mib_tree);
             let config = self.config.clone();
+            let usm = self.usm.clone();
+            let pending_replies = Arc::clone(&self.pending_replies);
             
             tokio::spawn(async move {
                 let mut buffer = [0u8; 1500]; // MTU-sized buffer
@@ -259,9 +305,22 @@ impl SnmpService {
                     match socket_clone.recv_from(&mut buffer).await {
                         Ok((len, src)) => {
                             let data = &buffer[..len];
-                            if let Err(e) = Self::handle_snmp_request(
-                                data, src, &socket_clone, &event_tx, &mib_tree, &config
-                            ).await {
+                            let result = match usm.as_ref().filter(|_| snmp_v3::is_v3_message(data)) {
+                                Some(usm) => {
+                                    // Replies to our informs and discovery probes
+                                    let waiter = V3Message::peek_msg_id(data)
+                                        .and_then(|id| pending_replies.remove(&id));
+                                    if let Some((_, waiter)) = waiter {
+                                        let _ = waiter.send(data.to_vec());
+                                        continue;
+                                    }
+                                    Self::handle_v3_request(data, src, &socket_clone, &event_tx, &mib_tree, usm).await
+                                }
+                                None => {
+                                    Self::handle_snmp_request(data, src, &socket_clone, &event_tx, &mib_tree, &config).await
+                                }
+                            };
+                            if let Err(e) = result {
                                 error!("Error handling SNMP request from {}: {}", src, e);
                                 let _ = event_tx.send(SnmpEvent::Error {
                                     source: src,
@@ -404,7 +463,7 @@ impl SnmpService {
                 source: src,
                 community: message.community.clone(),
             });
-            return Err(Error::internal("Authentication failed"));
+            return Err(Error::protocol("Authentication failed"));
         }
 
         // Process request
@@ -456,8 +515,8 @@ impl SnmpService {
                 message.community == config.community
             },
             SnmpVersion::V3 => {
-                // SNMPv3 would require proper USM authentication
-                true // Simplified for demo
+                // SNMPv3 messages are only accepted through the USM engine
+                false
             }
         }
     }
@@ -519,6 +578,118 @@ impl SnmpService {
         Ok(response)
     }
 
+    async fn handle_v3_request(
+        data: &[u8],
+        src: SocketAddr,
+        socket: &UdpSocket,
+        event_tx: &mpsc::UnboundedSender<SnmpEvent>,
+        mib_tree: &Arc<RwLock<HashMap<Oid, MibNode>>>,
+        usm: &UsmEngine,
+    ) -> Result<()> {
+        let request = match usm.process_incoming(data)? {
+            Ok(request) => request,
+            Err(failure) => {
+                let _ = event_tx.send(SnmpEvent::UsmFailure {
+                    source: src,
+                    user: failure.user_name.clone(),
+                    reason: format!("{:?}", failure.error),
+                });
+                if failure.reportable {
+                    let report = usm.report(&failure)?;
+                    socket.send_to(&report, src).await
+                        .map_err(|e| Error::network(format!("Failed to send SNMP report: {}", e)))?;
+                }
+                return Ok(());
+            }
+        };
+
+        let pdu = Self::process_v3_pdu(&request, usm, mib_tree).await;
+        let response = usm.respond(&request, pdu)?;
+        socket.send_to(&response, src).await
+            .map_err(|e| Error::network(format!("Failed to send SNMP response: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Answer a v3 request, hiding objects outside the user's VACM view
+    async fn process_v3_pdu(
+        request: &IncomingRequest,
+        usm: &UsmEngine,
+        mib_tree: &Arc<RwLock<HashMap<Oid, MibNode>>>,
+    ) -> Pdu {
+        let incoming = &request.scoped_pdu.pdu;
+        let readable = |oid: &Oid| usm.is_readable(&request.user, oid);
+        let mut response = Pdu::new(PduType::GetResponse, incoming.request_id, Vec::new());
+        let mib = mib_tree.read().await;
+
+        match incoming.pdu_type {
+            PduType::GetRequest => {
+                for var_bind in &incoming.var_binds {
+                    let value = match Self::lookup(&mib, &var_bind.oid) {
+                        Some(node) if readable(&var_bind.oid) => Self::get_mib_value(node).await,
+                        _ => SnmpValue::NoSuchObject,
+                    };
+                    response.var_binds.push(VarBind { oid: var_bind.oid.clone(), value });
+                }
+            },
+            PduType::GetNextRequest | PduType::GetBulkRequest => {
+                let (non_repeaters, max_repetitions) = match incoming.pdu_type {
+                    PduType::GetBulkRequest => (incoming.error_status.max(0) as usize, incoming.error_index.max(0) as usize),
+                    _ => (incoming.var_binds.len(), 1),
+                };
+
+                for (index, var_bind) in incoming.var_binds.iter().enumerate() {
+                    let repetitions = if index < non_repeaters { 1 } else { max_repetitions };
+                    let mut cursor = var_bind.oid.clone();
+                    for _ in 0..repetitions {
+                        match Self::get_next_readable(&cursor, &mib, &readable) {
+                            Some(next) => {
+                                let value = Self::get_mib_value(&mib[&next]).await;
+                                response.var_binds.push(VarBind { oid: next.clone(), value });
+                                cursor = next;
+                            }
+                            None => {
+                                response.var_binds.push(VarBind { oid: cursor, value: SnmpValue::EndOfMibView });
+                                break;
+                            }
+                        }
+                    }
+                }
+            },
+            PduType::SetRequest => {
+                response.error_status = ErrorStatus::NoAccess as i32;
+                response.error_index = 1;
+                response.var_binds = incoming.var_binds.clone();
+            },
+            _ => {
+                response.error_status = ErrorStatus::GenErr as i32;
+            }
+        }
+
+        response
+    }
+
+    /// Find the MIB node for an object or its `.0` scalar instance
+    fn lookup<'a>(mib: &'a HashMap<Oid, MibNode>, oid: &Oid) -> Option<&'a MibNode> {
+        mib.get(oid).or_else(|| match oid.components.split_last() {
+            Some((0, parent)) => mib.get(&Oid::new(parent.to_vec())),
+            _ => None,
+        })
+    }
+
+    fn get_next_readable(
+        current_oid: &Oid,
+        mib: &HashMap<Oid, MibNode>,
+        readable: &dyn Fn(&Oid) -> bool,
+    ) -> Option<Oid> {
+        let mut candidates: Vec<&Oid> = mib.keys()
+            .filter(|oid| Self::oid_compare(oid, current_oid) == std::cmp::Ordering::Greater && readable(oid))
+            .collect();
+
+        candidates.sort_by(|a, b| Self::oid_compare(a, b));
+        candidates.first().map(|oid| (*oid).clone())
+    }
+
     async fn get_mib_value(node: &MibNode) -> SnmpValue {
         // Get actual values based on the getter function
         match node.value_getter.as_deref() {
@@ -612,7 +783,13 @@ impl SnmpService {
 
         if let Some(socket) = &self.socket {
             for dest in destinations {
-                let trap_data = Self::encode_trap(&trap)?;
+                let trap_data = match (&self.usm, &self.config.v3.trap_user) {
+                    (Some(usm), Some(user)) => {
+                        let pdu = Pdu::new(PduType::Trap, self.next_msg_id(), Self::notification_var_binds(&trap));
+                        usm.encode_notification(user, self.next_msg_id(), pdu)?
+                    }
+                    _ => Self::encode_trap(&trap)?,
+                };
                 
                 match socket.send_to(&trap_data, dest).await {
                     Ok(_) => {
@@ -644,6 +821,167 @@ impl SnmpService {
         Ok(data)
     }
 
+    /// Send an acknowledged notification (InformRequest) to every
+    /// configured inform target, retrying until each acknowledges
+    pub async fn send_inform(&self, trap: SnmpTrap) -> Result<()> {
+        let (usm, socket) = match (&self.usm, &self.socket) {
+            (Some(usm), Some(socket)) if self.is_running => (usm, socket),
+            _ => return Err(Error::invalid_state("SNMPv3 agent is not running")),
+        };
+
+        for target in &self.config.v3.inform_targets {
+            let destination: SocketAddr = target.address.parse()
+                .map_err(|e| Error::parse(format!("Invalid inform target {}: {}", target.address, e)))?;
+
+            match self.deliver_inform(usm, socket, destination, target, &trap).await {
+                Ok(request_id) => {
+                    info!("SNMP inform {} acknowledged by {}", request_id, destination);
+                    let _ = self.event_tx.send(SnmpEvent::InformAcknowledged { destination, request_id });
+                }
+                Err(e) => {
+                    warn!("SNMP inform to {} failed: {}", destination, e);
+                    let _ = self.event_tx.send(SnmpEvent::InformFailed {
+                        destination,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn deliver_inform(
+        &self,
+        usm: &UsmEngine,
+        socket: &UdpSocket,
+        destination: SocketAddr,
+        target: &SnmpInformTarget,
+        trap: &SnmpTrap,
+    ) -> Result<i32> {
+        // The inform receiver is the authoritative engine, so keys are
+        // localized to its engine ID
+        let mut remote = self.discover_engine(socket, destination, target).await?;
+        let user = usm.localize_for(&target.user, &remote.engine_id)?;
+        let request_id = self.next_msg_id();
+        let scoped = ScopedPdu {
+            context_engine_id: remote.engine_id.clone(),
+            context_name: Vec::new(),
+            pdu: Pdu::new(PduType::InformRequest, request_id, Self::notification_var_binds(trap)),
+        };
+        let wait = Duration::from_millis(target.timeout_ms);
+
+        for attempt in 0..=target.retries {
+            let msg_id = self.next_msg_id();
+            let security = OutgoingSecurity {
+                user: Some(&user),
+                level: user.max_security_level(),
+                engine_id: &remote.engine_id,
+                engine_boots: remote.engine_boots,
+                engine_time: remote.estimated_time(),
+            };
+            let message = encode_secure_message(&security, msg_id, true, &scoped, usm.next_salt())?;
+
+            let reply = match self.exchange(socket, destination, msg_id, &message, wait).await? {
+                Some(reply) => reply,
+                None => {
+                    debug!("SNMP inform {} to {} timed out (attempt {})", request_id, destination, attempt + 1);
+                    continue;
+                }
+            };
+
+            let (header, reply) = open_remote_message(&reply, &user)?;
+            match reply.pdu.pdu_type {
+                PduType::GetResponse if reply.pdu.request_id == request_id => return Ok(request_id),
+                PduType::Report => {
+                    // Typically notInTimeWindow: resynchronize and retry
+                    remote = RemoteEngine::from_message(&header);
+                    self.remote_engines.insert(destination, remote.clone());
+                }
+                _ => {
+                    return Err(Error::protocol(format!("Unexpected reply to inform {}", request_id)));
+                }
+            }
+        }
+
+        Err(Error::timeout(format!("No acknowledgement for inform {} from {}", request_id, destination)))
+    }
+
+    /// Learn the engine ID, boots and time of a remote engine (RFC 3414 4)
+    async fn discover_engine(
+        &self,
+        socket: &UdpSocket,
+        destination: SocketAddr,
+        target: &SnmpInformTarget,
+    ) -> Result<RemoteEngine> {
+        if let Some(remote) = self.remote_engines.get(&destination) {
+            return Ok(remote.clone());
+        }
+
+        let wait = Duration::from_millis(target.timeout_ms);
+        for _ in 0..=target.retries {
+            let msg_id = self.next_msg_id();
+            let probe = UsmEngine::discovery_probe(msg_id)?;
+            if let Some(reply) = self.exchange(socket, destination, msg_id, &probe, wait).await? {
+                let (header, _) = V3Message::decode(&reply)?;
+                if header.security.engine_id.is_empty() {
+                    return Err(Error::protocol(format!("{} did not report an engine ID", destination)));
+                }
+                let remote = RemoteEngine::from_message(&header);
+                self.remote_engines.insert(destination, remote.clone());
+                return Ok(remote);
+            }
+        }
+
+        Err(Error::timeout(format!("SNMP engine discovery to {} timed out", destination)))
+    }
+
+    /// Send a message and wait for the reply carrying the same msgID
+    async fn exchange(
+        &self,
+        socket: &UdpSocket,
+        destination: SocketAddr,
+        msg_id: i32,
+        message: &[u8],
+        wait: Duration,
+    ) -> Result<Option<Vec<u8>>> {
+        let (tx, rx) = oneshot::channel();
+        self.pending_replies.insert(msg_id, tx);
+
+        if let Err(e) = socket.send_to(message, destination).await {
+            self.pending_replies.remove(&msg_id);
+            return Err(Error::network(format!("Failed to send SNMP message to {}: {}", destination, e)));
+        }
+
+        match tokio::time::timeout(wait, rx).await {
+            Ok(Ok(reply)) => Ok(Some(reply)),
+            _ => {
+                self.pending_replies.remove(&msg_id);
+                Ok(None)
+            }
+        }
+    }
+
+    /// sysUpTime.0 and snmpTrapOID.0 followed by the trap's own bindings
+    fn notification_var_binds(trap: &SnmpTrap) -> Vec<VarBind> {
+        let mut var_binds = vec![
+            VarBind {
+                oid: Oid::new(SYS_UPTIME_OID.to_vec()),
+                value: SnmpValue::TimeTicks(trap.timestamp),
+            },
+            VarBind {
+                oid: Oid::new(SNMP_TRAP_OID.to_vec()),
+                value: SnmpValue::ObjectId(trap.enterprise_oid.append(trap.specific_trap).components),
+            },
+        ];
+        var_binds.extend(trap.var_binds.iter().cloned());
+        var_binds
+    }
+
+    fn next_msg_id(&self) -> i32 {
+        self.next_msg_id.fetch_add(1, Ordering::Relaxed) & 0x7fff_ffff
+    }
+
     /// Add trap destination
     pub async fn add_trap_destination(&self, dest: SocketAddr) -> Result<()> {
         let mut destinations = self.trap_destinations.write().await;
@@ -667,6 +1005,30 @@ impl SnmpService {
     }
 }
 
+/// Snapshot of a remote authoritative engine's clock
+#[derive(Debug, Clone)]
+struct RemoteEngine {
+    engine_id: Vec<u8>,
+    engine_boots: u32,
+    engine_time: u32,
+    synced_at: Instant,
+}
+
+impl RemoteEngine {
+    fn from_message(message: &V3Message) -> Self {
+        Self {
+            engine_id: message.security.engine_id.clone(),
+            engine_boots: message.security.engine_boots,
+            engine_time: message.security.engine_time,
+            synced_at: Instant::now(),
+        }
+    }
+
+    fn estimated_time(&self) -> u32 {
+        self.engine_time.saturating_add(self.synced_at.elapsed().as_secs() as u32)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
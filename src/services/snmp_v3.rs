@@ -0,0 +1,1433 @@
+//! SNMPv3 message security and access control
+//!
+//! Implements the User-based Security Model (RFC 3414) with HMAC-SHA-2
+//! authentication (RFC 7860) and AES privacy (RFC 3826), read access
+//! restriction through VACM views (RFC 3415), engine ID/boot counter
+//! persistence, and the BER encoding of SNMPv3 messages and PDUs.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use aes::{Aes128, Aes192, Aes256};
+use cfb_mode::cipher::{AsyncStreamCipher, KeyIvInit};
+use cfb_mode::{Decryptor, Encryptor};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use tracing::{info, warn};
+
+use crate::config::{
+    SnmpAuthProtocol, SnmpPrivProtocol, SnmpSecurityLevel, SnmpUsmUser, SnmpV3Config, SnmpView,
+};
+use crate::services::snmp::{Oid, PduType, SnmpValue, VarBind};
+use crate::utils::constant_time_eq;
+use crate::{Error, Result};
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_IP_ADDRESS: u8 = 0x40;
+const TAG_COUNTER32: u8 = 0x41;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_TIMETICKS: u8 = 0x43;
+const TAG_OPAQUE: u8 = 0x44;
+const TAG_COUNTER64: u8 = 0x46;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+const SNMP_V3: i64 = 3;
+const USM_SECURITY_MODEL: i64 = 3;
+const FLAG_AUTH: u8 = 0x01;
+const FLAG_PRIV: u8 = 0x02;
+const FLAG_REPORTABLE: u8 = 0x04;
+
+/// Maximum message size advertised in outgoing messages
+pub const MAX_MESSAGE_SIZE: i64 = 1472;
+/// RFC 3414 time window in seconds
+const TIME_WINDOW_SECS: i64 = 150;
+const MAX_ENGINE_BOOTS: u32 = 2_147_483_647;
+/// usmStats counters (1.3.6.1.6.3.15.1.1)
+const USM_STATS_OID: [u32; 9] = [1, 3, 6, 1, 6, 3, 15, 1, 1];
+/// Enterprise number used when generating engine IDs
+const ENTERPRISE_NUMBER: u32 = 99999;
+
+// ---------------------------------------------------------------------------
+// BER encoding
+// ---------------------------------------------------------------------------
+
+fn encode_length(len: usize, out: &mut Vec<u8>) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (4 - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+fn header_len(content_len: usize) -> usize {
+    let mut header = Vec::with_capacity(6);
+    encode_length(content_len, &mut header);
+    1 + header.len()
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len() + 6);
+    out.push(tag);
+    encode_length(content.len(), &mut out);
+    out.extend_from_slice(content);
+    out
+}
+
+fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    tlv(TAG_INTEGER, &bytes[start..])
+}
+
+fn encode_unsigned(tag: u8, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    let mut content = Vec::with_capacity(9);
+    if bytes[start] & 0x80 != 0 {
+        content.push(0);
+    }
+    content.extend_from_slice(&bytes[start..]);
+    tlv(tag, &content)
+}
+
+fn encode_octets(value: &[u8]) -> Vec<u8> {
+    tlv(TAG_OCTET_STRING, value)
+}
+
+fn encode_oid(oid: &Oid) -> Vec<u8> {
+    let components = &oid.components;
+    let mut content = Vec::new();
+    if components.len() >= 2 {
+        push_base128(components[0] * 40 + components[1], &mut content);
+        for component in &components[2..] {
+            push_base128(*component, &mut content);
+        }
+    } else if let Some(first) = components.first() {
+        push_base128(first * 40, &mut content);
+    }
+    tlv(TAG_OID, &content)
+}
+
+fn push_base128(mut value: u32, out: &mut Vec<u8>) {
+    let mut chunk = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        chunk.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    chunk.reverse();
+    out.extend_from_slice(&chunk);
+}
+
+fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    tlv(TAG_SEQUENCE, &items.concat())
+}
+
+fn decode_tlv(data: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let malformed = || Error::parse("Malformed BER encoding");
+
+    let tag = *data.first().ok_or_else(malformed)?;
+    let first = *data.get(1).ok_or_else(malformed)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return Err(malformed());
+        }
+        let len = data
+            .get(2..2 + count)
+            .ok_or_else(malformed)?
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, 2 + count)
+    };
+    let content = data.get(header..header + len).ok_or_else(malformed)?;
+    Ok((tag, content, &data[header + len..]))
+}
+
+fn expect_tlv(data: &[u8], expected: u8) -> Result<(&[u8], &[u8])> {
+    let (tag, content, rest) = decode_tlv(data)?;
+    if tag != expected {
+        return Err(Error::parse(format!(
+            "Unexpected BER tag 0x{:02x}, expected 0x{:02x}",
+            tag, expected
+        )));
+    }
+    Ok((content, rest))
+}
+
+fn decode_integer(content: &[u8]) -> Result<i64> {
+    if content.is_empty() || content.len() > 8 {
+        return Err(Error::parse("Invalid BER integer length"));
+    }
+    let negative = content[0] & 0x80 != 0;
+    let mut value: i64 = if negative { -1 } else { 0 };
+    for byte in content {
+        value = (value << 8) | *byte as i64;
+    }
+    Ok(value)
+}
+
+fn decode_unsigned(content: &[u8]) -> Result<u64> {
+    if content.is_empty() || content.len() > 9 {
+        return Err(Error::parse("Invalid BER unsigned length"));
+    }
+    Ok(content.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+fn read_integer(data: &[u8]) -> Result<(i64, &[u8])> {
+    let (content, rest) = expect_tlv(data, TAG_INTEGER)?;
+    Ok((decode_integer(content)?, rest))
+}
+
+fn read_octets(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    expect_tlv(data, TAG_OCTET_STRING)
+}
+
+fn decode_oid(content: &[u8]) -> Result<Oid> {
+    let mut components = Vec::new();
+    let mut value: u32 = 0;
+    for (index, byte) in content.iter().enumerate() {
+        if value > u32::MAX >> 7 {
+            return Err(Error::parse("OID component overflow"));
+        }
+        value = (value << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            if components.is_empty() {
+                let first = (value / 40).min(2);
+                components.push(first);
+                components.push(value - first * 40);
+            } else {
+                components.push(value);
+            }
+            value = 0;
+        } else if index == content.len() - 1 {
+            return Err(Error::parse("Truncated OID"));
+        }
+    }
+    Ok(Oid::new(components))
+}
+
+fn encode_value(value: &SnmpValue) -> Vec<u8> {
+    match value {
+        SnmpValue::Integer(v) => encode_integer(*v as i64),
+        SnmpValue::OctetString(v) => encode_octets(v),
+        SnmpValue::Null => tlv(TAG_NULL, &[]),
+        SnmpValue::ObjectId(v) => encode_oid(&Oid::new(v.clone())),
+        SnmpValue::IpAddress(v) => tlv(TAG_IP_ADDRESS, v),
+        SnmpValue::Counter32(v) => encode_unsigned(TAG_COUNTER32, *v as u64),
+        SnmpValue::Gauge32(v) => encode_unsigned(TAG_GAUGE32, *v as u64),
+        SnmpValue::TimeTicks(v) => encode_unsigned(TAG_TIMETICKS, *v as u64),
+        SnmpValue::Opaque(v) => tlv(TAG_OPAQUE, v),
+        SnmpValue::Counter64(v) => encode_unsigned(TAG_COUNTER64, *v),
+        SnmpValue::NoSuchObject => tlv(TAG_NO_SUCH_OBJECT, &[]),
+        SnmpValue::NoSuchInstance => tlv(TAG_NO_SUCH_INSTANCE, &[]),
+        SnmpValue::EndOfMibView => tlv(TAG_END_OF_MIB_VIEW, &[]),
+    }
+}
+
+fn decode_value(tag: u8, content: &[u8]) -> Result<SnmpValue> {
+    Ok(match tag {
+        TAG_INTEGER => SnmpValue::Integer(decode_integer(content)? as i32),
+        TAG_OCTET_STRING => SnmpValue::OctetString(content.to_vec()),
+        TAG_NULL => SnmpValue::Null,
+        TAG_OID => SnmpValue::ObjectId(decode_oid(content)?.components),
+        TAG_IP_ADDRESS => {
+            let octets: [u8; 4] = content
+                .try_into()
+                .map_err(|_| Error::parse("Invalid IpAddress length"))?;
+            SnmpValue::IpAddress(octets)
+        }
+        TAG_COUNTER32 => SnmpValue::Counter32(decode_unsigned(content)? as u32),
+        TAG_GAUGE32 => SnmpValue::Gauge32(decode_unsigned(content)? as u32),
+        TAG_TIMETICKS => SnmpValue::TimeTicks(decode_unsigned(content)? as u32),
+        TAG_OPAQUE => SnmpValue::Opaque(content.to_vec()),
+        TAG_COUNTER64 => SnmpValue::Counter64(decode_unsigned(content)?),
+        TAG_NO_SUCH_OBJECT => SnmpValue::NoSuchObject,
+        TAG_NO_SUCH_INSTANCE => SnmpValue::NoSuchInstance,
+        TAG_END_OF_MIB_VIEW => SnmpValue::EndOfMibView,
+        other => return Err(Error::parse(format!("Unsupported SNMP value tag 0x{:02x}", other))),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// PDUs
+// ---------------------------------------------------------------------------
+
+/// SNMPv2 PDU
+#[derive(Debug, Clone)]
+pub struct Pdu {
+    pub pdu_type: PduType,
+    pub request_id: i32,
+    /// Error status, or non-repeaters for GetBulk
+    pub error_status: i32,
+    /// Error index, or max-repetitions for GetBulk
+    pub error_index: i32,
+    pub var_binds: Vec<VarBind>,
+}
+
+impl Pdu {
+    pub fn new(pdu_type: PduType, request_id: i32, var_binds: Vec<VarBind>) -> Self {
+        Self {
+            pdu_type,
+            request_id,
+            error_status: 0,
+            error_index: 0,
+            var_binds,
+        }
+    }
+
+    fn tag(pdu_type: &PduType) -> u8 {
+        match pdu_type {
+            PduType::GetRequest => 0xa0,
+            PduType::GetNextRequest => 0xa1,
+            PduType::GetResponse => 0xa2,
+            PduType::SetRequest => 0xa3,
+            PduType::GetBulkRequest => 0xa5,
+            PduType::InformRequest => 0xa6,
+            // SNMPv1 trap PDUs are not permitted in v3; always send SNMPv2-Trap
+            PduType::Trap => 0xa7,
+            PduType::Report => 0xa8,
+        }
+    }
+
+    fn pdu_type(tag: u8) -> Result<PduType> {
+        Ok(match tag {
+            0xa0 => PduType::GetRequest,
+            0xa1 => PduType::GetNextRequest,
+            0xa2 => PduType::GetResponse,
+            0xa3 => PduType::SetRequest,
+            0xa4 | 0xa7 => PduType::Trap,
+            0xa5 => PduType::GetBulkRequest,
+            0xa6 => PduType::InformRequest,
+            0xa8 => PduType::Report,
+            other => return Err(Error::parse(format!("Unknown PDU tag 0x{:02x}", other))),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let var_binds: Vec<Vec<u8>> = self
+            .var_binds
+            .iter()
+            .map(|vb| sequence(&[encode_oid(&vb.oid), encode_value(&vb.value)]))
+            .collect();
+
+        tlv(
+            Self::tag(&self.pdu_type),
+            &[
+                encode_integer(self.request_id as i64),
+                encode_integer(self.error_status as i64),
+                encode_integer(self.error_index as i64),
+                sequence(&var_binds),
+            ]
+            .concat(),
+        )
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let (tag, content, _) = decode_tlv(data)?;
+        let pdu_type = Self::pdu_type(tag)?;
+
+        let (request_id, rest) = read_integer(content)?;
+        let (error_status, rest) = read_integer(rest)?;
+        let (error_index, rest) = read_integer(rest)?;
+        let (mut list, _) = expect_tlv(rest, TAG_SEQUENCE)?;
+
+        let mut var_binds = Vec::new();
+        while !list.is_empty() {
+            let (var_bind, rest) = expect_tlv(list, TAG_SEQUENCE)?;
+            let (oid, value) = expect_tlv(var_bind, TAG_OID)?;
+            let (value_tag, value_content, _) = decode_tlv(value)?;
+            var_binds.push(VarBind {
+                oid: decode_oid(oid)?,
+                value: decode_value(value_tag, value_content)?,
+            });
+            list = rest;
+        }
+
+        Ok(Self {
+            pdu_type,
+            request_id: request_id as i32,
+            error_status: error_status as i32,
+            error_index: error_index as i32,
+            var_binds,
+        })
+    }
+}
+
+/// PDU together with its SNMPv3 context
+#[derive(Debug, Clone)]
+pub struct ScopedPdu {
+    pub context_engine_id: Vec<u8>,
+    pub context_name: Vec<u8>,
+    pub pdu: Pdu,
+}
+
+impl ScopedPdu {
+    pub fn encode(&self) -> Vec<u8> {
+        sequence(&[
+            encode_octets(&self.context_engine_id),
+            encode_octets(&self.context_name),
+            self.pdu.encode(),
+        ])
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let (content, _) = expect_tlv(data, TAG_SEQUENCE)?;
+        let (context_engine_id, rest) = read_octets(content)?;
+        let (context_name, rest) = read_octets(rest)?;
+
+        Ok(Self {
+            context_engine_id: context_engine_id.to_vec(),
+            context_name: context_name.to_vec(),
+            pdu: Pdu::decode(rest)?,
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Message wrapper
+// ---------------------------------------------------------------------------
+
+/// USM security parameters carried in every SNMPv3 message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsmSecurityParameters {
+    pub engine_id: Vec<u8>,
+    pub engine_boots: u32,
+    pub engine_time: u32,
+    pub user_name: String,
+    pub auth_params: Vec<u8>,
+    pub priv_params: Vec<u8>,
+}
+
+impl UsmSecurityParameters {
+    /// Encode, returning the offset of the authentication parameters
+    fn encode(&self) -> (Vec<u8>, usize) {
+        let prefix = [
+            encode_octets(&self.engine_id),
+            encode_integer(self.engine_boots as i64),
+            encode_integer(self.engine_time as i64),
+            encode_octets(self.user_name.as_bytes()),
+        ]
+        .concat();
+        let content = [
+            prefix.as_slice(),
+            &encode_octets(&self.auth_params),
+            &encode_octets(&self.priv_params),
+        ]
+        .concat();
+
+        let auth_offset = header_len(content.len()) + prefix.len() + header_len(self.auth_params.len());
+        (tlv(TAG_SEQUENCE, &content), auth_offset)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let (content, _) = expect_tlv(data, TAG_SEQUENCE)?;
+        let (engine_id, rest) = read_octets(content)?;
+        let (engine_boots, rest) = read_integer(rest)?;
+        let (engine_time, rest) = read_integer(rest)?;
+        let (user_name, rest) = read_octets(rest)?;
+        let (auth_params, rest) = read_octets(rest)?;
+        let (priv_params, _) = read_octets(rest)?;
+
+        Ok(Self {
+            engine_id: engine_id.to_vec(),
+            engine_boots: engine_boots as u32,
+            engine_time: engine_time as u32,
+            user_name: String::from_utf8_lossy(user_name).to_string(),
+            auth_params: auth_params.to_vec(),
+            priv_params: priv_params.to_vec(),
+        })
+    }
+}
+
+/// Scoped PDU as carried on the wire
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScopedPduData {
+    /// Encoded ScopedPDU
+    Plaintext(Vec<u8>),
+    /// Encrypted ScopedPDU octets
+    Encrypted(Vec<u8>),
+}
+
+/// SNMPv3 message (RFC 3412)
+#[derive(Debug, Clone)]
+pub struct V3Message {
+    pub msg_id: i32,
+    pub max_size: i32,
+    pub flags: u8,
+    pub security: UsmSecurityParameters,
+    pub data: ScopedPduData,
+}
+
+impl V3Message {
+    pub fn is_reportable(&self) -> bool {
+        self.flags & FLAG_REPORTABLE != 0
+    }
+
+    pub fn security_level(&self) -> Result<SnmpSecurityLevel> {
+        match (self.flags & FLAG_AUTH != 0, self.flags & FLAG_PRIV != 0) {
+            (false, false) => Ok(SnmpSecurityLevel::NoAuthNoPriv),
+            (true, false) => Ok(SnmpSecurityLevel::AuthNoPriv),
+            (true, true) => Ok(SnmpSecurityLevel::AuthPriv),
+            (false, true) => Err(Error::parse("Privacy requested without authentication")),
+        }
+    }
+
+    /// Encode, returning the absolute offset of the authentication parameters
+    fn encode(&self) -> (Vec<u8>, usize) {
+        let version = encode_integer(SNMP_V3);
+        let global = sequence(&[
+            encode_integer(self.msg_id as i64),
+            encode_integer(self.max_size as i64),
+            encode_octets(&[self.flags]),
+            encode_integer(USM_SECURITY_MODEL),
+        ]);
+        let (usp, usp_auth_offset) = self.security.encode();
+        let usp_octets = encode_octets(&usp);
+        let data = match &self.data {
+            ScopedPduData::Plaintext(bytes) => bytes.clone(),
+            ScopedPduData::Encrypted(bytes) => encode_octets(bytes),
+        };
+
+        let content = [version.clone(), global.clone(), usp_octets, data].concat();
+        let auth_offset = header_len(content.len())
+            + version.len()
+            + global.len()
+            + header_len(usp.len())
+            + usp_auth_offset;
+
+        (tlv(TAG_SEQUENCE, &content), auth_offset)
+    }
+
+    /// Decode, returning the absolute offset of the authentication parameters
+    pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
+        let (content, _) = expect_tlv(data, TAG_SEQUENCE)?;
+        let (version, rest) = read_integer(content)?;
+        if version != SNMP_V3 {
+            return Err(Error::parse(format!("Not an SNMPv3 message (version {})", version)));
+        }
+
+        let (global, rest) = expect_tlv(rest, TAG_SEQUENCE)?;
+        let (msg_id, g) = read_integer(global)?;
+        let (max_size, g) = read_integer(g)?;
+        let (flags, g) = read_octets(g)?;
+        let (model, _) = read_integer(g)?;
+        if model != USM_SECURITY_MODEL {
+            return Err(Error::not_supported(format!("Security model {}", model)));
+        }
+        let flags = *flags.first().ok_or_else(|| Error::parse("Empty msgFlags"))?;
+
+        let (usp, rest) = read_octets(rest)?;
+        let security = UsmSecurityParameters::decode(usp)?;
+        let auth_offset = Self::locate(data, usp, &security.auth_params)?;
+
+        let (tag, scoped, after) = decode_tlv(rest)?;
+        let scoped_data = match tag {
+            TAG_SEQUENCE => ScopedPduData::Plaintext(rest[..rest.len() - after.len()].to_vec()),
+            TAG_OCTET_STRING => ScopedPduData::Encrypted(scoped.to_vec()),
+            other => return Err(Error::parse(format!("Invalid msgData tag 0x{:02x}", other))),
+        };
+
+        Ok((
+            Self {
+                msg_id: msg_id as i32,
+                max_size: max_size as i32,
+                flags,
+                security,
+                data: scoped_data,
+            },
+            auth_offset,
+        ))
+    }
+
+    /// Absolute offset of the authentication parameters inside `data`
+    fn locate(data: &[u8], usp: &[u8], auth_params: &[u8]) -> Result<usize> {
+        let usp_offset = usp.as_ptr() as usize - data.as_ptr() as usize;
+        let (content, _) = expect_tlv(usp, TAG_SEQUENCE)?;
+        let mut rest = content;
+        for _ in 0..4 {
+            rest = decode_tlv(rest)?.2;
+        }
+        let field_offset = rest.as_ptr() as usize - usp.as_ptr() as usize;
+        Ok(usp_offset + field_offset + header_len(auth_params.len()))
+    }
+
+    /// Peek at msgID without processing security parameters
+    pub fn peek_msg_id(data: &[u8]) -> Option<i32> {
+        let (content, _) = expect_tlv(data, TAG_SEQUENCE).ok()?;
+        let (version, rest) = read_integer(content).ok()?;
+        if version != SNMP_V3 {
+            return None;
+        }
+        let (global, _) = expect_tlv(rest, TAG_SEQUENCE).ok()?;
+        read_integer(global).ok().map(|(id, _)| id as i32)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// USM cryptography
+// ---------------------------------------------------------------------------
+
+fn localize<D: Digest>(password: &[u8], engine_id: &[u8]) -> Vec<u8> {
+    // RFC 3414 A.2: hash one megabyte of the repeated password
+    let mut hasher = D::new();
+    let mut block = [0u8; 64];
+    let mut index = 0usize;
+    for _ in 0..(1_048_576 / 64) {
+        for byte in block.iter_mut() {
+            *byte = password[index % password.len()];
+            index += 1;
+        }
+        hasher.update(block);
+    }
+    let ku = hasher.finalize();
+
+    let mut hasher = D::new();
+    hasher.update(&ku);
+    hasher.update(engine_id);
+    hasher.update(&ku);
+    hasher.finalize().to_vec()
+}
+
+/// Derive a localized key from a password (RFC 3414 / RFC 7860)
+pub fn password_to_key(protocol: SnmpAuthProtocol, password: &str, engine_id: &[u8]) -> Result<Vec<u8>> {
+    if password.len() < 8 {
+        return Err(Error::invalid_config("USM passwords must be at least 8 characters"));
+    }
+    let password = password.as_bytes();
+    Ok(match protocol {
+        SnmpAuthProtocol::None => return Err(Error::invalid_state("No authentication protocol")),
+        SnmpAuthProtocol::HmacSha224 => localize::<Sha224>(password, engine_id),
+        SnmpAuthProtocol::HmacSha256 => localize::<Sha256>(password, engine_id),
+        SnmpAuthProtocol::HmacSha384 => localize::<Sha384>(password, engine_id),
+        SnmpAuthProtocol::HmacSha512 => localize::<Sha512>(password, engine_id),
+    })
+}
+
+fn digest(protocol: SnmpAuthProtocol, data: &[u8]) -> Vec<u8> {
+    match protocol {
+        SnmpAuthProtocol::None => Vec::new(),
+        SnmpAuthProtocol::HmacSha224 => Sha224::digest(data).to_vec(),
+        SnmpAuthProtocol::HmacSha256 => Sha256::digest(data).to_vec(),
+        SnmpAuthProtocol::HmacSha384 => Sha384::digest(data).to_vec(),
+        SnmpAuthProtocol::HmacSha512 => Sha512::digest(data).to_vec(),
+    }
+}
+
+macro_rules! hmac_digest {
+    ($digest:ty, $key:expr, $data:expr) => {{
+        let mut mac = <Hmac<$digest> as Mac>::new_from_slice($key).expect("HMAC accepts any key length");
+        mac.update($data);
+        mac.finalize().into_bytes().to_vec()
+    }};
+}
+
+/// Truncated HMAC used as msgAuthenticationParameters (RFC 7860)
+fn authentication_code(protocol: SnmpAuthProtocol, key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = match protocol {
+        SnmpAuthProtocol::None => return Vec::new(),
+        SnmpAuthProtocol::HmacSha224 => hmac_digest!(Sha224, key, data),
+        SnmpAuthProtocol::HmacSha256 => hmac_digest!(Sha256, key, data),
+        SnmpAuthProtocol::HmacSha384 => hmac_digest!(Sha384, key, data),
+        SnmpAuthProtocol::HmacSha512 => hmac_digest!(Sha512, key, data),
+    };
+    mac.truncate(mac_length(protocol));
+    mac
+}
+
+fn mac_length(protocol: SnmpAuthProtocol) -> usize {
+    match protocol {
+        SnmpAuthProtocol::None => 0,
+        SnmpAuthProtocol::HmacSha224 => 16,
+        SnmpAuthProtocol::HmacSha256 => 24,
+        SnmpAuthProtocol::HmacSha384 => 32,
+        SnmpAuthProtocol::HmacSha512 => 48,
+    }
+}
+
+fn priv_key_length(protocol: SnmpPrivProtocol) -> usize {
+    match protocol {
+        SnmpPrivProtocol::None => 0,
+        SnmpPrivProtocol::Aes128 => 16,
+        SnmpPrivProtocol::Aes192 => 24,
+        SnmpPrivProtocol::Aes256 => 32,
+    }
+}
+
+fn aes_cfb(protocol: SnmpPrivProtocol, key: &[u8], iv: &[u8], data: &[u8], encrypt: bool) -> Result<Vec<u8>> {
+    let mut buffer = data.to_vec();
+    let invalid = |_| Error::internal("Invalid AES key or IV length");
+
+    macro_rules! run {
+        ($cipher:ty) => {
+            if encrypt {
+                Encryptor::<$cipher>::new_from_slices(key, iv).map_err(invalid)?.encrypt(&mut buffer)
+            } else {
+                Decryptor::<$cipher>::new_from_slices(key, iv).map_err(invalid)?.decrypt(&mut buffer)
+            }
+        };
+    }
+
+    match protocol {
+        SnmpPrivProtocol::None => return Err(Error::invalid_state("No privacy protocol")),
+        SnmpPrivProtocol::Aes128 => run!(Aes128),
+        SnmpPrivProtocol::Aes192 => run!(Aes192),
+        SnmpPrivProtocol::Aes256 => run!(Aes256),
+    }
+
+    Ok(buffer)
+}
+
+/// RFC 3826 IV: engineBoots || engineTime || salt
+fn aes_iv(engine_boots: u32, engine_time: u32, salt: &[u8]) -> Vec<u8> {
+    [&engine_boots.to_be_bytes()[..], &engine_time.to_be_bytes()[..], salt].concat()
+}
+
+// ---------------------------------------------------------------------------
+// Users, views and engine state
+// ---------------------------------------------------------------------------
+
+/// USM user with keys localized to a specific engine
+#[derive(Clone)]
+pub struct LocalizedUser {
+    pub name: String,
+    pub auth_protocol: SnmpAuthProtocol,
+    pub priv_protocol: SnmpPrivProtocol,
+    pub read_view: String,
+    pub min_security_level: SnmpSecurityLevel,
+    auth_key: Vec<u8>,
+    priv_key: Vec<u8>,
+}
+
+impl std::fmt::Debug for LocalizedUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalizedUser")
+            .field("name", &self.name)
+            .field("auth_protocol", &self.auth_protocol)
+            .field("priv_protocol", &self.priv_protocol)
+            .field("read_view", &self.read_view)
+            .finish()
+    }
+}
+
+impl LocalizedUser {
+    pub fn localize(user: &SnmpUsmUser, engine_id: &[u8]) -> Result<Self> {
+        let auth_key = match (user.auth_protocol, &user.auth_password) {
+            (SnmpAuthProtocol::None, _) => Vec::new(),
+            (protocol, Some(password)) => password_to_key(protocol, password, engine_id)?,
+            (_, None) => {
+                return Err(Error::invalid_config(format!("USM user {} has no authentication password", user.name)))
+            }
+        };
+
+        let priv_key = match (user.priv_protocol, &user.priv_password) {
+            (SnmpPrivProtocol::None, _) => Vec::new(),
+            (_, _) if user.auth_protocol == SnmpAuthProtocol::None => {
+                return Err(Error::invalid_config(format!("USM user {} has privacy without authentication", user.name)))
+            }
+            (protocol, Some(password)) => {
+                // Keys longer than the hash are extended per draft-blumenthal-aes-usm
+                let mut key = password_to_key(user.auth_protocol, password, engine_id)?;
+                let wanted = priv_key_length(protocol);
+                while key.len() < wanted {
+                    let extension = digest(user.auth_protocol, &key);
+                    key.extend_from_slice(&extension);
+                }
+                key.truncate(wanted);
+                key
+            }
+            (_, None) => {
+                return Err(Error::invalid_config(format!("USM user {} has no privacy password", user.name)))
+            }
+        };
+
+        Ok(Self {
+            name: user.name.clone(),
+            auth_protocol: user.auth_protocol,
+            priv_protocol: user.priv_protocol,
+            read_view: user.read_view.clone(),
+            min_security_level: user.min_security_level,
+            auth_key,
+            priv_key,
+        })
+    }
+
+    /// Highest security level the user's keys support
+    pub fn max_security_level(&self) -> SnmpSecurityLevel {
+        match (self.auth_protocol, self.priv_protocol) {
+            (SnmpAuthProtocol::None, _) => SnmpSecurityLevel::NoAuthNoPriv,
+            (_, SnmpPrivProtocol::None) => SnmpSecurityLevel::AuthNoPriv,
+            _ => SnmpSecurityLevel::AuthPriv,
+        }
+    }
+}
+
+/// VACM view: a family of included and excluded subtrees
+#[derive(Debug, Clone)]
+pub struct VacmView {
+    included: Vec<Oid>,
+    excluded: Vec<Oid>,
+}
+
+impl VacmView {
+    pub fn from_config(view: &SnmpView) -> Result<Self> {
+        let parse = |oids: &[String]| oids.iter().map(|o| Oid::from_string(o)).collect::<Result<Vec<_>>>();
+        Ok(Self {
+            included: parse(&view.included)?,
+            excluded: parse(&view.excluded)?,
+        })
+    }
+
+    /// The most specific matching subtree decides; exclusions win ties
+    pub fn permits(&self, oid: &Oid) -> bool {
+        let longest = |subtrees: &[Oid]| {
+            subtrees
+                .iter()
+                .filter(|s| oid.components.starts_with(&s.components))
+                .map(|s| s.components.len())
+                .max()
+        };
+
+        match (longest(&self.included), longest(&self.excluded)) {
+            (Some(inc), Some(exc)) => inc > exc,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Persisted engine identity
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineState {
+    pub engine_id: Vec<u8>,
+    pub engine_boots: u32,
+}
+
+impl EngineState {
+    /// Load the persisted engine state, bump the boot counter and save it
+    pub fn load_and_increment(config: &SnmpV3Config) -> Result<Self> {
+        let path = Path::new(&config.engine_state_file);
+        let persisted = std::fs::read_to_string(path).ok().and_then(|text| {
+            let mut lines = text.lines();
+            let engine_id = hex::decode(lines.next()?.trim()).ok()?;
+            let boots = lines.next()?.trim().parse::<u32>().ok()?;
+            Some((engine_id, boots))
+        });
+
+        let configured = match &config.engine_id {
+            Some(id) => Some(hex::decode(id).map_err(|e| Error::invalid_config(format!("Invalid SNMP engine ID: {}", e)))?),
+            None => None,
+        };
+
+        let state = match (configured, persisted) {
+            // Boots restart whenever the engine ID changes
+            (Some(id), Some((saved, boots))) if id == saved => Self { engine_id: id, engine_boots: boots },
+            (Some(id), _) => Self { engine_id: id, engine_boots: 0 },
+            (None, Some((saved, boots))) => Self { engine_id: saved, engine_boots: boots },
+            (None, None) => Self { engine_id: Self::generate_engine_id(), engine_boots: 0 },
+        };
+
+        let state = Self {
+            engine_boots: (state.engine_boots + 1).min(MAX_ENGINE_BOOTS),
+            ..state
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, format!("{}\n{}\n", hex::encode(&state.engine_id), state.engine_boots))?;
+
+        info!("SNMP engine {} boot {}", hex::encode(&state.engine_id), state.engine_boots);
+        Ok(state)
+    }
+
+    /// RFC 3411 engine ID: enterprise number followed by random octets
+    pub fn generate_engine_id() -> Vec<u8> {
+        let mut id = (0x8000_0000 | ENTERPRISE_NUMBER).to_be_bytes().to_vec();
+        id.push(0x05);
+        id.extend_from_slice(&rand::random::<[u8; 8]>());
+        id
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Engine
+// ---------------------------------------------------------------------------
+
+/// USM processing failures that produce a Report PDU
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UsmError {
+    UnsupportedSecLevel,
+    NotInTimeWindow,
+    UnknownUserName,
+    UnknownEngineId,
+    WrongDigest,
+    DecryptionError,
+}
+
+impl UsmError {
+    /// Column of the matching usmStats counter
+    fn counter(&self) -> u32 {
+        match self {
+            UsmError::UnsupportedSecLevel => 1,
+            UsmError::NotInTimeWindow => 2,
+            UsmError::UnknownUserName => 3,
+            UsmError::UnknownEngineId => 4,
+            UsmError::WrongDigest => 5,
+            UsmError::DecryptionError => 6,
+        }
+    }
+
+    /// usmStats counter reported for this failure
+    pub fn report_oid(&self) -> Oid {
+        Oid::new(USM_STATS_OID.to_vec()).append(self.counter()).append(0)
+    }
+
+    fn index(&self) -> usize {
+        self.counter() as usize - 1
+    }
+}
+
+/// Rejected incoming message with what is needed to report it
+#[derive(Debug, Clone)]
+pub struct UsmFailure {
+    pub error: UsmError,
+    pub msg_id: i32,
+    pub user_name: String,
+    pub reportable: bool,
+    request_id: i32,
+}
+
+/// Authenticated and decrypted request
+#[derive(Debug, Clone)]
+pub struct IncomingRequest {
+    pub msg_id: i32,
+    pub user: String,
+    pub security_level: SnmpSecurityLevel,
+    pub scoped_pdu: ScopedPdu,
+}
+
+/// Security parameters used to encode an outgoing message
+pub struct OutgoingSecurity<'a> {
+    pub user: Option<&'a LocalizedUser>,
+    pub level: SnmpSecurityLevel,
+    pub engine_id: &'a [u8],
+    pub engine_boots: u32,
+    pub engine_time: u32,
+}
+
+/// Encode and, depending on the security level, encrypt and sign a message
+pub fn encode_secure_message(
+    security: &OutgoingSecurity<'_>,
+    msg_id: i32,
+    reportable: bool,
+    scoped_pdu: &ScopedPdu,
+    salt: u64,
+) -> Result<Vec<u8>> {
+    let plaintext = scoped_pdu.encode();
+    let user_name = security.user.map(|u| u.name.clone()).unwrap_or_default();
+
+    let mut flags = if reportable { FLAG_REPORTABLE } else { 0 };
+    let mut auth_params = Vec::new();
+    let mut priv_params = Vec::new();
+    let mut data = ScopedPduData::Plaintext(plaintext.clone());
+
+    if security.level >= SnmpSecurityLevel::AuthNoPriv {
+        let user = security.user.ok_or_else(|| Error::invalid_state("Authenticated message needs a user"))?;
+        flags |= FLAG_AUTH;
+        auth_params = vec![0u8; mac_length(user.auth_protocol)];
+
+        if security.level == SnmpSecurityLevel::AuthPriv {
+            flags |= FLAG_PRIV;
+            priv_params = salt.to_be_bytes().to_vec();
+            let iv = aes_iv(security.engine_boots, security.engine_time, &priv_params);
+            data = ScopedPduData::Encrypted(aes_cfb(user.priv_protocol, &user.priv_key, &iv, &plaintext, true)?);
+        }
+    }
+
+    let message = V3Message {
+        msg_id,
+        max_size: MAX_MESSAGE_SIZE as i32,
+        flags,
+        security: UsmSecurityParameters {
+            engine_id: security.engine_id.to_vec(),
+            engine_boots: security.engine_boots,
+            engine_time: security.engine_time,
+            user_name,
+            auth_params,
+            priv_params,
+        },
+        data,
+    };
+
+    let (mut encoded, auth_offset) = message.encode();
+    if let (Some(user), true) = (security.user, flags & FLAG_AUTH != 0) {
+        let mac = authentication_code(user.auth_protocol, &user.auth_key, &encoded);
+        encoded[auth_offset..auth_offset + mac.len()].copy_from_slice(&mac);
+    }
+
+    Ok(encoded)
+}
+
+/// Check msgAuthenticationParameters against the message digest
+fn verify_digest(
+    message: &V3Message,
+    raw: &[u8],
+    auth_offset: usize,
+    user: &LocalizedUser,
+) -> std::result::Result<(), UsmError> {
+    let expected_len = mac_length(user.auth_protocol);
+    if message.security.auth_params.len() != expected_len || raw.len() < auth_offset + expected_len {
+        return Err(UsmError::WrongDigest);
+    }
+    let mut zeroed = raw.to_vec();
+    zeroed[auth_offset..auth_offset + expected_len].fill(0);
+    let mac = authentication_code(user.auth_protocol, &user.auth_key, &zeroed);
+    if !constant_time_eq(&mac, &message.security.auth_params) {
+        return Err(UsmError::WrongDigest);
+    }
+    Ok(())
+}
+
+/// Encoded ScopedPDU, decrypted when the level requires privacy
+fn scoped_pdu_bytes(
+    message: &V3Message,
+    user: &LocalizedUser,
+    level: SnmpSecurityLevel,
+) -> std::result::Result<Vec<u8>, UsmError> {
+    match (&message.data, level) {
+        (ScopedPduData::Encrypted(ciphertext), SnmpSecurityLevel::AuthPriv) => {
+            if message.security.priv_params.len() != 8 {
+                return Err(UsmError::DecryptionError);
+            }
+            let iv = aes_iv(message.security.engine_boots, message.security.engine_time, &message.security.priv_params);
+            aes_cfb(user.priv_protocol, &user.priv_key, &iv, ciphertext, false)
+                .map_err(|_| UsmError::DecryptionError)
+        }
+        (ScopedPduData::Plaintext(plaintext), SnmpSecurityLevel::NoAuthNoPriv | SnmpSecurityLevel::AuthNoPriv) => {
+            Ok(plaintext.clone())
+        }
+        _ => Err(UsmError::DecryptionError),
+    }
+}
+
+/// Decode a message from a remote authoritative engine (inform responses)
+pub fn open_remote_message(raw: &[u8], user: &LocalizedUser) -> Result<(V3Message, ScopedPdu)> {
+    let (message, auth_offset) = V3Message::decode(raw)?;
+    let level = message.security_level()?;
+    let verified = if level >= SnmpSecurityLevel::AuthNoPriv {
+        verify_digest(&message, raw, auth_offset, user)
+    } else {
+        Ok(())
+    };
+    let plaintext = verified
+        .and_then(|_| scoped_pdu_bytes(&message, user, level))
+        .map_err(|e| Error::protocol(format!("USM verification failed: {:?}", e)))?;
+    let scoped = ScopedPdu::decode(&plaintext)?;
+    Ok((message, scoped))
+}
+
+/// Authoritative SNMPv3 engine for this agent
+pub struct UsmEngine {
+    engine_id: Vec<u8>,
+    engine_boots: u32,
+    started: Instant,
+    user_configs: Vec<SnmpUsmUser>,
+    users: HashMap<String, LocalizedUser>,
+    views: HashMap<String, VacmView>,
+    salt: AtomicU64,
+    stats: [AtomicU64; 6],
+}
+
+impl UsmEngine {
+    pub fn new(config: &SnmpV3Config, state: EngineState) -> Result<Self> {
+        let mut users = HashMap::new();
+        for user in &config.users {
+            users.insert(user.name.clone(), LocalizedUser::localize(user, &state.engine_id)?);
+        }
+
+        let mut views = HashMap::new();
+        for view in &config.views {
+            views.insert(view.name.clone(), VacmView::from_config(view)?);
+        }
+
+        for user in users.values() {
+            if !views.contains_key(&user.read_view) {
+                warn!("SNMP user {} references unknown view {}", user.name, user.read_view);
+            }
+        }
+
+        Ok(Self {
+            engine_id: state.engine_id,
+            engine_boots: state.engine_boots,
+            started: Instant::now(),
+            user_configs: config.users.clone(),
+            users,
+            views,
+            salt: AtomicU64::new(rand::random()),
+            stats: Default::default(),
+        })
+    }
+
+    pub fn engine_id(&self) -> &[u8] {
+        &self.engine_id
+    }
+
+    pub fn engine_boots(&self) -> u32 {
+        self.engine_boots
+    }
+
+    pub fn engine_time(&self) -> u32 {
+        self.started.elapsed().as_secs().min(MAX_ENGINE_BOOTS as u64) as u32
+    }
+
+    pub fn user(&self, name: &str) -> Option<&LocalizedUser> {
+        self.users.get(name)
+    }
+
+    /// User keys localized to a remote engine (for informs)
+    pub fn localize_for(&self, name: &str, engine_id: &[u8]) -> Result<LocalizedUser> {
+        let config = self
+            .user_configs
+            .iter()
+            .find(|u| u.name == name)
+            .ok_or_else(|| Error::invalid_state(format!("Unknown SNMP user: {}", name)))?;
+        LocalizedUser::localize(config, engine_id)
+    }
+
+    pub fn next_salt(&self) -> u64 {
+        self.salt.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn stat(&self, error: UsmError) -> u64 {
+        self.stats[error.index()].load(Ordering::Relaxed)
+    }
+
+    /// Whether `user` may read `oid` through its VACM view
+    pub fn is_readable(&self, user: &str, oid: &Oid) -> bool {
+        self.users
+            .get(user)
+            .and_then(|u| self.views.get(&u.read_view))
+            .map(|view| view.permits(oid))
+            .unwrap_or(false)
+    }
+
+    /// Authenticate, check timeliness and decrypt an incoming request
+    pub fn process_incoming(&self, raw: &[u8]) -> Result<std::result::Result<IncomingRequest, UsmFailure>> {
+        let (message, auth_offset) = V3Message::decode(raw)?;
+        let level = message.security_level()?;
+
+        let fail = |error: UsmError| -> Result<std::result::Result<IncomingRequest, UsmFailure>> {
+            self.stats[error.index()].fetch_add(1, Ordering::Relaxed);
+            Ok(Err(UsmFailure {
+                error,
+                msg_id: message.msg_id,
+                user_name: message.security.user_name.clone(),
+                reportable: message.is_reportable(),
+                request_id: Self::peek_request_id(&message),
+            }))
+        };
+
+        if message.security.engine_id != self.engine_id {
+            return fail(UsmError::UnknownEngineId);
+        }
+
+        let user = match self.users.get(&message.security.user_name) {
+            Some(user) => user,
+            None => return fail(UsmError::UnknownUserName),
+        };
+
+        if level > user.max_security_level() || level < user.min_security_level {
+            return fail(UsmError::UnsupportedSecLevel);
+        }
+
+        if level >= SnmpSecurityLevel::AuthNoPriv {
+            // Digest is checked before timeliness (RFC 3414 3.2 step 6-7)
+            if let Err(error) = verify_digest(&message, raw, auth_offset, user) {
+                return fail(error);
+            }
+
+            let time_delta = message.security.engine_time as i64 - self.engine_time() as i64;
+            if message.security.engine_boots != self.engine_boots
+                || self.engine_boots == MAX_ENGINE_BOOTS
+                || time_delta.abs() > TIME_WINDOW_SECS
+            {
+                return fail(UsmError::NotInTimeWindow);
+            }
+        }
+
+        let plaintext = match scoped_pdu_bytes(&message, user, level) {
+            Ok(plaintext) => plaintext,
+            Err(error) => return fail(error),
+        };
+
+        let scoped_pdu = match ScopedPdu::decode(&plaintext) {
+            Ok(scoped_pdu) => scoped_pdu,
+            Err(_) if level == SnmpSecurityLevel::AuthPriv => return fail(UsmError::DecryptionError),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Ok(IncomingRequest {
+            msg_id: message.msg_id,
+            user: user.name.clone(),
+            security_level: level,
+            scoped_pdu,
+        }))
+    }
+
+    fn peek_request_id(message: &V3Message) -> i32 {
+        match &message.data {
+            ScopedPduData::Plaintext(bytes) => ScopedPdu::decode(bytes).map(|s| s.pdu.request_id).unwrap_or(0),
+            ScopedPduData::Encrypted(_) => 0,
+        }
+    }
+
+    /// Encode a response to an authenticated request
+    pub fn respond(&self, request: &IncomingRequest, pdu: Pdu) -> Result<Vec<u8>> {
+        let scoped = ScopedPdu {
+            context_engine_id: self.engine_id.clone(),
+            context_name: request.scoped_pdu.context_name.clone(),
+            pdu,
+        };
+        let security = OutgoingSecurity {
+            user: self.users.get(&request.user),
+            level: request.security_level,
+            engine_id: &self.engine_id,
+            engine_boots: self.engine_boots,
+            engine_time: self.engine_time(),
+        };
+        encode_secure_message(&security, request.msg_id, false, &scoped, self.next_salt())
+    }
+
+    /// Encode a Report PDU for a rejected message (RFC 3412 7.1)
+    pub fn report(&self, failure: &UsmFailure) -> Result<Vec<u8>> {
+        let pdu = Pdu::new(
+            PduType::Report,
+            failure.request_id,
+            vec![VarBind {
+                oid: failure.error.report_oid(),
+                value: SnmpValue::Counter32(self.stat(failure.error) as u32),
+            }],
+        );
+        let scoped = ScopedPdu {
+            context_engine_id: self.engine_id.clone(),
+            context_name: Vec::new(),
+            pdu,
+        };
+
+        // Time window reports are authenticated so managers can resynchronize
+        let user = match failure.error {
+            UsmError::NotInTimeWindow => self.users.get(&failure.user_name),
+            _ => None,
+        };
+        let security = OutgoingSecurity {
+            user,
+            level: if user.is_some() { SnmpSecurityLevel::AuthNoPriv } else { SnmpSecurityLevel::NoAuthNoPriv },
+            engine_id: &self.engine_id,
+            engine_boots: self.engine_boots,
+            engine_time: self.engine_time(),
+        };
+        encode_secure_message(&security, failure.msg_id, false, &scoped, self.next_salt())
+    }
+
+    /// Encode a notification with this engine as authoritative (traps)
+    pub fn encode_notification(&self, user: &str, msg_id: i32, pdu: Pdu) -> Result<Vec<u8>> {
+        let user = self
+            .users
+            .get(user)
+            .ok_or_else(|| Error::invalid_state(format!("Unknown SNMP user: {}", user)))?;
+        let scoped = ScopedPdu {
+            context_engine_id: self.engine_id.clone(),
+            context_name: Vec::new(),
+            pdu,
+        };
+        let security = OutgoingSecurity {
+            user: Some(user),
+            level: user.max_security_level(),
+            engine_id: &self.engine_id,
+            engine_boots: self.engine_boots,
+            engine_time: self.engine_time(),
+        };
+        encode_secure_message(&security, msg_id, false, &scoped, self.next_salt())
+    }
+
+    /// Unauthenticated discovery probe used to learn a remote engine ID
+    pub fn discovery_probe(msg_id: i32) -> Result<Vec<u8>> {
+        let scoped = ScopedPdu {
+            context_engine_id: Vec::new(),
+            context_name: Vec::new(),
+            pdu: Pdu::new(PduType::GetRequest, msg_id, Vec::new()),
+        };
+        let security = OutgoingSecurity {
+            user: None,
+            level: SnmpSecurityLevel::NoAuthNoPriv,
+            engine_id: &[],
+            engine_boots: 0,
+            engine_time: 0,
+        };
+        encode_secure_message(&security, msg_id, true, &scoped, 0)
+    }
+}
+
+/// Whether a datagram carries an SNMPv3 message
+pub fn is_v3_message(data: &[u8]) -> bool {
+    V3Message::peek_msg_id(data).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(auth: SnmpAuthProtocol, privacy: SnmpPrivProtocol) -> SnmpUsmUser {
+        SnmpUsmUser {
+            name: "noc".to_string(),
+            auth_protocol: auth,
+            auth_password: Some("authpassword".to_string()),
+            priv_protocol: privacy,
+            priv_password: Some("privpassword".to_string()),
+            read_view: "system".to_string(),
+            min_security_level: SnmpSecurityLevel::AuthNoPriv,
+        }
+    }
+
+    fn engine(auth: SnmpAuthProtocol, privacy: SnmpPrivProtocol) -> UsmEngine {
+        let config = SnmpV3Config {
+            users: vec![user(auth, privacy)],
+            views: vec![SnmpView {
+                name: "system".to_string(),
+                included: vec!["1.3.6.1.2.1.1".to_string()],
+                excluded: vec!["1.3.6.1.2.1.1.4".to_string()],
+            }],
+            ..SnmpV3Config::default()
+        };
+        let state = EngineState {
+            engine_id: EngineState::generate_engine_id(),
+            engine_boots: 7,
+        };
+        UsmEngine::new(&config, state).unwrap()
+    }
+
+    fn get_request(engine: &UsmEngine, level: SnmpSecurityLevel) -> Vec<u8> {
+        let scoped = ScopedPdu {
+            context_engine_id: engine.engine_id().to_vec(),
+            context_name: Vec::new(),
+            pdu: Pdu::new(
+                PduType::GetRequest,
+                42,
+                vec![VarBind { oid: Oid::from_string("1.3.6.1.2.1.1.1.0").unwrap(), value: SnmpValue::Null }],
+            ),
+        };
+        let security = OutgoingSecurity {
+            user: engine.user("noc"),
+            level,
+            engine_id: engine.engine_id(),
+            engine_boots: engine.engine_boots(),
+            engine_time: engine.engine_time(),
+        };
+        encode_secure_message(&security, 1001, true, &scoped, 5).unwrap()
+    }
+
+    #[test]
+    fn test_ber_integer_and_oid() {
+        assert_eq!(encode_integer(0), vec![0x02, 0x01, 0x00]);
+        assert_eq!(encode_integer(128), vec![0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(encode_integer(-1), vec![0x02, 0x01, 0xff]);
+        assert_eq!(decode_integer(&[0x00, 0x80]).unwrap(), 128);
+        assert_eq!(decode_integer(&[0xff]).unwrap(), -1);
+
+        let oid = Oid::from_string("1.3.6.1.4.1.99999.1").unwrap();
+        let encoded = encode_oid(&oid);
+        let (_, content, _) = decode_tlv(&encoded).unwrap();
+        assert_eq!(decode_oid(content).unwrap(), oid);
+    }
+
+    #[test]
+    fn test_auth_priv_roundtrip() {
+        for (auth, privacy) in [
+            (SnmpAuthProtocol::HmacSha224, SnmpPrivProtocol::Aes256),
+            (SnmpAuthProtocol::HmacSha256, SnmpPrivProtocol::Aes128),
+            (SnmpAuthProtocol::HmacSha512, SnmpPrivProtocol::Aes192),
+        ] {
+            let engine = engine(auth, privacy);
+            let raw = get_request(&engine, SnmpSecurityLevel::AuthPriv);
+            let request = engine.process_incoming(&raw).unwrap().unwrap();
+            assert_eq!(request.user, "noc");
+            assert_eq!(request.scoped_pdu.pdu.request_id, 42);
+            assert_eq!(request.scoped_pdu.pdu.var_binds.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_tampered_message_fails_digest() {
+        let engine = engine(SnmpAuthProtocol::HmacSha256, SnmpPrivProtocol::Aes128);
+        let mut raw = get_request(&engine, SnmpSecurityLevel::AuthPriv);
+        let last = raw.len() - 1;
+        raw[last] ^= 0xff;
+
+        let failure = engine.process_incoming(&raw).unwrap().unwrap_err();
+        assert_eq!(failure.error, UsmError::WrongDigest);
+        assert_eq!(engine.stat(UsmError::WrongDigest), 1);
+        assert!(engine.report(&failure).is_ok());
+    }
+
+    #[test]
+    fn test_discovery_and_security_level_checks() {
+        let engine = engine(SnmpAuthProtocol::HmacSha256, SnmpPrivProtocol::None);
+
+        let probe = UsmEngine::discovery_probe(7).unwrap();
+        let failure = engine.process_incoming(&probe).unwrap().unwrap_err();
+        assert_eq!(failure.error, UsmError::UnknownEngineId);
+
+        // Report carries the engine ID the manager needs for discovery
+        let report = engine.report(&failure).unwrap();
+        let (message, _) = V3Message::decode(&report).unwrap();
+        assert_eq!(message.security.engine_id, engine.engine_id());
+        assert_eq!(message.msg_id, 7);
+
+        let raw = get_request(&engine, SnmpSecurityLevel::NoAuthNoPriv);
+        let failure = engine.process_incoming(&raw).unwrap().unwrap_err();
+        assert_eq!(failure.error, UsmError::UnsupportedSecLevel);
+    }
+
+    #[test]
+    fn test_vacm_view() {
+        let engine = engine(SnmpAuthProtocol::HmacSha256, SnmpPrivProtocol::None);
+        assert!(engine.is_readable("noc", &Oid::from_string("1.3.6.1.2.1.1.1.0").unwrap()));
+        assert!(!engine.is_readable("noc", &Oid::from_string("1.3.6.1.2.1.1.4.0").unwrap()));
+        assert!(!engine.is_readable("noc", &Oid::from_string("1.3.6.1.4.1.99999.2").unwrap()));
+        assert!(!engine.is_readable("other", &Oid::from_string("1.3.6.1.2.1.1.1.0").unwrap()));
+    }
+
+    #[test]
+    fn test_engine_state_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SnmpV3Config {
+            engine_state_file: dir.path().join("engine").to_string_lossy().to_string(),
+            ..SnmpV3Config::default()
+        };
+
+        let first = EngineState::load_and_increment(&config).unwrap();
+        let second = EngineState::load_and_increment(&config).unwrap();
+        assert_eq!(first.engine_id, second.engine_id);
+        assert_eq!(second.engine_boots, first.engine_boots + 1);
+    }
+}
@@ -0,0 +1,333 @@
+//! Public status page
+//!
+//! A load balancer or NOC wallboard needs to know whether the gateway is
+//! in service, not which numbers it is calling or how it is configured. The
+//! status page answers on a listener of its own, separate from the
+//! loopback-only management API, with overall health, uptime, span counts
+//! per trunk type, service states and alarm counts: JSON at
+//! [`PUBLIC_STATUS_PATH`] and a small self-refreshing HTML page at
+//! [`STATUS_PAGE_PATH`]. Node names, span names, addresses and alarm text
+//! are left out. Both answer 503 while the gateway is down so a health check
+//! can take it out of rotation. The page is open unless a secret is
+//! configured.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::config::StatusPageConfig;
+use crate::core::control::ControlStatus;
+use crate::services::management_api::{AlarmReport, ManagementSource, ServiceReport, SpanReport};
+use crate::utils::netbind;
+use crate::{Error, Result};
+
+/// Status page path answering JSON
+pub const PUBLIC_STATUS_PATH: &str = "/status";
+
+/// Status page path answering HTML
+pub const STATUS_PAGE_PATH: &str = "/";
+
+/// Overall state of the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Ok,
+    /// In service with spans down, services stopped or major alarms raised
+    Degraded,
+    /// Not running, or no span up
+    Down,
+}
+
+impl Health {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Health::Ok => "ok",
+            Health::Degraded => "degraded",
+            Health::Down => "down",
+        }
+    }
+}
+
+/// Spans of one trunk type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TrunkCount {
+    /// e1 or t1
+    pub trunk_type: String,
+    pub up: usize,
+    pub total: usize,
+}
+
+/// What the status page tells anyone who asks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PublicStatus {
+    pub health: Health,
+    pub uptime_secs: u64,
+    pub spans_up: usize,
+    pub spans_total: usize,
+    pub trunks: Vec<TrunkCount>,
+    pub services: Vec<ServiceReport>,
+    pub critical_alarms: usize,
+    pub major_alarms: usize,
+}
+
+impl PublicStatus {
+    pub fn summarize(
+        status: &ControlStatus,
+        spans: &[SpanReport],
+        services: Vec<ServiceReport>,
+        alarms: &[AlarmReport],
+    ) -> Self {
+        let mut trunks: BTreeMap<&str, TrunkCount> = BTreeMap::new();
+        for span in spans {
+            let count = trunks.entry(span.trunk_type.as_str()).or_insert_with(|| TrunkCount {
+                trunk_type: span.trunk_type.clone(),
+                up: 0,
+                total: 0,
+            });
+            count.total += 1;
+            count.up += usize::from(span.up);
+        }
+        let spans_up = spans.iter().filter(|span| span.up).count();
+        let severity = |severity: &str| alarms.iter().filter(|alarm| alarm.severity == severity).count();
+        let (critical_alarms, major_alarms) = (severity("critical"), severity("major"));
+
+        let health = if !status.running || (!spans.is_empty() && spans_up == 0) {
+            Health::Down
+        } else if spans_up < spans.len()
+            || services.iter().any(|service| service.state == "stopped")
+            || critical_alarms + major_alarms > 0
+        {
+            Health::Degraded
+        } else {
+            Health::Ok
+        };
+
+        Self {
+            health,
+            uptime_secs: status.uptime_secs,
+            spans_up,
+            spans_total: spans.len(),
+            trunks: trunks.into_values().collect(),
+            services,
+            critical_alarms,
+            major_alarms,
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self.health {
+            Health::Down => StatusCode::SERVICE_UNAVAILABLE,
+            Health::Ok | Health::Degraded => StatusCode::OK,
+        }
+    }
+
+    /// Wallboard page reloading itself every `refresh_secs`
+    pub fn to_html(&self, refresh_secs: u32) -> String {
+        let mut rows = format!(
+            "<tr><th>Uptime</th><td>{}d {:02}:{:02}</td></tr>\n<tr><th>Spans up</th><td>{} / {}</td></tr>\n",
+            self.uptime_secs / 86_400,
+            self.uptime_secs % 86_400 / 3600,
+            self.uptime_secs % 3600 / 60,
+            self.spans_up,
+            self.spans_total,
+        );
+        for trunk in &self.trunks {
+            let trunk_type = trunk.trunk_type.to_uppercase();
+            rows.push_str(&format!("<tr><th>{} up</th><td>{} / {}</td></tr>\n", trunk_type, trunk.up, trunk.total));
+        }
+        for service in &self.services {
+            let (name, state) = (&service.name, &service.state);
+            rows.push_str(&format!("<tr><th>{}</th><td class=\"{}\">{}</td></tr>\n", name, state, state));
+        }
+        rows.push_str(&format!(
+            "<tr><th>Alarms</th><td>{} critical, {} major</td></tr>\n",
+            self.critical_alarms, self.major_alarms
+        ));
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"{refresh}\">\
+             <title>Gateway {health}</title><style>body{{font-family:sans-serif}}\
+             th{{text-align:left;padding-right:1em}}.ok,.running{{color:green}}\
+             .degraded,.stopped{{color:orange}}.down{{color:red}}</style></head>\n\
+             <body><h1 class=\"{health}\">{health}</h1>\n<table>\n{rows}</table></body></html>\n",
+            refresh = refresh_secs,
+            health = self.health.as_str(),
+            rows = rows,
+        )
+    }
+}
+
+/// Whether a request carries the configured secret, when there is one
+fn authorized(secret: Option<&str>, headers: &HeaderMap, query: &SecretQuery) -> bool {
+    let Some(secret) = secret else {
+        return true;
+    };
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer == Some(secret) || query.secret.as_deref() == Some(secret)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SecretQuery {
+    secret: Option<String>,
+}
+
+#[derive(Clone)]
+struct PageState {
+    source: Arc<dyn ManagementSource>,
+    secret: Option<String>,
+    refresh_secs: u32,
+}
+
+impl PageState {
+    async fn public_status(
+        &self,
+        headers: &HeaderMap,
+        query: &SecretQuery,
+    ) -> std::result::Result<PublicStatus, Response> {
+        if !authorized(self.secret.as_deref(), headers, query) {
+            return Err((StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")]).into_response());
+        }
+        let status = self.source.status().await;
+        let spans = self.source.spans().await;
+        let services = self.source.services().await;
+        let alarms = self.source.alarms().await;
+        Ok(PublicStatus::summarize(&status, &spans, services, &alarms))
+    }
+}
+
+async fn status_json(State(state): State<PageState>, headers: HeaderMap, Query(query): Query<SecretQuery>) -> Response {
+    match state.public_status(&headers, &query).await {
+        Ok(status) => (status.status_code(), Json(status)).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn status_html(State(state): State<PageState>, headers: HeaderMap, Query(query): Query<SecretQuery>) -> Response {
+    match state.public_status(&headers, &query).await {
+        Ok(status) => (status.status_code(), Html(status.to_html(state.refresh_secs))).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Status page bound to its port, not yet serving
+pub struct StatusPage {
+    listener: TcpListener,
+    router: Router,
+}
+
+impl StatusPage {
+    pub async fn bind(config: &StatusPageConfig, source: Arc<dyn ManagementSource>) -> Result<Self> {
+        let listener = netbind::bind_tcp(config.listen, &config.network)
+            .map_err(|e| Error::network(format!("Status page cannot listen on {}: {}", config.listen, e)))?;
+        let router = Router::new()
+            .route(PUBLIC_STATUS_PATH, get(status_json))
+            .route(STATUS_PAGE_PATH, get(status_html))
+            .with_state(PageState { source, secret: config.secret.clone(), refresh_secs: config.refresh_secs });
+        Ok(Self { listener, router })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve until the task is aborted
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            if let Ok(addr) = self.listener.local_addr() {
+                info!("Status page listening on http://{}", addr);
+            }
+            if let Err(e) = axum::serve(self.listener, self.router).await {
+                error!("Status page stopped: {}", e);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use chrono::Utc;
+
+    fn span(span_id: u32, trunk_type: &str, up: bool) -> SpanReport {
+        SpanReport {
+            span_id,
+            name: format!("span{}", span_id),
+            trunk_type: trunk_type.to_string(),
+            up,
+            alarms: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    fn status(running: bool) -> ControlStatus {
+        ControlStatus {
+            node_id: "gw-secret-site".to_string(),
+            version: crate::VERSION.to_string(),
+            running,
+            uptime_secs: 90_061,
+            active_calls: 0,
+            active_channels: 0,
+            sip_sessions: 0,
+            rtp_sessions: 0,
+            spans: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_health_and_counts() {
+        let spans = vec![span(1, "e1", true), span(2, "e1", false), span(3, "t1", true)];
+        let services = vec![ServiceReport { name: "sip".to_string(), state: "running".to_string() }];
+        let summary = PublicStatus::summarize(&status(true), &spans, services.clone(), &[]);
+        assert_eq!(summary.health, Health::Degraded);
+        assert_eq!((summary.spans_up, summary.spans_total), (2, 3));
+        assert_eq!(summary.trunks[0], TrunkCount { trunk_type: "e1".to_string(), up: 1, total: 2 });
+        assert_eq!(summary.status_code(), StatusCode::OK);
+
+        let all_up = [span(1, "e1", true)];
+        assert_eq!(PublicStatus::summarize(&status(true), &all_up, services.clone(), &[]).health, Health::Ok);
+        let alarm = AlarmReport {
+            id: "a1".to_string(),
+            severity: "critical".to_string(),
+            alarm_type: "span_down".to_string(),
+            source: "span/1".to_string(),
+            description: "Loss of signal".to_string(),
+            raised_time: Utc::now(),
+            acknowledged: false,
+            event_count: 1,
+        };
+        let alarmed = PublicStatus::summarize(&status(true), &all_up, services.clone(), &[alarm]);
+        assert_eq!((alarmed.health, alarmed.critical_alarms), (Health::Degraded, 1));
+
+        let stopped = PublicStatus::summarize(&status(false), &all_up, services, &[]);
+        assert_eq!(stopped.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        let html = stopped.to_html(30);
+        assert!(html.contains("content=\"30\"") && html.contains("1d 01:01"));
+        assert!(!html.contains("gw-secret-site") && !html.contains("span1"));
+    }
+
+    #[test]
+    fn test_secret_in_header_or_query() {
+        let mut headers = HeaderMap::new();
+        let none = SecretQuery::default();
+        assert!(authorized(None, &headers, &none));
+        assert!(!authorized(Some("s3"), &headers, &none));
+        assert!(authorized(Some("s3"), &headers, &SecretQuery { secret: Some("s3".to_string()) }));
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer s3"));
+        assert!(authorized(Some("s3"), &headers, &none));
+        assert!(!authorized(Some("other"), &headers, &none));
+    }
+}
@@ -0,0 +1,418 @@
+//! Remote support tunnel initiated from the gateway
+//!
+//! Field units sit behind firewalls that accept no inbound connections. On
+//! an operator's request the gateway opens an outbound tunnel to the support
+//! concentrator: an SSH session forwarding a concentrator port back to the
+//! local management API, or a WireGuard interface whose only peer is the
+//! concentrator. The SSH forward is only configurable once the management
+//! API requires its secret. One tunnel is open at a time, it is closed when
+//! its time is up, and every open, close and failure is kept in an audit
+//! trail that is also appended to a log file.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::{SupportTunnelConfig, TunnelMethod};
+use crate::{Error, Result};
+
+/// Management API path opening (POST), closing (DELETE) and showing (GET) the tunnel
+pub const SUPPORT_TUNNEL_PATH: &str = "/api/v1/support/tunnel";
+/// Management API path serving the tunnel audit trail
+pub const SUPPORT_TUNNEL_AUDIT_PATH: &str = "/api/v1/support/tunnel/audit";
+
+/// Audit records kept in memory; the log file keeps them all
+const MAX_AUDIT_RECORDS: usize = 200;
+
+/// Operator request to open the tunnel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TunnelRequest {
+    pub operator: String,
+    /// Ticket or reason recorded in the audit trail
+    pub reason: String,
+    /// Tunnel lifetime; the configured default when omitted
+    pub duration_secs: Option<u64>,
+}
+
+/// An open tunnel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TunnelSession {
+    pub id: String,
+    pub method: TunnelMethod,
+    pub concentrator: String,
+    pub operator: String,
+    pub reason: String,
+    pub opened_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelAuditAction {
+    Opened,
+    /// Closed by an operator
+    Closed,
+    /// Closed when its lifetime ran out
+    Expired,
+    /// Could not be opened, or dropped unexpectedly
+    Failed,
+}
+
+/// One entry of the audit trail
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TunnelAuditRecord {
+    pub at: DateTime<Utc>,
+    pub action: TunnelAuditAction,
+    pub session_id: Option<String>,
+    pub operator: Option<String>,
+    pub detail: String,
+}
+
+struct ActiveTunnel {
+    session: TunnelSession,
+    /// Expiry timer, also owning the SSH process so aborting it kills the tunnel
+    task: JoinHandle<()>,
+}
+
+/// Opens, expires and audits the support tunnel
+pub struct SupportTunnel {
+    config: SupportTunnelConfig,
+    /// Held while the tunnel comes up or goes down, so two requests never
+    /// bring up the same interface and a closing tunnel is down before the
+    /// next one opens
+    transition: tokio::sync::Mutex<()>,
+    active: Mutex<Option<ActiveTunnel>>,
+    audit: Mutex<VecDeque<TunnelAuditRecord>>,
+}
+
+impl SupportTunnel {
+    pub fn new(config: SupportTunnelConfig) -> Self {
+        Self {
+            config,
+            transition: tokio::sync::Mutex::new(()),
+            active: Mutex::new(None),
+            audit: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Command bringing the tunnel up
+    pub fn open_command(&self) -> Vec<String> {
+        match self.config.method {
+            TunnelMethod::Ssh => {
+                let (host, port) = match self.config.concentrator.rsplit_once(':') {
+                    Some((host, port)) => (host, port),
+                    None => (self.config.concentrator.as_str(), "22"),
+                };
+                vec![
+                    self.config.ssh_path.clone(),
+                    "-N".to_string(),
+                    "-T".to_string(),
+                    "-o".to_string(),
+                    "BatchMode=yes".to_string(),
+                    "-o".to_string(),
+                    "ExitOnForwardFailure=yes".to_string(),
+                    "-o".to_string(),
+                    "ServerAliveInterval=30".to_string(),
+                    "-o".to_string(),
+                    "StrictHostKeyChecking=yes".to_string(),
+                    "-i".to_string(),
+                    self.config.ssh_identity_path.clone(),
+                    "-p".to_string(),
+                    port.to_string(),
+                    "-R".to_string(),
+                    format!("{}:{}", self.config.remote_port, self.config.management_api),
+                    format!("{}@{}", self.config.ssh_user, host),
+                ]
+            }
+            TunnelMethod::WireGuard => vec![
+                self.config.wg_quick_path.clone(),
+                "up".to_string(),
+                self.config.wireguard_config_path.clone(),
+            ],
+        }
+    }
+
+    /// Command tearing the tunnel down, for methods that outlive a process
+    pub fn close_command(&self) -> Option<Vec<String>> {
+        match self.config.method {
+            TunnelMethod::Ssh => None,
+            TunnelMethod::WireGuard => Some(vec![
+                self.config.wg_quick_path.clone(),
+                "down".to_string(),
+                self.config.wireguard_config_path.clone(),
+            ]),
+        }
+    }
+
+    /// Lifetime granted to a request
+    pub fn duration(&self, requested: Option<u64>) -> Result<Duration> {
+        let secs = requested.unwrap_or(self.config.default_duration_secs);
+        if secs == 0 || secs > self.config.max_duration_secs {
+            return Err(Error::invalid_config(format!(
+                "Support tunnel duration must be between 1 and {} seconds",
+                self.config.max_duration_secs
+            )));
+        }
+        Ok(Duration::from_secs(secs))
+    }
+
+    /// Open the tunnel; fails if one is already open
+    pub async fn open(self: &Arc<Self>, request: TunnelRequest) -> Result<TunnelSession> {
+        let duration = self.duration(request.duration_secs)?;
+        if request.operator.trim().is_empty() {
+            return Err(Error::invalid_config("Support tunnel requests must name the operator"));
+        }
+        let _transition = self.transition.lock().await;
+        if let Some(active) = self.status() {
+            return Err(Error::invalid_state(format!(
+                "Support tunnel {} opened by {} is already open",
+                active.id, active.operator
+            )));
+        }
+
+        let opened_at = Utc::now();
+        let session = TunnelSession {
+            id: Uuid::new_v4().to_string(),
+            method: self.config.method,
+            concentrator: self.config.concentrator.clone(),
+            operator: request.operator.clone(),
+            reason: request.reason.clone(),
+            opened_at,
+            expires_at: opened_at + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero()),
+        };
+
+        let child = match self.bring_up().await {
+            Ok(child) => child,
+            Err(e) => {
+                self.record(TunnelAuditAction::Failed, Some(&session), e.to_string());
+                return Err(e);
+            }
+        };
+
+        // Registered before the watcher can observe an early exit
+        let mut active = self.active.lock().unwrap();
+        let task = self.watch(session.id.clone(), duration, child);
+        *active = Some(ActiveTunnel { session: session.clone(), task });
+        drop(active);
+
+        info!(
+            "Support tunnel {} to {} opened by {} for {:?}: {}",
+            session.id, session.concentrator, session.operator, duration, session.reason
+        );
+        self.record(TunnelAuditAction::Opened, Some(&session), format!("{:?} for {}s", session.method, duration.as_secs()));
+        Ok(session)
+    }
+
+    /// Close the open tunnel on an operator's request
+    pub async fn close(&self, operator: &str) -> Result<TunnelSession> {
+        let _transition = self.transition.lock().await;
+        let active = self
+            .active
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| Error::invalid_state("No support tunnel is open"))?;
+        active.task.abort();
+        self.tear_down().await;
+        self.record(TunnelAuditAction::Closed, Some(&active.session), format!("closed by {}", operator));
+        Ok(active.session)
+    }
+
+    /// The open tunnel, if any
+    pub fn status(&self) -> Option<TunnelSession> {
+        self.active.lock().unwrap().as_ref().map(|active| active.session.clone())
+    }
+
+    /// Audit trail, oldest first
+    pub fn audit(&self) -> Vec<TunnelAuditRecord> {
+        self.audit.lock().unwrap().iter().cloned().collect()
+    }
+
+    async fn bring_up(&self) -> Result<Option<Child>> {
+        let command = self.open_command();
+        let mut process = Command::new(&command[0]);
+        process.args(&command[1..]).stdin(Stdio::null()).stdout(Stdio::null()).kill_on_drop(true);
+
+        match self.config.method {
+            TunnelMethod::Ssh => {
+                let child = process
+                    .spawn()
+                    .map_err(|e| Error::network(format!("Failed to start {}: {}", command[0], e)))?;
+                Ok(Some(child))
+            }
+            TunnelMethod::WireGuard => {
+                let status = process.status().await?;
+                if !status.success() {
+                    return Err(Error::network(format!("{} failed with {}", command.join(" "), status)));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    async fn tear_down(&self) {
+        let Some(command) = self.close_command() else {
+            return;
+        };
+        match Command::new(&command[0]).args(&command[1..]).status().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => warn!("{} failed with {}", command.join(" "), status),
+            Err(e) => warn!("Failed to run {}: {}", command[0], e),
+        }
+    }
+
+    /// Close the tunnel when its time is up or its process exits
+    fn watch(self: &Arc<Self>, id: String, duration: Duration, child: Option<Child>) -> JoinHandle<()> {
+        let tunnel = Arc::clone(self);
+        tokio::spawn(async move {
+            let (action, detail) = match child {
+                Some(mut child) => tokio::select! {
+                    _ = tokio::time::sleep(duration) => {
+                        let _ = child.kill().await;
+                        (TunnelAuditAction::Expired, "lifetime elapsed".to_string())
+                    }
+                    status = child.wait() => (
+                        TunnelAuditAction::Failed,
+                        match status {
+                            Ok(status) => format!("tunnel process exited with {}", status),
+                            Err(e) => format!("tunnel process lost: {}", e),
+                        },
+                    ),
+                },
+                None => {
+                    tokio::time::sleep(duration).await;
+                    (TunnelAuditAction::Expired, "lifetime elapsed".to_string())
+                }
+            };
+
+            let _transition = tunnel.transition.lock().await;
+            let ended = {
+                let mut active = tunnel.active.lock().unwrap();
+                match active.as_ref() {
+                    Some(open) if open.session.id == id => active.take().map(|open| open.session),
+                    _ => None,
+                }
+            };
+            if let Some(session) = ended {
+                tunnel.tear_down().await;
+                warn!("Support tunnel {} closed: {}", session.id, detail);
+                tunnel.record(action, Some(&session), detail);
+            }
+        })
+    }
+
+    fn record(&self, action: TunnelAuditAction, session: Option<&TunnelSession>, detail: String) {
+        let record = TunnelAuditRecord {
+            at: Utc::now(),
+            action,
+            session_id: session.map(|session| session.id.clone()),
+            operator: session.map(|session| session.operator.clone()),
+            detail,
+        };
+
+        if !self.config.audit_log_path.is_empty() {
+            if let Err(e) = append_audit(&self.config.audit_log_path, &record) {
+                warn!("Failed to write support tunnel audit log {}: {}", self.config.audit_log_path, e);
+            }
+        }
+        let mut audit = self.audit.lock().unwrap();
+        audit.push_back(record);
+        while audit.len() > MAX_AUDIT_RECORDS {
+            audit.pop_front();
+        }
+    }
+}
+
+fn append_audit(path: &str, record: &TunnelAuditRecord) -> std::io::Result<()> {
+    let line = serde_json::to_string(record).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(method: TunnelMethod) -> SupportTunnelConfig {
+        SupportTunnelConfig {
+            enabled: true,
+            method,
+            concentrator: "support.example.net:2222".to_string(),
+            remote_port: 40123,
+            // `true` stands in for wg-quick so interfaces come up and down without effect
+            wg_quick_path: "true".to_string(),
+            audit_log_path: String::new(),
+            ..Default::default()
+        }
+    }
+
+    fn request(duration_secs: Option<u64>) -> TunnelRequest {
+        TunnelRequest {
+            operator: "jdoe".to_string(),
+            reason: "TKT-4411".to_string(),
+            duration_secs,
+        }
+    }
+
+    #[test]
+    fn test_ssh_forwards_management_api() {
+        let tunnel = SupportTunnel::new(config(TunnelMethod::Ssh));
+        let command = tunnel.open_command();
+        assert_eq!(command[0], "ssh");
+        assert!(command.windows(2).any(|pair| pair == ["-p", "2222"]));
+        assert!(command.windows(2).any(|pair| pair == ["-R", "40123:127.0.0.1:8080"]));
+        assert_eq!(command.last().unwrap(), "support@support.example.net");
+        assert!(tunnel.close_command().is_none());
+
+        assert!(tunnel.duration(Some(0)).is_err());
+        assert!(tunnel.duration(Some(9 * 3600)).is_err());
+        assert_eq!(tunnel.duration(None).unwrap(), Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_expires_and_is_audited() {
+        let tunnel = Arc::new(SupportTunnel::new(config(TunnelMethod::WireGuard)));
+        let session = tunnel.open(request(Some(1))).await.unwrap();
+        assert_eq!(tunnel.status().unwrap().id, session.id);
+        assert!(tunnel.open(request(None)).await.is_err());
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(tunnel.status().is_none());
+        let actions: Vec<TunnelAuditAction> = tunnel.audit().iter().map(|record| record.action).collect();
+        assert_eq!(actions, vec![TunnelAuditAction::Opened, TunnelAuditAction::Expired]);
+
+        tunnel.open(request(None)).await.unwrap();
+        tunnel.close("jdoe").await.unwrap();
+        assert!(tunnel.close("jdoe").await.is_err());
+        assert_eq!(tunnel.audit().last().unwrap().action, TunnelAuditAction::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_opens_bring_up_one_interface() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("wg-quick.log");
+        let script = dir.path().join("wg-quick");
+        std::fs::write(&script, format!("#!/bin/sh\necho \"$1\" >> {}\n", log.display())).unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let config = SupportTunnelConfig {
+            wg_quick_path: script.display().to_string(),
+            ..config(TunnelMethod::WireGuard)
+        };
+        let tunnel = Arc::new(SupportTunnel::new(config));
+
+        let (first, second) = tokio::join!(tunnel.open(request(None)), tunnel.open(request(None)));
+        assert!(first.is_ok() != second.is_ok());
+        tunnel.close("jdoe").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "up\ndown\n");
+    }
+}
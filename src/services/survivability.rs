@@ -0,0 +1,701 @@
+//! Branch survivability
+//!
+//! REGISTERs from local phones are proxied to a hosted PBX and the resulting
+//! bindings cached. The registrar is probed with OPTIONS; once it stops
+//! answering, or a trunk towards it fails, the gateway enters survivability
+//! mode. Registrations are then answered locally with a short expiry, calls
+//! to registered phones stay local, numbers allowed by the fallback dial plan
+//! break out to TDM spans and everything else hears an announcement.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+
+use regex::Regex;
+
+use crate::config::{BreakoutCategory, RouteType, SurvivabilityConfig, SurvivabilityDialRule};
+use crate::protocols::sip_time::{self, SipTimestamp};
+use crate::protocols::sip_via;
+use crate::services::b2bua::RoutingInfo;
+use crate::services::routing_hook::RouteResolution;
+use crate::{Error, Result};
+
+/// Whether registrations are proxied or answered locally
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SurvivabilityMode {
+    /// Registrar reachable; REGISTERs are proxied
+    Normal,
+    /// Registrar unreachable; REGISTERs are answered locally
+    Survivability,
+}
+
+/// REGISTER received from a local phone
+#[derive(Debug, Clone)]
+pub struct RegisterRequest {
+    pub user: String,
+    pub contact: String,
+    pub expires: u32,
+    /// Headers from the phone's request, forwarded upstream as received
+    pub headers: Vec<(String, String)>,
+}
+
+impl RegisterRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Final answer to a REGISTER
+#[derive(Debug, Clone)]
+pub struct RegisterReply {
+    pub status_code: u16,
+    pub reason: String,
+    pub expires: u32,
+    /// Headers to copy into the response, e.g. an upstream auth challenge
+    pub headers: Vec<(String, String)>,
+}
+
+/// Cached registration of a local phone
+#[derive(Debug, Clone)]
+pub struct RegistrationBinding {
+    pub user: String,
+    pub contact: String,
+    pub expires_at: Instant,
+    /// Granted by the gateway rather than the upstream registrar
+    pub local: bool,
+}
+
+/// Survivability events
+#[derive(Debug, Clone)]
+pub enum SurvivabilityEvent {
+    ModeChanged {
+        mode: SurvivabilityMode,
+        reason: String,
+    },
+    BindingUpdated {
+        user: String,
+        contact: String,
+        expires: u32,
+        local: bool,
+    },
+    BindingRemoved {
+        user: String,
+        expired: bool,
+    },
+    CallBrokenOut {
+        callee: String,
+        rule_id: String,
+        category: BreakoutCategory,
+    },
+    CallAnnounced {
+        callee: String,
+        announcement: String,
+    },
+}
+
+/// Upstream registrar the gateway proxies to
+#[async_trait]
+pub trait RegistrarUpstream: Send + Sync {
+    async fn forward_register(&self, request: &RegisterRequest) -> Result<RegisterReply>;
+
+    /// Succeeds when the registrar answers at all
+    async fn probe(&self) -> Result<()>;
+}
+
+/// SIP over UDP to the hosted PBX
+pub struct UdpRegistrarUpstream {
+    registrar: String,
+    timeout: Duration,
+}
+
+impl UdpRegistrarUpstream {
+    pub fn new(registrar: impl Into<String>, timeout: Duration) -> Self {
+        Self { registrar: registrar.into(), timeout }
+    }
+
+    fn registrar_host(&self) -> &str {
+        self.registrar.rsplit_once(':').map(|(host, _)| host).unwrap_or(&self.registrar)
+    }
+
+    /// Send one request and wait for its final response
+    async fn transact(&self, method: &str, request_line_uri: &str, headers: &[(String, String)]) -> Result<RegisterReply> {
+        // Any socket failure counts as the registrar being unreachable
+        let network_err = |e: std::io::Error| Error::network(format!("{} to {} failed: {}", method, self.registrar, e));
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(network_err)?;
+        socket.connect(&self.registrar).await.map_err(network_err)?;
+        let local = socket.local_addr().map_err(network_err)?;
+
+        let branch = format!("z9hG4bK{:x}", rand::random::<u64>());
+        let mut message = format!(
+            "{} {} SIP/2.0\r\nVia: SIP/2.0/UDP {};branch={};rport\r\nMax-Forwards: 70\r\n",
+            method, request_line_uri, local, branch
+        );
+        for (name, value) in headers {
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        message.push_str(&format!("{}: {}\r\n", sip_time::TIMESTAMP, SipTimestamp::now().header_value()));
+        message.push_str("Content-Length: 0\r\n\r\n");
+        socket.send(message.as_bytes()).await.map_err(network_err)?;
+        let sent = Instant::now();
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let len = tokio::time::timeout_at(deadline, socket.recv(&mut buf))
+                .await
+                .map_err(|_| Error::timeout(format!("{} to {} timed out", method, self.registrar)))?
+                .map_err(network_err)?;
+            let mut reply = parse_response(&buf[..len])?;
+            // A late response to an earlier request on a reused port
+            if let Err(e) = sip_via::check_response(&reply.headers, &branch) {
+                debug!("Ignoring response from {}: {}", self.registrar, e);
+                continue;
+            }
+            // Provisional responses keep the transaction open
+            if reply.status_code >= 200 {
+                if let Some(timestamp) = SipTimestamp::from_headers(&reply.headers) {
+                    debug!("{} round trip to {}: {:?}", method, self.registrar, timestamp.round_trip(sent, Instant::now()));
+                    // The echo of our own timestamp means nothing to the phone
+                    reply.headers.retain(|(name, _)| !name.eq_ignore_ascii_case(sip_time::TIMESTAMP));
+                }
+                return Ok(reply);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RegistrarUpstream for UdpRegistrarUpstream {
+    async fn forward_register(&self, request: &RegisterRequest) -> Result<RegisterReply> {
+        let host = self.registrar_host();
+        let aor = format!("<sip:{}@{}>", request.user, host);
+        let call_id = request
+            .header("Call-ID")
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{:x}@{}", rand::random::<u64>(), host));
+        let cseq = request.header("CSeq").unwrap_or("1 REGISTER").to_string();
+
+        let mut headers = vec![
+            ("From".to_string(), format!("{};tag={:x}", aor, rand::random::<u32>())),
+            ("To".to_string(), aor),
+            ("Call-ID".to_string(), call_id),
+            ("CSeq".to_string(), cseq),
+            ("Contact".to_string(), request.contact.clone()),
+            ("Expires".to_string(), request.expires.to_string()),
+        ];
+        // Credentials and client identity pass through untouched
+        for (name, value) in &request.headers {
+            if ["Authorization", "User-Agent", "Supported", "Allow"]
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(name))
+            {
+                headers.push((name.clone(), value.clone()));
+            }
+        }
+
+        self.transact("REGISTER", &format!("sip:{}", host), &headers).await
+    }
+
+    async fn probe(&self) -> Result<()> {
+        let host = self.registrar_host();
+        let headers = vec![
+            ("From".to_string(), format!("<sip:gateway@{}>;tag={:x}", host, rand::random::<u32>())),
+            ("To".to_string(), format!("<sip:{}>", host)),
+            ("Call-ID".to_string(), format!("{:x}@{}", rand::random::<u64>(), host)),
+            ("CSeq".to_string(), "1 OPTIONS".to_string()),
+        ];
+        self.transact("OPTIONS", &format!("sip:{}", host), &headers).await.map(|_| ())
+    }
+}
+
+/// host[:port] of a Contact URI such as `<sip:1001@10.0.0.20:5060;transport=udp>`
+fn contact_host(contact: &str) -> Option<&str> {
+    let uri = contact.trim().trim_start_matches('<');
+    let uri = uri.split(['>', ';']).next()?;
+    let host = uri.rsplit_once('@').map(|(_, host)| host)
+        .or_else(|| uri.strip_prefix("sips:").or_else(|| uri.strip_prefix("sip:")))?;
+    (!host.is_empty()).then_some(host)
+}
+
+/// Parse the status line, headers and granted expiry of a SIP response
+pub(crate) fn parse_response(data: &[u8]) -> Result<RegisterReply> {
+    let text = std::str::from_utf8(data).map_err(|_| Error::parse("SIP response is not UTF-8"))?;
+    let mut lines = text.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+
+    let mut parts = status_line.splitn(3, ' ');
+    if parts.next() != Some("SIP/2.0") {
+        return Err(Error::parse(format!("Not a SIP response: {}", status_line)));
+    }
+    let status_code = parts
+        .next()
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| Error::parse(format!("Invalid status line: {}", status_line)))?;
+    let reason = parts.next().unwrap_or_default().to_string();
+
+    let headers: Vec<(String, String)> = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    // A contact's expires parameter overrides the Expires header
+    let contact_expires = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Contact") || name == "m")
+        .find_map(|(_, value)| {
+            value.split(';').find_map(|param| param.trim().strip_prefix("expires=")?.parse().ok())
+        });
+    let expires = contact_expires
+        .or_else(|| {
+            headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Expires"))
+                .and_then(|(_, value)| value.parse().ok())
+        })
+        .unwrap_or(0);
+
+    Ok(RegisterReply { status_code, reason, expires, headers })
+}
+
+/// Registration proxy with local fallback
+pub struct SurvivabilityService {
+    config: SurvivabilityConfig,
+    upstream: Arc<dyn RegistrarUpstream>,
+    dial_plan: Vec<(SurvivabilityDialRule, Regex)>,
+    bindings: DashMap<String, RegistrationBinding>,
+    registrar_down: AtomicBool,
+    failed_trunks: DashMap<String, Instant>,
+    /// Mode last announced, so transitions are reported once
+    mode: Mutex<SurvivabilityMode>,
+    consecutive_failures: AtomicU32,
+    consecutive_successes: AtomicU32,
+    event_tx: mpsc::UnboundedSender<SurvivabilityEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<SurvivabilityEvent>>,
+}
+
+impl SurvivabilityService {
+    pub fn new(config: SurvivabilityConfig) -> Result<Self> {
+        let upstream = UdpRegistrarUpstream::new(
+            config.upstream_registrar.clone(),
+            Duration::from_millis(config.upstream_timeout_ms),
+        );
+        Self::with_upstream(config, Arc::new(upstream))
+    }
+
+    pub fn with_upstream(config: SurvivabilityConfig, upstream: Arc<dyn RegistrarUpstream>) -> Result<Self> {
+        let dial_plan = config
+            .dial_plan
+            .iter()
+            .map(|rule| {
+                let pattern = Regex::new(&rule.pattern).map_err(|e| {
+                    Error::invalid_config(format!("Invalid survivability rule {} pattern: {}", rule.id, e))
+                })?;
+                Ok((rule.clone(), pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            config,
+            upstream,
+            dial_plan,
+            bindings: DashMap::new(),
+            registrar_down: AtomicBool::new(false),
+            failed_trunks: DashMap::new(),
+            mode: Mutex::new(SurvivabilityMode::Normal),
+            consecutive_failures: AtomicU32::new(0),
+            consecutive_successes: AtomicU32::new(0),
+            event_tx,
+            event_rx: Some(event_rx),
+        })
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<SurvivabilityEvent>> {
+        self.event_rx.take()
+    }
+
+    pub fn mode(&self) -> SurvivabilityMode {
+        *self.mode.lock().unwrap()
+    }
+
+    /// Enter survivability mode if `trunk` leads to the hosted PBX
+    pub fn on_trunk_down(&self, trunk: &str) {
+        if self.config.upstream_trunks.iter().any(|t| t == trunk) {
+            self.failed_trunks.insert(trunk.to_string(), Instant::now());
+            self.refresh_mode(format!("Upstream trunk {} failed", trunk));
+        }
+    }
+
+    pub fn on_trunk_up(&self, trunk: &str) {
+        if self.failed_trunks.remove(trunk).is_some() {
+            self.refresh_mode(format!("Upstream trunk {} recovered", trunk));
+        }
+    }
+
+    /// Proxy a REGISTER upstream, or answer it locally while surviving
+    pub async fn handle_register(&self, request: RegisterRequest) -> RegisterReply {
+        // Registration follows the registrar alone; a failed media trunk
+        // does not stop the PBX from accepting registrations
+        if !self.registrar_down.load(Ordering::SeqCst) {
+            match self.upstream.forward_register(&request).await {
+                Ok(reply) => {
+                    self.record_success();
+                    if (200..300).contains(&reply.status_code) {
+                        self.update_binding(&request.user, &request.contact, reply.expires, false);
+                    }
+                    return reply;
+                }
+                Err(e) if e.is_retryable() => {
+                    warn!("Registrar {} unreachable for {}: {}", self.config.upstream_registrar, request.user, e);
+                    self.record_failure(e.to_string());
+                }
+                Err(e) => {
+                    warn!("Failed to proxy REGISTER for {}: {}", request.user, e);
+                    let (status_code, reason) = e.sip_response();
+                    return RegisterReply {
+                        status_code,
+                        reason: reason.to_string(),
+                        expires: 0,
+                        headers: vec![],
+                    };
+                }
+            }
+        }
+
+        let expires = request.expires.min(self.config.local_expires_secs);
+        self.update_binding(&request.user, &request.contact, expires, true);
+        RegisterReply {
+            status_code: 200,
+            reason: "OK".to_string(),
+            expires,
+            headers: vec![("Contact".to_string(), format!("{};expires={}", request.contact, expires))],
+        }
+    }
+
+    /// Current binding for a local user
+    pub fn lookup(&self, user: &str) -> Option<RegistrationBinding> {
+        self.bindings
+            .get(user)
+            .filter(|binding| binding.expires_at > Instant::now())
+            .map(|binding| binding.clone())
+    }
+
+    /// Route for a call placed while surviving: registered local phones are
+    /// called directly, the fallback dial plan breaks out to TDM spans and
+    /// anything else is sent to the announcement
+    pub fn route(&self, callee: &str, static_route: RoutingInfo) -> RouteResolution {
+        if let Some(binding) = self.lookup(callee) {
+            return RouteResolution::Route(RoutingInfo {
+                route_type: RouteType::Direct,
+                target_gateway: contact_host(&binding.contact).map(str::to_string),
+                number_translation: None,
+                ring_group: None,
+                ..static_route
+            });
+        }
+
+        if let Some((rule, _)) = self.dial_plan.iter().find(|(_, pattern)| pattern.is_match(callee)) {
+            let _ = self.event_tx.send(SurvivabilityEvent::CallBrokenOut {
+                callee: callee.to_string(),
+                rule_id: rule.id.clone(),
+                category: rule.category,
+            });
+            return RouteResolution::Route(RoutingInfo {
+                route_type: match rule.category {
+                    BreakoutCategory::Emergency => RouteType::Emergency,
+                    BreakoutCategory::Local => RouteType::Trunk,
+                },
+                route: Some(rule.id.clone()),
+                target_gateway: None,
+                number_translation: None,
+                callee_override: rule.translation.as_ref().map(|t| t.apply(callee)),
+                egress_spans: rule.egress_spans.clone(),
+                priority: if rule.category == BreakoutCategory::Emergency { 0 } else { static_route.priority },
+                ring_group: None,
+                ..static_route
+            });
+        }
+
+        let _ = self.event_tx.send(SurvivabilityEvent::CallAnnounced {
+            callee: callee.to_string(),
+            announcement: self.config.announcement.clone(),
+        });
+        RouteResolution::Announce {
+            announcement: self.config.announcement.clone(),
+            status_code: self.config.announcement_release_status,
+        }
+    }
+
+    pub fn get_bindings(&self) -> Vec<RegistrationBinding> {
+        self.bindings.iter().map(|entry| entry.value().clone()).collect()
+    }
+
+    /// Probe the registrar once and apply the result
+    pub async fn probe_upstream(&self) {
+        match self.upstream.probe().await {
+            Ok(()) => self.record_success(),
+            Err(e) => {
+                debug!("Registrar probe failed: {}", e);
+                self.record_failure(e.to_string());
+            }
+        }
+    }
+
+    pub fn purge_expired(&self, now: Instant) {
+        let expired: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|entry| entry.expires_at <= now)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for user in expired {
+            self.bindings.remove(&user);
+            let _ = self.event_tx.send(SurvivabilityEvent::BindingRemoved { user, expired: true });
+        }
+    }
+
+    /// Spawn the registrar probe and binding expiry loop
+    pub fn spawn_monitor(self: &Arc<Self>) -> JoinHandle<()> {
+        let service = Arc::clone(self);
+        let period = Duration::from_secs(self.config.probe_interval_secs.max(1));
+
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            loop {
+                ticker.tick().await;
+                service.probe_upstream().await;
+                service.purge_expired(Instant::now());
+            }
+        })
+    }
+
+    fn update_binding(&self, user: &str, contact: &str, expires: u32, local: bool) {
+        if expires == 0 {
+            if self.bindings.remove(user).is_some() {
+                let _ = self.event_tx.send(SurvivabilityEvent::BindingRemoved {
+                    user: user.to_string(),
+                    expired: false,
+                });
+            }
+            return;
+        }
+
+        self.bindings.insert(user.to_string(), RegistrationBinding {
+            user: user.to_string(),
+            contact: contact.to_string(),
+            expires_at: Instant::now() + Duration::from_secs(expires as u64),
+            local,
+        });
+        let _ = self.event_tx.send(SurvivabilityEvent::BindingUpdated {
+            user: user.to_string(),
+            contact: contact.to_string(),
+            expires,
+            local,
+        });
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::SeqCst);
+        let successes = self.consecutive_successes.fetch_add(1, Ordering::SeqCst) + 1;
+        if successes >= self.config.successes_before_recovery && self.registrar_down.swap(false, Ordering::SeqCst) {
+            info!("Registrar {} reachable again; proxying registrations", self.config.upstream_registrar);
+            self.refresh_mode(format!("{} consecutive successful probes", successes));
+        }
+    }
+
+    fn record_failure(&self, reason: String) {
+        self.consecutive_successes.store(0, Ordering::SeqCst);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures >= self.config.failures_before_outage && !self.registrar_down.swap(true, Ordering::SeqCst) {
+            warn!("Registrar {} unreachable; answering registrations locally", self.config.upstream_registrar);
+            self.refresh_mode(reason);
+        }
+    }
+
+    /// Recompute the mode from registrar and trunk state, reporting any change
+    fn refresh_mode(&self, reason: String) {
+        let surviving = self.registrar_down.load(Ordering::SeqCst) || !self.failed_trunks.is_empty();
+        let mode = if surviving { SurvivabilityMode::Survivability } else { SurvivabilityMode::Normal };
+
+        let mut current = self.mode.lock().unwrap();
+        if *current == mode {
+            return;
+        }
+        *current = mode;
+
+        match mode {
+            SurvivabilityMode::Survivability => warn!("Entering survivability mode: {}", reason),
+            SurvivabilityMode::Normal => info!("Leaving survivability mode: {}", reason),
+        }
+        let _ = self.event_tx.send(SurvivabilityEvent::ModeChanged { mode, reason });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeRegistrar {
+        reachable: AtomicBool,
+    }
+
+    #[async_trait]
+    impl RegistrarUpstream for FakeRegistrar {
+        async fn forward_register(&self, request: &RegisterRequest) -> Result<RegisterReply> {
+            self.probe().await?;
+            Ok(RegisterReply {
+                status_code: 200,
+                reason: "OK".to_string(),
+                expires: request.expires,
+                headers: vec![],
+            })
+        }
+
+        async fn probe(&self) -> Result<()> {
+            if self.reachable.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(Error::timeout("registrar did not answer"))
+            }
+        }
+    }
+
+    fn register(user: &str, expires: u32) -> RegisterRequest {
+        RegisterRequest {
+            user: user.to_string(),
+            contact: format!("<sip:{}@10.0.0.20:5060>", user),
+            expires,
+            headers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_then_survive_then_recover() {
+        let registrar = Arc::new(FakeRegistrar { reachable: AtomicBool::new(true) });
+        let config = SurvivabilityConfig {
+            enabled: true,
+            upstream_registrar: "pbx.example.com:5060".to_string(),
+            failures_before_outage: 2,
+            successes_before_recovery: 1,
+            local_expires_secs: 120,
+            ..Default::default()
+        };
+        let mut service = SurvivabilityService::with_upstream(config, registrar.clone()).unwrap();
+        let mut events = service.take_event_receiver().unwrap();
+
+        let reply = service.handle_register(register("1001", 3600)).await;
+        assert_eq!((reply.status_code, reply.expires), (200, 3600));
+        assert!(!service.lookup("1001").unwrap().local);
+
+        registrar.reachable.store(false, Ordering::SeqCst);
+        service.probe_upstream().await;
+        assert_eq!(service.mode(), SurvivabilityMode::Normal);
+        let reply = service.handle_register(register("1002", 3600)).await;
+        assert_eq!(service.mode(), SurvivabilityMode::Survivability);
+        assert_eq!((reply.status_code, reply.expires), (200, 120));
+        assert!(service.lookup("1002").unwrap().local);
+
+        registrar.reachable.store(true, Ordering::SeqCst);
+        service.probe_upstream().await;
+        assert_eq!(service.mode(), SurvivabilityMode::Normal);
+
+        let modes: Vec<SurvivabilityMode> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                SurvivabilityEvent::ModeChanged { mode, .. } => Some(mode),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(modes, vec![SurvivabilityMode::Survivability, SurvivabilityMode::Normal]);
+    }
+
+    #[tokio::test]
+    async fn test_trunk_failure_activates_dial_plan() {
+        let registrar = Arc::new(FakeRegistrar { reachable: AtomicBool::new(true) });
+        let config = SurvivabilityConfig {
+            enabled: true,
+            upstream_registrar: "pbx.example.com:5060".to_string(),
+            upstream_trunks: vec!["pbx-trunk".to_string()],
+            dial_plan: vec![SurvivabilityDialRule {
+                id: "emergency".to_string(),
+                category: BreakoutCategory::Emergency,
+                pattern: "^(911|112)$".to_string(),
+                egress_spans: vec![1, 2],
+                translation: None,
+            }],
+            ..Default::default()
+        };
+        let mut service = SurvivabilityService::with_upstream(config, registrar).unwrap();
+        let mut events = service.take_event_receiver().unwrap();
+        let static_route = RoutingInfo {
+            route_type: RouteType::Trunk,
+            route: None,
+            target_gateway: Some("pbx-trunk".to_string()),
+            number_translation: None,
+            codec_preference: vec![],
+            priority: 100,
+            callee_override: None,
+            extra_headers: Default::default(),
+            egress_spans: vec![],
+            number_lookup: None,
+            caller_name: None,
+            no_answer: None,
+            alternate_targets: Vec::new(),
+            failover_actions: Default::default(),
+            ring_group: None,
+            early_media: None,
+        };
+
+        service.on_trunk_down("other-trunk");
+        assert_eq!(service.mode(), SurvivabilityMode::Normal);
+        service.on_trunk_down("pbx-trunk");
+        assert_eq!(service.mode(), SurvivabilityMode::Survivability);
+
+        match service.route("911", static_route.clone()) {
+            RouteResolution::Route(route) => {
+                assert!(matches!(route.route_type, RouteType::Emergency));
+                assert_eq!(route.egress_spans, vec![1, 2]);
+            }
+            other => panic!("expected breakout, got {:?}", other),
+        }
+        assert!(matches!(
+            service.route("4155550100", static_route),
+            RouteResolution::Announce { status_code: 503, .. }
+        ));
+
+        service.on_trunk_up("pbx-trunk");
+        assert_eq!(service.mode(), SurvivabilityMode::Normal);
+        let modes = std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|event| matches!(event, SurvivabilityEvent::ModeChanged { .. }))
+            .count();
+        assert_eq!(modes, 2);
+    }
+
+    #[test]
+    fn test_parse_register_response() {
+        let response = b"SIP/2.0 200 OK\r\nVia: SIP/2.0/UDP 10.0.0.1:5060\r\nContact: <sip:1001@10.0.0.20>;expires=1800\r\nExpires: 3600\r\nContent-Length: 0\r\n\r\n";
+        let reply = parse_response(response).unwrap();
+        assert_eq!(reply.status_code, 200);
+        assert_eq!(reply.expires, 1800);
+
+        let challenge = b"SIP/2.0 401 Unauthorized\r\nWWW-Authenticate: Digest realm=\"pbx\"\r\n\r\n";
+        let reply = parse_response(challenge).unwrap();
+        assert_eq!((reply.status_code, reply.reason.as_str()), (401, "Unauthorized"));
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n\r\n").is_err());
+
+        assert_eq!(contact_host("<sip:1001@10.0.0.20:5060;transport=udp>"), Some("10.0.0.20:5060"));
+        assert_eq!(contact_host("sip:10.0.0.21"), Some("10.0.0.21"));
+    }
+}
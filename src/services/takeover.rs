@@ -0,0 +1,291 @@
+//! Manual call takeover for NOC intervention
+//!
+//! An operator can move one leg of a live call to a new target, for example
+//! to pull a call off a degraded trunk. The gateway sends an offerless INVITE
+//! to the new target carrying a Replaces header for the leg being moved; once
+//! it answers the old leg is released and the other leg never notices. Each
+//! request and its outcome is written to the audit trail.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::TakeoverConfig;
+use crate::protocols::sip::SipSession;
+use crate::services::b2bua::CallLeg;
+
+/// Management API path for a takeover of `call_id`
+pub fn takeover_path(call_id: &str) -> String {
+    format!("/api/v1/b2bua/calls/{}/takeover", call_id)
+}
+
+/// Operator request to move one leg of a live call
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TakeoverRequest {
+    pub call_id: String,
+    pub leg: CallLeg,
+    /// Host of the new target, or a full SIP URI
+    pub target: String,
+    pub operator: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TakeoverOutcome {
+    Pending,
+    Completed,
+    Failed { reason: String },
+}
+
+/// Audit record of one takeover request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TakeoverRecord {
+    pub id: String,
+    pub request: TakeoverRequest,
+    pub previous_target: Option<String>,
+    pub previous_session_id: Option<String>,
+    pub new_session_id: Option<String>,
+    /// Request URI of the INVITE sent to the new target
+    pub destination_uri: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub outcome: TakeoverOutcome,
+}
+
+struct PendingTakeover {
+    record_id: String,
+    started: Instant,
+}
+
+/// Tracks takeovers in progress and keeps their audit trail
+pub struct CallTakeover {
+    config: TakeoverConfig,
+    records: DashMap<String, TakeoverRecord>,
+    /// New leg session ID to the takeover waiting on it
+    pending: DashMap<String, PendingTakeover>,
+}
+
+impl CallTakeover {
+    pub fn new(config: TakeoverConfig) -> Self {
+        Self {
+            config,
+            records: DashMap::new(),
+            pending: DashMap::new(),
+        }
+    }
+
+    /// Whether a takeover of `call_id` is already waiting for its new leg
+    pub fn in_progress(&self, call_id: &str) -> bool {
+        self.pending.iter().any(|entry| {
+            self.records
+                .get(&entry.value().record_id)
+                .is_some_and(|record| record.request.call_id == call_id)
+        })
+    }
+
+    pub fn is_pending(&self, session_id: &str) -> bool {
+        self.pending.contains_key(session_id)
+    }
+
+    /// Record a takeover whose INVITE went out on `new_session_id`
+    pub fn begin(
+        &self,
+        request: TakeoverRequest,
+        previous_target: Option<String>,
+        previous_session_id: Option<String>,
+        new_session_id: String,
+        destination_uri: String,
+    ) -> TakeoverRecord {
+        let record = TakeoverRecord {
+            id: Uuid::new_v4().to_string(),
+            request,
+            previous_target,
+            previous_session_id,
+            new_session_id: Some(new_session_id.clone()),
+            destination_uri: Some(destination_uri),
+            requested_at: Utc::now(),
+            finished_at: None,
+            outcome: TakeoverOutcome::Pending,
+        };
+        self.pending.insert(new_session_id, PendingTakeover {
+            record_id: record.id.clone(),
+            started: Instant::now(),
+        });
+        self.store(record)
+    }
+
+    /// Record a request refused before any INVITE was sent
+    pub fn refuse(&self, request: TakeoverRequest, reason: String) -> TakeoverRecord {
+        let now = Utc::now();
+        self.store(TakeoverRecord {
+            id: Uuid::new_v4().to_string(),
+            request,
+            previous_target: None,
+            previous_session_id: None,
+            new_session_id: None,
+            destination_uri: None,
+            requested_at: now,
+            finished_at: Some(now),
+            outcome: TakeoverOutcome::Failed { reason },
+        })
+    }
+
+    /// The new leg answered
+    pub fn complete(&self, session_id: &str) -> Option<TakeoverRecord> {
+        self.finish(session_id, TakeoverOutcome::Completed)
+    }
+
+    /// The new leg was refused or went away before answering
+    pub fn fail(&self, session_id: &str, reason: String) -> Option<TakeoverRecord> {
+        self.finish(session_id, TakeoverOutcome::Failed { reason })
+    }
+
+    /// Abandon takeovers whose new leg has not answered in time
+    pub fn expire(&self, now: Instant) -> Vec<TakeoverRecord> {
+        let timeout = Duration::from_secs(self.config.answer_timeout_secs);
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|entry| now.duration_since(entry.value().started) >= timeout)
+            .map(|entry| entry.key().clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|session_id| self.fail(&session_id, format!("New leg not answered within {:?}", timeout)))
+            .collect()
+    }
+
+    /// Takeover records, most recent first
+    pub fn history(&self) -> Vec<TakeoverRecord> {
+        let mut records: Vec<TakeoverRecord> = self.records.iter().map(|entry| entry.value().clone()).collect();
+        records.sort_by(|a, b| b.requested_at.cmp(&a.requested_at));
+        records
+    }
+
+    fn finish(&self, session_id: &str, outcome: TakeoverOutcome) -> Option<TakeoverRecord> {
+        let (_, pending) = self.pending.remove(session_id)?;
+        let mut record = self.records.get(&pending.record_id)?.clone();
+        record.finished_at = Some(Utc::now());
+        record.outcome = outcome;
+        Some(self.store(record))
+    }
+
+    fn store(&self, record: TakeoverRecord) -> TakeoverRecord {
+        self.audit(&record);
+        self.records.insert(record.id.clone(), record.clone());
+        record
+    }
+
+    fn audit(&self, record: &TakeoverRecord) {
+        info!(
+            target: "audit",
+            "Call takeover {} of call {} leg {:?} to {} by {}: {:?}",
+            record.id,
+            record.request.call_id,
+            record.request.leg,
+            record.request.target,
+            record.request.operator,
+            record.outcome
+        );
+
+        let Some(path) = &self.config.audit_log else {
+            return;
+        };
+        let written = serde_json::to_string(record).map_err(std::io::Error::from).and_then(|line| {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)
+        });
+        if let Err(e) = written {
+            warn!("Failed to write takeover audit record to {}: {}", path, e);
+        }
+    }
+}
+
+/// Replaces header (RFC 3891) identifying the dialog of `session`
+pub fn replaces_header(session: &SipSession) -> (String, String) {
+    let mut value = session.call_id.clone();
+    if let Some(remote_tag) = &session.remote_tag {
+        value.push_str(&format!(";to-tag={}", remote_tag));
+    }
+    value.push_str(&format!(";from-tag={}", session.local_tag));
+    ("Replaces".to_string(), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> TakeoverRequest {
+        TakeoverRequest {
+            call_id: "call-1".to_string(),
+            leg: CallLeg::B,
+            target: "backup.carrier.example.com".to_string(),
+            operator: "noc-jdoe".to_string(),
+            reason: Some("Trunk degraded".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_takeover_lifecycle_is_audited() {
+        let path = std::env::temp_dir().join(format!("takeover-audit-{}.jsonl", Uuid::new_v4()));
+        let takeover = CallTakeover::new(TakeoverConfig {
+            enabled: true,
+            answer_timeout_secs: 30,
+            audit_log: Some(path.to_string_lossy().into_owned()),
+        });
+
+        let record = takeover.begin(
+            request(),
+            Some("primary.carrier.example.com".to_string()),
+            Some("leg-b".to_string()),
+            "leg-b2".to_string(),
+            "sip:5551234@backup.carrier.example.com".to_string(),
+        );
+        assert_eq!(record.outcome, TakeoverOutcome::Pending);
+        assert!(takeover.in_progress("call-1"));
+        assert!(takeover.is_pending("leg-b2"));
+
+        let done = takeover.complete("leg-b2").unwrap();
+        assert_eq!(done.outcome, TakeoverOutcome::Completed);
+        assert!(done.finished_at.is_some());
+        assert!(!takeover.in_progress("call-1"));
+        assert!(takeover.complete("leg-b2").is_none());
+
+        let lines: Vec<TakeoverRecord> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].outcome, TakeoverOutcome::Pending);
+        assert_eq!(lines[1].outcome, TakeoverOutcome::Completed);
+        assert_eq!(lines[1].request.operator, "noc-jdoe");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_unanswered_takeover_expires() {
+        let takeover = CallTakeover::new(TakeoverConfig {
+            enabled: true,
+            answer_timeout_secs: 5,
+            audit_log: None,
+        });
+        takeover.begin(request(), None, Some("leg-b".to_string()), "leg-b2".to_string(), "sip:5551234@backup.carrier.example.com".to_string());
+
+        assert!(takeover.expire(Instant::now()).is_empty());
+        let expired = takeover.expire(Instant::now() + Duration::from_secs(5));
+        assert_eq!(expired.len(), 1);
+        assert!(matches!(expired[0].outcome, TakeoverOutcome::Failed { .. }));
+        assert!(!takeover.is_pending("leg-b2"));
+        assert_eq!(takeover.history().len(), 1);
+    }
+}
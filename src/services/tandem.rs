@@ -0,0 +1,700 @@
+//! TDM-to-TDM tandem switching
+//!
+//! This module routes calls arriving on one TDM span out another TDM span
+//! without creating SIP legs. Bearer channels are cross-connected directly
+//! at the timeslot level so media never hairpins through RTP.
+//!
+//! The service tracks calls and channels; the gateway does the signaling it
+//! asks for in [`TandemEvent`]s, placing the egress call on `CallRouted`,
+//! answering the ingress on `CrossConnected` and clearing the remaining
+//! channels on `CallReleased` and `CallRejected`.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use regex::Regex;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::config::{CdrCustomField, ChannelHunt, ChannelType, FreeTdmSpan, TandemConfig, TandemRoute};
+use crate::services::cdr::{extract_custom_fields, CdrService, DisconnectReason};
+use crate::services::ports::PortDirectory;
+use crate::services::trunk_failure::{
+    span_trunk_name, FailureDisposition, TrunkFailureEvent, TrunkFailureHandler, CAUSE_NETWORK_OUT_OF_ORDER,
+    CAUSE_RECOVERY_ON_TIMER_EXPIRY,
+};
+use crate::utils::ClockStamp;
+use crate::{Error, Result};
+
+/// Q.850 cause used when no egress channel is available
+pub const CAUSE_NO_CIRCUIT_AVAILABLE: u16 = 34;
+
+/// Span/channel pair identifying a single bearer timeslot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TdmChannel {
+    pub span_id: u32,
+    pub channel_id: u8,
+}
+
+impl TdmChannel {
+    pub fn new(span_id: u32, channel_id: u8) -> Self {
+        Self { span_id, channel_id }
+    }
+}
+
+impl std::fmt::Display for TdmChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.span_id, self.channel_id)
+    }
+}
+
+/// Tandem call state
+#[derive(Debug, Clone, PartialEq)]
+pub enum TandemCallState {
+    Proceeding,
+    Connected,
+    Released,
+}
+
+/// Tandem call between two TDM channels
+#[derive(Debug, Clone)]
+pub struct TandemCall {
+    pub id: String,
+    pub route_id: String,
+    pub ingress: TdmChannel,
+    pub egress: TdmChannel,
+    pub calling_number: Option<String>,
+    pub called_number: String,
+    pub translated_number: String,
+    pub state: TandemCallState,
+    pub cdr_id: Option<String>,
+    pub created_at: Instant,
+    pub connected_at: Option<Instant>,
+}
+
+/// Tandem switching events
+#[derive(Debug, Clone)]
+pub enum TandemEvent {
+    CallRouted {
+        call_id: String,
+        route_id: String,
+        ingress: TdmChannel,
+        egress: TdmChannel,
+        translated_number: String,
+    },
+    CrossConnected {
+        call_id: String,
+        ingress: TdmChannel,
+        egress: TdmChannel,
+    },
+    /// The gateway clears every channel of the call except `hung_up`
+    CallReleased {
+        call_id: String,
+        cause: u16,
+        ingress: TdmChannel,
+        egress: TdmChannel,
+        /// Channel whose hangup released the call; `None` when the gateway did
+        hung_up: Option<TdmChannel>,
+    },
+    /// An inbound call matching a tandem route could not be routed
+    CallRejected {
+        ingress: TdmChannel,
+        cause: u16,
+    },
+    NoChannelAvailable {
+        route_id: String,
+        ingress: TdmChannel,
+    },
+    SpanFailure {
+        event: TrunkFailureEvent,
+    },
+    Error {
+        call_id: Option<String>,
+        message: String,
+    },
+}
+
+struct CompiledRoute {
+    route: TandemRoute,
+    pattern: Regex,
+}
+
+/// Tandem switching service
+pub struct TandemService {
+    config: TandemConfig,
+    routes: Vec<CompiledRoute>,
+    span_channels: Arc<DashMap<u32, Vec<u8>>>,
+    busy_channels: Arc<DashMap<TdmChannel, String>>,
+    calls: Arc<DashMap<String, TandemCall>>,
+    cross_connects: Arc<DashMap<TdmChannel, TdmChannel>>,
+    cdr_service: Option<Arc<CdrService>>,
+    custom_fields: Vec<CdrCustomField>,
+    ports: Option<Arc<PortDirectory>>,
+    span_failure: Arc<TrunkFailureHandler>,
+    span_failure_rx: Mutex<Option<mpsc::UnboundedReceiver<TrunkFailureEvent>>>,
+    event_tx: mpsc::UnboundedSender<TandemEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<TandemEvent>>,
+}
+
+impl TandemService {
+    pub fn new(config: TandemConfig, spans: &[FreeTdmSpan]) -> Result<Self> {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        let mut routes = Vec::with_capacity(config.routes.len());
+        for route in &config.routes {
+            let pattern = Regex::new(&route.pattern).map_err(|e| {
+                Error::invalid_config(format!("Invalid tandem route pattern '{}': {}", route.pattern, e))
+            })?;
+            routes.push(CompiledRoute { route: route.clone(), pattern });
+        }
+        routes.sort_by_key(|r| r.route.priority);
+
+        let span_channels = DashMap::new();
+        for span in spans {
+            span_channels.insert(span.span_id, bearer_channels(span));
+        }
+
+        info!("Created tandem service with {} routes across {} spans",
+            routes.len(), span_channels.len());
+
+        let mut span_failure = TrunkFailureHandler::new(config.span_failure.clone());
+        let span_failure_rx = span_failure.take_event_receiver();
+
+        Ok(Self {
+            config,
+            routes,
+            span_channels: Arc::new(span_channels),
+            busy_channels: Arc::new(DashMap::new()),
+            calls: Arc::new(DashMap::new()),
+            cross_connects: Arc::new(DashMap::new()),
+            cdr_service: None,
+            custom_fields: Vec::new(),
+            ports: None,
+            span_failure: Arc::new(span_failure),
+            span_failure_rx: Mutex::new(span_failure_rx),
+            event_tx,
+            event_rx: Some(event_rx),
+        })
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TandemEvent>> {
+        self.event_rx.take()
+    }
+
+    pub fn set_cdr_service(&mut self, cdr_service: Arc<CdrService>) {
+        self.cdr_service = Some(cdr_service);
+    }
+
+    /// Custom CDR fields to fill from the SETUP of each call
+    pub fn set_cdr_custom_fields(&mut self, fields: Vec<CdrCustomField>) {
+        self.custom_fields = fields;
+    }
+
+    /// Record the custom fields of the SETUP that offered `call_id` on its
+    /// CDR. Tandem calls carry no SIP headers, so only user-user fields apply
+    pub async fn record_setup_fields(&self, call_id: &str, user_user: Option<&[u8]>) -> Result<()> {
+        let cdr_id = self.calls.get(call_id).and_then(|call| call.cdr_id.clone());
+        if let (Some(cdr), Some(cdr_id)) = (&self.cdr_service, cdr_id) {
+            let fields = extract_custom_fields(&self.custom_fields, &[], user_user);
+            if !fields.is_empty() {
+                cdr.set_custom_fields(&cdr_id, fields).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_port_directory(&mut self, ports: Arc<PortDirectory>) {
+        self.ports = Some(ports);
+    }
+
+    /// Channel with its provisioned description, for CDRs and logs
+    fn port_label(&self, channel: TdmChannel) -> String {
+        match &self.ports {
+            Some(ports) => ports.channel_label(channel.span_id, channel.channel_id),
+            None => format!("span {} channel {}", channel.span_id, channel.channel_id),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Make a hot-inserted span available to tandem routes
+    pub fn add_span(&self, span: &FreeTdmSpan) {
+        self.span_channels.insert(span.span_id, bearer_channels(span));
+    }
+
+    /// Withdraw a span whose hardware was removed; its calls must already
+    /// have been handled by `handle_span_down`
+    pub fn remove_span(&self, span_id: u32) {
+        self.span_channels.remove(&span_id);
+    }
+
+    /// Find the first tandem route matching an inbound call
+    pub fn match_route(&self, ingress_span: u32, called_number: &str) -> Option<&TandemRoute> {
+        self.routes
+            .iter()
+            .filter(|r| r.route.ingress_spans.is_empty() || r.route.ingress_spans.contains(&ingress_span))
+            .find(|r| r.pattern.is_match(called_number))
+            .map(|r| &r.route)
+    }
+
+    /// Route an inbound TDM call. Returns `Ok(None)` when no tandem route
+    /// matches and the call should continue to the SIP side.
+    pub async fn handle_incoming_call(
+        &self,
+        span_id: u32,
+        channel_id: u8,
+        calling_number: Option<String>,
+        called_number: &str,
+    ) -> Result<Option<TandemCall>> {
+        if !self.config.enabled {
+            return Ok(None);
+        }
+
+        let ingress = TdmChannel::new(span_id, channel_id);
+        let route = match self.match_route(span_id, called_number) {
+            Some(route) => route.clone(),
+            None => return Ok(None),
+        };
+
+        let translated_number = route.translation
+            .as_ref()
+            .map(|t| t.apply(called_number))
+            .unwrap_or_else(|| called_number.to_string());
+
+        let call_id = Uuid::new_v4().to_string();
+        self.busy_channels.insert(ingress, call_id.clone());
+
+        let egress = match self.seize_egress_channel(&route, &call_id) {
+            Some(egress) => egress,
+            None => {
+                self.busy_channels.remove(&ingress);
+                warn!("No egress channel available for tandem route {} from {}", route.id, self.port_label(ingress));
+                let _ = self.event_tx.send(TandemEvent::NoChannelAvailable {
+                    route_id: route.id.clone(),
+                    ingress,
+                });
+                return Err(Error::resource_exhausted(format!(
+                    "No circuit available on tandem route {} (cause {})",
+                    route.id, CAUSE_NO_CIRCUIT_AVAILABLE
+                )));
+            }
+        };
+
+        let cdr_id = match &self.cdr_service {
+            Some(cdr) => Some(cdr.start_tandem_call_record(
+                &call_id,
+                calling_number.as_deref().unwrap_or(""),
+                called_number,
+                &translated_number,
+                &route.id,
+                egress.span_id,
+                self.port_label(ingress),
+                self.port_label(egress),
+            ).await?),
+            None => None,
+        };
+
+        let call = TandemCall {
+            id: call_id.clone(),
+            route_id: route.id.clone(),
+            ingress,
+            egress,
+            calling_number,
+            called_number: called_number.to_string(),
+            translated_number: translated_number.clone(),
+            state: TandemCallState::Proceeding,
+            cdr_id,
+            created_at: Instant::now(),
+            connected_at: None,
+        };
+        self.calls.insert(call_id.clone(), call.clone());
+
+        let _ = self.event_tx.send(TandemEvent::CallRouted {
+            call_id: call_id.clone(),
+            route_id: route.id.clone(),
+            ingress,
+            egress,
+            translated_number: translated_number.clone(),
+        });
+
+        info!("Tandem call {} routed {} -> {} via {} ({} -> {})",
+            call_id, ingress, egress, route.id, called_number, translated_number);
+
+        Ok(Some(call))
+    }
+
+    /// Handle answer from the egress side: cross-connect bearers and start
+    /// billing. The gateway answers the ingress on `CrossConnected`, which
+    /// comes back here and is ignored.
+    pub async fn handle_answer(&self, span_id: u32, channel_id: u8) -> Result<()> {
+        let channel = TdmChannel::new(span_id, channel_id);
+        let call_id = match self.find_call(channel) {
+            Some(call_id) => call_id,
+            None => return Ok(()),
+        };
+
+        let (ingress, egress, cdr_id) = {
+            let mut call = self.calls.get_mut(&call_id)
+                .ok_or_else(|| Error::protocol("Answer for an unknown tandem call"))?;
+            if call.state != TandemCallState::Proceeding || call.egress != channel {
+                return Ok(());
+            }
+            call.state = TandemCallState::Connected;
+            call.connected_at = Some(Instant::now());
+            (call.ingress, call.egress, call.cdr_id.clone())
+        };
+
+        self.cross_connects.insert(ingress, egress);
+        self.cross_connects.insert(egress, ingress);
+
+        if let (Some(cdr), Some(cdr_id)) = (&self.cdr_service, cdr_id) {
+            cdr.update_call_answered(&cdr_id).await?;
+        }
+
+        let _ = self.event_tx.send(TandemEvent::CrossConnected {
+            call_id: call_id.clone(),
+            ingress,
+            egress,
+        });
+
+        debug!("Tandem call {} cross-connected {} <-> {}", call_id, ingress, egress);
+        Ok(())
+    }
+
+    /// Handle a hangup from either side and release both channels
+    pub async fn handle_hangup(&self, span_id: u32, channel_id: u8, cause: u16) -> Result<()> {
+        let channel = TdmChannel::new(span_id, channel_id);
+        let call_id = match self.find_call(channel) {
+            Some(call_id) => call_id,
+            None => return Ok(()),
+        };
+
+        // Signaling loss on the failed span must not tear down a preserved call
+        if self.span_failure.preserved_trunk(&call_id) == Some(span_trunk_name(span_id)) {
+            debug!("Ignoring hangup on {}/{} for preserved tandem call {}", span_id, channel_id, call_id);
+            return Ok(());
+        }
+
+        self.release_call(&call_id, cause, Some(channel)).await
+    }
+
+    /// Release a call the gateway could not signal, clearing both sides
+    pub async fn fail_call(&self, call_id: &str, cause: u16) -> Result<()> {
+        self.release_call(call_id, cause, None).await
+    }
+
+    /// Have the gateway clear an inbound call that matched a tandem route
+    /// but could not be routed
+    pub fn reject_call(&self, ingress: TdmChannel, cause: u16) {
+        let _ = self.event_tx.send(TandemEvent::CallRejected { ingress, cause });
+    }
+
+    /// Release a tandem call with the given Q.850 cause
+    async fn release_call(&self, call_id: &str, cause: u16, hung_up: Option<TdmChannel>) -> Result<()> {
+        self.span_failure.forget(call_id);
+
+        if let Some((_, mut call)) = self.calls.remove(call_id) {
+            call.state = TandemCallState::Released;
+
+            self.cross_connects.remove(&call.ingress);
+            self.cross_connects.remove(&call.egress);
+            self.busy_channels.remove(&call.ingress);
+            self.busy_channels.remove(&call.egress);
+
+            if let (Some(cdr), Some(cdr_id)) = (&self.cdr_service, &call.cdr_id) {
+                cdr.finalize_call_record(cdr_id, ClockStamp::now(), DisconnectReason::from_q850(cause)).await?;
+            }
+
+            let _ = self.event_tx.send(TandemEvent::CallReleased {
+                call_id: call_id.to_string(),
+                cause,
+                ingress: call.ingress,
+                egress: call.egress,
+                hung_up,
+            });
+
+            info!("Tandem call {} released (cause {})", call_id, cause);
+        }
+
+        Ok(())
+    }
+
+    /// Apply the span failure policy to tandem calls crossing a failed span.
+    ///
+    /// Calls still proceeding are released; connected calls keep their
+    /// cross-connect when media preservation is configured.
+    pub async fn handle_span_down(&self, span_id: u32) -> Result<FailureDisposition> {
+        let mut connected = Vec::new();
+        let mut proceeding = Vec::new();
+        for entry in self.calls.iter() {
+            let call = entry.value();
+            if call.ingress.span_id != span_id && call.egress.span_id != span_id {
+                continue;
+            }
+            if call.state == TandemCallState::Connected {
+                connected.push(call.id.clone());
+            } else {
+                proceeding.push(call.id.clone());
+            }
+        }
+
+        for call_id in &proceeding {
+            self.release_call(call_id, CAUSE_NETWORK_OUT_OF_ORDER, None).await?;
+        }
+
+        let disposition = self.span_failure.on_trunk_down(&span_trunk_name(span_id), &connected, Instant::now());
+        if let FailureDisposition::Release { cause } = disposition {
+            for call_id in &connected {
+                self.release_call(call_id, cause, None).await?;
+            }
+        }
+        Ok(disposition)
+    }
+
+    /// Recover calls preserved across an outage of this span
+    pub fn handle_span_up(&self, span_id: u32) -> Vec<String> {
+        self.span_failure.on_trunk_up(&span_trunk_name(span_id), Instant::now())
+    }
+
+    /// Release preserved calls whose span did not come back in time
+    pub async fn poll_span_failures(&self) -> Result<()> {
+        let poll = self.span_failure.poll(Instant::now());
+        for preserved in poll.expired {
+            self.release_call(&preserved.call_id, CAUSE_RECOVERY_ON_TIMER_EXPIRY, None).await?;
+        }
+        // TDM signaling is re-established by the span itself; attempts are only reported
+        for preserved in poll.reestablish {
+            debug!("Tandem call {} waiting for {} (attempt {})",
+                preserved.call_id, preserved.trunk, preserved.attempts);
+        }
+        Ok(())
+    }
+
+    /// Spawn the task that expires preserved calls and forwards span failure events
+    pub fn spawn_failure_monitor(self: &Arc<Self>) -> JoinHandle<()> {
+        let service = Arc::clone(self);
+        let mut failure_rx = self.span_failure_rx.lock().ok().and_then(|mut rx| rx.take());
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_secs(1));
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        if let Err(e) = service.poll_span_failures().await {
+                            warn!("Failed to expire preserved tandem calls: {}", e);
+                        }
+                    }
+                    Some(event) = async {
+                        match failure_rx.as_mut() {
+                            Some(rx) => rx.recv().await,
+                            None => std::future::pending().await,
+                        }
+                    } => {
+                        let _ = service.event_tx.send(TandemEvent::SpanFailure { event });
+                    }
+                }
+            }
+        })
+    }
+
+    /// Peer timeslot for a cross-connected channel, used by the TDM frame path
+    pub fn cross_connect_peer(&self, span_id: u32, channel_id: u8) -> Option<TdmChannel> {
+        self.cross_connects
+            .get(&TdmChannel::new(span_id, channel_id))
+            .map(|entry| *entry.value())
+    }
+
+    pub fn get_call(&self, call_id: &str) -> Option<TandemCall> {
+        self.calls.get(call_id).map(|entry| entry.value().clone())
+    }
+
+    pub fn get_active_calls(&self) -> Vec<TandemCall> {
+        self.calls.iter().map(|entry| entry.value().clone()).collect()
+    }
+
+    pub fn get_active_call_count(&self) -> usize {
+        self.calls.len()
+    }
+
+    fn find_call(&self, channel: TdmChannel) -> Option<String> {
+        self.busy_channels.get(&channel).map(|entry| entry.value().clone())
+    }
+
+    fn seize_egress_channel(&self, route: &TandemRoute, call_id: &str) -> Option<TdmChannel> {
+        for span_id in &route.egress_spans {
+            let channels = match self.span_channels.get(span_id) {
+                Some(channels) => channels.value().clone(),
+                None => continue,
+            };
+
+            let ordered: Vec<u8> = match self.config.channel_hunt {
+                ChannelHunt::Ascending => channels.iter().copied().collect::<BTreeSet<_>>().into_iter().collect(),
+                ChannelHunt::Descending => channels.iter().copied().collect::<BTreeSet<_>>().into_iter().rev().collect(),
+            };
+
+            for channel_id in ordered {
+                let candidate = TdmChannel::new(*span_id, channel_id);
+                if let dashmap::mapref::entry::Entry::Vacant(entry) = self.busy_channels.entry(candidate) {
+                    entry.insert(call_id.to_string());
+                    return Some(candidate);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Enabled bearer channels of a span, in configured order
+fn bearer_channels(span: &FreeTdmSpan) -> Vec<u8> {
+    span.channels
+        .iter()
+        .filter(|ch| ch.enabled && matches!(ch.channel_type, ChannelType::BChannel))
+        .map(|ch| ch.id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FreeTdmChannel, Layer1Type, NumberTranslation, SignalingType, TrunkFailureAction};
+
+    fn span(span_id: u32, channels: u8) -> FreeTdmSpan {
+        FreeTdmSpan {
+            span_id,
+            name: format!("span{}", span_id),
+            trunk_type: Layer1Type::E1,
+            d_channel: 16,
+            channels: (1..=channels)
+                .map(|id| FreeTdmChannel {
+                    id,
+                    channel_type: ChannelType::BChannel,
+                    enabled: true,
+                    signaling: SignalingType::Pri,
+                    description: Default::default(),
+                })
+                .collect(),
+            backend: "freetdm".to_string(),
+            description: Default::default(),
+            regulatory_pack: None,
+        }
+    }
+
+    fn config() -> TandemConfig {
+        TandemConfig {
+            enabled: true,
+            channel_hunt: ChannelHunt::Ascending,
+            routes: vec![TandemRoute {
+                id: "span1-to-span3".to_string(),
+                ingress_spans: vec![1],
+                pattern: "^9[0-9]+$".to_string(),
+                egress_spans: vec![3],
+                translation: Some(NumberTranslation {
+                    prefix_strip: Some("9".to_string()),
+                    prefix_add: Some("1".to_string()),
+                    suffix_strip: None,
+                    suffix_add: None,
+                }),
+                priority: 1,
+            }],
+            span_failure: Default::default(),
+            cdr_directory: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tandem_routing_with_translation() {
+        let service = TandemService::new(config(), &[span(1, 2), span(3, 2)]).unwrap();
+
+        let call = service
+            .handle_incoming_call(1, 1, Some("1000".to_string()), "95551234")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(call.egress, TdmChannel::new(3, 1));
+        assert_eq!(call.translated_number, "15551234");
+        assert_eq!(service.get_active_call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_call_falls_through() {
+        let service = TandemService::new(config(), &[span(1, 2), span(3, 2)]).unwrap();
+
+        let result = service.handle_incoming_call(2, 1, None, "95551234").await.unwrap();
+        assert!(result.is_none());
+
+        let result = service.handle_incoming_call(1, 1, None, "5551234").await.unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cross_connect_and_release() {
+        let mut service = TandemService::new(config(), &[span(1, 2), span(3, 1)]).unwrap();
+        let mut events = service.take_event_receiver().unwrap();
+
+        service.handle_incoming_call(1, 1, None, "95551234").await.unwrap().unwrap();
+        let routed = events.try_recv();
+        assert!(matches!(routed, Ok(TandemEvent::CallRouted { egress, .. }) if egress == TdmChannel::new(3, 1)));
+        let blocked = service.handle_incoming_call(1, 2, None, "95551235").await.unwrap_err();
+        assert_eq!(blocked.q850_cause(), CAUSE_NO_CIRCUIT_AVAILABLE);
+        assert!(matches!(events.try_recv(), Ok(TandemEvent::NoChannelAvailable { .. })));
+
+        // Only the egress answering connects the call
+        service.handle_answer(1, 1).await.unwrap();
+        assert_eq!(service.cross_connect_peer(1, 1), None);
+        service.handle_answer(3, 1).await.unwrap();
+        assert_eq!(service.cross_connect_peer(1, 1), Some(TdmChannel::new(3, 1)));
+        assert_eq!(service.cross_connect_peer(3, 1), Some(TdmChannel::new(1, 1)));
+        match events.try_recv() {
+            Ok(TandemEvent::CrossConnected { ingress, .. }) => assert_eq!(ingress, TdmChannel::new(1, 1)),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // The gateway clears the side that did not hang up
+        service.handle_hangup(1, 1, 16).await.unwrap();
+        assert_eq!(service.cross_connect_peer(1, 1), None);
+        assert_eq!(service.get_active_call_count(), 0);
+        match events.try_recv() {
+            Ok(TandemEvent::CallReleased { cause, hung_up, .. }) => {
+                assert_eq!((cause, hung_up), (16, Some(TdmChannel::new(1, 1))));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Egress channel is free again
+        assert!(service.handle_incoming_call(1, 2, None, "95551235").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_span_failure_preserves_connected_calls() {
+        let mut cfg = config();
+        cfg.span_failure.action = TrunkFailureAction::PreserveMedia;
+        let service = TandemService::new(cfg, &[span(1, 2), span(3, 2)]).unwrap();
+
+        service.handle_incoming_call(1, 1, None, "95551234").await.unwrap().unwrap();
+        service.handle_answer(3, 1).await.unwrap();
+        service.handle_incoming_call(1, 2, None, "95551235").await.unwrap().unwrap();
+
+        let disposition = service.handle_span_down(3).await.unwrap();
+        assert!(matches!(disposition, FailureDisposition::Preserve { .. }));
+        // The unanswered call is released, the connected one keeps its bearer path
+        assert_eq!(service.get_active_call_count(), 1);
+        assert_eq!(service.cross_connect_peer(1, 1), Some(TdmChannel::new(3, 1)));
+
+        // Hangup reported by the failed span is ignored while preserved
+        service.handle_hangup(3, 1, 38).await.unwrap();
+        assert_eq!(service.get_active_call_count(), 1);
+
+        assert_eq!(service.handle_span_up(3).len(), 1);
+        service.handle_hangup(3, 1, 16).await.unwrap();
+        assert_eq!(service.get_active_call_count(), 0);
+    }
+}
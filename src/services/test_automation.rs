@@ -14,6 +14,7 @@ use uuid::Uuid;
 use crate::services::interface_testing::{
     InterfaceTestingService, TestPattern, InterfaceTestType, InterfaceTestResult
 };
+use crate::utils::ClockStamp;
 use crate::{Error, Result};
 
 /// Test scenario definitions
@@ -599,6 +600,7 @@ impl TestAutomationService {
         test_steps: Vec<CustomTestStep>,
     ) -> Result<SessionSummary> {
         info!("Executing test session {}", session_id);
+        let started = ClockStamp::now();
         
         // Update session status
         {
@@ -733,12 +735,13 @@ impl TestAutomationService {
             recommendations.push("Majority of tests failed - system requires significant troubleshooting".to_string());
         }
 
+        let finished = started.map(&ClockStamp::now());
         let summary = SessionSummary {
             session_id,
             scenario_name: "Automated Test Session".to_string(),
-            start_time: Utc::now() - Duration::from_secs(600), // Approximate
-            end_time: Utc::now(),
-            duration: Duration::from_secs(600), // Approximate
+            start_time: started.utc,
+            end_time: finished.utc,
+            duration: finished.duration_since(&started),
             total_tests: test_steps.len(),
             passed_tests,
             failed_tests,
@@ -768,7 +771,7 @@ impl TestAutomationService {
             let active_tests = self.interface_testing.get_active_tests().await;
             if !active_tests.contains(&test_id) {
                 // Test completed but no result - might be an error
-                return Err(Error::internal("Test completed but no result available"));
+                return Err(Error::invalid_state("Test completed but no result available"));
             }
         }
     }
@@ -0,0 +1,547 @@
+//! Test numbers answered inside the gateway
+//!
+//! Calls to a configured test number never leave the gateway: it answers
+//! with its own media session and plays a test pattern, so a new trunk's
+//! media path can be verified without the far-end carrier's help. The echo
+//! number loops the caller's audio back and, once RTCP reports from the
+//! caller give a round-trip time, announces it from the prompt packs. The
+//! milliwatt number plays a 1 kHz tone at 0 dBm0 and the silence number
+//! plays PCMU silence. Calls still up after the configured limit, or whose
+//! media fails, are hung up with a BYE. Active and recent test calls are
+//! listed through the management API with the packets exchanged and the
+//! delay measured.
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+
+use crate::config::{TestNumberKind, TestNumbersConfig};
+use crate::protocols::rtp::{RtcpPacketType, RtpPacket};
+use crate::protocols::sdp::SessionDescription;
+use crate::protocols::sip::DialogHangup;
+use crate::services::canary::DIGITAL_MILLIWATT;
+use crate::services::media_interfaces::ResolvedInterface;
+use crate::services::prompts::{read_pcmu, PromptLibrary};
+use crate::{Error, Result};
+
+/// Management API path listing active and recent test calls
+pub const TEST_CALLS_PATH: &str = "/api/v1/test-calls";
+
+/// Prompt introducing the measured delay, e.g. "Round trip delay is"
+pub const LATENCY_PROMPT: &str = "echo-latency";
+/// Prompt following the spoken number
+pub const MILLISECONDS_PROMPT: &str = "milliseconds";
+
+const PCMU: u8 = 0;
+const PCMU_SILENCE: u8 = 0xff;
+const PACKET_INTERVAL: Duration = Duration::from_millis(20);
+const SAMPLES_PER_PACKET: usize = 160;
+const SENDER_REPORT_INTERVAL: Duration = Duration::from_secs(2);
+/// Seconds between the NTP era (1900) and the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// One call to a test number
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TestCallRecord {
+    pub session_id: String,
+    pub number: String,
+    pub kind: TestNumberKind,
+    pub caller: String,
+    pub started_at: DateTime<Utc>,
+    /// `None` while the call is up
+    pub ended_at: Option<DateTime<Utc>>,
+    pub packets_received: u64,
+    pub packets_sent: u64,
+    /// Latest round trip from the caller's RTCP reports
+    pub round_trip_ms: Option<u32>,
+    pub latency_announced: bool,
+}
+
+struct ActiveTestCall {
+    record: Arc<Mutex<TestCallRecord>>,
+    task: JoinHandle<()>,
+}
+
+/// Where and how the caller receives media
+struct MediaPath {
+    rtp: UdpSocket,
+    rtcp: Option<UdpSocket>,
+    remote_rtp: SocketAddr,
+    remote_rtcp: SocketAddr,
+}
+
+/// Current time as a 64-bit NTP timestamp
+fn ntp_now() -> (u32, u32) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = (now.as_secs() + NTP_UNIX_OFFSET) as u32;
+    let fraction = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds, fraction as u32)
+}
+
+/// Middle 32 bits of an NTP timestamp, the unit of RTCP LSR and DLSR
+fn ntp_middle((seconds, fraction): (u32, u32)) -> u32 {
+    (seconds << 16) | (fraction >> 16)
+}
+
+fn sender_report(ssrc: u32, ntp: (u32, u32), rtp_timestamp: u32, packets: u32, octets: u32) -> Vec<u8> {
+    let mut report = vec![0x80, RtcpPacketType::SenderReport as u8, 0, 6];
+    for word in [ssrc, ntp.0, ntp.1, rtp_timestamp, packets, octets] {
+        report.extend_from_slice(&word.to_be_bytes());
+    }
+    report
+}
+
+/// Round trip in milliseconds from the report blocks about `ssrc` in a
+/// compound RTCP packet, received when the middle NTP time was `arrival`
+fn round_trip_ms(packet: &[u8], ssrc: u32, arrival: u32) -> Option<u32> {
+    let mut pos = 0;
+    while pos + 4 <= packet.len() {
+        let report_count = (packet[pos] & 0x1f) as usize;
+        let packet_type = packet[pos + 1];
+        let len = (u16::from_be_bytes([packet[pos + 2], packet[pos + 3]]) as usize + 1) * 4;
+        let body = packet.get(pos..pos + len)?;
+        let blocks_at = match packet_type {
+            t if t == RtcpPacketType::SenderReport as u8 => Some(28),
+            t if t == RtcpPacketType::ReceiverReport as u8 => Some(8),
+            _ => None,
+        };
+        if let Some(blocks_at) = blocks_at {
+            for block in body.get(blocks_at..)?.chunks_exact(24).take(report_count) {
+                let word = |at: usize| u32::from_be_bytes([block[at], block[at + 1], block[at + 2], block[at + 3]]);
+                let (last_sr, delay) = (word(16), word(20));
+                if word(0) == ssrc && last_sr != 0 {
+                    let round_trip = arrival.wrapping_sub(last_sr).wrapping_sub(delay);
+                    // Anything over ten seconds is a clock or reporting fault
+                    if round_trip < 10 << 16 {
+                        return Some(((round_trip as u64 * 1000) >> 16) as u32);
+                    }
+                }
+            }
+        }
+        pos += len;
+    }
+    None
+}
+
+fn is_rtcp(packet: &[u8]) -> bool {
+    packet.len() >= 8 && (200..=204).contains(&packet[1])
+}
+
+/// Answers test numbers and runs their media
+pub struct TestNumbers {
+    config: TestNumbersConfig,
+    prompts: Option<Arc<PromptLibrary>>,
+    dialogs: Arc<dyn DialogHangup>,
+    active: DashMap<String, ActiveTestCall>,
+    recent: Mutex<VecDeque<TestCallRecord>>,
+}
+
+impl TestNumbers {
+    pub fn new(config: TestNumbersConfig, prompts: Option<Arc<PromptLibrary>>, dialogs: Arc<dyn DialogHangup>) -> Self {
+        Self {
+            config,
+            prompts,
+            dialogs,
+            active: DashMap::new(),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The kind of test number `callee` is, if it is one
+    pub fn kind_for(&self, callee: &str) -> Option<TestNumberKind> {
+        self.config.numbers.iter().find(|number| number.number == callee).map(|number| number.kind)
+    }
+
+    pub fn is_active(&self, session_id: &str) -> bool {
+        self.active.contains_key(session_id)
+    }
+
+    /// Start the media for a call to a test number and return the SDP answer.
+    /// Only PCMU is offered back; offers without it are refused.
+    pub async fn answer(
+        self: &Arc<Self>,
+        session_id: &str,
+        number: &str,
+        caller: &str,
+        offer: Option<&str>,
+        accept_language: Option<&str>,
+        interface: Option<&ResolvedInterface>,
+    ) -> Result<String> {
+        let kind = self.kind_for(number).ok_or_else(|| Error::routing(format!("{} is not a test number", number)))?;
+        let offer = SessionDescription::parse(offer.ok_or_else(|| Error::not_supported("Test numbers need an SDP offer"))?)?;
+        let stream = offer
+            .audio_streams()
+            .find(|stream| !stream.is_rejected())
+            .ok_or_else(|| Error::not_supported("Offer has no audio stream"))?;
+        if !stream.formats.iter().any(|format| format == "0") {
+            return Err(Error::not_supported("Test numbers only answer PCMU"));
+        }
+        let remote_rtp = offer.audio_endpoint().ok_or_else(|| Error::not_supported("Offer has no audio address"))?;
+        let remote_rtcp = stream
+            .attribute("rtcp")
+            .and_then(|value| value.split_whitespace().next()?.parse().ok())
+            .map_or_else(|| SocketAddr::new(remote_rtp.ip(), remote_rtp.port().wrapping_add(1)), |port| SocketAddr::new(remote_rtp.ip(), port));
+        let rtcp_mux = stream.has_attribute("rtcp-mux");
+
+        let (bind_ip, advertised_ip) = match interface {
+            Some(interface) => (interface.bind_ip, interface.advertised_ip),
+            None => (IpAddr::from([0, 0, 0, 0]), Self::local_address_towards(remote_rtp).await?),
+        };
+        let bind = |port| async move {
+            UdpSocket::bind(SocketAddr::new(bind_ip, port))
+                .await
+                .map_err(|e| Error::network(format!("Test call media socket failed: {}", e)))
+        };
+        let rtp = bind(0).await?;
+        let rtp_port = rtp.local_addr()?.port();
+        let rtcp = if rtcp_mux {
+            None
+        } else {
+            // RTCP conventionally sits on the port above RTP
+            Some(match bind(rtp_port.wrapping_add(1)).await {
+                Ok(socket) => socket,
+                Err(_) => bind(0).await?,
+            })
+        };
+        let rtcp_port = match &rtcp {
+            Some(socket) => socket.local_addr()?.port(),
+            None => rtp_port,
+        };
+
+        let address_type = if advertised_ip.is_ipv4() { "IP4" } else { "IP6" };
+        let mut answer = format!(
+            "v=0\r\no=redfire {} 1 IN {} {}\r\ns=test\r\nc=IN {} {}\r\nt=0 0\r\nm=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=ptime:20\r\n",
+            rand::random::<u32>(), address_type, advertised_ip, address_type, advertised_ip, rtp_port
+        );
+        if rtcp_mux {
+            answer.push_str("a=rtcp-mux\r\n");
+        } else {
+            answer.push_str(&format!("a=rtcp:{}\r\n", rtcp_port));
+        }
+        answer.push_str("a=sendrecv\r\n");
+
+        let record = Arc::new(Mutex::new(TestCallRecord {
+            session_id: session_id.to_string(),
+            number: number.to_string(),
+            kind,
+            caller: caller.to_string(),
+            started_at: Utc::now(),
+            ended_at: None,
+            packets_received: 0,
+            packets_sent: 0,
+            round_trip_ms: None,
+            latency_announced: false,
+        }));
+        let media = MediaPath { rtp, rtcp, remote_rtp, remote_rtcp: if rtcp_mux { remote_rtp } else { remote_rtcp } };
+
+        let this = Arc::clone(self);
+        let task_record = Arc::clone(&record);
+        let task_session = session_id.to_string();
+        let accept_language = accept_language.map(str::to_string);
+        // Media starts once the call is listed, so a failure can always retire it
+        let (start_tx, start_rx) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            if start_rx.await.is_err() {
+                return;
+            }
+            if let Err(e) = this.run_media(kind, media, &task_record, accept_language.as_deref()).await {
+                warn!("Test call {} media failed: {}", task_session, e);
+            } else {
+                info!("Test call {} reached its {}s limit", task_session, this.config.max_duration_secs);
+            }
+            // Retired first so the caller's answering BYE finds no active call
+            this.finish(&task_session);
+            if let Err(e) = this.dialogs.hang_up(&task_session).await {
+                warn!("Failed to hang up test call {}: {}", task_session, e);
+            }
+        });
+        self.active.insert(session_id.to_string(), ActiveTestCall { record, task });
+        let _ = start_tx.send(());
+
+        info!("Answered test number {} ({:?}) for {}", number, kind, caller);
+        Ok(answer)
+    }
+
+    /// The caller hung up
+    pub fn hangup(&self, session_id: &str) -> Option<TestCallRecord> {
+        let (_, call) = self.active.remove(session_id)?;
+        call.task.abort();
+        Some(self.retire(&call.record))
+    }
+
+    /// Active calls, then ended ones most recent first
+    pub fn calls(&self) -> Vec<TestCallRecord> {
+        let mut calls: Vec<TestCallRecord> = self.active.iter().map(|call| call.record.lock().unwrap().clone()).collect();
+        calls.sort_by_key(|call| call.started_at);
+        calls.extend(self.recent.lock().unwrap().iter().cloned());
+        calls
+    }
+
+    fn finish(&self, session_id: &str) {
+        if let Some((_, call)) = self.active.remove(session_id) {
+            self.retire(&call.record);
+        }
+    }
+
+    fn retire(&self, record: &Mutex<TestCallRecord>) -> TestCallRecord {
+        let mut record = record.lock().unwrap();
+        record.ended_at = Some(Utc::now());
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_front(record.clone());
+        recent.truncate(self.config.max_recent);
+        record.clone()
+    }
+
+    /// Source address the host would use to reach `remote`
+    pub(crate) async fn local_address_towards(remote: SocketAddr) -> Result<IpAddr> {
+        let unspecified: IpAddr = if remote.is_ipv4() { [0, 0, 0, 0].into() } else { [0u16; 8].into() };
+        let probe = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+        probe.connect(remote).await?;
+        Ok(probe.local_addr()?.ip())
+    }
+
+    /// Round-trip announcement in the caller's language, as PCMU
+    fn latency_announcement(&self, round_trip_ms: u32, accept_language: Option<&str>) -> Option<Vec<u8>> {
+        let library = self.prompts.as_ref()?;
+        let first = library.select(LATENCY_PROMPT, None, None, accept_language)?;
+        let mut events = vec![LATENCY_PROMPT.to_string()];
+        events.extend(round_trip_ms.to_string().chars().map(|digit| format!("digit-{}", digit)));
+        events.push(MILLISECONDS_PROMPT.to_string());
+
+        let mut audio = Vec::new();
+        for event in events {
+            // Every part comes from the same pack so the voice does not change
+            let prompt = library.lookup(&first.language, &event).filter(|prompt| prompt.language == first.language)?;
+            match read_pcmu(&prompt.path) {
+                Ok(samples) => audio.extend(samples),
+                Err(e) => {
+                    warn!("Cannot play prompt {}: {}", prompt.path.display(), e);
+                    return None;
+                }
+            }
+        }
+        Some(audio)
+    }
+
+    async fn run_media(
+        &self,
+        kind: TestNumberKind,
+        media: MediaPath,
+        record: &Mutex<TestCallRecord>,
+        accept_language: Option<&str>,
+    ) -> Result<()> {
+        let MediaPath { rtp, rtcp, mut remote_rtp, remote_rtcp } = media;
+        let ssrc: u32 = rand::random();
+        let mut sequence: u16 = rand::random();
+        let mut timestamp: u32 = rand::random();
+        let (mut packets_sent, mut octets_sent) = (0u32, 0u32);
+
+        let tone = DIGITAL_MILLIWATT.repeat(SAMPLES_PER_PACKET / DIGITAL_MILLIWATT.len());
+        let silence = vec![PCMU_SILENCE; SAMPLES_PER_PACKET];
+        let mut announcement: VecDeque<u8> = VecDeque::new();
+        let announce_at = tokio::time::Instant::now() + Duration::from_secs(self.config.latency_announcement_secs);
+        let ends_at = tokio::time::Instant::now() + Duration::from_secs(self.config.max_duration_secs);
+
+        let mut ticker = interval(PACKET_INTERVAL);
+        let mut reports = interval(SENDER_REPORT_INTERVAL);
+        let mut rtp_buf = vec![0u8; 2048];
+        let mut rtcp_buf = vec![0u8; 2048];
+
+        loop {
+            // Echoed packets are sent as they arrive; tone, silence and
+            // announcements go out on the packet clock
+            let mut outgoing: Option<(u8, Bytes)> = None;
+            let mut report: Option<Vec<u8>> = None;
+
+            tokio::select! {
+                _ = tokio::time::sleep_until(ends_at) => return Ok(()),
+                _ = ticker.tick() => {
+                    let needs_announcement = kind == TestNumberKind::Echo
+                        && tokio::time::Instant::now() >= announce_at
+                        && !record.lock().unwrap().latency_announced;
+                    if needs_announcement {
+                        let round_trip = record.lock().unwrap().round_trip_ms;
+                        if let Some(round_trip) = round_trip {
+                            match self.latency_announcement(round_trip, accept_language) {
+                                Some(audio) => announcement.extend(audio),
+                                None => debug!("No prompts to announce a {}ms round trip", round_trip),
+                            }
+                            record.lock().unwrap().latency_announced = true;
+                        }
+                    }
+
+                    if !announcement.is_empty() {
+                        let mut frame: Vec<u8> = announcement.drain(..SAMPLES_PER_PACKET.min(announcement.len())).collect();
+                        frame.resize(SAMPLES_PER_PACKET, PCMU_SILENCE);
+                        outgoing = Some((PCMU, Bytes::from(frame)));
+                    } else {
+                        match kind {
+                            TestNumberKind::Milliwatt => outgoing = Some((PCMU, Bytes::from(tone.clone()))),
+                            TestNumberKind::Silence => outgoing = Some((PCMU, Bytes::from(silence.clone()))),
+                            TestNumberKind::Echo => {}
+                        }
+                    }
+                }
+                _ = reports.tick() => {
+                    report = Some(sender_report(ssrc, ntp_now(), timestamp, packets_sent, octets_sent));
+                }
+                received = rtp.recv_from(&mut rtp_buf) => {
+                    let (len, source) = received?;
+                    let arrival = ntp_middle(ntp_now());
+                    if is_rtcp(&rtp_buf[..len]) {
+                        if let Some(round_trip) = round_trip_ms(&rtp_buf[..len], ssrc, arrival) {
+                            record.lock().unwrap().round_trip_ms = Some(round_trip);
+                        }
+                    } else if let Ok(packet) = RtpPacket::decode(Bytes::copy_from_slice(&rtp_buf[..len])) {
+                        // Symmetric RTP: answer wherever the caller sends from
+                        remote_rtp = source;
+                        record.lock().unwrap().packets_received += 1;
+                        if kind == TestNumberKind::Echo && announcement.is_empty() {
+                            outgoing = Some((packet.payload_type, packet.payload));
+                        }
+                    }
+                }
+                received = async { rtcp.as_ref().expect("guarded by is_some").recv_from(&mut rtcp_buf).await }, if rtcp.is_some() => {
+                    let (len, _) = received?;
+                    let arrival = ntp_middle(ntp_now());
+                    if let Some(round_trip) = round_trip_ms(&rtcp_buf[..len], ssrc, arrival) {
+                        record.lock().unwrap().round_trip_ms = Some(round_trip);
+                    }
+                }
+            }
+
+            if let Some((payload_type, payload)) = outgoing {
+                let mut packet = RtpPacket::new(payload_type, sequence, timestamp, ssrc);
+                octets_sent = octets_sent.wrapping_add(payload.len() as u32);
+                packet.payload = payload;
+                rtp.send_to(&packet.encode(), remote_rtp).await?;
+                sequence = sequence.wrapping_add(1);
+                timestamp = timestamp.wrapping_add(SAMPLES_PER_PACKET as u32);
+                packets_sent = packets_sent.wrapping_add(1);
+                record.lock().unwrap().packets_sent += 1;
+            }
+            if let Some(report) = report {
+                let socket = rtcp.as_ref().unwrap_or(&rtp);
+                socket.send_to(&report, remote_rtcp).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TestNumber;
+    use async_trait::async_trait;
+
+    /// Records the dialogs hung up
+    #[derive(Default)]
+    struct Hangups(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl DialogHangup for Hangups {
+        async fn hang_up(&self, session_id: &str) -> Result<()> {
+            self.0.lock().unwrap().push(session_id.to_string());
+            Ok(())
+        }
+    }
+
+    fn test_numbers(max_duration_secs: u64, hangups: Arc<Hangups>) -> Arc<TestNumbers> {
+        Arc::new(TestNumbers::new(
+            TestNumbersConfig {
+                enabled: true,
+                numbers: vec![
+                    TestNumber { number: "9999001".to_string(), kind: TestNumberKind::Echo },
+                    TestNumber { number: "9999002".to_string(), kind: TestNumberKind::Milliwatt },
+                ],
+                max_duration_secs,
+                ..Default::default()
+            },
+            None,
+            hangups,
+        ))
+    }
+
+    fn offer(caller: &UdpSocket) -> String {
+        format!(
+            "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio {} RTP/AVP 8 0\r\n",
+            caller.local_addr().unwrap().port()
+        )
+    }
+
+    #[test]
+    fn test_round_trip_from_receiver_report() {
+        let ssrc = 0x1234_5678;
+        let sent = ntp_middle(ntp_now());
+        // Receiver report from the caller: held our SR for 0.25 s
+        let mut report = vec![0x81, RtcpPacketType::ReceiverReport as u8, 0, 7];
+        report.extend_from_slice(&0xcafe_u32.to_be_bytes());
+        report.extend_from_slice(&ssrc.to_be_bytes());
+        report.extend_from_slice(&[0; 12]);
+        report.extend_from_slice(&sent.to_be_bytes());
+        report.extend_from_slice(&(1u32 << 14).to_be_bytes());
+
+        // Arrives 0.3 s after the SR was sent, so the network took 50 ms
+        let arrival = sent.wrapping_add(3 * (1 << 16) / 10);
+        let round_trip = round_trip_ms(&report, ssrc, arrival).unwrap();
+        assert!((49..=50).contains(&round_trip), "{}", round_trip);
+        assert_eq!(round_trip_ms(&report, 0x9999, arrival), None);
+        assert!(is_rtcp(&report));
+        assert_eq!(sender_report(ssrc, (1, 2), 3, 4, 5).len(), 28);
+    }
+
+    #[tokio::test]
+    async fn test_milliwatt_number_plays_tone() {
+        let hangups = Arc::new(Hangups::default());
+        let numbers = test_numbers(600, Arc::clone(&hangups));
+        assert_eq!(numbers.kind_for("9999001"), Some(TestNumberKind::Echo));
+        assert_eq!(numbers.kind_for("5551234"), None);
+
+        let caller = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let offer = offer(&caller);
+        let pcma_only = offer.replace("RTP/AVP 8 0", "RTP/AVP 8");
+        assert!(numbers.answer("s0", "9999002", "2125550100", Some(&pcma_only), None, None).await.is_err());
+
+        let answer = numbers.answer("s1", "9999002", "2125550100", Some(&offer), None, None).await.unwrap();
+        assert!(answer.contains("m=audio") && answer.contains("a=rtpmap:0 PCMU/8000"));
+        let mut buf = [0u8; 2048];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), caller.recv_from(&mut buf)).await.unwrap().unwrap();
+        let packet = RtpPacket::decode(Bytes::copy_from_slice(&buf[..len])).unwrap();
+        assert_eq!(packet.payload_type, PCMU);
+        assert_eq!(&packet.payload[..8], &DIGITAL_MILLIWATT);
+
+        assert!(numbers.is_active("s1"));
+        let record = numbers.hangup("s1").unwrap();
+        assert!(record.ended_at.is_some());
+        assert_eq!(record.kind, TestNumberKind::Milliwatt);
+        assert!(!numbers.is_active("s1"));
+        assert_eq!(numbers.calls().len(), 1);
+        // The caller hung up, so no BYE goes back
+        assert!(hangups.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_call_at_duration_limit_is_hung_up() {
+        let hangups = Arc::new(Hangups::default());
+        let numbers = test_numbers(1, Arc::clone(&hangups));
+        let caller = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        numbers.answer("s1", "9999002", "2125550100", Some(&offer(&caller)), None, None).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(*hangups.0.lock().unwrap(), vec!["s1".to_string()]);
+        assert!(!numbers.is_active("s1"));
+        assert!(numbers.calls()[0].ended_at.is_some());
+    }
+}
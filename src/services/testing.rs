@@ -197,7 +197,7 @@ impl TestingService {
 
             // Check concurrent test limit
             if active_tests.len() >= self.config.loopback_max_concurrent as usize {
-                return Err(Error::internal("Maximum concurrent loopback tests reached"));
+                return Err(Error::resource_exhausted("Maximum concurrent loopback tests reached"));
             }
         }
 
@@ -324,7 +324,7 @@ impl TestingService {
 
             // Check concurrent test limit
             if active_tests.len() >= self.config.bert_max_concurrent as usize {
-                return Err(Error::internal("Maximum concurrent BERT tests reached"));
+                return Err(Error::resource_exhausted("Maximum concurrent BERT tests reached"));
             }
         }
 
@@ -9,7 +9,7 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -17,6 +17,7 @@ use tokio::sync::{mpsc, RwLock};
 use tokio::time::interval;
 use tracing::{debug, info, warn};
 
+use crate::utils::TimeHealth;
 use crate::{Error, Result};
 
 /// Stratum levels for clock quality
@@ -95,6 +96,20 @@ pub enum ClockSourceType {
     },
 }
 
+impl ClockSourceType {
+    /// Short name of the kind of source
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClockSourceType::Internal { .. } => "internal",
+            ClockSourceType::Gps { .. } => "gps",
+            ClockSourceType::Ntp { .. } => "ntp",
+            ClockSourceType::Ptp { .. } => "ptp",
+            ClockSourceType::TdmoeRecovered { .. } => "tdmoe",
+            ClockSourceType::External { .. } => "external",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum GpsAntennaStatus {
     Ok,
@@ -284,6 +299,8 @@ pub struct TimingService {
     phase_offset: Arc<RwLock<i64>>,     // ns
     event_tx: mpsc::UnboundedSender<TimingEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<TimingEvent>>,
+    /// Holdover of the selected clock, reported to billing and SIP
+    time_health: Option<Arc<TimeHealth>>,
     is_running: bool,
 }
 
@@ -301,10 +318,16 @@ impl TimingService {
             phase_offset: Arc::new(RwLock::new(0)),
             event_tx,
             event_rx: Some(event_rx),
+            time_health: None,
             is_running: false,
         }
     }
 
+    /// Report the selected clock's holdover to `health`; set before `start`
+    pub fn set_time_health(&mut self, health: Arc<TimeHealth>) {
+        self.time_health = Some(health);
+    }
+
     pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TimingEvent>> {
         self.event_rx.take()
     }
@@ -681,6 +704,7 @@ impl TimingService {
             
             let mut sources = self.clock_sources.write().await;
             let config = self.config.read().await;
+            let selected = self.selected_clock.read().await.clone();
             
             for (source_id, status) in sources.iter_mut() {
                 // Update uptime
@@ -704,12 +728,34 @@ impl TimingService {
                             source_id: source_id.clone(),
                             reason: "No sync for 5 minutes".to_string(),
                         });
+                    } else if since_sync <= chrono::Duration::minutes(5) && status.is_holdover {
+                        status.is_holdover = false;
+                        let _ = self.event_tx.send(TimingEvent::ClockSynchronized {
+                            source_id: source_id.clone(),
+                            offset_ns: status.phase_offset_ns,
+                            accuracy_ns: status.time_error_ns,
+                        });
                     }
                 }
                 
                 // Simulate some measurement updates
                 self.update_clock_measurements(status).await;
             }
+
+            if let Some(ref health) = self.time_health {
+                match selected.as_ref().and_then(|id| sources.get(id)) {
+                    Some(status) if status.is_holdover => {
+                        // Holdover began when the last sync went stale
+                        let since_sync = status
+                            .last_sync
+                            .and_then(|last_sync| (Utc::now() - last_sync).to_std().ok())
+                            .unwrap_or_default();
+                        let now = Instant::now();
+                        health.enter_holdover(now.checked_sub(since_sync).unwrap_or(now));
+                    }
+                    _ => health.synchronized(),
+                }
+            }
         }
     }
 
@@ -947,6 +993,7 @@ impl Clone for TimingService {
             phase_offset: Arc::clone(&self.phase_offset),
             event_tx: self.event_tx.clone(),
             event_rx: None, // Don't clone receiver
+            time_health: self.time_health.clone(),
             is_running: self.is_running,
         }
     }
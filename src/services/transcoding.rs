@@ -2,6 +2,9 @@
 //! 
 //! This module provides transcoding functionality integrated with the
 //! external redfire-codec-engine library.
+//!
+//! Opus legs (behind the `opus` feature) are transcoded to the G.711 used on
+//! the TDM side here, resampling between the 48 kHz Opus clock and 8 kHz.
 
 use std::sync::Arc;
 use std::time::Instant;
@@ -13,6 +16,8 @@ use tracing::{info, warn};
 use uuid::Uuid;
 
 use crate::config::TranscodingBackend;
+use crate::services::call_trace::{CallTracer, TraceSubsystem};
+use crate::services::transcoding_latency::{BackendSelection, TranscodingLatencyMonitor};
 use crate::Result;
 
 // Import from external redfire-codec-engine library
@@ -97,6 +102,274 @@ impl CodecType {
     }
 }
 
+/// RTP clock rate of Opus regardless of the coded bandwidth (RFC 7587)
+pub const OPUS_CLOCK_RATE: u32 = 48_000;
+
+const G711_SAMPLE_RATE: u32 = 8_000;
+const RESAMPLE_FACTOR: usize = (OPUS_CLOCK_RATE / G711_SAMPLE_RATE) as usize;
+const RESAMPLE_TAPS: usize = RESAMPLE_FACTOR * 16;
+
+/// Opus fmtp parameters (RFC 7587 section 6.1). The parameters a side
+/// advertises describe what it prefers to receive, so the remote's
+/// parameters configure our encoder and our own ones the decoder.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpusParameters {
+    /// Peak average bitrate the receiver wants, in bit/s
+    pub max_average_bitrate: Option<u32>,
+    /// The receiver decodes in-band FEC, so the sender should add it
+    pub use_inband_fec: bool,
+}
+
+impl OpusParameters {
+    /// Parse the value of an `a=fmtp:` line, with or without the payload
+    /// type; unknown parameters are ignored
+    pub fn parse(fmtp: &str) -> Self {
+        let fmtp = fmtp.trim();
+        let params = match fmtp.split_once(' ') {
+            Some((pt, rest)) if pt.parse::<u8>().is_ok() => rest,
+            _ => fmtp,
+        };
+
+        let mut parsed = Self::default();
+        for param in params.split(';') {
+            let Some((name, value)) = param.split_once('=') else { continue };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "maxaveragebitrate" => {
+                    // RFC 7587 bounds the bitrate to 6-510 kbit/s
+                    parsed.max_average_bitrate =
+                        value.trim().parse::<u32>().ok().map(|rate| rate.clamp(6_000, 510_000));
+                }
+                "useinbandfec" => parsed.use_inband_fec = value.trim() == "1",
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    /// Render as an fmtp value without the payload type
+    pub fn to_fmtp(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(rate) = self.max_average_bitrate {
+            params.push(format!("maxaveragebitrate={}", rate));
+        }
+        params.push(format!("useinbandfec={}", u8::from(self.use_inband_fec)));
+        params.join(";")
+    }
+}
+
+/// Converts between the 8 kHz G.711 rate and the 48 kHz Opus rate with a
+/// windowed-sinc low-pass, carrying filter history across frames. Use one
+/// resampler per direction.
+pub struct Resampler {
+    taps: Vec<f32>,
+    history: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new() -> Self {
+        // Cut off at 3.6 kHz so neither upsampling images nor decimation
+        // aliases land in the 4 kHz G.711 band
+        let cutoff = 3_600.0 / OPUS_CLOCK_RATE as f32;
+        let mid = (RESAMPLE_TAPS - 1) as f32 / 2.0;
+        let span = (RESAMPLE_TAPS - 1) as f32;
+        let mut taps: Vec<f32> = (0..RESAMPLE_TAPS)
+            .map(|i| {
+                let t = i as f32 - mid;
+                let sinc = (2.0 * std::f32::consts::PI * cutoff * t).sin() / (std::f32::consts::PI * t);
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / span;
+                let blackman = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+                sinc * blackman
+            })
+            .collect();
+        let gain: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|tap| *tap /= gain);
+
+        Self { taps, history: Vec::new() }
+    }
+
+    /// 8 kHz to 48 kHz
+    pub fn upsample(&mut self, input: &[i16]) -> Vec<i16> {
+        // Polyphase: each input sample feeds every sixth tap, so only the
+        // last taps-per-phase inputs are needed
+        let per_phase = RESAMPLE_TAPS / RESAMPLE_FACTOR;
+        let buffer = self.extend_history(input, per_phase - 1);
+        let mut output = Vec::with_capacity(input.len() * RESAMPLE_FACTOR);
+        for m in 0..input.len() {
+            let newest = per_phase - 1 + m;
+            for phase in 0..RESAMPLE_FACTOR {
+                let sum: f32 = (0..per_phase)
+                    .map(|j| self.taps[phase + RESAMPLE_FACTOR * j] * buffer[newest - j])
+                    .sum();
+                output.push(clamp_sample(sum * RESAMPLE_FACTOR as f32));
+            }
+        }
+        output
+    }
+
+    /// 48 kHz to 8 kHz; the input should be a whole number of 8 kHz
+    /// samples, which every Opus frame size is
+    pub fn downsample(&mut self, input: &[i16]) -> Vec<i16> {
+        let buffer = self.extend_history(input, RESAMPLE_TAPS - 1);
+        (0..input.len() / RESAMPLE_FACTOR)
+            .map(|m| {
+                let newest = RESAMPLE_TAPS - 1 + m * RESAMPLE_FACTOR;
+                let sum: f32 = self.taps.iter().enumerate().map(|(k, tap)| tap * buffer[newest - k]).sum();
+                clamp_sample(sum)
+            })
+            .collect()
+    }
+
+    /// History followed by `input`, keeping the last `keep` samples as the
+    /// next frame's history
+    fn extend_history(&mut self, input: &[i16], keep: usize) -> Vec<f32> {
+        self.history.resize(keep, 0.0);
+        let mut buffer = std::mem::take(&mut self.history);
+        buffer.extend(input.iter().map(|&s| s as f32));
+        self.history = buffer[buffer.len() - keep..].to_vec();
+        buffer
+    }
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn clamp_sample(value: f32) -> i16 {
+    value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+#[cfg(feature = "opus")]
+fn g711_decode(codec: &CodecType, payload: &[u8]) -> Vec<i16> {
+    use crate::services::prompts::{alaw_to_linear, ulaw_to_linear};
+    match codec {
+        CodecType::G711a => payload.iter().map(|&b| alaw_to_linear(b)).collect(),
+        _ => payload.iter().map(|&b| ulaw_to_linear(b)).collect(),
+    }
+}
+
+#[cfg(feature = "opus")]
+fn g711_encode(codec: &CodecType, samples: &[i16]) -> Vec<u8> {
+    use crate::services::prompts::{linear_to_alaw, linear_to_ulaw};
+    match codec {
+        CodecType::G711a => samples.iter().map(|&s| linear_to_alaw(s)).collect(),
+        _ => samples.iter().map(|&s| linear_to_ulaw(s)).collect(),
+    }
+}
+
+/// Largest Opus frame: 120 ms at 48 kHz
+#[cfg(feature = "opus")]
+const OPUS_MAX_FRAME: usize = 5_760;
+
+/// Frames decoded from a loss gap before giving up on concealment
+#[cfg(feature = "opus")]
+const OPUS_MAX_CONCEALED: u32 = 5;
+
+#[cfg(feature = "opus")]
+enum OpusCoder {
+    Encode(opus::Encoder),
+    Decode(opus::Decoder),
+}
+
+/// Per-session Opus state between a G.711 TDM leg and an Opus leg
+#[cfg(feature = "opus")]
+struct OpusTranscoder {
+    g711: CodecType,
+    coder: OpusCoder,
+    resampler: Resampler,
+    /// Our own fmtp, governing FEC recovery on decode
+    local: OpusParameters,
+    last_timestamp: Option<u32>,
+    last_frame: usize,
+}
+
+#[cfg(feature = "opus")]
+impl OpusTranscoder {
+    fn new(g711: CodecType, to_opus: bool) -> Result<Self> {
+        let opus_err = |e: opus::Error| crate::Error::transcoding(format!("Failed to create Opus codec: {}", e));
+        let coder = if to_opus {
+            OpusCoder::Encode(
+                opus::Encoder::new(OPUS_CLOCK_RATE, opus::Channels::Mono, opus::Application::Voip).map_err(opus_err)?,
+            )
+        } else {
+            OpusCoder::Decode(opus::Decoder::new(OPUS_CLOCK_RATE, opus::Channels::Mono).map_err(opus_err)?)
+        };
+        Ok(Self {
+            g711,
+            coder,
+            resampler: Resampler::new(),
+            local: OpusParameters { max_average_bitrate: None, use_inband_fec: true },
+            last_timestamp: None,
+            last_frame: 960,
+        })
+    }
+
+    fn configure(&mut self, local: OpusParameters, remote: &OpusParameters) -> Result<()> {
+        let opus_err = |e: opus::Error| crate::Error::transcoding(format!("Failed to configure Opus encoder: {}", e));
+        if let OpusCoder::Encode(ref mut encoder) = self.coder {
+            let bitrate = match remote.max_average_bitrate {
+                Some(rate) => opus::Bitrate::Bits(rate as i32),
+                None => opus::Bitrate::Auto,
+            };
+            encoder.set_bitrate(bitrate).map_err(opus_err)?;
+            encoder.set_inband_fec(remote.use_inband_fec).map_err(opus_err)?;
+            // FEC is only emitted when the encoder expects loss
+            encoder.set_packet_loss_perc(if remote.use_inband_fec { 10 } else { 0 }).map_err(opus_err)?;
+        }
+        self.local = local;
+        Ok(())
+    }
+
+    fn transcode(&mut self, payload: &[u8], timestamp: u32) -> Result<Vec<u8>> {
+        match self.coder {
+            OpusCoder::Encode(ref mut encoder) => {
+                let pcm = self.resampler.upsample(&g711_decode(&self.g711, payload));
+                if ![120, 240, 480, 960, 1920, 2880].contains(&pcm.len()) {
+                    return Err(crate::Error::not_supported(format!(
+                        "{} byte G.711 frame is not an Opus frame size",
+                        payload.len()
+                    )));
+                }
+                let mut packet = vec![0u8; 1275];
+                let len = encoder
+                    .encode(&pcm, &mut packet)
+                    .map_err(|e| crate::Error::transcoding(format!("Opus encode failed: {}", e)))?;
+                packet.truncate(len);
+                Ok(packet)
+            }
+            OpusCoder::Decode(ref mut decoder) => {
+                let opus_err = |e: opus::Error| crate::Error::transcoding(format!("Opus decode failed: {}", e));
+                let mut pcm = Vec::new();
+                let mut frame = vec![0i16; OPUS_MAX_FRAME];
+
+                // Conceal packets missing since the last one: the decoder's
+                // PLC for all but the newest, which in-band FEC in this
+                // packet can rebuild
+                let lost = self
+                    .last_timestamp
+                    .map(|last| timestamp.wrapping_sub(last) / self.last_frame as u32)
+                    .filter(|&frames| frames > 1 && frames <= OPUS_MAX_CONCEALED + 1)
+                    .map_or(0, |frames| frames - 1);
+                for n in 0..lost {
+                    let fec = n + 1 == lost && self.local.use_inband_fec;
+                    let input: &[u8] = if fec { payload } else { &[] };
+                    let len = decoder.decode(input, &mut frame[..self.last_frame], fec).map_err(opus_err)?;
+                    pcm.extend_from_slice(&frame[..len]);
+                }
+
+                let len = decoder.decode(payload, &mut frame, false).map_err(opus_err)?;
+                pcm.extend_from_slice(&frame[..len]);
+                self.last_timestamp = Some(timestamp);
+                if len > 0 {
+                    self.last_frame = len;
+                }
+                Ok(g711_encode(&self.g711, &self.resampler.downsample(&pcm)))
+            }
+        }
+    }
+}
+
 /// Transcoding session configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TranscodingSession {
@@ -224,6 +497,10 @@ pub struct TranscodingService {
     enable_gpu: bool,
     auto_detect_gpu: bool,
     gpu_fallback: bool,
+    call_tracer: Option<Arc<CallTracer>>,
+    latency_monitor: Option<Arc<TranscodingLatencyMonitor>>,
+    #[cfg(feature = "opus")]
+    opus_sessions: Arc<DashMap<String, std::sync::Mutex<OpusTranscoder>>>,
 }
 
 impl TranscodingService {
@@ -320,9 +597,24 @@ impl TranscodingService {
             enable_gpu,
             auto_detect_gpu,
             gpu_fallback,
+            call_tracer: None,
+            latency_monitor: None,
+            #[cfg(feature = "opus")]
+            opus_sessions: Arc::new(DashMap::new()),
         }
     }
 
+    /// Log transcoding of traced calls into their per-call trace
+    pub fn set_call_tracer(&mut self, tracer: Arc<CallTracer>) {
+        self.call_tracer = Some(tracer);
+    }
+
+    /// Measure per-frame latency against its budget and place new sessions
+    /// on a backend within budget
+    pub fn set_latency_monitor(&mut self, monitor: Arc<TranscodingLatencyMonitor>) {
+        self.latency_monitor = Some(monitor);
+    }
+
     pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TranscodingEvent>> {
         self.event_rx.take()
     }
@@ -461,10 +753,59 @@ impl TranscodingService {
         source_sample_rate: u32,
         target_sample_rate: u32,
     ) -> Result<String> {
-        warn!("Transcoding session creation requested but service is in stub mode");
-        
-        // Create a placeholder session
         let session_id = Uuid::new_v4().to_string();
+        let (source_sample_rate, target_sample_rate) = match (&source_codec, &target_codec) {
+            (CodecType::Opus, CodecType::Opus) => (source_sample_rate, target_sample_rate),
+            (CodecType::Opus, CodecType::G711u | CodecType::G711a) => (OPUS_CLOCK_RATE, G711_SAMPLE_RATE),
+            (CodecType::G711u | CodecType::G711a, CodecType::Opus) => (G711_SAMPLE_RATE, OPUS_CLOCK_RATE),
+            (CodecType::Opus, other) | (other, CodecType::Opus) => {
+                return Err(crate::Error::not_supported(format!(
+                    "Opus transcodes to G.711 only, not {}",
+                    other.to_name()
+                )));
+            }
+            _ => {
+                warn!("Transcoding session creation requested but service is in stub mode");
+                (source_sample_rate, target_sample_rate)
+            }
+        };
+        if source_codec != target_codec && (source_codec == CodecType::Opus || target_codec == CodecType::Opus) {
+            #[cfg(feature = "opus")]
+            {
+                let (g711, to_opus) = if target_codec == CodecType::Opus {
+                    (source_codec.clone(), true)
+                } else {
+                    (target_codec.clone(), false)
+                };
+                let transcoder = OpusTranscoder::new(g711, to_opus)?;
+                self.opus_sessions.insert(session_id.clone(), std::sync::Mutex::new(transcoder));
+            }
+            #[cfg(not(feature = "opus"))]
+            return Err(crate::Error::not_supported("Opus transcoding requires the `opus` feature"));
+        }
+
+        let backend = match self.latency_monitor {
+            Some(ref monitor) => match monitor.select_backend(&target_codec, &self.backend_preference, Instant::now()) {
+                BackendSelection::Backend(backend) => backend,
+                BackendSelection::RelayOnly => {
+                    return Err(crate::Error::not_supported(format!(
+                        "No transcoding backend within latency budget for {}",
+                        target_codec.to_name()
+                    )));
+                }
+            },
+            None => self.backend_preference.clone(),
+        };
+        if backend != self.backend_preference {
+            let _ = self.event_tx.send(TranscodingEvent::BackendSwitch {
+                session_id: session_id.clone(),
+                from_backend: self.backend_preference.clone(),
+                to_backend: backend.clone(),
+                reason: "Preferred backend over latency budget".to_string(),
+            });
+        }
+
+        // Create a placeholder session
         let session = TranscodingSession {
             id: session_id.clone(),
             call_id: call_id.to_string(),
@@ -472,45 +813,99 @@ impl TranscodingService {
             target_codec: target_codec.clone(),
             source_sample_rate,
             target_sample_rate,
-            backend: self.backend_preference.clone(),
+            backend: backend.clone(),
             created_at: Instant::now(),
             last_activity: Instant::now(),
             stats: TranscodingStats::new(),
         };
 
         self.sessions.insert(session_id.clone(), session);
+        if let Some(ref tracer) = self.call_tracer {
+            tracer.record(call_id, TraceSubsystem::Transcoding, || {
+                format!(
+                    "Session {} {:?} {} Hz -> {:?} {} Hz on {:?}",
+                    session_id, source_codec, source_sample_rate, target_codec, target_sample_rate, backend
+                )
+            });
+        }
 
         // Emit event
         let _ = self.event_tx.send(TranscodingEvent::SessionStarted {
             session_id: session_id.clone(),
             source_codec,
             target_codec,
-            backend: self.backend_preference.clone(),
+            backend,
         });
 
-        info!("Created stub transcoding session: {}", session_id);
+        info!("Created transcoding session: {}", session_id);
         Ok(session_id)
     }
 
+    /// Apply negotiated Opus fmtp to a session: `remote` is what the Opus
+    /// endpoint asked to receive and shapes our encoder, `local` is what we
+    /// offered and enables FEC recovery when decoding
+    pub fn set_opus_parameters(&self, session_id: &str, local: OpusParameters, remote: &OpusParameters) -> Result<()> {
+        #[cfg(feature = "opus")]
+        if let Some(transcoder) = self.opus_sessions.get(session_id) {
+            return transcoder.lock().unwrap().configure(local, remote);
+        }
+        let _ = (local, remote);
+        Err(crate::Error::invalid_state(format!("No Opus transcoding session {}", session_id)))
+    }
+
     pub async fn transcode_packet(
         &self,
         session_id: &str,
         input_data: &[u8],
         timestamp: u32,
     ) -> Result<Vec<u8>> {
+        let started = Instant::now();
         // Update session activity if it exists
-        if let Some(mut session) = self.sessions.get_mut(session_id) {
-            session.last_activity = Instant::now();
-            session.stats.packets_processed += 1;
-            session.stats.bytes_processed += input_data.len() as u64;
-            
-            // If we have a codec service, use it for transcoding
-            if self.codec_service.is_some() {
-                // TODO: Implement actual transcoding using external library
-                // For now, return input unchanged but log that we're using the real service
-                info!("Transcoding packet for session {} using redfire-codec-engine", session_id);
-                return Ok(input_data.to_vec());
+        let transcoded = match self.sessions.get_mut(session_id) {
+            Some(mut session) => {
+                session.last_activity = Instant::now();
+                session.stats.packets_processed += 1;
+                session.stats.bytes_processed += input_data.len() as u64;
+                if let Some(ref tracer) = self.call_tracer {
+                    tracer.record(&session.call_id, TraceSubsystem::Transcoding, || {
+                        format!("Session {} packet ts {} ({} bytes)", session_id, timestamp, input_data.len())
+                    });
+                }
+
+                // If we have a codec service, use it for transcoding
+                if self.codec_service.is_some() {
+                    // TODO: Implement actual transcoding using external library
+                    // For now, return input unchanged but log that we're using the real service
+                    info!("Transcoding packet for session {} using redfire-codec-engine", session_id);
+                    Some((session.target_codec.clone(), session.backend.clone()))
+                } else {
+                    None
+                }
             }
+            None => None,
+        };
+        #[cfg(feature = "opus")]
+        let output = match self.opus_sessions.get(session_id) {
+            Some(transcoder) => Some(transcoder.lock().unwrap().transcode(input_data, timestamp)?),
+            None => None,
+        };
+        #[cfg(not(feature = "opus"))]
+        let output: Option<Vec<u8>> = None;
+        if let Some((codec, backend)) = transcoded {
+            if let Some(ref monitor) = self.latency_monitor {
+                if let Some(breach) = monitor.record(&codec, &backend, started.elapsed()).await {
+                    let _ = self.event_tx.send(TranscodingEvent::PerformanceAlert {
+                        session_id: session_id.to_string(),
+                        metric: format!("{} p99 frame latency on {:?} (us)", codec.to_name(), backend),
+                        value: breach.p99_us as f64,
+                        threshold: breach.budget_us as f64,
+                    });
+                }
+            }
+            return Ok(output.unwrap_or_else(|| input_data.to_vec()));
+        }
+        if let Some(output) = output {
+            return Ok(output);
         }
 
         // Fallback: return input unchanged
@@ -519,13 +914,20 @@ impl TranscodingService {
     }
 
     pub async fn destroy_transcoding_session(&self, session_id: &str) -> Result<()> {
+        #[cfg(feature = "opus")]
+        self.opus_sessions.remove(session_id);
         if let Some((_, session)) = self.sessions.remove(session_id) {
+            if let Some(ref tracer) = self.call_tracer {
+                tracer.record(&session.call_id, TraceSubsystem::Transcoding, || {
+                    format!("Session {} closed after {} packets", session_id, session.stats.packets_processed)
+                });
+            }
             let _ = self.event_tx.send(TranscodingEvent::SessionCompleted {
                 session_id: session_id.to_string(),
                 stats: session.stats,
             });
 
-            info!("Destroyed stub transcoding session: {}", session_id);
+            info!("Destroyed transcoding session: {}", session_id);
         }
 
         Ok(())
@@ -644,4 +1046,71 @@ mod tests {
         service.destroy_transcoding_session(&session_id).await.unwrap();
         service.stop().await.unwrap();
     }
+
+    #[test]
+    fn test_opus_fmtp_parameters() {
+        let params = OpusParameters::parse("111 minptime=10; useinbandfec=1;maxaveragebitrate=900000");
+        assert_eq!(params, OpusParameters { max_average_bitrate: Some(510_000), use_inband_fec: true });
+        assert_eq!(params.to_fmtp(), "maxaveragebitrate=510000;useinbandfec=1");
+        assert_eq!(OpusParameters::parse("stereo=1").to_fmtp(), "useinbandfec=0");
+    }
+
+    #[test]
+    fn test_resampler_band_limits() {
+        let tone = |freq: f32, rate: f32, len: usize| -> Vec<i16> {
+            (0..len).map(|n| (8000.0 * (2.0 * std::f32::consts::PI * freq * n as f32 / rate).sin()) as i16).collect()
+        };
+        let rms = |samples: &[i16]| {
+            (samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+        };
+
+        // A 1 kHz tone survives 8 -> 48 -> 8 kHz once the filters settle
+        let (mut up, mut down) = (Resampler::new(), Resampler::new());
+        let narrowband = tone(1000.0, 8000.0, 1600);
+        let wideband = up.upsample(&narrowband);
+        assert_eq!(wideband.len(), 9600);
+        let back = down.downsample(&wideband);
+        assert_eq!(back.len(), 1600);
+        assert!((rms(&back[800..]) / rms(&narrowband[800..]) - 1.0).abs() < 0.05);
+
+        // Wideband content above the G.711 band does not alias into it
+        let mut down = Resampler::new();
+        let aliased = down.downsample(&tone(6000.0, 48000.0, 9600));
+        assert!(rms(&aliased[800..]) < 80.0);
+    }
+
+    #[cfg(feature = "opus")]
+    #[tokio::test]
+    async fn test_opus_g711_transcoding() {
+        let service = TranscodingService::new(TranscodingBackend::Auto);
+        let encode = service.create_transcoding_session("opus-call", CodecType::G711u, CodecType::Opus, 8000, 8000)
+            .await.unwrap();
+        let decode = service.create_transcoding_session("opus-call", CodecType::Opus, CodecType::G711u, 8000, 8000)
+            .await.unwrap();
+        let remote = OpusParameters::parse("maxaveragebitrate=16000;useinbandfec=1");
+        service.set_opus_parameters(&encode, OpusParameters::default(), &remote).unwrap();
+        assert_eq!(service.get_active_sessions().iter().filter(|s| s.target_sample_rate == OPUS_CLOCK_RATE).count(), 1);
+
+        // 20 ms of G.711 makes one Opus frame and comes back as 20 ms
+        let frame: Vec<u8> = (0..160).map(linear_to_ulaw_tone).collect();
+        let mut decoded = Vec::new();
+        for (n, ts) in [0u32, 960, 2880].into_iter().enumerate() {
+            let packet = service.transcode_packet(&encode, &frame, ts / 6).await.unwrap();
+            assert!(!packet.is_empty() && packet.len() < 160, "frame {}", n);
+            decoded.push(service.transcode_packet(&decode, &packet, ts).await.unwrap());
+        }
+        // The gap before the last packet is concealed and prepended
+        assert_eq!(decoded.iter().map(Vec::len).collect::<Vec<_>>(), vec![160, 160, 320]);
+
+        assert!(service
+            .create_transcoding_session("opus-call", CodecType::Opus, CodecType::G729, 48000, 8000)
+            .await
+            .is_err());
+    }
+
+    #[cfg(feature = "opus")]
+    fn linear_to_ulaw_tone(n: usize) -> u8 {
+        let sample = (8000.0 * (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 8000.0).sin()) as i16;
+        crate::services::prompts::linear_to_ulaw(sample)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,314 @@
+//! Per-frame transcoding latency budgets
+//!
+//! The time each frame takes to transcode is measured by codec and backend
+//! over a window of recent frames. When the 99th percentile for a codec
+//! exceeds its budget on a backend, a performance alarm is raised and new
+//! sessions for that codec shift to the next configured fallback backend.
+//! Once every backend is over budget for a codec, it is no longer offered
+//! as a transcoded fallback, so new calls are only negotiated where they
+//! can be relayed. A backend is tried again once the hold-down time has
+//! passed since it was last found over budget, and its alarm clears when
+//! the percentile is back within budget.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::{TranscodingBackend, TranscodingLatencyConfig};
+use crate::services::alarms::{AlarmManager, AlarmSeverity, AlarmSource, AlarmType};
+use crate::services::transcoding::CodecType;
+
+/// Management API path reporting latency by codec and backend
+pub const TRANSCODING_LATENCY_PATH: &str = "/api/v1/b2bua/transcoding-latency";
+
+const ALARM_COMPONENT: &str = "transcoding";
+
+/// Frames needed before a percentile is judged against the budget
+const MIN_FRAMES: usize = 100;
+
+/// The percentile is checked every this many frames
+const CHECK_INTERVAL: u64 = 50;
+
+/// Backend for a new transcoding session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendSelection {
+    Backend(TranscodingBackend),
+    /// Every backend is over budget for the codec
+    RelayOnly,
+}
+
+/// The moment a codec's percentile went over budget on a backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyBreach {
+    pub codec: CodecType,
+    pub backend: TranscodingBackend,
+    pub p99_us: u64,
+    pub budget_us: u64,
+}
+
+/// Latency of one codec on one backend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TranscodingLatencyReport {
+    pub codec: String,
+    pub backend: TranscodingBackend,
+    pub frames: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub budget_us: u64,
+    /// New sessions avoid this backend for the codec
+    pub avoided: bool,
+}
+
+#[derive(Default)]
+struct LatencyWindow {
+    samples: VecDeque<u64>,
+    frames: u64,
+    /// Last check that found the percentile over budget
+    over_budget_at: Option<Instant>,
+    alarm_id: Option<String>,
+}
+
+impl LatencyWindow {
+    fn percentile(&self, percentile: usize) -> u64 {
+        let mut sorted: Vec<u64> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        match sorted.len() {
+            0 => 0,
+            len => sorted[(len * percentile / 100).min(len - 1)],
+        }
+    }
+}
+
+/// Measures transcoding latency and steers new sessions within budget
+pub struct TranscodingLatencyMonitor {
+    config: TranscodingLatencyConfig,
+    windows: DashMap<(CodecType, TranscodingBackend), LatencyWindow>,
+    alarm_manager: OnceLock<Arc<AlarmManager>>,
+}
+
+impl TranscodingLatencyMonitor {
+    pub fn new(config: TranscodingLatencyConfig) -> Self {
+        Self {
+            config,
+            windows: DashMap::new(),
+            alarm_manager: OnceLock::new(),
+        }
+    }
+
+    /// Raise latency alarms through `alarm_manager`; only the first one set is used
+    pub fn set_alarm_manager(&self, alarm_manager: Arc<AlarmManager>) {
+        let _ = self.alarm_manager.set(alarm_manager);
+    }
+
+    pub fn budget_us(&self, codec: &CodecType) -> u64 {
+        self.config
+            .codec_budgets_us
+            .iter()
+            .find(|(name, _)| CodecType::from_name(name) == *codec)
+            .map_or(self.config.budget_us, |(_, budget)| *budget)
+    }
+
+    fn avoided(&self, window: &LatencyWindow, now: Instant) -> bool {
+        let hold_down = Duration::from_secs(self.config.hold_down_secs);
+        window.over_budget_at.is_some_and(|at| now.duration_since(at) < hold_down)
+    }
+
+    fn is_avoided(&self, codec: &CodecType, backend: &TranscodingBackend, now: Instant) -> bool {
+        self.windows
+            .get(&(codec.clone(), backend.clone()))
+            .is_some_and(|window| self.avoided(&window, now))
+    }
+
+    /// Backend a new session transcoding to `codec` should use: `preferred`,
+    /// or the first fallback within budget
+    pub fn select_backend(&self, codec: &CodecType, preferred: &TranscodingBackend, now: Instant) -> BackendSelection {
+        if !self.config.enabled {
+            return BackendSelection::Backend(preferred.clone());
+        }
+        std::iter::once(preferred)
+            .chain(&self.config.fallback_backends)
+            .find(|backend| !self.is_avoided(codec, backend, now))
+            .map_or(BackendSelection::RelayOnly, |backend| BackendSelection::Backend(backend.clone()))
+    }
+
+    /// Whether new calls may be offered `codec` as a transcoded fallback
+    pub fn can_transcode(&self, codec: &CodecType, preferred: &TranscodingBackend) -> bool {
+        self.select_backend(codec, preferred, Instant::now()) != BackendSelection::RelayOnly
+    }
+
+    /// Record the time one frame took; returns the breach when this frame
+    /// takes the codec's percentile over budget on the backend
+    pub async fn record(&self, codec: &CodecType, backend: &TranscodingBackend, elapsed: Duration) -> Option<LatencyBreach> {
+        if !self.config.enabled {
+            return None;
+        }
+        let budget_us = self.budget_us(codec);
+        let now = Instant::now();
+        let (p99_us, breached, cleared_alarm) = {
+            let mut window = self.windows.entry((codec.clone(), backend.clone())).or_default();
+            window.samples.push_back(elapsed.as_micros() as u64);
+            while window.samples.len() > self.config.window_frames {
+                window.samples.pop_front();
+            }
+            window.frames += 1;
+            if window.samples.len() < MIN_FRAMES || window.frames % CHECK_INTERVAL != 0 {
+                return None;
+            }
+            let p99_us = window.percentile(99);
+            if p99_us > budget_us {
+                let breached = window.over_budget_at.is_none();
+                window.over_budget_at = Some(now);
+                (p99_us, breached, None)
+            } else {
+                window.over_budget_at = None;
+                (p99_us, false, window.alarm_id.take())
+            }
+        };
+
+        if let Some(alarm_id) = cleared_alarm {
+            info!("{} transcoding on {:?} back within budget: p99 {}us", codec.to_name(), backend, p99_us);
+            if let Some(alarms) = self.alarm_manager.get() {
+                let _ = alarms.clear_alarm(&alarm_id, ALARM_COMPONENT.to_string()).await;
+            }
+        }
+        if !breached {
+            return None;
+        }
+        warn!(
+            "{} transcoding on {:?} over budget: p99 {}us > {}us; shifting new sessions",
+            codec.to_name(),
+            backend,
+            p99_us,
+            budget_us
+        );
+        let breach = LatencyBreach { codec: codec.clone(), backend: backend.clone(), p99_us, budget_us };
+        self.raise_alarm(&breach).await;
+        Some(breach)
+    }
+
+    async fn raise_alarm(&self, breach: &LatencyBreach) {
+        let Some(alarms) = self.alarm_manager.get() else {
+            return;
+        };
+        let source = AlarmSource {
+            component: ALARM_COMPONENT.to_string(),
+            instance: format!("{:?}/{}", breach.backend, breach.codec.to_name()),
+            location: None,
+        };
+        let result = alarms
+            .raise_alarm(
+                AlarmSeverity::Major,
+                AlarmType::Processing,
+                source,
+                format!(
+                    "{} transcoding on {:?} takes {}us per frame at p99, over its {}us budget",
+                    breach.codec.to_name(),
+                    breach.backend,
+                    breach.p99_us,
+                    breach.budget_us
+                ),
+                None,
+                Some("Transcoding latency over budget".to_string()),
+                Some("Check CPU load and GPU health; add transcoding capacity".to_string()),
+            )
+            .await;
+        match result {
+            Ok(alarm_id) => {
+                if let Some(mut window) = self.windows.get_mut(&(breach.codec.clone(), breach.backend.clone())) {
+                    window.alarm_id = Some(alarm_id);
+                }
+            }
+            Err(e) => warn!("Failed to raise transcoding latency alarm: {}", e),
+        }
+    }
+
+    /// Latency by codec and backend
+    pub fn report(&self) -> Vec<TranscodingLatencyReport> {
+        let now = Instant::now();
+        let mut reports: Vec<TranscodingLatencyReport> = self
+            .windows
+            .iter()
+            .map(|entry| {
+                let (codec, backend) = entry.key();
+                let window = entry.value();
+                TranscodingLatencyReport {
+                    codec: codec.to_name().to_string(),
+                    backend: backend.clone(),
+                    frames: window.frames,
+                    p50_us: window.percentile(50),
+                    p99_us: window.percentile(99),
+                    budget_us: self.budget_us(codec),
+                    avoided: self.avoided(window, now),
+                }
+            })
+            .collect();
+        reports.sort_by(|a, b| a.codec.cmp(&b.codec).then_with(|| format!("{:?}", a.backend).cmp(&format!("{:?}", b.backend))));
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::alarms::AlarmConfig;
+
+    #[tokio::test]
+    async fn test_over_budget_backend_is_avoided() {
+        let monitor = TranscodingLatencyMonitor::new(TranscodingLatencyConfig {
+            enabled: true,
+            budget_us: 1000,
+            fallback_backends: vec![TranscodingBackend::Cpu],
+            ..Default::default()
+        });
+        let alarms = Arc::new(AlarmManager::new(AlarmConfig::default()));
+        monitor.set_alarm_manager(Arc::clone(&alarms));
+        let gpu = TranscodingBackend::Gpu;
+
+        let mut breaches = Vec::new();
+        for frame in 0..200u64 {
+            // Two frames in a hundred are slow: p99 over budget
+            let elapsed = if frame % 50 == 7 { 5000 } else { 300 };
+            breaches.extend(monitor.record(&CodecType::Opus, &gpu, Duration::from_micros(elapsed)).await);
+        }
+        assert_eq!(breaches.len(), 1);
+        assert_eq!(breaches[0].p99_us, 5000);
+        assert_eq!(alarms.get_active_alarms().await.len(), 1);
+
+        let now = Instant::now();
+        assert_eq!(monitor.select_backend(&CodecType::Opus, &gpu, now), BackendSelection::Backend(TranscodingBackend::Cpu));
+        assert_eq!(monitor.select_backend(&CodecType::G729, &gpu, now), BackendSelection::Backend(gpu.clone()));
+        let retried = monitor.select_backend(&CodecType::Opus, &gpu, now + Duration::from_secs(60));
+        assert_eq!(retried, BackendSelection::Backend(gpu.clone()));
+
+        let report = monitor.report();
+        assert_eq!((report[0].codec.as_str(), report[0].frames, report[0].avoided), ("OPUS", 200, true));
+
+        // Back within budget once the slow frames leave the window
+        for _ in 0..500 {
+            monitor.record(&CodecType::Opus, &gpu, Duration::from_micros(300)).await;
+        }
+        assert!(alarms.get_active_alarms().await.is_empty());
+        assert_eq!(monitor.select_backend(&CodecType::Opus, &gpu, Instant::now()), BackendSelection::Backend(gpu));
+    }
+
+    #[tokio::test]
+    async fn test_relay_only_once_every_backend_is_over_budget() {
+        let monitor = TranscodingLatencyMonitor::new(TranscodingLatencyConfig {
+            enabled: true,
+            codec_budgets_us: [("g729".to_string(), 500)].into_iter().collect(),
+            ..Default::default()
+        });
+        assert_eq!(monitor.budget_us(&CodecType::G729), 500);
+        assert_eq!(monitor.budget_us(&CodecType::G711u), 2000);
+        for _ in 0..100 {
+            monitor.record(&CodecType::G729, &TranscodingBackend::Cpu, Duration::from_micros(800)).await;
+        }
+        assert!(!monitor.can_transcode(&CodecType::G729, &TranscodingBackend::Cpu));
+        assert!(monitor.can_transcode(&CodecType::G711u, &TranscodingBackend::Cpu));
+    }
+}
@@ -0,0 +1,327 @@
+//! Call transfer on REFER (RFC 3515), blind and attended
+//!
+//! Either party of a connected call can transfer the other by sending REFER
+//! on its own leg. The gateway accepts it with 202 and INVITEs the Refer-To
+//! target on the transferee's behalf. For an attended transfer the Refer-To
+//! carries a Replaces (RFC 3891) for the consultation dialog the target holds
+//! with the transferor, which the target drops in favour of the new leg.
+//! Progress of the new leg is reported to the transferor in NOTIFYs with
+//! sipfrag bodies. Once the target answers, its leg takes the transferor's
+//! place in the call, the relay is pointed at its media and the transferor's
+//! leg is torn down.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::TransferConfig;
+use crate::services::b2bua::CallLeg;
+
+/// Event package of the implicit subscription a REFER creates
+pub const EVENT_PACKAGE: &str = "refer";
+
+pub const SIPFRAG_CONTENT_TYPE: &str = "message/sipfrag;version=2.0";
+
+pub const REFER_TO_HEADER: &str = "Refer-To";
+pub const REFERRED_BY_HEADER: &str = "Referred-By";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+    /// Transferee is sent to the target without a consultation call
+    Blind,
+    /// Transferee replaces the transferor's consultation call with the target
+    Attended,
+}
+
+/// Target of a REFER: the URI to INVITE and, for an attended transfer, the
+/// dialog it replaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferTo {
+    pub uri: String,
+    /// Replaces header value, unescaped
+    pub replaces: Option<String>,
+}
+
+impl ReferTo {
+    /// Parse a Refer-To value such as
+    /// `<sip:bob@example.com?Replaces=abc%40host%3Bto-tag%3D1%3Bfrom-tag%3D2>`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let addr = match value.find('<') {
+            Some(start) => &value[start + 1..start + value[start..].find('>')?],
+            None => value.split(';').next()?,
+        };
+        let (uri, headers) = addr.split_once('?').unwrap_or((addr, ""));
+        let scheme = uri.split(':').next()?.to_ascii_lowercase();
+        if scheme != "sip" && scheme != "sips" {
+            return None;
+        }
+        let replaces = headers
+            .split('&')
+            .filter_map(|header| header.split_once('='))
+            .find(|(name, _)| name.eq_ignore_ascii_case("Replaces"))
+            .map(|(_, value)| percent_decode(value));
+        Some(Self { uri: uri.to_string(), replaces })
+    }
+
+    pub fn kind(&self) -> TransferKind {
+        if self.replaces.is_some() {
+            TransferKind::Attended
+        } else {
+            TransferKind::Blind
+        }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// NOTIFY body reporting the new leg's latest response
+pub fn sipfrag(status_code: u16, reason: &str) -> String {
+    format!("SIP/2.0 {} {}\r\n", status_code, reason)
+}
+
+/// Subscription-State of a NOTIFY carrying `status_code`: the
+/// subscription ends with the first final response
+pub fn subscription_state(status_code: u16) -> &'static str {
+    if status_code >= 200 {
+        "terminated;reason=noresource"
+    } else {
+        "active"
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferOutcome {
+    Pending,
+    Completed,
+    Failed { status_code: u16, reason: String },
+}
+
+/// One REFER and what became of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferRecord {
+    pub id: String,
+    pub call_id: String,
+    pub kind: TransferKind,
+    /// Leg the REFER arrived on; its party leaves the call
+    pub transferor: CallLeg,
+    pub target: String,
+    pub referred_by: Option<String>,
+    pub replaces: Option<String>,
+    pub transferor_session_id: String,
+    pub new_session_id: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub outcome: TransferOutcome,
+}
+
+impl TransferRecord {
+    pub fn new(call_id: &str, transferor: CallLeg, transferor_session_id: &str, refer_to: &ReferTo, referred_by: Option<String>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            call_id: call_id.to_string(),
+            kind: refer_to.kind(),
+            transferor,
+            target: refer_to.uri.clone(),
+            referred_by,
+            replaces: refer_to.replaces.clone(),
+            transferor_session_id: transferor_session_id.to_string(),
+            new_session_id: None,
+            requested_at: Utc::now(),
+            finished_at: None,
+            outcome: TransferOutcome::Pending,
+        }
+    }
+}
+
+struct PendingTransfer {
+    record_id: String,
+    started: Instant,
+}
+
+/// Tracks transfers whose new leg has yet to answer
+pub struct CallTransfers {
+    config: TransferConfig,
+    records: DashMap<String, TransferRecord>,
+    /// New leg session ID to the transfer waiting on it
+    pending: DashMap<String, PendingTransfer>,
+}
+
+impl CallTransfers {
+    pub fn new(config: TransferConfig) -> Self {
+        Self {
+            config,
+            records: DashMap::new(),
+            pending: DashMap::new(),
+        }
+    }
+
+    /// Whether transfers of `kind` are accepted
+    pub fn allows(&self, kind: TransferKind) -> bool {
+        match kind {
+            TransferKind::Blind => self.config.allow_blind,
+            TransferKind::Attended => self.config.allow_attended,
+        }
+    }
+
+    /// Whether a transfer of `call_id` is already waiting for its new leg
+    pub fn in_progress(&self, call_id: &str) -> bool {
+        self.pending.iter().any(|entry| {
+            self.records
+                .get(&entry.value().record_id)
+                .is_some_and(|record| record.call_id == call_id)
+        })
+    }
+
+    pub fn is_pending(&self, session_id: &str) -> bool {
+        self.pending.contains_key(session_id)
+    }
+
+    /// The pending transfer whose new leg is `session_id`
+    pub fn pending(&self, session_id: &str) -> Option<TransferRecord> {
+        let record_id = self.pending.get(session_id)?.record_id.clone();
+        self.records.get(&record_id).map(|record| record.clone())
+    }
+
+    /// Record a transfer whose INVITE went out on `new_session_id`
+    pub fn begin(&self, mut record: TransferRecord, new_session_id: String) -> TransferRecord {
+        record.new_session_id = Some(new_session_id.clone());
+        self.pending.insert(new_session_id, PendingTransfer {
+            record_id: record.id.clone(),
+            started: Instant::now(),
+        });
+        self.store(record)
+    }
+
+    /// Record a REFER refused before any INVITE was sent
+    pub fn refuse(&self, mut record: TransferRecord, status_code: u16, reason: String) -> TransferRecord {
+        record.finished_at = Some(Utc::now());
+        record.outcome = TransferOutcome::Failed { status_code, reason };
+        self.store(record)
+    }
+
+    /// The new leg answered
+    pub fn complete(&self, session_id: &str) -> Option<TransferRecord> {
+        self.finish(session_id, TransferOutcome::Completed)
+    }
+
+    /// The new leg was refused or went away before answering
+    pub fn fail(&self, session_id: &str, status_code: u16, reason: String) -> Option<TransferRecord> {
+        self.finish(session_id, TransferOutcome::Failed { status_code, reason })
+    }
+
+    /// Abandon transfers whose new leg has not answered in time
+    pub fn expire(&self, now: Instant) -> Vec<TransferRecord> {
+        let timeout = Duration::from_secs(self.config.answer_timeout_secs);
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|entry| now.duration_since(entry.value().started) >= timeout)
+            .map(|entry| entry.key().clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|session_id| self.fail(&session_id, 408, format!("Target not answered within {:?}", timeout)))
+            .collect()
+    }
+
+    /// Transfer records, most recent first
+    pub fn history(&self) -> Vec<TransferRecord> {
+        let mut records: Vec<TransferRecord> = self.records.iter().map(|entry| entry.value().clone()).collect();
+        records.sort_by(|a, b| b.requested_at.cmp(&a.requested_at));
+        records
+    }
+
+    fn finish(&self, session_id: &str, outcome: TransferOutcome) -> Option<TransferRecord> {
+        let (_, pending) = self.pending.remove(session_id)?;
+        let mut record = self.records.get(&pending.record_id)?.clone();
+        record.finished_at = Some(Utc::now());
+        record.outcome = outcome;
+        Some(self.store(record))
+    }
+
+    fn store(&self, record: TransferRecord) -> TransferRecord {
+        info!(
+            "Transfer {} of call {} by leg {:?} to {} ({:?}): {:?}",
+            record.id, record.call_id, record.transferor, record.target, record.kind, record.outcome
+        );
+        self.records.insert(record.id.clone(), record.clone());
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refer_to_parsing() {
+        let blind = ReferTo::parse("<sip:5551234@pbx.example.com>").unwrap();
+        assert_eq!(blind.uri, "sip:5551234@pbx.example.com");
+        assert_eq!(blind.kind(), TransferKind::Blind);
+
+        let attended = ReferTo::parse(
+            "\"Bob\" <sip:bob@pbx.example.com?Replaces=abc%40host%3Bto-tag%3D1%3Bfrom-tag%3D2>;x=y",
+        )
+        .unwrap();
+        assert_eq!(attended.uri, "sip:bob@pbx.example.com");
+        assert_eq!(attended.replaces.as_deref(), Some("abc@host;to-tag=1;from-tag=2"));
+        assert_eq!(attended.kind(), TransferKind::Attended);
+
+        assert!(ReferTo::parse("<tel:+15551234>").is_none());
+        assert_eq!(sipfrag(180, "Ringing"), "SIP/2.0 180 Ringing\r\n");
+        assert_eq!(subscription_state(100), "active");
+    }
+
+    #[test]
+    fn test_transfer_lifecycle() {
+        let transfers = CallTransfers::new(TransferConfig {
+            enabled: true,
+            answer_timeout_secs: 5,
+            ..Default::default()
+        });
+        let refer_to = ReferTo::parse("sip:5551234@pbx.example.com").unwrap();
+        let record = TransferRecord::new("call-1", CallLeg::B, "leg-b", &refer_to, None);
+        transfers.begin(record, "leg-b2".to_string());
+        assert!(transfers.in_progress("call-1"));
+        assert_eq!(transfers.pending("leg-b2").unwrap().transferor_session_id, "leg-b");
+
+        let done = transfers.complete("leg-b2").unwrap();
+        assert_eq!(done.outcome, TransferOutcome::Completed);
+        assert!(!transfers.is_pending("leg-b2"));
+
+        let record = TransferRecord::new("call-1", CallLeg::A, "leg-a", &refer_to, None);
+        transfers.begin(record, "leg-a2".to_string());
+        assert!(transfers.expire(Instant::now()).is_empty());
+        let expired = transfers.expire(Instant::now() + Duration::from_secs(5));
+        assert!(matches!(expired[0].outcome, TransferOutcome::Failed { status_code: 408, .. }));
+        assert_eq!(transfers.history().len(), 2);
+    }
+}
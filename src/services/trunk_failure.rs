@@ -0,0 +1,309 @@
+//! Trunk and span failure handling for established calls
+//!
+//! When a SIP trunk or TDM span fails mid-call the configured action decides
+//! whether affected calls are released at once or kept alive with their
+//! media path intact while signaling is re-established. Preserved calls that
+//! do not recover within their window are released with a timer-expiry
+//! cause, so CDRs show which of the two behaviors ended the call.
+
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::{TrunkFailureAction, TrunkFailureConfig};
+
+/// Q.850 cause for calls released immediately on trunk failure
+pub const CAUSE_NETWORK_OUT_OF_ORDER: u16 = 38;
+/// Q.850 cause for preserved calls whose trunk did not recover in time
+pub const CAUSE_RECOVERY_ON_TIMER_EXPIRY: u16 = 102;
+
+/// Failure-handling name used for a TDM span
+pub fn span_trunk_name(span_id: u32) -> String {
+    format!("span-{}", span_id)
+}
+
+/// What happens to calls on a failed trunk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureDisposition {
+    Release { cause: u16 },
+    Preserve { window: Duration },
+}
+
+/// A call kept up while its trunk is down
+#[derive(Debug, Clone)]
+pub struct PreservedCall {
+    pub call_id: String,
+    pub trunk: String,
+    pub preserved_at: Instant,
+    pub deadline: Instant,
+    pub next_attempt: Instant,
+    pub attempts: u32,
+}
+
+/// Work due after a poll of preserved calls
+#[derive(Debug, Default)]
+pub struct FailurePoll {
+    /// Calls whose signaling should be re-established now
+    pub reestablish: Vec<PreservedCall>,
+    /// Calls that must be released with [`CAUSE_RECOVERY_ON_TIMER_EXPIRY`]
+    pub expired: Vec<PreservedCall>,
+}
+
+/// Trunk failure events
+#[derive(Debug, Clone)]
+pub enum TrunkFailureEvent {
+    TrunkDown {
+        trunk: String,
+        affected_calls: usize,
+        disposition: FailureDisposition,
+    },
+    CallPreserved {
+        call_id: String,
+        trunk: String,
+        window: Duration,
+    },
+    ReestablishAttempt {
+        call_id: String,
+        trunk: String,
+        attempt: u32,
+    },
+    CallRecovered {
+        call_id: String,
+        trunk: String,
+        outage: Duration,
+    },
+    PreservationExpired {
+        call_id: String,
+        trunk: String,
+        attempts: u32,
+    },
+}
+
+/// Tracks calls on failed trunks and decides their fate
+pub struct TrunkFailureHandler {
+    config: TrunkFailureConfig,
+    preserved: DashMap<String, PreservedCall>,
+    event_tx: mpsc::UnboundedSender<TrunkFailureEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<TrunkFailureEvent>>,
+}
+
+impl TrunkFailureHandler {
+    pub fn new(config: TrunkFailureConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            config,
+            preserved: DashMap::new(),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<TrunkFailureEvent>> {
+        self.event_rx.take()
+    }
+
+    /// Configured disposition for a trunk, honoring per-trunk overrides
+    pub fn disposition_for(&self, trunk: &str) -> FailureDisposition {
+        let (action, preserve_secs) = self
+            .config
+            .overrides
+            .iter()
+            .find(|o| o.trunk == trunk)
+            .map(|o| (o.action, o.preserve_secs.unwrap_or(self.config.preserve_secs)))
+            .unwrap_or((self.config.action, self.config.preserve_secs));
+
+        match action {
+            TrunkFailureAction::PreserveMedia if preserve_secs > 0 => FailureDisposition::Preserve {
+                window: Duration::from_secs(preserve_secs),
+            },
+            _ => FailureDisposition::Release { cause: CAUSE_NETWORK_OUT_OF_ORDER },
+        }
+    }
+
+    /// Apply the trunk's failure action to its established calls.
+    ///
+    /// Calls are recorded as preserved when media is to be kept; the caller
+    /// releases them itself when the disposition is `Release`.
+    pub fn on_trunk_down(&self, trunk: &str, call_ids: &[String], now: Instant) -> FailureDisposition {
+        let disposition = self.disposition_for(trunk);
+        let _ = self.event_tx.send(TrunkFailureEvent::TrunkDown {
+            trunk: trunk.to_string(),
+            affected_calls: call_ids.len(),
+            disposition,
+        });
+
+        match disposition {
+            FailureDisposition::Release { cause } => {
+                warn!("Trunk {} failed: releasing {} calls (cause {})", trunk, call_ids.len(), cause);
+            }
+            FailureDisposition::Preserve { window } => {
+                warn!("Trunk {} failed: preserving media for {} calls up to {:?}", trunk, call_ids.len(), window);
+                let retry = Duration::from_secs(self.config.retry_interval_secs.max(1));
+                for call_id in call_ids {
+                    if self.preserved.contains_key(call_id) {
+                        continue;
+                    }
+                    self.preserved.insert(call_id.clone(), PreservedCall {
+                        call_id: call_id.clone(),
+                        trunk: trunk.to_string(),
+                        preserved_at: now,
+                        deadline: now + window,
+                        next_attempt: now + retry,
+                        attempts: 0,
+                    });
+                    let _ = self.event_tx.send(TrunkFailureEvent::CallPreserved {
+                        call_id: call_id.clone(),
+                        trunk: trunk.to_string(),
+                        window,
+                    });
+                }
+            }
+        }
+        disposition
+    }
+
+    /// Trunk came back: every call preserved on it is recovered
+    pub fn on_trunk_up(&self, trunk: &str, now: Instant) -> Vec<String> {
+        let recovered: Vec<String> = self
+            .preserved
+            .iter()
+            .filter(|entry| entry.trunk == trunk)
+            .map(|entry| entry.key().clone())
+            .collect();
+        recovered.into_iter().filter(|call_id| self.recover(call_id, now)).collect()
+    }
+
+    /// A single preserved call had its signaling re-established
+    pub fn recover(&self, call_id: &str, now: Instant) -> bool {
+        match self.preserved.remove(call_id) {
+            Some((_, call)) => {
+                let outage = now.duration_since(call.preserved_at);
+                info!("Call {} recovered on trunk {} after {:?}", call_id, call.trunk, outage);
+                let _ = self.event_tx.send(TrunkFailureEvent::CallRecovered {
+                    call_id: call.call_id,
+                    trunk: call.trunk,
+                    outage,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Collect re-establishment attempts that are due and calls whose window ran out
+    pub fn poll(&self, now: Instant) -> FailurePoll {
+        let mut result = FailurePoll::default();
+        let retry = Duration::from_secs(self.config.retry_interval_secs.max(1));
+
+        let expired: Vec<String> = self
+            .preserved
+            .iter()
+            .filter(|entry| now >= entry.deadline)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for call_id in expired {
+            if let Some((_, call)) = self.preserved.remove(&call_id) {
+                warn!("Preserved call {} on trunk {} expired after {} attempts", call_id, call.trunk, call.attempts);
+                let _ = self.event_tx.send(TrunkFailureEvent::PreservationExpired {
+                    call_id: call.call_id.clone(),
+                    trunk: call.trunk.clone(),
+                    attempts: call.attempts,
+                });
+                result.expired.push(call);
+            }
+        }
+
+        for mut entry in self.preserved.iter_mut() {
+            if now < entry.next_attempt {
+                continue;
+            }
+            entry.attempts += 1;
+            entry.next_attempt = now + retry;
+            let _ = self.event_tx.send(TrunkFailureEvent::ReestablishAttempt {
+                call_id: entry.call_id.clone(),
+                trunk: entry.trunk.clone(),
+                attempt: entry.attempts,
+            });
+            result.reestablish.push(entry.clone());
+        }
+
+        result
+    }
+
+    pub fn is_preserved(&self, call_id: &str) -> bool {
+        self.preserved.contains_key(call_id)
+    }
+
+    /// Trunk a call is currently preserved on
+    pub fn preserved_trunk(&self, call_id: &str) -> Option<String> {
+        self.preserved.get(call_id).map(|entry| entry.trunk.clone())
+    }
+
+    /// Drop tracking for a preserved call that ended for another reason
+    pub fn forget(&self, call_id: &str) {
+        self.preserved.remove(call_id);
+    }
+
+    pub fn preserved_calls(&self) -> Vec<PreservedCall> {
+        self.preserved.iter().map(|entry| entry.value().clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TrunkFailureOverride;
+
+    fn config() -> TrunkFailureConfig {
+        TrunkFailureConfig {
+            action: TrunkFailureAction::PreserveMedia,
+            preserve_secs: 30,
+            retry_interval_secs: 5,
+            overrides: vec![TrunkFailureOverride {
+                trunk: "carrier-b".to_string(),
+                action: TrunkFailureAction::Release,
+                preserve_secs: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_release_override() {
+        let handler = TrunkFailureHandler::new(config());
+        let now = Instant::now();
+        let disposition = handler.on_trunk_down("carrier-b", &["c1".to_string()], now);
+        assert_eq!(disposition, FailureDisposition::Release { cause: CAUSE_NETWORK_OUT_OF_ORDER });
+        assert!(!handler.is_preserved("c1"));
+    }
+
+    #[test]
+    fn test_preserved_call_recovers_when_trunk_returns() {
+        let handler = TrunkFailureHandler::new(config());
+        let now = Instant::now();
+        handler.on_trunk_down("carrier-a", &["c1".to_string(), "c2".to_string()], now);
+        assert!(handler.is_preserved("c1"));
+
+        let poll = handler.poll(now + Duration::from_secs(6));
+        assert_eq!(poll.reestablish.len(), 2);
+        assert!(poll.expired.is_empty());
+
+        let mut recovered = handler.on_trunk_up("carrier-a", now + Duration::from_secs(8));
+        recovered.sort();
+        assert_eq!(recovered, vec!["c1", "c2"]);
+        assert!(handler.preserved_calls().is_empty());
+    }
+
+    #[test]
+    fn test_preservation_expires() {
+        let handler = TrunkFailureHandler::new(config());
+        let now = Instant::now();
+        handler.on_trunk_down("carrier-a", &["c1".to_string()], now);
+
+        let poll = handler.poll(now + Duration::from_secs(31));
+        assert_eq!(poll.expired.len(), 1);
+        assert!(poll.reestablish.is_empty());
+        assert!(!handler.is_preserved("c1"));
+    }
+}
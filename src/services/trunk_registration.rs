@@ -0,0 +1,338 @@
+//! Outbound registration of trunks with their carriers
+//!
+//! Trunks that must register before a carrier accepts their calls are
+//! registered here and refreshed before the granted expiry runs out. A
+//! failed registration is retried on an interval that grows with each
+//! consecutive failure up to a cap, or longer when the registrar sends
+//! Retry-After. Every interval is jittered, and first registrations after
+//! startup are spread out, so that trunks failing together during an
+//! upstream outage do not all retry at the same moment once it recovers.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+
+use crate::config::{RegistrationBackoffConfig, TrunkRegistrationConfig};
+use crate::services::survivability::{RegisterReply, RegisterRequest, RegistrarUpstream, UdpRegistrarUpstream};
+
+/// Management API path listing trunk registration state
+pub const TRUNK_REGISTRATIONS_PATH: &str = "/api/v1/trunks/registrations";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TrunkRegistrationState {
+    /// Not yet attempted
+    Pending,
+    Registered,
+    /// The last attempt failed; retrying with backoff
+    Failing,
+}
+
+/// Registration state of one trunk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TrunkRegistrationStatus {
+    pub trunk: String,
+    pub registrar: String,
+    pub state: TrunkRegistrationState,
+    pub consecutive_failures: u32,
+    /// Final response to the last attempt, if one arrived
+    pub last_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub registered_until: Option<DateTime<Utc>>,
+    pub next_attempt_at: DateTime<Utc>,
+    /// Interval to the next attempt, after backoff and jitter
+    pub next_interval_secs: u64,
+}
+
+impl RegistrationBackoffConfig {
+    /// `secs` moved by up to the jitter share either way; `sample` in [0, 1)
+    /// picks where
+    fn jittered(&self, secs: f64, sample: f64) -> f64 {
+        let spread = secs * self.jitter_percent as f64 / 100.0;
+        secs - spread + 2.0 * spread * sample
+    }
+
+    /// Wait before retrying after `failures` consecutive failures
+    pub fn retry_delay(&self, failures: u32, sample: f64, retry_after: Option<Duration>) -> Duration {
+        let exponent = failures.saturating_sub(1).min(64) as i32;
+        let base = (self.initial_retry_secs as f64 * self.multiplier.powi(exponent)).min(self.max_retry_secs as f64);
+        // A registrar asking for a longer wait gets it
+        Duration::from_secs_f64(self.jittered(base, sample)).max(retry_after.unwrap_or_default())
+    }
+
+    /// Wait before refreshing a registration granted for `granted`: around
+    /// half of it, so a refresh that fails still has time to be retried
+    pub fn refresh_delay(&self, granted: Duration, sample: f64) -> Duration {
+        Duration::from_secs_f64(self.jittered(granted.as_secs_f64() / 2.0, sample))
+    }
+}
+
+/// Retry-After seconds of a response, ignoring any comment or parameters
+fn retry_after(reply: &RegisterReply) -> Option<Duration> {
+    let (_, value) = reply.headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("Retry-After"))?;
+    let seconds = value.split([' ', ';', '(']).next()?.parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+struct TrunkState {
+    status: TrunkRegistrationStatus,
+    next_attempt: Instant,
+    in_flight: bool,
+}
+
+/// Registers the configured trunks and keeps them registered
+pub struct TrunkRegistrar {
+    config: TrunkRegistrationConfig,
+    upstreams: HashMap<String, Arc<dyn RegistrarUpstream>>,
+    trunks: DashMap<String, TrunkState>,
+}
+
+impl TrunkRegistrar {
+    pub fn new(config: TrunkRegistrationConfig) -> Self {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let upstreams = config
+            .trunks
+            .iter()
+            .map(|entry| {
+                let upstream: Arc<dyn RegistrarUpstream> = Arc::new(UdpRegistrarUpstream::new(entry.registrar.clone(), timeout));
+                (entry.trunk.clone(), upstream)
+            })
+            .collect();
+        Self::with_upstreams(config, upstreams)
+    }
+
+    pub fn with_upstreams(config: TrunkRegistrationConfig, upstreams: HashMap<String, Arc<dyn RegistrarUpstream>>) -> Self {
+        let now = Instant::now();
+        let trunks = DashMap::new();
+        for entry in &config.trunks {
+            let delay = Duration::from_secs_f64(config.backoff.startup_spread_secs as f64 * rand::random::<f64>());
+            trunks.insert(entry.trunk.clone(), TrunkState {
+                status: TrunkRegistrationStatus {
+                    trunk: entry.trunk.clone(),
+                    registrar: entry.registrar.clone(),
+                    state: TrunkRegistrationState::Pending,
+                    consecutive_failures: 0,
+                    last_status: None,
+                    last_error: None,
+                    registered_until: None,
+                    next_attempt_at: Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default(),
+                    next_interval_secs: delay.as_secs(),
+                },
+                next_attempt: now + delay,
+                in_flight: false,
+            });
+        }
+        Self { config, upstreams, trunks }
+    }
+
+    /// Registration state of every trunk
+    pub fn statuses(&self) -> Vec<TrunkRegistrationStatus> {
+        let mut statuses: Vec<TrunkRegistrationStatus> = self.trunks.iter().map(|state| state.status.clone()).collect();
+        statuses.sort_by(|a, b| a.trunk.cmp(&b.trunk));
+        statuses
+    }
+
+    /// Whether `trunk` is registered, for trunks that register at all
+    pub fn is_registered(&self, trunk: &str) -> Option<bool> {
+        self.trunks.get(trunk).map(|state| state.status.state == TrunkRegistrationState::Registered)
+    }
+
+    /// Trunks whose next attempt is due, marked as in flight
+    fn take_due(&self, now: Instant) -> Vec<String> {
+        self.trunks
+            .iter_mut()
+            .filter(|state| !state.in_flight && state.next_attempt <= now)
+            .map(|mut state| {
+                state.in_flight = true;
+                state.status.trunk.clone()
+            })
+            .collect()
+    }
+
+    /// Register `trunk` once and schedule its next attempt
+    pub async fn register(&self, trunk: &str) -> Option<TrunkRegistrationState> {
+        let entry = self.config.trunks.iter().find(|entry| entry.trunk == trunk)?;
+        let upstream = self.upstreams.get(trunk)?;
+        let request = RegisterRequest {
+            user: entry.user.clone(),
+            contact: entry.contact.clone(),
+            expires: entry.expires_secs,
+            headers: Vec::new(),
+        };
+        let result = upstream.forward_register(&request).await;
+
+        let mut state = self.trunks.get_mut(trunk)?;
+        let sample = rand::random::<f64>();
+        let (delay, failure) = match result {
+            Ok(reply) if (200..300).contains(&reply.status_code) => {
+                let expires = if reply.expires > 0 { reply.expires } else { entry.expires_secs };
+                let granted = Duration::from_secs(expires as u64);
+                if state.status.consecutive_failures > 0 {
+                    info!("Trunk {} registered again after {} failures", trunk, state.status.consecutive_failures);
+                }
+                let status = &mut state.status;
+                status.state = TrunkRegistrationState::Registered;
+                status.consecutive_failures = 0;
+                status.last_status = Some(reply.status_code);
+                status.last_error = None;
+                status.registered_until = Some(Utc::now() + chrono::Duration::from_std(granted).unwrap_or_default());
+                (self.config.backoff.refresh_delay(granted, sample), None)
+            }
+            Ok(reply) => {
+                state.status.last_status = Some(reply.status_code);
+                let delay_hint = retry_after(&reply);
+                (Duration::ZERO, Some((format!("{} {}", reply.status_code, reply.reason), delay_hint)))
+            }
+            Err(e) => {
+                state.status.last_status = None;
+                (Duration::ZERO, Some((e.to_string(), None)))
+            }
+        };
+
+        let delay = match failure {
+            None => delay,
+            Some((error, hint)) => {
+                let status = &mut state.status;
+                status.consecutive_failures += 1;
+                status.state = TrunkRegistrationState::Failing;
+                // A registration still within its expiry is kept until it lapses
+                if status.registered_until.is_some_and(|until| until <= Utc::now()) {
+                    status.registered_until = None;
+                }
+                let delay = self.config.backoff.retry_delay(status.consecutive_failures, sample, hint);
+                if status.consecutive_failures == 1 {
+                    warn!("Registration of trunk {} failed: {}; retrying in {:?}", trunk, error, delay);
+                } else {
+                    debug!("Registration of trunk {} failed {} times: {}; retrying in {:?}", trunk, status.consecutive_failures, error, delay);
+                }
+                status.last_error = Some(error);
+                delay
+            }
+        };
+
+        state.next_attempt = Instant::now() + delay;
+        state.in_flight = false;
+        state.status.next_attempt_at = Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+        state.status.next_interval_secs = delay.as_secs();
+        Some(state.status.state)
+    }
+
+    /// Attempt each trunk as its timer comes due
+    pub fn spawn_monitor(self: &Arc<Self>) -> JoinHandle<()> {
+        let registrar = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut poll_interval = interval(Duration::from_secs(1));
+            loop {
+                poll_interval.tick().await;
+                for trunk in registrar.take_due(Instant::now()) {
+                    // One slow registrar must not hold up the others
+                    let registrar = Arc::clone(&registrar);
+                    tokio::spawn(async move {
+                        registrar.register(&trunk).await;
+                    });
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TrunkRegistrationEntry;
+    use crate::{Error, Result};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    struct ScriptedRegistrar {
+        replies: Mutex<Vec<Result<RegisterReply>>>,
+    }
+
+    #[async_trait]
+    impl RegistrarUpstream for ScriptedRegistrar {
+        async fn forward_register(&self, _request: &RegisterRequest) -> Result<RegisterReply> {
+            self.replies.lock().unwrap().remove(0)
+        }
+
+        async fn probe(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn reply(status_code: u16, headers: Vec<(&str, &str)>) -> Result<RegisterReply> {
+        Ok(RegisterReply {
+            status_code,
+            reason: "Reason".to_string(),
+            expires: if status_code == 200 { 600 } else { 0 },
+            headers: headers.into_iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        })
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off_with_jitter() {
+        let backoff = RegistrationBackoffConfig::default();
+        let secs = |failures, sample| backoff.retry_delay(failures, sample, None).as_secs();
+        assert_eq!(secs(1, 0.5), 30);
+        assert_eq!(secs(2, 0.5), 60);
+        assert_eq!(secs(4, 0.5), 240);
+        // Capped, however long the outage
+        assert_eq!(secs(40, 0.5), 1800);
+        // Jitter moves the interval by up to a quarter either way
+        assert_eq!(secs(3, 0.0), 90);
+        assert_eq!(secs(3, 0.999), 149);
+        assert_eq!(backoff.retry_delay(1, 0.5, Some(Duration::from_secs(300))).as_secs(), 300);
+        assert_eq!(backoff.refresh_delay(Duration::from_secs(3600), 0.5).as_secs(), 1800);
+    }
+
+    #[tokio::test]
+    async fn test_failures_back_off_until_registered() {
+        let config = TrunkRegistrationConfig {
+            enabled: true,
+            trunks: vec![TrunkRegistrationEntry {
+                trunk: "carrier-a".to_string(),
+                registrar: "198.51.100.10:5060".to_string(),
+                user: "trunk-a".to_string(),
+                contact: "<sip:trunk-a@203.0.113.5:5060>".to_string(),
+                expires_secs: 3600,
+            }],
+            backoff: RegistrationBackoffConfig { jitter_percent: 0, startup_spread_secs: 0, ..Default::default() },
+            ..Default::default()
+        };
+        let upstream: Arc<dyn RegistrarUpstream> = Arc::new(ScriptedRegistrar {
+            replies: Mutex::new(vec![
+                Err(Error::timeout("REGISTER timed out")),
+                reply(503, vec![("Retry-After", "120 (maintenance)")]),
+                Err(Error::timeout("REGISTER timed out")),
+                reply(200, vec![]),
+            ]),
+        });
+        let registrar = TrunkRegistrar::with_upstreams(config, HashMap::from([("carrier-a".to_string(), upstream)]));
+        assert_eq!(registrar.take_due(Instant::now()), vec!["carrier-a".to_string()]);
+        assert!(registrar.take_due(Instant::now()).is_empty());
+
+        assert_eq!(registrar.register("carrier-a").await, Some(TrunkRegistrationState::Failing));
+        assert_eq!(registrar.statuses()[0].next_interval_secs, 30);
+        registrar.register("carrier-a").await;
+        assert_eq!(registrar.statuses()[0].next_interval_secs, 120);
+        assert_eq!(registrar.statuses()[0].last_status, Some(503));
+        registrar.register("carrier-a").await;
+        let status = &registrar.statuses()[0];
+        assert_eq!((status.consecutive_failures, status.next_interval_secs), (3, 120));
+        assert_eq!(registrar.is_registered("carrier-a"), Some(false));
+
+        assert_eq!(registrar.register("carrier-a").await, Some(TrunkRegistrationState::Registered));
+        let status = &registrar.statuses()[0];
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.next_interval_secs, 300);
+        assert!(status.registered_until.is_some() && status.last_error.is_none());
+        assert_eq!(registrar.is_registered("carrier-b"), None);
+    }
+}
@@ -0,0 +1,288 @@
+//! Call-preserving in-place software upgrades
+//!
+//! Before the gateway replaces its own binary, established calls are written
+//! to a snapshot: the B2BUA call, both legs' SIP dialogs and both media relay
+//! endpoints. The new process restores them before it takes traffic,
+//! rebinding each relay socket on its previous port with the same SSRC,
+//! sequence and timestamp base so the far ends see one continuous stream,
+//! and re-latching each leg to the address its next packet arrives from.
+//! Media stops only between the old process exiting and the new one
+//! rebinding. Calls whose relay terminates SRTP or DTLS-SRTP are left out,
+//! since their keys never leave the process, and end with the upgrade.
+//! Snapshots older than the configured age are discarded, since by then the
+//! far ends will have given up on the calls.
+//!
+//! Only the binary named in the configuration is executed, and only once it
+//! hashes to the SHA-256 the operator gave with the request.
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::protocols::rtp::{RtpSession, RtpStreamStats};
+use crate::protocols::sip::{SessionDirection, SessionState, SipSession};
+use crate::services::b2bua::B2buaCall;
+use crate::{Error, Result};
+
+/// Management API path that snapshots calls and restarts into a new binary
+pub const UPGRADE_PATH: &str = "/api/v1/system/upgrade";
+
+/// Snapshot layout version; snapshots of another version are not restored
+pub const SNAPSHOT_FORMAT: u32 = 1;
+
+/// Operator request to restart into the configured binary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UpgradeRequest {
+    /// Hex SHA-256 of the binary as installed; the upgrade is refused when
+    /// the binary on disk differs
+    pub sha256: String,
+}
+
+/// A SIP dialog as needed to keep matching in-dialog requests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialogSnapshot {
+    pub session_id: String,
+    pub call_id: String,
+    pub direction: SessionDirection,
+    pub local_uri: String,
+    pub remote_uri: String,
+    pub local_tag: String,
+    pub remote_tag: Option<String>,
+    pub cseq: u32,
+    pub remote_cseq: u32,
+    pub contact: Option<String>,
+    pub remote_target: Option<SocketAddr>,
+    pub sdp: Option<String>,
+    pub remote_sdp: Option<String>,
+}
+
+impl From<&SipSession> for DialogSnapshot {
+    fn from(session: &SipSession) -> Self {
+        Self {
+            session_id: session.id.clone(),
+            call_id: session.call_id.clone(),
+            direction: session.direction.clone(),
+            local_uri: session.local_uri.clone(),
+            remote_uri: session.remote_uri.clone(),
+            local_tag: session.local_tag.clone(),
+            remote_tag: session.remote_tag.clone(),
+            cseq: session.cseq,
+            remote_cseq: session.remote_cseq,
+            contact: session.contact.clone(),
+            remote_target: session.remote_target,
+            sdp: session.sdp.clone(),
+            remote_sdp: session.remote_sdp.clone(),
+        }
+    }
+}
+
+impl DialogSnapshot {
+    pub fn into_session(self) -> SipSession {
+        let mut session = match self.direction {
+            SessionDirection::Inbound => SipSession::new_inbound(self.call_id, self.local_uri, self.remote_uri),
+            SessionDirection::Outbound => SipSession::new_outbound(self.call_id, self.local_uri, self.remote_uri),
+        };
+        session.id = self.session_id;
+        session.state = SessionState::Confirmed;
+        session.local_tag = self.local_tag;
+        session.remote_tag = self.remote_tag;
+        session.cseq = self.cseq;
+        session.remote_cseq = self.remote_cseq;
+        session.contact = self.contact;
+        session.remote_target = self.remote_target;
+        session.sdp = self.sdp;
+        session.remote_sdp = self.remote_sdp;
+        session
+    }
+}
+
+/// A media relay socket and the stream it sends
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RtpEndpoint {
+    pub session_id: String,
+    pub local_ip: IpAddr,
+    pub local_port: u16,
+    pub device: Option<String>,
+    pub remote_addr: Option<SocketAddr>,
+    pub ssrc: u32,
+    pub payload_type: u8,
+    pub sequence_number: u16,
+    pub timestamp_base: u32,
+}
+
+impl RtpEndpoint {
+    pub async fn capture(session: &RtpSession) -> Self {
+        Self {
+            session_id: session.id.clone(),
+            local_ip: session.local_ip,
+            local_port: session.local_port,
+            device: session.device.clone(),
+            remote_addr: session.remote_addr,
+            ssrc: session.ssrc,
+            payload_type: session.payload_type,
+            sequence_number: *session.sequence_number.read().await,
+            timestamp_base: session.timestamp_base,
+        }
+    }
+
+    pub fn into_session(self) -> RtpSession {
+        let mut session = RtpSession::new(self.session_id, self.local_port, self.payload_type);
+        session.local_ip = self.local_ip;
+        session.device = self.device;
+        session.remote_addr = self.remote_addr;
+        session.ssrc = self.ssrc;
+        session.stats = RtpStreamStats::new(self.ssrc);
+        session.sequence_number = Arc::new(RwLock::new(self.sequence_number));
+        session.timestamp_base = self.timestamp_base;
+        session
+    }
+}
+
+/// One established call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotCall {
+    pub call: B2buaCall,
+    /// Time since answer, to carry the call duration across the restart
+    pub connected_for_ms: u64,
+    pub leg_a_dialog: Option<DialogSnapshot>,
+    pub leg_b_dialog: Option<DialogSnapshot>,
+    pub leg_a_media: Option<RtpEndpoint>,
+    pub leg_b_media: Option<RtpEndpoint>,
+}
+
+/// Established calls handed from one process to the next
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallSnapshot {
+    pub format: u32,
+    /// Version of the gateway that took the snapshot
+    pub version: String,
+    pub taken_at: DateTime<Utc>,
+    pub calls: Vec<SnapshotCall>,
+}
+
+impl CallSnapshot {
+    pub fn new(calls: Vec<SnapshotCall>) -> Self {
+        Self {
+            format: SNAPSHOT_FORMAT,
+            version: crate::VERSION.to_string(),
+            taken_at: Utc::now(),
+            calls,
+        }
+    }
+}
+
+/// Write `snapshot` to `path`, replacing any earlier one atomically
+pub fn write_snapshot(path: &Path, snapshot: &CallSnapshot) -> Result<()> {
+    let body = serde_json::to_vec(snapshot)
+        .map_err(|e| Error::internal(format!("Failed to encode call snapshot: {}", e)))?;
+    let staging = path.with_extension("tmp");
+    std::fs::write(&staging, body)?;
+    std::fs::rename(&staging, path)?;
+    info!("Wrote snapshot of {} calls to {}", snapshot.calls.len(), path.display());
+    Ok(())
+}
+
+/// Read and remove the snapshot at `path`; a missing, stale or incompatible
+/// snapshot yields `None` so a failed upgrade never restores calls twice
+pub fn take_snapshot(path: &Path, max_age: Duration) -> Result<Option<CallSnapshot>> {
+    let body = match std::fs::read(path) {
+        Ok(body) => body,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    std::fs::remove_file(path)?;
+
+    let snapshot: CallSnapshot = serde_json::from_slice(&body)
+        .map_err(|e| Error::parse(format!("Invalid call snapshot {}: {}", path.display(), e)))?;
+    if snapshot.format != SNAPSHOT_FORMAT {
+        warn!("Ignoring call snapshot in format {} from version {}", snapshot.format, snapshot.version);
+        return Ok(None);
+    }
+    let age = (Utc::now() - snapshot.taken_at).to_std().unwrap_or_default();
+    if age > max_age {
+        warn!("Ignoring call snapshot taken {:?} ago", age);
+        return Ok(None);
+    }
+    Ok(Some(snapshot))
+}
+
+/// Check that `binary` hashes to the hex SHA-256 `expected`
+pub fn verify_binary(binary: &Path, expected: &str) -> Result<()> {
+    let digest = hex::encode(Sha256::digest(std::fs::read(binary)?));
+    if digest != expected.trim().to_ascii_lowercase() {
+        return Err(Error::invalid_config(format!("{} does not have SHA-256 {}", binary.display(), expected)));
+    }
+    Ok(())
+}
+
+/// Replace the running process with `binary`, keeping its arguments;
+/// returns only if the exec failed
+#[cfg(unix)]
+pub fn exec_binary(binary: &Path) -> Error {
+    use std::os::unix::process::CommandExt;
+
+    info!("Restarting into {}", binary.display());
+    let error = std::process::Command::new(binary).args(std::env::args_os().skip(1)).exec();
+    Error::invalid_config(format!("Failed to execute {}: {}", binary.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_media_endpoint_keeps_stream_identity() {
+        let mut session = RtpSession::new("call-1_leg_a".to_string(), 16384, 0);
+        session.remote_addr = Some("192.0.2.10:30000".parse().unwrap());
+        session.device = Some("vrf-media".to_string());
+        let sequence = session.next_sequence_number().await;
+
+        let restored = RtpEndpoint::capture(&session).await.into_session();
+        assert_eq!(restored.local_port, 16384);
+        assert_eq!(restored.ssrc, session.ssrc);
+        assert_eq!(restored.timestamp_base, session.timestamp_base);
+        assert_eq!(restored.device.as_deref(), Some("vrf-media"));
+        assert_eq!(restored.remote_addr, session.remote_addr);
+        assert_eq!(restored.next_sequence_number().await, sequence.wrapping_add(1));
+    }
+
+    #[test]
+    fn test_snapshot_is_taken_once_and_expires() {
+        let dir = std::env::temp_dir().join(format!("redfire-snapshot-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("calls.json");
+
+        write_snapshot(&path, &CallSnapshot::new(Vec::new())).unwrap();
+        assert!(take_snapshot(&path, Duration::from_secs(30)).unwrap().is_some());
+        assert!(take_snapshot(&path, Duration::from_secs(30)).unwrap().is_none());
+
+        let mut stale = CallSnapshot::new(Vec::new());
+        stale.taken_at = Utc::now() - chrono::Duration::seconds(60);
+        write_snapshot(&path, &stale).unwrap();
+        assert!(take_snapshot(&path, Duration::from_secs(30)).unwrap().is_none());
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_binary_must_match_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("redfire-gateway");
+        std::fs::write(&binary, b"new build").unwrap();
+        let sha256 = hex::encode(Sha256::digest(b"new build"));
+
+        assert!(verify_binary(&binary, &sha256).is_ok());
+        assert!(verify_binary(&binary, &sha256.to_ascii_uppercase()).is_ok());
+        assert!(verify_binary(&binary, &hex::encode(Sha256::digest(b"old build"))).is_err());
+        assert!(verify_binary(&dir.path().join("missing"), &sha256).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! RTCP-XR reporting on IP legs and vq-rtcpxr publication
+//!
+//! While a call is up, each plain RTP leg sends its far end an extended
+//! report on the report interval: VoIP metrics for the stream it receives
+//! and, when configured, a Loss RLE block. When the call ends, the metrics
+//! of the trunk leg feed that trunk's quality baseline, and with a
+//! collector configured each leg is published to it as a SIP PUBLISH
+//! carrying an RFC 6035 `vq-rtcpxr` session report.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+use crate::config::RtcpXrConfig;
+use crate::protocols::rtcp_xr::{VoipMetrics, UNAVAILABLE};
+use crate::protocols::rtp::RtpHandler;
+use crate::protocols::sip_via;
+use crate::services::b2bua::{B2buaCall, CallLeg};
+use crate::services::quality_baseline::{QualityBaselineMonitor, QualitySample};
+use crate::services::survivability::parse_response;
+use crate::{Error, Result};
+
+/// SIP event package of the reports
+pub const EVENT_PACKAGE: &str = "vq-rtcpxr";
+pub const CONTENT_TYPE: &str = "application/vq-rtcpxr";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VqReportKind {
+    /// Sent while the call is up
+    Interval,
+    /// Sent once the call has ended
+    CallTerm,
+}
+
+/// Quality of one leg's stream pair, as a vq-rtcpxr report describes it
+#[derive(Debug, Clone)]
+pub struct VqReport {
+    pub kind: VqReportKind,
+    pub call_id: String,
+    pub local_id: String,
+    pub remote_id: String,
+    pub orig_id: String,
+    pub local_addr: SocketAddr,
+    pub local_ssrc: u32,
+    pub remote_addr: Option<SocketAddr>,
+    pub remote_ssrc: u32,
+    pub payload_type: u8,
+    pub start: DateTime<Utc>,
+    pub stop: DateTime<Utc>,
+    /// Measured here, on the stream the leg receives
+    pub local: VoipMetrics,
+    /// Reported by the far end, on the stream it receives from us
+    pub remote: Option<VoipMetrics>,
+}
+
+impl VqReport {
+    /// Body of the PUBLISH
+    pub fn body(&self) -> String {
+        let mut body = String::from(match self.kind {
+            VqReportKind::CallTerm => "VQSessionReport: CallTerm\r\n",
+            VqReportKind::Interval => "VQIntervalReport\r\n",
+        });
+        body.push_str(&format!("CallID: {}\r\n", self.call_id));
+        body.push_str(&format!("LocalID: {}\r\n", self.local_id));
+        body.push_str(&format!("RemoteID: {}\r\n", self.remote_id));
+        body.push_str(&format!("OrigID: {}\r\n", self.orig_id));
+        body.push_str(&format!(
+            "LocalAddr: IP={} PORT={} SSRC=0x{:08x}\r\n",
+            self.local_addr.ip(),
+            self.local_addr.port(),
+            self.local_ssrc
+        ));
+        if let Some(remote) = self.remote_addr {
+            body.push_str(&format!("RemoteAddr: IP={} PORT={} SSRC=0x{:08x}\r\n", remote.ip(), remote.port(), self.remote_ssrc));
+        }
+        body.push_str("LocalMetrics:\r\n");
+        self.push_metrics(&mut body, &self.local);
+        if let Some(remote) = &self.remote {
+            body.push_str("RemoteMetrics:\r\n");
+            self.push_metrics(&mut body, remote);
+        }
+        body
+    }
+
+    fn push_metrics(&self, body: &mut String, metrics: &VoipMetrics) {
+        let time = |at: &DateTime<Utc>| at.to_rfc3339_opts(SecondsFormat::Secs, true);
+        let pct = |fraction: u8| fraction as f64 * 100.0 / 256.0;
+        body.push_str(&format!("Timestamps: START={} STOP={}\r\n", time(&self.start), time(&self.stop)));
+        match payload_name(self.payload_type) {
+            Some((name, rate)) => body.push_str(&format!("SessionDesc: PT={} PD={} SR={}\r\n", self.payload_type, name, rate)),
+            None => body.push_str(&format!("SessionDesc: PT={}\r\n", self.payload_type)),
+        }
+        body.push_str(&format!(
+            "JitterBuffer: JBN={} JBM={} JBX={}\r\n",
+            metrics.jb_nominal_ms, metrics.jb_maximum_ms, metrics.jb_abs_max_ms
+        ));
+        body.push_str(&format!("PacketLoss: NLR={:.1} JDR={:.1}\r\n", pct(metrics.loss_rate), pct(metrics.discard_rate)));
+        body.push_str(&format!(
+            "BurstGapLoss: BLD={:.1} BD={} GLD={:.1} GD={} GMIN={}\r\n",
+            pct(metrics.burst_density),
+            metrics.burst_duration_ms,
+            pct(metrics.gap_density),
+            metrics.gap_duration_ms,
+            metrics.gmin
+        ));
+        body.push_str(&format!("Delay: RTD={} ESD={}\r\n", metrics.round_trip_delay_ms, metrics.end_system_delay_ms));
+
+        let mut estimates = Vec::new();
+        if metrics.r_factor != UNAVAILABLE {
+            estimates.push(format!("RCQ={}", metrics.r_factor));
+        }
+        if let Some(mos) = metrics.mos_lq() {
+            estimates.push(format!("MOSLQ={:.1}", mos));
+        }
+        if let Some(mos) = metrics.mos_cq() {
+            estimates.push(format!("MOSCQ={:.1}", mos));
+        }
+        if !estimates.is_empty() {
+            body.push_str(&format!("QualityEst: {}\r\n", estimates.join(" ")));
+        }
+    }
+}
+
+/// Encoding name and clock rate of the static payload types
+fn payload_name(payload_type: u8) -> Option<(&'static str, u32)> {
+    match payload_type {
+        0 => Some(("PCMU", 8000)),
+        4 => Some(("G723", 8000)),
+        8 => Some(("PCMA", 8000)),
+        9 => Some(("G722", 8000)),
+        18 => Some(("G729", 8000)),
+        _ => None,
+    }
+}
+
+/// Sends reports to the collector over UDP, one transaction at a time
+pub struct VqPublisher {
+    collector: String,
+    uri: String,
+    timeout: Duration,
+}
+
+impl VqPublisher {
+    pub fn new(collector: impl Into<String>, uri: Option<String>, timeout: Duration) -> Self {
+        let collector = collector.into();
+        let uri = uri.unwrap_or_else(|| format!("sip:collector@{}", host_of(&collector)));
+        Self { collector, uri, timeout }
+    }
+
+    /// PUBLISH one report; returns the collector's final status
+    pub async fn publish(&self, report: &VqReport) -> Result<u16> {
+        let network_err = |e: std::io::Error| Error::network(format!("PUBLISH to {} failed: {}", self.collector, e));
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(network_err)?;
+        socket.connect(&self.collector).await.map_err(network_err)?;
+        let local = socket.local_addr().map_err(network_err)?;
+
+        let host = host_of(&self.collector);
+        let branch = format!("z9hG4bK{:x}", rand::random::<u64>());
+        let body = report.body();
+        let message = format!(
+            "PUBLISH {uri} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch={branch};rport\r\n\
+             Max-Forwards: 70\r\n\
+             From: <sip:gateway@{host}>;tag={tag:x}\r\n\
+             To: <{uri}>\r\n\
+             Call-ID: {call_id:x}@{host}\r\n\
+             CSeq: 1 PUBLISH\r\n\
+             Event: {EVENT_PACKAGE}\r\n\
+             Content-Type: {CONTENT_TYPE}\r\n\
+             Content-Length: {length}\r\n\r\n{body}",
+            uri = self.uri,
+            tag = rand::random::<u32>(),
+            call_id = rand::random::<u64>(),
+            length = body.len(),
+        );
+        socket.send(message.as_bytes()).await.map_err(network_err)?;
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let len = tokio::time::timeout_at(deadline, socket.recv(&mut buf))
+                .await
+                .map_err(|_| Error::timeout(format!("PUBLISH to {} timed out", self.collector)))?
+                .map_err(network_err)?;
+            let reply = parse_response(&buf[..len])?;
+            if let Err(e) = sip_via::check_response(&reply.headers, &branch) {
+                debug!("Ignoring response from {}: {}", self.collector, e);
+                continue;
+            }
+            match reply.status_code {
+                100..=199 => continue,
+                200..=299 => return Ok(reply.status_code),
+                status => {
+                    return Err(Error::protocol(format!("Collector {} answered PUBLISH with {} {}", self.collector, status, reply.reason)))
+                }
+            }
+        }
+    }
+}
+
+/// host part of a `host:port` address
+fn host_of(address: &str) -> &str {
+    address.rsplit_once(':').map(|(host, _)| host).unwrap_or(address)
+}
+
+/// Extended reports of every connected call, and their publication
+pub struct RtcpXrReporter {
+    config: RtcpXrConfig,
+    publisher: Option<Arc<VqPublisher>>,
+    quality_monitor: Option<Arc<QualityBaselineMonitor>>,
+}
+
+impl RtcpXrReporter {
+    pub fn new(config: RtcpXrConfig, quality_monitor: Option<Arc<QualityBaselineMonitor>>) -> Self {
+        let publisher = config.collector.as_ref().map(|collector| {
+            let timeout = Duration::from_millis(config.publish_timeout_ms);
+            Arc::new(VqPublisher::new(collector.clone(), config.collector_uri.clone(), timeout))
+        });
+        Self { config, publisher, quality_monitor }
+    }
+
+    pub fn config(&self) -> &RtcpXrConfig {
+        &self.config
+    }
+
+    /// Send each plain RTP leg of a connected call its extended report
+    pub async fn send_reports(&self, call: &B2buaCall, rtp: &RtpHandler) {
+        for session_id in [&call.leg_a_rtp_session_id, &call.leg_b_rtp_session_id].into_iter().flatten() {
+            let Some(remote) = rtp.get_session(session_id).and_then(|session| session.remote_addr) else {
+                continue;
+            };
+            let Some(report) = rtp.extended_report(session_id, self.config.packet_ms, self.config.loss_rle) else {
+                continue;
+            };
+            // Without rtcp-mux the far end listens for RTCP one port up
+            let target = SocketAddr::new(remote.ip(), remote.port().saturating_add(1));
+            if let Err(e) = rtp.send_extended_report(session_id, &report, target).await {
+                debug!("No RTCP XR for session {} of call {}: {}", session_id, call.id, e);
+            }
+        }
+        if self.config.interval_reports {
+            for (_, report, _) in self.leg_reports(call, rtp, VqReportKind::Interval) {
+                self.publish(report);
+            }
+        }
+    }
+
+    /// Final metrics of each leg of an ended call. The trunk leg feeds the
+    /// trunk's quality baseline, and every leg is published
+    pub fn call_ended(&self, call: &B2buaCall, rtp: &RtpHandler) -> Vec<(CallLeg, VoipMetrics)> {
+        let mut ended = Vec::new();
+        for (leg, report, jitter_ms) in self.leg_reports(call, rtp, VqReportKind::CallTerm) {
+            let trunk = call.routing_info.target_gateway.as_deref();
+            if let (CallLeg::B, Some(monitor), Some(trunk), Some(mos)) = (leg, &self.quality_monitor, trunk, report.local.mos_lq()) {
+                let sample = QualitySample { mos, loss_pct: report.local.loss_pct(), jitter_ms };
+                monitor.record(trunk, sample, Utc::now(), std::time::Instant::now());
+            }
+            ended.push((leg, report.local));
+            self.publish(report);
+        }
+        ended
+    }
+
+    /// Report of each leg with media, with the jitter measured on it
+    fn leg_reports(&self, call: &B2buaCall, rtp: &RtpHandler, kind: VqReportKind) -> Vec<(CallLeg, VqReport, f64)> {
+        let Some(connected_at) = call.connected_at else {
+            return Vec::new();
+        };
+        let stop = Utc::now();
+        let start = chrono::Duration::from_std(connected_at.elapsed()).map_or(stop, |elapsed| stop - elapsed);
+
+        let legs = [(CallLeg::A, &call.leg_a_rtp_session_id), (CallLeg::B, &call.leg_b_rtp_session_id)];
+        let mut reports = Vec::new();
+        for (leg, session_id) in legs {
+            let Some(session_id) = session_id else {
+                continue;
+            };
+            let (Some(session), Some(xr)) = (rtp.get_session(session_id), rtp.extended_report(session_id, self.config.packet_ms, false)) else {
+                continue;
+            };
+            let Some(local) = xr.voip_metrics().copied() else {
+                continue;
+            };
+            // Towards the caller the gateway stands in for the callee, and the other way round
+            let (local_id, remote_id) = match leg {
+                CallLeg::A => (&call.callee, &call.caller),
+                CallLeg::B => (&call.caller, &call.callee),
+            };
+            let report = VqReport {
+                kind,
+                call_id: call.id.clone(),
+                local_id: local_id.clone(),
+                remote_id: remote_id.clone(),
+                orig_id: call.caller.clone(),
+                local_addr: SocketAddr::new(session.local_ip, session.local_port),
+                local_ssrc: session.ssrc,
+                remote_addr: session.remote_addr,
+                remote_ssrc: session.stats.ssrc,
+                payload_type: session.payload_type,
+                start,
+                stop,
+                local,
+                remote: session.remote_metrics,
+            };
+            reports.push((leg, report, session.stats.jitter));
+        }
+        reports
+    }
+
+    fn publish(&self, report: VqReport) {
+        let Some(publisher) = self.publisher.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            match publisher.publish(&report).await {
+                Ok(status) => debug!("Published quality of call {} ({})", report.call_id, status),
+                Err(e) => warn!("Failed to publish quality of call {}: {}", report.call_id, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::rtcp_xr::{CodecImpairment, LossStatistics, DEFAULT_GMIN};
+
+    fn report() -> VqReport {
+        let stats = LossStatistics {
+            received: 2940,
+            lost: 60,
+            loss_runs: 20,
+            bursts: 2,
+            burst_packets: 40,
+            burst_lost: 30,
+            gap_packets: 2960,
+            gap_lost: 30,
+        };
+        let local = VoipMetrics::measure(0x2222, &stats, DEFAULT_GMIN, 20, Some(80), CodecImpairment::for_payload_type(0));
+        let start: DateTime<Utc> = "2026-03-02T10:00:00Z".parse().unwrap();
+        VqReport {
+            kind: VqReportKind::CallTerm,
+            call_id: "call-1".to_string(),
+            local_id: "<sip:2000@gw.example.com>".to_string(),
+            remote_id: "<sip:1000@pbx.example.com>".to_string(),
+            orig_id: "<sip:1000@pbx.example.com>".to_string(),
+            local_addr: "192.0.2.1:20000".parse().unwrap(),
+            local_ssrc: 0x1111,
+            remote_addr: Some("192.0.2.50:30000".parse().unwrap()),
+            remote_ssrc: 0x2222,
+            payload_type: 0,
+            start,
+            stop: start + chrono::Duration::seconds(60),
+            local,
+            remote: None,
+        }
+    }
+
+    #[test]
+    fn test_report_body() {
+        let body = report().body();
+        let lines: Vec<&str> = body.split("\r\n").collect();
+        assert_eq!(lines[0], "VQSessionReport: CallTerm");
+        assert!(lines.contains(&"LocalAddr: IP=192.0.2.1 PORT=20000 SSRC=0x00001111"));
+        assert!(lines.contains(&"Timestamps: START=2026-03-02T10:00:00Z STOP=2026-03-02T10:01:00Z"));
+        assert!(lines.contains(&"SessionDesc: PT=0 PD=PCMU SR=8000"));
+        assert!(lines.contains(&"PacketLoss: NLR=2.0 JDR=0.0"));
+        assert!(lines.contains(&"BurstGapLoss: BLD=75.0 BD=400 GLD=0.8 GD=19733 GMIN=16"));
+        assert!(lines.iter().any(|line| line.starts_with("QualityEst: RCQ=") && line.contains("MOSLQ=")));
+        assert!(!body.contains("RemoteMetrics:"));
+    }
+
+    #[tokio::test]
+    async fn test_publish_to_collector() {
+        let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            let (len, peer) = collector.recv_from(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..len]).to_string();
+            let via = request.lines().find(|line| line.starts_with("Via:")).unwrap().to_string();
+            let reply = format!("SIP/2.0 200 OK\r\n{}\r\nCSeq: 1 PUBLISH\r\nContent-Length: 0\r\n\r\n", via);
+            collector.send_to(reply.as_bytes(), peer).await.unwrap();
+            request
+        });
+
+        let publisher = VqPublisher::new(address, None, Duration::from_secs(2));
+        assert_eq!(publisher.publish(&report()).await.unwrap(), 200);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("PUBLISH sip:collector@127.0.0.1 SIP/2.0\r\n"));
+        assert!(request.contains("\r\nEvent: vq-rtcpxr\r\n"));
+        assert!(request.contains("\r\nContent-Type: application/vq-rtcpxr\r\n"));
+        assert!(request.ends_with(&report().body()));
+    }
+}
@@ -1,4 +1,9 @@
 //! Testing modules for the Redfire Gateway
 
 // Re-export testing services from the services module
-pub use crate::services::testing::*;
\ No newline at end of file
+pub use crate::services::testing::*;
+pub mod pstn_sim;
+pub mod q931_conformance;
+
+pub use pstn_sim::{PstnSimConfig, PstnSimulator, SimEvent};
+pub use q931_conformance::{ConformanceConfig, ConformanceSuite, ConformanceTarget, ReportCard};
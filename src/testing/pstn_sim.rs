@@ -0,0 +1,646 @@
+//! Simulated PSTN peer
+//!
+//! Plays the network side of a PRI over TDMoE so the gateway can be
+//! exercised without telecom hardware: SETUPs from the gateway are answered
+//! with CALL PROCEEDING, ALERTING and CONNECT (or rejected for configured
+//! busy/unallocated numbers), and inbound calls are originated from a script.
+//! SETUPs missing mandatory elements, or naming a channel that does not
+//! exist, are refused with the Q.931 cause for the error, and messages for
+//! unknown call references get RELEASE COMPLETE with cause 81. The network
+//! timers T303, T305 and T308 run with their Q.931 default values.
+//! Q.931 messages travel on TDMoE control frames on the D-channel timeslot.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use bytes::Bytes;
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+use crate::config::Layer1Type;
+use crate::interfaces::tdmoe::{FrameType, TdmoeFrame};
+use crate::protocols::q931::{
+    MessageType, Q931Message, CAUSE_CHANNEL_DOES_NOT_EXIST, CAUSE_CHANNEL_UNAVAILABLE, CAUSE_INVALID_CALL_REFERENCE,
+    CAUSE_INVALID_IE_CONTENTS, CAUSE_MANDATORY_IE_MISSING, CAUSE_NORMAL_CLEARING, CAUSE_RECOVERY_ON_TIMER_EXPIRY,
+    CAUSE_UNALLOCATED_NUMBER, CAUSE_USER_BUSY, IE_BEARER_CAPABILITY, IE_CHANNEL_ID,
+};
+use crate::{Error, Result};
+
+/// Q.931 progress description: in-band information now available
+const PROGRESS_INBAND: u8 = 8;
+
+/// Network-side timer defaults (Q.931 table 9-1)
+pub const T303: Duration = Duration::from_secs(4);
+pub const T305: Duration = Duration::from_secs(30);
+pub const T308: Duration = Duration::from_secs(4);
+
+/// One period of the µ-law digital milliwatt (1004 Hz, 0 dBm0)
+const MILLIWATT: [u8; 8] = [0x1e, 0x0b, 0x0b, 0x1e, 0x9e, 0x8b, 0x8b, 0x9e];
+
+/// Simulator behaviour and inbound call script
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PstnSimConfig {
+    pub span_type: Layer1Type,
+    pub alerting_delay_ms: u64,
+    pub answer_delay_ms: u64,
+    /// Called numbers rejected with cause 17 (user busy)
+    pub busy_numbers: Vec<String>,
+    /// Called numbers rejected with cause 1 (unallocated number)
+    pub unallocated_numbers: Vec<String>,
+    /// Send a milliwatt tone on answered channels
+    pub play_tone: bool,
+    #[serde(rename = "call")]
+    pub calls: Vec<ScriptedCall>,
+}
+
+impl Default for PstnSimConfig {
+    fn default() -> Self {
+        Self {
+            span_type: Layer1Type::E1,
+            alerting_delay_ms: 200,
+            answer_delay_ms: 2000,
+            busy_numbers: Vec::new(),
+            unallocated_numbers: Vec::new(),
+            play_tone: true,
+            calls: Vec::new(),
+        }
+    }
+}
+
+impl PstnSimConfig {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| Error::parse(format!("Invalid simulator script: {}", e)))
+    }
+}
+
+/// Inbound call placed towards the gateway
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptedCall {
+    /// Seconds after start of the first attempt
+    pub at_secs: u64,
+    pub calling: String,
+    pub called: String,
+    /// Seconds the call is held after answer before the network clears it
+    #[serde(default = "default_hold_secs")]
+    pub hold_secs: u64,
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    /// Seconds between repeated attempts
+    #[serde(default)]
+    pub interval_secs: u64,
+}
+
+fn default_hold_secs() -> u64 {
+    30
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+/// Timeslot carrying the D-channel of a PRI of `span_type`
+pub fn d_channel(span_type: &Layer1Type) -> u16 {
+    match span_type {
+        Layer1Type::E1 => 16,
+        Layer1Type::T1 => 24,
+    }
+}
+
+/// Call reference and whether the simulator (network side) allocated it
+pub type CallKey = (u16, bool);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SimCallState {
+    /// SETUP sent to the gateway and not yet answered
+    Present,
+    Proceeding,
+    Alerting,
+    Active,
+    /// DISCONNECT sent, waiting for RELEASE
+    Disconnecting,
+    /// RELEASE sent, waiting for RELEASE COMPLETE
+    Releasing,
+}
+
+#[derive(Debug, Clone)]
+struct SimCall {
+    channel: u8,
+    state: SimCallState,
+    hold: Option<Duration>,
+    /// SETUP sent for a scripted call, kept to send again on T303 expiry
+    setup: Option<Q931Message>,
+    /// The running T303 or T308 has already expired once
+    retransmitted: bool,
+}
+
+impl SimCall {
+    fn new(channel: u8, state: SimCallState) -> Self {
+        Self { channel, state, hold: None, setup: None, retransmitted: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimTimer {
+    Alert,
+    Answer,
+    Hangup,
+    T303,
+    T305,
+    T308,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimEvent {
+    /// The gateway placed a call towards the network
+    CallOffered {
+        call_reference: u16,
+        calling: Option<String>,
+        called: String,
+        channel: u8,
+    },
+    /// The simulator placed a scripted call towards the gateway
+    CallPlaced {
+        call_reference: u16,
+        calling: String,
+        called: String,
+        channel: u8,
+    },
+    CallAnswered {
+        call_reference: u16,
+        channel: u8,
+    },
+    CallCleared {
+        call_reference: u16,
+        channel: u8,
+        cause: u8,
+    },
+    CallRejected {
+        called: String,
+        cause: u8,
+    },
+    ProtocolError {
+        message: String,
+    },
+}
+
+/// What the simulator wants done after an input
+#[derive(Debug, Default)]
+pub struct SimOutput {
+    pub messages: Vec<Q931Message>,
+    pub timers: Vec<(Duration, CallKey, SimTimer)>,
+    pub events: Vec<SimEvent>,
+}
+
+/// Network-side call control state machine
+pub struct PstnSimulator {
+    config: PstnSimConfig,
+    calls: HashMap<CallKey, SimCall>,
+    next_reference: u16,
+}
+
+impl PstnSimulator {
+    pub fn new(config: PstnSimConfig) -> Self {
+        Self {
+            config,
+            calls: HashMap::new(),
+            next_reference: 1,
+        }
+    }
+
+    /// Timeslot carrying the D-channel
+    pub fn d_channel(&self) -> u16 {
+        d_channel(&self.config.span_type)
+    }
+
+    fn is_bearer(&self, channel: u8) -> bool {
+        match self.config.span_type {
+            Layer1Type::E1 => (1..=31).contains(&channel) && channel != 16,
+            Layer1Type::T1 => (1..=23).contains(&channel),
+        }
+    }
+
+    fn channel_in_use(&self, channel: u8) -> bool {
+        self.calls.values().any(|call| call.channel == channel)
+    }
+
+    fn free_channel(&self) -> Option<u8> {
+        (1..=31).find(|&channel| self.is_bearer(channel) && !self.channel_in_use(channel))
+    }
+
+    /// Channels with an answered call
+    pub fn active_channels(&self) -> Vec<u8> {
+        self.calls
+            .values()
+            .filter(|call| call.state == SimCallState::Active)
+            .map(|call| call.channel)
+            .collect()
+    }
+
+    fn reply(key: CallKey, message_type: MessageType) -> Q931Message {
+        // The flag is set when answering for a reference the gateway allocated
+        Q931Message::new(message_type, key.0, !key.1)
+    }
+
+    /// Handle a message from the gateway
+    pub fn handle_message(&mut self, message: Q931Message) -> SimOutput {
+        let key = (message.call_reference, message.from_destination);
+        let mut output = SimOutput::default();
+
+        if message.message_type == MessageType::Setup {
+            // A SETUP for a call reference in use is ignored (Q.931 §5.8.3.2)
+            if !self.calls.contains_key(&key) {
+                self.offer(key, &message, &mut output);
+            }
+            return output;
+        }
+
+        let Some(call) = self.calls.get_mut(&key) else {
+            match message.message_type {
+                MessageType::Release => output.messages.push(Self::reply(key, MessageType::ReleaseComplete)),
+                MessageType::ReleaseComplete => {}
+                _ => {
+                    output.messages.push(
+                        Self::reply(key, MessageType::ReleaseComplete).with_cause(CAUSE_INVALID_CALL_REFERENCE),
+                    );
+                    output.events.push(SimEvent::ProtocolError {
+                        message: format!("{:?} for unknown call reference {}", message.message_type, key.0),
+                    });
+                }
+            }
+            return output;
+        };
+        let channel = call.channel;
+        let cause = message.cause().unwrap_or(CAUSE_NORMAL_CLEARING);
+
+        match message.message_type {
+            MessageType::Alerting => call.state = SimCallState::Alerting,
+            MessageType::Connect => {
+                call.state = SimCallState::Active;
+                output.messages.push(Self::reply(key, MessageType::ConnectAck));
+                output.events.push(SimEvent::CallAnswered { call_reference: key.0, channel });
+                if let Some(hold) = call.hold {
+                    output.timers.push((hold, key, SimTimer::Hangup));
+                }
+            }
+            MessageType::Disconnect => {
+                call.state = SimCallState::Releasing;
+                call.retransmitted = false;
+                output.messages.push(Self::reply(key, MessageType::Release));
+                output.timers.push((T308, key, SimTimer::T308));
+                output.events.push(SimEvent::CallCleared { call_reference: key.0, channel, cause });
+            }
+            MessageType::Release | MessageType::ReleaseComplete => {
+                let already_cleared = matches!(call.state, SimCallState::Disconnecting | SimCallState::Releasing);
+                self.calls.remove(&key);
+                if message.message_type == MessageType::Release {
+                    output.messages.push(Self::reply(key, MessageType::ReleaseComplete));
+                }
+                if !already_cleared {
+                    output.events.push(SimEvent::CallCleared { call_reference: key.0, channel, cause });
+                }
+            }
+            MessageType::CallProceeding if call.state == SimCallState::Present => call.state = SimCallState::Proceeding,
+            MessageType::Hold => output.messages.push(Self::reply(key, MessageType::HoldAcknowledge)),
+            MessageType::Retrieve => output.messages.push(Self::reply(key, MessageType::RetrieveAcknowledge)),
+            MessageType::CallProceeding | MessageType::Progress | MessageType::ConnectAck | MessageType::Setup => {}
+            MessageType::HoldAcknowledge
+            | MessageType::HoldReject
+            | MessageType::RetrieveAcknowledge
+            | MessageType::RetrieveReject => {}
+        }
+        output
+    }
+
+    fn offer(&mut self, key: CallKey, setup: &Q931Message, output: &mut SimOutput) {
+        let reject = |output: &mut SimOutput, called: String, cause: u8| {
+            output.messages.push(Self::reply(key, MessageType::ReleaseComplete).with_cause(cause));
+            output.events.push(SimEvent::CallRejected { called, cause });
+        };
+
+        // Mandatory elements first: bearer capability needs octets 3 and 4,
+        // and a channel identification must name a channel
+        let bearer = setup.ie(IE_BEARER_CAPABILITY);
+        let error = match bearer {
+            None => Some(("SETUP without bearer capability", CAUSE_MANDATORY_IE_MISSING)),
+            Some(contents) if contents.len() < 2 => Some(("SETUP with truncated bearer capability", CAUSE_INVALID_IE_CONTENTS)),
+            _ if setup.ie(IE_CHANNEL_ID).is_some() && setup.channel().is_none() => {
+                Some(("SETUP with unreadable channel identification", CAUSE_INVALID_IE_CONTENTS))
+            }
+            _ => None,
+        };
+        if let Some((message, cause)) = error {
+            output.events.push(SimEvent::ProtocolError { message: message.to_string() });
+            return reject(output, setup.called_number().unwrap_or_default(), cause);
+        }
+
+        let Some(called) = setup.called_number() else {
+            output.events.push(SimEvent::ProtocolError { message: "SETUP without called number".to_string() });
+            reject(output, String::new(), CAUSE_UNALLOCATED_NUMBER);
+            return;
+        };
+        if self.config.unallocated_numbers.contains(&called) {
+            return reject(output, called, CAUSE_UNALLOCATED_NUMBER);
+        }
+        if self.config.busy_numbers.contains(&called) {
+            return reject(output, called, CAUSE_USER_BUSY);
+        }
+
+        let channel = match setup.channel() {
+            Some(channel) if !self.is_bearer(channel) => return reject(output, called, CAUSE_CHANNEL_DOES_NOT_EXIST),
+            Some(channel) if !self.channel_in_use(channel) => channel,
+            Some(_) => return reject(output, called, CAUSE_CHANNEL_UNAVAILABLE),
+            None => match self.free_channel() {
+                Some(channel) => channel,
+                None => return reject(output, called, CAUSE_CHANNEL_UNAVAILABLE),
+            },
+        };
+
+        self.calls.insert(key, SimCall::new(channel, SimCallState::Proceeding));
+        output.messages.push(Self::reply(key, MessageType::CallProceeding).with_channel(channel));
+        output.timers.push((Duration::from_millis(self.config.alerting_delay_ms), key, SimTimer::Alert));
+        output.timers.push((Duration::from_millis(self.config.answer_delay_ms), key, SimTimer::Answer));
+        output.events.push(SimEvent::CallOffered {
+            call_reference: key.0,
+            calling: setup.calling_number(),
+            called,
+            channel,
+        });
+    }
+
+    /// Place a call towards the gateway, clearing it `hold` after answer
+    pub fn originate(&mut self, calling: &str, called: &str, hold: Duration) -> SimOutput {
+        let mut output = SimOutput::default();
+        let Some(channel) = self.free_channel() else {
+            output.events.push(SimEvent::CallRejected { called: called.to_string(), cause: CAUSE_CHANNEL_UNAVAILABLE });
+            return output;
+        };
+
+        let call_reference = self.next_reference;
+        self.next_reference = if self.next_reference >= 0x7fff { 1 } else { self.next_reference + 1 };
+        let key = (call_reference, true);
+
+        let setup = Self::reply(key, MessageType::Setup)
+            .with_speech_bearer()
+            .with_channel(channel)
+            .with_calling_number(calling)
+            .with_called_number(called);
+        self.calls.insert(
+            key,
+            SimCall { hold: Some(hold), setup: Some(setup.clone()), ..SimCall::new(channel, SimCallState::Present) },
+        );
+        output.messages.push(setup);
+        output.timers.push((T303, key, SimTimer::T303));
+        output.events.push(SimEvent::CallPlaced {
+            call_reference,
+            calling: calling.to_string(),
+            called: called.to_string(),
+            channel,
+        });
+        output
+    }
+
+    pub fn on_timer(&mut self, key: CallKey, timer: SimTimer) -> SimOutput {
+        let mut output = SimOutput::default();
+        let Some(call) = self.calls.get_mut(&key) else {
+            return output;
+        };
+
+        match (timer, call.state) {
+            (SimTimer::Alert, SimCallState::Proceeding) => {
+                call.state = SimCallState::Alerting;
+                output.messages.push(Self::reply(key, MessageType::Alerting).with_progress(PROGRESS_INBAND));
+            }
+            (SimTimer::Answer, SimCallState::Proceeding | SimCallState::Alerting) => {
+                call.state = SimCallState::Active;
+                output.messages.push(Self::reply(key, MessageType::Connect));
+                output.events.push(SimEvent::CallAnswered { call_reference: key.0, channel: call.channel });
+            }
+            (SimTimer::Hangup, SimCallState::Active) => {
+                call.state = SimCallState::Disconnecting;
+                output.messages.push(Self::reply(key, MessageType::Disconnect).with_cause(CAUSE_NORMAL_CLEARING));
+                output.timers.push((T305, key, SimTimer::T305));
+                output.events.push(SimEvent::CallCleared {
+                    call_reference: key.0,
+                    channel: call.channel,
+                    cause: CAUSE_NORMAL_CLEARING,
+                });
+            }
+            // The SETUP is sent once more, then the call is given up
+            (SimTimer::T303, SimCallState::Present) => match call.setup.clone() {
+                Some(setup) if !call.retransmitted => {
+                    call.retransmitted = true;
+                    output.messages.push(setup);
+                    output.timers.push((T303, key, SimTimer::T303));
+                }
+                _ => {
+                    let channel = call.channel;
+                    self.calls.remove(&key);
+                    output.messages.push(
+                        Self::reply(key, MessageType::ReleaseComplete).with_cause(CAUSE_RECOVERY_ON_TIMER_EXPIRY),
+                    );
+                    output.events.push(SimEvent::CallCleared {
+                        call_reference: key.0,
+                        channel,
+                        cause: CAUSE_RECOVERY_ON_TIMER_EXPIRY,
+                    });
+                }
+            },
+            // No RELEASE for our DISCONNECT: release anyway
+            (SimTimer::T305, SimCallState::Disconnecting) => {
+                call.state = SimCallState::Releasing;
+                call.retransmitted = false;
+                output.messages.push(Self::reply(key, MessageType::Release).with_cause(CAUSE_NORMAL_CLEARING));
+                output.timers.push((T308, key, SimTimer::T308));
+            }
+            // RELEASE is sent once more, then the call reference and channel are freed
+            (SimTimer::T308, SimCallState::Releasing) => {
+                if call.retransmitted {
+                    self.calls.remove(&key);
+                } else {
+                    call.retransmitted = true;
+                    output.messages.push(Self::reply(key, MessageType::Release).with_cause(CAUSE_NORMAL_CLEARING));
+                    output.timers.push((T308, key, SimTimer::T308));
+                }
+            }
+            _ => {}
+        }
+        output
+    }
+
+    /// Exchange frames with the gateway at `gateway` until the socket fails
+    pub async fn run(
+        mut self,
+        socket: UdpSocket,
+        gateway: SocketAddr,
+        events: mpsc::UnboundedSender<SimEvent>,
+    ) -> Result<()> {
+        enum Input {
+            Timer(CallKey, SimTimer),
+            Originate { calling: String, called: String, hold: Duration },
+        }
+
+        let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+        for scripted in self.config.calls.clone() {
+            let input_tx = input_tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(scripted.at_secs)).await;
+                for attempt in 0..scripted.repeat {
+                    if attempt > 0 {
+                        tokio::time::sleep(Duration::from_secs(scripted.interval_secs)).await;
+                    }
+                    let originate = Input::Originate {
+                        calling: scripted.calling.clone(),
+                        called: scripted.called.clone(),
+                        hold: Duration::from_secs(scripted.hold_secs),
+                    };
+                    if input_tx.send(originate).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        let d_channel = self.d_channel();
+        let mut sequence = 0u32;
+        let mut send = |frame_type: FrameType, channel: u16, payload: Bytes| {
+            let mut frame = TdmoeFrame::new(frame_type, channel, payload);
+            sequence = sequence.wrapping_add(1);
+            frame.sequence = sequence;
+            frame.encode()
+        };
+
+        let tone = Bytes::from(MILLIWATT.repeat(20));
+        let mut tone_tick = interval(Duration::from_millis(20));
+        let mut buf = vec![0u8; 2048];
+
+        loop {
+            let output = tokio::select! {
+                received = socket.recv_from(&mut buf) => {
+                    let (len, source) = received?;
+                    if source != gateway {
+                        continue;
+                    }
+                    let frame = match TdmoeFrame::decode(Bytes::copy_from_slice(&buf[..len])) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            debug!("Ignoring frame from {}: {}", source, e);
+                            continue;
+                        }
+                    };
+                    if frame.frame_type != FrameType::Control || frame.channel != d_channel {
+                        continue;
+                    }
+                    match Q931Message::decode(&frame.payload) {
+                        Ok(message) => self.handle_message(message),
+                        Err(e) => {
+                            warn!("Undecodable D-channel message: {}", e);
+                            SimOutput::default()
+                        }
+                    }
+                }
+                Some(input) = input_rx.recv() => match input {
+                    Input::Timer(key, timer) => self.on_timer(key, timer),
+                    Input::Originate { calling, called, hold } => self.originate(&calling, &called, hold),
+                },
+                _ = tone_tick.tick(), if self.config.play_tone => {
+                    for channel in self.active_channels() {
+                        let data = send(FrameType::Voice, channel as u16, tone.clone());
+                        socket.send_to(&data, gateway).await?;
+                    }
+                    continue;
+                }
+            };
+
+            for message in output.messages {
+                let data = send(FrameType::Control, d_channel, message.encode());
+                socket.send_to(&data, gateway).await?;
+            }
+            for (delay, key, timer) in output.timers {
+                let input_tx = input_tx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = input_tx.send(Input::Timer(key, timer));
+                });
+            }
+            for event in output.events {
+                let _ = events.send(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(output: &SimOutput) -> Vec<MessageType> {
+        output.messages.iter().map(|message| message.message_type).collect()
+    }
+
+    #[test]
+    fn test_outbound_call_is_answered_or_rejected() {
+        let mut sim = PstnSimulator::new(PstnSimConfig {
+            busy_numbers: vec!["5550000".to_string()],
+            ..Default::default()
+        });
+
+        let setup = Q931Message::new(MessageType::Setup, 42, false)
+            .with_speech_bearer()
+            .with_channel(3)
+            .with_called_number("5551234");
+        let output = sim.handle_message(setup);
+        assert_eq!(types(&output), vec![MessageType::CallProceeding]);
+        assert!(output.messages[0].from_destination);
+        assert_eq!(output.timers.len(), 2);
+
+        let key = (42, false);
+        assert_eq!(types(&sim.on_timer(key, SimTimer::Alert)), vec![MessageType::Alerting]);
+        assert_eq!(types(&sim.on_timer(key, SimTimer::Answer)), vec![MessageType::Connect]);
+        assert_eq!(sim.active_channels(), vec![3]);
+
+        // Channel 3 is now taken
+        let clash = Q931Message::new(MessageType::Setup, 43, false)
+            .with_speech_bearer()
+            .with_channel(3)
+            .with_called_number("5551234");
+        assert_eq!(sim.handle_message(clash).messages[0].cause(), Some(CAUSE_CHANNEL_UNAVAILABLE));
+        let busy = Q931Message::new(MessageType::Setup, 44, false).with_speech_bearer().with_called_number("5550000");
+        assert_eq!(sim.handle_message(busy).messages[0].cause(), Some(CAUSE_USER_BUSY));
+
+        let output = sim.handle_message(Q931Message::new(MessageType::Disconnect, 42, false).with_cause(16));
+        assert_eq!(types(&output), vec![MessageType::Release]);
+        assert!(sim.handle_message(Q931Message::new(MessageType::ReleaseComplete, 42, false)).events.is_empty());
+        assert!(sim.active_channels().is_empty());
+    }
+
+    #[test]
+    fn test_scripted_inbound_call_lifecycle() {
+        let mut sim = PstnSimulator::new(PstnSimConfig { span_type: Layer1Type::T1, ..Default::default() });
+        assert_eq!(sim.d_channel(), 24);
+
+        let output = sim.originate("2125550199", "1000", Duration::from_secs(5));
+        let setup = &output.messages[0];
+        assert_eq!(setup.message_type, MessageType::Setup);
+        assert!(!setup.from_destination);
+        assert_eq!(setup.channel(), Some(1));
+        let key = (setup.call_reference, true);
+
+        // Gateway answers; the simulator acknowledges and schedules the hangup
+        let output = sim.handle_message(Q931Message::new(MessageType::Connect, key.0, true));
+        assert_eq!(types(&output), vec![MessageType::ConnectAck]);
+        assert_eq!(output.timers, vec![(Duration::from_secs(5), key, SimTimer::Hangup)]);
+
+        let output = sim.on_timer(key, SimTimer::Hangup);
+        assert_eq!(types(&output), vec![MessageType::Disconnect]);
+        let output = sim.handle_message(Q931Message::new(MessageType::Release, key.0, true));
+        assert_eq!(types(&output), vec![MessageType::ReleaseComplete]);
+        assert!(output.events.is_empty());
+    }
+}
@@ -0,0 +1,884 @@
+//! Q.931 conformance suite
+//!
+//! Plays the user side of a PRI against its network side, either a span's
+//! far end reached over TDMoE or the built-in PSTN simulator, and runs a
+//! fixed library of cases: valid SETUP handling, refused SETUPs, mandatory
+//! information element violations, call reference errors and timer expiry
+//! (T303, T305, T308). Each case is a script of messages sent and
+//! messages expected, with the cause and channel they must carry. The
+//! outcome is a report card of pass, fail and skipped cases by category,
+//! printable as certification evidence or serialized as JSON.
+//!
+//! The simulator runs on a virtual clock, so timer cases complete at once;
+//! against a span they take the real timer values. Cases that need the
+//! network side to place a call are skipped on spans, which cannot be made
+//! to call on demand.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use crate::config::Layer1Type;
+use crate::interfaces::tdmoe::{FrameType, TdmoeFrame};
+use crate::protocols::q931::{
+    MessageType, Q931Message, CAUSE_CHANNEL_DOES_NOT_EXIST, CAUSE_CHANNEL_UNAVAILABLE, CAUSE_INVALID_CALL_REFERENCE,
+    CAUSE_INVALID_IE_CONTENTS, CAUSE_MANDATORY_IE_MISSING, CAUSE_NORMAL_CLEARING, CAUSE_RECOVERY_ON_TIMER_EXPIRY,
+    CAUSE_UNALLOCATED_NUMBER, IE_BEARER_CAPABILITY, IE_CHANNEL_ID,
+};
+use crate::testing::pstn_sim::{self, CallKey, PstnSimConfig, PstnSimulator, SimOutput, SimTimer, T303, T305, T308};
+use crate::{Error, Result};
+
+/// Slack allowed on top of a Q.931 timer before its expiry counts as missed
+const TIMER_MARGIN: Duration = Duration::from_secs(2);
+
+/// Time allowed for a reply that needs no timer or answer
+const REPLY_WITHIN: Duration = Duration::from_secs(2);
+
+/// Time the far end may take to answer a call
+const ANSWER_WITHIN: Duration = Duration::from_secs(30);
+
+/// Time spent collecting stray messages after a case on a span
+const DRAIN_WITHIN: Duration = Duration::from_millis(500);
+
+/// Numbers and span type the cases are run with
+#[derive(Debug, Clone)]
+pub struct ConformanceConfig {
+    pub span_type: Layer1Type,
+    pub calling: String,
+    /// Number the network side answers
+    pub called: String,
+    /// Number the network side refuses as unallocated
+    pub unallocated: String,
+}
+
+impl Default for ConformanceConfig {
+    fn default() -> Self {
+        Self {
+            span_type: Layer1Type::E1,
+            calling: "2125550199".to_string(),
+            called: "4155550100".to_string(),
+            unallocated: "4155550000".to_string(),
+        }
+    }
+}
+
+/// The simulator, stepped on a virtual clock
+pub struct Emulator {
+    config: PstnSimConfig,
+    simulator: PstnSimulator,
+    now: Duration,
+    timers: Vec<(Duration, CallKey, SimTimer)>,
+    received: VecDeque<Q931Message>,
+}
+
+impl Emulator {
+    fn new(config: PstnSimConfig) -> Self {
+        Self {
+            simulator: PstnSimulator::new(config.clone()),
+            config,
+            now: Duration::ZERO,
+            timers: Vec::new(),
+            received: VecDeque::new(),
+        }
+    }
+
+    fn apply(&mut self, output: SimOutput) -> Result<()> {
+        // Through the codec, as the messages would arrive from a span
+        for message in output.messages {
+            self.received.push_back(Q931Message::decode(&message.encode())?);
+        }
+        for (delay, key, timer) in output.timers {
+            self.timers.push((self.now + delay, key, timer));
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, within: Duration) -> Result<Option<Q931Message>> {
+        let deadline = self.now + within;
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(Some(message));
+            }
+            // Earliest timer due by the deadline; equal ones in the order set
+            let next = self
+                .timers
+                .iter()
+                .enumerate()
+                .filter(|(_, (due, _, _))| *due <= deadline)
+                .min_by_key(|(index, (due, _, _))| (*due, *index))
+                .map(|(index, _)| index);
+            let Some(index) = next else {
+                self.now = deadline;
+                return Ok(None);
+            };
+            let (due, key, timer) = self.timers.remove(index);
+            self.now = self.now.max(due);
+            let output = self.simulator.on_timer(key, timer);
+            self.apply(output)?;
+        }
+    }
+}
+
+/// The network side of a span, reached over TDMoE
+pub struct SpanPeer {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    d_channel: u16,
+    sequence: u32,
+    started: Instant,
+}
+
+impl SpanPeer {
+    async fn send(&mut self, message: &Q931Message) -> Result<()> {
+        let mut frame = TdmoeFrame::new(FrameType::Control, self.d_channel, message.encode());
+        self.sequence = self.sequence.wrapping_add(1);
+        frame.sequence = self.sequence;
+        self.socket.send_to(&frame.encode(), self.peer).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self, within: Duration) -> Result<Option<Q931Message>> {
+        let deadline = Instant::now() + within;
+        let mut buf = vec![0u8; 2048];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Ok(received) = timeout(remaining, self.socket.recv_from(&mut buf)).await else {
+                return Ok(None);
+            };
+            let (len, source) = received?;
+            if source != self.peer {
+                continue;
+            }
+            let Ok(frame) = TdmoeFrame::decode(Bytes::copy_from_slice(&buf[..len])) else {
+                continue;
+            };
+            if frame.frame_type == FrameType::Control && frame.channel == self.d_channel {
+                return Q931Message::decode(&frame.payload).map(Some);
+            }
+        }
+    }
+}
+
+/// What the cases are run against
+pub enum ConformanceTarget {
+    Emulator(Box<Emulator>),
+    Span(SpanPeer),
+}
+
+impl ConformanceTarget {
+    /// The PSTN simulator, refusing `config.unallocated` and answering everything else
+    pub fn emulator(config: &ConformanceConfig) -> Self {
+        ConformanceTarget::Emulator(Box::new(Emulator::new(PstnSimConfig {
+            span_type: config.span_type.clone(),
+            unallocated_numbers: vec![config.unallocated.clone()],
+            play_tone: false,
+            ..PstnSimConfig::default()
+        })))
+    }
+
+    /// The network side of a span at `peer`, exchanging TDMoE frames on `socket`
+    pub fn span(socket: UdpSocket, peer: SocketAddr, span_type: &Layer1Type) -> Self {
+        ConformanceTarget::Span(SpanPeer {
+            socket,
+            peer,
+            d_channel: pstn_sim::d_channel(span_type),
+            sequence: 0,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ConformanceTarget::Emulator(_) => "PSTN simulator".to_string(),
+            ConformanceTarget::Span(span) => format!("TDMoE peer {}", span.peer),
+        }
+    }
+
+    fn can_originate(&self) -> bool {
+        matches!(self, ConformanceTarget::Emulator(_))
+    }
+
+    /// Time on the target's clock
+    fn now(&self) -> Duration {
+        match self {
+            ConformanceTarget::Emulator(emulator) => emulator.now,
+            ConformanceTarget::Span(span) => span.started.elapsed(),
+        }
+    }
+
+    /// Start a case from a clean network side where that is possible
+    fn reset(&mut self) {
+        if let ConformanceTarget::Emulator(emulator) = self {
+            **emulator = Emulator::new(emulator.config.clone());
+        }
+    }
+
+    async fn send(&mut self, message: &Q931Message) -> Result<()> {
+        match self {
+            ConformanceTarget::Emulator(emulator) => {
+                let message = Q931Message::decode(&message.encode())?;
+                let output = emulator.simulator.handle_message(message);
+                emulator.apply(output)
+            }
+            ConformanceTarget::Span(span) => span.send(message).await,
+        }
+    }
+
+    async fn receive(&mut self, within: Duration) -> Result<Option<Q931Message>> {
+        match self {
+            ConformanceTarget::Emulator(emulator) => emulator.receive(within),
+            ConformanceTarget::Span(span) => span.receive(within).await,
+        }
+    }
+
+    fn originate(&mut self, calling: &str, called: &str) -> Result<()> {
+        match self {
+            ConformanceTarget::Emulator(emulator) => {
+                let output = emulator.simulator.originate(calling, called, Duration::from_secs(1));
+                emulator.apply(output)
+            }
+            ConformanceTarget::Span(_) => Err(Error::not_supported("Spans cannot be made to place calls")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseCategory {
+    ValidSetup,
+    InvalidSetup,
+    MandatoryIe,
+    CallReference,
+    Timers,
+}
+
+impl CaseCategory {
+    pub const ALL: [CaseCategory; 5] = [
+        CaseCategory::ValidSetup,
+        CaseCategory::InvalidSetup,
+        CaseCategory::MandatoryIe,
+        CaseCategory::CallReference,
+        CaseCategory::Timers,
+    ];
+}
+
+impl fmt::Display for CaseCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CaseCategory::ValidSetup => "Valid SETUP",
+            CaseCategory::InvalidSetup => "Invalid SETUP",
+            CaseCategory::MandatoryIe => "Mandatory IEs",
+            CaseCategory::CallReference => "Call reference",
+            CaseCategory::Timers => "Timers",
+        })
+    }
+}
+
+/// A message the network side must send next on a call
+#[derive(Debug, Clone)]
+struct Expectation {
+    message_type: MessageType,
+    cause: Option<u8>,
+    /// `Some(None)` when any channel must be identified
+    channel: Option<Option<u8>>,
+    within: Duration,
+}
+
+impl Expectation {
+    fn new(message_type: MessageType, within: Duration) -> Self {
+        Self { message_type, cause: None, channel: None, within }
+    }
+
+    fn cause(mut self, cause: u8) -> Self {
+        self.cause = Some(cause);
+        self
+    }
+
+    fn channel(mut self, channel: Option<u8>) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    fn check(&self, message: &Q931Message) -> std::result::Result<(), String> {
+        if message.message_type != self.message_type {
+            let cause = message.cause().map(|cause| format!(" cause {}", cause)).unwrap_or_default();
+            return Err(format!("expected {:?}, got {:?}{}", self.message_type, message.message_type, cause));
+        }
+        if let Some(cause) = self.cause {
+            if message.cause() != Some(cause) {
+                return Err(format!("{:?} carried cause {:?}, expected {}", message.message_type, message.cause(), cause));
+            }
+        }
+        match (self.channel, message.channel()) {
+            (Some(None), None) => Err(format!("{:?} identified no channel", message.message_type)),
+            (Some(Some(expected)), actual) if actual != Some(expected) => {
+                Err(format!("{:?} named channel {:?}, expected {}", message.message_type, actual, expected))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// One step of a case; calls are numbered within the case
+#[derive(Debug, Clone)]
+enum Step {
+    /// Send a message on a call, with the call's reference filled in
+    Send(usize, Q931Message),
+    /// Have the network side place a call towards us
+    Originate(usize),
+    Expect(usize, Expectation),
+    /// Nothing arrives on the call for this long
+    Silence(usize, Duration),
+}
+
+/// A conformance case and its script
+#[derive(Debug, Clone)]
+pub struct ConformanceCase {
+    pub id: &'static str,
+    pub category: CaseCategory,
+    pub description: &'static str,
+    steps: Vec<Step>,
+}
+
+impl ConformanceCase {
+    fn calls(&self) -> usize {
+        self.steps
+            .iter()
+            .map(|step| match step {
+                Step::Send(call, _) | Step::Originate(call) | Step::Expect(call, _) | Step::Silence(call, _) => call + 1,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn originates(&self) -> bool {
+        self.steps.iter().any(|step| matches!(step, Step::Originate(_)))
+    }
+}
+
+fn message(message_type: MessageType) -> Q931Message {
+    Q931Message::new(message_type, 0, false)
+}
+
+/// The case library, in the order it is run
+pub fn cases(config: &ConformanceConfig) -> Vec<ConformanceCase> {
+    let setup = |called: &str| {
+        message(MessageType::Setup)
+            .with_speech_bearer()
+            .with_calling_number(&config.calling)
+            .with_called_number(called)
+    };
+    let answered = setup(&config.called);
+    let not_a_bearer = pstn_sim::d_channel(&config.span_type) as u8;
+    let expect = |message_type| Expectation::new(message_type, REPLY_WITHIN);
+    let clear = |call: usize| {
+        vec![
+            Step::Send(call, message(MessageType::Disconnect).with_cause(CAUSE_NORMAL_CLEARING)),
+            Step::Expect(call, expect(MessageType::Release)),
+            Step::Send(call, message(MessageType::ReleaseComplete)),
+        ]
+    };
+    let case = |id, category, description, steps: Vec<Vec<Step>>| ConformanceCase {
+        id,
+        category,
+        description,
+        steps: steps.into_iter().flatten().collect(),
+    };
+
+    vec![
+        case(
+            "SETUP-01",
+            CaseCategory::ValidSetup,
+            "SETUP on an exclusive channel proceeds on it, is answered and clears",
+            vec![
+                vec![
+                    Step::Send(0, answered.clone().with_channel(1)),
+                    Step::Expect(0, expect(MessageType::CallProceeding).channel(Some(1))),
+                    Step::Expect(0, Expectation::new(MessageType::Alerting, ANSWER_WITHIN)),
+                    Step::Expect(0, Expectation::new(MessageType::Connect, ANSWER_WITHIN)),
+                    Step::Send(0, message(MessageType::ConnectAck)),
+                ],
+                clear(0),
+            ],
+        ),
+        case(
+            "SETUP-02",
+            CaseCategory::ValidSetup,
+            "SETUP without channel identification is given a channel",
+            vec![
+                vec![
+                    Step::Send(0, answered.clone()),
+                    Step::Expect(0, expect(MessageType::CallProceeding).channel(None)),
+                ],
+                clear(0),
+            ],
+        ),
+        case(
+            "SETUP-03",
+            CaseCategory::InvalidSetup,
+            "SETUP to an unallocated number is refused with cause 1",
+            vec![vec![
+                Step::Send(0, setup(&config.unallocated).with_channel(1)),
+                Step::Expect(0, expect(MessageType::ReleaseComplete).cause(CAUSE_UNALLOCATED_NUMBER)),
+            ]],
+        ),
+        case(
+            "SETUP-04",
+            CaseCategory::InvalidSetup,
+            "SETUP naming the D-channel timeslot is refused with cause 82",
+            vec![vec![
+                Step::Send(0, answered.clone().with_channel(not_a_bearer)),
+                Step::Expect(0, expect(MessageType::ReleaseComplete).cause(CAUSE_CHANNEL_DOES_NOT_EXIST)),
+            ]],
+        ),
+        case(
+            "SETUP-05",
+            CaseCategory::InvalidSetup,
+            "SETUP for a channel in use is refused with cause 44",
+            vec![
+                vec![
+                    Step::Send(0, answered.clone().with_channel(1)),
+                    Step::Expect(0, expect(MessageType::CallProceeding).channel(Some(1))),
+                    Step::Send(1, answered.clone().with_channel(1)),
+                    Step::Expect(1, expect(MessageType::ReleaseComplete).cause(CAUSE_CHANNEL_UNAVAILABLE)),
+                ],
+                clear(0),
+            ],
+        ),
+        case(
+            "IE-01",
+            CaseCategory::MandatoryIe,
+            "SETUP without bearer capability is refused with cause 96",
+            vec![vec![
+                Step::Send(
+                    0,
+                    message(MessageType::Setup).with_channel(1).with_called_number(&config.called),
+                ),
+                Step::Expect(0, expect(MessageType::ReleaseComplete).cause(CAUSE_MANDATORY_IE_MISSING)),
+            ]],
+        ),
+        case(
+            "IE-02",
+            CaseCategory::MandatoryIe,
+            "SETUP with a truncated bearer capability is refused with cause 100",
+            vec![vec![
+                Step::Send(
+                    0,
+                    message(MessageType::Setup)
+                        .with_ie(IE_BEARER_CAPABILITY, vec![0x80])
+                        .with_channel(1)
+                        .with_called_number(&config.called),
+                ),
+                Step::Expect(0, expect(MessageType::ReleaseComplete).cause(CAUSE_INVALID_IE_CONTENTS)),
+            ]],
+        ),
+        case(
+            "IE-03",
+            CaseCategory::MandatoryIe,
+            "SETUP with an unreadable channel identification is refused with cause 100",
+            vec![vec![
+                Step::Send(0, answered.clone().with_ie(IE_CHANNEL_ID, vec![0xa9])),
+                Step::Expect(0, expect(MessageType::ReleaseComplete).cause(CAUSE_INVALID_IE_CONTENTS)),
+            ]],
+        ),
+        case(
+            "CREF-01",
+            CaseCategory::CallReference,
+            "CONNECT for an unknown call reference is answered with cause 81",
+            vec![vec![
+                Step::Send(0, message(MessageType::Connect)),
+                Step::Expect(0, expect(MessageType::ReleaseComplete).cause(CAUSE_INVALID_CALL_REFERENCE)),
+            ]],
+        ),
+        case(
+            "CREF-02",
+            CaseCategory::CallReference,
+            "RELEASE for an unknown call reference is answered with RELEASE COMPLETE",
+            vec![vec![
+                Step::Send(0, message(MessageType::Release).with_cause(CAUSE_NORMAL_CLEARING)),
+                Step::Expect(0, expect(MessageType::ReleaseComplete)),
+            ]],
+        ),
+        case(
+            "CREF-03",
+            CaseCategory::CallReference,
+            "SETUP repeating a call reference in use is ignored",
+            vec![
+                vec![
+                    Step::Send(0, answered.clone().with_channel(1)),
+                    Step::Expect(0, expect(MessageType::CallProceeding).channel(Some(1))),
+                    Step::Send(0, answered.clone().with_channel(2)),
+                    Step::Send(0, message(MessageType::Disconnect).with_cause(CAUSE_NORMAL_CLEARING)),
+                    Step::Expect(0, expect(MessageType::Release)),
+                    Step::Send(0, message(MessageType::ReleaseComplete)),
+                ],
+            ],
+        ),
+        case(
+            "TMR-01",
+            CaseCategory::Timers,
+            "T308: an unacknowledged RELEASE is sent again, then the channel is freed",
+            vec![
+                vec![
+                    Step::Send(0, answered.clone().with_channel(1)),
+                    Step::Expect(0, expect(MessageType::CallProceeding).channel(Some(1))),
+                    Step::Send(0, message(MessageType::Disconnect).with_cause(CAUSE_NORMAL_CLEARING)),
+                    Step::Expect(0, expect(MessageType::Release)),
+                    Step::Expect(0, Expectation::new(MessageType::Release, T308 + TIMER_MARGIN)),
+                    Step::Silence(0, T308 + TIMER_MARGIN),
+                    Step::Send(1, answered.clone().with_channel(1)),
+                    Step::Expect(1, expect(MessageType::CallProceeding).channel(Some(1))),
+                ],
+                clear(1),
+            ],
+        ),
+        case(
+            "TMR-02",
+            CaseCategory::Timers,
+            "T303: an unanswered SETUP is sent again, then cleared with cause 102",
+            vec![vec![
+                Step::Originate(0),
+                Step::Expect(0, expect(MessageType::Setup).channel(None)),
+                Step::Expect(0, Expectation::new(MessageType::Setup, T303 + TIMER_MARGIN)),
+                Step::Expect(
+                    0,
+                    Expectation::new(MessageType::ReleaseComplete, T303 + TIMER_MARGIN)
+                        .cause(CAUSE_RECOVERY_ON_TIMER_EXPIRY),
+                ),
+            ]],
+        ),
+        case(
+            "TMR-03",
+            CaseCategory::Timers,
+            "T305: a DISCONNECT left unanswered is followed by RELEASE",
+            vec![vec![
+                Step::Originate(0),
+                Step::Expect(0, expect(MessageType::Setup)),
+                Step::Send(0, message(MessageType::Connect)),
+                Step::Expect(0, expect(MessageType::ConnectAck)),
+                Step::Expect(0, Expectation::new(MessageType::Disconnect, ANSWER_WITHIN)),
+                Step::Expect(0, Expectation::new(MessageType::Release, T305 + TIMER_MARGIN)),
+                Step::Send(0, message(MessageType::ReleaseComplete)),
+            ]],
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Pass,
+    Fail,
+    Skipped,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Verdict::Pass => "PASS",
+            Verdict::Fail => "FAIL",
+            Verdict::Skipped => "SKIP",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaseResult {
+    pub id: String,
+    pub category: CaseCategory,
+    pub description: String,
+    pub verdict: Verdict,
+    /// Why the case failed or was skipped
+    pub detail: Option<String>,
+}
+
+/// Outcome of a run against one span
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportCard {
+    pub span: String,
+    pub target: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub results: Vec<CaseResult>,
+}
+
+impl ReportCard {
+    pub fn count(&self, verdict: Verdict) -> usize {
+        self.results.iter().filter(|result| result.verdict == verdict).count()
+    }
+
+    /// No case failed
+    pub fn conformant(&self) -> bool {
+        self.count(Verdict::Fail) == 0
+    }
+
+    /// Passed and run (not skipped) cases per category
+    pub fn by_category(&self) -> Vec<(CaseCategory, usize, usize)> {
+        CaseCategory::ALL
+            .iter()
+            .map(|&category| {
+                let run: Vec<&CaseResult> = self
+                    .results
+                    .iter()
+                    .filter(|result| result.category == category && result.verdict != Verdict::Skipped)
+                    .collect();
+                let passed = run.iter().filter(|result| result.verdict == Verdict::Pass).count();
+                (category, passed, run.len())
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ReportCard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Q.931 conformance report card")?;
+        writeln!(f, "Span:     {}", self.span)?;
+        writeln!(f, "Target:   {}", self.target)?;
+        writeln!(f, "Started:  {}", self.started_at.to_rfc3339())?;
+        writeln!(f, "Finished: {}", self.finished_at.to_rfc3339())?;
+        writeln!(f)?;
+        for result in &self.results {
+            writeln!(f, "{:<9} {} {}", result.id, result.verdict, result.description)?;
+            if let Some(detail) = &result.detail {
+                writeln!(f, "               {}", detail)?;
+            }
+        }
+        writeln!(f)?;
+        for (category, passed, total) in self.by_category() {
+            writeln!(f, "{:<15} {}/{}", category.to_string(), passed, total)?;
+        }
+        write!(
+            f,
+            "Result: {} ({} passed, {} failed, {} skipped)",
+            if self.conformant() { "CONFORMANT" } else { "NOT CONFORMANT" },
+            self.count(Verdict::Pass),
+            self.count(Verdict::Fail),
+            self.count(Verdict::Skipped)
+        )
+    }
+}
+
+/// Reference of one call of a case, once known
+#[derive(Debug, Clone, Copy, Default)]
+struct CallSlot {
+    reference: Option<u16>,
+    /// The network side allocated the reference
+    network_originated: bool,
+}
+
+impl CallSlot {
+    fn owns(&self, message: &Q931Message) -> bool {
+        // The network side sets the flag on calls we originated, and not on its own
+        self.reference == Some(message.call_reference) && message.from_destination != self.network_originated
+    }
+}
+
+/// Runs the case library against a target
+pub struct ConformanceSuite {
+    config: ConformanceConfig,
+    target: ConformanceTarget,
+    next_reference: u16,
+    slots: Vec<CallSlot>,
+    /// Received messages not yet matched to a step
+    inbox: Vec<Q931Message>,
+}
+
+impl ConformanceSuite {
+    pub fn new(config: ConformanceConfig, target: ConformanceTarget) -> Self {
+        Self { config, target, next_reference: 1, slots: Vec::new(), inbox: Vec::new() }
+    }
+
+    /// Run every case and grade the span named `span`
+    pub async fn run(&mut self, span: &str) -> ReportCard {
+        let started_at = Utc::now();
+        let mut results = Vec::new();
+        for case in cases(&self.config) {
+            results.push(self.run_case(&case).await);
+        }
+        ReportCard {
+            span: span.to_string(),
+            target: self.target.describe(),
+            started_at,
+            finished_at: Utc::now(),
+            results,
+        }
+    }
+
+    async fn run_case(&mut self, case: &ConformanceCase) -> CaseResult {
+        let result = |verdict, detail| CaseResult {
+            id: case.id.to_string(),
+            category: case.category,
+            description: case.description.to_string(),
+            verdict,
+            detail,
+        };
+        if case.originates() && !self.target.can_originate() {
+            return result(Verdict::Skipped, Some("the target cannot be made to place calls".to_string()));
+        }
+
+        self.target.reset();
+        self.inbox.clear();
+        self.slots = vec![CallSlot::default(); case.calls()];
+        let mut outcome = Ok(());
+        for (index, step) in case.steps.iter().enumerate() {
+            if let Err(detail) = self.step(step).await {
+                outcome = Err(format!("step {}: {}", index + 1, detail));
+                break;
+            }
+        }
+        self.clean_up().await;
+
+        match outcome {
+            Ok(()) => result(Verdict::Pass, None),
+            Err(detail) => result(Verdict::Fail, Some(detail)),
+        }
+    }
+
+    async fn step(&mut self, step: &Step) -> std::result::Result<(), String> {
+        match step {
+            Step::Send(call, message) => {
+                let slot = &mut self.slots[*call];
+                let reference = match slot.reference {
+                    Some(reference) => reference,
+                    None => {
+                        let reference = self.next_reference;
+                        self.next_reference = if reference >= 0x7fff { 1 } else { reference + 1 };
+                        slot.reference = Some(reference);
+                        reference
+                    }
+                };
+                let mut message = message.clone();
+                message.call_reference = reference;
+                message.from_destination = slot.network_originated;
+                self.target.send(&message).await.map_err(|e| e.to_string())
+            }
+            Step::Originate(_) => {
+                let (calling, called) = (self.config.calling.clone(), self.config.called.clone());
+                self.target.originate(&calling, &called).map_err(|e| e.to_string())
+            }
+            Step::Expect(call, expectation) => {
+                let message = self
+                    .next_for(*call, expectation.within, expectation.message_type == MessageType::Setup)
+                    .await?
+                    .ok_or_else(|| format!("no {:?} within {:?}", expectation.message_type, expectation.within))?;
+                expectation.check(&message)
+            }
+            Step::Silence(call, period) => match self.next_for(*call, *period, false).await? {
+                Some(message) => Err(format!("unexpected {:?} within {:?}", message.message_type, period)),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Index in the inbox of the next message on `call`; a SETUP from the
+    /// network side may claim a call that has no reference yet
+    fn find(&mut self, call: usize, claim_setup: bool) -> Option<usize> {
+        if let Some(index) = self.inbox.iter().position(|message| self.slots[call].owns(message)) {
+            return Some(index);
+        }
+        if !claim_setup || self.slots[call].reference.is_some() {
+            return None;
+        }
+        let index = self.inbox.iter().position(|message| {
+            message.message_type == MessageType::Setup
+                && !message.from_destination
+                && !self.slots.iter().any(|slot| slot.owns(message))
+        })?;
+        self.slots[call] = CallSlot { reference: Some(self.inbox[index].call_reference), network_originated: true };
+        Some(index)
+    }
+
+    /// Next message on `call` within `within`, passing over PROGRESS
+    async fn next_for(
+        &mut self,
+        call: usize,
+        within: Duration,
+        claim_setup: bool,
+    ) -> std::result::Result<Option<Q931Message>, String> {
+        let deadline = self.target.now() + within;
+        loop {
+            if let Some(index) = self.find(call, claim_setup) {
+                let message = self.inbox.remove(index);
+                if message.message_type == MessageType::Progress {
+                    continue;
+                }
+                return Ok(Some(message));
+            }
+            let now = self.target.now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            if let Some(message) = self.target.receive(deadline - now).await.map_err(|e| e.to_string())? {
+                self.inbox.push(message);
+            }
+        }
+    }
+
+    /// Clear whatever a case left behind on a span; the simulator is reset
+    /// before the next case instead
+    async fn clean_up(&mut self) {
+        if !matches!(self.target, ConformanceTarget::Span(_)) {
+            return;
+        }
+        for slot in std::mem::take(&mut self.slots) {
+            if let Some(reference) = slot.reference {
+                let release = Q931Message::new(MessageType::ReleaseComplete, reference, slot.network_originated)
+                    .with_cause(CAUSE_NORMAL_CLEARING);
+                let _ = self.target.send(&release).await;
+            }
+        }
+        while let Ok(Some(_)) = self.target.receive(DRAIN_WITHIN).await {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_emulator_is_conformant() {
+        let config = ConformanceConfig::default();
+        let mut suite = ConformanceSuite::new(config.clone(), ConformanceTarget::emulator(&config));
+        let report = suite.run("span 1").await;
+
+        let failures: Vec<_> = report.results.iter().filter(|r| r.verdict != Verdict::Pass).collect();
+        assert!(failures.is_empty(), "{:?}", failures);
+        assert_eq!(report.results.len(), cases(&config).len());
+        assert!(report.conformant());
+        assert!(report.by_category().iter().all(|(_, passed, total)| passed == total && *total > 0));
+        assert!(report.to_string().ends_with("Result: CONFORMANT (14 passed, 0 failed, 0 skipped)"));
+    }
+
+    #[tokio::test]
+    async fn test_failures_and_skips_over_tdmoe() {
+        // A far end that never answers fails every case it is sent and
+        // skips the ones it would have to originate
+        let network = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = ConformanceConfig { span_type: Layer1Type::T1, ..ConformanceConfig::default() };
+        let target = ConformanceTarget::span(socket, network.local_addr().unwrap(), &config.span_type);
+        let mut suite = ConformanceSuite::new(config, target);
+
+        let case = &cases(&suite.config)[8];
+        assert_eq!(case.id, "CREF-01");
+        let result = suite.run_case(case).await;
+        assert_eq!(result.verdict, Verdict::Fail);
+        assert_eq!(result.detail.as_deref(), Some("step 2: no ReleaseComplete within 2s"));
+
+        let mut buf = [0u8; 256];
+        let (len, _) = network.recv_from(&mut buf).await.unwrap();
+        let frame = TdmoeFrame::decode(Bytes::copy_from_slice(&buf[..len])).unwrap();
+        assert_eq!((frame.frame_type, frame.channel), (FrameType::Control, 24));
+        assert_eq!(Q931Message::decode(&frame.payload).unwrap().message_type, MessageType::Connect);
+
+        let case = cases(&suite.config).into_iter().find(|case| case.id == "TMR-02").unwrap();
+        assert_eq!(suite.run_case(&case).await.verdict, Verdict::Skipped);
+    }
+}
@@ -0,0 +1,139 @@
+//! Monotonic call clock with UTC mapping
+//!
+//! Durations (call length, post-dial delay, test session run time) are
+//! measured on the monotonic clock so an NTP step during a call cannot
+//! shorten, lengthen or negate what gets billed. Wall-clock UTC is read once
+//! when a call or session starts; later moments of the same call are mapped
+//! to UTC from that anchor, so record timestamps always agree with the
+//! durations stored next to them.
+//!
+//! Whether wall-clock UTC can be trusted at all is tracked separately: once
+//! the timing reference is lost the clock free-runs in holdover, and past a
+//! configured limit its readings are no longer good enough to bill from.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+/// A moment read from both the monotonic and the wall clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockStamp {
+    pub utc: DateTime<Utc>,
+    pub instant: Instant,
+}
+
+impl ClockStamp {
+    pub fn now() -> Self {
+        Self {
+            utc: Utc::now(),
+            instant: Instant::now(),
+        }
+    }
+
+    /// Pair readings taken together, e.g. where a message came off the wire
+    pub fn new(utc: DateTime<Utc>, instant: Instant) -> Self {
+        Self { utc, instant }
+    }
+
+    /// Monotonic time from `earlier` to this stamp, zero if `earlier` is later
+    pub fn duration_since(&self, earlier: &ClockStamp) -> Duration {
+        self.instant.saturating_duration_since(earlier.instant)
+    }
+
+    /// UTC of `instant` as seen from this anchor, ignoring any wall-clock
+    /// step since the anchor was read
+    pub fn utc_at(&self, instant: Instant) -> DateTime<Utc> {
+        let offset = |duration: Duration| chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
+        if instant >= self.instant {
+            self.utc + offset(instant - self.instant)
+        } else {
+            self.utc - offset(self.instant - instant)
+        }
+    }
+
+    /// `later` mapped onto this anchor's timeline
+    pub fn map(&self, later: &ClockStamp) -> ClockStamp {
+        ClockStamp::new(self.utc_at(later.instant), later.instant)
+    }
+}
+
+/// Holdover state of the wall clock, shared by everything that stamps time
+/// for peers or billing
+#[derive(Debug)]
+pub struct TimeHealth {
+    max_holdover: Duration,
+    holdover_since: Mutex<Option<Instant>>,
+}
+
+impl TimeHealth {
+    /// Clock counted as synchronized until the timing service says otherwise
+    pub fn new(max_holdover: Duration) -> Self {
+        Self {
+            max_holdover,
+            holdover_since: Mutex::new(None),
+        }
+    }
+
+    /// Reference lost at `since`; a holdover already in progress keeps its start
+    pub fn enter_holdover(&self, since: Instant) {
+        self.holdover_since.lock().unwrap().get_or_insert(since);
+    }
+
+    /// Reference regained
+    pub fn synchronized(&self) {
+        *self.holdover_since.lock().unwrap() = None;
+    }
+
+    /// Time spent in holdover as of `now`, if the clock is free-running
+    pub fn holdover(&self, now: Instant) -> Option<Duration> {
+        self.holdover_since.lock().unwrap().map(|since| now.saturating_duration_since(since))
+    }
+
+    /// Whether readings taken at `now` count as synchronized: the clock is
+    /// locked, or has not been in holdover longer than the limit
+    pub fn is_synchronized(&self, now: Instant) -> bool {
+        self.holdover(now).map_or(true, |held| held <= self.max_holdover)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durations_ignore_wall_clock_steps() {
+        let start = ClockStamp::now();
+        let instant = start.instant + Duration::from_secs(90);
+
+        // NTP stepped the wall clock back an hour during the call
+        let stepped_back = ClockStamp::new(start.utc - chrono::Duration::hours(1), instant);
+        assert_eq!(stepped_back.duration_since(&start), Duration::from_secs(90));
+        assert_eq!(start.map(&stepped_back).utc, start.utc + chrono::Duration::seconds(90));
+
+        // ... or forward a day
+        let stepped_forward = ClockStamp::new(start.utc + chrono::Duration::days(1), instant);
+        assert_eq!(stepped_forward.duration_since(&start), Duration::from_secs(90));
+        assert_eq!(start.utc_at(stepped_forward.instant), start.utc + chrono::Duration::seconds(90));
+
+        // Out-of-order stamps never produce a negative duration
+        assert_eq!(start.duration_since(&stepped_back), Duration::ZERO);
+        assert_eq!(stepped_back.utc_at(start.instant), start.utc);
+    }
+
+    #[test]
+    fn test_holdover_beyond_limit_is_not_synchronized() {
+        let health = TimeHealth::new(Duration::from_secs(600));
+        let lost = Instant::now();
+        assert!(health.is_synchronized(lost));
+
+        health.enter_holdover(lost);
+        health.enter_holdover(lost + Duration::from_secs(60));
+        assert_eq!(health.holdover(lost + Duration::from_secs(300)), Some(Duration::from_secs(300)));
+        assert!(health.is_synchronized(lost + Duration::from_secs(600)));
+        assert!(!health.is_synchronized(lost + Duration::from_secs(601)));
+
+        health.synchronized();
+        assert!(health.is_synchronized(lost + Duration::from_secs(601)));
+    }
+}
@@ -7,15 +7,21 @@ use tracing_appender::{non_blocking, rolling};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 
 use crate::config::{LoggingConfig, LogFormat};
+use crate::services::call_trace::CALL_TRACE_TARGET;
 use crate::Result;
 
 /// Setup logging based on configuration
 pub fn setup_logging(config: &LoggingConfig) -> Result<()> {
     let level = parse_log_level(&config.level)?;
     
+    // Records of traced calls pass whatever the configured level
+    let call_trace = format!("{}=trace", CALL_TRACE_TARGET)
+        .parse()
+        .map_err(|e| crate::Error::invalid_config(format!("Invalid call trace directive: {}", e)))?;
     let env_filter = EnvFilter::builder()
         .with_default_directive(level.into())
-        .from_env_lossy();
+        .from_env_lossy()
+        .add_directive(call_trace);
 
     let registry = tracing_subscriber::registry().with(env_filter);
 
@@ -32,7 +38,7 @@ pub fn setup_logging(config: &LoggingConfig) -> Result<()> {
                 .rotation(rolling::Rotation::DAILY)
                 .filename_suffix("log")
                 .build(directory)
-                .map_err(|e| crate::Error::internal(format!("Failed to create file appender: {}", e)))?;
+                .map_err(|e| crate::Error::invalid_config(format!("Failed to create file appender: {}", e)))?;
             
             let (file_writer, _file_guard) = non_blocking(file_appender);
             
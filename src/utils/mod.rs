@@ -1,5 +1,13 @@
 //! Utility modules for the Redfire Gateway
 
+pub mod clock;
 pub mod logger;
+pub mod netbind;
 
-pub use logger::setup_logging;
\ No newline at end of file
+pub use clock::{ClockStamp, TimeHealth};
+pub use logger::setup_logging;
+
+/// Compare secrets in time independent of where they differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
\ No newline at end of file
@@ -0,0 +1,126 @@
+//! Binding service sockets and listeners to VRFs and network namespaces
+//!
+//! Carriers keep management traffic on the OAM network and signaling and
+//! media on the service network. A socket is placed by creating it inside
+//! the configured network namespace (on a short-lived thread that alone
+//! enters the namespace, since `setns` affects only the calling thread) and
+//! by binding it to a device with `SO_BINDTODEVICE`, which also selects a
+//! VRF when the device is the VRF master. Sockets keep their namespace for
+//! their whole life, so the rest of the process stays where it was started.
+
+use std::net::SocketAddr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::config::NetworkPlacement;
+
+/// Bind a non-blocking UDP socket to `addr` within `placement`
+pub fn bind_udp(addr: SocketAddr, placement: &NetworkPlacement) -> std::io::Result<tokio::net::UdpSocket> {
+    let socket = in_placement(placement, move |device| new_socket(addr, Type::DGRAM, Protocol::UDP, device))?;
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+/// Listen for TCP connections on `addr` within `placement`
+pub fn bind_tcp(addr: SocketAddr, placement: &NetworkPlacement) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = in_placement(placement, move |device| {
+        let socket = new_socket(addr, Type::STREAM, Protocol::TCP, device)?;
+        socket.listen(TCP_BACKLOG)?;
+        Ok(socket)
+    })?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+/// Backlog of pending connections on listening sockets
+const TCP_BACKLOG: i32 = 1024;
+
+/// Run `open` with the placement's device, inside its namespace if it has one
+fn in_placement<F>(placement: &NetworkPlacement, open: F) -> std::io::Result<Socket>
+where
+    F: FnOnce(Option<&str>) -> std::io::Result<Socket> + Send + 'static,
+{
+    let device = placement.device.clone();
+    match &placement.netns {
+        Some(netns) => {
+            let netns = netns.clone();
+            std::thread::spawn(move || {
+                enter_netns(&netns)?;
+                open(device.as_deref())
+            })
+            .join()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "network namespace thread panicked"))?
+        }
+        None => open(device.as_deref()),
+    }
+}
+
+fn new_socket(addr: SocketAddr, ty: Type, protocol: Protocol, device: Option<&str>) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if ty == Type::STREAM {
+        // As tokio's own TcpListener::bind does, so restarts can rebind at once
+        socket.set_reuse_address(true)?;
+    }
+    if let Some(device) = device {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        socket.bind_device(Some(device.as_bytes()))?;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("binding to device {} is not supported on this platform", device),
+        ));
+    }
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Move the calling thread into the namespace at `path`, either a full path
+/// or a name under `/var/run/netns` as created by `ip netns add`
+#[cfg(target_os = "linux")]
+fn enter_netns(path: &str) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::File::open(netns_path(path))?;
+    // SAFETY: the descriptor is valid for the duration of the call
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enter_netns(path: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("network namespace {} is not supported on this platform", path),
+    ))
+}
+
+fn netns_path(netns: &str) -> String {
+    if netns.contains('/') {
+        netns.to_string()
+    } else {
+        format!("/var/run/netns/{}", netns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netns_names_resolve_under_var_run() {
+        assert_eq!(netns_path("oam"), "/var/run/netns/oam");
+        assert_eq!(netns_path("/proc/1/ns/net"), "/proc/1/ns/net");
+    }
+
+    #[tokio::test]
+    async fn test_default_placement_binds_normally() {
+        let socket = bind_udp("127.0.0.1:0".parse().unwrap(), &NetworkPlacement::default()).unwrap();
+        assert!(socket.local_addr().unwrap().port() != 0);
+
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), &NetworkPlacement::default()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (accepted, connected) = tokio::join!(listener.accept(), tokio::net::TcpStream::connect(addr));
+        assert!(accepted.is_ok() && connected.is_ok());
+    }
+}